@@ -0,0 +1,373 @@
+//! A gRPC gateway to a Noria deployment.
+//!
+//! [`Gateway`] serves the `Controller`, `Views`, and `Tables` services of `proto/noria.proto` by
+//! forwarding each call to the deployment through a [`ControllerHandle`], so that clients that
+//! are not written in Rust can use Noria through stubs generated from the published `.proto`.
+//! Values are converted between `DataType` and the protocol's `Value` as described there.
+#![deny(missing_docs)]
+
+use noria::consensus::Authority;
+use noria::{ControllerHandle, DataType, Modification, Operation, Table, TableOperation, View};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tonic::{Request, Response, Status};
+
+/// The messages and services generated from `proto/noria.proto`.
+#[allow(missing_docs)]
+pub mod pb {
+    tonic::include_proto!("noria");
+}
+
+use pb::{value::Value as V, write::Op};
+
+/// Serves the gRPC services by forwarding calls to a Noria deployment.
+///
+/// Handles to views and tables are kept once they have been obtained, so each call after the
+/// first to a given view or table goes straight to the workers that hold it. Clones share those
+/// handles, so one gateway can serve all three services.
+pub struct Gateway<A: Authority + 'static> {
+    handle: Arc<Mutex<ControllerHandle<A>>>,
+    views: Arc<Mutex<HashMap<String, View>>>,
+    tables: Arc<Mutex<HashMap<String, Table>>>,
+}
+
+impl<A: Authority + 'static> Clone for Gateway<A> {
+    fn clone(&self) -> Self {
+        Gateway {
+            handle: self.handle.clone(),
+            views: self.views.clone(),
+            tables: self.tables.clone(),
+        }
+    }
+}
+
+impl<A: Authority + 'static> Gateway<A> {
+    /// Serve the deployment that `handle` is connected to.
+    pub fn new(handle: ControllerHandle<A>) -> Self {
+        Gateway {
+            handle: Arc::new(Mutex::new(handle)),
+            views: Arc::default(),
+            tables: Arc::default(),
+        }
+    }
+
+    async fn controller(&self) -> ControllerHandle<A> {
+        self.handle.lock().await.clone()
+    }
+
+    async fn view(&self, name: &str) -> Result<View, Status> {
+        if let Some(view) = self.views.lock().await.get(name) {
+            return Ok(view.clone());
+        }
+        let view = self
+            .controller()
+            .await
+            .view(name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        self.views
+            .lock()
+            .await
+            .insert(name.to_owned(), view.clone());
+        Ok(view)
+    }
+
+    async fn table(&self, name: &str) -> Result<Table, Status> {
+        if let Some(table) = self.tables.lock().await.get(name) {
+            return Ok(table.clone());
+        }
+        let table = self
+            .controller()
+            .await
+            .table(name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        self.tables
+            .lock()
+            .await
+            .insert(name.to_owned(), table.clone());
+        Ok(table)
+    }
+}
+
+/// Convert a value of the protocol to a `DataType`.
+pub fn to_datatype(value: pb::Value) -> Result<DataType, Status> {
+    let invalid =
+        |what: &str, v: &str| Status::invalid_argument(format!("invalid {}: {}", what, v));
+    Ok(match value.value {
+        None | Some(V::Null(_)) => DataType::None,
+        Some(V::Int(n)) => n.into(),
+        Some(V::Uint(n)) => n.into(),
+        Some(V::Real(f)) if f.is_finite() => f.into(),
+        Some(V::Real(f)) => return Err(invalid("real", &f.to_string())),
+        Some(V::Text(s)) => s.into(),
+        Some(V::Timestamp(s)) => match DataType::from(s.as_str()).to_timestamp() {
+            Some(ts) => ts.into(),
+            None => return Err(invalid("timestamp", &s)),
+        },
+        Some(V::Decimal(s)) => DataType::decimal(&s).ok_or_else(|| invalid("decimal", &s))?,
+        Some(V::Json(s)) => match serde_json::from_str::<serde_json::Value>(&s) {
+            Ok(json) => json.into(),
+            Err(_) => return Err(invalid("JSON", &s)),
+        },
+    })
+}
+
+/// Convert a `DataType` to a value of the protocol.
+pub fn from_datatype(dt: &DataType) -> pb::Value {
+    let value = match *dt {
+        DataType::None => V::Null(true),
+        DataType::Int(_) | DataType::BigInt(_) => V::Int(i64::from(dt.clone())),
+        DataType::UnsignedInt(_) | DataType::UnsignedBigInt(_) => V::Uint(u64::from(dt.clone())),
+        DataType::Real(..) => V::Real(f64::from(dt.clone())),
+        DataType::Text(..) | DataType::TinyText(..) | DataType::Compressed(..) => {
+            V::Text(<&str>::from(&*dt.decompress()).to_owned())
+        }
+        DataType::Timestamp(ts) => V::Timestamp(ts.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        DataType::Decimal(..) => V::Decimal(dt.to_string()),
+        DataType::Json(..) => V::Json(<&str>::from(dt).to_owned()),
+    };
+    pb::Value { value: Some(value) }
+}
+
+fn to_row(row: Option<pb::Row>) -> Result<Vec<DataType>, Status> {
+    row.map(|r| r.values)
+        .unwrap_or_default()
+        .into_iter()
+        .map(to_datatype)
+        .collect()
+}
+
+fn from_row(row: &[DataType]) -> pb::Row {
+    pb::Row {
+        values: row.iter().map(from_datatype).collect(),
+    }
+}
+
+/// The modifications to a row with `ncols` columns that the given changes make.
+fn to_modifications(changes: Vec<pb::Change>, ncols: usize) -> Result<Vec<Modification>, Status> {
+    let mut set = vec![Modification::None; ncols];
+    for change in changes {
+        let column = change.column as usize;
+        if column >= ncols {
+            return Err(Status::invalid_argument(format!(
+                "no column with index {}",
+                column
+            )));
+        }
+        let value = to_datatype(change.value.unwrap_or_default())?;
+        set[column] = match pb::ChangeOp::from_i32(change.op) {
+            Some(pb::ChangeOp::Set) => Modification::Set(value),
+            Some(pb::ChangeOp::Add) => Modification::Apply(Operation::Add, value),
+            Some(pb::ChangeOp::Sub) => Modification::Apply(Operation::Sub, value),
+            None => return Err(Status::invalid_argument("unknown change operation")),
+        };
+    }
+    Ok(set)
+}
+
+fn to_operation(write: pb::Write, ncols: usize) -> Result<TableOperation, Status> {
+    Ok(match write.op {
+        None => return Err(Status::invalid_argument("write without an operation")),
+        Some(Op::Insert(row)) => TableOperation::Insert(to_row(Some(row))?),
+        Some(Op::Delete(key)) => TableOperation::Delete {
+            key: to_row(Some(key))?,
+        },
+        Some(Op::Update(u)) => TableOperation::Update {
+            key: to_row(u.key)?,
+            set: to_modifications(u.changes, ncols)?,
+        },
+        Some(Op::Upsert(u)) => TableOperation::InsertOrUpdate {
+            row: to_row(u.row)?,
+            update: to_modifications(u.changes, ncols)?,
+        },
+    })
+}
+
+fn names<K>(map: impl IntoIterator<Item = (String, K)>) -> pb::Names {
+    pb::Names {
+        names: map.into_iter().map(|(name, _)| name).collect(),
+    }
+}
+
+#[tonic::async_trait]
+impl<A: Authority + 'static> pb::controller_server::Controller for Gateway<A> {
+    async fn inputs(&self, _: Request<pb::Empty>) -> Result<Response<pb::Names>, Status> {
+        let inputs = self.controller().await.inputs().await;
+        inputs
+            .map(|inputs| Response::new(names(inputs)))
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
+
+    async fn outputs(&self, _: Request<pb::Empty>) -> Result<Response<pb::Names>, Status> {
+        let outputs = self.controller().await.outputs().await;
+        outputs
+            .map(|outputs| Response::new(names(outputs)))
+            .map_err(|e| Status::unavailable(e.to_string()))
+    }
+
+    async fn extend_recipe(
+        &self,
+        request: Request<pb::Recipe>,
+    ) -> Result<Response<pb::Empty>, Status> {
+        let recipe = request.into_inner().text;
+        self.controller()
+            .await
+            .extend_recipe(&recipe)
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(pb::Empty {}))
+    }
+
+    async fn install_recipe(
+        &self,
+        request: Request<pb::Recipe>,
+    ) -> Result<Response<pb::Empty>, Status> {
+        let recipe = request.into_inner().text;
+        self.controller()
+            .await
+            .install_recipe(&recipe)
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        // views and tables may have changed, so look them up again
+        self.views.lock().await.clear();
+        self.tables.lock().await.clear();
+        Ok(Response::new(pb::Empty {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<A: Authority + 'static> pb::views_server::Views for Gateway<A> {
+    async fn lookup(
+        &self,
+        request: Request<pb::LookupRequest>,
+    ) -> Result<Response<pb::LookupResponse>, Status> {
+        let request = request.into_inner();
+        let keys = request
+            .keys
+            .into_iter()
+            .map(|k| to_row(Some(k)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut view = self.view(&request.view).await?;
+        let results = view
+            .multi_lookup(keys, request.block)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(pb::LookupResponse {
+            columns: view.columns().to_vec(),
+            results: results
+                .into_iter()
+                .map(|rs| {
+                    let rows: Vec<Vec<DataType>> = rs.into();
+                    pb::Rows {
+                        rows: rows.iter().map(|r| from_row(r)).collect(),
+                    }
+                })
+                .collect(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl<A: Authority + 'static> pb::tables_server::Tables for Gateway<A> {
+    async fn describe(
+        &self,
+        request: Request<pb::DescribeRequest>,
+    ) -> Result<Response<pb::TableDescription>, Status> {
+        let table = self.table(&request.into_inner().table).await?;
+        Ok(Response::new(pb::TableDescription {
+            columns: table.columns().to_vec(),
+            primary_key: table
+                .primary_key()
+                .unwrap_or_default()
+                .iter()
+                .map(|&c| c as u32)
+                .collect(),
+        }))
+    }
+
+    async fn write(
+        &self,
+        request: Request<pb::WriteRequest>,
+    ) -> Result<Response<pb::Empty>, Status> {
+        let request = request.into_inner();
+        let mut table = self.table(&request.table).await?;
+        let ncols = table.columns().len();
+        let ops = request
+            .writes
+            .into_iter()
+            .map(|w| to_operation(w, ncols))
+            .collect::<Result<Vec<_>, _>>()?;
+        table
+            .perform_all(ops)
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(pb::Empty {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_converts_values() {
+        let values = vec![
+            DataType::None,
+            DataType::from(-3),
+            DataType::from(u64::max_value()),
+            DataType::from(2.5),
+            DataType::from("hello"),
+            DataType::from("2020-03-04 12:34:56")
+                .to_timestamp()
+                .unwrap()
+                .into(),
+            DataType::decimal("12.50").unwrap(),
+            DataType::from(serde_json::json!({"a": [1, 2]})),
+        ];
+        for v in values {
+            assert_eq!(to_datatype(from_datatype(&v)).unwrap(), v);
+        }
+
+        // an unset value is NULL
+        assert_eq!(to_datatype(pb::Value::default()).unwrap(), DataType::None);
+        let invalid = |v| pb::Value { value: Some(v) };
+        assert!(to_datatype(invalid(V::Real(std::f64::NAN))).is_err());
+        assert!(to_datatype(invalid(V::Timestamp("yesterday".to_owned()))).is_err());
+        assert!(to_datatype(invalid(V::Json("{".to_owned()))).is_err());
+    }
+
+    #[test]
+    fn it_converts_writes() {
+        let value = |n: i64| pb::Value {
+            value: Some(V::Int(n)),
+        };
+        let update = pb::Write {
+            op: Some(Op::Update(pb::Update {
+                key: Some(pb::Row {
+                    values: vec![value(1)],
+                }),
+                changes: vec![pb::Change {
+                    column: 1,
+                    value: Some(value(5)),
+                    op: pb::ChangeOp::Add as i32,
+                }],
+            })),
+        };
+        assert_eq!(
+            to_operation(update.clone(), 2).unwrap(),
+            TableOperation::Update {
+                key: vec![1.into()],
+                set: vec![
+                    Modification::None,
+                    Modification::Apply(Operation::Add, 5.into())
+                ],
+            }
+        );
+        // changes must refer to columns of the table
+        assert!(to_operation(update, 1).is_err());
+        assert!(to_operation(pb::Write { op: None }, 2).is_err());
+    }
+}
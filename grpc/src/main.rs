@@ -0,0 +1,75 @@
+use clap::{App, Arg};
+use noria::ControllerHandle;
+use noria_grpc::pb::controller_server::ControllerServer;
+use noria_grpc::pb::tables_server::TablesServer;
+use noria_grpc::pb::views_server::ViewsServer;
+use noria_grpc::Gateway;
+use std::process;
+use tonic::transport::Server;
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("noria-grpc")
+        .version("0.0.1")
+        .about(
+            "Serves the tables and views of a Noria deployment over gRPC, as described by \
+             proto/noria.proto.",
+        )
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .short("d")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .short("a")
+                .takes_value(true)
+                .default_value("127.0.0.1:50051")
+                .help("The address to serve gRPC on."),
+        )
+        .get_matches();
+
+    let zookeeper_addr = format!(
+        "{}/{}",
+        matches.value_of("zookeeper").unwrap(),
+        matches.value_of("deployment").unwrap()
+    );
+    let addr = match matches.value_of("address").unwrap().parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("invalid --address: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let result: Result<(), failure::Error> = async {
+        let mut handle = ControllerHandle::from_zk(&zookeeper_addr).await?;
+        handle.ready().await?;
+
+        let gateway = Gateway::new(handle);
+        Server::builder()
+            .add_service(ControllerServer::new(gateway.clone()))
+            .add_service(ViewsServer::new(gateway.clone()))
+            .add_service(TablesServer::new(gateway))
+            .serve(addr)
+            .await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        eprintln!("noria-grpc failed: {}", e);
+        process::exit(1);
+    }
+}
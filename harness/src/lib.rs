@@ -0,0 +1,297 @@
+//! End-to-end tests for Noria recipes.
+//!
+//! A test declares a recipe, a sequence of writes, and the contents it expects views to
+//! eventually have:
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() {
+//! use noria_harness::Scenario;
+//!
+//! Scenario::new("cars_by_brand")
+//!     .recipe(
+//!         "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+//!          QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+//!     )
+//!     .insert("Car", vec![1.into(), "Volvo".into()])
+//!     .insert("Car", vec![2.into(), "Volvo".into()])
+//!     .expect(
+//!         "CarsByBrand",
+//!         vec!["Volvo".into()],
+//!         vec![vec![1.into(), "Volvo".into()], vec![2.into(), "Volvo".into()]],
+//!     )
+//!     .delete("Car", vec![1.into()])
+//!     .expect("CarsByBrand", vec!["Volvo".into()], vec![vec![2.into(), "Volvo".into()]])
+//!     .run()
+//!     .await;
+//! # }
+//! ```
+//!
+//! The harness starts a local Noria instance, installs the recipe, and then performs each step in
+//! order. Since Noria is eventually consistent, an expectation is re-checked until it holds or
+//! until the scenario's timeout expires, at which point the test fails with a diff between the
+//! expected and the observed rows.
+#![deny(missing_docs)]
+#![deny(unused_extern_crates)]
+
+use noria_server::{
+    Builder, DataType, DurabilityMode, Handle, LocalAuthority, Modification, PersistenceParameters,
+    TableOperation,
+};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SHARDING: Option<usize> = Some(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+enum Step {
+    Recipe(String),
+    Write {
+        table: String,
+        ops: Vec<TableOperation>,
+    },
+    Update {
+        table: String,
+        key: Vec<DataType>,
+        set: Vec<(usize, DataType)>,
+    },
+    Expect {
+        view: String,
+        key: Vec<DataType>,
+        rows: Vec<Vec<DataType>>,
+    },
+}
+
+/// A recipe, and a sequence of writes and expected view contents to check against it.
+pub struct Scenario {
+    name: String,
+    sharding: Option<usize>,
+    partial: bool,
+    timeout: Duration,
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Start a new scenario.
+    ///
+    /// `name` is used to name the scenario's persistent state, and in failure messages.
+    pub fn new(name: &str) -> Self {
+        Scenario {
+            name: name.to_owned(),
+            sharding: DEFAULT_SHARDING,
+            partial: true,
+            timeout: DEFAULT_TIMEOUT,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Run the scenario with the given number of shards (or unsharded, if `None`).
+    pub fn sharding(mut self, shards: Option<usize>) -> Self {
+        self.sharding = shards;
+        self
+    }
+
+    /// Run the scenario with partial materialization disabled.
+    pub fn full(mut self) -> Self {
+        self.partial = false;
+        self
+    }
+
+    /// Set how long an expectation may take to hold before the scenario fails.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Extend the installed recipe.
+    ///
+    /// Can be used several times to test migrations on a graph that already holds data.
+    pub fn recipe(mut self, recipe: &str) -> Self {
+        self.steps.push(Step::Recipe(recipe.to_owned()));
+        self
+    }
+
+    /// Insert a row into `table`.
+    pub fn insert(self, table: &str, row: Vec<DataType>) -> Self {
+        self.write(table, vec![TableOperation::Insert(row)])
+    }
+
+    /// Delete the row with the given key from `table`.
+    pub fn delete(self, table: &str, key: Vec<DataType>) -> Self {
+        self.write(table, vec![TableOperation::Delete { key }])
+    }
+
+    /// Update the row with the given key in `table`, replacing the given columns.
+    pub fn update(mut self, table: &str, key: Vec<DataType>, set: Vec<(usize, DataType)>) -> Self {
+        self.steps.push(Step::Update {
+            table: table.to_owned(),
+            key,
+            set,
+        });
+        self
+    }
+
+    /// Apply the given operations to `table` in a single write.
+    pub fn write(mut self, table: &str, ops: Vec<TableOperation>) -> Self {
+        self.steps.push(Step::Write {
+            table: table.to_owned(),
+            ops,
+        });
+        self
+    }
+
+    /// Expect a lookup for `key` in `view` to (eventually) return exactly `rows`.
+    ///
+    /// Rows are compared without regard for their order.
+    pub fn expect(mut self, view: &str, key: Vec<DataType>, rows: Vec<Vec<DataType>>) -> Self {
+        self.steps.push(Step::Expect {
+            view: view.to_owned(),
+            key,
+            rows,
+        });
+        self
+    }
+
+    /// Run the scenario against a fresh local Noria instance, panicking if any step fails.
+    pub async fn run(self) {
+        let mut g = self.start().await;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            match *step {
+                Step::Recipe(ref recipe) => {
+                    if let Err(e) = g.extend_recipe(recipe).await {
+                        panic!("{}: step {}: failed to install recipe: {}", self.name, i, e);
+                    }
+                }
+                Step::Write { ref table, ref ops } => {
+                    let mut t = match g.table(table).await {
+                        Ok(t) => t,
+                        Err(e) => panic!("{}: step {}: no table {}: {}", self.name, i, table, e),
+                    };
+                    if let Err(e) = t.perform_all(ops.clone()).await {
+                        panic!(
+                            "{}: step {}: write to {} failed: {}",
+                            self.name, i, table, e
+                        );
+                    }
+                }
+                Step::Update {
+                    ref table,
+                    ref key,
+                    ref set,
+                } => {
+                    let mut t = match g.table(table).await {
+                        Ok(t) => t,
+                        Err(e) => panic!("{}: step {}: no table {}: {}", self.name, i, table, e),
+                    };
+                    let set = set.iter().map(|(c, v)| (*c, Modification::Set(v.clone())));
+                    if let Err(e) = t.update(key.clone(), set).await {
+                        panic!(
+                            "{}: step {}: update of {} failed: {}",
+                            self.name, i, table, e
+                        );
+                    }
+                }
+                Step::Expect {
+                    ref view,
+                    ref key,
+                    ref rows,
+                } => {
+                    if let Err(diff) = self.check(&mut g, view, key, rows).await {
+                        panic!(
+                            "{}: step {}: lookup of {:?} in {} did not return the expected rows \
+                             within {:?}:\n{}",
+                            self.name, i, key, view, self.timeout, diff
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    async fn start(&self) -> Handle<LocalAuthority> {
+        let mut persistence = PersistenceParameters::default();
+        persistence.mode = DurabilityMode::DeleteOnExit;
+        persistence.log_prefix = self.name.clone();
+
+        let mut builder = Builder::default();
+        builder.set_sharding(self.sharding);
+        builder.set_persistence(persistence);
+        if !self.partial {
+            builder.disable_partial();
+        }
+
+        let mut g = builder.start_local().await.unwrap().0;
+        g.backend_ready().await;
+        g
+    }
+
+    /// Look up `key` in `view` until it returns `expected`, or return a diff against the last
+    /// result once the timeout expires.
+    async fn check(
+        &self,
+        g: &mut Handle<LocalAuthority>,
+        view: &str,
+        key: &[DataType],
+        expected: &[Vec<DataType>],
+    ) -> Result<(), String> {
+        let mut expected = expected.to_vec();
+        expected.sort();
+
+        let mut v = g
+            .view(view)
+            .await
+            .map_err(|e| format!("no view {}: {}", view, e))?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut actual: Vec<Vec<DataType>> = v
+                .lookup(key, true)
+                .await
+                .map_err(|e| format!("lookup failed: {}", e))?
+                .into();
+            actual.sort();
+
+            if actual == expected {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(diff_rows(&expected, &actual));
+            }
+
+            tokio::time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Render the difference between two sorted row sets, one row per line.
+fn diff_rows(expected: &[Vec<DataType>], actual: &[Vec<DataType>]) -> String {
+    let expected: Vec<_> = expected.iter().map(|r| format!("{:?}", r)).collect();
+    let actual: Vec<_> = actual.iter().map(|r| format!("{:?}", r)).collect();
+
+    let mut output = String::new();
+    for diff in diff::lines(&expected.join("\n"), &actual.join("\n")) {
+        match diff {
+            diff::Result::Left(l) => writeln!(&mut output, "-{}", l).unwrap(),
+            diff::Result::Both(l, _) => writeln!(&mut output, " {}", l).unwrap(),
+            diff::Result::Right(r) => writeln!(&mut output, "+{}", r).unwrap(),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_diffs_rows() {
+        let expected = vec![vec![1.into()], vec![2.into()]];
+        let actual = vec![vec![2.into()], vec![3.into()]];
+        assert_eq!(
+            diff_rows(&expected, &actual),
+            "-[Int(1)]\n [Int(2)]\n+[Int(3)]\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use noria_harness::Scenario;
+
+#[tokio::test(threaded_scheduler)]
+async fn filter_and_delete() {
+    Scenario::new("filter_and_delete")
+        .recipe(
+            "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+             QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+        )
+        .insert("Car", vec![1.into(), "Volvo".into()])
+        .insert("Car", vec![2.into(), "Volvo".into()])
+        .insert("Car", vec![3.into(), "Saab".into()])
+        .expect(
+            "CarsByBrand",
+            vec!["Volvo".into()],
+            vec![
+                vec![1.into(), "Volvo".into()],
+                vec![2.into(), "Volvo".into()],
+            ],
+        )
+        .delete("Car", vec![1.into()])
+        .expect(
+            "CarsByBrand",
+            vec!["Volvo".into()],
+            vec![vec![2.into(), "Volvo".into()]],
+        )
+        .run()
+        .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn join_sees_updates() {
+    Scenario::new("join_sees_updates")
+        .recipe(
+            "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+             CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(aid, uid));
+             QUERY ArticleVoters: SELECT Article.id, Article.title, Vote.uid \
+                FROM Article JOIN Vote ON (Article.id = Vote.aid) WHERE Article.id = ?;",
+        )
+        .insert("Article", vec![1.into(), "Noria".into()])
+        .insert("Vote", vec![1.into(), 10.into()])
+        .expect(
+            "ArticleVoters",
+            vec![1.into()],
+            vec![vec![1.into(), "Noria".into(), 10.into()]],
+        )
+        .update("Article", vec![1.into()], vec![(1, "Soup".into())])
+        .expect(
+            "ArticleVoters",
+            vec![1.into()],
+            vec![vec![1.into(), "Soup".into(), 10.into()]],
+        )
+        .run()
+        .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn migration_over_existing_data() {
+    Scenario::new("migration_over_existing_data")
+        .sharding(None)
+        .recipe("CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));")
+        .insert("Car", vec![1.into(), "Volvo".into()])
+        .recipe("QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;")
+        .expect(
+            "CarsByBrand",
+            vec!["Volvo".into()],
+            vec![vec![1.into(), "Volvo".into()]],
+        )
+        .run()
+        .await;
+}
+
+#[tokio::test(threaded_scheduler)]
+#[should_panic(expected = "did not return the expected rows")]
+async fn reports_mismatches() {
+    Scenario::new("reports_mismatches")
+        .timeout(std::time::Duration::from_millis(500))
+        .recipe(
+            "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+             QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+        )
+        .insert("Car", vec![1.into(), "Volvo".into()])
+        .expect("CarsByBrand", vec!["Volvo".into()], vec![])
+        .run()
+        .await;
+}
@@ -1,17 +1,139 @@
+//! The data and write types shared by Noria clients and servers.
+//!
+//! This crate only contains the types needed to construct rows and writes, so that producers that
+//! do not need the full [`noria`](https://docs.rs/noria) client (and its async runtime) can build
+//! them. Most users should use them through the re-exports in `noria` instead.
+//!
+//! # Features
+//!
+//!  - `serde-1`: implements `Serialize` and `Deserialize` for all types, as needed to send them
+//!    to Noria.
+//!  - `sql`: conversions from `nom_sql` literals.
+//!  - `mysql`: conversions from `mysql_common` values.
+//!  - `json`: conversions to and from `serde_json` values.
+//!  - `compression`: text values and packets compressed with LZ4 or Zstandard.
+#![deny(missing_docs)]
+#![deny(unreachable_pub)]
+#![warn(rust_2018_idioms)]
+
 use arccstr::ArcCStr;
 
-use chrono::{self, NaiveDate, NaiveDateTime};
+use chrono::{self, NaiveDateTime};
 
+#[cfg(feature = "sql")]
 use nom_sql::Literal;
 
+#[cfg(feature = "serde-1")]
+use serde::{Deserialize, Serialize, Serializer};
+
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::{Add, Div, Mul, Sub};
+#[cfg(feature = "compression")]
+use std::sync::Arc;
+
+#[cfg(feature = "serde-1")]
+pub mod sparse;
 
 const FLOAT_PRECISION: f64 = 1_000_000_000.0;
 const TINYTEXT_WIDTH: usize = 15;
 
+/// The most digits a [`DataType::Decimal`] can have after its decimal point, as in MySQL.
+pub const MAX_DECIMAL_SCALE: u8 = 30;
+
+/// The number of digits that division adds after the decimal point of the dividend, as MySQL's
+/// `div_precision_increment` does by default.
+const DECIMAL_DIV_INCREMENT: u8 = 4;
+
+/// Text values of at least this many bytes are compressed by [`DataType::compress`].
+#[cfg(feature = "compression")]
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// The zstd level that [`Compression::Zstd`] compresses at.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// A codec that values and packets can be compressed with.
+#[cfg(feature = "compression")]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// LZ4, which is cheap to compress and decompress.
+    Lz4,
+    /// Zstandard, which compresses better than LZ4 at the cost of more CPU.
+    Zstd,
+}
+
+#[cfg(feature = "compression")]
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Lz4
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Compression {
+    /// Compress `bytes` with this codec.
+    pub fn compress(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Lz4 => lz4_flex::compress_prepend_size(bytes),
+            Compression::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL).unwrap(),
+        }
+    }
+
+    /// Decompress `bytes` that were compressed with this codec.
+    pub fn decompress(self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(bytes).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            }),
+            Compression::Zstd => zstd::decode_all(bytes),
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Compression::Lz4 => 0,
+            Compression::Zstd => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Self {
+        match tag {
+            0 => Compression::Lz4,
+            1 => Compression::Zstd,
+            _ => unreachable!("unknown compression tag {}", tag),
+        }
+    }
+}
+
+/// Compressed text, as produced by [`DataType::compress_with`].
+///
+/// The first byte says which codec the rest was compressed with.
+#[cfg(feature = "compression")]
+#[derive(Clone)]
+pub struct CompressedText(Arc<Vec<u8>>);
+
+#[cfg(feature = "compression")]
+impl CompressedText {
+    /// The number of bytes the compressed text takes up.
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The codec the text was compressed with.
+    pub fn codec(&self) -> Compression {
+        Compression::from_tag(self.0[0])
+    }
+
+    fn decompress(&self) -> ArcCStr {
+        let bytes = self.codec().decompress(&self.0[1..]).unwrap();
+        ArcCStr::try_from(&bytes[..]).unwrap()
+    }
+}
+
 /// The main type used for user data throughout the codebase.
 ///
 /// Having this be an enum allows for our code to be agnostic about the types of user data except
@@ -20,7 +142,8 @@ const TINYTEXT_WIDTH: usize = 15;
 /// Note that cloning a `DataType` using the `Clone` trait is possible, but may result in cache
 /// contention on the reference counts for de-duplicated strings. Use `DataType::deep_clone` to
 /// clone the *value* of a `DataType` without danger of contention.
-#[derive(Eq, Clone, Serialize, Deserialize)]
+#[derive(Eq, Clone)]
+#[cfg_attr(feature = "serde-1", derive(Deserialize))]
 #[warn(variant_size_differences)]
 pub enum DataType {
     /// An empty value.
@@ -42,6 +165,72 @@ pub enum DataType {
     TinyText([u8; TINYTEXT_WIDTH]),
     /// A timestamp for date/time types.
     Timestamp(NaiveDateTime),
+    /// A JSON document, kept as its text.
+    Json(ArcCStr),
+    /// A fixed-point decimal number. The first field is the number without its decimal point, and
+    /// the second is the number of digits after the decimal point, so `Decimal(1250, 2)` is
+    /// `12.50`. Unlike `Real`, decimals are added, subtracted, and multiplied exactly.
+    ///
+    /// Decimals compare by their value, also with integers and reals, so `12.50` is equal to
+    /// `12.5`, and `3.0` is equal to `3`.
+    Decimal(i64, u8),
+    /// A text value that is kept compressed in memory. It is serialized as `Text`, and otherwise
+    /// compares and hashes like the text it holds, but cannot be borrowed as a `&str`.
+    #[cfg(feature = "compression")]
+    #[cfg_attr(feature = "serde-1", serde(skip_deserializing))]
+    Compressed(CompressedText),
+}
+
+#[cfg(feature = "serde-1")]
+impl Serialize for DataType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTupleVariant;
+        match *self {
+            DataType::None => serializer.serialize_unit_variant("DataType", 0, "None"),
+            DataType::Int(ref n) => serializer.serialize_newtype_variant("DataType", 1, "Int", n),
+            DataType::UnsignedInt(ref n) => {
+                serializer.serialize_newtype_variant("DataType", 2, "UnsignedInt", n)
+            }
+            DataType::BigInt(ref n) => {
+                serializer.serialize_newtype_variant("DataType", 3, "BigInt", n)
+            }
+            DataType::UnsignedBigInt(ref n) => {
+                serializer.serialize_newtype_variant("DataType", 4, "UnsignedBigInt", n)
+            }
+            DataType::Real(ref i, ref f) => {
+                let mut v = serializer.serialize_tuple_variant("DataType", 5, "Real", 2)?;
+                v.serialize_field(i)?;
+                v.serialize_field(f)?;
+                v.end()
+            }
+            DataType::Text(ref t) => serializer.serialize_newtype_variant("DataType", 6, "Text", t),
+            DataType::TinyText(ref t) => {
+                serializer.serialize_newtype_variant("DataType", 7, "TinyText", t)
+            }
+            DataType::Timestamp(ref ts) => {
+                serializer.serialize_newtype_variant("DataType", 8, "Timestamp", ts)
+            }
+            DataType::Json(ref j) => serializer.serialize_newtype_variant("DataType", 9, "Json", j),
+            DataType::Decimal(ref n, ref scale) => {
+                let mut v = serializer.serialize_tuple_variant("DataType", 10, "Decimal", 2)?;
+                v.serialize_field(n)?;
+                v.serialize_field(scale)?;
+                v.end()
+            }
+            // compressed text only ever lives in memory, so it is decompressed on the way out
+            #[cfg(feature = "compression")]
+            DataType::Compressed(ref c) => {
+                serializer.serialize_newtype_variant("DataType", 6, "Text", &c.decompress())
+            }
+        }
+    }
+}
+
+/// The default value is `DataType::None`, i.e., SQL's NULL.
+impl Default for DataType {
+    fn default() -> Self {
+        DataType::None
+    }
 }
 
 impl fmt::Display for DataType {
@@ -66,6 +255,19 @@ impl fmt::Display for DataType {
                 }
             }
             DataType::Timestamp(ts) => write!(f, "{}", ts.format("%c")),
+            DataType::Json(..) => {
+                let json: &str = self.into();
+                write!(f, "{}", json)
+            }
+            DataType::Decimal(n, 0) => write!(f, "{}", n),
+            DataType::Decimal(n, scale) => {
+                let sign = if n < 0 { "-" } else { "" };
+                let n = i128::from(n).abs();
+                let p = 10i128.pow(u32::from(scale));
+                write!(f, "{}{}.{:03$}", sign, n / p, n % p, usize::from(scale))
+            }
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => write!(f, "{}", self.decompress()),
         }
     }
 }
@@ -83,11 +285,22 @@ impl fmt::Debug for DataType {
                 write!(f, "TinyText({:?})", text)
             }
             DataType::Timestamp(ts) => write!(f, "Timestamp({:?})", ts),
+            DataType::Json(..) => {
+                let json: &str = self.into();
+                write!(f, "Json({})", json)
+            }
             DataType::Real(..) => write!(f, "Real({})", self),
+            DataType::Decimal(..) => write!(f, "Decimal({})", self),
             DataType::Int(n) => write!(f, "Int({})", n),
             DataType::UnsignedInt(n) => write!(f, "UnsignedInt({})", n),
             DataType::BigInt(n) => write!(f, "BigInt({})", n),
             DataType::UnsignedBigInt(n) => write!(f, "UnsignedBigInt({})", n),
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => {
+                let text = self.decompress();
+                let text: &str = (&*text).into();
+                write!(f, "Compressed({:?})", text)
+            }
         }
     }
 }
@@ -100,10 +313,54 @@ impl DataType {
     pub fn deep_clone(&self) -> Self {
         match *self {
             DataType::Text(ref cstr) => DataType::Text(ArcCStr::from(&**cstr)),
+            DataType::Json(ref cstr) => DataType::Json(ArcCStr::from(&**cstr)),
+            #[cfg(feature = "compression")]
+            DataType::Compressed(ref c) => {
+                DataType::Compressed(CompressedText(Arc::new((*c.0).clone())))
+            }
+            ref dt => dt.clone(),
+        }
+    }
+
+    /// Compress this value with LZ4 if it is text of at least [`COMPRESSION_THRESHOLD`] bytes,
+    /// and otherwise return it as-is.
+    #[cfg(feature = "compression")]
+    pub fn compress(&self) -> Self {
+        self.compress_with(Compression::Lz4)
+    }
+
+    /// Compress this value with `codec` if it is text of at least [`COMPRESSION_THRESHOLD`]
+    /// bytes, and otherwise return it as-is.
+    #[cfg(feature = "compression")]
+    pub fn compress_with(&self, codec: Compression) -> Self {
+        match *self {
+            DataType::Text(ref cstr) if cstr.to_bytes().len() >= COMPRESSION_THRESHOLD => {
+                let mut c = vec![codec.tag()];
+                c.extend(codec.compress(cstr.to_bytes()));
+                DataType::Compressed(CompressedText(Arc::new(c)))
+            }
             ref dt => dt.clone(),
         }
     }
 
+    /// The uncompressed form of this value.
+    pub fn decompress(&self) -> Cow<'_, Self> {
+        match *self {
+            #[cfg(feature = "compression")]
+            DataType::Compressed(ref c) => Cow::Owned(DataType::Text(c.decompress())),
+            ref dt => Cow::Borrowed(dt),
+        }
+    }
+
+    /// Checks if this value is `DataType::Compressed`.
+    pub fn is_compressed(&self) -> bool {
+        match *self {
+            #[cfg(feature = "compression")]
+            DataType::Compressed(_) => true,
+            _ => false,
+        }
+    }
+
     /// Checks if this value is `DataType::None`.
     pub fn is_none(&self) -> bool {
         match *self {
@@ -128,6 +385,81 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is a fixed-point decimal.
+    pub fn is_decimal(&self) -> bool {
+        match *self {
+            DataType::Decimal(..) => true,
+            _ => false,
+        }
+    }
+
+    /// The decimal written as `text`, such as `-12.50`, if it is one.
+    ///
+    /// The decimal keeps as many digits after its decimal point as `text` has, up to
+    /// [`MAX_DECIMAL_SCALE`].
+    pub fn decimal(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (negative, digits) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (integral, fractional) = match digits.find('.') {
+            Some(i) => (&digits[..i], &digits[i + 1..]),
+            None => (digits, ""),
+        };
+        if (integral.is_empty() && fractional.is_empty())
+            || !integral
+                .bytes()
+                .chain(fractional.bytes())
+                .all(|b| b.is_ascii_digit())
+            || fractional.len() > usize::from(MAX_DECIMAL_SCALE)
+        {
+            return None;
+        }
+
+        let mut n: i128 = 0;
+        for b in integral.bytes().chain(fractional.bytes()) {
+            n = n.checked_mul(10)?.checked_add(i128::from(b - b'0'))?;
+        }
+        let n = if negative { -n } else { n };
+        decimal(n, fractional.len() as u8)
+    }
+
+    /// The exact value of an integer or a decimal, as the number without its decimal point and
+    /// the number of digits after the decimal point. Integers have no digits after the point.
+    ///
+    /// Returns `None` for values of other types.
+    pub fn to_decimal(&self) -> Option<(i128, u8)> {
+        match *self {
+            DataType::Int(n) => Some((i128::from(n), 0)),
+            DataType::UnsignedInt(n) => Some((i128::from(n), 0)),
+            DataType::BigInt(n) => Some((i128::from(n), 0)),
+            DataType::UnsignedBigInt(n) => Some((i128::from(n), 0)),
+            DataType::Decimal(n, scale) => Some((i128::from(n), scale)),
+            _ => None,
+        }
+    }
+
+    /// The exact value of an integer, decimal, or real, as returned by `to_decimal`. Reals are
+    /// fixed-point numbers with nine digits after the decimal point, so they have one too.
+    fn exact(&self) -> Option<(i128, u8)> {
+        match *self {
+            DataType::Real(i, f) => Some((i128::from(i) * 1_000_000_000 + i128::from(f), 9)),
+            _ => self.to_decimal(),
+        }
+    }
+
+    /// The decimal `n` with `scale` digits after its decimal point, as returned by
+    /// [`DataType::to_decimal`].
+    ///
+    /// Trailing zeros after the decimal point are dropped if that is what it takes to make the
+    /// value fit, and this panics if it still does not.
+    pub fn from_decimal(n: i128, scale: u8) -> Self {
+        decimal(n, scale)
+            .unwrap_or_else(|| panic!("can't fit {}e-{} in a DataType::Decimal", n, scale))
+    }
+
     /// Checks if this value is of a string data type (i.e., can be converted into `String` and
     /// `&str`).
     pub fn is_string(&self) -> bool {
@@ -137,6 +469,37 @@ impl DataType {
         }
     }
 
+    /// Checks if this value is a JSON document.
+    pub fn is_json(&self) -> bool {
+        match *self {
+            DataType::Json(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A JSON document with the given text, if it is valid JSON.
+    #[cfg(feature = "json")]
+    pub fn json(text: &str) -> Option<Self> {
+        serde_json::from_str::<serde_json::Value>(text)
+            .ok()
+            .map(DataType::from)
+    }
+
+    /// The JSON value of this document, or of the JSON text it holds.
+    ///
+    /// Returns `None` for values that are neither JSON documents nor text that is valid JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Option<serde_json::Value> {
+        match *self {
+            DataType::Json(..) | DataType::Text(..) | DataType::TinyText(..) => {
+                serde_json::from_str(self.into()).ok()
+            }
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => self.decompress().to_json(),
+            _ => None,
+        }
+    }
+
     /// Checks if this values is of a timestamp data type.
     pub fn is_datetime(&self) -> bool {
         match *self {
@@ -144,6 +507,34 @@ impl DataType {
             _ => false,
         }
     }
+
+    /// The timestamp this value is, or that the text it holds describes.
+    ///
+    /// Text may be a date (`2020-03-04`) or a date and time (`2020-03-04 12:34:56`, or with a `T`
+    /// in place of the space), optionally with fractional seconds. Times followed by a UTC offset
+    /// (`Z`, `+02:00`, or `-0500`) are converted to UTC, which is what timestamps are kept in.
+    /// Returns `None` for values of other types, and for text that is not a timestamp.
+    pub fn to_timestamp(&self) -> Option<NaiveDateTime> {
+        let t = match *self {
+            DataType::Timestamp(ts) => return Some(ts),
+            DataType::Text(..) | DataType::TinyText(..) => <&str>::from(self),
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => return self.decompress().to_timestamp(),
+            _ => return None,
+        };
+        let t = t.trim().replacen(' ', "T", 1);
+        if let Ok(ts) = t.parse::<NaiveDateTime>() {
+            return Some(ts);
+        }
+        if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(&t)
+            .or_else(|_| chrono::DateTime::parse_from_str(&t, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        {
+            return Some(ts.naive_utc());
+        }
+        t.parse::<chrono::NaiveDate>()
+            .ok()
+            .map(|d| d.and_hms(0, 0, 0))
+    }
 }
 
 impl PartialEq for DataType {
@@ -161,6 +552,18 @@ impl PartialEq for DataType {
         }
 
         match (self, other) {
+            // compression is deterministic, so equal texts compress to the same bytes with the
+            // same codec
+            #[cfg(feature = "compression")]
+            (&DataType::Compressed(ref a), &DataType::Compressed(ref b))
+                if a.codec() == b.codec() =>
+            {
+                a.0 == b.0
+            }
+            #[cfg(feature = "compression")]
+            (&DataType::Compressed(..), _) => *self.decompress() == *other,
+            #[cfg(feature = "compression")]
+            (_, &DataType::Compressed(..)) => *self == *other.decompress(),
             (&DataType::Text(ref a), &DataType::Text(ref b)) => a == b,
             (&DataType::TinyText(ref a), &DataType::TinyText(ref b)) => a == b,
             (&DataType::Text(..), &DataType::TinyText(..))
@@ -191,6 +594,14 @@ impl PartialEq for DataType {
             }
             (&DataType::Real(ai, af), &DataType::Real(bi, bf)) => ai == bi && af == bf,
             (&DataType::Timestamp(tsa), &DataType::Timestamp(tsb)) => tsa == tsb,
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a == b,
+            // decimals are equal to the numbers with the same value
+            (&DataType::Decimal(..), _) | (_, &DataType::Decimal(..)) => {
+                match (self.exact(), other.exact()) {
+                    (Some(a), Some(b)) => cmp_decimals(a, b) == Ordering::Equal,
+                    _ => false,
+                }
+            }
             (&DataType::None, &DataType::None) => true,
 
             _ => false,
@@ -208,6 +619,10 @@ impl PartialOrd for DataType {
 impl Ord for DataType {
     fn cmp(&self, other: &DataType) -> Ordering {
         match (self, other) {
+            #[cfg(feature = "compression")]
+            (&DataType::Compressed(..), _) => self.decompress().as_ref().cmp(other),
+            #[cfg(feature = "compression")]
+            (_, &DataType::Compressed(..)) => self.cmp(other.decompress().as_ref()),
             (&DataType::Text(ref a), &DataType::Text(ref b)) => a.cmp(b),
             (&DataType::TinyText(ref a), &DataType::TinyText(ref b)) => a.cmp(b),
             (&DataType::Text(..), &DataType::TinyText(..))
@@ -240,16 +655,24 @@ impl Ord for DataType {
                 ai.cmp(bi).then_with(|| af.cmp(bf))
             }
             (&DataType::Timestamp(tsa), &DataType::Timestamp(ref tsb)) => tsa.cmp(tsb),
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a.cmp(b),
+            (&DataType::Decimal(..), _) | (_, &DataType::Decimal(..))
+                if self.exact().is_some() && other.exact().is_some() =>
+            {
+                cmp_decimals(self.exact().unwrap(), other.exact().unwrap())
+            }
             (&DataType::None, &DataType::None) => Ordering::Equal,
 
-            // order Ints, Reals, Text, Timestamps, None
+            // order Ints and Decimals, Reals, Text, Timestamps, JSON, None
             (&DataType::Int(..), _)
             | (&DataType::UnsignedInt(..), _)
             | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
+            | (&DataType::UnsignedBigInt(..), _)
+            | (&DataType::Decimal(..), _) => Ordering::Greater,
             (&DataType::Real(..), _) => Ordering::Greater,
             (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
             (&DataType::Timestamp(..), _) => Ordering::Greater,
+            (&DataType::Json(..), _) => Ordering::Greater,
             (&DataType::None, _) => Ordering::Greater,
         }
     }
@@ -270,19 +693,130 @@ impl Hash for DataType {
                 let n: u64 = self.into();
                 n.hash(state)
             }
-            DataType::Real(i, f) => {
-                i.hash(state);
-                f.hash(state);
+            DataType::Real(..) | DataType::Decimal(..) => {
+                // decimals are equal to the integers and reals with the same value, so the
+                // trailing zeros that make them differ are dropped, and integral values hash
+                // like the integer.
+                let (n, scale) = self.exact().unwrap();
+                let (n, scale) = normalize_decimal(n, scale);
+                if scale == 0 {
+                    (n as i64).hash(state);
+                } else {
+                    n.hash(state);
+                    scale.hash(state);
+                }
             }
-            DataType::Text(..) | DataType::TinyText(..) => {
+            DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => {
                 let t: &str = self.into();
                 t.hash(state)
             }
             DataType::Timestamp(ts) => ts.hash(state),
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => self.decompress().hash(state),
         }
     }
 }
 
+/// `n` with `scale` digits after the decimal point, without trailing zeros after the point.
+fn normalize_decimal(mut n: i128, mut scale: u8) -> (i128, u8) {
+    while scale > 0 && n % 10 == 0 {
+        n /= 10;
+        scale -= 1;
+    }
+    (n, scale)
+}
+
+/// `n` with `scale` digits after the decimal point, rounded half away from zero to `to` digits.
+fn round_decimal(n: i128, scale: u8, to: u8) -> i128 {
+    if scale <= to {
+        return n;
+    }
+    let p = 10i128.pow(u32::from(scale - to));
+    let (q, r) = (n / p, n % p);
+    if r.abs() * 2 >= p {
+        q + n.signum()
+    } else {
+        q
+    }
+}
+
+/// The decimal `n` with `scale` digits after the decimal point, if it fits in a
+/// `DataType::Decimal` after dropping trailing zeros after the point as needed.
+fn decimal(mut n: i128, mut scale: u8) -> Option<DataType> {
+    if scale > MAX_DECIMAL_SCALE {
+        n = round_decimal(n, scale, MAX_DECIMAL_SCALE);
+        scale = MAX_DECIMAL_SCALE;
+    }
+    if i64::try_from(n).is_err() {
+        let (m, s) = normalize_decimal(n, scale);
+        n = m;
+        scale = s;
+    }
+    i64::try_from(n).ok().map(|n| DataType::Decimal(n, scale))
+}
+
+/// Compare two exact values as returned by `DataType::to_decimal`.
+fn cmp_decimals((a, sa): (i128, u8), (b, sb): (i128, u8)) -> Ordering {
+    // the integral parts are compared first, so that scaling the fractional parts to the same
+    // number of digits cannot overflow.
+    let (pa, pb) = (10i128.pow(u32::from(sa)), 10i128.pow(u32::from(sb)));
+    let scale = u32::from(sa.max(sb));
+    (a / pa).cmp(&(b / pb)).then_with(|| {
+        let fa = a % pa * 10i128.pow(scale - u32::from(sa));
+        let fb = b % pb * 10i128.pow(scale - u32::from(sb));
+        fa.cmp(&fb)
+    })
+}
+
+/// Exact arithmetic on two values as returned by `DataType::to_decimal`.
+///
+/// Sums, differences, and products are exact, while quotients get
+/// [`DECIMAL_DIV_INCREMENT`] more digits after the decimal point than the dividend has, and are
+/// rounded. Dividing by zero gives `NULL`.
+fn decimal_arithmetic(op: &str, a: (i128, u8), b: (i128, u8)) -> DataType {
+    if op == "/" && b.0 == 0 {
+        return DataType::None;
+    }
+    exact_arithmetic(op, a, b)
+        .and_then(|(n, scale)| decimal(n, scale))
+        .unwrap_or_else(|| {
+            panic!(
+                "decimal overflow in {}e-{} {} {}e-{}",
+                a.0, a.1, op, b.0, b.1
+            )
+        })
+}
+
+/// The exact result of `a op b`, before it is made to fit a `DataType::Decimal`.
+fn exact_arithmetic(op: &str, (a, sa): (i128, u8), (b, sb): (i128, u8)) -> Option<(i128, u8)> {
+    let rescale = |n: i128, from: u8, to: u8| {
+        10i128
+            .checked_pow(u32::from(to - from))
+            .and_then(|p| n.checked_mul(p))
+    };
+
+    match op {
+        "+" | "-" => {
+            let scale = sa.max(sb);
+            let (a, b) = (rescale(a, sa, scale)?, rescale(b, sb, scale)?);
+            let n = if op == "+" {
+                a.checked_add(b)
+            } else {
+                a.checked_sub(b)
+            };
+            Some((n?, scale))
+        }
+        "*" => Some((a.checked_mul(b)?, sa + sb)),
+        "/" => {
+            // compute one more digit than we keep, to round by
+            let scale = (sa + DECIMAL_DIV_INCREMENT).min(MAX_DECIMAL_SCALE);
+            let n = rescale(a, sa, scale + sb + 1)? / b;
+            Some((round_decimal(n, scale + 1, scale), scale))
+        }
+        _ => unreachable!("unknown operator {}", op),
+    }
+}
+
 impl<T> From<Option<T>> for DataType
 where
     DataType: From<T>,
@@ -367,6 +901,7 @@ impl<'a> From<&'a DataType> for DataType {
     }
 }
 
+#[cfg(feature = "sql")]
 impl<'a> From<&'a Literal> for DataType {
     fn from(l: &'a Literal) -> Self {
         match *l {
@@ -374,7 +909,7 @@ impl<'a> From<&'a Literal> for DataType {
             Literal::Integer(i) => (i as i64).into(),
             Literal::String(ref s) => s.as_str().into(),
             Literal::CurrentTimestamp => {
-                let ts = chrono::Local::now().naive_local();
+                let ts = chrono::Utc::now().naive_utc();
                 DataType::Timestamp(ts)
             }
             Literal::FixedPoint(ref r) => {
@@ -385,6 +920,7 @@ impl<'a> From<&'a Literal> for DataType {
     }
 }
 
+#[cfg(feature = "sql")]
 impl From<Literal> for DataType {
     fn from(l: Literal) -> Self {
         (&l).into()
@@ -404,7 +940,7 @@ impl From<NaiveDateTime> for DataType {
 impl<'a> From<&'a DataType> for &'a str {
     fn from(data: &'a DataType) -> Self {
         match *data {
-            DataType::Text(ref s) => s.to_str().unwrap(),
+            DataType::Text(ref s) | DataType::Json(ref s) => s.to_str().unwrap(),
             DataType::TinyText(ref bts) => {
                 if bts[TINYTEXT_WIDTH - 1] == 0 {
                     // NULL terminated CStr
@@ -517,6 +1053,7 @@ impl From<&'_ DataType> for f64 {
     fn from(data: &'_ DataType) -> Self {
         match *data {
             DataType::Real(i, f) => i as f64 + f64::from(f) / FLOAT_PRECISION,
+            DataType::Decimal(n, scale) => n as f64 / 10f64.powi(i32::from(scale)),
             DataType::Int(i) => f64::from(i),
             DataType::BigInt(i) => i as f64,
             _ => panic!("attempted to convert a {:?} to an f64", data),
@@ -524,6 +1061,14 @@ impl From<&'_ DataType> for f64 {
     }
 }
 
+#[cfg(feature = "json")]
+impl From<serde_json::Value> for DataType {
+    fn from(v: serde_json::Value) -> Self {
+        // serde_json escapes NUL bytes in strings, so the text never contains one
+        DataType::Json(ArcCStr::try_from(v.to_string().as_bytes()).unwrap())
+    }
+}
+
 impl From<String> for DataType {
     fn from(s: String) -> Self {
         DataType::try_from(s.as_bytes()).unwrap()
@@ -558,6 +1103,7 @@ impl<'a> TryFrom<&'a [u8]> for DataType {
     }
 }
 
+#[cfg(feature = "mysql")]
 impl TryFrom<mysql_common::value::Value> for DataType {
     type Error = &'static str;
 
@@ -573,12 +1119,8 @@ impl TryFrom<mysql_common::value::Value> for DataType {
             Value::Double(v) => Ok(v.into()),
             Value::Date(year, month, day, hour, minutes, seconds, micros) => {
                 Ok(DataType::Timestamp(
-                    NaiveDate::from_ymd(year.into(), month.into(), day.into()).and_hms_micro(
-                        hour.into(),
-                        minutes.into(),
-                        seconds.into(),
-                        micros.into(),
-                    ),
+                    chrono::NaiveDate::from_ymd(year.into(), month.into(), day.into())
+                        .and_hms_micro(hour.into(), minutes.into(), seconds.into(), micros.into()),
                 ))
             }
             Value::Time(..) => Err("`mysql_common::value::Value::time` is not supported in Noria"),
@@ -586,6 +1128,31 @@ impl TryFrom<mysql_common::value::Value> for DataType {
     }
 }
 
+#[cfg(feature = "mysql")]
+impl<'a> From<&'a DataType> for mysql_common::value::Value {
+    fn from(dt: &'a DataType) -> Self {
+        use mysql_common::value::Value;
+
+        match *dt {
+            DataType::None => Value::NULL,
+            DataType::Int(n) => Value::Int(n.into()),
+            DataType::UnsignedInt(n) => Value::UInt(n.into()),
+            DataType::BigInt(n) => Value::Int(n),
+            DataType::UnsignedBigInt(n) => Value::UInt(n),
+            DataType::Real(..) => Value::Double(dt.into()),
+            // MySQL sends decimals as text, so that they don't lose any digits
+            DataType::Decimal(..) => Value::Bytes(dt.to_string().into_bytes()),
+            DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => {
+                let s: &str = dt.into();
+                Value::Bytes(s.as_bytes().to_vec())
+            }
+            #[cfg(feature = "compression")]
+            DataType::Compressed(..) => (&*dt.decompress()).into(),
+            DataType::Timestamp(ts) => ts.into(),
+        }
+    }
+}
+
 // Performs an arithmetic operation on two numeric DataTypes,
 // returning a new DataType as the result.
 macro_rules! arithmetic_operation (
@@ -606,6 +1173,13 @@ macro_rules! arithmetic_operation (
             (&DataType::UnsignedBigInt(a), &DataType::UnsignedInt(b)) => (a $op u64::from(b)).into(),
             (&DataType::UnsignedInt(a), &DataType::UnsignedBigInt(b)) => (u64::from(a) $op b).into(),
 
+            (first, second) if (first.is_decimal() && second.to_decimal().is_some())
+                || (second.is_decimal() && first.to_decimal().is_some()) => {
+                let a = first.to_decimal().unwrap();
+                let b = second.to_decimal().unwrap();
+                decimal_arithmetic(stringify!($op), a, b)
+            }
+
             (first @ &DataType::Int(..), second @ &DataType::Real(..)) |
             (first @ &DataType::BigInt(..), second @ &DataType::Real(..)) |
             (first @ &DataType::UnsignedInt(..), second @ &DataType::Real(..)) |
@@ -614,6 +1188,8 @@ macro_rules! arithmetic_operation (
             (first @ &DataType::Real(..), second @ &DataType::BigInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedInt(..)) |
             (first @ &DataType::Real(..), second @ &DataType::UnsignedBigInt(..)) |
+            (first @ &DataType::Decimal(..), second @ &DataType::Real(..)) |
+            (first @ &DataType::Real(..), second @ &DataType::Decimal(..)) |
             (first @ &DataType::Real(..), second @ &DataType::Real(..)) => {
                 let a: f64 = first.into();
                 let b: f64 = second.into();
@@ -664,7 +1240,8 @@ impl<'a, 'b> Div<&'b DataType> for &'a DataType {
 }
 
 /// A modification to make to an existing value.
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
 pub enum Operation {
     /// Add the given value to the existing one.
     Add,
@@ -673,7 +1250,8 @@ pub enum Operation {
 }
 
 /// A modification to make to a column in an existing row.
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
 pub enum Modification {
     /// Set the cell to this value.
     Set(DataType),
@@ -683,6 +1261,59 @@ pub enum Modification {
     None,
 }
 
+/// How a column is compared to a value in a [`Condition`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
+pub enum Comparison {
+    /// The column equals the value.
+    Equal,
+    /// The column does not equal the value.
+    NotEqual,
+    /// The column is less than the value.
+    Less,
+    /// The column is less than or equal to the value.
+    LessOrEqual,
+    /// The column is greater than the value.
+    Greater,
+    /// The column is greater than or equal to the value.
+    GreaterOrEqual,
+}
+
+/// A comparison of one of the columns of a row against a value.
+///
+/// Deletes and updates that select rows by conditions rather than by key apply to every row that
+/// satisfies all of their conditions.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
+pub struct Condition {
+    /// The index of the column to compare.
+    pub column: usize,
+    /// How the column is compared to `value`.
+    pub comparison: Comparison,
+    /// The value to compare the column to.
+    pub value: DataType,
+}
+
+impl Condition {
+    /// True if `row` satisfies this condition.
+    ///
+    /// As in SQL, no comparison with NULL is satisfied.
+    pub fn matches(&self, row: &[DataType]) -> bool {
+        let v = &row[self.column];
+        if v.is_none() || self.value.is_none() {
+            return false;
+        }
+        match self.comparison {
+            Comparison::Equal => *v == self.value,
+            Comparison::NotEqual => *v != self.value,
+            Comparison::Less => *v < self.value,
+            Comparison::LessOrEqual => *v <= self.value,
+            Comparison::Greater => *v > self.value,
+            Comparison::GreaterOrEqual => *v >= self.value,
+        }
+    }
+}
+
 impl<T> From<T> for Modification
 where
     T: Into<DataType>,
@@ -693,7 +1324,8 @@ where
 }
 
 /// An operation to apply to a base table.
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
 pub enum TableOperation {
     /// Insert the contained row.
     Insert(Vec<DataType>),
@@ -717,6 +1349,18 @@ pub enum TableOperation {
         /// The key used to identify the row to update.
         key: Vec<DataType>,
     },
+    /// Delete every row that satisfies all of the given conditions.
+    DeleteWhere {
+        /// The conditions that the rows to delete satisfy.
+        conditions: Vec<Condition>,
+    },
+    /// Update every row that satisfies all of the given conditions.
+    UpdateWhere {
+        /// The modifications to make to each column of the matching rows.
+        set: Vec<Modification>,
+        /// The conditions that the rows to update satisfy.
+        conditions: Vec<Condition>,
+    },
 }
 
 impl TableOperation {
@@ -741,8 +1385,10 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "mysql")]
     fn mysql_value_to_datatype() {
         use assert_approx_eq::assert_approx_eq;
+        use chrono::NaiveDate;
         use mysql_common::value::Value;
 
         // Test Value::NULL.
@@ -805,6 +1451,24 @@ mod tests {
         assert!(a_dt.is_err());
     }
 
+    #[test]
+    #[cfg(feature = "mysql")]
+    fn datatype_to_mysql_value() {
+        use mysql_common::value::Value;
+
+        assert_eq!(Value::from(&DataType::None), Value::NULL);
+        assert_eq!(Value::from(&DataType::from(-5)), Value::Int(-5));
+        assert_eq!(
+            Value::from(&DataType::from("hi")),
+            Value::Bytes(b"hi".to_vec())
+        );
+        // decimals go out as text, as MySQL sends them
+        let dec = DataType::decimal("-12.50").unwrap();
+        assert_eq!(Value::from(&dec), Value::Bytes(b"-12.50".to_vec()));
+        let back = DataType::try_from(Value::from(&dec)).unwrap();
+        assert_eq!(DataType::decimal((&back).into()), Some(dec));
+    }
+
     #[test]
     fn real_to_string() {
         let a: DataType = (2.5).into();
@@ -1192,4 +1856,208 @@ mod tests {
         assert_ne!(hash(&long), hash(&time));
         assert_ne!(hash(&long), hash(&shrt6));
     }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_text() {
+        use std::cmp::Ordering;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        fn hash(dt: &DataType) -> u64 {
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        }
+
+        let short = DataType::from("short");
+        assert!(!short.compress().is_compressed());
+
+        let text = DataType::from("a".repeat(COMPRESSION_THRESHOLD * 4));
+        let compressed = text.compress();
+        assert!(compressed.is_compressed());
+        match compressed {
+            DataType::Compressed(ref c) => assert!(c.size() < COMPRESSION_THRESHOLD),
+            _ => unreachable!(),
+        }
+        assert_eq!(*compressed.decompress(), text);
+        assert_eq!(compressed, text);
+        assert_eq!(text, compressed);
+        assert_eq!(compressed, text.compress());
+        assert_eq!(hash(&compressed), hash(&text));
+        assert_eq!(compressed.cmp(&text), Ordering::Equal);
+        assert_ne!(
+            compressed,
+            DataType::from("b".repeat(COMPRESSION_THRESHOLD * 4)).compress()
+        );
+        assert_eq!(format!("{}", compressed), format!("{}", text));
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_text_codecs() {
+        let text = DataType::from("a".repeat(COMPRESSION_THRESHOLD * 4));
+        let lz4 = text.compress_with(Compression::Lz4);
+        let zstd = text.compress_with(Compression::Zstd);
+        match (&lz4, &zstd) {
+            (DataType::Compressed(ref a), DataType::Compressed(ref b)) => {
+                assert_eq!(a.codec(), Compression::Lz4);
+                assert_eq!(b.codec(), Compression::Zstd);
+                assert!(b.size() < COMPRESSION_THRESHOLD);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(*zstd.decompress(), text);
+        assert_eq!(zstd, text);
+        assert_eq!(zstd, lz4);
+        assert_eq!(lz4, zstd);
+
+        let bytes = b"some bytes, some bytes, some bytes";
+        for &codec in &[Compression::Lz4, Compression::Zstd] {
+            let c = codec.compress(&bytes[..]);
+            assert_eq!(codec.decompress(&c).unwrap(), &bytes[..]);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde-1", feature = "compression"))]
+    fn compressed_text_serializes_as_text() {
+        let text = DataType::from("a".repeat(COMPRESSION_THRESHOLD * 4));
+        let compressed = text.compress();
+        assert_eq!(
+            bincode::serialize(&compressed).unwrap(),
+            bincode::serialize(&text).unwrap()
+        );
+        let back: DataType =
+            bincode::deserialize(&bincode::serialize(&compressed).unwrap()).unwrap();
+        assert!(!back.is_compressed());
+        assert_eq!(back, text);
+    }
+
+    #[test]
+    fn decimals() {
+        use std::cmp::Ordering;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        fn hash(dt: &DataType) -> u64 {
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        }
+        let d = |s| DataType::decimal(s).unwrap();
+
+        assert_eq!(d("12.50"), DataType::Decimal(1250, 2));
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("+7").to_string(), "7");
+        assert_eq!(format!("{:?}", d("1.10")), "Decimal(1.10)");
+        assert_eq!(DataType::decimal("1.2.3"), None);
+        assert_eq!(DataType::decimal("-"), None);
+        assert_eq!(DataType::decimal("1e3"), None);
+
+        // decimals compare by value, also with integers
+        assert_eq!(d("12.50"), d("12.5"));
+        assert_eq!(hash(&d("12.50")), hash(&d("12.5")));
+        assert_eq!(d("3.00"), DataType::from(3));
+        assert_eq!(hash(&d("3.00")), hash(&DataType::from(3)));
+        assert_ne!(d("3.01"), DataType::from(3));
+        assert_eq!(d("-1.5").cmp(&d("-1.25")), Ordering::Less);
+        assert_eq!(d("2.50"), DataType::from(2.5));
+        assert_eq!(hash(&d("2.50")), hash(&DataType::from(2.5)));
+        assert_eq!(d("9.995").cmp(&DataType::from(9.99)), Ordering::Greater);
+        assert_eq!(d("-0.5").cmp(&d("0.5")), Ordering::Less);
+        assert_eq!(d("2.001").cmp(&DataType::from(2)), Ordering::Greater);
+        assert_eq!(
+            d("0.000000000000000000000000000001").cmp(&DataType::BigInt(std::i64::MAX)),
+            Ordering::Less
+        );
+
+        // arithmetic is exact
+        let sum = (0..10).fold(d("0"), |sum, _| &sum + &d("0.1"));
+        assert_eq!(sum, DataType::from(1));
+        assert_eq!(sum.to_string(), "1.0");
+        assert_eq!((&d("19.99") - &DataType::from(20)).to_string(), "-0.01");
+        assert_eq!((&d("1.5") * &d("1.25")).to_string(), "1.875");
+        assert_eq!((&DataType::from(10) / &d("3")).to_string(), "3.3333");
+        assert_eq!((&d("2.00") / &d("3")).to_string(), "0.666667");
+        assert_eq!((&d("-1") / &d("8")).to_string(), "-0.1250");
+        assert_eq!(&d("1") / &d("0.00"), DataType::None);
+        assert_eq!(&d("0.5") + &DataType::from(0.25), DataType::from(0.75));
+
+        assert_eq!(d("-12.50").to_decimal(), Some((-1250, 2)));
+        assert_eq!(DataType::from(4).to_decimal(), Some((4, 0)));
+        assert_eq!(DataType::from(0.5).to_decimal(), None);
+        assert_eq!(
+            DataType::from_decimal(1_000_000_000_000_000_000_000, 5).to_string(),
+            "10000000000000000"
+        );
+        let f: f64 = (&d("-2.25")).into();
+        assert!((f + 2.25).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    #[cfg(feature = "serde-1")]
+    fn decimals_serialize() {
+        let dec = DataType::decimal("-1234.5678").unwrap();
+        let back: DataType = bincode::deserialize(&bincode::serialize(&dec).unwrap()).unwrap();
+        assert_eq!(back, dec);
+        assert!(back.is_decimal());
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "serde-1"))]
+    fn json_documents() {
+        let doc = DataType::json(r#"{"a": [1, "two"]}"#).unwrap();
+        assert!(doc.is_json());
+        assert_eq!(format!("{}", doc), r#"{"a":[1,"two"]}"#);
+        assert_eq!(doc.to_json().unwrap()["a"][1], "two");
+        assert_eq!(
+            DataType::from("[1, 2]").to_json(),
+            Some(serde_json::json!([1, 2]))
+        );
+        assert_eq!(DataType::json("{nope"), None);
+        assert_eq!(DataType::from(1).to_json(), None);
+
+        let back: DataType = bincode::deserialize(&bincode::serialize(&doc).unwrap()).unwrap();
+        assert_eq!(back, doc);
+        assert!(back.is_json());
+    }
+
+    #[test]
+    fn timestamps_parse_in_utc() {
+        use chrono::NaiveDate;
+
+        let noon = NaiveDate::from_ymd(2020, 3, 4).and_hms(12, 0, 0);
+        let ts = |t: &str| DataType::from(t).to_timestamp();
+        assert_eq!(ts("2020-03-04 12:00:00"), Some(noon));
+        assert_eq!(ts("2020-03-04T12:00:00Z"), Some(noon));
+        assert_eq!(ts("2020-03-04T14:00:00+02:00"), Some(noon));
+        assert_eq!(ts("2020-03-04 07:00:00.0-0500"), Some(noon));
+        assert_eq!(ts("2020-03-04"), Some(noon.date().and_hms(0, 0, 0)));
+        assert_eq!(ts("noon"), None);
+        assert_eq!(DataType::from(noon).to_timestamp(), Some(noon));
+        assert_eq!(DataType::from(12).to_timestamp(), None);
+    }
+
+    #[test]
+    fn conditions_match_like_sql() {
+        let cond = |comparison, value: DataType| Condition {
+            column: 1,
+            comparison,
+            value,
+        };
+        let row = vec![DataType::from(1), DataType::from(10), DataType::None];
+        assert!(cond(Comparison::Equal, 10.into()).matches(&row));
+        assert!(cond(Comparison::GreaterOrEqual, 10.into()).matches(&row));
+        assert!(cond(Comparison::Less, 11.into()).matches(&row));
+        assert!(!cond(Comparison::NotEqual, 10.into()).matches(&row));
+        assert!(!cond(Comparison::NotEqual, DataType::None).matches(&row));
+
+        let null = Condition {
+            column: 2,
+            comparison: Comparison::NotEqual,
+            value: 10.into(),
+        };
+        assert!(!null.matches(&row));
+    }
 }
@@ -0,0 +1,165 @@
+//! A compact encoding for rows in which many columns are NULL.
+//!
+//! A row is encoded as-is unless enough of its columns are `DataType::None` that leaving them out,
+//! and recording which columns are present in a bitmap instead, takes less space. Use it through
+//! `#[serde(with = "noria_types::sparse")]` on a `Vec<DataType>`, or by wrapping a row in a
+//! [`SparseRow`].
+
+use crate::DataType;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+
+#[derive(Serialize)]
+enum Encoded<'a> {
+    Dense(&'a [DataType]),
+    Sparse {
+        columns: usize,
+        present: Vec<u8>,
+        values: Vec<&'a DataType>,
+    },
+}
+
+#[derive(Deserialize)]
+enum Decoded {
+    Dense(Vec<DataType>),
+    Sparse {
+        columns: usize,
+        present: Vec<u8>,
+        values: Vec<DataType>,
+    },
+}
+
+/// True if `row` takes less space encoded sparsely.
+///
+/// Every NULL left out saves the four bytes of its tag, while the sparse form costs two more
+/// lengths and a bit for every column.
+fn is_sparse(row: &[DataType]) -> bool {
+    let nulls = row.iter().filter(|dt| dt.is_none()).count();
+    4 * nulls > 16 + (row.len() + 7) / 8
+}
+
+fn is_present(present: &[u8], column: usize) -> bool {
+    present
+        .get(column / 8)
+        .map(|b| b & (1 << (column % 8)) != 0)
+        .unwrap_or(false)
+}
+
+/// Serialize `row`, leaving out its NULLs if that makes it smaller.
+pub fn serialize<R, S>(row: &R, serializer: S) -> Result<S::Ok, S::Error>
+where
+    R: AsRef<[DataType]>,
+    S: Serializer,
+{
+    let row = row.as_ref();
+    if !is_sparse(row) {
+        return Encoded::Dense(row).serialize(serializer);
+    }
+
+    let mut present = vec![0u8; (row.len() + 7) / 8];
+    let mut values = Vec::with_capacity(row.len());
+    for (i, dt) in row.iter().enumerate() {
+        if !dt.is_none() {
+            present[i / 8] |= 1 << (i % 8);
+            values.push(dt);
+        }
+    }
+    Encoded::Sparse {
+        columns: row.len(),
+        present,
+        values,
+    }
+    .serialize(serializer)
+}
+
+/// Deserialize a row written by [`serialize`], filling in the NULLs that were left out.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<DataType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Decoded::deserialize(deserializer)? {
+        Decoded::Dense(row) => Ok(row),
+        Decoded::Sparse {
+            columns,
+            present,
+            values,
+        } => {
+            let expected = (0..columns).filter(|&i| is_present(&present, i)).count();
+            if values.len() != expected {
+                return Err(de::Error::invalid_length(
+                    values.len(),
+                    &"one value for every present column",
+                ));
+            }
+
+            let mut values = values.into_iter();
+            Ok((0..columns)
+                .map(|i| {
+                    if is_present(&present, i) {
+                        values.next().unwrap()
+                    } else {
+                        DataType::default()
+                    }
+                })
+                .collect())
+        }
+    }
+}
+
+/// A row that is serialized with the sparse encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseRow<'a>(pub Cow<'a, [DataType]>);
+
+impl Serialize for SparseRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SparseRow<'static> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(|row| SparseRow(Cow::Owned(row)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(row: Vec<DataType>) -> usize {
+        let raw = bincode::serialize(&SparseRow(Cow::Borrowed(&row))).unwrap();
+        let back: SparseRow<'_> = bincode::deserialize(&raw).unwrap();
+        assert_eq!(back.0, row);
+        raw.len()
+    }
+
+    #[test]
+    fn it_round_trips_dense_rows() {
+        let row: Vec<DataType> = vec![1.into(), "a".into(), DataType::None];
+        assert_eq!(
+            round_trip(row.clone()),
+            bincode::serialize(&row).unwrap().len() + 4
+        );
+    }
+
+    #[test]
+    fn it_shrinks_sparse_rows() {
+        let mut row = vec![DataType::None; 40];
+        row[3] = 7.into();
+        row[38] = "x".into();
+        assert!(round_trip(row.clone()) < bincode::serialize(&row).unwrap().len());
+        assert_eq!(round_trip(vec![DataType::None; 64]), 4 + 8 + 8 + 8 + 8);
+    }
+
+    #[test]
+    fn it_rejects_missing_values() {
+        let raw = bincode::serialize(&Encoded::Sparse {
+            columns: 9,
+            present: vec![0b1000_0001, 0b1],
+            values: vec![&DataType::from(1), &DataType::from(2)],
+        })
+        .unwrap();
+        assert!(bincode::deserialize::<SparseRow<'_>>(&raw).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::DataType;
+
+/// A property that every row of a view must have.
+///
+/// Assertions are checked against the rows added to a view as it is maintained, so that bugs in
+/// view maintenance are caught before they reach clients. See `ControllerHandle::add_assertion`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Assertion {
+    /// The column is never NULL, such as the key of a view.
+    NotNull {
+        /// The column to check.
+        column: String,
+    },
+    /// The column is never negative, such as a count that removals should never take below zero.
+    ///
+    /// NULL and non-numeric values are not checked.
+    NonNegative {
+        /// The column to check.
+        column: String,
+    },
+}
+
+impl Assertion {
+    /// The column that is checked.
+    pub fn column(&self) -> &str {
+        match *self {
+            Assertion::NotNull { ref column } | Assertion::NonNegative { ref column } => column,
+        }
+    }
+
+    /// True if `value`, a value of the checked column, has the asserted property.
+    pub fn holds(&self, value: &DataType) -> bool {
+        match *self {
+            Assertion::NotNull { .. } => !value.is_none(),
+            Assertion::NonNegative { .. } => match *value {
+                DataType::Int(n) => n >= 0,
+                DataType::BigInt(n) => n >= 0,
+                DataType::Real(i, f) => i >= 0 && f >= 0,
+                DataType::Decimal(n, _) => n >= 0,
+                _ => true,
+            },
+        }
+    }
+}
+
+/// A row that violated one of a view's assertions as it was being added to the view.
+///
+/// See `ControllerHandle::quarantine`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    /// The name of the assertion that was violated.
+    pub assertion: String,
+    /// The offending row.
+    pub row: Vec<DataType>,
+    /// The changes to the view's rows for the offending row's key that arrived along with it, and
+    /// whether each was added (`true`) or removed (`false`).
+    pub delta: Vec<(Vec<DataType>, bool)>,
+    /// Whether the changes in `delta` were kept out of the view.
+    pub halted: bool,
+    /// The operators that the checked column passed through on its way to the view, starting at
+    /// the view and ending where the column's values originate, for each way it may have taken.
+    pub provenance: Vec<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_values() {
+        let non_negative = Assertion::NonNegative {
+            column: "c".to_owned(),
+        };
+        assert!(non_negative.holds(&0.into()));
+        assert!(!non_negative.holds(&(-1).into()));
+        assert!(!non_negative.holds(&DataType::Real(0, -500_000_000)));
+        assert!(non_negative.holds(&DataType::None));
+
+        let not_null = Assertion::NotNull {
+            column: "c".to_owned(),
+        };
+        assert!(!not_null.holds(&DataType::None));
+        assert!(not_null.holds(&"".into()));
+    }
+}
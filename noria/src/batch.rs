@@ -0,0 +1,135 @@
+use crate::{DataType, TableOperation};
+
+/// Writes to several base tables that are applied in the order they were added.
+///
+/// Pass a batch to `ControllerHandle::write_batch`. A write is only sent once every earlier write
+/// in the batch has been applied by its base table, so a row that refers to another, such as a
+/// child row and its parent, can be added after the row it refers to and will never be applied
+/// before it. Consecutive writes to the same table are sent together.
+///
+/// A batch can also be passed to `ControllerHandle::write_atomically`, which does not order its
+/// writes, but has views show all of them at once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    writes: Vec<(String, Vec<TableOperation>)>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an operation on the named table.
+    pub fn perform<O>(&mut self, table: &str, op: O) -> &mut Self
+    where
+        O: Into<TableOperation>,
+    {
+        let op = op.into();
+        if let Some((t, ops)) = self.writes.last_mut() {
+            if *t == table {
+                ops.push(op);
+                return self;
+            }
+        }
+        self.writes.push((table.to_owned(), vec![op]));
+        self
+    }
+
+    /// Add an insert of `row` into the named table.
+    pub fn insert<V>(&mut self, table: &str, row: V) -> &mut Self
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.perform(table, TableOperation::Insert(row.into()))
+    }
+
+    /// Add a delete of the row with the given key from the named table.
+    pub fn delete<K>(&mut self, table: &str, key: K) -> &mut Self
+    where
+        K: Into<Vec<DataType>>,
+    {
+        self.perform(table, TableOperation::Delete { key: key.into() })
+    }
+
+    /// The number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.writes.iter().map(|(_, ops)| ops.len()).sum()
+    }
+
+    /// True if the batch contains no operations.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// The names of the tables the batch writes to, without duplicates.
+    pub(crate) fn tables(&self) -> Vec<&str> {
+        let mut tables: Vec<&str> = Vec::new();
+        for (t, _) in &self.writes {
+            if !tables.contains(&t.as_str()) {
+                tables.push(t);
+            }
+        }
+        tables
+    }
+
+    /// The runs of consecutive operations on the same table, in order.
+    pub(crate) fn into_runs(self) -> Vec<(String, Vec<TableOperation>)> {
+        self.writes
+    }
+
+    /// All the operations on each table, in order, with the tables in the order they were first
+    /// written to.
+    pub(crate) fn into_tables(self) -> Vec<(String, Vec<TableOperation>)> {
+        let mut tables: Vec<(String, Vec<TableOperation>)> = Vec::new();
+        for (t, ops) in self.writes {
+            match tables.iter_mut().find(|(other, _)| *other == t) {
+                Some((_, all)) => all.extend(ops),
+                None => tables.push((t, ops)),
+            }
+        }
+        tables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_writes_are_grouped() {
+        let mut batch = WriteBatch::new();
+        batch
+            .insert("parent", vec![DataType::from(1)])
+            .insert("parent", vec![DataType::from(2)])
+            .insert("child", vec![DataType::from(10), DataType::from(1)])
+            .delete("parent", vec![DataType::from(2)]);
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.tables(), vec!["parent", "child"]);
+
+        let tables: Vec<_> = batch
+            .clone()
+            .into_tables()
+            .into_iter()
+            .map(|(t, ops)| (t, ops.len()))
+            .collect();
+        assert_eq!(
+            tables,
+            vec![("parent".to_owned(), 3), ("child".to_owned(), 1)]
+        );
+
+        let runs: Vec<_> = batch
+            .into_runs()
+            .into_iter()
+            .map(|(t, ops)| (t, ops.len()))
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("parent".to_owned(), 2),
+                ("child".to_owned(), 1),
+                ("parent".to_owned(), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Reading rows from Parquet and Arrow IPC files, and writing rows to Parquet files, for
+//! [`Table::bulk_load`](crate::Table::bulk_load) and
+//! [`ControllerHandle::export_view`](crate::ControllerHandle::export_view).
+
+use crate::DataType;
+use arrow::array::{
+    Array, ArrayRef, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray,
+};
+use arrow::compute::kernels::cast::cast;
+use arrow::datatypes::{DataType as ArrowType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use chrono::NaiveDateTime;
+use parquet_rs::arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader};
+use parquet_rs::file::reader::SerializedFileReader;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of rows read from, or written to, a file at a time.
+const BATCH_SIZE: usize = 8192;
+
+/// Read the rows of the Parquet or Arrow IPC file at `path`, with their values in the order of
+/// `columns`.
+///
+/// The file's columns are matched to `columns` by name, and those of `columns` that the file does
+/// not have are left NULL.
+pub(crate) fn read_rows(path: &Path, columns: &[String]) -> Result<Vec<Vec<DataType>>, String> {
+    let mut magic = [0; 6];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> =
+        if magic.starts_with(b"PAR1") {
+            let reader = SerializedFileReader::new(file).map_err(|e| e.to_string())?;
+            let mut reader = ParquetFileArrowReader::new(Arc::new(reader));
+            Box::new(
+                reader
+                    .get_record_reader(BATCH_SIZE)
+                    .map_err(|e| e.to_string())?,
+            )
+        } else if &magic == b"ARROW1" {
+            Box::new(FileReader::try_new(file).map_err(|e| e.to_string())?)
+        } else {
+            return Err(format!(
+                "{} is neither a Parquet nor an Arrow IPC file",
+                path.display()
+            ));
+        };
+
+    let mut rows = Vec::new();
+    for batch in batches {
+        let batch = batch.map_err(|e| e.to_string())?;
+        let schema = batch.schema();
+        if let Some(f) = schema.fields().iter().find(|f| !columns.contains(f.name())) {
+            return Err(format!("unknown column {}", f.name()));
+        }
+        let mut values = columns
+            .iter()
+            .map(|c| match schema.index_of(c) {
+                Ok(i) => column_values(batch.column(i)).map(|vs| Some(vs.into_iter())),
+                Err(_) => Ok(None),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for _ in 0..batch.num_rows() {
+            rows.push(
+                values
+                    .iter_mut()
+                    .map(|vs| {
+                        vs.as_mut()
+                            .and_then(Iterator::next)
+                            .unwrap_or(DataType::None)
+                    })
+                    .collect(),
+            );
+        }
+    }
+    Ok(rows)
+}
+
+/// The values of an Arrow column.
+///
+/// Booleans and integers become integers, floating point numbers become reals, and timestamps
+/// become timestamps. Other types are not supported.
+fn column_values(array: &ArrayRef) -> Result<Vec<DataType>, String> {
+    let (target, unit) = match array.data_type() {
+        ArrowType::Boolean
+        | ArrowType::Int8
+        | ArrowType::Int16
+        | ArrowType::Int32
+        | ArrowType::Int64
+        | ArrowType::UInt8
+        | ArrowType::UInt16
+        | ArrowType::UInt32
+        | ArrowType::UInt64 => (ArrowType::Int64, None),
+        ArrowType::Float16 | ArrowType::Float32 | ArrowType::Float64 => (ArrowType::Float64, None),
+        ArrowType::Utf8 | ArrowType::LargeUtf8 => (ArrowType::Utf8, None),
+        ArrowType::Timestamp(unit, _) => (ArrowType::Int64, Some(unit.clone())),
+        t => return Err(format!("unsupported column type {:?}", t)),
+    };
+    let array = cast(array, &target).map_err(|e| e.to_string())?;
+    let values = (0..array.len()).map(|i| {
+        if array.is_null(i) {
+            return DataType::None;
+        }
+        match target {
+            ArrowType::Float64 => {
+                let floats = array.as_any().downcast_ref::<Float64Array>().unwrap();
+                floats.value(i).into()
+            }
+            ArrowType::Utf8 => {
+                let strings = array.as_any().downcast_ref::<StringArray>().unwrap();
+                strings.value(i).into()
+            }
+            _ => {
+                let ints = array.as_any().downcast_ref::<Int64Array>().unwrap();
+                match unit {
+                    Some(ref unit) => timestamp(ints.value(i), unit),
+                    None => ints.value(i).into(),
+                }
+            }
+        }
+    });
+    Ok(values.collect())
+}
+
+/// The timestamp `v` units after the epoch.
+fn timestamp(v: i64, unit: &TimeUnit) -> DataType {
+    let per_sec = match *unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Millisecond => 1_000,
+        TimeUnit::Microsecond => 1_000_000,
+        TimeUnit::Nanosecond => 1_000_000_000,
+    };
+    let nanos = v.rem_euclid(per_sec) * (1_000_000_000 / per_sec);
+    NaiveDateTime::from_timestamp(v.div_euclid(per_sec), nanos as u32).into()
+}
+
+/// Write `rows`, whose values are in the order of `columns`, to a Parquet file at `path`.
+pub(crate) fn write_parquet(
+    path: &Path,
+    columns: &[String],
+    rows: &[Vec<DataType>],
+) -> Result<(), String> {
+    let types: Vec<_> = (0..columns.len())
+        .map(|c| arrow_type(rows.iter().map(|r| &r[c])))
+        .collect();
+    let fields = columns
+        .iter()
+        .zip(&types)
+        .map(|(c, t)| Field::new(c, t.clone(), true))
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+
+    let file =
+        File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), None).map_err(|e| e.to_string())?;
+    for rows in rows.chunks(BATCH_SIZE) {
+        let arrays = types
+            .iter()
+            .enumerate()
+            .map(|(c, t)| column_array(t, rows.iter().map(|r| &r[c])))
+            .collect();
+        let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(|e| e.to_string())?;
+        writer.write(&batch).map_err(|e| e.to_string())?;
+    }
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The Arrow type of a column that holds `values`.
+///
+/// That is integers or timestamps if all values are, floating point numbers if they are numbers
+/// but not all integers, and text otherwise.
+fn arrow_type<'a>(values: impl Iterator<Item = &'a DataType>) -> ArrowType {
+    let mut t = None;
+    for v in values {
+        let vt = match *v {
+            DataType::None => continue,
+            DataType::UnsignedBigInt(n) if n > i64::max_value() as u64 => return ArrowType::Utf8,
+            DataType::Int(_)
+            | DataType::UnsignedInt(_)
+            | DataType::BigInt(_)
+            | DataType::UnsignedBigInt(_) => ArrowType::Int64,
+            DataType::Real(..) | DataType::Decimal(..) => ArrowType::Float64,
+            DataType::Timestamp(_) => ArrowType::Timestamp(TimeUnit::Microsecond, None),
+            _ => return ArrowType::Utf8,
+        };
+        t = match (t, vt) {
+            (None, vt) => Some(vt),
+            (Some(t), vt) if t == vt => Some(t),
+            (Some(ArrowType::Int64), ArrowType::Float64)
+            | (Some(ArrowType::Float64), ArrowType::Int64) => Some(ArrowType::Float64),
+            _ => return ArrowType::Utf8,
+        };
+    }
+    t.unwrap_or(ArrowType::Utf8)
+}
+
+/// An Arrow column of type `t`, as chosen by `arrow_type`, that holds `values`.
+fn column_array<'a>(t: &ArrowType, values: impl Iterator<Item = &'a DataType>) -> ArrayRef {
+    let int = |v: &DataType| match *v {
+        DataType::Int(n) => Some(i64::from(n)),
+        DataType::UnsignedInt(n) => Some(i64::from(n)),
+        DataType::BigInt(n) => Some(n),
+        DataType::UnsignedBigInt(n) => Some(n as i64),
+        _ => None,
+    };
+    match *t {
+        ArrowType::Int64 => Arc::new(Int64Array::from(values.map(int).collect::<Vec<_>>())),
+        ArrowType::Float64 => {
+            let floats = values.map(|v| match *v {
+                DataType::None => None,
+                DataType::Real(..) | DataType::Decimal(..) => Some(f64::from(v)),
+                _ => int(v).map(|n| n as f64),
+            });
+            Arc::new(Float64Array::from(floats.collect::<Vec<_>>()))
+        }
+        ArrowType::Timestamp(..) => {
+            let micros = values.map(|v| match *v {
+                DataType::Timestamp(ts) => {
+                    Some(ts.timestamp() * 1_000_000 + i64::from(ts.timestamp_subsec_micros()))
+                }
+                _ => None,
+            });
+            Arc::new(TimestampMicrosecondArray::from(micros.collect::<Vec<_>>()))
+        }
+        _ => {
+            let strings: Vec<_> = values
+                .map(|v| {
+                    if v.is_none() {
+                        None
+                    } else {
+                        Some(v.to_string())
+                    }
+                })
+                .collect();
+            Arc::new(StringArray::from(
+                strings.iter().map(Option::as_deref).collect::<Vec<_>>(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parquet_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rows.parquet");
+        let columns = vec!["id".to_owned(), "score".to_owned(), "name".to_owned()];
+        let rows: Vec<Vec<DataType>> = vec![
+            vec![1.into(), 1.5.into(), "a".into()],
+            vec![2.into(), 2.into(), DataType::None],
+        ];
+        write_parquet(&path, &columns, &rows).unwrap();
+
+        let back = read_rows(&path, &columns).unwrap();
+        assert_eq!(back[0], rows[0]);
+        assert_eq!(back[1][0], 2.into());
+        assert_eq!(f64::from(&back[1][1]), 2.0);
+        assert_eq!(back[1][2], DataType::None);
+
+        // columns the file does not have are NULL, and columns only the file has are an error
+        let more = vec![
+            "id".to_owned(),
+            "score".to_owned(),
+            "name".to_owned(),
+            "x".to_owned(),
+        ];
+        assert_eq!(read_rows(&path, &more).unwrap()[0][3], DataType::None);
+        assert!(read_rows(&path, &columns[..2]).is_err());
+    }
+}
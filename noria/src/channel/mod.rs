@@ -24,6 +24,19 @@ pub use self::tcp::{DualTcpStream, TcpSender};
 pub const CONNECTION_FROM_BASE: u8 = 1;
 pub const CONNECTION_FROM_DOMAIN: u8 = 2;
 
+/// What a connection that writes to a base table starts with: its tag, followed by the length of
+/// the credentials the writer presents as a big-endian `u16`, and then the credentials.
+///
+/// The worker issues every write on the connection as whoever the credentials belong to.
+pub fn base_preamble(credentials: Option<&str>) -> Vec<u8> {
+    let credentials = credentials.unwrap_or("").as_bytes();
+    assert!(credentials.len() <= u16::max_value() as usize);
+    let mut preamble = vec![CONNECTION_FROM_BASE];
+    preamble.extend_from_slice(&(credentials.len() as u16).to_be_bytes());
+    preamble.extend_from_slice(credentials);
+    preamble
+}
+
 pub struct Remote;
 pub struct MaybeLocal;
 
@@ -87,32 +100,34 @@ where
 {
     pub fn build_async(
         self,
-    ) -> io::Result<AsyncBincodeWriter<BufWriter<tokio::net::TcpStream>, T, AsyncDestination>> {
+    ) -> io::Result<AsyncBincodeWriter<BufWriter<crate::tls::Stream>, T, AsyncDestination>> {
         // TODO: async
-        // we must currently write and call flush, because the remote end (currently) does a
-        // synchronous read upon accepting a connection.
-        let s = self.build_sync()?.into_inner().into_inner()?;
-
-        tokio::net::TcpStream::from_std(s)
-            .map(BufWriter::new)
-            .map(AsyncBincodeWriter::from)
-            .map(AsyncBincodeWriter::for_async)
+        let s = tcp::connect_from(self.sport, &self.addr)?;
+        let s = tokio::net::TcpStream::from_std(s)?;
+        // the remote end reads the first byte to tell connections from bases apart
+        let s = crate::tls::Stream::client_with(s, self.preamble());
+        Ok(AsyncBincodeWriter::from(BufWriter::new(s)).for_async())
     }
 
     pub fn build_sync(self) -> io::Result<TcpSender<T>> {
         let mut s = TcpSender::connect_from(self.sport, &self.addr)?;
         {
+            let preamble = self.preamble();
             let s = s.get_mut();
-            s.write_all(&[if self.is_for_base {
-                CONNECTION_FROM_BASE
-            } else {
-                CONNECTION_FROM_DOMAIN
-            }])?;
+            s.write_all(&preamble)?;
             s.flush()?;
         }
 
         Ok(s)
     }
+
+    fn preamble(&self) -> Vec<u8> {
+        if self.is_for_base {
+            base_preamble(None)
+        } else {
+            vec![CONNECTION_FROM_DOMAIN]
+        }
+    }
 }
 
 pub trait Sender {
@@ -3,6 +3,8 @@ use std::io::{self, Write};
 use std::marker::PhantomData;
 use std::net::{Ipv4Addr, SocketAddr};
 
+use crate::table::WriteReply;
+use crate::tls::SyncStream;
 use crate::Tagged;
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use bufstream::BufStream;
@@ -52,57 +54,41 @@ macro_rules! poisoning_try {
 }
 
 pub struct TcpSender<T> {
-    stream: BufStream<std::net::TcpStream>,
+    stream: BufStream<SyncStream>,
     poisoned: bool,
 
     phantom: PhantomData<T>,
 }
 
 impl<T: Serialize> TcpSender<T> {
+    /// A sender on `stream`, which is encrypted if TLS is turned on.
     pub fn new(stream: std::net::TcpStream) -> Result<Self, io::Error> {
         stream.set_nodelay(true).unwrap();
         Ok(Self {
-            stream: BufStream::new(stream),
+            stream: BufStream::new(SyncStream::client(stream)),
             poisoned: false,
             phantom: PhantomData,
         })
     }
 
     pub(crate) fn connect_from(sport: Option<u16>, addr: &SocketAddr) -> Result<Self, io::Error> {
-        let f = move || {
-            let s = net2::TcpBuilder::new_v4()?
-                .reuse_address(true)?
-                .bind((Ipv4Addr::UNSPECIFIED, sport.unwrap_or(0)))?
-                .connect(addr)?;
-            s.set_nodelay(true)?;
-            Self::new(s)
-        };
-
-        if tokio::runtime::Handle::try_current().is_ok() {
-            tokio::task::block_in_place(f)
-        } else {
-            f()
-        }
+        Self::new(connect_from(sport, addr)?)
     }
 
     pub fn connect(addr: &SocketAddr) -> Result<Self, io::Error> {
         Self::connect_from(None, addr)
     }
 
-    pub fn get_mut(&mut self) -> &mut BufStream<std::net::TcpStream> {
+    pub fn get_mut(&mut self) -> &mut BufStream<SyncStream> {
         &mut self.stream
     }
 
-    pub(crate) fn into_inner(self) -> BufStream<std::net::TcpStream> {
-        self.stream
-    }
-
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.stream.get_ref().local_addr()
+        self.stream.get_ref().get_ref().local_addr()
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.stream.get_ref().peer_addr()
+        self.stream.get_ref().get_ref().peer_addr()
     }
 
     /// Send a message on this channel. Ownership isn't actually required, but is taken anyway to
@@ -136,6 +122,27 @@ impl<T: Serialize> TcpSender<T> {
     }
 }
 
+/// Open a TCP connection to `addr`, from the local port `sport` if given.
+pub(crate) fn connect_from(
+    sport: Option<u16>,
+    addr: &SocketAddr,
+) -> Result<std::net::TcpStream, io::Error> {
+    let f = move || {
+        let s = net2::TcpBuilder::new_v4()?
+            .reuse_address(true)?
+            .bind((Ipv4Addr::UNSPECIFIED, sport.unwrap_or(0)))?
+            .connect(addr)?;
+        s.set_nodelay(true)?;
+        Ok(s)
+    };
+
+    if tokio::runtime::Handle::try_current().is_ok() {
+        tokio::task::block_in_place(f)
+    } else {
+        f()
+    }
+}
+
 impl<T: Serialize> super::Sender for TcpSender<T> {
     type Item = T;
     fn send(&mut self, t: T) -> Result<(), SendError> {
@@ -158,9 +165,9 @@ pub enum RecvError {
 
 #[pin_project(project = DualTcpStreamProj)]
 pub enum DualTcpStream<S, T, T2, D> {
-    Passthrough(#[pin] AsyncBincodeStream<S, T, Tagged<()>, D>),
+    Passthrough(#[pin] AsyncBincodeStream<S, T, Tagged<WriteReply>, D>),
     Upgrade(
-        #[pin] AsyncBincodeStream<S, T2, Tagged<()>, D>,
+        #[pin] AsyncBincodeStream<S, T2, Tagged<WriteReply>, D>,
         Box<dyn FnMut(T2) -> T + Send + Sync>,
     ),
 }
@@ -173,7 +180,7 @@ impl<S, T, T2> From<S> for DualTcpStream<S, T, T2, AsyncDestination> {
 
 impl<S, T, T2> DualTcpStream<S, T, T2, AsyncDestination> {
     pub fn upgrade<F: 'static + FnMut(T2) -> T + Send + Sync>(stream: S, f: F) -> Self {
-        let s: AsyncBincodeStream<S, T2, Tagged<()>, AsyncDestination> =
+        let s: AsyncBincodeStream<S, T2, Tagged<WriteReply>, AsyncDestination> =
             AsyncBincodeStream::from(stream).for_async();
         DualTcpStream::Upgrade(s, Box::new(f))
     }
@@ -186,11 +193,13 @@ impl<S, T, T2> DualTcpStream<S, T, T2, AsyncDestination> {
     }
 }
 
-impl<S, T, T2, D> Sink<Tagged<()>> for DualTcpStream<S, T, T2, D>
+impl<S, T, T2, D> Sink<Tagged<WriteReply>> for DualTcpStream<S, T, T2, D>
 where
     S: AsyncWrite,
-    AsyncBincodeStream<S, T, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
-    AsyncBincodeStream<S, T2, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
+    AsyncBincodeStream<S, T, Tagged<WriteReply>, D>:
+        Sink<Tagged<WriteReply>, Error = bincode::Error>,
+    AsyncBincodeStream<S, T2, Tagged<WriteReply>, D>:
+        Sink<Tagged<WriteReply>, Error = bincode::Error>,
 {
     type Error = bincode::Error;
 
@@ -201,7 +210,7 @@ where
         }
     }
 
-    fn start_send(self: Pin<&mut Self>, item: Tagged<()>) -> Result<(), Self::Error> {
+    fn start_send(self: Pin<&mut Self>, item: Tagged<WriteReply>) -> Result<(), Self::Error> {
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.start_send(item),
             DualTcpStreamProj::Upgrade(abs, _) => abs.start_send(item),
@@ -228,8 +237,8 @@ where
     for<'a> T: Deserialize<'a>,
     for<'a> T2: Deserialize<'a>,
     S: AsyncRead,
-    AsyncBincodeStream<S, T, Tagged<()>, D>: Stream<Item = Result<T, bincode::Error>>,
-    AsyncBincodeStream<S, T2, Tagged<()>, D>: Stream<Item = Result<T2, bincode::Error>>,
+    AsyncBincodeStream<S, T, Tagged<WriteReply>, D>: Stream<Item = Result<T, bincode::Error>>,
+    AsyncBincodeStream<S, T2, Tagged<WriteReply>, D>: Stream<Item = Result<T2, bincode::Error>>,
 {
     type Item = Result<T, bincode::Error>;
 
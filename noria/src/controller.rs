@@ -1,14 +1,40 @@
+use crate::assertion::{Assertion, Violation};
+use crate::batch::WriteBatch;
+#[cfg(feature = "parquet")]
+use crate::bulk;
 use crate::consensus::{self, Authority};
+use crate::debug::oracle::{Divergence, ReferenceRows};
+use crate::debug::profile::Profile;
+use crate::debug::replays::SlowReplay;
 use crate::debug::stats;
-use crate::table::{Table, TableBuilder, TableRpc};
+use crate::estimate::QueryEstimate;
+use crate::event::ControllerEvent;
+use crate::eviction::Eviction;
+use crate::explain::QueryPlan;
+use crate::graph::DataflowGraph;
+use crate::kafka::KafkaSource;
+use crate::lint::{StatementLint, UnsupportedStatement};
+use crate::load::ReaderLoad;
+use crate::migration::{MigrationStatus, RecipeDiff, RecipeVersion, ViewWarming};
+use crate::mirror::Mirror;
+use crate::priority::ViewPriority;
+use crate::protocol::{feature, Protocol};
+use crate::session::Session;
+use crate::storage::ViewStorage;
+use crate::table::{DeadLetter, Table, TableBuilder, TableRpc};
+use crate::transaction::ReadTransaction;
+use crate::trigger::TriggerAction;
 use crate::view::{View, ViewBuilder, ViewRpc};
 use crate::ActivationResult;
+use crate::DataType;
 use failure::{self, ResultExt};
 use futures_util::future;
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+#[cfg(feature = "parquet")]
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{
@@ -29,17 +55,34 @@ pub struct ControllerDescriptor {
     pub worker_addr: SocketAddr,
     pub domain_addr: SocketAddr,
     pub nonce: u64,
+    /// Descriptors written by controllers from before protocol versioning lack this.
+    #[serde(default)]
+    pub protocol: Protocol,
 }
 
 struct Controller<A> {
     authority: Arc<A>,
-    client: hyper::Client<hyper::client::HttpConnector>,
+    client: hyper::Client<crate::tls::HttpConnector>,
+    /// What to send in the `Authorization` header of every request, if anything.
+    credentials: Option<String>,
+}
+
+impl<A: 'static + Authority> Controller<A> {
+    fn new(authority: Arc<A>, credentials: Option<String>) -> Self {
+        Controller {
+            authority,
+            client: hyper::Client::builder().build(crate::tls::HttpConnector::new()),
+            credentials,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ControllerRequest {
     path: &'static str,
     request: Vec<u8>,
+    /// A feature the controller must support for the request to be sent.
+    requires: Option<&'static str>,
 }
 
 impl ControllerRequest {
@@ -47,8 +90,26 @@ impl ControllerRequest {
         Ok(ControllerRequest {
             path,
             request: serde_json::to_vec(&r)?,
+            requires: None,
         })
     }
+
+    fn requiring(mut self, feature: &'static str) -> Self {
+        self.requires = Some(feature);
+        self
+    }
+}
+
+/// Fetch the descriptor of the current controller from `authority`.
+fn leader<A: Authority>(authority: &A) -> Result<ControllerDescriptor, failure::Error> {
+    let descriptor = serde_json::from_slice(
+        &authority
+            .get_leader()
+            .context("failed to get current leader")?
+            .1,
+    )
+    .context("failed to deserialize authority reply")?;
+    Ok(descriptor)
 }
 
 impl<A> Service<ControllerRequest> for Controller<A>
@@ -70,8 +131,10 @@ where
     fn call(&mut self, req: ControllerRequest) -> Self::Future {
         let client = self.client.clone();
         let auth = self.authority.clone();
+        let credentials = self.credentials.clone();
         let path = req.path;
         let body = req.request;
+        let requires = req.requires;
 
         async move {
             let mut url = None;
@@ -80,17 +143,32 @@ where
                 if url.is_none() {
                     // TODO: don't do blocking things here...
                     // TODO: cache this value?
-                    let descriptor: ControllerDescriptor = serde_json::from_slice(
-                        &auth.get_leader().context("failed to get current leader")?.1,
-                    )
-                    .context("failed to deserialize authority reply")?;
+                    let descriptor = leader(&*auth)?;
+                    let protocol = &descriptor.protocol;
+                    if !protocol.is_compatible(&Protocol::current()) {
+                        bail!(
+                            "controller speaks protocol version {}, which is incompatible with {}",
+                            protocol.version,
+                            crate::protocol::PROTOCOL_VERSION
+                        );
+                    }
+                    if let Some(feature) = requires {
+                        if !protocol.supports(feature) {
+                            bail!("controller does not support {} for {}", feature, path);
+                        }
+                    }
 
                     url = Some(format!("http://{}/{}", descriptor.external_addr, path));
                 }
 
-                let r = hyper::Request::post(url.as_ref().unwrap())
-                    .body(hyper::Body::from(body.clone()))
-                    .unwrap();
+                let mut r = hyper::Request::post(url.as_ref().unwrap());
+                if let Some(ref credentials) = credentials {
+                    r = r.header(
+                        hyper::header::AUTHORIZATION,
+                        format!("Bearer {}", credentials),
+                    );
+                }
+                let r = r.body(hyper::Body::from(body.clone())).unwrap();
 
                 let res = client
                     .request(r)
@@ -109,6 +187,11 @@ where
                         path,
                         String::from_utf8_lossy(&*body)
                     ),
+                    hyper::StatusCode::UNAUTHORIZED => bail!(
+                        "rpc call to {} was not authenticated: {}",
+                        path,
+                        String::from_utf8_lossy(&*body)
+                    ),
                     s => {
                         if s == hyper::StatusCode::SERVICE_UNAVAILABLE {
                             url = None;
@@ -145,9 +228,13 @@ where
     A: 'static + Authority,
 {
     handle: Buffer<Controller<A>, ControllerRequest>,
+    authority: Arc<A>,
     domains: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
     views: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+    session: Session,
     tracer: tracing::Dispatch,
+    /// The credentials that the `Table`s fetched through this handle present to the workers.
+    credentials: Option<String>,
 }
 
 impl<A> Clone for ControllerHandle<A>
@@ -157,9 +244,12 @@ where
     fn clone(&self) -> Self {
         ControllerHandle {
             handle: self.handle.clone(),
+            authority: self.authority.clone(),
             domains: self.domains.clone(),
             views: self.views.clone(),
+            session: self.session.fork(),
             tracer: self.tracer.clone(),
+            credentials: self.credentials.clone(),
         }
     }
 }
@@ -203,16 +293,13 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         // need to use lazy otherwise current executor won't be known
         let tracer = tracing::dispatcher::get_default(|d| d.clone());
         Ok(ControllerHandle {
+            authority: authority.clone(),
             views: Default::default(),
             domains: Default::default(),
-            handle: Buffer::new(
-                Controller {
-                    authority,
-                    client: hyper::Client::new(),
-                },
-                1,
-            ),
+            session: Session::default(),
+            handle: Buffer::new(Controller::new(authority, None), 1),
             tracer,
+            credentials: None,
         })
     }
 
@@ -245,11 +332,29 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         Self::make(Arc::new(authority)).await
     }
 
+    /// Present `credentials` to the controller with every request made through this handle from
+    /// now on, including those that fetch `View`s and `Table`s.
+    ///
+    /// The controller decides who the credentials belong to. Views fetched as a user who has a
+    /// security universe are the user's versions of them. Tables present the credentials to the
+    /// workers they write to, which issue their writes as whoever the credentials belong to.
+    ///
+    /// Clones of this handle made earlier keep their credentials.
+    pub fn authenticate(&mut self, credentials: &str) {
+        self.handle = Buffer::new(
+            Controller::new(self.authority.clone(), Some(credentials.to_owned())),
+            1,
+        );
+        self.credentials = Some(credentials.to_owned());
+        // connections to the workers are bound to the credentials they were opened with
+        self.domains = Default::default();
+    }
+
     /// Enumerate all known base tables.
     ///
     /// These have all been created in response to a `CREATE TABLE` statement in a recipe.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn inputs(
         &mut self,
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
@@ -273,7 +378,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     ///
     /// These have all been created in response to a `CREATE EXT VIEW` statement in a recipe.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn outputs(
         &mut self,
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
@@ -295,7 +400,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Obtain a `View` that allows you to query the given external view.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn view(&mut self, name: &str) -> impl Future<Output = Result<View, failure::Error>> {
         // This call attempts to detect if this function is being called in a loop. If this is
         // getting false positives, then it is safe to increase the allowed hit count, however, the
@@ -304,6 +409,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         assert_infrequent::at_most(200);
 
         let views = self.views.clone();
+        let session = self.session.clone();
         let name = name.to_string();
         let fut = self
             .handle
@@ -315,7 +421,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
                 .context("failed to fetch view builder")?;
 
             match serde_json::from_slice::<Option<ViewBuilder>>(&body) {
-                Ok(Some(vb)) => Ok(vb.build(views)?),
+                Ok(Some(vb)) => Ok(vb.build(views)?.with_session(session)),
                 Ok(None) => Err(failure::err_msg("view does not exist")),
                 Err(e) => Err(failure::Error::from(e)),
             }
@@ -326,7 +432,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     /// Obtain a `Table` that allows you to perform writes, deletes, and other operations on the
     /// given base table.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn table(&mut self, name: &str) -> impl Future<Output = Result<Table, failure::Error>> {
         // This call attempts to detect if this function is being called in a loop. If this
         // is getting false positives, then it is safe to increase the allowed hit count.
@@ -334,6 +440,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         assert_infrequent::at_most(200);
 
         let domains = self.domains.clone();
+        let credentials = self.credentials.clone();
         let name = name.to_string();
         let fut = self
             .handle
@@ -346,7 +453,10 @@ impl<A: Authority + 'static> ControllerHandle<A> {
                 .context("failed to fetch table builder")?;
 
             match serde_json::from_slice::<Option<TableBuilder>>(&body) {
-                Ok(Some(tb)) => Ok(tb.build(domains)?),
+                Ok(Some(mut tb)) => {
+                    tb.credentials = credentials;
+                    Ok(tb.build(domains)?)
+                }
                 Ok(None) => Err(failure::err_msg("view table not exist")),
                 Err(e) => Err(failure::Error::from(e)),
             }
@@ -370,25 +480,71 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         finalize(fut, err)
     }
 
+    /// Like `rpc`, but fails without contacting the controller if it does not support `feature`.
+    fn feature_rpc<Q: Serialize, R: 'static>(
+        &mut self,
+        feature: &'static str,
+        path: &'static str,
+        r: Q,
+        err: &'static str,
+    ) -> RpcFuture<A, R>
+    where
+        for<'de> R: Deserialize<'de>,
+        R: Send,
+    {
+        let req = ControllerRequest::new(path, r).unwrap().requiring(feature);
+        let fut = self.handle.call(req);
+
+        finalize(fut, err)
+    }
+
+    /// The protocol version and optional features supported by the current controller.
+    pub fn protocol(&self) -> Result<Protocol, failure::Error> {
+        Ok(leader(&*self.authority)?.protocol)
+    }
+
     /// Get statistics about the time spent processing different parts of the graph.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn statistics(
         &mut self,
     ) -> impl Future<Output = Result<stats::GraphStats, failure::Error>> {
         self.rpc("get_statistics", (), "failed to get stats")
     }
 
+    /// Get the number of rows, distinct keys, and bytes of the state of every materialized node
+    /// and view, along with how often lookups in each view found their key.
+    ///
+    /// The controller gathers these every ten seconds, and returns the last ones it gathered
+    /// unless `fresh` is set, in which case it gathers them again first.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn state_statistics(
+        &mut self,
+        fresh: bool,
+    ) -> impl Future<Output = Result<stats::StateStatistics, failure::Error>> {
+        self.feature_rpc(
+            feature::STATE_STATISTICS,
+            "state_statistics",
+            fresh,
+            "failed to get state statistics",
+        )
+    }
+
     /// Flush all partial state, evicting all rows present.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn flush_partial(&mut self) -> impl Future<Output = Result<(), failure::Error>> {
         self.rpc("flush_partial", (), "failed to flush partial")
     }
 
     /// Extend the existing recipe with the given set of queries.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// The extension may also change the columns of existing tables with `ALTER TABLE`, and remove
+    /// tables and views that no other view reads from with `DROP TABLE` and `DROP VIEW`, which
+    /// frees their state.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn extend_recipe(
         &mut self,
         recipe_addition: &str,
@@ -398,7 +554,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Replace the existing recipe with this one.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn install_recipe(
         &mut self,
         new_recipe: &str,
@@ -406,16 +562,68 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("install_recipe", new_recipe, "failed to install recipe")
     }
 
+    /// Work out what installing `recipe`, or extending the running recipe with it if `replace` is
+    /// false, would change, without changing anything.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn dry_run_recipe(
+        &mut self,
+        recipe: &str,
+        replace: bool,
+    ) -> impl Future<Output = Result<RecipeDiff, failure::Error>> {
+        self.feature_rpc(
+            feature::RECIPE_HISTORY,
+            "dry_run_recipe",
+            (recipe, replace),
+            "failed to dry-run recipe",
+        )
+    }
+
+    /// Get the most recent versions of the recipe, oldest first.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn recipe_history(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<RecipeVersion>, failure::Error>> {
+        self.feature_rpc(
+            feature::RECIPE_HISTORY,
+            "recipe_history",
+            (),
+            "failed to get recipe history",
+        )
+    }
+
+    /// Go back to the recipe as it was at `version`, which must be one of the versions returned
+    /// by `Self::recipe_history`.
+    ///
+    /// This installs that version's recipe in a single migration, which removes the tables and
+    /// views added since and adds back those removed since, along with the pass-through queries
+    /// and view aliases of that version. Rows in tables that were dropped since are not restored.
+    /// The rollback itself becomes a new version of the recipe.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn rollback_recipe(
+        &mut self,
+        version: usize,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        self.feature_rpc(
+            feature::RECIPE_HISTORY,
+            "rollback_recipe",
+            version,
+            "failed to roll back recipe",
+        )
+    }
+
     /// Fetch a graphviz description of the dataflow graph.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn graphviz(&mut self) -> impl Future<Output = Result<String, failure::Error>> {
         self.rpc("graphviz", (), "failed to fetch graphviz output")
     }
 
     /// Fetch a simplified graphviz description of the dataflow graph.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn simple_graphviz(&mut self) -> impl Future<Output = Result<String, failure::Error>> {
         self.rpc(
             "simple_graphviz",
@@ -424,9 +632,684 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         )
     }
 
+    /// Run `action` whenever the contents of `view` change.
+    ///
+    /// The trigger is identified by `name`, and survives changes of controller leadership.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn add_trigger(
+        &mut self,
+        name: &str,
+        view: &str,
+        action: TriggerAction,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let feature = match action {
+            TriggerAction::Federate { .. } => feature::FEDERATION,
+            TriggerAction::DeleteReferencing { .. } => feature::CASCADES,
+            TriggerAction::Kafka { .. } | TriggerAction::Redis { .. } => feature::VIEW_SINKS,
+            _ => feature::TRIGGERS,
+        };
+        self.feature_rpc(
+            feature,
+            "add_trigger",
+            (name.to_owned(), view.to_owned(), action),
+            "failed to add trigger",
+        )
+    }
+
+    /// Write the messages of a Kafka topic to a base table, as described by `source`.
+    ///
+    /// The source is identified by `name`, and survives changes of controller leadership.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn add_kafka_source(
+        &mut self,
+        name: &str,
+        source: KafkaSource,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::KAFKA_SOURCES,
+            "add_kafka_source",
+            (name.to_owned(), source),
+            "failed to add Kafka source",
+        )
+    }
+
+    /// Stop consuming the Kafka source added as `name`.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn remove_kafka_source(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::KAFKA_SOURCES,
+            "remove_kafka_source",
+            name.to_owned(),
+            "failed to remove Kafka source",
+        )
+    }
+
+    /// Check that the rows added to `view` have the property given by `assertion`.
+    ///
+    /// Rows that violate the assertion are recorded in the view's quarantine (see
+    /// [`ControllerHandle::quarantine`]). If `halt` is set, the changes to the violating row's key
+    /// that arrived with it are also kept out of the view, so that the view keeps serving the
+    /// key's last rows that were fine. Only changes made after the assertion is added are checked,
+    /// and rows that fill misses are not checked at all.
+    ///
+    /// The assertion is identified by `name`, and survives changes of controller leadership.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn add_assertion(
+        &mut self,
+        name: &str,
+        view: &str,
+        assertion: Assertion,
+        halt: bool,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::ASSERTIONS,
+            "add_assertion",
+            (name.to_owned(), view.to_owned(), assertion, halt),
+            "failed to add assertion",
+        )
+    }
+
+    /// The most recent rows that violated an assertion on `view`, oldest first.
+    ///
+    /// Like dead letters, violations are kept in the controller's memory only, and are lost if
+    /// the controller fails over.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn quarantine(
+        &mut self,
+        view: &str,
+    ) -> impl Future<Output = Result<Vec<Violation>, failure::Error>> {
+        self.feature_rpc(
+            feature::ASSERTIONS,
+            "quarantine",
+            view,
+            "failed to get quarantined rows",
+        )
+    }
+
+    /// Put a table, or all tables if `table` is `None`, into or out of read-only mode.
+    ///
+    /// Writes to a read-only table fail with `WriteRejection::ReadOnly`, while its views continue
+    /// to serve reads.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_read_only(
+        &mut self,
+        table: Option<&str>,
+        read_only: bool,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::READ_ONLY,
+            "set_read_only",
+            (table.map(String::from), read_only),
+            "failed to set read-only mode",
+        )
+    }
+
+    /// Forward a sample of the writes to `table` as `mirror` describes, or stop doing so if
+    /// `mirror` is `None`.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_mirror(
+        &mut self,
+        table: &str,
+        mirror: Option<Mirror>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::MIRRORS,
+            "set_mirror",
+            (table.to_owned(), mirror),
+            "failed to set mirror",
+        )
+    }
+
+    /// Use `column` as the event-time column of `table`, or go back to processing time only if
+    /// `column` is `None`.
+    ///
+    /// The latest event time that each shard of the table has seen is carried through the
+    /// data-flow along with its writes, so that views know how far along in event time they are;
+    /// see [`View::event_time_lag`]. Event times are read from timestamp columns, or from integer
+    /// columns that hold milliseconds since the Unix epoch.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_event_time(
+        &mut self,
+        table: &str,
+        column: Option<&str>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::EVENT_TIME,
+            "set_event_time",
+            (table.to_owned(), column.map(String::from)),
+            "failed to set event-time column",
+        )
+    }
+
+    /// The most recent writes to `table` that were rejected because they did not fit the table's
+    /// schema, oldest first.
+    ///
+    /// Dead letters are kept in the controller's memory only, and are lost if the controller
+    /// fails over.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn dead_letters(
+        &mut self,
+        table: &str,
+    ) -> impl Future<Output = Result<Vec<DeadLetter>, failure::Error>> {
+        self.feature_rpc(
+            feature::DEAD_LETTERS,
+            "dead_letters",
+            table,
+            "failed to get dead letters",
+        )
+    }
+
+    /// Check the statements in `recipe` for constructs that are unsupported, that force full
+    /// materialization, or that prevent sharding, without changing the running recipe.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn lint_recipe(
+        &mut self,
+        recipe: &str,
+    ) -> impl Future<Output = Result<Vec<StatementLint>, failure::Error>> {
+        self.feature_rpc(
+            feature::LINT,
+            "lint_recipe",
+            recipe,
+            "failed to lint recipe",
+        )
+    }
+
+    /// Find the statements in `recipe` that use SQL constructs Noria does not support, along with
+    /// where in each statement the constructs appear, without changing the running recipe.
+    ///
+    /// `Self::install_recipe` and `Self::extend_recipe` reject recipes with such statements as a
+    /// whole, so adapters can use this to find the statements to execute elsewhere instead.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn check_recipe(
+        &mut self,
+        recipe: &str,
+    ) -> impl Future<Output = Result<Vec<UnsupportedStatement>, failure::Error>> {
+        self.feature_rpc(
+            feature::CAPABILITIES,
+            "check_recipe",
+            recipe,
+            "failed to check recipe",
+        )
+    }
+
+    /// Predict the state size, number of nodes, sharding, and read amplification of `query`,
+    /// given the current size of the tables and views it reads from, without adding it.
+    ///
+    /// `query` must be a single `SELECT`, optionally named as in a recipe.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn estimate(
+        &mut self,
+        query: &str,
+    ) -> impl Future<Output = Result<QueryEstimate, failure::Error>> {
+        self.feature_rpc(
+            feature::ESTIMATE,
+            "estimate",
+            query,
+            "failed to estimate query",
+        )
+    }
+
+    /// Describe how `query` is computed: the nodes of its plan, which of them are shared with
+    /// other queries, how its view is keyed and sharded, and how much state it holds.
+    ///
+    /// `query` is either the name of an installed view or a single `SELECT`, optionally preceded
+    /// by `EXPLAIN`. A `SELECT` is planned without being added, and the size of its state is
+    /// estimated as by `Self::estimate`.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn explain(
+        &mut self,
+        query: &str,
+    ) -> impl Future<Output = Result<QueryPlan, failure::Error>> {
+        self.feature_rpc(
+            feature::EXPLAIN,
+            "explain",
+            query,
+            "failed to explain query",
+        )
+    }
+
+    /// Fetch the whole data-flow graph: every node, the domain and shards it runs in, whether its
+    /// state is materialized, and how large that state is.
+    ///
+    /// Use `DataflowGraph::graphviz` to render the result.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn dataflow_graph(
+        &mut self,
+    ) -> impl Future<Output = Result<DataflowGraph, failure::Error>> {
+        self.feature_rpc(
+            feature::DATAFLOW_GRAPH,
+            "dataflow_graph",
+            (),
+            "failed to fetch data-flow graph",
+        )
+    }
+
+    /// Fetch the partial replays that took longer than the threshold set with
+    /// `Builder::set_slow_replay_threshold` to fill a key, oldest first.
+    ///
+    /// Each domain remembers only its most recent slow replays.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn slow_replays(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<SlowReplay>, failure::Error>> {
+        self.feature_rpc(
+            feature::SLOW_REPLAYS,
+            "slow_replays",
+            (),
+            "failed to fetch slow replays",
+        )
+    }
+
+    /// Get the queries that Noria could not support, and that the adapter should execute against
+    /// its upstream database instead, by name.
+    ///
+    /// Queries are only registered this way if the deployment was started with pass-through
+    /// enabled.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn pass_through_queries(
+        &mut self,
+    ) -> impl Future<Output = Result<BTreeMap<String, String>, failure::Error>> {
+        self.feature_rpc(
+            feature::PASS_THROUGH,
+            "pass_through",
+            (),
+            "failed to get pass-through queries",
+        )
+    }
+
+    /// Get the entries of the controller's event log, starting at sequence number `since`.
+    ///
+    /// Only the most recent events are kept, so older entries may be missing.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn events(
+        &mut self,
+        since: u64,
+    ) -> impl Future<Output = Result<Vec<ControllerEvent>, failure::Error>> {
+        self.feature_rpc(feature::EVENTS, "events", since, "failed to get events")
+    }
+
+    /// Get the progress of the migration that the controller is applying, or of the most recent
+    /// one if none is running.
+    ///
+    /// Unlike other requests, this is answered while a migration is in progress.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn migration_status(
+        &mut self,
+    ) -> impl Future<Output = Result<MigrationStatus, failure::Error>> {
+        self.feature_rpc(
+            feature::MIGRATION_STATUS,
+            "migration_status",
+            (),
+            "failed to get migration status",
+        )
+    }
+
+    /// Get how far along the controller is in warming the views that it populates in the
+    /// background, if it was started with a warming rate.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn warming_status(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<ViewWarming>, failure::Error>> {
+        self.feature_rpc(
+            feature::VIEW_WARMING,
+            "warming_status",
+            (),
+            "failed to get warming status",
+        )
+    }
+
+    /// Get how busy the reader of each worker is, as of its most recent heartbeat.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn reader_load(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<(SocketAddr, ReaderLoad)>, failure::Error>> {
+        self.feature_rpc(
+            feature::READER_LOAD,
+            "reader_load",
+            (),
+            "failed to get reader load",
+        )
+    }
+
+    /// Serve lookups on the view called `name` from `replicas` readers rather than just one.
+    ///
+    /// Each replica keeps its own copy of the view's rows, and is placed on a different worker
+    /// where possible. `View`s take turns sending lookups to the replicas, so that a few hot keys
+    /// do not keep the readers of a single worker busy. Views only learn about replicas when they
+    /// are fetched, so fetch views again after changing the number of replicas. Setting
+    /// `replicas` to 1 removes all replicas.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn replicate_view(
+        &mut self,
+        name: &str,
+        replicas: usize,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::READ_REPLICAS,
+            "replicate_view",
+            (name.to_owned(), replicas),
+            "failed to replicate view",
+        )
+    }
+
+    /// Move the reader of the view called `name` to the worker at `worker`.
+    ///
+    /// This is how a worker that joins a running deployment takes over serving lookups on
+    /// existing views; use [`Self::reader_load`] to find the addresses of the workers. The new
+    /// reader gets the view's rows like any new reader, and the old one is only removed once the
+    /// new one is ready, so lookups keep being served throughout, and writes are not held up.
+    /// Views fetched before the move must be fetched again. Views with replicas cannot be moved,
+    /// since their readers are spread across workers.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn move_view(
+        &mut self,
+        name: &str,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::MOVE_VIEWS,
+            "move_view",
+            (name.to_owned(), worker),
+            "failed to move view",
+        )
+    }
+
+    /// Choose how the reader of the partially materialized view called `name` evicts keys, and
+    /// how much memory it may use.
+    ///
+    /// The setting is kept across recipe changes and controller failures, and applies to any
+    /// replicas of the view too. Fully materialized views never evict, so they cannot be given
+    /// one, unless they are kept on disk with `Self::set_view_storage`, in which case the setting
+    /// applies to the keys cached in memory.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_eviction(
+        &mut self,
+        name: &str,
+        eviction: Eviction,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::EVICTION_POLICIES,
+            "set_eviction",
+            (name.to_owned(), eviction),
+            "failed to set eviction",
+        )
+    }
+
+    /// Choose how urgently the upqueries of the view called `name` are handled, relative to those
+    /// of other views and to backfills for new views.
+    ///
+    /// The setting is kept across recipe changes and controller failures, and applies to any
+    /// replicas of the view too.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_view_priority(
+        &mut self,
+        name: &str,
+        priority: ViewPriority,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::VIEW_PRIORITIES,
+            "set_view_priority",
+            (name.to_owned(), priority),
+            "failed to set view priority",
+        )
+    }
+
+    /// Choose whether the reader of the view called `name` keeps its rows in memory or on disk.
+    ///
+    /// Only fully materialized views can be kept on disk. The view's reader is rebuilt with the
+    /// new storage, and the old reader serves lookups until the new one has all the rows, so
+    /// `View`s obtained before the change should be obtained again afterwards. The setting is
+    /// kept across recipe changes and controller failures, and applies to any replicas of the
+    /// view too.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub fn set_view_storage(
+        &mut self,
+        name: &str,
+        storage: ViewStorage,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.feature_rpc(
+            feature::VIEW_STORAGE,
+            "set_view_storage",
+            (name.to_owned(), storage),
+            "failed to set view storage",
+        )
+    }
+
+    /// Write every row of the view called `name` to a Parquet file at `path`, for analysis
+    /// outside of Noria, and return how many rows were written.
+    ///
+    /// Only fully materialized views can be exported, since partially materialized views only
+    /// hold the keys that have been read. The rows are those the view held when it was asked for
+    /// them, across all its shards, and are written in no particular order.
+    ///
+    /// This method is only available with the `parquet` feature.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    #[cfg(feature = "parquet")]
+    pub async fn export_view(
+        &mut self,
+        name: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<usize, failure::Error> {
+        let (columns, rows): (Vec<String>, Vec<Vec<DataType>>) = self
+            .feature_rpc(
+                feature::VIEW_EXPORT,
+                "view_rows",
+                name,
+                "failed to fetch view rows",
+            )
+            .await?;
+        let path = path.as_ref().to_owned();
+        let n = rows.len();
+        tokio::task::spawn_blocking(move || bulk::write_parquet(&path, &columns, &rows))
+            .await?
+            .map_err(failure::err_msg)?;
+        Ok(n)
+    }
+
+    /// Check the view called `name` against a naive evaluation of its query over the full
+    /// contents of the tables it reads from, and return the keys for which the two disagree.
+    ///
+    /// This is meant for finding bugs in operators and in the rewriting of queries, and is slow:
+    /// the controller reads every row of every table the query reads from, and every key that
+    /// the naive evaluation produces is then looked up in the view, which replays the keys that
+    /// a partially materialized view does not hold. Keys for which the query produces no rows
+    /// are not looked up, unless the query has no parameters. Writes that have not reached the
+    /// view yet show up as divergences, so call `Table::flush_barrier` first.
+    ///
+    /// Queries that use parts of SQL that the naive evaluation does not support, such as nested
+    /// queries and `LIMIT`, fail with an error.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub async fn check_view(&mut self, name: &str) -> Result<Vec<Divergence>, failure::Error> {
+        let reference: ReferenceRows = self
+            .feature_rpc(
+                feature::VIEW_CHECK,
+                "reference_rows",
+                name,
+                "failed to evaluate query",
+            )
+            .await?;
+        let mut view = self.view(name).await?;
+        let mut divergences = Vec::new();
+        for (key, expected) in reference.by_key() {
+            // views without parameters are keyed on a constant column
+            let lookup = if reference.key.is_empty() {
+                vec![DataType::from(0)]
+            } else {
+                key.clone()
+            };
+            let got = view.lookup(&lookup, true).await?.into();
+            divergences.extend(reference.diff(key, expected, view.columns(), got));
+        }
+        Ok(divergences)
+    }
+
+    /// Apply the writes in `batch`, each only once all writes before it have been applied.
+    ///
+    /// If a write fails, the writes after it are not sent, but the writes before it remain
+    /// applied.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub async fn write_batch(&mut self, batch: WriteBatch) -> Result<(), failure::Error> {
+        let mut tables = HashMap::new();
+        for name in batch.tables() {
+            self.ready().await?;
+            tables.insert(name.to_owned(), self.table(name).await?);
+        }
+
+        for (name, ops) in batch.into_runs() {
+            let table = tables.get_mut(&name).unwrap();
+            table.perform_all(ops).await.map_err(|e| {
+                failure::Error::from(e).context(format!("failed to write batch to {}", name))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Apply the writes in `batch` so that every view shows either all of them or none of them.
+    ///
+    /// The writes are sent to all their tables at once, and the views derived from those tables
+    /// hold back the changes the writes cause until every table's writes have reached every view,
+    /// at which point the views show them together. Until then, views also hold back any changes
+    /// that arrive after the held ones, so reads may briefly see slightly older results. Once this
+    /// returns, every view reflects the writes.
+    ///
+    /// Unlike with `write_batch`, the writes to different tables are not ordered. If a table
+    /// rejects its writes, the other tables undo theirs before the views are told to show them,
+    /// so that the views show none of the writes, and the rejection is returned. Other writes to
+    /// the same rows in the meantime are not isolated from this.
+    ///
+    /// Tables also undo their writes if they are not told to show them within 30 seconds, in case
+    /// the client went away, and reject the late commit.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub async fn write_atomically(&mut self, batch: WriteBatch) -> Result<(), failure::Error> {
+        if !self.protocol()?.supports(feature::ATOMIC_WRITES) {
+            bail!("controller does not support atomic writes");
+        }
+
+        let mut writes = Vec::new();
+        for (name, ops) in batch.into_tables() {
+            self.ready().await?;
+            writes.push((self.table(&name).await?, name, ops));
+        }
+
+        let id = crate::trace::random_id();
+        let written = future::join_all(
+            writes
+                .iter_mut()
+                .map(|(table, _, ops)| table.transaction_write(std::mem::take(ops), id)),
+        )
+        .await;
+        // the writes that were applied are undone if any were rejected, and the undoing reaches
+        // the views as part of the atomic write, so that they show none of it
+        let aborted = if written.iter().any(Result::is_err) {
+            future::join_all(
+                writes
+                    .iter_mut()
+                    .map(|(table, _, _)| table.transaction_abort(id)),
+            )
+            .await
+        } else {
+            Vec::new()
+        };
+        // the views have to be told to show the writes even if they were undone, or they would
+        // hold back every change after them
+        let committed = future::join_all(
+            writes
+                .iter_mut()
+                .map(|(table, _, _)| table.transaction_commit(id)),
+        )
+        .await;
+
+        let names = writes.iter().map(|(_, name, _)| name);
+        for (r, name) in written
+            .into_iter()
+            .zip(names.clone())
+            .chain(aborted.into_iter().zip(names.clone()))
+            .chain(committed.into_iter().zip(names))
+        {
+            r.map_err(|e| {
+                failure::Error::from(e).context(format!("failed to write atomically to {}", name))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Measure how much time every dataflow node spends processing updates over the next
+    /// `duration`.
+    ///
+    /// Use [`Profile::folded`] to render the result as a flamegraph.
+    ///
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
+    pub async fn profile(&mut self, duration: Duration) -> Result<Profile, failure::Error> {
+        self.feature_rpc::<_, ()>(
+            feature::PROFILING,
+            "start_profiling",
+            (),
+            "failed to start profiling",
+        )
+        .await?;
+        tokio::time::delay_for(duration).await;
+        self.ready().await?;
+        self.feature_rpc(
+            feature::PROFILING,
+            "stop_profiling",
+            (),
+            "failed to stop profiling",
+        )
+        .await
+    }
+
+    /// The settings that apply to reads on the views obtained through this handle.
+    ///
+    /// Clones of this handle start out with the same settings, but can change them independently.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Start a transaction that reads a consistent snapshot across several views.
+    ///
+    /// This is only supported by deployments started with snapshot reads enabled.
+    pub fn read_transaction(&self) -> Result<ReadTransaction, failure::Error> {
+        if !self.protocol()?.supports(feature::SNAPSHOT_READS) {
+            bail!("controller does not support snapshot reads");
+        }
+        Ok(ReadTransaction::new())
+    }
+
     /// Remove the given external view from the graph.
     ///
-    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    /// `Self::poll_ready` must have returned `Poll::Ready` before you call this method.
     pub fn remove_node(
         &mut self,
         view: NodeIndex,
@@ -1,2 +1,8 @@
+/// Types related to checking views against a naive evaluation of their queries.
+pub mod oracle;
+/// Types related to profiling the time spent in individual nodes.
+pub mod profile;
+/// Types related to the slow-replay log.
+pub mod replays;
 /// Types related to graph statistics.
 pub mod stats;
@@ -0,0 +1,150 @@
+use crate::DataType;
+use std::collections::BTreeMap;
+
+/// The rows that a view should hold, as worked out by evaluating its query naively over the full
+/// contents of the tables it reads from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceRows {
+    /// The names of the columns of `rows`.
+    pub columns: Vec<String>,
+    /// The columns of `rows` that the view is keyed on, which is none if the query has no
+    /// parameters.
+    pub key: Vec<usize>,
+    /// The rows, in no particular order.
+    pub rows: Vec<Vec<DataType>>,
+}
+
+/// A key for which a view holds different rows than its query produces when evaluated naively.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Divergence {
+    /// The key, which is empty if the query has no parameters.
+    pub key: Vec<DataType>,
+    /// Rows that the view should hold for the key, but does not.
+    pub missing: Vec<Vec<DataType>>,
+    /// Rows that the view holds for the key, but should not.
+    pub unexpected: Vec<Vec<DataType>>,
+}
+
+impl ReferenceRows {
+    /// The rows grouped by their key.
+    ///
+    /// A query without parameters has a single, empty key, even if it produces no rows.
+    pub fn by_key(&self) -> BTreeMap<Vec<DataType>, Vec<Vec<DataType>>> {
+        let mut keys = BTreeMap::new();
+        if self.key.is_empty() {
+            keys.insert(Vec::new(), Vec::new());
+        }
+        for row in &self.rows {
+            let key = self.key.iter().map(|&c| row[c].clone()).collect();
+            keys.entry(key).or_insert_with(Vec::new).push(row.clone());
+        }
+        keys
+    }
+
+    /// Compare the rows `expected` for `key` with the rows `got` that a view with the columns
+    /// `columns` holds for it.
+    ///
+    /// Only the columns that both have in common are compared, and numbers are equal if they are
+    /// close enough, since the view may compute them with a different type or in a different
+    /// order. Rows are compared as multisets.
+    pub fn diff(
+        &self,
+        key: Vec<DataType>,
+        expected: Vec<Vec<DataType>>,
+        columns: &[String],
+        got: Vec<Vec<DataType>>,
+    ) -> Option<Divergence> {
+        let shared: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i, columns.iter().position(|vc| vc == c)?)))
+            .collect();
+        let mut missing: Vec<Vec<DataType>> = expected
+            .into_iter()
+            .map(|r| shared.iter().map(|&(i, _)| r[i].clone()).collect())
+            .collect();
+        let mut unexpected = Vec::new();
+        for row in got {
+            let row: Vec<_> = shared.iter().map(|&(_, j)| row[j].clone()).collect();
+            match missing.iter().position(|r| same_row(r, &row)) {
+                Some(i) => {
+                    missing.swap_remove(i);
+                }
+                None => unexpected.push(row),
+            }
+        }
+        if missing.is_empty() && unexpected.is_empty() {
+            return None;
+        }
+        missing.sort();
+        unexpected.sort();
+        Some(Divergence {
+            key,
+            missing,
+            unexpected,
+        })
+    }
+}
+
+fn same_row(a: &[DataType], b: &[DataType]) -> bool {
+    a.iter().zip(b).all(|(a, b)| same_value(a, b))
+}
+
+fn same_value(a: &DataType, b: &DataType) -> bool {
+    if a == b {
+        return true;
+    }
+    let is_number = |v: &DataType| {
+        matches!(
+            *v,
+            DataType::Int(_) | DataType::BigInt(_) | DataType::Real(..) | DataType::Decimal(..)
+        )
+    };
+    if is_number(a) && is_number(b) {
+        let (a, b) = (f64::from(a), f64::from(b));
+        return (a - b).abs() <= 1e-9 * f64::max(1.0, f64::max(a.abs(), b.abs()));
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_diffs_shared_columns() {
+        let reference = ReferenceRows {
+            columns: vec!["aid".to_owned(), "votes".to_owned()],
+            key: vec![0],
+            rows: vec![vec![1.into(), 2.into()], vec![2.into(), 1.into()]],
+        };
+        let keys = reference.by_key();
+        assert_eq!(keys.len(), 2);
+        let expected = keys[&vec![DataType::from(1)]].clone();
+
+        // the view has a hidden column, and computes the count as a real
+        let columns = vec!["aid".to_owned(), "votes".to_owned(), "bogokey".to_owned()];
+        let got = vec![vec![1.into(), 2.0.into(), 0.into()]];
+        let key = vec![DataType::from(1)];
+        assert_eq!(
+            reference.diff(key.clone(), expected.clone(), &columns, got),
+            None
+        );
+
+        let got = vec![vec![1.into(), 3.into(), 0.into()]];
+        let d = reference.diff(key, expected, &columns, got).unwrap();
+        assert_eq!(d.missing, vec![vec![1.into(), 2.into()]]);
+        assert_eq!(d.unexpected, vec![vec![1.into(), 3.into()]]);
+    }
+
+    #[test]
+    fn queries_without_parameters_have_a_key() {
+        let reference = ReferenceRows {
+            columns: vec!["n".to_owned()],
+            key: vec![],
+            rows: vec![],
+        };
+        assert_eq!(reference.by_key().len(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use petgraph::graph::NodeIndex;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Time spent processing updates in one shard of a dataflow node during a profiling run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeProfile {
+    /// The global index of the node.
+    pub node: NodeIndex,
+    /// The name of the node.
+    pub name: String,
+    /// A short description of the node's operator.
+    pub operator: String,
+    /// The index of the domain the node belongs to.
+    pub domain: usize,
+    /// The shard of the domain that the time was measured in.
+    pub shard: usize,
+    /// Total wall-clock time spent processing in this node, in nanoseconds.
+    pub process_time: u64,
+}
+
+/// Where the dataflow spent its time during a profiling run.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// How long the run lasted.
+    pub duration: Duration,
+    /// Every node that spent time processing during the run, busiest first.
+    pub nodes: Vec<NodeProfile>,
+}
+
+impl Profile {
+    /// Render the profile in the folded-stack format read by `flamegraph.pl` and `inferno`.
+    ///
+    /// Every node becomes a stack of its domain shard and its name, with the time it spent
+    /// processing in microseconds as the sample count.
+    pub fn folded(&self) -> String {
+        let mut s = String::new();
+        for n in &self.nodes {
+            let us = n.process_time / 1_000;
+            if us == 0 {
+                continue;
+            }
+            writeln!(
+                s,
+                "domain {}.{};{} ({}) {}",
+                n.domain,
+                n.shard,
+                frame(&n.name),
+                frame(&n.operator),
+                us
+            )
+            .unwrap();
+        }
+        s
+    }
+}
+
+/// Strip the characters that separate frames and counts in the folded-stack format.
+fn frame(s: &str) -> String {
+    s.replace(';', ",").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded_skips_idle_nodes() {
+        let node = |name: &str, process_time| NodeProfile {
+            node: NodeIndex::new(1),
+            name: name.to_owned(),
+            operator: "⋈".to_owned(),
+            domain: 2,
+            shard: 0,
+            process_time,
+        };
+        let p = Profile {
+            duration: Duration::from_secs(1),
+            nodes: vec![node("q_join;0", 3_500_000), node("q_idle", 999)],
+        };
+        assert_eq!(p.folded(), "domain 2.0;q_join,0 (⋈) 3500\n");
+    }
+}
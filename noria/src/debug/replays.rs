@@ -0,0 +1,23 @@
+use crate::DataType;
+use petgraph::graph::NodeIndex;
+use std::time::{Duration, SystemTime};
+
+/// A partial replay that took longer than the slow-replay threshold to fill a key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlowReplay {
+    /// The global index of the node whose missing key the replay filled.
+    pub node: NodeIndex,
+    /// The name of that node, which is the name of the view if the node is a reader.
+    pub view: String,
+    /// The key that was missing.
+    pub key: Vec<DataType>,
+    /// The domain shards the replay passed through, from the one it started in to the one that
+    /// requested it.
+    pub domains: Vec<(usize, usize)>,
+    /// The number of bytes of records that the replay carried into the domains on its path.
+    pub bytes: u64,
+    /// How long it took from requesting the replay until the key was filled.
+    pub took: Duration,
+    /// When the key was filled.
+    pub finished: SystemTime,
+}
@@ -2,7 +2,8 @@ use crate::internal::*;
 use crate::MaterializationStatus;
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 
 type DomainMap = HashMap<(DomainIndex, usize), (DomainStats, HashMap<NodeIndex, NodeStats>)>;
 
@@ -21,6 +22,13 @@ pub struct DomainStats {
     pub total_forward_time: u64,
     /// Total wall-clock time spent waiting for work in this domain.
     pub wait_time: u64,
+    /// The number of packets waiting to be sent from this domain to others.
+    #[serde(default)]
+    pub queued_packets: u64,
+    /// Total wall-clock time this domain spent not accepting input because too many packets were
+    /// waiting to be sent to other domains.
+    #[serde(default)]
+    pub backpressure_time: u64,
 }
 
 /// Statistics about a node.
@@ -40,6 +48,74 @@ pub struct NodeStats {
     pub materialized: MaterializationStatus,
     /// The value returned from Ingredient::probe.
     pub probe_result: HashMap<String, String>,
+    /// The number of rows in this node's state.
+    #[serde(default)]
+    pub rows: u64,
+    /// The number of distinct keys in those indices of this node's state where it is known or
+    /// estimated, along with the columns each index is on.
+    #[serde(default)]
+    pub distinct_keys: Vec<(Vec<usize>, u64)>,
+    /// The number of keys looked up in this node, if it is a reader, that were present.
+    #[serde(default)]
+    pub hits: u64,
+    /// The number of keys looked up in this node, if it is a reader, that had to be replayed.
+    #[serde(default)]
+    pub misses: u64,
+}
+
+/// The size of the state of a node or view, added up over its shards.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateStats {
+    /// The number of rows in the state.
+    pub rows: u64,
+    /// The number of distinct keys in those indices of the state where it is known or estimated,
+    /// along with the columns each index is on.
+    ///
+    /// The keys of indices on persisted base tables that are not a primary key are estimated from
+    /// sketches that also count the keys of deleted rows. Keys that are on several shards are
+    /// counted once for each of them.
+    pub distinct_keys: Vec<(Vec<usize>, u64)>,
+    /// Total memory size of the state, or disk size for persisted base tables.
+    pub mem_size: u64,
+    /// The number of keys looked up in the state of a reader that were present.
+    pub hits: u64,
+    /// The number of keys looked up in the state of a reader that had to be replayed.
+    pub misses: u64,
+}
+
+impl StateStats {
+    /// Add the statistics of a shard of a node.
+    pub fn add(&mut self, shard: &NodeStats) {
+        self.rows += shard.rows;
+        for (columns, count) in &shard.distinct_keys {
+            match self.distinct_keys.iter_mut().find(|(c, _)| c == columns) {
+                Some((_, total)) => *total += count,
+                None => self.distinct_keys.push((columns.clone(), *count)),
+            }
+        }
+        self.mem_size += shard.mem_size;
+        self.hits += shard.hits;
+        self.misses += shard.misses;
+    }
+
+    /// The fraction of the keys looked up that were present, if any were looked up.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            lookups => Some(self.hits as f64 / lookups as f64),
+        }
+    }
+}
+
+/// The state of every materialized node and view, as of the controller's last refresh.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateStatistics {
+    /// How long ago the statistics were gathered.
+    pub age: Duration,
+    /// The state of each node that has any, along with the node's name.
+    pub nodes: BTreeMap<NodeIndex, (String, StateStats)>,
+    /// The state of the reader of each view, including any replicas of it, by the view's name.
+    pub views: BTreeMap<String, StateStats>,
 }
 
 /// Statistics about the Soup data-flow.
@@ -0,0 +1,21 @@
+/// What `ControllerHandle::estimate` predicts that adding a query would cost.
+///
+/// Estimates are derived from the shape of the query and the current size of the state of the
+/// tables and views it reads from. They are meant for capacity planning, and are not exact.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryEstimate {
+    /// The number of bytes of state the query's view holds once every key has been read.
+    ///
+    /// Partially materialized views start out empty, and only reach this size if all their keys
+    /// are read.
+    pub state_bytes: u64,
+    /// The number of data-flow nodes the query adds, before any are reused from existing queries.
+    pub nodes: usize,
+    /// The number of shards the query's view is split across.
+    pub shards: usize,
+    /// The number of state lookups a read of a single key causes in the worst case, that is, when
+    /// it misses and has to be replayed from the query's inputs.
+    pub read_amplification: usize,
+    /// Whether the query's view will be partially materialized.
+    pub partial: bool,
+}
@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+/// Something that happened to a Noria deployment.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ControllerEventKind {
+    /// A controller became the leader.
+    LeaderElected {
+        /// The address the new controller serves external requests on.
+        controller: SocketAddr,
+    },
+    /// A worker joined the deployment.
+    WorkerJoined {
+        /// The address of the worker.
+        worker: SocketAddr,
+    },
+    /// A worker stopped sending heartbeats, and its domains were recovered elsewhere.
+    WorkerFailed {
+        /// The address of the worker.
+        worker: SocketAddr,
+    },
+    /// A recipe change was applied.
+    Migration {
+        /// The version of the recipe after the change.
+        recipe_version: usize,
+        /// Whether the recipe was replaced, rather than extended.
+        replace: bool,
+    },
+    /// A worker evicted at least the configured number of bytes of state in one go.
+    Evicted {
+        /// The address of the worker.
+        worker: SocketAddr,
+        /// The number of bytes evicted.
+        bytes: usize,
+    },
+}
+
+/// An entry in the controller's event log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControllerEvent {
+    /// The position of the event in the log.
+    ///
+    /// Sequence numbers increase by one for every event, and are preserved across controller
+    /// failures.
+    pub seq: u64,
+    /// When the controller recorded the event.
+    pub at: SystemTime,
+    /// What happened.
+    pub kind: ControllerEventKind,
+}
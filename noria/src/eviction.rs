@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+/// How the reader of a view picks the keys to evict when it must free memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Evict randomly chosen keys.
+    Random,
+    /// Evict the keys that were looked up least recently.
+    LeastRecentlyUsed,
+    /// Evict the keys that were looked up the fewest times.
+    LeastFrequentlyUsed,
+    /// Evict keys once they have not been looked up for the given time, even if no memory must be
+    /// freed. If memory must be freed before then, the least recently looked up keys go first.
+    Ttl(Duration),
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::Random
+    }
+}
+
+/// How the reader of a partially materialized view evicts keys, and how much memory it may use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eviction {
+    /// Which keys to evict first, both to keep to `budget` and when the worker runs out of
+    /// memory.
+    pub policy: EvictionPolicy,
+    /// The number of bytes that the view's reader may hold, split evenly across its shards.
+    ///
+    /// A reader that holds more has keys evicted until it fits again. Budgets are checked about
+    /// twice a second, so a reader may briefly hold more.
+    pub budget: Option<usize>,
+}
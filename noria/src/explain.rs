@@ -0,0 +1,40 @@
+/// What `ControllerHandle::explain` reports about how a query is, or would be, computed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryPlan {
+    /// The name of the view that computes the query, if it is installed already.
+    pub view: Option<String>,
+    /// The nodes of the query's plan, from the tables it reads from to its view, such that every
+    /// node comes after the nodes it reads from.
+    pub nodes: Vec<PlanNode>,
+    /// The columns that the query's view is looked up by.
+    pub key: Vec<String>,
+    /// The column that the query's view is sharded by, if it is installed and sharded.
+    pub sharded_by: Option<String>,
+    /// The number of shards the query's view is split across.
+    pub shards: usize,
+    /// Whether the query's view is, or will be, partially materialized.
+    pub partial: bool,
+    /// The number of bytes of state the query's view holds.
+    ///
+    /// For a query that is not installed, this is what `ControllerHandle::estimate` predicts it
+    /// will hold once every key has been read.
+    pub state_bytes: u64,
+}
+
+/// A node in the plan of a query.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanNode {
+    /// The name of the node.
+    pub name: String,
+    /// What the node computes, including the columns it joins, filters, or groups on.
+    pub operator: String,
+    /// The columns the node emits.
+    pub columns: Vec<String>,
+    /// The names of the nodes this node reads from.
+    pub ancestors: Vec<String>,
+    /// Whether the node is shared with a query that was installed earlier, rather than added for
+    /// this one.
+    pub reused: bool,
+    /// The index of the data-flow node that implements this node, if it exists.
+    pub node: Option<usize>,
+}
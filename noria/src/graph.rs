@@ -0,0 +1,156 @@
+use crate::MaterializationStatus;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The data-flow graph as returned by `ControllerHandle::dataflow_graph`: every node, the domain
+/// and shards it runs in, and the state it holds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DataflowGraph {
+    /// The nodes of the graph, in the order they were added.
+    pub nodes: Vec<GraphNode>,
+    /// The edges of the graph, from the index of the node that sends updates to the index of the
+    /// node that receives them.
+    pub edges: Vec<(usize, usize)>,
+}
+
+/// A node of the data-flow graph.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphNode {
+    /// The index of the node in the graph.
+    pub index: usize,
+    /// The name of the node.
+    pub name: String,
+    /// What kind of node this is, and for operators, what it computes.
+    pub operator: String,
+    /// The domain the node runs in, if it has been assigned one.
+    pub domain: Option<usize>,
+    /// The number of shards the node's domain is split across.
+    pub shards: usize,
+    /// The column the node is sharded by, if it is sharded by a column.
+    pub sharded_by: Option<String>,
+    /// Whether, and how, the node's state is materialized.
+    pub materialized: MaterializationStatus,
+    /// The number of bytes of state the node holds, across all its shards.
+    pub mem_size: u64,
+    /// The number of rows in the node's state, across all its shards.
+    pub rows: u64,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+        .replace('|', "\\|")
+        .replace('<', "\\<")
+        .replace('>', "\\>")
+}
+
+/// A human-readable number of bytes.
+fn bytes(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut n = n as f64 / 1024.0;
+    let mut unit = 0;
+    while n >= 1024.0 && unit + 1 < UNITS.len() {
+        n /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", n, UNITS[unit])
+}
+
+impl DataflowGraph {
+    /// Render the graph in the GraphViz DOT format, with the nodes of each domain drawn together
+    /// and every materialized node labeled with the size of its state.
+    pub fn graphviz(&self) -> String {
+        let mut domains: BTreeMap<Option<usize>, Vec<&GraphNode>> = BTreeMap::new();
+        for n in &self.nodes {
+            domains.entry(n.domain).or_default().push(n);
+        }
+
+        let mut s = String::new();
+        s.push_str("digraph {\n");
+        s.push_str("    node [shape=record, fontsize=10]\n");
+        for (domain, nodes) in domains {
+            let indent = if let Some(domain) = domain {
+                let shards = nodes[0].shards;
+                writeln!(s, "    subgraph cluster_d{} {{", domain).unwrap();
+                if shards > 1 {
+                    writeln!(s, "        label=\"domain {} ({} shards)\"", domain, shards).unwrap();
+                } else {
+                    writeln!(s, "        label=\"domain {}\"", domain).unwrap();
+                }
+                "        "
+            } else {
+                "    "
+            };
+            for n in nodes {
+                let mut label = format!("{{ {} / {}", n.index, escape(&n.name));
+                if !n.operator.is_empty() {
+                    write!(label, " | {}", escape(&n.operator)).unwrap();
+                }
+                if let Some(ref col) = n.sharded_by {
+                    write!(label, " | sharded by {}", escape(col)).unwrap();
+                }
+                match n.materialized {
+                    MaterializationStatus::Not => {}
+                    MaterializationStatus::Full => {
+                        write!(label, " | full: {}, {} rows", bytes(n.mem_size), n.rows).unwrap()
+                    }
+                    MaterializationStatus::Partial { .. } => {
+                        write!(label, " | partial: {}, {} rows", bytes(n.mem_size), n.rows).unwrap()
+                    }
+                }
+                label.push_str(" }");
+                writeln!(s, "{}n{} [label=\"{}\"]", indent, n.index, label).unwrap();
+            }
+            if domain.is_some() {
+                s.push_str("    }\n");
+            }
+        }
+        for &(from, to) in &self.edges {
+            writeln!(s, "    n{} -> n{}", from, to).unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(index: usize, domain: Option<usize>, materialized: MaterializationStatus) -> GraphNode {
+        GraphNode {
+            index,
+            name: format!("n{}", index),
+            operator: String::new(),
+            domain,
+            shards: 1,
+            sharded_by: None,
+            materialized,
+            mem_size: 2048,
+            rows: 10,
+        }
+    }
+
+    #[test]
+    fn graphviz_groups_nodes_by_domain() {
+        let graph = DataflowGraph {
+            nodes: vec![
+                node(1, Some(0), MaterializationStatus::Full),
+                node(2, Some(1), MaterializationStatus::Not),
+                node(3, Some(1), MaterializationStatus::Not),
+            ],
+            edges: vec![(1, 2), (2, 3)],
+        };
+        let dot = graph.graphviz();
+        assert!(dot.starts_with("digraph {"));
+        assert_eq!(dot.matches("subgraph cluster_d").count(), 2);
+        assert!(dot.contains("full: 2.0 KB, 10 rows"));
+        assert!(dot.contains("n1 -> n2"));
+        assert_eq!(bytes(3 * 1024 * 1024), "3.0 MB");
+    }
+}
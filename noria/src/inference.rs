@@ -0,0 +1,221 @@
+use crate::consensus::Authority;
+use crate::results::Results;
+use crate::{ControllerHandle, DataType, View};
+use nom_sql::{ConditionBase, ConditionExpression, Literal, Operator, SelectStatement, SqlQuery};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// Statistics about the rewrites performed by a [`ParameterInference`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InferenceStats {
+    /// Number of queries that were observed.
+    pub observed: u64,
+    /// Number of queries that were answered by a keyed lookup into an inferred view.
+    pub lookups: u64,
+    /// The names of the views that were inferred, by the query they were inferred from (with
+    /// the literals that became parameters replaced by `?`).
+    pub rewrites: BTreeMap<String, String>,
+}
+
+/// How many query shapes are remembered before those without a view are forgotten.
+const MAX_SHAPES: usize = 10_000;
+
+/// A query, with its literals replaced by parameters, that has been seen before.
+#[derive(Default)]
+struct Shape {
+    seen: usize,
+    view: Option<View>,
+    /// Whether installing a view for the shape failed, in which case it is not tried again.
+    failed: bool,
+}
+
+/// Rewrites queries that are issued repeatedly with different literals in their `WHERE`
+/// equalities into parameterized views, and answers later instances with keyed lookups.
+///
+/// This is meant for adapters that accept ad-hoc SQL, such as the MySQL adapter: queries that
+/// cannot be answered from an inferred view yet must be executed by the adapter as usual.
+pub struct ParameterInference {
+    threshold: usize,
+    shapes: HashMap<String, Shape>,
+    stats: InferenceStats,
+}
+
+impl ParameterInference {
+    /// Infer a parameterized view for a query once it has been seen `threshold` times with
+    /// literals in its `WHERE` equalities.
+    pub fn new(threshold: usize) -> Self {
+        ParameterInference {
+            threshold,
+            shapes: HashMap::default(),
+            stats: InferenceStats::default(),
+        }
+    }
+
+    /// Statistics about the queries seen and the views inferred so far.
+    pub fn stats(&self) -> &InferenceStats {
+        &self.stats
+    }
+
+    /// Answer `sql` from an inferred view, installing that view first if `sql` has now been seen
+    /// often enough.
+    ///
+    /// Returns `None` if `sql` is not answered from an inferred view, in which case the caller
+    /// should execute it itself. If the view for a query cannot be installed, the error is
+    /// returned once, and queries of the same shape are left to the caller from then on.
+    ///
+    /// At most 10,000 shapes are remembered. Beyond that, the shapes that have no view are
+    /// forgotten, and have to be seen `threshold` times again.
+    ///
+    /// `ControllerHandle::poll_ready` must have returned `Poll::Ready` before you call this
+    /// method.
+    pub async fn execute<A: Authority + 'static>(
+        &mut self,
+        ch: &mut ControllerHandle<A>,
+        sql: &str,
+    ) -> Result<Option<Results>, failure::Error> {
+        let (query, key) = match nom_sql::parse_query(sql) {
+            Ok(SqlQuery::Select(ref q)) => match parameterize(q) {
+                Some(p) => p,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.stats.observed += 1;
+
+        let shape = query.to_string();
+        if self.shapes.len() >= MAX_SHAPES && !self.shapes.contains_key(&shape) {
+            self.shapes.retain(|_, s| s.view.is_some());
+        }
+        let s = self.shapes.entry(shape.clone()).or_default();
+        s.seen += 1;
+        if s.failed {
+            return Ok(None);
+        }
+        if s.view.is_none() {
+            if s.seen < self.threshold {
+                return Ok(None);
+            }
+
+            let name = view_name(&shape);
+            let installed = async {
+                ch.extend_recipe(&format!("QUERY {}: {};", name, shape))
+                    .await?;
+                ch.view(&name).await
+            };
+            match installed.await {
+                Ok(view) => s.view = Some(view),
+                Err(e) => {
+                    // a shape that could not be installed now will not be the next time either
+                    s.failed = true;
+                    return Err(e);
+                }
+            }
+            self.stats.rewrites.insert(shape, name);
+        }
+
+        let rs = s.view.as_mut().unwrap().lookup(&key, true).await?;
+        self.stats.lookups += 1;
+        Ok(Some(rs))
+    }
+}
+
+/// The name of the view inferred for the parameterized query `shape`.
+fn view_name(shape: &str) -> String {
+    let mut h = DefaultHasher::new();
+    shape.hash(&mut h);
+    format!("inferred_{:x}", h.finish())
+}
+
+/// Replace the literals in the top-level `WHERE` equalities of `q` with parameters.
+///
+/// Returns the rewritten query, along with the replaced literals in parameter order, or `None`
+/// if there is nothing to replace or `q` already has parameters.
+fn parameterize(q: &SelectStatement) -> Option<(SelectStatement, Vec<DataType>)> {
+    let mut q = q.clone();
+    let mut key = Vec::new();
+    if has_placeholder(q.where_clause.as_ref()?) {
+        return None;
+    }
+    extract_literals(q.where_clause.as_mut()?, &mut key);
+    if key.is_empty() {
+        None
+    } else {
+        Some((q, key))
+    }
+}
+
+fn extract_literals(ce: &mut ConditionExpression, key: &mut Vec<DataType>) {
+    match *ce {
+        ConditionExpression::LogicalOp(ref mut ct) if ct.operator == Operator::And => {
+            extract_literals(&mut ct.left, key);
+            extract_literals(&mut ct.right, key);
+        }
+        ConditionExpression::ComparisonOp(ref mut ct) if ct.operator == Operator::Equal => {
+            if let ConditionExpression::Base(ConditionBase::Field(_)) = *ct.left {
+                if let ConditionExpression::Base(ConditionBase::Literal(ref mut l)) = *ct.right {
+                    // `= NULL` never matches, so there is no point in looking it up
+                    if *l != Literal::Null {
+                        key.push(DataType::from(&*l));
+                        *l = Literal::Placeholder;
+                    }
+                }
+            }
+        }
+        ConditionExpression::Bracketed(ref mut inner) => extract_literals(inner, key),
+        _ => (),
+    }
+}
+
+fn has_placeholder(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) | ConditionExpression::ComparisonOp(ref ct) => {
+            has_placeholder(&ct.left) || has_placeholder(&ct.right)
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            has_placeholder(inner)
+        }
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(sql: &str) -> SelectStatement {
+        match nom_sql::parse_query(sql).unwrap() {
+            SqlQuery::Select(q) => q,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_parameterizes_equalities() {
+        let (q, key) = parameterize(&select(
+            "SELECT title FROM article WHERE id = 42 AND author = 'jon'",
+        ))
+        .unwrap();
+        assert_eq!(key, vec![DataType::from(42i64), DataType::from("jon")]);
+
+        let (q2, _) = parameterize(&select(
+            "SELECT title FROM article WHERE id = 7 AND author = 'ana'",
+        ))
+        .unwrap();
+        assert_eq!(q.to_string(), q2.to_string());
+        assert_eq!(view_name(&q.to_string()), view_name(&q2.to_string()));
+    }
+
+    #[test]
+    fn it_leaves_other_queries_alone() {
+        assert!(parameterize(&select("SELECT title FROM article")).is_none());
+        assert!(parameterize(&select("SELECT title FROM article WHERE id > 42")).is_none());
+        assert!(
+            parameterize(&select("SELECT title FROM article WHERE id = 1 OR id = 2")).is_none()
+        );
+        assert!(
+            parameterize(&select("SELECT title FROM article WHERE id = ? AND a = 1")).is_none()
+        );
+    }
+}
@@ -0,0 +1,37 @@
+/// How the values of the messages consumed by a [`KafkaSource`] are encoded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KafkaFormat {
+    /// A JSON object with a field for each column of the table, by name.
+    Json,
+    /// An Avro record with a field for each column of the table, by name.
+    ///
+    /// Messages that start with a zero byte are taken to be in the Confluent wire format, which
+    /// puts a schema id before the record, and are read with `schema` regardless of the id.
+    Avro {
+        /// The Avro schema that the records are written with, as JSON.
+        schema: String,
+    },
+}
+
+/// A Kafka topic whose messages the controller writes to a base table.
+///
+/// Each message is inserted into the table, replacing the row with the same key if the table has
+/// a primary key. A message without a value (a tombstone) deletes the row whose key is the
+/// message's key, given as JSON: either an object with a field for each key column, or the value
+/// of the key column for tables with single-column keys. Fields that a message does not have are
+/// left NULL.
+///
+/// The offsets of the messages that have been written are committed to Kafka under the consumer
+/// group `noria-<name>`, so consumption resumes where it left off when the controller fails.
+/// Messages may be written again after a failure, which is harmless for tables with a primary key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KafkaSource {
+    /// The Kafka brokers to bootstrap from, as `host:port[,host:port...]`.
+    pub brokers: String,
+    /// The topic to consume.
+    pub topic: String,
+    /// The base table to write to.
+    pub table: String,
+    /// How the values of the messages are encoded.
+    pub format: KafkaFormat,
+}
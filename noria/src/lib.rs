@@ -204,9 +204,32 @@ use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use tokio_tower::multiplex;
 
+mod assertion;
+mod batch;
+#[cfg(feature = "parquet")]
+mod bulk;
 mod controller;
-mod data;
+mod estimate;
+mod event;
+mod eviction;
+mod explain;
+mod graph;
+mod inference;
+mod kafka;
+mod lint;
+mod load;
+mod migration;
+mod mirror;
+mod prepared;
+mod priority;
+mod session;
+mod sharding;
+mod storage;
 mod table;
+mod trace;
+mod transaction;
+mod trigger;
+mod upstream;
 mod view;
 
 #[doc(hidden)]
@@ -219,6 +242,9 @@ pub mod doc_mock;
 #[doc(hidden)]
 #[allow(unreachable_pub)] // https://github.com/rust-lang/rust/issues/57411
 pub mod internal;
+/// Versioning of the protocol spoken between clients, workers, and the controller.
+pub mod protocol;
+pub mod tls;
 
 // for the row! macro
 #[doc(hidden)]
@@ -245,23 +271,33 @@ pub mod results {
 
 /// Noria errors.
 pub mod error {
-    pub use crate::table::TableError;
+    pub use crate::table::{TableError, WriteRejection};
+    pub use crate::transaction::TransactionError;
     pub use crate::view::ViewError;
 }
 
 task_local! {
-    static TRACE_NEXT: ();
+    static TRACE_NEXT: TraceContext;
 }
 
-fn trace_next_op() -> bool {
-    TRACE_NEXT.try_with(|_| true).unwrap_or(false)
+/// The trace context to send along with the next read or write, if it should be traced.
+fn trace_next_op() -> Option<TraceContext> {
+    TRACE_NEXT.try_with(TraceContext::child).ok()
 }
 
 /// The next Noria read or write issued from the current thread will be traced using tokio-trace.
 ///
-/// The trace output is visible by setting the environment variable `RUST_LOG=trace`.
+/// The trace output is visible by setting the environment variable `RUST_LOG=trace`. Writes, and
+/// the upqueries that reads trigger, also carry the trace into the data-flow, where each domain
+/// they pass through emits a span for them.
 pub async fn trace_ops_in<T>(f: impl Future<Output = T>) -> T {
-    TRACE_NEXT.scope((), f).await
+    TRACE_NEXT.scope(TraceContext::new(), f).await
+}
+
+/// Like `trace_ops_in`, but the spans are part of the existing trace `ctx`, for example one
+/// parsed from an incoming request's `traceparent` header.
+pub async fn trace_ops_with<T>(ctx: TraceContext, f: impl Future<Output = T>) -> T {
+    TRACE_NEXT.scope(ctx, f).await
 }
 
 #[derive(Debug, Default)]
@@ -295,13 +331,47 @@ impl<T> From<T> for Tagged<T> {
     }
 }
 
+pub use crate::assertion::{Assertion, Violation};
+pub use crate::batch::WriteBatch;
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
-pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::estimate::QueryEstimate;
+pub use crate::event::{ControllerEvent, ControllerEventKind};
+pub use crate::eviction::{Eviction, EvictionPolicy};
+pub use crate::explain::{PlanNode, QueryPlan};
+pub use crate::graph::{DataflowGraph, GraphNode};
+pub use crate::inference::{InferenceStats, ParameterInference};
+pub use crate::kafka::{KafkaFormat, KafkaSource};
+pub use crate::lint::{StateGrowth, StatementLint, UnsupportedFeature, UnsupportedStatement};
+pub use crate::load::ReaderLoad;
+pub use crate::migration::{
+    DomainProgress, MigrationStatus, RecipeDiff, RecipeVersion, ViewWarming,
+};
+pub use crate::mirror::{Mirror, MirrorTarget};
+pub use crate::prepared::{PreparedInsert, PreparedUpdate};
+pub use crate::priority::ViewPriority;
+pub use crate::protocol::Protocol;
+pub use crate::session::{Session, SessionError};
+pub use crate::sharding::{
+    key_hash, set_shard_hasher, DefaultShardHasher, JumpShardHasher, ShardHasher, ShardScheme,
+};
+pub use crate::storage::ViewStorage;
+pub use crate::table::{DeadLetter, Table};
+pub use crate::tls::{set_tls, TlsConfig};
+pub use crate::trace::TraceContext;
+pub use crate::transaction::{ReadTransaction, Watermarks};
+pub use crate::trigger::TriggerAction;
+pub use crate::upstream::{Upstream, UpstreamFuture};
+pub use crate::view::{BatchedLookup, Cursor, LookupStream, Subscription, View};
+pub use noria_types::{
+    sparse, Comparison, Compression, Condition, DataType, Modification, Operation, TableOperation,
+    COMPRESSION_THRESHOLD, MAX_DECIMAL_SCALE,
+};
+
+#[doc(hidden)]
+pub use crate::table::{Input, WriteAck, WriteReply};
 
 #[doc(hidden)]
-pub use crate::table::Input;
+pub use crate::transaction::TransactionPhase;
 
 #[doc(hidden)]
 pub use crate::view::{ReadQuery, ReadReply, ReadReplyBatch};
@@ -326,27 +396,17 @@ pub struct ActivationResult {
     pub expressions_added: usize,
     /// Number of expressions the recipe removed compared to the prior recipe.
     pub expressions_removed: usize,
+    /// Names of the queries that Noria could not support, and that were registered as
+    /// pass-through queries instead.
+    #[serde(default)]
+    pub pass_through: Vec<String>,
 }
 
+#[doc(hidden)]
+pub use crate::sharding::shard_by_key;
+
 #[doc(hidden)]
 #[inline]
 pub fn shard_by(dt: &DataType, shards: usize) -> usize {
-    match *dt {
-        DataType::Int(n) => n as usize % shards,
-        DataType::UnsignedInt(n) => n as usize % shards,
-        DataType::BigInt(n) => n as usize % shards,
-        DataType::UnsignedBigInt(n) => n as usize % shards,
-        DataType::Text(..) | DataType::TinyText(..) => {
-            use std::hash::Hasher;
-            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
-            let s: &str = dt.into();
-            hasher.write(s.as_bytes());
-            hasher.finish() as usize % shards
-        }
-        // a bit hacky: send all NULL values to the first shard
-        DataType::None => 0,
-        ref x => {
-            unimplemented!("asked to shard on value {:?}", x);
-        }
-    }
+    shard_by_key(std::slice::from_ref(dt), shards)
 }
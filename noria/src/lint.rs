@@ -0,0 +1,93 @@
+use std::fmt;
+
+/// How the state Noria keeps for a statement is expected to grow, ordered from least to most
+/// growth.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum StateGrowth {
+    /// The statement keeps no state, for example because it cannot be added to the graph.
+    None,
+    /// At most the given number of rows is kept.
+    Bounded(usize),
+    /// Grows with the number of distinct keys that are read, since the view is only partially
+    /// materialized.
+    PerKeyRead,
+    /// Grows with the number of groups the statement aggregates over.
+    PerGroup,
+    /// Grows with the number of rows written to the tables the statement reads from.
+    PerRow,
+    /// Grows with the number of rows produced by the statement's joins.
+    PerJoinedRow,
+}
+
+/// What `ControllerHandle::lint_recipe` found for a single statement of a recipe.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatementLint {
+    /// The name given to the statement in the recipe, if any.
+    pub name: Option<String>,
+    /// The text of the statement.
+    pub statement: String,
+    /// Constructs that Noria does not support, and which would make the migration fail.
+    pub unsupported: Vec<String>,
+    /// Parts of the statement that Noria accepts, but does not honor.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Reasons why the statement's state will be fully materialized.
+    pub full_materialization: Vec<String>,
+    /// Reasons why (part of) the statement's dataflow will not be sharded.
+    pub unsharded: Vec<String>,
+    /// Estimated growth of the state kept for the statement.
+    pub state_growth: StateGrowth,
+}
+
+impl StatementLint {
+    /// True if nothing was found that would prevent, or degrade, the statement's migration.
+    pub fn is_clean(&self) -> bool {
+        self.unsupported.is_empty()
+            && self.warnings.is_empty()
+            && self.full_materialization.is_empty()
+            && self.unsharded.is_empty()
+    }
+}
+
+/// A construct that Noria does not support, and where it appears in a statement.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsupportedFeature {
+    /// What is not supported.
+    pub feature: String,
+    /// The byte offset in the statement's text at which the construct appears, if it could be
+    /// located.
+    pub offset: Option<usize>,
+}
+
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} (at offset {})", self.feature, offset),
+            None => write!(f, "{}", self.feature),
+        }
+    }
+}
+
+/// What `ControllerHandle::check_recipe` found for a statement that Noria cannot add to its graph.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsupportedStatement {
+    /// The name given to the statement in the recipe, if any.
+    pub name: Option<String>,
+    /// The text of the statement.
+    pub statement: String,
+    /// Every unsupported construct in the statement, in the order they were found.
+    pub features: Vec<UnsupportedFeature>,
+}
+
+impl fmt::Display for UnsupportedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported SQL in `{}`: ", self.statement)?;
+        for (i, feature) in self.features.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", feature)?;
+        }
+        Ok(())
+    }
+}
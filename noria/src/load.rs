@@ -0,0 +1,16 @@
+/// How busy the reader of one worker is.
+///
+/// Workers report this with every heartbeat, so it may be up to one heartbeat interval old.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReaderLoad {
+    /// The number of threads dedicated to serving reads, or `None` if reads are served by the
+    /// threads that also process writes and replays.
+    pub threads: Option<usize>,
+    /// The number of reads that have been accepted but not yet answered, including blocking
+    /// reads that are waiting for a backfill.
+    pub pending: usize,
+    /// The number of pending reads beyond which new reads are turned away, if any.
+    pub max_pending: Option<usize>,
+    /// The number of reads turned away since the worker joined the controller.
+    pub shed: u64,
+}
@@ -0,0 +1,99 @@
+use crate::PlanNode;
+use std::time::Duration;
+
+/// How far along the controller is in applying a migration.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    /// How long the running migration has been going for, or `None` if no migration is running.
+    ///
+    /// When no migration is running, the remaining fields describe the most recent migration.
+    pub running_for: Option<Duration>,
+    /// The number of rows replayed into new materializations so far.
+    pub rows_replayed: u64,
+    /// The size of the rows replayed so far, in bytes.
+    pub bytes_replayed: u64,
+    /// The number of rows that the replays started so far will replay in total.
+    pub rows_to_replay: u64,
+    /// The progress of each domain that the migration adds nodes to.
+    pub domains: Vec<DomainProgress>,
+    /// An estimate of how much longer the migration will take, if one can be made yet.
+    pub eta: Option<Duration>,
+}
+
+/// How many of the nodes that a migration adds to a domain are ready to process updates.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DomainProgress {
+    /// The index of the domain.
+    pub domain: usize,
+    /// The number of new nodes that are ready.
+    pub ready: usize,
+    /// The number of new nodes.
+    pub nodes: usize,
+}
+
+impl DomainProgress {
+    /// True if all the new nodes in this domain are ready.
+    pub fn is_complete(&self) -> bool {
+        self.ready == self.nodes
+    }
+}
+
+/// What applying a recipe change would do, as reported by `ControllerHandle::dry_run_recipe`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecipeDiff {
+    /// The version the recipe would have once the change is applied.
+    pub version: usize,
+    /// The tables and views the change adds, by name. Queries without a name are listed by their
+    /// SQL.
+    pub added: Vec<String>,
+    /// The tables and views the change removes.
+    pub removed: Vec<String>,
+    /// The tables and views the change redefines, such as tables whose columns it alters.
+    pub changed: Vec<String>,
+    /// The nodes that the added queries would add to the data-flow graph, as planned against the
+    /// graph as it is now. Each query is planned on its own, so nodes that several of them would
+    /// share are listed once for each.
+    pub new_nodes: Vec<PlanNode>,
+    /// The added queries that could not be planned ahead of the change, typically because they
+    /// read from tables or views that the change also adds.
+    pub unplanned: Vec<String>,
+    /// The indices of the leaf nodes of the tables and views that the change removes.
+    pub removed_leaves: Vec<usize>,
+    /// An estimate of the number of bytes of state that the change backfills into the fully
+    /// materialized views it adds. Partially materialized views start out empty.
+    pub backfill_bytes: u64,
+}
+
+/// A version of the recipe that the controller remembers, and can roll back to with
+/// `ControllerHandle::rollback_recipe`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecipeVersion {
+    /// The version number.
+    pub version: usize,
+    /// The recipe text of this version: the recipe it was installed with, followed by the
+    /// extensions applied to it since.
+    pub recipe: String,
+}
+
+/// How far along the controller is in warming a view in the background, as reported by
+/// `ControllerHandle::warming_status`.
+///
+/// The view can be read from while it is being warmed; keys that have not been warmed yet are
+/// filled in when they are first read.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewWarming {
+    /// The name of the view.
+    pub view: String,
+    /// The number of keys that have been requested so far.
+    pub warmed: usize,
+    /// The number of keys to warm, once they have been looked up. Keys are looked up when the
+    /// materializations that hold them are ready.
+    pub keys: Option<usize>,
+}
+
+impl ViewWarming {
+    /// True if every key of the view has been requested.
+    pub fn is_complete(&self) -> bool {
+        self.keys == Some(self.warmed)
+    }
+}
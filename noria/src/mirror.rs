@@ -0,0 +1,29 @@
+/// Where the sampled writes to a mirrored table are sent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MirrorTarget {
+    /// Perform the writes against the table of the same name in another Noria deployment.
+    Cluster {
+        /// The ZooKeeper address of the other deployment, as in `host:port/deployment`.
+        zookeeper: String,
+    },
+    /// Append every batch of writes to the given file as a line of JSON.
+    Sink {
+        /// The file to append to.
+        path: String,
+    },
+}
+
+/// Forwards a fraction of the writes to a table to a shadow deployment or a sink, so that new
+/// recipes and versions can be validated against real traffic.
+///
+/// Writes are forwarded by the workers that host the mirrored table, off the path of the writes
+/// themselves. Mirroring is best-effort: writes that cannot be forwarded, or that arrive while
+/// too many others are waiting to be, are dropped, and do not affect the writes to the mirrored
+/// table itself.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mirror {
+    /// The fraction of writes to forward, between 0 and 1.
+    pub fraction: f64,
+    /// Where to forward writes to.
+    pub target: MirrorTarget,
+}
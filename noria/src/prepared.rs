@@ -0,0 +1,328 @@
+use crate::table::{Table, TableError};
+use crate::{ColumnConstraint, DataType, Modification, TableOperation};
+use nom_sql::{CreateTableStatement, SqlType};
+
+/// What the values written to a column are checked against.
+#[derive(Clone, Debug)]
+struct ColumnCheck {
+    name: String,
+    /// The column's type, if the table has a schema.
+    sql_type: Option<SqlType>,
+    /// Whether the column may not be NULL. Auto-increment columns may be left NULL for the table
+    /// to generate their values.
+    not_null: bool,
+}
+
+impl ColumnCheck {
+    fn new(schema: Option<&CreateTableStatement>, columns: &[String], coli: usize) -> Self {
+        let spec = schema.map(|s| &s.fields[coli]);
+        ColumnCheck {
+            name: columns[coli].clone(),
+            sql_type: spec.map(|spec| spec.sql_type.clone()),
+            not_null: spec.map_or(false, |spec| {
+                spec.constraints.contains(&ColumnConstraint::NotNull)
+                    && !spec.constraints.contains(&ColumnConstraint::AutoIncrement)
+            }),
+        }
+    }
+
+    fn check(&self, value: &DataType) -> Result<(), TableError> {
+        let invalid = |reason: &str| {
+            Err(TableError::InvalidValue {
+                column: self.name.clone(),
+                reason: reason.to_owned(),
+            })
+        };
+        if value.is_none() {
+            return if self.not_null {
+                invalid("column is NOT NULL")
+            } else {
+                Ok(())
+            };
+        }
+        match self.sql_type {
+            Some(ref t) if !fits(t, value) => invalid(&format!("{} is not a valid {}", value, t)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// True if the non-NULL `value` can be stored in a column of type `sql_type`.
+///
+/// Types that Noria does not distinguish between values of, such as enums, accept any value.
+fn fits(sql_type: &SqlType, value: &DataType) -> bool {
+    let integer = match *value {
+        DataType::Int(_)
+        | DataType::UnsignedInt(_)
+        | DataType::BigInt(_)
+        | DataType::UnsignedBigInt(_) => true,
+        _ => false,
+    };
+    let text = match *value {
+        DataType::Text(_) | DataType::TinyText(_) => true,
+        _ => false,
+    };
+    match *sql_type {
+        SqlType::Bool
+        | SqlType::Tinyint(_)
+        | SqlType::UnsignedTinyint(_)
+        | SqlType::Int(_)
+        | SqlType::UnsignedInt(_)
+        | SqlType::Bigint(_)
+        | SqlType::UnsignedBigint(_) => integer,
+        SqlType::Real | SqlType::Float | SqlType::Double | SqlType::Decimal(..) => match *value {
+            DataType::Real(..) | DataType::Decimal(..) => true,
+            _ => integer,
+        },
+        SqlType::Char(_)
+        | SqlType::Varchar(_)
+        | SqlType::Tinytext
+        | SqlType::Text
+        | SqlType::Mediumtext
+        | SqlType::Longtext => text,
+        SqlType::Date | SqlType::Timestamp => match *value {
+            DataType::Timestamp(_) => true,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/// The positions of the named columns of `table`.
+fn positions(table: &Table, columns: &[&str]) -> Result<Vec<usize>, TableError> {
+    let mut positions: Vec<usize> = Vec::with_capacity(columns.len());
+    for &c in columns {
+        let coli = table
+            .columns()
+            .iter()
+            .position(|tc| tc == c)
+            .ok_or_else(|| TableError::UnknownColumn(c.to_owned()))?;
+        if positions.contains(&coli) {
+            return Err(TableError::InvalidValue {
+                column: c.to_owned(),
+                reason: String::from("column is given more than once"),
+            });
+        }
+        positions.push(coli);
+    }
+    Ok(positions)
+}
+
+/// An insert into a base table of values for a fixed set of its columns.
+///
+/// Made with [`Table::prepare_insert`], which resolves the columns and finds the defaults of the
+/// other columns once, so that each execution only has to check the values it is given. Values
+/// that do not fit their column's type are rejected before anything is sent to Noria.
+#[derive(Clone, Debug)]
+pub struct PreparedInsert {
+    table: Table,
+    /// The position in the table's rows of each of the values given to `execute`.
+    positions: Vec<usize>,
+    checks: Vec<ColumnCheck>,
+    /// A row that holds the defaults of the columns that are not given.
+    template: Vec<DataType>,
+    /// The columns to set to their new values if a row with the same key already exists.
+    on_duplicate: Option<Vec<usize>>,
+}
+
+impl PreparedInsert {
+    pub(crate) fn new(table: Table, columns: &[&str]) -> Result<Self, TableError> {
+        let positions = positions(&table, columns)?;
+        let schema = table.schema();
+        let mut template = vec![DataType::None; table.columns().len()];
+        for coli in 0..template.len() {
+            if positions.contains(&coli) {
+                continue;
+            }
+            let default = schema.and_then(|s| {
+                s.fields[coli].constraints.iter().find_map(|c| match *c {
+                    ColumnConstraint::DefaultValue(ref literal) => Some(DataType::from(literal)),
+                    _ => None,
+                })
+            });
+            match default {
+                Some(default) => template[coli] = default,
+                None if ColumnCheck::new(schema, table.columns(), coli).not_null => {
+                    return Err(TableError::InvalidValue {
+                        column: table.columns()[coli].clone(),
+                        reason: String::from(
+                            "column is NOT NULL, has no default, and was not given",
+                        ),
+                    });
+                }
+                None => {}
+            }
+        }
+
+        let checks = positions
+            .iter()
+            .map(|&coli| ColumnCheck::new(schema, table.columns(), coli))
+            .collect();
+        Ok(PreparedInsert {
+            table,
+            positions,
+            checks,
+            template,
+            on_duplicate: None,
+        })
+    }
+
+    /// Update the named columns of the existing row instead if a row with the same primary key
+    /// already exists, like `ON DUPLICATE KEY UPDATE c = VALUES(c)` in SQL.
+    ///
+    /// The named columns must be among the prepared ones, and are set to the values given for
+    /// them. The base node applies the update against the row it holds, so there is no window
+    /// between reading the existing row and writing the new one.
+    pub fn on_duplicate_key_update(mut self, columns: &[&str]) -> Result<Self, TableError> {
+        if self.table.primary_key().is_none() {
+            panic!("update operations can only be applied to base nodes with key columns");
+        }
+        let update = positions(&self.table, columns)?;
+        for (&coli, &c) in update.iter().zip(columns) {
+            if !self.positions.contains(&coli) {
+                return Err(TableError::InvalidValue {
+                    column: c.to_owned(),
+                    reason: String::from("column is not given a value"),
+                });
+            }
+        }
+        self.on_duplicate = Some(update);
+        Ok(self)
+    }
+
+    fn op(&self, values: Vec<DataType>) -> Result<TableOperation, TableError> {
+        let row = self.row(values)?;
+        Ok(match self.on_duplicate {
+            None => TableOperation::Insert(row),
+            Some(ref update) => {
+                let mut set = vec![Modification::None; row.len()];
+                for &coli in update {
+                    set[coli] = Modification::Set(row[coli].clone());
+                }
+                TableOperation::InsertOrUpdate { row, update: set }
+            }
+        })
+    }
+
+    fn row(&self, values: Vec<DataType>) -> Result<Vec<DataType>, TableError> {
+        if values.len() != self.positions.len() {
+            return Err(TableError::WrongColumnCount(
+                self.positions.len(),
+                values.len(),
+            ));
+        }
+        let mut row = self.template.clone();
+        for ((value, check), &coli) in values.into_iter().zip(&self.checks).zip(&self.positions) {
+            check.check(&value)?;
+            row[coli] = value;
+        }
+        Ok(row)
+    }
+
+    /// Insert a row with the given values for the prepared columns, in the order they were given.
+    pub async fn execute<V>(&mut self, values: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        let op = self.op(values.into())?;
+        self.table.perform_all(vec![op]).await
+    }
+
+    /// Insert a row for each of the given sets of values in a single write.
+    ///
+    /// If any of the sets of values is invalid, none of the rows are inserted.
+    pub async fn execute_all<I, V>(&mut self, rows: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Vec<DataType>>,
+    {
+        let ops = rows
+            .into_iter()
+            .map(|values| self.op(values.into()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.table.perform_all(ops).await
+    }
+}
+
+/// An update that sets a fixed set of columns of the row with a given key in a base table.
+///
+/// Made with [`Table::prepare_update`]; see [`PreparedInsert`].
+#[derive(Clone, Debug)]
+pub struct PreparedUpdate {
+    table: Table,
+    positions: Vec<usize>,
+    checks: Vec<ColumnCheck>,
+    key_len: usize,
+}
+
+impl PreparedUpdate {
+    pub(crate) fn new(table: Table, columns: &[&str]) -> Result<Self, TableError> {
+        let key_len = match table.primary_key() {
+            Some(key) => key.len(),
+            None => panic!("update operations can only be applied to base nodes with key columns"),
+        };
+        let positions = positions(&table, columns)?;
+        let checks = positions
+            .iter()
+            .map(|&coli| ColumnCheck::new(table.schema(), table.columns(), coli))
+            .collect();
+        Ok(PreparedUpdate {
+            table,
+            positions,
+            checks,
+            key_len,
+        })
+    }
+
+    /// Set the prepared columns of the row with key `key` to the given values, in the order the
+    /// columns were given.
+    pub async fn execute<V>(&mut self, key: Vec<DataType>, values: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        let values = values.into();
+        if key.len() != self.key_len {
+            return Err(TableError::WrongKeyColumnCount(self.key_len, key.len()));
+        }
+        if values.len() != self.positions.len() {
+            return Err(TableError::WrongColumnCount(
+                self.positions.len(),
+                values.len(),
+            ));
+        }
+
+        let mut set = vec![Modification::None; self.table.columns().len()];
+        for ((value, check), &coli) in values.into_iter().zip(&self.checks).zip(&self.positions) {
+            check.check(&value)?;
+            set[coli] = Modification::Set(value);
+        }
+        self.table
+            .perform_all(vec![TableOperation::Update { key, set }])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_values_against_column_types() {
+        let int = SqlType::Int(32);
+        assert!(fits(&int, &1.into()));
+        assert!(!fits(&int, &"1".into()));
+        assert!(!fits(&int, &DataType::Real(1, 0)));
+        assert!(fits(&SqlType::Real, &1.into()));
+        assert!(fits(&SqlType::Varchar(8), &"a".into()));
+        assert!(!fits(&SqlType::Text, &1.into()));
+
+        let check = ColumnCheck {
+            name: "c".to_owned(),
+            sql_type: Some(int),
+            not_null: true,
+        };
+        assert!(check.check(&1.into()).is_ok());
+        assert!(check.check(&DataType::None).is_err());
+        assert!(check.check(&"x".into()).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+/// How urgently the domains of a view handle the upqueries that fill keys missing from its reader.
+///
+/// Upqueries of views with a higher priority are handled ahead of backfills for new views and of
+/// upqueries of views with a lower priority. Deferred work is still handled whenever a domain has
+/// nothing more urgent to do, and regularly even when it does, so it is never starved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ViewPriority {
+    /// Upqueries that can wait behind backfills and other upqueries, such as those of analytics.
+    Low,
+    /// Upqueries that go ahead of backfills and of `Low` upqueries.
+    Normal,
+    /// Latency-sensitive upqueries, which also go ahead of `Normal` upqueries waiting for a
+    /// replay slot.
+    High,
+}
+
+impl Default for ViewPriority {
+    fn default() -> Self {
+        ViewPriority::Normal
+    }
+}
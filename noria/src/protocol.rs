@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+
+/// The version of the protocol spoken between clients, workers, and the controller.
+///
+/// Bump this whenever a message changes shape in a way that older peers cannot deserialize.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// The oldest protocol version that peers may speak and still interoperate with this one.
+///
+/// Peers from before versioning was introduced speak version 0. Version 2 acknowledges writes with
+/// the base table writes they became, and version 3 also with the values generated for
+/// `AUTO_INCREMENT` columns, which older peers cannot deserialize. Version 4 tables present
+/// credentials when they connect to a worker, which older workers do not expect, and version 5
+/// can abort atomic writes.
+pub const MIN_PROTOCOL_VERSION: u32 = 5;
+
+/// Names of the optional features a controller may support.
+///
+/// Clients check for these before issuing the corresponding requests, so that requests to an
+/// older controller fail with an error instead of being retried forever.
+pub mod feature {
+    /// `ControllerHandle::add_trigger`.
+    pub const TRIGGERS: &str = "triggers";
+    /// `ControllerHandle::set_read_only`.
+    pub const READ_ONLY: &str = "read_only";
+    /// `ControllerHandle::set_mirror`.
+    pub const MIRRORS: &str = "mirrors";
+    /// `ControllerHandle::dead_letters`.
+    pub const DEAD_LETTERS: &str = "dead_letters";
+    /// `ControllerHandle::lint_recipe`.
+    pub const LINT: &str = "lint";
+    /// `ControllerHandle::events`.
+    pub const EVENTS: &str = "events";
+    /// `ControllerHandle::estimate`.
+    pub const ESTIMATE: &str = "estimate";
+    /// `ControllerHandle::profile`.
+    pub const PROFILING: &str = "profiling";
+    /// `ControllerHandle::pass_through_queries`.
+    pub const PASS_THROUGH: &str = "pass_through";
+    /// `ControllerHandle::migration_status`.
+    pub const MIGRATION_STATUS: &str = "migration_status";
+    /// `ControllerHandle::set_event_time`.
+    pub const EVENT_TIME: &str = "event_time";
+    /// `ControllerHandle::read_transaction`.
+    ///
+    /// Only advertised by deployments that track which writes their views reflect.
+    pub const SNAPSHOT_READS: &str = "snapshot_reads";
+    /// `ControllerHandle::reader_load`.
+    pub const READER_LOAD: &str = "reader_load";
+    /// `ControllerHandle::check_recipe`.
+    pub const CAPABILITIES: &str = "capabilities";
+    /// `ControllerHandle::add_trigger` with `TriggerAction::Federate`.
+    pub const FEDERATION: &str = "federation";
+    /// `ControllerHandle::add_assertion` and `ControllerHandle::quarantine`.
+    pub const ASSERTIONS: &str = "assertions";
+    /// `ControllerHandle::add_trigger` with `TriggerAction::DeleteReferencing`.
+    pub const CASCADES: &str = "cascades";
+    /// `ControllerHandle::add_kafka_source`.
+    ///
+    /// Only advertised by deployments built with the `connectors` feature.
+    pub const KAFKA_SOURCES: &str = "kafka_sources";
+    /// `ControllerHandle::add_trigger` with `TriggerAction::Kafka` or `TriggerAction::Redis`.
+    ///
+    /// Only advertised by deployments built with the `connectors` feature.
+    pub const VIEW_SINKS: &str = "view_sinks";
+    /// `ControllerHandle::replicate_view`.
+    pub const READ_REPLICAS: &str = "read_replicas";
+    /// `ControllerHandle::move_view`.
+    pub const MOVE_VIEWS: &str = "move_views";
+    /// `ControllerHandle::set_eviction`.
+    pub const EVICTION_POLICIES: &str = "eviction_policies";
+    /// `ControllerHandle::set_view_priority`.
+    pub const VIEW_PRIORITIES: &str = "view_priorities";
+    /// `ControllerHandle::explain`.
+    pub const EXPLAIN: &str = "explain";
+    /// `ControllerHandle::dataflow_graph`.
+    pub const DATAFLOW_GRAPH: &str = "dataflow_graph";
+    /// `ControllerHandle::slow_replays`.
+    pub const SLOW_REPLAYS: &str = "slow_replays";
+    /// `ControllerHandle::write_atomically`.
+    pub const ATOMIC_WRITES: &str = "atomic_writes";
+    /// `ControllerHandle::dry_run_recipe`, `ControllerHandle::recipe_history`, and
+    /// `ControllerHandle::rollback_recipe`.
+    pub const RECIPE_HISTORY: &str = "recipe_history";
+    /// `ControllerHandle::warming_status`.
+    pub const VIEW_WARMING: &str = "view_warming";
+    /// `ControllerHandle::set_view_storage`.
+    pub const VIEW_STORAGE: &str = "view_storage";
+    /// `ControllerHandle::export_view`.
+    pub const VIEW_EXPORT: &str = "view_export";
+    /// `ControllerHandle::check_view`.
+    pub const VIEW_CHECK: &str = "view_check";
+    /// `ControllerHandle::state_statistics`.
+    pub const STATE_STATISTICS: &str = "state_statistics";
+    /// `View::lookup_fresh` and `View::staleness`.
+    ///
+    /// Only advertised by deployments that track the write frontiers of their base tables.
+    pub const FRESH_READS: &str = "fresh_reads";
+}
+
+/// The protocol version and features that a Noria process supports.
+///
+/// The default value describes a process from before protocol versioning was introduced.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Protocol {
+    /// The version of the protocol spoken.
+    pub version: u32,
+    /// The oldest version of the protocol that peers may speak.
+    pub min_version: u32,
+    /// The optional features that are supported.
+    ///
+    /// Features that are not known to a peer are ignored by it.
+    pub features: BTreeSet<String>,
+}
+
+impl Protocol {
+    /// The protocol spoken by this build.
+    pub fn current() -> Self {
+        Protocol {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            features: [
+                feature::TRIGGERS,
+                feature::READ_ONLY,
+                feature::MIRRORS,
+                feature::DEAD_LETTERS,
+                feature::LINT,
+                feature::EVENTS,
+                feature::ESTIMATE,
+                feature::PROFILING,
+                feature::PASS_THROUGH,
+                feature::MIGRATION_STATUS,
+                feature::EVENT_TIME,
+                feature::READER_LOAD,
+                feature::CAPABILITIES,
+                feature::FEDERATION,
+                feature::ASSERTIONS,
+                feature::CASCADES,
+                feature::READ_REPLICAS,
+                feature::MOVE_VIEWS,
+                feature::EVICTION_POLICIES,
+                feature::VIEW_PRIORITIES,
+                feature::EXPLAIN,
+                feature::DATAFLOW_GRAPH,
+                feature::SLOW_REPLAYS,
+                feature::ATOMIC_WRITES,
+                feature::RECIPE_HISTORY,
+                feature::VIEW_WARMING,
+                feature::VIEW_STORAGE,
+                feature::VIEW_EXPORT,
+                feature::VIEW_CHECK,
+                feature::STATE_STATISTICS,
+            ]
+            .iter()
+            .map(|&f| f.to_owned())
+            .collect(),
+        }
+    }
+
+    /// True if a process speaking this protocol can talk to one speaking `other`.
+    pub fn is_compatible(&self, other: &Protocol) -> bool {
+        self.version >= other.min_version && other.version >= self.min_version
+    }
+
+    /// True if `feature` is supported.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_peers_are_incompatible_and_support_nothing() {
+        let legacy = Protocol::default();
+        assert!(!Protocol::current().is_compatible(&legacy));
+        assert!(!legacy.is_compatible(&Protocol::current()));
+        assert!(!legacy.supports(feature::TRIGGERS));
+        assert!(Protocol::current().supports(feature::TRIGGERS));
+    }
+
+    #[test]
+    fn newer_peers_may_drop_support() {
+        let newer = Protocol {
+            version: PROTOCOL_VERSION + 1,
+            min_version: PROTOCOL_VERSION + 1,
+            features: BTreeSet::new(),
+        };
+        assert!(!Protocol::current().is_compatible(&newer));
+        assert!(!newer.is_compatible(&Protocol::current()));
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The session variable that sets [`Session::max_staleness`].
+const MAX_STALENESS: &str = "noria_max_staleness";
+
+/// The session variable that sets [`Session::block_on_miss`].
+const BLOCK_ON_MISS: &str = "noria_block_on_miss";
+
+/// A failed [`Session::set`].
+#[derive(Debug, Fail)]
+pub enum SessionError {
+    /// There is no session variable by the given name.
+    #[fail(display = "unknown session variable {}", _0)]
+    UnknownVariable(String),
+    /// The value can't be assigned to the session variable.
+    #[fail(display = "invalid value '{}' for session variable {}", value, name)]
+    InvalidValue {
+        /// The session variable.
+        name: String,
+        /// The value that was given for it.
+        value: String,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+struct Variables {
+    max_staleness: Duration,
+    block_on_miss: Option<bool>,
+}
+
+/// Settings that apply to every read made through one client connection.
+///
+/// Each [`ControllerHandle`](crate::ControllerHandle) carries a session, which the views it
+/// hands out share, so settings changed after a view was created still apply to that view's
+/// reads. A cloned handle starts out with the settings of the handle it was cloned from, but
+/// changing the settings of one does not affect the other.
+///
+/// Settings are usually changed by forwarding a client's `SET name = value` statements to
+/// [`Session::set`].
+#[derive(Clone, Debug, Default)]
+pub struct Session(Arc<RwLock<Variables>>);
+
+impl Session {
+    /// A new session with the same settings as this one.
+    pub(crate) fn fork(&self) -> Self {
+        Session(Arc::new(RwLock::new(self.0.read().unwrap().clone())))
+    }
+
+    /// Set the session variable `name` to `value`, as in `SET name = value`.
+    ///
+    /// The supported variables are:
+    ///
+    ///  - `noria_max_staleness`: how old the results of a read may be. Durations are given as a
+    ///    number followed by `us`, `ms`, `s`, or `min`, and are in milliseconds if no unit is
+    ///    given.
+    ///  - `noria_block_on_miss`: `on` or `off`, to override whether reads wait for missing
+    ///    results to be computed.
+    ///
+    /// Setting a variable to `DEFAULT` restores its default value. Names and keywords are
+    /// case-insensitive, and values may be quoted.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), SessionError> {
+        let invalid = || SessionError::InvalidValue {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        };
+        let v = value.trim().trim_matches(|c| c == '\'' || c == '"');
+        let default = v.eq_ignore_ascii_case("default");
+
+        let mut vars = self.0.write().unwrap();
+        match &*name.trim().to_lowercase() {
+            MAX_STALENESS if default => vars.max_staleness = Duration::default(),
+            MAX_STALENESS => vars.max_staleness = parse_duration(v).ok_or_else(invalid)?,
+            BLOCK_ON_MISS if default => vars.block_on_miss = None,
+            BLOCK_ON_MISS => vars.block_on_miss = Some(parse_bool(v).ok_or_else(invalid)?),
+            _ => return Err(SessionError::UnknownVariable(name.to_owned())),
+        }
+        Ok(())
+    }
+
+    /// How old the results of a read on this connection may be.
+    ///
+    /// Views remember the results of blocking reads for this long and answer repeated reads of
+    /// the same keys from them, without asking the server. Zero, the default, disables this.
+    pub fn max_staleness(&self) -> Duration {
+        self.0.read().unwrap().max_staleness
+    }
+
+    /// Whether reads on this connection wait for missing results to be computed, regardless of
+    /// what they ask for.
+    ///
+    /// `None`, the default, leaves it up to each read.
+    pub fn block_on_miss(&self) -> Option<bool> {
+        self.0.read().unwrap().block_on_miss
+    }
+}
+
+fn parse_bool(v: &str) -> Option<bool> {
+    match &*v.to_lowercase() {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_duration(v: &str) -> Option<Duration> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (n, unit) = v.split_at(split);
+    let n: u64 = n.parse().ok()?;
+    match &*unit.trim().to_lowercase() {
+        "us" => Some(Duration::from_micros(n)),
+        "" | "ms" => Some(Duration::from_millis(n)),
+        "s" => Some(Duration::from_secs(n)),
+        "min" => Some(Duration::from_secs(n * 60)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_settings() {
+        let s = Session::default();
+        assert_eq!(s.max_staleness(), Duration::from_millis(0));
+        assert_eq!(s.block_on_miss(), None);
+
+        s.set("noria_max_staleness", "100ms").unwrap();
+        assert_eq!(s.max_staleness(), Duration::from_millis(100));
+        s.set("NORIA_MAX_STALENESS", "'2 s'").unwrap();
+        assert_eq!(s.max_staleness(), Duration::from_secs(2));
+        s.set("noria_max_staleness", "250").unwrap();
+        assert_eq!(s.max_staleness(), Duration::from_millis(250));
+
+        s.set("noria_block_on_miss", "off").unwrap();
+        assert_eq!(s.block_on_miss(), Some(false));
+        s.set("noria_block_on_miss", "DEFAULT").unwrap();
+        assert_eq!(s.block_on_miss(), None);
+
+        assert!(s.set("noria_block_on_miss", "maybe").is_err());
+        assert!(s.set("noria_max_staleness", "soon").is_err());
+        assert!(s.set("autocommit", "1").is_err());
+    }
+
+    #[test]
+    fn forks_are_independent() {
+        let s = Session::default();
+        s.set("noria_block_on_miss", "on").unwrap();
+        let f = s.fork();
+        assert_eq!(f.block_on_miss(), Some(true));
+
+        f.set("noria_block_on_miss", "off").unwrap();
+        assert_eq!(s.block_on_miss(), Some(true));
+
+        let shared = s.clone();
+        shared.set("noria_block_on_miss", "off").unwrap();
+        assert_eq!(s.block_on_miss(), Some(false));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::DataType;
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Decides which shard the rows with a given shard key belong to.
+///
+/// Noria routes every sharded row, write, and read through the installed hasher (see
+/// [`set_shard_hasher`]), so a hasher can make Noria's shards line up with the partitions of an
+/// upstream system. Since clients route writes and reads themselves, every process that talks to
+/// a sharded deployment, including the servers, must install the same hasher before it handles any
+/// data.
+pub trait ShardHasher: Send + Sync + 'static {
+    /// The shard, out of `shards`, that rows whose shard key has the given values go to.
+    ///
+    /// Most shard keys have a single value; the keys of tables with compound shard keys have one
+    /// value per column of the shard key, in the order the columns were given in.
+    fn shard(&self, key: &[DataType], shards: usize) -> usize;
+}
+
+/// The hasher Noria uses unless told otherwise.
+///
+/// Integers are taken modulo the number of shards, and everything else is hashed. This spreads
+/// dense integer keys evenly, but moves almost every key when the number of shards changes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultShardHasher;
+
+impl ShardHasher for DefaultShardHasher {
+    fn shard(&self, key: &[DataType], shards: usize) -> usize {
+        match key {
+            [dt] => match *dt {
+                DataType::Int(n) => n as usize % shards,
+                DataType::UnsignedInt(n) => n as usize % shards,
+                DataType::BigInt(n) => n as usize % shards,
+                DataType::UnsignedBigInt(n) => n as usize % shards,
+                // a bit hacky: send all NULL values to the first shard
+                DataType::None => 0,
+                _ => key_hash(key) as usize % shards,
+            },
+            _ => key_hash(key) as usize % shards,
+        }
+    }
+}
+
+/// Jump consistent hashing, as described by Lamping and Veach.
+///
+/// When the number of shards grows from `n` to `n + 1`, only `1 / (n + 1)` of the keys move, and
+/// all of them move to the new shard. Keys are hashed with [`key_hash`] first, except for single
+/// integers, which are used as-is so that the partitioning matches that of other systems that
+/// jump-hash integer keys.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JumpShardHasher;
+
+impl ShardHasher for JumpShardHasher {
+    fn shard(&self, key: &[DataType], shards: usize) -> usize {
+        let mut k = match key {
+            [DataType::Int(n)] => *n as u64,
+            [DataType::UnsignedInt(n)] => u64::from(*n),
+            [DataType::BigInt(n)] => *n as u64,
+            [DataType::UnsignedBigInt(n)] => *n,
+            _ => key_hash(key),
+        };
+
+        let (mut b, mut j) = (-1i64, 0i64);
+        while j < shards as i64 {
+            b = j;
+            k = k.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+            j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((k >> 33) + 1) as f64)) as i64;
+        }
+        b as usize
+    }
+}
+
+/// How the rows of a node that is sharded by a column are spread across its shards.
+///
+/// Each node can be sharded its own way, so clients learn the scheme of every table and view they
+/// route writes and reads for from the controller, along with the number of shards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShardScheme {
+    /// Hash the shard key with the installed [`ShardHasher`].
+    Hash,
+    /// Use jump consistent hashing (see [`JumpShardHasher`]), so that adding a shard only moves
+    /// the keys that the new shard takes over.
+    Consistent,
+    /// Split the integer keys from `start` up to `end` into contiguous ranges of about the same
+    /// size, one per shard, so that neighbouring keys stay together.
+    ///
+    /// Keys below `start` go to the first shard, and keys from `end` on to the last. Keys that are
+    /// not integers have no place in the ranges and are hashed instead.
+    Range {
+        /// The first key of the first range.
+        start: i64,
+        /// The key after the last key of the last range.
+        end: i64,
+    },
+}
+
+impl Default for ShardScheme {
+    fn default() -> Self {
+        ShardScheme::Hash
+    }
+}
+
+impl ShardScheme {
+    /// The shard, out of `shards`, that rows with the given shard key go to under this scheme.
+    pub fn shard(&self, key: &[DataType], shards: usize) -> usize {
+        match *self {
+            ShardScheme::Hash => shard_by_key(key, shards),
+            ShardScheme::Consistent => JumpShardHasher.shard(key, shards),
+            ShardScheme::Range { start, end } => {
+                let k = match key {
+                    [DataType::Int(n)] => i128::from(*n),
+                    [DataType::UnsignedInt(n)] => i128::from(*n),
+                    [DataType::BigInt(n)] => i128::from(*n),
+                    [DataType::UnsignedBigInt(n)] => i128::from(*n),
+                    // like the default hasher, send all NULL values to the first shard
+                    [DataType::None] => return 0,
+                    _ => return key_hash(key) as usize % shards,
+                };
+                let (start, end) = (i128::from(start), i128::from(end));
+                if k < start {
+                    0
+                } else if k >= end {
+                    shards - 1
+                } else {
+                    ((k - start) * shards as i128 / (end - start)) as usize
+                }
+            }
+        }
+    }
+}
+
+/// A hash of the values of a shard key that is the same in every process.
+///
+/// Text is hashed by its contents, so compressed and uncompressed text hash alike. `NULL` hashes
+/// like an empty key.
+pub fn key_hash(key: &[DataType]) -> u64 {
+    let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+    for dt in key {
+        match *dt {
+            DataType::Int(..) | DataType::BigInt(..) => hasher.write_i64(dt.into()),
+            DataType::UnsignedInt(..) | DataType::UnsignedBigInt(..) => hasher.write_u64(dt.into()),
+            DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => {
+                let s: &str = dt.into();
+                hasher.write(s.as_bytes());
+            }
+            DataType::Compressed(..) => {
+                let dt = dt.decompress();
+                let s: &str = (&*dt).into();
+                hasher.write(s.as_bytes());
+            }
+            DataType::Decimal(..) => {
+                // equal decimals can have different numbers of trailing zeros
+                let (mut n, mut scale) = dt.to_decimal().unwrap();
+                while scale > 0 && n % 10 == 0 {
+                    n /= 10;
+                    scale -= 1;
+                }
+                hasher.write_i128(n);
+                hasher.write_u8(scale);
+            }
+            DataType::None => {}
+            ref x => {
+                unimplemented!("asked to shard on value {:?}", x);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+lazy_static::lazy_static! {
+    static ref HASHER: RwLock<Arc<dyn ShardHasher>> = RwLock::new(Arc::new(DefaultShardHasher));
+}
+
+/// Whether a hasher other than `DefaultShardHasher` was installed, so that the common case can
+/// skip the lock.
+static CUSTOM: AtomicBool = AtomicBool::new(false);
+
+/// Use `hasher` to decide which shard each key belongs to, in this process.
+///
+/// This must be called before any sharded data is written or read, and with the same hasher in
+/// every process of a deployment, including its clients.
+pub fn set_shard_hasher(hasher: Arc<dyn ShardHasher>) {
+    *HASHER.write().unwrap() = hasher;
+    CUSTOM.store(true, Ordering::Release);
+}
+
+/// The shard, out of `shards`, that rows with the given shard key go to.
+#[doc(hidden)]
+#[inline]
+pub fn shard_by_key(key: &[DataType], shards: usize) -> usize {
+    if CUSTOM.load(Ordering::Acquire) {
+        HASHER.read().unwrap().shard(key, shards)
+    } else {
+        DefaultShardHasher.shard(key, shards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matches_single_value_sharding() {
+        let h = DefaultShardHasher;
+        assert_eq!(h.shard(&[DataType::from(7)], 4), 3);
+        assert_eq!(h.shard(&[DataType::None], 4), 0);
+        let text = DataType::from("a".repeat(1024));
+        assert_eq!(h.shard(&[text.compress()], 8), h.shard(&[text], 8));
+    }
+
+    #[test]
+    fn range_scheme_keeps_neighbours_together() {
+        let s = ShardScheme::Range { start: 0, end: 100 };
+        let shard = |n: i64| s.shard(&[DataType::from(n)], 4);
+        assert_eq!(shard(0), 0);
+        assert_eq!(shard(24), 0);
+        assert_eq!(shard(25), 1);
+        assert_eq!(shard(99), 3);
+        // keys outside the ranges go to the shards at either end
+        assert_eq!(shard(-5), 0);
+        assert_eq!(shard(i64::max_value()), 3);
+        assert_eq!(s.shard(&[DataType::None], 4), 0);
+        assert!(s.shard(&[DataType::from("x")], 4) < 4);
+
+        assert_eq!(ShardScheme::default().shard(&[DataType::from(7)], 4), 3);
+        assert_eq!(
+            ShardScheme::Consistent.shard(&[DataType::UnsignedBigInt(4)], 2),
+            1
+        );
+    }
+
+    #[test]
+    fn jump_hash_moves_few_keys() {
+        let h = JumpShardHasher;
+        let keys: Vec<_> = (0..1000).map(|i| [DataType::from(i), "x".into()]).collect();
+        let mut moved = 0;
+        for k in &keys {
+            let before = h.shard(k, 10);
+            let after = h.shard(k, 11);
+            assert!(before < 10 && after < 11);
+            if before != after {
+                // keys only ever move to the new shard
+                assert_eq!(after, 10);
+                moved += 1;
+            }
+        }
+        assert!(moved > 0 && moved < 200, "{} keys moved", moved);
+
+        // the reference implementation's results
+        assert_eq!(h.shard(&[DataType::from(0)], 1), 0);
+        assert_eq!(h.shard(&[DataType::UnsignedBigInt(1)], 2), 0);
+        assert_eq!(h.shard(&[DataType::UnsignedBigInt(4)], 2), 1);
+    }
+}
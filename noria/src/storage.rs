@@ -0,0 +1,19 @@
+/// Where the reader of a view keeps its rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ViewStorage {
+    /// Every row is kept in memory.
+    Memory,
+    /// Every row is kept in an on-disk store, with the keys that are looked up cached in memory
+    /// in front of it.
+    ///
+    /// The cache keeps to the budget set with `ControllerHandle::set_eviction`, and is also
+    /// evicted from when its worker runs short of memory. Keys that are evicted are read back
+    /// from disk, rather than recomputed, when they are next looked up.
+    Disk,
+}
+
+impl Default for ViewStorage {
+    fn default() -> Self {
+        ViewStorage::Memory
+    }
+}
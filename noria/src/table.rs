@@ -1,7 +1,13 @@
-use crate::channel::CONNECTION_FROM_BASE;
-use crate::data::*;
+#[cfg(feature = "parquet")]
+use crate::bulk;
+use crate::channel;
 use crate::internal::*;
+use crate::prepared::{PreparedInsert, PreparedUpdate};
+use crate::transaction::{TransactionPhase, Watermarks};
 use crate::LocalOrNot;
+use crate::ShardScheme;
+use crate::TraceContext;
+use crate::{ColumnConstraint, Condition, DataType, Modification, TableOperation};
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
@@ -13,6 +19,8 @@ use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use std::future::Future;
 use std::net::SocketAddr;
+#[cfg(feature = "parquet")]
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::{fmt, io};
@@ -25,9 +33,13 @@ use tower_limit::concurrency::ConcurrencyLimit;
 use tower_service::Service;
 use vec_map::VecMap;
 
+/// The number of rows that `Table::bulk_load` sends to the base table at a time.
+#[cfg(feature = "parquet")]
+const BULK_LOAD_BATCH: usize = 16 * 1024;
+
 type Transport = AsyncBincodeStream<
-    tokio::net::TcpStream,
-    Tagged<()>,
+    crate::tls::Stream,
+    Tagged<WriteReply>,
     Tagged<LocalOrNot<Input>>,
     AsyncDestination,
 >;
@@ -104,6 +116,7 @@ macro_rules! row {
 
                     // Maybe we have a default value?
                     let mut allow_null = true;
+                    let mut generated = false;
                     let spec = &schema.fields[coli];
                     for c in &spec.constraints {
                         use $crate::ColumnConstraint;
@@ -115,13 +128,14 @@ macro_rules! row {
                                 row[coli] = Into::<$crate::DataType>::into(literal);
                             }
                             ColumnConstraint::AutoIncrement => {
-                                // TODO
+                                // the table generates a value if the column is left NULL
+                                generated = true;
                             }
                             _ => {}
                         }
                     }
 
-                    if !allow_null && row[coli].is_none() {
+                    if !allow_null && !generated && row[coli].is_none() {
                         panic!("Column {} is declared NOT NULL, has no default, and was not provided", cname);
                     }
                 }
@@ -201,8 +215,9 @@ async fn update_user(users: &mut Table) -> Result<(), TableError> {
     users.update(vec!["jonhoo".into()], user).await
 }
 
+/// Connects to the worker at the address, presenting the credentials, if any.
 #[derive(Debug)]
-struct Endpoint(SocketAddr);
+struct Endpoint(SocketAddr, Option<String>);
 
 type InnerService = multiplex::Client<
     multiplex::MultiplexTransport<Transport, Tagger>,
@@ -225,11 +240,16 @@ impl Service<()> for Endpoint {
 
     fn call(&mut self, _: ()) -> Self::Future {
         let f = tokio::net::TcpStream::connect(self.0);
+        let credentials = self.1.clone();
         async move {
-            let mut s = f.await?;
+            let s = f.await?;
             s.set_nodelay(true)?;
-            s.write_all(&[CONNECTION_FROM_BASE]).await.unwrap();
-            s.flush().await.unwrap();
+            let mut s = crate::tls::Stream::client(s);
+            s.write_all(&channel::base_preamble(
+                credentials.as_ref().map(String::as_str),
+            ))
+            .await?;
+            s.flush().await?;
             let s = AsyncBincodeStream::from(s).for_async();
             let t = multiplex::MultiplexTransport::new(s, Tagger::default());
             Ok(multiplex::Client::with_error_handler(t, |e| {
@@ -241,6 +261,7 @@ impl Service<()> for Endpoint {
 
 fn make_table_stream(
     addr: SocketAddr,
+    credentials: Option<String>,
 ) -> impl futures_util::stream::TryStream<
     Ok = tower_discover::Change<usize, InnerService>,
     Error = tokio::io::Error,
@@ -248,15 +269,18 @@ fn make_table_stream(
     // TODO: use whatever comes out of https://github.com/tower-rs/tower/issues/456 instead of
     // creating _all_ the connections every time.
     (0..crate::TABLE_POOL_SIZE)
-        .map(|i| async move {
-            let svc = Endpoint(addr).call(()).await?;
-            Ok(tower_discover::Change::Insert(i, svc))
+        .map(|i| {
+            let mut endpoint = Endpoint(addr, credentials.clone());
+            async move {
+                let svc = endpoint.call(()).await?;
+                Ok(tower_discover::Change::Insert(i, svc))
+            }
         })
         .collect::<futures_util::stream::FuturesUnordered<_>>()
 }
 
-fn make_table_discover(addr: SocketAddr) -> Discover {
-    ServiceStream::new(make_table_stream(addr))
+fn make_table_discover(addr: SocketAddr, credentials: Option<String>) -> Discover {
+    ServiceStream::new(make_table_stream(addr, credentials))
 }
 
 // Unpin + Send bounds are needed due to https://github.com/rust-lang/rust/issues/55997
@@ -289,11 +313,133 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// The base table refused to apply the write.
+    #[fail(display = "{}", _0)]
+    Rejected(#[cause] WriteRejection),
+
+    /// A prepared write named a column that the table does not have.
+    #[fail(display = "unknown column {}", _0)]
+    UnknownColumn(String),
+
+    /// A value given to a prepared write does not fit its column.
+    #[fail(display = "invalid value for column {}: {}", column, reason)]
+    InvalidValue {
+        /// The column the value was given for.
+        column: String,
+        /// What is wrong with the value.
+        reason: String,
+    },
+
+    /// The file given to `Table::bulk_load` could not be read.
+    #[fail(display = "cannot load file: {}", _0)]
+    InvalidFile(String),
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
 }
 
+/// The reason a base table refused to apply a write.
+///
+/// A rejected write is not applied at all, even if only some of the operations it contained
+/// were at fault.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq)]
+pub enum WriteRejection {
+    /// None of the table's write policies permit the writer to write one of the affected rows.
+    #[fail(display = "writer {:?} may not write to table {}", writer, table)]
+    Unauthorized {
+        /// The table that was written to.
+        table: String,
+        /// The identity the write was issued as, if any.
+        writer: Option<DataType>,
+    },
+
+    /// The table is in read-only mode, e.g., for maintenance.
+    #[fail(display = "table {} is read-only", table)]
+    ReadOnly {
+        /// The table that was written to.
+        table: String,
+    },
+
+    /// One of the operations does not fit the table's schema, e.g., because it has the wrong
+    /// number of columns. The offending operations are kept as the table's dead letters.
+    #[fail(display = "invalid write to table {}: {}", table, reason)]
+    Invalid {
+        /// The table that was written to.
+        table: String,
+        /// What was wrong with the first invalid operation.
+        reason: String,
+    },
+
+    /// One of the rows the write would leave in the table violates one of its constraints, such
+    /// as a `NOT NULL` column or a `CHECK` declared with `ALTER TABLE ... ADD CONSTRAINT`.
+    #[fail(
+        display = "row {:?} violates constraint {} of table {}",
+        row, constraint, table
+    )]
+    ConstraintViolation {
+        /// The table that was written to.
+        table: String,
+        /// The name of the first violated constraint.
+        constraint: String,
+        /// The offending row, as it would have been written.
+        row: Vec<DataType>,
+    },
+
+    /// The table undid its writes of an atomic write because they were not committed in time,
+    /// before the commit arrived.
+    #[fail(
+        display = "atomic write to table {} was undone before it committed",
+        table
+    )]
+    Expired {
+        /// The table that was written to.
+        table: String,
+    },
+
+    /// The writer has used up its share of writes to the table for this second.
+    ///
+    /// Writers are told apart by the identity they write as, or by their connection if they do
+    /// not write as anyone.
+    #[fail(
+        display = "writer {:?} exceeded its write quota for table {}",
+        writer, table
+    )]
+    QuotaExceeded {
+        /// The table that was written to.
+        table: String,
+        /// The identity the write was issued as, if any.
+        writer: Option<DataType>,
+    },
+}
+
+/// A write operation that a base table could not apply, kept for operators to inspect.
+///
+/// See `ControllerHandle::dead_letters`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeadLetter {
+    /// The operation that was rejected.
+    pub op: TableOperation,
+    /// Why the operation could not be applied.
+    pub reason: String,
+}
+
+/// What a base table sends back for a write it applied.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WriteAck {
+    /// The base table writes that a view must reflect for it to include this one (see
+    /// [`Table::insert_with_token`]).
+    pub written: Watermarks,
+    /// The values generated for the `AUTO_INCREMENT` column of the inserted rows that left it
+    /// NULL, in the order the rows were given.
+    pub generated: Vec<DataType>,
+}
+
+/// The reply a base table sends back for every write it receives.
+#[doc(hidden)]
+pub type WriteReply = Result<WriteAck, WriteRejection>;
+
 impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
     fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
         TableError::TransportError(failure::Error::from_boxed_compat(e))
@@ -305,6 +451,20 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    /// Who the write is issued by.
+    ///
+    /// Clients leave this empty; the worker that receives the write fills it in with the identity
+    /// that the connection it arrived on authenticated as.
+    pub writer: Option<DataType>,
+    /// Whether this is a flush barrier rather than a write.
+    #[serde(default)]
+    pub barrier: bool,
+    /// The trace this write is part of, if it is being traced.
+    #[serde(default)]
+    pub trace: Option<TraceContext>,
+    /// The atomic write this write is part of, if any.
+    #[serde(default)]
+    pub txn: Option<TransactionPhase>,
 }
 
 impl fmt::Debug for Input {
@@ -312,6 +472,10 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("writer", &self.writer)
+            .field("barrier", &self.barrier)
+            .field("trace", &self.trace)
+            .field("txn", &self.txn)
             .finish()
     }
 }
@@ -324,15 +488,25 @@ pub struct TableBuilder {
     pub addr: LocalNodeIndex,
     pub key_is_primary: bool,
     pub key: Vec<usize>,
+    #[serde(default)]
+    pub shard_key: Vec<usize>,
+    #[serde(default)]
+    pub shard_scheme: ShardScheme,
     pub dropped: VecMap<DataType>,
 
     pub table_name: String,
     pub columns: Vec<String>,
     pub schema: Option<CreateTableStatement>,
+    /// The credentials that the table's connections present to the workers, which issue the
+    /// table's writes as whoever the credentials belong to.
+    #[serde(skip)]
+    pub credentials: Option<String>,
 }
 
 impl TableBuilder {
-    pub(crate) fn build(
+    /// Build a `Table` out of a `TableBuilder`
+    #[doc(hidden)]
+    pub fn build(
         self,
         rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
     ) -> Result<Table, io::Error> {
@@ -352,7 +526,10 @@ impl TableBuilder {
                     // TODO: maybe always use the same local port?
                     let (c, w) = Buffer::pair(
                         ConcurrencyLimit::new(
-                            Balance::from_entropy(make_table_discover(addr)),
+                            Balance::from_entropy(make_table_discover(
+                                addr,
+                                self.credentials.clone(),
+                            )),
                             crate::PENDING_LIMIT,
                         ),
                         crate::BUFFER_TO_POOL,
@@ -370,16 +547,36 @@ impl TableBuilder {
             conns.push(s);
         }
 
+        // rows include the dropped columns, which the table's columns leave out
+        let auto_increment = self
+            .schema
+            .as_ref()
+            .and_then(|s| {
+                s.fields
+                    .iter()
+                    .find(|f| f.constraints.contains(&ColumnConstraint::AutoIncrement))
+            })
+            .and_then(|f| self.columns.iter().position(|c| *c == f.column.name))
+            .and_then(|i| {
+                (0..self.columns.len() + self.dropped.len())
+                    .filter(|c| !self.dropped.contains_key(*c))
+                    .nth(i)
+            });
+
         let dispatch = tracing::dispatcher::get_default(|d| d.clone());
         Ok(Table {
             ni: self.ni,
             node: self.addr,
             key: self.key,
             key_is_primary: self.key_is_primary,
+            shard_key: self.shard_key,
+            shard_scheme: self.shard_scheme,
             columns: self.columns,
             dropped: self.dropped,
             table_name: self.table_name,
             schema: self.schema,
+            auto_increment,
+            next_shard: 0,
             dst_is_local: false,
 
             shard_addrs: addrs,
@@ -402,10 +599,16 @@ pub struct Table {
     node: LocalNodeIndex,
     key_is_primary: bool,
     key: Vec<usize>,
+    shard_key: Vec<usize>,
+    shard_scheme: ShardScheme,
     columns: Vec<String>,
     dropped: VecMap<DataType>,
     table_name: String,
     schema: Option<CreateTableStatement>,
+    /// The index in written rows of the column whose values the table generates, if any.
+    auto_increment: Option<usize>,
+    /// The shard to send the next row that needs a generated value for its shard key to.
+    next_shard: usize,
     dst_is_local: bool,
 
     shards: Vec<TableRpc>,
@@ -421,10 +624,13 @@ impl fmt::Debug for Table {
             .field("node", &self.node)
             .field("key_is_primary", &self.key_is_primary)
             .field("key", &self.key)
+            .field("shard_key", &self.shard_key)
+            .field("shard_scheme", &self.shard_scheme)
             .field("columns", &self.columns)
             .field("dropped", &self.dropped)
             .field("table_name", &self.table_name)
             .field("schema", &self.schema)
+            .field("auto_increment", &self.auto_increment)
             .field("dst_is_local", &self.dst_is_local)
             .field("shard_addrs", &self.shard_addrs)
             .finish()
@@ -436,11 +642,13 @@ impl Table {
     fn input(
         &mut self,
         mut i: Input,
-    ) -> impl Future<Output = Result<Tagged<()>, TableError>> + Send {
-        let span = if crate::trace_next_op() {
+    ) -> impl Future<Output = Result<Tagged<WriteAck>, TableError>> + Send {
+        i.trace = crate::trace_next_op();
+        let span = if let Some(ref ctx) = i.trace {
             Some(tracing::trace_span!(
                 "table-request",
-                base = self.ni.index()
+                base = self.ni.index(),
+                traceparent = %ctx.traceparent()
             ))
         } else {
             None
@@ -488,6 +696,20 @@ impl Table {
                             ));
                         }
                     }
+                    TableOperation::DeleteWhere { ref conditions }
+                    | TableOperation::UpdateWhere { ref conditions, .. } => {
+                        if let Some(c) = conditions.iter().find(|c| c.column >= ncols) {
+                            return Err(TableError::WrongColumnCount(ncols, c.column + 1));
+                        }
+                        if let TableOperation::UpdateWhere { ref set, .. } = *op {
+                            if set.len() > self.columns.len() {
+                                return Err(TableError::WrongColumnCount(
+                                    self.columns.len(),
+                                    set.len(),
+                                ));
+                            }
+                        }
+                    }
                 }
             }
             Ok(())
@@ -507,30 +729,74 @@ impl Table {
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("submit request");
             future::Either::Right(future::Either::Left(
-                self.shards[0].call(request).map_err(TableError::from),
+                self.shards[0]
+                    .call(request)
+                    .map_err(TableError::from)
+                    .and_then(|Tagged { tag, v }| {
+                        future::ready(v.map(|v| Tagged { tag, v }).map_err(TableError::Rejected))
+                    }),
             ))
         } else {
-            if self.key.is_empty() {
-                unreachable!("sharded base without a key?");
-            }
-            if self.key.len() != 1 {
-                // base sharded by complex key
-                unimplemented!();
+            if self.shard_key.is_empty() {
+                unreachable!("sharded base without a shard key?");
             }
-            let key_col = self.key[0];
+            // deletes and updates only give the key columns, so find the shard key among those
+            let shard_key_in_key: Vec<_> = self
+                .shard_key
+                .iter()
+                .map(|c| {
+                    self.key
+                        .iter()
+                        .position(|k| k == c)
+                        .expect("shard key must be part of the key")
+                })
+                .collect();
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("shard request");
-            let mut shard_writes = vec![Vec::new(); self.shards.len()];
+            let shards = self.shards.len();
+            let mut shard_writes = vec![Vec::new(); shards];
+            // the shard that generates the value of each row that leaves it to the table
+            let mut generated_by = Vec::new();
             for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
+                let shard = match r {
+                    TableOperation::Insert(ref row)
+                    | TableOperation::InsertOrUpdate { ref row, .. } => {
+                        match self.auto_increment {
+                            Some(c) if row[c].is_none() => {
+                                // the shard key isn't known until the value is generated, so any
+                                // shard will do, as long as it generates a value that is its own
+                                let shard = if self.shard_key.contains(&c) {
+                                    self.next_shard = (self.next_shard + 1) % shards;
+                                    self.next_shard
+                                } else {
+                                    let values = self.shard_key.iter().map(|&c| &row[c]);
+                                    shard_of(values, shards, self.shard_scheme)
+                                };
+                                generated_by.push(shard);
+                                shard
+                            }
+                            _ => shard_of(
+                                self.shard_key.iter().map(|&c| &row[c]),
+                                shards,
+                                self.shard_scheme,
+                            ),
+                        }
+                    }
+                    TableOperation::Delete { ref key } | TableOperation::Update { ref key, .. } => {
+                        shard_of(
+                            shard_key_in_key.iter().map(|&k| &key[k]),
+                            shards,
+                            self.shard_scheme,
+                        )
+                    }
+                    TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+                        // any shard may have rows that match
+                        for ws in &mut shard_writes {
+                            ws.push(r.clone());
+                        }
+                        continue;
+                    }
                 };
                 shard_writes[shard].push(r);
             }
@@ -543,12 +809,20 @@ impl Table {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                writer: i.writer.clone(),
+                                barrier: i.barrier,
+                                trace: i.trace,
+                                txn: i.txn,
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            writer: i.writer.clone(),
+                            barrier: i.barrier,
+                            trace: i.trace,
+                            txn: i.txn,
                         })
                     };
                     let request = Tagged::from(p);
@@ -562,7 +836,7 @@ impl Table {
                     let _guard = span.as_ref().map(tracing::Span::enter);
                     tracing::trace!("submit request shard");
 
-                    wait_for.push(self.shards[s].call(request));
+                    wait_for.push(self.shards[s].call(request).map_ok(move |t| (s, t)));
                 } else {
                     // poll_ready reserves a sender slot which we have to release
                     // we do that by dropping the old handle and replacing it with a clone
@@ -571,24 +845,57 @@ impl Table {
                 }
             }
 
+            // the write is included once every shard it went to has been
+            let acks = (Watermarks::default(), vec![Vec::new(); shards]);
             future::Either::Right(future::Either::Right(
                 wait_for
-                    .try_for_each(|_| async { Ok(()) })
                     .map_err(TableError::from)
-                    .map_ok(Tagged::from),
+                    .try_fold(
+                        acks,
+                        |(mut written, mut generated), (s, Tagged { v, .. })| {
+                            future::ready(v.map_err(TableError::Rejected).map(|ack| {
+                                written.merge(&ack.written);
+                                generated[s] = ack.generated;
+                                (written, generated)
+                            }))
+                        },
+                    )
+                    .map_ok(move |(written, generated)| {
+                        // put the values each shard generated back in the order of their rows
+                        let mut generated: Vec<_> =
+                            generated.into_iter().map(Vec::into_iter).collect();
+                        let generated = generated_by
+                            .into_iter()
+                            .filter_map(|s| generated[s].next())
+                            .collect();
+                        Tagged::from(WriteAck { written, generated })
+                    }),
             ))
         }
     }
 }
 
+/// The shard, out of `shards`, that `scheme` puts a row whose shard key columns hold `values` in.
+fn shard_of<'a>(
+    mut values: impl ExactSizeIterator<Item = &'a DataType>,
+    shards: usize,
+    scheme: ShardScheme,
+) -> usize {
+    if values.len() == 1 {
+        scheme.shard(std::slice::from_ref(values.next().unwrap()), shards)
+    } else {
+        scheme.shard(&values.cloned().collect::<Vec<_>>(), shards)
+    }
+}
+
 impl Service<Vec<TableOperation>> for Table {
     type Error = TableError;
-    type Response = <TableRpc as Service<Tagged<LocalOrNot<Input>>>>::Response;
+    type Response = Tagged<WriteAck>;
 
     #[cfg(not(doc))]
-    type Future = impl Future<Output = Result<Tagged<()>, TableError>> + Send;
+    type Future = impl Future<Output = Result<Tagged<WriteAck>, TableError>> + Send;
     #[cfg(doc)]
-    type Future = crate::doc_mock::Future<Result<Tagged<()>, TableError>>;
+    type Future = crate::doc_mock::Future<Result<Tagged<WriteAck>, TableError>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         for s in &mut self.shards {
@@ -630,6 +937,34 @@ impl Table {
         self.schema.as_ref()
     }
 
+    /// Get the indices of the columns that make up this base table's primary key, if it has one.
+    pub fn primary_key(&self) -> Option<&[usize]> {
+        if self.key_is_primary && !self.key.is_empty() {
+            Some(&self.key)
+        } else {
+            None
+        }
+    }
+
+    /// Prepare inserts that give values for the named columns, in that order.
+    ///
+    /// The columns are resolved, and the defaults of the other columns found, only once, and the
+    /// values given to [`PreparedInsert::execute`] are checked against the types of their columns
+    /// in the table's schema before they are sent. Columns that are not named are set to their
+    /// default, or NULL if they have none, and preparing fails if such a column is NOT NULL.
+    ///
+    /// Use [`PreparedInsert::on_duplicate_key_update`] to update rows that already exist instead.
+    pub fn prepare_insert(&self, columns: &[&str]) -> Result<PreparedInsert, TableError> {
+        PreparedInsert::new(self.clone(), columns)
+    }
+
+    /// Prepare updates that set the named columns, in that order, of the row with a given key.
+    ///
+    /// See [`Table::prepare_insert`].
+    pub fn prepare_update(&self, columns: &[&str]) -> Result<PreparedUpdate, TableError> {
+        PreparedUpdate::new(self.clone(), columns)
+    }
+
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
         use std::mem;
         let ndropped = self.dropped.len();
@@ -724,6 +1059,10 @@ impl Table {
         Input {
             dst: self.node,
             data: ops,
+            writer: None,
+            barrier: false,
+            trace: None,
+            txn: None,
         }
     }
 
@@ -740,12 +1079,45 @@ impl Table {
     }
 
     /// Insert a single row of data into this base table.
-    pub async fn insert<V>(&mut self, u: V) -> Result<(), TableError>
+    ///
+    /// If the table has an `AUTO_INCREMENT` column and the row leaves it NULL, the table generates
+    /// a value for it, which is returned. Each shard of a sharded table generates values from its
+    /// own part of the column's range, so values increase per shard rather than across the table.
+    pub async fn insert<V>(&mut self, u: V) -> Result<Option<DataType>, TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.quick_n_dirty(vec![TableOperation::Insert(u.into())])
+            .await
+            .map(|ack| ack.generated.into_iter().next())
+    }
+
+    /// Insert a single row of data into this base table, and return a write token for it.
+    ///
+    /// Pass the token to [`View::lookup_after`](crate::View::lookup_after) to read from a view
+    /// derived from this table once the view includes the row. Only deployments that track which
+    /// writes their views reflect (see `feature::SNAPSHOT_READS`) issue tokens; others return an
+    /// empty token, which every view satisfies straight away.
+    pub async fn insert_with_token<V>(&mut self, u: V) -> Result<Watermarks, TableError>
     where
         V: Into<Vec<DataType>>,
     {
         self.quick_n_dirty(vec![TableOperation::Insert(u.into())])
             .await
+            .map(|ack| ack.written)
+    }
+
+    /// Perform multiple operations on this base table, and return a write token for them.
+    ///
+    /// See [`Table::insert_with_token`].
+    pub async fn perform_all_with_token<I, V>(&mut self, i: I) -> Result<Watermarks, TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        self.quick_n_dirty(i.into_iter().map(Into::into).collect::<Vec<_>>())
+            .await
+            .map(|ack| ack.written)
     }
 
     /// Perform multiple operation on this base table.
@@ -756,6 +1128,41 @@ impl Table {
     {
         self.quick_n_dirty(i.into_iter().map(Into::into).collect::<Vec<_>>())
             .await
+            .map(drop)
+    }
+
+    /// Insert every row of the Parquet or Arrow IPC file at `path` into this base table, and
+    /// return how many rows were inserted.
+    ///
+    /// The file's columns are matched to the table's columns by name, and the table's columns
+    /// that the file does not have are left NULL. Rather than write by write, the rows are sent
+    /// in batches of many thousands, each of which the base table applies at once and forwards
+    /// through the data-flow as a single update. If a batch fails, the batches before it remain
+    /// applied.
+    ///
+    /// This method is only available with the `parquet` feature.
+    #[cfg(feature = "parquet")]
+    pub async fn bulk_load(&mut self, path: impl AsRef<Path>) -> Result<usize, TableError> {
+        let path = path.as_ref().to_owned();
+        let columns = self.columns.clone();
+        let rows = tokio::task::spawn_blocking(move || bulk::read_rows(&path, &columns))
+            .await
+            .map_err(|e| TableError::TransportError(e.into()))?
+            .map_err(TableError::InvalidFile)?;
+
+        let n = rows.len();
+        let mut rows = rows.into_iter();
+        loop {
+            let batch: Vec<_> = rows
+                .by_ref()
+                .take(BULK_LOAD_BATCH)
+                .map(TableOperation::Insert)
+                .collect();
+            if batch.is_empty() {
+                return Ok(n);
+            }
+            self.perform_all(batch).await?;
+        }
     }
 
     /// Delete the row with the given key from this base table.
@@ -765,6 +1172,7 @@ impl Table {
     {
         self.quick_n_dirty(vec![TableOperation::Delete { key: key.into() }])
             .await
+            .map(drop)
     }
 
     /// Update the row with the given key in this base table.
@@ -790,6 +1198,48 @@ impl Table {
 
         self.quick_n_dirty(vec![TableOperation::Update { key, set }])
             .await
+            .map(drop)
+    }
+
+    /// Delete every row of this base table that satisfies all of the given conditions.
+    ///
+    /// The base table finds the matching rows by scanning its rows as they were before the batch
+    /// of writes that this one is applied along with, so it does not see rows that are inserted
+    /// in the same batch. The scan makes this much slower than [`Table::delete`] on large tables.
+    pub async fn delete_where(&mut self, conditions: Vec<Condition>) -> Result<(), TableError> {
+        self.quick_n_dirty(vec![TableOperation::DeleteWhere { conditions }])
+            .await
+            .map(drop)
+    }
+
+    /// Update every row of this base table that satisfies all of the given conditions.
+    ///
+    /// `u` is a set of column-modification pairs, as documented in `Table::update`. The matching
+    /// rows are found as documented in `Table::delete_where`.
+    pub async fn update_where<V>(
+        &mut self,
+        conditions: Vec<Condition>,
+        u: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+
+        let mut set = vec![Modification::None; self.columns.len()];
+        for (coli, m) in u {
+            if coli >= self.columns.len() {
+                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+            }
+            set[coli] = m;
+        }
+
+        self.quick_n_dirty(vec![TableOperation::UpdateWhere { conditions, set }])
+            .await
+            .map(drop)
     }
 
     /// Perform a insert-or-update on this base table.
@@ -822,5 +1272,79 @@ impl Table {
             update: set,
         }])
         .await
+        .map(drop)
+    }
+
+    /// Wait until the writes submitted to this table before the call are visible in every view
+    /// derived from it.
+    ///
+    /// This covers all writes that were acknowledged before the call, as well as all writes
+    /// submitted through this handle before it. Views whose materializations are still being
+    /// populated by a migration will reflect the writes once they are ready.
+    pub async fn flush_barrier(&mut self) -> Result<(), TableError> {
+        self.barrier(None).await
+    }
+
+    /// Send `ops` as the writes of the atomic write `id` to this table, and wait until they have
+    /// reached every reader derived from the table, which holds them back until `commit` is
+    /// called with the same `id`.
+    ///
+    /// Nothing is sent if `ops` is empty.
+    pub(crate) async fn transaction_write(
+        &mut self,
+        ops: Vec<TableOperation>,
+        id: u64,
+    ) -> Result<(), TableError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        let mut i = self.prep_records(ops);
+        // as a barrier, the write is only acknowledged once it has passed through every node
+        i.barrier = true;
+        i.txn = Some(TransactionPhase::Write(id));
+        self.input(i).await.map(drop)
+    }
+
+    /// Let every reader derived from this table show the writes of the atomic write `id`, and wait
+    /// until they all do.
+    pub(crate) async fn transaction_commit(&mut self, id: u64) -> Result<(), TableError> {
+        self.barrier(Some(TransactionPhase::Commit(id))).await
+    }
+
+    /// Undo the writes of the atomic write `id` to this table, and wait until the undoing has
+    /// reached every reader derived from the table, which holds it back along with the writes.
+    pub(crate) async fn transaction_abort(&mut self, id: u64) -> Result<(), TableError> {
+        self.barrier(Some(TransactionPhase::Abort(id))).await
+    }
+
+    async fn barrier(&mut self, txn: Option<TransactionPhase>) -> Result<(), TableError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        // every shard of the table has to pass the barrier on to its part of the data-flow
+        let wait_for = FuturesUnordered::new();
+        for shard in &mut self.shards {
+            let i = Input {
+                dst: self.node,
+                data: Vec::new(),
+                writer: None,
+                barrier: true,
+                trace: None,
+                txn,
+            };
+            let request = Tagged::from(if self.dst_is_local {
+                unsafe { LocalOrNot::for_local_transfer(i) }
+            } else {
+                LocalOrNot::new(i)
+            });
+            wait_for.push(shard.call(request));
+        }
+
+        wait_for
+            .map_err(TableError::from)
+            .try_for_each(|Tagged { v, .. }| {
+                future::ready(v.map(drop).map_err(TableError::Rejected))
+            })
+            .await
     }
 }
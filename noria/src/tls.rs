@@ -0,0 +1,358 @@
+//! Encryption of the connections between clients, workers, and the controller with TLS.
+//!
+//! TLS is turned on for a process with [`set_tls`], after which every connection it makes to, or
+//! accepts from, another Noria process is encrypted: controller RPCs, reads and writes through
+//! `View` and `Table`, and the channels between domains. Every process of a deployment, including
+//! its clients, must call it before it connects to anything, since a process without TLS cannot
+//! talk to one with it.
+//!
+//! Servers are addressed by IP address, so all of a deployment's servers present certificates
+//! that are valid for a single, shared server name instead of for their addresses.
+
+use futures_util::ready;
+use rustls::internal::pemfile;
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, ClientSession, NoClientAuth,
+    PrivateKey, RootCertStore, ServerConfig, StreamOwned,
+};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use webpki::{DNSName, DNSNameRef};
+
+/// The certificates and keys that a process uses to encrypt its connections.
+#[derive(Clone)]
+pub struct TlsConfig {
+    roots: RootCertStore,
+    server_name: DNSName,
+    identity: Option<(Vec<Certificate>, PrivateKey)>,
+    verify_clients: bool,
+}
+
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("server_name", &AsRef::<str>::as_ref(&self.server_name))
+            .field("identity", &self.identity.is_some())
+            .field("verify_clients", &self.verify_clients)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Trust servers whose certificates are signed by one of the certificate authorities in the
+    /// PEM file `ca`, and are valid for `server_name`.
+    pub fn new(ca: &[u8], server_name: &str) -> Result<Self, failure::Error> {
+        let mut roots = RootCertStore::empty();
+        match roots.add_pem_file(&mut &ca[..]) {
+            Ok((added, _)) if added > 0 => {}
+            _ => bail!("no valid CA certificates found"),
+        }
+        let server_name = DNSNameRef::try_from_ascii_str(server_name)
+            .map_err(|_| format_err!("invalid server name {}", server_name))?
+            .to_owned();
+        Ok(TlsConfig {
+            roots,
+            server_name,
+            identity: None,
+            verify_clients: false,
+        })
+    }
+
+    /// Present the certificate chain in the PEM file `cert`, whose private key is in the PEM file
+    /// `key`, to the other end of every connection.
+    ///
+    /// Servers must have an identity, and clients need one if the servers verify them.
+    pub fn with_identity(mut self, cert: &[u8], key: &[u8]) -> Result<Self, failure::Error> {
+        let certs =
+            pemfile::certs(&mut &cert[..]).map_err(|_| format_err!("invalid certificate chain"))?;
+        if certs.is_empty() {
+            bail!("no certificates found");
+        }
+        let mut keys = pemfile::pkcs8_private_keys(&mut &key[..])
+            .map_err(|_| format_err!("invalid private key"))?;
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut &key[..])
+                .map_err(|_| format_err!("invalid private key"))?;
+        }
+        let key = keys
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("no private key found"))?;
+        self.identity = Some((certs, key));
+        Ok(self)
+    }
+
+    /// Only accept connections from clients that present a certificate signed by one of the
+    /// trusted certificate authorities.
+    pub fn verify_clients(mut self) -> Self {
+        self.verify_clients = true;
+        self
+    }
+
+    /// Whether this process has a certificate to present.
+    pub fn has_identity(&self) -> bool {
+        self.identity.is_some()
+    }
+}
+
+struct Tls {
+    connector: TlsConnector,
+    client: Arc<ClientConfig>,
+    acceptor: Option<TlsAcceptor>,
+    server_name: DNSName,
+}
+
+lazy_static::lazy_static! {
+    static ref TLS: RwLock<Option<Arc<Tls>>> = RwLock::new(None);
+}
+
+/// Encrypt every connection this process makes or accepts from now on with `config`.
+///
+/// Connections that are already open stay as they are.
+pub fn set_tls(config: TlsConfig) -> Result<(), failure::Error> {
+    let mut client = ClientConfig::new();
+    client.root_store = config.roots.clone();
+    let mut server = None;
+    if let Some((certs, key)) = config.identity {
+        client
+            .set_single_client_cert(certs.clone(), key.clone())
+            .map_err(|e| format_err!("invalid identity: {}", e))?;
+        let verifier = if config.verify_clients {
+            AllowAnyAuthenticatedClient::new(config.roots)
+        } else {
+            NoClientAuth::new()
+        };
+        let mut s = ServerConfig::new(verifier);
+        s.set_single_cert(certs, key)
+            .map_err(|e| format_err!("invalid identity: {}", e))?;
+        server = Some(TlsAcceptor::from(Arc::new(s)));
+    }
+    let client = Arc::new(client);
+    *TLS.write().unwrap() = Some(Arc::new(Tls {
+        connector: TlsConnector::from(client.clone()),
+        client,
+        acceptor: server,
+        server_name: config.server_name,
+    }));
+    Ok(())
+}
+
+fn current() -> Option<Arc<Tls>> {
+    TLS.read().unwrap().clone()
+}
+
+type Handshake = Pin<Box<dyn Future<Output = io::Result<Stream>> + Send>>;
+
+/// A connection to or from another Noria process, which is encrypted if TLS is turned on.
+///
+/// The TLS handshake happens when the connection is first read from or written to.
+#[doc(hidden)]
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Handshaking(Handshake),
+}
+
+impl Stream {
+    /// The client end of the connection `tcp`.
+    pub fn client(tcp: TcpStream) -> Self {
+        Self::client_with(tcp, Vec::new())
+    }
+
+    /// The client end of the connection `tcp`, which sends `preamble` before anything else.
+    pub(crate) fn client_with(mut tcp: TcpStream, preamble: Vec<u8>) -> Self {
+        use tokio::io::AsyncWriteExt;
+        let tls = match current() {
+            Some(tls) => tls,
+            None if preamble.is_empty() => return Stream::Plain(tcp),
+            None => {
+                return Stream::Handshaking(Box::pin(async move {
+                    tcp.write_all(&preamble).await?;
+                    tcp.flush().await?;
+                    Ok(Stream::Plain(tcp))
+                }));
+            }
+        };
+        Stream::Handshaking(Box::pin(async move {
+            let mut s = tls.connector.connect(tls.server_name.as_ref(), tcp).await?;
+            if !preamble.is_empty() {
+                s.write_all(&preamble).await?;
+                s.flush().await?;
+            }
+            Ok(Stream::Tls(Box::new(TlsStream::from(s))))
+        }))
+    }
+
+    /// The server end of the connection `tcp`.
+    pub fn server(tcp: TcpStream) -> Self {
+        let tls = match current() {
+            Some(tls) => tls,
+            None => return Stream::Plain(tcp),
+        };
+        Stream::Handshaking(Box::pin(async move {
+            match tls.acceptor {
+                Some(ref acceptor) => {
+                    let s = acceptor.accept(tcp).await?;
+                    Ok(Stream::Tls(Box::new(TlsStream::from(s))))
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "TLS is turned on, but this process has no certificate to accept with",
+                )),
+            }
+        }))
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Stream::Handshaking(ref mut handshake) = *self {
+            *self = ready!(handshake.as_mut().poll(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match *this {
+            Stream::Plain(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Tls(ref mut s) => Pin::new(&mut **s).poll_read(cx, buf),
+            Stream::Handshaking(_) => unreachable!(),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match *this {
+            Stream::Plain(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Tls(ref mut s) => Pin::new(&mut **s).poll_write(cx, buf),
+            Stream::Handshaking(_) => unreachable!(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match *this {
+            Stream::Plain(ref mut s) => Pin::new(s).poll_flush(cx),
+            Stream::Tls(ref mut s) => Pin::new(&mut **s).poll_flush(cx),
+            Stream::Handshaking(_) => unreachable!(),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match *this {
+            Stream::Plain(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Tls(ref mut s) => Pin::new(&mut **s).poll_shutdown(cx),
+            Stream::Handshaking(_) => unreachable!(),
+        }
+    }
+}
+
+impl hyper::client::connect::Connection for Stream {
+    fn connected(&self) -> hyper::client::connect::Connected {
+        hyper::client::connect::Connected::new()
+    }
+}
+
+/// A connector for the HTTP client that talks to the controller, which encrypts its connections
+/// if TLS is turned on.
+///
+/// The URLs keep the `http` scheme either way, since the server name to check certificates
+/// against is not part of them.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpConnector(hyper::client::HttpConnector);
+
+impl HttpConnector {
+    pub(crate) fn new() -> Self {
+        HttpConnector(hyper::client::HttpConnector::new())
+    }
+}
+
+impl tower_service::Service<hyper::Uri> for HttpConnector {
+    type Response = Stream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Stream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        let connect = self.0.call(uri);
+        Box::pin(async move { Ok(Stream::client(connect.await?)) })
+    }
+}
+
+/// A blocking connection to another Noria process, which is encrypted if TLS is turned on.
+#[doc(hidden)]
+pub enum SyncStream {
+    Plain(std::net::TcpStream),
+    Tls(Box<StreamOwned<ClientSession, std::net::TcpStream>>),
+}
+
+impl SyncStream {
+    /// The client end of the connection `tcp`.
+    pub fn client(tcp: std::net::TcpStream) -> Self {
+        match current() {
+            Some(tls) => {
+                let session = ClientSession::new(&tls.client, tls.server_name.as_ref());
+                SyncStream::Tls(Box::new(StreamOwned::new(session, tcp)))
+            }
+            None => SyncStream::Plain(tcp),
+        }
+    }
+
+    /// The underlying TCP connection.
+    pub fn get_ref(&self) -> &std::net::TcpStream {
+        match *self {
+            SyncStream::Plain(ref s) => s,
+            SyncStream::Tls(ref s) => &s.sock,
+        }
+    }
+}
+
+impl Read for SyncStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            SyncStream::Plain(ref mut s) => s.read(buf),
+            SyncStream::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for SyncStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            SyncStream::Plain(ref mut s) => s.write(buf),
+            SyncStream::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            SyncStream::Plain(ref mut s) => s.flush(),
+            SyncStream::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
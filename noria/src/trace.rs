@@ -0,0 +1,114 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Identifies the span of a trace that caused a write or an upquery, so that its path through the
+/// data-flow can be followed across domains and workers.
+///
+/// The identifiers follow the [W3C Trace Context](https://www.w3.org/TR/trace-context/) format,
+/// so a trace started by an OpenTelemetry-instrumented client can be continued inside Noria with
+/// `trace_ops_with`, and the spans Noria emits can be joined with the client's.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TraceContext {
+    /// The trace this span is part of.
+    pub trace_id: u128,
+    /// The span that work done on behalf of this context is a child of.
+    pub span_id: u64,
+}
+
+/// A random, non-zero identifier.
+pub(crate) fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut h = RandomState::new().build_hasher();
+        h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let id = h.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+impl TraceContext {
+    /// Start a new trace.
+    pub fn new() -> Self {
+        TraceContext {
+            trace_id: u128::from(random_id()) << 64 | u128::from(random_id()),
+            span_id: random_id(),
+        }
+    }
+
+    /// A new span of the same trace, whose parent is this span.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: random_id(),
+        }
+    }
+
+    /// Parse a W3C `traceparent` header, such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        if version.len() != 2 || version == "ff" || trace_id.len() != 32 || span_id.len() != 16 {
+            return None;
+        }
+        // later versions may append fields, but version 00 has exactly four
+        if flags.len() != 2 || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        u8::from_str_radix(version, 16).ok()?;
+        u8::from_str_radix(flags, 16).ok()?;
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(TraceContext { trace_id, span_id })
+    }
+
+    /// Format this context as a W3C `traceparent` header.
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}/{:016x}", self.trace_id, self.span_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_roundtrip() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let ctx = TraceContext::from_traceparent(header).unwrap();
+        assert_eq!(ctx.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(ctx.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(ctx.traceparent(), header);
+
+        let child = ctx.child();
+        assert_eq!(child.trace_id, ctx.trace_id);
+        assert_ne!(child.span_id, ctx.span_id);
+
+        assert!(TraceContext::from_traceparent("00-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::from_traceparent(&header.replace("4bf9", "zzzz")).is_none());
+        let zero = format!("00-{:032x}-00f067aa0ba902b7-01", 0);
+        assert!(TraceContext::from_traceparent(&zero).is_none());
+    }
+}
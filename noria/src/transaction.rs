@@ -0,0 +1,287 @@
+use crate::view::results::Results;
+use crate::view::{View, ViewError};
+use crate::DataType;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a lagging view before retrying the read.
+pub(crate) const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long a read may wait for a lagging view to catch up with the transaction's snapshot.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of writes from each base table shard that a view reflects.
+///
+/// Writes are numbered as each shard of a base table processes them, and every view keeps the
+/// highest such number it has seen from each of its bases. Only bases that a view has received
+/// writes from since it was created appear in its watermarks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watermarks(BTreeMap<(usize, usize), u64>);
+
+impl Watermarks {
+    /// True if no writes are reflected.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The latest write reflected from the given shard of the base table with global index `base`.
+    pub fn get(&self, base: usize, shard: usize) -> Option<u64> {
+        self.0.get(&(base, shard)).cloned()
+    }
+
+    /// Record that writes up to and including `seq` from the given base table shard are reflected.
+    pub fn advance(&mut self, base: usize, shard: usize, seq: u64) {
+        let e = self.0.entry((base, shard)).or_insert(seq);
+        if *e < seq {
+            *e = seq;
+        }
+    }
+
+    /// Advance to include every write that is reflected in `other`.
+    ///
+    /// Returns true if anything changed.
+    pub fn merge(&mut self, other: &Watermarks) -> bool {
+        let mut changed = false;
+        for (&k, &seq) in &other.0 {
+            let e = self.0.entry(k).or_insert_with(|| {
+                changed = true;
+                seq
+            });
+            if *e < seq {
+                *e = seq;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// True if, for some base table shard that both know about, `self` reflects writes that
+    /// `other` does not.
+    pub fn is_ahead_of(&self, other: &Watermarks) -> bool {
+        self.0
+            .iter()
+            .any(|(k, &seq)| other.0.get(k).map(|&o| seq > o).unwrap_or(false))
+    }
+
+    /// True if every write reflected in `other` is also reflected in `self`.
+    ///
+    /// Unlike [`Watermarks::is_ahead_of`], a base table shard that `self` knows nothing about
+    /// counts as not reflected.
+    pub fn includes(&self, other: &Watermarks) -> bool {
+        other
+            .0
+            .iter()
+            .all(|(k, &seq)| self.0.get(k).map(|&s| s >= seq).unwrap_or(false))
+    }
+}
+
+/// The part that a write plays in an atomic write (see `ControllerHandle::write_atomically`).
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransactionPhase {
+    /// One of the transaction's writes to a table. Readers hold back the updates it causes.
+    Write(u64),
+    /// Every write of the transaction has reached every reader, which can now show them.
+    Commit(u64),
+    /// The transaction is given up on, and its writes to a table are undone. Readers hold back
+    /// the undoing along with the writes, so that they show neither.
+    Abort(u64),
+}
+
+/// A failed [`ReadTransaction`] operation.
+#[derive(Debug, Fail)]
+pub enum TransactionError {
+    /// A view has already moved past the transaction's snapshot.
+    ///
+    /// The transaction must be restarted to read a consistent snapshot.
+    #[fail(display = "a view has moved past the transaction's snapshot")]
+    Conflict,
+    /// A view did not catch up with the transaction's snapshot in time.
+    #[fail(display = "a view did not catch up with the transaction's snapshot")]
+    Timeout,
+    /// The underlying view read failed.
+    #[fail(display = "{}", _0)]
+    View(#[cause] ViewError),
+}
+
+impl From<ViewError> for TransactionError {
+    fn from(e: ViewError) -> Self {
+        TransactionError::View(e)
+    }
+}
+
+/// A sequence of reads across views that all observe the same writes.
+///
+/// The first read from a view pins the writes that view reflects. Later reads wait for lagging
+/// views to catch up with the pinned writes, and fail with [`TransactionError::Conflict`] if a
+/// view has already applied writes beyond them, in which case the caller should start a new
+/// transaction.
+///
+/// Views that share no base tables do not constrain each other. Write numbering restarts when a
+/// base table's domain is restarted, so transactions should not span a recovery.
+#[derive(Clone, Debug)]
+pub struct ReadTransaction {
+    snapshot: Watermarks,
+    timeout: Duration,
+}
+
+impl ReadTransaction {
+    pub(crate) fn new() -> Self {
+        ReadTransaction {
+            snapshot: Watermarks::default(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long a read may wait for a lagging view to catch up with the snapshot.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The writes pinned by this transaction so far.
+    pub fn snapshot(&self) -> &Watermarks {
+        &self.snapshot
+    }
+
+    /// Retrieve the query results for the given parameter value as of the transaction's snapshot.
+    ///
+    /// Misses are backfilled before the read completes.
+    pub async fn lookup(
+        &mut self,
+        view: &mut View,
+        key: &[DataType],
+    ) -> Result<Results, TransactionError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let (rs, watermarks) = read(view, key).await?;
+
+            if watermarks.is_ahead_of(&self.snapshot) {
+                return Err(TransactionError::Conflict);
+            }
+            if self.snapshot.is_ahead_of(&watermarks) {
+                if Instant::now() > deadline {
+                    return Err(TransactionError::Timeout);
+                }
+                tokio::time::delay_for(RETRY_INTERVAL).await;
+                continue;
+            }
+
+            self.snapshot.merge(&watermarks);
+            return Ok(rs);
+        }
+    }
+
+    /// Retrieve the query results for a parameter value from each of several views, all as of the
+    /// same writes.
+    ///
+    /// Where [`ReadTransaction::lookup`] pins the writes the first view it reads reflects, this
+    /// reads every view first, and then reads the ones that lag behind the others again until they
+    /// have caught up. Only the writes pinned by earlier reads in the transaction constrain it, so
+    /// at the start of a transaction it settles on the latest writes that all the views reflect,
+    /// such as a story and its vote count as of the same vote.
+    ///
+    /// The results are returned in the order of `reads`.
+    pub async fn lookup_all(
+        &mut self,
+        reads: &mut [(&mut View, &[DataType])],
+    ) -> Result<Vec<Results>, TransactionError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut results = Vec::with_capacity(reads.len());
+        for (view, key) in reads.iter_mut() {
+            results.push(read(view, *key).await?);
+        }
+
+        loop {
+            let mut frontier = self.snapshot.clone();
+            for (_, watermarks) in &results {
+                if watermarks.is_ahead_of(&self.snapshot) {
+                    return Err(TransactionError::Conflict);
+                }
+                frontier.merge(watermarks);
+            }
+
+            let behind: Vec<_> = (0..results.len())
+                .filter(|&i| frontier.is_ahead_of(&results[i].1))
+                .collect();
+            if behind.is_empty() {
+                self.snapshot = frontier;
+                return Ok(results.into_iter().map(|(rs, _)| rs).collect());
+            }
+            if Instant::now() > deadline {
+                return Err(TransactionError::Timeout);
+            }
+            tokio::time::delay_for(RETRY_INTERVAL).await;
+            for i in behind {
+                let (ref mut view, key) = reads[i];
+                results[i] = read(view, key).await?;
+            }
+        }
+    }
+}
+
+/// Read `key` from `view` along with the writes the result reflects, filling it if it misses.
+async fn read(view: &mut View, key: &[DataType]) -> Result<(Results, Watermarks), ViewError> {
+    loop {
+        match view.lookup_watermarked(key).await? {
+            Some(r) => return Ok(r),
+            None => {
+                // fill the hole and try again
+                view.lookup(key, true).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_shared_bases_are_compared() {
+        let mut a = Watermarks::default();
+        a.advance(1, 0, 3);
+        a.advance(2, 0, 7);
+        let mut b = Watermarks::default();
+        b.advance(1, 0, 3);
+        b.advance(3, 0, 1);
+        assert!(!a.is_ahead_of(&b));
+        assert!(!b.is_ahead_of(&a));
+
+        b.advance(1, 0, 4);
+        assert!(b.is_ahead_of(&a));
+        assert!(!a.is_ahead_of(&b));
+    }
+
+    #[test]
+    fn merge_keeps_the_latest_write() {
+        let mut a = Watermarks::default();
+        a.advance(1, 0, 3);
+        a.advance(1, 0, 2);
+        assert_eq!(a.get(1, 0), Some(3));
+
+        let mut b = Watermarks::default();
+        b.advance(1, 0, 5);
+        b.advance(1, 1, 1);
+        assert!(a.merge(&b));
+        assert!(!a.merge(&b));
+        assert_eq!(a.get(1, 0), Some(5));
+        assert_eq!(a.get(1, 1), Some(1));
+    }
+
+    #[test]
+    fn unknown_shards_are_not_included() {
+        let mut a = Watermarks::default();
+        a.advance(1, 0, 3);
+        let mut token = Watermarks::default();
+        assert!(a.includes(&token));
+
+        token.advance(1, 0, 3);
+        assert!(a.includes(&token));
+        token.advance(1, 1, 1);
+        assert!(!a.includes(&token));
+        a.advance(1, 1, 2);
+        assert!(a.includes(&token));
+        token.advance(1, 0, 4);
+        assert!(!a.includes(&token));
+    }
+}
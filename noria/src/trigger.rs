@@ -0,0 +1,76 @@
+/// What to do when the contents of a view that a trigger is attached to change.
+///
+/// Actions are executed by the controller at least once for every batch of changes, so they
+/// should be idempotent. If actions keep failing, the controller drops the oldest batches once
+/// the unfinished ones take up half a megabyte.
+///
+/// `Kafka` and `Redis` actions are instead run by the worker that the view's changes come from,
+/// at most once, so that the changes do not have to pass through the controller. A worker drops
+/// batches that it fails to publish, and batches that arrive while too many are waiting to be
+/// published.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TriggerAction {
+    /// Insert every row that is added to the view into the given base table.
+    Insert {
+        /// The table to insert into.
+        table: String,
+    },
+    /// `POST` every batch of changes to the given URL as JSON.
+    Webhook {
+        /// The URL to post to.
+        url: String,
+    },
+    /// Append every batch of changes to the given file as a line of JSON.
+    Sink {
+        /// The file to append to.
+        path: String,
+    },
+    /// Publish every batch of changes to the given Kafka topic as a message of JSON.
+    ///
+    /// Only deployments built with the `connectors` feature support this. Messages are keyed by
+    /// the trigger's name, so a view's changes stay in order within a partition. A batch counts
+    /// as delivered once all in-sync replicas have acknowledged it.
+    Kafka {
+        /// The brokers to connect to, as a comma-separated list of `host:port`.
+        brokers: String,
+        /// The topic to publish to.
+        topic: String,
+    },
+    /// Publish every batch of changes to the given Redis pub/sub channel as JSON.
+    ///
+    /// Only deployments built with the `connectors` feature support this. Redis does not keep
+    /// published messages, so only subscribers that are connected when a batch is published see
+    /// it.
+    Redis {
+        /// The URL of the Redis server, as in `redis://host:port/`.
+        url: String,
+        /// The channel to publish to.
+        channel: String,
+    },
+    /// Keep the given base table of another Noria deployment in sync with the view.
+    ///
+    /// Rows added to the view are inserted into the table, and rows removed from the view are
+    /// deleted from it by the table's primary key, so the table must have one. This lets views in
+    /// the other deployment join against this one's view as if it were a local table. Only changes
+    /// made after the trigger is added are forwarded.
+    Federate {
+        /// The ZooKeeper address of the other deployment.
+        zookeeper: String,
+        /// The table to keep in sync.
+        table: String,
+    },
+    /// Delete the rows of the given base table that refer to rows removed from the view.
+    ///
+    /// The rows deleted are those whose `column` holds the removed row's value in the view's
+    /// column at index `key`. Values that the same batch of changes adds back, as when another
+    /// column of the row is updated, are left alone. This is how foreign keys declared with
+    /// `ON DELETE CASCADE` are enforced.
+    DeleteReferencing {
+        /// The table to delete from.
+        table: String,
+        /// The column of `table` that refers to the view's rows.
+        column: String,
+        /// The index of the view's column that `column` refers to.
+        key: usize,
+    },
+}
@@ -0,0 +1,30 @@
+use crate::DataType;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The future returned by [`Upstream::execute`].
+pub type UpstreamFuture =
+    Pin<Box<dyn Future<Output = Result<Vec<Vec<DataType>>, failure::Error>> + Send>>;
+
+/// A database that a [`View`](crate::View) can read from instead while it is still computing
+/// results, such as the database that Noria replicates.
+///
+/// This lets an adapter adopt Noria for latency-critical reads: a lookup that misses and does not
+/// complete in time is answered by the upstream database, while the view fills in the results for
+/// later reads.
+pub trait Upstream: Send + Sync {
+    /// Execute `query` with `params` as the values of its parameters, and return the rows it
+    /// produces.
+    ///
+    /// `query` is the SQL text of the query that defines the view, with a `?` for each parameter.
+    fn execute(&self, query: &str, params: &[DataType]) -> UpstreamFuture;
+}
+
+/// How a view falls back to its upstream database.
+#[derive(Clone)]
+pub(crate) struct Fallback {
+    pub(crate) upstream: Arc<dyn Upstream>,
+    pub(crate) timeout: Duration,
+}
@@ -1,19 +1,25 @@
-use crate::data::*;
-use crate::{Tagged, Tagger};
+use crate::session::Session;
+use crate::transaction::Watermarks;
+use crate::upstream::{Fallback, Upstream};
+use crate::{DataType, ShardScheme, Tagged, Tagger, TraceContext};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::StreamExt, stream::TryStreamExt,
+    stream::Stream, stream::StreamExt, stream::TryStreamExt,
 };
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, OrderType};
 use petgraph::graph::NodeIndex;
-use std::collections::HashMap;
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::future::Future;
 use std::io;
+use std::mem;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio_tower::multiplex;
 use tower_balance::p2c::Balance;
 use tower_buffer::Buffer;
@@ -21,12 +27,15 @@ use tower_discover::ServiceStream;
 use tower_limit::concurrency::ConcurrencyLimit;
 use tower_service::Service;
 
-type Transport = AsyncBincodeStream<
-    tokio::net::TcpStream,
-    Tagged<ReadReply>,
-    Tagged<ReadQuery>,
-    AsyncDestination,
->;
+/// The most keys that [`View::lookup_batched`] asks for in a single request.
+const MAX_BATCH_KEYS: usize = 1024;
+
+/// How long a subscription waits before asking the view's workers for changes again after they
+/// had none.
+const SUBSCRIPTION_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type Transport =
+    AsyncBincodeStream<crate::tls::Stream, Tagged<ReadReply>, Tagged<ReadQuery>, AsyncDestination>;
 
 #[derive(Debug)]
 struct Endpoint(SocketAddr);
@@ -55,7 +64,7 @@ impl Service<()> for Endpoint {
         async move {
             let s = f.await?;
             s.set_nodelay(true)?;
-            let s = AsyncBincodeStream::from(s).for_async();
+            let s = AsyncBincodeStream::from(crate::tls::Stream::client(s)).for_async();
             let t = multiplex::MultiplexTransport::new(s, Tagger::default());
             Ok(multiplex::Client::with_error_handler(t, |e| {
                 eprintln!("view server went away: {}", e)
@@ -104,6 +113,29 @@ pub enum ViewError {
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
+    /// The upstream database failed to answer a lookup that fell back to it.
+    #[fail(display = "upstream read failed: {}", _0)]
+    UpstreamError(#[cause] failure::Error),
+    /// The worker serving the view has too many reads pending, and turned this one away.
+    ///
+    /// The read had no effect, and can be retried.
+    #[fail(display = "the view's worker is overloaded")]
+    Overloaded,
+    /// The connection to the view's worker has used up its share of lookups or upqueries, and
+    /// the read was turned away.
+    ///
+    /// The read had no effect, and can be retried once the connection has fewer reads in flight,
+    /// or in the next second.
+    #[fail(display = "the read quota of this connection is exhausted")]
+    QuotaExceeded,
+    /// A read did not complete before its deadline, either because the view did not reflect the
+    /// writes the read was asked to wait for, or because a missing key was not filled in time.
+    #[fail(display = "the read did not complete in time")]
+    Timeout,
+    /// The view's workers dropped a subscription, either because it was not polled for too long
+    /// or because it let too many changes pile up. Changes may have been missed.
+    #[fail(display = "the subscription was dropped by the view's workers")]
+    SubscriptionLost,
 }
 
 impl From<Box<dyn std::error::Error + Send + Sync>> for ViewError {
@@ -123,12 +155,57 @@ pub enum ReadQuery {
         keys: Vec<Vec<DataType>>,
         /// Whether to block if a partial replay is triggered
         block: bool,
+        /// The trace that the replays this read triggers are part of, if it is being traced
+        #[serde(default)]
+        trace: Option<TraceContext>,
     },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Read a single key from a leaf view along with the writes the view reflects
+    Watermarked {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read with
+        key: Vec<DataType>,
+    },
+    /// Read the low watermark of the event times a leaf view reflects
+    EventTime {
+        /// Where to read from
+        target: (NodeIndex, usize),
+    },
+    /// Read the time up to which a leaf view reflects every write to its base tables
+    WriteFrontier {
+        /// Where to read from
+        target: (NodeIndex, usize),
+    },
+    /// Start queueing the changes to the given keys of a leaf view
+    Subscribe {
+        /// Where to subscribe
+        target: (NodeIndex, usize),
+        /// Keys to watch
+        keys: Vec<Vec<DataType>>,
+    },
+    /// Take the changes queued for a subscription
+    Changes {
+        /// Where the subscription was made
+        target: (NodeIndex, usize),
+        /// The subscription's id
+        id: u64,
+    },
+    /// Read some of the rows of a single key from a leaf view, without blocking
+    Chunk {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Key to read with
+        key: Vec<DataType>,
+        /// How many of the key's rows to skip
+        skip: usize,
+        /// The most rows to read
+        rows: usize,
+    },
 }
 
 #[doc(hidden)]
@@ -138,6 +215,27 @@ pub enum ReadReply<D = ReadReplyBatch> {
     Normal(Result<Vec<D>, ()>),
     /// Read size of view
     Size(usize),
+    /// Errors if view isn't ready yet, and is `None` if the key missed.
+    Watermarked(Result<Option<(D, Watermarks)>, ()>),
+    /// Low watermark of the view's event times, in milliseconds since the Unix epoch.
+    EventTime(Option<i64>),
+    /// Write frontier of the view, in milliseconds since the Unix epoch.
+    WriteFrontier(Option<i64>),
+    /// The read was turned away because the worker has too many reads pending.
+    Overloaded,
+    /// The read was turned away because the connection exceeded its read quota.
+    QuotaExceeded,
+    /// The id of a new subscription. Errors if view isn't ready yet.
+    Subscribed(Result<u64, ()>),
+    /// The changes queued for a subscription, each along with whether it was added, or `None` if
+    /// the subscription was dropped.
+    Changes(Option<Vec<(Vec<DataType>, bool)>>),
+    /// Like a successful `Normal` reply, but the rows of the keys at the given positions were cut
+    /// off at the worker's row cap.
+    Truncated(Vec<D>, Vec<usize>),
+    /// Errors if view isn't ready yet, and is `None` if the key missed. Otherwise holds the rows
+    /// read, and whether the key has more rows after them.
+    Chunk(Result<Option<(D, bool)>, ()>),
 }
 
 #[doc(hidden)]
@@ -147,6 +245,21 @@ pub struct ViewBuilder {
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
     pub shards: Vec<SocketAddr>,
+    #[serde(default)]
+    pub query: Option<String>,
+    /// The order, offset, and number of the rows returned for a key if every shard may hold
+    /// rows of any key, in which case lookups go to all shards and merge their rows.
+    #[serde(default)]
+    pub merge: Option<(Vec<(usize, OrderType)>, usize, usize)>,
+    /// The other readers that serve the same lookups, along with the addresses of their shards.
+    #[serde(default)]
+    pub replicas: Vec<(NodeIndex, Vec<SocketAddr>)>,
+    /// The position in the key of the value that decides which shard a key is on.
+    #[serde(default)]
+    pub shard_key_index: usize,
+    /// How keys are spread across the shards of the view.
+    #[serde(default)]
+    pub shard_scheme: ShardScheme,
 }
 
 impl ViewBuilder {
@@ -160,54 +273,75 @@ impl ViewBuilder {
         let columns = self.columns.clone();
         let shards = self.shards.clone();
         let schema = self.schema.clone();
+        let query = self.query.clone();
+        let merge = self.merge.clone();
 
-        let mut addrs = Vec::with_capacity(shards.len());
-        let mut conns = Vec::with_capacity(shards.len());
-
-        for (shardi, &addr) in shards.iter().enumerate() {
-            use std::collections::hash_map::Entry;
-
-            addrs.push(addr);
-
-            // one entry per shard so that we can send sharded requests in parallel even if
-            // they happen to be targeting the same machine.
-            let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let (c, w) = Buffer::pair(
-                        ConcurrencyLimit::new(
-                            Balance::from_entropy(make_views_discover(addr)),
-                            crate::PENDING_LIMIT,
-                        ),
-                        crate::BUFFER_TO_POOL,
-                    );
-                    use tracing_futures::Instrument;
-                    tokio::spawn(w.instrument(tracing::debug_span!(
-                        "view_worker",
-                        addr = %addr,
-                        shard = shardi
-                    )));
-                    h.insert(c.clone());
-                    c
-                }
-            };
-            conns.push(s);
-        }
+        let conns = connect(&rpcs, &shards);
+        let replicas = self
+            .replicas
+            .iter()
+            .map(|(node, addrs)| (*node, connect(&rpcs, addrs), addrs.clone()))
+            .collect();
 
         let tracer = tracing::dispatcher::get_default(|d| d.clone());
         Ok(View {
             node,
             schema,
             columns,
-            shard_addrs: addrs,
+            shard_addrs: shards,
             shards: conns,
+            replicas,
+            picked: false,
+            shard_key_index: self.shard_key_index,
+            shard_scheme: self.shard_scheme,
+            query,
+            merge,
+            fallback: None,
+            session: Session::default(),
+            recent: HashMap::new(),
             tracer,
         })
     }
 }
 
+/// Connections to the shards of a reader at `addrs`, shared with other views through `rpcs`.
+fn connect(
+    rpcs: &Mutex<HashMap<(SocketAddr, usize), ViewRpc>>,
+    addrs: &[SocketAddr],
+) -> Vec<ViewRpc> {
+    let mut conns = Vec::with_capacity(addrs.len());
+    for (shardi, &addr) in addrs.iter().enumerate() {
+        use std::collections::hash_map::Entry;
+
+        // one entry per shard so that we can send sharded requests in parallel even if
+        // they happen to be targeting the same machine.
+        let mut rpcs = rpcs.lock().unwrap();
+        let s = match rpcs.entry((addr, shardi)) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(h) => {
+                // TODO: maybe always use the same local port?
+                let (c, w) = Buffer::pair(
+                    ConcurrencyLimit::new(
+                        Balance::from_entropy(make_views_discover(addr)),
+                        crate::PENDING_LIMIT,
+                    ),
+                    crate::BUFFER_TO_POOL,
+                );
+                use tracing_futures::Instrument;
+                tokio::spawn(w.instrument(tracing::debug_span!(
+                    "view_worker",
+                    addr = %addr,
+                    shard = shardi
+                )));
+                h.insert(c.clone());
+                c
+            }
+        };
+        conns.push(s);
+    }
+    conns
+}
+
 /// A `View` is used to query previously defined external views.
 ///
 /// Note that if you create multiple `View` handles from a single `ControllerHandle`, they may
@@ -220,6 +354,22 @@ pub struct View {
 
     shards: Vec<ViewRpc>,
     shard_addrs: Vec<SocketAddr>,
+    /// The readers that lookups are not currently sent to, which take turns with the current one.
+    replicas: VecDeque<(NodeIndex, Vec<ViewRpc>, Vec<SocketAddr>)>,
+    /// Whether the reader for the next lookup has been picked since the last lookup.
+    picked: bool,
+    /// The position in the key of the value that decides which shard a key is on.
+    shard_key_index: usize,
+    /// How keys are spread across the shards of the view.
+    shard_scheme: ShardScheme,
+
+    query: Option<String>,
+    merge: Option<(Vec<(usize, OrderType)>, usize, usize)>,
+    fallback: Option<Fallback>,
+
+    session: Session,
+    /// When each key was last read, and what was read, if the session allows stale reads.
+    recent: HashMap<Vec<DataType>, (Instant, Results)>,
 
     tracer: tracing::Dispatch,
 }
@@ -247,6 +397,16 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
     type Future = crate::doc_mock::Future<Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.picked {
+            // spread lookups across the replicas of the reader, if it has any
+            if let Some((node, shards, addrs)) = self.replicas.pop_front() {
+                let node = mem::replace(&mut self.node, node);
+                let shards = mem::replace(&mut self.shards, shards);
+                let addrs = mem::replace(&mut self.shard_addrs, addrs);
+                self.replicas.push_back((node, shards, addrs));
+            }
+            self.picked = true;
+        }
         for s in &mut self.shards {
             ready!(s.poll_ready(cx)).map_err(ViewError::from)?;
         }
@@ -254,11 +414,14 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
     }
 
     fn call(&mut self, (keys, block): (Vec<Vec<DataType>>, bool)) -> Self::Future {
-        let span = if crate::trace_next_op() {
+        self.picked = false;
+        let trace = crate::trace_next_op();
+        let span = if let Some(ref ctx) = trace {
             Some(tracing::trace_span!(
                 "view-request",
                 ?keys,
-                node = self.node.index()
+                node = self.node.index(),
+                traceparent = %ctx.traceparent()
             ))
         } else {
             None
@@ -270,6 +433,7 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                 target: (self.node, 0),
                 keys,
                 block,
+                trace,
             });
 
             let _guard = span.as_ref().map(tracing::Span::enter);
@@ -280,35 +444,83 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                     .call(request)
                     .map_err(ViewError::from)
                     .and_then(move |reply| async move {
-                        match reply.v {
-                            ReadReply::Normal(Ok(rows)) => Ok(rows
-                                .into_iter()
-                                .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                                .collect()),
-                            ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                            _ => unreachable!(),
-                        }
+                        Ok(lookup_rows(reply.v)?
+                            .into_iter()
+                            .map(|(rows, truncated)| {
+                                Results::new(rows.into(), Arc::clone(&columns))
+                                    .with_truncated(truncated)
+                            })
+                            .collect())
                     }),
             );
         }
 
+        let node = self.node;
+        if let Some((order, offset, limit)) = self.merge.clone() {
+            if let Some(ref span) = span {
+                span.in_scope(|| tracing::trace!("merging shard request"));
+            }
+            // any shard may have rows for any of the keys, so ask them all, and merge their rows
+            let nkeys = keys.len();
+            return future::Either::Right(future::Either::Left(
+                self.shards
+                    .iter_mut()
+                    .enumerate()
+                    .map(move |(shardi, shard)| {
+                        let request = Tagged::from(ReadQuery::Normal {
+                            target: (node, shardi),
+                            keys: keys.clone(),
+                            block,
+                            trace,
+                        });
+
+                        shard
+                            .call(request)
+                            .map_err(ViewError::from)
+                            .and_then(|reply| async move { lookup_rows(reply.v) })
+                    })
+                    .collect::<FuturesUnordered<_>>()
+                    .try_collect::<Vec<_>>()
+                    .map_ok(move |shards| {
+                        let mut shards: Vec<_> = shards.into_iter().map(Vec::into_iter).collect();
+                        (0..nkeys)
+                            .map(|_| {
+                                let mut truncated = false;
+                                let rows = shards
+                                    .iter_mut()
+                                    .map(|rows| {
+                                        let (rows, t) = rows.next().unwrap();
+                                        truncated |= t;
+                                        rows.into()
+                                    })
+                                    .collect();
+                                let rows = merge_ordered(rows, &order, offset, limit);
+                                Results::new(rows, Arc::clone(&columns)).with_truncated(truncated)
+                            })
+                            .collect()
+                    }),
+            ));
+        }
+
         if let Some(ref span) = span {
             span.in_scope(|| tracing::trace!("shard request"));
         }
-        assert!(keys.iter().all(|k| k.len() == 1));
+        // compound keys are sharded by one of their columns
+        let nkeys = keys.len();
         let mut shard_queries = vec![Vec::new(); self.shards.len()];
-        for key in keys {
-            let shard = crate::shard_by(&key[0], self.shards.len());
+        let mut shard_indices = vec![Vec::new(); self.shards.len()];
+        for (i, key) in keys.into_iter().enumerate() {
+            let shard = self.shard_of(&key, self.shards.len());
             shard_queries[shard].push(key);
+            shard_indices[shard].push(i);
         }
 
-        let node = self.node;
-        future::Either::Right(
+        future::Either::Right(future::Either::Right(
             self.shards
                 .iter_mut()
                 .enumerate()
-                .zip(shard_queries.into_iter())
-                .filter_map(|((shardi, shard), shard_queries)| {
+                .zip(shard_queries.into_iter().zip(shard_indices))
+                .filter_map(|((shardi, shard), (shard_queries, indices))| {
                     if shard_queries.is_empty() {
                         // poll_ready reserves a sender slot which we have to release
                         // we do that by dropping the old handle and replacing it with a clone
@@ -316,14 +528,15 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         *shard = shard.clone();
                         None
                     } else {
-                        Some(((shardi, shard), shard_queries))
+                        Some(((shardi, shard), shard_queries, indices))
                     }
                 })
-                .map(move |((shardi, shard), shard_queries)| {
+                .map(move |((shardi, shard), shard_queries, indices)| {
                     let request = Tagged::from(ReadQuery::Normal {
                         target: (node, shardi),
                         keys: shard_queries,
                         block,
+                        trace,
                     });
 
                     let _guard = span.as_ref().map(tracing::Span::enter);
@@ -339,27 +552,227 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                     shard
                         .call(request)
                         .map_err(ViewError::from)
-                        .and_then(|reply| async move {
-                            match reply.v {
-                                ReadReply::Normal(Ok(rows)) => Ok(rows),
-                                ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                                _ => unreachable!(),
-                            }
-                        })
+                        .and_then(|reply| async move { Ok((indices, lookup_rows(reply.v)?)) })
                 })
                 .collect::<FuturesUnordered<_>>()
-                .try_concat()
-                .map_ok(move |rows| {
-                    rows.into_iter()
-                        .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                        .collect()
+                .try_collect::<Vec<_>>()
+                .map_ok(move |shards| {
+                    // shards reply in any order, so put each key's rows back where it was asked
+                    let mut results: Vec<_> = (0..nkeys).map(|_| None).collect();
+                    for (indices, rows) in shards {
+                        for (i, (rows, truncated)) in indices.into_iter().zip(rows) {
+                            let rows = Results::new(rows.into(), Arc::clone(&columns));
+                            results[i] = Some(rows.with_truncated(truncated));
+                        }
+                    }
+                    results.into_iter().map(Option::unwrap).collect()
                 }),
-        )
+        ))
+    }
+}
+
+/// The rows of each key of a reply to a lookup, along with whether they were truncated.
+fn lookup_rows(reply: ReadReply) -> Result<Vec<(ReadReplyBatch, bool)>, ViewError> {
+    match reply {
+        ReadReply::Normal(Ok(rows)) => Ok(rows.into_iter().map(|rows| (rows, false)).collect()),
+        ReadReply::Truncated(rows, truncated) => {
+            let mut rows: Vec<_> = rows.into_iter().map(|rows| (rows, false)).collect();
+            for i in truncated {
+                rows[i].1 = true;
+            }
+            Ok(rows)
+        }
+        ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+        ReadReply::Overloaded => Err(ViewError::Overloaded),
+        ReadReply::QuotaExceeded => Err(ViewError::QuotaExceeded),
+        _ => unreachable!(),
+    }
+}
+
+/// A batch of keys being looked up, which resolves to the keys along with their results.
+type Batch = Pin<Box<dyn Future<Output = Result<Vec<(Vec<DataType>, Results)>, ViewError>> + Send>>;
+
+/// The results of [`View::lookup_batched`]: each key that was looked up, along with its results.
+///
+/// Keys are yielded in the order their batches complete. If a batch fails, its error is yielded
+/// in place of its keys, and the keys of the other batches still follow.
+pub struct BatchedLookup {
+    batches: FuturesUnordered<Batch>,
+    ready: std::vec::IntoIter<(Vec<DataType>, Results)>,
+}
+
+impl fmt::Debug for BatchedLookup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchedLookup")
+            .field("pending_batches", &self.batches.len())
+            .field("ready", &self.ready.len())
+            .finish()
+    }
+}
+
+impl Stream for BatchedLookup {
+    type Item = Result<(Vec<DataType>, Results), ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(r) = self.ready.next() {
+                return Poll::Ready(Some(Ok(r)));
+            }
+            match ready!(self.batches.poll_next_unpin(cx)) {
+                None => return Poll::Ready(None),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Some(Ok(batch)) => self.ready = batch.into_iter(),
+            }
+        }
+    }
+}
+
+/// The changes to the watched keys of a view; see [`View::subscribe`].
+///
+/// Each change is a row, along with whether it was added (`true`) or removed (`false`).
+pub struct Subscription {
+    changes: Pin<Box<dyn Stream<Item = Result<(Vec<DataType>, bool), ViewError>> + Send>>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").finish()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<(Vec<DataType>, bool), ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.changes.poll_next_unpin(cx)
+    }
+}
+
+/// How far a chunked read of the rows of a key has got; see [`View::lookup_chunk`].
+///
+/// Cursors can be serialized, so that a read can be picked up by another client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Cursor {
+    /// The number of rows read so far.
+    offset: usize,
+}
+
+/// The rows of a single key, read in chunks; see [`View::lookup_stream`].
+pub struct LookupStream {
+    chunks: Pin<Box<dyn Stream<Item = Result<Results, ViewError>> + Send>>,
+}
+
+impl fmt::Debug for LookupStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LookupStream").finish()
+    }
+}
+
+impl Stream for LookupStream {
+    type Item = Result<Results, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.chunks.poll_next_unpin(cx)
+    }
+}
+
+/// The state of a subscription between polls.
+struct Subscribed {
+    view: View,
+    /// The shards that watch some of the keys, and the id of the subscription at each of them.
+    ids: Vec<(usize, u64)>,
+    ready: std::vec::IntoIter<(Vec<DataType>, bool)>,
+}
+
+impl Subscribed {
+    /// Wait for the next change, polling the shards for more if there are none left.
+    ///
+    /// Returns `None` once the subscription has been lost.
+    async fn next(mut self) -> Option<(Result<(Vec<DataType>, bool), ViewError>, Self)> {
+        loop {
+            if let Some(change) = self.ready.next() {
+                return Some((Ok(change), self));
+            }
+            if self.ids.is_empty() {
+                return None;
+            }
+
+            let mut changes = Vec::new();
+            for (shardi, id) in self.ids.clone() {
+                let target = (self.view.node, shardi);
+                match self
+                    .view
+                    .call_shard(shardi, ReadQuery::Changes { target, id })
+                    .await
+                {
+                    Ok(ReadReply::Changes(Some(cs))) => changes.extend(cs),
+                    Ok(ReadReply::Changes(None)) => {
+                        self.ids.clear();
+                        return Some((Err(ViewError::SubscriptionLost), self));
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => return Some((Err(e), self)),
+                }
+            }
+            if changes.is_empty() {
+                tokio::time::delay_for(SUBSCRIPTION_POLL_INTERVAL).await;
+            }
+            self.ready = changes.into_iter();
+        }
+    }
+}
+
+/// Compare two rows by the given columns and SQL order.
+fn cmp_rows(order: &[(usize, OrderType)], a: &[DataType], b: &[DataType]) -> Ordering {
+    for &(c, ref order_type) in order {
+        let result = match *order_type {
+            OrderType::OrderAscending => a[c].cmp(&b[c]),
+            OrderType::OrderDescending => b[c].cmp(&a[c]),
+        };
+        if result != Ordering::Equal {
+            return result;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Merge the rows of several shards, each sorted by `order`, into the `limit` rows overall that
+/// follow the first `offset` ones.
+fn merge_ordered(
+    shards: Vec<Vec<Vec<DataType>>>,
+    order: &[(usize, OrderType)],
+    offset: usize,
+    limit: usize,
+) -> Vec<Vec<DataType>> {
+    let mut shards: Vec<_> = shards
+        .into_iter()
+        .map(|rows| rows.into_iter().peekable())
+        .collect();
+    let mut merged = Vec::new();
+    while merged.len() < offset + limit {
+        let next = shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(shardi, rows)| rows.peek().map(|r| (shardi, r)))
+            .min_by(|(_, a), (_, b)| cmp_rows(order, a, b))
+            .map(|(shardi, _)| shardi);
+        match next {
+            Some(shardi) => merged.push(shards[shardi].next().unwrap()),
+            None => break,
+        }
     }
+    merged.drain(..cmp::min(offset, merged.len()));
+    merged
 }
 
 #[allow(clippy::len_without_is_empty)]
 impl View {
+    /// The shard, out of `shards`, that holds the rows for `key`.
+    fn shard_of(&self, key: &[DataType], shards: usize) -> usize {
+        let i = self.shard_key_index;
+        self.shard_scheme.shard(&key[i..=i], shards)
+    }
+
     /// Get the list of columns in this view.
     pub fn columns(&self) -> &[String] {
         &*self.columns
@@ -370,6 +783,27 @@ impl View {
         self.schema.as_deref()
     }
 
+    /// Get the SQL text of the query that defines this view, if it was defined by one.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Answer blocking lookups from `upstream` when this view cannot answer them within `timeout`.
+    ///
+    /// A lookup falls back if it is still waiting for missing results when the timeout expires,
+    /// or if the view is not yet available. The view keeps computing the missing results, so
+    /// later lookups of the same keys are answered by the view. Lookups that do not block are
+    /// never sent upstream, and neither are lookups on views that are not defined by a query.
+    pub fn fall_back_to(&mut self, upstream: Arc<dyn Upstream>, timeout: Duration) {
+        self.fallback = Some(Fallback { upstream, timeout });
+    }
+
+    /// Apply the settings of `session` to the reads on this view.
+    pub(crate) fn with_session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
     /// Get the current size of this view.
     ///
     /// Note that you must also continue to poll this `View` for the returned future to resolve.
@@ -400,18 +834,228 @@ impl View {
         Ok(nrows)
     }
 
+    /// How far behind the current time the event times that this view reflects are.
+    ///
+    /// The lag is measured from the low watermark of the view, the earliest of the latest event
+    /// times seen by the shards of the base tables it reads from that have event-time columns.
+    /// It is `None` if none of those shards has seen a record with an event time yet.
+    pub async fn event_time_lag(&mut self) -> Result<Option<Duration>, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let node = self.node;
+        let mut rsps = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .map(|(shardi, shard)| {
+                shard.call(Tagged::from(ReadQuery::EventTime {
+                    target: (node, shardi),
+                }))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut low_watermark: Option<i64> = None;
+        while let Some(reply) = rsps.next().await.transpose()? {
+            if let ReadReply::EventTime(t) = reply.v {
+                low_watermark = match (low_watermark, t) {
+                    (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                    (a, b) => a.or(b),
+                };
+            } else {
+                unreachable!();
+            }
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system clock is before the Unix epoch")
+            .as_millis() as i64;
+        Ok(low_watermark.map(|t| Duration::from_millis(cmp::max(now - t, 0) as u64)))
+    }
+
+    /// The earliest write frontier of the given shards of this view.
+    ///
+    /// It is `None` if any of them has not yet been told a frontier by every base table shard it
+    /// reads from.
+    async fn write_frontier(&mut self, shards: &[usize]) -> Result<Option<i64>, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let node = self.node;
+        let mut rsps = shards
+            .iter()
+            .map(|&shardi| {
+                self.shards[shardi].call(Tagged::from(ReadQuery::WriteFrontier {
+                    target: (node, shardi),
+                }))
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut frontier = Some(i64::max_value());
+        while let Some(reply) = rsps.next().await.transpose()? {
+            if let ReadReply::WriteFrontier(t) = reply.v {
+                frontier = match (frontier, t) {
+                    (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                    _ => None,
+                };
+            } else {
+                unreachable!();
+            }
+        }
+        Ok(frontier)
+    }
+
+    /// How far behind the current time this view may be.
+    ///
+    /// Every write that its base tables had processed this long ago is reflected in the view. It
+    /// is `None` if the deployment does not track write frontiers, or no frontier has reached
+    /// every shard of the view yet.
+    pub async fn staleness(&mut self) -> Result<Option<Duration>, ViewError> {
+        let shards: Vec<_> = (0..self.shards.len()).collect();
+        let frontier = self.write_frontier(&shards).await?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system clock is before the Unix epoch")
+            .as_millis() as i64;
+        Ok(frontier.map(|t| Duration::from_millis(cmp::max(now - t, 0) as u64)))
+    }
+
+    /// Retrieve the query results for the given parameter value once they reflect every write
+    /// that the base tables had processed by `since`.
+    ///
+    /// Passing the time a write returned makes the read see that write, as well as any other
+    /// write that finished before it, from any client. The read fails with
+    /// [`ViewError::Timeout`] if the view does not catch up within `timeout`, which it never does
+    /// unless the deployment tracks write frontiers.
+    pub async fn lookup_fresh(
+        &mut self,
+        key: &[DataType],
+        since: std::time::SystemTime,
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        let since = since
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system clock is before the Unix epoch")
+            .as_millis() as i64;
+        let shards = if self.merge.is_some() || self.shards.len() == 1 {
+            (0..self.shards.len()).collect()
+        } else {
+            vec![self.shard_of(key, self.shards.len())]
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.write_frontier(&shards).await? {
+                Some(frontier) if frontier >= since => return self.lookup(key, true).await,
+                _ => {
+                    if Instant::now() > deadline {
+                        return Err(ViewError::Timeout);
+                    }
+                    tokio::time::delay_for(crate::transaction::RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
     /// Retrieve the query results for the given parameter values.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
     /// If `block` is false, misses will be returned as empty results. Any requested keys that have
-    /// missing state will be backfilled (asynchronously if `block` is `false`).
+    /// missing state will be backfilled (asynchronously if `block` is `false`). Blocking lookups
+    /// that take too long are answered by the upstream database instead if one was set with
+    /// [`View::fall_back_to`].
+    ///
+    /// The settings of the [`Session`] of the handle this view was obtained from override
+    /// `block`, and may let blocking lookups be answered from the results of earlier ones.
     pub async fn multi_lookup(
         &mut self,
         keys: Vec<Vec<DataType>>,
         block: bool,
+    ) -> Result<Vec<Results>, ViewError> {
+        let block = self.session.block_on_miss().unwrap_or(block);
+        let max_staleness = self.session.max_staleness();
+        if !block || max_staleness == Duration::from_secs(0) {
+            // non-blocking lookups return empty results for misses, which we mustn't remember
+            return self.read(keys, block).await;
+        }
+
+        let now = Instant::now();
+        self.recent
+            .retain(|_, &mut (at, _)| now.duration_since(at) <= max_staleness);
+        let missing: Vec<_> = keys
+            .iter()
+            .filter(|&key| !self.recent.contains_key(key))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            let rs = self.read(missing.clone(), true).await?;
+            let now = Instant::now();
+            self.recent
+                .extend(missing.into_iter().zip(rs.into_iter().map(|rs| (now, rs))));
+        }
+        Ok(keys.iter().map(|key| self.recent[key].1.clone()).collect())
+    }
+
+    /// Retrieve the query results for many parameter values at once.
+    ///
+    /// Repeated keys are looked up only once. The rest are sent in batches of up to 1024 keys,
+    /// each of which is a single request to each shard, and the misses of each batch are
+    /// backfilled together. All batches are sent before this method returns, and each key is
+    /// yielded along with its results as soon as its batch completes, so a batch that waits for
+    /// backfills does not hold up the others.
+    ///
+    /// Unlike [`View::multi_lookup`], this ignores the view's session and upstream fallback.
+    pub async fn lookup_batched(
+        &mut self,
+        mut keys: Vec<Vec<DataType>>,
+        block: bool,
+    ) -> Result<BatchedLookup, ViewError> {
+        keys.sort();
+        keys.dedup();
+
+        let batches = FuturesUnordered::new();
+        while !keys.is_empty() {
+            let batch = keys.split_off(keys.len().saturating_sub(MAX_BATCH_KEYS));
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            let results = self.call((batch.clone(), block));
+            let batch: Batch =
+                Box::pin(results.map_ok(move |rs| batch.into_iter().zip(rs).collect()));
+            batches.push(batch);
+        }
+        Ok(BatchedLookup {
+            batches,
+            ready: Vec::new().into_iter(),
+        })
+    }
+
+    async fn read(
+        &mut self,
+        keys: Vec<Vec<DataType>>,
+        block: bool,
     ) -> Result<Vec<Results>, ViewError> {
         future::poll_fn(|cx| self.poll_ready(cx)).await?;
-        self.call((keys, block)).await
+        let fallback = match (block, self.fallback.clone(), self.query.clone()) {
+            (true, Some(fallback), Some(query)) => (fallback, query),
+            _ => return self.call((keys, block)).await,
+        };
+
+        match tokio::time::timeout(fallback.timeout, self.call((keys.clone(), true))).await {
+            Ok(Err(ViewError::NotYetAvailable)) | Err(_) => {}
+            Ok(rs) => return rs,
+        }
+
+        tracing::debug!(node = self.node.index(), "falling back to upstream");
+        let columns: Arc<[String]> = Arc::from(&self.columns[..]);
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            let rows = fallback
+                .upstream
+                .execute(&query, &key)
+                .await
+                .map_err(ViewError::UpstreamError)?;
+            results.push(Results::new(rows, Arc::clone(&columns)));
+        }
+        Ok(results)
     }
 
     /// Retrieve the query results for the given parameter value.
@@ -423,6 +1067,279 @@ impl View {
         Ok(rs.into_iter().next().unwrap())
     }
 
+    /// Retrieve the query results for the given parameter value, waiting at most `timeout` for
+    /// them if the key misses.
+    ///
+    /// A miss triggers a replay of the key just like a blocking lookup does, but rather than wait
+    /// for as long as the replay takes, the lookup fails with [`ViewError::Timeout`] once `timeout`
+    /// has passed. The replay carries on regardless, so a later lookup of the key may well hit.
+    pub async fn lookup_timeout(
+        &mut self,
+        key: &[DataType],
+        timeout: Duration,
+    ) -> Result<Results, ViewError> {
+        tokio::time::timeout(timeout, self.lookup(key, true))
+            .await
+            .map_err(|_| ViewError::Timeout)?
+    }
+
+    /// Retrieve the query results for the given parameter value along with the writes they
+    /// reflect, without blocking.
+    ///
+    /// Returns `None` if the key missed, in which case a backfill is triggered.
+    pub(crate) async fn lookup_watermarked(
+        &mut self,
+        key: &[DataType],
+    ) -> Result<Option<(Results, Watermarks)>, ViewError> {
+        let columns: Arc<[String]> = Arc::from(&self.columns[..]);
+        if let Some((order, offset, limit)) = self.merge.clone() {
+            // the merged rows reflect the writes that any of the shards reflect
+            let mut rows = Vec::with_capacity(self.shards.len());
+            let mut watermarks = Watermarks::default();
+            for shardi in 0..self.shards.len() {
+                match self.lookup_watermarked_shard(shardi, key).await? {
+                    Some((rs, w)) => {
+                        rows.push(rs);
+                        watermarks.merge(&w);
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let rows = merge_ordered(rows, &order, offset, limit);
+            return Ok(Some((Results::new(rows, columns), watermarks)));
+        }
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            self.shard_of(&key, self.shards.len())
+        };
+        Ok(self
+            .lookup_watermarked_shard(shardi, key)
+            .await?
+            .map(|(rows, watermarks)| (Results::new(rows, columns), watermarks)))
+    }
+
+    /// Retrieve the query results for the given parameter value once they reflect the writes
+    /// that `token` was issued for.
+    ///
+    /// Tokens come from [`Table::insert_with_token`](crate::Table::insert_with_token), and let a
+    /// client read its own writes without making every read wait. Misses are backfilled, and the
+    /// read fails with [`ViewError::Timeout`] if the view does not catch up within five seconds.
+    pub async fn lookup_after(
+        &mut self,
+        key: &[DataType],
+        token: &Watermarks,
+    ) -> Result<Results, ViewError> {
+        if token.is_empty() {
+            return self.lookup(key, true).await;
+        }
+
+        let deadline = Instant::now() + crate::transaction::DEFAULT_TIMEOUT;
+        loop {
+            match self.lookup_watermarked(key).await? {
+                Some((rs, watermarks)) if watermarks.includes(token) => return Ok(rs),
+                Some(_) => {
+                    if Instant::now() > deadline {
+                        return Err(ViewError::Timeout);
+                    }
+                    tokio::time::delay_for(crate::transaction::RETRY_INTERVAL).await;
+                }
+                None => {
+                    // fill the hole and try again
+                    self.lookup(key, true).await?;
+                }
+            }
+        }
+    }
+
+    /// Retrieve up to `rows` of the query results for the given parameter value, starting at
+    /// `cursor`, along with the cursor that the next chunk starts at.
+    ///
+    /// The first chunk starts at `Cursor::default()`, and no cursor is returned with the last
+    /// one. Only a chunk's worth of rows is held in memory at a time, by the client and by the
+    /// view's worker, so this reads keys with more rows than a single lookup returns. A missing
+    /// key is backfilled, and the read fails with [`ViewError::Timeout`] if that takes longer than
+    /// five seconds.
+    ///
+    /// The chunks are not read from a single snapshot of the view, so rows that are added or
+    /// removed between chunks may be missed or read twice.
+    pub async fn lookup_chunk(
+        &mut self,
+        key: &[DataType],
+        cursor: Cursor,
+        rows: usize,
+    ) -> Result<(Results, Option<Cursor>), ViewError> {
+        assert_ne!(rows, 0);
+        let columns: Arc<[String]> = Arc::from(&self.columns[..]);
+        if self.merge.is_some() {
+            // merged rows only exist on the client, and are no more than the view's limit
+            let all: Vec<Vec<DataType>> = self.lookup(key, true).await?.into();
+            let end = cmp::min(cursor.offset.saturating_add(rows), all.len());
+            let start = cmp::min(cursor.offset, end);
+            let next = if end < all.len() {
+                Some(Cursor { offset: end })
+            } else {
+                None
+            };
+            return Ok((Results::new(all[start..end].to_vec(), columns), next));
+        }
+
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            self.shard_of(key, self.shards.len())
+        };
+        let deadline = Instant::now() + crate::transaction::DEFAULT_TIMEOUT;
+        loop {
+            let query = ReadQuery::Chunk {
+                target: (self.node, shardi),
+                key: Vec::from(key),
+                skip: cursor.offset,
+                rows,
+            };
+            match self.call_shard(shardi, query).await? {
+                ReadReply::Chunk(Ok(Some((chunk, more)))) => {
+                    let chunk: Vec<_> = chunk.into();
+                    let next = if more {
+                        Some(Cursor {
+                            offset: cursor.offset + chunk.len(),
+                        })
+                    } else {
+                        None
+                    };
+                    return Ok((Results::new(chunk, columns), next));
+                }
+                ReadReply::Chunk(Ok(None)) => {
+                    // the key is being backfilled
+                    if Instant::now() > deadline {
+                        return Err(ViewError::Timeout);
+                    }
+                    tokio::time::delay_for(crate::transaction::RETRY_INTERVAL).await;
+                }
+                ReadReply::Chunk(Err(())) => return Err(ViewError::NotYetAvailable),
+                ReadReply::Overloaded => return Err(ViewError::Overloaded),
+                ReadReply::QuotaExceeded => return Err(ViewError::QuotaExceeded),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Retrieve the query results for the given parameter value in chunks of up to `rows` rows,
+    /// as [`View::lookup_chunk`] does.
+    ///
+    /// The stream ends after the last chunk, or after the first error.
+    pub fn lookup_stream(&self, key: &[DataType], rows: usize) -> LookupStream {
+        assert_ne!(rows, 0);
+        let start = Some((self.clone(), Vec::from(key), Cursor::default()));
+        let chunks = futures_util::stream::unfold(start, move |state| async move {
+            let (mut view, key, cursor) = state?;
+            match view.lookup_chunk(&key, cursor, rows).await {
+                Ok((chunk, Some(next))) => Some((Ok(chunk), Some((view, key, next)))),
+                Ok((chunk, None)) => Some((Ok(chunk), None)),
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+        LookupStream {
+            chunks: Box::pin(chunks),
+        }
+    }
+
+    /// Read the given key from one shard along with the writes the rows reflect.
+    async fn lookup_watermarked_shard(
+        &mut self,
+        shardi: usize,
+        key: &[DataType],
+    ) -> Result<Option<(Vec<Vec<DataType>>, Watermarks)>, ViewError> {
+        let target = (self.node, shardi);
+        let key = Vec::from(key);
+        match self
+            .call_shard(shardi, ReadQuery::Watermarked { target, key })
+            .await?
+        {
+            ReadReply::Watermarked(Ok(r)) => {
+                Ok(r.map(|(rows, watermarks)| (rows.into(), watermarks)))
+            }
+            ReadReply::Watermarked(Err(())) => Err(ViewError::NotYetAvailable),
+            ReadReply::Overloaded => Err(ViewError::Overloaded),
+            ReadReply::QuotaExceeded => Err(ViewError::QuotaExceeded),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Send `query` to one shard of the view.
+    async fn call_shard(
+        &mut self,
+        shardi: usize,
+        query: ReadQuery,
+    ) -> Result<ReadReply, ViewError> {
+        let shard = &mut self.shards[shardi];
+        future::poll_fn(|cx| shard.poll_ready(cx))
+            .await
+            .map_err(ViewError::from)?;
+        let reply = shard
+            .call(Tagged::from(query))
+            .await
+            .map_err(ViewError::from)?;
+        Ok(reply.v)
+    }
+
+    /// Watch the given keys for changes to their rows.
+    ///
+    /// The returned stream yields each row that is added to or removed from the results for any
+    /// of the keys from now on. Changes are queued as the view's readers receive them, which may
+    /// be shortly before lookups see them, and the stream collects them from the view's workers,
+    /// asking again every 10 milliseconds while there are none. For a view whose rows are merged
+    /// from all of its shards, such as one with a `LIMIT`, the changes are those to each shard's
+    /// rows.
+    ///
+    /// Missing keys of a partially materialized view are backfilled when subscribing. Changes to
+    /// keys that are evicted later may be missed until the keys are read again. The workers drop
+    /// subscriptions that are not polled for 30 seconds or that let too many changes pile up, in
+    /// which case the stream yields [`ViewError::SubscriptionLost`] and ends.
+    pub async fn subscribe(&mut self, keys: Vec<Vec<DataType>>) -> Result<Subscription, ViewError> {
+        let nshards = self.shards.len();
+        let mut shard_keys = vec![Vec::new(); nshards];
+        for key in keys {
+            if self.merge.is_some() {
+                // any shard may have rows for any of the keys
+                for ks in &mut shard_keys {
+                    ks.push(key.clone());
+                }
+            } else if nshards == 1 {
+                shard_keys[0].push(key);
+            } else {
+                let shard = self.shard_of(&key, nshards);
+                shard_keys[shard].push(key);
+            }
+        }
+
+        let mut ids = Vec::new();
+        for (shardi, keys) in shard_keys.into_iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+            let target = (self.node, shardi);
+            match self
+                .call_shard(shardi, ReadQuery::Subscribe { target, keys })
+                .await?
+            {
+                ReadReply::Subscribed(Ok(id)) => ids.push((shardi, id)),
+                ReadReply::Subscribed(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            }
+        }
+
+        let subscribed = Subscribed {
+            view: self.clone(),
+            ids,
+            ready: Vec::new().into_iter(),
+        };
+        Ok(Subscription {
+            changes: Box::pin(futures_util::stream::unfold(subscribed, Subscribed::next)),
+        })
+    }
+
     /// Retrieve the first query result for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
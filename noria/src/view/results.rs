@@ -1,13 +1,14 @@
-use crate::data::*;
+use crate::DataType;
 use std::fmt;
 use std::ops::Deref;
 use std::sync::Arc;
 
 /// A result set from a Noria query.
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Results {
     results: Vec<Vec<DataType>>,
     columns: Arc<[String]>,
+    truncated: bool,
 }
 
 impl Results {
@@ -15,13 +16,30 @@ impl Results {
     // https://github.com/rust-lang/rust/issues/69785
     #[doc(hidden)]
     pub fn new(results: Vec<Vec<DataType>>, columns: Arc<[String]>) -> Self {
-        Self { results, columns }
+        Self {
+            results,
+            columns,
+            truncated: false,
+        }
+    }
+
+    pub(crate) fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
     }
 
     /// Iterate over references to the returned rows.
     pub fn iter(&self) -> ResultIter<'_> {
         self.into_iter()
     }
+
+    /// Whether the view's worker left out rows beyond its cap on the rows of a single lookup.
+    ///
+    /// All the rows of a key whose results were truncated can still be read in chunks with
+    /// [`View::lookup_chunk`](crate::View::lookup_chunk).
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
 }
 
 impl Into<Vec<Vec<DataType>>> for Results {
@@ -21,7 +21,10 @@ impl SizeOf for DataType {
         use std::mem::size_of_val;
 
         let inner = match *self {
-            DataType::Text(ref t) => size_of_val(t) as u64 + t.to_bytes().len() as u64,
+            DataType::Text(ref t) | DataType::Json(ref t) => {
+                size_of_val(t) as u64 + t.to_bytes().len() as u64
+            }
+            DataType::Compressed(ref c) => c.size() as u64,
             _ => 0u64,
         };
 
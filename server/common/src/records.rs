@@ -3,11 +3,13 @@ use std::borrow::Borrow;
 use std::ops::{Deref, DerefMut};
 
 /// A record is a single positive or negative data record with an associated time stamp.
+///
+/// Records are sent between domains with the sparse row encoding, so mostly NULL rows are cheap.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
 #[warn(variant_size_differences)]
 pub enum Record {
-    Positive(Vec<DataType>),
-    Negative(Vec<DataType>),
+    Positive(#[serde(with = "noria::sparse")] Vec<DataType>),
+    Negative(#[serde(with = "noria::sparse")] Vec<DataType>),
 }
 
 impl Record {
@@ -0,0 +1,157 @@
+use crate::prelude::*;
+use noria::EvictionPolicy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Allocate the record of lookups on a reader, shared between its writer and its readers.
+pub(super) fn new() -> Accesses {
+    Accesses(Arc::new(Inner {
+        tracking: AtomicBool::new(false),
+        keys: Mutex::new(HashMap::new()),
+    }))
+}
+
+/// When each filled key of a partially materialized reader was last looked up, and how often.
+///
+/// Only eviction policies that pick keys by how they are used need this, so lookups are not
+/// recorded unless the reader's policy asks for it.
+#[derive(Clone)]
+pub(super) struct Accesses(Arc<Inner>);
+
+struct Inner {
+    tracking: AtomicBool,
+    keys: Mutex<HashMap<Vec<DataType>, Access>>,
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    last: Instant,
+    count: u64,
+}
+
+impl Accesses {
+    /// Start or stop recording lookups.
+    pub(super) fn track(&self, tracking: bool) {
+        self.0.tracking.store(tracking, Ordering::Release);
+        if !tracking {
+            self.0.keys.lock().unwrap().clear();
+        }
+    }
+
+    /// Record that `key` was filled, which makes it a candidate for eviction.
+    ///
+    /// Keys are filled because a lookup missed on them, and that lookup is retried once they are,
+    /// so filling a key does not count as looking it up.
+    pub(super) fn filled(&self, key: &[DataType]) {
+        if !self.0.tracking.load(Ordering::Acquire) {
+            return;
+        }
+        let mut keys = self.0.keys.lock().unwrap();
+        if !keys.contains_key(key) {
+            let access = Access {
+                last: Instant::now(),
+                count: 0,
+            };
+            keys.insert(key.to_vec(), access);
+        }
+    }
+
+    /// Record a lookup that found `key`.
+    pub(super) fn touch(&self, key: &[DataType]) {
+        if !self.0.tracking.load(Ordering::Acquire) {
+            return;
+        }
+        let now = Instant::now();
+        let mut keys = self.0.keys.lock().unwrap();
+        if let Some(access) = keys.get_mut(key) {
+            access.last = now;
+            access.count += 1;
+        } else {
+            keys.insert(
+                key.to_vec(),
+                Access {
+                    last: now,
+                    count: 1,
+                },
+            );
+        }
+    }
+
+    /// Stop considering `key`, which is no longer filled.
+    pub(super) fn forget(&self, key: &[DataType]) {
+        if self.0.tracking.load(Ordering::Acquire) {
+            self.0.keys.lock().unwrap().remove(key);
+        }
+    }
+
+    /// The keys that have been recorded, in the order that `policy` evicts them in.
+    pub(super) fn victims(&self, policy: EvictionPolicy) -> Vec<Vec<DataType>> {
+        let keys = self.0.keys.lock().unwrap();
+        let mut victims: Vec<_> = keys.iter().map(|(k, &a)| (a, k)).collect();
+        match policy {
+            // random eviction does not go by lookups
+            EvictionPolicy::Random => victims.clear(),
+            EvictionPolicy::LeastRecentlyUsed | EvictionPolicy::Ttl(_) => {
+                victims.sort_unstable_by_key(|&(a, _)| a.last)
+            }
+            EvictionPolicy::LeastFrequentlyUsed => {
+                victims.sort_unstable_by_key(|&(a, _)| (a.count, a.last))
+            }
+        }
+        victims.into_iter().map(|(_, k)| k.clone()).collect()
+    }
+
+    /// The keys that have not been looked up for `ttl`.
+    pub(super) fn expired(&self, ttl: Duration) -> Vec<Vec<DataType>> {
+        let now = Instant::now();
+        let keys = self.0.keys.lock().unwrap();
+        keys.iter()
+            .filter(|&(_, a)| now.duration_since(a.last) >= ttl)
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn victims_follow_the_policy() {
+        let accesses = new();
+        let key = |n: i32| vec![DataType::from(n)];
+        accesses.filled(&key(1));
+        assert!(accesses
+            .victims(EvictionPolicy::LeastRecentlyUsed)
+            .is_empty());
+
+        accesses.track(true);
+        for n in 1..=3 {
+            accesses.filled(&key(n));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+        accesses.touch(&key(1));
+        accesses.touch(&key(1));
+        std::thread::sleep(Duration::from_millis(1));
+        accesses.touch(&key(2));
+
+        assert_eq!(
+            accesses.victims(EvictionPolicy::LeastRecentlyUsed),
+            vec![key(3), key(1), key(2)]
+        );
+        assert_eq!(
+            accesses.victims(EvictionPolicy::LeastFrequentlyUsed),
+            vec![key(3), key(2), key(1)]
+        );
+        assert_eq!(accesses.expired(Duration::from_millis(10)), vec![key(3)]);
+        assert!(accesses.victims(EvictionPolicy::Random).is_empty());
+
+        accesses.forget(&key(3));
+        assert_eq!(
+            accesses.victims(EvictionPolicy::LeastRecentlyUsed),
+            vec![key(1), key(2)]
+        );
+    }
+}
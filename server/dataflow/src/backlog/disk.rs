@@ -0,0 +1,90 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use rocksdb::{self, IteratorMode, WriteBatch};
+use tempfile::{tempdir, TempDir};
+
+use crate::prelude::*;
+use crate::state::{deserialize_rows, serialize_rows};
+
+/// The rows of a reader that keeps them on disk, grouped by the reader's key.
+///
+/// The reader's in-memory state then only caches the keys that are looked up. Every row is here,
+/// so a key that is missing from the cache can always be filled from disk, and a key that has no
+/// rows on disk has no rows at all.
+pub(crate) struct DiskRows {
+    key: Vec<usize>,
+    db: rocksdb::DB,
+    // dropping the directory also removes the database
+    _directory: TempDir,
+}
+
+impl DiskRows {
+    pub(crate) fn new(key: &[usize]) -> Self {
+        let directory = tempdir().unwrap();
+        let db = rocksdb::DB::open_default(directory.path()).unwrap();
+        DiskRows {
+            key: Vec::from(key),
+            db,
+            _directory: directory,
+        }
+    }
+
+    /// Add the positive records in `rs`, and remove the negative ones.
+    pub(crate) fn apply(&mut self, rs: &[Record]) {
+        if rs.is_empty() {
+            return;
+        }
+        tokio::task::block_in_place(|| {
+            let mut changed: HashMap<Vec<DataType>, Vec<Vec<DataType>>> = HashMap::new();
+            for r in rs {
+                let key = self.key.iter().map(|&c| r[c].clone()).collect();
+                let rows = match changed.entry(key) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => {
+                        let rows = self.get(e.key());
+                        e.insert(rows)
+                    }
+                };
+                match *r {
+                    Record::Positive(ref r) => rows.push(r.clone()),
+                    Record::Negative(ref r) => {
+                        if let Some(i) = rows.iter().position(|row| row == r) {
+                            rows.swap_remove(i);
+                        }
+                    }
+                }
+            }
+
+            let mut batch = WriteBatch::default();
+            for (key, rows) in changed {
+                let k = bincode::serialize(&key).unwrap();
+                if rows.is_empty() {
+                    batch.delete(k);
+                } else {
+                    batch.put(k, serialize_rows(&rows));
+                }
+            }
+            self.db.write(batch).unwrap();
+        })
+    }
+
+    /// All rows with the given key.
+    pub(crate) fn get(&self, key: &[DataType]) -> Vec<Vec<DataType>> {
+        let k = bincode::serialize(key).unwrap();
+        tokio::task::block_in_place(|| self.db.get(k).unwrap())
+            .map(|raw| deserialize_rows(&*raw))
+            .unwrap_or_default()
+    }
+
+    /// The rows that satisfy `f`.
+    pub(crate) fn rows_where(&self, mut f: impl FnMut(&[DataType]) -> bool) -> Vec<Vec<DataType>> {
+        tokio::task::block_in_place(|| {
+            self.db
+                .iterator(IteratorMode::Start)
+                .flat_map(|(_, value)| deserialize_rows(&*value))
+                .filter(|row| f(&row[..]))
+                .collect()
+        })
+    }
+}
@@ -1,15 +1,38 @@
+use crate::metrics::NodeMetrics;
+use crate::ops::topk::Order;
+use crate::payload::EventTimes;
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
+use nom_sql::{Operator, OrderType};
+use noria::{Compression, EvictionPolicy, TraceContext, Watermarks};
 use rand::prelude::*;
 use std::borrow::Cow;
+use std::cmp;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) use self::disk::DiskRows;
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
-    new_inner(cols, key, None)
+    new_inner(cols, key, None, None)
 }
 
+/// Allocate a new end-user facing result table that answers range lookups on the `key` column.
+///
+/// A lookup for `k` returns the records whose key compares to `k` as given by `op`, so `Greater`
+/// makes a lookup for `k` return all records with a key greater than `k`.
+pub(crate) fn new_range(cols: usize, key: usize, op: Operator) -> (SingleReadHandle, WriteHandle) {
+    new_inner(cols, &[key], None, Some(op))
+}
+
+/// Requests replays of the given missing keys, as part of the given trace if there is one.
+type Trigger =
+    Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>, Option<TraceContext>) -> bool + Send + Sync>;
+
 /// Allocate a new partially materialized end-user facing result table.
 ///
 /// Misses in this table will call `trigger` to populate the entry, and retry until successful.
@@ -19,15 +42,22 @@ pub(crate) fn new_partial<F>(
     trigger: F,
 ) -> (SingleReadHandle, WriteHandle)
 where
-    F: Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + 'static + Send + Sync,
+    F: Fn(&mut dyn Iterator<Item = &[DataType]>, Option<TraceContext>) -> bool
+        + 'static
+        + Send
+        + Sync,
 {
-    new_inner(cols, key, Some(Arc::new(trigger)))
+    new_inner(cols, key, Some(Arc::new(trigger)), None)
 }
 
+/// The low watermark of a reader that hasn't seen any event times.
+const NO_EVENT_TIME: i64 = std::i64::MIN;
+
 fn new_inner(
     cols: usize,
     key: &[usize],
-    trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    trigger: Option<Trigger>,
+    range: Option<Operator>,
 ) -> (SingleReadHandle, WriteHandle) {
     let contiguous = {
         let mut contiguous = true;
@@ -48,7 +78,7 @@ fn new_inner(
         ($variant:tt) => {{
             use evmap;
             let (r, w) = evmap::Options::default()
-                .with_meta(-1)
+                .with_meta(Watermarks::default())
                 .with_hasher(RandomState::default())
                 .construct();
 
@@ -63,6 +93,19 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    let (range_r, range_w) = match range {
+        Some(op) => {
+            assert!(trigger.is_none(), "range lookups need full materialization");
+            let (r, w) = range::new();
+            (Some((op, r)), Some(w))
+        }
+        None => (None, None),
+    };
+
+    let low_watermark = Arc::new(AtomicI64::new(NO_EVENT_TIME));
+    let write_frontier = Arc::new(AtomicI64::new(NO_EVENT_TIME));
+    let subscriptions = subscriptions::new();
+    let accesses = accesses::new();
     let w = WriteHandle {
         partial: trigger.is_some(),
         handle: w,
@@ -70,18 +113,43 @@ fn new_inner(
         cols,
         contiguous,
         mem_size: 0,
+        compressed: Vec::new(),
+        codec: Compression::default(),
+        watermarks: Watermarks::default(),
+        event_times: EventTimes::default(),
+        low_watermark: Arc::clone(&low_watermark),
+        frontier: EventTimes::default(),
+        write_frontier: Arc::clone(&write_frontier),
+        range: range_w,
+        subscriptions: subscriptions.clone(),
+        accesses: accesses.clone(),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
         key: Vec::from(key),
+        range: range_r,
+        order: None,
+        low_watermark,
+        write_frontier,
+        subscriptions,
+        accesses,
+        metrics: None,
     };
 
     (r, w)
 }
 
+mod accesses;
+mod disk;
 mod multir;
 mod multiw;
+mod range;
+mod subscriptions;
+
+fn key_size(k: &[DataType]) -> u64 {
+    k.iter().map(SizeOf::deep_size_of).sum()
+}
 
 fn key_to_single(k: Key) -> Cow<DataType> {
     assert_eq!(k.len(), 1);
@@ -111,6 +179,18 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    compressed: Vec<usize>,
+    codec: Compression,
+    watermarks: Watermarks,
+    event_times: EventTimes,
+    /// The low watermark of `event_times` as of the last `swap()`, shared with the readers.
+    low_watermark: Arc<AtomicI64>,
+    frontier: EventTimes,
+    /// The earliest time in `frontier` as of the last `swap()`, shared with the readers.
+    write_frontier: Arc<AtomicI64>,
+    range: Option<range::WriteHandle>,
+    subscriptions: subscriptions::Subscriptions,
+    accesses: accesses::Accesses,
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -125,11 +205,14 @@ pub(crate) struct WriteHandleEntry<'a> {
 
 impl<'a> MutWriteHandleEntry<'a> {
     pub(crate) fn mark_filled(self) {
-        if let Some((None, _)) = self
+        if let Some(None) = self
             .handle
             .handle
-            .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
+            .get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            // a filled key takes up space in the map even if no records are ever added for it
+            self.handle.mem_size += key_size(&self.key) as usize;
+            self.handle.accesses.filled(&self.key);
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -140,25 +223,23 @@ impl<'a> MutWriteHandleEntry<'a> {
         let size = self
             .handle
             .handle
-            .meta_get_and(Cow::Borrowed(&*self.key), |rs| {
-                rs.iter().map(SizeOf::deep_size_of).sum()
+            .get_and(Cow::Borrowed(&*self.key), |rs| {
+                key_size(&self.key) + rs.iter().map(SizeOf::deep_size_of).sum::<u64>()
             })
-            .map(|r| r.0.unwrap_or(0))
+            .map(|r| r.unwrap_or(0))
             .unwrap_or(0);
         self.handle.mem_size = self.handle.mem_size.checked_sub(size as usize).unwrap();
+        self.handle.accesses.forget(&self.key);
         self.handle.handle.empty(self.key)
     }
 }
 
 impl<'a> WriteHandleEntry<'a> {
-    pub(crate) fn try_find_and<F, T>(self, mut then: F) -> Result<(Option<T>, i64), ()>
+    pub(crate) fn try_find_and<F, T>(self, mut then: F) -> Result<Option<T>, ()>
     where
         F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
     {
-        self.handle
-            .handle
-            .meta_get_and(self.key, &mut then)
-            .ok_or(())
+        self.handle.handle.get_and(self.key, &mut then).ok_or(())
     }
 }
 
@@ -229,7 +310,58 @@ impl WriteHandle {
     }
 
     pub(crate) fn swap(&mut self) {
-        self.handle.refresh();
+        match self.range {
+            Some(ref mut range) => {
+                let handle = &mut self.handle;
+                range.publish(|| handle.refresh());
+            }
+            None => self.handle.refresh(),
+        }
+        if let Some(t) = self.event_times.low_watermark() {
+            self.low_watermark.store(t, Ordering::Release);
+        }
+        if let Some(t) = self.frontier.low_watermark() {
+            self.write_frontier.store(t, Ordering::Release);
+        }
+    }
+
+    /// Record that the state reflects the writes in `watermarks`.
+    ///
+    /// Like other changes, this is made visible to readers after the next call to `swap()`.
+    pub(crate) fn advance(&mut self, watermarks: &Watermarks) {
+        if self.watermarks.merge(watermarks) {
+            self.handle.set_meta(self.watermarks.clone());
+        }
+    }
+
+    /// Record that the state reflects the records of its base tables up to `event_times`.
+    ///
+    /// Like other changes, this is made visible to readers after the next call to `swap()`.
+    pub(crate) fn advance_event_times(&mut self, event_times: &EventTimes) {
+        self.event_times.merge(event_times);
+    }
+
+    /// Record that the state reflects every write its base tables were sent before the times in
+    /// `frontier`.
+    ///
+    /// Like other changes, this is made visible to readers after the next call to `swap()`.
+    pub(crate) fn advance_frontier(&mut self, frontier: &EventTimes) {
+        self.frontier.merge(frontier);
+    }
+
+    /// Store wide text values in the given columns compressed with `codec`.
+    ///
+    /// They are only decompressed when they are serialized for a reader.
+    pub(crate) fn compress_columns(&mut self, columns: Vec<usize>, codec: Compression) {
+        self.compressed = columns;
+        self.codec = codec;
+    }
+
+    /// Queue the changes in `rs` for the clients that subscribed to their keys.
+    ///
+    /// Unlike the records given to `add()`, subscribers are given these right away.
+    pub(crate) fn notify(&self, rs: &[Record]) {
+        self.subscriptions.notify(&self.key[..], rs);
     }
 
     /// Add a new set of records to the backlog.
@@ -239,7 +371,26 @@ impl WriteHandle {
     where
         I: IntoIterator<Item = Record>,
     {
-        let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
+        let key = self.key[0];
+        let range = &mut self.range;
+        let index = move |r: &Record| {
+            if let Some(ref mut range) = *range {
+                range.add(&r[key], r.is_positive());
+            }
+        };
+        let mem_delta = if self.compressed.is_empty() {
+            self.handle
+                .add(&self.key[..], self.cols, rs.into_iter().inspect(index))
+        } else {
+            let (compressed, codec) = (&self.compressed, self.codec);
+            let rs = rs.into_iter().map(|mut r| {
+                for &c in compressed {
+                    r[c] = r[c].compress_with(codec);
+                }
+                r
+            });
+            self.handle.add(&self.key[..], self.cols, rs.inspect(index))
+        };
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
         } else if mem_delta < 0 {
@@ -263,9 +414,14 @@ impl WriteHandle {
                 unreachable!("mem size is {}, but map is empty", self.mem_size);
             }
 
-            self.handle.empty_random_for_each(rng, n, |vs| {
+            let partial = self.partial;
+            self.handle.empty_random_for_each(rng, n, |key_size, vs| {
                 let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
                 bytes_to_be_freed += size;
+                if partial {
+                    // only keys filled through replays are accounted for
+                    bytes_to_be_freed += key_size;
+                }
                 n -= 1;
             });
         }
@@ -276,6 +432,59 @@ impl WriteHandle {
             .unwrap();
         bytes_to_be_freed
     }
+
+    /// Record lookups from now on if `policy` picks keys to evict by them.
+    pub(crate) fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.accesses.track(policy != EvictionPolicy::Random);
+    }
+
+    /// Evict keys in the order that `policy` picks them until at least `bytes` bytes will be
+    /// freed, and return how many will be.
+    ///
+    /// Keys that were filled before lookups were recorded are evicted at random once the recorded
+    /// keys run out.
+    pub(crate) fn evict_by(&mut self, policy: EvictionPolicy, bytes: u64) -> u64 {
+        let mut freed = 0;
+        for key in self.accesses.victims(policy) {
+            if freed >= bytes {
+                return freed;
+            }
+            freed += self.evict_key(&key);
+        }
+        while freed < bytes {
+            match self.evict_random_keys(&mut rand::thread_rng(), 16) {
+                0 => break,
+                n => freed += n,
+            }
+        }
+        freed
+    }
+
+    /// Evict the keys that have not been looked up for `ttl`, and return the number of bytes that
+    /// will be freed.
+    pub(crate) fn evict_expired(&mut self, ttl: Duration) -> u64 {
+        self.accesses
+            .expired(ttl)
+            .iter()
+            .map(|key| self.evict_key(key))
+            .sum()
+    }
+
+    /// The rows in the state, as of the last call to `swap()`, that satisfy `f`.
+    pub(crate) fn rows_where(&self, f: impl FnMut(&[DataType]) -> bool) -> Vec<Vec<DataType>> {
+        self.handle.rows_where(f)
+    }
+
+    /// The number of keys in the state, and of rows under them, as of the last call to `swap()`.
+    pub(crate) fn counts(&self) -> (usize, usize) {
+        self.handle.counts()
+    }
+
+    fn evict_key(&mut self, key: &[DataType]) -> u64 {
+        let before = self.mem_size;
+        self.mut_with_key(key).mark_hole();
+        (before - self.mem_size) as u64
+    }
 }
 
 impl SizeOf for WriteHandle {
@@ -298,8 +507,15 @@ impl SizeOf for WriteHandle {
 #[derive(Clone)]
 pub struct SingleReadHandle {
     handle: multir::Handle,
-    trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    trigger: Option<Trigger>,
     key: Vec<usize>,
+    range: Option<(Operator, range::ReadHandle)>,
+    order: Option<(Order, usize, usize)>,
+    low_watermark: Arc<AtomicI64>,
+    write_frontier: Arc<AtomicI64>,
+    subscriptions: subscriptions::Subscriptions,
+    accesses: accesses::Accesses,
+    metrics: Option<Arc<NodeMetrics>>,
 }
 
 impl std::fmt::Debug for SingleReadHandle {
@@ -308,13 +524,50 @@ impl std::fmt::Debug for SingleReadHandle {
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
             .field("key", &self.key)
+            .field("range", &self.range.as_ref().map(|&(ref op, _)| op))
+            .field("limit", &self.order.as_ref().map(|&(_, _, limit)| limit))
             .finish()
     }
 }
 
 impl SingleReadHandle {
+    /// The earliest of the latest event times of the base tables this reader's records derive
+    /// from, in milliseconds since the Unix epoch, if any of them have event-time columns.
+    pub fn low_watermark(&self) -> Option<i64> {
+        match self.low_watermark.load(Ordering::Acquire) {
+            NO_EVENT_TIME => None,
+            t => Some(t),
+        }
+    }
+
+    /// The time, in milliseconds since the Unix epoch, before which the base tables this reader's
+    /// records derive from had processed every write they were sent, as far as the reader has
+    /// heard from them. `None` if write frontiers are not tracked.
+    pub fn write_frontier(&self) -> Option<i64> {
+        match self.write_frontier.load(Ordering::Acquire) {
+            NO_EVENT_TIME => None,
+            t => Some(t),
+        }
+    }
+
+    /// Start queueing the changes to the records of the given keys for a client, and return the
+    /// id that the client collects them with through `changes()`.
+    pub fn subscribe(&self, keys: Vec<Vec<DataType>>) -> u64 {
+        self.subscriptions.subscribe(keys)
+    }
+
+    /// Take the changes queued for subscription `id`, each along with whether it was added.
+    ///
+    /// Returns `None` if the subscription was dropped, which happens if its client does not
+    /// collect its changes for too long, or lets too many of them pile up.
+    pub fn changes(&self, id: u64) -> Option<Vec<(Vec<DataType>, bool)>> {
+        self.subscriptions.changes(id)
+    }
+
     /// Trigger a replay of a missing key from a partially materialized view.
-    pub fn trigger<'a, I>(&self, keys: I) -> bool
+    ///
+    /// If `trace` is given, the replay is traced as part of it.
+    pub fn trigger<'a, I>(&self, keys: I, trace: Option<TraceContext>) -> bool
     where
         I: Iterator<Item = &'a [DataType]>,
     {
@@ -326,7 +579,7 @@ impl SingleReadHandle {
         let mut it = keys;
 
         // trigger a replay to populate
-        (*self.trigger.as_ref().unwrap())(&mut it)
+        (*self.trigger.as_ref().unwrap())(&mut it, trace)
     }
 
     /// Find all entries that matched the given conditions.
@@ -337,7 +590,11 @@ impl SingleReadHandle {
     /// swapped in by the writer.
     ///
     /// Holes in partially materialized state are returned as `Ok((None, _))`.
-    pub fn try_find_and<F, T>(&self, key: &[DataType], mut then: F) -> Result<(Option<T>, i64), ()>
+    pub fn try_find_and<F, T>(
+        &self,
+        key: &[DataType],
+        mut then: F,
+    ) -> Result<(Option<T>, Watermarks), ()>
     where
         F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
     {
@@ -347,11 +604,85 @@ impl SingleReadHandle {
             .map(|(mut records, meta)| {
                 if records.is_none() && self.trigger.is_none() {
                     records = Some(then(&evmap::Values::default()));
+                } else if records.is_some() && self.trigger.is_some() {
+                    self.accesses.touch(key);
                 }
                 (records, meta)
             })
     }
 
+    /// Whether lookups on this reader are range lookups; see `try_find_range_and`.
+    pub fn is_range(&self) -> bool {
+        self.range.is_some()
+    }
+
+    /// Find all entries whose key compares to `key` as given by the reader's range operator.
+    ///
+    /// The records for each matching key are passed to `then`, in key order. Only readers made
+    /// with `new_range` support this.
+    pub fn try_find_range_and<F, T>(
+        &self,
+        key: &[DataType],
+        then: F,
+    ) -> Result<(Vec<T>, Watermarks), ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
+    {
+        let (ref op, ref index) = *self.range.as_ref().expect("not a range reader");
+        assert_eq!(key.len(), 1);
+        let k = &key[0];
+        let bounds = match *op {
+            Operator::Greater => (Bound::Excluded(k), Bound::Unbounded),
+            Operator::GreaterOrEqual => (Bound::Included(k), Bound::Unbounded),
+            Operator::Less => (Bound::Unbounded, Bound::Excluded(k)),
+            Operator::LessOrEqual => (Bound::Unbounded, Bound::Included(k)),
+            _ => unreachable!("{:?} is not a range operator", op),
+        };
+
+        let keys = index.read();
+        self.handle
+            .meta_get_many_and(keys.range::<DataType, _>(bounds), then)
+            .ok_or(())
+    }
+
+    /// Have lookups return the records for each key sorted by `order`, skipping the first
+    /// `offset` of them, and no more than `limit` of the rest.
+    pub(crate) fn set_order(
+        &mut self,
+        order: Vec<(usize, OrderType)>,
+        offset: usize,
+        limit: usize,
+    ) {
+        self.order = Some((order.into(), offset, limit));
+    }
+
+    /// Count the lookups recorded with `looked_up` in `metrics`.
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<NodeMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Record lookups of `hits` keys that were present and `misses` keys that were not.
+    pub fn looked_up(&self, hits: usize, misses: usize) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.looked_up(hits, misses);
+        }
+    }
+
+    /// Whether the records found for a key must be passed through `order`.
+    pub fn is_ordered(&self) -> bool {
+        self.order.is_some()
+    }
+
+    /// Sort the records found for a key as this reader's order says, and drop the ones before
+    /// its offset and beyond its limit.
+    pub fn order(&self, rs: &mut Vec<Vec<DataType>>) {
+        if let Some((ref order, offset, limit)) = self.order {
+            rs.sort_by(|a, b| order.cmp(a, b));
+            rs.drain(..cmp::min(offset, rs.len()));
+            rs.truncate(limit);
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -377,12 +708,18 @@ mod tests {
         w.swap();
 
         // after first swap, it is empty, but ready
-        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()), Ok((Some(0), -1)));
+        assert_eq!(
+            r.try_find_and(&a[0..1], |rs| rs.len()),
+            Ok((Some(0), Watermarks::default()))
+        );
 
         w.add(vec![Record::Positive(a.clone())]);
 
         // it is empty even after an add (we haven't swapped yet)
-        assert_eq!(r.try_find_and(&a[0..1], |rs| rs.len()), Ok((Some(0), -1)));
+        assert_eq!(
+            r.try_find_and(&a[0..1], |rs| rs.len()),
+            Ok((Some(0), Watermarks::default()))
+        );
 
         w.swap();
 
@@ -397,6 +734,47 @@ mod tests {
             .unwrap());
     }
 
+    #[test]
+    fn range_query() {
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+        let c = vec![3.into(), "c".into()];
+        let d = vec![1.into(), "d".into()];
+
+        let (r, mut w) = new_range(2, 0, Operator::Greater);
+        let find = |k: i32| {
+            r.try_find_range_and(&[k.into()], |rs| rs.len())
+                .map(|rs| rs.0)
+        };
+
+        // initially, store is uninitialized
+        assert_eq!(find(0), Err(()));
+
+        w.add(vec![
+            Record::Positive(a.clone()),
+            Record::Positive(b.clone()),
+            Record::Positive(c.clone()),
+        ]);
+        w.swap();
+
+        // one entry for each key greater than the one looked up
+        assert_eq!(find(0), Ok(vec![1, 1, 1]));
+        assert_eq!(find(1), Ok(vec![1, 1]));
+        assert_eq!(find(3), Ok(vec![]));
+
+        w.add(vec![
+            Record::Negative(b.clone()),
+            Record::Positive(d.clone()),
+        ]);
+
+        // nothing changes until the swap
+        assert_eq!(find(0), Ok(vec![1, 1, 1]));
+
+        w.swap();
+        assert_eq!(find(0), Ok(vec![2, 1]));
+        assert_eq!(find(1), Ok(vec![1]));
+    }
+
     #[test]
     fn busybusybusy() {
         use std::thread;
@@ -564,4 +942,83 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn partial_key_sizes() {
+        let a: Vec<DataType> = vec!["key".into(), "a".into()];
+        let k = vec![a[0].clone()];
+
+        let (_r, mut w) = new_partial(2, &[0], |_: &mut dyn Iterator<Item = &[DataType]>, _| true);
+        w.swap();
+
+        // an empty filled key is still accounted for
+        w.mut_with_key(&k[..]).mark_filled();
+        w.swap();
+        let key_size = w.deep_size_of();
+        assert!(key_size > 0);
+
+        w.add(vec![Record::Positive(a.clone())]);
+        w.swap();
+        assert_eq!(w.deep_size_of(), key_size + a.deep_size_of());
+
+        // and freed along with its records
+        w.mut_with_key(&k[..]).mark_hole();
+        w.swap();
+        assert_eq!(w.deep_size_of(), 0);
+
+        w.mut_with_key(&k[..]).mark_filled();
+        w.swap();
+        assert_eq!(w.evict_random_keys(&mut rand::thread_rng(), 1), key_size);
+        assert_eq!(w.deep_size_of(), 0);
+    }
+
+    #[test]
+    fn least_recently_used_keys_are_evicted_first() {
+        let (r, mut w) = new_partial(1, &[0], |_: &mut dyn Iterator<Item = &[DataType]>, _| true);
+        w.set_eviction_policy(EvictionPolicy::LeastRecentlyUsed);
+        w.swap();
+        for n in 0..3 {
+            let k = vec![DataType::from(n)];
+            w.mut_with_key(&k[..]).mark_filled();
+            w.add(vec![Record::Positive(k)]);
+        }
+        w.swap();
+        let size = w.deep_size_of();
+
+        std::thread::sleep(Duration::from_millis(1));
+        let found = |k: i32| r.try_find_and(&[k.into()], |_| ()).unwrap().0.is_some();
+        assert!(found(0));
+        assert!(found(2));
+
+        let freed = w.evict_by(EvictionPolicy::LeastRecentlyUsed, 1);
+        w.swap();
+        assert_eq!(freed, size / 3);
+        assert!(!found(1));
+        assert!(found(0));
+        assert!(found(2));
+
+        // keys that were not looked up for long enough go without any memory pressure
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(found(2));
+        w.evict_expired(Duration::from_millis(10));
+        w.swap();
+        assert!(!found(0));
+        assert!(found(2));
+    }
+
+    #[test]
+    fn subscribers_see_changes_to_their_keys() {
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+
+        let (r, w) = new(2, &[0]);
+        let id = r.subscribe(vec![vec![1.into()]]);
+        assert_eq!(r.changes(id), Some(vec![]));
+
+        w.notify(&[Record::Positive(a.clone()), Record::Positive(b)]);
+        w.notify(&[Record::Negative(a.clone())]);
+        assert_eq!(r.changes(id), Some(vec![(a.clone(), true), (a, false)]));
+        assert_eq!(r.changes(id), Some(vec![]));
+        assert_eq!(r.changes(id + 1), None);
+    }
 }
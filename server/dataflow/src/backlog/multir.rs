@@ -1,12 +1,13 @@
 use ahash::RandomState;
 use common::DataType;
 use evmap;
+use noria::Watermarks;
 
 #[derive(Clone, Debug)]
 pub(super) enum Handle {
-    Single(evmap::ReadHandle<DataType, Vec<DataType>, i64, RandomState>),
-    Double(evmap::ReadHandle<(DataType, DataType), Vec<DataType>, i64, RandomState>),
-    Many(evmap::ReadHandle<Vec<DataType>, Vec<DataType>, i64, RandomState>),
+    Single(evmap::ReadHandle<DataType, Vec<DataType>, Watermarks, RandomState>),
+    Double(evmap::ReadHandle<(DataType, DataType), Vec<DataType>, Watermarks, RandomState>),
+    Many(evmap::ReadHandle<Vec<DataType>, Vec<DataType>, Watermarks, RandomState>),
 }
 
 impl Handle {
@@ -18,7 +19,11 @@ impl Handle {
         }
     }
 
-    pub(super) fn meta_get_and<F, T>(&self, key: &[DataType], then: F) -> Option<(Option<T>, i64)>
+    pub(super) fn meta_get_and<F, T>(
+        &self,
+        key: &[DataType],
+        then: F,
+    ) -> Option<(Option<T>, Watermarks)>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, RandomState>) -> T,
     {
@@ -27,7 +32,7 @@ impl Handle {
                 assert_eq!(key.len(), 1);
                 let map = h.read()?;
                 let v = map.get(&key[0]).map(then);
-                let m = *map.meta();
+                let m = map.meta().clone();
                 Some((v, m))
             }
             Handle::Double(ref h) => {
@@ -56,16 +61,41 @@ impl Handle {
                     let stack_key = mem::transmute::<_, &(DataType, DataType)>(&stack_key);
                     let map = h.read()?;
                     let v = map.get(&stack_key).map(then);
-                    let m = *map.meta();
+                    let m = map.meta().clone();
                     Some((v, m))
                 }
             }
             Handle::Many(ref h) => {
                 let map = h.read()?;
                 let v = map.get(key).map(then);
-                let m = *map.meta();
+                let m = map.meta().clone();
                 Some((v, m))
             }
         }
     }
+
+    /// Like `meta_get_and`, but for each of the given single-column `keys` in turn, all under a
+    /// single read of the map. Keys that are not in the map are skipped.
+    pub(super) fn meta_get_many_and<'k, I, F, T>(
+        &self,
+        keys: I,
+        mut then: F,
+    ) -> Option<(Vec<T>, Watermarks)>
+    where
+        I: IntoIterator<Item = &'k DataType>,
+        F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
+    {
+        match *self {
+            Handle::Single(ref h) => {
+                let map = h.read()?;
+                let vs = keys
+                    .into_iter()
+                    .filter_map(|k| map.get(k).map(&mut then))
+                    .collect();
+                let m = map.meta().clone();
+                Some((vs, m))
+            }
+            _ => unreachable!("only single-column keys can be looked up in bulk"),
+        }
+    }
 }
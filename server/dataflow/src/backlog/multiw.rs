@@ -1,12 +1,14 @@
-use super::{key_to_double, key_to_single, Key};
+use super::{key_size, key_to_double, key_to_single, Key};
 use crate::prelude::*;
 use ahash::RandomState;
+use common::SizeOf;
 use evmap;
+use noria::Watermarks;
 
 pub(super) enum Handle {
-    Single(evmap::WriteHandle<DataType, Vec<DataType>, i64, RandomState>),
-    Double(evmap::WriteHandle<(DataType, DataType), Vec<DataType>, i64, RandomState>),
-    Many(evmap::WriteHandle<Vec<DataType>, Vec<DataType>, i64, RandomState>),
+    Single(evmap::WriteHandle<DataType, Vec<DataType>, Watermarks, RandomState>),
+    Double(evmap::WriteHandle<(DataType, DataType), Vec<DataType>, Watermarks, RandomState>),
+    Many(evmap::WriteHandle<Vec<DataType>, Vec<DataType>, Watermarks, RandomState>),
 }
 
 impl Handle {
@@ -46,18 +48,24 @@ impl Handle {
         }
     }
 
-    /// Evict `count` randomly selected keys from state and return them along with the number of
-    /// bytes freed.
+    /// Evict `count` randomly selected keys from state, and call `f` with the size of each
+    /// evicted key along with its records.
     pub fn empty_random_for_each(
         &mut self,
         rng: &mut impl rand::Rng,
         n: usize,
-        mut f: impl FnMut(&evmap::Values<Vec<DataType>, RandomState>),
+        mut f: impl FnMut(u64, &evmap::Values<Vec<DataType>, RandomState>),
     ) {
         match *self {
-            Handle::Single(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Double(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
+            Handle::Single(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(key_size(std::slice::from_ref(r.0)), r.1)),
+            Handle::Double(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f((r.0).0.deep_size_of() + (r.0).1.deep_size_of(), r.1)),
+            Handle::Many(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|r| f(key_size(&r.0[..]), r.1)),
         }
     }
 
@@ -75,7 +83,74 @@ impl Handle {
         }
     }
 
-    pub fn meta_get_and<F, T>(&self, key: Key, then: F) -> Option<(Option<T>, i64)>
+    pub fn set_meta(&mut self, meta: Watermarks) {
+        match *self {
+            Handle::Single(ref mut h) => {
+                h.set_meta(meta);
+            }
+            Handle::Double(ref mut h) => {
+                h.set_meta(meta);
+            }
+            Handle::Many(ref mut h) => {
+                h.set_meta(meta);
+            }
+        }
+    }
+
+    /// The rows in the map, as of the last refresh, that satisfy `f`.
+    pub fn rows_where(&self, mut f: impl FnMut(&[DataType]) -> bool) -> Vec<Vec<DataType>> {
+        let mut rows = Vec::new();
+        let mut collect = |vs: &evmap::Values<Vec<DataType>, RandomState>| {
+            rows.extend(vs.iter().filter(|r| f(&r[..])).cloned());
+        };
+        match *self {
+            Handle::Single(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| collect(vs));
+                }
+            }
+            Handle::Double(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| collect(vs));
+                }
+            }
+            Handle::Many(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| collect(vs));
+                }
+            }
+        }
+        rows
+    }
+
+    /// The number of keys in the map, and of rows under them, as of the last refresh.
+    pub fn counts(&self) -> (usize, usize) {
+        let (mut keys, mut rows) = (0, 0);
+        let mut count = |vs: &evmap::Values<Vec<DataType>, RandomState>| {
+            keys += 1;
+            rows += vs.len();
+        };
+        match *self {
+            Handle::Single(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| count(vs));
+                }
+            }
+            Handle::Double(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| count(vs));
+                }
+            }
+            Handle::Many(ref h) => {
+                if let Some(map) = h.read() {
+                    map.iter().for_each(|(_, vs)| count(vs));
+                }
+            }
+        }
+        (keys, rows)
+    }
+
+    pub fn get_and<F, T>(&self, key: Key, then: F) -> Option<Option<T>>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, RandomState>) -> T,
     {
@@ -84,8 +159,7 @@ impl Handle {
                 assert_eq!(key.len(), 1);
                 let map = h.read()?;
                 let v = map.get(&key[0]).map(then);
-                let m = *map.meta();
-                Some((v, m))
+                Some(v)
             }
             Handle::Double(ref h) => {
                 assert_eq!(key.len(), 2);
@@ -113,15 +187,13 @@ impl Handle {
                     let stack_key = mem::transmute::<_, &(DataType, DataType)>(&stack_key);
                     let map = h.read()?;
                     let v = map.get(&stack_key).map(then);
-                    let m = *map.meta();
-                    Some((v, m))
+                    Some(v)
                 }
             }
             Handle::Many(ref h) => {
                 let map = h.read()?;
                 let v = map.get(&key[..]).map(then);
-                let m = *map.meta();
-                Some((v, m))
+                Some(v)
             }
         }
     }
@@ -0,0 +1,79 @@
+use ahash::RandomState;
+use common::DataType;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// Allocate a new ordered index over the keys of a reader.
+///
+/// `evmap` can only look up exact keys, so a reader that answers range lookups also keeps the
+/// set of keys it holds in a `BTreeSet`, and looks up every key of that set that falls within the
+/// range.
+pub(super) fn new() -> (ReadHandle, WriteHandle) {
+    let keys = Arc::new(RwLock::new(BTreeSet::new()));
+    let r = ReadHandle(Arc::clone(&keys));
+    let w = WriteHandle {
+        keys,
+        counts: HashMap::default(),
+        pending: Vec::new(),
+    };
+    (r, w)
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct ReadHandle(Arc<RwLock<BTreeSet<DataType>>>);
+
+impl ReadHandle {
+    /// Get the keys as of the last publish.
+    ///
+    /// The writer cannot publish while the returned guard is held, so the keys agree with what a
+    /// read of the map made under the guard sees.
+    pub(super) fn read(&self) -> RwLockReadGuard<'_, BTreeSet<DataType>> {
+        self.0.read().unwrap()
+    }
+}
+
+pub(super) struct WriteHandle {
+    keys: Arc<RwLock<BTreeSet<DataType>>>,
+    // number of records with each key, including ones that have not been published yet
+    counts: HashMap<DataType, usize, RandomState>,
+    // keys to add to (true) or remove from (false) the published set on the next publish
+    pending: Vec<(DataType, bool)>,
+}
+
+impl WriteHandle {
+    /// Note that a record with the given key was added to or removed from the map.
+    pub(super) fn add(&mut self, key: &DataType, positive: bool) {
+        if positive {
+            let n = self.counts.entry(key.clone()).or_insert(0);
+            *n += 1;
+            if *n == 1 {
+                self.pending.push((key.clone(), true));
+            }
+        } else if let Some(n) = self.counts.get_mut(key) {
+            *n -= 1;
+            if *n == 0 {
+                self.counts.remove(key);
+                self.pending.push((key.clone(), false));
+            }
+        }
+    }
+
+    /// Make the keys added since the last publish visible, along with the map itself.
+    ///
+    /// `refresh` must publish the writes to the map, and is called while no reader holds the keys,
+    /// so that no reader sees keys that disagree with the map.
+    pub(super) fn publish<F>(&mut self, refresh: F)
+    where
+        F: FnOnce(),
+    {
+        let mut keys = self.keys.write().unwrap();
+        refresh();
+        for (key, add) in self.pending.drain(..) {
+            if add {
+                keys.insert(key);
+            } else {
+                keys.remove(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a subscription is kept without its client asking for its changes.
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many changes are queued for a subscription before it is dropped for falling behind.
+const MAX_QUEUED_CHANGES: usize = 100_000;
+
+/// Allocate the subscriptions of a reader, shared between its writer and its readers.
+pub(super) fn new() -> Subscriptions {
+    Subscriptions(Arc::new(Mutex::new(Inner {
+        next: 0,
+        subscriptions: HashMap::new(),
+    })))
+}
+
+#[derive(Clone)]
+pub(super) struct Subscriptions(Arc<Mutex<Inner>>);
+
+struct Inner {
+    next: u64,
+    subscriptions: HashMap<u64, Subscription>,
+}
+
+/// The keys that a client watches, and the changes to them it has yet to be given.
+struct Subscription {
+    keys: HashSet<Vec<DataType>>,
+    changes: Vec<(Vec<DataType>, bool)>,
+    polled: Instant,
+}
+
+impl Subscriptions {
+    /// Start queueing the changes to the given keys, and return the id to collect them with.
+    pub(super) fn subscribe(&self, keys: Vec<Vec<DataType>>) -> u64 {
+        let mut inner = self.0.lock().unwrap();
+        let id = inner.next;
+        inner.next += 1;
+        inner.subscriptions.insert(
+            id,
+            Subscription {
+                keys: keys.into_iter().collect(),
+                changes: Vec::new(),
+                polled: Instant::now(),
+            },
+        );
+        id
+    }
+
+    /// Take the changes queued for subscription `id`.
+    ///
+    /// Returns `None` if there is no such subscription, either because it timed out or because
+    /// it fell too far behind.
+    pub(super) fn changes(&self, id: u64) -> Option<Vec<(Vec<DataType>, bool)>> {
+        let mut inner = self.0.lock().unwrap();
+        let s = inner.subscriptions.get_mut(&id)?;
+        s.polled = Instant::now();
+        Some(std::mem::replace(&mut s.changes, Vec::new()))
+    }
+
+    /// Queue the changes in `rs` for the subscriptions that watch their keys.
+    pub(super) fn notify(&self, key: &[usize], rs: &[Record]) {
+        let mut inner = self.0.lock().unwrap();
+        if inner.subscriptions.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        inner.subscriptions.retain(|_, s| {
+            s.changes.len() <= MAX_QUEUED_CHANGES
+                && now.duration_since(s.polled) < SUBSCRIPTION_TIMEOUT
+        });
+        for r in rs.iter() {
+            let k: Vec<_> = key.iter().map(|&c| r[c].clone()).collect();
+            for s in inner.subscriptions.values_mut() {
+                if s.keys.contains(&k) {
+                    s.changes.push((r.rec().to_vec(), r.is_positive()));
+                }
+            }
+        }
+    }
+}
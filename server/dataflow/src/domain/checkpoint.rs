@@ -0,0 +1,145 @@
+//! Snapshots of the fully materialized state of nodes as of a checkpoint.
+//!
+//! Recovery restores a node's state from the latest checkpoint rather than rebuilding it by
+//! replaying everything above it, and then only replays the writes that base tables have logged
+//! since.
+
+use crate::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The rows of one shard of a node as of a checkpoint.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// What the node computes and how it is sharded, so that a snapshot is not restored into a
+    /// different node that got the same index after the recipe changed, or into a shard that
+    /// holds different rows after its table was re-sharded.
+    node: String,
+    rows: Vec<Vec<DataType>>,
+}
+
+fn identity(node: &Node) -> String {
+    format!(
+        "{} {} {:?} {:?}",
+        node.name(),
+        node.description(true),
+        node.fields(),
+        node.sharded_by()
+    )
+}
+
+fn path(dir: &Path, checkpoint: u64, node: &Node, shard: usize) -> PathBuf {
+    dir.join(checkpoint.to_string()).join(format!(
+        "{}.{}.snapshot",
+        node.global_addr().index(),
+        shard
+    ))
+}
+
+/// Write the rows of `node` to its snapshot for `checkpoint`.
+pub(super) fn write(
+    dir: &Path,
+    checkpoint: u64,
+    node: &Node,
+    shard: usize,
+    rows: Vec<Vec<DataType>>,
+) -> io::Result<()> {
+    let path = path(dir, checkpoint, node, shard);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let snapshot = Snapshot {
+        node: identity(node),
+        rows,
+    };
+    let data =
+        bincode::serialize(&snapshot).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    // a checkpoint is only complete once every snapshot has been written, so a partial file is
+    // never read back. it still shouldn't be mistaken for a whole one.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+/// The rows of `node` as of `checkpoint`, if it has a snapshot for that checkpoint.
+pub(super) fn read(
+    dir: &Path,
+    checkpoint: u64,
+    node: &Node,
+    shard: usize,
+) -> io::Result<Option<Vec<Vec<DataType>>>> {
+    let data = match fs::read(path(dir, checkpoint, node, shard)) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let snapshot: Snapshot =
+        bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if snapshot.node != identity(node) {
+        return Ok(None);
+    }
+    Ok(Some(snapshot.rows))
+}
+
+/// Remove the snapshots of the checkpoints before `checkpoint`.
+///
+/// The shards of every domain on a worker share the directory, so any of them may get there first.
+pub(super) fn remove_older(dir: &Path, checkpoint: u64) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let older = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u64>().ok())
+            .map_or(false, |c| c < checkpoint);
+        if older {
+            match fs::remove_dir_all(entry.path()) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                r => r?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::special::Base;
+    use petgraph::graph::NodeIndex;
+
+    fn node(name: &str, index: usize) -> Node {
+        let mut n = Node::new(name, &["x", "y"], Base::default());
+        n.set_finalized_addr(NodeIndex::new(index).into());
+        n
+    }
+
+    #[test]
+    fn it_only_restores_snapshots_of_the_same_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let rows = vec![vec![1.into(), "a".into()]];
+        write(dir.path(), 1, &node("n", 3), 0, rows.clone()).unwrap();
+        assert_eq!(read(dir.path(), 1, &node("n", 3), 0).unwrap(), Some(rows));
+        assert_eq!(read(dir.path(), 1, &node("n", 3), 1).unwrap(), None);
+        assert_eq!(read(dir.path(), 2, &node("n", 3), 0).unwrap(), None);
+        // a different node that got the same index
+        assert_eq!(read(dir.path(), 1, &node("m", 3), 0).unwrap(), None);
+        // the same node, sharded differently
+        let mut resharded = node("n", 3);
+        resharded.shard_by(Sharding::ByColumn(0, 2, ShardScheme::Hash));
+        assert_eq!(read(dir.path(), 1, &resharded, 0).unwrap(), None);
+
+        write(dir.path(), 2, &node("n", 3), 0, Vec::new()).unwrap();
+        remove_older(dir.path(), 2).unwrap();
+        assert_eq!(read(dir.path(), 1, &node("n", 3), 0).unwrap(), None);
+        assert_eq!(
+            read(dir.path(), 2, &node("n", 3), 0).unwrap(),
+            Some(Vec::new())
+        );
+    }
+}
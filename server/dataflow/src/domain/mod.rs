@@ -10,19 +10,47 @@ use std::sync::Arc;
 use std::time;
 
 use crate::group_commit::GroupCommitQueueSet;
-use crate::payload::{ControlReplyPacket, ReplayPieceContext, SourceSelection};
+use crate::metrics::{DomainMetrics, Metrics, NodeMetrics};
+use crate::payload::{
+    Barrier, ControlReplyPacket, EventTimes, ReplayConfig, ReplayPacing, ReplayPieceContext,
+    SourceSelection,
+};
 use crate::prelude::*;
 use ahash::RandomState;
 use futures_util::{future::FutureExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
+use noria::consensus::Epoch;
+use noria::debug::replays::SlowReplay;
 pub use noria::internal::DomainIndex as Index;
+use noria::internal::LocalOrNot;
+use noria::{
+    Compression, DeadLetter, TraceContext, TransactionPhase, ViewPriority, ViewStorage, Watermarks,
+    WriteAck,
+};
 use slog::Logger;
 use stream_cancel::Valve;
 
+use crate::quota::RateLimiter;
 use crate::Readers;
 use timekeeper::{RealTime, SimpleTracker, ThreadTime, Timer, TimerSet};
 use tokio;
 
+mod checkpoint;
+mod reshard;
+
+/// How often the chunker of a full replay reports its progress to the controller.
+const PROGRESS_EVERY: time::Duration = time::Duration::from_millis(500);
+
+/// How many upqueries may go ahead of deferred work before one deferred packet is handled anyway.
+const OVERTAKE_LIMIT: usize = 16;
+
+/// How many deferred packets are handled each time a domain runs out of more urgent work.
+const BULK_BATCH: usize = 8;
+
+/// The most records that consecutive updates from other domains are merged into before they are
+/// processed.
+const MAX_COALESCED_RECORDS: usize = 4096;
+
 #[derive(Debug)]
 pub enum PollEvent {
     ResumePolling,
@@ -41,9 +69,72 @@ pub enum ProcessResult {
 pub struct Config {
     pub concurrent_replays: usize,
     pub replay_batch_timeout: time::Duration,
+    /// Fully materialized join inputs that grow beyond this many bytes spill their coldest
+    /// partitions to disk.
+    #[serde(default)]
+    pub join_spill_threshold: Option<usize>,
+    /// Number the writes at each base table and track which of them every reader reflects.
+    #[serde(default)]
+    pub snapshot_reads: bool,
+    /// Advance the write frontier of every base table this often, so that readers know up to
+    /// what time they reflect every write.
+    #[serde(default)]
+    pub frontier_interval: Option<time::Duration>,
+    /// Stop accepting input once this many packets are waiting to be sent to other domains, until
+    /// they have caught up.
+    #[serde(default)]
+    pub max_queued_packets: Option<usize>,
+    /// Record the partial replays that take longer than this to fill a key.
+    #[serde(default)]
+    pub slow_replay_threshold: Option<time::Duration>,
+    /// Keep the wide text values of every materialized node compressed with this codec.
+    #[serde(default)]
+    pub state_compression: Option<Compression>,
+    /// Compress the updates and replay pieces sent to domains on other workers with this codec.
+    #[serde(default)]
+    pub packet_compression: Option<Compression>,
+    /// Reject the client writes to base tables beyond this many operations per second from any
+    /// one writer.
+    #[serde(default)]
+    pub write_quota: Option<u32>,
 }
 
-const BATCH_SIZE: usize = 256;
+/// The number of write quota buckets each domain keeps before it forgets the full ones.
+const WRITE_LIMITERS_KEPT: usize = 1024;
+
+/// Who a client write counts against for the write quota.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Writer {
+    /// The identity the write was issued as.
+    Identity(DataType),
+    /// The connection the write arrived on, by token and epoch, for writes without an identity.
+    Connection(usize, usize),
+}
+
+/// The number of slow replays each domain remembers.
+const SLOW_REPLAYS_KEPT: usize = 256;
+
+/// How long a base table waits for an atomic write to commit before it undoes the atomic write's
+/// writes to it, in case the client that started it went away.
+const TRANSACTION_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// How long a base table remembers the atomic writes it undid, so that it can reject their late
+/// commits.
+const EXPIRED_TRANSACTIONS_KEPT: time::Duration = time::Duration::from_secs(600);
+
+/// How often a domain with open atomic writes checks whether any have taken too long.
+const TRANSACTION_CHECK_EVERY: time::Duration = time::Duration::from_secs(1);
+
+/// How often a domain with operators that hold back rows checks whether they are ready.
+const DEFERRED_CHECK_EVERY: time::Duration = time::Duration::from_millis(10);
+
+/// The writes of an atomic write that a base table has applied, but that have not committed.
+struct OpenTransaction {
+    /// The changes that undo the writes, in the order they are to be applied.
+    undo: Records,
+    /// When the first of the writes was applied.
+    since: time::Instant,
+}
 
 #[derive(Debug)]
 enum DomainMode {
@@ -133,6 +224,8 @@ pub struct DomainBuilder {
     pub persistence_parameters: PersistenceParameters,
     /// Configuration parameters for the domain.
     pub config: Config,
+    /// The checkpoint to restore the domain's materialized state from, if it is being recovered.
+    pub restore: Option<u64>,
 }
 
 unsafe impl Send for DomainBuilder {}
@@ -147,6 +240,7 @@ impl DomainBuilder {
         control_addr: SocketAddr,
         shutdown_valve: &Valve,
         state_size: Arc<AtomicUsize>,
+        metrics: &Metrics,
     ) -> Domain {
         // initially, all nodes are not ready
         let not_ready = self
@@ -162,7 +256,7 @@ impl DomainBuilder {
         Domain {
             index: self.index,
             shard: self.shard,
-            _nshards: self.nshards,
+            nshards: self.nshards,
 
             persistence_parameters: self.persistence_parameters,
             nodes: self.nodes,
@@ -188,12 +282,53 @@ impl DomainBuilder {
 
             concurrent_replays: 0,
             max_concurrent_replays: self.config.concurrent_replays,
+            join_spill_threshold: self.config.join_spill_threshold,
+            state_compression: self.config.state_compression,
+            packet_compression: self.config.packet_compression,
+            max_queued_packets: self.config.max_queued_packets,
+            queued_packets: 0,
+            backpressure_time: time::Duration::from_secs(0),
+            snapshot_reads: self.config.snapshot_reads,
+            base_writes: Default::default(),
+            frontier_interval: self.config.frontier_interval,
+            frontier_advanced: time::Instant::now(),
+            barriers: Default::default(),
+            next_barrier: 0,
+            checkpoint_barriers: 0,
+            paused_writes: None,
+            restore: self.restore,
+            rewound: Vec::new(),
+            restored: StateMap::default(),
+            generated_ids: Default::default(),
+            write_quota: self.config.write_quota,
+            write_limiters: Default::default(),
+            transactions: Default::default(),
+            expired_transactions: Default::default(),
+            transactions_checked: time::Instant::now(),
+            holding: false,
+            deferring: HashSet::new(),
+            deferred_checked: time::Instant::now(),
+            profile: None,
             replay_request_queue: Default::default(),
+            replay_priorities: Default::default(),
+            replay_traces: Default::default(),
+            slow_replay_threshold: self.config.slow_replay_threshold,
+            replays_started: Default::default(),
+            slow_replays: Default::default(),
+            prioritized: false,
+            bulk: Default::default(),
+            overtaken: 0,
+            coalesced: None,
             delayed_for_self: Default::default(),
 
             group_commit_queues,
 
+            fence: None,
+
             state_size,
+            metrics: metrics.domain(self.index, self.shard.unwrap_or(0)),
+            node_metrics: Default::default(),
+            foreground: Default::default(),
             total_time: Timer::new(),
             total_ptime: Timer::new(),
             wait_time: Timer::new(),
@@ -206,6 +341,49 @@ impl DomainBuilder {
     }
 }
 
+/// Paces the chunks of a full-state replay.
+struct Pacer {
+    pacing: ReplayPacing,
+    foreground: Arc<AtomicUsize>,
+    seen: usize,
+    backoff: time::Duration,
+}
+
+impl Pacer {
+    fn new(config: ReplayConfig, foreground: Arc<AtomicUsize>) -> Self {
+        let seen = foreground.load(Ordering::Relaxed);
+        Pacer {
+            pacing: config.pacing,
+            foreground,
+            seen,
+            backoff: time::Duration::from_millis(0),
+        }
+    }
+
+    /// Wait as long as we should before sending the next chunk.
+    fn pace(&mut self) {
+        match self.pacing {
+            ReplayPacing::Unpaced => {}
+            ReplayPacing::Fixed(every) => std::thread::sleep(every),
+            ReplayPacing::Adaptive(max) => {
+                // if the domain has processed regular writes since the last chunk, it is busy,
+                // so we back off exponentially to let it catch up; otherwise we go full speed.
+                let seen = self.foreground.load(Ordering::Relaxed);
+                if seen != self.seen {
+                    self.backoff = cmp::min(
+                        cmp::max(self.backoff * 2, time::Duration::from_millis(1)),
+                        max,
+                    );
+                    std::thread::sleep(self.backoff);
+                } else {
+                    self.backoff = time::Duration::from_millis(0);
+                }
+                self.seen = self.foreground.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct TimedPurge {
     time: time::Instant,
@@ -217,7 +395,7 @@ struct TimedPurge {
 pub struct Domain {
     index: Index,
     shard: Option<usize>,
-    _nshards: usize,
+    nshards: usize,
 
     nodes: DomainNodes,
     state: StateMap,
@@ -239,7 +417,84 @@ pub struct Domain {
 
     concurrent_replays: usize,
     max_concurrent_replays: usize,
+    join_spill_threshold: Option<usize>,
+    state_compression: Option<Compression>,
+    packet_compression: Option<Compression>,
+    max_queued_packets: Option<usize>,
+    /// The number of packets waiting to be sent to other domains, as last recorded.
+    queued_packets: usize,
+    /// The time this domain has spent not accepting input because too many packets were waiting
+    /// to be sent to other domains.
+    backpressure_time: time::Duration,
+    snapshot_reads: bool,
+    /// The number of writes each local base table has processed, if snapshot reads are enabled.
+    base_writes: Map<u64>,
+    frontier_interval: Option<time::Duration>,
+    /// When the write frontiers of the local base tables were last advanced.
+    frontier_advanced: time::Instant,
+    /// The client waiting for each flush barrier this domain started, unless it was started for a
+    /// checkpoint, along with the credit that has not yet come back from the data-flow.
+    barriers: HashMap<u64, (Option<SourceChannelIdentifier>, u64)>,
+    next_barrier: u64,
+    /// The number of barriers started for a checkpoint that are still making their way through
+    /// the data-flow.
+    checkpoint_barriers: usize,
+    /// Client writes that arrived while a checkpoint was being taken, or while base tables were
+    /// rewound for recovery, which are applied once that is done.
+    paused_writes: Option<VecDeque<Box<Packet>>>,
+    /// The checkpoint that recovery restores this domain's state from, until it has done so.
+    restore: Option<u64>,
+    /// Base tables that were rewound to `restore`, whose writes since are yet to be applied again.
+    rewound: Vec<LocalNodeIndex>,
+    /// State loaded from snapshots, which is only used if every shard of its node has one.
+    restored: StateMap,
+    /// The values generated for the auto-increment columns of queued client writes, which are
+    /// sent back to the clients once the writes are applied.
+    generated_ids: HashMap<SourceChannelIdentifier, Vec<DataType>>,
+    write_quota: Option<u32>,
+    /// The writes each writer may still make this second, if there is a write quota.
+    write_limiters: HashMap<Writer, RateLimiter>,
+    /// The atomic writes that the local base tables have applied writes of, but that have not
+    /// committed, by id and base table.
+    transactions: HashMap<(u64, LocalNodeIndex), OpenTransaction>,
+    /// When the local base tables undid the atomic writes that did not commit in time.
+    expired_transactions: HashMap<(u64, LocalNodeIndex), time::Instant>,
+    /// When the atomic writes were last checked for taking too long.
+    transactions_checked: time::Instant,
+    /// Whether a local reader may be holding back the updates of atomic writes.
+    holding: bool,
+    /// The nodes whose operators are holding back rows to emit later.
+    deferring: HashSet<LocalNodeIndex>,
+    /// When the operators that hold back rows were last checked for rows ready to be forwarded.
+    deferred_checked: time::Instant,
+    /// The time each node has spent processing since profiling was started, if it was.
+    profile: Option<Map<time::Duration>>,
     replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
+    /// The priority of the upqueries that replays along each tag were last requested for, unless
+    /// it was `ViewPriority::Normal`.
+    replay_priorities: HashMap<Tag, ViewPriority>,
+    /// The trace of the upqueries that replays along each tag were last requested for, if they
+    /// were traced.
+    replay_traces: HashMap<Tag, TraceContext>,
+    /// Replays that take longer than this to fill a key are recorded in `slow_replays`.
+    slow_replay_threshold: Option<time::Duration>,
+    /// When this domain first requested a replay of each key along each tag that has not been
+    /// filled yet, if slow replays are recorded.
+    replays_started: HashMap<(Tag, Vec<DataType>), time::Instant>,
+    /// The most recent replays that took longer than `slow_replay_threshold`.
+    slow_replays: VecDeque<SlowReplay>,
+    /// Whether this domain has seen upqueries with a priority other than `ViewPriority::Normal`.
+    ///
+    /// Until it has, packets are handled in the order they arrive.
+    prioritized: bool,
+    /// Backfills and low-priority upqueries that were deferred so that more urgent upqueries
+    /// could go ahead of them, in the order they arrived.
+    bulk: VecDeque<Box<Packet>>,
+    /// The number of upqueries that have gone ahead of `bulk` since it was last served.
+    overtaken: usize,
+    /// An update from another domain that the updates arriving right after it along the same
+    /// link are merged into, until something else arrives or the domain runs out of input.
+    coalesced: Option<Box<Packet>>,
 
     shutdown_valve: Valve,
     readers: Readers,
@@ -252,7 +507,17 @@ pub struct Domain {
 
     group_commit_queues: GroupCommitQueueSet,
 
+    /// The newest controller epoch that has changed a base's read-only status.
+    fence: Option<Epoch>,
+
     state_size: Arc<AtomicUsize>,
+    metrics: Arc<DomainMetrics>,
+    /// The metrics of each node, so that recording into them does not have to go through
+    /// `metrics`.
+    node_metrics: Map<Arc<NodeMetrics>>,
+    /// Number of regular (non-replay) packets this domain has processed, so that replays can
+    /// yield to them.
+    foreground: Arc<AtomicUsize>,
     total_time: Timer<SimpleTracker, RealTime>,
     total_ptime: Timer<SimpleTracker, ThreadTime>,
     wait_time: Timer<SimpleTracker, RealTime>,
@@ -266,11 +531,98 @@ pub struct Domain {
 }
 
 impl Domain {
+    /// The function that a partial reader on `node` calls with the keys it misses on, which
+    /// sends them to the shards of `trigger_domain` that they belong to, to be filled.
+    fn reader_trigger(
+        &self,
+        node: LocalNodeIndex,
+        key: &[usize],
+        trigger_domain: Index,
+        shards: usize,
+    ) -> impl Fn(&mut dyn Iterator<Item = &[DataType]>, Option<TraceContext>) -> bool
+           + 'static
+           + Send
+           + Sync {
+        let i = self.nodes[node]
+            .borrow()
+            .with_reader(|r| r.shard_key_index())
+            .unwrap_or(0);
+        let scheme = match self.nodes[node].borrow().sharded_by() {
+            Sharding::ByColumn(_, _, scheme) => scheme,
+            _ => ShardScheme::Hash,
+        };
+        let txs = (0..shards)
+            .map(|shard| {
+                let key = Vec::from(key);
+                let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+                let sender = self
+                    .channel_coordinator
+                    .builder_for(&(trigger_domain, shard))
+                    .unwrap()
+                    .build_async()
+                    .unwrap();
+
+                tokio::spawn(
+                    self.shutdown_valve
+                        .wrap(rx)
+                        .map(move |(misses, trace)| {
+                            Box::new(Packet::RequestReaderReplay {
+                                keys: misses,
+                                cols: key.clone(),
+                                node,
+                                trace,
+                            })
+                        })
+                        .map(Ok)
+                        .forward(sender)
+                        .map(|r| {
+                            if let Err(e) = r {
+                                // domain went away?
+                                eprintln!("replay source went away: {:?}", e);
+                            }
+                        }),
+                );
+                tx
+            })
+            .collect::<Vec<_>>();
+
+        move |misses: &mut dyn Iterator<Item = &[DataType]>, trace: Option<TraceContext>| {
+            let n = txs.len();
+            if n == 1 {
+                use std::iter::FromIterator;
+                let misses = Vec::from_iter(misses.map(Vec::from));
+                if misses.is_empty() {
+                    return true;
+                }
+                txs[0].send((misses, trace)).is_ok()
+            } else {
+                let mut per_shard = HashMap::new();
+                for miss in misses {
+                    // compound keys are sharded by one of their columns, the first unless the
+                    // reader says otherwise
+                    let shard = scheme.shard(&miss[i..=i], n);
+                    per_shard
+                        .entry(shard)
+                        .or_insert_with(Vec::new)
+                        .push(Vec::from(miss));
+                }
+                if per_shard.is_empty() {
+                    return true;
+                }
+                per_shard
+                    .into_iter()
+                    .all(|(shard, keys)| txs[shard].send((keys, trace)).is_ok())
+            }
+        }
+    }
+
     fn find_tags_and_replay(
         &mut self,
         miss_keys: Vec<Vec<DataType>>,
         miss_columns: &[usize],
         miss_in: LocalNodeIndex,
+        priority: ViewPriority,
+        trace: Option<TraceContext>,
     ) {
         let mut tags = Vec::new();
         if let Some(ref candidates) = self.replay_paths_by_dst.get(miss_in) {
@@ -282,6 +634,17 @@ impl Domain {
         }
 
         for &tag in &tags {
+            self.set_replay_priority(tag, priority);
+            self.set_replay_trace(tag, trace);
+            if self.slow_replay_threshold.is_some() {
+                let now = time::Instant::now();
+                for key in &miss_keys {
+                    self.replays_started
+                        .entry((tag, key.clone()))
+                        .or_insert(now);
+                }
+            }
+
             // send a message to the source domain(s) responsible
             // for the chosen tag so they'll start replay.
             let keys = miss_keys.clone(); // :(
@@ -311,6 +674,8 @@ impl Domain {
                         keys,
                         unishard: true, // local replays are necessarily single-shard
                         requesting_shard: self.shard.unwrap_or(0),
+                        priority,
+                        trace,
                     }));
                 continue;
             }
@@ -338,6 +703,7 @@ impl Domain {
         was_single_shard: bool,
         requesting_shard: usize,
         needed_for: Tag,
+        trace: Option<TraceContext>,
     ) {
         use std::collections::hash_map::Entry;
         use std::ops::AddAssign;
@@ -378,11 +744,109 @@ impl Domain {
             return;
         }
 
-        self.find_tags_and_replay(vec![miss_key], miss_columns, miss_in);
+        let priority = self.replay_priority(needed_for);
+        self.find_tags_and_replay(vec![miss_key], miss_columns, miss_in, priority, trace);
+    }
+
+    /// The priority of the upqueries that replays along `tag` were last requested for.
+    fn replay_priority(&self, tag: Tag) -> ViewPriority {
+        self.replay_priorities
+            .get(&tag)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_replay_priority(&mut self, tag: Tag, priority: ViewPriority) {
+        if priority == ViewPriority::Normal {
+            self.replay_priorities.remove(&tag);
+        } else {
+            self.prioritized = true;
+            self.replay_priorities.insert(tag, priority);
+        }
+    }
+
+    /// The trace of the upquery that replays along `tag` were last requested for, if it was
+    /// traced.
+    fn replay_trace(&self, tag: Tag) -> Option<TraceContext> {
+        self.replay_traces.get(&tag).copied()
+    }
+
+    fn set_replay_trace(&mut self, tag: Tag, trace: Option<TraceContext>) {
+        match trace {
+            Some(trace) => self.replay_traces.insert(tag, trace),
+            None => self.replay_traces.remove(&tag),
+        };
+    }
+
+    /// Record the replays of `keys` into `node` along `tag` that took longer than the slow-replay
+    /// threshold to arrive.
+    fn record_slow_replays(
+        &mut self,
+        tag: Tag,
+        node: LocalNodeIndex,
+        keys: &HashSet<Vec<DataType>>,
+        domains: Vec<(usize, usize)>,
+        bytes: u64,
+    ) {
+        let threshold = match self.slow_replay_threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let n = self.nodes[node].borrow();
+        for key in keys {
+            let took = match self.replays_started.remove(&(tag, key.clone())) {
+                Some(started) => started.elapsed(),
+                None => continue,
+            };
+            if took < threshold {
+                continue;
+            }
+            if self.slow_replays.len() == SLOW_REPLAYS_KEPT {
+                self.slow_replays.pop_front();
+            }
+            self.slow_replays.push_back(SlowReplay {
+                node: n.global_addr(),
+                view: n.name().to_owned(),
+                key: key.clone(),
+                domains: domains.clone(),
+                bytes,
+                took,
+                finished: time::SystemTime::now(),
+            });
+        }
+    }
+
+    /// Start a span of the trace `parent` for work this domain does at `node`, and return it along
+    /// with the context that the packets sent on behalf of that work carry on.
+    fn trace_span(
+        &self,
+        name: &'static str,
+        parent: TraceContext,
+        node: LocalNodeIndex,
+        tag: Option<Tag>,
+    ) -> (tracing::Span, TraceContext) {
+        let ctx = parent.child();
+        let span = tracing::info_span!(
+            "noria",
+            otel.name = name,
+            trace_id = %format!("{:032x}", ctx.trace_id),
+            span_id = %format!("{:016x}", ctx.span_id),
+            parent_span_id = %format!("{:016x}", parent.span_id),
+            domain = self.index.index(),
+            shard = self.shard.unwrap_or(0),
+            node = self.nodes[node].borrow().global_addr().index(),
+            tag = tracing::field::Empty,
+        );
+        if let Some(tag) = tag {
+            span.record("tag", &tracing::field::display(tag));
+        }
+        (span, ctx)
     }
 
     fn send_partial_replay_request(&mut self, tag: Tag, keys: Vec<Vec<DataType>>) {
         debug_assert!(self.concurrent_replays < self.max_concurrent_replays);
+        let priority = self.replay_priority(tag);
+        let trace = self.replay_trace(tag);
         if let TriggerEndpoint::End {
             source,
             ref mut options,
@@ -396,7 +860,11 @@ impl Domain {
                     // options.len() == 1.
                     None
                 }
-                SourceSelection::KeyShard { key_i_to_shard, .. } => Some(key_i_to_shard),
+                SourceSelection::KeyShard {
+                    key_i_to_shard,
+                    scheme,
+                    ..
+                } => Some((key_i_to_shard, scheme)),
             };
 
             if ask_shard_by_key_i.is_none() && options.len() != 1 {
@@ -417,6 +885,8 @@ impl Domain {
                             unishard: false, // ask_all is true, so replay is sharded
                             keys: keys.clone(), // sad to clone here
                             requesting_shard: self.shard.unwrap_or(0),
+                            priority,
+                            trace,
                         }))
                         .is_err()
                     {
@@ -441,15 +911,17 @@ impl Domain {
                         keys,
                         unishard: true, // only one option, so only one path
                         requesting_shard: self.shard.unwrap_or(0),
+                        priority,
+                        trace,
                     }))
                     .is_err()
                 {
                     // we're shutting down -- it's fine.
                 }
-            } else if let Some(key_shard_i) = ask_shard_by_key_i {
+            } else if let Some((key_shard_i, scheme)) = ask_shard_by_key_i {
                 let mut shards = HashMap::new();
                 for key in keys {
-                    let shard = crate::shard_by(&key[key_shard_i], options.len());
+                    let shard = scheme.shard(&key[key_shard_i..=key_shard_i], options.len());
                     shards.entry(shard).or_insert_with(Vec::new).push(key);
                 }
                 for (shard, keys) in shards {
@@ -459,6 +931,8 @@ impl Domain {
                             keys,
                             unishard: true, // !ask_all, so only one path
                             requesting_shard: self.shard.unwrap_or(0),
+                            priority,
+                            trace,
                         }))
                         .is_err()
                     {
@@ -484,7 +958,12 @@ impl Domain {
                 "keys" => ?keys,
                 "buffered" => self.replay_request_queue.len(),
             );
-            self.replay_request_queue.push_back((tag, keys));
+            if self.replay_priority(tag) == ViewPriority::High {
+                // latency-sensitive upqueries get the next free replay slot
+                self.replay_request_queue.push_front((tag, keys));
+            } else {
+                self.replay_request_queue.push_back((tag, keys));
+            }
         }
     }
 
@@ -558,7 +1037,7 @@ impl Domain {
         }
     }
 
-    fn dispatch(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+    fn dispatch(&mut self, mut m: Box<Packet>, executor: &mut dyn Executor) {
         let src = m.src();
         let me = m.dst();
 
@@ -576,13 +1055,57 @@ impl Domain {
         }
 
         if !self.not_ready.is_empty() && self.not_ready.contains(&me) {
+            // the node will only see the writes before the barrier once it has been backfilled
+            if let Some(b) = m.barrier() {
+                self.reach_barrier(b.clone(), executor);
+            }
             return;
         }
 
+        // a barrier ends wherever the update carrying it does, unless the node forwards the
+        // update to other domains itself
+        let barrier = if self.nodes[me].borrow().is_sender() {
+            None
+        } else {
+            m.barrier().cloned()
+        };
+        // a barrier sent to a base table starts out here
+        let started = match *m {
+            Packet::Input {
+                ref inner,
+                src: Some(src),
+                ..
+            } if unsafe { inner.deref() }.barrier => Some(self.start_barrier(Some(src))),
+            _ => None,
+        };
+        // writes are acknowledged once they have been numbered, so that clients can wait for them
+        let senders = match *m {
+            Packet::Input {
+                ref mut senders, ..
+            } => mem::take(senders),
+            _ => Vec::new(),
+        };
+
+        // every node that a traced write passes through gets a span of its own
+        let traced = m.trace().map(|parent| {
+            let (span, ctx) = self.trace_span("process", parent, me, None);
+            m.set_trace(ctx);
+            span
+        });
+        let _guard = traced.as_ref().map(tracing::Span::enter);
+
+        let records = match *m {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.data.len(),
+            Packet::Message { .. } => m.len(),
+            _ => 0,
+        };
+        self.node_metrics(me).processed(records);
+
         let (mut m, evictions) = {
             let mut n = self.nodes[me].borrow_mut();
             self.process_times.start(me);
             self.process_ptimes.start(me);
+            let start = self.profile.as_ref().map(|_| time::Instant::now());
             let mut m = Some(m);
             let (misses, _, captured) = n.process(
                 &mut m,
@@ -598,12 +1121,55 @@ impl Domain {
             assert_eq!(captured.len(), 0);
             self.process_ptimes.stop();
             self.process_times.stop();
+            if let (Some(profile), Some(start)) = (self.profile.as_mut(), start) {
+                *profile.entry(me).or_default() += start.elapsed();
+            }
+            if !self.holding {
+                self.holding = n.with_reader(|r| r.is_holding()).unwrap_or(false);
+            }
+            if n.has_deferred() {
+                self.deferring.insert(me);
+            }
 
             if m.is_none() {
                 // no need to deal with our children if we're not sending them anything
+                drop(n);
+                if let Some(b) = barrier {
+                    self.reach_barrier(b, executor);
+                }
                 return;
             }
 
+            if let Some(b) = started {
+                m.as_mut().unwrap().set_barrier(b);
+            }
+
+            let mut written = Watermarks::default();
+            if self.snapshot_reads && n.is_base() {
+                let seq = self.base_writes.entry(me).or_insert(0);
+                *seq += 1;
+                written.advance(n.global_addr().index(), self.shard.unwrap_or(0), *seq);
+                m.as_mut().unwrap().watermarks_mut().merge(&written);
+            }
+            for src in senders {
+                let generated = self.generated_ids.remove(&src).unwrap_or_default();
+                executor.ack(
+                    src,
+                    WriteAck {
+                        written: written.clone(),
+                        generated,
+                    },
+                );
+            }
+
+            if let Some(t) = n.get_base().and_then(|b| b.latest_event_time()) {
+                m.as_mut().unwrap().event_times_mut().advance(
+                    n.global_addr().index(),
+                    self.shard.unwrap_or(0),
+                    t,
+                );
+            }
+
             // normally, we ignore misses during regular forwarding.
             // however, we have to be a little careful in the case of joins.
             let evictions = if n.is_internal() && n.is_join() && !misses.is_empty() {
@@ -696,6 +1262,10 @@ impl Domain {
             (m, evictions)
         };
 
+        if self.nodes[me].borrow().is_base() {
+            self.track_transaction(me, m.as_mut().unwrap());
+        }
+
         if let Some(evictions) = evictions {
             // now send evictions for all the (tag, [key]) things in evictions
             for (tag, keys) in evictions {
@@ -711,8 +1281,17 @@ impl Domain {
         }
 
         match &**m.as_ref().unwrap() {
-            m @ &Packet::Message { .. } if m.is_empty() => {
-                // no need to deal with our children if we're not sending them anything
+            m @ &Packet::Message { .. }
+                if m.is_empty()
+                    && m.watermarks().is_empty()
+                    && m.event_times().is_empty()
+                    && m.frontier().is_empty()
+                    && m.barrier().is_none() =>
+            {
+                // no need to deal with our children if we're not sending them anything.
+                // updates with watermarks, event times or frontiers are still forwarded so that
+                // readers learn about writes that did not change them, and so are updates with
+                // barriers, which have to pass through every node below the base table.
                 return;
             }
             &Packet::Message { .. } => {}
@@ -722,8 +1301,28 @@ impl Domain {
             m => unreachable!("dispatch process got {:?}", m),
         }
 
+        self.dispatch_to_children(me, m.unwrap(), executor);
+    }
+
+    /// Forward a regular update that `me` has produced to each of its children.
+    fn dispatch_to_children(
+        &mut self,
+        me: LocalNodeIndex,
+        m: Box<Packet>,
+        executor: &mut dyn Executor,
+    ) {
+        let mut m = Some(m);
+
         // NOTE: we can't directly iterate over .children due to self.dispatch in the loop
         let nchildren = self.nodes[me].borrow().children().len();
+        if nchildren == 0 {
+            if let Some(b) = m.as_ref().unwrap().barrier() {
+                self.reach_barrier(b.clone(), executor);
+            }
+            return;
+        }
+
+        let mut barrier_shares = m.as_ref().unwrap().barrier_shares(nchildren);
         for i in 0..nchildren {
             // avoid cloning if we can
             let mut m = if i == nchildren - 1 {
@@ -731,6 +1330,9 @@ impl Domain {
             } else {
                 m.as_ref().map(|m| Box::new(m.clone_data())).unwrap()
             };
+            if let Some(ref mut shares) = barrier_shares {
+                m.set_barrier(shares.next().unwrap());
+            }
 
             let childi = self.nodes[me].borrow().children()[i];
             let child_is_merger = {
@@ -750,6 +1352,582 @@ impl Domain {
         }
     }
 
+    /// Start a flush barrier for the client write `src`, which is acknowledged once the barrier
+    /// has passed through every node below the base table. Barriers without a client are started
+    /// for checkpoints.
+    fn start_barrier(&mut self, src: Option<SourceChannelIdentifier>) -> Barrier {
+        let id = self.next_barrier;
+        self.next_barrier += 1;
+        self.barriers.insert(id, (src, Barrier::CREDIT));
+        Barrier {
+            origin: (self.index, self.shard.unwrap_or(0)),
+            id,
+            credit: Barrier::CREDIT,
+        }
+    }
+
+    /// Hand the credit of a barrier that has stopped here back to the domain that started it.
+    fn reach_barrier(&mut self, b: Barrier, executor: &mut dyn Executor) {
+        if b.origin == (self.index, self.shard.unwrap_or(0)) {
+            self.barrier_reached(b.id, b.credit, executor);
+        } else {
+            executor.send(
+                b.origin,
+                Box::new(Packet::BarrierReached {
+                    id: b.id,
+                    credit: b.credit,
+                }),
+            );
+        }
+    }
+
+    fn barrier_reached(&mut self, id: u64, credit: u64, executor: &mut dyn Executor) {
+        let done = {
+            let (_, ref mut left) = self
+                .barriers
+                .get_mut(&id)
+                .expect("credit returned for unknown barrier");
+            *left -= credit;
+            *left == 0
+        };
+        if done {
+            match self.barriers.remove(&id).unwrap() {
+                (Some(src), _) => {
+                    // the writes of an atomic write are only acknowledged once they have passed
+                    // through the data-flow, along with any values generated for them
+                    let generated = self.generated_ids.remove(&src).unwrap_or_default();
+                    executor.ack(
+                        src,
+                        WriteAck {
+                            generated,
+                            ..WriteAck::default()
+                        },
+                    )
+                }
+                (None, _) => {
+                    self.checkpoint_barriers -= 1;
+                    if self.checkpoint_barriers == 0 {
+                        // every write from before the pause has been applied everywhere
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pause client writes, and send a barrier from each base table once the writes queued for it
+    /// have been applied. The controller is told once all the barriers have passed through the
+    /// data-flow, at which point its state is what the writes so far made it.
+    fn start_checkpoint(&mut self, executor: &mut dyn Executor) {
+        self.paused_writes.get_or_insert_with(VecDeque::new);
+        let bases: Vec<_> = self
+            .nodes
+            .values()
+            .filter_map(|n| {
+                let n = n.borrow();
+                if n.is_base() && !n.is_dropped() && !self.not_ready.contains(&n.local_addr()) {
+                    Some(n.local_addr())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if bases.is_empty() {
+            self.control_reply_tx
+                .send(ControlReplyPacket::ack())
+                .unwrap();
+            return;
+        }
+
+        self.checkpoint_barriers = bases.len();
+        for base in bases {
+            if let Some(m) = self.group_commit_queues.flush(base) {
+                self.dispatch(m, executor);
+            }
+            let m = Box::new(Packet::Message {
+                link: Link::new(base, base),
+                data: Records::default(),
+                watermarks: Default::default(),
+                barrier: Some(self.start_barrier(None)),
+                event_times: Default::default(),
+                frontier: Default::default(),
+                trace: None,
+                txn: None,
+            });
+            self.dispatch_to_children(base, m, executor);
+        }
+    }
+
+    /// Write the state of every fully materialized node below the base tables to its snapshot for
+    /// checkpoint `id`, and mark where in their logs the base tables are. Returns false if any of
+    /// the snapshots could not be written.
+    fn checkpoint(&mut self, id: u64) -> bool {
+        let dir = self.persistence_parameters.checkpoint_dir();
+        let shard = self.shard.unwrap_or(0);
+        let mut written = true;
+        for (node, state) in self.state.iter_mut() {
+            if let Some(base) = state.as_persistent_mut() {
+                base.mark_checkpoint(id);
+                continue;
+            }
+            let n = self.nodes[node].borrow();
+            if n.is_base() || state.is_partial() || self.not_ready.contains(&node) {
+                continue;
+            }
+            if let Err(e) = checkpoint::write(&dir, id, &n, shard, state.cloned_records()) {
+                error!(self.log, "failed to write snapshot: {}", e; "node" => node.id());
+                written = false;
+            }
+        }
+        written
+    }
+
+    /// Load the rows that the shards of the old layout of re-sharded base node `node` wrote out for
+    /// this shard into its new state `s`, if they have not been loaded yet.
+    ///
+    /// Whatever rows `s` already has are from an earlier layout of the same shape, or from an
+    /// attempt at loading the rows that was cut short, so they are dropped first.
+    fn import_rows(&mut self, node: LocalNodeIndex, s: &mut dyn State) {
+        let dir = self.persistence_parameters.checkpoint_dir();
+        let name = {
+            let n = self.nodes[node].borrow();
+            format!(
+                "{}-{}-{}",
+                self.persistence_parameters.log_prefix,
+                n.get_base().unwrap().state_name(n.name()),
+                self.shard.unwrap_or(0),
+            )
+        };
+        let rows = match reshard::import(&dir, &name) {
+            Ok(Some(rows)) => rows,
+            Ok(None) => return,
+            Err(e) => {
+                crit!(self.log, "failed to load rows of re-sharded base: {}", e;
+                      "node" => node.id());
+                return;
+            }
+        };
+        info!(self.log, "loading rows of re-sharded base";
+              "node" => node.id(),
+              "rows" => rows.len());
+        let mut stale: Records = s
+            .cloned_records()
+            .into_iter()
+            .map(Record::Negative)
+            .collect();
+        if !stale.is_empty() {
+            s.process_records(&mut stale, None);
+        }
+        s.process_records(&mut rows.into(), None);
+        if let Err(e) = reshard::finish_import(&dir, &name) {
+            warn!(
+                self.log,
+                "failed to remove loaded rows of re-sharded base: {}", e
+            );
+        }
+    }
+
+    /// Write out the rows of base node `node` for the shards of its new layout. Returns false if
+    /// they could not all be written.
+    fn export_rows(
+        &mut self,
+        node: LocalNodeIndex,
+        to: &str,
+        key: &[usize],
+        shards: usize,
+        scheme: ShardScheme,
+        token: u64,
+    ) -> bool {
+        let mut parts = vec![Vec::new(); shards];
+        if let Some(state) = self.state.get(node) {
+            for row in state.cloned_records() {
+                // the same way that clients route their writes
+                let values: Vec<_> = key.iter().map(|&c| row[c].clone()).collect();
+                parts[scheme.shard(&values, shards)].push(row);
+            }
+        }
+        let dir = self.persistence_parameters.checkpoint_dir();
+        let from = self.shard.unwrap_or(0);
+        let to = format!("{}-{}", self.persistence_parameters.log_prefix, to);
+        match reshard::export(&dir, &to, token, from, parts) {
+            Ok(()) => true,
+            Err(e) => {
+                error!(self.log, "failed to write rows for re-shard: {}", e; "node" => node.id());
+                false
+            }
+        }
+    }
+
+    /// Apply the client writes that were paused, in the order they arrived in.
+    fn resume_writes(&mut self, executor: &mut dyn Executor) {
+        if let Some(paused) = self.paused_writes.take() {
+            for m in paused {
+                self.on_event(executor, PollEvent::Process(m));
+            }
+        }
+    }
+
+    /// Apply the writes that base tables undid when they were rewound to the checkpoint that
+    /// recovery started from again, and forward them through the data-flow, whose state is now
+    /// what it was at that checkpoint.
+    fn replay_logs(&mut self, executor: &mut dyn Executor) {
+        for base in mem::replace(&mut self.rewound, Vec::new()) {
+            let redone = self
+                .state
+                .get_mut(base)
+                .and_then(|s| s.as_persistent_mut())
+                .map(|s| s.redo())
+                .unwrap_or_default();
+            info!(self.log, "replaying writes since checkpoint";
+                  "node" => base.id(), "batches" => redone.len());
+            for data in redone {
+                let m = Box::new(Packet::Message {
+                    link: Link::new(base, base),
+                    data,
+                    watermarks: Default::default(),
+                    barrier: None,
+                    event_times: Default::default(),
+                    frontier: Default::default(),
+                    trace: None,
+                    txn: None,
+                });
+                self.dispatch_to_children(base, m, executor);
+            }
+        }
+        self.restore = None;
+        self.resume_writes(executor);
+    }
+
+    /// Re-evaluate a periodically refreshed node over all of its parent's rows, and forward the
+    /// difference between that and the node's current output as a regular update.
+    fn handle_refresh(&mut self, me: LocalNodeIndex, executor: &mut dyn Executor) {
+        if self.not_ready.contains(&me) {
+            return;
+        }
+        if let DomainMode::Replaying { ref to, .. } = self.mode {
+            if *to == me {
+                // the node's state is still being filled, and will be refreshed next time around
+                return;
+            }
+        }
+
+        let parent = {
+            let n = self.nodes[me].borrow();
+            assert_eq!(
+                n.parents().len(),
+                1,
+                "refreshed nodes must be query-through"
+            );
+            n.parents()[0]
+        };
+        let (input, current) = match (self.state.get(parent), self.state.get(me)) {
+            (Some(p), Some(s)) if !p.is_partial() && !s.is_partial() => {
+                (p.cloned_records(), s.cloned_records())
+            }
+            _ => {
+                warn!(self.log, "cannot refresh node that is not fully materialized";
+                      "node" => me.id());
+                return;
+            }
+        };
+
+        let fresh = self.nodes[me]
+            .borrow_mut()
+            .on_input(
+                executor,
+                parent,
+                input.into_iter().collect(),
+                None,
+                &self.nodes,
+                &self.state,
+            )
+            .results;
+
+        // we can't tell which of the node's rows changed, so compute the difference between its
+        // old and new outputs
+        let mut counts: HashMap<Vec<DataType>, isize> = HashMap::new();
+        for r in fresh {
+            let (r, positive) = r.extract();
+            *counts.entry(r).or_default() += if positive { 1 } else { -1 };
+        }
+        for r in current {
+            *counts.entry(r).or_default() -= 1;
+        }
+        let rs: Records = counts
+            .into_iter()
+            .flat_map(|(r, n)| {
+                (0..n.abs()).map(move |_| {
+                    if n > 0 {
+                        Record::Positive(r.clone())
+                    } else {
+                        Record::Negative(r.clone())
+                    }
+                })
+            })
+            .collect();
+        if rs.is_empty() {
+            return;
+        }
+
+        trace!(self.log, "refreshed node"; "node" => me.id(), "changes" => rs.len());
+        self.forward(me, parent, rs, executor);
+    }
+
+    /// Forward the rows that the operators which hold back rows are now ready to emit.
+    fn forward_deferred(&mut self, executor: &mut dyn Executor) {
+        if self.deferred_checked.elapsed() < DEFERRED_CHECK_EVERY {
+            return;
+        }
+        if let DomainMode::Replaying { .. } = self.mode {
+            // the rows are forwarded once the replay has finished, like any other update
+            return;
+        }
+        self.deferred_checked = time::Instant::now();
+
+        let ready: Vec<_> = self
+            .deferring
+            .iter()
+            .filter(|me| !self.not_ready.contains(me))
+            .copied()
+            .collect();
+
+        for me in ready {
+            let (parent, rs) = {
+                let mut n = self.nodes[me].borrow_mut();
+                if !n.has_deferred() {
+                    // the held back rows were retracted, or the node was removed
+                    self.deferring.remove(&me);
+                    continue;
+                }
+                let rs = n.take_deferred();
+                if !n.has_deferred() {
+                    self.deferring.remove(&me);
+                }
+                (n.parents()[0], rs)
+            };
+            if !rs.is_empty() {
+                self.forward(me, parent, rs, executor);
+            }
+        }
+    }
+
+    /// Apply rows that `me` emitted outside of handling an update from `parent` to its state,
+    /// and send them on to its children.
+    fn forward(
+        &mut self,
+        me: LocalNodeIndex,
+        parent: LocalNodeIndex,
+        mut rs: Records,
+        executor: &mut dyn Executor,
+    ) {
+        if let Some(state) = self.state.get_mut(me) {
+            state.process_records(&mut rs, None);
+        }
+        let m = Box::new(Packet::Message {
+            link: Link::new(parent, me),
+            data: rs,
+            watermarks: Default::default(),
+            barrier: None,
+            event_times: Default::default(),
+            frontier: Default::default(),
+            trace: None,
+            txn: None,
+        });
+        self.dispatch_to_children(me, m, executor);
+    }
+
+    /// Tell the nodes below each local base table that it has processed every write it was sent
+    /// so far, if its write frontier is due to advance.
+    ///
+    /// The writes still queued for a base table are processed first. Nothing is advanced while
+    /// writes are paused, or while deferred packets, which may hold writes, wait to be handled.
+    fn advance_frontiers(&mut self, executor: &mut dyn Executor) {
+        match self.frontier_interval {
+            Some(every) if self.frontier_advanced.elapsed() >= every => {}
+            _ => return,
+        }
+        self.flush_coalesced(executor);
+        if !self.bulk.is_empty() || self.paused_writes.is_some() {
+            return;
+        }
+        self.frontier_advanced = time::Instant::now();
+
+        let bases: Vec<_> = self
+            .nodes
+            .values()
+            .filter_map(|n| {
+                let n = n.borrow();
+                if n.is_base() && !n.is_dropped() && !self.not_ready.contains(&n.local_addr()) {
+                    Some((n.local_addr(), n.global_addr().index()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for (base, global) in bases {
+            if let Some(m) = self.group_commit_queues.flush(base) {
+                self.dispatch(m, executor);
+            }
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .expect("system clock is before the Unix epoch")
+                .as_millis() as i64;
+            let mut frontier = EventTimes::default();
+            frontier.advance(global, self.shard.unwrap_or(0), now);
+            let m = Box::new(Packet::Message {
+                link: Link::new(base, base),
+                data: Records::default(),
+                watermarks: Default::default(),
+                barrier: None,
+                event_times: Default::default(),
+                frontier,
+                trace: None,
+                txn: None,
+            });
+            self.dispatch_to_children(base, m, executor);
+        }
+    }
+
+    /// Keep track of the atomic writes that the update `m` from the local base table `me` is part
+    /// of.
+    ///
+    /// The table remembers how to undo the writes of an atomic write until it commits, and the
+    /// update that aborts it carries the undoing.
+    fn track_transaction(&mut self, me: LocalNodeIndex, m: &mut Packet) {
+        match m.txn() {
+            Some(TransactionPhase::Write(id)) => {
+                let t = self
+                    .transactions
+                    .entry((id, me))
+                    .or_insert_with(|| OpenTransaction {
+                        undo: Records::default(),
+                        since: time::Instant::now(),
+                    });
+                // undoing the changes in reverse order leaves the table as it was
+                let mut undo = Records::default();
+                m.map_data(|data| {
+                    undo.extend(
+                        data.iter()
+                            .rev()
+                            .map(|r| Record::from((r.rec().to_vec(), !r.is_positive()))),
+                    )
+                });
+                undo.extend(mem::take(&mut t.undo));
+                t.undo = undo;
+            }
+            Some(TransactionPhase::Commit(id)) => {
+                self.transactions.remove(&(id, me));
+            }
+            Some(TransactionPhase::Abort(id)) => {
+                if let Some(t) = self.transactions.remove(&(id, me)) {
+                    let mut undo = t.undo;
+                    crate::node::materialize(&mut undo, None, self.state.get_mut(me));
+                    m.map_data(|data| data.extend(undo));
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Undo the writes of the atomic writes that have not committed in time, and stop holding
+    /// back the updates of the ones that readers have held for too long.
+    fn expire_transactions(&mut self, executor: &mut dyn Executor) {
+        if self.transactions_checked.elapsed() < TRANSACTION_CHECK_EVERY {
+            return;
+        }
+        let now = time::Instant::now();
+        self.transactions_checked = now;
+
+        self.expired_transactions
+            .retain(|_, &mut at| now.duration_since(at) < EXPIRED_TRANSACTIONS_KEPT);
+        // paused writes are undone once they resume
+        let expired: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|_| self.paused_writes.is_none())
+            .filter(|(_, t)| now.duration_since(t.since) >= TRANSACTION_TIMEOUT)
+            .map(|(&k, _)| k)
+            .collect();
+        for (id, base) in expired {
+            warn!(self.log, "undoing atomic write that did not commit in time";
+                  "node" => base.id(), "id" => id);
+            self.expired_transactions.insert((id, base), now);
+            // the undoing must come after the writes that are still queued
+            if let Some(m) = self.group_commit_queues.flush(base) {
+                self.dispatch(m, executor);
+            }
+            let m = Box::new(Packet::Input {
+                inner: LocalOrNot::new(Input {
+                    dst: base,
+                    data: Vec::new(),
+                    writer: None,
+                    barrier: false,
+                    trace: None,
+                    txn: Some(TransactionPhase::Abort(id)),
+                }),
+                src: None,
+                senders: Vec::new(),
+            });
+            self.dispatch(m, executor);
+        }
+
+        if self.holding {
+            let mut holding = false;
+            for n in self.nodes.values() {
+                let mut n = n.borrow_mut();
+                if let Ok(h) = n.with_reader_mut(|r| r.expire_held(now)) {
+                    holding |= h;
+                }
+            }
+            self.holding = holding;
+        }
+    }
+
+    /// Retract the rows of a node with a row TTL that have outlived it.
+    ///
+    /// Base nodes delete their expired rows like a client would, so that the deletes reach all
+    /// the state below them. Readers have nothing below them, and just drop the rows.
+    fn expire_rows(&mut self, me: LocalNodeIndex, executor: &mut dyn Executor) {
+        if self.not_ready.contains(&me) || self.paused_writes.is_some() {
+            // the rows will expire next time around
+            return;
+        }
+
+        let expired = {
+            let mut n = self.nodes[me].borrow_mut();
+            if let Some(b) = n.get_base() {
+                b.expired(me, &self.state)
+            } else {
+                let _ = n.with_reader_mut(|r| r.expire_rows());
+                return;
+            }
+        };
+        if expired.is_empty() {
+            return;
+        }
+
+        trace!(self.log, "expiring rows"; "node" => me.id(), "rows" => expired.len());
+        // the deletes must come after the writes that are still queued
+        if let Some(m) = self.group_commit_queues.flush(me) {
+            self.dispatch(m, executor);
+        }
+        let m = Box::new(Packet::Input {
+            inner: LocalOrNot::new(Input {
+                dst: me,
+                data: expired,
+                writer: None,
+                barrier: false,
+                trace: None,
+                txn: None,
+            }),
+            src: None,
+            senders: Vec::new(),
+        });
+        self.dispatch(m, executor);
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn handle(&mut self, m: Box<Packet>, executor: &mut dyn Executor, top: bool) {
         if self.wait_time.is_running() {
@@ -758,19 +1936,35 @@ impl Domain {
 
         match *m {
             Packet::Message { .. } | Packet::Input { .. } => {
+                self.foreground.fetch_add(1, Ordering::Relaxed);
                 // WO for https://github.com/rust-lang/rfcs/issues/1403
                 self.total_forward_time.start();
                 self.dispatch(m, executor);
                 self.total_forward_time.stop();
             }
             Packet::ReplayPiece { .. } => {
+                let start = time::Instant::now();
                 self.total_replay_time.start();
                 self.handle_replay(m, executor);
                 self.total_replay_time.stop();
+                self.metrics.replayed(start.elapsed());
             }
             Packet::Evict { .. } | Packet::EvictKeys { .. } => {
                 self.handle_eviction(m, executor);
             }
+            Packet::Refresh { node } => {
+                self.total_forward_time.start();
+                self.handle_refresh(node, executor);
+                self.total_forward_time.stop();
+            }
+            Packet::Expire { node } => {
+                self.total_forward_time.start();
+                self.expire_rows(node, executor);
+                self.total_forward_time.stop();
+            }
+            Packet::BarrierReached { id, credit } => {
+                self.barrier_reached(id, credit, executor);
+            }
             consumed => {
                 match consumed {
                     // workaround #16223
@@ -790,7 +1984,10 @@ impl Domain {
                     }
                     Packet::RemoveNodes { nodes } => {
                         for &node in &nodes {
-                            self.nodes[node].borrow_mut().remove();
+                            let mut n = self.nodes[node].borrow_mut();
+                            n.remove();
+                            self.metrics.remove(n.global_addr());
+                            self.node_metrics.remove(node);
                             self.state.remove(node);
                             trace!(self.log, "node removed"; "local" => node.id());
                         }
@@ -834,6 +2031,103 @@ impl Domain {
                             .send(ControlReplyPacket::ack())
                             .unwrap();
                     }
+                    Packet::SetReadOnly {
+                        node,
+                        read_only,
+                        epoch,
+                    } => {
+                        if self.fence.map(|f| f > epoch).unwrap_or(false) {
+                            warn!(
+                                self.log,
+                                "ignoring read-only change from demoted controller";
+                                "epoch" => ?epoch,
+                            );
+                        } else {
+                            self.fence = Some(epoch);
+                            let mut n = self.nodes[node].borrow_mut();
+                            n.get_base_mut()
+                                .expect("told to make non-base node read-only")
+                                .set_read_only(read_only);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetMirror { node, mirror } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.get_base_mut()
+                            .expect("told to mirror writes to non-base node")
+                            .set_mirror(mirror);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetEventTime { node, column } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.get_base_mut()
+                            .expect("told to set event time on non-base node")
+                            .set_event_time(column);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetAssertions { node, assertions } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_reader_mut(|r| r.set_assertions(assertions))
+                            .expect("told to set assertions on non-reader node");
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetTtl { node, ttl } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        if let Some(b) = n.get_base_mut() {
+                            b.set_ttl(ttl);
+                        } else {
+                            n.with_reader_mut(|r| r.set_ttl(ttl))
+                                .expect("told to set ttl on node that is neither base nor reader");
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetPriority { node, priority } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_reader_mut(|r| r.set_priority(priority))
+                            .expect("told to set priority on non-reader node");
+                        if priority != ViewPriority::Normal {
+                            self.prioritized = true;
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetEviction { node, eviction } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_reader_mut(|r| r.set_eviction(eviction))
+                            .expect("told to set eviction on non-reader node");
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetConstraints { node, constraints } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.get_base_mut()
+                            .expect("told to set constraints on non-base node")
+                            .set_constraints(constraints);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::SetWritePolicies { node, policies } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.get_base_mut()
+                            .expect("told to set write policies on non-base node")
+                            .set_write_policies(policies);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
                     Packet::UpdateEgress {
                         node,
                         new_tx,
@@ -844,22 +2138,100 @@ impl Domain {
                             if let Some((node, local, addr)) = new_tx {
                                 e.add_tx(node, local, addr);
                             }
-                            if let Some(new_tag) = new_tag {
-                                e.add_tag(new_tag.0, new_tag.1);
+                            if let Some(new_tag) = new_tag {
+                                e.add_tag(new_tag.0, new_tag.1);
+                            }
+                        });
+                    }
+                    Packet::UpdateSharder { node, new_txs } => {
+                        let mut n = self.nodes[node].borrow_mut();
+                        n.with_sharder_mut(move |s| {
+                            s.add_sharded_child(new_txs.0, new_txs.1);
+                        });
+                    }
+                    Packet::StateSizeProbe { node } => {
+                        let row_count = self.state.get(node).map(|r| r.rows()).unwrap_or(0);
+                        let mem_size = self.state.get(node).map(|s| s.deep_size_of()).unwrap_or(0);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::StateSize(row_count, mem_size))
+                            .unwrap();
+                    }
+                    Packet::StartCheckpoint => {
+                        self.start_checkpoint(executor);
+                    }
+                    Packet::Checkpoint { id } => {
+                        let written = self.checkpoint(id);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Checkpointed(written))
+                            .unwrap();
+                    }
+                    Packet::FinishCheckpoint { id } => {
+                        if let Some(id) = id {
+                            for (_, state) in self.state.iter_mut() {
+                                if let Some(base) = state.as_persistent_mut() {
+                                    base.trim_log(id);
+                                }
+                            }
+                            let dir = self.persistence_parameters.checkpoint_dir();
+                            if let Err(e) = checkpoint::remove_older(&dir, id) {
+                                warn!(self.log, "failed to remove old snapshots: {}", e);
+                            }
+                        }
+                        self.resume_writes(executor);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::RestoreState {
+                        node,
+                        index,
+                        checkpoint,
+                    } => {
+                        let dir = self.persistence_parameters.checkpoint_dir();
+                        let rows = checkpoint::read(
+                            &dir,
+                            checkpoint,
+                            &self.nodes[node].borrow(),
+                            self.shard.unwrap_or(0),
+                        );
+                        let restored = match rows {
+                            Ok(Some(rows)) => {
+                                let mut s = self.memory_state_for(node);
+                                for idx in index {
+                                    s.add_key(&idx[..], None);
+                                }
+                                s.process_records(&mut rows.into(), None);
+                                self.restored.insert(node, Box::new(s));
+                                true
+                            }
+                            Ok(None) => false,
+                            Err(e) => {
+                                warn!(self.log, "failed to read snapshot: {}", e;
+                                      "node" => node.id());
+                                false
                             }
-                        });
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Restored(restored))
+                            .unwrap();
                     }
-                    Packet::UpdateSharder { node, new_txs } => {
-                        let mut n = self.nodes[node].borrow_mut();
-                        n.with_sharder_mut(move |s| {
-                            s.add_sharded_child(new_txs.0, new_txs.1);
-                        });
+                    Packet::ReplayLogs => {
+                        self.replay_logs(executor);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
                     }
-                    Packet::StateSizeProbe { node } => {
-                        let row_count = self.state.get(node).map(|r| r.rows()).unwrap_or(0);
-                        let mem_size = self.state.get(node).map(|s| s.deep_size_of()).unwrap_or(0);
+                    Packet::ExportRows {
+                        node,
+                        to,
+                        key,
+                        shards,
+                        scheme,
+                        token,
+                    } => {
+                        let written = self.export_rows(node, &to, &key, shards, scheme, token);
                         self.control_reply_tx
-                            .send(ControlReplyPacket::StateSize(row_count, mem_size))
+                            .send(ControlReplyPacket::Checkpointed(written))
                             .unwrap();
                     }
                     Packet::PrepareState { node, state } => {
@@ -867,7 +2239,8 @@ impl Domain {
                         match state {
                             InitialState::PartialLocal(index) => {
                                 if !self.state.contains_key(node) {
-                                    self.state.insert(node, Box::new(MemoryState::default()));
+                                    let s = self.memory_state_for(node);
+                                    self.state.insert(node, Box::new(s));
                                 }
                                 let state = self.state.get_mut(node).unwrap();
                                 for (key, tags) in index {
@@ -878,8 +2251,11 @@ impl Domain {
                                 }
                             }
                             InitialState::IndexedLocal(index) => {
+                                // the other shards had no snapshot, so the state is replayed
+                                self.restored.remove(node);
                                 if !self.state.contains_key(node) {
-                                    self.state.insert(node, Box::new(MemoryState::default()));
+                                    let s = self.memory_state_for(node);
+                                    self.state.insert(node, Box::new(s));
                                 }
                                 let state = self.state.get_mut(node).unwrap();
                                 for idx in index {
@@ -888,6 +2264,13 @@ impl Domain {
                                     state.add_key(&idx[..], None);
                                 }
                             }
+                            InitialState::Restored => {
+                                let state = self
+                                    .restored
+                                    .remove(node)
+                                    .expect("told to use state that was not restored");
+                                self.state.insert(node, state);
+                            }
                             InitialState::PartialGlobal {
                                 gid,
                                 cols,
@@ -895,77 +2278,25 @@ impl Domain {
                                 trigger_domain: (trigger_domain, shards),
                             } => {
                                 use crate::backlog;
-                                let k = key.clone(); // ugh
-                                let txs = (0..shards)
-                                    .map(|shard| {
-                                        let key = key.clone();
-                                        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-                                        let sender = self
-                                            .channel_coordinator
-                                            .builder_for(&(trigger_domain, shard))
-                                            .unwrap()
-                                            .build_async()
-                                            .unwrap();
-
-                                        tokio::spawn(
-                                            self.shutdown_valve
-                                                .wrap(rx)
-                                                .map(move |misses| {
-                                                    Box::new(Packet::RequestReaderReplay {
-                                                        keys: misses,
-                                                        cols: key.clone(),
-                                                        node,
-                                                    })
-                                                })
-                                                .map(Ok)
-                                                .forward(sender)
-                                                .map(|r| {
-                                                    if let Err(e) = r {
-                                                        // domain went away?
-                                                        eprintln!(
-                                                            "replay source went away: {:?}",
-                                                            e
-                                                        );
-                                                    }
-                                                }),
-                                        );
-                                        tx
-                                    })
-                                    .collect::<Vec<_>>();
-                                let (r_part, w_part) = backlog::new_partial(
-                                    cols,
-                                    &k[..],
-                                    move |misses: &mut dyn Iterator<Item = &[DataType]>| {
-                                        let n = txs.len();
-                                        if n == 1 {
-                                            use std::iter::FromIterator;
-                                            let misses = Vec::from_iter(misses.map(Vec::from));
-                                            if misses.is_empty() {
-                                                return true;
-                                            }
-                                            txs[0].send(misses).is_ok()
-                                        } else {
-                                            // TODO: compound reader
-                                            let mut per_shard = HashMap::new();
-                                            for miss in misses {
-                                                assert_eq!(miss.len(), 1);
-                                                let shard = crate::shard_by(&miss[0], n);
-                                                per_shard
-                                                    .entry(shard)
-                                                    .or_insert_with(Vec::new)
-                                                    .push(Vec::from(miss));
-                                            }
-                                            if per_shard.is_empty() {
-                                                return true;
-                                            }
-                                            per_shard
-                                                .into_iter()
-                                                .all(|(shard, keys)| txs[shard].send(keys).is_ok())
-                                        }
-                                    },
-                                );
+                                let trigger =
+                                    self.reader_trigger(node, &key, trigger_domain, shards);
+                                let (mut r_part, mut w_part) =
+                                    backlog::new_partial(cols, &key[..], trigger);
 
                                 let mut n = self.nodes[node].borrow_mut();
+                                if let Some((order, offset, limit)) =
+                                    n.with_reader(|r| r.order().cloned()).unwrap()
+                                {
+                                    if n.merges_shards() {
+                                        // the offset applies once the shards' rows are merged
+                                        r_part.set_order(order, 0, offset + limit);
+                                    } else {
+                                        r_part.set_order(order, offset, limit);
+                                    }
+                                }
+                                let (columns, codec) = self.compression_for(&n);
+                                w_part.compress_columns(columns, codec);
+                                r_part.set_metrics(self.metrics.node(gid));
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
                                         assert!(self
@@ -986,9 +2317,44 @@ impl Domain {
                             }
                             InitialState::Global { gid, cols, key } => {
                                 use crate::backlog;
-                                let (r_part, w_part) = backlog::new(cols, &key[..]);
-
+                                let (range, storage) = self.nodes[node]
+                                    .borrow()
+                                    .with_reader(|r| (r.range().cloned(), r.storage()))
+                                    .unwrap();
+                                let mut disk = None;
+                                let (mut r_part, mut w_part) = match range {
+                                    Some(op) => {
+                                        assert_eq!(key.len(), 1);
+                                        backlog::new_range(cols, key[0], op)
+                                    }
+                                    None if storage == ViewStorage::Disk => {
+                                        // every row is on disk, so this shard fills the keys that
+                                        // miss in the cache itself
+                                        disk = Some(backlog::DiskRows::new(&key[..]));
+                                        let trigger = self.reader_trigger(
+                                            node,
+                                            &key,
+                                            self.index,
+                                            self.nshards,
+                                        );
+                                        backlog::new_partial(cols, &key[..], trigger)
+                                    }
+                                    None => backlog::new(cols, &key[..]),
+                                };
                                 let mut n = self.nodes[node].borrow_mut();
+                                if let Some((order, offset, limit)) =
+                                    n.with_reader(|r| r.order().cloned()).unwrap()
+                                {
+                                    if n.merges_shards() {
+                                        // the offset applies once the shards' rows are merged
+                                        r_part.set_order(order, 0, offset + limit);
+                                    } else {
+                                        r_part.set_order(order, offset, limit);
+                                    }
+                                }
+                                let (columns, codec) = self.compression_for(&n);
+                                w_part.compress_columns(columns, codec);
+                                r_part.set_metrics(self.metrics.node(gid));
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
                                         assert!(self
@@ -1002,7 +2368,10 @@ impl Domain {
                                             .is_none());
 
                                         // make sure Reader is actually prepared to receive state
-                                        r.set_write_handle(w_part)
+                                        r.set_write_handle(w_part);
+                                        if let Some(disk) = disk {
+                                            r.set_disk(disk);
+                                        }
                                     })
                                 })
                                 .unwrap();
@@ -1094,8 +2463,16 @@ impl Domain {
                         mut keys,
                         cols,
                         node,
+                        trace,
                     } => {
+                        let traced =
+                            trace.map(|parent| self.trace_span("upquery", parent, node, None));
+                        let _guard = traced.as_ref().map(|(span, _)| span.enter());
                         self.total_replay_time.start();
+                        let priority = self.nodes[node]
+                            .borrow()
+                            .with_reader(|r| r.priority())
+                            .expect("reader replay requested for non-reader node");
                         // the reader could have raced with us filling in the key after some
                         // *other* reader requested it, so let's double check that it indeed still
                         // misses!
@@ -1122,12 +2499,20 @@ impl Domain {
                                     w.with_key(&*key)
                                         .try_find_and(|_| ())
                                         .expect("reader replay requested for non-ready reader")
-                                        .0
                                         .is_none()
                                 });
                             })
                             .unwrap();
 
+                        // a reader that keeps its rows on disk fills the keys from there
+                        if self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.fill_from_disk(&keys))
+                            .unwrap()
+                        {
+                            keys.clear();
+                        }
+
                         // ensure that we haven't already requested a replay of this key
                         keys.retain(|key| {
                             self.reader_triggered
@@ -1136,7 +2521,8 @@ impl Domain {
                                 .insert(key.clone())
                         });
                         if !keys.is_empty() {
-                            self.find_tags_and_replay(keys, &cols[..], node);
+                            let trace = traced.as_ref().map(|&(_, ctx)| ctx);
+                            self.find_tags_and_replay(keys, &cols[..], node, priority, trace);
                         }
                         self.total_replay_time.stop();
                     }
@@ -1145,7 +2531,17 @@ impl Domain {
                         keys,
                         unishard,
                         requesting_shard,
+                        priority,
+                        trace,
                     } => {
+                        let traced = trace.map(|parent| {
+                            let source = self.replay_paths[&tag].path[0].node;
+                            self.trace_span("upquery", parent, source, Some(tag))
+                        });
+                        let _guard = traced.as_ref().map(|(span, _)| span.enter());
+                        self.set_replay_priority(tag, priority);
+                        // the replay that answers the request is a child of its span
+                        self.set_replay_trace(tag, traced.as_ref().map(|&(_, ctx)| ctx));
                         trace!(
                             self.log,
                            "got replay request";
@@ -1164,7 +2560,7 @@ impl Domain {
                         }
                         self.total_replay_time.stop();
                     }
-                    Packet::StartReplay { tag, from } => {
+                    Packet::StartReplay { tag, from, config } => {
                         use std::thread;
                         assert_eq!(self.replay_paths[&tag].source, Some(from));
 
@@ -1238,6 +2634,12 @@ impl Domain {
                                 .channel_coordinator
                                 .builder_for(&(self.index, self.shard.unwrap_or(0)))
                                 .unwrap();
+                            let mut pacer = Pacer::new(config, self.foreground.clone());
+
+                            // the controller tracks how far along the migration is, so we report
+                            // on the chunking as it goes. the chunker gets its own connection
+                            // since it runs outside of the domain thread.
+                            let progress_addr = self.control_reply_tx.peer_addr().ok();
 
                             thread::Builder::new()
                                 .name(format!(
@@ -1261,16 +2663,31 @@ impl Domain {
                                     let start = time::Instant::now();
                                     debug!(log, "starting state chunker"; "node" => %link.dst);
 
-                                    let iter = state.into_iter().chunks(BATCH_SIZE);
+                                    let mut progress_tx = progress_addr.and_then(|addr| {
+                                        TcpSender::<ControlReplyPacket>::connect(&addr)
+                                            .map_err(|e| {
+                                                warn!(log, "cannot report replay progress";
+                                                      "err" => ?e);
+                                            })
+                                            .ok()
+                                    });
+                                    let mut unreported = (0, 0, state.len());
+                                    let mut last_report = time::Instant::now();
+
+                                    let iter = state.into_iter().chunks(config.batch_size);
                                     let mut iter = iter.into_iter().enumerate().peekable();
 
                                     // process all records in state to completion within domain
                                     // and then forward on tx (if there is one)
                                     while let Some((i, chunk)) = iter.next() {
                                         use std::iter::FromIterator;
-                                        let chunk = Records::from_iter(chunk.map(&fix));
+                                        let chunk = Records::from_iter(chunk.map(&fix).map(|r| {
+                                            unreported.1 += r.deep_size_of();
+                                            r
+                                        }));
                                         let len = chunk.len();
                                         let last = iter.peek().is_none();
+                                        unreported.0 += len;
                                         let p = Box::new(Packet::ReplayPiece {
                                             tag,
                                             link, // to is overwritten by receiver
@@ -1278,11 +2695,28 @@ impl Domain {
                                             data: chunk,
                                         });
 
+                                        // report before sending, so that the report for the last
+                                        // chunk arrives before the replay is acknowledged.
+                                        if last || last_report.elapsed() >= PROGRESS_EVERY {
+                                            let (rows, bytes, total) =
+                                                mem::replace(&mut unreported, (0, 0, 0));
+                                            if let Some(ref mut tx) = progress_tx {
+                                                let _ =
+                                                    tx.send(ControlReplyPacket::ReplayProgress(
+                                                        rows, bytes, total,
+                                                    ));
+                                            }
+                                            last_report = time::Instant::now();
+                                        }
+
                                         trace!(log, "sending batch"; "#" => i, "[]" => len);
                                         if chunked_replay_tx.send(p).is_err() {
                                             warn!(log, "replayer noticed domain shutdown");
                                             break;
                                         }
+                                        if !last {
+                                            pacer.pace();
+                                        }
                                     }
 
                                     debug!(log,
@@ -1317,22 +2751,35 @@ impl Domain {
                                         let base_name = format!(
                                             "{}-{}-{}",
                                             params.log_prefix,
-                                            n.name(),
+                                            base.state_name(n.name()),
                                             self.shard.unwrap_or(0),
                                         );
 
-                                        Box::new(PersistentState::new(
-                                            base_name,
-                                            base.key(),
-                                            &params,
-                                        ))
+                                        let mut s =
+                                            PersistentState::new(base_name, base.key(), &params);
+                                        if params.checkpoint_every.is_some() {
+                                            s.enable_log();
+                                            // downstream state is restored as of the checkpoint,
+                                            // and then sees the writes since once more
+                                            if let Some(checkpoint) = self.restore {
+                                                if s.rewind(checkpoint) {
+                                                    self.rewound.push(node);
+                                                    self.paused_writes
+                                                        .get_or_insert_with(VecDeque::new);
+                                                }
+                                            }
+                                        }
+                                        Box::new(s)
                                     }
-                                    _ => Box::new(MemoryState::default()),
+                                    _ => Box::new(self.memory_state_for(node)),
                                 }
                             };
                             for idx in index {
                                 s.add_key(&idx[..], None);
                             }
+                            if self.nodes[node].borrow().is_base() {
+                                self.import_rows(node, &mut *s);
+                            }
                             assert!(self.state.insert(node, s).is_none());
                         } else {
                             // NOTE: just because index_on is None does *not* mean we're not
@@ -1369,6 +2816,8 @@ impl Domain {
                             total_replay_time: self.total_replay_time.num_nanoseconds(),
                             total_forward_time: self.total_forward_time.num_nanoseconds(),
                             wait_time: self.wait_time.num_nanoseconds(),
+                            queued_packets: self.queued_packets as u64,
+                            backpressure_time: self.backpressure_time.as_nanos() as u64,
                         };
 
                         let node_stats = self
@@ -1425,6 +2874,31 @@ impl Domain {
                                     Default::default()
                                 };
 
+                                let (rows, distinct_keys) = match self.state.get(local_index) {
+                                    _ if n.is_reader() => n
+                                        .with_reader(|r| match (r.key(), r.state_counts()) {
+                                            (Some(key), Some((keys, rows))) => {
+                                                (rows as u64, vec![(key.to_vec(), keys as u64)])
+                                            }
+                                            _ => (0, Vec::new()),
+                                        })
+                                        .unwrap(),
+                                    Some(s) => (
+                                        s.rows() as u64,
+                                        s.distinct_keys()
+                                            .into_iter()
+                                            .map(|(cols, n)| (cols, n as u64))
+                                            .collect(),
+                                    ),
+                                    None => (0, Vec::new()),
+                                };
+
+                                let (hits, misses) = if n.is_reader() {
+                                    self.metrics.node(node_index).lookups()
+                                } else {
+                                    (0, 0)
+                                };
+
                                 if time.is_some() && ptime.is_some() {
                                     Some((
                                         node_index,
@@ -1435,6 +2909,10 @@ impl Domain {
                                             mem_size,
                                             materialized: mat_state,
                                             probe_result,
+                                            rows,
+                                            distinct_keys,
+                                            hits,
+                                            misses,
                                         },
                                     ))
                                 } else {
@@ -1450,6 +2928,73 @@ impl Domain {
                     Packet::UpdateStateSize => {
                         self.update_state_sizes();
                     }
+                    Packet::GetSlowReplays => {
+                        let slow = self.slow_replays.iter().cloned().collect();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::SlowReplays(slow))
+                            .unwrap();
+                    }
+                    Packet::ScanKeys { node, cols } => {
+                        let mut keys: Vec<Vec<DataType>> = match self.state.get(node) {
+                            Some(s) if !s.is_partial() => s
+                                .cloned_records()
+                                .into_iter()
+                                .map(|r| cols.iter().map(|&c| r[c].clone()).collect())
+                                .collect(),
+                            _ => Vec::new(),
+                        };
+                        keys.sort();
+                        keys.dedup();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Keys(keys))
+                            .unwrap();
+                    }
+                    Packet::ScanRows { node } => {
+                        let n = self.nodes[node].borrow();
+                        let rows = match n.get_base() {
+                            Some(b) => {
+                                // rows written before a column was added are stored without it
+                                let mut rows = self
+                                    .state
+                                    .get(node)
+                                    .map(|s| s.cloned_records())
+                                    .unwrap_or_default();
+                                for row in &mut rows {
+                                    b.fix(row);
+                                }
+                                rows
+                            }
+                            None => n
+                                .with_reader(|r| r.rows())
+                                .ok()
+                                .flatten()
+                                .unwrap_or_default(),
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Rows(rows))
+                            .unwrap();
+                    }
+                    Packet::StartProfiling => {
+                        self.profile = Some(Map::default());
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::StopProfiling => {
+                        let profile = self
+                            .profile
+                            .take()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(local, t)| {
+                                let ni = self.nodes[local].borrow().global_addr();
+                                (ni, t.as_nanos() as u64)
+                            })
+                            .collect();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Profile(profile))
+                            .unwrap();
+                    }
                     Packet::Quit => unreachable!("Quit messages are handled by event loop"),
                     Packet::Spin => {
                         // spinning as instructed
@@ -1546,6 +3091,35 @@ impl Domain {
         }
     }
 
+    /// The columns of `n` whose wide text values are kept compressed, and the codec to use.
+    ///
+    /// With state compression on, that is every column; otherwise it is only the columns that
+    /// the node was told to compress.
+    fn compression_for(&self, n: &Node) -> (Vec<usize>, Compression) {
+        match self.state_compression {
+            Some(codec) => ((0..n.fields().len()).collect(), codec),
+            None => (n.compressed_columns().to_vec(), Compression::default()),
+        }
+    }
+
+    /// Empty in-memory state for `node`, configured for how that node is used.
+    fn memory_state_for(&self, node: LocalNodeIndex) -> MemoryState {
+        let n = self.nodes[node].borrow();
+        let mut s = MemoryState::default();
+        let (columns, codec) = self.compression_for(&n);
+        s.compress_columns(columns, codec);
+        if let Some(threshold) = self.join_spill_threshold {
+            let join_input = n.children().iter().any(|&c| {
+                let c = self.nodes[c].borrow();
+                c.is_internal() && c.is_join()
+            });
+            if join_input {
+                s.spill_after(threshold as u64);
+            }
+        }
+        s
+    }
+
     fn seed_row<'a>(&self, source: LocalNodeIndex, row: Cow<'a, [DataType]>) -> Record {
         if let Some(&(start, ref defaults)) = self.ingress_inject.get(source) {
             let mut v = Vec::with_capacity(start + defaults.len());
@@ -1610,6 +3184,9 @@ impl Domain {
                             unishard: single_shard, // if we are the only source, only one path
                             ignore: false,
                             requesting_shard,
+                            trace: self.replay_trace(tag),
+                            path: Vec::new(),
+                            bytes: 0,
                         },
                         data: rs.into(),
                     }))
@@ -1644,6 +3221,7 @@ impl Domain {
                     single_shard,
                     requesting_shard,
                     tag,
+                    self.replay_trace(tag),
                 );
             }
         }
@@ -1738,6 +3316,9 @@ impl Domain {
                             unishard: single_shard, // if we are the only source, only one path
                             ignore: false,
                             requesting_shard,
+                            trace: self.replay_trace(tag),
+                            path: Vec::new(),
+                            bytes: 0,
                         },
                         data,
                     }));
@@ -1764,6 +3345,7 @@ impl Domain {
                 single_shard,
                 requesting_shard,
                 tag,
+                self.replay_trace(tag),
             );
         } else {
             trace!(self.log,
@@ -1780,7 +3362,7 @@ impl Domain {
     }
 
     #[allow(clippy::cognitive_complexity)]
-    fn handle_replay(&mut self, m: Box<Packet>, ex: &mut dyn Executor) {
+    fn handle_replay(&mut self, mut m: Box<Packet>, ex: &mut dyn Executor) {
         let tag = m.tag().unwrap();
         if self.nodes[self.replay_paths[&tag].path.last().unwrap().node]
             .borrow()
@@ -1789,6 +3371,32 @@ impl Domain {
             return;
         }
 
+        // the replays that misses along the way trigger are part of the same trace
+        let traced = m.trace().map(|parent| {
+            let (span, ctx) = self.trace_span("replay", parent, m.dst(), Some(tag));
+            m.set_trace(ctx);
+            (span, ctx)
+        });
+        let _guard = traced.as_ref().map(|(span, _)| span.enter());
+        let trace = traced.as_ref().map(|&(_, ctx)| ctx);
+
+        if self.slow_replay_threshold.is_some() {
+            if let Packet::ReplayPiece {
+                ref data,
+                context:
+                    ReplayPieceContext::Partial {
+                        ref mut path,
+                        ref mut bytes,
+                        ..
+                    },
+                ..
+            } = *m
+            {
+                path.push((self.index.index(), self.shard.unwrap_or(0)));
+                *bytes += data.iter().map(|r| r.deep_size_of()).sum::<u64>();
+            }
+        }
+
         let mut finished = None;
         let mut need_replay = Vec::new();
         let mut finished_partial = 0;
@@ -1999,6 +3607,9 @@ impl Domain {
                             ex,
                             &self.log,
                         );
+                        if n.has_deferred() {
+                            self.deferring.insert(segment.node);
+                        }
 
                         // ignore duplicate misses
                         misses.sort_unstable_by(|a, b| {
@@ -2399,10 +4010,14 @@ impl Domain {
                         ReplayPieceContext::Partial {
                             for_keys,
                             ignore,
-                            unishard: _,
-                            requesting_shard: _,
+                            path,
+                            bytes,
+                            ..
                         } => {
                             assert!(!ignore);
+                            if dst_is_reader || dst_is_target {
+                                self.record_slow_replays(tag, dst, &for_keys, path, bytes);
+                            }
                             if dst_is_reader {
                                 if self.nodes[dst].borrow().beyond_mat_frontier() {
                                     // make sure we eventually evict these from here
@@ -2454,6 +4069,7 @@ impl Domain {
                 single_shard,
                 requesting_shard,
                 tag,
+                trace,
             );
         }
 
@@ -2522,12 +4138,15 @@ impl Domain {
                         requesting_shard,
                     } in replay
                     {
+                        let priority = self.replay_priority(tag);
                         self.delayed_for_self
                             .push_back(Box::new(Packet::RequestPartialReplay {
                                 tag,
                                 unishard,
                                 keys: vec![replay_key],
                                 requesting_shard,
+                                priority,
+                                trace,
                             }));
                     }
                 }
@@ -2627,7 +4246,7 @@ impl Domain {
                 // NOTE: this will only be Some for non-partial replays
                 info!(self.log, "acknowledging replay completed"; "node" => node.id());
                 self.control_reply_tx
-                    .send(ControlReplyPacket::ack())
+                    .send(ControlReplyPacket::Replayed(self.index, node))
                     .unwrap();
             } else {
                 unreachable!()
@@ -2797,6 +4416,7 @@ impl Domain {
                     candidates
                 };
 
+                let mut total_freed = 0;
                 for (node, num_bytes) in nodes {
                     let mut freed = 0u64;
                     let mut n = self.nodes[node].borrow_mut();
@@ -2805,7 +4425,8 @@ impl Domain {
                         if n.is_dropped() {
                             break; // Node was dropped. Give up.
                         } else if n.is_reader() {
-                            let freed_now = n.with_reader_mut(|r| r.evict_random_keys(16)).unwrap();
+                            let remaining = num_bytes as u64 - freed;
+                            let freed_now = n.with_reader_mut(|r| r.evict(remaining)).unwrap();
 
                             freed += freed_now;
                             if n.with_reader(|r| r.is_empty()).unwrap() {
@@ -2845,7 +4466,9 @@ impl Domain {
                     }
                     debug!(self.log, "evicted {} from node {:?}", freed, n);
                     self.state_size.fetch_sub(freed as usize, Ordering::AcqRel);
+                    total_freed += freed;
                 }
+                self.metrics.evicted(total_freed);
             }
             (Packet::EvictKeys {
                 link: Link { dst, .. },
@@ -2913,39 +4536,271 @@ impl Domain {
             .unwrap();
     }
 
+    /// How many packets may wait to be sent to other domains before this domain stops accepting
+    /// input.
+    pub fn max_queued_packets(&self) -> Option<usize> {
+        self.max_queued_packets
+    }
+
+    /// The codec to compress updates and replay pieces sent to other workers with, if any.
+    pub fn packet_compression(&self) -> Option<Compression> {
+        self.packet_compression
+    }
+
+    /// Record how many packets are waiting to be sent to other domains, and how long this domain
+    /// just spent not accepting input because there were too many of them.
+    pub fn record_backlog(&mut self, queued: usize, stalled: time::Duration) {
+        self.queued_packets = queued;
+        self.backpressure_time += stalled;
+    }
+
+    /// Evict from the readers that have an eviction budget or a TTL until they keep to them.
+    pub fn enforce_eviction(&mut self) {
+        for n in self.nodes.values() {
+            let mut n = n.borrow_mut();
+            if n.is_reader() && !n.is_dropped() {
+                n.with_reader_mut(|r| r.enforce_eviction()).unwrap();
+            }
+        }
+    }
+
     pub fn update_state_sizes(&mut self) {
-        let total: u64 = self
-            .nodes
-            .values()
-            .map(|nd| {
-                let n = &*nd.borrow();
-                let local_index = n.local_addr();
-
-                if n.is_reader() {
-                    // We are a reader, which has its own kind of state
-                    let mut size = 0;
-                    n.with_reader(|r| {
-                        if r.is_partial() {
-                            size = r.state_size().unwrap_or(0)
-                        }
-                    })
+        let mut total = 0;
+        let mut sizes = Vec::new();
+        for nd in self.nodes.values() {
+            let n = &*nd.borrow();
+            let local_index = n.local_addr();
+
+            let (size, partial) = if n.is_reader() {
+                // We are a reader, which has its own kind of state
+                let mut size = (0, false);
+                n.with_reader(|r| size = (r.state_size().unwrap_or(0), r.is_partial()))
                     .unwrap();
-                    size
-                } else {
-                    // Not a reader, state is with domain
-                    self.state
-                        .get(local_index)
-                        .filter(|state| state.is_partial())
-                        .map(|s| s.deep_size_of())
-                        .unwrap_or(0)
+                size
+            } else {
+                // Not a reader, state is with domain
+                match self.state.get(local_index) {
+                    Some(s) => (s.deep_size_of(), s.is_partial()),
+                    None => continue,
                 }
-            })
-            .sum();
+            };
+            // only partial state can be evicted, but all of it is reported
+            if partial {
+                total += size;
+            }
+            sizes.push((local_index, size));
+        }
+        for (node, size) in sizes {
+            self.node_metrics(node).set_state_size(size);
+        }
 
         self.state_size.store(total as usize, Ordering::Release);
         // no response sent, as worker will read the atomic
     }
 
+    /// The metrics of the node `node` of this domain.
+    fn node_metrics(&mut self, node: LocalNodeIndex) -> &NodeMetrics {
+        let (metrics, nodes) = (&self.metrics, &self.nodes);
+        self.node_metrics
+            .entry(node)
+            .or_insert_with(|| metrics.node(nodes[node].borrow().global_addr()))
+    }
+
+    /// Checks that every operation in a client write fits the schema of the base table it targets.
+    ///
+    /// Operations that do not are handed to the controller as the table's dead letters, and the
+    /// write is rejected, rather than letting the base node choke on them.
+    fn validate_input(
+        &self,
+        packet: &Packet,
+        executor: &mut dyn Executor,
+    ) -> Result<(), WriteRejection> {
+        if let Packet::Input { ref inner, .. } = *packet {
+            let input = unsafe { inner.deref() };
+            let n = self.nodes[input.dst].borrow();
+            let base = n.get_base().expect("input sent to non-base node");
+            let columns = n.fields().len();
+            let dead: Vec<_> = input
+                .data
+                .iter()
+                .filter_map(|op| {
+                    base.validate(columns, op).err().map(|reason| DeadLetter {
+                        op: op.clone(),
+                        reason,
+                    })
+                })
+                .collect();
+            if !dead.is_empty() {
+                let reason = dead[0].reason.clone();
+                executor.dead_letters(n.name().to_owned(), dead);
+                return Err(WriteRejection::Invalid {
+                    table: n.name().to_owned(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the base table targeted by a client write currently accepts it, given the
+    /// table's read-only status and write policies.
+    ///
+    /// This has to happen before the write is merged with others in the group commit queue, since
+    /// we want to reject only the offending write.
+    fn authorize_input(&self, packet: &Packet) -> Result<(), WriteRejection> {
+        if let Packet::Input { ref inner, .. } = *packet {
+            let input = unsafe { inner.deref() };
+            if input.barrier && input.data.is_empty() {
+                // flush barriers carry no writes, unlike the barriers of atomic writes
+                return Ok(());
+            }
+            let n = self.nodes[input.dst].borrow();
+            let base = n.get_base().expect("input sent to non-base node");
+            if base.is_read_only() {
+                return Err(WriteRejection::ReadOnly {
+                    table: n.name().to_owned(),
+                });
+            }
+            if !base.authorize(input.dst, &input.data, input.writer.as_ref(), &self.state) {
+                return Err(WriteRejection::Unauthorized {
+                    table: n.name().to_owned(),
+                    writer: input.writer.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate values for the auto-increment column of the rows a client write inserts that
+    /// leave it NULL.
+    ///
+    /// This happens before the write is authorized and checked against the table's constraints,
+    /// which then see the generated values.
+    fn generate_ids(&mut self, packet: &mut Packet) -> Vec<DataType> {
+        if let Packet::Input { ref mut inner, .. } = *packet {
+            let input = unsafe { inner.deref_mut() };
+            if input.barrier {
+                return Vec::new();
+            }
+            let mut n = self.nodes[input.dst].borrow_mut();
+            let routed_by = match (n.get_base().and_then(|b| b.shard_key()), n.sharded_by()) {
+                (Some(cols), _) => cols.to_vec(),
+                (None, Sharding::ByColumn(col, _, _)) => vec![col],
+                (None, _) => Vec::new(),
+            };
+            let scheme = n.shard_scheme();
+            let shard = self
+                .shard
+                .map(|s| (&routed_by[..], s, self.nshards, scheme));
+            n.get_base_mut()
+                .expect("input sent to non-base node")
+                .generate_ids(input.dst, &mut input.data, &self.state, shard)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Rejects the commit of an atomic write whose writes a base table has already undone because
+    /// the commit did not arrive in time.
+    fn check_commit(&self, packet: &Packet) -> Result<(), WriteRejection> {
+        if let Packet::Input { ref inner, .. } = *packet {
+            let input = unsafe { inner.deref() };
+            if let Some(TransactionPhase::Commit(id)) = input.txn {
+                if self.expired_transactions.contains_key(&(id, input.dst)) {
+                    return Err(WriteRejection::Expired {
+                        table: self.nodes[input.dst].borrow().name().to_owned(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Charges a client write to its writer's write quota, and rejects it if the quota is used
+    /// up.
+    ///
+    /// Each operation counts once, and flush barriers are free. Every shard of a base table keeps
+    /// its own quota, as does every domain a writer writes to.
+    fn limit_writes(&mut self, packet: &Packet) -> Result<(), WriteRejection> {
+        let rate = match self.write_quota {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        if let Packet::Input {
+            ref inner,
+            src: Some(ref src),
+            ..
+        } = *packet
+        {
+            let input = unsafe { inner.deref() };
+            if input.data.is_empty() {
+                return Ok(());
+            }
+            let writer = match input.writer {
+                Some(ref identity) => Writer::Identity(identity.clone()),
+                None => Writer::Connection(src.token, src.epoch),
+            };
+            if self.write_limiters.len() >= WRITE_LIMITERS_KEPT {
+                // writers with a full bucket are no different from writers never seen before
+                self.write_limiters.retain(|_, l| !l.is_full());
+            }
+            let limiter = self
+                .write_limiters
+                .entry(writer)
+                .or_insert_with(|| RateLimiter::new(rate));
+            if !limiter.try_take(input.data.len()) {
+                return Err(WriteRejection::QuotaExceeded {
+                    table: self.nodes[input.dst].borrow().name().to_owned(),
+                    writer: input.writer.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_constraints(&self, packet: &Packet) -> Result<(), WriteRejection> {
+        if let Packet::Input { ref inner, .. } = *packet {
+            let input = unsafe { inner.deref() };
+            if input.barrier && input.data.is_empty() {
+                return Ok(());
+            }
+            let n = self.nodes[input.dst].borrow();
+            let base = n.get_base().expect("input sent to non-base node");
+            if let Err((constraint, row)) =
+                base.check_constraints(input.dst, &input.data, &self.state)
+            {
+                return Err(WriteRejection::ConstraintViolation {
+                    table: n.name().to_owned(),
+                    constraint,
+                    row,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands a sample of the client writes to mirrored base tables to the worker, which forwards
+    /// them to the table's mirror.
+    fn mirror_input(&self, packet: &Packet, executor: &mut dyn Executor) {
+        if let Packet::Input { ref inner, .. } = *packet {
+            let input = unsafe { inner.deref() };
+            if input.data.is_empty() {
+                return;
+            }
+            let n = self.nodes[input.dst].borrow();
+            let base = n.get_base().expect("input sent to non-base node");
+            if let Some(mirror) = base.mirror() {
+                if rand::random::<f64>() < mirror.fraction {
+                    executor.mirror(
+                        n.name().to_owned(),
+                        mirror.target.clone(),
+                        input.data.clone(),
+                    );
+                }
+            }
+        }
+    }
+
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
         if self.wait_time.is_running() {
             self.wait_time.stop();
@@ -2954,6 +4809,8 @@ impl Domain {
         //self.total_ptime.start();
         let res = match event {
             PollEvent::ResumePolling => {
+                self.flush_coalesced(executor);
+
                 // when do we need to be woken up again?
                 let now = time::Instant::now();
                 let opt1 = self
@@ -2975,37 +4832,88 @@ impl Domain {
                     }
                 });
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                let opt4 = self.frontier_interval.map(|every| {
+                    every
+                        .checked_sub(self.frontier_advanced.elapsed())
+                        .unwrap_or(time::Duration::from_millis(0))
+                });
+
+                let opt5 = if !self.transactions.is_empty() || self.holding {
+                    Some(
+                        TRANSACTION_CHECK_EVERY
+                            .checked_sub(self.transactions_checked.elapsed())
+                            .unwrap_or(time::Duration::from_millis(0)),
+                    )
+                } else {
+                    None
+                };
+
+                let opt6 = if !self.deferring.is_empty() {
+                    Some(
+                        DEFERRED_CHECK_EVERY
+                            .checked_sub(self.deferred_checked.elapsed())
+                            .unwrap_or(time::Duration::from_millis(0)),
+                    )
+                } else {
+                    None
+                };
+
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4).or(opt5).or(opt6);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
+                if let Some(opt5) = opt5 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt5));
+                }
+                if let Some(opt6) = opt6 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt6));
+                }
+                if !self.bulk.is_empty() {
+                    // deferred work is picked up as soon as nothing more urgent is waiting
+                    timeout = Some(time::Duration::from_millis(0));
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
                 if let Packet::Quit = *packet {
                     return ProcessResult::StopPolling;
                 }
-
-                // TODO: Initialize tracer here, and when flushing group commit
-                // queue.
-                if self.group_commit_queues.should_append(&packet, &self.nodes) {
-                    if let Some(packet) = self.group_commit_queues.append(packet) {
-                        self.handle(packet, executor, true);
+                match self.upquery_priority(&packet) {
+                    _ if !self.prioritized => self.process(packet, executor),
+                    Some(priority) if priority > ViewPriority::Low => {
+                        // handling an upquery ahead of the packets that arrived before it is no
+                        // different from it having arrived first
+                        if !self.bulk.is_empty() {
+                            self.overtaken += 1;
+                            if self.overtaken >= OVERTAKE_LIMIT {
+                                self.serve_bulk(1, executor);
+                            }
+                        }
+                        self.process(packet, executor);
                     }
-                } else {
-                    self.handle(packet, executor, true);
-                }
-
-                while let Some(m) = self.group_commit_queues.flush_if_necessary() {
-                    self.handle(m, executor, true);
+                    Some(_) => self.bulk.push_back(packet),
+                    // nothing else may overtake what has been deferred, so it all queues up
+                    None if !self.bulk.is_empty() || is_backfill(&packet) => {
+                        self.bulk.push_back(packet)
+                    }
+                    None => self.process(packet, executor),
                 }
-
+                // a domain that is never idle must still advance its frontiers, and forward the
+                // rows its operators held back
+                self.advance_frontiers(executor);
+                self.forward_deferred(executor);
                 ProcessResult::Processed
             }
             PollEvent::Timeout => {
+                self.flush_coalesced(executor);
+                self.serve_bulk(BULK_BATCH, executor);
+
                 while let Some(m) = self.group_commit_queues.flush_if_necessary() {
                     self.handle(m, executor, true);
                 }
@@ -3014,6 +4922,12 @@ impl Domain {
                     self.handle(Box::new(Packet::Spin), executor, true);
                 }
 
+                self.advance_frontiers(executor);
+                if !self.transactions.is_empty() || self.holding {
+                    self.expire_transactions(executor);
+                }
+                self.forward_deferred(executor);
+
                 ProcessResult::Processed
             }
         };
@@ -3022,4 +4936,132 @@ impl Domain {
         }
         res
     }
+
+    /// The priority of the view that `packet` is an upquery for, if it is one.
+    fn upquery_priority(&self, packet: &Packet) -> Option<ViewPriority> {
+        match *packet {
+            Packet::RequestPartialReplay { priority, .. } => Some(priority),
+            Packet::RequestReaderReplay { node, .. } => self
+                .nodes
+                .get(node)
+                .and_then(|n| n.borrow().with_reader(|r| r.priority()).ok()),
+            _ => None,
+        }
+    }
+
+    /// Handle up to `n` of the packets that were deferred for more urgent upqueries.
+    fn serve_bulk(&mut self, n: usize, executor: &mut dyn Executor) {
+        self.overtaken = 0;
+        for _ in 0..n {
+            match self.bulk.pop_front() {
+                Some(m) => self.process(m, executor),
+                None => break,
+            }
+        }
+    }
+
+    /// Handle a packet that arrived at this domain.
+    ///
+    /// Updates that arrive one after the other along the same link are merged before they are
+    /// handled, so that operators, state and the channels to other domains each deal with one
+    /// larger batch rather than many small ones.
+    fn process(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) {
+        let packet = match self.coalesced.take() {
+            Some(mut held) if held.len() < MAX_COALESCED_RECORDS => match held.absorb(packet) {
+                Ok(()) => {
+                    self.coalesced = Some(held);
+                    return;
+                }
+                Err(packet) => {
+                    self.handle_arrived(held, executor);
+                    packet
+                }
+            },
+            Some(held) => {
+                self.handle_arrived(held, executor);
+                packet
+            }
+            None => packet,
+        };
+        if packet.is_regular() {
+            self.coalesced = Some(packet);
+        } else {
+            self.handle_arrived(packet, executor);
+        }
+    }
+
+    /// Handle the update that later updates were being merged into, if there is one.
+    fn flush_coalesced(&mut self, executor: &mut dyn Executor) {
+        if let Some(m) = self.coalesced.take() {
+            self.handle_arrived(m, executor);
+        }
+    }
+
+    /// Handle a packet as it arrived, or a batch of updates that `process` merged.
+    fn handle_arrived(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) {
+        if self.paused_writes.is_some() && matches!(*packet, Packet::Input { .. }) {
+            self.paused_writes.as_mut().unwrap().push_back(packet);
+            return;
+        }
+
+        // TODO: Initialize tracer here, and when flushing group commit
+        // queue.
+        let mut packet = packet;
+        let valid = self
+            .validate_input(&packet, executor)
+            .and_then(|_| self.check_commit(&packet))
+            .and_then(|_| self.limit_writes(&packet))
+            .map(|_| self.generate_ids(&mut packet))
+            .and_then(|generated| {
+                self.authorize_input(&packet)?;
+                self.check_constraints(&packet)?;
+                Ok(generated)
+            })
+            .map(|generated| {
+                // the generated values go back to the client once the write is applied
+                if let Packet::Input { src: Some(src), .. } = *packet {
+                    if !generated.is_empty() {
+                        self.generated_ids.insert(src, generated);
+                    }
+                }
+            });
+        if let Err(rejection) = valid {
+            debug!(self.log, "rejected write"; "reason" => %rejection);
+            if let Packet::Input { src: Some(src), .. } = *packet {
+                executor.reject(src, rejection);
+            }
+        } else if packet.is_barrier() {
+            // the barrier must follow the writes that are still queued, and may carry writes of
+            // its own if it is part of an atomic write
+            self.mirror_input(&packet, executor);
+            if let Some(m) = self.group_commit_queues.flush(packet.dst()) {
+                self.handle(m, executor, true);
+            }
+            self.handle(packet, executor, true);
+        } else {
+            self.mirror_input(&packet, executor);
+            if self.group_commit_queues.should_append(&packet, &self.nodes) {
+                if let Some(packet) = self.group_commit_queues.append(packet) {
+                    self.handle(packet, executor, true);
+                }
+            } else {
+                self.handle(packet, executor, true);
+            }
+        }
+
+        while let Some(m) = self.group_commit_queues.flush_if_necessary() {
+            self.handle(m, executor, true);
+        }
+    }
+}
+
+/// Whether `packet` is part of a full replay, which backfills a new view or index.
+fn is_backfill(packet: &Packet) -> bool {
+    matches!(
+        *packet,
+        Packet::ReplayPiece {
+            context: ReplayPieceContext::Regular { .. },
+            ..
+        }
+    )
 }
@@ -0,0 +1,114 @@
+//! The rows of base tables on their way from one layout of shards to another.
+//!
+//! When a table is re-sharded, each shard of its old layout writes its rows out, split by the
+//! shard of the new layout they belong to. Each shard of the new layout then loads the rows meant
+//! for it when it first opens its state.
+
+use crate::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn dir_for(dir: &Path, state: &str) -> PathBuf {
+    dir.join("reshard").join(state)
+}
+
+/// Write the rows of shard `from` of the old layout for the shards of the new layout, whose
+/// states are called `to` followed by the index of the shard. `parts` holds the rows of each of
+/// them.
+///
+/// `token` tells the rows of this re-shard from those of an earlier attempt that never finished.
+pub(super) fn export(
+    dir: &Path,
+    to: &str,
+    token: u64,
+    from: usize,
+    parts: Vec<Vec<Vec<DataType>>>,
+) -> io::Result<()> {
+    for (shard, rows) in parts.into_iter().enumerate() {
+        let dir = dir_for(dir, &format!("{}-{}", to, shard));
+        fs::create_dir_all(&dir)?;
+        let data =
+            bincode::serialize(&rows).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let path = dir.join(format!("{}.{}.rows", token, from));
+        // the new shards load whatever they find, so they must never find half a file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(tmp, path)?;
+    }
+    Ok(())
+}
+
+/// The rows that the shards of the old layout wrote for the state called `state`, if it is the
+/// state of a shard of a re-sharded table that has not loaded them yet.
+pub(super) fn import(dir: &Path, state: &str) -> io::Result<Option<Vec<Vec<DataType>>>> {
+    let entries = match fs::read_dir(dir_for(dir, state)) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let parsed = name.to_str().and_then(|name| {
+            let mut parts = name.split('.');
+            let token = parts.next()?.parse::<u64>().ok()?;
+            let _from = parts.next()?.parse::<usize>().ok()?;
+            match parts.next() {
+                Some("rows") => Some(token),
+                _ => None,
+            }
+        });
+        if let Some(token) = parsed {
+            files.push((token, entry.path()));
+        }
+    }
+    let latest = match files.iter().map(|&(token, _)| token).max() {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+
+    let mut rows = Vec::new();
+    for (_, path) in files.into_iter().filter(|&(token, _)| token == latest) {
+        let part: Vec<Vec<DataType>> = bincode::deserialize(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        rows.extend(part);
+    }
+    Ok(Some(rows))
+}
+
+/// Remove the rows written for the state called `state`, once it has loaded them.
+pub(super) fn finish_import(dir: &Path, state: &str) -> io::Result<()> {
+    match fs::remove_dir_all(dir_for(dir, state)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_imports_the_rows_of_the_latest_reshard() {
+        let dir = tempfile::tempdir().unwrap();
+        let row = |n: i32| vec![DataType::from(n)];
+        assert_eq!(import(dir.path(), "soup-t-0").unwrap(), None);
+
+        // an earlier attempt that had more shards to move from
+        export(dir.path(), "soup-t", 1, 2, vec![vec![row(9)], vec![]]).unwrap();
+
+        export(dir.path(), "soup-t", 2, 0, vec![vec![row(1)], vec![row(2)]]).unwrap();
+        export(dir.path(), "soup-t", 2, 1, vec![vec![row(3)], vec![]]).unwrap();
+        let mut rows = import(dir.path(), "soup-t-0").unwrap().unwrap();
+        rows.sort();
+        assert_eq!(rows, vec![row(1), row(3)]);
+        assert_eq!(import(dir.path(), "soup-t-1").unwrap(), Some(vec![row(2)]));
+
+        finish_import(dir.path(), "soup-t-0").unwrap();
+        assert_eq!(import(dir.path(), "soup-t-0").unwrap(), None);
+        assert_eq!(import(dir.path(), "soup-t-1").unwrap(), Some(vec![row(2)]));
+    }
+}
@@ -45,6 +45,16 @@ impl GroupCommitQueueSet {
         }
     }
 
+    /// Merge the packets queued for `node`, if there are any, without waiting for the queue to
+    /// time out.
+    pub fn flush(&mut self, node: LocalNodeIndex) -> Option<Box<Packet>> {
+        if self.pending_packets.contains_key(node) {
+            self.flush_internal(node)
+        } else {
+            None
+        }
+    }
+
     /// Merge any pending packets.
     fn flush_internal(&mut self, node: LocalNodeIndex) -> Option<Box<Packet>> {
         Self::merge_packets(&mut self.pending_packets[node].1)
@@ -93,6 +103,7 @@ impl GroupCommitQueueSet {
         let merged_dst = packets.peek().as_mut().unwrap().dst();
 
         let mut all_senders = vec![];
+        let mut merged_trace = None;
         let merged_data = packets.fold(Vec::new(), |mut acc, p| {
             match *p {
                 Packet::Input {
@@ -100,11 +111,15 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input {
+                        dst, data, trace, ..
+                    } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
                     assert_eq!(merged_dst, dst);
                     acc.extend(data);
+                    // the merged write can only be part of one trace
+                    merged_trace = merged_trace.or(trace);
 
                     if let Some(src) = src {
                         all_senders.push(src);
@@ -119,6 +134,11 @@ impl GroupCommitQueueSet {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                // writes are authorized before they are queued
+                writer: None,
+                barrier: false,
+                trace: merged_trace,
+                txn: None,
             }),
             src: None,
             senders: all_senders,
@@ -8,12 +8,15 @@
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate slog;
 
 pub(crate) mod backlog;
+pub mod metrics;
 pub mod node;
 pub mod ops;
 pub mod payload; // it makes me _really_ sad that this has to be pub
@@ -23,6 +26,7 @@ pub(crate) mod state;
 mod domain;
 mod group_commit;
 mod processing;
+mod quota;
 
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -35,14 +39,20 @@ pub type Readers =
 pub type DomainConfig = domain::Config;
 
 pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
-pub use crate::payload::Packet;
+pub use crate::payload::{Packet, PacketSummary, ReplayConfig, ReplayPacing};
+pub use crate::quota::RateLimiter;
+pub use crate::state::{
+    compact_state, inspect_state, upgrade_state, StateReport, STATE_FORMAT_VERSION,
+};
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Sharding {
     None,
     ForcedNone,
     Random(usize),
-    ByColumn(usize, usize),
+    /// Sharded by the given column into the given number of shards, spreading rows across them
+    /// according to the given scheme.
+    ByColumn(usize, usize, noria::ShardScheme),
 }
 
 impl Sharding {
@@ -56,7 +66,7 @@ impl Sharding {
     pub fn shards(&self) -> Option<usize> {
         match *self {
             Sharding::None | Sharding::ForcedNone => None,
-            Sharding::Random(shards) | Sharding::ByColumn(_, shards) => Some(shards),
+            Sharding::Random(shards) | Sharding::ByColumn(_, shards, _) => Some(shards),
         }
     }
 }
@@ -85,6 +95,12 @@ pub struct PersistenceParameters {
     pub log_dir: Option<PathBuf>,
     /// Number of background threads PersistentState can use (shared acrosss all worker threads).
     pub persistence_threads: i32,
+    /// How often to checkpoint the fully materialized state below the base tables, if at all.
+    ///
+    /// Base tables then log their writes since the last checkpoint, so that recovery can restore
+    /// the checkpointed state and only replay those writes through it.
+    #[serde(default)]
+    pub checkpoint_every: Option<time::Duration>,
 }
 
 impl Default for PersistenceParameters {
@@ -95,6 +111,7 @@ impl Default for PersistenceParameters {
             log_prefix: String::from("soup"),
             log_dir: None,
             persistence_threads: 1,
+            checkpoint_every: None,
         }
     }
 }
@@ -126,6 +143,14 @@ impl PersistenceParameters {
             ..Default::default()
         }
     }
+
+    /// The directory that checkpoints of materialized state are written to.
+    pub fn checkpoint_dir(&self) -> PathBuf {
+        self.log_dir
+            .clone()
+            .unwrap_or_default()
+            .join(format!("{}-checkpoints", self.log_prefix))
+    }
 }
 
 pub use noria::shard_by;
@@ -0,0 +1,286 @@
+//! Counters that domains and readers keep about their work, rendered in the Prometheus text
+//! exposition format.
+//!
+//! Each worker keeps one `Metrics`, and each domain it runs records into the `DomainMetrics` it
+//! got from there. Everything is updated with atomics, so that the worker can render the metrics
+//! without waiting for its domains.
+
+use crate::domain::Index;
+use petgraph::graph::NodeIndex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// The upper bounds, in seconds, of the buckets that replay latencies are counted in.
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// The metrics of the domains running on a worker.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<(usize, usize), Weak<DomainMetrics>>>>);
+
+/// The metrics of a single shard of a domain.
+#[derive(Default)]
+pub struct DomainMetrics {
+    nodes: Mutex<BTreeMap<usize, Arc<NodeMetrics>>>,
+    replays: AtomicU64,
+    replay_latency: Histogram,
+    evictions: AtomicU64,
+    evicted_bytes: AtomicU64,
+}
+
+/// The metrics of a node in a domain.
+#[derive(Debug, Default)]
+pub(crate) struct NodeMetrics {
+    records: AtomicU64,
+    state_size: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_ns: AtomicU64,
+}
+
+impl Metrics {
+    /// Allocate the metrics of shard `shard` of domain `domain`.
+    ///
+    /// They are rendered for as long as the domain holds on to them.
+    pub fn domain(&self, domain: Index, shard: usize) -> Arc<DomainMetrics> {
+        let metrics = Arc::new(DomainMetrics::default());
+        self.0
+            .lock()
+            .unwrap()
+            .insert((domain.index(), shard), Arc::downgrade(&metrics));
+        metrics
+    }
+
+    /// Render the metrics of all the domains that are still running.
+    pub fn render(&self) -> String {
+        let domains: Vec<_> = {
+            let mut domains = self.0.lock().unwrap();
+            domains.retain(|_, d| d.strong_count() > 0);
+            domains
+                .iter()
+                .filter_map(|(&(domain, shard), d)| {
+                    let labels = format!("domain=\"{}\",shard=\"{}\"", domain, shard);
+                    Some((labels, d.upgrade()?))
+                })
+                .collect()
+        };
+        let nodes: Vec<_> = domains
+            .iter()
+            .flat_map(|(labels, d)| {
+                let nodes = d.nodes.lock().unwrap();
+                nodes
+                    .iter()
+                    .map(|(ni, n)| (format!("{},node=\"{}\"", labels, ni), n.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut out = String::new();
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        family(
+            &mut out,
+            "noria_node_records_processed_total",
+            "counter",
+            "Records processed by each node, not counting replays.",
+            nodes.iter().map(|(l, n)| (l, load(&n.records))),
+        );
+        family(
+            &mut out,
+            "noria_node_state_bytes",
+            "gauge",
+            "Bytes of state held by each materialized node.",
+            nodes.iter().map(|(l, n)| (l, load(&n.state_size))),
+        );
+        family(
+            &mut out,
+            "noria_reader_hits_total",
+            "counter",
+            "Keys looked up in each reader that were present.",
+            nodes.iter().map(|(l, n)| (l, load(&n.hits))),
+        );
+        family(
+            &mut out,
+            "noria_reader_misses_total",
+            "counter",
+            "Keys looked up in each reader that had to be replayed.",
+            nodes.iter().map(|(l, n)| (l, load(&n.misses))),
+        );
+        family(
+            &mut out,
+            "noria_domain_replays_total",
+            "counter",
+            "Replay pieces handled by each domain.",
+            domains.iter().map(|(l, d)| (l, load(&d.replays))),
+        );
+        family(
+            &mut out,
+            "noria_domain_evictions_total",
+            "counter",
+            "Evictions handled by each domain.",
+            domains.iter().map(|(l, d)| (l, load(&d.evictions))),
+        );
+        family(
+            &mut out,
+            "noria_domain_evicted_bytes_total",
+            "counter",
+            "Bytes of state freed by evictions in each domain.",
+            domains.iter().map(|(l, d)| (l, load(&d.evicted_bytes))),
+        );
+
+        let name = "noria_domain_replay_seconds";
+        writeln!(
+            out,
+            "# HELP {} Time taken to handle each replay piece.",
+            name
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (labels, d) in &domains {
+            let h = &d.replay_latency;
+            let mut cumulative = 0;
+            for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&h.buckets) {
+                cumulative += load(bucket);
+                writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, bound, cumulative
+                )
+                .unwrap();
+            }
+            let count = load(&h.count);
+            writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, count).unwrap();
+            let sum = load(&h.sum_ns) as f64 / 1e9;
+            writeln!(out, "{}_sum{{{}}} {}", name, labels, sum).unwrap();
+            writeln!(out, "{}_count{{{}}} {}", name, labels, count).unwrap();
+        }
+        out
+    }
+}
+
+/// Render a metric that has a single value for each set of labels.
+pub fn family<L: AsRef<str>>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: impl Iterator<Item = (L, u64)>,
+) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for (labels, value) in values {
+        if labels.as_ref().is_empty() {
+            writeln!(out, "{} {}", name, value).unwrap();
+        } else {
+            writeln!(out, "{}{{{}}} {}", name, labels.as_ref(), value).unwrap();
+        }
+    }
+}
+
+impl DomainMetrics {
+    /// The metrics of the node `ni` of this domain.
+    pub(crate) fn node(&self, ni: NodeIndex) -> Arc<NodeMetrics> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .entry(ni.index())
+            .or_default()
+            .clone()
+    }
+
+    /// Stop rendering the metrics of the node `ni`, which has been removed.
+    pub(crate) fn remove(&self, ni: NodeIndex) {
+        self.nodes.lock().unwrap().remove(&ni.index());
+    }
+
+    /// Record that a replay piece took `took` to handle.
+    pub(crate) fn replayed(&self, took: Duration) {
+        self.replays.fetch_add(1, Ordering::Relaxed);
+        self.replay_latency.observe(took);
+    }
+
+    /// Record an eviction that freed `bytes` of state.
+    pub(crate) fn evicted(&self, bytes: u64) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+        self.evicted_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl NodeMetrics {
+    /// Record that the node processed `records` records.
+    pub(crate) fn processed(&self, records: usize) {
+        self.records.fetch_add(records as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_state_size(&self, bytes: u64) {
+        self.state_size.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Record lookups in the reader, of which `hits` found their key and `misses` did not.
+    pub(crate) fn looked_up(&self, hits: usize, misses: usize) {
+        self.hits.fetch_add(hits as u64, Ordering::Relaxed);
+        self.misses.fetch_add(misses as u64, Ordering::Relaxed);
+    }
+
+    /// The number of keys looked up in the reader so far that were present, and that were not.
+    pub(crate) fn lookups(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl Histogram {
+    fn observe(&self, took: Duration) {
+        let secs = took.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ns
+            .fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_live_domains() {
+        let metrics = Metrics::default();
+        let d = metrics.domain(Index::from(1), 0);
+        d.node(NodeIndex::new(7)).processed(3);
+        d.node(NodeIndex::new(7)).processed(2);
+        d.node(NodeIndex::new(8)).looked_up(4, 1);
+        d.replayed(Duration::from_millis(2));
+        d.replayed(Duration::from_secs(10));
+
+        let out = metrics.render();
+        let has = |name: &str, labels: &str, value: u64| {
+            let line = format!("{}{{domain=\"1\",shard=\"0\"{}}} {}\n", name, labels, value);
+            out.contains(&line)
+        };
+        assert!(out.contains("# TYPE noria_node_records_processed_total counter\n"));
+        assert!(has("noria_node_records_processed_total", ",node=\"7\"", 5));
+        assert!(has("noria_reader_misses_total", ",node=\"8\"", 1));
+        assert!(has(
+            "noria_domain_replay_seconds_bucket",
+            ",le=\"0.005\"",
+            1
+        ));
+        assert!(has("noria_domain_replay_seconds_bucket", ",le=\"+Inf\"", 2));
+        assert!(has("noria_domain_replay_seconds_count", "", 2));
+
+        drop(d);
+        assert!(!metrics.render().contains("domain=\"1\""));
+    }
+}
@@ -26,7 +26,7 @@ impl Node {
     ) -> String {
         let mut s = String::new();
         let border = match self.sharded_by {
-            Sharding::ByColumn(_, _) | Sharding::Random(_) => "filled,dashed",
+            Sharding::ByColumn(..) | Sharding::Random(_) => "filled,dashed",
             _ => {
                 if Self::is_security(self.name()) {
                     "filled,rounded"
@@ -68,9 +68,14 @@ impl Node {
                     ));
                 }
                 NodeType::Internal(ref i) => {
+                    let refresh = match self.refresh_every {
+                        Some(every) => format!(" ⟳ {:?}", every),
+                        None => String::new(),
+                    };
                     s.push_str(&format!(
-                        "[label=\"{}\"]\n",
-                        Self::escape(&i.description(detailed))
+                        "[label=\"{}{}\"]\n",
+                        Self::escape(&i.description(detailed)),
+                        refresh
                     ));
 
                     match materialization_status {
@@ -134,7 +139,16 @@ impl Node {
             };
 
             let sharding = match self.sharded_by {
-                Sharding::ByColumn(k, w) => format!("shard ⚷: {} / {}-way", self.fields[k], w),
+                Sharding::ByColumn(k, w, ShardScheme::Hash) => {
+                    format!("shard ⚷: {} / {}-way", self.fields[k], w)
+                }
+                Sharding::ByColumn(k, w, ShardScheme::Consistent) => {
+                    format!("shard ⚷: {} / {}-way consistent", self.fields[k], w)
+                }
+                Sharding::ByColumn(k, w, ShardScheme::Range { start, end }) => format!(
+                    "shard ⚷: {} / {}-way ranges of [{}, {})",
+                    self.fields[k], w, start, end
+                ),
                 Sharding::Random(_) => "shard randomly".to_owned(),
                 Sharding::None => "unsharded".to_owned(),
                 Sharding::ForcedNone => "desharded to avoid SS".to_owned(),
@@ -204,6 +218,11 @@ impl Node {
                         materialized
                     ));
 
+                    // Periodically refreshed nodes are not maintained incrementally.
+                    if let Some(every) = self.refresh_every {
+                        s.push_str(&format!(" | ⟳ refreshed every {:?}", every));
+                    }
+
                     // Output node outputs. Second row.
                     s.push_str(&format!(" | {}", self.fields().join(", \\n")));
                     s.push_str(&format!(" | {}", sharding));
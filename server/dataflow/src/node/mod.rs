@@ -4,6 +4,7 @@ use crate::prelude::*;
 use petgraph;
 use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
+use std::time;
 
 mod process;
 #[cfg(test)]
@@ -33,7 +34,20 @@ pub struct Node {
 
     pub purge: bool,
 
+    /// How often the controller should re-evaluate this node's output, if ever.
+    refresh_every: Option<time::Duration>,
+
+    /// Whether this node's state is kept in full even if nothing reads from it.
+    pinned: bool,
+
+    /// Columns whose wide text values are kept compressed in this node's state.
+    compressed_columns: Vec<usize>,
+
     sharded_by: Sharding,
+
+    /// How the rows of this node are spread across its shards if it has to be sharded by one of
+    /// its own columns.
+    shard_scheme: ShardScheme,
 }
 
 // constructors
@@ -58,7 +72,15 @@ impl Node {
 
             purge: false,
 
+            refresh_every: None,
+
+            pinned: false,
+
+            compressed_columns: Vec::new(),
+
             sharded_by: Sharding::None,
+
+            shard_scheme: ShardScheme::Hash,
         }
     }
 
@@ -136,6 +158,19 @@ impl Node {
         Ingredient::requires_full_materialization(&**self)
     }
 
+    /// Returns true if this node is an operator that holds back rows to emit later
+    pub fn has_deferred(&self) -> bool {
+        match self.inner {
+            NodeType::Internal(ref i) => i.has_deferred(),
+            _ => false,
+        }
+    }
+
+    /// Returns true if this operator reads all the rows of its parents
+    pub fn requires_materialized_parents(&self) -> bool {
+        Ingredient::requires_materialized_parents(&**self)
+    }
+
     pub fn can_query_through(&self) -> bool {
         Ingredient::can_query_through(&**self)
     }
@@ -163,6 +198,7 @@ impl Node {
     ///    ⋈    |  Join
     ///    ⋉    |  Left join
     ///    ⋃    |  Union
+    ///    ⇝    |  External lookup
     pub fn description(&self, detailed: bool) -> String {
         Ingredient::description(&**self, detailed)
     }
@@ -186,6 +222,66 @@ impl Node {
     pub fn shard_by(&mut self, s: Sharding) {
         self.sharded_by = s;
     }
+
+    /// The scheme that this node spreads its rows across its shards with when the sharding pass
+    /// shards it by one of its columns.
+    ///
+    /// A node that is sharded like its inputs keeps their scheme instead.
+    pub fn shard_scheme(&self) -> ShardScheme {
+        self.shard_scheme
+    }
+
+    /// Spread the rows of this node across its shards with `scheme` when it is sharded by one of
+    /// its columns.
+    pub fn set_shard_scheme(&mut self, scheme: ShardScheme) {
+        self.shard_scheme = scheme;
+    }
+
+    /// How often this node's output is re-evaluated from scratch, if it is periodically refreshed.
+    pub fn refresh_every(&self) -> Option<time::Duration> {
+        self.refresh_every
+    }
+
+    /// Make the controller periodically re-evaluate this node's output, and diff the result into
+    /// the data-flow.
+    ///
+    /// This is for operators whose output can change without any change to their input, and thus
+    /// cannot be maintained purely incrementally. Only query-through operators can be refreshed.
+    pub fn set_refresh_every(&mut self, every: time::Duration) {
+        assert!(
+            self.is_internal() && self.can_query_through(),
+            "only query-through operators can be refreshed"
+        );
+        self.refresh_every = Some(every);
+    }
+
+    /// Whether this node's state is kept in full even if nothing reads from it.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Keep this node's state in full even if nothing reads from it, so that nodes added later can
+    /// use it without a replay.
+    pub fn pin(&mut self) {
+        assert!(self.is_internal(), "only internal nodes can be pinned");
+        self.pinned = true;
+    }
+
+    /// The columns whose wide text values are kept compressed in this node's state.
+    pub fn compressed_columns(&self) -> &[usize] {
+        &self.compressed_columns[..]
+    }
+
+    /// Keep wide text values in the given columns compressed in this node's state.
+    ///
+    /// Values are decompressed when they are read, so this trades CPU for memory.
+    pub fn set_compressed_columns(&mut self, columns: Vec<usize>) {
+        assert!(
+            columns.iter().all(|&c| c < self.fields.len()),
+            "cannot compress non-existing column"
+        );
+        self.compressed_columns = columns;
+    }
 }
 
 // events
@@ -202,6 +298,9 @@ impl Node {
         n.index = self.index;
         n.domain = self.domain;
         n.purge = self.purge;
+        n.refresh_every = self.refresh_every;
+        n.pinned = self.pinned;
+        n.compressed_columns = self.compressed_columns.clone();
         self.taken = true;
 
         DanglingDomainNode(n)
@@ -235,6 +334,17 @@ impl Node {
         }
     }
 
+    pub fn with_topk<'a, F, R>(&'a self, f: F) -> Option<R>
+    where
+        F: FnOnce(&'a ops::topk::TopK) -> R,
+        R: 'a,
+    {
+        match self.inner {
+            NodeType::Internal(NodeOperator::TopK(ref t)) => Some(f(t)),
+            _ => None,
+        }
+    }
+
     pub(crate) fn with_egress_mut<F>(&mut self, f: F)
     where
         F: FnOnce(&mut special::Egress),
@@ -282,6 +392,13 @@ impl Node {
             _ => HashMap::new(),
         }
     }
+
+    pub fn suggest_secondary_indexes(&self) -> Vec<(NodeIndex, Vec<usize>)> {
+        match self.inner {
+            NodeType::Internal(ref i) => i.suggest_secondary_indexes(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl Deref for Node {
@@ -473,6 +590,32 @@ impl Node {
         }
     }
 
+    pub fn is_project(&self) -> bool {
+        if let NodeType::Internal(NodeOperator::Project(_)) = self.inner {
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_topk(&self) -> bool {
+        if let NodeType::Internal(NodeOperator::TopK(_)) = self.inner {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether this is a reader that isn't sharded by its key, so that every shard may hold
+    /// records for any key, and lookups must merge the records of all the shards.
+    pub fn merges_shards(&self) -> bool {
+        self.with_reader(|r| match (self.sharded_by, r.key()) {
+            (Sharding::ByColumn(c, _, _), Some(key)) => key[r.shard_key_index()] != c,
+            (sharding, _) => !sharding.is_none(),
+        })
+        .unwrap_or(false)
+    }
+
     pub fn is_shard_merger(&self) -> bool {
         if let NodeType::Internal(NodeOperator::Union(ref u)) = self.inner {
             u.is_shard_merger()
@@ -32,11 +32,20 @@ impl Node {
             NodeType::Base(ref mut b) => {
                 // NOTE: bases only accept BaseOperations
                 match m.take().map(|p| *p) {
-                    Some(Packet::Input {
-                        inner, mut senders, ..
-                    }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
+                    Some(Packet::Input { inner, .. }) => {
+                        let Input {
+                            dst,
+                            data,
+                            trace,
+                            txn,
+                            ..
+                        } = unsafe { inner.take() };
                         let mut rs = b.process(addr, data, &*state);
+                        b.observe_event_times(&rs);
+                        let dead = b.take_dead_letters();
+                        if !dead.is_empty() {
+                            ex.dead_letters(self.name.clone(), dead);
+                        }
 
                         // When a replay originates at a base node, we replay the data *through* that
                         // same base node because its column set may have changed. However, this replay
@@ -49,13 +58,17 @@ impl Node {
                             materialize(&mut rs, None, state.get_mut(addr));
                         }
 
-                        // Send write-ACKs to all the clients with updates that made
-                        // it into this merged packet:
-                        senders.drain(..).for_each(|src| ex.ack(src));
-
+                        // the domain acknowledges the writes that made it into this merged
+                        // packet once it has numbered them
                         *m = Some(Box::new(Packet::Message {
                             link: Link::new(dst, dst),
                             data: rs,
+                            watermarks: Default::default(),
+                            barrier: None,
+                            event_times: Default::default(),
+                            frontier: Default::default(),
+                            trace,
+                            txn,
                         }));
                     }
                     Some(ref p) => {
@@ -66,7 +79,7 @@ impl Node {
                 }
             }
             NodeType::Reader(ref mut r) => {
-                r.process(m, swap);
+                r.process(m, swap, &self.name, ex);
             }
             NodeType::Egress(None) => unreachable!(),
             NodeType::Egress(Some(ref mut e)) => {
@@ -101,6 +114,7 @@ impl Node {
                                     requesting_shard,
                                     unishard,
                                     ignore,
+                                    ..
                                 },
                             ..
                         } => {
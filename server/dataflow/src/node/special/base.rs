@@ -1,10 +1,19 @@
+use crate::node::special::{RowTtl, TableConstraint, WritePolicy};
 use crate::prelude::*;
-use noria::{Modification, Operation, TableOperation};
+use noria::{Comparison, Condition, DeadLetter, Mirror, Modification, Operation, TableOperation};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use vec_map::VecMap;
 
+/// How many candidate values a shard tries for each shard there is before it stops looking for an
+/// auto-increment value that routes to it.
+///
+/// A hashing scheme routes about one in every `shards` values to each shard, so the search only
+/// gives up when no value ahead routes to the shard, as under a range scheme once the shard's
+/// range is used up.
+const ID_PROBES_PER_SHARD: usize = 64;
+
 /// Base is used to represent the root nodes of the Noria data flow graph.
 ///
 /// These nodes perform no computation, and their job is merely to persist all received updates and
@@ -13,10 +22,43 @@ use vec_map::VecMap;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Base {
     primary_key: Option<Vec<usize>>,
+    #[serde(default)]
+    shard_key: Option<Vec<usize>>,
+    /// The number of shards of this base node, if not the number that the deployment shards
+    /// nodes into.
+    #[serde(default)]
+    shards: Option<usize>,
+    /// Additional indexes kept on this base node's state, besides the one on its primary key.
+    #[serde(default)]
+    indexes: Vec<Vec<usize>>,
+    /// The column whose values this base node generates for inserted rows that leave it NULL.
+    #[serde(default)]
+    auto_increment: Option<usize>,
+    /// The smallest value that may be generated next, once it has been found from the state.
+    #[serde(skip)]
+    next_id: Option<i128>,
 
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
     unmodified: bool,
+
+    write_policies: Vec<WritePolicy>,
+    read_only: bool,
+    mirror: Option<Mirror>,
+    #[serde(default)]
+    constraints: Vec<TableConstraint>,
+
+    #[serde(default)]
+    event_time: Option<usize>,
+    /// The latest event time of the records this base node has processed.
+    #[serde(default)]
+    latest_event_time: Option<i64>,
+    /// How long rows are kept before they are deleted, going by their timestamp.
+    #[serde(default)]
+    ttl: Option<RowTtl>,
+    /// The operations that `process` could not apply to the rows they target.
+    #[serde(skip)]
+    dead_letters: Vec<DeadLetter>,
 }
 
 impl Base {
@@ -37,6 +79,94 @@ impl Base {
         self.primary_key.as_ref().map(|cols| &cols[..])
     }
 
+    /// Builder with the columns to shard this base node by, rather than by its primary key.
+    ///
+    /// The shard key must be part of the primary key, if there is one, so that all the rows with
+    /// a given primary key are on the same shard. Rows are routed by the values of all the shard
+    /// key columns together, as decided by the installed `noria::ShardHasher`.
+    pub fn with_shard_key(mut self, shard_key: Vec<usize>) -> Base {
+        assert!(
+            !shard_key.is_empty(),
+            "shard key must have at least one column"
+        );
+        self.shard_key = Some(shard_key);
+        self
+    }
+
+    pub fn shard_key(&self) -> Option<&[usize]> {
+        self.shard_key.as_ref().map(|cols| &cols[..])
+    }
+
+    /// Shard this base node by `shard_key` into `shards` shards, or into as many shards as the
+    /// deployment shards nodes into if not given.
+    ///
+    /// See `with_shard_key`. The layout can only be set before the node is added to the graph.
+    pub fn set_shard_key(&mut self, shard_key: Vec<usize>, shards: Option<usize>) {
+        assert!(
+            !shard_key.is_empty(),
+            "shard key must have at least one column"
+        );
+        assert_ne!(shards, Some(0), "base node must have at least one shard");
+        self.shard_key = Some(shard_key);
+        self.shards = shards;
+    }
+
+    /// The number of shards of this base node, if it differs from the deployment's.
+    pub fn shards(&self) -> Option<usize> {
+        self.shards
+    }
+
+    /// The name that the state of the shards of this base node, whose table is called `table`,
+    /// is kept under.
+    ///
+    /// Each layout of a table keeps its rows under a name of its own, so that re-sharding a table
+    /// never opens the rows of one layout as those of another.
+    pub fn state_name(&self, table: &str) -> String {
+        match (&self.shard_key, self.shards) {
+            (None, None) => table.to_owned(),
+            (key, shards) => {
+                let mut name = table.to_owned();
+                for col in key.iter().flatten() {
+                    name.push_str(&format!(".{}", col));
+                }
+                if let Some(shards) = shards {
+                    name.push_str(&format!("x{}", shards));
+                }
+                name
+            }
+        }
+    }
+
+    /// Builder with an additional index on the given columns.
+    ///
+    /// The index is kept alongside the primary key in the base node's state, which for durable
+    /// bases means as its own RocksDB column family. Deletes and updates that select rows by
+    /// equality on all of its columns, and replays keyed on those columns, then use it rather than
+    /// scanning every row.
+    pub fn with_index(mut self, columns: Vec<usize>) -> Base {
+        assert!(!columns.is_empty(), "index must have at least one column");
+        if !self.indexes.contains(&columns) {
+            self.indexes.push(columns);
+        }
+        self
+    }
+
+    /// The additional indexes of this base node.
+    pub fn indexes(&self) -> &[Vec<usize>] {
+        &self.indexes[..]
+    }
+
+    /// Builder with a column whose values are generated for inserted rows that leave it NULL,
+    /// like an `AUTO_INCREMENT` column in MySQL.
+    pub fn with_auto_increment(mut self, column: usize) -> Base {
+        self.auto_increment = Some(column);
+        self
+    }
+
+    pub fn auto_increment(&self) -> Option<usize> {
+        self.auto_increment
+    }
+
     /// Add a new column to this base node.
     pub fn add_column(&mut self, default: DataType) -> usize {
         assert!(
@@ -72,6 +202,438 @@ impl Base {
             .collect()
     }
 
+    /// Replace the policies that writes to this base node are checked against.
+    ///
+    /// An empty set of policies permits all writes.
+    pub fn set_write_policies(&mut self, policies: Vec<WritePolicy>) {
+        self.write_policies = policies;
+    }
+
+    /// Replace the constraints that the rows of this base node are checked against.
+    pub fn set_constraints(&mut self, constraints: Vec<TableConstraint>) {
+        self.constraints = constraints;
+    }
+
+    /// Make this base node reject (or stop rejecting) all writes.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Forward a sample of the client writes to this base node as `mirror` describes, or stop
+    /// mirroring writes if `None`.
+    pub fn set_mirror(&mut self, mirror: Option<Mirror>) {
+        self.mirror = mirror;
+    }
+
+    /// Where a sample of the client writes to this base node should be forwarded, and how many.
+    pub fn mirror(&self) -> Option<&Mirror> {
+        self.mirror.as_ref()
+    }
+
+    /// Use the given column as the event time of this base node's records, or stop tracking event
+    /// time if `None`.
+    pub fn set_event_time(&mut self, column: Option<usize>) {
+        if self.event_time != column {
+            self.latest_event_time = None;
+        }
+        self.event_time = column;
+    }
+
+    /// The column that holds the event time of this base node's records, if it has one.
+    pub fn event_time(&self) -> Option<usize> {
+        self.event_time
+    }
+
+    /// The latest event time of the records this base node has processed, in milliseconds since
+    /// the Unix epoch.
+    pub fn latest_event_time(&self) -> Option<i64> {
+        self.latest_event_time
+    }
+
+    /// Delete rows once the timestamp in their `ttl.column` is older than `ttl.ttl`, or stop
+    /// deleting them if `None`.
+    ///
+    /// Only base nodes with a primary key can delete rows.
+    pub fn set_ttl(&mut self, ttl: Option<RowTtl>) {
+        assert!(
+            ttl.is_none() || self.primary_key.is_some(),
+            "base nodes without a primary key cannot expire rows"
+        );
+        self.ttl = ttl;
+    }
+
+    pub fn ttl(&self) -> Option<RowTtl> {
+        self.ttl
+    }
+
+    /// Deletes of the rows of this base node that have outlived its row TTL.
+    pub(crate) fn expired(&self, us: LocalNodeIndex, state: &StateMap) -> Vec<TableOperation> {
+        let (ttl, db) = match (self.ttl, state.get(us)) {
+            (Some(ttl), Some(db)) => (ttl, db),
+            _ => return Vec::new(),
+        };
+        let cutoff = ttl.cutoff();
+        db.cloned_records()
+            .into_iter()
+            .filter_map(|mut row| {
+                // rows from before the column was added don't have it yet
+                self.fix(&mut row);
+                if ttl.expired(&row, cutoff) {
+                    Some(TableOperation::Delete {
+                        key: self.key_of_row(&row),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Advance the latest event time past that of any new records in `rs`.
+    pub(in crate::node) fn observe_event_times(&mut self, rs: &Records) {
+        let col = match self.event_time {
+            Some(col) => col,
+            None => return,
+        };
+        let latest = rs
+            .iter()
+            .filter(|r| r.is_positive())
+            .filter_map(|r| r.get(col).and_then(event_time))
+            .max();
+        if latest > self.latest_event_time {
+            self.latest_event_time = latest;
+        }
+    }
+
+    /// Returns true if `writer` may perform all of `ops`.
+    ///
+    /// Inserted rows are checked as given. Updates and deletes are checked against the row they
+    /// would modify, so that writers cannot change rows they would not have been allowed to write
+    /// in the first place, and updates are also checked against the row they produce, so that
+    /// writers cannot turn their rows into ones they would not have been allowed to write.
+    pub(crate) fn authorize(
+        &self,
+        us: LocalNodeIndex,
+        ops: &[TableOperation],
+        writer: Option<&DataType>,
+        state: &StateMap,
+    ) -> bool {
+        if self.write_policies.is_empty() {
+            return true;
+        }
+
+        let permits = |row: &[DataType]| self.write_policies.iter().any(|p| p.permits(row, writer));
+        let existing = |key: &[DataType]| {
+            let key_cols = &self.primary_key.as_ref()?[..];
+            let db = state
+                .get(us)
+                .expect("base with primary key must be materialized");
+            match db.lookup(key_cols, &KeyType::from(key)) {
+                LookupResult::Some(rows) => rows.into_iter().next().map(Cow::into_owned),
+                LookupResult::Missing => unreachable!(),
+            }
+        };
+
+        let before = ops.iter().all(|op| match *op {
+            TableOperation::Insert(ref row) => permits(row),
+            TableOperation::InsertOrUpdate { ref row, .. } => {
+                permits(row) && existing(&self.key_of_row(row)).map_or(true, |r| permits(&r))
+            }
+            TableOperation::Delete { ref key } | TableOperation::Update { ref key, .. } => {
+                existing(key).map_or(true, |r| permits(&r))
+            }
+            TableOperation::DeleteWhere { ref conditions }
+            | TableOperation::UpdateWhere { ref conditions, .. } => self
+                .matching(us, conditions, state)
+                .iter()
+                .all(|r| permits(&r[..])),
+        });
+        if !before {
+            return false;
+        }
+
+        // like constraints, the rows the operations produce are found by applying them to a copy
+        let rs = self.clone().process(us, ops.to_vec(), state);
+        rs.iter()
+            .filter(|r| r.is_positive())
+            .all(|r| permits(r.rec()))
+    }
+
+    /// Fill in the auto-increment column of the rows inserted by `ops` that leave it NULL, and
+    /// return the generated values in the order of their rows.
+    ///
+    /// If this base node is sharded, `shard` holds the columns rows are routed to shards by, this
+    /// shard, the number of shards, and the scheme rows are routed with. Shards only generate
+    /// values of their own, so that no two shards generate the same value: values that route to
+    /// the shard if the column is among the columns rows are routed by, and values that are the
+    /// shard modulo the number of shards otherwise. If no value ahead routes to the shard, it
+    /// falls back to the latter. Values given explicitly move the next generated value past them,
+    /// as in MySQL.
+    pub(crate) fn generate_ids(
+        &mut self,
+        us: LocalNodeIndex,
+        ops: &mut [TableOperation],
+        state: &StateMap,
+        shard: Option<(&[usize], usize, usize, ShardScheme)>,
+    ) -> Vec<DataType> {
+        let col = match self.auto_increment {
+            Some(col) => col,
+            None => return Vec::new(),
+        };
+
+        let mut next = match self.next_id {
+            Some(next) => next,
+            None => state
+                .get(us)
+                .map(|db| db.cloned_records())
+                .unwrap_or_default()
+                .iter()
+                .filter(|row| col < row.len() && is_integer(&row[col]))
+                .map(|row| i128::from(&row[col]) + 1)
+                .max()
+                .unwrap_or(1),
+        };
+        let mut generated = Vec::new();
+        // whether no value ahead routes to this shard
+        let mut exhausted = false;
+        for op in ops {
+            let row = match *op {
+                TableOperation::Insert(ref mut row)
+                | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
+                _ => continue,
+            };
+            if col >= row.len() {
+                // the row is from before the column was added
+                continue;
+            }
+            if !row[col].is_none() {
+                if is_integer(&row[col]) {
+                    next = std::cmp::max(next, i128::from(&row[col]) + 1);
+                }
+                continue;
+            }
+
+            let mut probes = 0;
+            loop {
+                row[col] = DataType::from(next);
+                let ours = match shard {
+                    None => true,
+                    Some((cols, shard, shards, scheme)) if cols.contains(&col) && !exhausted => {
+                        exhausted = probes >= ID_PROBES_PER_SHARD * shards;
+                        let key: Vec<_> = cols.iter().map(|&c| row[c].clone()).collect();
+                        !exhausted && scheme.shard(&key, shards) == shard
+                    }
+                    Some((_, shard, shards, _)) => next.rem_euclid(shards as i128) == shard as i128,
+                };
+                next += 1;
+                probes += 1;
+                if ours {
+                    break;
+                }
+            }
+            generated.push(row[col].clone());
+        }
+        self.next_id = Some(next);
+        generated
+    }
+
+    /// Checks that the rows `ops` would leave behind satisfy this base node's constraints, and
+    /// returns the name of the first violated constraint and the offending row otherwise.
+    ///
+    /// Updates are checked against the row they produce, so that an update cannot take an
+    /// existing row out of its constraints either.
+    pub(crate) fn check_constraints(
+        &self,
+        us: LocalNodeIndex,
+        ops: &[TableOperation],
+        state: &StateMap,
+    ) -> Result<(), (String, Vec<DataType>)> {
+        if self.constraints.is_empty() {
+            return Ok(());
+        }
+
+        // applying the operations leaves the state as it is, so we can find out what they would
+        // write by doing so on a copy
+        let rs = self.clone().process(us, ops.to_vec(), state);
+        for r in rs.iter().filter(|r| r.is_positive()) {
+            if let Some(c) = self.constraints.iter().find(|c| !c.holds(r.rec())) {
+                return Err((c.name().to_owned(), r.rec().to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `op` fits this base node, which has `columns` columns, and returns why it does
+    /// not otherwise.
+    ///
+    /// Clients check the shape of their writes before sending them, but an out-of-date or
+    /// misbehaving client could still send writes that `process` cannot apply.
+    pub(crate) fn validate(&self, columns: usize, op: &TableOperation) -> Result<(), String> {
+        let check_row = |row: &[DataType]| {
+            // writes from before a column was added are filled in with its default value
+            let len = if self.unmodified {
+                row.len()
+            } else {
+                std::cmp::max(row.len(), self.defaults.len())
+            };
+            if len == columns {
+                Ok(())
+            } else {
+                Err(format!("expected {} columns, got {}", columns, row.len()))
+            }
+        };
+        let check_key = |key: &[DataType]| match self.primary_key {
+            None => Err(String::from("table has no primary key")),
+            Some(ref cols) if cols.len() != key.len() => Err(format!(
+                "expected {} key columns, got {}",
+                cols.len(),
+                key.len()
+            )),
+            Some(_) => Ok(()),
+        };
+        let check_update = |update: &[Modification]| {
+            if update.len() > columns {
+                return Err(format!(
+                    "expected at most {} modified columns, got {}",
+                    columns,
+                    update.len()
+                ));
+            }
+            for m in update {
+                if let Modification::Apply(_, ref v) = *m {
+                    if !is_integer(v) {
+                        return Err(format!("cannot add or subtract non-integer {}", v));
+                    }
+                }
+            }
+            Ok(())
+        };
+
+        match *op {
+            TableOperation::Insert(ref row) => check_row(row),
+            TableOperation::Delete { ref key } => check_key(key),
+            TableOperation::Update { ref key, ref set } => {
+                check_key(key)?;
+                check_update(set)
+            }
+            TableOperation::InsertOrUpdate {
+                ref row,
+                ref update,
+            } => {
+                if self.primary_key.is_none() {
+                    return Err(String::from("table has no primary key"));
+                }
+                check_row(row)?;
+                check_update(update)
+            }
+            TableOperation::DeleteWhere { ref conditions }
+            | TableOperation::UpdateWhere { ref conditions, .. } => {
+                if self.primary_key.is_none() {
+                    return Err(String::from("table has no primary key"));
+                }
+                if let Some(c) = conditions.iter().find(|c| c.column >= columns) {
+                    return Err(format!(
+                        "condition on column {}, but there are only {} columns",
+                        c.column, columns
+                    ));
+                }
+                match *op {
+                    TableOperation::UpdateWhere { ref set, .. } => check_update(set),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// The rows of this base node that satisfy all of `conditions`.
+    fn matching(
+        &self,
+        us: LocalNodeIndex,
+        conditions: &[Condition],
+        state: &StateMap,
+    ) -> Vec<Vec<DataType>> {
+        let db = state
+            .get(us)
+            .expect("base with primary key must be materialized");
+
+        // if the conditions fix all the columns of an index, only the rows it points to can match
+        let equal_to = |col: usize| {
+            conditions
+                .iter()
+                .find(|c| c.column == col && c.comparison == Comparison::Equal)
+                .map(|c| c.value.clone())
+        };
+        let indexed = self
+            .primary_key
+            .iter()
+            .chain(self.indexes.iter())
+            .find_map(|cols| {
+                let key: Option<Vec<_>> = cols.iter().map(|&c| equal_to(c)).collect();
+                key.map(|key| (cols, key))
+            });
+        let rows = match indexed {
+            Some((cols, key)) => match db.lookup(cols, &KeyType::from(&key[..])) {
+                LookupResult::Some(rows) => rows.into_iter().map(Cow::into_owned).collect(),
+                LookupResult::Missing => unreachable!(),
+            },
+            None => db.cloned_records(),
+        };
+        rows.into_iter()
+            .filter_map(|mut row| {
+                // rows from before a column was added don't have it yet
+                self.fix(&mut row);
+                if conditions.iter().all(|c| c.matches(&row)) {
+                    Some(row)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the deletes and updates in `ops` that select rows by conditions with deletes and
+    /// updates of the keys of the rows that match them.
+    fn resolve_conditions(
+        &self,
+        us: LocalNodeIndex,
+        ops: Vec<TableOperation>,
+        state: &StateMap,
+    ) -> Vec<TableOperation> {
+        let mut resolved = Vec::with_capacity(ops.len());
+        for op in ops {
+            match op {
+                TableOperation::DeleteWhere { conditions } => resolved.extend(
+                    self.matching(us, &conditions, state)
+                        .into_iter()
+                        .map(|row| TableOperation::Delete {
+                            key: self.key_of_row(&row),
+                        }),
+                ),
+                TableOperation::UpdateWhere { conditions, set } => resolved.extend(
+                    self.matching(us, &conditions, state)
+                        .into_iter()
+                        .map(|row| TableOperation::Update {
+                            key: self.key_of_row(&row),
+                            set: set.clone(),
+                        }),
+                ),
+                op => resolved.push(op),
+            }
+        }
+        resolved
+    }
+
+    fn key_of_row(&self, row: &[DataType]) -> Vec<DataType> {
+        match self.primary_key {
+            Some(ref key_cols) => key_cols.iter().map(|&c| row[c].clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub(crate) fn fix(&self, row: &mut Vec<DataType>) {
         if self.unmodified {
             return;
@@ -91,10 +653,25 @@ impl Clone for Base {
     fn clone(&self) -> Base {
         Base {
             primary_key: self.primary_key.clone(),
+            shard_key: self.shard_key.clone(),
+            shards: self.shards,
+            indexes: self.indexes.clone(),
+            auto_increment: self.auto_increment,
+            next_id: self.next_id,
 
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
             unmodified: self.unmodified,
+
+            write_policies: self.write_policies.clone(),
+            read_only: self.read_only,
+            mirror: self.mirror.clone(),
+            constraints: self.constraints.clone(),
+
+            event_time: self.event_time,
+            latest_event_time: self.latest_event_time,
+            ttl: self.ttl,
+            dead_letters: Vec::new(),
         }
     }
 }
@@ -103,20 +680,63 @@ impl Default for Base {
     fn default() -> Self {
         Base {
             primary_key: None,
+            shard_key: None,
+            shards: None,
+            indexes: Vec::new(),
+            auto_increment: None,
+            next_id: None,
 
             defaults: Vec::new(),
             dropped: Vec::new(),
             unmodified: true,
+
+            write_policies: Vec::new(),
+            read_only: false,
+            mirror: None,
+            constraints: Vec::new(),
+
+            event_time: None,
+            latest_event_time: None,
+            ttl: None,
+            dead_letters: Vec::new(),
         }
     }
 }
 
+fn is_integer(v: &DataType) -> bool {
+    match *v {
+        DataType::Int(_)
+        | DataType::UnsignedInt(_)
+        | DataType::BigInt(_)
+        | DataType::UnsignedBigInt(_) => true,
+        _ => false,
+    }
+}
+
+/// The event time that `v` denotes, in milliseconds since the Unix epoch.
+///
+/// Integer event-time columns are taken to hold milliseconds since the epoch already.
+pub(super) fn event_time(v: &DataType) -> Option<i64> {
+    use std::convert::TryFrom;
+    match *v {
+        DataType::Timestamp(ts) => Some(ts.timestamp_millis()),
+        DataType::Int(n) => Some(i64::from(n)),
+        DataType::UnsignedInt(n) => Some(i64::from(n)),
+        DataType::BigInt(n) => Some(n),
+        DataType::UnsignedBigInt(n) => i64::try_from(n).ok(),
+        _ => None,
+    }
+}
+
 fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
     match *r {
         TableOperation::Insert(ref row) => &row[col],
         TableOperation::Delete { ref key } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
+        TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+            unreachable!("conditions are resolved to keys before operations are sorted")
+        }
     }
 }
 
@@ -138,6 +758,9 @@ impl Base {
         mut ops: Vec<TableOperation>,
         state: &StateMap,
     ) -> Records {
+        if self.primary_key.is_some() {
+            ops = self.resolve_conditions(us, ops, state);
+        }
         if self.primary_key.is_none() || ops.is_empty() {
             return ops
                 .into_iter()
@@ -226,6 +849,9 @@ impl Base {
                     }
                     update
                 }
+                TableOperation::DeleteWhere { .. } | TableOperation::UpdateWhere { .. } => {
+                    unreachable!("conditions were resolved to keys above")
+                }
             };
 
             if current.is_none() {
@@ -234,7 +860,26 @@ impl Base {
                 continue;
             }
 
-            let mut future = current.unwrap().into_owned();
+            // `validate` only sees the written values, not the ones they are applied to, so an
+            // update that adds to a column without an integer in it is dropped here as a whole
+            let row = current.unwrap();
+            if let Some((_, v)) = update
+                .iter()
+                .zip(row.iter())
+                .find(|(m, v)| matches!(m, Modification::Apply(..)) && !is_integer(v))
+            {
+                self.dead_letters.push(DeadLetter {
+                    reason: format!("cannot add to or subtract from non-integer {}", v),
+                    op: TableOperation::Update {
+                        key: this_key.clone(),
+                        set: update,
+                    },
+                });
+                current = Some(row);
+                continue;
+            }
+
+            let mut future = row.into_owned();
             for (col, op) in update.into_iter().enumerate() {
                 // XXX: make sure user doesn't update primary key?
                 match op {
@@ -270,6 +915,12 @@ impl Base {
         results.into()
     }
 
+    /// The operations that `process` dropped because they could not be applied, which belong in
+    /// the table's dead letters.
+    pub(in crate::node) fn take_dead_letters(&mut self) -> Vec<DeadLetter> {
+        std::mem::replace(&mut self.dead_letters, Vec::new())
+    }
+
     pub(in crate::node) fn suggest_indexes(&self, n: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         if self.primary_key.is_some() {
             Some((n, self.primary_key.as_ref().unwrap().clone()))
@@ -284,6 +935,8 @@ impl Base {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::node::special::PolicyValue;
+    use nom_sql::Operator;
 
     #[test]
     fn it_works_default() {
@@ -307,6 +960,20 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
+    #[test]
+    fn it_names_state_by_layout() {
+        let b = Base::new(vec![]).with_key(vec![0, 1]);
+        assert_eq!(b.state_name("t"), "t");
+
+        let mut by_one = b.clone();
+        by_one.set_shard_key(vec![1], None);
+        assert_eq!(by_one.state_name("t"), "t.1");
+
+        let mut by_both = b.clone();
+        by_both.set_shard_key(vec![0, 1], Some(8));
+        assert_eq!(by_both.state_name("t"), "t.0.1x8");
+    }
+
     fn test_lots_of_changes_in_same_batch(mut state: Box<dyn State>) {
         use crate::node;
         use crate::prelude::*;
@@ -417,4 +1084,107 @@ mod tests {
 
         test_lots_of_changes_in_same_batch(Box::new(state));
     }
+
+    #[test]
+    fn it_authorizes_the_rows_updates_produce() {
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        b.set_write_policies(vec![WritePolicy::new(
+            "own",
+            vec![(1, Operator::Equal, PolicyValue::Writer)],
+        )]);
+        let us = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut db = MemoryState::default();
+        db.add_key(&[0], None);
+        db.process_records(&mut vec![vec![1.into(), 42.into()]].into(), None);
+        let mut state = StateMap::new();
+        state.insert(us, Box::new(db));
+
+        let writer = DataType::from(42);
+        let update = |author: i32| {
+            vec![TableOperation::Update {
+                key: vec![1.into()],
+                set: vec![Modification::None, Modification::Set(author.into())],
+            }]
+        };
+        assert!(b.authorize(us, &update(42), Some(&writer), &state));
+        // a writer may not hand their row to someone else
+        assert!(!b.authorize(us, &update(43), Some(&writer), &state));
+    }
+
+    #[test]
+    fn it_sends_updates_it_cannot_apply_to_dead_letters() {
+        let mut b = Base::new(vec![]).with_key(vec![0]);
+        let us = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut db = MemoryState::default();
+        db.add_key(&[0], None);
+        db.process_records(
+            &mut vec![vec![1.into(), "one".into(), 1.into()]].into(),
+            None,
+        );
+        let mut state = StateMap::new();
+        state.insert(us, Box::new(db));
+
+        let set = vec![
+            Modification::None,
+            Modification::Apply(Operation::Add, 1.into()),
+            Modification::Apply(Operation::Add, 1.into()),
+        ];
+        let rs = b.process(
+            us,
+            vec![TableOperation::Update {
+                key: vec![1.into()],
+                set: set.clone(),
+            }],
+            &state,
+        );
+        // the update is dropped as a whole, rather than only in part
+        assert!(rs.is_empty());
+        let dead = b.take_dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(
+            dead[0].op,
+            TableOperation::Update {
+                key: vec![1.into()],
+                set,
+            }
+        );
+        assert!(b.take_dead_letters().is_empty());
+    }
+
+    #[test]
+    fn it_generates_ids_per_shard() {
+        let mut b = Base::new(vec![]).with_key(vec![0]).with_auto_increment(0);
+        let us = unsafe { LocalNodeIndex::make(0 as u32) };
+        let state = StateMap::new();
+        let mut ops = vec![
+            TableOperation::Insert(vec![DataType::None, 1.into()]),
+            TableOperation::Insert(vec![5.into(), 2.into()]),
+            TableOperation::Insert(vec![DataType::None, 3.into()]),
+        ];
+
+        // shard 1 of 2, with rows routed by another column
+        let generated = b.generate_ids(us, &mut ops, &state, Some((&[1], 1, 2, ShardScheme::Hash)));
+        assert_eq!(generated, vec![1.into(), 7.into()]);
+        assert_eq!(ops[2], TableOperation::Insert(vec![7.into(), 3.into()]));
+    }
+
+    #[test]
+    fn it_generates_ids_past_the_range_of_a_shard() {
+        let mut b = Base::new(vec![]).with_key(vec![0]).with_auto_increment(0);
+        let us = unsafe { LocalNodeIndex::make(0 as u32) };
+        let state = StateMap::new();
+        let mut ops: Vec<_> = (0..8)
+            .map(|i| TableOperation::Insert(vec![DataType::None, i.into()]))
+            .collect();
+
+        // shard 0 of 2 only has the values up to 4 to itself, and once it gives up looking for
+        // more, it uses every other value
+        let scheme = ShardScheme::Range { start: 0, end: 10 };
+        let generated = b.generate_ids(us, &mut ops, &state, Some((&[0], 0, 2, scheme)));
+        let expected: Vec<DataType> = vec![1, 2, 3, 4, 134, 136, 138, 140]
+            .into_iter()
+            .map(DataType::from)
+            .collect();
+        assert_eq!(generated, expected);
+    }
 }
@@ -0,0 +1,94 @@
+use crate::prelude::*;
+use nom_sql::Operator;
+
+/// A value that a written row's column is compared against by a `TableConstraint::Check`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum CheckValue {
+    /// A literal value.
+    Constant(DataType),
+    /// Another column of the same row.
+    Column(usize),
+}
+
+/// A property that every row of a base table must have, checked before writes are applied.
+///
+/// Writes that would leave a row without the property are rejected as a whole with a
+/// `noria::WriteRejection::ConstraintViolation`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum TableConstraint {
+    /// The column is never NULL.
+    NotNull { name: String, column: usize },
+    /// Every one of the comparisons holds.
+    ///
+    /// As in SQL, a comparison with NULL does not violate the constraint.
+    Check {
+        name: String,
+        conditions: Vec<(usize, Operator, CheckValue)>,
+    },
+}
+
+impl TableConstraint {
+    pub fn name(&self) -> &str {
+        match *self {
+            TableConstraint::NotNull { ref name, .. } | TableConstraint::Check { ref name, .. } => {
+                name
+            }
+        }
+    }
+
+    /// Returns true if `row` satisfies this constraint.
+    pub fn holds(&self, row: &[DataType]) -> bool {
+        match *self {
+            TableConstraint::NotNull { column, .. } => !row[column].is_none(),
+            TableConstraint::Check { ref conditions, .. } => {
+                conditions.iter().all(|&(col, ref op, ref value)| {
+                    let d = &row[col];
+                    let v = match *value {
+                        CheckValue::Constant(ref dt) => dt,
+                        CheckValue::Column(c) => &row[c],
+                    };
+                    if d.is_none() || v.is_none() {
+                        return true;
+                    }
+                    match *op {
+                        Operator::Equal => d == v,
+                        Operator::NotEqual => d != v,
+                        Operator::Greater => d > v,
+                        Operator::GreaterOrEqual => d >= v,
+                        Operator::Less => d < v,
+                        Operator::LessOrEqual => d <= v,
+                        _ => unreachable!("checks only compile comparisons, not {:?}", op),
+                    }
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_rows() {
+        let not_null = TableConstraint::NotNull {
+            name: "b NOT NULL".to_owned(),
+            column: 1,
+        };
+        assert!(not_null.holds(&[1.into(), 0.into()]));
+        assert!(!not_null.holds(&[1.into(), DataType::None]));
+
+        let check = TableConstraint::Check {
+            name: "ordered".to_owned(),
+            conditions: vec![
+                (0, Operator::GreaterOrEqual, CheckValue::Constant(0.into())),
+                (1, Operator::Greater, CheckValue::Column(0)),
+            ],
+        };
+        assert!(check.holds(&[0.into(), 1.into()]));
+        assert!(!check.holds(&[(-1).into(), 1.into()]));
+        assert!(!check.holds(&[1.into(), 1.into()]));
+        assert!(check.holds(&[DataType::None, 1.into()]));
+        assert!(check.holds(&[1.into(), DataType::None]));
+    }
+}
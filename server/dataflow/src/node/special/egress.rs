@@ -71,6 +71,9 @@ impl Egress {
                 .expect("egress node told about replay message, but not on replay path")
         });
 
+        // a regular update goes to every child, and each gets a share of any barrier it carries
+        let mut barrier_shares = m.as_ref().unwrap().barrier_shares(txs.len());
+
         for (txi, ref mut tx) in txs.iter_mut().enumerate() {
             let mut take = txi == txn;
             if let Some(replay_to) = replay_to.as_ref() {
@@ -90,6 +93,10 @@ impl Egress {
                 m.as_ref().map(|m| Box::new(m.clone_data())).unwrap()
             };
 
+            if let Some(ref mut shares) = barrier_shares {
+                m.set_barrier(shares.next().unwrap());
+            }
+
             // src is usually ignored and overwritten by ingress
             // *except* if the ingress is marked as a shard merger
             // in which case it wants to know about the shard
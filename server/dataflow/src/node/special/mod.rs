@@ -1,12 +1,18 @@
 mod base;
+mod constraint;
 mod egress;
 mod reader;
 mod sharder;
+mod ttl;
+mod write_policy;
 
 pub struct Ingress;
 pub struct Source;
 
 pub use self::base::Base;
+pub use self::constraint::{CheckValue, TableConstraint};
 pub use self::egress::Egress;
-pub use self::reader::Reader;
+pub use self::reader::{Reader, ReaderAssertion};
 pub use self::sharder::Sharder;
+pub use self::ttl::RowTtl;
+pub use self::write_policy::{PolicyValue, WritePolicy};
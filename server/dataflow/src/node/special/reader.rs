@@ -1,5 +1,31 @@
 use crate::backlog;
+use crate::node::special::RowTtl;
+use crate::payload::{EventTimes, ReplayPieceContext};
 use crate::prelude::*;
+use nom_sql::{Operator, OrderType};
+use noria::{Assertion, Eviction, EvictionPolicy, ViewPriority, ViewStorage, Violation};
+use noria::{TransactionPhase, Watermarks};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How long a reader holds back the updates of an atomic write that has not committed, in case the
+/// client that started it went away before committing it.
+///
+/// This is longer than the base tables wait before undoing such a write, so the undoing has
+/// normally reached the reader by the time it gives up.
+const HELD_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An assertion that the rows added to a reader are checked against.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReaderAssertion {
+    pub name: String,
+    pub assertion: Assertion,
+    /// The index of the checked column.
+    pub column: usize,
+    /// Whether to keep the changes to the key of a violating row out of the reader.
+    pub halt: bool,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
@@ -8,6 +34,36 @@ pub struct Reader {
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
+    // lookups return the records whose key compares to the looked-up one by this operator
+    #[serde(default)]
+    range: Option<Operator>,
+    // lookups sort the records for each key by these columns, skip the offset, and return at most
+    // the limit of the rest
+    #[serde(default)]
+    order: Option<(Vec<(usize, OrderType)>, usize, usize)>,
+    // the rows added to the reader must satisfy these
+    #[serde(default)]
+    assertions: Vec<ReaderAssertion>,
+    // the position in the key of the column that the reader's shards are keyed by
+    #[serde(default)]
+    shard_key_index: usize,
+    // which keys to evict first, and how many bytes this shard of the reader may hold
+    #[serde(default)]
+    eviction: Eviction,
+    // how long rows are kept before they are retracted, going by their timestamp
+    #[serde(default)]
+    ttl: Option<RowTtl>,
+    // how urgently the upqueries that fill keys missing from this reader are handled
+    #[serde(default)]
+    priority: ViewPriority,
+    // whether the rows are kept on disk, with only the keys that are looked up kept in `writer`
+    #[serde(default)]
+    storage: ViewStorage,
+    #[serde(skip)]
+    disk: Option<backlog::DiskRows>,
+    // the updates that wait for an atomic write to commit before they are applied
+    #[serde(skip)]
+    held: Held,
 }
 
 impl Clone for Reader {
@@ -17,6 +73,16 @@ impl Clone for Reader {
             writer: None,
             state: self.state.clone(),
             for_node: self.for_node,
+            range: self.range.clone(),
+            order: self.order.clone(),
+            assertions: self.assertions.clone(),
+            shard_key_index: self.shard_key_index,
+            eviction: self.eviction,
+            ttl: self.ttl,
+            priority: self.priority,
+            storage: self.storage,
+            disk: None,
+            held: Held::default(),
         }
     }
 }
@@ -27,6 +93,16 @@ impl Reader {
             writer: None,
             state: None,
             for_node,
+            range: None,
+            order: None,
+            assertions: Vec::new(),
+            shard_key_index: 0,
+            eviction: Eviction::default(),
+            ttl: None,
+            priority: ViewPriority::default(),
+            storage: ViewStorage::default(),
+            disk: None,
+            held: Held::default(),
         }
     }
 
@@ -50,6 +126,16 @@ impl Reader {
             writer: self.writer.take(),
             state: self.state.clone(),
             for_node: self.for_node,
+            range: self.range.clone(),
+            order: self.order.clone(),
+            assertions: self.assertions.clone(),
+            shard_key_index: self.shard_key_index,
+            eviction: self.eviction,
+            ttl: self.ttl,
+            priority: self.priority,
+            storage: self.storage,
+            disk: self.disk.take(),
+            held: mem::take(&mut self.held),
         }
     }
 
@@ -64,8 +150,9 @@ impl Reader {
         }
     }
 
-    pub(crate) fn set_write_handle(&mut self, wh: backlog::WriteHandle) {
+    pub(crate) fn set_write_handle(&mut self, mut wh: backlog::WriteHandle) {
         assert!(self.writer.is_none());
+        wh.set_eviction_policy(self.eviction.policy);
         self.writer = Some(wh);
     }
 
@@ -81,14 +168,194 @@ impl Reader {
         }
     }
 
+    /// Key this reader on the single column `key` such that a lookup returns the records whose
+    /// key compares to the looked-up one as given by `op`.
+    pub fn set_range_key(&mut self, key: usize, op: Operator) {
+        self.set_key(&[key]);
+        if let Some(ref range) = self.range {
+            assert_eq!(*range, op);
+        } else {
+            self.range = Some(op);
+        }
+    }
+
+    pub fn range(&self) -> Option<&Operator> {
+        self.range.as_ref()
+    }
+
+    /// Return the records for each key sorted by the given columns, skipping the first `offset`
+    /// of them, and at most `limit` of the rest.
+    pub fn set_order(&mut self, order: Vec<(usize, OrderType)>, offset: usize, limit: usize) {
+        self.order = Some((order, offset, limit));
+    }
+
+    pub fn order(&self) -> Option<&(Vec<(usize, OrderType)>, usize, usize)> {
+        self.order.as_ref()
+    }
+
+    /// Shard this reader by the column at position `index` of its key, rather than by the first.
+    ///
+    /// Lookups are then sent to the shard that the value at that position of the key belongs to.
+    pub fn set_shard_key_index(&mut self, index: usize) {
+        if let Some(ref key) = self.state {
+            assert!(index < key.len());
+        }
+        self.shard_key_index = index;
+    }
+
+    /// The position in the key of the column that this reader is sharded by.
+    pub fn shard_key_index(&self) -> usize {
+        self.shard_key_index
+    }
+
+    /// Check the rows added to this reader against the given assertions from now on.
+    pub(crate) fn set_assertions(&mut self, assertions: Vec<ReaderAssertion>) {
+        self.assertions = assertions;
+    }
+
+    /// Evict keys as `eviction` says from now on.
+    pub(crate) fn set_eviction(&mut self, eviction: Eviction) {
+        self.eviction = eviction;
+        if let Some(ref mut w) = self.writer {
+            w.set_eviction_policy(eviction.policy);
+        }
+    }
+
+    /// Handle the upqueries of this reader as urgently as `priority` says from now on.
+    pub(crate) fn set_priority(&mut self, priority: ViewPriority) {
+        self.priority = priority;
+    }
+
+    /// How urgently the upqueries of this reader are handled.
+    pub(crate) fn priority(&self) -> ViewPriority {
+        self.priority
+    }
+
+    /// Keep this reader's rows as `storage` says once its state is prepared.
+    pub fn set_storage(&mut self, storage: ViewStorage) {
+        assert!(self.writer.is_none());
+        self.storage = storage;
+    }
+
+    pub fn storage(&self) -> ViewStorage {
+        self.storage
+    }
+
+    /// Keep every row in `disk`, so that the write handle only caches the keys that are looked up.
+    pub(crate) fn set_disk(&mut self, disk: backlog::DiskRows) {
+        assert!(self.writer.as_ref().map(|w| w.is_partial()).unwrap_or(true));
+        self.disk = Some(disk);
+    }
+
+    /// Fill the given keys, which are missing from this reader's cache, with their rows on disk.
+    ///
+    /// Returns false if the reader does not keep its rows on disk, in which case the keys have to
+    /// be replayed instead.
+    pub(crate) fn fill_from_disk(&mut self, keys: &[Vec<DataType>]) -> bool {
+        let (disk, w) = match (self.disk.as_ref(), self.writer.as_mut()) {
+            (Some(disk), Some(w)) => (disk, w),
+            _ => return false,
+        };
+        let mut filled = HashSet::new();
+        for key in keys {
+            if filled.insert(key) {
+                w.mut_with_key(&key[..]).mark_filled();
+                w.add(disk.get(key).into_iter().map(Record::Positive));
+            }
+        }
+        w.swap();
+        true
+    }
+
+    /// Retract rows once the timestamp in their `ttl.column` is older than `ttl.ttl`, or stop
+    /// retracting them if `None`.
+    pub(crate) fn set_ttl(&mut self, ttl: Option<RowTtl>) {
+        self.ttl = ttl;
+    }
+
+    /// Retract the rows that have outlived this reader's row TTL.
+    pub(crate) fn expire_rows(&mut self) {
+        let (ttl, state) = match (self.ttl, self.writer.as_mut()) {
+            (Some(ttl), Some(state)) => (ttl, state),
+            _ => return,
+        };
+        let cutoff = ttl.cutoff();
+        // the rows on disk include those in the cache
+        let expired = match self.disk {
+            Some(ref disk) => disk.rows_where(|row| ttl.expired(row, cutoff)),
+            None => state.rows_where(|row| ttl.expired(row, cutoff)),
+        };
+        let mut expired: Records = expired.into_iter().map(Record::Negative).collect();
+        if expired.is_empty() {
+            return;
+        }
+        state.notify(&expired);
+        if let Some(ref mut disk) = self.disk {
+            disk.apply(&expired);
+            retain_filled(state, &mut expired);
+        }
+        state.add(expired);
+        state.swap();
+    }
+
+    /// Whether this reader is holding back updates until the atomic writes they are part of
+    /// commit.
+    pub(crate) fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Apply the held updates of the atomic writes that have been held for too long as of `now`,
+    /// and return whether any updates are still held.
+    pub(crate) fn expire_held(&mut self, now: Instant) -> bool {
+        if self.held.is_empty() {
+            return false;
+        }
+        self.held.expire(now);
+        let released = self.held.release();
+        if let Some(state) = self.writer.as_mut() {
+            if !released.is_empty() {
+                for u in released {
+                    let disk = self.disk.as_mut();
+                    apply(
+                        state,
+                        disk,
+                        u.data,
+                        &u.watermarks,
+                        &u.event_times,
+                        &u.frontier,
+                    );
+                }
+                state.swap();
+            }
+        }
+        !self.held.is_empty()
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.writer.as_ref().map(|w| w.is_empty()).unwrap_or(true)
     }
 
+    /// Every row of this reader, or `None` if it is partial, and so may not hold them all.
+    pub(crate) fn rows(&self) -> Option<Vec<Vec<DataType>>> {
+        // the rows on disk include those in the cache
+        if let Some(ref disk) = self.disk {
+            return Some(disk.rows_where(|_| true));
+        }
+        match self.writer {
+            Some(ref w) if !w.is_partial() => Some(w.rows_where(|_| true)),
+            _ => None,
+        }
+    }
+
     pub(crate) fn state_size(&self) -> Option<u64> {
         self.writer.as_ref().map(SizeOf::deep_size_of)
     }
 
+    /// The number of keys in the reader's state, and of rows under them.
+    pub(crate) fn state_counts(&self) -> Option<(usize, usize)> {
+        self.writer.as_ref().map(backlog::WriteHandle::counts)
+    }
+
     /// Evict a randomly selected key, returning the number of bytes evicted.
     /// Note that due to how `evmap` applies the evictions asynchronously, we can only evict a
     /// single key at a time here.
@@ -102,6 +369,45 @@ impl Reader {
         bytes_freed
     }
 
+    /// Evict keys as this reader's eviction policy picks them until at least `bytes` bytes are
+    /// freed or the reader is empty, returning the number of bytes evicted.
+    pub(crate) fn evict(&mut self, bytes: u64) -> u64 {
+        if let EvictionPolicy::Random = self.eviction.policy {
+            return self.evict_random_keys(16);
+        }
+        let mut bytes_freed = 0;
+        if let Some(ref mut handle) = self.writer {
+            bytes_freed = handle.evict_by(self.eviction.policy, bytes);
+            handle.swap();
+        }
+        bytes_freed
+    }
+
+    /// Evict the keys that this reader's policy says have expired, and then as many more as it
+    /// takes for it to keep to its budget. Returns the number of bytes evicted.
+    pub(crate) fn enforce_eviction(&mut self) -> u64 {
+        let eviction = self.eviction;
+        let handle = match self.writer {
+            Some(ref mut handle) if handle.is_partial() => handle,
+            _ => return 0,
+        };
+
+        let mut bytes_freed = 0;
+        if let EvictionPolicy::Ttl(ttl) = eviction.policy {
+            bytes_freed += handle.evict_expired(ttl);
+        }
+        if let Some(budget) = eviction.budget {
+            let size = handle.deep_size_of();
+            if size > budget as u64 {
+                bytes_freed += handle.evict_by(eviction.policy, size - budget as u64);
+            }
+        }
+        if bytes_freed > 0 {
+            handle.swap();
+        }
+        bytes_freed
+    }
+
     pub(in crate::node) fn on_eviction(&mut self, keys: &[Vec<DataType>]) {
         // NOTE: *could* be None if reader has been created but its state hasn't been built yet
         if let Some(w) = self.writer.as_mut() {
@@ -112,43 +418,100 @@ impl Reader {
         }
     }
 
-    pub(in crate::node) fn process(&mut self, m: &mut Option<Box<Packet>>, swap: bool) {
+    pub(in crate::node) fn process(
+        &mut self,
+        m: &mut Option<Box<Packet>>,
+        swap: bool,
+        name: &str,
+        ex: &mut dyn Executor,
+    ) {
         if let Some(ref mut state) = self.writer {
             let m = m.as_mut().unwrap();
-            // make sure we don't fill a partial materialization
-            // hole with incomplete (i.e., non-replay) state.
-            if m.is_regular() && state.is_partial() {
-                m.map_data(|data| {
-                    data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
-                            Ok((None, _)) => {
-                                // row would miss in partial state.
-                                // leave it blank so later lookup triggers replay.
-                                false
-                            }
-                            Err(_) => unreachable!(),
-                            _ => {
-                                // state is already present,
-                                // so we can safely keep it up to date.
-                                true
-                            }
-                        }
-                    });
-                });
+            // expired rows are kept out, and the ones already here are retracted by
+            // `expire_rows`, so changes to them are dropped too
+            if let Some(ttl) = self.ttl {
+                let cutoff = ttl.cutoff();
+                m.map_data(|data| data.retain(|r| !ttl.expired(r, cutoff)));
+            }
+            // replays carry rows that were already checked, or that were never added
+            if m.is_regular() && !self.assertions.is_empty() {
+                let (assertions, key) = (&self.assertions, self.state.as_ref());
+                let mut violations = Vec::new();
+                m.map_data(|data| violations = check(assertions, key.map(|k| &k[..]), data));
+                if !violations.is_empty() {
+                    ex.quarantine(name.to_owned(), violations);
+                }
+            }
+
+            if m.is_regular() {
+                let txn = m.txn();
+                let data = m.take_data();
+                let held = match txn {
+                    Some(TransactionPhase::Write(id)) if !data.is_empty() => Some(id),
+                    // the undoing of an aborted write is released along with the write
+                    Some(TransactionPhase::Abort(id)) if self.held.is_pending(id) => Some(id),
+                    _ => None,
+                };
+                // the updates of an atomic write wait until it has committed, and so does every
+                // update after them, so that updates are still applied in order
+                if held.is_some() || !self.held.is_empty() {
+                    let (watermarks, event_times) = (m.watermarks(), m.event_times());
+                    self.held.hold(
+                        held,
+                        data,
+                        watermarks.clone(),
+                        event_times.clone(),
+                        m.frontier().clone(),
+                    );
+                } else {
+                    let disk = self.disk.as_mut();
+                    let (watermarks, event_times) = (m.watermarks(), m.event_times());
+                    apply(state, disk, data, watermarks, event_times, m.frontier());
+                }
+                match txn {
+                    Some(TransactionPhase::Commit(id)) | Some(TransactionPhase::Abort(id)) => {
+                        self.held.commit(id)
+                    }
+                    _ => {}
+                }
+                if !self.held.is_empty() {
+                    for u in self.held.release() {
+                        let disk = self.disk.as_mut();
+                        apply(
+                            state,
+                            disk,
+                            u.data,
+                            &u.watermarks,
+                            &u.event_times,
+                            &u.frontier,
+                        );
+                    }
+                }
+
+                if swap {
+                    state.swap();
+                }
+                return;
+            }
+
+            // the replayed rows of a reader that keeps them on disk are only cached once their
+            // keys are looked up
+            if let Some(ref mut disk) = self.disk {
+                disk.apply(&m.take_data());
             }
 
             // it *can* happen that multiple readers miss (and thus request replay for) the
             // same hole at the same time. we need to make sure that we ignore any such
             // duplicated replay.
-            if !m.is_regular() && state.is_partial() {
+            if state.is_partial() {
                 m.map_data(|data| {
                     data.retain(|row| {
                         match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
-                            Ok((None, _)) => {
+                            Ok(None) => {
                                 // filling a hole with replay -- ok
                                 true
                             }
-                            Ok((Some(_), _)) => {
+                            Ok(Some(_)) => {
                                 // a given key should only be replayed to once!
                                 false
                             }
@@ -164,6 +527,18 @@ impl Reader {
                 });
             }
 
+            // the keys a replay fills already reflect the held updates to them
+            if let (
+                Some(key),
+                Packet::ReplayPiece {
+                    context: ReplayPieceContext::Partial { ref for_keys, .. },
+                    ..
+                },
+            ) = (self.state.as_ref(), &**m)
+            {
+                self.held.forget(key, for_keys);
+            }
+
             state.add(m.take_data());
 
             if swap {
@@ -173,3 +548,270 @@ impl Reader {
         }
     }
 }
+
+/// Apply a regular update to the reader's state.
+fn apply(
+    state: &mut backlog::WriteHandle,
+    disk: Option<&mut backlog::DiskRows>,
+    mut data: Records,
+    watermarks: &Watermarks,
+    event_times: &EventTimes,
+    frontier: &EventTimes,
+) {
+    // subscribers watch keys whether or not they are materialized, but replays only bring in
+    // records that are already there
+    state.notify(&data);
+    if let Some(disk) = disk {
+        disk.apply(&data);
+    }
+
+    // make sure we don't fill a partial materialization
+    // hole with incomplete (i.e., non-replay) state.
+    if state.is_partial() {
+        retain_filled(state, &mut data);
+    }
+
+    state.add(data);
+    state.advance(watermarks);
+    state.advance_event_times(event_times);
+    state.advance_frontier(frontier);
+}
+
+/// Keep only the records whose keys are present in the given partial state.
+fn retain_filled(state: &backlog::WriteHandle, data: &mut Records) {
+    data.retain(|row| {
+        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+            Ok(None) => {
+                // row would miss in partial state.
+                // leave it blank so later lookup triggers replay.
+                false
+            }
+            Err(_) => unreachable!(),
+            _ => {
+                // state is already present,
+                // so we can safely keep it up to date.
+                true
+            }
+        }
+    });
+}
+
+/// A regular update that a reader has not applied yet.
+struct HeldUpdate {
+    /// The atomic write the update is part of, if any.
+    txn: Option<u64>,
+    data: Records,
+    watermarks: Watermarks,
+    event_times: EventTimes,
+    frontier: EventTimes,
+}
+
+/// The updates that a reader holds back until the atomic writes they are part of have committed.
+#[derive(Default)]
+struct Held {
+    updates: VecDeque<HeldUpdate>,
+    /// The atomic writes with held updates that have not committed, and when each was first held.
+    pending: HashMap<u64, Instant>,
+}
+
+impl Held {
+    fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    fn hold(
+        &mut self,
+        txn: Option<u64>,
+        data: Records,
+        watermarks: Watermarks,
+        event_times: EventTimes,
+        frontier: EventTimes,
+    ) {
+        if let Some(id) = txn {
+            self.pending.entry(id).or_insert_with(Instant::now);
+        }
+        self.updates.push_back(HeldUpdate {
+            txn,
+            data,
+            watermarks,
+            event_times,
+            frontier,
+        });
+    }
+
+    /// Whether updates of the atomic write `id` are held back waiting for it to commit.
+    fn is_pending(&self, id: u64) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    /// Record that the atomic write `id` has committed, or has been aborted and undone.
+    fn commit(&mut self, id: u64) {
+        self.pending.remove(&id);
+    }
+
+    /// Stop waiting for the atomic writes that have been held for too long as of `now`.
+    ///
+    /// Their updates are released along with any undoing of them that has arrived.
+    fn expire(&mut self, now: Instant) {
+        self.pending
+            .retain(|_, &mut since| now.duration_since(since) < HELD_TRANSACTION_TIMEOUT);
+    }
+
+    /// Take the updates that come before the first one whose atomic write has not committed.
+    fn release(&mut self) -> Vec<HeldUpdate> {
+        let mut released = Vec::new();
+        while let Some(u) = self.updates.front() {
+            if let Some(id) = u.txn {
+                if self.pending.contains_key(&id) {
+                    break;
+                }
+            }
+            released.extend(self.updates.pop_front());
+        }
+        released
+    }
+
+    /// Drop the held changes to rows whose values in the `key` columns are among `keys`.
+    fn forget(&mut self, key: &[usize], keys: &HashSet<Vec<DataType>>) {
+        if self.updates.is_empty() {
+            return;
+        }
+        let key_of = |row: &[DataType]| key.iter().map(|&c| row[c].clone()).collect::<Vec<_>>();
+        for u in &mut self.updates {
+            u.data.retain(|r| !keys.contains(&key_of(r)));
+        }
+    }
+}
+
+/// Check the rows added by `data` against `assertions`.
+///
+/// The changes to the keys of rows that violate an assertion that halts are removed from `data`.
+/// Rows are grouped by the columns in `key`; if the reader has no key yet, nothing is removed.
+fn check(
+    assertions: &[ReaderAssertion],
+    key: Option<&[usize]>,
+    data: &mut Records,
+) -> Vec<Violation> {
+    let key_of =
+        |row: &[DataType]| key.map(|k| k.iter().map(|&c| row[c].clone()).collect::<Vec<_>>());
+
+    let mut violations = Vec::new();
+    let mut halted = HashSet::new();
+    for r in data.iter().filter(|r| r.is_positive()) {
+        for a in assertions {
+            if a.assertion.holds(&r[a.column]) {
+                continue;
+            }
+
+            let k = key_of(r);
+            let delta = match k {
+                Some(ref k) => data
+                    .iter()
+                    .filter(|other| key_of(other).as_ref() == Some(k))
+                    .map(|other| (other.rec().to_vec(), other.is_positive()))
+                    .collect(),
+                None => vec![(r.rec().to_vec(), true)],
+            };
+            let halt = a.halt && k.is_some();
+            if halt {
+                halted.insert(k.unwrap());
+            }
+            violations.push(Violation {
+                assertion: a.name.clone(),
+                row: r.rec().to_vec(),
+                delta,
+                halted: halt,
+                // the controller knows where the column came from
+                provenance: Vec::new(),
+            });
+        }
+    }
+
+    if !halted.is_empty() {
+        data.retain(|r| !halted.contains(&key_of(r).unwrap()));
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halted_keys_are_kept_out() {
+        let assertions = vec![ReaderAssertion {
+            name: "positive".to_owned(),
+            assertion: Assertion::NonNegative {
+                column: "n".to_owned(),
+            },
+            column: 1,
+            halt: true,
+        }];
+        let mut data: Records = vec![
+            (vec![1.into(), 1.into()], false),
+            (vec![1.into(), (-1).into()], true),
+            (vec![2.into(), 1.into()], true),
+        ]
+        .into();
+
+        let violations = check(&assertions, Some(&[0]), &mut data);
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].halted);
+        assert_eq!(violations[0].row, vec![1.into(), (-1).into()]);
+        assert_eq!(violations[0].delta.len(), 2);
+        assert_eq!(data, vec![(vec![2.into(), 1.into()], true)].into());
+
+        // without a key, there is nothing to halt
+        let mut data: Records = vec![(vec![1.into(), (-1).into()], true)].into();
+        let violations = check(&assertions, None, &mut data);
+        assert!(!violations[0].halted);
+        assert_eq!(data.len(), 1);
+    }
+
+    #[test]
+    fn held_updates_wait_for_their_transaction() {
+        let update = |i: i32| -> Records { vec![(vec![i.into()], true)].into() };
+        let mut held = Held::default();
+        let hold = |held: &mut Held, txn, i| {
+            let (watermarks, times) = (Watermarks::default(), EventTimes::default());
+            held.hold(txn, update(i), watermarks, times.clone(), times)
+        };
+        hold(&mut held, None, 1);
+        hold(&mut held, Some(7), 2);
+        hold(&mut held, None, 3);
+        hold(&mut held, Some(8), 4);
+
+        // nothing after an uncommitted transaction is released
+        let released: Vec<_> = held.release().into_iter().map(|u| u.data).collect();
+        assert_eq!(released, vec![update(1)]);
+        assert!(held.release().is_empty());
+
+        held.commit(7);
+        let released: Vec<_> = held.release().into_iter().map(|u| u.data).collect();
+        assert_eq!(released, vec![update(2), update(3)]);
+
+        // a transaction that never commits is given up on eventually
+        held.expire(Instant::now() + HELD_TRANSACTION_TIMEOUT);
+        assert_eq!(held.release().len(), 1);
+        assert!(held.is_empty());
+
+        // an aborted transaction is released along with its undoing
+        hold(&mut held, Some(10), 6);
+        assert!(held.is_pending(10));
+        held.hold(
+            Some(10),
+            vec![(vec![6.into()], false)].into(),
+            Watermarks::default(),
+            EventTimes::default(),
+            EventTimes::default(),
+        );
+        held.commit(10);
+        assert_eq!(held.release().len(), 2);
+        assert!(!held.is_pending(10));
+
+        hold(&mut held, Some(9), 5);
+        held.forget(&[0], &vec![vec![5.into()]].into_iter().collect());
+        held.commit(9);
+        assert!(held.release()[0].data.is_empty());
+    }
+}
@@ -7,6 +7,7 @@ pub struct Sharder {
     txs: Vec<(LocalNodeIndex, ReplicaAddr)>,
     sharded: VecMap<Box<Packet>>,
     shard_by: usize,
+    scheme: ShardScheme,
 }
 
 impl Clone for Sharder {
@@ -17,15 +18,19 @@ impl Clone for Sharder {
             txs: Vec::new(),
             sharded: Default::default(),
             shard_by: self.shard_by,
+            scheme: self.scheme,
         }
     }
 }
 
 impl Sharder {
-    pub fn new(by: usize) -> Self {
+    /// A sharder that sends each record to the shard that `scheme` puts the value in column `by`
+    /// of the record in.
+    pub fn new(by: usize, scheme: ShardScheme) -> Self {
         Self {
             txs: Default::default(),
             shard_by: by,
+            scheme,
             sharded: VecMap::default(),
         }
     }
@@ -37,6 +42,7 @@ impl Sharder {
             txs,
             sharded: VecMap::default(),
             shard_by: self.shard_by,
+            scheme: self.scheme,
         }
     }
 
@@ -52,6 +58,10 @@ impl Sharder {
         self.shard_by
     }
 
+    pub fn scheme(&self) -> ShardScheme {
+        self.scheme
+    }
+
     #[inline]
     fn to_shard(&self, r: &Record) -> usize {
         self.shard(&r[self.shard_by])
@@ -59,7 +69,7 @@ impl Sharder {
 
     #[inline]
     fn shard(&self, dt: &DataType) -> usize {
-        crate::shard_by(dt, self.txs.len())
+        self.scheme.shard(std::slice::from_ref(dt), self.txs.len())
     }
 
     pub fn process(
@@ -118,6 +128,16 @@ impl Sharder {
             }
         } else {
             assert!(is_last_sharder_for_tag.is_none());
+            if m.is_regular()
+                && (!m.watermarks().is_empty()
+                    || !m.event_times().is_empty()
+                    || !m.frontier().is_empty()
+                    || m.barrier().is_some())
+            {
+                // readers in every shard need to learn about the write, even if its records
+                // all went elsewhere, and so does every shard that a barrier has to pass through.
+                dest = Destination::All;
+            }
         }
 
         match dest {
@@ -151,6 +171,12 @@ impl Sharder {
             unimplemented!();
         }
 
+        if let Some(shares) = m.barrier_shares(self.sharded.len()) {
+            for (shard, share) in self.sharded.values_mut().zip(shares) {
+                shard.set_barrier(share);
+            }
+        }
+
         for (i, &mut (dst, addr)) in self.txs.iter_mut().enumerate() {
             if let Some(mut shard) = self.sharded.remove(i) {
                 shard.link_mut().src = index;
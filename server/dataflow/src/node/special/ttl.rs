@@ -0,0 +1,54 @@
+use super::base::event_time;
+use crate::prelude::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the rows of a base or reader node are kept, going by the timestamp in one of their
+/// columns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowTtl {
+    /// The index of the column that holds the timestamp of each row.
+    pub column: usize,
+    /// How old the timestamp of a row may get before the row is retracted.
+    pub ttl: Duration,
+}
+
+impl RowTtl {
+    /// The time before which rows have expired as of now, in milliseconds since the Unix epoch.
+    pub(crate) fn cutoff(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_millis() as i64 - self.ttl.as_millis() as i64
+    }
+
+    /// Whether `row` expired before `cutoff`.
+    ///
+    /// Rows whose timestamp is NULL, or not a timestamp at all, never expire.
+    pub(crate) fn expired(&self, row: &[DataType], cutoff: i64) -> bool {
+        row.get(self.column)
+            .and_then(event_time)
+            .map_or(false, |t| t < cutoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_expire_by_their_timestamp() {
+        let ttl = RowTtl {
+            column: 1,
+            ttl: Duration::from_secs(60),
+        };
+        let cutoff = ttl.cutoff();
+        let row = |t: DataType| vec![1.into(), t];
+        assert!(ttl.expired(&row(DataType::BigInt(cutoff - 1)), cutoff));
+        assert!(!ttl.expired(&row(DataType::BigInt(cutoff)), cutoff));
+        assert!(!ttl.expired(&row(DataType::None), cutoff));
+        assert!(!ttl.expired(&row("yesterday".into()), cutoff));
+
+        let old = chrono::NaiveDateTime::from_timestamp(0, 0);
+        assert!(ttl.expired(&row(DataType::Timestamp(old)), cutoff));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::prelude::*;
+use nom_sql::Operator;
+
+/// A value that a written row's column is compared against by a `WritePolicy`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum PolicyValue {
+    /// A literal value.
+    Constant(DataType),
+    /// Another column of the same row.
+    Column(usize),
+    /// The identity of the writer.
+    Writer,
+}
+
+/// A conjunction of conditions over a row and the identity of its writer.
+///
+/// A base table with write policies only accepts writes to rows that satisfy at least one of its
+/// policies.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WritePolicy {
+    name: String,
+    conditions: Vec<(usize, Operator, PolicyValue)>,
+}
+
+impl WritePolicy {
+    /// Construct a policy that requires every one of `conditions` to hold.
+    pub fn new(name: &str, conditions: Vec<(usize, Operator, PolicyValue)>) -> Self {
+        WritePolicy {
+            name: name.to_owned(),
+            conditions,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if `writer` may write `row`.
+    ///
+    /// Anonymous writers never satisfy conditions that refer to the writer.
+    pub fn permits(&self, row: &[DataType], writer: Option<&DataType>) -> bool {
+        self.conditions.iter().all(|&(col, ref op, ref value)| {
+            let d = &row[col];
+            let v = match *value {
+                PolicyValue::Constant(ref dt) => dt,
+                PolicyValue::Column(c) => &row[c],
+                PolicyValue::Writer => match writer {
+                    Some(w) => w,
+                    None => return false,
+                },
+            };
+            match *op {
+                Operator::Equal => d == v,
+                Operator::NotEqual => d != v,
+                Operator::Greater => d > v,
+                Operator::GreaterOrEqual => d >= v,
+                Operator::Less => d < v,
+                Operator::LessOrEqual => d <= v,
+                _ => unreachable!("write policies only compile comparisons, not {:?}", op),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_writer() {
+        let p = WritePolicy::new("own", vec![(1, Operator::Equal, PolicyValue::Writer)]);
+        let row: Vec<DataType> = vec![1.into(), 42.into()];
+        assert!(p.permits(&row, Some(&42.into())));
+        assert!(!p.permits(&row, Some(&43.into())));
+        assert!(!p.permits(&row, None));
+    }
+
+    #[test]
+    fn it_requires_all_conditions() {
+        let p = WritePolicy::new(
+            "own_public",
+            vec![
+                (1, Operator::Equal, PolicyValue::Writer),
+                (2, Operator::Equal, PolicyValue::Constant(0.into())),
+                (0, Operator::NotEqual, PolicyValue::Column(1)),
+            ],
+        );
+        let writer = DataType::from(42);
+        assert!(p.permits(&[1.into(), 42.into(), 0.into()], Some(&writer)));
+        assert!(!p.permits(&[1.into(), 42.into(), 1.into()], Some(&writer)));
+        assert!(!p.permits(&[42.into(), 42.into(), 0.into()], Some(&writer)));
+    }
+}
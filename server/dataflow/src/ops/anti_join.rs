@@ -0,0 +1,330 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::*;
+
+/// AntiJoin emits the records of its left parent whose join column matches no record of its right
+/// parent, as `NOT IN` requires.
+///
+/// The number of right records that match each key is read from the right parent's materialized
+/// state. Since that state has already absorbed the records the right parent sends us, undoing a
+/// batch tells us what the count was before it, and so whether the first match for a key has
+/// appeared or the last one has disappeared. The left records with that key are then revoked or
+/// re-emitted respectively.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AntiJoin {
+    left: IndexPair,
+    right: IndexPair,
+
+    // Key column in the left and right parents respectively
+    on: (usize, usize),
+}
+
+impl AntiJoin {
+    /// Create a new instance of AntiJoin.
+    ///
+    /// `left` and `right` are the left and right parents respectively, and `on` is a tuple
+    /// specifying the join columns: (left_parent_column, right_parent_column). The anti-join
+    /// outputs the columns of its left parent.
+    pub fn new(left: NodeIndex, right: NodeIndex, on: (usize, usize)) -> Self {
+        Self {
+            left: left.into(),
+            right: right.into(),
+            on,
+        }
+    }
+}
+
+impl Ingredient for AntiJoin {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.left.as_global(), self.right.as_global()]
+    }
+
+    fn is_join(&self) -> bool {
+        true
+    }
+
+    fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
+        // all our records come from the left
+        Some(Some(self.left.as_global()).into_iter().collect())
+    }
+
+    fn on_connected(&mut self, _g: &Graph) {}
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.left.remap(remap);
+        self.right.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        nodes: &DomainNodes,
+        state: &StateMap,
+    ) -> ProcessingResult {
+        if rs.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
+        let from_left = from == *self.left;
+        let (other, from_key, other_key) = if from_left {
+            (*self.right, self.on.0, self.on.1)
+        } else {
+            (*self.left, self.on.1, self.on.0)
+        };
+
+        // handle all the records with the same key at once, so that each key is looked up once
+        let mut rs: Vec<_> = rs.into();
+        rs.sort_by(|a, b| a[from_key].cmp(&b[from_key]));
+
+        let mut out = Vec::new();
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+        let mut rs = rs.into_iter().peekable();
+        while let Some(r) = rs.next() {
+            let key = r[from_key].clone();
+            let mut group = vec![r];
+            while let Some(r) = rs.peek() {
+                if r[from_key] != key {
+                    break;
+                }
+                group.push(rs.next().unwrap());
+            }
+
+            // the number of right records that match this key, after this batch
+            let matches = if from_left {
+                self.lookup(other, &[other_key], &KeyType::Single(&key), nodes, state)
+            } else {
+                self.lookup(from, &[from_key], &KeyType::Single(&key), nodes, state)
+            }
+            .unwrap();
+            let matches = match matches {
+                Some(matches) => matches.count(),
+                None if from_left => {
+                    misses.extend(group.into_iter().map(|r| Miss {
+                        on: other,
+                        lookup_idx: vec![other_key],
+                        lookup_cols: vec![from_key],
+                        replay_cols: replay_key_cols.map(Vec::from),
+                        record: r.extract().0,
+                    }));
+                    continue;
+                }
+                None => {
+                    // we got something from right, but that row's key is not in right. as in
+                    // `Join`, this is a partial replay of some other key, and we can ignore it.
+                    continue;
+                }
+            };
+
+            if from_left {
+                if replay_key_cols.is_some() {
+                    lookups.push(Lookup {
+                        on: other,
+                        cols: vec![other_key],
+                        key: vec![key.clone()],
+                    });
+                }
+                if matches == 0 {
+                    out.extend(group);
+                }
+                continue;
+            }
+
+            let before = group.iter().fold(matches as isize, |n, r| {
+                if r.is_positive() {
+                    n - 1
+                } else {
+                    n + 1
+                }
+            });
+            let positive = match (before == 0, matches == 0) {
+                (true, false) => false,
+                (false, true) => true,
+                _ => continue,
+            };
+
+            // the first match for this key appeared, or the last one disappeared
+            match self
+                .lookup(other, &[other_key], &KeyType::Single(&key), nodes, state)
+                .unwrap()
+            {
+                Some(lefts) => {
+                    out.extend(lefts.map(|l| (l.into_owned(), positive).into()));
+                }
+                None => {
+                    misses.extend(group.into_iter().map(|r| Miss {
+                        on: other,
+                        lookup_idx: vec![other_key],
+                        lookup_cols: vec![from_key],
+                        replay_cols: None,
+                        record: r.extract().0,
+                    }));
+                }
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            lookups,
+            misses,
+        }
+    }
+
+    fn suggest_indexes(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![
+            (self.left.as_global(), vec![self.on.0]),
+            (self.right.as_global(), vec![self.on.1]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        Some(vec![(self.left.as_global(), col)])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("▷");
+        }
+
+        format!(
+            "{}:{} ▷ {}:{}",
+            self.left.as_global().index(),
+            self.on.0,
+            self.right.as_global().index(),
+            self.on.1
+        )
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        vec![(self.left.as_global(), Some(col))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let j = AntiJoin::new(l.as_global(), r.as_global(), (0, 0));
+        g.set_op("antijoin", &["j0", "j1"], j, false);
+        (g, l, r)
+    }
+
+    #[test]
+    fn it_describes() {
+        let (j, l, r) = setup();
+        assert_eq!(j.node().description(true), format!("{}:0 ▷ {}:0", l, r));
+    }
+
+    #[test]
+    fn it_emits_unmatched_lefts() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_b2 = vec![2.into(), "b".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+
+        j.seed(r, r_x1.clone());
+        j.seed(l, l_a1.clone());
+        j.seed(l, l_b2.clone());
+        let rs = j.one(l, vec![l_a1.clone(), l_b2.clone()], false);
+        assert_eq!(rs, vec![(l_b2.clone(), true)].into());
+
+        // removing an unmatched left record revokes it
+        let rs = j.one_row(l, (l_b2.clone(), false), false);
+        assert_eq!(rs, vec![(l_b2.clone(), false)].into());
+    }
+
+    #[test]
+    fn it_revokes_on_first_match() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_b1 = vec![1.into(), "b".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y1 = vec![1.into(), "y".into()];
+
+        j.seed(l, l_a1.clone());
+        j.seed(l, l_b1.clone());
+        j.one(l, vec![l_a1.clone(), l_b1.clone()], false);
+
+        // the first match revokes all the left records with its key
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_negative(&l_a1[..]));
+        assert!(rs.has_negative(&l_b1[..]));
+
+        // and later matches change nothing
+        j.seed(r, r_y1.clone());
+        let rs = j.one_row(r, r_y1.clone(), false);
+        assert!(rs.is_empty());
+    }
+
+    #[test]
+    fn it_restores_on_last_match() {
+        let (mut j, l, r) = setup();
+        let l_a1 = vec![1.into(), "a".into()];
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y1 = vec![1.into(), "y".into()];
+
+        j.seed(l, l_a1.clone());
+        j.seed(r, r_x1.clone());
+        j.seed(r, r_y1.clone());
+
+        // removing one of two matches changes nothing
+        j.unseed_row(r, r_x1.clone());
+        let rs = j.one_row(r, (r_x1.clone(), false), false);
+        assert!(rs.is_empty());
+
+        // removing the last one brings the left record back
+        j.unseed_row(r, r_y1.clone());
+        let rs = j.one_row(r, (r_y1.clone(), false), false);
+        assert_eq!(rs, vec![(l_a1.clone(), true)].into());
+
+        // a batch that replaces the last match with another revokes it once, and changes nothing
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(rs, vec![(l_a1.clone(), false)].into());
+        j.unseed_row(r, r_x1.clone());
+        j.seed(r, r_y1.clone());
+        let rs = j.one(r, vec![(r_x1.clone(), false), (r_y1.clone(), true)], false);
+        assert!(rs.is_empty());
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 2.into();
+        let (g, l, r) = setup();
+        let hm: HashMap<_, _> = vec![
+            (l.as_global(), vec![0]), /* join column for left */
+            (r.as_global(), vec![0]), /* join column for right */
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(g.node().suggest_indexes(me), hm);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, l, _) = setup();
+        assert_eq!(g.node().resolve(0), Some(vec![(l.as_global(), 0)]));
+        assert_eq!(g.node().resolve(1), Some(vec![(l.as_global(), 1)]));
+    }
+}
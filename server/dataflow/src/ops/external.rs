@@ -0,0 +1,698 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// How long to wait for the external service before considering a lookup failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many keys may wait to be looked up before more are turned away, to be asked for again later.
+const MAX_QUEUED_LOOKUPS: usize = 1024;
+
+/// How long a cached response is kept past its `staleness`, as a fallback for failed lookups.
+/// Older responses are evicted, so that the cache does not grow with every key ever seen.
+const STALE_FALLBACK: Duration = Duration::from_secs(60);
+
+/// What an `ExternalLookup` does with a row whose lookup fails.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Drop the row.
+    Drop,
+    /// Emit the row with `NULL` in every enriched column.
+    Null,
+}
+
+/// A cached response from the external service. `None` means the service does not know the key.
+#[derive(Debug, Clone)]
+struct Cached {
+    value: Option<Vec<DataType>>,
+    fetched: Instant,
+}
+
+/// A key, and what looking it up returned.
+type Lookup = (DataType, Result<Option<Vec<DataType>>, String>);
+
+/// Looks keys up on a thread of its own, so that the domain never waits for the service.
+///
+/// The thread is started by the first lookup, and exits once the fetcher is dropped.
+#[derive(Default)]
+struct Fetcher {
+    running: Option<(mpsc::SyncSender<DataType>, mpsc::Receiver<Lookup>)>,
+}
+
+impl Fetcher {
+    /// Ask for `key` to be looked up at `url`, which fails if too many lookups are queued.
+    fn request(&mut self, url: &str, columns: usize, key: &DataType) -> bool {
+        let (requests, _) = self.running.get_or_insert_with(|| {
+            let (requests, rx) = mpsc::sync_channel::<DataType>(MAX_QUEUED_LOOKUPS);
+            let (tx, done) = mpsc::channel();
+            let url = url.to_owned();
+            thread::Builder::new()
+                .name(String::from("external-lookup"))
+                .spawn(move || {
+                    for key in rx {
+                        let value = fetch(&url, &key, columns);
+                        if tx.send((key, value)).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .unwrap();
+            (requests, done)
+        });
+
+        match requests.try_send(key.clone()) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => false,
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                // the next request starts a new thread
+                self.running = None;
+                false
+            }
+        }
+    }
+
+    /// The lookups that have finished since the last call.
+    fn finished(&mut self) -> Vec<Lookup> {
+        match self.running {
+            Some((_, ref done)) => done.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Clone for Fetcher {
+    fn clone(&self) -> Self {
+        Fetcher::default()
+    }
+}
+
+impl fmt::Debug for Fetcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Fetcher")
+            .field("running", &self.running.is_some())
+            .finish()
+    }
+}
+
+/// Enriches rows with columns fetched from an external keyed HTTP service.
+///
+/// For a row whose key column holds `k`, the operator issues `GET <url>/<k>`, and expects a JSON
+/// array with one value for each enriched column in response. Keys the service answers with
+/// `404 Not Found` for get `NULL` in every enriched column, like a left join.
+///
+/// Lookups happen off the domain thread. A row whose key has no fresh cached response is held
+/// back until its lookup finishes, and is then emitted through `take_deferred`. Responses,
+/// including negative ones, are cached for `staleness`. If a lookup fails, a stale cached
+/// response is used if there is one, and `on_failure` is applied if there is not. Stale responses
+/// are kept for at most a minute before they are evicted.
+///
+/// The operator is fully materialized, and retractions are looked up in its own state by key, so
+/// that they match the row that was originally emitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalLookup {
+    us: Option<IndexPair>,
+    src: IndexPair,
+    key: usize,
+    url: String,
+    columns: usize,
+    staleness: Duration,
+    on_failure: FailurePolicy,
+    cols: usize,
+
+    #[serde(skip)]
+    cache: HashMap<DataType, Cached>,
+    /// When responses that are too old to fall back on were last evicted from the cache.
+    #[serde(skip)]
+    evicted: Option<Instant>,
+    /// Rows held back until the lookup of their key finishes, by key.
+    #[serde(skip)]
+    waiting: HashMap<DataType, Vec<Vec<DataType>>>,
+    /// Keys that the fetcher has been asked to look up.
+    #[serde(skip)]
+    requested: HashSet<DataType>,
+    #[serde(skip)]
+    fetcher: Fetcher,
+}
+
+impl ExternalLookup {
+    /// Construct a new external lookup operator.
+    ///
+    /// Rows from `src` are looked up by their `key` column at `url`, and are extended with the
+    /// `columns` values the service responds with.
+    pub fn new(
+        src: NodeIndex,
+        key: usize,
+        url: &str,
+        columns: usize,
+        staleness: Duration,
+        on_failure: FailurePolicy,
+    ) -> ExternalLookup {
+        ExternalLookup {
+            us: None,
+            src: src.into(),
+            key,
+            url: url.trim_end_matches('/').to_owned(),
+            columns,
+            staleness,
+            on_failure,
+            cols: 0,
+            cache: HashMap::new(),
+            evicted: None,
+            waiting: HashMap::new(),
+            requested: HashSet::new(),
+            fetcher: Fetcher::default(),
+        }
+    }
+
+    fn fill(&self, value: Option<Vec<DataType>>) -> Vec<DataType> {
+        value.unwrap_or_else(|| vec![DataType::None; self.columns])
+    }
+
+    /// Evict the cached responses that are too old to fall back on, at most once every
+    /// `STALE_FALLBACK`.
+    fn evict(&mut self) {
+        if self.evicted.map_or(false, |t| t.elapsed() < STALE_FALLBACK) {
+            return;
+        }
+        let keep = self.staleness + STALE_FALLBACK;
+        self.cache
+            .retain(|_, cached| cached.fetched.elapsed() < keep);
+        self.evicted = Some(Instant::now());
+    }
+
+    /// Have `key` looked up, unless it already is being.
+    fn request(&mut self, key: &DataType) {
+        if !self.requested.contains(key) && self.fetcher.request(&self.url, self.columns, key) {
+            self.requested.insert(key.clone());
+        }
+    }
+
+    /// Emit `row` enriched from the cache, or hold it back until its key has been looked up.
+    fn enrich(&mut self, mut row: Vec<DataType>, results: &mut Vec<Record>) {
+        let key = row[self.key].clone();
+        if let Some(waiting) = self.waiting.get_mut(&key) {
+            // rows must not overtake the earlier rows with the same key
+            waiting.push(row);
+            return;
+        }
+
+        if let Some(cached) = self.cache.get(&key) {
+            if cached.fetched.elapsed() < self.staleness {
+                let value = cached.value.clone();
+                row.extend(self.fill(value));
+                results.push(Record::Positive(row));
+                return;
+            }
+        }
+
+        self.request(&key);
+        self.waiting.entry(key).or_default().push(row);
+    }
+
+    /// Retract `row` as it was emitted, if it was.
+    fn retract(&mut self, row: Vec<DataType>, results: &mut Vec<Record>, states: &StateMap) {
+        let key = row[self.key].clone();
+        if let Some(waiting) = self.waiting.get_mut(&key) {
+            if let Some(i) = waiting.iter().position(|r| *r == row) {
+                // the row has not been emitted yet, so there is nothing to retract
+                waiting.remove(i);
+                if waiting.is_empty() {
+                    self.waiting.remove(&key);
+                }
+                return;
+            }
+        }
+
+        let cols = self.cols;
+        let emitted = results
+            .iter()
+            .rev()
+            .find(|r| r.is_positive() && r[..cols] == row[..])
+            .map(|r| r[cols..].to_vec())
+            .or_else(|| {
+                let state = states
+                    .get(*self.us.unwrap())
+                    .expect("ExternalLookup must have its own state initialized");
+                match state.lookup(&[self.key], &KeyType::Single(&key)) {
+                    LookupResult::Some(rs) => rs
+                        .into_iter()
+                        .find(|r| r[..cols] == row[..])
+                        .map(|r| r[cols..].to_vec()),
+                    LookupResult::Missing => unreachable!("ExternalLookup is fully materialized"),
+                }
+            });
+
+        // a row that is not there was dropped when its lookup failed
+        if let Some(enriched) = emitted {
+            let mut row = row;
+            row.extend(enriched);
+            results.push(Record::Negative(row));
+        }
+    }
+}
+
+/// Look up `key` at `url`.
+///
+/// Returns `Ok(None)` if the service does not know the key.
+fn fetch(url: &str, key: &DataType, columns: usize) -> Result<Option<Vec<DataType>>, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported url {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let addr = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let key = match *key {
+        DataType::Text(..) | DataType::TinyText(..) => <&str>::from(key).to_owned(),
+        ref k => k.to_string(),
+    };
+    let request = format!(
+        "GET {}/{} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+        path,
+        percent_encode(&key),
+        host
+    );
+
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("could not resolve {}", host))?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, REQUEST_TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| e.to_string())?;
+
+    let response = String::from_utf8_lossy(&response);
+    let mut parts = response.splitn(2, "\r\n\r\n");
+    let head = parts.next().unwrap();
+    let body = parts.next().unwrap_or("");
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| "malformed response".to_owned())?;
+    match status {
+        "200" => {}
+        "404" => return Ok(None),
+        s => return Err(format!("lookup failed with status {}", s)),
+    }
+
+    let values: Vec<serde_json::Value> = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    if values.len() != columns {
+        return Err(format!(
+            "expected {} values in response, got {}",
+            columns,
+            values.len()
+        ));
+    }
+
+    values
+        .into_iter()
+        .map(|v| match v {
+            serde_json::Value::Null => Ok(DataType::None),
+            serde_json::Value::Bool(b) => Ok(DataType::from(b as i32)),
+            serde_json::Value::Number(ref n) if n.is_i64() => Ok(n.as_i64().unwrap().into()),
+            serde_json::Value::Number(ref n) if n.is_u64() => Ok(n.as_u64().unwrap().into()),
+            serde_json::Value::Number(n) => Ok(n.as_f64().unwrap().into()),
+            serde_json::Value::String(s) => Ok(s.into()),
+            v => Err(format!("cannot use {} as a column value", v)),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl Ingredient for ExternalLookup {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        self.cols = g[self.src.as_global()].fields().len();
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        states: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        let mut results = Vec::new();
+        for r in rs {
+            let (row, positive) = r.extract();
+            if positive {
+                self.enrich(row, &mut results);
+            } else {
+                self.retract(row, &mut results, states);
+            }
+        }
+
+        ProcessingResult {
+            results: results.into(),
+            ..Default::default()
+        }
+    }
+
+    fn has_deferred(&self) -> bool {
+        !self.waiting.is_empty()
+    }
+
+    fn take_deferred(&mut self) -> Records {
+        let mut results = Vec::new();
+        for (key, value) in self.fetcher.finished() {
+            self.requested.remove(&key);
+            let value = match value {
+                Ok(value) => {
+                    let fetched = Instant::now();
+                    self.cache.insert(
+                        key.clone(),
+                        Cached {
+                            value: value.clone(),
+                            fetched,
+                        },
+                    );
+                    Some(value)
+                }
+                // a stale answer is better than none
+                Err(_) => self.cache.get(&key).map(|cached| cached.value.clone()),
+            };
+
+            let rows = match self.waiting.remove(&key) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let enriched = match (value, self.on_failure) {
+                (Some(value), _) => self.fill(value),
+                (None, FailurePolicy::Null) => self.fill(None),
+                (None, FailurePolicy::Drop) => continue,
+            };
+            results.extend(rows.into_iter().map(|mut row| {
+                row.extend(enriched.iter().cloned());
+                Record::Positive(row)
+            }));
+        }
+
+        self.evict();
+
+        // keys that were turned away when too many lookups were queued are asked for again
+        let unrequested: Vec<_> = self
+            .waiting
+            .keys()
+            .filter(|&key| !self.requested.contains(key))
+            .cloned()
+            .collect();
+        for key in unrequested {
+            self.request(&key);
+        }
+
+        results.into()
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![(this, vec![self.key])].into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col < self.cols {
+            Some(vec![(self.src.as_global(), col)])
+        } else {
+            None
+        }
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("⇝");
+        }
+
+        format!("⇝ {}/[{}]", self.url, self.key)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column < self.cols {
+            vec![(self.src.as_global(), Some(column))]
+        } else {
+            vec![(self.src.as_global(), None)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Start a service that knows key 1, and count the requests it receives. The service answers
+    /// with the number of requests it has received so far.
+    fn serve() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dim", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let r = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                let count = r.fetch_add(1, Ordering::SeqCst) + 1;
+                let response = if buf[..n].starts_with(b"GET /dim/1 ") {
+                    format!("HTTP/1.0 200 OK\r\n\r\n[\"one\", {}]", count)
+                } else {
+                    String::from("HTTP/1.0 404 Not Found\r\n\r\n")
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn setup(url: &str, staleness: Duration, on_failure: FailurePolicy) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "enrich",
+            &["x", "y", "name", "n"],
+            ExternalLookup::new(s.as_global(), 0, url, 2, staleness, on_failure),
+            true,
+        );
+        g
+    }
+
+    /// Wait for the operator to emit all the rows it held back for their lookups.
+    fn settle(g: &mut ops::test::MockGraph) -> Records {
+        let start = Instant::now();
+        let mut rs = Vec::new();
+        while g.node().has_deferred() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "lookups never finished"
+            );
+            thread::sleep(Duration::from_millis(10));
+            rs.extend(g.deferred(true));
+        }
+        rs.into()
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup(
+            "http://localhost/dim",
+            Duration::from_secs(1),
+            FailurePolicy::Drop,
+        );
+        assert_eq!(g.node().description(true), "⇝ http://localhost/dim/[0]");
+    }
+
+    #[test]
+    fn it_evicts_responses_too_old_to_fall_back_on() {
+        let mut op = ExternalLookup::new(
+            NodeIndex::new(0),
+            0,
+            "http://localhost/dim",
+            2,
+            Duration::from_secs(1),
+            FailurePolicy::Drop,
+        );
+        let now = Instant::now();
+        let old = now - (Duration::from_secs(1) + STALE_FALLBACK);
+        op.cache.insert(
+            1.into(),
+            Cached {
+                value: None,
+                fetched: now,
+            },
+        );
+        op.cache.insert(
+            2.into(),
+            Cached {
+                value: None,
+                fetched: old,
+            },
+        );
+        op.evict();
+        assert!(op.cache.contains_key(&1.into()));
+        assert!(!op.cache.contains_key(&2.into()));
+    }
+
+    #[test]
+    fn it_enriches() {
+        let (url, _) = serve();
+        let mut g = setup(&url, Duration::from_secs(60), FailurePolicy::Drop);
+
+        // rows are held back rather than waiting for the service
+        assert!(g
+            .narrow_one_row(vec![1.into(), "a".into()], true)
+            .is_empty());
+        assert_eq!(
+            settle(&mut g),
+            vec![vec![1.into(), "a".into(), "one".into(), 1.into()]].into()
+        );
+
+        assert!(g
+            .narrow_one_row(vec![2.into(), "b".into()], true)
+            .is_empty());
+        assert_eq!(
+            settle(&mut g),
+            vec![vec![2.into(), "b".into(), DataType::None, DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_caches() {
+        let (url, requests) = serve();
+        let mut g = setup(&url, Duration::from_secs(60), FailurePolicy::Drop);
+        g.narrow_one_row(vec![1.into(), "a".into()], true);
+        g.narrow_one_row(vec![1.into(), "b".into()], true);
+        g.narrow_one_row(vec![2.into(), "c".into()], true);
+        g.narrow_one_row(vec![2.into(), "d".into()], true);
+        assert_eq!(settle(&mut g).len(), 4);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // cached keys are enriched right away
+        assert_eq!(
+            g.narrow_one_row(vec![1.into(), "e".into()], true),
+            vec![vec![1.into(), "e".into(), "one".into(), 1.into()]].into()
+        );
+
+        // stale entries are refetched
+        let mut g = setup(&url, Duration::from_secs(0), FailurePolicy::Drop);
+        g.narrow_one_row(vec![1.into(), "a".into()], true);
+        settle(&mut g);
+        g.narrow_one_row(vec![1.into(), "b".into()], true);
+        settle(&mut g);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn it_retracts_what_it_emitted() {
+        let (url, _) = serve();
+        let mut g = setup(&url, Duration::from_secs(0), FailurePolicy::Drop);
+        g.narrow_one_row(vec![1.into(), "a".into()], true);
+        settle(&mut g);
+        g.narrow_one_row(vec![1.into(), "b".into()], true);
+        assert_eq!(
+            settle(&mut g),
+            vec![vec![1.into(), "b".into(), "one".into(), 2.into()]].into()
+        );
+
+        // the service now answers differently, but the row goes as it came
+        assert_eq!(
+            g.narrow_one_row((vec![1.into(), "a".into()], false), true),
+            vec![(vec![1.into(), "a".into(), "one".into(), 1.into()], false)].into()
+        );
+
+        // a row that is retracted while it is held back is never emitted
+        g.narrow_one_row(vec![1.into(), "c".into()], true);
+        assert!(g
+            .narrow_one_row((vec![1.into(), "c".into()], false), true)
+            .is_empty());
+        assert!(settle(&mut g).is_empty());
+    }
+
+    #[test]
+    fn it_applies_failure_policy() {
+        // nothing listens on the discard port
+        let url = "http://127.0.0.1:9/dim";
+
+        let mut g = setup(url, Duration::from_secs(60), FailurePolicy::Drop);
+        g.narrow_one_row(vec![1.into(), "a".into()], true);
+        assert!(settle(&mut g).is_empty());
+        // nothing is retracted for a row that was dropped
+        assert!(g
+            .narrow_one_row((vec![1.into(), "a".into()], false), true)
+            .is_empty());
+
+        let mut g = setup(url, Duration::from_secs(60), FailurePolicy::Null);
+        g.narrow_one_row(vec![1.into(), "a".into()], true);
+        assert_eq!(
+            settle(&mut g),
+            vec![vec![1.into(), "a".into(), DataType::None, DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(
+            "http://localhost/dim",
+            Duration::from_secs(1),
+            FailurePolicy::Drop,
+        );
+        assert_eq!(
+            g.node().resolve(0),
+            Some(vec![(g.narrow_base_id().as_global(), 0)])
+        );
+        assert_eq!(g.node().resolve(2), None);
+    }
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::sync;
 
+use crate::ops::project::{self, ProjectExpression};
 use crate::prelude::*;
 pub use nom_sql::Operator;
 
@@ -38,6 +39,60 @@ impl Display for Value {
 pub enum FilterCondition {
     Comparison(Operator, Value),
     In(Vec<DataType>),
+    /// Holds if the expression, computed over the whole record, is neither 0 nor `NULL`.
+    ///
+    /// This is how conditions on computed values, like `DATE(created) = '2020-01-01'`, are
+    /// expressed. The column such a condition is paired with is only used to describe it, and is
+    /// by convention the first column the expression reads.
+    Expression(ProjectExpression),
+}
+
+impl FilterCondition {
+    /// Whether the value `d` of the record `r` satisfies this condition.
+    ///
+    /// Comparisons with `NULL` are neither true nor false, and so never match, with the exception
+    /// of (in)equality with a `NULL` constant, which is how `IS NULL` and `IS NOT NULL` are
+    /// expressed.
+    pub(crate) fn matches(&self, d: &DataType, r: &[DataType]) -> bool {
+        match *self {
+            FilterCondition::Comparison(ref op, Value::Constant(DataType::None)) => match *op {
+                Operator::Equal => d.is_none(),
+                Operator::NotEqual => !d.is_none(),
+                _ => false,
+            },
+            FilterCondition::Comparison(ref op, ref f) => {
+                let v = match *f {
+                    Value::Constant(ref dt) => dt,
+                    Value::Column(c) => &r[c],
+                };
+                if d.is_none() || v.is_none() {
+                    return false;
+                }
+                let (d, v) = project::comparable(d, v);
+                match *op {
+                    Operator::Equal => d == v,
+                    Operator::NotEqual => d != v,
+                    Operator::Greater => d > v,
+                    Operator::GreaterOrEqual => d >= v,
+                    Operator::Less => d < v,
+                    Operator::LessOrEqual => d <= v,
+                    Operator::In => unreachable!(),
+                    _ => unimplemented!(),
+                }
+            }
+            FilterCondition::In(ref fs) => !d.is_none() && fs.contains(d),
+            FilterCondition::Expression(ref e) => project::holds(&e.eval(r)),
+        }
+    }
+
+    /// Whether records can start or stop matching this condition as time passes, because it
+    /// depends on the current time.
+    pub fn is_time_dependent(&self) -> bool {
+        match *self {
+            FilterCondition::Expression(ref e) => e.is_time_dependent(),
+            FilterCondition::Comparison(..) | FilterCondition::In(..) => false,
+        }
+    }
 }
 
 impl Filter {
@@ -81,31 +136,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| self.filter.iter().all(|(i, cond)| cond.matches(&r[*i], r)));
 
         ProcessingResult {
             results: rs,
@@ -150,6 +181,9 @@ impl Ingredient for Filter {
                             .collect::<Vec<_>>()
                             .join(", ")
                     )),
+                    FilterCondition::Expression(ref e) => {
+                        Some(escape(&format!("{}", e)))
+                    }
                 })
                 .collect::<Vec<_>>()
                 .as_slice()
@@ -172,30 +206,8 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let filter =
+                    move |r: &[DataType]| f.iter().all(|(i, cond)| cond.matches(&r[*i], r));
 
                 match result {
                     Some(rs) => {
@@ -375,6 +387,32 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
 
+    #[test]
+    fn it_works_with_decimals() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Greater, Value::Constant(9.99.into())),
+            )]),
+        );
+        let d = |s| DataType::decimal(s).unwrap();
+
+        let mut left: Vec<DataType>;
+
+        // decimals compare with reals by value (10.00 > 9.99)
+        left = vec![d("10.00"), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // 9.990 > 9.99 fails
+        left = vec![d("9.990"), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // 9.991 > 9.99
+        left = vec![d("9.991"), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+    }
+
     #[test]
     fn it_works_with_columns() {
         let mut g = setup(
@@ -392,6 +430,68 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
     }
 
+    #[test]
+    fn it_works_with_nulls() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::NotEqual, Value::Constant(2.into())),
+            )]),
+        );
+
+        // NULL != 2 is unknown, and so does not match
+        let mut left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+        left = vec![3.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // NULL = NULL is also unknown
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Column(1)),
+            )]),
+        );
+        left = vec![DataType::None, DataType::None];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // x IS NULL
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::Equal, Value::Constant(DataType::None)),
+            )]),
+        );
+        left = vec![DataType::None, "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![1.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // x IS NOT NULL
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(Operator::NotEqual, Value::Constant(DataType::None)),
+            )]),
+        );
+        left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+        left = vec![1.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // NULL IN (NULL, 1) is unknown
+        let mut g = setup(
+            false,
+            Some(&[(0, FilterCondition::In(vec![DataType::None, 1.into()]))]),
+        );
+        left = vec![DataType::None, "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
     #[test]
     fn it_works_with_in_list() {
         let mut g = setup(
@@ -420,4 +520,34 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+
+    #[test]
+    fn it_works_with_expressions() {
+        use crate::ops::project::BuiltinFunction;
+
+        // WHERE DATE(y) = '2020-01-01'
+        let on_day = ProjectExpression::Compare {
+            op: Operator::Equal,
+            left: Box::new(ProjectExpression::Call(
+                BuiltinFunction::Date,
+                vec![ProjectExpression::Column(1)],
+            )),
+            right: Box::new(ProjectExpression::Literal(DataType::Timestamp(
+                "2020-01-01T00:00:00".parse().unwrap(),
+            ))),
+        };
+        let mut g = setup(false, Some(&[(1, FilterCondition::Expression(on_day))]));
+
+        let mut left: Vec<DataType>;
+
+        left = vec![1.into(), "2020-01-01 10:11:12".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![1.into(), "2020-01-02 10:11:12".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // NULL is not on any day
+        left = vec![1.into(), DataType::None];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
 }
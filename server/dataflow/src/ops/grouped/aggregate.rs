@@ -1,3 +1,4 @@
+use crate::ops::grouped::distinct_aggregate::DistinctAggregator;
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 
@@ -8,7 +9,7 @@ use crate::prelude::*;
 pub enum Aggregation {
     /// Count the number of records for each group. The value for the `over` column is ignored.
     COUNT,
-    /// Sum the value of the `over` column for all records of each group.
+    /// Sum the value of the `over` column for all records of each group, ignoring `NULL`s.
     SUM,
 }
 
@@ -17,7 +18,7 @@ impl Aggregation {
     ///
     /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
     /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
-    /// identifier. The `over` column should not be in the `group_by` array.
+    /// identifier. The `over` column should not be in the `group_by` array, unless counting.
     pub fn over(
         self,
         src: NodeIndex,
@@ -25,7 +26,7 @@ impl Aggregation {
         group_by: &[usize],
     ) -> GroupedOperator<Aggregator> {
         assert!(
-            !group_by.iter().any(|&i| i == over),
+            self == Aggregation::COUNT || !group_by.iter().any(|&i| i == over),
             "cannot group by aggregation column"
         );
         GroupedOperator::new(
@@ -37,6 +38,26 @@ impl Aggregation {
             },
         )
     }
+
+    /// Construct a new `DistinctAggregator` that performs this operation over distinct values.
+    ///
+    /// The aggregation will aggregate the distinct values in column number `over` from its inputs,
+    /// and use the columns in the `group_by` array as a group identifier. The last column of `src`
+    /// must hold the number of times each value of `over` occurs in its group, as the output of a
+    /// `COUNT` grouped by both `group_by` and `over` does. The `over` column should not be in the
+    /// `group_by` array.
+    pub fn over_distinct(
+        self,
+        src: NodeIndex,
+        over: usize,
+        group_by: &[usize],
+    ) -> GroupedOperator<DistinctAggregator> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(src, DistinctAggregator::new(self, over, group_by.into()))
+    }
 }
 
 /// Aggregator implementas a Soup node that performans common aggregation operations such as counts
@@ -60,7 +81,8 @@ pub struct Aggregator {
 }
 
 impl GroupedOperation for Aggregator {
-    type Diff = i128;
+    /// The exact value to add to the group's value, as returned by `DataType::to_decimal`.
+    type Diff = (i128, u8);
 
     fn setup(&mut self, parent: &Node) {
         assert!(
@@ -75,21 +97,19 @@ impl GroupedOperation for Aggregator {
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
+            Aggregation::COUNT if pos => (1, 0),
+            Aggregation::COUNT => (-1, 0),
             Aggregation::SUM => {
-                let v = match r[self.over] {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    DataType::None => 0,
-                    ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
+                let (v, scale) = match r[self.over] {
+                    DataType::None => (0, 0),
+                    ref x => x.to_decimal().unwrap_or_else(|| {
+                        unreachable!("tried to aggregate over {:?} on {:?}", x, r)
+                    }),
                 };
                 if pos {
-                    v
+                    (v, scale)
                 } else {
-                    0i128 - v
+                    (0i128 - v, scale)
                 }
             }
         }
@@ -101,14 +121,10 @@ impl GroupedOperation for Aggregator {
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
         let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
-            _ => unreachable!(),
+            Some(dt) => dt.to_decimal().unwrap(),
+            None => (0, 0),
         };
-        diffs.fold(n, |n, d| n + d).into()
+        super::exact_value(diffs.fold(n, super::add_exact))
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -387,7 +403,78 @@ mod tests {
         }
     }
 
-    // TODO: also test SUM
+    #[test]
+    fn it_sums_values_that_are_not_null() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+
+        let rs = g.narrow_one_row(vec![DataType::None, 2.into()], true);
+        assert_eq!(rs, vec![(vec![DataType::None, 2.into()], true)].into());
+
+        // NULLs are not added
+        let rs = g.narrow_one_row(vec![DataType::None, DataType::None], true);
+        assert!(rs.is_empty());
+
+        // and records whose group is NULL are all in the same group
+        let rs = g.narrow_one_row(vec![DataType::None, 3.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![DataType::None, 2.into()], false),
+                (vec![DataType::None, 5.into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_sums_decimals() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "sum",
+            &["x", "ys"],
+            Aggregation::SUM.over(s.as_global(), 1, &[0]),
+            true,
+        );
+        let d = |s| DataType::decimal(s).unwrap();
+
+        let rs = g.narrow_one_row(vec![1.into(), d("0.10")], true);
+        assert_eq!(rs, vec![(vec![1.into(), d("0.10")], true)].into());
+
+        // ten cents twenty times is exactly two dollars, which floats don't manage
+        let rows: Vec<_> = (0..19).map(|_| (vec![1.into(), d("0.10")], true)).collect();
+        let rs = g.narrow_one(rows, true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), d("0.10")], false),
+                (vec![1.into(), DataType::Decimal(200, 2)], true),
+            ]
+            .into()
+        );
+
+        // integers are added exactly, and the sum keeps the digits of the decimals
+        let rs = g.narrow_one_row(vec![1.into(), 3.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), d("2.00")], false),
+                (vec![1.into(), d("5.00")], true),
+            ]
+            .into()
+        );
+        match rs.into_iter().last().unwrap() {
+            Record::Positive(r) => assert_eq!(r[1].to_string(), "5.00"),
+            _ => unreachable!(),
+        }
+    }
 
     #[test]
     fn it_suggests_indices() {
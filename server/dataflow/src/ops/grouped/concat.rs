@@ -1,6 +1,7 @@
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 
+use std::borrow::Cow;
 use std::collections::HashSet;
 
 use crate::prelude::*;
@@ -18,6 +19,8 @@ pub enum TextComponent {
 pub enum Modify {
     Add(String),
     Remove(String),
+    /// A record with a NULL in one of the emitted columns, which is left out of its group.
+    Null,
 }
 
 /// `GroupConcat` joins multiple records into one using string concatenation.
@@ -39,10 +42,18 @@ pub enum Modify {
 /// is the primary reason for the "separator as sentinel" behavior mentioned above, and may be made
 /// optional in the future such that more efficient incremental updating and relaxed separator
 /// semantics can be implemented.
+///
+/// Records that are the same are all kept, and removing one of them only removes one copy of its
+/// string representation. As in SQL, records with a NULL in one of the emitted columns are left
+/// out. Rather than a delimited string, a `GroupConcat` made with `GroupConcat::list` emits a JSON
+/// array of the strings, which needs no sentinel.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupConcat {
     components: Vec<TextComponent>,
     separator: String,
+    /// Whether the strings are emitted as a JSON array rather than joined by `separator`.
+    #[serde(default)]
+    list: bool,
     group: Vec<usize>,
     slen: usize,
 }
@@ -75,36 +86,96 @@ impl GroupConcat {
             GroupConcat {
                 components,
                 separator,
+                list: false,
                 group: Vec::new(),
                 slen: 0,
             },
         )
     }
 
-    fn build(&self, rec: &[DataType]) -> String {
+    /// Construct a new `GroupConcat` operator that emits the string representations of the
+    /// records of each group as a JSON array, in order, rather than joining them.
+    ///
+    /// Groups and string representations are as for `GroupConcat::new`, but the strings may
+    /// contain anything.
+    pub fn list(src: NodeIndex, components: Vec<TextComponent>) -> GroupedOperator<GroupConcat> {
+        GroupedOperator::new(
+            src,
+            GroupConcat {
+                components,
+                separator: String::new(),
+                list: true,
+                group: Vec::new(),
+                slen: 0,
+            },
+        )
+    }
+
+    /// The string representation of `rec`, or `None` if one of the emitted columns is NULL.
+    fn build(&self, rec: &[DataType]) -> Option<String> {
         let mut s = String::with_capacity(self.slen);
         for tc in &self.components {
             match *tc {
                 TextComponent::Literal(ref l) => {
                     s.push_str(l);
                 }
-                TextComponent::Column(ref i) => match rec[*i] {
-                    DataType::Text(..) | DataType::TinyText(..) => {
-                        let text: &str = (&rec[*i]).into();
-                        s.push_str(text);
-                    }
-                    DataType::Int(ref n) => s.push_str(&n.to_string()),
-                    DataType::UnsignedInt(ref n) => s.push_str(&n.to_string()),
-                    DataType::BigInt(ref n) => s.push_str(&n.to_string()),
-                    DataType::UnsignedBigInt(ref n) => s.push_str(&n.to_string()),
-                    DataType::Real(..) => s.push_str(&rec[*i].to_string()),
-                    DataType::Timestamp(ref ts) => s.push_str(&ts.format("%+").to_string()),
-                    DataType::None => unreachable!(),
-                },
+                TextComponent::Column(i) if rec[i].is_none() => return None,
+                TextComponent::Column(i) => s.push_str(&text(&rec[i])),
+            }
+        }
+
+        Some(s)
+    }
+
+    /// The strings that the group value `current` is made of, in order.
+    fn elements<'a>(&self, current: Option<&'a DataType>) -> Vec<Cow<'a, str>> {
+        let current = match current {
+            Some(current) => current,
+            None => return Vec::new(),
+        };
+        if self.list {
+            match current.to_json() {
+                Some(serde_json::Value::Array(vs)) => vs
+                    .into_iter()
+                    .map(|v| match v {
+                        serde_json::Value::String(s) => Cow::Owned(s),
+                        v => Cow::Owned(v.to_string()),
+                    })
+                    .collect(),
+                _ => unreachable!(),
             }
+        } else {
+            let current: &str = match current {
+                dt @ &DataType::Text(..) | dt @ &DataType::TinyText(..) => dt.into(),
+                _ => unreachable!(),
+            };
+            // TODO this is not particularly robust, and requires a non-empty separator
+            current
+                .split_terminator(&self.separator)
+                .map(Cow::Borrowed)
+                .collect()
         }
+    }
+}
 
-        s
+/// The string representation of `v` in the output of a `GroupConcat`.
+///
+/// Timestamps are formatted as in RFC 3339, and all other values as they are displayed.
+pub fn text(v: &DataType) -> Cow<'_, str> {
+    match *v {
+        DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => Cow::Borrowed(v.into()),
+        DataType::Compressed(..) => {
+            let text = v.decompress();
+            let text: &str = (&*text).into();
+            Cow::Owned(text.to_owned())
+        }
+        DataType::Int(ref n) => Cow::Owned(n.to_string()),
+        DataType::UnsignedInt(ref n) => Cow::Owned(n.to_string()),
+        DataType::BigInt(ref n) => Cow::Owned(n.to_string()),
+        DataType::UnsignedBigInt(ref n) => Cow::Owned(n.to_string()),
+        DataType::Real(..) | DataType::Decimal(..) => Cow::Owned(v.to_string()),
+        DataType::Timestamp(ref ts) => Cow::Owned(ts.format("%+").to_string()),
+        DataType::None => unreachable!(),
     }
 }
 
@@ -142,11 +213,10 @@ impl GroupedOperation for GroupConcat {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        let v = self.build(r);
-        if pos {
-            Modify::Add(v)
-        } else {
-            Modify::Remove(v)
+        match self.build(r) {
+            Some(v) if pos => Modify::Add(v),
+            Some(v) => Modify::Remove(v),
+            None => Modify::Null,
         }
     }
 
@@ -155,57 +225,40 @@ impl GroupedOperation for GroupConcat {
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
-        use std::collections::BTreeSet;
-        use std::iter::FromIterator;
-
-        // updating the value is a bit tricky because we want to retain ordering of the
-        // elements. we therefore need to first split the value, add the new ones,
-        // remove revoked ones, sort, and then join again. ugh. we try to make it more
-        // efficient by splitting into a BTree, which maintains sorting while
-        // supporting efficient add/remove.
-
-        use std::borrow::Cow;
-        let current: &str = match current {
-            Some(dt @ &DataType::Text(..)) | Some(dt @ &DataType::TinyText(..)) => dt.into(),
-            None => "",
-            _ => unreachable!(),
-        };
-        let clen = current.len();
-
-        // TODO this is not particularly robust, and requires a non-empty separator
-        let mut current = BTreeSet::from_iter(
-            current
-                .split_terminator(&self.separator)
-                .map(|s| Cow::Borrowed(s)),
-        );
+        // the strings of the current value are already in order, so each change only has to find
+        // its place among them. removing a string only removes one copy of it.
+        let mut elements = self.elements(current);
         for diff in diffs {
             match diff {
                 Modify::Add(s) => {
-                    current.insert(Cow::Owned(s));
+                    let i = match elements.binary_search_by(|e| (**e).cmp(s.as_str())) {
+                        Ok(i) | Err(i) => i,
+                    };
+                    elements.insert(i, Cow::Owned(s));
                 }
                 Modify::Remove(s) => {
-                    current.remove(&*s);
+                    if let Ok(i) = elements.binary_search_by(|e| (**e).cmp(s.as_str())) {
+                        elements.remove(i);
+                    }
                 }
+                Modify::Null => {}
             }
         }
 
-        // WHY doesn't rust have an iterator joiner?
-        let mut new = current
-            .into_iter()
-            .fold(String::with_capacity(2 * clen), |mut acc, s| {
-                acc.push_str(&*s);
-                acc.push_str(&self.separator);
-                acc
-            });
-        // we pushed one separator too many above
-        let real_len = new.len() - self.separator.len();
-        new.truncate(real_len);
-        new.into()
+        if self.list {
+            let elements = elements
+                .into_iter()
+                .map(|s| serde_json::Value::String(s.into_owned()))
+                .collect();
+            serde_json::Value::Array(elements).into()
+        } else {
+            elements.join(self.separator.as_str()).into()
+        }
     }
 
     fn description(&self, detailed: bool) -> String {
         if !detailed {
-            return String::from("CONCAT");
+            return String::from(if self.list { "ARRAY" } else { "CONCAT" });
         }
 
         let fields = self
@@ -227,7 +280,11 @@ impl GroupedOperation for GroupConcat {
             .collect::<Vec<_>>()
             .join(", ");
 
-        format!("||([{}], \"{}\") γ[{}]", fields, self.separator, group_cols)
+        if self.list {
+            format!("[]([{}]) γ[{}]", fields, group_cols)
+        } else {
+            format!("||([{}], \"{}\") γ[{}]", fields, self.separator, group_cols)
+        }
     }
 
     fn over_columns(&self) -> Vec<usize> {
@@ -370,7 +427,7 @@ mod tests {
         // multiple positives and negatives should update aggregation value by appropriate amount
         let rs = c.narrow_one(u, true);
         assert_eq!(rs.len(), 5); // one - and one + for each group, except last (new) group
-                                 // group 1 had [2], now has [1,2]
+                                 // group 1 had [2], now has [1,2,2]
         assert!(rs.iter().any(|r| if let Record::Negative(ref r) = *r {
             if r[0] == 1.into() {
                 assert_eq!(r[1], ".2;".into());
@@ -383,7 +440,7 @@ mod tests {
         }));
         assert!(rs.iter().any(|r| if let Record::Positive(ref r) = *r {
             if r[0] == 1.into() {
-                assert_eq!(r[1], ".1;#.2;".into());
+                assert_eq!(r[1], ".1;#.2;#.2;".into());
                 true
             } else {
                 false
@@ -425,6 +482,75 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn it_keeps_duplicates() {
+        let mut c = setup(true);
+        c.narrow_one_row(vec![1.into(), 1.into()], true);
+        c.narrow_one_row(vec![1.into(), 1.into()], true);
+        c.narrow_one_row(vec![1.into(), 2.into()], true);
+
+        // retracting one copy leaves the other
+        let rs = c.narrow_one_row((vec![1.into(), 1.into()], false), true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), ".1;#.1;#.2;".into()], false),
+                (vec![1.into(), ".1;#.2;".into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_skips_nulls() {
+        let mut c = setup(true);
+        c.narrow_one_row(vec![1.into(), 1.into()], true);
+
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert!(rs.is_empty());
+        let rs = c.narrow_one_row((vec![1.into(), DataType::None], false), true);
+        assert!(rs.is_empty());
+
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), ".1;".into()], false),
+                (vec![1.into(), ".1;#.2;".into()], true),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_emits_lists() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let c = GroupConcat::list(s.as_global(), vec![TextComponent::Column(1)]);
+        g.set_op("list", &["x", "ys"], c, true);
+        assert_eq!(g.node().description(true), "[]([1]) γ[0]");
+
+        // the separator of a delimited concat may appear in the strings of a list
+        g.narrow_one_row(vec![1.into(), "b#c".into()], true);
+        let rs = g.narrow_one_row(vec![1.into(), "a".into()], true);
+        let expected: DataType = serde_json::json!(["a", "b#c"]).into();
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), serde_json::json!(["b#c"]).into()], false),
+                (vec![1.into(), expected], true),
+            ]
+            .into()
+        );
+
+        let rs = g.narrow_one_row((vec![1.into(), "b#c".into()], false), true);
+        let r = match rs.into_iter().last().unwrap() {
+            Record::Positive(r) => r,
+            _ => unreachable!(),
+        };
+        assert_eq!(r[1].to_json(), Some(serde_json::json!(["a"])));
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
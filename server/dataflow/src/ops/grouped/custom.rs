@@ -0,0 +1,219 @@
+use crate::ops::grouped::GroupedOperation;
+use crate::ops::grouped::GroupedOperator;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::prelude::*;
+
+/// An incremental aggregation function that applications can plug into the dataflow.
+///
+/// Like the built-in aggregations, a `GroupedAggregator` folds the values of one column over all
+/// the records in a group into a single value. Since groups are updated incrementally, it is only
+/// ever given the group's current value and the values that were added to or removed from the
+/// group since, so any state the function needs (such as the registers of a HyperLogLog sketch)
+/// must be encoded in the value it produces.
+pub trait GroupedAggregator: Send + Sync + 'static {
+    /// Given the `current` value of a group, if it has one, and the values of the aggregated
+    /// column of records that were added to (`true`) or removed from (`false`) the group, compute
+    /// the group's updated value.
+    fn apply(
+        &self,
+        current: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = (DataType, bool)>,
+    ) -> DataType;
+}
+
+lazy_static! {
+    static ref AGGREGATORS: RwLock<HashMap<String, Arc<dyn GroupedAggregator>>> = RwLock::default();
+}
+
+/// Make `aggregator` available to `CustomAggregator` nodes as the function called `name`.
+///
+/// Function names are case-insensitive. Registering a function under a name that is already
+/// taken replaces the earlier function.
+pub fn register(name: &str, aggregator: Arc<dyn GroupedAggregator>) {
+    AGGREGATORS
+        .write()
+        .unwrap()
+        .insert(name.to_lowercase(), aggregator);
+}
+
+/// Look up the function registered as `name`.
+pub fn lookup(name: &str) -> Option<Arc<dyn GroupedAggregator>> {
+    AGGREGATORS
+        .read()
+        .unwrap()
+        .get(&name.to_lowercase())
+        .cloned()
+}
+
+/// `CustomAggregator` aggregates a column using a function registered with `register`.
+///
+/// Only the name of the function is stored in the node, so the function must be registered in
+/// every process that hosts a domain with the node in it before that domain processes any records.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomAggregator {
+    function: String,
+    over: usize,
+    group: Vec<usize>,
+}
+
+impl CustomAggregator {
+    /// Construct a new `CustomAggregator` that applies the registered function `function`.
+    ///
+    /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
+    /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
+    /// identifier. The `over` column should not be in the `group_by` array.
+    pub fn over(
+        function: &str,
+        src: NodeIndex,
+        over: usize,
+        group_by: &[usize],
+    ) -> GroupedOperator<CustomAggregator> {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        GroupedOperator::new(
+            src,
+            CustomAggregator {
+                function: function.to_lowercase(),
+                over,
+                group: group_by.into(),
+            },
+        )
+    }
+}
+
+impl GroupedOperation for CustomAggregator {
+    type Diff = (DataType, bool);
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        (r[self.over].clone(), pos)
+    }
+
+    fn apply(
+        &self,
+        current: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> DataType {
+        let f = lookup(&self.function)
+            .unwrap_or_else(|| panic!("aggregate function {} is not registered", self.function));
+        f.apply(current, diffs)
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return self.function.clone();
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({}) γ[{}]", self.function, self.over, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+    use std::collections::HashSet;
+
+    /// Counts the distinct values in each group, keeping them in the group's value.
+    struct DistinctCount;
+
+    impl GroupedAggregator for DistinctCount {
+        fn apply(
+            &self,
+            current: Option<&DataType>,
+            diffs: &mut dyn Iterator<Item = (DataType, bool)>,
+        ) -> DataType {
+            let mut seen: HashSet<String> = match current {
+                Some(dt) => {
+                    let s: &str = dt.into();
+                    s.split_terminator(',').map(String::from).collect()
+                }
+                None => HashSet::new(),
+            };
+            for (v, pos) in diffs {
+                if pos {
+                    seen.insert(v.to_string());
+                } else {
+                    seen.remove(&v.to_string());
+                }
+            }
+            let mut seen: Vec<_> = seen.into_iter().collect();
+            seen.sort();
+            seen.join(",").into()
+        }
+    }
+
+    fn setup(mat: bool) -> ops::test::MockGraph {
+        register("test_distinct", Arc::new(DistinctCount));
+
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "distinct",
+            &["x", "ys"],
+            CustomAggregator::over("TEST_DISTINCT", s.as_global(), 1, &[0]),
+            mat,
+        );
+        g
+    }
+
+    #[test]
+    fn it_describes() {
+        let c = CustomAggregator::over("hll", 0.into(), 1, &[0, 2]);
+        assert_eq!(c.description(true), "hll(1) γ[0, 2]");
+    }
+
+    #[test]
+    fn it_forwards() {
+        let mut c = setup(true);
+
+        let rs = c.narrow_one_row(vec![DataType::from(1), 1.into()], true);
+        assert_eq!(rs, vec![(vec![1.into(), "1".into()], true)].into());
+
+        let rs = c.narrow_one_row(vec![DataType::from(1), 2.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "1".into()], false),
+                (vec![1.into(), "1,2".into()], true),
+            ]
+            .into()
+        );
+
+        let rs = c.narrow_one_row((vec![DataType::from(1), 1.into()], false), true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "1,2".into()], false),
+                (vec![1.into(), "2".into()], true),
+            ]
+            .into()
+        );
+    }
+}
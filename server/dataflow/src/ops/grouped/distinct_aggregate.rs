@@ -0,0 +1,213 @@
+use crate::ops::grouped::aggregate::Aggregation;
+use crate::ops::grouped::GroupedOperation;
+
+use crate::prelude::*;
+
+/// DistinctAggregator implements a Soup node that computes `COUNT(DISTINCT ..)` and
+/// `SUM(DISTINCT ..)`.
+///
+/// `DistinctAggregator` nodes are constructed through `Aggregation` variants using
+/// `Aggregation::over_distinct`.
+///
+/// Its input is the multiset of values in each group: one record for every distinct value in
+/// every group, along with the number of times the value occurs. A value is only counted (or
+/// summed) while it occurs at least once, so adding a second occurrence of a value, or removing
+/// one of several occurrences, changes the number of occurrences but leaves the aggregated value
+/// alone. NULL values are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistinctAggregator {
+    op: Aggregation,
+    over: usize,
+    occurrences: usize,
+    group: Vec<usize>,
+}
+
+impl DistinctAggregator {
+    pub(super) fn new(op: Aggregation, over: usize, group: Vec<usize>) -> Self {
+        DistinctAggregator {
+            op,
+            over,
+            occurrences: 0,
+            group,
+        }
+    }
+}
+
+impl GroupedOperation for DistinctAggregator {
+    /// The exact value to add to the group's value, as returned by `DataType::to_decimal`.
+    type Diff = (i128, u8);
+
+    fn setup(&mut self, parent: &Node) {
+        assert!(
+            self.over < parent.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+        self.occurrences = parent.fields().len() - 1;
+        assert_ne!(
+            self.over, self.occurrences,
+            "cannot aggregate over the number of occurrences"
+        );
+    }
+
+    fn group_by(&self) -> &[usize] {
+        &self.group[..]
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
+        if r[self.over].is_none() || i128::from(&r[self.occurrences]) <= 0 {
+            return (0, 0);
+        }
+
+        let (v, scale) = match self.op {
+            Aggregation::COUNT => (1, 0),
+            Aggregation::SUM => r[self.over].to_decimal().unwrap_or_else(|| {
+                unreachable!("tried to aggregate over {:?} on {:?}", r[self.over], r)
+            }),
+        };
+        if pos {
+            (v, scale)
+        } else {
+            (0i128 - v, scale)
+        }
+    }
+
+    fn apply(
+        &self,
+        current: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> DataType {
+        let n = match current {
+            Some(dt) => dt.to_decimal().unwrap(),
+            None => (0, 0),
+        };
+        super::exact_value(diffs.fold(n, super::add_exact))
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from(match self.op {
+                Aggregation::COUNT => "+δ",
+                Aggregation::SUM => "𝛴δ",
+            });
+        }
+
+        let op_string = match self.op {
+            Aggregation::COUNT => format!("|δ({})|", self.over),
+            Aggregation::SUM => format!("𝛴δ({})", self.over),
+        };
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} γ[{}]", op_string, group_cols)
+    }
+
+    fn over_columns(&self) -> Vec<usize> {
+        vec![self.over]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(op: Aggregation) -> ops::test::MockGraph {
+        // the source holds the number of occurrences of each y for each x
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "n"]);
+        g.set_op(
+            "distinct",
+            &["x", "ys"],
+            op.over_distinct(s.as_global(), 1, &[0]),
+            true,
+        );
+        g
+    }
+
+    #[test]
+    fn it_describes() {
+        let s = 0.into();
+
+        let c = Aggregation::COUNT.over_distinct(s, 1, &[0, 2]);
+        assert_eq!(c.description(true), "|δ(1)| γ[0, 2]");
+
+        let s = Aggregation::SUM.over_distinct(s, 1, &[2, 0]);
+        assert_eq!(s.description(true), "𝛴δ(1) γ[2, 0]");
+    }
+
+    #[test]
+    fn it_counts_each_value_once() {
+        let mut c = setup(Aggregation::COUNT);
+
+        // the first occurrence of a value counts
+        let rs = c.narrow_one_row(vec![1.into(), 1.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 1.into()]].into());
+
+        // but the second does not
+        let rs = c.narrow_one(
+            vec![
+                (vec![1.into(), 1.into(), 1.into()], false),
+                (vec![1.into(), 1.into(), 2.into()], true),
+            ],
+            true,
+        );
+        assert!(rs.is_empty());
+
+        // and neither does removing it again
+        let rs = c.narrow_one(
+            vec![
+                (vec![1.into(), 1.into(), 2.into()], false),
+                (vec![1.into(), 1.into(), 1.into()], true),
+            ],
+            true,
+        );
+        assert!(rs.is_empty());
+
+        // a new value does
+        let rs = c.narrow_one_row(vec![1.into(), 2.into(), 1.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 1.into()], false),
+                (vec![1.into(), 2.into()], true)
+            ]
+            .into()
+        );
+
+        // as does removing the last occurrence of a value
+        let rs = c.narrow_one(
+            vec![
+                (vec![1.into(), 1.into(), 1.into()], false),
+                (vec![1.into(), 1.into(), 0.into()], true),
+            ],
+            true,
+        );
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 2.into()], false),
+                (vec![1.into(), 1.into()], true)
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn it_sums_distinct_values() {
+        let mut c = setup(Aggregation::SUM);
+
+        let rs = c.narrow_one(
+            vec![
+                vec![1.into(), 5.into(), 2.into()],
+                vec![1.into(), 7.into(), 1.into()],
+                vec![1.into(), DataType::None, 3.into()],
+            ],
+            true,
+        );
+        assert_eq!(rs, vec![vec![1.into(), 12.into()]].into());
+    }
+}
@@ -51,6 +51,10 @@ impl Extremum {
 /// incoming record. The output record is constructed by concatenating the columns identifying the
 /// group, and appending the aggregated value. For example, for a sum with `self.over == 1`, a
 /// previous sum of `3`, and an incoming record with `[a, 1, x]`, the output would be `[a, x, 4]`.
+///
+/// Removing the current extremum of a group leaves the new one unknown unless the same update also
+/// adds a value that is at least as extreme, so in that case the group's value is recomputed from
+/// all of its records in the parent, which is indexed by the group columns for this purpose.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExtremumOperator {
     op: Extremum,
@@ -58,9 +62,63 @@ pub struct ExtremumOperator {
     group: Vec<usize>,
 }
 
+/// A value of the `over` column that was added to or removed from a group. Values are integers or
+/// decimals, which compare by their numeric value.
+#[derive(Clone)]
 pub enum DiffType {
-    Insert(i128),
-    Remove(i128),
+    Insert(DataType),
+    Remove(DataType),
+}
+
+impl ExtremumOperator {
+    /// The extremum after applying `diffs` to a group whose extremum is `current`, if it can be
+    /// determined without looking at the group's other records.
+    ///
+    /// `NULL`s are ignored, and the extremum of a group that only has `NULL`s is `NULL`.
+    fn extremum(
+        &self,
+        current: Option<&DataType>,
+        diffs: &mut dyn Iterator<Item = DiffType>,
+    ) -> Option<DataType> {
+        // a group whose extremum is NULL has no other values
+        let current = current.filter(|c| !c.is_none());
+
+        // Extreme values are those that are at least as extreme as the current min/max (if any).
+        let mut extreme_values: Vec<DataType> = current.cloned().into_iter().collect();
+
+        let is_extreme_value = |x: &DataType| match current {
+            Some(n) => match self.op {
+                Extremum::MAX => x >= n,
+                Extremum::MIN => x <= n,
+            },
+            None => true,
+        };
+
+        for d in diffs {
+            match d {
+                DiffType::Insert(DataType::None) | DiffType::Remove(DataType::None) => {}
+                DiffType::Insert(v) if is_extreme_value(&v) => extreme_values.push(v),
+                DiffType::Remove(v) if is_extreme_value(&v) => {
+                    if let Some(i) = extreme_values.iter().position(|x| *x == v) {
+                        extreme_values.swap_remove(i);
+                    }
+                }
+                _ => {}
+            };
+        }
+
+        // any value that remains is at least as extreme as all the values we didn't see
+        let extremum = match self.op {
+            Extremum::MIN => extreme_values.into_iter().min(),
+            Extremum::MAX => extreme_values.into_iter().max(),
+        };
+        match (extremum, current) {
+            (Some(v), _) => Some(v),
+            // without a current extremum, the diffs held all of the group's values
+            (None, None) => Some(DataType::None),
+            (None, Some(_)) => None,
+        }
+    }
 }
 
 impl GroupedOperation for ExtremumOperator {
@@ -79,10 +137,7 @@ impl GroupedOperation for ExtremumOperator {
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         let v = match r[self.over] {
-            DataType::Int(n) => i128::from(n),
-            DataType::UnsignedInt(n) => i128::from(n),
-            DataType::BigInt(n) => i128::from(n),
-            DataType::UnsignedBigInt(n) => i128::from(n),
+            ref v if v.is_none() || v.to_decimal().is_some() => v.clone(),
             _ => {
                 // the column we're aggregating over is non-numerical (or rather, this value is).
                 // if you've removed a column, chances are the  default value has the wrong type.
@@ -109,60 +164,21 @@ impl GroupedOperation for ExtremumOperator {
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
-        // Extreme values are those that are at least as extreme as the current min/max (if any).
-        // let mut is_extreme_value : Box<dyn Fn(i64) -> bool> = Box::new(|_|true);
-        let mut extreme_values: Vec<i128> = vec![];
-        if let Some(data) = current {
-            match *data {
-                DataType::Int(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedInt(n) => extreme_values.push(i128::from(n)),
-                DataType::BigInt(n) => extreme_values.push(i128::from(n)),
-                DataType::UnsignedBigInt(n) => extreme_values.push(i128::from(n)),
-                _ => unreachable!(),
-            }
-        };
-
-        let is_extreme_value = |x: i128| {
-            if let Some(data) = current {
-                let n = match *data {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    _ => unreachable!(),
-                };
-                match self.op {
-                    Extremum::MAX => x >= n,
-                    Extremum::MIN => x <= n,
-                }
-            } else {
-                true
-            }
-        };
-
-        for d in diffs {
-            match d {
-                DiffType::Insert(v) if is_extreme_value(v) => extreme_values.push(v),
-                DiffType::Remove(v) if is_extreme_value(v) => {
-                    if let Some(i) = extreme_values.iter().position(|x: &i128| *x == v) {
-                        extreme_values.swap_remove(i);
-                    }
-                }
-                _ => {}
-            };
+        let extremum = self
+            .extremum(current, diffs)
+            .expect("groups whose extremum was removed are recomputed");
+        match extremum.to_decimal() {
+            Some(n) => super::exact_value(n),
+            None => DataType::None,
         }
+    }
 
-        let extreme = match self.op {
-            Extremum::MIN => extreme_values.into_iter().min(),
-            Extremum::MAX => extreme_values.into_iter().max(),
-        };
-
-        if let Some(extreme) = extreme {
-            return extreme.into();
-        }
+    fn is_incremental(&self) -> bool {
+        false
+    }
 
-        // TODO: handle this case by querying into the parent.
-        unimplemented!();
+    fn can_apply(&self, current: Option<&DataType>, diffs: &[Self::Diff]) -> bool {
+        self.extremum(current, &mut diffs.iter().cloned()).is_some()
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -323,18 +339,102 @@ mod tests {
         assert!(out.is_empty());
     }
 
+    #[test]
+    fn it_recomputes_removed_extremum() {
+        let mut c = setup(Extremum::MAX, true);
+        let s = c.narrow_base_id();
+        let key = 1;
+
+        // the parent's state reflects each update by the time we see it
+        let write = |c: &mut ops::test::MockGraph, v: i32, positive: bool| {
+            let r: Vec<DataType> = vec![key.into(), v.into()];
+            c.states
+                .get_mut(*s)
+                .unwrap()
+                .process_records(&mut vec![(r.clone(), positive)].into(), None);
+            c.narrow_one_row((r, positive), true)
+        };
+
+        write(&mut c, 4, true);
+        write(&mut c, 7, true);
+        write(&mut c, 7, true);
+        let out = write(&mut c, 9, true);
+        assert_record_change(key, 7, 9, out);
+
+        // removing the maximum falls back to the next largest value
+        let out = write(&mut c, 9, false);
+        assert_record_change(key, 9, 7, out);
+
+        // the maximum still occurs in another record
+        let rs = write(&mut c, 7, false);
+        assert!(rs.is_empty());
+
+        let out = write(&mut c, 7, false);
+        assert_record_change(key, 7, 4, out);
+
+        // removing the last record removes the group
+        let rs = write(&mut c, 4, false);
+        assert_eq!(rs, vec![(vec![key.into(), 4.into()], false)].into());
+    }
+
+    #[test]
+    fn it_ignores_nulls() {
+        let mut c = setup(Extremum::MIN, true);
+
+        // a group with only NULLs has no minimum
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert_eq!(rs, vec![(vec![1.into(), DataType::None], true)].into());
+
+        let rs = c.narrow_one_row(vec![1.into(), 5.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), DataType::None], false),
+                (vec![1.into(), 5.into()], true),
+            ]
+            .into()
+        );
+
+        // NULL is not smaller than 5
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert!(rs.is_empty());
+    }
+
+    #[test]
+    fn it_forwards_decimal_extrema() {
+        let mut c = setup(Extremum::MIN, true);
+        let d = |s| DataType::decimal(s).unwrap();
+
+        let rs = c.narrow_one_row(vec![1.into(), d("2.50")], true);
+        assert_eq!(rs, vec![(vec![1.into(), d("2.50")], true)].into());
+
+        // decimals and integers compare by value, whatever their number of digits
+        let rs = c.narrow_one_row(vec![1.into(), d("2.125")], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), d("2.50")], false),
+                (vec![1.into(), d("2.125")], true),
+            ]
+            .into()
+        );
+        let rs = c.narrow_one_row(vec![1.into(), 3.into()], true);
+        assert!(rs.is_empty());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
         let c = setup(Extremum::MAX, false);
         let idx = c.node().suggest_indexes(me);
 
-        // should only add index on own columns
-        assert_eq!(idx.len(), 1);
+        // should add an index on own columns, and one on the parent to recompute groups with
+        assert_eq!(idx.len(), 2);
         assert!(idx.contains_key(&me));
 
         // should only index on the group-by column
         assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&c.narrow_base_id().as_global()], vec![0]);
     }
 
     #[test]
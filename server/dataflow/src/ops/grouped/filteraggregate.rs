@@ -1,6 +1,6 @@
 use std::sync;
 
-use crate::ops::filter::{FilterCondition, Value};
+use crate::ops::filter::FilterCondition;
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 pub use nom_sql::{Literal, Operator};
@@ -10,7 +10,8 @@ use crate::prelude::*;
 /// Supported aggregation operators.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FilterAggregation {
-    /// Count the number of records for each filtered group. The value for the `over` column is ignored.
+    /// Count the number of records for each filtered group whose `over` column (or, for records
+    /// that don't pass the filter, the else value) is not `NULL`.
     COUNT,
     /// Sum the value of the `over` column for all records of each filtered group.
     SUM,
@@ -21,7 +22,7 @@ impl FilterAggregation {
     ///
     /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
     /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
-    /// identifier. The `over` column should not be in the `group_by` array.
+    /// identifier. The `over` column should not be in the `group_by` array, unless counting.
     pub fn over(
         self,
         src: NodeIndex,
@@ -31,7 +32,7 @@ impl FilterAggregation {
         group_by: &[usize],
     ) -> GroupedOperator<FilterAggregator> {
         assert!(
-            !group_by.iter().any(|&i| i == over),
+            self == FilterAggregation::COUNT || !group_by.iter().any(|&i| i == over),
             "cannot group by aggregation column"
         );
 
@@ -72,7 +73,8 @@ pub struct FilterAggregator {
 }
 
 impl GroupedOperation for FilterAggregator {
-    type Diff = i128;
+    /// The exact value to add to the group's value, as returned by `DataType::to_decimal`.
+    type Diff = (i128, u8);
 
     fn setup(&mut self, parent: &Node) {
         assert!(
@@ -86,60 +88,39 @@ impl GroupedOperation for FilterAggregator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        let passes_filter = self.filter.iter().all(|(i, cond)| {
-            // check if this filter matches
-            let d = &r[*i];
-            match *cond {
-                FilterCondition::Comparison(ref op, ref f) => {
-                    let v = match *f {
-                        Value::Constant(ref dt) => dt,
-                        Value::Column(c) => &r[c],
-                    };
-                    match *op {
-                        Operator::Equal => d == v,
-                        Operator::NotEqual => d != v,
-                        Operator::Greater => d > v,
-                        Operator::GreaterOrEqual => d >= v,
-                        Operator::Less => d < v,
-                        Operator::LessOrEqual => d <= v,
-                        Operator::In => unreachable!(),
-                        _ => unimplemented!(),
-                    }
-                }
-                FilterCondition::In(ref fs) => fs.contains(d),
-            }
-        });
-        let v = if passes_filter {
+        let passes_filter = self.filter.iter().all(|(i, cond)| cond.matches(&r[*i], r));
+        let (v, scale) = if passes_filter {
             match self.op {
-                FilterAggregation::COUNT => 1,
+                FilterAggregation::COUNT if r[self.over].is_none() => (0, 0),
+                FilterAggregation::COUNT => (1, 0),
                 FilterAggregation::SUM => match r[self.over] {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    DataType::None => 0,
-                    ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
+                    DataType::None => (0, 0),
+                    ref x => x.to_decimal().unwrap_or_else(|| {
+                        unreachable!("tried to aggregate over {:?} on {:?}", x, r)
+                    }),
                 },
             }
         } else {
             // the filter returned false, so check whether we have an else case
             match self.over_else.clone() {
                 Some(over_else) => match self.op {
-                    FilterAggregation::COUNT => 1,
+                    FilterAggregation::COUNT if over_else == Literal::Null => (0, 0),
+                    FilterAggregation::COUNT => (1, 0),
                     FilterAggregation::SUM => match over_else {
-                        Literal::Integer(n) => i128::from(n),
-                        Literal::UnsignedInteger(n) => i128::from(n),
+                        Literal::Integer(n) => (i128::from(n), 0),
+                        Literal::UnsignedInteger(n) => (i128::from(n), 0),
+                        Literal::Null => (0, 0),
                         ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
                     },
                 },
-                None => 0,
+                None => (0, 0),
             }
         };
 
         if pos {
-            v
+            (v, scale)
         } else {
-            0i128 - v
+            (0i128 - v, scale)
         }
     }
 
@@ -149,14 +130,10 @@ impl GroupedOperation for FilterAggregator {
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
         let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
-            _ => unreachable!(),
+            Some(dt) => dt.to_decimal().unwrap(),
+            None => (0, 0),
         };
-        diffs.fold(n, |n, d| n + d).into()
+        super::exact_value(diffs.fold(n, super::add_exact))
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -192,6 +169,7 @@ mod tests {
     use super::*;
 
     use crate::ops;
+    use crate::ops::filter::Value;
 
     fn setup(mat: bool) -> ops::test::MockGraph {
         let mut g = ops::test::MockGraph::new();
@@ -724,6 +702,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_counts_values_that_are_not_null() {
+        // COUNT(y), grouped by x
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op(
+            "identity",
+            &["x", "ys"],
+            FilterAggregation::COUNT.over(
+                s.as_global(),
+                &[(
+                    1,
+                    FilterCondition::Comparison(
+                        Operator::NotEqual,
+                        Value::Constant(DataType::None),
+                    ),
+                )],
+                1,
+                None,
+                &[0],
+            ),
+            true,
+        );
+
+        let rs = g.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert_eq!(rs, vec![vec![1.into(), 0.into()]].into());
+
+        let rs = g.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), 0.into()], false),
+                (vec![1.into(), 1.into()], true),
+            ]
+            .into()
+        );
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = 1.into();
@@ -8,6 +8,8 @@ use crate::prelude::*;
 // pub mod latest;
 pub mod aggregate;
 pub mod concat;
+pub mod custom;
+pub mod distinct_aggregate;
 pub mod extremum;
 pub mod filteraggregate;
 
@@ -59,6 +61,24 @@ pub trait GroupedOperation: fmt::Debug + Clone {
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType;
 
+    /// Whether `apply` can always compute the new value of a group from its current value and the
+    /// changes to it.
+    ///
+    /// Operations that can't have their parent indexed by the group columns, so that a group's
+    /// value can instead be recomputed from all of its records whenever `can_apply` says so.
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    /// Whether `apply` can compute the new value of a group whose value is `current` from `diffs`.
+    ///
+    /// If it can't, the group's value is recomputed by calling `apply` with no current value on
+    /// all of the group's records in the parent, which already reflect the changes. Only consulted
+    /// for operations that are not `is_incremental`.
+    fn can_apply(&self, _current: Option<&DataType>, _diffs: &[Self::Diff]) -> bool {
+        true
+    }
+
     fn description(&self, detailed: bool) -> String;
     fn over_columns(&self) -> Vec<usize>;
 }
@@ -97,6 +117,24 @@ impl<T: GroupedOperation> GroupedOperator<T> {
     }
 }
 
+/// Add two exact numbers, as returned by `DataType::to_decimal`, keeping all the digits of both.
+fn add_exact((a, sa): (i128, u8), (b, sb): (i128, u8)) -> (i128, u8) {
+    let scale = sa.max(sb);
+    let a = a * 10i128.pow(u32::from(scale - sa));
+    let b = b * 10i128.pow(u32::from(scale - sb));
+    (a + b, scale)
+}
+
+/// The value of the exact number `n` with `scale` digits after the decimal point, which is an
+/// integer if it has no such digits and a decimal otherwise.
+fn exact_value((n, scale): (i128, u8)) -> DataType {
+    if scale == 0 {
+        n.into()
+    } else {
+        DataType::from_decimal(n, scale)
+    }
+}
+
 /// Extract a copy of all values in the record being targeted by the group
 fn get_group_values(group_by: &[usize], row: &Record) -> Vec<DataType> {
     // This attribute is only here, because `is_sorted` is unstable. I didn't
@@ -164,7 +202,7 @@ where
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -200,9 +238,10 @@ where
         let mut lookups = Vec::new();
         let mut out = Vec::new();
         {
+            let this = &*self;
             let out_key = &self.out_key;
             let mut handle_group =
-                |inner: &mut T,
+                |inner: &T,
                  group_rs: ::std::vec::Drain<Record>,
                  mut diffs: ::std::vec::Drain<_>| {
                     let mut group_rs = group_rs.peekable();
@@ -244,13 +283,60 @@ where
                         Cow::Owned(rs) => Cow::Owned(rs[rs.len() - 1].clone()),
                     });
 
-                    // new is the result of applying all diffs for the group to the current value
-                    let new = inner.apply(current.as_ref().map(|v| &**v), &mut diffs as &mut _);
-                    match current {
-                        Some(ref current) if new == **current => {
+                    let current_value = current.as_ref().map(|v| &**v);
+                    let new = if inner.is_incremental()
+                        || inner.can_apply(current_value, diffs.as_slice())
+                    {
+                        // new is the result of applying all diffs to the current value
+                        Some(inner.apply(current_value, &mut diffs as &mut _))
+                    } else {
+                        // the group's new value depends on records that aren't in this update, so
+                        // recompute it from all the group's records in our parent instead. there
+                        // is no value if the group has no records left.
+                        let rows = this
+                            .lookup(
+                                *this.src,
+                                &group_by[..],
+                                &KeyType::from(&group[..]),
+                                nodes,
+                                state,
+                            )
+                            .expect("parent of recomputing grouped operator must be materialized");
+                        let rows = match rows {
+                            Some(rows) => rows,
+                            None => {
+                                misses.extend(group_rs.map(|r| Miss {
+                                    on: *this.src,
+                                    lookup_idx: group_by.clone(),
+                                    lookup_cols: group_by.clone(),
+                                    replay_cols: replay_key_cols.map(Vec::from),
+                                    record: r.extract().0,
+                                }));
+                                return;
+                            }
+                        };
+                        if replay_key_cols.is_some() {
+                            lookups.push(Lookup {
+                                on: *this.src,
+                                cols: group_by.clone(),
+                                key: group.clone(),
+                            });
+                        }
+
+                        let mut rows = rows.peekable();
+                        if rows.peek().is_some() {
+                            let mut diffs = rows.map(|r| inner.to_diff(&r[..], true));
+                            Some(inner.apply(None, &mut diffs as &mut _))
+                        } else {
+                            None
+                        }
+                    };
+
+                    match (current, new) {
+                        (Some(ref current), Some(ref new)) if *new == **current => {
                             // no change
                         }
-                        _ => {
+                        (current, new) => {
                             if let Some(old) = old {
                                 // revoke old value
                                 debug_assert!(current.is_some());
@@ -258,9 +344,11 @@ where
                             }
 
                             // emit positive, which is group + new.
-                            let mut rec = group;
-                            rec.push(new);
-                            out.push(Record::Positive(rec));
+                            if let Some(new) = new {
+                                let mut rec = group;
+                                rec.push(new);
+                                out.push(Record::Positive(rec));
+                            }
                         }
                     }
                 };
@@ -269,14 +357,14 @@ where
             let mut group_rs = Vec::new();
             for r in rs {
                 if !group_rs.is_empty() && cmp(&group_rs[0], &r) != Ordering::Equal {
-                    handle_group(&mut self.inner, group_rs.drain(..), diffs.drain(..));
+                    handle_group(&this.inner, group_rs.drain(..), diffs.drain(..));
                 }
 
-                diffs.push(self.inner.to_diff(&r[..], r.is_positive()));
+                diffs.push(this.inner.to_diff(&r[..], r.is_positive()));
                 group_rs.push(r);
             }
             assert!(!diffs.is_empty());
-            handle_group(&mut self.inner, group_rs.drain(..), diffs.drain(..));
+            handle_group(&this.inner, group_rs.drain(..), diffs.drain(..));
         }
 
         ProcessingResult {
@@ -288,7 +376,12 @@ where
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         // index by our primary key
-        Some((this, self.out_key.clone())).into_iter().collect()
+        let mut indexes: HashMap<_, _> = Some((this, self.out_key.clone())).into_iter().collect();
+        if !self.inner.is_incremental() {
+            // so that we can look up all the records in a group when recomputing it
+            indexes.insert(self.src.as_global(), self.group_by.clone());
+        }
+        indexes
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
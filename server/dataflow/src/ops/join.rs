@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::mem;
 
+use slog::Logger;
+
 use crate::prelude::*;
 
 /// Kind of join
@@ -11,6 +13,8 @@ pub enum JoinType {
     Left,
     /// Inner join between two views
     Inner,
+    /// Full outer join between two views
+    Full,
 }
 
 /// Where to source a join column
@@ -24,7 +28,7 @@ pub enum JoinSource {
     B(usize, usize),
 }
 
-/// Join provides a left outer join between two views.
+/// Join provides an inner, left outer, or full outer join between two views.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Join {
     left: IndexPair,
@@ -180,18 +184,65 @@ impl Join {
     }
 
     // TODO: make non-allocating
-    fn generate_null(&self, left: &[DataType]) -> Vec<DataType> {
+    fn generate_null(&self, row: &[DataType], row_from_left: bool) -> Vec<DataType> {
         self.emit
             .iter()
             .map(|&(from_left, col)| {
-                if from_left {
-                    left[col].clone()
+                if from_left == row_from_left {
+                    row[col].clone()
+                } else if row_from_left {
+                    DataType::None
+                } else if col == self.on.0 {
+                    // the join column is emitted from the left, but the right has the same value
+                    row[self.on.1].clone()
                 } else {
                     DataType::None
                 }
             })
             .collect()
     }
+
+    /// True if rows from `from` that match no rows in the other parent are emitted padded with
+    /// NULLs.
+    fn pads(&self, from: LocalNodeIndex) -> bool {
+        match self.kind {
+            JoinType::Left => from == *self.left,
+            JoinType::Inner => false,
+            JoinType::Full => true,
+        }
+    }
+
+    /// The rows of the right parent that match no rows in the left parent, padded with NULLs.
+    ///
+    /// Full replays to a full outer join only come from the left parent, and so never include
+    /// these.
+    fn unmatched_right(&self, nodes: &DomainNodes, states: &StateMap) -> Vec<Record> {
+        let right = states
+            .get(*self.right)
+            .expect("full outer join must have a materialized right parent");
+        right
+            .cloned_records()
+            .into_iter()
+            .filter(|r| {
+                // rows with a NULL key match nothing
+                if r[self.on.1].is_none() {
+                    return true;
+                }
+                let mut lefts = self
+                    .lookup(
+                        *self.left,
+                        &[self.on.0],
+                        &KeyType::Single(&r[self.on.1]),
+                        nodes,
+                        states,
+                    )
+                    .expect("full outer join must have a materialized left parent")
+                    .expect("full outer join must have a fully materialized left parent");
+                lefts.next().is_none()
+            })
+            .map(|r| (self.generate_null(&r, false), true).into())
+            .collect()
+    }
 }
 
 impl Ingredient for Join {
@@ -209,7 +260,9 @@ impl Ingredient for Join {
 
     fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
         match self.kind {
-            JoinType::Left => Some(Some(self.left.as_global()).into_iter().collect()),
+            JoinType::Left | JoinType::Full => {
+                Some(Some(self.left.as_global()).into_iter().collect())
+            }
             JoinType::Inner => Some(
                 vec![self.left.as_global(), self.right.as_global()]
                     .into_iter()
@@ -225,6 +278,29 @@ impl Ingredient for Join {
         self.right.remap(remap);
     }
 
+    fn on_input_raw(
+        &mut self,
+        ex: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay: ReplayContext,
+        nodes: &DomainNodes,
+        states: &StateMap,
+        _: &Logger,
+    ) -> RawProcessingResult {
+        let last_full = match replay {
+            ReplayContext::Full { last } => last,
+            _ => false,
+        };
+
+        let mut m = self.on_input(ex, from, rs, replay.key(), nodes, states);
+        if last_full && self.kind == JoinType::Full {
+            // the replay has gone through all of the left, so add what's only in the right
+            m.results.extend(self.unmatched_right(nodes, states));
+        }
+        RawProcessingResult::Regular(m)
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn on_input(
         &mut self,
@@ -250,6 +326,12 @@ impl Ingredient for Join {
         } else {
             (*self.left, self.on.1, self.on.0)
         };
+        let from_left = from == *self.left;
+
+        // rows from `from` that match nothing are padded with NULLs, and rows from `other` are
+        // padded when the last matching row from `from` goes away (and revoked when one arrives)
+        let pad_from = self.pads(from);
+        let pad_other = self.pads(other);
 
         let replay_key_cols = replay_key_cols.map(|cols| {
             cols.iter()
@@ -288,15 +370,34 @@ impl Ingredient for Join {
         let mut ret: Vec<Record> = Vec::with_capacity(rs.len());
         let mut at = 0;
         while at != rs.len() {
-            let mut old_right_count = None;
-            let mut new_right_count = None;
+            let mut old_from_count = None;
+            let mut new_from_count = None;
             let prev_join_key = rs[at][from_key].clone();
 
-            if from == *self.right && self.kind == JoinType::Left {
+            if prev_join_key.is_none() {
+                // NULL is not equal to anything, not even NULL, so these rows match nothing, and
+                // don't change whether rows on the other side match anything either
+                let start = at;
+                at = rs[at..]
+                    .iter()
+                    .position(|r| !r[from_key].is_none())
+                    .map(|p| at + p)
+                    .unwrap_or_else(|| rs.len());
+                if pad_from {
+                    ret.extend(
+                        rs[start..at]
+                            .iter()
+                            .map(|r| (self.generate_null(r, from_left), r.is_positive()).into()),
+                    );
+                }
+                continue;
+            }
+
+            if pad_other {
                 let rc = self
                     .lookup(
-                        *self.right,
-                        &[self.on.1],
+                        from,
+                        &[from_key],
                         &KeyType::Single(&prev_join_key),
                         nodes,
                         state,
@@ -322,15 +423,15 @@ impl Ingredient for Join {
                 } else {
                     if replay_key_cols.is_some() {
                         lookups.push(Lookup {
-                            on: *self.right,
-                            cols: vec![self.on.1],
+                            on: from,
+                            cols: vec![from_key],
                             key: vec![prev_join_key.clone()],
                         });
                     }
 
                     let rc = rc.unwrap().count();
-                    old_right_count = Some(rc);
-                    new_right_count = Some(rc);
+                    old_from_count = Some(rc);
+                    new_from_count = Some(rc);
                 }
             }
 
@@ -374,11 +475,12 @@ impl Ingredient for Join {
 
             let start = at;
             let mut make_null = None;
-            if self.kind == JoinType::Left && from == *self.right {
-                // If records are being received from the right, we need to find the number of
-                // records that existed *before* this batch of records was processed so we know
-                // whether or not to generate +/- NULL rows.
-                if let Some(mut old_rc) = old_right_count {
+            if pad_other {
+                // If the rows of the other side are padded with NULLs when nothing on this side
+                // matches them, we need to find the number of records that existed *before* this
+                // batch of records was processed so we know whether or not to generate +/- NULL
+                // rows.
+                if let Some(mut old_rc) = old_from_count {
                     while at != rs.len() && rs[at][from_key] == prev_join_key {
                         if rs[at].is_positive() {
                             old_rc -= 1
@@ -388,8 +490,8 @@ impl Ingredient for Join {
                         at += 1;
                     }
 
-                    // emit null rows if necessary for left join
-                    let new_rc = new_right_count.unwrap();
+                    // emit null rows if necessary for outer join
+                    let new_rc = new_from_count.unwrap();
                     if new_rc == 0 && old_rc != 0 {
                         // all lefts for this key must emit + NULLs
                         make_null = Some(true);
@@ -407,7 +509,7 @@ impl Ingredient for Join {
                         .unwrap_or_else(|| rs.len());
                     misses.extend((start..at).map(|i| Miss {
                         on: from,
-                        lookup_idx: vec![self.on.1],
+                        lookup_idx: vec![from_key],
                         lookup_cols: vec![from_key],
                         replay_cols: replay_key_cols.clone(),
                         // NOTE: we're stealing data here!
@@ -426,7 +528,8 @@ impl Ingredient for Join {
                     .unwrap_or_else(|| rs.len());
             }
 
-            let mut other_rows_count = 0;
+            // where in ret the rows joined with the first record ended up
+            let mut joined = Vec::new();
             for r in &mut rs[start..at] {
                 // put something bogus in rs (which will be discarded anyway) so we can take r.
                 let r = mem::replace(r, Record::Positive(Vec::new()));
@@ -436,9 +539,9 @@ impl Ingredient for Join {
                     // we have yet to iterate through other_rows
                     let mut other_rows = other_rows.peekable();
                     if other_rows.peek().is_none() {
-                        if self.kind == JoinType::Left && from == *self.left {
-                            // left join, got a thing from left, no rows in right == NULL
-                            ret.push((self.generate_null(&row), positive).into());
+                        if pad_from {
+                            // outer join, got a thing from left, no rows in right == NULL
+                            ret.push((self.generate_null(&row, from_left), positive).into());
                         }
                         continue;
                     }
@@ -446,13 +549,13 @@ impl Ingredient for Join {
                     // we're going to pull a little trick here so that the *last* time we use
                     // `row`, we re-use its memory instead of allocating a new Vec. we do this by
                     // (ab)using .peek() to terminate the loop one iteration early.
-                    other_rows_count += 1;
                     let mut other = other_rows.next().unwrap();
                     while other_rows.peek().is_some() {
                         if let Some(false) = make_null {
                             // we need to generate a -NULL for all these lefts
-                            ret.push((self.generate_null(&other), false).into());
+                            ret.push((self.generate_null(&other, !from_left), false).into());
                         }
+                        joined.push(ret.len());
                         if from == *self.left {
                             ret.push(
                                 (
@@ -472,16 +575,16 @@ impl Ingredient for Join {
                         }
                         if let Some(true) = make_null {
                             // we need to generate a +NULL for all these lefts
-                            ret.push((self.generate_null(&other), true).into());
+                            ret.push((self.generate_null(&other, !from_left), true).into());
                         }
                         other = other_rows.next().unwrap();
-                        other_rows_count += 1;
                     }
 
                     if let Some(false) = make_null {
                         // we need to generate a -NULL for the last left too
-                        ret.push((self.generate_null(&other), false).into());
+                        ret.push((self.generate_null(&other, !from_left), false).into());
                     }
+                    joined.push(ret.len());
                     ret.push(
                         (
                             self.regenerate_row(row, &other, from == *self.left, false),
@@ -491,20 +594,19 @@ impl Ingredient for Join {
                     );
                     if let Some(true) = make_null {
                         // we need to generate a +NULL for the last left too
-                        ret.push((self.generate_null(&other), true).into());
+                        ret.push((self.generate_null(&other, !from_left), true).into());
                     }
-                } else if other_rows_count == 0 {
-                    if self.kind == JoinType::Left && from == *self.left {
-                        // left join, got a thing from left, no rows in right == NULL
-                        ret.push((self.generate_null(&row), positive).into());
+                } else if joined.is_empty() {
+                    if pad_from {
+                        // outer join, got a thing from left, no rows in right == NULL
+                        ret.push((self.generate_null(&row, from_left), positive).into());
                     }
                 } else {
                     // we no longer have access to `other_rows`
-                    // *but* the values are all in ret[-other_rows_count:]!
-                    let start = ret.len() - other_rows_count;
-                    let end = ret.len();
+                    // *but* the values are all in ret at the positions in `joined`!
+                    let (&last, rest) = joined.split_last().unwrap();
                     // we again use the trick above where the last row we produce reuses `row`
-                    for i in start..(end - 1) {
+                    for &i in rest {
                         if from == *self.left {
                             let r = (
                                 self.generate_row(&row, &ret[i], Preprocessed::Right),
@@ -522,7 +624,7 @@ impl Ingredient for Join {
                         }
                     }
                     let r = (
-                        self.regenerate_row(row, &ret[end - 1], from == *self.left, true),
+                        self.regenerate_row(row, &ret[last], from == *self.left, true),
                         positive,
                     )
                         .into();
@@ -538,13 +640,33 @@ impl Ingredient for Join {
         }
     }
 
-    fn suggest_indexes(&self, _this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        vec![
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        let mut indexes: HashMap<_, _> = vec![
             (self.left.as_global(), vec![self.on.0]),
             (self.right.as_global(), vec![self.on.1]),
         ]
         .into_iter()
-        .collect()
+        .collect();
+
+        if self.kind == JoinType::Full {
+            // replays through us would miss the rows only in the parent not replayed from, so we
+            // keep our own state to replay from
+            let key = self
+                .emit
+                .iter()
+                .position(|&e| e == (true, self.on.0) || e == (false, self.on.1))
+                .unwrap_or(0);
+            indexes.insert(this, vec![key]);
+        }
+        indexes
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        self.kind == JoinType::Full
+    }
+
+    fn requires_materialized_parents(&self) -> bool {
+        self.kind == JoinType::Full
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
@@ -561,6 +683,7 @@ impl Ingredient for Join {
             return String::from(match self.kind {
                 JoinType::Left => "⋉",
                 JoinType::Inner => "⋈",
+                JoinType::Full => "⟗",
             });
         }
 
@@ -577,6 +700,7 @@ impl Ingredient for Join {
         let op = match self.kind {
             JoinType::Left => "⋉",
             JoinType::Inner => "⋈",
+            JoinType::Full => "⟗",
         };
 
         format!(
@@ -614,6 +738,10 @@ mod tests {
     use crate::ops;
 
     fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        setup_kind(JoinType::Left)
+    }
+
+    fn setup_kind(kind: JoinType) -> (ops::test::MockGraph, IndexPair, IndexPair) {
         let mut g = ops::test::MockGraph::new();
         let l = g.add_base("left", &["l0", "l1"]);
         let r = g.add_base("right", &["r0", "r1"]);
@@ -622,7 +750,7 @@ mod tests {
         let j = Join::new(
             l.as_global(),
             r.as_global(),
-            JoinType::Left,
+            kind,
             vec![B(0, 0), L(1), R(1)],
         );
 
@@ -737,6 +865,120 @@ mod tests {
         assert_eq!(rs.len(), 0);
     }
 
+    #[test]
+    fn it_never_matches_null() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        let l_null = vec![DataType::None, "a".into()];
+        let r_null = vec![DataType::None, "x".into()];
+
+        j.seed(r, r_null.clone());
+        let rs = j.one_row(r, r_null.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![DataType::None, DataType::None, "x".into()], true)].into()
+        );
+
+        // NULL = NULL does not hold, so neither side's padding is revoked
+        j.seed(l, l_null.clone());
+        let rs = j.one_row(l, l_null.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![DataType::None, "a".into(), DataType::None], true)].into()
+        );
+
+        // and an inner join produces nothing
+        let (mut j, l, r) = setup_kind(JoinType::Inner);
+        j.seed(r, r_null.clone());
+        j.one_row(r, r_null.clone(), false);
+        j.seed(l, l_null.clone());
+        assert!(j.one_row(l, l_null.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_full() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        assert_eq!(j.node().description(false), "⟗");
+
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_b2 = vec![2.into(), "b".into()];
+        let l_bb2 = vec![2.into(), "bb".into()];
+        let l_c3 = vec![3.into(), "c".into()];
+
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_z2 = vec![2.into(), "z".into()];
+        let r_w3 = vec![3.into(), "w".into()];
+        let r_v4 = vec![4.into(), "v".into()];
+
+        // forward x1 from right; should produce [1 + None + x] since no records in left are 1
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), DataType::None, "x".into()], true)].into()
+        );
+
+        // a matching record from the left should revoke it
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), DataType::None, "x".into()], false),
+                (vec![1.into(), "a".into(), "x".into()], true),
+            ]
+            .into()
+        );
+
+        // unmatched records from the left are padded as in a left join
+        j.seed(l, l_c3.clone());
+        let rs = j.one_row(l, l_c3.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![3.into(), "c".into(), DataType::None], true)].into()
+        );
+
+        // and revoked when a matching record arrives from the right
+        j.seed(r, r_w3.clone());
+        let rs = j.one_row(r, r_w3.clone(), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![3.into(), "c".into(), DataType::None], false),
+                (vec![3.into(), "c".into(), "w".into()], true),
+            ]
+            .into()
+        );
+
+        // several records with the same key only revoke the padding once
+        j.seed(r, r_z2.clone());
+        j.one_row(r, r_z2.clone(), false);
+        j.seed(l, l_b2.clone());
+        j.seed(l, l_bb2.clone());
+        let rs = j.one(l, vec![l_b2.clone(), l_bb2.clone()], false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![2.into(), DataType::None, "z".into()], false),
+                (vec![2.into(), "b".into(), "z".into()], true),
+                (vec![2.into(), "bb".into(), "z".into()], true),
+            ]
+            .into()
+        );
+
+        // removing an unmatched record from the right removes its padded row
+        j.seed(r, r_v4.clone());
+        let rs = j.one_row(r, r_v4.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![4.into(), DataType::None, "v".into()], true)].into()
+        );
+        let rs = j.one_row(r, (r_v4.clone(), false), false);
+        assert_eq!(
+            rs,
+            vec![(vec![4.into(), DataType::None, "v".into()], false)].into()
+        );
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
@@ -4,17 +4,22 @@ use std::collections::{HashMap, HashSet};
 
 use crate::prelude::*;
 
+pub mod anti_join;
 pub mod distinct;
+pub mod external;
 pub mod filter;
 pub mod grouped;
 pub mod identity;
 pub mod join;
 pub mod latest;
+pub mod multi_join;
 pub mod project;
 pub mod rewrite;
+pub mod session;
 pub mod topk;
 pub mod trigger;
 pub mod union;
+pub mod window;
 
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
@@ -23,7 +28,11 @@ pub enum NodeOperator {
     Extremum(grouped::GroupedOperator<grouped::extremum::ExtremumOperator>),
     Concat(grouped::GroupedOperator<grouped::concat::GroupConcat>),
     FilterSum(grouped::GroupedOperator<grouped::filteraggregate::FilterAggregator>),
+    DistinctSum(grouped::GroupedOperator<grouped::distinct_aggregate::DistinctAggregator>),
+    CustomAggregation(grouped::GroupedOperator<grouped::custom::CustomAggregator>),
     Join(join::Join),
+    MultiJoin(multi_join::MultiJoin),
+    AntiJoin(anti_join::AntiJoin),
     Latest(latest::Latest),
     Project(project::Project),
     Union(union::Union),
@@ -33,6 +42,9 @@ pub enum NodeOperator {
     Trigger(trigger::Trigger),
     Rewrite(rewrite::Rewrite),
     Distinct(distinct::Distinct),
+    ExternalLookup(external::ExternalLookup),
+    Window(window::Window),
+    Session(session::Session),
 }
 
 macro_rules! nodeop_from_impl {
@@ -61,7 +73,17 @@ nodeop_from_impl!(
     NodeOperator::FilterSum,
     grouped::GroupedOperator<grouped::filteraggregate::FilterAggregator>
 );
+nodeop_from_impl!(
+    NodeOperator::DistinctSum,
+    grouped::GroupedOperator<grouped::distinct_aggregate::DistinctAggregator>
+);
+nodeop_from_impl!(
+    NodeOperator::CustomAggregation,
+    grouped::GroupedOperator<grouped::custom::CustomAggregator>
+);
 nodeop_from_impl!(NodeOperator::Join, join::Join);
+nodeop_from_impl!(NodeOperator::MultiJoin, multi_join::MultiJoin);
+nodeop_from_impl!(NodeOperator::AntiJoin, anti_join::AntiJoin);
 nodeop_from_impl!(NodeOperator::Latest, latest::Latest);
 nodeop_from_impl!(NodeOperator::Project, project::Project);
 nodeop_from_impl!(NodeOperator::Union, union::Union);
@@ -71,6 +93,9 @@ nodeop_from_impl!(NodeOperator::TopK, topk::TopK);
 nodeop_from_impl!(NodeOperator::Trigger, trigger::Trigger);
 nodeop_from_impl!(NodeOperator::Rewrite, rewrite::Rewrite);
 nodeop_from_impl!(NodeOperator::Distinct, distinct::Distinct);
+nodeop_from_impl!(NodeOperator::ExternalLookup, external::ExternalLookup);
+nodeop_from_impl!(NodeOperator::Window, window::Window);
+nodeop_from_impl!(NodeOperator::Session, session::Session);
 
 macro_rules! impl_ingredient_fn_mut {
     ($self:ident, $fn:ident, $( $arg:ident ),* ) => {
@@ -79,7 +104,11 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Extremum(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Concat(ref mut i) => i.$fn($($arg),*),
             NodeOperator::FilterSum(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::DistinctSum(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::CustomAggregation(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Join(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::MultiJoin(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::AntiJoin(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Latest(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Project(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Union(ref mut i) => i.$fn($($arg),*),
@@ -89,6 +118,9 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Trigger(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::ExternalLookup(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Window(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Session(ref mut i) => i.$fn($($arg),*),
         }
     }
 }
@@ -100,7 +132,11 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Extremum(ref i) => i.$fn($($arg),*),
             NodeOperator::Concat(ref i) => i.$fn($($arg),*),
             NodeOperator::FilterSum(ref i) => i.$fn($($arg),*),
+            NodeOperator::DistinctSum(ref i) => i.$fn($($arg),*),
+            NodeOperator::CustomAggregation(ref i) => i.$fn($($arg),*),
             NodeOperator::Join(ref i) => i.$fn($($arg),*),
+            NodeOperator::MultiJoin(ref i) => i.$fn($($arg),*),
+            NodeOperator::AntiJoin(ref i) => i.$fn($($arg),*),
             NodeOperator::Latest(ref i) => i.$fn($($arg),*),
             NodeOperator::Project(ref i) => i.$fn($($arg),*),
             NodeOperator::Union(ref i) => i.$fn($($arg),*),
@@ -110,6 +146,9 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Trigger(ref i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref i) => i.$fn($($arg),*),
+            NodeOperator::ExternalLookup(ref i) => i.$fn($($arg),*),
+            NodeOperator::Window(ref i) => i.$fn($($arg),*),
+            NodeOperator::Session(ref i) => i.$fn($($arg),*),
         }
     }
 }
@@ -127,6 +166,9 @@ impl Ingredient for NodeOperator {
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         impl_ingredient_fn_ref!(self, suggest_indexes, you)
     }
+    fn suggest_secondary_indexes(&self) -> Vec<(NodeIndex, Vec<usize>)> {
+        impl_ingredient_fn_ref!(self, suggest_secondary_indexes,)
+    }
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>> {
         impl_ingredient_fn_ref!(self, resolve, i)
     }
@@ -190,6 +232,12 @@ impl Ingredient for NodeOperator {
     fn on_eviction(&mut self, from: LocalNodeIndex, tag: Tag, keys: &[Vec<DataType>]) {
         impl_ingredient_fn_mut!(self, on_eviction, from, tag, keys)
     }
+    fn has_deferred(&self) -> bool {
+        impl_ingredient_fn_ref!(self, has_deferred,)
+    }
+    fn take_deferred(&mut self) -> Records {
+        impl_ingredient_fn_mut!(self, take_deferred,)
+    }
     fn can_query_through(&self) -> bool {
         impl_ingredient_fn_ref!(self, can_query_through,)
     }
@@ -223,9 +271,32 @@ impl Ingredient for NodeOperator {
     fn requires_full_materialization(&self) -> bool {
         impl_ingredient_fn_ref!(self, requires_full_materialization,)
     }
+    fn requires_materialized_parents(&self) -> bool {
+        impl_ingredient_fn_ref!(self, requires_materialized_parents,)
+    }
 }
 
-#[cfg(test)]
+/// A harness for testing a single operator in isolation.
+///
+/// A [`MockGraph`](test::MockGraph) holds the operator under test along with the base tables it
+/// reads from, all in a single domain. Records fed to the operator are processed as if they came
+/// from one of its ancestors, and the records the operator emits are returned, so tests can check
+/// its deltas directly. Operators that look up rows in their ancestors see whatever was `seed`ed
+/// into the ancestors' state.
+///
+/// ```rust,ignore
+/// let mut g = MockGraph::new();
+/// let s = g.add_base("source", &["x", "y"]);
+/// g.set_op("count", &["x", "n"], Aggregation::COUNT.over(s.as_global(), 1, &[0]), true);
+/// assert_eq!(
+///     g.narrow_one_row(vec![1.into(), 1.into()], true),
+///     vec![(vec![1.into(), 1.into()], true)].into()
+/// );
+/// ```
+///
+/// Outside of this crate's own tests, the harness is only available with the `test-harness`
+/// feature.
+#[cfg(any(test, feature = "test-harness"))]
 pub mod test {
     use std::cell;
     use std::collections::HashMap;
@@ -235,7 +306,8 @@ pub mod test {
 
     use petgraph::graph::NodeIndex;
 
-    pub(super) struct MockGraph {
+    /// A data-flow graph with a single operator under test.
+    pub struct MockGraph {
         graph: Graph,
         source: NodeIndex,
         nut: Option<IndexPair>, // node under test
@@ -246,6 +318,7 @@ pub mod test {
 
     #[allow(clippy::new_without_default)]
     impl MockGraph {
+        /// Create a graph with no base tables and no operator.
         pub fn new() -> MockGraph {
             let mut graph = Graph::new();
             let source = graph.add_node(Node::new(
@@ -263,10 +336,12 @@ pub mod test {
             }
         }
 
+        /// Add a base table for the operator under test to read from.
         pub fn add_base(&mut self, name: &str, fields: &[&str]) -> IndexPair {
             self.add_base_defaults(name, fields, vec![])
         }
 
+        /// Add a base table whose columns have the given default values.
         pub fn add_base_defaults(
             &mut self,
             name: &str,
@@ -295,17 +370,22 @@ pub mod test {
             ip
         }
 
-        pub fn set_op<I>(&mut self, name: &str, fields: &[&str], mut i: I, materialized: bool)
+        /// Add the operator under test, with the given output `fields`, once all of the base
+        /// tables it reads from have been added.
+        ///
+        /// The operator's own state is kept if `materialized` is set, and the base tables it looks
+        /// up into are indexed as it asks.
+        pub fn set_op<I>(&mut self, name: &str, fields: &[&str], i: I, materialized: bool)
         where
-            I: Ingredient + Into<NodeOperator>,
+            I: Into<NodeOperator>,
         {
             assert!(self.nut.is_none(), "only one node under test is supported");
 
+            let mut i: NodeOperator = i.into();
             i.on_connected(&self.graph);
             let parents = i.ancestors();
             assert!(!parents.is_empty(), "node under test should have ancestors");
 
-            let i: NodeOperator = i.into();
             let global = self.graph.add_node(Node::new(name, fields, i));
             let local = unsafe { LocalNodeIndex::make(self.remap.len() as u32) };
             if materialized {
@@ -328,7 +408,8 @@ pub mod test {
 
             // we need to set the indices for all the base tables so they *actually* store things.
             let idx = self.graph[global].suggest_indexes(global);
-            for (tbl, col) in idx {
+            let more = self.graph[global].suggest_secondary_indexes();
+            for (tbl, col) in idx.into_iter().chain(more) {
                 if let Some(ref mut s) = self.states.get_mut(self.graph[tbl].local_addr()) {
                     s.add_key(&col[..], None);
                 }
@@ -375,6 +456,7 @@ pub mod test {
                 .collect();
         }
 
+        /// Add a row to the state of a base table, without feeding it to the operator.
         pub fn seed(&mut self, base: IndexPair, data: Vec<DataType>) {
             assert!(self.nut.is_some(), "seed must happen after set_op");
 
@@ -398,12 +480,24 @@ pub mod test {
             }
         }
 
+        /// Remove a row from the state of a base table, as if it had been deleted, without feeding
+        /// the removal to the operator.
+        pub fn unseed_row(&mut self, base: IndexPair, data: Vec<DataType>) {
+            assert!(self.nut.is_some(), "unseed must happen after set_op");
+            self.states
+                .get_mut(*base)
+                .expect("base table has no state")
+                .process_records(&mut vec![(data, false)].into(), None);
+        }
+
+        /// Remove all rows from the state of a base table.
         pub fn unseed(&mut self, base: IndexPair) {
             assert!(self.nut.is_some(), "unseed must happen after set_op");
             let global = self.nut.unwrap().as_global();
             let idx = self.graph[global].suggest_indexes(global);
+            let more = self.graph[global].suggest_secondary_indexes();
             let mut state = MemoryState::default();
-            for (tbl, col) in idx {
+            for (tbl, col) in idx.into_iter().chain(more) {
                 if tbl == base.as_global() {
                     state.add_key(&col[..], None);
                 }
@@ -412,6 +506,9 @@ pub mod test {
             self.states.insert(*base, Box::new(state));
         }
 
+        /// Feed records from the ancestor `src` to the operator, and return the records it emits.
+        ///
+        /// If `remember` is set, the emitted records are also applied to the operator's state.
         pub fn one<U: Into<Records>>(&mut self, src: IndexPair, u: U, remember: bool) -> Records {
             assert!(self.nut.is_some());
             assert!(!remember || self.states.contains_key(*self.nut.unwrap()));
@@ -419,8 +516,19 @@ pub mod test {
             struct Ex;
 
             impl Executor for Ex {
-                fn ack(&mut self, _: SourceChannelIdentifier) {}
+                fn ack(&mut self, _: SourceChannelIdentifier, _: noria::WriteAck) {}
+                fn reject(&mut self, _: SourceChannelIdentifier, _: WriteRejection) {}
                 fn create_universe(&mut self, _: HashMap<String, DataType>) {}
+                fn fire_trigger(&mut self, _: crate::ops::trigger::TriggerFiring) {}
+                fn mirror(
+                    &mut self,
+                    _: String,
+                    _: noria::MirrorTarget,
+                    _: Vec<noria::TableOperation>,
+                ) {
+                }
+                fn dead_letters(&mut self, _: String, _: Vec<noria::DeadLetter>) {}
+                fn quarantine(&mut self, _: String, _: Vec<noria::Violation>) {}
                 fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
             }
 
@@ -440,6 +548,19 @@ pub mod test {
             u
         }
 
+        /// Take the rows the operator held back and is now ready to emit.
+        ///
+        /// If `remember` is set, the rows are also applied to the operator's state.
+        pub fn deferred(&mut self, remember: bool) -> Records {
+            let id = *self.nut.unwrap();
+            let mut u = self.nodes[id].borrow_mut().take_deferred();
+            if remember && self.states.contains_key(id) {
+                node::materialize(&mut u, None, self.states.get_mut(id));
+            }
+            u
+        }
+
+        /// Feed a single record from the ancestor `src` to the operator.
         pub fn one_row<R: Into<Record>>(
             &mut self,
             src: IndexPair,
@@ -449,19 +570,23 @@ pub mod test {
             self.one::<Record>(src, d.into(), remember)
         }
 
+        /// Feed records to an operator with a single ancestor.
         pub fn narrow_one<U: Into<Records>>(&mut self, u: U, remember: bool) -> Records {
             let src = self.narrow_base_id();
             self.one::<Records>(src, u.into(), remember)
         }
 
+        /// Feed a single record to an operator with a single ancestor.
         pub fn narrow_one_row<R: Into<Record>>(&mut self, d: R, remember: bool) -> Records {
             self.narrow_one::<Record>(d.into(), remember)
         }
 
+        /// The operator under test.
         pub fn node(&self) -> cell::Ref<Node> {
             self.nodes[*self.nut.unwrap()].borrow()
         }
 
+        /// The base table of an operator with a single ancestor.
         pub fn narrow_base_id(&self) -> IndexPair {
             assert_eq!(self.remap.len(), 2 /* base + nut */);
             *self
@@ -0,0 +1,434 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::prelude::*;
+
+/// MultiJoin provides an inner join between any number of views in a single step.
+///
+/// A chain of binary joins keeps the output of every join but the last, so that the next join can
+/// look up into it, and those intermediate results are often much larger than the views that are
+/// joined. `MultiJoin` instead joins a change to one parent with the rows of the other parents by
+/// looking them up directly in the state of each of them. It needs no state of its own, but all of
+/// its parents must be fully materialized.
+///
+/// The joins between the parents form a tree: every parent but the first is joined with one of the
+/// parents before it, on a single column of each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiJoin {
+    parents: Vec<IndexPair>,
+
+    // For every parent but the first, the parent before it that it is joined with, the join column
+    // of that parent, and its own join column
+    on: Vec<(usize, usize, usize)>,
+
+    // The parent and column of each output column
+    emit: Vec<(usize, usize)>,
+
+    // For each parent, the order in which the other parents are looked up when a change to it
+    // arrives. Each step is (joined parent, its join column, parent to look up, its join column),
+    // where the first parent has already been joined by an earlier step.
+    plans: Vec<Vec<(usize, usize, usize, usize)>>,
+}
+
+impl MultiJoin {
+    /// Create a new instance of MultiJoin
+    ///
+    /// `on` has an entry for each of `parents` but the first, which gives the parent before it
+    /// that it is joined with, the join column of that parent, and its own join column. `emit`
+    /// gives the parent and column of each output column.
+    pub fn new(
+        parents: Vec<NodeIndex>,
+        on: Vec<(usize, usize, usize)>,
+        emit: Vec<(usize, usize)>,
+    ) -> Self {
+        assert!(parents.len() > 1, "a join needs at least two parents");
+        assert_eq!(
+            on.len(),
+            parents.len() - 1,
+            "every parent but the first must be joined with another"
+        );
+        for (i, p) in parents.iter().enumerate() {
+            assert!(
+                !parents[..i].contains(p),
+                "a parent can only be joined once"
+            );
+        }
+        for (i, &(p, _, _)) in on.iter().enumerate() {
+            assert!(p <= i, "parents must be joined with a parent before them");
+        }
+
+        let plans = (0..parents.len()).map(|from| plan(&on, from)).collect();
+        Self {
+            parents: parents.into_iter().map(IndexPair::from).collect(),
+            on,
+            emit,
+            plans,
+        }
+    }
+
+    /// The columns that always hold the same value as column `col` of parent `p`, including that
+    /// column itself, as (parent, column).
+    fn equal_columns(&self, p: usize, col: usize) -> Vec<(usize, usize)> {
+        let mut equal = vec![(p, col)];
+        let mut i = 0;
+        while i < equal.len() {
+            for (a, b) in edges(&self.on) {
+                let other = if a == equal[i] {
+                    b
+                } else if b == equal[i] {
+                    a
+                } else {
+                    continue;
+                };
+                if !equal.contains(&other) {
+                    equal.push(other);
+                }
+            }
+            i += 1;
+        }
+        equal
+    }
+
+    /// The columns that each parent is looked up by.
+    fn lookup_columns(&self) -> Vec<Vec<usize>> {
+        let mut cols = vec![Vec::new(); self.parents.len()];
+        for (a, b) in edges(&self.on) {
+            for &(p, c) in &[a, b] {
+                if !cols[p].contains(&c) {
+                    cols[p].push(c);
+                }
+            }
+        }
+        cols
+    }
+}
+
+/// The joins of `on` as pairs of (parent, column).
+fn edges<'a>(
+    on: &'a [(usize, usize, usize)],
+) -> impl Iterator<Item = ((usize, usize), (usize, usize))> + 'a {
+    on.iter()
+        .enumerate()
+        .map(|(i, &(p, pc, c))| ((p, pc), (i + 1, c)))
+}
+
+/// The order in which to look up the other parents when a change to parent `from` arrives.
+fn plan(on: &[(usize, usize, usize)], from: usize) -> Vec<(usize, usize, usize, usize)> {
+    let mut joined = vec![false; on.len() + 1];
+    joined[from] = true;
+    let mut steps = Vec::with_capacity(on.len());
+    while steps.len() < on.len() {
+        // every parent is joined with one before it, so there is always a join to follow
+        let (known, next) = edges(on)
+            .find_map(|(a, b)| match (joined[a.0], joined[b.0]) {
+                (true, false) => Some((a, b)),
+                (false, true) => Some((b, a)),
+                _ => None,
+            })
+            .unwrap();
+        joined[next.0] = true;
+        steps.push((known.0, known.1, next.0, next.1));
+    }
+    steps
+}
+
+impl Ingredient for MultiJoin {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        self.parents.iter().map(IndexPair::as_global).collect()
+    }
+
+    fn is_join(&self) -> bool {
+        true
+    }
+
+    fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
+        Some(self.parents.iter().map(IndexPair::as_global).collect())
+    }
+
+    fn on_connected(&mut self, _g: &Graph) {}
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        for p in &mut self.parents {
+            p.remap(remap);
+        }
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        nodes: &DomainNodes,
+        states: &StateMap,
+    ) -> ProcessingResult {
+        let from = self
+            .parents
+            .iter()
+            .position(|p| **p == from)
+            .expect("got records from a node that is not a parent");
+
+        let mut ret: Vec<Record> = Vec::with_capacity(rs.len());
+        for r in rs {
+            let (row, positive) = r.extract();
+
+            // the row of each parent that goes into each output row, filled in one parent at a time
+            let mut joined = {
+                let mut rows: Vec<Option<Cow<[DataType]>>> = vec![None; self.parents.len()];
+                rows[from] = Some(Cow::Owned(row));
+                vec![rows]
+            };
+            for &(known, known_col, next, next_col) in &self.plans[from] {
+                let mut extended = Vec::new();
+                for rows in &joined {
+                    let key = &rows[known].as_ref().unwrap()[known_col];
+                    if key.is_none() {
+                        // NULL is not equal to anything, not even NULL
+                        continue;
+                    }
+                    // the parents are fully materialized, so lookups never miss
+                    let matches = self
+                        .lookup(
+                            *self.parents[next],
+                            &[next_col],
+                            &KeyType::Single(key),
+                            nodes,
+                            states,
+                        )
+                        .expect("multi-way join must have materialized parents")
+                        .expect("multi-way join must have fully materialized parents");
+                    for m in matches {
+                        let mut rows = rows.clone();
+                        rows[next] = Some(m);
+                        extended.push(rows);
+                    }
+                }
+                joined = extended;
+                if joined.is_empty() {
+                    break;
+                }
+            }
+
+            ret.extend(joined.into_iter().map(|rows| {
+                let row: Vec<DataType> = self
+                    .emit
+                    .iter()
+                    .map(|&(p, c)| rows[p].as_ref().unwrap()[c].clone())
+                    .collect();
+                Record::from((row, positive))
+            }));
+        }
+
+        ProcessingResult {
+            results: ret.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, _: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        self.lookup_columns()
+            .into_iter()
+            .enumerate()
+            .map(|(p, cols)| (self.parents[p].as_global(), vec![cols[0]]))
+            .collect()
+    }
+
+    fn suggest_secondary_indexes(&self) -> Vec<(NodeIndex, Vec<usize>)> {
+        self.lookup_columns()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(p, cols)| {
+                let p = self.parents[p].as_global();
+                cols.into_iter().skip(1).map(move |c| (p, vec![c]))
+            })
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        let (p, c) = self.emit[col];
+        Some(vec![(self.parents[p].as_global(), c)])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("⋈");
+        }
+
+        let emit = self
+            .emit
+            .iter()
+            .map(|&(p, c)| format!("{}:{}", self.parents[p].as_global().index(), c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let on = edges(&self.on)
+            .map(|((a, ac), (b, bc))| {
+                format!(
+                    "{}:{} ⋈ {}:{}",
+                    self.parents[a].as_global().index(),
+                    ac,
+                    self.parents[b].as_global().index(),
+                    bc
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("[{}] {}", emit, on)
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        // join columns come from all the parents that they join
+        let (p, c) = self.emit[col];
+        self.equal_columns(p, c)
+            .into_iter()
+            .map(|(p, c)| (self.parents[p].as_global(), Some(c)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> (ops::test::MockGraph, Vec<IndexPair>) {
+        let mut g = ops::test::MockGraph::new();
+        let sales = g.add_base("sales", &["product", "store", "amount"]);
+        let products = g.add_base("products", &["id", "name"]);
+        let stores = g.add_base("stores", &["id", "city"]);
+
+        let j = MultiJoin::new(
+            vec![sales.as_global(), products.as_global(), stores.as_global()],
+            vec![(0, 0, 0), (0, 1, 0)],
+            vec![(0, 0), (1, 1), (0, 1), (2, 1), (0, 2)],
+        );
+        g.set_op(
+            "join",
+            &["product", "name", "store", "city", "amount"],
+            j,
+            false,
+        );
+        (g, vec![sales, products, stores])
+    }
+
+    #[test]
+    fn it_describes() {
+        let (j, p) = setup();
+        let (s, pr, st) = (p[0], p[1], p[2]);
+        assert_eq!(
+            j.node().description(true),
+            format!(
+                "[{}:0, {}:1, {}:1, {}:1, {}:2] {}:0 ⋈ {}:0, {}:1 ⋈ {}:0",
+                s, pr, s, st, s, s, pr, s, st
+            )
+        );
+    }
+
+    #[test]
+    fn it_joins_changes_to_any_parent() {
+        let (mut j, p) = setup();
+        let (sales, products, stores) = (p[0], p[1], p[2]);
+
+        let tea = vec![1.into(), "tea".into()];
+        let oslo = vec![10.into(), "oslo".into()];
+        let rome = vec![11.into(), "rome".into()];
+        j.seed(products, tea.clone());
+        j.seed(stores, oslo.clone());
+        j.seed(stores, rome.clone());
+
+        // a sale joins with its product and its store
+        let sale = vec![1.into(), 10.into(), 5.into()];
+        j.seed(sales, sale.clone());
+        let rs = j.one_row(sales, sale.clone(), false);
+        let oslo_tea: Vec<DataType> =
+            vec![1.into(), "tea".into(), 10.into(), "oslo".into(), 5.into()];
+        assert_eq!(rs, vec![(oslo_tea.clone(), true)].into());
+
+        // a sale in a store we don't know about joins with nothing
+        let lost = vec![1.into(), 12.into(), 7.into()];
+        j.seed(sales, lost.clone());
+        assert!(j.one_row(sales, lost, false).is_empty());
+
+        // as does a sale of no product in particular
+        let null = vec![DataType::None, 11.into(), 7.into()];
+        j.seed(sales, null.clone());
+        assert!(j.one_row(sales, null, false).is_empty());
+
+        // a new product joins with its sales, and their stores
+        let coffee = vec![2.into(), "coffee".into()];
+        j.seed(sales, vec![2.into(), 10.into(), 3.into()]);
+        j.seed(sales, vec![2.into(), 11.into(), 4.into()]);
+        j.seed(products, coffee.clone());
+        let mut rs: Vec<_> = j.one_row(products, coffee, false).into();
+        rs.sort();
+        assert_eq!(
+            rs,
+            vec![
+                Record::from(vec![
+                    2.into(),
+                    "coffee".into(),
+                    10.into(),
+                    "oslo".into(),
+                    3.into(),
+                ]),
+                Record::from(vec![
+                    2.into(),
+                    "coffee".into(),
+                    11.into(),
+                    "rome".into(),
+                    4.into(),
+                ]),
+            ]
+        );
+
+        // and removing a store revokes the rows of the sales made there
+        j.unseed_row(stores, oslo.clone());
+        let rs = j.one_row(stores, (oslo, false), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs
+            .iter()
+            .all(|r| !r.is_positive() && r[3] == DataType::from("oslo")));
+        assert!(rs.iter().any(|r| **r == oslo_tea[..]));
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 3.into();
+        let (g, p) = setup();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 3);
+        assert_eq!(idx[&p[0].as_global()], vec![0]);
+        assert_eq!(idx[&p[1].as_global()], vec![0]);
+        assert_eq!(idx[&p[2].as_global()], vec![0]);
+
+        // sales are also looked up by store
+        assert_eq!(
+            g.node().suggest_secondary_indexes(),
+            vec![(p[0].as_global(), vec![1])]
+        );
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, p) = setup();
+        assert_eq!(g.node().resolve(1), Some(vec![(p[1].as_global(), 1)]));
+        assert_eq!(g.node().resolve(3), Some(vec![(p[2].as_global(), 1)]));
+
+        // join columns come from both sides
+        let mut pcs = g.node().parent_columns(2);
+        pcs.sort();
+        assert_eq!(
+            pcs,
+            vec![(p[0].as_global(), Some(1)), (p[2].as_global(), Some(0))]
+        );
+        assert_eq!(
+            g.node().parent_columns(4),
+            vec![(p[0].as_global(), Some(2))]
+        );
+    }
+}
@@ -1,53 +1,514 @@
-use nom_sql::ArithmeticOperator;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use nom_sql::{ArithmeticOperator, Operator};
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 
 use crate::prelude::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ProjectExpressionBase {
-    Column(usize),
-    Literal(DataType),
+/// A function that a projection can apply to the values it computes a column from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BuiltinFunction {
+    /// `UPPER(text)`
+    Upper,
+    /// `LOWER(text)`
+    Lower,
+    /// `LENGTH(text)`, in characters.
+    Length,
+    /// `CONCAT(value, ...)`
+    Concat,
+    /// `COALESCE(value, ...)`, the first of its arguments that is not `NULL`.
+    Coalesce,
+    /// `DATE(timestamp)`, the start of the day the timestamp falls on. Text is parsed as a
+    /// timestamp first, and is `NULL` if it is not one.
+    Date,
+    /// `SUBSTRING(text, pos[, len])`, in characters. Positions start at 1, and count from the end
+    /// of the text if they are negative.
+    Substring,
+    /// `doc -> path`, or `JSON_EXTRACT(doc, path)`: the JSON value at `path` in the JSON document
+    /// `doc`, which may also be text. Paths are of the form `$.key[index]...`. It is `NULL` if
+    /// there is no value at the path.
+    JsonExtract,
+    /// `doc ->> path`: like `JsonExtract`, but gives strings as text and integers as integers
+    /// rather than as JSON. JSON `null` gives `NULL`, and other values give their JSON text.
+    JsonExtractText,
+    /// `NOW()`, the current time in UTC. Expressions that call it change over time, and so are
+    /// re-evaluated periodically (see [`ProjectExpression::is_time_dependent`]).
+    Now,
+    /// `TIMESTAMP(value)`, the timestamp that text describes, converted to UTC if it has an
+    /// offset. It is `NULL` if the text is not a timestamp.
+    Timestamp,
+    /// `DATE_ADD(timestamp, n, unit)`, or `timestamp + INTERVAL n unit`. The unit is one of
+    /// `MICROSECOND`, `SECOND`, `MINUTE`, `HOUR`, `DAY`, `WEEK`, `MONTH`, and `YEAR`, and it is
+    /// `NULL` if it is none of them. Adding months keeps the day of the month where possible, and
+    /// otherwise gives the last day of the month.
+    DateAdd,
+    /// `DATE_SUB(timestamp, n, unit)`, or `timestamp - INTERVAL n unit`.
+    DateSub,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProjectExpression {
-    op: ArithmeticOperator,
-    left: ProjectExpressionBase,
-    right: ProjectExpressionBase,
+impl BuiltinFunction {
+    /// Looks up a function by its SQL name, ignoring case.
+    pub fn from_name(name: &str) -> Option<BuiltinFunction> {
+        match &*name.to_lowercase() {
+            "upper" | "ucase" => Some(BuiltinFunction::Upper),
+            "lower" | "lcase" => Some(BuiltinFunction::Lower),
+            "length" | "char_length" => Some(BuiltinFunction::Length),
+            "concat" => Some(BuiltinFunction::Concat),
+            "coalesce" | "ifnull" => Some(BuiltinFunction::Coalesce),
+            "date" => Some(BuiltinFunction::Date),
+            "substring" | "substr" => Some(BuiltinFunction::Substring),
+            "json_extract" | "->" => Some(BuiltinFunction::JsonExtract),
+            "->>" => Some(BuiltinFunction::JsonExtractText),
+            "now" | "current_timestamp" | "utc_timestamp" => Some(BuiltinFunction::Now),
+            "timestamp" => Some(BuiltinFunction::Timestamp),
+            "date_add" | "adddate" => Some(BuiltinFunction::DateAdd),
+            "date_sub" | "subdate" => Some(BuiltinFunction::DateSub),
+            _ => None,
+        }
+    }
+
+    fn apply(self, mut args: Vec<DataType>) -> DataType {
+        // all but COALESCE are NULL if any of their arguments are
+        if self != BuiltinFunction::Coalesce && args.iter().any(DataType::is_none) {
+            return DataType::None;
+        }
+
+        match self {
+            BuiltinFunction::Upper => text(&args[0]).to_uppercase().into(),
+            BuiltinFunction::Lower => text(&args[0]).to_lowercase().into(),
+            BuiltinFunction::Length => (text(&args[0]).chars().count() as i64).into(),
+            BuiltinFunction::Concat => args.iter().map(text).collect::<Vec<_>>().concat().into(),
+            BuiltinFunction::Coalesce => args
+                .iter()
+                .position(|a| !a.is_none())
+                .map(|i| args.swap_remove(i))
+                .unwrap_or(DataType::None),
+            BuiltinFunction::Date => date(&args[0]),
+            BuiltinFunction::Substring => {
+                let len = args.get(2).map(integer);
+                substring(&text(&args[0]), integer(&args[1]), len).into()
+            }
+            BuiltinFunction::JsonExtract => match json_extract(&args[0], &text(&args[1])) {
+                Some(v) => v.into(),
+                None => DataType::None,
+            },
+            BuiltinFunction::JsonExtractText => match json_extract(&args[0], &text(&args[1])) {
+                None | Some(serde_json::Value::Null) => DataType::None,
+                Some(serde_json::Value::String(s)) => s.into(),
+                Some(serde_json::Value::Number(ref n)) if n.is_i64() => n.as_i64().unwrap().into(),
+                Some(v) => v.to_string().into(),
+            },
+            BuiltinFunction::Now => DataType::Timestamp(chrono::Utc::now().naive_utc()),
+            BuiltinFunction::Timestamp => args[0]
+                .to_timestamp()
+                .map(DataType::Timestamp)
+                .unwrap_or(DataType::None),
+            BuiltinFunction::DateAdd | BuiltinFunction::DateSub => {
+                let n = integer(&args[1]);
+                let n = if self == BuiltinFunction::DateSub {
+                    -n
+                } else {
+                    n
+                };
+                args[0]
+                    .to_timestamp()
+                    .and_then(|ts| add_interval(ts, n, &text(&args[2])))
+                    .map(DataType::Timestamp)
+                    .unwrap_or(DataType::None)
+            }
+        }
+    }
+}
+
+impl fmt::Display for BuiltinFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            BuiltinFunction::Upper => "upper",
+            BuiltinFunction::Lower => "lower",
+            BuiltinFunction::Length => "length",
+            BuiltinFunction::Concat => "concat",
+            BuiltinFunction::Coalesce => "coalesce",
+            BuiltinFunction::Date => "date",
+            BuiltinFunction::Substring => "substring",
+            BuiltinFunction::JsonExtract => "json_extract",
+            BuiltinFunction::JsonExtractText => "json_extract_text",
+            BuiltinFunction::Now => "now",
+            BuiltinFunction::Timestamp => "timestamp",
+            BuiltinFunction::DateAdd => "date_add",
+            BuiltinFunction::DateSub => "date_sub",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The text of a value, which need not be a string.
+fn text(value: &DataType) -> Cow<str> {
+    match *value {
+        DataType::Text(..) | DataType::TinyText(..) | DataType::Json(..) => {
+            Cow::Borrowed(value.into())
+        }
+        DataType::Compressed(..) => {
+            let s: &str = (&*value.decompress()).into();
+            Cow::Owned(s.to_owned())
+        }
+        _ => Cow::Owned(value.to_string()),
+    }
+}
+
+/// The integer value of a function argument, which is 0 if it is not an integer.
+fn integer(value: &DataType) -> i64 {
+    match *value {
+        DataType::Int(n) => i64::from(n),
+        DataType::UnsignedInt(n) => i64::from(n),
+        DataType::BigInt(n) => n,
+        DataType::UnsignedBigInt(n) => n as i64,
+        _ => 0,
+    }
+}
+
+fn date(value: &DataType) -> DataType {
+    match value.to_timestamp() {
+        Some(ts) => DataType::Timestamp(ts.date().and_hms(0, 0, 0)),
+        None => DataType::None,
+    }
+}
+
+/// `ts` moved `n` of the given unit into the future, or into the past if `n` is negative.
+fn add_interval(ts: NaiveDateTime, n: i64, unit: &str) -> Option<NaiveDateTime> {
+    let months = match &*unit.trim().to_lowercase() {
+        "microsecond" => return ts.checked_add_signed(Duration::microseconds(n)),
+        "second" => return ts.checked_add_signed(Duration::seconds(n)),
+        "minute" => return ts.checked_add_signed(Duration::minutes(n)),
+        "hour" => return ts.checked_add_signed(Duration::hours(n)),
+        "day" => return ts.checked_add_signed(Duration::days(n)),
+        "week" => return ts.checked_add_signed(Duration::weeks(n)),
+        "month" => n,
+        "year" => n.checked_mul(12)?,
+        _ => return None,
+    };
+
+    let month0 = i64::from(ts.year()) * 12 + i64::from(ts.month0()) + months;
+    let year = i32::try_from(month0.div_euclid(12)).ok()?;
+    let month = month0.rem_euclid(12) as u32 + 1;
+    // clamp the day to the length of the month, so that January 31st plus a month is in February
+    let day = (1..=ts.day())
+        .rev()
+        .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))?;
+    Some(day.and_time(ts.time()))
+}
+
+/// The values `left` and `right` as they should be compared.
+///
+/// Text that is compared with a timestamp is read as a timestamp, so that times written with a
+/// UTC offset compare correctly. All other values are compared as they are.
+pub(crate) fn comparable<'a>(
+    left: &'a DataType,
+    right: &'a DataType,
+) -> (Cow<'a, DataType>, Cow<'a, DataType>) {
+    let as_timestamp = |v: &'a DataType| match v.to_timestamp() {
+        Some(ts) if !v.is_datetime() => Cow::Owned(DataType::Timestamp(ts)),
+        _ => Cow::Borrowed(v),
+    };
+    match (left.is_datetime(), right.is_datetime()) {
+        (true, false) => (Cow::Borrowed(left), as_timestamp(right)),
+        (false, true) => (as_timestamp(left), Cow::Borrowed(right)),
+        _ => (Cow::Borrowed(left), Cow::Borrowed(right)),
+    }
+}
+
+/// The value at `path` in the JSON document `doc`.
+fn json_extract(doc: &DataType, path: &str) -> Option<serde_json::Value> {
+    let mut v = doc.to_json()?;
+    let mut rest = path.trim().strip_prefix('$')?;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('.') {
+            let (key, r) = if let Some(r) = r.strip_prefix('"') {
+                let end = r.find('"')?;
+                (&r[..end], &r[end + 1..])
+            } else {
+                let end = r.find(|c: char| c == '.' || c == '[').unwrap_or(r.len());
+                r.split_at(end)
+            };
+            v = v.get_mut(key)?.take();
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']')?;
+            let i: usize = r[..end].trim().parse().ok()?;
+            v = v.get_mut(i)?.take();
+            rest = &r[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(v)
+}
+
+fn substring(s: &str, pos: i64, len: Option<i64>) -> String {
+    let chars = s.chars().count() as i64;
+    let start = match pos {
+        0 => return String::new(),
+        p if p > 0 => p - 1,
+        p => chars + p,
+    };
+    if start < 0 || start >= chars {
+        return String::new();
+    }
+    let len = len.unwrap_or(chars).max(0);
+    s.chars().skip(start as usize).take(len as usize).collect()
+}
+
+/// An expression that a projection computes an additional column with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProjectExpression {
+    /// The value of a column of the input.
+    Column(usize),
+    /// A constant.
+    Literal(DataType),
+    /// An arithmetic operation. It is `NULL` if either operand is.
+    Op {
+        op: ArithmeticOperator,
+        left: Box<ProjectExpression>,
+        right: Box<ProjectExpression>,
+    },
+    /// A call to a built-in function.
+    Call(BuiltinFunction, Vec<ProjectExpression>),
+    /// A comparison, which is 1 if it holds and 0 if it does not. It is `NULL` if either operand
+    /// is. `AND` and `OR` combine the results of other comparisons.
+    Compare {
+        op: Operator,
+        left: Box<ProjectExpression>,
+        right: Box<ProjectExpression>,
+    },
+    /// `CASE WHEN condition THEN value ... ELSE otherwise END`. The value is that of the first
+    /// branch whose condition is neither 0 nor `NULL`, or `otherwise` (or `NULL`, if there is
+    /// none) if there is no such branch.
+    Case {
+        branches: Vec<(ProjectExpression, ProjectExpression)>,
+        otherwise: Option<Box<ProjectExpression>>,
+    },
 }
 
 impl ProjectExpression {
+    /// An arithmetic operation on the results of two other expressions.
     pub fn new(
         op: ArithmeticOperator,
-        left: ProjectExpressionBase,
-        right: ProjectExpressionBase,
+        left: ProjectExpression,
+        right: ProjectExpression,
     ) -> ProjectExpression {
-        ProjectExpression { op, left, right }
+        ProjectExpression::Op {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
     }
-}
 
-impl fmt::Display for ProjectExpressionBase {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    /// Calls `f` with every column index this expression reads, so that it can change them.
+    pub fn map_columns(&mut self, f: &mut dyn FnMut(&mut usize)) {
         match *self {
-            ProjectExpressionBase::Column(u) => write!(f, "{}", u),
-            ProjectExpressionBase::Literal(ref l) => write!(f, "(lit: {})", l),
+            ProjectExpression::Column(ref mut i) => f(i),
+            ProjectExpression::Literal(_) => (),
+            ProjectExpression::Op {
+                ref mut left,
+                ref mut right,
+                ..
+            }
+            | ProjectExpression::Compare {
+                ref mut left,
+                ref mut right,
+                ..
+            } => {
+                left.map_columns(f);
+                right.map_columns(f);
+            }
+            ProjectExpression::Call(_, ref mut args) => {
+                for a in args {
+                    a.map_columns(f);
+                }
+            }
+            ProjectExpression::Case {
+                ref mut branches,
+                ref mut otherwise,
+            } => {
+                for (condition, value) in branches {
+                    condition.map_columns(f);
+                    value.map_columns(f);
+                }
+                if let Some(ref mut o) = *otherwise {
+                    o.map_columns(f);
+                }
+            }
+        }
+    }
+
+    /// Whether the value of this expression can change over time for the same record, because it
+    /// calls `NOW()`.
+    pub fn is_time_dependent(&self) -> bool {
+        match *self {
+            ProjectExpression::Column(_) | ProjectExpression::Literal(_) => false,
+            ProjectExpression::Op {
+                ref left,
+                ref right,
+                ..
+            }
+            | ProjectExpression::Compare {
+                ref left,
+                ref right,
+                ..
+            } => left.is_time_dependent() || right.is_time_dependent(),
+            ProjectExpression::Call(f, ref args) => {
+                f == BuiltinFunction::Now || args.iter().any(ProjectExpression::is_time_dependent)
+            }
+            ProjectExpression::Case {
+                ref branches,
+                ref otherwise,
+            } => {
+                branches
+                    .iter()
+                    .any(|(c, v)| c.is_time_dependent() || v.is_time_dependent())
+                    || otherwise.as_ref().map_or(false, |o| o.is_time_dependent())
+            }
+        }
+    }
+
+    /// Computes the value of this expression for the given input record.
+    pub fn eval(&self, record: &[DataType]) -> DataType {
+        match *self {
+            ProjectExpression::Column(i) => record[i].clone(),
+            ProjectExpression::Literal(ref data) => data.clone(),
+            ProjectExpression::Op {
+                ref op,
+                ref left,
+                ref right,
+            } => {
+                let left = left.eval(record);
+                let right = right.eval(record);
+                match *op {
+                    ArithmeticOperator::Add => &left + &right,
+                    ArithmeticOperator::Subtract => &left - &right,
+                    ArithmeticOperator::Multiply => &left * &right,
+                    ArithmeticOperator::Divide => &left / &right,
+                }
+            }
+            ProjectExpression::Call(f, ref args) => {
+                f.apply(args.iter().map(|a| a.eval(record)).collect())
+            }
+            ProjectExpression::Compare {
+                ref op,
+                ref left,
+                ref right,
+            } => {
+                let left = left.eval(record);
+                let right = right.eval(record);
+                if *op == Operator::And || *op == Operator::Or {
+                    // `FALSE AND NULL` is false and `TRUE OR NULL` is true, since the unknown
+                    // side cannot change the result
+                    let decides = *op == Operator::Or;
+                    let known = |v: &DataType| if v.is_none() { None } else { Some(holds(v)) };
+                    let (left, right) = (known(&left), known(&right));
+                    return if left == Some(decides) || right == Some(decides) {
+                        DataType::from(decides as i32)
+                    } else if left.is_none() || right.is_none() {
+                        DataType::None
+                    } else {
+                        DataType::from(!decides as i32)
+                    };
+                }
+                if left.is_none() || right.is_none() {
+                    return DataType::None;
+                }
+                let (left, right) = comparable(&left, &right);
+                let result = match *op {
+                    Operator::Equal => left == right,
+                    Operator::NotEqual => left != right,
+                    Operator::Greater => left > right,
+                    Operator::GreaterOrEqual => left >= right,
+                    Operator::Less => left < right,
+                    Operator::LessOrEqual => left <= right,
+                    _ => unimplemented!(),
+                };
+                DataType::from(result as i32)
+            }
+            ProjectExpression::Case {
+                ref branches,
+                ref otherwise,
+            } => branches
+                .iter()
+                .find(|(condition, _)| holds(&condition.eval(record)))
+                .map(|(_, value)| value.eval(record))
+                .or_else(|| otherwise.as_ref().map(|o| o.eval(record)))
+                .unwrap_or(DataType::None),
         }
     }
 }
 
+/// True if a condition with the given value holds.
+pub(crate) fn holds(value: &DataType) -> bool {
+    !value.is_none() && *value != DataType::Int(0)
+}
+
 impl fmt::Display for ProjectExpression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.op {
-            ArithmeticOperator::Add => "+",
-            ArithmeticOperator::Subtract => "-",
-            ArithmeticOperator::Divide => "/",
-            ArithmeticOperator::Multiply => "*",
-        };
+        match *self {
+            ProjectExpression::Column(u) => write!(f, "{}", u),
+            ProjectExpression::Literal(ref l) => write!(f, "(lit: {})", l),
+            ProjectExpression::Op {
+                ref op,
+                ref left,
+                ref right,
+            } => {
+                let op = match *op {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Divide => "/",
+                    ArithmeticOperator::Multiply => "*",
+                };
+                write!(f, "{} {} {}", Operand(left), op, Operand(right))
+            }
+            ProjectExpression::Call(func, ref args) => write!(
+                f,
+                "{}({})",
+                func,
+                args.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ProjectExpression::Compare {
+                ref op,
+                ref left,
+                ref right,
+            } => write!(f, "{} {} {}", Operand(left), op, Operand(right)),
+            ProjectExpression::Case {
+                ref branches,
+                ref otherwise,
+            } => {
+                write!(f, "case")?;
+                for (condition, value) in branches {
+                    write!(f, " when {} then {}", condition, value)?;
+                }
+                if let Some(ref otherwise) = *otherwise {
+                    write!(f, " else {}", otherwise)?;
+                }
+                write!(f, " end")
+            }
+        }
+    }
+}
+
+/// Displays an operand of a binary operation, parenthesized if it is an operation itself.
+struct Operand<'a>(&'a ProjectExpression);
 
-        write!(f, "{} {} {}", self.left, op, self.right)
+impl<'a> fmt::Display for Operand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            ProjectExpression::Op { .. } | ProjectExpression::Compare { .. } => {
+                write!(f, "({})", self.0)
+            }
+            ref e => write!(f, "{}", e),
+        }
     }
 }
 
@@ -100,25 +561,6 @@ impl Project {
     }
 }
 
-fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
-    let left = match expression.left {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    let right = match expression.right {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    match expression.op {
-        ArithmeticOperator::Add => left + right,
-        ArithmeticOperator::Subtract => left - right,
-        ArithmeticOperator::Multiply => left * right,
-        ArithmeticOperator::Divide => left / right,
-    }
-}
-
 impl Ingredient for Project {
     fn take(&mut self) -> NodeOperator {
         Clone::clone(self).into()
@@ -168,7 +610,7 @@ impl Ingredient for Project {
                         Some(emit) => Box::new(rs.map(move |r| {
                             let mut new_r = Vec::with_capacity(r.len());
                             let mut expr: Vec<DataType> = if let Some(ref e) = expressions {
-                                e.iter().map(|i| eval_expression(i, &r[..])).collect()
+                                e.iter().map(|e| e.eval(&r[..])).collect()
                             } else {
                                 vec![]
                             };
@@ -239,7 +681,7 @@ impl Ingredient for Project {
                 }
 
                 if let Some(ref e) = self.expressions {
-                    new_r.extend(e.iter().map(|i| eval_expression(i, &r[..])));
+                    new_r.extend(e.iter().map(|e| e.eval(&r[..])));
                 }
 
                 if let Some(ref a) = self.additional {
@@ -351,11 +793,11 @@ mod tests {
     }
 
     fn setup_column_arithmetic(op: ArithmeticOperator) -> ops::test::MockGraph {
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
+        let expression = ProjectExpression::new(
             op,
-        };
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        );
 
         setup_arithmetic(expression)
     }
@@ -473,11 +915,11 @@ mod tests {
     #[test]
     fn it_forwards_arithmetic_w_literals() {
         let number: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Literal(number),
-            op: ArithmeticOperator::Multiply,
-        };
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Multiply,
+            ProjectExpression::Column(0),
+            ProjectExpression::Literal(number),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![10.into(), 0.into()];
@@ -491,11 +933,11 @@ mod tests {
     fn it_forwards_arithmetic_w_only_literals() {
         let a: DataType = 80.into();
         let b: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Literal(a),
-            right: ProjectExpressionBase::Literal(b),
-            op: ArithmeticOperator::Divide,
-        };
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Divide,
+            ProjectExpression::Literal(a),
+            ProjectExpression::Literal(b),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![0.into(), 0.into()];
@@ -505,6 +947,319 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_forwards_nested_arithmetic() {
+        // (x + y) * 2
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Multiply,
+            ProjectExpression::new(
+                ArithmeticOperator::Add,
+                ProjectExpression::Column(0),
+                ProjectExpression::Column(1),
+            ),
+            ProjectExpression::Literal(2.into()),
+        );
+        assert_eq!(format!("{}", expression), "(0 + 1) * (lit: 2)");
+
+        let mut p = setup_arithmetic(expression);
+        let rec = vec![10.into(), 5.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), 5.into(), 30.into()]].into()
+        );
+        let rec = vec![10.into(), DataType::None];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), DataType::None, DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_function_calls() {
+        let upper =
+            ProjectExpression::Call(BuiltinFunction::Upper, vec![ProjectExpression::Column(0)]);
+        let mut p = setup_arithmetic(upper);
+        let rec = vec!["hello".into(), 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["hello".into(), 1.into(), "HELLO".into()]].into()
+        );
+
+        let concat = ProjectExpression::Call(
+            BuiltinFunction::Concat,
+            vec![
+                ProjectExpression::Column(0),
+                ProjectExpression::Literal("-".into()),
+                ProjectExpression::Column(1),
+            ],
+        );
+        let mut p = setup_arithmetic(concat);
+        let rec = vec!["a".into(), 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["a".into(), 1.into(), "a-1".into()]].into()
+        );
+
+        let coalesce = ProjectExpression::Call(
+            BuiltinFunction::Coalesce,
+            vec![ProjectExpression::Column(0), ProjectExpression::Column(1)],
+        );
+        let mut p = setup_arithmetic(coalesce);
+        let rec = vec![DataType::None, 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![DataType::None, 1.into(), 1.into()]].into()
+        );
+
+        assert_eq!(
+            BuiltinFunction::from_name("UPPER"),
+            Some(BuiltinFunction::Upper)
+        );
+        assert_eq!(BuiltinFunction::from_name("nope"), None);
+    }
+
+    #[test]
+    fn it_forwards_dates_and_substrings() {
+        let date =
+            ProjectExpression::Call(BuiltinFunction::Date, vec![ProjectExpression::Column(0)]);
+        let mut p = setup_arithmetic(date);
+        let day = DataType::Timestamp("2020-03-04T00:00:00".parse().unwrap());
+        let noon = DataType::Timestamp("2020-03-04T12:34:56".parse().unwrap());
+        for input in &[noon, "2020-03-04 12:34:56".into(), "2020-03-04".into()] {
+            assert_eq!(
+                p.narrow_one_row(vec![input.clone(), 1.into()], false),
+                vec![vec![input.clone(), 1.into(), day.clone()]].into()
+            );
+        }
+        let rec = vec!["yesterday".into(), 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["yesterday".into(), 1.into(), DataType::None]].into()
+        );
+
+        let substring = ProjectExpression::Call(
+            BuiltinFunction::Substring,
+            vec![
+                ProjectExpression::Column(0),
+                ProjectExpression::Column(1),
+                ProjectExpression::Literal(3.into()),
+            ],
+        );
+        let mut p = setup_arithmetic(substring);
+        let rec = vec!["noria rocks".into(), 2.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["noria rocks".into(), 2.into(), "ori".into()]].into()
+        );
+        let rec = vec!["noria rocks".into(), (-5).into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["noria rocks".into(), (-5).into(), "roc".into()]].into()
+        );
+        assert_eq!(super::substring("noria", 3, None), "ria");
+        assert_eq!(super::substring("noria", 0, None), "");
+        assert_eq!(super::substring("noria", 9, Some(1)), "");
+    }
+
+    #[test]
+    fn it_forwards_interval_arithmetic() {
+        let ts = |t: &str| DataType::Timestamp(t.parse().unwrap());
+        let shift = |f, n: i32, unit: &str| {
+            ProjectExpression::Call(
+                f,
+                vec![
+                    ProjectExpression::Column(0),
+                    ProjectExpression::Literal(n.into()),
+                    ProjectExpression::Literal(unit.into()),
+                ],
+            )
+        };
+        let cases = vec![
+            (
+                shift(BuiltinFunction::DateSub, 1, "HOUR"),
+                "2020-03-04T11:00:00",
+            ),
+            (
+                shift(BuiltinFunction::DateAdd, 90, "minute"),
+                "2020-03-04T13:30:00",
+            ),
+            (
+                shift(BuiltinFunction::DateAdd, 1, "WEEK"),
+                "2020-03-11T12:00:00",
+            ),
+            (
+                shift(BuiltinFunction::DateSub, 3, "MONTH"),
+                "2019-12-04T12:00:00",
+            ),
+        ];
+        for (e, expected) in cases {
+            let mut p = setup_arithmetic(e);
+            let rec = vec!["2020-03-04T14:00:00+02:00".into(), 1.into()];
+            assert_eq!(p.narrow_one_row(rec, false)[0].rec()[2], ts(expected));
+        }
+        let mut p = setup_arithmetic(shift(BuiltinFunction::DateAdd, 1, "fortnight"));
+        let rec = vec![ts("2020-03-04T12:00:00"), 1.into()];
+        assert_eq!(p.narrow_one_row(rec, false)[0].rec()[2], DataType::None);
+
+        // months that are too short for the day end on their last day
+        assert_eq!(
+            add_interval("2020-01-31T08:00:00".parse().unwrap(), 1, "month"),
+            Some("2020-02-29T08:00:00".parse().unwrap())
+        );
+        assert_eq!(
+            add_interval("2020-02-29T08:00:00".parse().unwrap(), -1, "year"),
+            Some("2019-02-28T08:00:00".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn it_compares_timestamps_with_text() {
+        let recent = ProjectExpression::Compare {
+            op: Operator::Greater,
+            left: Box::new(ProjectExpression::Column(0)),
+            right: Box::new(ProjectExpression::Call(
+                BuiltinFunction::DateSub,
+                vec![
+                    ProjectExpression::Call(BuiltinFunction::Now, vec![]),
+                    ProjectExpression::Literal(1.into()),
+                    ProjectExpression::Literal("hour".into()),
+                ],
+            )),
+        };
+        assert!(recent.is_time_dependent());
+        let now = chrono::Utc::now().naive_utc();
+        assert_eq!(recent.eval(&[now.into()]), 1.into());
+        assert_eq!(recent.eval(&[(now - Duration::hours(2)).into()]), 0.into());
+
+        // noon in UTC is after 13:30 in a timezone two hours ahead
+        let after = ProjectExpression::Compare {
+            op: Operator::Greater,
+            left: Box::new(ProjectExpression::Column(0)),
+            right: Box::new(ProjectExpression::Literal(
+                "2020-03-04 13:30:00+02:00".into(),
+            )),
+        };
+        assert!(!after.is_time_dependent());
+        let noon: NaiveDateTime = "2020-03-04T12:00:00".parse().unwrap();
+        assert_eq!(after.eval(&[noon.into()]), 1.into());
+    }
+
+    #[test]
+    fn it_forwards_json_extractions() {
+        let extract = |f, path: &str| {
+            ProjectExpression::Call(
+                f,
+                vec![
+                    ProjectExpression::Column(0),
+                    ProjectExpression::Literal(path.into()),
+                ],
+            )
+        };
+        let doc = DataType::json(r#"{"user": {"id": 7, "name": "ada", "tags": ["x", "y"]}}"#);
+        let doc = doc.unwrap();
+
+        let mut p = setup_arithmetic(extract(BuiltinFunction::JsonExtract, "$.user.tags[1]"));
+        assert_eq!(
+            p.narrow_one_row(vec![doc.clone(), 1.into()], false),
+            vec![vec![
+                doc.clone(),
+                1.into(),
+                DataType::json(r#""y""#).unwrap()
+            ]]
+            .into()
+        );
+
+        let mut p = setup_arithmetic(extract(BuiltinFunction::JsonExtractText, "$.user.name"));
+        assert_eq!(
+            p.narrow_one_row(vec![doc.clone(), 1.into()], false),
+            vec![vec![doc.clone(), 1.into(), "ada".into()]].into()
+        );
+
+        let mut p = setup_arithmetic(extract(BuiltinFunction::JsonExtractText, "$.user.id"));
+        assert_eq!(
+            p.narrow_one_row(vec![doc.clone(), 1.into()], false),
+            vec![vec![doc.clone(), 1.into(), 7.into()]].into()
+        );
+
+        // text is parsed as JSON too, and missing values are NULL
+        let mut p = setup_arithmetic(extract(BuiltinFunction::JsonExtractText, "$.nope"));
+        let rec = vec![r#"{"a": 1}"#.into(), 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec.clone(), false),
+            vec![vec![rec[0].clone(), 1.into(), DataType::None]].into()
+        );
+
+        assert_eq!(super::json_extract(&doc, "$.user.tags[2]"), None);
+        assert_eq!(super::json_extract(&doc, "user"), None);
+        assert_eq!(
+            super::json_extract(&doc, r#"$."user".id"#),
+            Some(serde_json::json!(7))
+        );
+    }
+
+    #[test]
+    fn it_forwards_case() {
+        // CASE WHEN x > 10 THEN 'big' WHEN x > 5 THEN 'medium' ELSE 'small' END
+        let bigger_than = |n: i32| ProjectExpression::Compare {
+            op: Operator::Greater,
+            left: Box::new(ProjectExpression::Column(0)),
+            right: Box::new(ProjectExpression::Literal(n.into())),
+        };
+        let case = ProjectExpression::Case {
+            branches: vec![
+                (bigger_than(10), ProjectExpression::Literal("big".into())),
+                (bigger_than(5), ProjectExpression::Literal("medium".into())),
+            ],
+            otherwise: Some(Box::new(ProjectExpression::Literal("small".into()))),
+        };
+
+        let mut p = setup_arithmetic(case);
+        for &(x, size) in &[(20, "big"), (7, "medium"), (1, "small")] {
+            let rec = vec![x.into(), 0.into()];
+            assert_eq!(
+                p.narrow_one_row(rec, false),
+                vec![vec![x.into(), 0.into(), size.into()]].into()
+            );
+        }
+
+        // a NULL condition does not hold
+        let rec = vec![DataType::None, 0.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![DataType::None, 0.into(), "small".into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_logic_with_nulls() {
+        let logic = |op| ProjectExpression::Compare {
+            op,
+            left: Box::new(ProjectExpression::Column(0)),
+            right: Box::new(ProjectExpression::Column(1)),
+        };
+        let (t, f, n) = (DataType::from(1), DataType::from(0), DataType::None);
+        let cases = [
+            (Operator::And, &f, &n, &f),
+            (Operator::And, &t, &n, &n),
+            (Operator::And, &t, &t, &t),
+            (Operator::Or, &n, &t, &t),
+            (Operator::Or, &n, &f, &n),
+            (Operator::Or, &f, &f, &f),
+        ];
+        for &(ref op, left, right, result) in &cases {
+            let mut p = setup_arithmetic(logic(op.clone()));
+            let rec = vec![left.clone(), right.clone()];
+            assert_eq!(
+                p.narrow_one_row(rec, false),
+                vec![vec![left.clone(), right.clone(), result.clone()]].into(),
+                "{} {} {}",
+                left,
+                op,
+                right
+            );
+        }
+    }
+
     fn setup_query_through(
         mut state: Box<dyn State>,
         permutation: &[usize],
@@ -617,11 +1372,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::new(
+            ArithmeticOperator::Add,
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        )]);
 
         let state = Box::new(MemoryState::default());
         let (p, states) = setup_query_through(state, &[1], additional, expressions);
@@ -632,11 +1387,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals_persistent() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::new(
+            ArithmeticOperator::Add,
+            ProjectExpression::Column(0),
+            ProjectExpression::Column(1),
+        )]);
 
         let state = Box::new(PersistentState::new(
             String::from("it_queries_through_w_arithmetic_and_literals_persistent"),
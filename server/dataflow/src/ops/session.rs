@@ -0,0 +1,370 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Session assigns the records of each partition of its input to session windows, and appends
+/// the start of each record's session as a new column.
+///
+/// A session is a maximal run of records whose timestamps are each at most `gap` after the
+/// previous one, so a new record may extend a session, start one of its own, or merge the two
+/// sessions on either side of it. Grouping by the partition columns and the session start then
+/// aggregates over sessions. Records without a timestamp are not part of any session, and get a
+/// NULL session start.
+///
+/// Like `Window`, a change to a partition recomputes the partition from the operator's own state,
+/// but only the records whose session changed are emitted. Adding a record to the end of a
+/// session therefore emits just that record, while merging two sessions re-emits the records of
+/// the later one.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    src: IndexPair,
+
+    // some cache state
+    us: Option<IndexPair>,
+    cols: usize,
+
+    timestamp: usize,
+    gap: i64,
+    partition_by: Vec<usize>,
+}
+
+impl Session {
+    /// Construct a new Session operator.
+    ///
+    /// `src` is this operator's ancestor, `timestamp` is the column that orders the records of a
+    /// partition in time, `gap` is the longest time between two records of the same session, and
+    /// `partition_by` indicates the columns that this operator is keyed on. For timestamp columns,
+    /// `gap` is in seconds; for integer columns, it is in the same unit as the column.
+    pub fn new(src: NodeIndex, timestamp: usize, gap: i64, partition_by: Vec<usize>) -> Self {
+        assert!(gap >= 0, "session gap must not be negative");
+
+        let mut partition_by = partition_by;
+        partition_by.sort();
+
+        Session {
+            src: src.into(),
+
+            us: None,
+            cols: 0,
+
+            timestamp,
+            gap,
+            partition_by,
+        }
+    }
+
+    /// The column that orders the records of each partition in time.
+    pub fn timestamp_column(&self) -> usize {
+        self.timestamp
+    }
+
+    /// True if `later` is at most one gap after `earlier`.
+    fn within_gap(&self, earlier: &DataType, later: &DataType) -> bool {
+        match (earlier, later) {
+            (&DataType::Timestamp(ref a), &DataType::Timestamp(ref b)) => {
+                b.signed_duration_since(*a).num_milliseconds() <= self.gap * 1000
+            }
+            _ => i128::from(later) - i128::from(earlier) <= i128::from(self.gap),
+        }
+    }
+
+    /// Compute the sessions of all the records in a partition.
+    ///
+    /// Returns the records in time order, each with the start of its session appended.
+    fn compute(&self, mut rows: Vec<Vec<DataType>>) -> Vec<Vec<DataType>> {
+        let ts = self.timestamp;
+        rows.sort_by(|a, b| a[ts].cmp(&b[ts]).then_with(|| a.cmp(b)));
+
+        let mut start = DataType::None;
+        let mut last: Option<DataType> = None;
+        for r in &mut rows {
+            if r[ts].is_none() {
+                r.push(DataType::None);
+                continue;
+            }
+            match last {
+                Some(ref l) if self.within_gap(l, &r[ts]) => (),
+                _ => start = r[ts].clone(),
+            }
+            last = Some(r[ts].clone());
+            r.push(start.clone());
+        }
+        rows
+    }
+}
+
+impl Ingredient for Session {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        self.cols = srcn.fields().len();
+        assert!(
+            self.timestamp < self.cols,
+            "cannot compute sessions over non-existing column"
+        );
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        // who's our parent really?
+        self.src.remap(remap);
+
+        // who are we?
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        _: &DomainNodes,
+        state: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        if rs.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
+        // handle all the changes to a partition at once, so that it is only recomputed once
+        let partition_by = &self.partition_by;
+        let mut rs: Vec<_> = rs.into();
+        rs.sort_by(|a, b| {
+            partition_by
+                .iter()
+                .map(|&col| &a[col])
+                .cmp(partition_by.iter().map(|&col| &b[col]))
+        });
+
+        let us = self.us.unwrap();
+        let db = state
+            .get(*us)
+            .expect("session operators must have their own state materialized");
+
+        let mut out = Vec::new();
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+        let mut rs = rs.into_iter().peekable();
+        while let Some(r) = rs.next() {
+            let key: Vec<_> = partition_by.iter().map(|&col| r[col].clone()).collect();
+            let mut changes = vec![r];
+            while let Some(r) = rs.peek() {
+                if partition_by.iter().map(|&col| &r[col]).ne(key.iter()) {
+                    break;
+                }
+                changes.push(rs.next().unwrap());
+            }
+
+            let old: Vec<Vec<DataType>> =
+                match db.lookup(&partition_by[..], &KeyType::from(&key[..])) {
+                    LookupResult::Some(rs) => {
+                        if replay_key_cols.is_some() {
+                            lookups.push(Lookup {
+                                on: *us,
+                                cols: partition_by.clone(),
+                                key: key.clone(),
+                            });
+                        }
+                        rs.into_iter().map(Cow::into_owned).collect()
+                    }
+                    LookupResult::Missing => {
+                        misses.extend(changes.into_iter().map(|r| Miss {
+                            on: *us,
+                            lookup_idx: partition_by.clone(),
+                            lookup_cols: partition_by.clone(),
+                            replay_cols: replay_key_cols.map(Vec::from),
+                            record: r.extract().0,
+                        }));
+                        continue;
+                    }
+                };
+
+            let mut rows: Vec<_> = old.iter().map(|r| r[..self.cols].to_vec()).collect();
+            for r in changes {
+                match r {
+                    Record::Positive(r) => rows.push(r),
+                    Record::Negative(r) => {
+                        if let Some(p) = rows.iter().position(|x| *x == r) {
+                            rows.swap_remove(p);
+                        }
+                    }
+                }
+            }
+
+            // only emit the records whose session changed
+            let mut new = self.compute(rows);
+            for r in old {
+                match new.iter().position(|x| *x == r) {
+                    Some(p) => {
+                        new.swap_remove(p);
+                    }
+                    None => out.push(Record::Negative(r)),
+                }
+            }
+            out.extend(new.into_iter().map(Record::Positive));
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            lookups,
+            misses,
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![(this, self.partition_by.clone())]
+            .into_iter()
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.cols {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), col)])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Session");
+        }
+
+        let partition_cols = self
+            .partition_by
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "SESSION({}, {}) γ[{}]",
+            self.timestamp, self.gap, partition_cols
+        )
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if col == self.cols {
+            return vec![(self.src.as_global(), None)];
+        }
+        vec![(self.src.as_global(), Some(col))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup() -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "t"]);
+        g.set_op(
+            "session",
+            &["x", "y", "t", "start"],
+            Session::new(s.as_global(), 2, 10, vec![1]),
+            true,
+        );
+        g
+    }
+
+    #[test]
+    fn it_extends_sessions() {
+        let mut g = setup();
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 100.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), 110.into()];
+        let r3: Vec<DataType> = vec![3.into(), "a".into(), 121.into()];
+
+        let a = g.narrow_one_row(r1.clone(), true);
+        assert_eq!(
+            a,
+            vec![vec![1.into(), "a".into(), 100.into(), 100.into()]].into()
+        );
+
+        // a record within the gap joins the session, and leaves the others alone
+        let a = g.narrow_one_row(r2.clone(), true);
+        assert_eq!(
+            a,
+            vec![vec![2.into(), "a".into(), 110.into(), 100.into()]].into()
+        );
+
+        // a record beyond it starts a new one
+        let a = g.narrow_one_row(r3.clone(), true);
+        assert_eq!(
+            a,
+            vec![vec![3.into(), "a".into(), 121.into(), 121.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_merges_and_splits_sessions() {
+        let mut g = setup();
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 100.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), 120.into()];
+        let r3: Vec<DataType> = vec![3.into(), "a".into(), 110.into()];
+        let r4: Vec<DataType> = vec![4.into(), "b".into(), 115.into()];
+
+        let a = g.narrow_one(vec![r1.clone(), r2.clone(), r4.clone()], true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_positive(&[2.into(), "a".into(), 120.into(), 120.into()][..]));
+
+        // a record between two sessions merges them
+        let a = g.narrow_one_row(r3.clone(), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_positive(&[3.into(), "a".into(), 110.into(), 100.into()][..]));
+        assert!(a.has_negative(&[2.into(), "a".into(), 120.into(), 120.into()][..]));
+        assert!(a.has_positive(&[2.into(), "a".into(), 120.into(), 100.into()][..]));
+
+        // and removing it splits them again
+        let a = g.narrow_one_row((r3.clone(), false), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_negative(&[3.into(), "a".into(), 110.into(), 100.into()][..]));
+        assert!(a.has_negative(&[2.into(), "a".into(), 120.into(), 100.into()][..]));
+        assert!(a.has_positive(&[2.into(), "a".into(), 120.into(), 120.into()][..]));
+    }
+
+    #[test]
+    fn it_ignores_missing_timestamps() {
+        let mut g = setup();
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 100.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), DataType::None];
+
+        let a = g.narrow_one(vec![r1.clone(), r2.clone()], true);
+        assert_eq!(a.len(), 2);
+        assert!(a.has_positive(&[1.into(), "a".into(), 100.into(), 100.into()][..]));
+        assert!(a.has_positive(&[2.into(), "a".into(), DataType::None, DataType::None][..]));
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 2.into();
+        let g = setup();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup();
+        assert_eq!(
+            g.node().resolve(0),
+            Some(vec![(g.narrow_base_id().as_global(), 0)])
+        );
+        assert_eq!(g.node().resolve(3), None);
+    }
+}
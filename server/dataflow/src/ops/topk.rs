@@ -1,15 +1,17 @@
 use std::borrow::Cow;
-use std::cmp::Ordering;
+use std::cmp::{self, Ordering};
 use std::collections::HashMap;
+use std::mem;
 
 use crate::prelude::*;
+use common::SizeOf;
 
 use nom_sql::OrderType;
 
 #[derive(Clone, Serialize, Deserialize)]
-struct Order(Vec<(usize, OrderType)>);
+pub(crate) struct Order(Vec<(usize, OrderType)>);
 impl Order {
-    fn cmp(&self, a: &[DataType], b: &[DataType]) -> Ordering {
+    pub(crate) fn cmp(&self, a: &[DataType], b: &[DataType]) -> Ordering {
         for &(c, ref order_type) in &self.0 {
             let result = match *order_type {
                 OrderType::OrderAscending => a[c].cmp(&b[c]),
@@ -29,11 +31,30 @@ impl From<Vec<(usize, OrderType)>> for Order {
     }
 }
 
+/// The most memory the spare rows of a single TopK operator may take up.
+const SPARE_MEMORY_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// What a TopK operator knows about a group beyond the k rows in its state.
+#[derive(Clone, Default)]
+struct GroupStats {
+    /// The number of rows that have left the top k of the group.
+    churn: usize,
+    /// Rows ranked directly below the top k, best first.
+    spares: Vec<Vec<DataType>>,
+    /// Whether `spares` holds *every* row ranked below the top k.
+    complete: bool,
+}
+
 /// TopK provides an operator that will produce the top k elements for each group.
 ///
 /// Positives are generally fast to process, while negative records can trigger expensive backwards
 /// queries. It is also worth noting that due the nature of Soup, the results of this operator are
 /// unordered.
+///
+/// To avoid those queries, the operator keeps some of the rows ranked just below the top k of each
+/// group that sees rows leave its top k, and promotes them when the top k shrinks. The more rows
+/// leave a group's top k, the more spare rows it keeps, up to k of them, and up to a fixed amount
+/// of memory across all groups.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TopK {
     src: IndexPair,
@@ -47,6 +68,12 @@ pub struct TopK {
 
     order: Order,
     k: usize,
+
+    // per-group churn and spare rows
+    #[serde(skip)]
+    groups: HashMap<Vec<DataType>, GroupStats>,
+    #[serde(skip)]
+    spare_bytes: u64,
 }
 
 impl TopK {
@@ -73,6 +100,26 @@ impl TopK {
             group_by,
             order: order.into(),
             k,
+
+            groups: HashMap::new(),
+            spare_bytes: 0,
+        }
+    }
+
+    /// Construct a TopK like this one that reads from `src` instead.
+    pub fn over(&self, src: NodeIndex) -> Self {
+        TopK {
+            src: src.into(),
+
+            us: None,
+            cols: 0,
+
+            group_by: self.group_by.clone(),
+            order: self.order.clone(),
+            k: self.k,
+
+            groups: HashMap::new(),
+            spare_bytes: 0,
         }
     }
 }
@@ -90,6 +137,9 @@ impl Ingredient for TopK {
 
             order: self.order.clone(),
             k: self.k,
+
+            groups: mem::take(&mut self.groups),
+            spare_bytes: self.spare_bytes,
         }
         .into()
     }
@@ -98,6 +148,14 @@ impl Ingredient for TopK {
         vec![self.src.as_global()]
     }
 
+    fn probe(&self) -> HashMap<String, String> {
+        let mut hm = HashMap::new();
+        let spares: usize = self.groups.values().map(|g| g.spares.len()).sum();
+        hm.insert("spares".into(), format!("{}", spares));
+        hm.insert("spare bytes".into(), format!("{}", self.spare_bytes));
+        hm
+    }
+
     fn on_connected(&mut self, g: &Graph) {
         let srcn = &g[self.src.as_global()];
         self.cols = srcn.fields().len();
@@ -118,7 +176,7 @@ impl Ingredient for TopK {
         from: LocalNodeIndex,
         rs: Records,
         replay_key_cols: Option<&[usize]>,
-        _: &DomainNodes,
+        nodes: &DomainNodes,
         state: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
@@ -155,23 +213,72 @@ impl Ingredient for TopK {
         let mut missed = false;
         // current holds (Cow<Row>, bool) where bool = is_new
         let mut current: Vec<(Cow<[DataType]>, bool)> = Vec::new();
+        let mut stats = GroupStats::default();
         let mut misses = Vec::new();
         let mut lookups = Vec::new();
 
         macro_rules! post_group {
-            ($out:ident, $current:ident, $grpk:expr, $k:expr, $order:expr) => {{
+            (
+                $out:ident,
+                $current:ident,
+                $stats:ident,
+                $grpk:expr,
+                $k:expr,
+                $order:expr,
+                $this:ident
+            ) => {{
+                // rows that end up below the top k can only become spares if we know that there
+                // are no better rows we haven't seen, which is the case if they rank no lower than
+                // a row we know to be among the spares or the top k.
+                let threshold = $stats.spares.last().cloned().or_else(|| {
+                    $current
+                        .iter()
+                        .filter(|&&(_, is_new)| !is_new)
+                        .map(|(r, _)| r)
+                        .min_by(|a, b| $order.cmp(a, b))
+                        .map(|r| r.to_vec())
+                });
+
+                if $current.len() < $k {
+                    let n = cmp::min($k - $current.len(), $stats.spares.len());
+                    $current.extend($stats.spares.drain(..n).map(|r| (Cow::Owned(r), true)));
+                }
+
+                if $grpk == $k && $current.len() < $grpk && !$stats.complete {
+                    // there used to be k things in the group, and now there are fewer than k. the
+                    // next best rows are only in our parent, so find them there. the parent has
+                    // already seen this batch, so it has all the rows we have, and then some.
+                    let key = KeyType::from(&grp[..]);
+                    match $this.lookup(*$this.src, &group_by[..], &key, nodes, state) {
+                        Some(Some(rs)) => {
+                            if replay_key_cols.is_some() {
+                                lookups.push(Lookup {
+                                    on: *$this.src,
+                                    cols: group_by.clone(),
+                                    key: grp.clone(),
+                                });
+                            }
+
+                            // the parent also has any spares we still hold
+                            $stats.spares.clear();
+                            let mut rs: Vec<_> = rs.collect();
+                            for (r, _) in &$current {
+                                if let Some(i) = rs.iter().position(|x| **x == **r) {
+                                    rs.swap_remove(i);
+                                }
+                            }
+                            $current.extend(rs.into_iter().map(|r| (r, true)));
+                            $stats.complete = true;
+                        }
+                        _ => unimplemented!("topk parent state for group is not available"),
+                    }
+                }
+
                 $current.sort_unstable_by(|a, b| $order.cmp(&*a.0, &*b.0));
 
                 let start = $current.len().saturating_sub($k);
 
                 if $grpk == $k {
-                    if $current.len() < $grpk {
-                        // there used to be k things in the group
-                        // now there are fewer than k
-                        // we don't know if querying would bring us back to k
-                        unimplemented!();
-                    }
-
                     // FIXME: if all the elements with the smallest value in the new topk are new,
                     // then it *could* be that there exists some value that is greater than all
                     // those values, and <= the smallest old value. we would only discover that by
@@ -211,14 +318,38 @@ impl Ingredient for TopK {
                     }
                 }
 
-                if !$current.is_empty() {
-                    $out.extend($current.drain(..).filter_map(|(r, is_new)| {
-                        if !is_new {
-                            Some(Record::Negative(r.into_owned()))
-                        } else {
-                            None
-                        }
-                    }));
+                let mut spares = mem::take(&mut $stats.spares);
+                for (r, is_new) in $current.drain(..) {
+                    let known = $stats.complete
+                        || threshold
+                            .as_ref()
+                            .map(|t| $order.cmp(&r, t) != Ordering::Less)
+                            .unwrap_or(false);
+                    if !is_new {
+                        $out.push(Record::Negative(r.to_vec()));
+                        $stats.churn += 1;
+                    }
+                    if known {
+                        spares.push(r.into_owned());
+                    }
+                }
+                spares.sort_by(|a, b| $order.cmp(b, a));
+
+                // high-churn groups keep more spares, as long as there's memory for them
+                let cap = cmp::min($stats.churn, $k);
+                if spares.len() > cap {
+                    spares.truncate(cap);
+                    $stats.complete = false;
+                }
+                let mut bytes: u64 = spares.iter().map(SizeOf::deep_size_of).sum();
+                while $this.spare_bytes + bytes > SPARE_MEMORY_LIMIT {
+                    bytes -= spares.pop().unwrap().deep_size_of();
+                    $stats.complete = false;
+                }
+                $this.spare_bytes += bytes;
+                $stats.spares = spares;
+                if $stats.churn != 0 || !$stats.spares.is_empty() {
+                    $this.groups.insert(grp.clone(), mem::take(&mut $stats));
                 }
             }};
         };
@@ -229,13 +360,17 @@ impl Ingredient for TopK {
 
                 // first, tidy up the old one
                 if !grp.is_empty() {
-                    post_group!(out, current, grpk, self.k, self.order);
+                    post_group!(out, current, stats, grpk, self.k, self.order, self);
                 }
 
                 // make ready for the new one
                 grp.clear();
                 grp.extend(group_by.iter().map(|&col| &r[col]).cloned());
 
+                // the group's spares are only counted again once we're done with it
+                stats = self.groups.remove(&grp).unwrap_or_default();
+                self.spare_bytes -= stats.spares.iter().map(SizeOf::deep_size_of).sum::<u64>();
+
                 // check out current state
                 match db.lookup(&group_by[..], &KeyType::from(&grp[..])) {
                     LookupResult::Some(rs) => {
@@ -249,9 +384,17 @@ impl Ingredient for TopK {
 
                         missed = false;
                         grpk = rs.len();
+                        if grpk < self.k {
+                            // the state holds every row in the group
+                            stats.spares.clear();
+                            stats.complete = true;
+                        }
                         current.extend(rs.into_iter().map(|r| (r, false)))
                     }
                     LookupResult::Missing => {
+                        // anything we knew about the group went away with its state
+                        stats = GroupStats::default();
+                        grpk = 0;
                         missed = true;
                     }
                 }
@@ -273,14 +416,17 @@ impl Ingredient for TopK {
                             let (_, was_new) = current.swap_remove(p);
                             if !was_new {
                                 out.push(Record::Negative(r));
+                                stats.churn += 1;
                             }
+                        } else if let Some(p) = stats.spares.iter().position(|x| *r == *x) {
+                            stats.spares.remove(p);
                         }
                     }
                 }
             }
         }
         if !grp.is_empty() {
-            post_group!(out, current, grpk, self.k, self.order);
+            post_group!(out, current, stats, grpk, self.k, self.order, self);
         }
 
         ProcessingResult {
@@ -291,7 +437,13 @@ impl Ingredient for TopK {
     }
 
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
-        vec![(this, self.group_by.clone())].into_iter().collect()
+        // our parent is queried for the next best rows when a group's top k shrinks
+        vec![
+            (this, self.group_by.clone()),
+            (self.src.as_global(), self.group_by.clone()),
+        ]
+        .into_iter()
+        .collect()
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
@@ -395,7 +547,27 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
+    fn it_orders_decimals() {
+        let (mut g, _) = setup(false);
+        let d = |s| DataType::decimal(s).unwrap();
+
+        let r250: Vec<DataType> = vec![1.into(), "z".into(), d("2.50")];
+        let r2125: Vec<DataType> = vec![2.into(), "z".into(), d("2.125")];
+        let r3: Vec<DataType> = vec![3.into(), "z".into(), 3.into()];
+        let r22: Vec<DataType> = vec![4.into(), "z".into(), d("2.2")];
+
+        g.narrow_one_row(r250, true);
+        g.narrow_one_row(r2125.clone(), true);
+        g.narrow_one_row(r3, true);
+
+        // 2.2 beats 2.125, even though 2125 > 22
+        let a = g.narrow_one_row(r22.clone(), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r2125.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r22.clone(), true).into()));
+    }
+
+    #[test]
     fn it_must_query() {
         let (mut g, s) = setup(false);
 
@@ -444,6 +616,59 @@ mod tests {
         assert!(a[1] == (r10b.clone(), true).into() || a[1] == (r10c.clone(), true).into());
     }
 
+    #[test]
+    fn it_refills_from_spares() {
+        let (mut g, _) = setup(false);
+        let ni = g.node().local_addr();
+
+        let r12: Vec<DataType> = vec![1.into(), "z".into(), 12.into()];
+        let r10: Vec<DataType> = vec![2.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+        let r15: Vec<DataType> = vec![5.into(), "z".into(), 15.into()];
+
+        g.narrow_one_row(r12.clone(), true);
+        g.narrow_one_row(r10.clone(), true);
+        g.narrow_one_row(r11.clone(), true);
+
+        // 10 leaves the top k, but is kept around since the group now has churn
+        let a = g.narrow_one_row(r15.clone(), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r10.clone(), false).into()));
+        assert_eq!(g.node().probe()["spares"], "1");
+
+        // so removing 15 brings 10 back without querying
+        let a = g.narrow_one_row((r15.clone(), false), true);
+        assert_eq!(a.len(), 2);
+        assert!(a.iter().any(|r| r == &(r15.clone(), false).into()));
+        assert!(a.iter().any(|r| r == &(r10.clone(), true).into()));
+        assert_eq!(g.states[ni].rows(), 3);
+        assert_eq!(g.node().probe()["spares"], "0");
+    }
+
+    #[test]
+    fn it_queries_parent_without_spares() {
+        let (mut g, s) = setup(false);
+
+        let r12: Vec<DataType> = vec![1.into(), "z".into(), 12.into()];
+        let r10: Vec<DataType> = vec![2.into(), "z".into(), 10.into()];
+        let r11: Vec<DataType> = vec![3.into(), "z".into(), 11.into()];
+        let r5: Vec<DataType> = vec![4.into(), "z".into(), 5.into()];
+
+        g.narrow_one_row(r12.clone(), true);
+        g.narrow_one_row(r10.clone(), true);
+        g.narrow_one_row(r11.clone(), true);
+        g.narrow_one_row(r5.clone(), true);
+
+        // nothing has left the top k, so 5 isn't kept around, and must come from the parent
+        g.seed(s, r10.clone());
+        g.seed(s, r11.clone());
+        g.seed(s, r5.clone());
+        let a = g.narrow_one_row((r12.clone(), false), true);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0], (r12.clone(), false).into());
+        assert_eq!(a[1], (r5.clone(), true).into());
+    }
+
     #[test]
     fn it_forwards_reversed() {
         let (mut g, _) = setup(true);
@@ -477,8 +702,9 @@ mod tests {
         let (g, _) = setup(false);
         let me = 2.into();
         let idx = g.node().suggest_indexes(me);
-        assert_eq!(idx.len(), 1);
-        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![1]);
+        assert_eq!(idx[&g.narrow_base_id().as_global()], vec![1]);
     }
 
     #[test]
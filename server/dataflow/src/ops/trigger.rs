@@ -1,4 +1,6 @@
 use crate::prelude::*;
+use noria::TriggerAction;
+use slog::Logger;
 use std::collections::HashMap;
 
 /// A Trigger data-flow operator.
@@ -17,6 +19,18 @@ pub struct Trigger {
 pub enum TriggerEvent {
     /// Triggers the creation of a new group universe.
     GroupCreation { group: String },
+    /// Asks the controller to run `action` for every batch of changes to the trigger's parent.
+    Action { name: String, action: TriggerAction },
+}
+
+/// A batch of changes to a view that the controller should run a trigger action for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TriggerFiring {
+    /// The name of the trigger that fired.
+    pub trigger: String,
+    pub action: TriggerAction,
+    /// The changed rows, and whether each was added (`true`) or removed (`false`).
+    pub changes: Vec<(Vec<DataType>, bool)>,
 }
 
 impl Trigger {
@@ -24,7 +38,8 @@ impl Trigger {
     ///
     /// `src` is the parent node from which this node receives records.
     /// Whenever this node receives a record with a new value for `key`,
-    /// it triggers the event specified by `trigger`
+    /// it triggers the event specified by `trigger`. `TriggerEvent::Action` triggers instead
+    /// fire for every record, and ignore `key`.
     pub fn new(src: NodeIndex, trigger: TriggerEvent, key: usize) -> Trigger {
         Trigger {
             us: None,
@@ -43,6 +58,13 @@ impl Trigger {
         }
     }
 
+    fn is_action(&self) -> bool {
+        match self.trigger {
+            TriggerEvent::Action { .. } => true,
+            TriggerEvent::GroupCreation { .. } => false,
+        }
+    }
+
     fn trigger(&self, executor: &mut dyn Executor, ids: Vec<DataType>) {
         if ids.is_empty() {
             return;
@@ -60,6 +82,34 @@ impl Trigger {
                     }),
                 );
             }
+            TriggerEvent::Action { .. } => unreachable!(),
+        }
+    }
+
+    fn fire(&self, executor: &mut dyn Executor, rs: &Records) {
+        if rs.is_empty() {
+            return;
+        }
+
+        if let TriggerEvent::Action {
+            ref name,
+            ref action,
+        } = self.trigger
+        {
+            if let TriggerAction::DeleteReferencing { .. } = *action {
+                if rs.iter().all(|r| r.is_positive()) {
+                    // only removals cascade, so there is no need to bother the controller
+                    return;
+                }
+            }
+            executor.fire_trigger(TriggerFiring {
+                trigger: name.clone(),
+                action: action.clone(),
+                changes: rs
+                    .iter()
+                    .map(|r| (r.rec().to_vec(), r.is_positive()))
+                    .collect(),
+            });
         }
     }
 }
@@ -91,6 +141,14 @@ impl Ingredient for Trigger {
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
 
+        if self.is_action() {
+            self.fire(executor, &rs);
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
         let us = self.us.unwrap();
         let db = state
             .get(*us)
@@ -125,7 +183,40 @@ impl Ingredient for Trigger {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
+    fn on_input_raw(
+        &mut self,
+        executor: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay: ReplayContext,
+        domain: &DomainNodes,
+        states: &StateMap,
+        _: &Logger,
+    ) -> RawProcessingResult {
+        if self.is_action() && !matches!(replay, ReplayContext::None) {
+            // replays carry existing rows, not changes, so they must not fire actions
+            return RawProcessingResult::Regular(ProcessingResult {
+                results: rs,
+                ..Default::default()
+            });
+        }
+
+        RawProcessingResult::Regular(self.on_input(
+            executor,
+            from,
+            rs,
+            replay.key(),
+            domain,
+            states,
+        ))
+    }
+
     fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        if self.is_action() {
+            return HashMap::new();
+        }
+
         // index all key columns
         Some((this, vec![self.key])).into_iter().collect()
     }
@@ -146,8 +237,10 @@ impl Ingredient for Trigger {
     // to be long lived and to exist even if no user makes use of it.
     // We do this for two reasons: 1) to make user universe creation faster and
     // 2) so we don't have to order group and user universe migrations.
+    //
+    // Action triggers keep no state of their own.
     fn requires_full_materialization(&self) -> bool {
-        true
+        !self.is_action()
     }
 }
 
@@ -189,6 +282,26 @@ mod tests {
         assert_eq!(idx.len(), 1);
     }
 
+    #[test]
+    fn it_does_not_index_for_actions() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let action = TriggerEvent::Action {
+            name: String::from("t"),
+            action: TriggerAction::Sink {
+                path: String::from("/dev/null"),
+            },
+        };
+        g.set_op(
+            "trigger",
+            &["x", "y"],
+            Trigger::new(s.as_global(), action, 0),
+            false,
+        );
+        assert!(g.node().suggest_indexes(1.into()).is_empty());
+        assert!(!g.node().requires_full_materialization());
+    }
+
     #[test]
     fn it_resolves() {
         let g = setup(false);
@@ -0,0 +1,412 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::ops::topk::Order;
+use crate::prelude::*;
+
+use nom_sql::OrderType;
+
+/// Supported window functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowFunction {
+    /// The position of each record in its partition, counting from 1. Records that are equal in
+    /// the window order are numbered by comparing the rest of their columns.
+    RowNumber,
+    /// One more than the number of records that come strictly before each record in its
+    /// partition, so records that are equal in the window order share a rank.
+    Rank,
+    /// The sum of the `over` column across all records in the partition up to and including
+    /// those that are equal to each record in the window order. NULLs are skipped.
+    Sum,
+}
+
+/// Window computes a window function over the partitions of its input, and appends the result for
+/// each record as a new column.
+///
+/// A change to a partition recomputes the whole partition from the window's own state, and only
+/// the records whose computed value changed are emitted. This makes each change cost time linear
+/// in the size of its partition, so window functions are best suited to small partitions.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Window {
+    src: IndexPair,
+
+    // some cache state
+    us: Option<IndexPair>,
+    cols: usize,
+
+    function: WindowFunction,
+    over: Option<usize>,
+    partition_by: Vec<usize>,
+    order: Order,
+}
+
+impl Window {
+    /// Construct a new Window operator.
+    ///
+    /// `src` is this operator's ancestor, `function` is the window function to compute over the
+    /// column `over`, which must be given exactly when the function takes an argument,
+    /// `partition_by` indicates the columns that this operator is keyed on, and `order` is the
+    /// order of the records within each partition.
+    pub fn new(
+        src: NodeIndex,
+        function: WindowFunction,
+        over: Option<usize>,
+        partition_by: Vec<usize>,
+        order: Vec<(usize, OrderType)>,
+    ) -> Self {
+        assert_eq!(
+            over.is_some(),
+            function == WindowFunction::Sum,
+            "{:?} needs exactly as many arguments as it takes",
+            function
+        );
+
+        let mut partition_by = partition_by;
+        partition_by.sort();
+
+        Window {
+            src: src.into(),
+
+            us: None,
+            cols: 0,
+
+            function,
+            over,
+            partition_by,
+            order: order.into(),
+        }
+    }
+
+    /// Compute the window function for all the records in a partition.
+    ///
+    /// Returns the records in window order, each with its computed value appended.
+    fn compute(&self, mut rows: Vec<Vec<DataType>>) -> Vec<Vec<DataType>> {
+        // break ties on the whole record so that the result does not depend on arrival order
+        rows.sort_by(|a, b| self.order.cmp(a, b).then_with(|| a.cmp(b)));
+
+        let mut sum = DataType::None;
+        let mut start = 0;
+        while start < rows.len() {
+            let peers = rows[start..]
+                .iter()
+                .take_while(|r| self.order.cmp(r, &rows[start]) == Ordering::Equal)
+                .count();
+            let end = start + peers;
+
+            if let Some(over) = self.over {
+                for r in &rows[start..end] {
+                    if r[over].is_none() {
+                        continue;
+                    }
+                    sum = if sum.is_none() {
+                        r[over].clone()
+                    } else {
+                        &sum + &r[over]
+                    };
+                }
+            }
+
+            for (i, r) in rows[start..end].iter_mut().enumerate() {
+                r.push(match self.function {
+                    WindowFunction::RowNumber => (start + i + 1).into(),
+                    WindowFunction::Rank => (start + 1).into(),
+                    WindowFunction::Sum => sum.clone(),
+                });
+            }
+            start = end;
+        }
+        rows
+    }
+}
+
+impl Ingredient for Window {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        self.cols = srcn.fields().len();
+        if let Some(over) = self.over {
+            assert!(over < self.cols, "cannot compute over non-existing column");
+        }
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        // who's our parent really?
+        self.src.remap(remap);
+
+        // who are we?
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        _: &DomainNodes,
+        state: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        if rs.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
+        // handle all the changes to a partition at once, so that it is only recomputed once
+        let partition_by = &self.partition_by;
+        let mut rs: Vec<_> = rs.into();
+        rs.sort_by(|a, b| {
+            partition_by
+                .iter()
+                .map(|&col| &a[col])
+                .cmp(partition_by.iter().map(|&col| &b[col]))
+        });
+
+        let us = self.us.unwrap();
+        let db = state
+            .get(*us)
+            .expect("window operators must have their own state materialized");
+
+        let mut out = Vec::new();
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+        let mut rs = rs.into_iter().peekable();
+        while let Some(r) = rs.next() {
+            let key: Vec<_> = partition_by.iter().map(|&col| r[col].clone()).collect();
+            let mut changes = vec![r];
+            while let Some(r) = rs.peek() {
+                if partition_by.iter().map(|&col| &r[col]).ne(key.iter()) {
+                    break;
+                }
+                changes.push(rs.next().unwrap());
+            }
+
+            let old: Vec<Vec<DataType>> =
+                match db.lookup(&partition_by[..], &KeyType::from(&key[..])) {
+                    LookupResult::Some(rs) => {
+                        if replay_key_cols.is_some() {
+                            lookups.push(Lookup {
+                                on: *us,
+                                cols: partition_by.clone(),
+                                key: key.clone(),
+                            });
+                        }
+                        rs.into_iter().map(Cow::into_owned).collect()
+                    }
+                    LookupResult::Missing => {
+                        misses.extend(changes.into_iter().map(|r| Miss {
+                            on: *us,
+                            lookup_idx: partition_by.clone(),
+                            lookup_cols: partition_by.clone(),
+                            replay_cols: replay_key_cols.map(Vec::from),
+                            record: r.extract().0,
+                        }));
+                        continue;
+                    }
+                };
+
+            let mut rows: Vec<_> = old.iter().map(|r| r[..self.cols].to_vec()).collect();
+            for r in changes {
+                match r {
+                    Record::Positive(r) => rows.push(r),
+                    Record::Negative(r) => {
+                        if let Some(p) = rows.iter().position(|x| *x == r) {
+                            rows.swap_remove(p);
+                        }
+                    }
+                }
+            }
+
+            // only emit the records whose computed value changed
+            let mut new = self.compute(rows);
+            for r in old {
+                match new.iter().position(|x| *x == r) {
+                    Some(p) => {
+                        new.swap_remove(p);
+                    }
+                    None => out.push(Record::Negative(r)),
+                }
+            }
+            out.extend(new.into_iter().map(Record::Positive));
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            lookups,
+            misses,
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![(this, self.partition_by.clone())]
+            .into_iter()
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.cols {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), col)])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Window");
+        }
+
+        let function = match self.function {
+            WindowFunction::RowNumber => String::from("ROW_NUMBER()"),
+            WindowFunction::Rank => String::from("RANK()"),
+            WindowFunction::Sum => format!("SUM({})", self.over.unwrap()),
+        };
+        let partition_cols = self
+            .partition_by
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} OVER γ[{}]", function, partition_cols)
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if col == self.cols {
+            return vec![(self.src.as_global(), None)];
+        }
+        vec![(self.src.as_global(), Some(col))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(function: WindowFunction, over: Option<usize>) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "window",
+            &["x", "y", "z", "w"],
+            Window::new(
+                s.as_global(),
+                function,
+                over,
+                vec![1],
+                vec![(2, OrderType::OrderDescending)],
+            ),
+            true,
+        );
+        g
+    }
+
+    #[test]
+    fn it_numbers_rows() {
+        let mut g = setup(WindowFunction::RowNumber, None);
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 10.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), 20.into()];
+        let r3: Vec<DataType> = vec![3.into(), "b".into(), 30.into()];
+
+        let a = g.narrow_one_row(r1.clone(), true);
+        assert_eq!(
+            a,
+            vec![vec![1.into(), "a".into(), 10.into(), 1.into()]].into()
+        );
+
+        // a new first row renumbers the rest of its partition
+        let a = g.narrow_one_row(r2.clone(), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_negative(&[1.into(), "a".into(), 10.into(), 1.into()][..]));
+        assert!(a.has_positive(&[1.into(), "a".into(), 10.into(), 2.into()][..]));
+        assert!(a.has_positive(&[2.into(), "a".into(), 20.into(), 1.into()][..]));
+
+        // but leaves other partitions alone
+        let a = g.narrow_one_row(r3.clone(), true);
+        assert_eq!(
+            a,
+            vec![vec![3.into(), "b".into(), 30.into(), 1.into()]].into()
+        );
+
+        // removing the last row renumbers nothing
+        let a = g.narrow_one_row((r1.clone(), false), true);
+        assert_eq!(
+            a,
+            vec![(vec![1.into(), "a".into(), 10.into(), 2.into()], false)].into()
+        );
+    }
+
+    #[test]
+    fn it_ranks_ties_together() {
+        let mut g = setup(WindowFunction::Rank, None);
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 10.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), 20.into()];
+        let r3: Vec<DataType> = vec![3.into(), "a".into(), 20.into()];
+
+        let a = g.narrow_one(vec![r1.clone(), r2.clone(), r3.clone()], true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_positive(&[1.into(), "a".into(), 10.into(), 3.into()][..]));
+        assert!(a.has_positive(&[2.into(), "a".into(), 20.into(), 1.into()][..]));
+        assert!(a.has_positive(&[3.into(), "a".into(), 20.into(), 1.into()][..]));
+
+        // removing one of the tied rows moves the next one up
+        let a = g.narrow_one_row((r3.clone(), false), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.has_negative(&[3.into(), "a".into(), 20.into(), 1.into()][..]));
+        assert!(a.has_negative(&[1.into(), "a".into(), 10.into(), 3.into()][..]));
+        assert!(a.has_positive(&[1.into(), "a".into(), 10.into(), 2.into()][..]));
+    }
+
+    #[test]
+    fn it_sums_up_to_each_row() {
+        let mut g = setup(WindowFunction::Sum, Some(0));
+
+        let r1: Vec<DataType> = vec![1.into(), "a".into(), 10.into()];
+        let r2: Vec<DataType> = vec![2.into(), "a".into(), 20.into()];
+        let r4: Vec<DataType> = vec![4.into(), "a".into(), 20.into()];
+
+        let a = g.narrow_one(vec![r1.clone(), r2.clone()], true);
+        assert_eq!(a.len(), 2);
+        assert!(a.has_positive(&[2.into(), "a".into(), 20.into(), 2.into()][..]));
+        assert!(a.has_positive(&[1.into(), "a".into(), 10.into(), 3.into()][..]));
+
+        // rows that are equal in the window order share their sum
+        let a = g.narrow_one_row(r4.clone(), true);
+        assert_eq!(a.len(), 5);
+        assert!(a.has_positive(&[2.into(), "a".into(), 20.into(), 6.into()][..]));
+        assert!(a.has_positive(&[4.into(), "a".into(), 20.into(), 6.into()][..]));
+        assert!(a.has_positive(&[1.into(), "a".into(), 10.into(), 7.into()][..]));
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 2.into();
+        let g = setup(WindowFunction::RowNumber, None);
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(WindowFunction::RowNumber, None);
+        assert_eq!(
+            g.node().resolve(0),
+            Some(vec![(g.narrow_base_id().as_global(), 0)])
+        );
+        assert_eq!(g.node().resolve(3), None);
+    }
+}
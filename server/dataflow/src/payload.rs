@@ -5,10 +5,12 @@ use crate::domain;
 use crate::prelude::*;
 use noria;
 use noria::internal::LocalOrNot;
+use noria::{Compression, TraceContext, TransactionPhase, Watermarks};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::net::SocketAddr;
+use std::time;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReplayPathSegment {
@@ -23,6 +25,8 @@ pub enum SourceSelection {
     KeyShard {
         key_i_to_shard: usize,
         nshards: usize,
+        /// How the source spreads keys across its shards.
+        scheme: ShardScheme,
     },
     /// Query the same shard of the source as the destination.
     SameShard,
@@ -55,6 +59,38 @@ pub enum InitialState {
         cols: usize,
         key: Vec<usize>,
     },
+    /// The state that the last `RestoreState` for the node loaded from its snapshot.
+    Restored,
+}
+
+/// How the chunks of a full-state replay are paced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ReplayPacing {
+    /// Send chunks as fast as the target domain accepts them.
+    Unpaced,
+    /// Wait the given amount of time between chunks.
+    Fixed(time::Duration),
+    /// Back off between chunks, up to the given amount of time, while the replaying domain is also
+    /// processing regular writes.
+    Adaptive(time::Duration),
+}
+
+/// How a full-state replay is split into chunks, and how fast those chunks are sent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// Number of records per replayed chunk.
+    pub batch_size: usize,
+    /// How fast chunks are sent.
+    pub pacing: ReplayPacing,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            batch_size: 256,
+            pacing: ReplayPacing::Unpaced,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -64,19 +100,106 @@ pub enum ReplayPieceContext {
         requesting_shard: usize,
         unishard: bool,
         ignore: bool,
+        /// The trace of the upquery this replay answers, if it is being traced.
+        trace: Option<TraceContext>,
+        /// The domain shards the replay has passed through, if slow replays are recorded.
+        path: Vec<(usize, usize)>,
+        /// The bytes of records the replay carried into those domains.
+        bytes: u64,
     },
     Regular {
         last: bool,
     },
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SourceChannelIdentifier {
     pub token: usize,
     pub epoch: usize,
     pub tag: u32,
 }
 
+/// A flush barrier that follows the writes a base table domain processed before it through the
+/// data-flow.
+///
+/// The domain that started the barrier gives it a fixed amount of credit, which is divided between
+/// the copies of the update that carries it whenever the update is forwarded to several nodes or
+/// domains. Wherever a copy stops, its share is handed back to the origin domain, which knows that
+/// the barrier has passed through every node below the base table once it has all of its credit
+/// back.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Barrier {
+    /// The base table domain shard that started the barrier.
+    pub origin: ReplicaAddr,
+    pub id: u64,
+    pub credit: u64,
+}
+
+impl Barrier {
+    /// The credit that every barrier starts out with.
+    pub const CREDIT: u64 = 1 << 62;
+
+    /// Divide this barrier's credit between `n` copies of the update that carries it.
+    pub(crate) fn split(&self, n: usize) -> Vec<Barrier> {
+        let n = n as u64;
+        (0..n)
+            .map(|i| Barrier {
+                credit: self.credit / n + if i == 0 { self.credit % n } else { 0 },
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// The latest event time that each base table shard with an event-time column has seen.
+///
+/// Event times are in milliseconds since the Unix epoch. A node can only have seen all the records
+/// up to the earliest of the event times of the bases it reads from, which is its low watermark.
+///
+/// The write frontiers of base table shards, the processing times before which each had handled
+/// every write it was sent, are kept the same way.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventTimes(BTreeMap<(usize, usize), i64>);
+
+impl EventTimes {
+    /// True if no base table shard has seen an event time.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Record that the given shard of the base table with global index `base` has seen records
+    /// up to event time `t`.
+    pub fn advance(&mut self, base: usize, shard: usize, t: i64) {
+        let e = self.0.entry((base, shard)).or_insert(t);
+        if *e < t {
+            *e = t;
+        }
+    }
+
+    /// Advance to include every event time in `other`.
+    ///
+    /// Returns true if anything changed.
+    pub fn merge(&mut self, other: &EventTimes) -> bool {
+        let mut changed = false;
+        for (&k, &t) in &other.0 {
+            let e = self.0.entry(k).or_insert_with(|| {
+                changed = true;
+                t
+            });
+            if *e < t {
+                *e = t;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The earliest of the event times, if any.
+    pub fn low_watermark(&self) -> Option<i64> {
+        self.0.values().min().cloned()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum Packet {
@@ -92,6 +215,20 @@ pub enum Packet {
     Message {
         link: Link,
         data: Records,
+        /// The base table writes this update reflects, if snapshot reads are enabled.
+        watermarks: Watermarks,
+        /// The flush barrier that follows the writes in this update, if any.
+        barrier: Option<Barrier>,
+        /// The event times seen by the base tables this update derives from, if they have
+        /// event-time columns.
+        event_times: EventTimes,
+        /// The times before which the base tables this update derives from had processed every
+        /// write they were sent, if write frontiers are tracked.
+        frontier: EventTimes,
+        /// The trace of the write this update stems from, if it is being traced.
+        trace: Option<TraceContext>,
+        /// The atomic write this update is part of, if any.
+        txn: Option<TransactionPhase>,
     },
 
     /// Update that is part of a tagged data-flow replay path.
@@ -102,6 +239,12 @@ pub enum Packet {
         context: ReplayPieceContext,
     },
 
+    /// An update or replay piece, serialized and compressed to be sent to another worker.
+    Compressed {
+        codec: Compression,
+        bytes: Vec<u8>,
+    },
+
     /// Trigger an eviction from the target node.
     Evict {
         node: Option<LocalNodeIndex>,
@@ -116,6 +259,23 @@ pub enum Packet {
         keys: Vec<Vec<DataType>>,
     },
 
+    /// Re-evaluate the output of a periodically refreshed node, and forward any changes to it.
+    Refresh {
+        node: LocalNodeIndex,
+    },
+
+    /// Retract the rows of a node that have outlived its row TTL, and forward the retractions.
+    Expire {
+        node: LocalNodeIndex,
+    },
+
+    /// Part of the credit of a flush barrier that this domain started has stopped somewhere in
+    /// the data-flow.
+    BarrierReached {
+        id: u64,
+        credit: u64,
+    },
+
     //
     // Internal control
     //
@@ -147,6 +307,67 @@ pub enum Packet {
         column: usize,
     },
 
+    /// Replace the write policies of an existing `Base` node.
+    SetWritePolicies {
+        node: LocalNodeIndex,
+        policies: Vec<crate::node::special::WritePolicy>,
+    },
+
+    /// Replace the constraints that the rows of an existing `Base` node are checked against.
+    SetConstraints {
+        node: LocalNodeIndex,
+        constraints: Vec<crate::node::special::TableConstraint>,
+    },
+
+    /// Make an existing `Base` node reject or accept writes.
+    ///
+    /// `epoch` is the epoch of the controller that issued the change; changes from controllers
+    /// older than the newest one the domain has heard from are ignored.
+    SetReadOnly {
+        node: LocalNodeIndex,
+        read_only: bool,
+        epoch: noria::consensus::Epoch,
+    },
+
+    /// Make an existing `Base` node forward a fraction of its client writes to a mirror, or stop
+    /// doing so.
+    SetMirror {
+        node: LocalNodeIndex,
+        mirror: Option<noria::Mirror>,
+    },
+
+    /// Make the given column of an existing `Base` node its event-time column, or make it use
+    /// processing time only.
+    SetEventTime {
+        node: LocalNodeIndex,
+        column: Option<usize>,
+    },
+
+    /// Replace the assertions that an existing `Reader` node checks the rows added to it against.
+    SetAssertions {
+        node: LocalNodeIndex,
+        assertions: Vec<crate::node::special::ReaderAssertion>,
+    },
+
+    /// Choose how an existing `Reader` node evicts keys, and how many bytes it may hold.
+    SetEviction {
+        node: LocalNodeIndex,
+        eviction: noria::Eviction,
+    },
+
+    /// Choose how urgently the upqueries of an existing `Reader` node are handled.
+    SetPriority {
+        node: LocalNodeIndex,
+        priority: noria::ViewPriority,
+    },
+
+    /// Make an existing `Base` or `Reader` node retract rows once they outlive the given TTL, or
+    /// stop doing so.
+    SetTtl {
+        node: LocalNodeIndex,
+        ttl: Option<crate::node::special::RowTtl>,
+    },
+
     /// Update Egress node.
     UpdateEgress {
         node: LocalNodeIndex,
@@ -191,6 +412,10 @@ pub enum Packet {
         keys: Vec<Vec<DataType>>,
         unishard: bool,
         requesting_shard: usize,
+        /// The priority of the view whose upquery the replay is for.
+        priority: noria::ViewPriority,
+        /// The trace of the upquery, if it is being traced.
+        trace: Option<TraceContext>,
     },
 
     /// Ask domain (nicely) to replay a particular set of keys into a Reader.
@@ -198,12 +423,15 @@ pub enum Packet {
         node: LocalNodeIndex,
         cols: Vec<usize>,
         keys: Vec<Vec<DataType>>,
+        /// The trace of the read that missed, if it is being traced.
+        trace: Option<TraceContext>,
     },
 
     /// Instruct domain to replay the state of a particular node along an existing replay path.
     StartReplay {
         tag: Tag,
         from: LocalNodeIndex,
+        config: ReplayConfig,
     },
 
     /// Sent to instruct a domain that a particular node should be considered ready to process
@@ -226,9 +454,115 @@ pub enum Packet {
 
     /// Ask domain to log its state size
     UpdateStateSize,
+
+    /// Request that a domain send the replays it recorded as slow on the control reply channel.
+    GetSlowReplays,
+
+    /// Request that a domain send the distinct values of `cols` in the state of the fully
+    /// materialized node `node` on the control reply channel.
+    ScanKeys {
+        node: LocalNodeIndex,
+        cols: Vec<usize>,
+    },
+
+    /// Request that a domain send every row of the fully materialized reader or base table `node`
+    /// on the control reply channel.
+    ScanRows {
+        node: LocalNodeIndex,
+    },
+
+    /// Start measuring the time each node spends processing updates.
+    StartProfiling,
+
+    /// Stop measuring, and send the time each node spent on the control reply channel.
+    StopProfiling,
+
+    /// Hold client writes back, and reply once every write before them has passed through the
+    /// data-flow.
+    StartCheckpoint,
+
+    /// Write snapshots of fully materialized state for the given checkpoint, and mark where the
+    /// logs of base tables are.
+    Checkpoint {
+        id: u64,
+    },
+
+    /// Apply the writes that were held back. If the checkpoint was taken, drop what it includes
+    /// from the logs of base tables, along with older snapshots.
+    FinishCheckpoint {
+        id: Option<u64>,
+    },
+
+    /// Load the state of a new node from its snapshot for the given checkpoint, if it has one.
+    RestoreState {
+        node: LocalNodeIndex,
+        index: HashSet<Vec<usize>>,
+        checkpoint: u64,
+    },
+
+    /// Apply the writes that base tables undid when they were rewound to a checkpoint again, and
+    /// forward them through the data-flow.
+    ReplayLogs,
+
+    /// Write out the rows of a base node for the shards of its new layout, which are sharded by
+    /// the columns in `key` into `shards` shards with `scheme`, and whose states are called `to`
+    /// followed by the index of the shard.
+    ExportRows {
+        node: LocalNodeIndex,
+        to: String,
+        key: Vec<usize>,
+        shards: usize,
+        scheme: ShardScheme,
+        token: u64,
+    },
 }
 
 impl Packet {
+    /// This packet compressed with `codec`, if it is an update or replay piece whose records
+    /// take up less space that way, and otherwise the packet itself.
+    pub fn compress(self: Box<Self>, codec: Compression) -> Box<Packet> {
+        match *self {
+            Packet::Message { ref data, .. } | Packet::ReplayPiece { ref data, .. }
+                if !data.is_empty() => {}
+            _ => return self,
+        }
+        let raw = bincode::serialize(&self).expect("packets can always be serialized");
+        let bytes = codec.compress(&raw);
+        if bytes.len() >= raw.len() {
+            return self;
+        }
+        Box::new(Packet::Compressed { codec, bytes })
+    }
+
+    /// The packet that `compress` compressed, or this packet if it was not compressed.
+    pub fn decompress(self: Box<Self>) -> Result<Box<Packet>, bincode::Error> {
+        match *self {
+            Packet::Compressed { codec, ref bytes } => {
+                bincode::deserialize(&codec.decompress(bytes)?)
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// What kind of packet this is, and where its records go, without copying any of them.
+    pub fn summary(&self) -> PacketSummary {
+        let (kind, link, records) = match *self {
+            Packet::Input { .. } => ("Input", None, None),
+            Packet::Message { link, ref data, .. } => ("Message", Some(link), Some(data.len())),
+            Packet::ReplayPiece { link, ref data, .. } => {
+                ("ReplayPiece", Some(link), Some(data.len()))
+            }
+            Packet::RequestReaderReplay { .. } => ("RequestReaderReplay", None, None),
+            Packet::RequestPartialReplay { .. } => ("RequestPartialReplay", None, None),
+            _ => ("Control", None, None),
+        };
+        PacketSummary {
+            kind,
+            link,
+            records,
+        }
+    }
+
     pub(crate) fn src(&self) -> LocalNodeIndex {
         match *self {
             Packet::Input { ref inner, .. } => {
@@ -267,6 +601,14 @@ impl Packet {
         }
     }
 
+    pub(crate) fn len(&self) -> usize {
+        match *self {
+            Packet::Message { ref data, .. } => data.len(),
+            Packet::ReplayPiece { ref data, .. } => data.len(),
+            _ => unreachable!(),
+        }
+    }
+
     pub(crate) fn map_data<F>(&mut self, map: F)
     where
         F: FnOnce(&mut Records),
@@ -281,6 +623,14 @@ impl Packet {
         }
     }
 
+    /// True if this is a client write that is a flush barrier.
+    pub(crate) fn is_barrier(&self) -> bool {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.barrier,
+            _ => false,
+        }
+    }
+
     pub(crate) fn is_regular(&self) -> bool {
         match *self {
             Packet::Message { .. } => true,
@@ -306,11 +656,185 @@ impl Packet {
         mem::replace(inner, Records::default())
     }
 
+    pub(crate) fn watermarks(&self) -> &Watermarks {
+        match *self {
+            Packet::Message { ref watermarks, .. } => watermarks,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn watermarks_mut(&mut self) -> &mut Watermarks {
+        match *self {
+            Packet::Message {
+                ref mut watermarks, ..
+            } => watermarks,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn event_times(&self) -> &EventTimes {
+        match *self {
+            Packet::Message {
+                ref event_times, ..
+            } => event_times,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn event_times_mut(&mut self) -> &mut EventTimes {
+        match *self {
+            Packet::Message {
+                ref mut event_times,
+                ..
+            } => event_times,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn frontier(&self) -> &EventTimes {
+        match *self {
+            Packet::Message { ref frontier, .. } => frontier,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn barrier(&self) -> Option<&Barrier> {
+        match *self {
+            Packet::Message { ref barrier, .. } => barrier.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Divide the credit of the flush barrier that this update carries, if any, between `n`
+    /// copies of the update, which should each be given one share with `set_barrier`.
+    pub(crate) fn barrier_shares(&self, n: usize) -> Option<std::vec::IntoIter<Barrier>> {
+        self.barrier().map(|b| b.split(n).into_iter())
+    }
+
+    /// Append the records of the update `other` to this update, so that both are processed as
+    /// one batch.
+    ///
+    /// Only updates along the same link are merged, and only if this one carries no flush
+    /// barrier, since a barrier must come after every write it follows, and is not part of an
+    /// atomic write. `other` is handed back if the updates cannot be merged.
+    pub(crate) fn absorb(&mut self, other: Box<Packet>) -> Result<(), Box<Packet>> {
+        let mergeable = match (&*self, &*other) {
+            (
+                Packet::Message {
+                    link,
+                    barrier: None,
+                    txn: None,
+                    ..
+                },
+                Packet::Message {
+                    link: other_link, ..
+                },
+            ) => link == other_link,
+            _ => false,
+        };
+        if !mergeable {
+            return Err(other);
+        }
+
+        if let (
+            Packet::Message {
+                data: ref mut ours,
+                watermarks: ref mut our_watermarks,
+                barrier: ref mut our_barrier,
+                event_times: ref mut our_event_times,
+                frontier: ref mut our_frontier,
+                trace: ref mut our_trace,
+                txn: ref mut our_txn,
+                ..
+            },
+            Packet::Message {
+                data,
+                watermarks,
+                barrier,
+                event_times,
+                frontier,
+                trace,
+                txn,
+                ..
+            },
+        ) = (self, *other)
+        {
+            ours.extend(data);
+            our_watermarks.merge(&watermarks);
+            our_event_times.merge(&event_times);
+            our_frontier.merge(&frontier);
+            *our_barrier = barrier;
+            // a batch can only be part of one trace, so the first traced write wins
+            *our_trace = our_trace.or(trace);
+            // updates that are part of an atomic write are never merged into, so only the absorbed
+            // update can be part of one
+            *our_txn = txn;
+        }
+        Ok(())
+    }
+
+    /// The trace this write, update, or replay piece is part of, if it is being traced.
+    pub(crate) fn trace(&self) -> Option<TraceContext> {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.trace,
+            Packet::Message { trace, .. }
+            | Packet::ReplayPiece {
+                context: ReplayPieceContext::Partial { trace, .. },
+                ..
+            } => trace,
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_trace(&mut self, ctx: TraceContext) {
+        match *self {
+            Packet::Input { ref mut inner, .. } => unsafe { inner.deref_mut() }.trace = Some(ctx),
+            Packet::Message { ref mut trace, .. }
+            | Packet::ReplayPiece {
+                context: ReplayPieceContext::Partial { ref mut trace, .. },
+                ..
+            } => *trace = Some(ctx),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The atomic write this update is part of, if any.
+    pub(crate) fn txn(&self) -> Option<TransactionPhase> {
+        match *self {
+            Packet::Message { txn, .. } => txn,
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_barrier(&mut self, share: Barrier) {
+        match *self {
+            Packet::Message {
+                ref mut barrier, ..
+            } => *barrier = Some(share),
+            _ => unreachable!(),
+        }
+    }
+
     pub(crate) fn clone_data(&self) -> Self {
         match *self {
-            Packet::Message { link, ref data } => Packet::Message {
+            Packet::Message {
+                link,
+                ref data,
+                ref watermarks,
+                ref barrier,
+                ref event_times,
+                ref frontier,
+                trace,
+                txn,
+            } => Packet::Message {
                 link,
                 data: data.clone(),
+                watermarks: watermarks.clone(),
+                barrier: barrier.clone(),
+                event_times: event_times.clone(),
+                frontier: frontier.clone(),
+                trace,
+                txn,
             },
             Packet::ReplayPiece {
                 link,
@@ -359,6 +883,27 @@ impl fmt::Debug for Packet {
     }
 }
 
+/// A summary of a [`Packet`] that is cheap to take, as returned by [`Packet::summary`].
+#[derive(Clone, Copy)]
+pub struct PacketSummary {
+    kind: &'static str,
+    link: Option<Link>,
+    records: Option<usize>,
+}
+
+impl fmt::Display for PacketSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Packet::{}", self.kind)?;
+        if let Some(link) = self.link {
+            write!(f, "({:?})", link)?;
+        }
+        if let Some(records) = self.records {
+            write!(f, " with {} records", records)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ControlReplyPacket {
     Ack(()),
@@ -369,6 +914,22 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    /// Nanoseconds spent processing in each node since profiling started.
+    Profile(HashMap<petgraph::graph::NodeIndex, u64>),
+    /// (rows replayed, bytes replayed, rows added to the replay) since the last report
+    ReplayProgress(usize, u64, usize),
+    /// A full replay into the given node has finished.
+    Replayed(DomainIndex, LocalNodeIndex),
+    /// Whether every snapshot of a checkpoint, or every row of a re-sharded base, was written.
+    Checkpointed(bool),
+    /// Whether a node's state was loaded from its snapshot.
+    Restored(bool),
+    /// The most recent replays that took longer than the slow-replay threshold.
+    SlowReplays(Vec<noria::debug::replays::SlowReplay>),
+    /// The distinct keys in a node's state, in order.
+    Keys(Vec<Vec<DataType>>),
+    /// Every row of a reader or base table.
+    Rows(Vec<Vec<DataType>>),
 }
 
 impl ControlReplyPacket {
@@ -376,3 +937,81 @@ impl ControlReplyPacket {
         ControlReplyPacket::Ack(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(link: Link, row: i32, barrier: Option<Barrier>) -> Box<Packet> {
+        Box::new(Packet::Message {
+            link,
+            data: vec![vec![DataType::from(row)]].into(),
+            watermarks: Watermarks::default(),
+            barrier,
+            event_times: EventTimes::default(),
+            frontier: EventTimes::default(),
+            trace: None,
+            txn: None,
+        })
+    }
+
+    #[test]
+    fn updates_along_a_link_are_merged() {
+        let node = |i: u32| unsafe { LocalNodeIndex::make(i) };
+        let a = Link::new(node(0), node(1));
+        let b = Link::new(node(0), node(2));
+        let barrier = Barrier {
+            origin: (0usize.into(), 0),
+            id: 1,
+            credit: Barrier::CREDIT,
+        };
+
+        let mut m = message(a, 1, None);
+        assert!(m.absorb(message(b, 2, None)).is_err());
+        m.absorb(message(a, 2, Some(barrier))).unwrap();
+        assert_eq!(m.clone_data().take_data().len(), 2);
+        assert!(m.barrier().is_some());
+
+        // nothing may be merged in ahead of the barrier
+        assert!(m.absorb(message(a, 3, None)).is_err());
+    }
+
+    #[test]
+    fn summaries_leave_out_records() {
+        let node = |i: u32| unsafe { LocalNodeIndex::make(i) };
+        let m = message(Link::new(node(0), node(1)), 1, None);
+        assert_eq!(
+            m.summary().to_string(),
+            format!(
+                "Packet::Message({:?}) with 1 records",
+                Link::new(node(0), node(1))
+            )
+        );
+        assert_eq!(Packet::Spin.summary().to_string(), "Packet::Control");
+    }
+
+    #[test]
+    fn compressed_packets_roundtrip() {
+        let node = |i: u32| unsafe { LocalNodeIndex::make(i) };
+        let text = DataType::from("x".repeat(4096));
+        let mut m = message(Link::new(node(0), node(1)), 1, None);
+        m.map_data(|rs| rs.push((vec![text.clone()], true).into()));
+
+        for &codec in &[Compression::Lz4, Compression::Zstd] {
+            let c = m.clone().compress(codec);
+            match *c {
+                Packet::Compressed { ref bytes, .. } => assert!(bytes.len() < 4096),
+                _ => unreachable!(),
+            }
+            let mut d = c.decompress().unwrap();
+            assert_eq!(d.src(), node(0));
+            assert_eq!(d.take_data().len(), 2);
+        }
+
+        // control packets are left alone
+        assert!(matches!(
+            *Box::new(Packet::Spin).compress(Compression::Lz4),
+            Packet::Spin
+        ));
+    }
+}
@@ -16,6 +16,7 @@ pub(crate) type Edge = ();
 
 // dataflow types
 pub(crate) use crate::payload::{ReplayPathSegment, SourceChannelIdentifier};
+pub(crate) use noria::error::WriteRejection;
 pub(crate) use noria::Input;
 
 // domain local state
@@ -33,6 +34,7 @@ pub use crate::payload::Packet;
 pub use crate::Sharding;
 pub use common::*;
 pub use noria::internal::*;
+pub use noria::ShardScheme;
 pub use petgraph::graph::NodeIndex;
 pub type Graph = petgraph::Graph<Node, Edge>;
 pub use crate::DurabilityMode;
@@ -41,7 +43,19 @@ pub use crate::PersistenceParameters;
 /// Channel coordinator type specialized for domains
 pub type ChannelCoordinator = noria::channel::ChannelCoordinator<(DomainIndex, usize), Box<Packet>>;
 pub trait Executor {
-    fn ack(&mut self, tag: SourceChannelIdentifier);
+    /// Acknowledge a write, along with the base table writes a view must reflect to include it.
+    fn ack(&mut self, tag: SourceChannelIdentifier, ack: noria::WriteAck);
+    fn reject(&mut self, tag: SourceChannelIdentifier, reason: WriteRejection);
     fn create_universe(&mut self, req: HashMap<String, DataType>);
+    fn fire_trigger(&mut self, firing: crate::ops::trigger::TriggerFiring);
+    fn mirror(
+        &mut self,
+        table: String,
+        target: noria::MirrorTarget,
+        ops: Vec<noria::TableOperation>,
+    );
+    fn dead_letters(&mut self, table: String, letters: Vec<noria::DeadLetter>);
+    /// Record rows that violated an assertion on the given view.
+    fn quarantine(&mut self, view: String, violations: Vec<noria::Violation>);
     fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>);
 }
@@ -95,7 +95,7 @@ pub(crate) enum ReplayContext<'a> {
 }
 
 impl<'a> ReplayContext<'a> {
-    fn key(&self) -> Option<&'a [usize]> {
+    pub(crate) fn key(&self) -> Option<&'a [usize]> {
         if let ReplayContext::Partial { key_cols, .. } = *self {
             Some(key_cols)
         } else {
@@ -126,6 +126,12 @@ where
     /// *compound* key, *not* that multiple columns should be independently indexed.
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>>;
 
+    /// Suggest indexes on ancestors in addition to those of `suggest_indexes`, for operators that
+    /// look up into one ancestor by more than one key.
+    fn suggest_secondary_indexes(&self) -> Vec<(NodeIndex, Vec<usize>)> {
+        Vec::new()
+    }
+
     /// Resolve where the given field originates from. If the view is materialized, or the value is
     /// otherwise created by this view, None should be returned.
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>>;
@@ -214,6 +220,18 @@ where
     /// state other than what is stored in its materialization.
     fn on_eviction(&mut self, _from: LocalNodeIndex, _tag: Tag, _keys: &[Vec<DataType>]) {}
 
+    /// Returns true if the operator is holding back rows that it will emit later, through
+    /// `take_deferred`, rather than from `on_input`.
+    fn has_deferred(&self) -> bool {
+        false
+    }
+
+    /// Take the held back rows that the operator is now ready to emit. The domain forwards them
+    /// to the operator's children as if `on_input` had produced them.
+    fn take_deferred(&mut self) -> Records {
+        Records::default()
+    }
+
     fn can_query_through(&self) -> bool {
         false
     }
@@ -279,4 +297,10 @@ where
     fn requires_full_materialization(&self) -> bool {
         false
     }
+
+    /// Returns true if this operator reads all the rows of its parents, which must then be
+    /// materialized themselves rather than queried through
+    fn requires_materialized_parents(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+/// A token bucket that admits up to `rate` units of work per second.
+///
+/// The bucket holds at most a second's worth of units, so a client that has been idle can burst
+/// up to `rate` units at once, but no more. A single request for more units than that is admitted
+/// when the bucket is full, and leaves it in debt, so that large requests are slowed down rather
+/// than turned away forever.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    /// The units that may be taken, which is negative while the bucket is in debt.
+    credit: f64,
+    /// When `credit` was last topped up.
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// A full bucket that admits `rate` units per second.
+    pub fn new(rate: u32) -> Self {
+        assert_ne!(rate, 0);
+        RateLimiter {
+            rate: f64::from(rate),
+            credit: f64::from(rate),
+            last: Instant::now(),
+        }
+    }
+
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.credit = f64::min(self.credit + earned, self.rate);
+        self.last = now;
+    }
+
+    /// Take `n` units, unless there are not enough of them left in this second.
+    pub fn try_take(&mut self, n: usize) -> bool {
+        self.tick();
+        let n = n as f64;
+        if n <= self.credit || self.credit >= self.rate {
+            self.credit -= n;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket is full, in which case forgetting it makes no difference.
+    pub fn is_full(&mut self) -> bool {
+        self.tick();
+        self.credit >= self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn it_admits_a_second_worth() {
+        let mut l = RateLimiter::new(10);
+        assert!(l.try_take(4));
+        assert!(l.try_take(6));
+        assert!(!l.try_take(1));
+        assert!(!l.is_full());
+
+        l.last -= Duration::from_millis(500);
+        assert!(l.try_take(5));
+        assert!(!l.try_take(1));
+
+        // credit does not pile up beyond a second's worth
+        l.last -= Duration::from_secs(10);
+        assert!(l.is_full());
+        assert!(l.try_take(10));
+        assert!(!l.try_take(1));
+    }
+
+    #[test]
+    fn large_requests_go_into_debt() {
+        let mut l = RateLimiter::new(10);
+        assert!(l.try_take(25));
+        assert!(!l.try_take(1));
+
+        // the debt has to be paid off before the next request
+        l.last -= Duration::from_secs(1);
+        assert!(!l.try_take(1));
+        l.last -= Duration::from_secs(1);
+        assert!(l.try_take(5));
+    }
+}
@@ -18,6 +18,22 @@ pub(super) enum KeyedState {
     Sex(HashMap<(DataType, DataType, DataType, DataType, DataType, DataType), Rows>),
 }
 
+macro_rules! drain_where_impl {
+    ($m:ident, $f:ident, $($i:tt),+) => {{
+        let keys: Vec<_> = $m
+            .keys()
+            .filter(|k| $f(&[$(k.$i.clone()),+]))
+            .cloned()
+            .collect();
+        keys.into_iter()
+            .map(|k| {
+                let rs = $m.swap_remove(&k).unwrap();
+                (vec![$(k.$i),+], rs)
+            })
+            .collect()
+    }};
+}
+
 impl KeyedState {
     pub(super) fn lookup<'a>(&'a self, key: &KeyType) -> Option<&'a Rows> {
         match (self, key) {
@@ -31,6 +47,18 @@ impl KeyedState {
         }
     }
 
+    /// The number of keys in the map.
+    pub(super) fn key_count(&self) -> usize {
+        match *self {
+            KeyedState::Single(ref m) => m.len(),
+            KeyedState::Double(ref m) => m.len(),
+            KeyedState::Tri(ref m) => m.len(),
+            KeyedState::Quad(ref m) => m.len(),
+            KeyedState::Quin(ref m) => m.len(),
+            KeyedState::Sex(ref m) => m.len(),
+        }
+    }
+
     /// Remove all rows for a randomly chosen key seeded by `seed`, returning that key along with
     /// the number of bytes freed. Returns `None` if map is empty.
     pub(super) fn evict_with_seed(&mut self, seed: usize) -> Option<(u64, Vec<DataType>)> {
@@ -78,6 +106,33 @@ impl KeyedState {
         ))
     }
 
+    /// Remove the rows of all keys for which `f` returns true, returning them by key.
+    pub(super) fn drain_where<F>(&mut self, f: F) -> Vec<(Vec<DataType>, Rows)>
+    where
+        F: Fn(&[DataType]) -> bool,
+    {
+        match *self {
+            KeyedState::Single(ref mut m) => {
+                let keys: Vec<_> = m
+                    .keys()
+                    .filter(|k| f(std::slice::from_ref(k)))
+                    .cloned()
+                    .collect();
+                keys.into_iter()
+                    .map(|k| {
+                        let rs = m.swap_remove(&k).unwrap();
+                        (vec![k], rs)
+                    })
+                    .collect()
+            }
+            KeyedState::Double(ref mut m) => drain_where_impl!(m, f, 0, 1),
+            KeyedState::Tri(ref mut m) => drain_where_impl!(m, f, 0, 1, 2),
+            KeyedState::Quad(ref mut m) => drain_where_impl!(m, f, 0, 1, 2, 3),
+            KeyedState::Quin(ref mut m) => drain_where_impl!(m, f, 0, 1, 2, 3, 4),
+            KeyedState::Sex(ref mut m) => drain_where_impl!(m, f, 0, 1, 2, 3, 4, 5),
+        }
+    }
+
     /// Remove all rows for the given key, returning the number of bytes freed.
     pub(super) fn evict(&mut self, key: &[DataType]) -> u64 {
         match *self {
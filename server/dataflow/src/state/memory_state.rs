@@ -5,13 +5,23 @@ use rand::{self, Rng};
 
 use crate::prelude::*;
 use crate::state::single_state::SingleState;
+use crate::state::spill::{key_of, Spill};
 use common::SizeOf;
+use noria::Compression;
 
 #[derive(Default)]
 pub struct MemoryState {
     state: Vec<SingleState>,
     by_tag: HashMap<Tag, usize>,
     mem_size: u64,
+
+    /// Columns whose wide text values are stored compressed, and the codec they are compressed
+    /// with.
+    compressed: Vec<usize>,
+    codec: Compression,
+
+    /// Where cold partitions go once this state grows too large, if anywhere.
+    spill: Option<Spill>,
 }
 
 impl SizeOf for MemoryState {
@@ -26,7 +36,7 @@ impl SizeOf for MemoryState {
     }
 
     fn is_empty(&self) -> bool {
-        self.state[0].is_empty()
+        self.state[0].is_empty() && self.spill.as_ref().map(Spill::rows).unwrap_or(0) == 0
     }
 }
 
@@ -50,6 +60,13 @@ impl State for MemoryState {
             return;
         }
 
+        // spilled partitions are keyed by the existing index, so bring them back before building
+        // another one
+        let spilled = self.spill.as_mut().map(Spill::drain).unwrap_or_default();
+        for r in spilled {
+            self.insert(r, None);
+        }
+
         self.state
             .push(SingleState::new(columns, partial.is_some()));
 
@@ -111,11 +128,13 @@ impl State for MemoryState {
                     }
                 }
             }
+            self.spill_cold_partitions();
         }
     }
 
     fn rows(&self) -> usize {
-        self.state.iter().map(SingleState::rows).sum()
+        self.state.iter().map(SingleState::rows).sum::<usize>()
+            + self.spill.as_ref().map(Spill::rows).unwrap_or(0)
     }
 
     fn mark_filled(&mut self, key: Vec<DataType>, tag: Tag) {
@@ -136,21 +155,51 @@ impl State for MemoryState {
         let index = self
             .state_for(columns)
             .expect("lookup on non-indexed column set");
-        self.state[index].lookup(key)
+        if let Some(ref spill) = self.spill {
+            if index == 0 {
+                let key = key_of(key);
+                let partition = Spill::partition(&key);
+                spill.probed(partition);
+                if spill.is_spilled(partition) {
+                    return LookupResult::Some(RecordResult::Owned(spill.lookup(&key)));
+                }
+            }
+        }
+        match self.state[index].lookup(key) {
+            LookupResult::Some(RecordResult::Borrowed(rs)) if !self.compressed.is_empty() => {
+                LookupResult::Some(RecordResult::Owned(
+                    rs.iter().map(|r| decompress(&r[..])).collect(),
+                ))
+            }
+            r => r,
+        }
     }
 
     fn keys(&self) -> Vec<Vec<usize>> {
         self.state.iter().map(|s| s.key().to_vec()).collect()
     }
 
+    fn distinct_keys(&self) -> Vec<(Vec<usize>, usize)> {
+        // a partial index only holds the keys that have been asked for
+        self.state
+            .iter()
+            .filter(|s| !s.partial())
+            .map(|s| (s.key().to_vec(), s.key_count()))
+            .collect()
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         #[allow(clippy::ptr_arg)]
         fn fix<'a>(rs: &'a Rows) -> impl Iterator<Item = Vec<DataType>> + 'a {
-            rs.iter().map(|r| Vec::clone(&**r))
+            rs.iter().map(|r| decompress(&r[..]))
         }
 
         assert!(!self.state[0].partial());
-        self.state[0].values().flat_map(fix).collect()
+        let mut rs: Vec<_> = self.state[0].values().flat_map(fix).collect();
+        if let Some(ref spill) = self.spill {
+            rs.extend(spill.cloned_records());
+        }
+        rs
     }
 
     fn evict_random_keys(&mut self, count: usize) -> (&[usize], Vec<Vec<DataType>>, u64) {
@@ -176,18 +225,91 @@ impl State for MemoryState {
         for state in &mut self.state {
             state.clear();
         }
+        if let Some(ref mut spill) = self.spill {
+            spill.clear();
+        }
         self.mem_size = 0;
     }
 }
 
 impl MemoryState {
+    /// Store wide text values in the given columns compressed with `codec`.
+    ///
+    /// Rows are decompressed again when they are looked up.
+    pub(crate) fn compress_columns(&mut self, columns: Vec<usize>, codec: Compression) {
+        self.compressed = columns;
+        self.codec = codec;
+    }
+
+    /// Move the coldest partitions of this state to disk once it grows beyond `bytes`.
+    ///
+    /// Only fully materialized state with a single index is spilled, since any other index would
+    /// keep the spilled rows in memory anyway. Partial state should be evicted from instead.
+    pub(crate) fn spill_after(&mut self, bytes: u64) {
+        self.spill = Some(Spill::new(bytes));
+    }
+
+    /// Spill the least recently probed partitions until we are within the spill threshold again.
+    fn spill_cold_partitions(&mut self) {
+        let spill = match self.spill {
+            Some(ref mut spill) => spill,
+            None => return,
+        };
+        if self.mem_size <= spill.threshold() || self.state.len() != 1 || self.state[0].partial() {
+            return;
+        }
+
+        for partition in spill.coldest() {
+            if self.mem_size <= spill.threshold() {
+                break;
+            }
+
+            let mut freed = 0;
+            let rows = self.state[0]
+                .drain_where(|k| Spill::partition(k) == partition)
+                .into_iter()
+                .map(|(key, rs)| {
+                    let rs = rs
+                        .iter()
+                        .map(|r| {
+                            freed += r.deep_size_of();
+                            Vec::clone(&**r)
+                        })
+                        .collect();
+                    (key, rs)
+                })
+                .collect();
+            spill.spill(partition, rows);
+            self.mem_size = self.mem_size.saturating_sub(freed);
+        }
+    }
+
+    /// The key of `r` in the state's only index, if `r` belongs to a spilled partition.
+    fn spilled_key(&self, r: &[DataType]) -> Option<Vec<DataType>> {
+        let spill = self.spill.as_ref()?;
+        let key: Vec<_> = self.state[0].key().iter().map(|&c| r[c].clone()).collect();
+        if spill.is_spilled(Spill::partition(&key)) {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
     /// Returns the index in `self.state` of the index keyed on `cols`, or None if no such index
     /// exists.
     fn state_for(&self, cols: &[usize]) -> Option<usize> {
         self.state.iter().position(|s| s.key() == cols)
     }
 
-    fn insert(&mut self, r: Vec<DataType>, partial_tag: Option<Tag>) -> bool {
+    fn insert(&mut self, mut r: Vec<DataType>, partial_tag: Option<Tag>) -> bool {
+        if let Some(key) = self.spilled_key(&r) {
+            self.spill.as_mut().unwrap().insert(key, r);
+            return true;
+        }
+
+        for &c in &self.compressed {
+            r[c] = r[c].compress_with(self.codec);
+        }
         let r = Rc::new(r);
 
         if let Some(tag) = partial_tag {
@@ -215,6 +337,10 @@ impl MemoryState {
     }
 
     fn remove(&mut self, r: &[DataType]) -> bool {
+        if let Some(key) = self.spilled_key(r) {
+            return self.spill.as_mut().unwrap().remove(key, r);
+        }
+
         let mut hit = false;
         for s in &mut self.state {
             if let Some(row) = s.remove_row(r, &mut hit) {
@@ -228,6 +354,11 @@ impl MemoryState {
     }
 }
 
+/// A copy of `r` with any compressed values decompressed.
+fn decompress(r: &[DataType]) -> Vec<DataType> {
+    r.iter().map(|v| v.decompress().into_owned()).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -284,4 +415,62 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_compressed_columns() {
+        let mut state = MemoryState::default();
+        state.compress_columns(vec![1], Compression::Lz4);
+        state.add_key(&[0], None);
+
+        let body = "x".repeat(4096);
+        let row: Vec<DataType> = vec![1.into(), body.as_str().into()];
+        insert(&mut state, row.clone());
+        assert!(state.deep_size_of() < body.len() as u64);
+
+        match state.lookup(&[0], &KeyType::Single(&row[0])) {
+            LookupResult::Some(RecordResult::Owned(rows)) => {
+                assert_eq!(rows, vec![row.clone()]);
+                assert!(!rows[0][1].is_compressed());
+            }
+            _ => unreachable!(),
+        };
+        assert_eq!(state.cloned_records(), vec![row.clone()]);
+
+        let record: Record = (row.clone(), false).into();
+        state.process_records(&mut record.into(), None);
+        assert_eq!(state.rows(), 0);
+    }
+
+    #[test]
+    fn memory_state_spills_cold_partitions() {
+        let mut state = MemoryState::default();
+        state.spill_after(1);
+        state.add_key(&[0], None);
+
+        let rows: Vec<Vec<DataType>> = (0..100).map(|i| vec![i.into(), "A".into()]).collect();
+        for row in &rows {
+            insert(&mut state, row.clone());
+        }
+        assert_eq!(state.rows(), 100);
+        assert!(state.deep_size_of() <= 1);
+
+        // spilled rows are still found, and still maintained
+        insert(&mut state, vec![7.into(), "B".into()]);
+        let record: Record = (rows[7].clone(), false).into();
+        state.process_records(&mut record.into(), None);
+        match state.lookup(&[0], &KeyType::Single(&rows[7][0])) {
+            LookupResult::Some(RecordResult::Owned(rs)) => {
+                assert_eq!(rs, vec![vec![7.into(), "B".into()]])
+            }
+            _ => unreachable!(),
+        };
+        assert_eq!(state.cloned_records().len(), 100);
+
+        // adding another index brings the spilled rows back into memory
+        state.add_key(&[1], None);
+        match state.lookup(&[1], &KeyType::Single(&"A".into())) {
+            LookupResult::Some(RecordResult::Borrowed(rs)) => assert_eq!(rs.len(), 99),
+            _ => unreachable!(),
+        };
+    }
 }
@@ -3,6 +3,8 @@ mod memory_state;
 mod mk_key;
 mod persistent_state;
 mod single_state;
+mod sketch;
+mod spill;
 
 use std::borrow::Cow;
 use std::ops::Deref;
@@ -16,6 +18,11 @@ use hashbag::HashBag;
 
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
+pub use self::persistent_state::{
+    compact_state, inspect_state, upgrade_state, StateReport,
+    FORMAT_VERSION as STATE_FORMAT_VERSION,
+};
+pub(crate) use self::spill::{deserialize_rows, serialize_rows};
 
 pub(crate) trait State: SizeOf + Send {
     /// Add an index keyed by the given columns and replayed to by the given partial tags.
@@ -41,6 +48,10 @@ pub(crate) trait State: SizeOf + Send {
 
     fn keys(&self) -> Vec<Vec<usize>>;
 
+    /// The number of distinct keys in each index whose count is known, along with the columns the
+    /// index is on.
+    fn distinct_keys(&self) -> Vec<(Vec<usize>, usize)>;
+
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
@@ -53,6 +64,11 @@ pub(crate) trait State: SizeOf + Send {
     fn evict_keys(&mut self, tag: Tag, keys: &[Vec<DataType>]) -> Option<(&[usize], u64)>;
 
     fn clear(&mut self);
+
+    /// The state as a `PersistentState`, if it is one.
+    fn as_persistent_mut(&mut self) -> Option<&mut PersistentState> {
+        None
+    }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -1,10 +1,15 @@
 use bincode;
 use itertools::Itertools;
+use noria::sparse::SparseRow;
 use rocksdb::{self, PlainTableFactoryOptions, SliceTransform, WriteBatch};
 use serde;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
 use tempfile::{tempdir, TempDir};
 
 use crate::prelude::*;
+use crate::state::sketch::KeySketch;
 use crate::state::{RecordResult, State};
 use common::SizeOf;
 
@@ -15,12 +20,35 @@ type IndexEpoch = u64;
 // Monotonically increasing sequence number since last IndexEpoch used to uniquely identify a row.
 type IndexSeq = u64;
 
+/// The format that new persisted state is written in.
+///
+/// * 1: rows are stored as plain vectors.
+/// * 2: rows are stored with the sparse row encoding.
+/// * 3: rows are stored with the sparse row encoding followed by a checksum, and the format
+///   version is recorded in a header.
+///
+/// Existing state keeps the format it was created with until it is upgraded with `upgrade_state`.
+pub const FORMAT_VERSION: u32 = 3;
+
 // RocksDB key used for storing meta information (like indices).
 const META_KEY: &[u8] = b"meta";
+// RocksDB key used for storing the Header.
+const HEADER_KEY: &[u8] = b"header";
+// RocksDB key that is present in format version 2, which predates the header.
+const SPARSE_ROWS_KEY: &[u8] = b"sparse_rows";
 // A default column family is always created, so we'll make use of that for meta information.
 // The indices themselves are stored in a column family each, with their position in
 // PersistentState::indices as name.
 const DEFAULT_CF: &str = "default";
+// Column family that logs the batches of records applied since the oldest retained checkpoint,
+// keyed by their big-endian sequence number, if the state keeps a log.
+const LOG_CF: &str = "log";
+// Key in LOG_CF of the retained checkpoints, along with the sequence number of the first batch
+// that each of them does not include.
+const CHECKPOINTS_KEY: &[u8] = b"checkpoints";
+// Key in LOG_CF that is present while the state is rewound to a checkpoint. It holds the sequence
+// number that the log ends at, and the one that the applied batches go up to.
+const REWOUND_KEY: &[u8] = b"rewound";
 
 // Maximum rows per WriteBatch when building new indices for existing rows.
 const INDEX_BATCH_SIZE: usize = 100_000;
@@ -32,6 +60,11 @@ struct PersistentMeta {
     epoch: IndexEpoch,
 }
 
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
 #[derive(Clone)]
 struct PersistentIndex {
     column_family: String,
@@ -50,9 +83,15 @@ pub struct PersistentState {
     // read during lookups. When `self.has_unique_index` is true the first index is a primary key,
     // and all its keys are considered unique.
     indices: Vec<PersistentIndex>,
+    // A sketch of the keys of each index, in the same order as `self.indices`. They are kept in
+    // memory only, and rebuilt from the rows when the state is opened.
+    sketches: Vec<KeySketch>,
     seq: IndexSeq,
     epoch: IndexEpoch,
     has_unique_index: bool,
+    version: u32,
+    // The sequence number of the next batch of records to log, if the state keeps a log.
+    log_seq: Option<u64>,
     // With DurabilityMode::DeleteOnExit,
     // RocksDB files are stored in a temporary directory.
     _directory: Option<TempDir>,
@@ -77,6 +116,18 @@ impl State for PersistentState {
             }
         }
 
+        if let Some(seq) = self.log_seq {
+            // logged in the same write as the records themselves, so the two can't disagree
+            let db = self.db.as_ref().unwrap();
+            let cf = db.cf_handle(LOG_CF).unwrap();
+            batch.put_cf(
+                cf,
+                &seq.to_be_bytes(),
+                &bincode::serialize(&*records).unwrap(),
+            );
+            self.log_seq = Some(seq + 1);
+        }
+
         // Sync the writes to RocksDB's WAL:
         let mut opts = rocksdb::WriteOptions::default();
         opts.set_sync(true);
@@ -98,7 +149,7 @@ impl State for PersistentState {
                 // (no need to use prefix_iterator).
                 let raw_row = db.get_cf(cf, &prefix).unwrap();
                 if let Some(raw) = raw_row {
-                    let row = bincode::deserialize(&*raw).unwrap();
+                    let row = Self::deserialize_row(self.version, &*raw);
                     vec![row]
                 } else {
                     vec![]
@@ -106,7 +157,7 @@ impl State for PersistentState {
             } else {
                 // This could correspond to more than one value, so we'll use a prefix_iterator:
                 db.prefix_iterator_cf(cf, &prefix)
-                    .map(|(_key, value)| bincode::deserialize(&*value).unwrap())
+                    .map(|(_key, value)| Self::deserialize_row(self.version, &*value))
                     .collect()
             };
 
@@ -129,19 +180,22 @@ impl State for PersistentState {
         // We'll store all the pointers (or values if this is index 0) for
         // this index in its own column family:
         let index_id = self.indices.len().to_string();
+        let version = self.version;
 
         tokio::task::block_in_place(|| {
             let db = self.db.as_mut().unwrap();
             db.create_cf(&index_id, &self.db_opts).unwrap();
 
             // Build the new index for existing values:
+            let mut sketch = KeySketch::new();
             if !self.indices.is_empty() {
                 let first_cf = db.cf_handle(&self.indices[0].column_family).unwrap();
                 let iter = db.full_iterator_cf(first_cf, rocksdb::IteratorMode::Start);
                 for chunk in iter.chunks(INDEX_BATCH_SIZE).into_iter() {
                     let mut batch = WriteBatch::default();
                     for (ref pk, ref value) in chunk {
-                        let row: Vec<DataType> = bincode::deserialize(&value).unwrap();
+                        let row = Self::deserialize_row(version, &value);
+                        sketch.insert(&row, columns);
                         let index_key = Self::build_key(&row, columns);
                         let key = Self::serialize_secondary(&index_key, pk);
                         let cf = db.cf_handle(&index_id).unwrap();
@@ -156,6 +210,7 @@ impl State for PersistentState {
                 columns: cols,
                 column_family: index_id.to_string(),
             });
+            self.sketches.push(sketch);
 
             self.persist_meta();
         });
@@ -168,9 +223,27 @@ impl State for PersistentState {
             .collect()
     }
 
+    fn distinct_keys(&self) -> Vec<(Vec<usize>, usize)> {
+        // counting the keys of an index means scanning it, so they are estimated from sketches,
+        // except for a primary key, which has as many keys as there are rows
+        self.indices
+            .iter()
+            .zip(&self.sketches)
+            .enumerate()
+            .map(|(i, (index, sketch))| {
+                let count = if i == 0 && self.has_unique_index {
+                    self.rows()
+                } else {
+                    sketch.estimate()
+                };
+                (index.columns.clone(), count)
+            })
+            .collect()
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         self.all_rows()
-            .map(|(_, ref value)| bincode::deserialize(&value).unwrap())
+            .map(|(_, ref value)| Self::deserialize_row(self.version, &value))
             .collect()
     }
 
@@ -215,6 +288,10 @@ impl State for PersistentState {
     fn clear(&mut self) {
         unreachable!("can't clear PersistentState")
     }
+
+    fn as_persistent_mut(&mut self) -> Option<&mut PersistentState> {
+        Some(self)
+    }
 }
 
 impl PersistentState {
@@ -248,7 +325,12 @@ impl PersistentState {
                 column_families
                     .iter()
                     .map(|cf| {
-                        ColumnFamilyDescriptor::new(cf.clone(), Self::build_options(&name, &params))
+                        let opts = if cf == LOG_CF {
+                            Self::log_options()
+                        } else {
+                            Self::build_options(&name, &params)
+                        };
+                        ColumnFamilyDescriptor::new(cf.clone(), opts)
                     })
                     .collect()
             };
@@ -263,6 +345,22 @@ impl PersistentState {
             }
             let mut db = db.unwrap();
             let meta = Self::retrieve_and_update_meta(&db);
+            let version = match Self::stored_version(&db, &meta) {
+                Some(version) => {
+                    assert!(
+                        version <= FORMAT_VERSION,
+                        "{} was written in unknown format version {}",
+                        full_name,
+                        version
+                    );
+                    version
+                }
+                None => {
+                    // a new database, which has no rows in an older format
+                    Self::persist_header(&db);
+                    FORMAT_VERSION
+                }
+            };
             let indices: Vec<PersistentIndex> = meta
                 .indices
                 .into_iter()
@@ -273,18 +371,25 @@ impl PersistentState {
                 })
                 .collect();
 
-            // If there are more column families than indices (-1 to account for the default column
-            // family) we probably crashed while trying to build the last index (in Self::add_key), so
-            // we'll throw away our progress and try re-building it again later:
-            if column_families.len() - 1 > indices.len() {
+            // If there are more index column families than indices we probably crashed while trying
+            // to build the last index (in Self::add_key), so we'll throw away our progress and try
+            // re-building it again later:
+            let index_cfs = column_families
+                .iter()
+                .filter(|cf| cf.parse::<usize>().is_ok())
+                .count();
+            if index_cfs > indices.len() {
                 db.drop_cf(&indices.len().to_string()).unwrap();
             }
 
             let mut state = Self {
                 seq: 0,
+                sketches: vec![KeySketch::new(); indices.len()],
                 indices,
                 has_unique_index: primary_key.is_some(),
+                version,
                 epoch: meta.epoch,
+                log_seq: None,
                 db_opts: opts,
                 db: Some(db),
                 _directory: directory,
@@ -306,13 +411,31 @@ impl PersistentState {
                 };
 
                 state.indices.push(persistent_index);
+                state.sketches.push(KeySketch::new());
                 state.persist_meta();
+            } else if state.indices.len() > 1
+                || (!state.indices.is_empty() && !state.has_unique_index)
+            {
+                state.rebuild_sketches();
             }
 
             state
         })
     }
 
+    // Fill in the sketches of the indices from the rows that are already stored.
+    fn rebuild_sketches(&mut self) {
+        let version = self.version;
+        let mut sketches = vec![KeySketch::new(); self.indices.len()];
+        for (_, ref value) in self.all_rows() {
+            let row = Self::deserialize_row(version, &value);
+            for (sketch, index) in sketches.iter_mut().zip(&self.indices) {
+                sketch.insert(&row, &index.columns);
+            }
+        }
+        self.sketches = sketches;
+    }
+
     fn build_options(name: &str, params: &PersistenceParameters) -> rocksdb::Options {
         let mut opts = rocksdb::Options::default();
         opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
@@ -372,23 +495,306 @@ impl PersistentState {
         opts
     }
 
+    // The log is iterated over in order, so unlike the indices it can't use the hashed memtable
+    // and plain tables that are made for prefix lookups.
+    fn log_options() -> rocksdb::Options {
+        let mut opts = rocksdb::Options::default();
+        opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
+        opts
+    }
+
+    /// Log every batch of records applied to this state from now on, so that the state can be
+    /// taken back to a checkpoint with `rewind`, and brought forward again with `redo`.
+    pub(crate) fn enable_log(&mut self) {
+        if self.log_seq.is_some() {
+            return;
+        }
+
+        tokio::task::block_in_place(|| {
+            let db = self.db.as_mut().unwrap();
+            if db.cf_handle(LOG_CF).is_none() {
+                db.create_cf(LOG_CF, &Self::log_options()).unwrap();
+            }
+        });
+        // the log may have been trimmed down to nothing, but numbering must continue from where
+        // the last checkpoint left off
+        let next = self
+            .log_seqs()
+            .last()
+            .map(|seq| seq + 1)
+            .into_iter()
+            .chain(self.logged_checkpoints().into_iter().map(|(_, seq)| seq))
+            .chain(self.rewound().map(|(end, _)| end))
+            .max()
+            .unwrap_or(0);
+        self.log_seq = Some(next);
+    }
+
+    /// Record that checkpoint `id` includes every batch of records applied so far.
+    pub(crate) fn mark_checkpoint(&mut self, id: u64) {
+        let seq = self.log_seq.expect("checkpoint of state that keeps no log");
+        let mut checkpoints = self.logged_checkpoints();
+        // an attempt at the same checkpoint may have been abandoned
+        checkpoints.retain(|&(c, _)| c != id);
+        checkpoints.push((id, seq));
+        self.write_log(|batch, cf| {
+            batch.put_cf(
+                cf,
+                CHECKPOINTS_KEY,
+                &bincode::serialize(&checkpoints).unwrap(),
+            );
+        });
+    }
+
+    /// Drop the batches of records that checkpoint `id` includes from the log, along with the
+    /// checkpoints before it, which recovery will no longer start from.
+    pub(crate) fn trim_log(&mut self, id: u64) {
+        let mut checkpoints = self.logged_checkpoints();
+        let seq = match checkpoints.iter().find(|&&(c, _)| c == id) {
+            Some(&(_, seq)) => seq,
+            None => return,
+        };
+        checkpoints.retain(|&(c, _)| c >= id);
+        let trimmed: Vec<_> = self
+            .log_seqs()
+            .into_iter()
+            .take_while(|&s| s < seq)
+            .collect();
+        self.write_log(|batch, cf| {
+            for s in trimmed {
+                batch.delete_cf(cf, &s.to_be_bytes());
+            }
+            batch.put_cf(
+                cf,
+                CHECKPOINTS_KEY,
+                &bincode::serialize(&checkpoints).unwrap(),
+            );
+        });
+    }
+
+    /// Undo the batches of records applied since checkpoint `id`, most recent first.
+    ///
+    /// The undone batches stay in the log until `redo` applies them again. Each batch is undone in
+    /// a write of its own, along with how far the rewind has got, so that a rewind that is cut
+    /// short picks up where it left off when it is started again. Returns false, and leaves the
+    /// state as it is, if the log does not go back to that checkpoint.
+    pub(crate) fn rewind(&mut self, id: u64) -> bool {
+        let from = match self
+            .logged_checkpoints()
+            .into_iter()
+            .find(|&(c, _)| c == id)
+        {
+            Some((_, seq)) => seq,
+            None => return false,
+        };
+        let (end, at) = self.rewound().unwrap_or_else(|| {
+            let end = self.log_seq.unwrap();
+            (end, end)
+        });
+
+        for (seq, records) in self.log_entries(from).into_iter().rev() {
+            if seq >= at {
+                continue;
+            }
+            let mut batch = WriteBatch::default();
+            for r in without_reversals(records).into_iter().rev() {
+                match r {
+                    Record::Positive(r) => self.remove(&mut batch, &r),
+                    Record::Negative(r) => self.insert(&mut batch, &r),
+                }
+            }
+            self.write_batch_and_log(batch, |batch, cf| {
+                batch.put_cf(cf, REWOUND_KEY, &bincode::serialize(&(end, seq)).unwrap());
+            });
+        }
+        true
+    }
+
+    /// Apply the batches of records that `rewind` undid again, and return them in the order they
+    /// were applied in.
+    pub(crate) fn redo(&mut self) -> Vec<Records> {
+        let (end, at) = match self.rewound() {
+            Some(rewound) => rewound,
+            None => return Vec::new(),
+        };
+
+        let mut redone = Vec::new();
+        for (seq, records) in self.log_entries(at) {
+            if seq >= end {
+                break;
+            }
+            let mut batch = WriteBatch::default();
+            for r in records.iter() {
+                match *r {
+                    Record::Positive(ref r) => self.insert(&mut batch, r),
+                    Record::Negative(ref r) => self.remove(&mut batch, r),
+                }
+            }
+            self.write_batch_and_log(batch, |batch, cf| {
+                if seq + 1 == end {
+                    batch.delete_cf(cf, REWOUND_KEY);
+                } else {
+                    batch.put_cf(
+                        cf,
+                        REWOUND_KEY,
+                        &bincode::serialize(&(end, seq + 1)).unwrap(),
+                    );
+                }
+            });
+            redone.push(records);
+        }
+        redone
+    }
+
+    // The retained checkpoints, oldest first, along with where in the log each of them ends.
+    fn logged_checkpoints(&self) -> Vec<(u64, u64)> {
+        let db = self.db.as_ref().unwrap();
+        match db.cf_handle(LOG_CF) {
+            Some(cf) => db
+                .get_cf(cf, CHECKPOINTS_KEY)
+                .unwrap()
+                .map(|raw| bincode::deserialize(&*raw).unwrap())
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    // Where the log ends and how far its batches are applied, if the state is rewound.
+    fn rewound(&self) -> Option<(u64, u64)> {
+        let db = self.db.as_ref().unwrap();
+        let cf = db.cf_handle(LOG_CF)?;
+        db.get_cf(cf, REWOUND_KEY)
+            .unwrap()
+            .map(|raw| bincode::deserialize(&*raw).unwrap())
+    }
+
+    // The sequence numbers of the logged batches of records, in order.
+    fn log_seqs(&self) -> Vec<u64> {
+        let db = self.db.as_ref().unwrap();
+        let cf = db.cf_handle(LOG_CF).unwrap();
+        // the other keys in the log start with a letter, so they sort after every sequence number
+        db.full_iterator_cf(cf, rocksdb::IteratorMode::Start)
+            .take_while(|(key, _)| key.len() == 8)
+            .map(|(key, _)| log_seq(&key))
+            .collect()
+    }
+
+    // The logged batches of records from sequence number `from` on, in order.
+    fn log_entries(&self, from: u64) -> Vec<(u64, Records)> {
+        let db = self.db.as_ref().unwrap();
+        let cf = db.cf_handle(LOG_CF).unwrap();
+        let start = from.to_be_bytes();
+        let mode = rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward);
+        db.full_iterator_cf(cf, mode)
+            .take_while(|(key, _)| key.len() == 8)
+            .map(|(key, value)| (log_seq(&key), bincode::deserialize(&*value).unwrap()))
+            .collect()
+    }
+
+    fn write_log<F>(&self, f: F)
+    where
+        F: FnOnce(&mut WriteBatch, &rocksdb::ColumnFamily),
+    {
+        self.write_batch_and_log(WriteBatch::default(), f)
+    }
+
+    // Sync `batch` to disk along with the changes `f` makes to the log.
+    fn write_batch_and_log<F>(&self, mut batch: WriteBatch, f: F)
+    where
+        F: FnOnce(&mut WriteBatch, &rocksdb::ColumnFamily),
+    {
+        tokio::task::block_in_place(|| {
+            let db = self.db.as_ref().unwrap();
+            f(&mut batch, db.cf_handle(LOG_CF).unwrap());
+            let mut opts = rocksdb::WriteOptions::default();
+            opts.set_sync(true);
+            db.write_opt(batch, &opts).unwrap();
+        })
+    }
+
+    fn serialize_row(&self, r: &[DataType]) -> Vec<u8> {
+        Self::encode_row(self.version, r)
+    }
+
+    fn deserialize_row(version: u32, raw: &[u8]) -> Vec<DataType> {
+        Self::decode_row(version, raw).expect("persisted row is corrupt")
+    }
+
+    fn encode_row(version: u32, r: &[DataType]) -> Vec<u8> {
+        match version {
+            1 => bincode::serialize(&r).unwrap(),
+            2 => bincode::serialize(&SparseRow(Cow::Borrowed(r))).unwrap(),
+            _ => {
+                let mut raw = Self::encode_row(2, r);
+                let sum = checksum(&raw);
+                raw.extend_from_slice(&sum.to_le_bytes());
+                raw
+            }
+        }
+    }
+
+    // Returns None if the row can't be decoded, or doesn't match its checksum.
+    fn decode_row(version: u32, raw: &[u8]) -> Option<Vec<DataType>> {
+        match version {
+            1 => bincode::deserialize(raw).ok(),
+            2 => bincode::deserialize::<SparseRow<'_>>(raw)
+                .ok()
+                .map(|row| row.0.into_owned()),
+            _ => {
+                if raw.len() < 8 {
+                    return None;
+                }
+                let (row, sum) = raw.split_at(raw.len() - 8);
+                if checksum(row).to_le_bytes() != sum {
+                    return None;
+                }
+                Self::decode_row(2, row)
+            }
+        }
+    }
+
     fn build_key<'a>(row: &'a [DataType], columns: &[usize]) -> KeyType<'a> {
         KeyType::from(columns.iter().map(|i| &row[*i]))
     }
 
-    fn retrieve_and_update_meta(db: &rocksdb::DB) -> PersistentMeta {
+    fn retrieve_meta(db: &rocksdb::DB) -> PersistentMeta {
         let indices = db.get(META_KEY).unwrap();
-        let mut meta = match indices {
+        match indices {
             Some(data) => bincode::deserialize(&*data).unwrap(),
             None => PersistentMeta::default(),
-        };
+        }
+    }
 
+    fn retrieve_and_update_meta(db: &rocksdb::DB) -> PersistentMeta {
+        let mut meta = Self::retrieve_meta(db);
         meta.epoch += 1;
         let data = bincode::serialize(&meta).unwrap();
         db.put(META_KEY, &data).unwrap();
         meta
     }
 
+    // The format version of an existing database, or None if nothing has been stored in it yet.
+    fn stored_version(db: &rocksdb::DB, meta: &PersistentMeta) -> Option<u32> {
+        if let Some(data) = db.get(HEADER_KEY).unwrap() {
+            let header: Header = bincode::deserialize(&*data).unwrap();
+            Some(header.version)
+        } else if meta.indices.is_empty() {
+            None
+        } else if db.get(SPARSE_ROWS_KEY).unwrap().is_some() {
+            Some(2)
+        } else {
+            Some(1)
+        }
+    }
+
+    fn persist_header(db: &rocksdb::DB) {
+        let header = Header {
+            version: FORMAT_VERSION,
+        };
+        db.put(HEADER_KEY, &bincode::serialize(&header).unwrap())
+            .unwrap();
+    }
+
     fn persist_meta(&mut self) {
         let db = self.db.as_ref().unwrap();
         // Stores the columns of self.indices in RocksDB so that we don't rebuild indices on recovery.
@@ -470,8 +876,12 @@ impl PersistentState {
             }
         };
 
+        for (sketch, index) in self.sketches.iter_mut().zip(&self.indices) {
+            sketch.insert(r, &index.columns);
+        }
+
         // First insert the actual value for our primary index:
-        let serialized_row = bincode::serialize(&r).unwrap();
+        let serialized_row = self.serialize_row(r);
 
         tokio::task::block_in_place(|| {
             let db = self.db.as_ref().unwrap();
@@ -492,6 +902,7 @@ impl PersistentState {
     fn remove(&self, batch: &mut WriteBatch, r: &[DataType]) {
         tokio::task::block_in_place(|| {
             let db = self.db.as_ref().unwrap();
+            let version = self.version;
             let pk_index = &self.indices[0];
             let value_cf = db.cf_handle(&pk_index.column_family).unwrap();
             let mut do_remove = move |primary_key: &[u8]| {
@@ -518,7 +929,7 @@ impl PersistentState {
                         .get_cf(value_cf, &prefix)
                         .unwrap()
                         .expect("tried removing non-existant primary key row");
-                    let value: Vec<DataType> = bincode::deserialize(&*raw).unwrap();
+                    let value = Self::deserialize_row(version, &*raw);
                     assert_eq!(r, &value[..], "tried removing non-matching primary key row");
                 }
 
@@ -527,7 +938,7 @@ impl PersistentState {
                 let (key, _value) = db
                     .prefix_iterator_cf(value_cf, &prefix)
                     .find(|(_, raw_value)| {
-                        let value: Vec<DataType> = bincode::deserialize(&*raw_value).unwrap();
+                        let value = Self::deserialize_row(version, &*raw_value);
                         r == &value[..]
                     })
                     .expect("tried removing non-existant row");
@@ -567,10 +978,10 @@ impl PersistentState {
 // prefix transformed this key before or not
 // (without including the byte size of Vec<DataType>).
 fn prefix_transform<'a>(key: &'a [u8]) -> &'a [u8] {
-    // We'll have to make sure this isn't the META_KEY even when we're filtering it out
-    // in Self::in_domain_fn, as the SliceTransform is used to make hashed keys for our
+    // We'll have to make sure this isn't a meta information key even when we're filtering those
+    // out in Self::in_domain_fn, as the SliceTransform is used to make hashed keys for our
     // HashLinkedList memtable factory.
-    if key == META_KEY {
+    if !in_domain(key) {
         return key;
     }
 
@@ -584,7 +995,43 @@ fn prefix_transform<'a>(key: &'a [u8]) -> &'a [u8] {
 
 // Decides which keys the prefix transform should apply to.
 fn in_domain(key: &[u8]) -> bool {
-    key != META_KEY
+    key != META_KEY && key != HEADER_KEY && key != SPARSE_ROWS_KEY
+}
+
+fn log_seq(key: &[u8]) -> u64 {
+    let mut seq = [0; 8];
+    seq.copy_from_slice(key);
+    u64::from_be_bytes(seq)
+}
+
+// The records of a batch without the pairs of a positive and a negative record for the same row,
+// which leave the state as it was. What remains can be undone in reverse without lookups of rows
+// that are only added or removed earlier in the same write.
+fn without_reversals(records: Records) -> Vec<Record> {
+    let mut kept: Vec<Option<Record>> = Vec::with_capacity(records.len());
+    // the positions of the kept records of each row, which all have the same sign
+    let mut open: HashMap<Vec<DataType>, Vec<usize>> = HashMap::new();
+    for r in records {
+        let same_row = open.entry(r.rec().to_vec()).or_default();
+        match same_row.last() {
+            Some(&i) if kept[i].as_ref().unwrap().is_positive() != r.is_positive() => {
+                kept[i] = None;
+                same_row.pop();
+            }
+            _ => {
+                same_row.push(kept.len());
+                kept.push(Some(r));
+            }
+        }
+    }
+    kept.into_iter().flatten().collect()
+}
+
+// FNV-1a, which unlike the hashers in std is guaranteed to stay the same across releases.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
 }
 
 impl SizeOf for PersistentState {
@@ -610,6 +1057,132 @@ impl SizeOf for PersistentState {
     }
 }
 
+/// What `inspect_state` found in the persisted state of a base table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateReport {
+    /// The format version the state is stored in.
+    pub version: u32,
+    /// The columns of each index, starting with the primary index.
+    pub indices: Vec<Vec<usize>>,
+    /// The number of rows in the primary index.
+    pub rows: usize,
+    /// The number of stored rows, across all indices, that could not be decoded or did not match
+    /// their checksum.
+    pub corrupt: usize,
+    /// The size of the files in the state's directory, in bytes.
+    pub bytes: u64,
+}
+
+// Opens the RocksDB database at `path` without touching its contents, unlike
+// PersistentState::new, which bumps the epoch and creates missing databases.
+fn open_offline(
+    path: &Path,
+    params: &PersistenceParameters,
+) -> Result<rocksdb::DB, rocksdb::Error> {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut opts = PersistentState::build_options(&name, params);
+    opts.create_if_missing(false);
+
+    let column_families = rocksdb::DB::list_cf(&opts, path)?;
+    let cfs = column_families.into_iter().map(|cf| {
+        rocksdb::ColumnFamilyDescriptor::new(cf, PersistentState::build_options(&name, params))
+    });
+    rocksdb::DB::open_cf_descriptors(&opts, path, cfs)
+}
+
+/// Report on the persisted state at `path`, decoding every stored row to verify it.
+///
+/// `path` is the `.db` directory of a base table, which must not be in use by a running server.
+pub fn inspect_state(
+    path: &Path,
+    params: &PersistenceParameters,
+) -> Result<StateReport, rocksdb::Error> {
+    let db = open_offline(path, params)?;
+    let meta = PersistentState::retrieve_meta(&db);
+    let version = PersistentState::stored_version(&db, &meta).unwrap_or(FORMAT_VERSION);
+
+    let mut rows = 0;
+    let mut corrupt = 0;
+    for i in 0..meta.indices.len() {
+        let cf = db.cf_handle(&i.to_string()).unwrap();
+        for (_, value) in db.full_iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            if i == 0 {
+                rows += 1;
+            }
+            if PersistentState::decode_row(version, &value).is_none() {
+                corrupt += 1;
+            }
+        }
+    }
+
+    let bytes = std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    Ok(StateReport {
+        version,
+        indices: meta.indices,
+        rows,
+        corrupt,
+        bytes,
+    })
+}
+
+/// Rewrite the persisted state at `path` in the current `FORMAT_VERSION`, and return the version
+/// it was stored in before.
+///
+/// Rows that cannot be decoded are left as they are, so that `inspect_state` keeps reporting them.
+pub fn upgrade_state(path: &Path, params: &PersistenceParameters) -> Result<u32, rocksdb::Error> {
+    let db = open_offline(path, params)?;
+    let meta = PersistentState::retrieve_meta(&db);
+    let version = match PersistentState::stored_version(&db, &meta) {
+        Some(version) => version,
+        None => return Ok(FORMAT_VERSION),
+    };
+    if version >= FORMAT_VERSION {
+        return Ok(version);
+    }
+
+    // Secondary indices hold copies of the rows too, so all of them need rewriting.
+    for i in 0..meta.indices.len() {
+        let cf = db.cf_handle(&i.to_string()).unwrap();
+        let iter = db.full_iterator_cf(cf, rocksdb::IteratorMode::Start);
+        for chunk in iter.chunks(INDEX_BATCH_SIZE).into_iter() {
+            let mut batch = WriteBatch::default();
+            for (key, value) in chunk {
+                if let Some(row) = PersistentState::decode_row(version, &value) {
+                    batch.put_cf(cf, &key, PersistentState::encode_row(FORMAT_VERSION, &row));
+                }
+            }
+            db.write(batch)?;
+        }
+    }
+
+    // Only mark the state as upgraded once all of the rows have been.
+    PersistentState::persist_header(&db);
+    db.delete(SPARSE_ROWS_KEY)?;
+    Ok(version)
+}
+
+/// Compact all the indices of the persisted state at `path`, discarding the space taken up by
+/// overwritten and deleted rows.
+pub fn compact_state(path: &Path, params: &PersistenceParameters) -> Result<(), rocksdb::Error> {
+    let db = open_offline(path, params)?;
+    let meta = PersistentState::retrieve_meta(&db);
+    let indices = (0..meta.indices.len()).map(|i| i.to_string());
+    for cf in std::iter::once(DEFAULT_CF.to_string()).chain(indices) {
+        let cf = db.cf_handle(&cf).unwrap();
+        db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -880,6 +1453,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_distinct_keys() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            state.add_key(&[1], None);
+            let rows: Vec<Vec<DataType>> =
+                (0..20).map(|i| vec![i.into(), (i % 4).into()]).collect();
+            state.process_records(&mut rows.into(), None);
+            assert_eq!(state.distinct_keys()[1], (vec![1], 4));
+        }
+
+        // the sketches are rebuilt from the stored rows on recovery
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(state.distinct_keys()[1], (vec![1], 4));
+    }
+
     #[test]
     fn persistent_state_recover_unique_key() {
         let (_dir, name) = get_tmp_path();
@@ -1061,7 +1653,7 @@ mod tests {
 
         let actual_rows: Vec<Vec<DataType>> = state
             .all_rows()
-            .map(|(_key, value)| bincode::deserialize(&value).unwrap())
+            .map(|(_key, value)| PersistentState::deserialize_row(state.version, &value))
             .collect();
 
         assert_eq!(actual_rows, rows);
@@ -1079,6 +1671,95 @@ mod tests {
         assert_eq!(state.cloned_records(), vec![first, second]);
     }
 
+    #[test]
+    fn persistent_state_sparse_rows() {
+        let mut state = setup_persistent("persistent_state_sparse_rows");
+        assert_eq!(state.version, FORMAT_VERSION);
+        let mut row = vec![DataType::None; 50];
+        row[0] = 10.into();
+        row[42] = "Cat".into();
+        state.add_key(&[0], None);
+        insert(&mut state, row.clone());
+        state.add_key(&[42], None);
+
+        match state.lookup(&[42], &KeyType::Single(&"Cat".into())) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows, vec![row.clone()]),
+            _ => unreachable!(),
+        }
+        assert_eq!(state.cloned_records(), vec![row]);
+    }
+
+    #[test]
+    fn persistent_state_upgrade() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        let first: Vec<DataType> = vec![10.into(), "Cat".into()];
+        let second: Vec<DataType> = vec![20.into(), DataType::None];
+        {
+            // pretend this state was created before the header existed
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            let db = state.db.as_ref().unwrap();
+            db.delete(HEADER_KEY).unwrap();
+            db.put(SPARSE_ROWS_KEY, &[]).unwrap();
+            state.version = 2;
+            state.add_key(&[1], None);
+            state.process_records(&mut vec![first.clone(), second.clone()].into(), None);
+        }
+
+        let path = PathBuf::from(format!("{}.db", name));
+        let report = inspect_state(&path, &params).unwrap();
+        assert_eq!(report.version, 2);
+        assert_eq!(report.indices, vec![vec![0], vec![1]]);
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.corrupt, 0);
+        assert!(report.bytes > 0);
+
+        assert_eq!(upgrade_state(&path, &params).unwrap(), 2);
+        let report = inspect_state(&path, &params).unwrap();
+        assert_eq!(report.version, FORMAT_VERSION);
+        assert_eq!(report.corrupt, 0);
+        assert_eq!(upgrade_state(&path, &params).unwrap(), FORMAT_VERSION);
+
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(state.version, FORMAT_VERSION);
+        match state.lookup(&[1], &KeyType::Single(&DataType::None)) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows, vec![second.clone()]),
+            _ => unreachable!(),
+        }
+        assert_eq!(state.cloned_records(), vec![first, second]);
+    }
+
+    #[test]
+    fn persistent_state_verify_and_compact() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            let rows: Vec<Vec<DataType>> = (0..10).map(|i| vec![i.into()]).collect();
+            state.process_records(&mut rows.clone().into(), None);
+            let mut deletes: Records = rows.into_iter().skip(1).map(Record::Negative).collect();
+            state.process_records(&mut deletes, None);
+
+            // flip a bit in the one remaining row
+            let db = state.db.as_ref().unwrap();
+            let cf = db.cf_handle("0").unwrap();
+            let (key, mut value) = db
+                .full_iterator_cf(cf, rocksdb::IteratorMode::Start)
+                .next()
+                .unwrap();
+            value[0] ^= 1;
+            db.put_cf(cf, &key, &value).unwrap();
+        }
+
+        let path = PathBuf::from(format!("{}.db", name));
+        compact_state(&path, &params).unwrap();
+        let report = inspect_state(&path, &params).unwrap();
+        assert_eq!(report.rows, 1);
+        assert_eq!(report.corrupt, 1);
+    }
+
     #[test]
     #[cfg(not(windows))]
     fn persistent_state_drop() {
@@ -1141,6 +1822,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_state_rewind_and_redo() {
+        let mut state = PersistentState::new(
+            String::from("persistent_state_rewind_and_redo"),
+            Some(&[0][..]),
+            &PersistenceParameters::default(),
+        );
+        let rows = |state: &PersistentState| {
+            let mut rows = state.cloned_records();
+            rows.sort();
+            rows
+        };
+        state.enable_log();
+        insert(&mut state, vec![1.into(), "a".into()]);
+        state.mark_checkpoint(1);
+
+        let since: Vec<Records> = vec![
+            vec![(vec![2.into(), "b".into()], true)].into(),
+            vec![
+                (vec![1.into(), "a".into()], false),
+                (vec![1.into(), "x".into()], true),
+            ]
+            .into(),
+        ];
+        for records in &since {
+            state.process_records(&mut records.clone(), None);
+        }
+
+        assert!(!state.rewind(2));
+        assert!(state.rewind(1));
+        assert_eq!(rows(&state), vec![vec![1.into(), "a".into()]]);
+        // rewinding again doesn't undo anything twice
+        assert!(state.rewind(1));
+        assert_eq!(rows(&state), vec![vec![1.into(), "a".into()]]);
+
+        assert_eq!(state.redo(), since);
+        assert_eq!(
+            rows(&state),
+            vec![vec![1.into(), "x".into()], vec![2.into(), "b".into()]]
+        );
+        assert!(state.redo().is_empty());
+
+        // only the batch from before the checkpoint is dropped
+        state.trim_log(1);
+        assert_eq!(state.log_seqs(), vec![1, 2]);
+    }
+
     #[test]
     #[allow(clippy::op_ref)]
     fn persistent_state_prefix_transform() {
@@ -240,6 +240,16 @@ impl SingleState {
         (bytes_freed, keys)
     }
 
+    /// Remove the rows of all keys for which `f` returns true, returning them by key.
+    pub(super) fn drain_where<F>(&mut self, f: F) -> Vec<(Vec<DataType>, Rows)>
+    where
+        F: Fn(&[DataType]) -> bool,
+    {
+        let drained = self.state.drain_where(f);
+        self.rows -= drained.iter().map(|(_, rs)| rs.len()).sum::<usize>();
+        drained
+    }
+
     /// Evicts a specified key from this state, returning the number of bytes freed.
     pub(super) fn evict_keys(&mut self, keys: &[Vec<DataType>]) -> u64 {
         keys.iter().map(|k| self.state.evict(k)).sum()
@@ -264,6 +274,9 @@ impl SingleState {
     pub(super) fn rows(&self) -> usize {
         self.rows
     }
+    pub(super) fn key_count(&self) -> usize {
+        self.state.key_count()
+    }
     pub(super) fn is_empty(&self) -> bool {
         self.rows == 0
     }
@@ -0,0 +1,89 @@
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::prelude::*;
+
+/// Number of bits of a key's hash that pick its register.
+const PRECISION: u32 = 10;
+
+/// Number of registers in a sketch, each of which takes up a byte.
+const REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch of the keys of an index, which estimates how many distinct keys it has
+/// seen to within a few percent in a kilobyte of memory.
+///
+/// Keys cannot be taken out of a sketch, so the estimate counts the keys of rows that have since
+/// been removed as well.
+#[derive(Clone)]
+pub(super) struct KeySketch {
+    registers: Vec<u8>,
+}
+
+impl KeySketch {
+    pub(super) fn new() -> Self {
+        KeySketch {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Record the key that `row` has in the given columns.
+    pub(super) fn insert(&mut self, row: &[DataType], columns: &[usize]) {
+        let mut hasher = DefaultHasher::new();
+        for &c in columns {
+            row[c].hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+
+        let register = (hash >> (64 - PRECISION)) as usize;
+        // the position of the first set bit of the rest of the hash, counting from one
+        let rank = cmp::min((hash << PRECISION).leading_zeros(), 64 - PRECISION) + 1;
+        self.registers[register] = cmp::max(self.registers[register], rank as u8);
+    }
+
+    /// The estimated number of distinct keys recorded.
+    pub(super) fn estimate(&self) -> usize {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // small cardinalities are estimated better from how many registers are still empty
+        let empty = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && empty != 0 {
+            (m * (m / empty as f64).ln()).round() as usize
+        } else {
+            estimate.round() as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_small_sets_exactly() {
+        let mut sketch = KeySketch::new();
+        assert_eq!(sketch.estimate(), 0);
+        for i in 0..10 {
+            sketch.insert(&[i.into(), "a".into()], &[0]);
+            sketch.insert(&[i.into(), "b".into()], &[0]);
+        }
+        assert_eq!(sketch.estimate(), 10);
+    }
+
+    #[test]
+    fn it_estimates_large_sets() {
+        let mut sketch = KeySketch::new();
+        for i in 0..100_000 {
+            sketch.insert(&[(i % 50_000).into()], &[0]);
+        }
+        let estimate = sketch.estimate() as f64;
+        assert!((estimate - 50_000.0).abs() < 50_000.0 * 0.1, "{}", estimate);
+    }
+}
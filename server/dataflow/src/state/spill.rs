@@ -0,0 +1,275 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use noria::sparse::SparseRow;
+use rocksdb::{self, Direction, IteratorMode, WriteBatch};
+use tempfile::{tempdir, TempDir};
+
+use crate::prelude::*;
+
+/// Number of partitions the keys of a spillable state are hashed into.
+///
+/// A partition is the unit that is moved to disk, so this bounds how finely we can choose which
+/// keys stay in memory.
+const PARTITIONS: usize = 16;
+
+type Partition = HashMap<Vec<DataType>, Vec<Vec<DataType>>>;
+
+/// The on-disk part of a `MemoryState` that may grow beyond what we want to keep in memory.
+///
+/// Keys are hashed into a fixed number of partitions, and partitions are spilled whole, coldest
+/// first. Probes into a spilled partition read the entire partition into a buffer, so that the
+/// runs of lookups a join performs for one batch of records only go to disk once.
+pub(super) struct Spill {
+    threshold: u64,
+    /// Only opened once the first partition is spilled.
+    disk: Option<(rocksdb::DB, TempDir)>,
+    rows: usize,
+    spilled: [bool; PARTITIONS],
+    /// Number of probes into each partition since we last picked partitions to spill.
+    probes: [Cell<u64>; PARTITIONS],
+    /// The most recently probed spilled partition.
+    buffer: RefCell<Option<(usize, Partition)>>,
+}
+
+impl Spill {
+    /// Spill once the in-memory state exceeds `threshold` bytes.
+    pub(super) fn new(threshold: u64) -> Self {
+        Spill {
+            threshold,
+            disk: None,
+            rows: 0,
+            spilled: Default::default(),
+            probes: Default::default(),
+            buffer: RefCell::new(None),
+        }
+    }
+
+    /// The partition that rows with the given key belong to.
+    pub(super) fn partition(key: &[DataType]) -> usize {
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        h.finish() as usize % PARTITIONS
+    }
+
+    pub(super) fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Number of rows that are currently on disk.
+    pub(super) fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub(super) fn is_spilled(&self, partition: usize) -> bool {
+        self.spilled[partition]
+    }
+
+    /// Note that `partition` was probed, whether or not it has been spilled.
+    pub(super) fn probed(&self, partition: usize) {
+        let probes = &self.probes[partition];
+        probes.set(probes.get() + 1);
+    }
+
+    /// The partitions that are still in memory, from least to most recently probed.
+    pub(super) fn coldest(&self) -> Vec<usize> {
+        let mut partitions: Vec<_> = (0..PARTITIONS).filter(|&p| !self.spilled[p]).collect();
+        partitions.sort_by_key(|&p| self.probes[p].get());
+        for probes in &self.probes {
+            probes.set(0);
+        }
+        partitions
+    }
+
+    /// Move the rows of `partition`, grouped by key, to disk.
+    pub(super) fn spill(
+        &mut self,
+        partition: usize,
+        rows: Vec<(Vec<DataType>, Vec<Vec<DataType>>)>,
+    ) {
+        assert!(!self.spilled[partition]);
+        let mut batch = WriteBatch::default();
+        for (key, rs) in rows {
+            self.rows += rs.len();
+            batch.put(Self::serialize_key(partition, &key), serialize_rows(&rs));
+        }
+        let db = &self
+            .disk
+            .get_or_insert_with(|| {
+                let directory = tempdir().unwrap();
+                let db = rocksdb::DB::open_default(directory.path()).unwrap();
+                (db, directory)
+            })
+            .0;
+        tokio::task::block_in_place(|| db.write(batch)).unwrap();
+        self.spilled[partition] = true;
+    }
+
+    /// All rows with the given key in a spilled partition.
+    pub(super) fn lookup(&self, key: &[DataType]) -> Vec<Vec<DataType>> {
+        let partition = Self::partition(key);
+        debug_assert!(self.spilled[partition]);
+
+        let mut buffer = self.buffer.borrow_mut();
+        match *buffer {
+            Some((p, _)) if p == partition => {}
+            _ => *buffer = Some((partition, self.read_partition(partition))),
+        }
+        buffer
+            .as_ref()
+            .and_then(|(_, rows)| rows.get(key).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Add a row to a spilled partition.
+    pub(super) fn insert(&mut self, key: Vec<DataType>, r: Vec<DataType>) {
+        let partition = Self::partition(&key);
+        let mut rs = self.get(partition, &key);
+        rs.push(r);
+        self.put(partition, key, rs);
+        self.rows += 1;
+    }
+
+    /// Remove a row from a spilled partition, returning false if it was not there.
+    pub(super) fn remove(&mut self, key: Vec<DataType>, r: &[DataType]) -> bool {
+        let partition = Self::partition(&key);
+        let mut rs = self.get(partition, &key);
+        match rs.iter().position(|row| &row[..] == r) {
+            Some(i) => {
+                rs.swap_remove(i);
+                self.put(partition, key, rs);
+                self.rows -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A copy of every spilled row.
+    pub(super) fn cloned_records(&self) -> Vec<Vec<DataType>> {
+        match self.disk {
+            Some((ref db, _)) => tokio::task::block_in_place(|| {
+                db.iterator(IteratorMode::Start)
+                    .flat_map(|(_, value)| deserialize_rows(&*value))
+                    .collect()
+            }),
+            None => Vec::new(),
+        }
+    }
+
+    /// Read every spilled row back, and empty the spill.
+    pub(super) fn drain(&mut self) -> Vec<Vec<DataType>> {
+        let rows = self.cloned_records();
+        self.clear();
+        rows
+    }
+
+    pub(super) fn clear(&mut self) {
+        // dropping the database also removes its directory
+        self.disk = None;
+        self.rows = 0;
+        self.spilled = Default::default();
+        *self.buffer.get_mut() = None;
+    }
+
+    fn db(&self) -> &rocksdb::DB {
+        &self
+            .disk
+            .as_ref()
+            .expect("no partitions have been spilled")
+            .0
+    }
+
+    fn serialize_key(partition: usize, key: &[DataType]) -> Vec<u8> {
+        let mut k = vec![partition as u8];
+        bincode::serialize_into(&mut k, key).unwrap();
+        k
+    }
+
+    fn read_partition(&self, partition: usize) -> Partition {
+        let prefix = [partition as u8];
+        tokio::task::block_in_place(|| {
+            self.db()
+                .iterator(IteratorMode::From(&prefix, Direction::Forward))
+                .take_while(|(key, _)| key[0] == prefix[0])
+                .map(|(key, value)| {
+                    (
+                        bincode::deserialize(&key[1..]).unwrap(),
+                        deserialize_rows(&*value),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    fn get(&self, partition: usize, key: &[DataType]) -> Vec<Vec<DataType>> {
+        let raw = tokio::task::block_in_place(|| {
+            self.db().get(Self::serialize_key(partition, key)).unwrap()
+        });
+        raw.map(|raw| deserialize_rows(&*raw)).unwrap_or_default()
+    }
+
+    fn put(&mut self, partition: usize, key: Vec<DataType>, rs: Vec<Vec<DataType>>) {
+        let k = Self::serialize_key(partition, &key);
+        tokio::task::block_in_place(|| {
+            if rs.is_empty() {
+                self.db().delete(k)
+            } else {
+                self.db().put(k, serialize_rows(&rs))
+            }
+        })
+        .unwrap();
+
+        // keep the buffer in sync with what is on disk
+        if let Some((p, ref mut buffered)) = *self.buffer.get_mut() {
+            if p == partition {
+                if rs.is_empty() {
+                    buffered.remove(&key);
+                } else {
+                    buffered.insert(key, rs);
+                }
+            }
+        }
+    }
+}
+
+/// The key of a lookup, as it is hashed into partitions.
+pub(super) fn key_of(key: &KeyType) -> Vec<DataType> {
+    match *key {
+        KeyType::Single(k) => vec![k.clone()],
+        KeyType::Double(ref k) => vec![k.0.clone(), k.1.clone()],
+        KeyType::Tri(ref k) => vec![k.0.clone(), k.1.clone(), k.2.clone()],
+        KeyType::Quad(ref k) => vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()],
+        KeyType::Quin(ref k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+        ],
+        KeyType::Sex(ref k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+            k.5.clone(),
+        ],
+    }
+}
+
+pub(crate) fn serialize_rows(rs: &[Vec<DataType>]) -> Vec<u8> {
+    let rs: Vec<_> = rs
+        .iter()
+        .map(|r| SparseRow(Cow::Borrowed(&r[..])))
+        .collect();
+    bincode::serialize(&rs).unwrap()
+}
+
+pub(crate) fn deserialize_rows(raw: &[u8]) -> Vec<Vec<DataType>> {
+    let rs: Vec<SparseRow<'_>> = bincode::deserialize(raw).unwrap();
+    rs.into_iter().map(|r| r.0.into_owned()).collect()
+}
@@ -0,0 +1,39 @@
+use std::error::Error;
+use std::fmt;
+
+/// An error that prevents a query from being turned into MIR or from being optimized.
+///
+/// These are caused by the query rather than by a bug in Noria, so they are returned to the client
+/// that tried to add the query instead of taking down the controller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MirError {
+    /// The query is in a security universe, but no mapping from its columns to the universe's
+    /// tables was computed.
+    MissingTableMapping {
+        /// The name of the query.
+        query: String,
+    },
+    /// The query uses a construct that is not supported.
+    Unsupported(String),
+}
+
+impl fmt::Display for MirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MirError::MissingTableMapping { ref query } => write!(
+                f,
+                "query {} is in a security universe, but has no table mapping",
+                query
+            ),
+            MirError::Unsupported(ref what) => write!(f, "unsupported query: {}", what),
+        }
+    }
+}
+
+impl Error for MirError {}
+
+impl From<MirError> for String {
+    fn from(e: MirError) -> Self {
+        e.to_string()
+    }
+}
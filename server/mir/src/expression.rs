@@ -0,0 +1,139 @@
+use crate::Column;
+use common::DataType;
+use dataflow::ops::project::BuiltinFunction;
+use nom_sql::{ArithmeticBase, ArithmeticExpression, ArithmeticOperator, Operator};
+use std::fmt;
+
+/// An expression that a projection computes a column with, from the columns of its parent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    /// The value of a column of the parent.
+    Column(Column),
+    /// A constant.
+    Literal(DataType),
+    /// An arithmetic operation.
+    Op {
+        op: ArithmeticOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// A call to a built-in function.
+    Call(BuiltinFunction, Vec<Expression>),
+    /// A comparison, which is 1 if it holds and 0 if it does not.
+    Compare {
+        op: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// `CASE WHEN condition THEN value ... ELSE otherwise END`.
+    Case {
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Box<Expression>>,
+    },
+}
+
+impl Expression {
+    /// The columns of the parent that this expression reads, in the order they appear in it.
+    pub fn columns(&self) -> Vec<&Column> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a Column>) {
+        match *self {
+            Expression::Column(ref c) => columns.push(c),
+            Expression::Literal(_) => (),
+            Expression::Op {
+                ref left,
+                ref right,
+                ..
+            }
+            | Expression::Compare {
+                ref left,
+                ref right,
+                ..
+            } => {
+                left.collect_columns(columns);
+                right.collect_columns(columns);
+            }
+            Expression::Call(_, ref args) => {
+                for a in args {
+                    a.collect_columns(columns);
+                }
+            }
+            Expression::Case {
+                ref branches,
+                ref otherwise,
+            } => {
+                for (condition, value) in branches {
+                    condition.collect_columns(columns);
+                    value.collect_columns(columns);
+                }
+                if let Some(ref o) = *otherwise {
+                    o.collect_columns(columns);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a ArithmeticBase> for Expression {
+    fn from(base: &'a ArithmeticBase) -> Expression {
+        match *base {
+            ArithmeticBase::Column(ref c) => Expression::Column(Column::from(c)),
+            ArithmeticBase::Scalar(ref l) => Expression::Literal(DataType::from(l)),
+        }
+    }
+}
+
+impl<'a> From<&'a ArithmeticExpression> for Expression {
+    fn from(e: &'a ArithmeticExpression) -> Expression {
+        Expression::Op {
+            op: e.op.clone(),
+            left: Box::new(Expression::from(&e.left)),
+            right: Box::new(Expression::from(&e.right)),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Column(ref c) => write!(f, "{}", c.name),
+            Expression::Literal(ref l) => write!(f, "{}", l),
+            Expression::Op {
+                ref op,
+                ref left,
+                ref right,
+            } => write!(f, "({} {} {})", left, op, right),
+            Expression::Call(func, ref args) => write!(
+                f,
+                "{}({})",
+                func,
+                args.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::Compare {
+                ref op,
+                ref left,
+                ref right,
+            } => write!(f, "({} {} {})", left, op, right),
+            Expression::Case {
+                ref branches,
+                ref otherwise,
+            } => {
+                write!(f, "CASE")?;
+                for (condition, value) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, value)?;
+                }
+                if let Some(ref otherwise) = *otherwise {
+                    write!(f, " ELSE {}", otherwise)?;
+                }
+                write!(f, " END")
+            }
+        }
+    }
+}
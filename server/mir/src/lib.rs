@@ -8,6 +8,8 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 mod column;
+mod error;
+mod expression;
 pub mod node;
 mod optimize;
 pub mod query;
@@ -18,6 +20,8 @@ pub mod visualize;
 pub type MirNodeRef = Rc<RefCell<node::MirNode>>;
 
 pub use column::Column;
+pub use error::MirError;
+pub use expression::Expression;
 
 #[derive(Clone, Debug)]
 pub enum FlowNode {
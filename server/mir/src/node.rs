@@ -1,10 +1,11 @@
-use nom_sql::{ArithmeticExpression, ColumnSpecification, Literal, OrderType};
+use nom_sql::{ColumnSpecification, Literal, Operator, OrderType};
 use petgraph::graph::NodeIndex;
 use std::cell::RefCell;
 use std::fmt::{Debug, Display, Error, Formatter};
 use std::rc::Rc;
 
 use crate::column::Column;
+use crate::expression::Expression;
 use crate::{FlowNode, MirNodeRef};
 use common::DataType;
 use dataflow::ops;
@@ -12,14 +13,18 @@ use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
+use dataflow::ops::window::WindowFunction as WindowKind;
 use std::collections::HashMap;
 
 /// Helper enum to avoid having separate `make_aggregation_node` and `make_extremum_node` functions
 pub enum GroupedNodeType {
     Aggregation(ops::grouped::aggregate::Aggregation),
+    DistinctAggregation(ops::grouped::aggregate::Aggregation),
     Extremum(ops::grouped::extremum::Extremum),
     FilterAggregation(ops::grouped::filteraggregate::FilterAggregation),
     GroupConcat(String),
+    /// An aggregation by a function registered with `ops::grouped::custom::register`.
+    CustomAggregation(String),
 }
 
 pub struct MirNode {
@@ -72,6 +77,7 @@ impl MirNode {
             MirNodeType::Base {
                 ref column_specs,
                 ref keys,
+                ref indexes,
                 ..
             } => {
                 let new_column_specs: Vec<(ColumnSpecification, Option<usize>)> = column_specs
@@ -103,6 +109,7 @@ impl MirNode {
                         columns_added: added_cols.into_iter().cloned().collect(),
                         columns_removed: removed_cols.into_iter().cloned().collect(),
                     }),
+                    indexes: indexes.clone(),
                 };
                 MirNode::new(
                     &over_node.name,
@@ -184,15 +191,50 @@ impl MirNode {
         }
     }
 
-    pub fn add_column(&mut self, c: Column) {
+    // The position at which `add_column` inserts new columns.
+    fn new_column_position(&self) -> usize {
         match self.inner {
             // the aggregation column must always be the last column
-            MirNodeType::Aggregation { .. } | MirNodeType::FilterAggregation { .. } => {
-                let pos = self.columns.len() - 1;
-                self.columns.insert(pos, c.clone());
+            MirNodeType::Aggregation { .. }
+            | MirNodeType::DistinctAggregation { .. }
+            | MirNodeType::CustomAggregation { .. }
+            | MirNodeType::FilterAggregation { .. } => self.columns.len() - 1,
+            // so must the session start
+            MirNodeType::Session { .. } => self.columns.len() - 1,
+            // filters and anti-joins emit their (left) parent's columns, so new columns go
+            // wherever the parent puts them; this matters for filters on the output of an
+            // aggregation
+            MirNodeType::Filter { .. } | MirNodeType::AntiJoin { .. } => {
+                match self.ancestors.first() {
+                    Some(a) => a.borrow().new_column_position().min(self.columns.len()),
+                    None => self.columns.len(),
+                }
+            }
+            _ => self.columns.len(),
+        }
+    }
+
+    pub fn add_column(&mut self, c: Column) {
+        let pos = self.new_column_position();
+        if let MirNodeType::Filter { ref mut conditions } = self.inner {
+            // conditions refer to columns by index, so the ones after the new column move along
+            let mut shift = |i: &mut usize| {
+                if *i >= pos {
+                    *i += 1;
+                }
+            };
+            for (i, cond) in conditions.iter_mut() {
+                shift(i);
+                match *cond {
+                    FilterCondition::Comparison(_, ops::filter::Value::Column(ref mut j)) => {
+                        shift(j)
+                    }
+                    FilterCondition::Expression(ref mut e) => e.map_columns(&mut shift),
+                    _ => (),
+                }
             }
-            _ => self.columns.push(c.clone()),
         }
+        self.columns.insert(pos, c.clone());
         self.inner.add_column(c);
     }
 
@@ -331,6 +373,8 @@ impl MirNode {
         // + any parent columns referenced internally by the operator
         match self.inner {
             MirNodeType::Aggregation { ref on, .. }
+            | MirNodeType::DistinctAggregation { ref on, .. }
+            | MirNodeType::CustomAggregation { ref on, .. }
             | MirNodeType::Extremum { ref on, .. }
             | MirNodeType::GroupConcat { ref on, .. } => {
                 // need the "over" column
@@ -360,8 +404,16 @@ impl MirNode {
                     columns.push(on.clone());
                 }
             }
-            MirNodeType::Project { ref emit, .. } => {
-                for c in emit {
+            MirNodeType::Project {
+                ref emit,
+                ref expressions,
+                ..
+            } => {
+                // need the emitted columns, and the ones that computed columns are computed from
+                for c in emit
+                    .iter()
+                    .chain(expressions.iter().flat_map(|(_, e)| e.columns()))
+                {
                     if !columns.contains(&c) {
                         columns.push(c.clone());
                     }
@@ -403,11 +455,24 @@ pub enum MirNodeType {
         group_by: Vec<Column>,
         kind: AggregationKind,
     },
-    /// column specifications, keys (non-compound), tx flag, adapted base
+    /// over column, group_by columns; the parent holds the number of occurrences of each value
+    DistinctAggregation {
+        on: Column,
+        group_by: Vec<Column>,
+        kind: AggregationKind,
+    },
+    /// over column, group_by columns, name of the registered aggregate function
+    CustomAggregation {
+        on: Column,
+        group_by: Vec<Column>,
+        function: String,
+    },
+    /// column specifications, keys (non-compound), tx flag, adapted base, secondary indexes
     Base {
         column_specs: Vec<(ColumnSpecification, Option<usize>)>,
         keys: Vec<Column>,
         adapted_over: Option<BaseNodeAdaptation>,
+        indexes: Vec<Vec<Column>>,
     },
     /// over column, group_by columns
     Extremum {
@@ -446,16 +511,33 @@ pub enum MirNodeType {
         on_right: Vec<Column>,
         project: Vec<Column>,
     },
+    /// on left column, on right column, emit columns
+    OuterJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+    },
+    /// for each parent but the first: the parent before it that it is joined with, the join
+    /// column of that parent, and its own join column; emit columns
+    MultiJoin {
+        on: Vec<(usize, Column, Column)>,
+        project: Vec<Column>,
+    },
+    /// on left column, on right column; emits the left columns
+    AntiJoin {
+        on_left: Column,
+        on_right: Column,
+    },
     /// group columns
     // currently unused
     #[allow(dead_code)]
     Latest {
         group_by: Vec<Column>,
     },
-    /// emit columns
+    /// emit columns, and computed columns
     Project {
         emit: Vec<Column>,
-        arithmetic: Vec<(String, ArithmeticExpression)>,
+        expressions: Vec<(String, Expression)>,
         literals: Vec<(String, DataType)>,
     },
     /// emit columns
@@ -469,6 +551,19 @@ pub enum MirNodeType {
         k: usize,
         offset: usize,
     },
+    /// window function, over column, partition columns, order within partitions
+    Window {
+        kind: WindowKind,
+        on: Option<Column>,
+        partition_by: Vec<Column>,
+        order: Vec<(Column, OrderType)>,
+    },
+    /// timestamp column, longest gap within a session, partition columns
+    Session {
+        on: Column,
+        gap: i64,
+        partition_by: Vec<Column>,
+    },
     // Get the distinct element sorted by a specific column
     Distinct {
         group_by: Vec<Column>,
@@ -477,10 +572,13 @@ pub enum MirNodeType {
     Reuse {
         node: MirNodeRef,
     },
-    /// leaf (reader) node, keys
+    /// leaf (reader) node, keys, the comparison if lookups are by range over a single key, and
+    /// the order, offset, and number of rows returned for each key if the query has a LIMIT
     Leaf {
         node: MirNodeRef,
         keys: Vec<Column>,
+        range: Option<Operator>,
+        order: Option<(Vec<(Column, OrderType)>, usize, usize)>,
     },
     /// Rewrite node
     Rewrite {
@@ -499,6 +597,12 @@ impl MirNodeType {
         match *self {
             MirNodeType::Aggregation {
                 ref mut group_by, ..
+            }
+            | MirNodeType::DistinctAggregation {
+                ref mut group_by, ..
+            }
+            | MirNodeType::CustomAggregation {
+                ref mut group_by, ..
             } => {
                 group_by.push(c);
             }
@@ -519,6 +623,12 @@ impl MirNodeType {
             }
             | MirNodeType::LeftJoin {
                 ref mut project, ..
+            }
+            | MirNodeType::OuterJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::MultiJoin {
+                ref mut project, ..
             } => {
                 project.push(c);
             }
@@ -587,15 +697,41 @@ impl MirNodeType {
                     _ => false,
                 }
             }
+            MirNodeType::DistinctAggregation {
+                on: ref our_on,
+                group_by: ref our_group_by,
+                kind: ref our_kind,
+            } => match *other {
+                MirNodeType::DistinctAggregation {
+                    ref on,
+                    ref group_by,
+                    ref kind,
+                } => our_on == on && our_group_by == group_by && our_kind == kind,
+                _ => false,
+            },
+            MirNodeType::CustomAggregation {
+                on: ref our_on,
+                group_by: ref our_group_by,
+                function: ref our_function,
+            } => match *other {
+                MirNodeType::CustomAggregation {
+                    ref on,
+                    ref group_by,
+                    ref function,
+                } => our_on == on && our_group_by == group_by && our_function == function,
+                _ => false,
+            },
             MirNodeType::Base {
                 column_specs: ref our_column_specs,
                 keys: ref our_keys,
                 adapted_over: ref our_adapted_over,
+                indexes: ref our_indexes,
             } => {
                 match *other {
                     MirNodeType::Base {
                         ref column_specs,
                         ref keys,
+                        ref indexes,
                         ..
                     } => {
                         // if we are instructed to adapt an earlier base node, we cannot reuse
@@ -611,7 +747,9 @@ impl MirNodeType {
                         // note that as long as we are not adapting a previous base node,
                         // we do *not* need `adapted_over` to *match*, since current reuse
                         // does not depend on how base node was created from an earlier one
-                        our_column_specs == column_specs && our_keys == keys
+                        our_column_specs == column_specs
+                            && our_keys == keys
+                            && our_indexes == indexes
                     }
                     _ => false,
                 }
@@ -692,16 +830,54 @@ impl MirNodeType {
                     _ => false,
                 }
             }
+            MirNodeType::OuterJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+            } => {
+                match *other {
+                    MirNodeType::OuterJoin {
+                        ref on_left,
+                        ref on_right,
+                        ref project,
+                    } => {
+                        // TODO(malte): column order does not actually need to match, but this only
+                        // succeeds if it does.
+                        our_on_left == on_left && our_on_right == on_right && our_project == project
+                    }
+                    _ => false,
+                }
+            }
+            MirNodeType::MultiJoin {
+                on: ref our_on,
+                project: ref our_project,
+            } => match *other {
+                MirNodeType::MultiJoin {
+                    ref on,
+                    ref project,
+                } => our_on == on && our_project == project,
+                _ => false,
+            },
+            MirNodeType::AntiJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+            } => match *other {
+                MirNodeType::AntiJoin {
+                    ref on_left,
+                    ref on_right,
+                } => our_on_left == on_left && our_on_right == on_right,
+                _ => false,
+            },
             MirNodeType::Project {
                 emit: ref our_emit,
                 literals: ref our_literals,
-                arithmetic: ref our_arithmetic,
+                expressions: ref our_expressions,
             } => match *other {
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
-                    ref arithmetic,
-                } => our_emit == emit && our_literals == literals && our_arithmetic == arithmetic,
+                    ref expressions,
+                } => our_emit == emit && our_literals == literals && our_expressions == expressions,
                 _ => false,
             },
             MirNodeType::Distinct {
@@ -741,10 +917,49 @@ impl MirNodeType {
                 }
                 _ => false,
             },
+            MirNodeType::Window {
+                kind: our_kind,
+                on: ref our_on,
+                partition_by: ref our_partition_by,
+                order: ref our_order,
+            } => match *other {
+                MirNodeType::Window {
+                    kind,
+                    ref on,
+                    ref partition_by,
+                    ref order,
+                } => {
+                    kind == our_kind
+                        && on == our_on
+                        && partition_by == our_partition_by
+                        && order == our_order
+                }
+                _ => false,
+            },
+            MirNodeType::Session {
+                on: ref our_on,
+                gap: our_gap,
+                partition_by: ref our_partition_by,
+            } => match *other {
+                MirNodeType::Session {
+                    ref on,
+                    gap,
+                    ref partition_by,
+                } => on == our_on && gap == our_gap && partition_by == our_partition_by,
+                _ => false,
+            },
             MirNodeType::Leaf {
-                keys: ref our_keys, ..
+                keys: ref our_keys,
+                range: ref our_range,
+                order: ref our_order,
+                ..
             } => match *other {
-                MirNodeType::Leaf { ref keys, .. } => keys == our_keys,
+                MirNodeType::Leaf {
+                    ref keys,
+                    ref range,
+                    ref order,
+                    ..
+                } => keys == our_keys && range == our_range && order == our_order,
                 _ => false,
             },
             MirNodeType::Union { emit: ref our_emit } => match *other {
@@ -815,6 +1030,34 @@ impl Debug for MirNodeType {
                     .join(", ");
                 write!(f, "{} γ[{}]", op_string, group_cols)
             }
+            MirNodeType::DistinctAggregation {
+                ref on,
+                ref group_by,
+                ref kind,
+            } => {
+                let op_string = match *kind {
+                    AggregationKind::COUNT => format!("|δ({})|", on.name.as_str()),
+                    AggregationKind::SUM => format!("𝛴δ({})", on.name.as_str()),
+                };
+                let group_cols = group_by
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{} γ[{}]", op_string, group_cols)
+            }
+            MirNodeType::CustomAggregation {
+                ref on,
+                ref group_by,
+                ref function,
+            } => {
+                let group_cols = group_by
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{}({}) γ[{}]", function, on.name.as_str(), group_cols)
+            }
             MirNodeType::Base {
                 ref column_specs,
                 ref keys,
@@ -874,6 +1117,9 @@ impl Debug for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Expression(ref e) => {
+                                Some(escape(&format!("{}", e)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -925,13 +1171,20 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
-            MirNodeType::Leaf { ref keys, .. } => {
+            MirNodeType::Leaf {
+                ref keys,
+                ref range,
+                ..
+            } => {
                 let key_cols = keys
                     .iter()
                     .map(|k| k.name.clone())
                     .collect::<Vec<_>>()
                     .join(", ");
-                write!(f, "Leaf [⚷: {}]", key_cols)
+                match *range {
+                    Some(ref op) => write!(f, "Leaf [⚷: {} {} ?]", key_cols, op),
+                    None => write!(f, "Leaf [⚷: {}]", key_cols),
+                }
             }
             MirNodeType::LeftJoin {
                 ref on_left,
@@ -955,6 +1208,52 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
+            MirNodeType::OuterJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "⟗ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
+            MirNodeType::MultiJoin {
+                ref on,
+                ref project,
+            } => {
+                let jc = on
+                    .iter()
+                    .map(|(_, l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "⋈ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
+            MirNodeType::AntiJoin {
+                ref on_left,
+                ref on_right,
+            } => write!(f, "▷ [{}:{}]", on_left.name, on_right.name),
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -966,7 +1265,7 @@ impl Debug for MirNodeType {
             MirNodeType::Project {
                 ref emit,
                 ref literals,
-                ref arithmetic,
+                ref expressions,
             } => write!(
                 f,
                 "π [{}{}{}]",
@@ -974,12 +1273,12 @@ impl Debug for MirNodeType {
                     .map(|c| c.name.as_str())
                     .collect::<Vec<_>>()
                     .join(", "),
-                if arithmetic.is_empty() {
+                if expressions.is_empty() {
                     "".into()
                 } else {
                     format!(
                         ", {}",
-                        arithmetic
+                        expressions
                             .iter()
                             .map(|&(ref n, ref e)| format!("{}: {}", n, e))
                             .collect::<Vec<_>>()
@@ -1016,6 +1315,40 @@ impl Debug for MirNodeType {
             MirNodeType::TopK {
                 ref order, ref k, ..
             } => write!(f, "TopK [k: {}, {:?}]", k, order),
+            MirNodeType::Window {
+                kind,
+                ref on,
+                ref partition_by,
+                ..
+            } => {
+                let function = match kind {
+                    WindowKind::RowNumber => "ROW_NUMBER".to_owned(),
+                    WindowKind::Rank => "RANK".to_owned(),
+                    WindowKind::Sum => format!("𝛴({})", on.as_ref().unwrap().name),
+                };
+                let partition_cols = partition_by
+                    .iter()
+                    .map(|c| c.name.clone())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "Window [{}, γ: {}]", function, partition_cols)
+            }
+            MirNodeType::Session {
+                ref on,
+                gap,
+                ref partition_by,
+            } => {
+                let partition_cols = partition_by
+                    .iter()
+                    .map(|c| c.name.clone())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "Session [{}, gap: {}, γ: {}]",
+                    on.name, gap, partition_cols
+                )
+            }
             MirNodeType::Union { ref emit } => {
                 let cols = emit
                     .iter()
@@ -1,7 +1,7 @@
 use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
 use crate::MirNodeRef;
-use dataflow::ops::filter::FilterCondition;
+use dataflow::ops::filter::{FilterCondition, Value};
 use dataflow::ops::grouped::aggregate::Aggregation;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation;
 
@@ -13,9 +13,174 @@ use std::collections::HashMap;
 // can add them to any other internal representations.
 pub fn optimize(mut q: &mut MirQuery) -> Vec<MirNodeRef> {
     //remove_extraneous_projections(&mut q);
+    push_filters_below_joins(&mut q);
+    merge_adjacent_filters(&mut q);
     find_and_merge_filter_aggregates(&mut q)
 }
 
+/// Whether `n` is part of a security policy chain. Rewrites must leave these nodes where they are,
+/// so that the queries of a universe only ever see the rows the universe's policies let through.
+fn is_policy_node(n: &MirNode) -> bool {
+    n.name().starts_with("sp_")
+}
+
+/// Replace `old` with `new` among the ancestors of `n`, keeping its position, which matters for
+/// joins.
+fn replace_ancestor(n: &MirNodeRef, old: &MirNodeRef, new: &MirNodeRef) {
+    let old_name = old.borrow().versioned_name();
+    for a in n.borrow_mut().ancestors.iter_mut() {
+        if a.borrow().versioned_name() == old_name {
+            *a = new.clone();
+        }
+    }
+}
+
+fn replace_child(n: &MirNodeRef, old: &MirNodeRef, new: &MirNodeRef) {
+    let old_name = old.borrow().versioned_name();
+    for c in n.borrow_mut().children.iter_mut() {
+        if c.borrow().versioned_name() == old_name {
+            *c = new.clone();
+        }
+    }
+}
+
+/// If `f` is a filter directly below a join whose conditions only compare the columns of one side
+/// of the join with constants, returns that side along with the conditions rewritten to refer to
+/// its columns.
+fn filter_pushdown_target(f: &MirNodeRef) -> Option<(MirNodeRef, Vec<(usize, FilterCondition)>)> {
+    let f = f.borrow();
+    let conditions = match f.inner {
+        MirNodeType::Filter { ref conditions } => conditions,
+        _ => return None,
+    };
+    if f.ancestors.len() != 1 || f.flow_node.is_some() || is_policy_node(&f) {
+        return None;
+    }
+
+    let join = f.ancestors[0].borrow();
+    if join.children.len() != 1
+        || join.flow_node.is_some()
+        || is_policy_node(&join)
+        || join.ancestors.len() < 2
+        || join.ancestors.iter().enumerate().any(|(i, a)| {
+            join.ancestors[..i]
+                .iter()
+                .any(|b| b.borrow().versioned_name() == a.borrow().versioned_name())
+        })
+    {
+        return None;
+    }
+    let sides = match join.inner {
+        MirNodeType::Join { .. } | MirNodeType::MultiJoin { .. } => &join.ancestors[..],
+        // rows of the right side of a left join that don't match are padded with NULLs rather
+        // than dropped, so only conditions on the left side can be applied before the join
+        MirNodeType::LeftJoin { .. } => &join.ancestors[..1],
+        _ => return None,
+    };
+
+    sides.iter().find_map(|side| {
+        let side_columns = side.borrow().columns().to_vec();
+        conditions
+            .iter()
+            .map(|&(i, ref cond)| {
+                match *cond {
+                    FilterCondition::Comparison(_, Value::Constant(_)) | FilterCondition::In(_) => {
+                    }
+                    // conditions that read other columns may read both sides
+                    _ => return None,
+                }
+                let pos = side_columns.iter().position(|c| *c == join.columns[i])?;
+                Some((pos, cond.clone()))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|conditions| (side.clone(), conditions))
+    })
+}
+
+/// Move filters that only look at one side of a join to above that side, so that the join only
+/// ever sees, and holds state for, the rows that make it through the filter.
+fn push_filters_below_joins(q: &mut MirQuery) {
+    loop {
+        let next = q.topo_nodes().into_iter().find_map(|n| {
+            let (side, conditions) = filter_pushdown_target(&n)?;
+            Some((n, side, conditions))
+        });
+        let (f, side, conditions) = match next {
+            Some(next) => next,
+            None => break,
+        };
+
+        // take the filter out from below the join...
+        let join = f.borrow().ancestors[0].clone();
+        let children = f.borrow().children.clone();
+        for c in &children {
+            replace_ancestor(c, &f, &join);
+        }
+        join.borrow_mut().children = children;
+        if q.leaf.borrow().versioned_name() == f.borrow().versioned_name() {
+            q.leaf = join.clone();
+        }
+
+        // ...and put it between the join and the side it filters
+        replace_child(&side, &join, &f);
+        replace_ancestor(&join, &side, &f);
+        let columns = side.borrow().columns().to_vec();
+        let mut f = f.borrow_mut();
+        f.columns = columns;
+        f.inner = MirNodeType::Filter { conditions };
+        f.ancestors = vec![side];
+        f.children = vec![join];
+    }
+}
+
+/// Fold chains of filters into a single filter that checks all of their conditions.
+fn merge_adjacent_filters(q: &mut MirQuery) {
+    let mergeable = |n: &MirNode| {
+        match n.inner {
+            MirNodeType::Filter { .. } => {}
+            _ => return false,
+        }
+        n.flow_node.is_none() && !is_policy_node(n)
+    };
+
+    loop {
+        let next = q.topo_nodes().into_iter().find(|n| {
+            let n = n.borrow();
+            if n.ancestors.len() != 1 || !mergeable(&n) {
+                return false;
+            }
+            let parent = n.ancestors[0].borrow();
+            parent.children.len() == 1 && mergeable(&parent)
+        });
+        let f = match next {
+            Some(f) => f,
+            None => break,
+        };
+
+        // a filter has the same columns as its parent, so the conditions apply unchanged
+        let parent = f.borrow().ancestors[0].clone();
+        let conditions = match f.borrow().inner {
+            MirNodeType::Filter { ref conditions } => conditions.clone(),
+            _ => unreachable!(),
+        };
+        if let MirNodeType::Filter {
+            conditions: ref mut merged,
+        } = parent.borrow_mut().inner
+        {
+            merged.extend(conditions);
+        }
+
+        let children = f.borrow().children.clone();
+        for c in &children {
+            replace_ancestor(c, &f, &parent);
+        }
+        parent.borrow_mut().children = children;
+        if q.leaf.borrow().versioned_name() == f.borrow().versioned_name() {
+            q.leaf = parent;
+        }
+    }
+}
+
 pub fn optimize_post_reuse(_q: &mut MirQuery) {
     // find_and_merge_filter_chains(q);
 }
@@ -85,6 +250,14 @@ fn find_and_merge_filter_aggregates(q: &mut MirQuery) -> Vec<MirNodeRef> {
                     if child.ancestors.len() != 1 {
                         continue;
                     }
+                    // merging moves the conditions onto the aggregation's input columns, which
+                    // conditions on expressions can't follow
+                    if conditions
+                        .iter()
+                        .any(|(_, c)| matches!(*c, FilterCondition::Expression(_)))
+                    {
+                        continue;
+                    }
                     candidate = true;
 
                     // But wait -- need to check if the filter is on the aggregation result
@@ -355,3 +528,118 @@ fn to_conditions(chained_filters: &[MirNodeRef]) -> Vec<(usize, FilterCondition)
 fn remove_extraneous_projections(_q: &mut MirQuery) {
     unimplemented!()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::column::Column;
+    use nom_sql::{self, ColumnSpecification, Operator, SqlType};
+
+    #[test]
+    fn pushes_filters_below_joins() {
+        let base = |name: &str, cols: &[&str]| {
+            MirNode::new(
+                name,
+                0,
+                cols.iter().map(|&c| Column::from(c)).collect(),
+                MirNodeType::Base {
+                    column_specs: cols
+                        .iter()
+                        .map(|&c| {
+                            let spec =
+                                ColumnSpecification::new(nom_sql::Column::from(c), SqlType::Text);
+                            (spec, None)
+                        })
+                        .collect(),
+                    keys: vec![Column::from(cols[0])],
+                    adapted_over: None,
+                    indexes: vec![],
+                },
+                vec![],
+                vec![],
+            )
+        };
+        let filter = |name: &str, i: usize, parent: &MirNodeRef| {
+            let columns = parent.borrow().columns().to_vec();
+            MirNode::new(
+                name,
+                0,
+                columns,
+                MirNodeType::Filter {
+                    conditions: vec![(
+                        i,
+                        FilterCondition::Comparison(Operator::Equal, Value::Constant(1.into())),
+                    )],
+                },
+                vec![parent.clone()],
+                vec![],
+            )
+        };
+        let t = base("t", &["t.a", "t.b"]);
+        let u = base("u", &["u.a", "u.c"]);
+        let join_cols = vec![
+            Column::from("t.a"),
+            Column::from("t.b"),
+            Column::from("u.c"),
+        ];
+        let join = MirNode::new(
+            "join",
+            0,
+            join_cols.clone(),
+            MirNodeType::Join {
+                on_left: vec![Column::from("t.a")],
+                on_right: vec![Column::from("u.a")],
+                project: join_cols.clone(),
+            },
+            vec![t.clone(), u.clone()],
+            vec![],
+        );
+        // WHERE t.b = 1 AND u.c = 1 AND t.a = 1
+        let f1 = filter("f1", 1, &join);
+        let f2 = filter("f2", 2, &f1);
+        let f3 = filter("f3", 0, &f2);
+        let leaf = MirNode::new(
+            "leaf",
+            0,
+            join_cols.clone(),
+            MirNodeType::Project {
+                emit: join_cols,
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![f3.clone()],
+            vec![],
+        );
+
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![t.clone(), u.clone()],
+            leaf: leaf.clone(),
+        };
+        push_filters_below_joins(&mut q);
+        merge_adjacent_filters(&mut q);
+
+        // the conditions on t end up in one filter above t, and the one on u above u
+        let ancestors = join.borrow().ancestors().to_vec();
+        assert_eq!(ancestors[0].borrow().name(), "f1");
+        assert_eq!(ancestors[1].borrow().name(), "f2");
+        assert_eq!(t.borrow().children()[0].borrow().name(), "f1");
+        assert_eq!(u.borrow().children()[0].borrow().name(), "f2");
+        match f1.borrow().inner {
+            MirNodeType::Filter { ref conditions } => {
+                assert_eq!(
+                    conditions.iter().map(|c| c.0).collect::<Vec<_>>(),
+                    vec![1, 0]
+                )
+            }
+            _ => unreachable!(),
+        }
+        match f2.borrow().inner {
+            MirNodeType::Filter { ref conditions } => assert_eq!(conditions[0].0, 1),
+            _ => unreachable!(),
+        }
+        assert_eq!(join.borrow().children()[0].borrow().name(), "leaf");
+        assert_eq!(leaf.borrow().ancestors()[0].borrow().name(), "join");
+        assert_eq!(q.topo_nodes().len(), 6);
+    }
+}
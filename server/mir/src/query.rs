@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Error, Formatter};
 
-use crate::MirNodeRef;
+use crate::{MirError, MirNodeRef};
 use petgraph::graph::NodeIndex;
 
 /// Represents the result of a query incorporation, specifying query name (auto-generated or
@@ -32,7 +32,6 @@ impl MirQuery {
         }
     }
 
-    #[cfg(test)]
     pub fn topo_nodes(&self) -> Vec<MirNodeRef> {
         use std::collections::VecDeque;
 
@@ -71,14 +70,17 @@ impl MirQuery {
     // merging certain nodes together, and return it.
     // Also return a list of any new nodes created so that the
     // caller can add them to any other internal representations.
+    // Fails if the query cannot be rewritten, for example because it is in a security universe
+    // that has no table mapping.
     pub fn optimize(
         mut self,
         table_mapping: Option<&HashMap<(String, Option<String>), String>>,
         sec: bool,
-    ) -> (MirQuery, Vec<MirNodeRef>) {
-        super::rewrite::pull_required_base_columns(&mut self, table_mapping, sec);
+    ) -> Result<(MirQuery, Vec<MirNodeRef>), MirError> {
+        super::rewrite::pull_required_base_columns(&mut self, table_mapping, sec)?;
+        super::rewrite::prune_dead_columns(&mut self);
         let nodes_added = super::optimize::optimize(&mut self);
-        (self, nodes_added)
+        Ok((self, nodes_added))
     }
 
     pub fn optimize_post_reuse(mut self) -> MirQuery {
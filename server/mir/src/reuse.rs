@@ -2,6 +2,9 @@ use crate::column::Column;
 use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
 use crate::MirNodeRef;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 pub fn rewind_until_columns_found(leaf: MirNodeRef, columns: &[Column]) -> Option<MirNodeRef> {
     let mut cur = leaf;
@@ -32,15 +35,80 @@ pub fn rewind_until_columns_found(leaf: MirNodeRef, columns: &[Column]) -> Optio
     }
 }
 
+/// Make a node that stands in for `old` in a new query.
+fn reuse_node(old: &MirNodeRef) -> MirNodeRef {
+    let o = old.borrow();
+    // Note that we manually build the `MirNode` here, rather than calling `MirNode::new()`
+    // because `new()` automatically registers the node as a child with its ancestors. We
+    // don't want to do this here because we later re-write the ancestors' child that this
+    // node replaces to point to this node.
+    Rc::new(RefCell::new(MirNode {
+        name: o.name.clone(),
+        from_version: o.from_version,
+        columns: o.columns.clone(),
+        inner: MirNodeType::Reuse { node: old.clone() },
+        ancestors: o.ancestors.clone(),
+        children: o.children.clone(),
+        flow_node: None,
+    }))
+}
+
+/// Whether the join `new` can read from the state of the join `old` and project out the columns
+/// it needs, even though the two project different columns.
+fn can_share_join(old: &MirNode, new: &MirNode) -> bool {
+    let same_join = match (&old.inner, &new.inner) {
+        (
+            MirNodeType::Join {
+                on_left: ref old_left,
+                on_right: ref old_right,
+                ..
+            },
+            MirNodeType::Join {
+                ref on_left,
+                ref on_right,
+                ..
+            },
+        )
+        | (
+            MirNodeType::LeftJoin {
+                on_left: ref old_left,
+                on_right: ref old_right,
+                ..
+            },
+            MirNodeType::LeftJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            },
+        ) => old_left == on_left && old_right == on_right,
+        _ => false,
+    };
+    same_join && new.columns.iter().all(|c| old.columns.contains(c))
+}
+
+/// Whether `new` reads from the nodes that `old` reads from, in the same order, or `None` if
+/// some of its ancestors have not been matched against the old query yet.
+fn same_inputs(old: &MirNode, new: &MirNode, reuse: &HashMap<String, MirNodeRef>) -> Option<bool> {
+    if old.ancestors.len() != new.ancestors.len() {
+        return Some(false);
+    }
+    for (o, n) in old.ancestors.iter().zip(new.ancestors.iter()) {
+        let n = reuse.get(&n.borrow().versioned_name())?;
+        match n.borrow().inner {
+            MirNodeType::Reuse { ref node } if Rc::ptr_eq(node, o) => {}
+            _ => return Some(false),
+        }
+    }
+    Some(true)
+}
+
 #[allow(clippy::cognitive_complexity)]
 pub fn merge_mir_for_queries(
     log: &slog::Logger,
     new_query: &MirQuery,
     old_query: &MirQuery,
 ) -> (MirQuery, usize) {
-    use std::cell::RefCell;
-    use std::collections::{HashMap, HashSet, VecDeque};
-    use std::rc::Rc;
+    use std::collections::{HashSet, VecDeque};
 
     let mut trace_nodes = VecDeque::new();
     for old_base in &old_query.roots {
@@ -62,6 +130,9 @@ pub fn merge_mir_for_queries(
     let mut visited = HashSet::new();
     let mut reuse = HashMap::new();
     let mut reused = HashSet::new();
+    // joins shared with the old query, and the projections that narrow them down to the columns
+    // the new query's joins had
+    let mut reprojected = HashMap::new();
     while let Some((old, new)) = trace_nodes.pop_front() {
         let new_id = new.borrow().versioned_name();
         // reuseable node found, keep going
@@ -72,26 +143,7 @@ pub fn merge_mir_for_queries(
             new
         );
         assert!(!reuse.contains_key(&new_id));
-
-        let reuse_node;
-        {
-            let o_ref = old.clone();
-            let o = old.borrow();
-            // Note that we manually build the `MirNode` here, rather than calling `MirNode::new()`
-            // because `new()` automatically registers the node as a child with its ancestors. We
-            // don't want to do this here because we later re-write the ancestors' child that this
-            // node replaces to point to this node.
-            reuse_node = Rc::new(RefCell::new(MirNode {
-                name: o.name.clone(),
-                from_version: o.from_version,
-                columns: o.columns.clone(),
-                inner: MirNodeType::Reuse { node: o_ref },
-                ancestors: o.ancestors.clone(),
-                children: o.children.clone(),
-                flow_node: None,
-            }));
-        }
-        reuse.insert(new_id.clone(), reuse_node);
+        reuse.insert(new_id.clone(), reuse_node(&old));
 
         // look for matching old node children for each of the new node's children.
         // If any are found, we can continue exploring that path, as the new query contains one
@@ -111,24 +163,68 @@ pub fn merge_mir_for_queries(
             visited.insert(new_child_id.clone());
 
             let mut found = false;
+            let mut unresolved = false;
             for old_child in old.borrow().children() {
-                if old_child.borrow().can_reuse_as(&*new_child.borrow()) {
-                    if reused.contains(&old_child.borrow().versioned_name()) {
+                let old_child_id = old_child.borrow().versioned_name();
+                if reused.contains(&old_child_id) {
+                    continue;
+                }
+                let reusable = old_child.borrow().can_reuse_as(&*new_child.borrow());
+                if !reusable && !can_share_join(&*old_child.borrow(), &*new_child.borrow()) {
+                    continue;
+                }
+                // a node with several ancestors is only the same as the old one if it reads from
+                // the same nodes, which we cannot tell until we have reached all of them
+                match same_inputs(&*old_child.borrow(), &*new_child.borrow(), &reuse) {
+                    None => {
+                        unresolved = true;
                         continue;
                     }
+                    Some(false) => continue,
+                    Some(true) => {}
+                }
 
+                found = true;
+                reused.insert(old_child_id);
+                if reusable {
                     trace!(
                         log,
                         "add child {:?} to queue as it has a match",
                         new_child_id
                     );
                     trace_nodes.push_back((old_child.clone(), new_child.clone()));
-                    found = true;
-                    reused.insert(old_child.borrow().versioned_name());
-                    break;
+                } else {
+                    // the old join keeps more columns than the new one needs, so we read from
+                    // its state and project away the rest. we do not look further downstream,
+                    // since everything after the join sees different columns.
+                    trace!(log, "sharing join {:?} with {:?}", old_child, new_child_id);
+                    let nc = new_child.borrow();
+                    let project = Rc::new(RefCell::new(MirNode {
+                        name: format!("{}_shared_prj", nc.name),
+                        from_version: nc.from_version,
+                        columns: nc.columns.clone(),
+                        inner: MirNodeType::Project {
+                            emit: nc.columns.clone(),
+                            expressions: vec![],
+                            literals: vec![],
+                        },
+                        ancestors: vec![],
+                        children: vec![],
+                        flow_node: None,
+                    }));
+                    reuse.insert(new_child_id.clone(), reuse_node(old_child));
+                    reprojected.insert(new_child_id.clone(), project);
                 }
+                break;
             }
-            if !found {
+            if !found && unresolved {
+                trace!(
+                    log,
+                    "not all ancestors of {:?} matched yet, revisiting later",
+                    new_child_id
+                );
+                visited.remove(&new_child_id);
+            } else if !found {
                 // if no child of this node is reusable, we give up on this path
                 trace!(
                     log,
@@ -159,9 +255,12 @@ pub fn merge_mir_for_queries(
             .borrow()
             .ancestors()
             .iter()
-            .map(|a| match reuse.get(&a.borrow().versioned_name()) {
-                None => a,
-                Some(ref reused) => reused,
+            .map(|a| {
+                let id = a.borrow().versioned_name();
+                match reprojected.get(&id).or_else(|| reuse.get(&id)) {
+                    None => a,
+                    Some(ref reused) => reused,
+                }
             })
             .cloned()
             .collect();
@@ -181,6 +280,15 @@ pub fn merge_mir_for_queries(
             None => n.clone(),
             Some(reused) => reused.clone(),
         };
+        // a shared join feeds the projection that stands in for the new query's join
+        let last = match reprojected.get(&n.borrow().versioned_name()) {
+            None => real_n.clone(),
+            Some(project) => {
+                real_n.borrow_mut().children = vec![project.clone()];
+                project.borrow_mut().ancestors = vec![real_n.clone()];
+                project.clone()
+            }
+        };
 
         if ancestors.is_empty() {
             rewritten_roots.push(real_n.clone());
@@ -188,11 +296,11 @@ pub fn merge_mir_for_queries(
         if children.is_empty() {
             assert!(!found_leaf); // should only find one leaf!
             found_leaf = true;
-            rewritten_leaf = real_n.clone();
+            rewritten_leaf = last.clone();
         }
 
         real_n.borrow_mut().ancestors = ancestors;
-        real_n.borrow_mut().children = children;
+        last.borrow_mut().children = children;
 
         for c in original_children {
             let cid = c.borrow().versioned_name();
@@ -241,6 +349,7 @@ mod tests {
                 column_specs: vec![cspec("aa"), cspec("ab")],
                 keys: vec![Column::from("aa")],
                 adapted_over: None,
+                indexes: vec![],
             },
             vec![],
             vec![],
@@ -253,6 +362,7 @@ mod tests {
                 column_specs: vec![cspec("ba"), cspec("bb")],
                 keys: vec![Column::from("ba")],
                 adapted_over: None,
+                indexes: vec![],
             },
             vec![],
             vec![],
@@ -276,6 +386,8 @@ mod tests {
             MirNodeType::Leaf {
                 node: c.clone(),
                 keys: vec![Column::from("ba")],
+                range: None,
+                order: None,
             },
             vec![],
             vec![],
@@ -323,7 +435,7 @@ mod tests {
             vec![Column::from("aa")],
             MirNodeType::Project {
                 emit: vec![Column::from("aa")],
-                arithmetic: vec![],
+                expressions: vec![],
                 literals: vec![],
             },
             vec![c.clone()],
@@ -352,4 +464,116 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn merge_mir_shares_joins() {
+        use crate::node::{MirNode, MirNodeType};
+        use crate::query::MirQuery;
+
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        // an old query that joins a and b and keeps all of their columns
+        let (a, b, _, _) = make_nodes();
+        let all = vec![
+            Column::from("aa"),
+            Column::from("ab"),
+            Column::from("ba"),
+            Column::from("bb"),
+        ];
+        let c = MirNode::new(
+            "c",
+            0,
+            all.clone(),
+            MirNodeType::Join {
+                on_left: vec![Column::from("ab")],
+                on_right: vec![Column::from("bb")],
+                project: all.clone(),
+            },
+            vec![a.clone(), b.clone()],
+            vec![],
+        );
+        let d = MirNode::new(
+            "d",
+            0,
+            all,
+            MirNodeType::Leaf {
+                node: c.clone(),
+                keys: vec![Column::from("aa")],
+                range: None,
+                order: None,
+            },
+            vec![c],
+            vec![],
+        );
+        let mq1 = MirQuery {
+            name: String::from("q1"),
+            roots: vec![a, b],
+            leaf: d,
+        };
+
+        // a new query with the same join, but fewer columns
+        let (a, b, c, d) = make_nodes();
+        a.borrow_mut().add_child(c.clone());
+        b.borrow_mut().add_child(c.clone());
+        c.borrow_mut().add_ancestor(a.clone());
+        c.borrow_mut().add_ancestor(b.clone());
+        c.borrow_mut().add_child(d.clone());
+        d.borrow_mut().add_ancestor(c);
+        let mq2 = MirQuery {
+            name: String::from("q2"),
+            roots: vec![a, b],
+            leaf: d,
+        };
+
+        let (merged, _) = merge_mir_for_queries(&log, &mq2, &mq1);
+        let nodes = merged.topo_nodes();
+        assert_eq!(nodes.len(), 5);
+        for n in nodes {
+            let n = n.borrow();
+            match n.name() {
+                "a" | "b" | "c" => assert!(n.is_reused()),
+                "c_shared_prj" => {
+                    assert!(!n.is_reused());
+                    assert_eq!(n.ancestors()[0].borrow().name(), "c");
+                    assert_eq!(n.columns(), &[Column::from("aa"), Column::from("ba")][..]);
+                }
+                "d" => {
+                    assert!(!n.is_reused());
+                    assert_eq!(n.ancestors()[0].borrow().name(), "c_shared_prj");
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // a join over a filtered b reads from different nodes, and so cannot be shared
+        let (a, b, c, d) = make_nodes();
+        let f = MirNode::new(
+            "f",
+            0,
+            b.borrow().columns().to_vec(),
+            MirNodeType::Filter { conditions: vec![] },
+            vec![b.clone()],
+            vec![],
+        );
+        a.borrow_mut().add_child(c.clone());
+        f.borrow_mut().add_child(c.clone());
+        c.borrow_mut().add_ancestor(a.clone());
+        c.borrow_mut().add_ancestor(f);
+        c.borrow_mut().add_child(d.clone());
+        d.borrow_mut().add_ancestor(c);
+        let mq3 = MirQuery {
+            name: String::from("q3"),
+            roots: vec![a, b],
+            leaf: d,
+        };
+        let (merged, _) = merge_mir_for_queries(&log, &mq3, &mq1);
+        for n in merged.topo_nodes() {
+            let n = n.borrow();
+            match n.name() {
+                "a" | "b" => assert!(n.is_reused()),
+                "c" | "d" | "f" => assert!(!n.is_reused()),
+                _ => unreachable!(),
+            }
+        }
+    }
 }
@@ -1,11 +1,27 @@
 use crate::column::Column;
+use crate::error::MirError;
+use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
 use crate::MirNodeRef;
 use std::collections::HashMap;
 
+/// Whether `n` replaces the values of columns called like `column` with a literal, as the
+/// projections that mask columns in security universes do.
+fn masks_column(n: &MirNodeRef, column: &Column) -> bool {
+    match n.borrow().inner {
+        MirNodeType::Project { ref literals, .. } => {
+            literals.iter().any(|&(ref name, _)| *name == column.name)
+        }
+        _ => false,
+    }
+}
+
 fn has_column(n: &MirNodeRef, column: &Column) -> bool {
     if n.borrow().columns().contains(column) {
         return true;
+    } else if masks_column(n, column) {
+        // the original values must not be pulled through the mask
+        return false;
     } else {
         for a in n.borrow().ancestors() {
             if has_column(a, column) {
@@ -69,15 +85,14 @@ pub(super) fn pull_required_base_columns(
     q: &mut MirQuery,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
     sec: bool,
-) {
+) -> Result<(), MirError> {
     let mut queue = Vec::new();
     queue.push(q.leaf.clone());
 
-    if sec {
-        match table_mapping {
-            Some(_) => (),
-            None => panic!("no table mapping computed, but in secure universe."),
-        }
+    if sec && table_mapping.is_none() {
+        return Err(MirError::MissingTableMapping {
+            query: q.name.clone(),
+        });
     }
 
     while !queue.is_empty() {
@@ -148,6 +163,116 @@ pub(super) fn pull_required_base_columns(
             }
         }
     }
+    Ok(())
+}
+
+/// The columns of `mn` that its children read, or `None` if a child may need all of them.
+fn needed_by_children(mn: &MirNodeRef) -> Option<Vec<Column>> {
+    let mut needed = Vec::new();
+    for child in mn.borrow().children() {
+        let child = child.borrow();
+        match child.inner {
+            MirNodeType::Project { .. }
+            | MirNodeType::Aggregation { .. }
+            | MirNodeType::Extremum { .. }
+            | MirNodeType::GroupConcat { .. } => needed.extend(child.referenced_columns()),
+            MirNodeType::Join {
+                ref on_left,
+                ref on_right,
+                ..
+            }
+            | MirNodeType::LeftJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            }
+            | MirNodeType::OuterJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                needed.extend(child.referenced_columns());
+                needed.extend(on_left.iter().chain(on_right).cloned());
+            }
+            MirNodeType::MultiJoin { ref on, .. } => {
+                needed.extend(child.referenced_columns());
+                needed.extend(on.iter().flat_map(|(_, l, r)| vec![l.clone(), r.clone()]));
+            }
+            // every other operator passes on, or otherwise depends on, all of its parent's columns
+            _ => return None,
+        }
+    }
+    Some(needed)
+}
+
+/// Removes the columns that the projection or join `mn` emits, but that nothing needs.
+fn prune_node(mn: &MirNodeRef, needed: &[Column]) {
+    let mut n = mn.borrow_mut();
+    let MirNode {
+        ref mut columns,
+        ref mut inner,
+        ..
+    } = *n;
+    let (emitted, keep) = match *inner {
+        // computed columns come after the emitted ones, and are always kept
+        MirNodeType::Project { ref mut emit, .. } if emit.len() <= columns.len() => {
+            (emit, Vec::new())
+        }
+        // the left join column is always part of a join's output
+        MirNodeType::Join {
+            ref mut project,
+            ref on_left,
+            ..
+        }
+        | MirNodeType::LeftJoin {
+            ref mut project,
+            ref on_left,
+            ..
+        }
+        | MirNodeType::OuterJoin {
+            ref mut project,
+            ref on_left,
+            ..
+        } if project.len() == columns.len() => (project, on_left.clone()),
+        // as are the join columns of the earlier parents of a multi-way join
+        MirNodeType::MultiJoin {
+            ref mut project,
+            ref on,
+        } if project.len() == columns.len() => {
+            (project, on.iter().map(|(_, l, _)| l.clone()).collect())
+        }
+        _ => return,
+    };
+
+    let dead: Vec<usize> = (0..emitted.len())
+        .filter(|&i| {
+            let c = &columns[i];
+            !needed.contains(c) && !needed.contains(&emitted[i]) && !keep.contains(c)
+        })
+        .collect();
+    for &i in dead.iter().rev() {
+        emitted.remove(i);
+        columns.remove(i);
+    }
+}
+
+/// Removes the columns that intermediate projections and joins carry, but that none of their
+/// descendants reference.
+///
+/// This is the inverse of `pull_required_base_columns`. Joins emit all of their parents' columns,
+/// so queries over wide tables would otherwise carry every column into the state of the nodes
+/// below. Nodes that are already part of the graph, and nodes without children, whose columns are
+/// the output of the query, are left alone.
+pub(super) fn prune_dead_columns(q: &mut MirQuery) {
+    // children are pruned first, so that the columns they no longer emit don't count as needed
+    for mn in q.topo_nodes().into_iter().rev() {
+        if mn.borrow().flow_node.is_some() || mn.borrow().children().is_empty() {
+            continue;
+        }
+        if let Some(needed) = needed_by_children(&mn) {
+            prune_node(&mn, &needed);
+        }
+    }
 }
 
 // currently unused
@@ -176,3 +301,376 @@ pub(super) fn push_all_base_columns(q: &mut MirQuery) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{MirNode, MirNodeType};
+    use crate::Expression;
+    use dataflow::ops::filter::{FilterCondition, Value};
+    use dataflow::ops::grouped::aggregate::Aggregation;
+    use dataflow::ops::project::BuiltinFunction;
+    use nom_sql::{self, ColumnSpecification, Operator, SqlType};
+
+    #[test]
+    fn pulls_columns_through_filters_on_aggregations() {
+        let cspec = |n: &str| -> (ColumnSpecification, Option<usize>) {
+            (
+                ColumnSpecification::new(nom_sql::Column::from(n), SqlType::Text),
+                None,
+            )
+        };
+        let base = MirNode::new(
+            "t",
+            0,
+            vec![Column::from("g"), Column::from("x"), Column::from("y")],
+            MirNodeType::Base {
+                column_specs: vec![cspec("g"), cspec("x"), cspec("y")],
+                keys: vec![Column::from("g")],
+                adapted_over: None,
+                indexes: vec![],
+            },
+            vec![],
+            vec![],
+        );
+        let agg = MirNode::new(
+            "agg",
+            0,
+            vec![Column::from("g"), Column::from("n")],
+            MirNodeType::Aggregation {
+                on: Column::from("x"),
+                group_by: vec![Column::from("g")],
+                kind: Aggregation::COUNT,
+            },
+            vec![base.clone()],
+            vec![],
+        );
+        // HAVING n > 5
+        let having = MirNode::new(
+            "having",
+            0,
+            vec![Column::from("g"), Column::from("n")],
+            MirNodeType::Filter {
+                conditions: vec![(
+                    1,
+                    FilterCondition::Comparison(Operator::Greater, Value::Constant(5.into())),
+                )],
+            },
+            vec![agg.clone()],
+            vec![],
+        );
+        let project = MirNode::new(
+            "project",
+            0,
+            vec![Column::from("n"), Column::from("y")],
+            MirNodeType::Project {
+                emit: vec![Column::from("n"), Column::from("y")],
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![having.clone()],
+            vec![],
+        );
+
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![base],
+            leaf: project,
+        };
+        pull_required_base_columns(&mut q, None, false).unwrap();
+
+        // the filter has to keep emitting the columns of the aggregation, and still filter on
+        // the aggregation result
+        assert_eq!(agg.borrow().columns(), having.borrow().columns());
+        assert_eq!(
+            having.borrow().columns(),
+            &[Column::from("g"), Column::from("y"), Column::from("n")]
+        );
+        match having.borrow().inner {
+            MirNodeType::Filter { ref conditions } => assert_eq!(conditions[0].0, 2),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn pulls_columns_that_expressions_read() {
+        let cspec = |n: &str| -> (ColumnSpecification, Option<usize>) {
+            (
+                ColumnSpecification::new(nom_sql::Column::from(n), SqlType::Text),
+                None,
+            )
+        };
+        let base = MirNode::new(
+            "t",
+            0,
+            vec![Column::from("a"), Column::from("b"), Column::from("c")],
+            MirNodeType::Base {
+                column_specs: vec![cspec("a"), cspec("b"), cspec("c")],
+                keys: vec![Column::from("a")],
+                adapted_over: None,
+                indexes: vec![],
+            },
+            vec![],
+            vec![],
+        );
+        let inner = MirNode::new(
+            "inner",
+            0,
+            vec![Column::from("a")],
+            MirNodeType::Project {
+                emit: vec![Column::from("a")],
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![base.clone()],
+            vec![],
+        );
+        // SELECT a, UPPER(b) AS ub, CASE WHEN c > 5 THEN 1 ELSE 0 END AS big
+        let big = Expression::Case {
+            branches: vec![(
+                Expression::Compare {
+                    op: Operator::Greater,
+                    left: Box::new(Expression::Column(Column::from("c"))),
+                    right: Box::new(Expression::Literal(5.into())),
+                },
+                Expression::Literal(1.into()),
+            )],
+            otherwise: Some(Box::new(Expression::Literal(0.into()))),
+        };
+        let project = MirNode::new(
+            "project",
+            0,
+            vec![Column::from("a"), Column::from("ub"), Column::from("big")],
+            MirNodeType::Project {
+                emit: vec![Column::from("a")],
+                expressions: vec![
+                    (
+                        String::from("ub"),
+                        Expression::Call(
+                            BuiltinFunction::Upper,
+                            vec![Expression::Column(Column::from("b"))],
+                        ),
+                    ),
+                    (String::from("big"), big),
+                ],
+                literals: vec![],
+            },
+            vec![inner.clone()],
+            vec![],
+        );
+
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![base],
+            leaf: project,
+        };
+        pull_required_base_columns(&mut q, None, false).unwrap();
+
+        // the columns that the computed columns are computed from are pulled through
+        assert_eq!(
+            inner.borrow().columns(),
+            &[Column::from("a"), Column::from("b"), Column::from("c")]
+        );
+    }
+
+    #[test]
+    fn does_not_pull_masked_columns() {
+        let cspec = |n: &str| -> (ColumnSpecification, Option<usize>) {
+            (
+                ColumnSpecification::new(nom_sql::Column::from(n), SqlType::Text),
+                None,
+            )
+        };
+        let base = MirNode::new(
+            "t",
+            0,
+            vec![Column::from("a"), Column::from("secret")],
+            MirNodeType::Base {
+                column_specs: vec![cspec("a"), cspec("secret")],
+                keys: vec![Column::from("a")],
+                adapted_over: None,
+                indexes: vec![],
+            },
+            vec![],
+            vec![],
+        );
+        let mask = MirNode::new(
+            "mask",
+            0,
+            vec![Column::from("a"), Column::from("t.secret")],
+            MirNodeType::Project {
+                emit: vec![Column::from("a")],
+                expressions: vec![],
+                literals: vec![(String::from("secret"), "***".into())],
+            },
+            vec![base.clone()],
+            vec![],
+        );
+        let inner = MirNode::new(
+            "inner",
+            0,
+            vec![Column::from("a")],
+            MirNodeType::Project {
+                emit: vec![Column::from("a")],
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![mask.clone()],
+            vec![],
+        );
+        // SELECT a, UPPER(secret) AS s
+        let project = MirNode::new(
+            "project",
+            0,
+            vec![Column::from("a"), Column::from("s")],
+            MirNodeType::Project {
+                emit: vec![Column::from("a")],
+                expressions: vec![(
+                    String::from("s"),
+                    Expression::Call(
+                        BuiltinFunction::Upper,
+                        vec![Expression::Column(Column::from("secret"))],
+                    ),
+                )],
+                literals: vec![],
+            },
+            vec![inner.clone()],
+            vec![],
+        );
+
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![base],
+            leaf: project,
+        };
+        pull_required_base_columns(&mut q, None, false).unwrap();
+
+        // the unmasked column is not pulled past the mask
+        assert_eq!(inner.borrow().columns(), &[Column::from("a")]);
+        match mask.borrow().inner {
+            MirNodeType::Project { ref emit, .. } => assert_eq!(emit, &[Column::from("a")]),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn prunes_columns_nothing_reads() {
+        let base = |name: &str, cols: &[&str]| {
+            MirNode::new(
+                name,
+                0,
+                cols.iter().map(|&c| Column::from(c)).collect(),
+                MirNodeType::Base {
+                    column_specs: cols
+                        .iter()
+                        .map(|&c| {
+                            let spec =
+                                ColumnSpecification::new(nom_sql::Column::from(c), SqlType::Text);
+                            (spec, None)
+                        })
+                        .collect(),
+                    keys: vec![Column::from(cols[0])],
+                    adapted_over: None,
+                    indexes: vec![],
+                },
+                vec![],
+                vec![],
+            )
+        };
+        let t = base("t", &["t.a", "t.b", "t.c"]);
+        let u = base("u", &["u.a", "u.d"]);
+        let join_cols = vec![
+            Column::from("t.a"),
+            Column::from("t.b"),
+            Column::from("t.c"),
+            Column::from("u.d"),
+        ];
+        let join = MirNode::new(
+            "join",
+            0,
+            join_cols.clone(),
+            MirNodeType::Join {
+                on_left: vec![Column::from("t.a")],
+                on_right: vec![Column::from("u.a")],
+                project: join_cols,
+            },
+            vec![t.clone(), u.clone()],
+            vec![],
+        );
+        let inner = MirNode::new(
+            "inner",
+            0,
+            vec![Column::from("t.b"), Column::from("u.d")],
+            MirNodeType::Project {
+                emit: vec![Column::from("t.b"), Column::from("u.d")],
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![join.clone()],
+            vec![],
+        );
+        let project = MirNode::new(
+            "project",
+            0,
+            vec![Column::from("t.b")],
+            MirNodeType::Project {
+                emit: vec![Column::from("t.b")],
+                expressions: vec![],
+                literals: vec![],
+            },
+            vec![inner.clone()],
+            vec![],
+        );
+
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![t.clone(), u],
+            leaf: project.clone(),
+        };
+        prune_dead_columns(&mut q);
+
+        // the join keeps its join column, and the query's output is left as it is
+        assert_eq!(
+            join.borrow().columns(),
+            &[Column::from("t.a"), Column::from("t.b")]
+        );
+        assert_eq!(inner.borrow().columns(), &[Column::from("t.b")]);
+        assert_eq!(project.borrow().columns(), &[Column::from("t.b")]);
+        assert_eq!(t.borrow().columns().len(), 3);
+        match join.borrow().inner {
+            MirNodeType::Join { ref project, .. } => assert_eq!(project.len(), 2),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rejects_secure_queries_without_table_mapping() {
+        let base = MirNode::new(
+            "t",
+            0,
+            vec![Column::from("a")],
+            MirNodeType::Base {
+                column_specs: vec![(
+                    ColumnSpecification::new(nom_sql::Column::from("a"), SqlType::Text),
+                    None,
+                )],
+                keys: vec![Column::from("a")],
+                adapted_over: None,
+                indexes: vec![],
+            },
+            vec![],
+            vec![],
+        );
+        let mut q = MirQuery {
+            name: String::from("q"),
+            roots: vec![base.clone()],
+            leaf: base,
+        };
+        assert_eq!(
+            pull_required_base_columns(&mut q, None, true),
+            Err(MirError::MissingTableMapping {
+                query: String::from("q")
+            })
+        );
+    }
+}
@@ -7,6 +7,7 @@ use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
+use dataflow::ops::window::WindowFunction as WindowKind;
 
 pub trait GraphViz {
     fn to_graphviz(&self) -> Result<String, fmt::Error>;
@@ -115,6 +116,34 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "{} | γ: {}", op_string, group_cols)?;
             }
+            MirNodeType::DistinctAggregation {
+                ref on,
+                ref group_by,
+                ref kind,
+            } => {
+                let op_string = match *kind {
+                    AggregationKind::COUNT => format!("\\|δ({})\\|", print_col(on)),
+                    AggregationKind::SUM => format!("𝛴δ({})", print_col(on)),
+                };
+                let group_cols = group_by
+                    .iter()
+                    .map(|c| print_col(c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "{} | γ: {}", op_string, group_cols)?;
+            }
+            MirNodeType::CustomAggregation {
+                ref on,
+                ref group_by,
+                ref function,
+            } => {
+                let group_cols = group_by
+                    .iter()
+                    .map(|c| print_col(c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "{}({}) | γ: {}", function, print_col(on), group_cols)?;
+            }
             MirNodeType::Base {
                 ref column_specs,
                 ref keys,
@@ -193,6 +222,9 @@ impl GraphViz for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Expression(ref e) => {
+                                Some(escape(&format!("{}", e)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -221,13 +253,20 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "⋈  | on: {}", jc)?;
             }
-            MirNodeType::Leaf { ref keys, .. } => {
+            MirNodeType::Leaf {
+                ref keys,
+                ref range,
+                ..
+            } => {
                 let key_cols = keys
                     .iter()
                     .map(|k| print_col(k))
                     .collect::<Vec<_>>()
                     .join(", ");
-                write!(out, "Leaf | ⚷: {}", key_cols)?;
+                match *range {
+                    Some(ref op) => write!(out, "Leaf | ⚷: {} {} ?", key_cols, op)?,
+                    None => write!(out, "Leaf | ⚷: {}", key_cols)?,
+                }
             }
             MirNodeType::LeftJoin {
                 ref on_left,
@@ -242,6 +281,38 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "⋉  | on: {}", jc)?;
             }
+            MirNodeType::OuterJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "⟗  | on: {}", jc)?;
+            }
+            MirNodeType::MultiJoin { ref on, .. } => {
+                let jc = on
+                    .iter()
+                    .map(|(_, l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "⋈  | on: {}", jc)?;
+            }
+            MirNodeType::AntiJoin {
+                ref on_left,
+                ref on_right,
+            } => {
+                write!(
+                    out,
+                    "▷  | on: {}:{}",
+                    print_col(on_left),
+                    print_col(on_right)
+                )?;
+            }
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -253,7 +324,7 @@ impl GraphViz for MirNodeType {
             MirNodeType::Project {
                 ref emit,
                 ref literals,
-                ref arithmetic,
+                ref expressions,
             } => {
                 write!(
                     out,
@@ -262,12 +333,12 @@ impl GraphViz for MirNodeType {
                         .map(|c| print_col(c))
                         .collect::<Vec<_>>()
                         .join(", "),
-                    if arithmetic.is_empty() {
+                    if expressions.is_empty() {
                         "".into()
                     } else {
                         format!(
                             ", {}",
-                            arithmetic
+                            expressions
                                 .iter()
                                 .map(|&(ref n, ref e)| format!("{}: {}", n, e))
                                 .collect::<Vec<_>>()
@@ -316,6 +387,52 @@ impl GraphViz for MirNodeType {
                         .unwrap_or_else(|| "".into())
                 )?;
             }
+            MirNodeType::Window {
+                kind,
+                ref on,
+                ref partition_by,
+                ref order,
+            } => {
+                let function = match kind {
+                    WindowKind::RowNumber => "ROW_NUMBER".to_owned(),
+                    WindowKind::Rank => "RANK".to_owned(),
+                    WindowKind::Sum => format!("𝛴({})", print_col(on.as_ref().unwrap())),
+                };
+                let partition_cols = partition_by
+                    .iter()
+                    .map(|c| print_col(c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    out,
+                    "{} | γ: {}; {}",
+                    function,
+                    partition_cols,
+                    order
+                        .iter()
+                        .map(|(c, o)| format!("{}: {}", c.name.as_str(), o))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+            }
+            MirNodeType::Session {
+                ref on,
+                gap,
+                ref partition_by,
+            } => {
+                let partition_cols = partition_by
+                    .iter()
+                    .map(|c| print_col(c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    out,
+                    "SESSION({}, {}) | γ: {}",
+                    print_col(on),
+                    gap,
+                    partition_cols
+                )?;
+            }
             MirNodeType::Union { ref emit } => {
                 let cols = emit
                     .iter()
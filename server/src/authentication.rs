@@ -0,0 +1,33 @@
+use noria::DataType;
+use std::collections::HashMap;
+
+/// Decides who the requests that clients make to the controller, and the writes they make to the
+/// workers, come from.
+///
+/// Clients present credentials with `ControllerHandle::authenticate`. The identity a request is
+/// authenticated as selects the security universe of the views the client reads. The tables a
+/// client fetches present the same credentials when they connect to a worker, which issues every
+/// write on the connection as the identity they belong to, and turns away connections whose
+/// credentials it rejects.
+///
+/// Reads go straight to the workers without credentials, so any client that can reach a worker
+/// can read any view, including the views of other users' universes. Only TLS, with client
+/// certificates, keeps unknown clients from reading.
+pub trait Authenticator: Send + Sync {
+    /// The identity that a request with `credentials` acts as, `None` if it acts as no one in
+    /// particular, or why it is turned away.
+    fn authenticate(&self, credentials: Option<&str>) -> Result<Option<DataType>, String>;
+}
+
+/// Fixed credentials, each of which belongs to an identity.
+///
+/// Requests without credentials are turned away.
+impl Authenticator for HashMap<String, DataType> {
+    fn authenticate(&self, credentials: Option<&str>) -> Result<Option<DataType>, String> {
+        let credentials = credentials.ok_or_else(|| "no credentials given".to_owned())?;
+        self.get(credentials)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| "unknown credentials".to_owned())
+    }
+}
@@ -0,0 +1,195 @@
+use clap::{App, Arg, ArgMatches};
+use noria::{ControllerHandle, ZookeeperAuthority};
+use noria_server::{BinlogDecoder, BinlogPosition, BinlogReplicator};
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+// Runs mysqlbinlog from `start` and applies the transactions it prints until it exits, saving the
+// position after each transaction to `checkpoint`. Returns the position to resume at.
+async fn tail(
+    matches: &ArgMatches<'_>,
+    replicator: &mut BinlogReplicator<ZookeeperAuthority>,
+    start: BinlogPosition,
+    checkpoint: &Path,
+) -> Result<BinlogPosition, failure::Error> {
+    let mut cmd = Command::new(matches.value_of("mysqlbinlog").unwrap());
+    cmd.arg("--read-from-remote-server")
+        .arg("--stop-never")
+        .arg("--verbose")
+        .arg("--base64-output=DECODE-ROWS")
+        .arg(format!("--host={}", matches.value_of("host").unwrap()))
+        .arg(format!("--port={}", matches.value_of("port").unwrap()))
+        .arg(format!("--user={}", matches.value_of("user").unwrap()))
+        .arg(format!(
+            "--connection-server-id={}",
+            matches.value_of("server-id").unwrap()
+        ))
+        .arg(format!("--start-position={}", start.offset))
+        .arg(&start.file)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(password) = matches.value_of("password") {
+        cmd.env("MYSQL_PWD", password);
+    }
+    let mut child = cmd.spawn()?;
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+
+    let mut decoder = BinlogDecoder::new(start.clone());
+    let mut last = start;
+    while let Some(line) = lines.next_line().await? {
+        let line = decoder.line(&line).map_err(failure::err_msg)?;
+        if let Some((changes, position)) = line {
+            if !changes.is_empty() {
+                replicator.apply(changes).await?;
+            }
+            position.save(checkpoint)?;
+            last = position;
+        }
+    }
+
+    let status = child.await?;
+    if !status.success() {
+        eprintln!("mysqlbinlog exited with {}", status);
+    }
+    Ok(last)
+}
+
+fn main() {
+    let matches = App::new("noria-binlog")
+        .version("0.0.1")
+        .about(
+            "Tails the binary log of a MySQL server and applies its row changes to the base \
+             tables of the same names in a Noria deployment. The server must use \
+             binlog_format=ROW, and mysqlbinlog must be installed.",
+        )
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .short("d")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("host")
+                .long("host")
+                .takes_value(true)
+                .default_value("127.0.0.1")
+                .help("The MySQL server to replicate from."),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .takes_value(true)
+                .default_value("3306")
+                .help("The port of the MySQL server."),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .takes_value(true)
+                .default_value("root")
+                .help("A MySQL user with the REPLICATION SLAVE privilege."),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .takes_value(true)
+                .help("The password of the MySQL user."),
+        )
+        .arg(
+            Arg::with_name("database")
+                .long("database")
+                .takes_value(true)
+                .help("Only replicate the tables of this MySQL database."),
+        )
+        .arg(
+            Arg::with_name("server-id")
+                .long("server-id")
+                .takes_value(true)
+                .default_value("1048576")
+                .help("The replica server id to connect with, unique among the server's replicas."),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .takes_value(true)
+                .required(true)
+                .help("The file to keep the position of the last applied transaction in."),
+        )
+        .arg(
+            Arg::with_name("start")
+                .long("start")
+                .takes_value(true)
+                .help(
+                    "The position to start at if there is no checkpoint, as file[:offset], \
+                     e.g., mysql-bin.000001:4.",
+                ),
+        )
+        .arg(
+            Arg::with_name("mysqlbinlog")
+                .long("mysqlbinlog")
+                .takes_value(true)
+                .default_value("mysqlbinlog")
+                .help("The mysqlbinlog executable to read the binary log with."),
+        )
+        .get_matches();
+
+    let zookeeper_addr = format!(
+        "{}/{}",
+        matches.value_of("zookeeper").unwrap(),
+        matches.value_of("deployment").unwrap()
+    );
+    let checkpoint = Path::new(matches.value_of("checkpoint").unwrap());
+    let mut position = match BinlogPosition::load(checkpoint) {
+        Ok(Some(position)) => position,
+        Ok(None) => match matches.value_of("start").map(str::parse) {
+            Some(Ok(position)) => position,
+            Some(Err(e)) => {
+                eprintln!("invalid --start: {}", e);
+                process::exit(1);
+            }
+            None => {
+                eprintln!(
+                    "no checkpoint in {}, so --start is needed",
+                    checkpoint.display()
+                );
+                process::exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!("failed to read checkpoint {}: {}", checkpoint.display(), e);
+            process::exit(1);
+        }
+    };
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let result: Result<(), failure::Error> = rt.block_on(async {
+        let handle = ControllerHandle::from_zk(&zookeeper_addr).await?;
+        let mut replicator = BinlogReplicator::new(handle);
+        if let Some(database) = matches.value_of("database") {
+            replicator = replicator.only_database(database);
+        }
+        loop {
+            eprintln!("replicating from {}", position);
+            position = tail(&matches, &mut replicator, position.clone(), checkpoint).await?;
+            // the connection to the MySQL server was lost
+            tokio::time::delay_for(Duration::from_secs(1)).await;
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("replication failed at {}: {}", position, e);
+        process::exit(1);
+    }
+}
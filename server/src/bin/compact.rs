@@ -0,0 +1,100 @@
+use clap::{App, Arg};
+use noria_server::{PersistenceParameters, STATE_FORMAT_VERSION};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Upgrades and compacts the state at `path` as requested, and then reports on it. Returns false if
+// anything went wrong.
+fn run(path: &Path, params: &PersistenceParameters, upgrade: bool, compact: bool) -> bool {
+    let name = path.display();
+    if upgrade {
+        match noria_server::upgrade_state(path, params) {
+            Ok(from) if from < STATE_FORMAT_VERSION => {
+                println!("{}: upgraded from format {}", name, from)
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}: failed to upgrade: {}", name, e);
+                return false;
+            }
+        }
+    }
+    if compact {
+        if let Err(e) = noria_server::compact_state(path, params) {
+            eprintln!("{}: failed to compact: {}", name, e);
+            return false;
+        }
+    }
+
+    let report = match noria_server::inspect_state(path, params) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}: failed to open: {}", name, e);
+            return false;
+        }
+    };
+    println!(
+        "{}: format {}, {} rows, {} indices, {} bytes",
+        name,
+        report.version,
+        report.rows,
+        report.indices.len(),
+        report.bytes
+    );
+    if report.version < STATE_FORMAT_VERSION {
+        println!(
+            "{}: the current format is {}, use --upgrade to convert",
+            name, STATE_FORMAT_VERSION
+        );
+    }
+    if report.corrupt > 0 {
+        eprintln!("{}: {} corrupt rows", name, report.corrupt);
+        return false;
+    }
+    true
+}
+
+fn main() {
+    let matches = App::new("noria-compact")
+        .version("0.0.1")
+        .about(
+            "Verifies, upgrades, and compacts the persisted state of base tables. \
+             The server must not be running.",
+        )
+        .arg(
+            Arg::with_name("log-dir")
+                .long("log-dir")
+                .takes_value(true)
+                .help("The --log-dir the server was started with, if any."),
+        )
+        .arg(
+            Arg::with_name("upgrade")
+                .long("upgrade")
+                .help("Rewrite state stored in an older format in the current one."),
+        )
+        .arg(
+            Arg::with_name("compact")
+                .long("compact")
+                .help("Discard the space taken up by overwritten and deleted rows."),
+        )
+        .arg(
+            Arg::with_name("state")
+                .required(true)
+                .multiple(true)
+                .help("The .db directories of the base tables to process."),
+        )
+        .get_matches();
+
+    let mut params = PersistenceParameters::default();
+    params.log_dir = matches.value_of("log-dir").map(PathBuf::from);
+    let upgrade = matches.is_present("upgrade");
+    let compact = matches.is_present("compact");
+
+    let mut ok = true;
+    for path in matches.values_of("state").unwrap() {
+        ok &= run(Path::new(path), &params, upgrade, compact);
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
@@ -4,6 +4,7 @@ extern crate serde_json;
 extern crate zookeeper;
 
 use noria::consensus::{CONTROLLER_KEY, STATE_KEY};
+use noria::{ControllerHandle, MigrationStatus};
 use serde_json::Value;
 use std::process;
 use std::time::Duration;
@@ -20,6 +21,29 @@ impl Watcher for EventWatcher {
     }
 }
 
+fn print_migration(status: &MigrationStatus) {
+    match status.running_for {
+        Some(elapsed) => println!("Migration running for {:?}", elapsed),
+        None => println!("No migration running; the most recent one:"),
+    }
+    println!(
+        "  {}/{} rows replayed ({} bytes)",
+        status.rows_replayed, status.rows_to_replay, status.bytes_replayed
+    );
+    for d in &status.domains {
+        println!(
+            "  domain {}: {}/{} new nodes ready{}",
+            d.domain,
+            d.ready,
+            d.nodes,
+            if d.is_complete() { " (done)" } else { "" }
+        );
+    }
+    if let Some(eta) = status.eta {
+        println!("  about {}s to go", eta.as_secs());
+    }
+}
+
 fn main() {
     use clap::{App, Arg};
     let matches = App::new("zkUtil")
@@ -46,7 +70,7 @@ fn main() {
                 .short("c")
                 .long("clean")
                 .takes_value(false)
-                .required_unless("show")
+                .required_unless_one(&["show", "migration"])
                 .help("Remove existing configuration."),
         )
         .arg(
@@ -54,15 +78,23 @@ fn main() {
                 .short("s")
                 .long("show")
                 .takes_value(false)
-                .required_unless("clean")
+                .required_unless_one(&["clean", "migration"])
                 .help("Print current configuration to stdout."),
         )
+        .arg(
+            Arg::with_name("migration")
+                .short("m")
+                .long("migration")
+                .takes_value(false)
+                .help("Print the progress of the current migration to stdout."),
+        )
         .get_matches();
 
     let deployment = matches.value_of("deployment").unwrap();
     let zookeeper_addr = format!("{}/{}", matches.value_of("zookeeper").unwrap(), deployment);
     let clean = matches.is_present("clean");
     let dump = matches.is_present("show");
+    let migration = matches.is_present("migration");
 
     let zk = ZooKeeper::connect(&zookeeper_addr, Duration::from_secs(1), EventWatcher).unwrap();
 
@@ -102,6 +134,19 @@ fn main() {
         );
     }
 
+    if migration {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let status = rt.block_on(async {
+            let mut ch = ControllerHandle::from_zk(&zookeeper_addr).await?;
+            ch.ready().await?;
+            ch.migration_status().await
+        });
+        match status {
+            Ok(status) => print_migration(&status),
+            Err(e) => println!("Failed to get migration status: {}", e),
+        }
+    }
+
     if clean {
         match zk.delete(CONTROLLER_KEY, None) {
             // any version
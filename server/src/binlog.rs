@@ -0,0 +1,571 @@
+use chrono::NaiveDateTime;
+use nom_sql::SqlType;
+use noria::consensus::Authority;
+use noria::{ControllerHandle, DataType, Modification, Table, TableOperation};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A position in the binary log of a MySQL server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinlogPosition {
+    /// The name of the binary log file, such as `mysql-bin.000042`.
+    pub file: String,
+    /// The offset in the file of the next event to read.
+    pub offset: u64,
+}
+
+impl BinlogPosition {
+    /// Read the position last saved to `path` with `save`, if any.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the position to `path`, replacing whatever position was saved there before.
+    ///
+    /// The position is written to a temporary file first, so that a crash never leaves behind a
+    /// partially written position.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, format!("{}\n", self))?;
+        fs::rename(&tmp, path)
+    }
+}
+
+impl fmt::Display for BinlogPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.offset)
+    }
+}
+
+impl FromStr for BinlogPosition {
+    type Err = String;
+
+    /// Parse a position written as `file:offset`, or just `file` for the start of the file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file, offset) = match s.rfind(':') {
+            Some(i) => (
+                &s[..i],
+                s[i + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid binlog offset in {}", s))?,
+            ),
+            // every binlog file starts with a 4-byte magic number
+            None => (s, 4),
+        };
+        if file.is_empty() {
+            return Err(format!("no binlog file in {}", s));
+        }
+        Ok(BinlogPosition {
+            file: file.to_owned(),
+            offset,
+        })
+    }
+}
+
+/// A change to one row of a MySQL table, as decoded from a row event in the binary log.
+///
+/// Values are kept as `mysqlbinlog` prints them until they are converted to the types of the
+/// columns of the Noria table that the change is applied to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowChange {
+    /// The database that the table belongs to.
+    pub database: String,
+    /// The table whose row changed.
+    pub table: String,
+    /// The row before the change, for updates and deletes.
+    pub before: Option<Vec<String>>,
+    /// The row after the change, for inserts and updates.
+    pub after: Option<Vec<String>>,
+}
+
+/// Which of the rows of a `RowChange` the values being decoded belong to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Image {
+    Before,
+    After,
+}
+
+/// Decodes the output of `mysqlbinlog --verbose --base64-output=DECODE-ROWS` into the row
+/// changes of each committed transaction.
+///
+/// This needs row-based replication (`binlog_format=ROW`); statements logged in other formats are
+/// skipped, as are schema changes.
+#[derive(Debug)]
+pub struct BinlogDecoder {
+    position: BinlogPosition,
+    changes: Vec<RowChange>,
+    current: Option<(RowChange, Image)>,
+}
+
+impl BinlogDecoder {
+    /// Decode a binary log that is read starting at `start`.
+    pub fn new(start: BinlogPosition) -> Self {
+        BinlogDecoder {
+            position: start,
+            changes: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// The position just after the last event decoded.
+    pub fn position(&self) -> &BinlogPosition {
+        &self.position
+    }
+
+    /// Decode the next line of output.
+    ///
+    /// When the line commits a transaction, returns the row changes made by the transaction and
+    /// the position to resume reading at so that the transaction is not read again.
+    pub fn line(&mut self, line: &str) -> Result<Option<(Vec<RowChange>, BinlogPosition)>, String> {
+        if let Some(row) = line.strip_prefix("### ") {
+            return self.row_line(row.trim()).map(|_| None);
+        }
+        self.finish_row();
+
+        if line.starts_with('#') {
+            // an event header, such as
+            // #200101 12:00:00 server id 1  end_log_pos 1381 CRC32 0x8b2c3e4f 	Xid = 42
+            if let Some(rotate) = line.find("\tRotate to ") {
+                let rotate = &line[rotate + "\tRotate to ".len()..];
+                let mut words = rotate.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some(file), Some("pos:"), Some(offset)) => {
+                        self.position = BinlogPosition {
+                            file: file.to_owned(),
+                            offset: offset
+                                .parse()
+                                .map_err(|_| format!("invalid rotate event: {}", line))?,
+                        };
+                    }
+                    _ => return Err(format!("invalid rotate event: {}", line)),
+                }
+            } else if let Some(end) = line.find("end_log_pos ") {
+                let end = line[end + "end_log_pos ".len()..]
+                    .split_whitespace()
+                    .next()
+                    .and_then(|end| end.parse().ok())
+                    .ok_or_else(|| format!("invalid event header: {}", line))?;
+                self.position.offset = end;
+            }
+        } else if line.starts_with("COMMIT") {
+            let changes = std::mem::replace(&mut self.changes, Vec::new());
+            return Ok(Some((changes, self.position.clone())));
+        }
+        Ok(None)
+    }
+
+    fn row_line(&mut self, line: &str) -> Result<(), String> {
+        let (before, after, table) = if let Some(table) = line.strip_prefix("INSERT INTO ") {
+            (false, true, table)
+        } else if let Some(table) = line.strip_prefix("UPDATE ") {
+            (true, true, table)
+        } else if let Some(table) = line.strip_prefix("DELETE FROM ") {
+            (true, false, table)
+        } else {
+            return self.row_value(line);
+        };
+
+        self.finish_row();
+        let (database, table) = match table.find("`.`") {
+            Some(i) => (
+                table[..i].trim_start_matches('`'),
+                table[i + 3..].trim_end_matches('`'),
+            ),
+            None => return Err(format!("invalid table name {}", table)),
+        };
+        let change = RowChange {
+            database: database.to_owned(),
+            table: table.to_owned(),
+            before: if before { Some(Vec::new()) } else { None },
+            after: if after { Some(Vec::new()) } else { None },
+        };
+        let image = if before { Image::Before } else { Image::After };
+        self.current = Some((change, image));
+        Ok(())
+    }
+
+    fn row_value(&mut self, line: &str) -> Result<(), String> {
+        let (change, image) = match self.current {
+            Some((ref mut change, ref mut image)) => (change, image),
+            None => return Err(format!("row data outside of a row event: {}", line)),
+        };
+        match line {
+            "WHERE" => *image = Image::Before,
+            "SET" => *image = Image::After,
+            _ if line.starts_with('@') => {
+                let row = match *image {
+                    Image::Before => change.before.as_mut(),
+                    Image::After => change.after.as_mut(),
+                }
+                .ok_or_else(|| format!("unexpected row data: {}", line))?;
+                let eq = line
+                    .find('=')
+                    .ok_or_else(|| format!("invalid column value: {}", line))?;
+                if line[1..eq].parse::<usize>().ok() != Some(row.len() + 1) {
+                    return Err(format!("column value out of order: {}", line));
+                }
+                row.push(line[eq + 1..].to_owned());
+            }
+            _ => return Err(format!("unexpected row data: {}", line)),
+        }
+        Ok(())
+    }
+
+    fn finish_row(&mut self) {
+        if let Some((change, _)) = self.current.take() {
+            self.changes.push(change);
+        }
+    }
+}
+
+/// Convert a value as `mysqlbinlog` prints it to a value of a column of type `sql_type`.
+fn value(text: &str, sql_type: Option<&SqlType>) -> Result<DataType, String> {
+    let invalid = || format!("{} is not a valid {:?}", text, sql_type);
+    let text = text.trim();
+    if text == "NULL" {
+        return Ok(DataType::None);
+    }
+
+    if let Some(quoted) = text.strip_prefix('\'') {
+        let s = match quoted.strip_suffix('\'') {
+            Some(s) => unquote(s),
+            None => return Err(invalid()),
+        };
+        return match sql_type {
+            // dates are printed as YYYY:MM:DD
+            Some(SqlType::Date) => DataType::from(s.replace(':', "-"))
+                .to_timestamp()
+                .map(DataType::from)
+                .ok_or_else(invalid),
+            Some(SqlType::DateTime(_)) | Some(SqlType::Timestamp) => DataType::from(s)
+                .to_timestamp()
+                .map(DataType::from)
+                .ok_or_else(invalid),
+            _ => Ok(DataType::from(s)),
+        };
+    }
+
+    // negative values of unsigned columns are followed by their unsigned reading in parentheses
+    let (signed, unsigned) = match text.find(" (") {
+        Some(i) => (&text[..i], Some(text[i + 2..].trim_end_matches(')'))),
+        None => (text, None),
+    };
+    match sql_type {
+        Some(SqlType::UnsignedInt(_))
+        | Some(SqlType::UnsignedBigint(_))
+        | Some(SqlType::UnsignedTinyint(_)) => unsigned
+            .unwrap_or(signed)
+            .parse::<u64>()
+            .map(DataType::from)
+            .map_err(|_| invalid()),
+        // timestamps are printed as seconds since the epoch
+        Some(SqlType::DateTime(_)) | Some(SqlType::Timestamp) => {
+            let (secs, frac) = match signed.find('.') {
+                Some(i) => (&signed[..i], &signed[i + 1..]),
+                None => (signed, ""),
+            };
+            let secs = secs.parse::<i64>().map_err(|_| invalid())?;
+            let nanos = if frac.is_empty() {
+                0
+            } else {
+                format!("{:0<9.9}", frac).parse().map_err(|_| invalid())?
+            };
+            NaiveDateTime::from_timestamp_opt(secs, nanos)
+                .map(DataType::from)
+                .ok_or_else(invalid)
+        }
+        Some(SqlType::Decimal(..)) => DataType::decimal(signed).ok_or_else(invalid),
+        Some(SqlType::Real) | Some(SqlType::Float) | Some(SqlType::Double) => signed
+            .parse::<f64>()
+            .map(DataType::from)
+            .map_err(|_| invalid()),
+        _ => {
+            if let Ok(n) = signed.parse::<i64>() {
+                Ok(DataType::from(n))
+            } else if let Some(d) = DataType::decimal(signed) {
+                Ok(d)
+            } else {
+                signed
+                    .parse::<f64>()
+                    .map(DataType::from)
+                    .map_err(|_| invalid())
+            }
+        }
+    }
+}
+
+/// Undo the escaping of control characters as `\xNN` in a quoted value.
+fn unquote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("\\x") {
+        out.push_str(&rest[..i]);
+        match rest
+            .get(i + 2..i + 4)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            Some(c) => {
+                out.push(char::from(c));
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push_str("\\x");
+                rest = &rest[i + 2..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Applies the row changes read from a MySQL server's binary log to the Noria base tables of the
+/// same names, so that Noria can serve reads for a database that applications keep writing to.
+///
+/// Changes to tables that Noria has no base table for are skipped. Inserts and updates are
+/// applied as upserts where the table has a primary key, so a transaction that is applied again
+/// after a restart leaves the tables as they were.
+pub struct BinlogReplicator<A: Authority + 'static> {
+    handle: ControllerHandle<A>,
+    database: Option<String>,
+    tables: HashMap<String, Option<Table>>,
+}
+
+impl<A: Authority + 'static> BinlogReplicator<A> {
+    /// Apply changes to the tables of the Noria deployment behind `handle`.
+    pub fn new(handle: ControllerHandle<A>) -> Self {
+        BinlogReplicator {
+            handle,
+            database: None,
+            tables: HashMap::new(),
+        }
+    }
+
+    /// Only apply changes to the tables of the given MySQL database.
+    pub fn only_database(mut self, database: &str) -> Self {
+        self.database = Some(database.to_owned());
+        self
+    }
+
+    /// Apply the changes made by a transaction, in order.
+    ///
+    /// Noria does not apply writes to several tables atomically, so readers may see some of the
+    /// transaction's changes before the others.
+    pub async fn apply(&mut self, changes: Vec<RowChange>) -> Result<(), failure::Error> {
+        let mut runs: Vec<(String, Vec<TableOperation>)> = Vec::new();
+        for change in changes {
+            if let Some(ref database) = self.database {
+                if change.database != *database {
+                    continue;
+                }
+            }
+            let table = match self.table(&change.table).await? {
+                Some(table) => table,
+                None => continue,
+            };
+            let ops = operations(table, &change)
+                .map_err(|e| format_err!("cannot apply change to {}: {}", change.table, e))?;
+            if let Some((t, run)) = runs.last_mut() {
+                if *t == change.table {
+                    run.extend(ops);
+                    continue;
+                }
+            }
+            runs.push((change.table, ops));
+        }
+
+        for (name, ops) in runs {
+            let table = self.tables.get_mut(&name).unwrap().as_mut().unwrap();
+            table.perform_all(ops).await.map_err(|e| {
+                failure::Error::from(e).context(format!("failed to apply changes to {}", name))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The base table named `name`, or `None` if there is no such table.
+    async fn table(&mut self, name: &str) -> Result<Option<&Table>, failure::Error> {
+        if !self.tables.contains_key(name) {
+            self.handle.ready().await?;
+            let table = if self.handle.inputs().await?.contains_key(name) {
+                self.handle.ready().await?;
+                Some(self.handle.table(name).await?)
+            } else {
+                None
+            };
+            self.tables.insert(name.to_owned(), table);
+        }
+        Ok(self.tables[name].as_ref())
+    }
+}
+
+/// The operations that apply `change` to `table`.
+fn operations(table: &Table, change: &RowChange) -> Result<Vec<TableOperation>, String> {
+    let row = |values: &[String]| -> Result<Vec<DataType>, String> {
+        if values.len() != table.columns().len() {
+            return Err(format!(
+                "row has {} columns, but the table has {}",
+                values.len(),
+                table.columns().len()
+            ));
+        }
+        let schema = table.schema().filter(|s| s.fields.len() == values.len());
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| value(v, schema.map(|s| &s.fields[i].sql_type)))
+            .collect()
+    };
+    let upsert = |row: Vec<DataType>| match table.primary_key() {
+        Some(_) => TableOperation::InsertOrUpdate {
+            update: row.iter().cloned().map(Modification::Set).collect(),
+            row,
+        },
+        None => TableOperation::Insert(row),
+    };
+    let key_of = |row: &[DataType]| match table.primary_key() {
+        Some(key) => Ok(key.iter().map(|&c| row[c].clone()).collect::<Vec<_>>()),
+        None => Err(String::from(
+            "rows can only be updated and deleted in tables with a primary key",
+        )),
+    };
+
+    match (&change.before, &change.after) {
+        (None, Some(after)) => Ok(vec![upsert(row(after)?)]),
+        (Some(before), None) => Ok(vec![TableOperation::Delete {
+            key: key_of(&row(before)?)?,
+        }]),
+        (Some(before), Some(after)) => {
+            let (before, after) = (row(before)?, row(after)?);
+            let key = key_of(&before)?;
+            if key == key_of(&after)? {
+                Ok(vec![TableOperation::Update {
+                    key,
+                    set: after.into_iter().map(Modification::Set).collect(),
+                }])
+            } else {
+                // the row may move to another shard
+                Ok(vec![TableOperation::Delete { key }, upsert(after)])
+            }
+        }
+        (None, None) => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_transactions() {
+        let output = "\
+# at 4
+#200101 12:00:00 server id 1  end_log_pos 124 \tStart: binlog v 4
+# at 124
+#200101 12:00:00 server id 1  end_log_pos 199 \tQuery\tthread_id=8
+BEGIN
+/*!*/;
+#200101 12:00:00 server id 1  end_log_pos 260 \tTable_map: `shop`.`item` mapped to number 90
+#200101 12:00:00 server id 1  end_log_pos 320 \tWrite_rows: table id 90
+### INSERT INTO `shop`.`item`
+### SET
+###   @1=1
+###   @2='a\\x0ab'
+### UPDATE `shop`.`item`
+### WHERE
+###   @1=2
+###   @2=NULL
+### SET
+###   @1=2
+###   @2='c'
+#200101 12:00:00 server id 1  end_log_pos 360 \tDelete_rows: table id 90
+### DELETE FROM `shop`.`item`
+### WHERE
+###   @1=3
+###   @2='d'
+#200101 12:00:00 server id 1  end_log_pos 391 \tXid = 42
+COMMIT/*!*/;
+#200101 12:00:00 server id 1  end_log_pos 438 \tRotate to mysql-bin.000002  pos: 4
+";
+        let mut decoder = BinlogDecoder::new("mysql-bin.000001".parse().unwrap());
+        let mut transactions = Vec::new();
+        for line in output.lines() {
+            if let Some(t) = decoder.line(line).unwrap() {
+                transactions.push(t);
+            }
+        }
+        assert_eq!(transactions.len(), 1);
+        let (changes, position) = transactions.pop().unwrap();
+        assert_eq!(position, "mysql-bin.000001:391".parse().unwrap());
+        assert_eq!(decoder.position().to_string(), "mysql-bin.000002:4");
+
+        let strings = |vs: &[&str]| vs.iter().map(|&v| v.to_owned()).collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                RowChange {
+                    database: "shop".to_owned(),
+                    table: "item".to_owned(),
+                    before: None,
+                    after: Some(strings(&["1", "'a\\x0ab'"])),
+                },
+                RowChange {
+                    database: "shop".to_owned(),
+                    table: "item".to_owned(),
+                    before: Some(strings(&["2", "NULL"])),
+                    after: Some(strings(&["2", "'c'"])),
+                },
+                RowChange {
+                    database: "shop".to_owned(),
+                    table: "item".to_owned(),
+                    before: Some(strings(&["3", "'d'"])),
+                    after: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_converts_values() {
+        assert_eq!(value("NULL", Some(&SqlType::Int(32))), Ok(DataType::None));
+        assert_eq!(value("-1", Some(&SqlType::Int(32))), Ok((-1).into()));
+        assert_eq!(
+            value("-1 (4294967295)", Some(&SqlType::UnsignedInt(32))),
+            Ok(4_294_967_295u64.into())
+        );
+        assert_eq!(value("'a\\x0ab'", Some(&SqlType::Text)), Ok("a\nb".into()));
+        assert_eq!(value("1.5", Some(&SqlType::Double)), Ok(1.5.into()));
+        assert_eq!(
+            value("12.50", Some(&SqlType::Decimal(10, 2))),
+            Ok(DataType::from_decimal(1250, 2))
+        );
+
+        let noon = DataType::from("2020-01-01 12:00:00")
+            .to_timestamp()
+            .unwrap();
+        assert_eq!(
+            value("'2020-01-01 12:00:00'", Some(&SqlType::DateTime(0))),
+            Ok(noon.into())
+        );
+        assert_eq!(
+            value("1577880000", Some(&SqlType::Timestamp)),
+            Ok(noon.into())
+        );
+        assert_eq!(
+            value("'2020:01:01'", Some(&SqlType::Date)),
+            Ok(noon.date().and_hms(0, 0, 0).into())
+        );
+        assert!(value("'x'", Some(&SqlType::Timestamp)).is_err());
+    }
+}
@@ -1,9 +1,12 @@
 use crate::handle::Handle;
+use crate::Authenticator;
 use crate::Config;
 use crate::FrontierStrategy;
+use crate::GroupedAggregator;
 use crate::ReuseConfigType;
 use dataflow::PersistenceParameters;
 use noria::consensus::{Authority, LocalAuthority};
+use noria::{Compression, TlsConfig};
 use std::future::Future;
 use std::net::IpAddr;
 use std::sync::Arc;
@@ -15,6 +18,7 @@ pub struct Builder {
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
     listen_addr: IpAddr,
+    authenticator: Option<Arc<dyn Authenticator>>,
     log: slog::Logger,
 }
 impl Default for Builder {
@@ -25,6 +29,7 @@ impl Default for Builder {
             log: slog::Logger::root(slog::Discard, o!()),
             memory_limit: None,
             memory_check_frequency: None,
+            authenticator: None,
         }
     }
 }
@@ -43,6 +48,108 @@ impl Builder {
         self.config.domain_config.replay_batch_timeout = t;
     }
 
+    /// Spill the coldest partitions of fully materialized join inputs to disk once they grow
+    /// beyond `bytes`.
+    ///
+    /// Probes into spilled partitions are answered from disk, so this trades join throughput for
+    /// not running out of memory on very large joins.
+    pub fn set_join_spill_threshold(&mut self, bytes: usize) {
+        assert_ne!(bytes, 0);
+        self.config.domain_config.join_spill_threshold = Some(bytes);
+    }
+
+    /// Have a domain stop accepting input once `n` packets are waiting to be sent from it to other
+    /// domains, or never if `None`.
+    ///
+    /// Domains that stop accepting input make the domains that feed them queue up packets in turn,
+    /// and eventually make writes to base tables wait until the slowest domain has caught up.
+    pub fn set_max_queued_packets(&mut self, n: Option<usize>) {
+        assert_ne!(n, Some(0));
+        self.config.domain_config.max_queued_packets = n;
+    }
+
+    /// Record the partial replays that take longer than `threshold` to fill a key, so that they
+    /// can be inspected with `ControllerHandle::slow_replays`.
+    pub fn set_slow_replay_threshold(&mut self, threshold: time::Duration) {
+        self.config.domain_config.slow_replay_threshold = Some(threshold);
+    }
+
+    /// Keep the wide text values of every materialized node compressed with `codec`.
+    ///
+    /// Values are decompressed whenever they are read, so this trades CPU for memory in
+    /// deployments with wide, string-heavy rows. Without it, only the columns given to
+    /// `Migration::compress_columns` are compressed.
+    pub fn set_state_compression(&mut self, codec: Compression) {
+        self.config.domain_config.state_compression = Some(codec);
+    }
+
+    /// Compress the updates and replay pieces that domains send to domains on other workers
+    /// with `codec`.
+    ///
+    /// Packets between domains on the same worker are not serialized, and are left as they are.
+    pub fn set_packet_compression(&mut self, codec: Compression) {
+        self.config.domain_config.packet_compression = Some(codec);
+    }
+
+    /// Number the writes at each base table and track which of them every view reflects, so that
+    /// clients can read consistent snapshots across views with
+    /// `ControllerHandle::read_transaction`.
+    ///
+    /// Every write is then forwarded to every view below its base table, even if it does not
+    /// change that view.
+    pub fn enable_snapshot_reads(&mut self) {
+        self.config.domain_config.snapshot_reads = true;
+    }
+
+    /// Advance the write frontier of every base table shard this often, so that clients can read
+    /// results that reflect every write up to a point in time with `View::lookup_fresh`.
+    ///
+    /// Each advance sends an empty update from every base table shard to every view below it,
+    /// and a view's staleness can be up to this interval more than the time it takes updates to
+    /// reach it.
+    pub fn set_frontier_interval(&mut self, every: time::Duration) {
+        self.config.domain_config.frontier_interval = Some(every);
+    }
+
+    /// `POST` every controller event to `url` as JSON, in addition to recording it in the event
+    /// log.
+    pub fn set_event_webhook(&mut self, url: String) {
+        self.config.event_webhook = Some(url);
+    }
+
+    /// Make new views readable as soon as their migration finishes, and fill in their keys in the
+    /// background at up to `keys_per_second` keys per second.
+    ///
+    /// The reader of a new view is then partially materialized even if partial materialization
+    /// is disabled, and misses on keys that have not been filled in yet are served by replays.
+    /// How far along the warming is can be seen with `ControllerHandle::warming_status`.
+    pub fn set_warm_rate(&mut self, keys_per_second: usize) {
+        assert_ne!(keys_per_second, 0);
+        self.config.warm_rate = Some(keys_per_second);
+    }
+
+    /// Record an event whenever a worker evicts at least `bytes` of state at once.
+    pub fn set_eviction_event_threshold(&mut self, bytes: usize) {
+        self.config.eviction_event_threshold = Some(bytes);
+    }
+
+    /// Register queries that Noria cannot support as pass-through queries, for the adapter to
+    /// execute against its upstream database, instead of failing the recipe they are part of.
+    ///
+    /// Only enable this if the adapter has an upstream database configured.
+    pub fn enable_pass_through(&mut self) {
+        self.config.pass_through_unsupported = true;
+    }
+
+    /// Make the aggregate function `aggregator` available under `name`, for use by
+    /// `CustomAggregator` nodes.
+    ///
+    /// Functions are looked up by name when records are aggregated, so every process that runs a
+    /// worker must register the same functions before it joins the deployment.
+    pub fn register_aggregate(&mut self, name: &str, aggregator: Arc<dyn GroupedAggregator>) {
+        dataflow::ops::grouped::custom::register(name, aggregator);
+    }
+
     /// Set the persistence parameters used by the system.
     pub fn set_persistence(&mut self, p: PersistenceParameters) {
         self.config.persistence = p;
@@ -93,6 +200,123 @@ impl Builder {
         self.config.reuse = reuse_type;
     }
 
+    /// Serve reads on each worker from a dedicated pool of `threads` threads.
+    ///
+    /// By default, reads are served by the same threads that process writes and replays, so read
+    /// latency suffers while those are busy.
+    pub fn set_reader_threads(&mut self, threads: usize) {
+        assert_ne!(threads, 0);
+        self.config.reader_threads = Some(threads);
+    }
+
+    /// Turn reads away with `ViewError::Overloaded` once `n` reads are pending on a worker.
+    ///
+    /// Blocking reads that wait for a backfill count as pending until they are answered.
+    pub fn set_max_pending_reads(&mut self, n: usize) {
+        assert_ne!(n, 0);
+        self.config.max_pending_reads = Some(n);
+    }
+
+    /// Turn reads away with `ViewError::QuotaExceeded` once a client connection has looked up
+    /// `per_sec` keys in the last second.
+    ///
+    /// Each connection has a quota of its own, so that one busy client cannot crowd out the reads
+    /// of the others.
+    pub fn set_lookup_quota(&mut self, per_sec: u32) {
+        assert_ne!(per_sec, 0);
+        self.config.lookup_quota = Some(per_sec);
+    }
+
+    /// Reject writes with `WriteRejection::QuotaExceeded` once a writer has made `per_sec` writes
+    /// to a table in the last second.
+    ///
+    /// Writers are told apart by the identity they write as, or else by their connection. Every
+    /// shard of a sharded table has a quota of its own.
+    pub fn set_write_quota(&mut self, per_sec: u32) {
+        assert_ne!(per_sec, 0);
+        self.config.domain_config.write_quota = Some(per_sec);
+    }
+
+    /// Turn reads away with `ViewError::QuotaExceeded` that would have a client connection wait
+    /// on more than `n` missed keys at a time.
+    ///
+    /// This keeps a single client from flooding the data-flow with upqueries. A read that misses
+    /// on more than `n` keys is still served if the connection is not waiting on anything else.
+    pub fn set_max_client_upqueries(&mut self, n: usize) {
+        assert_ne!(n, 0);
+        self.config.max_client_upqueries = Some(n);
+    }
+
+    /// Return at most `n` rows for each key that a lookup reads.
+    ///
+    /// Results that are cut off say so with `Results::is_truncated`, and all of their rows can
+    /// still be read in chunks with `View::lookup_chunk`. This keeps a key with a huge number of
+    /// rows from being serialized into a single reply. Reads made with `View::lookup_after` are
+    /// not capped.
+    pub fn set_max_lookup_rows(&mut self, n: usize) {
+        assert_ne!(n, 0);
+        self.config.max_lookup_rows = Some(n);
+    }
+
+    /// Only answer the controller requests, and accept the writes, of clients that
+    /// `authenticator` accepts.
+    ///
+    /// The identity a client authenticates as selects the user universe whose views it reads, if
+    /// one has been created for it, and is the writer of the writes it makes. Workers check the
+    /// credentials of writers themselves, so every instance of a deployment must be given the
+    /// same authenticator. The handle that
+    /// `start` returns is a client like any other, and must `authenticate` before it is used.
+    /// Lookups over HTTP carry no credentials, so they are turned away.
+    ///
+    /// Reads from views are not authenticated: any client that can reach a worker's read port
+    /// can read any view, including the views of other users' universes. Use `set_tls` with
+    /// client verification to keep unknown clients from connecting at all.
+    pub fn set_authenticator(&mut self, authenticator: Arc<dyn Authenticator>) {
+        self.authenticator = Some(authenticator);
+    }
+
+    /// Encrypt every connection to, from, and between workers with `tls`.
+    ///
+    /// This turns TLS on for the whole process, as `noria::set_tls` does, so clients in the same
+    /// process use it too. Workers must have an identity to present to the clients that connect
+    /// to them.
+    pub fn set_tls(&mut self, tls: TlsConfig) -> Result<(), failure::Error> {
+        if !tls.has_identity() {
+            bail!("workers need a certificate and key to accept TLS connections with");
+        }
+        noria::set_tls(tls)
+    }
+
+    /// Serve view lookups as JSON on `port` of the listen address, at `GET /view/{name}/{key}`.
+    ///
+    /// This is meant for quick integrations and debugging; clients that care about performance
+    /// should use `View` instead. A port of 0 picks a free port, which
+    /// `Handle::http_reads_addr` reports.
+    pub fn enable_http_reads(&mut self, port: u16) {
+        self.config.http_reads_port = Some(port);
+    }
+
+    /// Let HTTP proxies cache the results of blocking HTTP lookups for `max_age`.
+    ///
+    /// Cached results may miss writes made within `max_age` of the lookup.
+    pub fn set_http_reads_max_age(&mut self, max_age: time::Duration) {
+        self.config.http_reads_max_age = max_age;
+    }
+
+    /// Only serve `views` over HTTP.
+    ///
+    /// HTTP lookups are not authenticated, so by default their results are only served to pages
+    /// of the same origin and may not be cached by shared proxies. The views named here are meant
+    /// for anyone to read: their results may be read from any origin and cached by proxies, and
+    /// all other views are not served.
+    pub fn set_http_reads_views<I, S>(&mut self, views: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.http_reads_views = Some(views.into_iter().map(Into::into).collect());
+    }
+
     /// Set the number of pool threads to use (default is #cores)
     pub fn set_threads(&mut self, threads: usize) {
         self.config.threads = Some(threads);
@@ -113,10 +337,12 @@ impl Builder {
             ref config,
             memory_limit,
             memory_check_frequency,
+            ref authenticator,
             ref log,
         } = *self;
 
         let config = config.clone();
+        let authenticator = authenticator.clone();
         let log = log.clone();
 
         crate::startup::start_instance(
@@ -125,6 +351,7 @@ impl Builder {
             config,
             memory_limit,
             memory_check_frequency,
+            authenticator,
             log,
         )
     }
@@ -0,0 +1,64 @@
+use crate::controller::keys::provenance_of;
+use dataflow::node::special::ReaderAssertion;
+use dataflow::prelude::*;
+use noria::Assertion;
+
+/// An assertion added through `ControllerHandle::add_assertion`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct AssertionSpec {
+    pub(crate) name: String,
+    pub(crate) view: String,
+    pub(crate) assertion: Assertion,
+    pub(crate) halt: bool,
+}
+
+impl AssertionSpec {
+    /// The assertion as checked by a reader with the given columns.
+    pub(super) fn compile(&self, fields: &[String]) -> Result<ReaderAssertion, String> {
+        let column = fields
+            .iter()
+            .position(|f| f == self.assertion.column())
+            .ok_or_else(|| {
+                format!(
+                    "view {} has no column {}",
+                    self.view,
+                    self.assertion.column()
+                )
+            })?;
+        Ok(ReaderAssertion {
+            name: self.name.clone(),
+            assertion: self.assertion.clone(),
+            column,
+            halt: self.halt,
+        })
+    }
+}
+
+/// The names of the operators that the given column of `reader` passes through, from the reader up
+/// to the node that produces the column's values, for every path the column may take.
+///
+/// Nodes that only move records between domains and shards are left out.
+pub(super) fn column_provenance(
+    graph: &Graph,
+    reader: NodeIndex,
+    column: usize,
+) -> Vec<Vec<String>> {
+    provenance_of(graph, reader, &[column], |_, _, _| None)
+        .into_iter()
+        .map(|path| {
+            // the column originates at the last node on the path that still has it
+            let origin = path
+                .iter()
+                .rposition(|&(_, ref cols)| cols[0].is_some())
+                .unwrap_or(0);
+            path[..=origin]
+                .iter()
+                .filter(|&&(ni, _)| {
+                    let n = &graph[ni];
+                    n.is_reader() || n.is_internal() || n.is_base()
+                })
+                .map(|&(ni, _)| graph[ni].name().to_owned())
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,338 @@
+use crate::controller::recipe::{Alteration, Recipe, Removal};
+use nom_sql::{
+    CompoundSelectOperator, CompoundSelectStatement, ConditionBase, ConditionExpression,
+    JoinConstraint, JoinOperator, JoinRightSide, Operator, SelectSpecification, SelectStatement,
+    SqlQuery,
+};
+use noria::{UnsupportedFeature, UnsupportedStatement};
+use std::collections::HashSet;
+
+/// Find the statements in `recipe_text` that use constructs that Noria cannot convert into a
+/// dataflow, so that they can be rejected before conversion panics on them.
+///
+/// Unlike linting, this only flags constructs that conversion does not handle at all. Statements
+/// that do not parse are left to the recipe, which rejects them with the parse error.
+pub(super) fn check_recipe(recipe_text: &str) -> Vec<UnsupportedStatement> {
+    Recipe::parse_statements(recipe_text)
+        .into_iter()
+        .filter_map(|(statement, parsed)| {
+            // the recipe applies `ALTER TABLE` and `DROP` itself
+            if Alteration::parse(&statement).is_some() || Removal::parse(&statement).is_some() {
+                return None;
+            }
+            let (name, query) = parsed.ok()?;
+
+            let features = {
+                let (_, sql) = Recipe::split_name(&statement);
+                let mut check = Check {
+                    sql,
+                    base: statement.len() - sql.len(),
+                    features: Vec::new(),
+                };
+                check.query(&query);
+                check.features
+            };
+            if features.is_empty() {
+                return None;
+            }
+            Some(UnsupportedStatement {
+                name,
+                statement,
+                features,
+            })
+        })
+        .collect()
+}
+
+/// The unsupported constructs found in a single statement so far.
+struct Check<'a> {
+    /// The statement's SQL, without the name it is given in the recipe.
+    sql: &'a str,
+    /// The offset of `sql` in the statement.
+    base: usize,
+    features: Vec<UnsupportedFeature>,
+}
+
+impl Check<'_> {
+    /// Flag `feature`, locating it at the first of the fragments in `near` found in the SQL.
+    fn unsupported(&mut self, feature: &str, near: &[&str]) {
+        let offset = near
+            .iter()
+            .filter_map(|fragment| find(self.sql, fragment))
+            .next()
+            .map(|offset| self.base + offset);
+        self.features.push(UnsupportedFeature {
+            feature: feature.to_owned(),
+            offset,
+        });
+    }
+
+    fn query(&mut self, query: &SqlQuery) {
+        match *query {
+            SqlQuery::CreateTable(_) => (),
+            SqlQuery::CreateView(ref cvq) => match *cvq.definition {
+                SelectSpecification::Simple(ref sq) => self.select(sq),
+                SelectSpecification::Compound(ref csq) => self.compound(csq),
+            },
+            SqlQuery::Select(ref sq) => self.select(sq),
+            SqlQuery::CompoundSelect(ref csq) => self.compound(csq),
+            _ => {
+                let first = self.sql.split_whitespace().next().unwrap_or("");
+                self.unsupported(
+                    "only CREATE TABLE, CREATE VIEW and SELECT statements can be part of a recipe",
+                    &[first],
+                );
+            }
+        }
+    }
+
+    fn compound(&mut self, csq: &CompoundSelectStatement) {
+        let ops: HashSet<_> = csq
+            .selects
+            .iter()
+            .filter_map(|&(ref op, _)| op.clone())
+            .collect();
+        if ops.contains(&CompoundSelectOperator::Intersect)
+            || ops.contains(&CompoundSelectOperator::Except)
+        {
+            self.unsupported(
+                "INTERSECT and EXCEPT are not supported",
+                &["INTERSECT", "EXCEPT"],
+            );
+        } else if ops.len() > 1 {
+            self.unsupported("mixing UNION and UNION ALL is not supported", &["UNION"]);
+        }
+
+        for &(_, ref sq) in &csq.selects {
+            self.select(sq);
+        }
+    }
+
+    fn select(&mut self, sq: &SelectStatement) {
+        for jc in &sq.join {
+            match jc.operator {
+                JoinOperator::Join
+                | JoinOperator::InnerJoin
+                | JoinOperator::LeftJoin
+                | JoinOperator::LeftOuterJoin => (),
+                ref op => {
+                    let op = op.to_string();
+                    self.unsupported(&format!("{} is not supported", op), &[op.as_str(), "JOIN"]);
+                }
+            }
+            match jc.right {
+                JoinRightSide::Table(_) => (),
+                JoinRightSide::NestedSelect(ref ns, _) => self.select(ns),
+                JoinRightSide::Tables(_) | JoinRightSide::NestedJoin(_) => self.unsupported(
+                    "joins may only be against a single table or subquery",
+                    &["JOIN"],
+                ),
+            }
+            match jc.constraint {
+                JoinConstraint::On(ConditionExpression::ComparisonOp(ref ct)) => {
+                    match (&*ct.left, &*ct.right) {
+                        (
+                            ConditionExpression::Base(ConditionBase::Field(_)),
+                            ConditionExpression::Base(ConditionBase::Field(_)),
+                        ) => (),
+                        _ => self.unsupported(
+                            "join conditions must compare two columns",
+                            &[ct.to_string().as_str(), "ON"],
+                        ),
+                    }
+                }
+                JoinConstraint::On(ref cond) => self.unsupported(
+                    "join conditions must be a single comparison",
+                    &[cond.to_string().as_str(), "ON"],
+                ),
+                JoinConstraint::Using(ref cols) => {
+                    if cols.len() != 1 {
+                        self.unsupported("USING joins must name exactly one column", &["USING"]);
+                    }
+                }
+            }
+        }
+
+        if let Some(ref cond) = sq.where_clause {
+            let tables: Vec<_> = sq.tables.iter().map(|t| t.name.clone()).collect();
+            self.condition(cond, &tables);
+        }
+    }
+
+    fn condition(&mut self, cond: &ConditionExpression, tables: &[String]) {
+        match *cond {
+            ConditionExpression::LogicalOp(ref ct) => {
+                self.condition(&ct.left, tables);
+                self.condition(&ct.right, tables);
+            }
+            ConditionExpression::ComparisonOp(ref ct) => {
+                let written = ct.to_string();
+                let near = [written.as_str(), "WHERE"];
+                let l = match *ct.left {
+                    ConditionExpression::Base(ConditionBase::Field(ref f)) => f,
+                    _ => {
+                        return self.unsupported(
+                            "the left-hand side of a comparison must be a column",
+                            &near,
+                        );
+                    }
+                };
+                match *ct.right {
+                    ConditionExpression::Base(ConditionBase::Field(ref r)) => {
+                        // a comparison between columns of two of the tables is a comma join
+                        let joins = |c: &nom_sql::Column| {
+                            c.table.as_ref().map_or(false, |t| tables.contains(t))
+                        };
+                        if joins(l)
+                            && joins(r)
+                            && ct.operator != Operator::Equal
+                            && ct.operator != Operator::In
+                        {
+                            self.unsupported("joins must compare columns for equality", &near);
+                        }
+                    }
+                    ConditionExpression::Base(ConditionBase::NestedSelect(ref ns)) => {
+                        self.select(ns)
+                    }
+                    ConditionExpression::Base(_) => (),
+                    // NOT IN
+                    ConditionExpression::NegationOp(ref inner) if ct.operator == Operator::In => {
+                        match **inner {
+                            ConditionExpression::Base(ConditionBase::NestedSelect(ref ns)) => {
+                                self.select(ns)
+                            }
+                            _ => self.unsupported(
+                                "NOT IN is only supported with a subquery",
+                                &["NOT IN", "WHERE"],
+                            ),
+                        }
+                    }
+                    _ => self.unsupported(
+                        "the right-hand side of a comparison must be a column, literal, or \
+                         subquery",
+                        &near,
+                    ),
+                }
+            }
+            ConditionExpression::NegationOp(ref inner)
+            | ConditionExpression::Bracketed(ref inner) => self.condition(inner, tables),
+            ConditionExpression::Arithmetic(ref ae) => self.unsupported(
+                "arithmetic in conditions is not supported",
+                &[ae.to_string().as_str(), "WHERE"],
+            ),
+            ConditionExpression::Base(ref base) => self.unsupported(
+                "conditions must be comparisons",
+                &[base.to_string().as_str(), "WHERE"],
+            ),
+        }
+    }
+}
+
+/// The byte offset of the first occurrence of `fragment` in `sql` that is not inside a string
+/// literal, ignoring case.
+///
+/// Fragments that start or end with a word character only match at word boundaries, so that
+/// `ON` is not found in `person`.
+fn find(sql: &str, fragment: &str) -> Option<usize> {
+    if fragment.is_empty() {
+        return None;
+    }
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let lower = sql.to_ascii_lowercase();
+    let fragment = fragment.to_ascii_lowercase();
+    let word_start = fragment.starts_with(is_word);
+    let word_end = fragment.ends_with(is_word);
+
+    let mut quote = None;
+    let mut prev = None;
+    for (i, c) in sql.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if lower[i..].starts_with(&fragment) => {
+                let end = i + fragment.len();
+                let starts = !word_start || !prev.map_or(false, is_word);
+                let ends = !word_end || !sql[end..].starts_with(is_word);
+                if starts && ends {
+                    return Some(i);
+                }
+            }
+            None => (),
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(recipe: &str) -> Vec<(String, Option<usize>)> {
+        check_recipe(recipe)
+            .into_iter()
+            .flat_map(|s| s.features)
+            .map(|f| (f.feature, f.offset))
+            .collect()
+    }
+
+    #[test]
+    fn it_accepts_supported_statements() {
+        let unsupported = check_recipe(
+            "CREATE TABLE vote (aid int, uid int);
+             ALTER TABLE vote ADD COLUMN ts int DEFAULT 0;
+             QUERY votes: SELECT aid, COUNT(uid) AS n FROM vote WHERE aid = ? GROUP BY aid;
+             QUERY mine: SELECT aid FROM vote WHERE uid = ? ORDER BY aid;
+             DROP VIEW votes;",
+        );
+        assert!(unsupported.is_empty(), "{:?}", unsupported);
+    }
+
+    #[test]
+    fn it_locates_unsupported_constructs() {
+        let recipe = "QUERY q: SELECT t.a FROM t CROSS JOIN u ON t.a = u.a WHERE t.b > ?;";
+        let unsupported = check_recipe(recipe);
+        assert_eq!(unsupported.len(), 1);
+        assert_eq!(unsupported[0].name, Some("q".to_owned()));
+        assert_eq!(
+            unsupported[0].features,
+            vec![UnsupportedFeature {
+                feature: "CROSS JOIN is not supported".to_owned(),
+                offset: recipe.find("CROSS"),
+            }]
+        );
+    }
+
+    #[test]
+    fn it_flags_unsupported_conditions() {
+        let recipe = "QUERY q: SELECT a FROM t WHERE a = ? AND 1 = b;";
+        assert_eq!(
+            features(recipe),
+            vec![(
+                "the left-hand side of a comparison must be a column".to_owned(),
+                recipe.find("1 = b"),
+            )]
+        );
+        assert_eq!(
+            features("QUERY q: SELECT t.a FROM t, u WHERE t.a < u.a;").len(),
+            1
+        );
+        assert_eq!(
+            features("SELECT a FROM t WHERE a = 1 INTERSECT SELECT a FROM u WHERE a = 2;").len(),
+            1
+        );
+        assert_eq!(features("INSERT INTO t (a) VALUES (1);").len(), 1);
+    }
+
+    #[test]
+    fn it_finds_fragments_outside_literals() {
+        assert_eq!(
+            find("SELECT person FROM t WHERE x = 'on' ON", "ON"),
+            Some(36)
+        );
+        assert_eq!(find("select a from t where a = 1", "WHERE"), Some(16));
+        assert_eq!(find("SELECT a FROM t", "WHERE"), None);
+        assert_eq!(find("SELECT a FROM t", ""), None);
+    }
+}
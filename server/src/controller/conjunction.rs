@@ -0,0 +1,119 @@
+use dataflow::prelude::DataType;
+use nom_sql::{Column, ConditionBase, ConditionExpression, Operator};
+
+/// A value that a column of a table can be compared with in a compiled conjunction.
+pub(in crate::controller) trait Operand: Sized {
+    /// A literal value.
+    fn constant(value: DataType) -> Self;
+
+    /// Another column of the same row.
+    fn column(index: usize) -> Self;
+
+    /// The value that `column` names, if it names something other than a column of the table.
+    fn named(_column: &Column) -> Option<Self> {
+        None
+    }
+}
+
+/// A predicate over the rows of a table that is a conjunction of comparisons between its columns
+/// and other values, like those of `CHECK` constraints and write policies.
+pub(in crate::controller) struct Conjunction<'a> {
+    /// What the predicate belongs to, as in `constraint` or `write policy`.
+    pub(in crate::controller) kind: &'static str,
+    pub(in crate::controller) name: &'a str,
+    pub(in crate::controller) table: &'a str,
+    /// The columns of the table.
+    pub(in crate::controller) fields: &'a [String],
+}
+
+impl Conjunction<'_> {
+    /// Compile `predicate` into the comparisons it is a conjunction of.
+    pub(in crate::controller) fn compile<V: Operand>(
+        &self,
+        predicate: &ConditionExpression,
+    ) -> Result<Vec<(usize, Operator, V)>, String> {
+        let mut conditions = Vec::new();
+        self.compile_into(predicate, &mut conditions)?;
+        Ok(conditions)
+    }
+
+    fn compile_into<V: Operand>(
+        &self,
+        ce: &ConditionExpression,
+        conditions: &mut Vec<(usize, Operator, V)>,
+    ) -> Result<(), String> {
+        match *ce {
+            ConditionExpression::LogicalOp(ref ct) if ct.operator == Operator::And => {
+                self.compile_into(&ct.left, conditions)?;
+                self.compile_into(&ct.right, conditions)
+            }
+            ConditionExpression::Bracketed(ref inner) => self.compile_into(inner, conditions),
+            ConditionExpression::ComparisonOp(ref ct) => {
+                match ct.operator {
+                    Operator::Equal
+                    | Operator::NotEqual
+                    | Operator::Greater
+                    | Operator::GreaterOrEqual
+                    | Operator::Less
+                    | Operator::LessOrEqual => {}
+                    ref op => {
+                        return Err(format!(
+                            "{} {} uses unsupported comparison {:?}",
+                            self.kind, self.name, op
+                        ))
+                    }
+                }
+                let col = match *ct.left {
+                    ConditionExpression::Base(ConditionBase::Field(ref c)) => {
+                        self.column_index(&c.name, c.table.as_ref())?
+                    }
+                    ref e => {
+                        return Err(format!(
+                            "{} {} compares non-column {:?}",
+                            self.kind, self.name, e
+                        ))
+                    }
+                };
+                let value = match *ct.right {
+                    ConditionExpression::Base(ConditionBase::Literal(ref l)) => {
+                        V::constant(DataType::from(l))
+                    }
+                    ConditionExpression::Base(ConditionBase::Field(ref c)) => match V::named(c) {
+                        Some(value) => value,
+                        None => V::column(self.column_index(&c.name, c.table.as_ref())?),
+                    },
+                    ref e => {
+                        return Err(format!(
+                            "{} {} compares against unsupported {:?}",
+                            self.kind, self.name, e
+                        ))
+                    }
+                };
+                conditions.push((col, ct.operator.clone(), value));
+                Ok(())
+            }
+            ref e => Err(format!(
+                "{} {} is not a conjunction of comparisons: {:?}",
+                self.kind, self.name, e
+            )),
+        }
+    }
+
+    fn column_index(&self, column: &str, table: Option<&String>) -> Result<usize, String> {
+        if table.map(|t| t != self.table).unwrap_or(false) {
+            return Err(format!(
+                "{} {} refers to a column of another table: {}.{}",
+                self.kind,
+                self.name,
+                table.unwrap(),
+                column
+            ));
+        }
+        self.fields.iter().position(|f| f == column).ok_or_else(|| {
+            format!(
+                "{} {} refers to unknown column {}.{}",
+                self.kind, self.name, self.table, column
+            )
+        })
+    }
+}
@@ -0,0 +1,111 @@
+use crate::controller::conjunction::{Conjunction, Operand};
+use dataflow::node::special::{CheckValue, TableConstraint};
+use dataflow::prelude::DataType;
+use nom_sql::parser as sql_parser;
+use nom_sql::{ColumnConstraint, ConditionExpression, CreateTableStatement, SqlQuery};
+
+/// A `CHECK` constraint on a base table, declared with `ALTER TABLE table ADD [CONSTRAINT name]
+/// CHECK (predicate)`.
+///
+/// The predicate is a conjunction of comparisons between columns of the table and literals or
+/// other columns, e.g., `(price >= 0 AND discount <= price)`.
+#[derive(Clone, Debug, PartialEq)]
+pub(in crate::controller) struct CheckConstraint {
+    pub(in crate::controller) name: String,
+    pub(in crate::controller) table: String,
+    pub(in crate::controller) predicate: ConditionExpression,
+}
+
+impl CheckConstraint {
+    /// Parse the predicate of a check on `table`.
+    pub(in crate::controller) fn parse_predicate(
+        table: &str,
+        predicate: &str,
+    ) -> Option<ConditionExpression> {
+        // the parser only knows predicates as part of a query
+        match sql_parser::parse_query(&format!("SELECT * FROM {} WHERE {};", table, predicate)) {
+            Ok(SqlQuery::Select(sq)) => sq.where_clause,
+            _ => None,
+        }
+    }
+
+    /// Compile the check into a form that can be checked by the table's base node, whose columns
+    /// are `fields`.
+    pub(in crate::controller) fn compile(
+        &self,
+        fields: &[String],
+    ) -> Result<TableConstraint, String> {
+        let conditions = Conjunction {
+            kind: "constraint",
+            name: &self.name,
+            table: &self.table,
+            fields,
+        }
+        .compile(&self.predicate)?;
+        Ok(TableConstraint::Check {
+            name: self.name.clone(),
+            conditions,
+        })
+    }
+}
+
+impl Operand for CheckValue {
+    fn constant(value: DataType) -> Self {
+        CheckValue::Constant(value)
+    }
+
+    fn column(index: usize) -> Self {
+        CheckValue::Column(index)
+    }
+}
+
+/// The constraints that the columns of the table created by `ctq` are declared with, as checked
+/// by the table's base node, whose columns are `fields`.
+pub(in crate::controller) fn column_constraints(
+    ctq: &CreateTableStatement,
+    fields: &[String],
+) -> Vec<TableConstraint> {
+    ctq.fields
+        .iter()
+        .filter(|f| f.constraints.contains(&ColumnConstraint::NotNull))
+        .filter_map(|f| {
+            let column = fields.iter().position(|c| *c == f.column.name)?;
+            Some(TableConstraint::NotNull {
+                name: format!("{} NOT NULL", f.column.name),
+                column,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_compiles_checks() {
+        let check = CheckConstraint {
+            name: "sane_price".to_owned(),
+            table: "item".to_owned(),
+            predicate: CheckConstraint::parse_predicate(
+                "item",
+                "(price >= 0 AND item.discount <= price)",
+            )
+            .unwrap(),
+        };
+        let fields = vec!["id".to_owned(), "price".to_owned(), "discount".to_owned()];
+        let compiled = check.compile(&fields).unwrap();
+        assert_eq!(compiled.name(), "sane_price");
+        assert!(compiled.holds(&[1.into(), 10.into(), 5.into()]));
+        assert!(!compiled.holds(&[1.into(), (-1).into(), (-5).into()]));
+        assert!(!compiled.holds(&[1.into(), 10.into(), 20.into()]));
+
+        // only columns of the table can be checked
+        assert!(check.compile(&fields[..2]).is_err());
+        let other = CheckConstraint {
+            predicate: CheckConstraint::parse_predicate("item", "other.price >= 0").unwrap(),
+            ..check
+        };
+        assert!(other.compile(&fields).is_err());
+    }
+}
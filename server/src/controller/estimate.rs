@@ -0,0 +1,227 @@
+use crate::controller::lint;
+use crate::controller::recipe::Recipe;
+use nom_sql::{
+    ConditionBase, ConditionExpression, FieldDefinitionExpression, JoinRightSide, Literal,
+    SelectSpecification, SelectStatement, SqlQuery,
+};
+use noria::{QueryEstimate, StateGrowth};
+
+/// Estimate what adding `query` would cost.
+///
+/// `sharding` and `partial` reflect the settings of this deployment, and `state_bytes` gives the
+/// current size of the state of the table or view with the given name, or `None` if there is no
+/// such table or view.
+///
+/// The state of a view is assumed to be at most as large as the state of its inputs, which holds
+/// unless it joins many rows of one input with many rows of another.
+pub(super) fn estimate_query<F>(
+    query: &str,
+    sharding: Option<usize>,
+    partial: bool,
+    state_bytes: F,
+) -> Result<QueryEstimate, String>
+where
+    F: Fn(&str) -> Option<u64>,
+{
+    let mut statements = Recipe::parse_statements(query);
+    if statements.len() != 1 {
+        return Err("expected a single query".to_owned());
+    }
+    let (_, parsed) = statements.pop().unwrap();
+    let (_, parsed) = parsed?;
+
+    let lint = lint::lint_recipe(query, sharding.is_some(), partial, |t| {
+        state_bytes(t).is_some()
+    })
+    .pop()
+    .unwrap();
+    if !lint.unsupported.is_empty() {
+        return Err(lint.unsupported.join("; "));
+    }
+
+    let selects: Vec<&SelectStatement> = match parsed {
+        SqlQuery::Select(ref sq) => vec![sq],
+        SqlQuery::CompoundSelect(ref csq) => csq.selects.iter().map(|&(_, ref sq)| sq).collect(),
+        SqlQuery::CreateView(ref cvq) => match *cvq.definition {
+            SelectSpecification::Simple(ref sq) => vec![sq],
+            SelectSpecification::Compound(ref csq) => {
+                csq.selects.iter().map(|&(_, ref sq)| sq).collect()
+            }
+        },
+        _ => return Err("only SELECT statements can be estimated".to_owned()),
+    };
+
+    let mut inputs = Vec::new();
+    let mut nodes = selects
+        .iter()
+        .map(|sq| count_nodes(sq, &mut inputs))
+        .sum::<usize>();
+    if selects.len() > 1 {
+        // the union of the branches
+        nodes += 1;
+    }
+    // the reader
+    nodes += 1;
+
+    let partial = lint.full_materialization.is_empty();
+    let shards = match sharding {
+        Some(shards) if lint.unsharded.is_empty() => shards,
+        _ => 1,
+    };
+    let read_amplification = if partial {
+        // a miss is replayed from every input, and from every shard of each input unless the
+        // view is sharded the same way
+        let fanout = match sharding {
+            Some(shards) if !lint.unsharded.is_empty() => shards,
+            _ => 1,
+        };
+        1 + inputs.len() * fanout
+    } else {
+        1
+    };
+
+    Ok(QueryEstimate {
+        state_bytes: match lint.state_growth {
+            StateGrowth::None => 0,
+            _ => inputs.iter().map(|t| state_bytes(t).unwrap_or(0)).sum(),
+        },
+        nodes,
+        shards,
+        read_amplification,
+        partial,
+    })
+}
+
+/// Count the operators needed to compute `sq`, and collect the tables and views it reads from.
+fn count_nodes(sq: &SelectStatement, inputs: &mut Vec<String>) -> usize {
+    let mut nodes = 0;
+    let mut sources = 0;
+
+    for t in &sq.tables {
+        inputs.push(t.name.clone());
+        sources += 1;
+    }
+    for jc in &sq.join {
+        match jc.right {
+            JoinRightSide::Table(ref t) => {
+                inputs.push(t.name.clone());
+                sources += 1;
+            }
+            JoinRightSide::Tables(ref ts) => {
+                inputs.extend(ts.iter().map(|t| t.name.clone()));
+                sources += ts.len();
+            }
+            JoinRightSide::NestedSelect(ref ns, _) => {
+                nodes += count_nodes(ns, inputs);
+                sources += 1;
+            }
+            JoinRightSide::NestedJoin(_) => sources += 1,
+        }
+    }
+    // every source beyond the first is joined in
+    nodes += sources.saturating_sub(1);
+
+    if let Some(ref cond) = sq.where_clause {
+        let (filters, subqueries) = count_conditions(cond, inputs);
+        if filters {
+            nodes += 1;
+        }
+        nodes += subqueries;
+    }
+
+    let aggregates = sq.fields.iter().any(|f| match *f {
+        FieldDefinitionExpression::Col(ref c) => c.function.is_some(),
+        _ => false,
+    });
+    if aggregates || sq.group_by.is_some() {
+        nodes += 1;
+    }
+    if sq.limit.is_some() {
+        nodes += 1;
+    }
+
+    // the projection that produces the query's columns
+    nodes + 1
+}
+
+/// Whether `cond` filters rows, rather than only binding parameters, and how many operators its
+/// subqueries need.
+fn count_conditions(cond: &ConditionExpression, inputs: &mut Vec<String>) -> (bool, usize) {
+    match *cond {
+        ConditionExpression::LogicalOp(ref ct) => {
+            let (lf, ln) = count_conditions(&ct.left, inputs);
+            let (rf, rn) = count_conditions(&ct.right, inputs);
+            (lf || rf, ln + rn)
+        }
+        ConditionExpression::ComparisonOp(ref ct) => match *ct.right {
+            ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => (false, 0),
+            ConditionExpression::Base(ConditionBase::NestedSelect(ref ns)) => {
+                (true, count_nodes(ns, inputs))
+            }
+            _ => (true, 0),
+        },
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            count_conditions(inner, inputs)
+        }
+        _ => (true, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(t: &str) -> Option<u64> {
+        match t {
+            "article" => Some(1000),
+            "vote" => Some(500),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn it_estimates_a_partial_join() {
+        let e = estimate_query(
+            "QUERY awv: SELECT article.id, article.title, vote.uid \
+             FROM article JOIN vote ON (article.id = vote.aid) WHERE article.id = ?;",
+            None,
+            true,
+            sizes,
+        )
+        .unwrap();
+        assert!(e.partial);
+        assert_eq!(e.state_bytes, 1500);
+        // join, projection, reader
+        assert_eq!(e.nodes, 3);
+        assert_eq!(e.shards, 1);
+        assert_eq!(e.read_amplification, 3);
+    }
+
+    #[test]
+    fn it_estimates_an_unparameterized_aggregate() {
+        let e = estimate_query(
+            "SELECT COUNT(uid) AS votes FROM vote WHERE uid > 10;",
+            Some(2),
+            true,
+            sizes,
+        )
+        .unwrap();
+        assert!(!e.partial);
+        // filter, aggregation, projection, reader
+        assert_eq!(e.nodes, 4);
+        assert_eq!(e.shards, 1);
+        assert_eq!(e.read_amplification, 1);
+    }
+
+    #[test]
+    fn it_rejects_unknown_tables() {
+        assert!(estimate_query("SELECT id FROM nope WHERE id = ?;", None, true, sizes).is_err());
+        assert!(estimate_query(
+            "SELECT id FROM article WHERE id = ?; SELECT uid FROM vote WHERE aid = ?;",
+            None,
+            true,
+            sizes
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use noria::ControllerEvent;
+use slog::Logger;
+
+/// `POST` the given event to `url` as JSON.
+///
+/// The request is made in the background, so that a slow or unavailable webhook does not hold up
+/// the controller. Events whose delivery fails are not retried; they remain in the event log.
+pub(super) fn post_to_webhook(log: &Logger, url: &str, event: &ControllerEvent) {
+    let req = serde_json::to_vec(event)
+        .map_err(|e| e.to_string())
+        .and_then(|body| {
+            hyper::Request::post(url)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(hyper::Body::from(body))
+                .map_err(|e| e.to_string())
+        });
+    let req = match req {
+        Ok(req) => req,
+        Err(e) => {
+            warn!(log, "failed to build event webhook request: {}", e; "url" => url);
+            return;
+        }
+    };
+
+    let log = log.clone();
+    let url = url.to_owned();
+    tokio::spawn(async move {
+        match hyper::Client::new().request(req).await {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => {
+                warn!(log, "event webhook returned {}", res.status(); "url" => url);
+            }
+            Err(e) => {
+                warn!(log, "event webhook failed: {}", e; "url" => url);
+            }
+        }
+    });
+}
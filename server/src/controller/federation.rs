@@ -0,0 +1,113 @@
+use noria::{ControllerHandle, DataType, Table, TableOperation, ZookeeperAuthority};
+use std::collections::HashMap;
+
+/// Connections to the tables of other deployments that federated views are written to.
+#[derive(Default)]
+pub(super) struct Shadows {
+    /// Tables by the ZooKeeper address of their deployment and their name.
+    tables: HashMap<(String, String), Table>,
+}
+
+impl Shadows {
+    /// Apply the changes to a federated view to `table` in the deployment at `zookeeper`.
+    pub(super) fn federate(
+        &mut self,
+        zookeeper: &str,
+        table: &str,
+        changes: &[(Vec<DataType>, bool)],
+    ) -> Result<(), String> {
+        let key = (zookeeper.to_owned(), table.to_owned());
+        let pkey = self
+            .connect(&key)
+            .map_err(|e| format!("failed to connect to federated table {}: {}", table, e))?
+            .primary_key()
+            .map(<[usize]>::to_vec)
+            .ok_or_else(|| format!("federated table {} has no primary key", table))?;
+
+        let ops = federated_writes(changes, &pkey);
+        if ops.is_empty() {
+            return Ok(());
+        }
+        self.perform(&key, ops)
+            .map_err(|e| format!("failed to write to federated table {}: {}", table, e))
+    }
+
+    /// The table with the given ZooKeeper address and name, connecting to it if necessary.
+    fn connect(&mut self, key: &(String, String)) -> Result<&mut Table, failure::Error> {
+        if !self.tables.contains_key(key) {
+            let (ref zookeeper, ref table) = *key;
+            let t = futures_executor::block_on(async {
+                let mut ch = ControllerHandle::<ZookeeperAuthority>::from_zk(zookeeper).await?;
+                ch.ready().await?;
+                ch.table(table).await
+            })?;
+            self.tables.insert(key.clone(), t);
+        }
+        Ok(self.tables.get_mut(key).unwrap())
+    }
+
+    /// Perform `ops` on a table that `connect` returned.
+    fn perform(&mut self, key: &(String, String), ops: Vec<TableOperation>) -> Result<(), String> {
+        let t = self.tables.get_mut(key).unwrap();
+        if let Err(e) = futures_executor::block_on(t.perform_all(ops)) {
+            // the table may have moved, so reconnect next time around
+            self.tables.remove(key);
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The writes that bring a table whose primary key is `pkey` in line with the given changes to a
+/// view.
+///
+/// Rows that are added and then removed again cancel out. The remaining deletions are issued
+/// before the insertions, so that a row that replaces another with the same key is not lost.
+fn federated_writes(changes: &[(Vec<DataType>, bool)], pkey: &[usize]) -> Vec<TableOperation> {
+    let mut added: Vec<&[DataType]> = Vec::new();
+    let mut removed = Vec::new();
+    for (row, positive) in changes {
+        if *positive {
+            added.push(row);
+        } else if let Some(i) = added.iter().position(|r| *r == &row[..]) {
+            added.remove(i);
+        } else {
+            removed.push(TableOperation::Delete {
+                key: pkey.iter().map(|&c| row[c].clone()).collect(),
+            });
+        }
+    }
+    removed.extend(
+        added
+            .into_iter()
+            .map(|r| TableOperation::Insert(r.to_vec())),
+    );
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn federated_writes_replace_rows() {
+        let row = |id: i32, v: &str| vec![DataType::from(id), DataType::from(v)];
+        let changes = vec![
+            (row(1, "a"), true),
+            (row(2, "b"), true),
+            (row(2, "b"), false),
+            (row(3, "c"), false),
+            (row(3, "d"), true),
+        ];
+        assert_eq!(
+            federated_writes(&changes, &[0]),
+            vec![
+                TableOperation::Delete {
+                    key: vec![3.into()]
+                },
+                TableOperation::Insert(row(1, "a")),
+                TableOperation::Insert(row(3, "d")),
+            ]
+        );
+    }
+}
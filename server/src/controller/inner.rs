@@ -1,29 +1,76 @@
+use crate::controller::assertions::{self, AssertionSpec};
+use crate::controller::capabilities;
+use crate::controller::constraints;
 use crate::controller::domain_handle::{DomainHandle, DomainShardHandle};
-use crate::controller::migrate::materialization::Materializations;
-use crate::controller::recipe::Schema;
+use crate::controller::estimate;
+use crate::controller::events;
+use crate::controller::federation::Shadows;
+use crate::controller::kafka::KafkaConnectors;
+use crate::controller::lint;
+use crate::controller::migrate::materialization::{Backfill, Materializations};
+use crate::controller::oracle;
+use crate::controller::pass_through;
+use crate::controller::progress::MigrationProgress;
+use crate::controller::recipe::{self, ForeignKey, Schema, Ttl};
 use crate::controller::schema;
-use crate::controller::{ControllerState, Migration, Recipe};
+use crate::controller::security::SecurityConfig;
+use crate::controller::sql::cost::TableStatistics;
+use crate::controller::triggers::{self, PendingFiring, PendingFirings, RunningActions};
+use crate::controller::triggers::{TriggerSpec, TriggerState};
+use crate::controller::view_names::{self, NameChange, ViewNames};
+use crate::controller::warming::Warming;
+use crate::controller::{ControllerState, Migration, PendingMigration, Recipe, RecipeSnapshot};
 use crate::controller::{Worker, WorkerIdentifier};
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use crate::startup::Event;
+use dataflow::ops::project::Project;
+use dataflow::ops::trigger::{Trigger, TriggerEvent, TriggerFiring};
 use dataflow::prelude::*;
 use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use futures_util::future::{self, BoxFuture, FutureExt};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, SqlQuery};
 use noria::builders::*;
 use noria::channel::tcp::{SendError, TcpSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
-use noria::ActivationResult;
+use noria::debug::oracle::ReferenceRows;
+use noria::debug::profile::{NodeProfile, Profile};
+use noria::debug::replays::SlowReplay;
+use noria::debug::stats::{DomainStats, GraphStats, NodeStats, StateStatistics, StateStats};
+use noria::{
+    ActivationResult, Assertion, Comparison, Condition, ControllerEvent, ControllerEventKind,
+    DataflowGraph, DeadLetter, Eviction, GraphNode, KafkaSource, Mirror, PlanNode, Protocol,
+    QueryEstimate, QueryPlan, ReaderLoad, RecipeDiff, RecipeVersion, StatementLint, TableOperation,
+    TriggerAction, UnsupportedStatement, ViewPriority, ViewStorage, ViewWarming, Violation,
+};
 use petgraph::visit::Bfs;
 use slog::Logger;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{cell, io, time};
 
+/// How many rejected writes are kept per table.
+const MAX_DEAD_LETTERS: usize = 1000;
+
+/// How many assertion violations are kept per view.
+const MAX_VIOLATIONS: usize = 1000;
+
+/// How many events are kept in the event log.
+const MAX_EVENTS: usize = 1000;
+
+/// How often the queries of a failed domain are recovered before they are quarantined.
+const MAX_DOMAIN_RESTARTS: usize = 3;
+
+/// How often nodes with a row TTL are asked to retract their expired rows.
+const EXPIRE_EVERY: Duration = Duration::from_secs(1);
+
+/// How often the statistics of the state of every node are gathered.
+const STATE_STATISTICS_EVERY: Duration = Duration::from_secs(10);
+
 /// `Controller` is the core component of the alternate Soup implementation.
 ///
 /// It keeps track of the structure of the underlying data flow graph and its domains. `Controller`
@@ -44,6 +91,11 @@ pub(super) struct ControllerInner {
 
     /// Current recipe
     recipe: Recipe,
+    /// The most recent versions of the recipe, oldest first.
+    recipe_history: VecDeque<RecipeSnapshot>,
+    /// The contexts of the universes that have been created, so that changes to the security
+    /// configuration can be applied to them.
+    universes: Vec<HashMap<String, DataType>>,
 
     pub(super) domains: HashMap<DomainIndex, DomainHandle>,
     pub(in crate::controller) domain_nodes: HashMap<DomainIndex, Vec<NodeIndex>>,
@@ -59,8 +111,96 @@ pub(super) struct ControllerInner {
     pub(super) epoch: Epoch,
 
     pending_recovery: Option<(Vec<String>, usize)>,
+    pending_migration: Option<PendingMigration>,
+
+    /// Whether all tables are in read-only mode.
+    read_only: bool,
+    /// Tables that are in read-only mode regardless of `read_only`.
+    read_only_tables: HashSet<String>,
+
+    triggers: TriggerState,
+    /// Trigger firings whose actions have yet to complete.
+    firings: PendingFirings,
+    /// The actions of trigger firings that are running.
+    actions: RunningActions,
+    /// The base node each foreign key refers to, and the trigger below it that cascades deletes.
+    ///
+    /// These come from the recipe, so they are not persisted, and are set up again as the recipe
+    /// is applied.
+    cascades: HashMap<ForeignKey, (NodeIndex, NodeIndex)>,
+
+    /// Tables whose writes are mirrored, and where to.
+    mirrors: HashMap<String, Mirror>,
+    /// Shared with the trigger actions that federate views, which run as tasks of their own.
+    shadows: Arc<Mutex<Shadows>>,
+    /// The event-time column of each table that has one.
+    event_time_columns: HashMap<String, String>,
+    /// How many readers each replicated view has.
+    read_replicas: HashMap<String, usize>,
+    /// How the readers of views evict keys, where set.
+    evictions: HashMap<String, Eviction>,
+    /// How urgently the upqueries of views are handled, where set.
+    priorities: HashMap<String, ViewPriority>,
+    /// Where the readers of views keep their rows, where set.
+    pub(super) view_storage: HashMap<String, ViewStorage>,
+    /// The worker that new domains are placed on while a view is being moved to it.
+    place_on: Option<WorkerIdentifier>,
+
+    /// Kafka topics whose messages are written to base tables, by name.
+    kafka_sources: BTreeMap<String, KafkaSource>,
+    connectors: KafkaConnectors,
+
+    /// The most recent writes to each table that were rejected for not fitting its schema.
+    dead_letters: HashMap<String, VecDeque<DeadLetter>>,
+
+    /// Assertions on the rows of views.
+    assertions: Vec<AssertionSpec>,
+    /// The most recent rows that violated an assertion on each view.
+    quarantine: HashMap<String, VecDeque<Violation>>,
+
+    /// How often a domain failed, by the queries that were affected and the reason it failed.
+    domain_failures: HashMap<(Vec<String>, String), usize>,
+
+    /// The most recent entries of the event log.
+    events: VecDeque<ControllerEvent>,
+    /// Where to `POST` events to, if anywhere.
+    event_webhook: Option<String>,
+    /// Evictions of at least this many bytes are recorded as events.
+    eviction_event_threshold: Option<usize>,
+
+    /// Unsupported queries that the adapter executes against the upstream database, by name.
+    pass_through: BTreeMap<String, String>,
+    /// Whether unsupported queries become pass-through queries instead of failing the recipe.
+    pass_through_unsupported: bool,
+    /// Aliases and renames of views.
+    view_names: ViewNames,
+
+    /// The latest complete checkpoint of materialized state.
+    checkpoint: Option<u64>,
+    /// When the last checkpoint was attempted.
+    last_checkpoint: Instant,
+
+    /// When each periodically refreshed node was last refreshed.
+    last_refreshed: HashMap<NodeIndex, Instant>,
+    /// The base and reader nodes with a row TTL.
+    expiring: Vec<NodeIndex>,
+    /// When the nodes with a row TTL were last asked to retract their expired rows.
+    last_expired: Instant,
+    /// When the current profiling run was started, if one is in progress.
+    profiling_since: Option<Instant>,
+    /// The statistics of the state of every node as of when they were last gathered, if they
+    /// have been.
+    state_statistics: Option<(Instant, StateStatistics)>,
+    /// Whether migrations return before their new materializations have been populated.
+    background_backfills: bool,
+    /// The new views that are filled in the background, if they are.
+    warming: Option<Warming>,
 
     quorum: usize,
+    /// How many workers were healthy under the previous leader.
+    expected_workers: usize,
+    /// When this controller became the leader.
+    leader_since: Instant,
     heartbeat_every: Duration,
     healthcheck_every: Duration,
     last_checked_workers: Instant,
@@ -72,22 +212,103 @@ pub(super) struct ControllerInner {
 
 pub(in crate::controller) struct DomainReplies(
     tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket>,
+    MigrationProgress,
+    /// The number of shards of each node that have finished a full replay into it, for replays
+    /// that have not yet been waited for.
+    HashMap<(DomainIndex, LocalNodeIndex), usize>,
 );
 
 impl DomainReplies {
-    async fn read_n_domain_replies(&mut self, n: usize) -> Vec<ControlReplyPacket> {
-        let crps: Vec<_> = (&mut self.0).take(n).collect().await;
+    pub(in crate::controller) fn progress(&self) -> &MigrationProgress {
+        &self.1
+    }
 
-        if crps.len() != n {
-            unreachable!(
-                "got unexpected EOF from domain reply channel after {} replies",
-                crps.len()
-            );
+    /// Record `crp` if it is one of the replies that replays send whenever they get around to it,
+    /// rather than in response to a request, and return it otherwise.
+    fn record(&mut self, crp: ControlReplyPacket) -> Option<ControlReplyPacket> {
+        match crp {
+            ControlReplyPacket::ReplayProgress(rows, bytes, added) => {
+                self.1.replayed(rows, bytes, added);
+                None
+            }
+            ControlReplyPacket::Replayed(domain, node) => {
+                *self.2.entry((domain, node)).or_insert(0) += 1;
+                None
+            }
+            crp => Some(crp),
+        }
+    }
+
+    async fn next_domain_reply(&mut self) -> Option<ControlReplyPacket> {
+        while let Some(crp) = self.0.next().await {
+            if let Some(crp) = self.record(crp) {
+                return Some(crp);
+            }
         }
+        None
+    }
 
+    async fn read_n_domain_replies(&mut self, n: usize) -> Vec<ControlReplyPacket> {
+        let mut crps = Vec::with_capacity(n);
+        while crps.len() != n {
+            match self.next_domain_reply().await {
+                Some(crp) => crps.push(crp),
+                None => unreachable!(
+                    "got unexpected EOF from domain reply channel after {} replies",
+                    crps.len()
+                ),
+            }
+        }
         crps
     }
 
+    /// Record the replies that have arrived since we last waited for any, without waiting.
+    ///
+    /// Only replays report anything unprompted, so there are no other replies to be had.
+    pub(in crate::controller) fn poll(&mut self) {
+        while let Some(Some(crp)) = self.0.next().now_or_never() {
+            if let Some(crp) = self.record(crp) {
+                unreachable!("got unprompted control reply: {:?}", crp);
+            }
+        }
+    }
+
+    /// Wait for the next report from a replay.
+    pub(in crate::controller) async fn wait_for_replays(&mut self) {
+        match self.0.next().await {
+            Some(crp) => {
+                if let Some(crp) = self.record(crp) {
+                    unreachable!("got unprompted control reply: {:?}", crp);
+                }
+            }
+            None => unreachable!("got unexpected EOF from domain reply channel"),
+        }
+    }
+
+    /// True if every shard of `d` has finished the full replay into `node`.
+    pub(in crate::controller) fn replayed(
+        &mut self,
+        d: &DomainHandle,
+        node: LocalNodeIndex,
+    ) -> bool {
+        let key = (d.index(), node);
+        if self.2.get(&key).cloned().unwrap_or(0) < d.shards() {
+            return false;
+        }
+        self.2.remove(&key);
+        true
+    }
+
+    pub(in crate::controller) async fn wait_for_replay(
+        &mut self,
+        d: &DomainHandle,
+        node: LocalNodeIndex,
+    ) {
+        while !self.replayed(d, node) {
+            self.wait_for_replays().await;
+        }
+    }
+
     pub(in crate::controller) async fn wait_for_acks(&mut self, d: &DomainHandle) {
         for r in self.read_n_domain_replies(d.shards()).await {
             match r {
@@ -97,6 +318,76 @@ impl DomainReplies {
         }
     }
 
+    /// Wait for every shard of `d` to say whether it restored a node's state from its snapshot,
+    /// and return true if they all did.
+    pub(in crate::controller) async fn wait_for_restores(&mut self, d: &DomainHandle) -> bool {
+        let mut restored = true;
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Restored(r) => restored &= r,
+                r => unreachable!("got unexpected non-restore control reply: {:?}", r),
+            }
+        }
+        restored
+    }
+
+    /// Wait for every shard of `d` to say whether it wrote its snapshots for a checkpoint, and
+    /// return true if they all did.
+    async fn wait_for_checkpoints(&mut self, d: &DomainHandle) -> bool {
+        let mut written = true;
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Checkpointed(w) => written &= w,
+                r => unreachable!("got unexpected non-checkpoint control reply: {:?}", r),
+            }
+        }
+        written
+    }
+
+    async fn wait_for_profiles(&mut self, d: &DomainHandle) -> Vec<HashMap<NodeIndex, u64>> {
+        let mut profiles = Vec::with_capacity(d.shards());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Profile(p) => profiles.push(p),
+                r => unreachable!("got unexpected non-profile control reply: {:?}", r),
+            }
+        }
+        profiles
+    }
+
+    async fn wait_for_slow_replays(&mut self, d: &DomainHandle) -> Vec<SlowReplay> {
+        let mut slow = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::SlowReplays(s) => slow.extend(s),
+                r => unreachable!("got unexpected non-slow-replays control reply: {:?}", r),
+            }
+        }
+        slow
+    }
+
+    async fn wait_for_keys(&mut self, d: &DomainHandle) -> Vec<Vec<DataType>> {
+        let mut keys = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Keys(k) => keys.extend(k),
+                r => unreachable!("got unexpected non-keys control reply: {:?}", r),
+            }
+        }
+        keys
+    }
+
+    async fn wait_for_rows(&mut self, d: &DomainHandle) -> Vec<Vec<DataType>> {
+        let mut rows = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Rows(rs) => rows.extend(rs),
+                r => unreachable!("got unexpected non-rows control reply: {:?}", r),
+            }
+        }
+        rows
+    }
+
     async fn wait_for_statistics(
         &mut self,
         d: &DomainHandle,
@@ -167,6 +458,41 @@ pub(super) fn graphviz(
     s
 }
 
+fn plan_column(c: &mir::Column) -> String {
+    match c.table {
+        Some(ref table) => format!("{}.{}", table, c.name),
+        None => c.name.clone(),
+    }
+}
+
+/// The nodes of the plan `mir`, in topological order.
+fn plan_nodes(mir: &mir::query::MirQuery) -> Vec<PlanNode> {
+    use mir::node::MirNodeType;
+
+    mir.topo_nodes()
+        .into_iter()
+        .map(|n| {
+            let n = n.borrow();
+            let node = match n.inner {
+                MirNodeType::Reuse { ref node } => node.borrow().flow_node_addr(),
+                _ => n.flow_node_addr(),
+            };
+            PlanNode {
+                name: n.versioned_name(),
+                operator: n.to_string(),
+                columns: n.columns().iter().map(plan_column).collect(),
+                ancestors: n
+                    .ancestors()
+                    .iter()
+                    .map(|a| a.borrow().versioned_name())
+                    .collect(),
+                reused: n.is_reused(),
+                node: node.ok().map(|ni| ni.index()),
+            }
+        })
+        .collect()
+}
+
 impl ControllerInner {
     pub(in crate::controller) fn topo_order(&self, new: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
         let mut topo_list = Vec::with_capacity(new.len());
@@ -192,6 +518,7 @@ impl ControllerInner {
         path: String,
         query: Option<String>,
         body: hyper::body::Bytes,
+        identity: Option<DataType>,
         authority: &Arc<A>,
     ) -> Result<Result<String, String>, StatusCode> {
         use serde_json as json;
@@ -205,6 +532,13 @@ impl ControllerInner {
             (&Method::POST, "/graphviz") => {
                 return Ok(Ok(json::to_string(&self.graphviz(true)).unwrap()));
             }
+            (&Method::GET, "/metrics") => return Ok(Ok(self.metrics())),
+            (&Method::GET, "/dataflow_graph") => {
+                return Ok(Ok(self.dataflow_graph().graphviz()));
+            }
+            (&Method::POST, "/dataflow_graph") => {
+                return Ok(Ok(json::to_string(&self.dataflow_graph()).unwrap()));
+            }
             (&Method::GET, "/get_statistics") => {
                 return Ok(Ok(json::to_string(&self.get_statistics()).unwrap()));
             }
@@ -214,7 +548,10 @@ impl ControllerInner {
             _ => {}
         }
 
-        if self.pending_recovery.is_some() || self.workers.len() < self.quorum {
+        if self.pending_recovery.is_some()
+            || self.pending_migration.is_some()
+            || self.workers.len() < self.quorum
+        {
             return Err(StatusCode::SERVICE_UNAVAILABLE);
         }
 
@@ -222,8 +559,27 @@ impl ControllerInner {
             (Method::GET, "/flush_partial") => {
                 Ok(Ok(json::to_string(&self.flush_partial()).unwrap()))
             }
+            (Method::POST, "/start_profiling") => {
+                self.start_profiling();
+                Ok(Ok(json::to_string(&()).unwrap()))
+            }
+            (Method::POST, "/stop_profiling") => {
+                Ok(Ok(json::to_string(&self.stop_profiling()).unwrap()))
+            }
+            (Method::POST, "/slow_replays") => {
+                Ok(Ok(json::to_string(&self.slow_replays()).unwrap()))
+            }
+            (Method::POST, "/warming_status") => {
+                Ok(Ok(json::to_string(&self.warming_status()).unwrap()))
+            }
+            (Method::POST, "/state_statistics") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|fresh| Ok(json::to_string(&self.state_statistics(fresh)).unwrap())),
             (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
-            (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
+            (Method::POST, "/outputs") => {
+                let outputs = self.view_names.rename_outputs(self.outputs());
+                Ok(Ok(json::to_string(&outputs).unwrap()))
+            }
             (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
             (Method::GET, "/nodes") => {
                 // TODO(malte): this is a pretty yucky hack, but hyper doesn't provide easy access
@@ -264,17 +620,51 @@ impl ControllerInner {
                 .map(|args| Ok(json::to_string(&self.table_builder(args)).unwrap())),
             (Method::POST, "/view_builder") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| Ok(json::to_string(&self.view_builder(args)).unwrap())),
+                .map(|args: &str| {
+                    let vb = match identity.as_ref().and_then(|id| self.user_universe(id)) {
+                        Some(id) => self.view_builder(&recipe::user_universe_name(args, id)),
+                        None => self.view_builder(args),
+                    };
+                    Ok(json::to_string(&vb).unwrap())
+                }),
             (Method::POST, "/extend_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
-                    self.extend_recipe(authority, args)
+                    self.in_background(|this| this.extend_recipe(authority, args))
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/pass_through") => Ok(Ok(json::to_string(&self.pass_through).unwrap())),
+            (Method::POST, "/lint_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.lint_recipe(&args)).unwrap())),
+            (Method::POST, "/check_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.check_recipe(&args)).unwrap())),
+            (Method::POST, "/estimate") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| self.estimate(&args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/explain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| self.explain(&args).map(|r| json::to_string(&r).unwrap())),
             (Method::POST, "/install_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
-                    self.install_recipe(authority, args)
+                    self.in_background(|this| this.install_recipe(authority, args))
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/dry_run_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|(recipe, replace): (String, bool)| {
+                    self.dry_run_recipe(&recipe, replace)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/recipe_history") => {
+                Ok(Ok(json::to_string(&self.recipe_history()).unwrap()))
+            }
+            (Method::POST, "/rollback_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|version| {
+                    self.in_background(|this| this.rollback_recipe(authority, version))
                         .map(|r| json::to_string(&r).unwrap())
                 }),
             (Method::POST, "/set_security_config") => json::from_slice(&body)
@@ -283,6 +673,94 @@ impl ControllerInner {
                     self.set_security_config(args)
                         .map(|r| json::to_string(&r).unwrap())
                 }),
+            (Method::POST, "/add_trigger") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.add_trigger(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/add_kafka_source") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.add_kafka_source(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/remove_kafka_source") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.remove_kafka_source(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/add_assertion") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.add_assertion(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/quarantine") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.quarantine(&args)).unwrap())),
+            (Method::POST, "/set_mirror") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_mirror(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_event_time") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_event_time(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/dead_letters") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.dead_letters(&args)).unwrap())),
+            (Method::POST, "/reader_load") => Ok(Ok(json::to_string(&self.reader_load()).unwrap())),
+            (Method::POST, "/replicate_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.replicate_view(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_eviction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_eviction(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_view_priority") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_view_priority(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/set_view_storage") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_view_storage(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/view_rows") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|name: String| self.view_rows(&name).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/reference_rows") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|name: String| {
+                    self.reference_rows(&name)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/move_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.move_view(args).map(|r| json::to_string(&r).unwrap())),
+            (Method::POST, "/events") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|since: u64| Ok(json::to_string(&self.events(since)).unwrap())),
+            (Method::POST, "/set_read_only") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_read_only(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                }),
             (Method::POST, "/create_universe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -299,18 +777,33 @@ impl ControllerInner {
         }
     }
 
-    pub(super) fn handle_register(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
-        let (remote, read_listen_addr) = if let CoordinationPayload::Register {
+    pub(super) fn handle_register<A: Authority + 'static>(
+        &mut self,
+        msg: CoordinationMessage,
+        authority: &Arc<A>,
+    ) -> Result<(), io::Error> {
+        let (remote, read_listen_addr, protocol) = if let CoordinationPayload::Register {
             addr: remote,
             read_listen_addr,
+            protocol,
             ..
         } = msg.payload
         {
-            (remote, read_listen_addr)
+            (remote, read_listen_addr, protocol)
         } else {
             unreachable!();
         };
 
+        if !protocol.is_compatible(&Protocol::current()) {
+            error!(
+                self.log,
+                "refusing worker at {:?}, which speaks incompatible protocol version {}",
+                msg.source,
+                protocol.version
+            );
+            return Ok(());
+        }
+
         info!(
             self.log,
             "new worker registered from {:?}, which listens on {:?}", msg.source, remote
@@ -320,29 +813,120 @@ impl ControllerInner {
         let ws = Worker::new(sender);
         self.workers.insert(msg.source, ws);
         self.read_addrs.insert(msg.source, read_listen_addr);
+        self.record_event(
+            authority,
+            ControllerEventKind::WorkerJoined { worker: msg.source },
+        );
+
+        if self.can_start_graph() {
+            self.start_graph(authority);
+        }
+        if self.pending_recovery.is_none() {
+            self.persist_workers(authority);
+        }
 
-        if self.workers.len() >= self.quorum {
-            if let Some((recipes, recipe_version)) = self.pending_recovery.take() {
-                assert_eq!(self.workers.len(), self.quorum);
-                assert_eq!(self.recipe.version(), 0);
-                assert!(recipe_version + 1 >= recipes.len());
+        Ok(())
+    }
 
-                info!(self.log, "Restoring graph configuration");
-                self.recipe = Recipe::with_version(
-                    recipe_version + 1 - recipes.len(),
-                    Some(self.log.clone()),
-                );
-                for r in recipes {
-                    self.apply_recipe(self.recipe.clone().extend(&r).unwrap())
-                        .unwrap();
+    /// Whether enough workers are healthy to build the graph on.
+    ///
+    /// A new leader also waits for as many workers as were healthy under the previous one, so that
+    /// it does not place every domain on the first few to register again. Workers that have not
+    /// registered by the time they would have been declared failed are not waited for.
+    fn can_start_graph(&self) -> bool {
+        let healthy = self.workers.values().filter(|w| w.healthy).count();
+        healthy >= self.quorum
+            && (self.pending_recovery.is_none()
+                || healthy >= self.expected_workers
+                || self.leader_since.elapsed() > self.heartbeat_every * 4)
+    }
+
+    /// Remember how many workers are healthy, so that the next leader waits for as many.
+    fn persist_workers<A: Authority + 'static>(&self, authority: &Arc<A>) {
+        let healthy = self.workers.values().filter(|w| w.healthy).count();
+        let _ =
+            authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.workers = healthy;
+                    Ok(state)
                 }
+            });
+    }
+
+    /// Stop acting as the controller after another instance has taken over.
+    ///
+    /// Returns the channel that domains reply on, so that this instance can take over again
+    /// later.
+    pub(super) fn step_down(mut self) -> tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket> {
+        let (_, standin) = tokio::sync::mpsc::unbounded_channel();
+        let mut drx = std::mem::replace(&mut self.replies.0, standin);
+        // replies from the domains of this term must not be mistaken for replies in the next
+        while let Some(Some(_)) = drx.recv().now_or_never() {}
+        // the domains now belong to the successor, so dropping this instance must not tell them
+        // to quit
+        self.domains.clear();
+        drx
+    }
+
+    /// Build the graph from the recipes that recovery left pending, if any, and bring up whatever
+    /// else needs the graph to be there.
+    fn start_graph<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
+        if let Some((recipes, recipe_version)) = self.pending_recovery.take() {
+            assert_eq!(self.recipe.version(), 0);
+            assert!(recipe_version + 1 >= recipes.len());
+
+            info!(self.log, "Restoring graph configuration");
+            self.recipe =
+                Recipe::with_version(recipe_version + 1 - recipes.len(), Some(self.log.clone()));
+            let checkpoint = self
+                .checkpoint
+                .filter(|_| self.persistence.checkpoint_every.is_some());
+            if let Some(checkpoint) = checkpoint {
+                info!(
+                    self.log,
+                    "Restoring materialized state from checkpoint {}", checkpoint
+                );
+            }
+            self.materializations.restore_from(checkpoint);
+            for r in recipes {
+                self.apply_recipe(self.recipe.clone().extend(&r).unwrap())
+                    .unwrap();
+            }
+            self.materializations.restore_from(None);
+            if checkpoint.is_some() {
+                self.replay_logs();
             }
         }
 
-        Ok(())
+        if let Some(m) = self.pending_migration.take() {
+            self.resume_migration(authority, m);
+        }
+
+        // trigger nodes are not part of the recipe, so they must be restored separately
+        for spec in self.triggers.registered.clone() {
+            if let Err(e) = self.install_trigger(&spec) {
+                crit!(self.log, "failed to restore trigger {}: {}", spec.name, e);
+            }
+        }
+        if let Err(e) = self.restore_firings(authority) {
+            crit!(self.log, "{}", e);
+        }
+        if !self.firings.pending.is_empty() {
+            self.run_triggers(authority);
+        }
+
+        for name in self.kafka_sources.keys().cloned().collect::<Vec<_>>() {
+            if !self.connectors.is_running(&name) {
+                if let Err(e) = self.start_kafka_source(&name) {
+                    crit!(self.log, "failed to restore Kafka source {}: {}", name, e);
+                }
+            }
+        }
     }
 
-    fn check_worker_liveness(&mut self) {
+    fn check_worker_liveness<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
         let mut any_failed = false;
 
         // check if there are any newly failed workers
@@ -367,11 +951,21 @@ impl ControllerInner {
                     failed.push(addr.clone());
                 }
             }
-            self.handle_failed_workers(failed);
+            for &worker in &failed {
+                self.record_event(authority, ControllerEventKind::WorkerFailed { worker });
+            }
+            self.persist_workers(authority);
+            if self.pending_recovery.is_none() {
+                self.handle_failed_workers(authority, failed);
+            }
         }
     }
 
-    fn handle_failed_workers(&mut self, failed: Vec<WorkerIdentifier>) {
+    fn handle_failed_workers<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        failed: Vec<WorkerIdentifier>,
+    ) {
         // first, translate from the affected workers to affected data-flow nodes
         let mut affected_nodes = Vec::new();
         for wi in failed {
@@ -379,9 +973,149 @@ impl ControllerInner {
             affected_nodes.extend(self.get_failed_nodes(&wi));
         }
 
+        if affected_nodes
+            .iter()
+            .any(|&ni| self.ingredients[ni].is_base())
+        {
+            // base tables are not part of any query, so removing queries cannot bring them back
+            self.replace_domains(authority);
+            return;
+        }
+
         // then, figure out which queries are affected (and thus must be removed and added again in
         // a migration)
         let affected_queries = self.recipe.queries_for_nodes(affected_nodes);
+        self.recover_queries(affected_queries);
+    }
+
+    /// Build the whole graph again on the workers that are still healthy.
+    ///
+    /// This is how the domains of a failed worker that had base tables on it are re-placed. Every
+    /// other domain is shut down, and the graph is built from the committed recipes just like when
+    /// a controller recovers, so base tables open their persisted rows again, and everything below
+    /// them is replayed from there. For the rows of base tables to survive the failure of the
+    /// worker they were on, the log directory must be on storage that the other workers share.
+    ///
+    /// If fewer than a quorum of workers are healthy, the graph is built once enough workers have
+    /// registered.
+    fn replace_domains<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
+        warn!(
+            self.log,
+            "re-placing all domains, since a failed worker had base tables"
+        );
+        self.rebuild_graph(authority);
+    }
+
+    /// Tear down every domain, and build the graph again from the persisted recipes.
+    fn rebuild_graph<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
+        let state: ControllerState =
+            serde_json::from_slice(&authority.try_read(STATE_KEY).unwrap().unwrap()).unwrap();
+
+        for d in self.domains.values_mut() {
+            for shard in 0..d.shards() {
+                // shards on failed workers are gone already
+                drop(d.send_to_healthy_shard(shard, Box::new(Packet::Quit), &self.workers));
+            }
+        }
+        for name in self.kafka_sources.keys() {
+            self.connectors.stop(name);
+        }
+
+        let mut g = petgraph::Graph::new();
+        self.source = g.add_node(node::Node::new(
+            "source",
+            &["because-type-inference"],
+            node::special::Source,
+        ));
+        self.ingredients = g;
+        self.ndomains = 0;
+        self.domains.clear();
+        self.domain_nodes.clear();
+        self.remap.clear();
+        self.cascades.clear();
+        self.last_refreshed.clear();
+        self.expiring.clear();
+        if let Some(ref mut warming) = self.warming {
+            warming.clear();
+        }
+        self.materializations.clear();
+        self.replies.2.clear();
+        self.recipe = Recipe::blank(Some(self.log.clone()));
+        self.pending_recovery = Some((state.recipes, state.recipe_version));
+
+        if self.workers.values().filter(|w| w.healthy).count() >= self.quorum {
+            self.start_graph(authority);
+        } else {
+            warn!(
+                self.log,
+                "waiting for a quorum of workers before re-placing domains"
+            );
+        }
+    }
+
+    /// Recover the nodes of a domain whose event loop panicked, along with everything downstream
+    /// of them.
+    ///
+    /// Like when a worker fails, the affected queries are removed and added again, which rebuilds
+    /// their state from their ancestors. If the same queries fail again for the same reason, the
+    /// poisonous records are presumably part of that state, and adding the queries back would only
+    /// make them fail once more. After `MAX_DOMAIN_RESTARTS` such failures, the queries are
+    /// therefore quarantined instead: they are removed, and stay removed until they are installed
+    /// again.
+    pub(super) fn handle_failed_domain(
+        &mut self,
+        domain: DomainIndex,
+        shard: usize,
+        packet: Option<String>,
+        reason: String,
+    ) {
+        crit!(self.log, "domain failed: {}", reason;
+              "domain" => domain.index(),
+              "shard" => shard,
+              "packet" => packet.as_deref().unwrap_or("none"));
+
+        let affected_nodes = self.with_descendants(self.nodes_in_domain(domain));
+        if affected_nodes
+            .iter()
+            .any(|&ni| self.ingredients[ni].is_base())
+        {
+            // base tables are not part of any query, so removing queries cannot rebuild them
+            crit!(self.log, "cannot recover domain with base tables";
+                  "domain" => domain.index());
+            return;
+        }
+
+        let mut affected_queries = self.recipe.queries_for_nodes(affected_nodes);
+        affected_queries.sort();
+        affected_queries.dedup();
+        if affected_queries.is_empty() {
+            warn!(self.log, "failed domain is not part of any query";
+                  "domain" => domain.index());
+            return;
+        }
+
+        let failures = self
+            .domain_failures
+            .entry((affected_queries.clone(), reason))
+            .or_insert(0);
+        *failures += 1;
+        if *failures > MAX_DOMAIN_RESTARTS {
+            crit!(
+                self.log,
+                "quarantining queries that keep failing: {:?}",
+                affected_queries
+            );
+            let (recovery, _) = self.recipe.make_recovery(affected_queries);
+            if let Err(e) = self.apply_recipe(recovery) {
+                crit!(self.log, "failed to quarantine queries: {}", e);
+            }
+        } else {
+            self.recover_queries(affected_queries);
+        }
+    }
+
+    /// Remove `affected_queries` from the graph, and then add them back again.
+    fn recover_queries(&mut self, affected_queries: Vec<String>) {
         let (recovery, mut original) = self.recipe.make_recovery(affected_queries);
 
         // activate recipe
@@ -400,7 +1134,11 @@ impl ControllerInner {
             .expect("failed to activate original recipe");
     }
 
-    pub(super) fn handle_heartbeat(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
+    pub(super) fn handle_heartbeat<A: Authority + 'static>(
+        &mut self,
+        msg: CoordinationMessage,
+        authority: &Arc<A>,
+    ) -> Result<(), io::Error> {
         match self.workers.get_mut(&msg.source) {
             None => crit!(
                 self.log,
@@ -412,15 +1150,451 @@ impl ControllerInner {
             }
         }
 
-        self.check_worker_liveness();
+        self.check_worker_liveness(authority);
+
+        // workers that the previous leader had may never come back
+        if self.pending_recovery.is_some() && self.can_start_graph() {
+            self.start_graph(authority);
+        }
+
+        // heartbeats are as good a clock as any to collect finished trigger actions on, and to
+        // retry failed ones
+        if self.pending_recovery.is_none() && !self.firings.pending.is_empty() {
+            self.run_triggers(authority);
+        }
+
+        if self.pending_recovery.is_none() {
+            self.refresh_nodes();
+            self.expire_rows();
+            self.warm_views();
+            self.restart_kafka_sources();
+            self.checkpoint_if_due(authority);
+            self.refresh_state_statistics_if_due();
+        }
         Ok(())
     }
 
-    /// Construct `ControllerInner` with a specified listening interface
+    /// Take a checkpoint of materialized state if one is due, unless the graph is changing.
+    fn checkpoint_if_due<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
+        let every = match self.persistence.checkpoint_every {
+            Some(every) => every,
+            None => return,
+        };
+        if self.last_checkpoint.elapsed() < every
+            || self.pending_migration.is_some()
+            || self.materializations.any_backfilling()
+            || self.workers.len() < self.quorum
+        {
+            return;
+        }
+        self.last_checkpoint = Instant::now();
+
+        let start = Instant::now();
+        match self.take_checkpoint(authority) {
+            Ok(id) => info!(self.log, "took checkpoint";
+                            "checkpoint" => id,
+                            "ms" => start.elapsed().as_millis()),
+            Err(e) => error!(self.log, "failed to take checkpoint: {}", e),
+        }
+    }
+
+    /// Checkpoint the fully materialized state below the base tables.
+    ///
+    /// Client writes are held back at the base tables until every earlier write has passed
+    /// through the data-flow, so that the snapshots of all nodes reflect the same writes. The base
+    /// tables mark where in their logs they are when the snapshots are written, and drop what the
+    /// checkpoint includes from their logs once it is persisted.
+    fn take_checkpoint<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+    ) -> Result<u64, String> {
+        let id = self.checkpoint.map_or(1, |c| c + 1);
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::StartCheckpoint), &self.workers)
+                .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+
+        let mut written = true;
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::Checkpoint { id }), &self.workers)
+                .unwrap();
+            written &= futures_executor::block_on(self.replies.wait_for_checkpoints(d));
+        }
+        let result = if written {
+            self.persist_checkpoint(authority, id)
+        } else {
+            Err("not every snapshot could be written".to_owned())
+        };
+
+        // writes are held back until the domains hear from us, whether or not we took it
+        let taken = result.as_ref().ok().map(|_| id);
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(
+                Box::new(Packet::FinishCheckpoint { id: taken }),
+                &self.workers,
+            )
+            .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+        if taken.is_some() {
+            self.checkpoint = taken;
+        }
+        result.map(|_| id)
+    }
+
+    /// The base tables whose layout `new` changes, each with the name that the state of its new
+    /// layout is kept under, the columns of its new shard key, its new number of shards, and the
+    /// scheme that spreads its rows across them.
+    fn resharded_tables(
+        &self,
+        new: &Recipe,
+    ) -> Result<Vec<(NodeIndex, String, Vec<usize>, usize, ShardScheme)>, String> {
+        let mut resharded = Vec::new();
+        for (name, ni) in self.inputs() {
+            let key = match new.shard_key(&name) {
+                Some(key) if new.prior().and_then(|p| p.shard_key(&name)) != Some(key) => key,
+                _ => continue,
+            };
+            let shards = match (key.shards, self.sharding) {
+                (Some(_), None) => {
+                    return Err(format!(
+                        "table {} cannot have its own number of shards when nodes are not sharded",
+                        name
+                    ));
+                }
+                (Some(n), _) => n,
+                (None, sharding) => sharding.unwrap_or(1),
+            };
+            let n = &self.ingredients[ni];
+            let cols = key
+                .columns
+                .iter()
+                .map(|c| {
+                    n.fields()
+                        .iter()
+                        .position(|f| f == c)
+                        .ok_or_else(|| format!("{} has no column {}", name, c))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut base = n.get_base().unwrap().clone();
+            base.set_shard_key(cols.clone(), key.shards);
+            resharded.push((ni, base.state_name(&name), cols, shards, key.scheme));
+        }
+        Ok(resharded)
+    }
+
+    /// Have the shards of each base table in `resharded` write out their rows for the shards of
+    /// its new layout.
+    ///
+    /// Client writes are held back first, like for a checkpoint, so that no write is left behind
+    /// in the old layout. They stay held back once the rows are written, since the domains are
+    /// about to be torn down; if not every row could be written, they are let through again.
+    fn export_rows(
+        &mut self,
+        resharded: &[(NodeIndex, String, Vec<usize>, usize, ShardScheme)],
+        token: u64,
+    ) -> Result<(), String> {
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::StartCheckpoint), &self.workers)
+                .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+
+        let mut written = true;
+        for &(ni, ref to, ref key, shards, scheme) in resharded {
+            let n = &self.ingredients[ni];
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            let m = Box::new(Packet::ExportRows {
+                node: n.local_addr(),
+                to: to.clone(),
+                key: key.clone(),
+                shards,
+                scheme,
+                token,
+            });
+            d.send_to_healthy(m, &self.workers).unwrap();
+            written &= futures_executor::block_on(self.replies.wait_for_checkpoints(d));
+        }
+        if written {
+            return Ok(());
+        }
+        self.resume_writes();
+        Err("not every row of the re-sharded tables could be written".to_owned())
+    }
+
+    /// Let through the client writes that `export_rows` held back.
+    fn resume_writes(&mut self) {
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(
+                Box::new(Packet::FinishCheckpoint { id: None }),
+                &self.workers,
+            )
+            .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+    }
+
+    /// The views whose shard key `new` changes, and whose readers are not sharded by it yet.
+    fn resharded_views(&self, new: &Recipe) -> Result<Vec<String>, String> {
+        let mut resharded = Vec::new();
+        for (name, ni) in self.outputs() {
+            let key = match new.shard_key(&name) {
+                Some(key) if new.prior().and_then(|p| p.shard_key(&name)) != Some(key) => key,
+                _ => continue,
+            };
+            let n = &self.ingredients[ni];
+            let col = n.fields().iter().position(|f| *f == key.columns[0]);
+            for r in self.readers_of(ni) {
+                let (reader_key, i) = self.ingredients[r]
+                    .with_reader(|r| (r.key().map(<[usize]>::to_vec), r.shard_key_index()))
+                    .unwrap();
+                let reader_key = reader_key.unwrap_or_default();
+                let scheme = self.ingredients[r].shard_scheme();
+                match col.and_then(|col| reader_key.iter().position(|&c| c == col)) {
+                    Some(j) if j == i && scheme == key.scheme => (),
+                    Some(_) => {
+                        resharded.push(name.clone());
+                        break;
+                    }
+                    None => {
+                        return Err(format!(
+                            "view {} can only be sharded by one of its parameters",
+                            name
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(resharded)
+    }
+
+    /// Apply the writes that base tables undid when they were rewound to the checkpoint that
+    /// recovery restored state from again.
+    fn replay_logs(&mut self) {
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::ReplayLogs), &self.workers)
+                .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+    }
+
+    fn persist_checkpoint<A: Authority + 'static>(
+        &self,
+        authority: &Arc<A>,
+        checkpoint: u64,
+    ) -> Result<(), String> {
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.checkpoint = Some(checkpoint);
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist checkpoint".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Ask the domains of periodically refreshed nodes that are due for a refresh to refresh them.
+    fn refresh_nodes(&mut self) {
+        let now = Instant::now();
+        for ni in self.ingredients.node_indices() {
+            let n = &self.ingredients[ni];
+            let every = match n.refresh_every() {
+                Some(every) if !n.is_dropped() => every,
+                _ => continue,
+            };
+
+            // a node's first refresh is one interval after we first see it
+            let last = self.last_refreshed.entry(ni).or_insert(now);
+            if now.duration_since(*last) < every {
+                continue;
+            }
+            *last = now;
+
+            let m = Box::new(Packet::Refresh {
+                node: n.local_addr(),
+            });
+            if let Err(e) = self
+                .domains
+                .get_mut(&n.domain())
+                .unwrap()
+                .send_to_healthy(m, &self.workers)
+            {
+                warn!(self.log, "failed to refresh node: {:?}", e; "node" => ni.index());
+            }
+        }
+    }
+
+    /// Start filling in the partial readers of the views in `new_nodes` in the background, if
+    /// new views are warmed.
+    fn start_warming(&mut self, new_nodes: &HashMap<String, NodeIndex>) {
+        if self.warming.is_none() {
+            return;
+        }
+        let readers: Vec<_> = new_nodes
+            .keys()
+            .filter_map(|name| Some((name.clone(), self.reader_for(name)?)))
+            .filter(|&(_, r)| {
+                let n = &self.ingredients[r];
+                matches!(
+                    self.materializations.get_status(r, n),
+                    MaterializationStatus::Partial { .. }
+                )
+            })
+            .collect();
+        let warming = self.warming.as_mut().unwrap();
+        for (name, r) in readers {
+            warming.add(name, r);
+        }
+    }
+
+    /// Request the next keys of the views that are being warmed, as many as the warming rate
+    /// allows.
+    fn warm_views(&mut self) {
+        let ingredients = &self.ingredients;
+        let warming = match self.warming {
+            Some(ref mut warming) => warming,
+            None => return,
+        };
+        warming.retain(|r| !ingredients[r].is_dropped());
+        warming.tick();
+
+        // the keys are looked up in materializations that may still be being populated, and
+        // readers cannot be replayed into until they are ready
+        if self.materializations.any_backfilling() {
+            return;
+        }
+        for r in warming.unscanned() {
+            let keys = self.scan_keys(r);
+            self.warming.as_mut().unwrap().scanned(r, keys);
+        }
+
+        for (r, keys) in self.warming.as_mut().unwrap().take() {
+            let n = &self.ingredients[r];
+            let (cols, i) = n
+                .with_reader(|r| (r.key().map(<[usize]>::to_vec), r.shard_key_index()))
+                .unwrap();
+            let cols = cols.unwrap();
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            let request = |keys| {
+                Box::new(Packet::RequestReaderReplay {
+                    node: n.local_addr(),
+                    cols: cols.clone(),
+                    keys,
+                    trace: None,
+                })
+            };
+
+            // like lookups, keys go to the shard that holds them, unless any shard may hold any key
+            let sent = if d.shards() == 1 || n.merges_shards() {
+                d.send_to_healthy(request(keys), &self.workers)
+            } else {
+                let scheme = match n.sharded_by() {
+                    Sharding::ByColumn(_, _, scheme) => scheme,
+                    _ => ShardScheme::Hash,
+                };
+                let mut by_shard = vec![Vec::new(); d.shards()];
+                for key in keys {
+                    by_shard[scheme.shard(&key[i..=i], d.shards())].push(key);
+                }
+                by_shard
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, keys)| !keys.is_empty())
+                    .try_for_each(|(shard, keys)| {
+                        d.send_to_healthy_shard(shard, request(keys), &self.workers)
+                    })
+            };
+            if let Err(e) = sent {
+                warn!(self.log, "failed to warm view: {:?}", e; "node" => r.index());
+            }
+        }
+    }
+
+    /// The distinct keys of the partial reader `r`, as found in the full materializations that
+    /// its replays start from.
+    fn scan_keys(&mut self, r: NodeIndex) -> Vec<Vec<DataType>> {
+        let cols = self.ingredients[r]
+            .with_reader(|r| r.key().map(<[usize]>::to_vec))
+            .unwrap()
+            .unwrap();
+        let sources = match self
+            .materializations
+            .key_sources(&self.ingredients, r, &cols)
+        {
+            Some(sources) => sources,
+            None => {
+                warn!(self.log, "cannot find the keys to warm view with"; "node" => r.index());
+                return Vec::new();
+            }
+        };
+
+        let mut keys = HashSet::new();
+        for (source, cols) in sources {
+            let n = &self.ingredients[source];
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            let m = Box::new(Packet::ScanKeys {
+                node: n.local_addr(),
+                cols,
+            });
+            if let Err(e) = d.send_to_healthy(m, &self.workers) {
+                warn!(self.log, "failed to look up keys to warm: {:?}", e; "node" => r.index());
+                return Vec::new();
+            }
+            keys.extend(futures_executor::block_on(self.replies.wait_for_keys(d)));
+        }
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort();
+        keys
+    }
+
+    fn warming_status(&self) -> Vec<ViewWarming> {
+        self.warming
+            .as_ref()
+            .map(Warming::status)
+            .unwrap_or_default()
+    }
+
+    /// Ask the domains of the nodes with a row TTL to retract their expired rows, if they were
+    /// not asked to recently.
+    fn expire_rows(&mut self) {
+        if self.last_expired.elapsed() < EXPIRE_EVERY {
+            return;
+        }
+        self.last_expired = Instant::now();
+
+        for &ni in &self.expiring {
+            let n = &self.ingredients[ni];
+            if n.is_dropped() {
+                continue;
+            }
+            let m = Box::new(Packet::Expire {
+                node: n.local_addr(),
+            });
+            if let Err(e) = self
+                .domains
+                .get_mut(&n.domain())
+                .unwrap()
+                .send_to_healthy(m, &self.workers)
+            {
+                warn!(self.log, "failed to expire rows: {:?}", e; "node" => ni.index());
+            }
+        }
+    }
+
+    /// Construct `ControllerInner` with a specified listening interface
     pub(super) fn new(
         log: slog::Logger,
         state: ControllerState,
         drx: tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket>,
+        progress: MigrationProgress,
+        wake: tokio::sync::mpsc::UnboundedSender<Event>,
     ) -> Self {
         let mut g = petgraph::Graph::new();
         let source = g.add_node(node::Node::new(
@@ -433,6 +1607,9 @@ impl ControllerInner {
         if !state.config.partial_enabled {
             materializations.disable_partial()
         }
+        if state.config.warm_rate.is_some() {
+            materializations.enable_warm_readers();
+        }
         materializations.set_frontier_strategy(state.config.frontier_strategy);
 
         let cc = Arc::new(ChannelCoordinator::new());
@@ -443,6 +1620,7 @@ impl ControllerInner {
         } else {
             None
         };
+        let pending_migration = state.pending_migration;
 
         let mut recipe = Recipe::blank(Some(log.clone()));
         recipe.enable_reuse(state.config.reuse);
@@ -459,6 +1637,7 @@ impl ControllerInner {
             heartbeat_every: state.config.heartbeat_every,
             healthcheck_every: state.config.healthcheck_every,
             recipe,
+            recipe_history: state.recipe_history,
             quorum: state.config.quorum,
             log,
 
@@ -473,9 +1652,48 @@ impl ControllerInner {
             workers: HashMap::default(),
 
             pending_recovery,
+            pending_migration,
+            read_only: state.read_only,
+            read_only_tables: state.read_only_tables,
+            triggers: state.triggers,
+            firings: PendingFirings::new(state.epoch),
+            actions: RunningActions::new(wake),
+            cascades: HashMap::new(),
+            mirrors: state.mirrors,
+            shadows: Default::default(),
+            event_time_columns: state.event_time_columns,
+            read_replicas: state.read_replicas,
+            evictions: state.evictions,
+            priorities: state.priorities,
+            view_storage: state.view_storage,
+            place_on: None,
+            kafka_sources: state.kafka_sources,
+            connectors: KafkaConnectors::default(),
+            dead_letters: HashMap::new(),
+            assertions: state.assertions,
+            quarantine: HashMap::new(),
+            domain_failures: HashMap::new(),
+            events: state.events,
+            event_webhook: state.config.event_webhook,
+            eviction_event_threshold: state.config.eviction_event_threshold,
+            pass_through: state.pass_through,
+            pass_through_unsupported: state.config.pass_through_unsupported,
+            universes: Vec::new(),
+            view_names: state.view_names,
+            checkpoint: state.checkpoint,
+            last_checkpoint: Instant::now(),
+            expected_workers: state.workers,
+            leader_since: Instant::now(),
+            last_refreshed: HashMap::new(),
+            expiring: Vec::new(),
+            last_expired: Instant::now(),
+            profiling_since: None,
+            state_statistics: None,
+            background_backfills: false,
+            warming: state.config.warm_rate.map(Warming::new),
             last_checked_workers: Instant::now(),
 
-            replies: DomainReplies(drx),
+            replies: DomainReplies(drx, progress, HashMap::new()),
         }
     }
 
@@ -507,6 +1725,22 @@ impl ControllerInner {
         log: &Logger,
         nodes: Vec<(NodeIndex, bool)>,
     ) -> DomainHandle {
+        // replicas of a reader go on different workers where possible, so that they share its load
+        let avoid: HashSet<WorkerIdentifier> = nodes
+            .iter()
+            .filter_map(|&(ni, _)| self.ingredients[ni].with_reader(|r| r.is_for()).ok())
+            .flat_map(|of| self.readers_of(of))
+            .filter_map(|r| self.domains.get(&self.ingredients[r].domain()))
+            .flat_map(|d| (0..d.shards()).map(move |i| d.assignment(i)))
+            .collect();
+        let spread = self
+            .workers
+            .iter()
+            .any(|(i, w)| w.healthy && !avoid.contains(i));
+        let pinned = self
+            .place_on
+            .filter(|i| self.workers.get(i).map_or(false, |w| w.healthy));
+
         // TODO: can we just redirect all domain traffic through the worker's connection?
         let mut assignments = Vec::new();
         let mut nodes = Some(
@@ -538,11 +1772,16 @@ impl ControllerInner {
                 config: self.domain_config.clone(),
                 nodes,
                 persistence_parameters: self.persistence.clone(),
+                restore: self.materializations.restoring(),
             };
 
             let (identifier, w) = loop {
                 if let Some((i, w)) = wi.next() {
-                    if w.healthy {
+                    let wanted = match pinned {
+                        Some(pinned) => *i == pinned,
+                        None => !(spread && avoid.contains(i)),
+                    };
+                    if w.healthy && wanted {
                         break (*i, w);
                     }
                 } else {
@@ -659,15 +1898,71 @@ impl ControllerInner {
             added: Default::default(),
             columns: Default::default(),
             readers: Default::default(),
+            replay: Default::default(),
             context,
             start: time::Instant::now(),
             log: miglog,
         };
         let r = f(&mut m);
-        m.commit();
+        let backfill = m.commit();
+        if !self.background_backfills {
+            self.finish_backfills(|_, b| b.id() == backfill);
+        }
+        if !self.materializations.any_backfilling() {
+            self.replies.progress().finish();
+        }
+        r
+    }
+
+    /// Run `f`, letting the migrations it performs populate their new materializations in the
+    /// background.
+    fn in_background<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.background_backfills = true;
+        let r = f(self);
+        self.background_backfills = false;
         r
     }
 
+    /// The identifier that the backfill of the next migration will get.
+    pub(super) fn next_backfill(&self) -> usize {
+        self.materializations.next_backfill()
+    }
+
+    /// True if the new nodes of the migration with the given backfill are not all ready yet.
+    pub(super) fn is_backfilling(&self, backfill: usize) -> bool {
+        self.materializations.is_backfilling(backfill)
+    }
+
+    /// Ready the new nodes of earlier migrations whose replays have since finished.
+    pub(super) fn advance_backfills(&mut self) {
+        self.replies.poll();
+        self.materializations.advance_backfills(
+            &self.ingredients,
+            &mut self.domains,
+            &self.workers,
+            &mut self.replies,
+        );
+        if !self.materializations.any_backfilling() {
+            self.replies.progress().finish();
+        }
+    }
+
+    /// Wait until no earlier migration that `conflicts` is still populating its new
+    /// materializations.
+    pub(in crate::controller) fn finish_backfills<F>(&mut self, conflicts: F)
+    where
+        F: Fn(&Graph, &Backfill) -> bool,
+    {
+        let graph = &self.ingredients;
+        self.materializations.finish_backfills(
+            graph,
+            &mut self.domains,
+            &self.workers,
+            &mut self.replies,
+            |b| conflicts(graph, b),
+        );
+    }
+
     /// Perform a new query schema migration.
     // crate viz for tests
     pub(crate) fn migrate<F, T>(&mut self, f: F) -> T
@@ -675,12 +1970,14 @@ impl ControllerInner {
         F: FnOnce(&mut Migration) -> T,
     {
         info!(self.log, "starting migration");
+        self.replies.progress().start();
         let miglog = self.log.new(o!());
         let mut m = Migration {
             mainline: self,
             added: Default::default(),
             columns: Default::default(),
             readers: Default::default(),
+            replay: Default::default(),
             context: Default::default(),
             start: time::Instant::now(),
             log: miglog,
@@ -749,9 +2046,10 @@ impl ControllerInner {
         None
     }
 
-    /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
-    /// (already maintained) reader node called `name`.
-    fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
+    /// The reader node of the (already maintained) view called `name`.
+    fn reader_for(&self, name: &str) -> Option<NodeIndex> {
+        let name = self.view_names.resolve(name)?;
+
         // first try to resolve the node via the recipe, which handles aliasing between identical
         // queries.
         let node = match self.recipe.node_addr_for(name) {
@@ -767,23 +2065,76 @@ impl ControllerInner {
             None => name,
             Some(alias) => alias,
         };
-        self.find_view_for(node, name).map(|r| {
-            let domain = self.ingredients[r].domain();
+        self.find_view_for(node, name)
+    }
+
+    /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
+    /// (already maintained) reader node called `name`.
+    fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
+        let query = self.recipe.sql_for(self.view_names.resolve(name)?);
+        self.reader_for(name).map(|r| {
             let columns = self.ingredients[r].fields().to_vec();
             let schema = self.view_schema(r);
-            let shards = (0..self.domains[&domain].shards())
-                .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
+            let shards = self.reader_addrs(r);
+            let replicas = self
+                .readers_of(self.ingredients[r].with_reader(|r| r.is_for()).unwrap())
+                .into_iter()
+                .filter(|&replica| replica != r)
+                .map(|replica| (replica, self.reader_addrs(replica)))
                 .collect();
 
+            // lookups merge the rows of every shard of some readers in the reader's order
+            let n = &self.ingredients[r];
+            let merge = if n.merges_shards() {
+                n.with_reader(|reader| reader.order().cloned()).unwrap()
+            } else {
+                None
+            };
+
+            let shard_key_index = n.with_reader(|reader| reader.shard_key_index()).unwrap();
+            let shard_scheme = match n.sharded_by() {
+                Sharding::ByColumn(_, _, scheme) => scheme,
+                _ => ShardScheme::Hash,
+            };
+
             ViewBuilder {
                 node: r,
                 columns,
                 schema,
                 shards,
+                query,
+                merge,
+                replicas,
+                shard_key_index,
+                shard_scheme,
             }
         })
     }
 
+    /// The addresses that the shards of `reader` serve lookups on.
+    fn reader_addrs(&self, reader: NodeIndex) -> Vec<SocketAddr> {
+        let domain = &self.domains[&self.ingredients[reader].domain()];
+        (0..domain.shards())
+            .map(|i| self.read_addrs[&domain.assignment(i)])
+            .collect()
+    }
+
+    /// The readers of `node`: the reader of its view, along with any replicas of that reader.
+    fn readers_of(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        // like in `find_view_for`, the readers may not be immediate children due to sharding
+        let mut readers = Vec::new();
+        let mut bfs = Bfs::new(&self.ingredients, node);
+        while let Some(child) = bfs.next(&self.ingredients) {
+            if self.ingredients[child]
+                .with_reader(|r| r.is_for() == node)
+                .unwrap_or(false)
+            {
+                readers.push(child);
+            }
+        }
+        readers
+    }
+
     fn view_schema(&self, view_ni: NodeIndex) -> Option<Vec<ColumnSpecification>> {
         let n = &self.ingredients[view_ni];
         let schema: Vec<_> = (0..n.fields().len())
@@ -814,7 +2165,7 @@ impl ControllerInner {
             .unwrap_or_else(Vec::new);
         let mut is_primary = false;
         if key.is_empty() {
-            if let Sharding::ByColumn(col, _) = self.ingredients[ni].sharded_by() {
+            if let Sharding::ByColumn(col, _, _) = self.ingredients[ni].sharded_by() {
                 key = vec![col];
             }
         } else {
@@ -832,6 +2183,16 @@ impl ControllerInner {
         let base_operator = node
             .get_base()
             .expect("asked to get table for non-base node");
+        let shard_key = match (base_operator.shard_key(), node.sharded_by()) {
+            (Some(cols), _) => cols.to_vec(),
+            (None, Sharding::ByColumn(col, _, _)) => vec![col],
+            (None, _) => Vec::new(),
+        };
+        let shard_scheme = match node.sharded_by() {
+            Sharding::ByColumn(_, _, scheme) => scheme,
+            // rows are routed by all the columns of a compound shard key together
+            _ => node.shard_scheme(),
+        };
         let columns: Vec<String> = node
             .fields()
             .iter()
@@ -854,13 +2215,27 @@ impl ControllerInner {
             addr: node.local_addr(),
             key,
             key_is_primary: is_primary,
+            shard_key,
+            shard_scheme,
             dropped: base_operator.get_dropped(),
             table_name: node.name().to_owned(),
             columns,
             schema,
+            credentials: None,
         })
     }
 
+    /// The id of the user universe of `identity`, if one has been created for it.
+    ///
+    /// Clients that authenticate as such an identity only read the views of its universe.
+    fn user_universe<'a>(&'a self, identity: &DataType) -> Option<&'a DataType> {
+        self.universes
+            .iter()
+            .filter(|c| !c.contains_key("group"))
+            .filter_map(|c| c.get("id"))
+            .find(|&id| id == identity)
+    }
+
     /// Get statistics about the time spent processing different parts of the graph.
     fn get_statistics(&mut self) -> GraphStats {
         trace!(self.log, "asked to get statistics");
@@ -885,6 +2260,76 @@ impl ControllerInner {
         GraphStats { domains }
     }
 
+    /// Gather the statistics of the state of every node again if they are due to be, unless the
+    /// graph is changing.
+    fn refresh_state_statistics_if_due(&mut self) {
+        let due = match self.state_statistics {
+            Some((refreshed, _)) => refreshed.elapsed() >= STATE_STATISTICS_EVERY,
+            None => true,
+        };
+        if due && self.pending_migration.is_none() && self.workers.len() >= self.quorum {
+            self.refresh_state_statistics();
+        }
+    }
+
+    /// Gather the statistics of the state of every node, and add up those of the readers of each
+    /// view.
+    fn refresh_state_statistics(&mut self) {
+        let mut nodes = BTreeMap::new();
+        for (_, (_, shards)) in self.get_statistics().domains {
+            for (ni, ns) in shards {
+                if ns.materialized == MaterializationStatus::Not {
+                    continue;
+                }
+                let name = self.ingredients[ni].name().to_owned();
+                nodes
+                    .entry(ni)
+                    .or_insert_with(|| (name, StateStats::default()))
+                    .1
+                    .add(&ns);
+            }
+        }
+
+        let mut views = BTreeMap::new();
+        for (name, node) in self.view_names.rename_outputs(self.outputs()) {
+            let mut view = StateStats::default();
+            for (i, reader) in self.readers_of(node).into_iter().enumerate() {
+                if let Some((_, stats)) = nodes.get(&reader) {
+                    // replicas hold copies of the rows of the reader, so only their size and
+                    // lookups add to those of the view
+                    if i == 0 {
+                        view.rows = stats.rows;
+                        view.distinct_keys = stats.distinct_keys.clone();
+                    }
+                    view.mem_size += stats.mem_size;
+                    view.hits += stats.hits;
+                    view.misses += stats.misses;
+                }
+            }
+            views.insert(name, view);
+        }
+
+        let statistics = StateStatistics {
+            age: Duration::from_secs(0),
+            nodes,
+            views,
+        };
+        self.state_statistics = Some((Instant::now(), statistics));
+    }
+
+    /// The statistics of the state of every node and view, gathering them first if `fresh` or if
+    /// they never have been.
+    fn state_statistics(&mut self, fresh: bool) -> StateStatistics {
+        if fresh || self.state_statistics.is_none() {
+            self.refresh_state_statistics();
+        }
+        let (refreshed, statistics) = self.state_statistics.as_ref().unwrap();
+        StateStatistics {
+            age: refreshed.elapsed(),
+            ..statistics.clone()
+        }
+    }
+
     fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
         self.workers
             .iter()
@@ -892,6 +2337,69 @@ impl ControllerInner {
             .collect()
     }
 
+    fn start_profiling(&mut self) {
+        info!(self.log, "starting profiling run");
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::StartProfiling), &self.workers)
+                .unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(d));
+        }
+        self.profiling_since = Some(Instant::now());
+    }
+
+    fn stop_profiling(&mut self) -> Profile {
+        let duration = self
+            .profiling_since
+            .take()
+            .map(|t| t.elapsed())
+            .unwrap_or_default();
+        info!(self.log, "finished profiling run"; "duration" => ?duration);
+
+        let mut nodes = Vec::new();
+        for (&di, d) in &mut self.domains {
+            d.send_to_healthy(Box::new(Packet::StopProfiling), &self.workers)
+                .unwrap();
+            let replies = futures_executor::block_on(self.replies.wait_for_profiles(d));
+            for (shard, profile) in replies.into_iter().enumerate() {
+                for (ni, process_time) in profile {
+                    let n = &self.ingredients[ni];
+                    nodes.push(NodeProfile {
+                        node: ni,
+                        name: n.name().to_owned(),
+                        operator: if n.is_internal() {
+                            n.description(false)
+                        } else if n.is_base() {
+                            "Base table".to_owned()
+                        } else if n.is_reader() {
+                            "Leaf view".to_owned()
+                        } else {
+                            "Bookkeeping".to_owned()
+                        },
+                        domain: di.index(),
+                        shard,
+                        process_time,
+                    });
+                }
+            }
+        }
+        nodes.sort_by(|a, b| b.process_time.cmp(&a.process_time));
+
+        Profile { duration, nodes }
+    }
+
+    fn slow_replays(&mut self) -> Vec<SlowReplay> {
+        let mut slow = Vec::new();
+        for d in self.domains.values_mut() {
+            d.send_to_healthy(Box::new(Packet::GetSlowReplays), &self.workers)
+                .unwrap();
+            slow.extend(futures_executor::block_on(
+                self.replies.wait_for_slow_replays(d),
+            ));
+        }
+        slow.sort_by_key(|s| s.finished);
+        slow
+    }
+
     fn flush_partial(&mut self) -> u64 {
         // get statistics for current domain sizes
         // and evict all state from partial nodes
@@ -948,12 +2456,13 @@ impl ControllerInner {
         total_evicted
     }
 
-    pub(super) fn create_universe(
+    /// The groups that the user of a user universe with the given context is a member of.
+    ///
+    /// Group universes are not members of any groups.
+    fn universe_groups(
         &mut self,
-        context: HashMap<String, DataType>,
-    ) -> Result<(), String> {
-        let log = self.log.clone();
-        let mut r = self.recipe.clone();
+        context: &HashMap<String, DataType>,
+    ) -> HashMap<String, Vec<DataType>> {
         let groups = self.recipe.security_groups();
 
         let mut universe_groups = HashMap::new();
@@ -980,6 +2489,16 @@ impl ControllerInner {
                 universe_groups.insert(g, my_groups);
             }
         }
+        universe_groups
+    }
+
+    pub(super) fn create_universe(
+        &mut self,
+        context: HashMap<String, DataType>,
+    ) -> Result<(), String> {
+        let log = self.log.clone();
+        let mut r = self.recipe.clone();
+        let universe_groups = self.universe_groups(&context);
 
         self.add_universe(context.clone(), |mut mig| {
             r.next();
@@ -998,22 +2517,1567 @@ impl ControllerInner {
         });
 
         self.recipe = r;
+        self.universes.push(context);
         Ok(())
     }
 
+    /// Replace the security configuration.
+    ///
+    /// Universes that have already been created are updated in place: only their queries that
+    /// depend on a table whose read policies changed are reinstalled.
     fn set_security_config(&mut self, p: String) -> Result<(), String> {
+        let changed = match self.recipe.security_config() {
+            Some(old) if !self.universes.is_empty() => {
+                let new = SecurityConfig::parse(&p);
+                let changed = old.changed_tables(&new).ok_or_else(|| {
+                    "security groups cannot be changed once universes have been created".to_owned()
+                })?;
+                Some(changed)
+            }
+            _ => None,
+        };
+
         self.recipe.set_security_config(&p);
-        Ok(())
+        if let Some(changed) = changed {
+            self.update_universes(changed)?;
+        }
+        self.install_write_policies()
     }
 
-    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
-        let r = self.migrate(|mig| {
-            new.activate(mig)
-                .map_err(|e| format!("failed to activate recipe: {}", e))
-        });
+    /// Reinstall the queries of existing universes that depend on the tables whose read policies
+    /// changed for the universe's group, or for user universes under `None`.
+    fn update_universes(
+        &mut self,
+        changed: HashMap<Option<String>, HashSet<String>>,
+    ) -> Result<(), String> {
+        let log = self.log.clone();
+        let mut r = self.recipe.clone();
+
+        // user universes read from the queries of the groups their user is a member of, so they
+        // have to follow policy changes of any group, and be updated after the group universes
+        let all: HashSet<String> = changed.values().flatten().cloned().collect();
+        let mut universes = self.universes.clone();
+        universes.sort_by_key(|context| !context.contains_key("group"));
+
+        // the updated user universes must not hang off the views of their old shared policies
+        if let Some(tables) = changed.get(&None) {
+            r.forget_shared_policies(tables);
+        }
+
+        let mut removed = Vec::new();
+        for context in universes {
+            let tables = match context.get("group") {
+                Some(g) => changed.get(&Some(g.to_string())),
+                None => Some(&all),
+            };
+            let tables = match tables {
+                Some(tables) if !tables.is_empty() => tables,
+                _ => continue,
+            };
+
+            let universe_groups = self.universe_groups(&context);
+            let ar = self.add_universe(context.clone(), |mig| {
+                r.update_universe(mig, universe_groups, tables)
+            })?;
+            info!(log, "updated security policies of universe";
+                  "id" => ?context.get("id"), "queries" => ar.expressions_added);
+            removed.extend(ar.removed_leaves);
+        }
+        self.recipe = r;
+
+        // the old queries of user universes hang off those of group universes
+        for leaf in removed.into_iter().rev() {
+            self.remove_leaf(leaf)?;
+        }
+        Ok(())
+    }
+
+    /// Put `table` (or all tables, if `None`) into or out of read-only mode.
+    ///
+    /// Taking all tables out of read-only mode also clears any per-table settings. The setting is
+    /// persisted, so that it survives a change of leadership.
+    fn set_read_only<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (table, read_only): (Option<String>, bool),
+    ) -> Result<(), String> {
+        match table {
+            Some(table) => {
+                if !self.inputs().contains_key(&table) {
+                    return Err(format!("no table named {}", table));
+                }
+                if read_only {
+                    self.read_only_tables.insert(table);
+                } else {
+                    self.read_only_tables.remove(&table);
+                }
+            }
+            None => {
+                self.read_only = read_only;
+                if !read_only {
+                    self.read_only_tables.clear();
+                }
+            }
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.read_only = self.read_only;
+                    state.read_only_tables = self.read_only_tables.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist read-only mode".to_owned());
+        }
+
+        self.install_read_only()
+    }
+
+    /// Tell every base table whether it should currently reject writes.
+    ///
+    /// The messages carry our epoch as a fencing token, so that domains ignore read-only changes
+    /// from a controller that has since been replaced.
+    fn install_read_only(&mut self) -> Result<(), String> {
+        for (name, ni) in self.inputs() {
+            let n = &self.ingredients[ni];
+            let m = Box::new(Packet::SetReadOnly {
+                node: n.local_addr(),
+                read_only: self.read_only || self.read_only_tables.contains(&name),
+                epoch: self.epoch,
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set read-only mode: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        Ok(())
+    }
+
+    /// Forward a sample of the writes to `table` as `mirror` describes, or stop doing so.
+    ///
+    /// The setting is persisted, so that it survives a change of leadership.
+    fn set_mirror<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (table, mirror): (String, Option<Mirror>),
+    ) -> Result<(), String> {
+        if !self.inputs().contains_key(&table) {
+            return Err(format!("no table named {}", table));
+        }
+        match mirror {
+            Some(ref m) if !(0.0..=1.0).contains(&m.fraction) => {
+                return Err(format!(
+                    "cannot mirror a fraction of {} of writes",
+                    m.fraction
+                ));
+            }
+            Some(m) => {
+                self.mirrors.insert(table, m);
+            }
+            None => {
+                self.mirrors.remove(&table);
+            }
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.mirrors = self.mirrors.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist mirrors".to_owned());
+        }
+
+        self.install_mirrors()
+    }
+
+    /// Tell every base table what fraction of its writes to mirror, and where to.
+    fn install_mirrors(&mut self) -> Result<(), String> {
+        for (name, ni) in self.inputs() {
+            let n = &self.ingredients[ni];
+            let m = Box::new(Packet::SetMirror {
+                node: n.local_addr(),
+                mirror: self.mirrors.get(&name).cloned(),
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set mirror: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        Ok(())
+    }
+
+    /// Use `column` as the event-time column of `table`, or stop tracking its event time.
+    ///
+    /// The setting is persisted, so that it survives a change of leadership.
+    fn set_event_time<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (table, column): (String, Option<String>),
+    ) -> Result<(), String> {
+        let ni = match self.inputs().get(&table) {
+            Some(&ni) => ni,
+            None => return Err(format!("no table named {}", table)),
+        };
+        match column {
+            Some(column) => {
+                if !self.ingredients[ni].fields().contains(&column) {
+                    return Err(format!("table {} has no column {}", table, column));
+                }
+                self.event_time_columns.insert(table, column);
+            }
+            None => {
+                self.event_time_columns.remove(&table);
+            }
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.event_time_columns = self.event_time_columns.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist event-time columns".to_owned());
+        }
+
+        self.install_event_times()
+    }
+
+    /// Serve lookups on the view called `name` from `replicas` readers rather than just one.
+    fn replicate_view<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, replicas): (String, usize),
+    ) -> Result<(), String> {
+        if replicas == 0 {
+            return Err("a view needs at least one reader".to_owned());
+        }
+        let name = match self.view_names.resolve(&name) {
+            Some(resolved) if self.reader_for(&name).is_some() => resolved.to_owned(),
+            _ => return Err(format!("no view named {}", name)),
+        };
+        if replicas == 1 {
+            self.read_replicas.remove(&name);
+        } else {
+            self.read_replicas.insert(name.clone(), replicas);
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.read_replicas = self.read_replicas.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist read replicas".to_owned());
+        }
+
+        let replicated = self.replicate_reader(&name, replicas);
+        // new replicas must check the view's assertions, evict, prioritize and expire rows like its
+        // reader does
+        self.install_assertions()?;
+        self.install_evictions()?;
+        self.install_priorities()?;
+        self.install_ttls()?;
+        replicated
+    }
+
+    /// Give every replicated view as many readers as it is meant to have.
+    fn install_read_replicas(&mut self) -> Result<(), String> {
+        for (name, replicas) in self.read_replicas.clone() {
+            self.replicate_reader(&name, replicas)?;
+        }
+        Ok(())
+    }
+
+    /// Add or remove replicas of the reader of the view called `name` until it has `replicas`
+    /// readers in all.
+    fn replicate_reader(&mut self, name: &str, replicas: usize) -> Result<(), String> {
+        let reader = match self.reader_for(name) {
+            Some(r) => r,
+            None => {
+                // the view may be added back by a later recipe
+                debug!(self.log, "replicas of unknown view {}", name);
+                return Ok(());
+            }
+        };
+        let readers = self.readers_of(
+            self.ingredients[reader]
+                .with_reader(|r| r.is_for())
+                .unwrap(),
+        );
+        if readers.len() < replicas {
+            info!(self.log, "adding replicas of reader for {}", name;
+                  "replicas" => replicas - readers.len());
+            self.migrate(|mig| {
+                for _ in readers.len()..replicas {
+                    mig.add_reader_replica(reader);
+                }
+            });
+            return Ok(());
+        }
+
+        let surplus = readers.len() - replicas;
+        let extra: Vec<_> = readers
+            .into_iter()
+            .filter(|&r| r != reader)
+            .take(surplus)
+            .collect();
+        for &replica in &extra {
+            let mut parents = self
+                .ingredients
+                .neighbors_directed(replica, petgraph::EdgeDirection::Incoming)
+                .detach();
+            while let Some(edge) = parents.next_edge(&self.ingredients) {
+                self.ingredients.remove_edge(edge);
+            }
+        }
+        if extra.is_empty() {
+            Ok(())
+        } else {
+            info!(self.log, "removing replicas of reader for {}", name;
+                  "replicas" => extra.len());
+            self.remove_nodes(&extra)
+        }
+    }
+
+    /// Move the reader of the view called `name` to `worker`.
+    ///
+    /// A new reader is added on `worker` first, and gets the view's rows like any new reader, so
+    /// that the old reader serves lookups until then. Only after that is the old reader removed.
+    fn move_view(&mut self, (name, worker): (String, WorkerIdentifier)) -> Result<(), String> {
+        let reader = self
+            .reader_for(&name)
+            .ok_or_else(|| format!("no view named {}", name))?;
+        match self.workers.get(&worker) {
+            Some(w) if w.healthy => (),
+            _ => return Err(format!("no healthy worker at {}", worker)),
+        }
+        let of = self.ingredients[reader]
+            .with_reader(|r| r.is_for())
+            .unwrap();
+        if self.readers_of(of).len() > 1 {
+            return Err(format!(
+                "view {} has replicas, which are spread across workers",
+                name
+            ));
+        }
+        let domain = &self.domains[&self.ingredients[reader].domain()];
+        if (0..domain.shards()).all(|i| domain.assignment(i) == worker) {
+            return Ok(());
+        }
+
+        info!(
+            self.log,
+            "moving reader for {} to worker {:?}", name, worker
+        );
+        self.place_on = Some(worker);
+        self.migrate(|mig| {
+            mig.add_reader_replica(reader);
+        });
+        self.place_on = None;
+
+        let mut parents = self
+            .ingredients
+            .neighbors_directed(reader, petgraph::EdgeDirection::Incoming)
+            .detach();
+        while let Some(edge) = parents.next_edge(&self.ingredients) {
+            self.ingredients.remove_edge(edge);
+        }
+        self.remove_nodes(&[reader])?;
+        // the new reader must check the view's assertions, evict, prioritize and expire rows like
+        // the old one
+        self.install_assertions()?;
+        self.install_evictions()?;
+        self.install_priorities()?;
+        self.install_ttls()
+    }
+
+    /// Choose how the reader of the view called `name` evicts keys, and how many bytes it may
+    /// hold.
+    fn set_eviction<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, eviction): (String, Eviction),
+    ) -> Result<(), String> {
+        let reader = self
+            .reader_for(&name)
+            .ok_or_else(|| format!("no view named {}", name))?;
+        let on_disk = self.ingredients[reader]
+            .with_reader(|r| r.storage() == ViewStorage::Disk)
+            .unwrap();
+        match self
+            .materializations
+            .get_status(reader, &self.ingredients[reader])
+        {
+            MaterializationStatus::Partial { .. } => (),
+            // a view that is kept on disk evicts from the keys it caches in memory
+            _ if on_disk => (),
+            _ => {
+                return Err(format!(
+                    "view {} is fully materialized, so it never evicts",
+                    name
+                ));
+            }
+        }
+        let name = self.view_names.resolve(&name).unwrap().to_owned();
+        if eviction == Eviction::default() {
+            self.evictions.remove(&name);
+        } else {
+            self.evictions.insert(name.clone(), eviction);
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.evictions = self.evictions.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist eviction".to_owned());
+        }
+
+        self.evict_as(&name, eviction)
+    }
+
+    /// Tell the readers of every view with an eviction setting how to evict.
+    fn install_evictions(&mut self) -> Result<(), String> {
+        for (name, eviction) in self.evictions.clone() {
+            self.evict_as(&name, eviction)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the reader of the view called `name`, and any replicas of it, how to evict.
+    ///
+    /// The view's budget is split evenly across the shards of each reader.
+    fn evict_as(&mut self, name: &str, eviction: Eviction) -> Result<(), String> {
+        let reader = match self.reader_for(name) {
+            Some(r) => r,
+            None => {
+                // the view may be added back by a later recipe
+                debug!(self.log, "eviction for unknown view {}", name);
+                return Ok(());
+            }
+        };
+        let of = self.ingredients[reader]
+            .with_reader(|r| r.is_for())
+            .unwrap();
+        for r in self.readers_of(of) {
+            let n = &self.ingredients[r];
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            let shards = domain.shards();
+            let m = Box::new(Packet::SetEviction {
+                node: n.local_addr(),
+                eviction: Eviction {
+                    budget: eviction.budget.map(|budget| budget / shards),
+                    ..eviction
+                },
+            });
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set eviction: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+        Ok(())
+    }
+
+    /// Choose how urgently the upqueries of the view called `name` are handled.
+    fn set_view_priority<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, priority): (String, ViewPriority),
+    ) -> Result<(), String> {
+        if self.reader_for(&name).is_none() {
+            return Err(format!("no view named {}", name));
+        }
+        let name = self.view_names.resolve(&name).unwrap().to_owned();
+        if priority == ViewPriority::default() {
+            self.priorities.remove(&name);
+        } else {
+            self.priorities.insert(name.clone(), priority);
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.priorities = self.priorities.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist view priority".to_owned());
+        }
+
+        self.prioritize_as(&name, priority)
+    }
+
+    /// Tell the readers of every view with a priority how urgent their upqueries are.
+    fn install_priorities(&mut self) -> Result<(), String> {
+        for (name, priority) in self.priorities.clone() {
+            self.prioritize_as(&name, priority)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the reader of the view called `name`, and any replicas of it, how urgent its
+    /// upqueries are.
+    fn prioritize_as(&mut self, name: &str, priority: ViewPriority) -> Result<(), String> {
+        let reader = match self.reader_for(name) {
+            Some(r) => r,
+            None => {
+                // the view may be added back by a later recipe
+                debug!(self.log, "priority for unknown view {}", name);
+                return Ok(());
+            }
+        };
+        let of = self.ingredients[reader]
+            .with_reader(|r| r.is_for())
+            .unwrap();
+        for r in self.readers_of(of) {
+            let n = &self.ingredients[r];
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            let m = Box::new(Packet::SetPriority {
+                node: n.local_addr(),
+                priority,
+            });
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set view priority: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+        Ok(())
+    }
+
+    /// Choose whether the readers of the view called `name` keep their rows in memory or on disk.
+    /// The columns of the view called `name`, and every row it holds across its shards.
+    fn view_rows(&mut self, name: &str) -> Result<(Vec<String>, Vec<Vec<DataType>>), String> {
+        let reader = self
+            .reader_for(name)
+            .ok_or_else(|| format!("no view named {}", name))?;
+        if let MaterializationStatus::Partial { .. } = self
+            .materializations
+            .get_status(reader, &self.ingredients[reader])
+        {
+            return Err(format!(
+                "view {} is partially materialized, so it only holds the keys that are read",
+                name
+            ));
+        }
+
+        let n = &self.ingredients[reader];
+        let d = self.domains.get_mut(&n.domain()).unwrap();
+        let m = Box::new(Packet::ScanRows {
+            node: n.local_addr(),
+        });
+        d.send_to_healthy(m, &self.workers)
+            .map_err(|e| format!("failed to ask for the rows of {}: {:?}", name, e))?;
+        let rows = futures_executor::block_on(self.replies.wait_for_rows(d));
+        Ok((n.fields().to_vec(), rows))
+    }
+
+    /// The rows that the view called `name` should hold, from evaluating its query naively over
+    /// every row of the tables it reads from.
+    ///
+    /// Views that the query reads from are evaluated the same way, rather than read.
+    fn reference_rows(&mut self, name: &str) -> Result<ReferenceRows, String> {
+        let query = self
+            .recipe
+            .query_for(name)
+            .ok_or_else(|| format!("no view named {}", name))?
+            .clone();
+        oracle::evaluate(&query, |table| {
+            let ni = self.recipe.node_addr_for(table)?;
+            if !self.ingredients[ni].is_base() {
+                let view = self.reference_rows(table)?;
+                if !view.key.is_empty() {
+                    return Err(format!("view {} has parameters", table));
+                }
+                return Ok((view.columns, view.rows));
+            }
+
+            let n = &self.ingredients[ni];
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            let m = Box::new(Packet::ScanRows {
+                node: n.local_addr(),
+            });
+            d.send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to ask for the rows of {}: {:?}", table, e))?;
+            let rows = futures_executor::block_on(self.replies.wait_for_rows(d));
+            Ok((n.fields().to_vec(), rows))
+        })
+    }
+
+    fn set_view_storage<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, storage): (String, ViewStorage),
+    ) -> Result<(), String> {
+        let reader = self
+            .reader_for(&name)
+            .ok_or_else(|| format!("no view named {}", name))?;
+        if storage == ViewStorage::Disk {
+            if let MaterializationStatus::Partial { .. } = self
+                .materializations
+                .get_status(reader, &self.ingredients[reader])
+            {
+                return Err(format!(
+                    "view {} is partially materialized, so it only holds the keys that are read",
+                    name
+                ));
+            }
+            if self.ingredients[reader]
+                .with_reader(|r| r.range().is_some())
+                .unwrap()
+            {
+                return Err(format!(
+                    "view {} answers range lookups, which need all its keys in memory",
+                    name
+                ));
+            }
+        }
+        let name = self.view_names.resolve(&name).unwrap().to_owned();
+        if storage == ViewStorage::default() {
+            self.view_storage.remove(&name);
+        } else {
+            self.view_storage.insert(name.clone(), storage);
+        }
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.view_storage = self.view_storage.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist view storage".to_owned());
+        }
+
+        self.store_as(reader, storage)
+    }
+
+    /// Replace the readers that serve the same lookups as `reader`, and that keep their rows
+    /// elsewhere than `storage` says, with readers that keep them there.
+    ///
+    /// A reader is told where to keep its rows before it is added, so like when a view is moved,
+    /// the old readers serve lookups until the new ones have all the rows.
+    fn store_as(&mut self, reader: NodeIndex, storage: ViewStorage) -> Result<(), String> {
+        let of = self.ingredients[reader]
+            .with_reader(|r| r.is_for())
+            .unwrap();
+        let old: Vec<_> = self
+            .readers_of(of)
+            .into_iter()
+            .filter(|&r| {
+                self.ingredients[r]
+                    .with_reader(|r| r.storage() != storage)
+                    .unwrap()
+            })
+            .collect();
+        if old.is_empty() {
+            return Ok(());
+        }
+
+        info!(self.log, "replacing readers to change their storage";
+              "readers" => old.len(), "storage" => ?storage);
+        for &r in &old {
+            self.ingredients[r]
+                .with_reader_mut(|r| r.set_storage(storage))
+                .unwrap();
+        }
+        self.migrate(|mig| {
+            for &r in &old {
+                mig.add_reader_replica(r);
+            }
+        });
+
+        for &r in &old {
+            let mut parents = self
+                .ingredients
+                .neighbors_directed(r, petgraph::EdgeDirection::Incoming)
+                .detach();
+            while let Some(edge) = parents.next_edge(&self.ingredients) {
+                self.ingredients.remove_edge(edge);
+            }
+        }
+        self.remove_nodes(&old)?;
+        // the new readers must check the view's assertions, evict, prioritize and expire rows
+        // like the old ones
+        self.install_assertions()?;
+        self.install_evictions()?;
+        self.install_priorities()?;
+        self.install_ttls()
+    }
+
+    /// Tell every base table which constraints its rows must satisfy: the `NOT NULL` columns of
+    /// its schema, and the checks declared on it in the recipe.
+    fn install_constraints(&mut self) -> Result<(), String> {
+        for (name, ni) in self.inputs() {
+            let n = &self.ingredients[ni];
+            let mut constraints = match self.recipe.schema_for(&name) {
+                Some(Schema::Table(ctq)) => constraints::column_constraints(&ctq, n.fields()),
+                _ => Vec::new(),
+            };
+            for check in self.recipe.checks().iter().filter(|c| c.table == name) {
+                constraints.push(check.compile(n.fields())?);
+            }
+            let m = Box::new(Packet::SetConstraints {
+                node: n.local_addr(),
+                constraints,
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to install constraints: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        Ok(())
+    }
+
+    /// Tell every base table, and the readers of every view with a row TTL, how long to keep
+    /// rows for.
+    fn install_ttls(&mut self) -> Result<(), String> {
+        let mut expiring = Vec::new();
+        let mut ttls = Vec::new();
+        let inputs = self.inputs();
+        for (name, &ni) in &inputs {
+            let ttl = match self.recipe.ttl(name) {
+                Some(ttl) => Some(self.row_ttl(ni, ttl)?),
+                None => None,
+            };
+            ttls.push((ni, ttl));
+        }
+        for ttl in self.recipe.ttls() {
+            if inputs.contains_key(&ttl.name) {
+                continue;
+            }
+            let reader = match self.reader_for(&ttl.name) {
+                Some(r) => r,
+                None => {
+                    debug!(self.log, "row ttl on unknown view {}", ttl.name);
+                    continue;
+                }
+            };
+            let row_ttl = self.row_ttl(reader, ttl)?;
+            // replicas of the reader keep rows for just as long
+            let of = self.ingredients[reader]
+                .with_reader(|r| r.is_for())
+                .unwrap();
+            for r in self.readers_of(of) {
+                ttls.push((r, Some(row_ttl)));
+            }
+        }
+
+        for (ni, ttl) in ttls {
+            if ttl.is_some() {
+                expiring.push(ni);
+            }
+            let n = &self.ingredients[ni];
+            let m = Box::new(Packet::SetTtl {
+                node: n.local_addr(),
+                ttl,
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set row ttl: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        self.expiring = expiring;
+        Ok(())
+    }
+
+    /// The row TTL of node `ni` of the table or view that `ttl` was declared for.
+    fn row_ttl(&self, ni: NodeIndex, ttl: &Ttl) -> Result<node::special::RowTtl, String> {
+        let column = self.ingredients[ni]
+            .fields()
+            .iter()
+            .position(|f| *f == ttl.column)
+            .ok_or_else(|| format!("{} has no column named {}", ttl.name, ttl.column))?;
+        Ok(node::special::RowTtl {
+            column,
+            ttl: ttl.ttl,
+        })
+    }
+
+    /// Tell every base table which of its columns holds the event time of its records.
+    fn install_event_times(&mut self) -> Result<(), String> {
+        for (name, ni) in self.inputs() {
+            let n = &self.ingredients[ni];
+            let column = self
+                .event_time_columns
+                .get(&name)
+                .and_then(|c| n.fields().iter().position(|f| f == c));
+            let m = Box::new(Packet::SetEventTime {
+                node: n.local_addr(),
+                column,
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to set event-time column: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        Ok(())
+    }
+
+    /// Keep writes to `table` that were rejected for not fitting its schema.
+    ///
+    /// Only the last `MAX_DEAD_LETTERS` rejected writes to each table are kept, and they are not
+    /// persisted across controller restarts.
+    pub(super) fn record_dead_letters(&mut self, table: String, letters: Vec<DeadLetter>) {
+        warn!(self.log, "rejected {} invalid writes", letters.len(); "table" => &table);
+        let kept = self.dead_letters.entry(table).or_default();
+        kept.extend(letters);
+        while kept.len() > MAX_DEAD_LETTERS {
+            kept.pop_front();
+        }
+    }
+
+    /// The writes to `table` that were most recently rejected for not fitting its schema.
+    fn dead_letters(&self, table: &str) -> Vec<DeadLetter> {
+        self.dead_letters
+            .get(table)
+            .map(|kept| kept.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Check the rows added to a view against an assertion from now on.
+    fn add_assertion<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, view, assertion, halt): (String, String, Assertion, bool),
+    ) -> Result<(), String> {
+        if self.assertions.iter().any(|a| a.name == name) {
+            return Err(format!("assertion {} already exists", name));
+        }
+        let reader = self
+            .reader_for(&view)
+            .ok_or_else(|| format!("no view named {}", view))?;
+
+        let spec = AssertionSpec {
+            name,
+            view,
+            assertion,
+            halt,
+        };
+        spec.compile(self.ingredients[reader].fields())?;
+        self.assertions.push(spec);
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.assertions = self.assertions.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist assertions".to_owned());
+        }
+
+        self.install_assertions()
+    }
+
+    /// Tell the reader of every view with assertions which assertions to check.
+    ///
+    /// Views are recreated by recipe changes, so this has to be done again after every change.
+    fn install_assertions(&mut self) -> Result<(), String> {
+        let mut by_reader: HashMap<NodeIndex, Vec<_>> = HashMap::new();
+        for spec in &self.assertions {
+            let reader = match self.reader_for(&spec.view) {
+                Some(r) => r,
+                None => {
+                    // the view may be added back by a later recipe
+                    debug!(
+                        self.log,
+                        "assertion {} on unknown view {}", spec.name, spec.view
+                    );
+                    continue;
+                }
+            };
+            let compiled = spec.compile(self.ingredients[reader].fields())?;
+            by_reader.entry(reader).or_default().push(compiled);
+        }
+
+        for (reader, assertions) in by_reader {
+            // replicas of the reader check the same assertions
+            let of = self.ingredients[reader]
+                .with_reader(|r| r.is_for())
+                .unwrap();
+            for r in self.readers_of(of) {
+                let n = &self.ingredients[r];
+                let m = Box::new(Packet::SetAssertions {
+                    node: n.local_addr(),
+                    assertions: assertions.clone(),
+                });
+
+                let domain = self.domains.get_mut(&n.domain()).unwrap();
+                domain
+                    .send_to_healthy(m, &self.workers)
+                    .map_err(|e| format!("failed to install assertions: {:?}", e))?;
+                futures_executor::block_on(self.replies.wait_for_acks(&domain));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keep rows that violated an assertion on `view`, along with where the checked column came
+    /// from.
+    ///
+    /// Only the last `MAX_VIOLATIONS` violations on each view are kept, and they are not persisted
+    /// across controller restarts.
+    pub(super) fn record_violations(&mut self, view: String, mut violations: Vec<Violation>) {
+        warn!(self.log, "{} rows violated assertions", violations.len(); "view" => &view);
+        if let Some(reader) = self.reader_for(&view) {
+            let fields = self.ingredients[reader].fields();
+            for v in &mut violations {
+                let column = self
+                    .assertions
+                    .iter()
+                    .find(|a| a.name == v.assertion)
+                    .and_then(|a| fields.iter().position(|f| f == a.assertion.column()));
+                if let Some(column) = column {
+                    v.provenance = assertions::column_provenance(&self.ingredients, reader, column);
+                }
+            }
+        }
+
+        let kept = self.quarantine.entry(view).or_default();
+        kept.extend(violations);
+        while kept.len() > MAX_VIOLATIONS {
+            kept.pop_front();
+        }
+    }
+
+    /// The rows that most recently violated an assertion on `view`.
+    fn quarantine(&self, view: &str) -> Vec<Violation> {
+        self.quarantine
+            .get(view)
+            .map(|kept| kept.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub(super) fn record_reader_load(&mut self, worker: WorkerIdentifier, load: ReaderLoad) {
+        match self.workers.get_mut(&worker) {
+            None => warn!(self.log, "got reader load for unknown worker {:?}", worker),
+            Some(ws) => ws.reader_load = load,
+        }
+    }
+
+    /// How busy the reader of each worker was when it last reported.
+    fn reader_load(&self) -> Vec<(WorkerIdentifier, ReaderLoad)> {
+        self.workers
+            .iter()
+            .map(|(&id, ws)| (id, ws.reader_load.clone()))
+            .collect()
+    }
+
+    /// Append an event to the event log, and `POST` it to the event webhook if there is one.
+    ///
+    /// Only the last `MAX_EVENTS` events are kept. They are persisted in the authority, so that
+    /// they survive controller failures.
+    pub(super) fn record_event<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        kind: ControllerEventKind,
+    ) {
+        let event = ControllerEvent {
+            seq: self.events.back().map(|e| e.seq + 1).unwrap_or(0),
+            at: SystemTime::now(),
+            kind,
+        };
+        info!(self.log, "controller event: {:?}", event.kind; "seq" => event.seq);
+
+        if let Some(ref url) = self.event_webhook {
+            events::post_to_webhook(&self.log, url, &event);
+        }
+
+        self.events.push_back(event);
+        while self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.events = self.events.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            warn!(self.log, "failed to persist event log");
+        }
+    }
+
+    /// Record an eviction by the given worker, if it was large enough to be of interest.
+    pub(super) fn handle_eviction<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        worker: WorkerIdentifier,
+        bytes: usize,
+    ) {
+        match self.eviction_event_threshold {
+            Some(threshold) if bytes >= threshold => {
+                self.record_event(authority, ControllerEventKind::Evicted { worker, bytes });
+            }
+            _ => {}
+        }
+    }
+
+    /// The events in the event log, starting at sequence number `since`.
+    fn events(&self, since: u64) -> Vec<ControllerEvent> {
+        self.events
+            .iter()
+            .filter(|e| e.seq >= since)
+            .cloned()
+            .collect()
+    }
+
+    /// Run `action` whenever the contents of `view` change.
+    fn add_trigger<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, view, action): (String, String, TriggerAction),
+    ) -> Result<(), String> {
+        if self.triggers.registered.iter().any(|t| t.name == name) {
+            return Err(format!("trigger {} already exists", name));
+        }
+        if let TriggerAction::Kafka { .. } | TriggerAction::Redis { .. } = action {
+            if !cfg!(feature = "connectors") {
+                return Err(String::from(
+                    "publishing to Kafka and Redis needs the connectors feature",
+                ));
+            }
+        }
+
+        let spec = TriggerSpec { name, view, action };
+        self.install_trigger(&spec)?;
+        self.triggers.registered.push(spec);
+        self.persist_triggers(authority)
+    }
+
+    /// Add a trigger node for `spec` below the leaf of its view.
+    fn install_trigger(&mut self, spec: &TriggerSpec) -> Result<(), String> {
+        let leaf = self.recipe.node_addr_for(&spec.view)?;
+        let mut fields = self.ingredients[leaf].fields().to_vec();
+        let event = TriggerEvent::Action {
+            name: spec.name.clone(),
+            action: spec.action.clone(),
+        };
+        let name = spec.name.clone();
+        self.migrate(move |mig| {
+            // views without parameters are keyed on a constant column that actions shouldn't see
+            let src = if fields.last().map(String::as_str) == Some("bogokey") {
+                fields.pop();
+                let emit: Vec<_> = (0..fields.len()).collect();
+                mig.add_ingredient(
+                    format!("{}-project", name),
+                    fields.clone(),
+                    Project::new(leaf, &emit, None, None),
+                )
+            } else {
+                leaf
+            };
+            mig.add_ingredient(
+                format!("{}-trigger", name),
+                fields,
+                Trigger::new(src, event, 0),
+            );
+        });
+        Ok(())
+    }
+
+    /// Remove the cascades of foreign keys that `new` no longer declares, or whose referenced table
+    /// it has replaced with a new base node.
+    fn remove_cascades(&mut self, new: &Recipe) -> Result<(), String> {
+        let stale: Vec<_> = self
+            .cascades
+            .iter()
+            .filter(|&(fk, &(base, _))| {
+                !new.foreign_keys().contains(fk) || new.node_addr_for(&fk.references) != Ok(base)
+            })
+            .map(|(fk, _)| fk.clone())
+            .collect();
+        for fk in stale {
+            let (_, trigger) = self.cascades.remove(&fk).unwrap();
+            debug!(self.log, "removing cascade"; "table" => &fk.table, "column" => &fk.column);
+            self.remove_leaf(trigger)?;
+        }
+        Ok(())
+    }
+
+    /// Add a trigger below the referenced table of each foreign key in the recipe that does not
+    /// have one yet, which deletes the rows that refer to rows deleted from that table.
+    ///
+    /// The deletes are issued by the controller once the trigger fires, so views briefly see the
+    /// referring rows after the rows they refer to are gone.
+    fn install_cascades(&mut self) -> Result<(), String> {
+        let missing: Vec<_> = self
+            .recipe
+            .foreign_keys()
+            .iter()
+            .filter(|fk| !self.cascades.contains_key(fk))
+            .cloned()
+            .collect();
+        for fk in missing {
+            let base = self.recipe.node_addr_for(&fk.references)?;
+            let fields = self.ingredients[base].fields().to_vec();
+            let key = fields
+                .iter()
+                .position(|f| *f == fk.referenced_column)
+                .ok_or_else(|| {
+                    format!(
+                        "table {} has no column named {}",
+                        fk.references, fk.referenced_column
+                    )
+                })?;
+            let name = format!("{}.{}-cascade", fk.table, fk.column);
+            let event = TriggerEvent::Action {
+                name: name.clone(),
+                action: TriggerAction::DeleteReferencing {
+                    table: fk.table.clone(),
+                    column: fk.column.clone(),
+                    key,
+                },
+            };
+            let trigger = self.migrate(move |mig| {
+                mig.add_ingredient(
+                    format!("{}-trigger", name),
+                    fields,
+                    Trigger::new(base, event, 0),
+                )
+            });
+            self.cascades.insert(fk, (base, trigger));
+        }
+        Ok(())
+    }
+
+    /// Queue the action for a trigger firing, and try to run it.
+    ///
+    /// The firing is persisted before its action is run for the first time.
+    pub(super) fn fire_trigger<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        firing: TriggerFiring,
+    ) {
+        for p in self.firings.push(PendingFiring::new(firing)) {
+            crit!(
+                self.log,
+                "dropping trigger firing, since too many firings have not completed";
+                "trigger" => &p.firing.trigger,
+                "attempts" => p.attempts,
+            );
+        }
+        if let Err(e) = self.persist_firings(authority) {
+            crit!(self.log, "{}", e);
+        }
+        self.run_triggers(authority);
+    }
+
+    /// Collect the trigger actions that have finished, and start the actions of pending trigger
+    /// firings that are due, in the order they fired.
+    ///
+    /// Each trigger runs one action at a time, and if a firing's action fails, later firings of
+    /// the same trigger are held back until it succeeds, so that each trigger observes changes
+    /// in order.
+    pub(super) fn run_triggers<A: Authority + 'static>(&mut self, authority: &Arc<A>) {
+        let mut completed = 0;
+        for (id, result) in self.actions.reap() {
+            // the firing may have been dropped while its action ran
+            let i = match self.firings.pending.iter().position(|p| p.id == id) {
+                Some(i) => i,
+                None => continue,
+            };
+            match result {
+                Ok(()) => {
+                    self.firings.pending.remove(i);
+                    completed += 1;
+                }
+                Err(e) => {
+                    let p = &mut self.firings.pending[i];
+                    warn!(
+                        self.log,
+                        "trigger action failed: {}", e;
+                        "trigger" => &p.firing.trigger,
+                        "attempts" => p.attempts + 1,
+                    );
+                    p.failed();
+                }
+            }
+        }
+        if completed > 0 {
+            if let Err(e) = self.persist_firings(authority) {
+                crit!(self.log, "{}", e);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for i in 0..self.firings.pending.len() {
+            let p = &self.firings.pending[i];
+            if !seen.insert(p.firing.trigger.clone())
+                || self.actions.is_running(&p.firing.trigger)
+                || !p.is_due()
+            {
+                continue;
+            }
+
+            let (id, firing) = (p.id, p.firing.clone());
+            match self.trigger_action(firing.clone()) {
+                Ok(action) => self.actions.start(&firing.trigger, id, action),
+                Err(e) => {
+                    let p = &mut self.firings.pending[i];
+                    warn!(
+                        self.log,
+                        "trigger action failed: {}", e;
+                        "trigger" => &p.firing.trigger,
+                        "attempts" => p.attempts + 1,
+                    );
+                    p.failed();
+                }
+            }
+        }
+    }
+
+    /// The action to run for a trigger firing.
+    ///
+    /// Anything that needs the controller is done here, and the rest is left to the returned
+    /// future, which runs as a task of its own.
+    fn trigger_action(
+        &mut self,
+        firing: TriggerFiring,
+    ) -> Result<BoxFuture<'static, Result<(), String>>, String> {
+        match firing.action {
+            TriggerAction::Insert { ref table } => {
+                let rows: Vec<_> = firing
+                    .changes
+                    .iter()
+                    .filter(|&&(_, positive)| positive)
+                    .map(|(row, _)| TableOperation::Insert(row.clone()))
+                    .collect();
+                if rows.is_empty() {
+                    return Ok(future::ready(Ok(())).boxed());
+                }
+
+                let mut table = self
+                    .table_builder(table)
+                    .ok_or_else(|| format!("no table named {}", table))?
+                    .build(Arc::new(Mutex::new(HashMap::new())))
+                    .map_err(|e| e.to_string())?;
+                Ok(async move { table.perform_all(rows).await.map_err(|e| e.to_string()) }.boxed())
+            }
+            TriggerAction::Webhook { ref url } => {
+                Ok(triggers::post_to_webhook(url.clone(), firing.clone()).boxed())
+            }
+            TriggerAction::Sink { ref path } => {
+                let path = path.clone();
+                Ok(triggers::blocking(move || {
+                    triggers::append_to_sink(&path, &firing)
+                }))
+            }
+            TriggerAction::Kafka { .. } | TriggerAction::Redis { .. } => {
+                // the workers publish these changes themselves, and never hand them over
+                Ok(future::ready(Ok(())).boxed())
+            }
+            TriggerAction::Federate {
+                ref zookeeper,
+                ref table,
+            } => {
+                let (zookeeper, table) = (zookeeper.clone(), table.clone());
+                let shadows = self.shadows.clone();
+                Ok(triggers::blocking(move || {
+                    let mut shadows = shadows.lock().unwrap();
+                    shadows.federate(&zookeeper, &table, &firing.changes)
+                }))
+            }
+            TriggerAction::DeleteReferencing {
+                ref table,
+                ref column,
+                key,
+            } => {
+                let added: HashSet<_> = firing
+                    .changes
+                    .iter()
+                    .filter(|&&(_, positive)| positive)
+                    .map(|(row, _)| &row[key])
+                    .collect();
+                let mut removed: Vec<_> = firing
+                    .changes
+                    .iter()
+                    .filter(|&&(_, positive)| !positive)
+                    .map(|(row, _)| &row[key])
+                    .filter(|k| !added.contains(k))
+                    .collect();
+                removed.sort();
+                removed.dedup();
+                if removed.is_empty() {
+                    return Ok(future::ready(Ok(())).boxed());
+                }
+
+                let mut referring = self
+                    .table_builder(table)
+                    .ok_or_else(|| format!("no table named {}", table))?
+                    .build(Arc::new(Mutex::new(HashMap::new())))
+                    .map_err(|e| e.to_string())?;
+                let column = referring
+                    .columns()
+                    .iter()
+                    .position(|c| c == column)
+                    .ok_or_else(|| format!("table {} has no column named {}", table, column))?;
+                let deletes: Vec<_> = removed
+                    .into_iter()
+                    .map(|k| TableOperation::DeleteWhere {
+                        conditions: vec![Condition {
+                            column,
+                            comparison: Comparison::Equal,
+                            value: k.clone(),
+                        }],
+                    })
+                    .collect();
+                Ok(async move {
+                    referring
+                        .perform_all(deletes)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                .boxed())
+            }
+        }
+    }
+
+    fn add_kafka_source<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (name, source): (String, KafkaSource),
+    ) -> Result<(), String> {
+        if !cfg!(feature = "connectors") {
+            return Err(String::from("Kafka sources need the connectors feature"));
+        }
+        if self.kafka_sources.contains_key(&name) {
+            return Err(format!("Kafka source {} already exists", name));
+        }
+        if !self.inputs().contains_key(&source.table) {
+            return Err(format!("no table named {}", source.table));
+        }
+        self.kafka_sources.insert(name.clone(), source);
+        self.persist_kafka_sources(authority)?;
+        self.start_kafka_source(&name)
+    }
+
+    fn remove_kafka_source<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        name: String,
+    ) -> Result<(), String> {
+        if self.kafka_sources.remove(&name).is_none() {
+            return Err(format!("no Kafka source named {}", name));
+        }
+        self.connectors.stop(&name);
+        self.persist_kafka_sources(authority)
+    }
+
+    /// Start writing the messages of the Kafka source `name` to its table.
+    fn start_kafka_source(&mut self, name: &str) -> Result<(), String> {
+        if !cfg!(feature = "connectors") {
+            return Err(String::from("Kafka sources need the connectors feature"));
+        }
+        let source = self.kafka_sources[name].clone();
+        let table = self
+            .table_builder(&source.table)
+            .ok_or_else(|| format!("no table named {}", source.table))?
+            .build(Arc::new(Mutex::new(HashMap::new())))
+            .map_err(|e| e.to_string())?;
+        let log = self.log.new(o!("kafka_source" => name.to_owned()));
+        self.connectors.start(name, source, table, log);
+        Ok(())
+    }
+
+    /// Start new connectors for the Kafka sources whose connectors have stopped, such as because
+    /// the domain of their table failed.
+    fn restart_kafka_sources(&mut self) {
+        for (name, why) in self.connectors.reap() {
+            warn!(self.log, "Kafka source stopped: {}", why; "source" => &name);
+            if !self.kafka_sources.contains_key(&name) {
+                continue;
+            }
+            if let Err(e) = self.start_kafka_source(&name) {
+                error!(self.log, "failed to restart Kafka source: {}", e; "source" => &name);
+            }
+        }
+    }
+
+    fn persist_kafka_sources<A: Authority + 'static>(
+        &self,
+        authority: &Arc<A>,
+    ) -> Result<(), String> {
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.kafka_sources = self.kafka_sources.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist Kafka sources".to_owned());
+        }
+        Ok(())
+    }
+
+    fn persist_triggers<A: Authority + 'static>(&self, authority: &Arc<A>) -> Result<(), String> {
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.triggers = self.triggers.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist trigger state".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Take over the trigger firings that the previous controller left pending, so that their
+    /// actions are run, and so that it can no longer change them.
+    fn restore_firings<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+    ) -> Result<(), String> {
+        let epoch = self.epoch;
+        match authority.read_modify_write(
+            triggers::FIRINGS_KEY,
+            |firings: Option<PendingFirings>| match firings {
+                None => Ok(PendingFirings::new(epoch)),
+                Some(ref firings) if firings.epoch > epoch => Err(()),
+                Some(mut firings) => {
+                    firings.epoch = epoch;
+                    Ok(firings)
+                }
+            },
+        ) {
+            Ok(Ok(firings)) => {
+                self.firings = firings;
+                Ok(())
+            }
+            _ => Err("Failed to restore trigger firings".to_owned()),
+        }
+    }
+
+    fn persist_firings<A: Authority + 'static>(&self, authority: &Arc<A>) -> Result<(), String> {
+        if authority
+            .read_modify_write(triggers::FIRINGS_KEY, |firings: Option<PendingFirings>| {
+                match firings {
+                    Some(ref firings) if firings.epoch > self.epoch => Err(()),
+                    _ => Ok(self.firings.clone()),
+                }
+            })
+            .is_err()
+        {
+            return Err("Failed to persist trigger firings".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Tell every base table which write policies it should enforce.
+    ///
+    /// Bases without write policies are sent an empty list, so that policies removed from the
+    /// security configuration stop being enforced.
+    fn install_write_policies(&mut self) -> Result<(), String> {
+        let mut policies: HashMap<NodeIndex, Vec<node::special::WritePolicy>> = HashMap::new();
+        for wp in self.recipe.write_policies() {
+            let ni = match self.inputs().get(&wp.table) {
+                Some(&ni) => ni,
+                None => {
+                    // the table may be added by a later recipe
+                    debug!(self.log, "write policy for unknown table {}", wp.table);
+                    continue;
+                }
+            };
+            let compiled = wp.compile(self.ingredients[ni].fields())?;
+            policies.entry(ni).or_insert_with(Vec::new).push(compiled);
+        }
+
+        for &ni in self.inputs().values() {
+            let n = &self.ingredients[ni];
+            let m = Box::new(Packet::SetWritePolicies {
+                node: n.local_addr(),
+                policies: policies.remove(&ni).unwrap_or_default(),
+            });
+
+            let domain = self.domains.get_mut(&n.domain()).unwrap();
+            domain
+                .send_to_healthy(m, &self.workers)
+                .map_err(|e| format!("failed to install write policies: {:?}", e))?;
+            futures_executor::block_on(self.replies.wait_for_acks(&domain));
+        }
+
+        Ok(())
+    }
+
+    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
+        let r = self.migrate(|mig| {
+            new.activate(mig)
+                .map_err(|e| format!("failed to activate recipe: {}", e))
+        });
 
         match r {
             Ok(ref ra) => {
+                // cascades hang off the bases they refer to, so they must go before those do
+                self.remove_cascades(&new)?;
+
                 let (removed_bases, removed_other): (Vec<_>, Vec<_>) = ra
                     .removed_leaves
                     .iter()
@@ -1050,11 +4114,25 @@ impl ControllerInner {
                         self.ingredients[base].name();
                         "node" => base.index(),
                     );
-                    // now drop the (orphaned) base
+                    // now drop the (orphaned) base, which is then no longer an input
+                    let e = self.ingredients.find_edge(self.source, base).unwrap();
+                    self.ingredients.remove_edge(e);
                     self.remove_nodes(vec![base].as_slice()).unwrap();
                 }
 
                 self.recipe = new;
+                self.install_write_policies()?;
+                self.install_constraints()?;
+                self.install_read_only()?;
+                self.install_mirrors()?;
+                self.install_event_times()?;
+                self.install_read_replicas()?;
+                self.install_assertions()?;
+                self.install_evictions()?;
+                self.install_priorities()?;
+                self.install_ttls()?;
+                self.install_cascades()?;
+                self.start_warming(&ra.new_nodes);
             }
             Err(ref e) => {
                 crit!(self.log, "failed to apply recipe: {}", e);
@@ -1072,63 +4150,357 @@ impl ControllerInner {
         authority: &Arc<A>,
         add_txt: String,
     ) -> Result<ActivationResult, String> {
+        let (add_txt, name_changes) = self.split_name_changes(&add_txt);
+        let view_names = self.plan_name_changes(&add_txt, &name_changes, false)?;
+        let (add_txt, pass_through) = self.split_pass_through(&add_txt);
+        self.reject_unsupported(&add_txt)?;
+        self.plan_migration(authority, &add_txt, false)?;
+
         // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
         let new = mem::replace(&mut self.recipe, Recipe::blank(None));
         match new.extend(&add_txt) {
-            Ok(new) => {
+            Ok(mut new) => {
+                // tables and views that are already there keep their layout when the recipe is
+                // activated, so those whose shard key changes are re-sharded separately
+                let resharded = self
+                    .resharded_tables(&new)
+                    .and_then(|tables| Ok((tables, self.resharded_views(&new)?)));
+                let (tables, views) = match resharded {
+                    Ok(resharded) => resharded,
+                    Err(e) => {
+                        self.recipe = new.revert();
+                        self.abandon_migration(authority);
+                        return Err(e);
+                    }
+                };
+                if !tables.is_empty() {
+                    info!(self.log, "re-sharding tables"; "tables" => tables.len());
+                    if let Err(e) = self.export_rows(&tables, new.version()) {
+                        self.recipe = new.revert();
+                        self.abandon_migration(authority);
+                        return Err(e);
+                    }
+                }
+
+                new.set_table_statistics(self.table_statistics());
                 let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                let activated = activation_result.is_ok();
+                if activated && !views.is_empty() {
+                    info!(self.log, "re-sharding views"; "views" => ?views);
+                    self.recover_queries(views);
+                }
+                let resharded = activated && !tables.is_empty();
+                match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| {
+                    match state {
                         None => unreachable!(),
                         Some(ref state) if state.epoch > self.epoch => Err(()),
                         Some(mut state) => {
-                            state.recipe_version = self.recipe.version();
-                            state.recipes.push(add_txt.clone());
+                            if activated {
+                                state.recipe_version = self.recipe.version();
+                                state.recipes.push(add_txt.clone());
+                                state.pass_through.extend(pass_through.clone());
+                                state.view_names = view_names.clone();
+                                RecipeSnapshot::record(&mut state);
+                            }
+                            if resharded {
+                                // the snapshots of the views of a re-sharded table describe
+                                // bases that are no more, so views are rebuilt from the tables
+                                state.checkpoint = None;
+                            }
+                            state.pending_migration = None;
                             Ok(state)
                         }
-                    })
-                    .is_err()
-                {
-                    return Err("Failed to persist recipe extension".to_owned());
+                    }
+                }) {
+                    Ok(Ok(state)) => self.recipe_history = state.recipe_history,
+                    Ok(Err(())) => {}
+                    Err(_) => return Err("Failed to persist recipe extension".to_owned()),
+                }
+                if !tables.is_empty() && !activated {
+                    // the rows written out for the new layouts are never loaded
+                    self.resume_writes();
+                }
+                if activated {
+                    self.record_event(
+                        authority,
+                        ControllerEventKind::Migration {
+                            recipe_version: self.recipe.version(),
+                            replace: false,
+                        },
+                    );
+                }
+                if resharded {
+                    // only bases that are added anew take on the layout their recipe declares
+                    self.checkpoint = None;
+                    self.rebuild_graph(authority);
                 }
 
-                activation_result
+                activation_result.map(|mut r| {
+                    r.pass_through = pass_through.keys().cloned().collect();
+                    self.pass_through.extend(pass_through);
+                    self.view_names = view_names;
+                    r
+                })
             }
             Err((old, e)) => {
                 // need to restore the old recipe
                 crit!(self.log, "failed to extend recipe: {:?}", e);
                 self.recipe = old;
+                self.abandon_migration(authority);
                 Err("failed to extend recipe".to_owned())
             }
         }
     }
 
+    /// Remove the statements from `recipe` that only alias or rename views.
+    fn split_name_changes(&self, recipe: &str) -> (String, Vec<NameChange>) {
+        view_names::split_name_changes(recipe, |name| {
+            matches!(self.recipe.schema_for(name), Some(Schema::Table(_)))
+        })
+    }
+
+    /// Work out the view names after `changes`, which accompany `recipe`, are applied.
+    ///
+    /// If the recipe replaces the running one, all earlier aliases and renames are dropped, and
+    /// only the views it defines can be aliased or renamed.
+    fn plan_name_changes(
+        &self,
+        recipe: &str,
+        changes: &[NameChange],
+        replace: bool,
+    ) -> Result<ViewNames, String> {
+        let mut names = if replace {
+            ViewNames::default()
+        } else {
+            self.view_names.clone()
+        };
+        let defined: HashSet<String> = Recipe::parse_statements(recipe)
+            .into_iter()
+            .filter_map(|(_, parsed)| match parsed {
+                Ok((_, SqlQuery::CreateView(cvq))) => Some(cvq.name),
+                Ok((name, SqlQuery::Select(_))) | Ok((name, SqlQuery::CompoundSelect(_))) => name,
+                _ => None,
+            })
+            .collect();
+        let is_view = |name: &str| {
+            defined.contains(name)
+                || !replace && matches!(self.recipe.schema_for(name), Some(Schema::View(_)))
+        };
+        for change in changes {
+            names.apply(change, &is_view)?;
+        }
+        Ok(names)
+    }
+
+    /// Remove the queries that Noria cannot support from `recipe`, if they should be passed
+    /// through to the upstream database instead.
+    fn split_pass_through(&self, recipe: &str) -> (String, BTreeMap<String, String>) {
+        if !self.pass_through_unsupported {
+            return (recipe.to_owned(), BTreeMap::new());
+        }
+
+        let (recipe, pass_through) = pass_through::split_unsupported(
+            recipe,
+            self.sharding.is_some(),
+            self.materializations.partial_enabled(),
+            |name| self.recipe.schema_for(name).is_some(),
+        );
+        for name in pass_through.keys() {
+            warn!(self.log, "registering unsupported query as pass-through"; "query" => name);
+        }
+        (recipe, pass_through)
+    }
+
+    /// Report problems with the statements in `recipe` without changing the running recipe.
+    fn lint_recipe(&self, recipe: &str) -> Vec<StatementLint> {
+        lint::lint_recipe(
+            recipe,
+            self.sharding.is_some(),
+            self.materializations.partial_enabled(),
+            |name| self.recipe.schema_for(name).is_some(),
+        )
+    }
+
+    /// Find the statements in `recipe` that use SQL constructs Noria does not support.
+    fn check_recipe(&self, recipe: &str) -> Vec<UnsupportedStatement> {
+        capabilities::check_recipe(recipe)
+    }
+
+    /// Refuse a recipe change if any of its statements use SQL constructs Noria does not
+    /// support, listing each construct and where it appears.
+    fn reject_unsupported(&self, recipe: &str) -> Result<(), String> {
+        let unsupported = self.check_recipe(recipe);
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+        for s in &unsupported {
+            warn!(self.log, "rejecting unsupported statement"; "statement" => &s.statement);
+        }
+        Err(unsupported
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// What the state of each base table says about its contents, for planning joins.
+    ///
+    /// The counts of the shards of a sharded table are added up, which overestimates the distinct
+    /// values of columns other than the shard key.
+    fn table_statistics(&mut self) -> HashMap<String, TableStatistics> {
+        let ingredients = &self.ingredients;
+        let domains: HashSet<_> = ingredients
+            .node_indices()
+            .map(|ni| &ingredients[ni])
+            .filter(|n| n.is_base() && !n.is_dropped() && n.has_domain())
+            .map(|n| n.domain())
+            .collect();
+
+        let mut tables = HashMap::new();
+        for di in domains {
+            let d = self.domains.get_mut(&di).unwrap();
+            d.send_to_healthy(Box::new(Packet::GetStatistics), &self.workers)
+                .unwrap();
+            for (_, nodes) in futures_executor::block_on(self.replies.wait_for_statistics(d)) {
+                for (ni, ns) in nodes {
+                    let n = &ingredients[ni];
+                    if !n.is_base() {
+                        continue;
+                    }
+                    let t = tables
+                        .entry(n.name().to_owned())
+                        .or_insert_with(TableStatistics::default);
+                    t.rows += ns.rows;
+                    for (columns, count) in ns.distinct_keys {
+                        let field = match columns[..] {
+                            [column] => n.fields().get(column),
+                            _ => None,
+                        };
+                        if let Some(field) = field {
+                            *t.distinct.entry(field.clone()).or_insert(0) += count;
+                        }
+                    }
+                }
+            }
+        }
+        tables
+    }
+
+    fn estimate(&mut self, query: &str) -> Result<QueryEstimate, String> {
+        // the state of a table or view is held by its node and any readers attached to it
+        let mut mem_size = HashMap::new();
+        for (_, (_, nodes)) in self.get_statistics().domains {
+            for (ni, ns) in nodes {
+                *mem_size.entry(ni).or_insert(0) += ns.mem_size;
+            }
+        }
+        let ingredients = &self.ingredients;
+        let recipe = &self.recipe;
+        let state_bytes = |name: &str| -> Option<u64> {
+            let ni = recipe.node_addr_for(name).ok()?;
+            let readers = ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .filter(|&c| ingredients[c].is_reader());
+            Some(
+                Some(ni)
+                    .into_iter()
+                    .chain(readers)
+                    .map(|n| mem_size.get(&n).cloned().unwrap_or(0))
+                    .sum(),
+            )
+        };
+
+        estimate::estimate_query(
+            query,
+            self.sharding,
+            self.materializations.partial_enabled(),
+            state_bytes,
+        )
+    }
+
+    fn explain(&mut self, query: &str) -> Result<QueryPlan, String> {
+        use mir::node::MirNodeType;
+
+        // everything sent here is explained, so saying so is optional
+        let query = query.trim();
+        let query = match query.split_whitespace().next() {
+            Some(w) if w.eq_ignore_ascii_case("explain") => query[w.len()..].trim_start(),
+            _ => query,
+        };
+        let query = self.view_names.resolve(query).unwrap_or(query);
+        let (view, mir) = self.recipe.plan(query)?;
+
+        let nodes = plan_nodes(&mir);
+        let key = match mir.leaf.borrow().inner {
+            MirNodeType::Leaf { ref keys, .. } | MirNodeType::Base { ref keys, .. } => {
+                keys.iter().map(plan_column).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let view = match view {
+            Some(view) => view,
+            None => {
+                let estimate = self.estimate(query)?;
+                return Ok(QueryPlan {
+                    view: None,
+                    nodes,
+                    key,
+                    sharded_by: None,
+                    shards: estimate.shards,
+                    partial: estimate.partial,
+                    state_bytes: estimate.state_bytes,
+                });
+            }
+        };
+
+        // the state of an installed view is held by its node and its reader
+        let leaf = self.recipe.node_addr_for(&view).ok();
+        let ni = match self.reader_for(&view).or(leaf) {
+            Some(ni) => ni,
+            None => return Err(format!("{} is not installed", view)),
+        };
+        let n = &self.ingredients[ni];
+        let shards = self.domains[&n.domain()].shards();
+        let sharded_by = match n.sharded_by() {
+            Sharding::ByColumn(col, _, _) => n.fields().get(col).cloned(),
+            _ => None,
+        };
+        let partial = match self.materializations.get_status(ni, n) {
+            MaterializationStatus::Partial { .. } => true,
+            _ => false,
+        };
+        let state_bytes = self
+            .get_statistics()
+            .domains
+            .into_iter()
+            .flat_map(|(_, (_, nodes))| nodes)
+            .filter(|&(i, _)| i == ni || Some(i) == leaf)
+            .map(|(_, ns)| ns.mem_size)
+            .sum();
+
+        Ok(QueryPlan {
+            view: Some(view),
+            nodes,
+            key,
+            sharded_by,
+            shards,
+            partial,
+            state_bytes,
+        })
+    }
+
     fn install_recipe<A: Authority + 'static>(
         &mut self,
         authority: &Arc<A>,
         r_txt: String,
     ) -> Result<ActivationResult, String> {
+        let (r_txt, name_changes) = self.split_name_changes(&r_txt);
+        let view_names = self.plan_name_changes(&r_txt, &name_changes, true)?;
+        let (r_txt, pass_through) = self.split_pass_through(&r_txt);
+        self.reject_unsupported(&r_txt)?;
         match Recipe::from_str(&r_txt, Some(self.log.clone())) {
-            Ok(r) => {
-                let old = mem::replace(&mut self.recipe, Recipe::blank(None));
-                let new = old.replace(r).unwrap();
-                let activation_result = self.apply_recipe(new);
-                if authority
-                    .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
-                        None => unreachable!(),
-                        Some(ref state) if state.epoch > self.epoch => Err(()),
-                        Some(mut state) => {
-                            state.recipe_version = self.recipe.version();
-                            state.recipes = vec![r_txt.clone()];
-                            Ok(state)
-                        }
-                    })
-                    .is_err()
-                {
-                    return Err("Failed to persist recipe installation".to_owned());
-                }
-                activation_result
-            }
+            Ok(r) => self.replace_recipe(authority, r, vec![r_txt], pass_through, view_names),
             Err(e) => {
                 crit!(self.log, "failed to parse recipe: {:?}", e);
                 Err("failed to parse recipe".to_owned())
@@ -1136,6 +4508,400 @@ impl ControllerInner {
         }
     }
 
+    /// Replace the running recipe with `r`, which is made up of the recipe texts `recipes`, along
+    /// with the pass-through queries and view names that go with it.
+    fn replace_recipe<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        r: Recipe,
+        recipes: Vec<String>,
+        pass_through: BTreeMap<String, String>,
+        view_names: ViewNames,
+    ) -> Result<ActivationResult, String> {
+        // tables keep their layout when the recipe is replaced, so one cannot be re-sharded to
+        // how the new recipe would shard it
+        let sharded = r.expressions().into_iter().find_map(|(_, q)| match *q {
+            SqlQuery::CreateTable(ref ctq)
+                if self.recipe.shard_key(&ctq.table.name).is_some()
+                    && r.shard_key(&ctq.table.name) != self.recipe.shard_key(&ctq.table.name) =>
+            {
+                Some(ctq.table.name.clone())
+            }
+            _ => None,
+        });
+        if let Some(name) = sharded {
+            return Err(format!(
+                "table {} has a shard key, so it must be dropped before the recipe is replaced",
+                name
+            ));
+        }
+        let r_txt = recipes.join("\n");
+        self.plan_migration(authority, &r_txt, true)?;
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let mut new = old.replace(r).unwrap();
+        new.set_table_statistics(self.table_statistics());
+        let activation_result = self.apply_recipe(new);
+        let activated = activation_result.is_ok();
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                if activated {
+                    state.recipe_version = self.recipe.version();
+                    state.recipes = recipes.clone();
+                    state.pass_through = pass_through.clone();
+                    state.view_names = view_names.clone();
+                    RecipeSnapshot::record(&mut state);
+                }
+                state.pending_migration = None;
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(state)) => self.recipe_history = state.recipe_history,
+            Ok(Err(())) => {}
+            Err(_) => return Err("Failed to persist recipe installation".to_owned()),
+        }
+        if activated {
+            self.record_event(
+                authority,
+                ControllerEventKind::Migration {
+                    recipe_version: self.recipe.version(),
+                    replace: true,
+                },
+            );
+        }
+        activation_result.map(|mut r| {
+            r.pass_through = pass_through.keys().cloned().collect();
+            self.pass_through = pass_through;
+            self.view_names = view_names;
+            r
+        })
+    }
+
+    /// The versions of the recipe that can be rolled back to, oldest first.
+    fn recipe_history(&self) -> Vec<RecipeVersion> {
+        self.recipe_history
+            .iter()
+            .map(|s| RecipeVersion {
+                version: s.version,
+                recipe: s.recipes.join("\n"),
+            })
+            .collect()
+    }
+
+    /// Go back to the recipe as it was at `version`, in a single migration.
+    fn rollback_recipe<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        version: usize,
+    ) -> Result<ActivationResult, String> {
+        if version == self.recipe.version() {
+            return Err(format!("recipe version {} is already installed", version));
+        }
+        let snapshot = match self.recipe_history.iter().find(|s| s.version == version) {
+            Some(snapshot) => snapshot.clone(),
+            None => return Err(format!("recipe version {} is not kept", version)),
+        };
+
+        // the version is rebuilt from its recipe texts the same way recovery rebuilds the recipe
+        let mut r = Recipe::blank(Some(self.log.clone()));
+        for r_txt in &snapshot.recipes {
+            r = r.extend(r_txt).map_err(|(_, e)| e)?;
+        }
+        info!(
+            self.log,
+            "rolling back recipe";
+            "from" => self.recipe.version(),
+            "to" => version,
+        );
+        self.replace_recipe(
+            authority,
+            r,
+            snapshot.recipes,
+            snapshot.pass_through,
+            snapshot.view_names,
+        )
+    }
+
+    /// Work out what installing `recipe`, or extending the running recipe with it, would change,
+    /// without changing anything.
+    fn dry_run_recipe(&mut self, recipe: &str, replace: bool) -> Result<RecipeDiff, String> {
+        let (recipe, name_changes) = self.split_name_changes(recipe);
+        self.plan_name_changes(&recipe, &name_changes, replace)?;
+        let (recipe, _) = self.split_pass_through(&recipe);
+        self.reject_unsupported(&recipe)?;
+        let new = if replace {
+            self.recipe
+                .clone()
+                .replace(Recipe::from_str(&recipe, None)?)?
+        } else {
+            self.recipe.clone().extend(&recipe).map_err(|(_, e)| e)?
+        };
+
+        let name = |n: Option<&String>, q: &SqlQuery| match (n, q) {
+            (Some(n), _) => n.clone(),
+            (None, SqlQuery::CreateTable(ref ctq)) => ctq.table.name.clone(),
+            (None, SqlQuery::CreateView(ref cvq)) => cvq.name.clone(),
+            (None, q) => q.to_string(),
+        };
+        let (added, removed) = new.delta();
+        let added: Vec<_> = added.into_iter().map(|(n, q)| (name(n, q), q)).collect();
+        let removed: Vec<_> = removed.into_iter().map(|(n, q)| name(n, q)).collect();
+        // altering a table replaces its definition
+        let changed: Vec<String> = added
+            .iter()
+            .map(|&(ref n, _)| n)
+            .filter(|n| removed.contains(n))
+            .cloned()
+            .collect();
+
+        let mut diff = RecipeDiff {
+            version: new.version(),
+            added: added
+                .iter()
+                .map(|&(ref n, _)| n)
+                .filter(|n| !changed.contains(n))
+                .cloned()
+                .collect(),
+            removed: removed
+                .into_iter()
+                .filter(|n| !changed.contains(n))
+                .collect(),
+            changed,
+            new_nodes: Vec::new(),
+            unplanned: Vec::new(),
+            removed_leaves: Vec::new(),
+            backfill_bytes: 0,
+        };
+        for name in &diff.removed {
+            if let Ok(ni) = self.recipe.node_addr_for(name) {
+                diff.removed_leaves.push(ni.index());
+            }
+        }
+        for (name, q) in added {
+            if let SqlQuery::CreateTable(_) = *q {
+                continue;
+            }
+            let sql = q.to_string();
+            match self.recipe.plan(&sql) {
+                Ok((_, mir)) => {
+                    diff.new_nodes
+                        .extend(plan_nodes(&mir).into_iter().filter(|n| !n.reused));
+                }
+                Err(_) => {
+                    diff.unplanned.push(name);
+                    continue;
+                }
+            }
+            match self.estimate(&sql) {
+                Ok(ref estimate) if !estimate.partial => {
+                    diff.backfill_bytes += estimate.state_bytes
+                }
+                _ => {}
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Record in the authority that we are about to apply the given recipe change.
+    ///
+    /// The record is cleared once the change has either been committed or rolled back. If it is
+    /// still present when a new controller takes over, the change was interrupted, and the new
+    /// controller resumes it (see `resume_migration`).
+    fn plan_migration<A: Authority + 'static>(
+        &self,
+        authority: &Arc<A>,
+        recipe: &str,
+        replace: bool,
+    ) -> Result<(), String> {
+        let plan = PendingMigration {
+            recipe: recipe.to_owned(),
+            replace,
+            epoch: self.epoch,
+        };
+        match authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+            None => unreachable!(),
+            Some(ref state) if state.epoch > self.epoch => Err(()),
+            Some(mut state) => {
+                state.pending_migration = Some(plan.clone());
+                Ok(state)
+            }
+        }) {
+            Ok(Ok(_)) => Ok(()),
+            _ => Err("Failed to persist migration plan".to_owned()),
+        }
+    }
+
+    /// Forget about a planned recipe change that was never applied.
+    fn abandon_migration<A: Authority + 'static>(&self, authority: &Arc<A>) {
+        let _ =
+            authority.read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.pending_migration = None;
+                    Ok(state)
+                }
+            });
+    }
+
+    /// Finish a recipe change that a previous controller was applying when it failed.
+    ///
+    /// The graph has at this point been restored from the committed recipes only, so whatever
+    /// the failed controller had installed for the change is not part of it. We therefore apply
+    /// the change again from the start; if that fails, it is rolled back like any other failed
+    /// migration, and the committed recipe stays in place.
+    fn resume_migration<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        m: PendingMigration,
+    ) {
+        info!(
+            self.log,
+            "resuming migration interrupted in epoch {:?}", m.epoch;
+            "replace" => m.replace,
+        );
+
+        let res = if m.replace {
+            self.install_recipe(authority, m.recipe)
+        } else {
+            self.extend_recipe(authority, m.recipe)
+        };
+
+        match res {
+            Ok(_) => info!(self.log, "completed interrupted migration"),
+            Err(e) => {
+                crit!(self.log, "rolled back interrupted migration: {}", e);
+                // the recipe may not even have parsed, in which case the plan is still recorded
+                self.abandon_migration(authority);
+            }
+        }
+    }
+
+    /// The controller's metrics, in the Prometheus text exposition format.
+    fn metrics(&self) -> String {
+        use dataflow::metrics::family;
+
+        let workers: Vec<_> = self
+            .workers
+            .iter()
+            .map(|(addr, w)| (format!("worker=\"{}\"", addr), w))
+            .collect();
+        let nodes = self
+            .ingredients
+            .node_indices()
+            .filter(|&ni| ni != self.source && !self.ingredients[ni].is_dropped())
+            .count();
+
+        let mut out = String::new();
+        family(
+            &mut out,
+            "noria_worker_healthy",
+            "gauge",
+            "Whether each worker is sending heartbeats.",
+            workers.iter().map(|(l, w)| (l, w.healthy as u64)),
+        );
+        family(
+            &mut out,
+            "noria_worker_pending_reads",
+            "gauge",
+            "Reads each worker has accepted but not yet answered.",
+            workers
+                .iter()
+                .map(|(l, w)| (l, w.reader_load.pending as u64)),
+        );
+        family(
+            &mut out,
+            "noria_worker_shed_reads_total",
+            "counter",
+            "Reads each worker has turned away.",
+            workers.iter().map(|(l, w)| (l, w.reader_load.shed)),
+        );
+        family(
+            &mut out,
+            "noria_domains",
+            "gauge",
+            "Domains in the data-flow graph.",
+            std::iter::once(("", self.domains.len() as u64)),
+        );
+        family(
+            &mut out,
+            "noria_nodes",
+            "gauge",
+            "Nodes in the data-flow graph.",
+            std::iter::once(("", nodes as u64)),
+        );
+        out
+    }
+
+    fn dataflow_graph(&mut self) -> DataflowGraph {
+        let mut state = HashMap::new();
+        for (_, (_, nodes)) in self.get_statistics().domains {
+            for (ni, ns) in nodes {
+                let s = state.entry(ni).or_insert((0, 0));
+                s.0 += ns.mem_size;
+                s.1 += ns.rows;
+            }
+        }
+
+        let nodes = self
+            .ingredients
+            .node_indices()
+            .filter(|&ni| ni != self.source && !self.ingredients[ni].is_dropped())
+            .map(|ni| {
+                let n = &self.ingredients[ni];
+                let operator = if n.is_internal() {
+                    n.description(true)
+                } else if n.is_base() {
+                    "base".to_owned()
+                } else if n.is_reader() {
+                    "reader".to_owned()
+                } else if n.is_ingress() {
+                    "ingress".to_owned()
+                } else if n.is_egress() {
+                    "egress".to_owned()
+                } else {
+                    "sharder".to_owned()
+                };
+                let domain = if n.has_domain() {
+                    Some(n.domain())
+                } else {
+                    None
+                };
+                let sharded_by = match n.sharded_by() {
+                    Sharding::ByColumn(col, _, _) => n.fields().get(col).cloned(),
+                    _ => None,
+                };
+                let (mem_size, rows) = state.get(&ni).cloned().unwrap_or((0, 0));
+                GraphNode {
+                    index: ni.index(),
+                    name: n.name().to_owned(),
+                    operator,
+                    domain: domain.map(|d| d.index()),
+                    shards: domain
+                        .and_then(|d| self.domains.get(&d))
+                        .map(|d| d.shards())
+                        .unwrap_or(1),
+                    sharded_by,
+                    materialized: self.materializations.get_status(ni, n),
+                    mem_size,
+                    rows,
+                }
+            })
+            .collect();
+
+        let edges = self
+            .ingredients
+            .raw_edges()
+            .iter()
+            .filter(|e| e.source() != self.source)
+            .filter(|e| !self.ingredients[e.target()].is_dropped())
+            .map(|e| (e.source().index(), e.target().index()))
+            .collect();
+
+        DataflowGraph { nodes, edges }
+    }
+
     fn graphviz(&self, detailed: bool) -> String {
         graphviz(&self.ingredients, detailed, &self.materializations)
     }
@@ -1158,16 +4924,8 @@ impl ControllerInner {
         if nchildren > 0 {
             // This query leaf node has children -- typically, these are readers, but they can also
             // include egress nodes or other, dependent queries. We need to find the actual reader,
-            // and remove that.
-            if nchildren != 1 {
-                crit!(
-                    self.log,
-                    "cannot remove node {}, as it still has multiple children",
-                    leaf.index()
-                );
-                unreachable!();
-            }
-
+            // and remove that. Any other queries that reuse the leaf keep it, since it then still
+            // has children once its reader is gone.
             let mut readers = Vec::new();
             let mut bfs = Bfs::new(&self.ingredients, leaf);
             while let Some(child) = bfs.next(&self.ingredients) {
@@ -1177,9 +4935,20 @@ impl ControllerInner {
                 }
             }
 
-            // nodes can have only one reader attached
-            assert_eq!(readers.len(), 1);
+            // nodes can have only one reader attached, along with any replicas of it, which go
+            // along with it
+            assert!(!readers.is_empty());
             let reader = readers[0];
+            for &replica in &readers[1..] {
+                let mut parents = self
+                    .ingredients
+                    .neighbors_directed(replica, petgraph::EdgeDirection::Incoming)
+                    .detach();
+                while let Some(edge) = parents.next_edge(&self.ingredients) {
+                    self.ingredients.remove_edge(edge);
+                }
+                removals.push(replica);
+            }
             debug!(
                 self.log,
                 "Removing query leaf \"{}\"", self.ingredients[leaf].name();
@@ -1228,6 +4997,10 @@ impl ControllerInner {
     }
 
     fn remove_nodes(&mut self, removals: &[NodeIndex]) -> Result<(), String> {
+        // nodes must be ready before they can be removed
+        let removed: HashSet<_> = removals.iter().cloned().collect();
+        self.finish_backfills(|graph, b| b.conflicts(graph, &removed, &HashSet::new()));
+
         // Remove node from controller local state
         let mut domain_removals: HashMap<DomainIndex, Vec<LocalNodeIndex>> = HashMap::default();
         for ni in removals {
@@ -1276,9 +5049,14 @@ impl ControllerInner {
 
     fn get_failed_nodes(&self, lost_worker: &WorkerIdentifier) -> Vec<NodeIndex> {
         // Find nodes directly impacted by worker failure.
-        let mut nodes: Vec<NodeIndex> = self.nodes_on_worker(Some(lost_worker));
+        let nodes: Vec<NodeIndex> = self.nodes_on_worker(Some(lost_worker));
 
         // Add any other downstream nodes.
+        self.with_descendants(nodes)
+    }
+
+    /// `nodes`, along with all nodes downstream of them.
+    fn with_descendants(&self, mut nodes: Vec<NodeIndex>) -> Vec<NodeIndex> {
         let mut failed_nodes = Vec::new();
         while let Some(node) = nodes.pop() {
             failed_nodes.push(node);
@@ -1300,30 +5078,31 @@ impl ControllerInner {
         // domain. We do this to avoid keeping separate state that may get out of sync, but it
         // could become a performance bottleneck in the future (e.g., when recovering large
         // graphs).
-        let domain_nodes = |i: DomainIndex| -> Vec<NodeIndex> {
-            self.ingredients
-                .node_indices()
-                .filter(|&ni| ni != self.source)
-                .filter(|&ni| !self.ingredients[ni].is_dropped())
-                .filter(|&ni| self.ingredients[ni].domain() == i)
-                .collect()
-        };
-
         if worker.is_some() {
             self.domains
                 .values()
                 .filter(|dh| dh.assigned_to_worker(worker.unwrap()))
                 .fold(Vec::new(), |mut acc, dh| {
-                    acc.extend(domain_nodes(dh.index()));
+                    acc.extend(self.nodes_in_domain(dh.index()));
                     acc
                 })
         } else {
             self.domains.values().fold(Vec::new(), |mut acc, dh| {
-                acc.extend(domain_nodes(dh.index()));
+                acc.extend(self.nodes_in_domain(dh.index()));
                 acc
             })
         }
     }
+
+    /// List the data-flow nodes assigned to domain `i`.
+    fn nodes_in_domain(&self, i: DomainIndex) -> Vec<NodeIndex> {
+        self.ingredients
+            .node_indices()
+            .filter(|&ni| ni != self.source)
+            .filter(|&ni| !self.ingredients[ni].is_dropped())
+            .filter(|&ni| self.ingredients[ni].domain() == i)
+            .collect()
+    }
 }
 
 impl Drop for ControllerInner {
@@ -0,0 +1,369 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use futures_util::future::{AbortHandle, Abortable, Aborted};
+use futures_util::{FutureExt, StreamExt};
+use nom_sql::SqlType;
+use noria::{DataType, KafkaFormat, KafkaSource, Modification, Table, TableOperation};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tokio::task::JoinHandle;
+
+/// The connectors of the Kafka sources that are being consumed, by source name.
+///
+/// Connectors are stopped when this is dropped, such as when the controller loses leadership.
+#[derive(Default)]
+pub(super) struct KafkaConnectors {
+    running: HashMap<String, (AbortHandle, JoinHandle<Result<Result<(), String>, Aborted>>)>,
+}
+
+impl KafkaConnectors {
+    /// Start consuming `source`, and write its messages to `table`.
+    pub(super) fn start(
+        &mut self,
+        name: &str,
+        source: KafkaSource,
+        table: Table,
+        log: slog::Logger,
+    ) {
+        self.stop(name);
+        let (abort, registration) = AbortHandle::new_pair();
+        let consume = consume(name.to_owned(), source, table, log);
+        let task = tokio::spawn(Abortable::new(consume, registration));
+        self.running.insert(name.to_owned(), (abort, task));
+    }
+
+    /// True if the source added as `name` is being consumed.
+    pub(super) fn is_running(&self, name: &str) -> bool {
+        self.running.contains_key(name)
+    }
+
+    /// Stop consuming the source added as `name`, if it is being consumed.
+    pub(super) fn stop(&mut self, name: &str) {
+        if let Some((abort, _)) = self.running.remove(name) {
+            abort.abort();
+        }
+    }
+
+    /// Forget the connectors that have stopped since the last call, and return the names of their
+    /// sources along with why they stopped.
+    pub(super) fn reap(&mut self) -> Vec<(String, String)> {
+        let mut stopped = Vec::new();
+        for (name, (_, task)) in &mut self.running {
+            let why = match task.now_or_never() {
+                None => continue,
+                Some(Ok(Ok(Ok(())))) => String::from("the consumer closed"),
+                Some(Ok(Ok(Err(e)))) => e,
+                Some(Ok(Err(Aborted))) => continue,
+                Some(Err(e)) => format!("the connector panicked: {}", e),
+            };
+            stopped.push((name.clone(), why));
+        }
+        for (name, _) in &stopped {
+            self.running.remove(name);
+        }
+        stopped
+    }
+}
+
+impl Drop for KafkaConnectors {
+    fn drop(&mut self) {
+        for (abort, _) in self.running.values() {
+            abort.abort();
+        }
+    }
+}
+
+/// Consume the messages of `source`, write them to `table`, and commit their offsets once they
+/// have been written.
+///
+/// Returns if the consumer fails, or if a write to the table fails, such as when the table's
+/// domain is recovering, so that the controller can start a new connector.
+async fn consume(
+    name: String,
+    source: KafkaSource,
+    mut table: Table,
+    log: slog::Logger,
+) -> Result<(), String> {
+    let decoder = Decoder::new(&table, &source.format)?;
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("group.id", &format!("noria-{}", name))
+        .set("bootstrap.servers", &source.brokers)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .map_err(|e| format!("failed to create Kafka consumer: {}", e))?;
+    consumer
+        .subscribe(&[&source.topic])
+        .map_err(|e| format!("failed to subscribe to {}: {}", source.topic, e))?;
+
+    let mut messages = consumer.start();
+    while let Some(message) = messages.next().await {
+        // borrowed messages cannot be held across the write
+        let message = message
+            .map_err(|e| format!("failed to consume: {}", e))?
+            .detach();
+        match decoder.operation(message.key(), message.payload()) {
+            Ok(op) => table
+                .perform_all(vec![op])
+                .await
+                .map_err(|e| format!("failed to write to {}: {}", source.table, e))?,
+            Err(e) => warn!(log, "skipping undecodable Kafka message: {}", e;
+                            "source" => &name, "offset" => message.offset()),
+        }
+        let mut offsets = TopicPartitionList::new();
+        offsets.add_partition_offset(
+            message.topic(),
+            message.partition(),
+            Offset::Offset(message.offset() + 1),
+        );
+        consumer
+            .commit(&offsets, CommitMode::Async)
+            .map_err(|e| format!("failed to commit offset: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Turns the messages of a Kafka source into operations on its table.
+struct Decoder {
+    columns: Vec<String>,
+    /// The type of each column, if the table has a schema.
+    types: Option<Vec<SqlType>>,
+    primary_key: Option<Vec<usize>>,
+    /// The schema of the messages, if they are Avro records.
+    avro: Option<avro_rs::Schema>,
+}
+
+impl Decoder {
+    fn new(table: &Table, format: &KafkaFormat) -> Result<Self, String> {
+        let avro = match *format {
+            KafkaFormat::Json => None,
+            KafkaFormat::Avro { ref schema } => Some(
+                avro_rs::Schema::parse_str(schema)
+                    .map_err(|e| format!("invalid Avro schema: {}", e))?,
+            ),
+        };
+        Ok(Decoder {
+            columns: table.columns().to_vec(),
+            types: table
+                .schema()
+                .map(|s| s.fields.iter().map(|f| f.sql_type.clone()).collect()),
+            primary_key: table.primary_key().map(|key| key.to_vec()),
+            avro,
+        })
+    }
+
+    /// The operation that a message with the given key and value stands for.
+    fn operation(
+        &self,
+        key: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<TableOperation, String> {
+        let value = match value {
+            Some(value) => value,
+            None => return self.tombstone(key),
+        };
+
+        let row = match self.avro {
+            None => match serde_json::from_slice(value) {
+                Ok(serde_json::Value::Object(mut fields)) => (0..self.columns.len())
+                    .map(|c| self.json(c, fields.remove(&self.columns[c])))
+                    .collect::<Result<Vec<_>, _>>()?,
+                Ok(_) => return Err(String::from("message is not a JSON object")),
+                Err(e) => return Err(format!("message is not JSON: {}", e)),
+            },
+            Some(ref schema) => {
+                let mut reader = value;
+                // skip the magic byte and schema id of the Confluent wire format
+                if reader.len() >= 5 && reader[0] == 0 {
+                    reader = &reader[5..];
+                }
+                let fields = match avro_rs::from_avro_datum(schema, &mut reader, None) {
+                    Ok(avro_rs::types::Value::Record(fields)) => fields,
+                    Ok(_) => return Err(String::from("message is not an Avro record")),
+                    Err(e) => return Err(format!("message is not Avro: {}", e)),
+                };
+                let mut row = vec![DataType::None; self.columns.len()];
+                for (field, value) in fields {
+                    if let Some(c) = self.columns.iter().position(|col| *col == field) {
+                        row[c] = self.coerce(c, avro_value(value)?)?;
+                    }
+                }
+                row
+            }
+        };
+
+        Ok(match self.primary_key {
+            Some(_) => TableOperation::InsertOrUpdate {
+                update: row.iter().cloned().map(Modification::Set).collect(),
+                row,
+            },
+            None => TableOperation::Insert(row),
+        })
+    }
+
+    /// The delete that a message with the given key and no value stands for.
+    fn tombstone(&self, key: Option<&[u8]>) -> Result<TableOperation, String> {
+        let key_cols = self
+            .primary_key
+            .as_ref()
+            .ok_or("tombstones can only delete from tables with a primary key")?;
+        let key = key.ok_or("tombstone has no key")?;
+        let key = match serde_json::from_slice(key) {
+            Ok(serde_json::Value::Object(mut fields)) => key_cols
+                .iter()
+                .map(|&c| self.json(c, fields.remove(&self.columns[c])))
+                .collect::<Result<_, _>>()?,
+            Ok(key) if key_cols.len() == 1 => vec![self.json(key_cols[0], Some(key))?],
+            Ok(_) => return Err(String::from("tombstone key is not an object")),
+            Err(e) => return Err(format!("tombstone key is not JSON: {}", e)),
+        };
+        Ok(TableOperation::Delete { key })
+    }
+
+    /// The value of column `c` given by the JSON value `v`.
+    fn json(&self, c: usize, v: Option<serde_json::Value>) -> Result<DataType, String> {
+        use serde_json::Value;
+        let dt = match v {
+            None | Some(Value::Null) => DataType::None,
+            Some(Value::Bool(b)) => DataType::from(i32::from(b)),
+            Some(Value::Number(n)) => match (n.as_i64(), n.as_u64()) {
+                (Some(n), _) => DataType::from(n),
+                (None, Some(n)) => DataType::from(n),
+                (None, None) => DataType::from(n.as_f64().unwrap()),
+            },
+            Some(Value::String(s)) => DataType::from(s),
+            Some(v) => DataType::from(v),
+        };
+        self.coerce(c, dt)
+    }
+
+    /// Convert text given for a date or time column to a timestamp.
+    fn coerce(&self, c: usize, v: DataType) -> Result<DataType, String> {
+        match self.types.as_ref().and_then(|types| types.get(c)) {
+            Some(SqlType::Date) | Some(SqlType::DateTime(_)) | Some(SqlType::Timestamp)
+                if v.is_string() =>
+            {
+                v.to_timestamp()
+                    .map(DataType::from)
+                    .ok_or_else(|| format!("{} is not a valid {}", v, self.columns[c]))
+            }
+            _ => Ok(v),
+        }
+    }
+}
+
+/// Convert a value decoded from Avro.
+fn avro_value(v: avro_rs::types::Value) -> Result<DataType, String> {
+    use avro_rs::types::Value;
+    Ok(match v {
+        Value::Null => DataType::None,
+        Value::Boolean(b) => DataType::from(i32::from(b)),
+        Value::Int(n) => DataType::from(n),
+        Value::Long(n) => DataType::from(n),
+        Value::Float(f) => DataType::from(f64::from(f)),
+        Value::Double(f) => DataType::from(f),
+        Value::String(s) | Value::Enum(_, s) => DataType::from(s),
+        Value::Bytes(b) | Value::Fixed(_, b) => DataType::try_from(&b[..])?,
+        Value::Union(v) => avro_value(*v)?,
+        Value::Date(days) => DataType::from(
+            NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0)
+                + chrono::Duration::days(i64::from(days)),
+        ),
+        Value::TimestampMillis(ms) => DataType::from(NaiveDateTime::from_timestamp(
+            ms.div_euclid(1_000),
+            (ms.rem_euclid(1_000) * 1_000_000) as u32,
+        )),
+        Value::TimestampMicros(us) => DataType::from(NaiveDateTime::from_timestamp(
+            us.div_euclid(1_000_000),
+            (us.rem_euclid(1_000_000) * 1_000) as u32,
+        )),
+        v => return Err(format!("unsupported Avro value {:?}", v)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoder(avro: Option<&str>) -> Decoder {
+        Decoder {
+            columns: vec!["id".to_owned(), "name".to_owned(), "at".to_owned()],
+            types: Some(vec![SqlType::Int(32), SqlType::Text, SqlType::Timestamp]),
+            primary_key: Some(vec![0]),
+            avro: avro.map(|s| avro_rs::Schema::parse_str(s).unwrap()),
+        }
+    }
+
+    #[test]
+    fn it_decodes_json() {
+        let d = decoder(None);
+        let at = DataType::from("2020-01-01 12:00:00")
+            .to_timestamp()
+            .unwrap();
+        let row = vec![1.into(), "a".into(), at.into()];
+        assert_eq!(
+            d.operation(
+                None,
+                Some(r#"{"id": 1, "name": "a", "at": "2020-01-01 12:00:00"}"#.as_bytes())
+            ),
+            Ok(TableOperation::InsertOrUpdate {
+                update: row.iter().cloned().map(Modification::Set).collect(),
+                row,
+            })
+        );
+        // missing fields are NULL
+        let row = vec![2.into(), DataType::None, DataType::None];
+        assert_eq!(
+            d.operation(None, Some(r#"{"id": 2, "other": true}"#.as_bytes())),
+            Ok(TableOperation::InsertOrUpdate {
+                update: row.iter().cloned().map(Modification::Set).collect(),
+                row,
+            })
+        );
+        assert!(d.operation(None, Some(b"[1]".as_ref())).is_err());
+        assert!(d
+            .operation(None, Some(r#"{"at": "soon"}"#.as_bytes()))
+            .is_err());
+    }
+
+    #[test]
+    fn it_decodes_tombstones() {
+        let d = decoder(None);
+        let delete = Ok(TableOperation::Delete {
+            key: vec![1.into()],
+        });
+        assert_eq!(d.operation(Some(r#"{"id": 1}"#.as_bytes()), None), delete);
+        assert_eq!(d.operation(Some(b"1".as_ref()), None), delete);
+        assert!(d.operation(None, None).is_err());
+    }
+
+    #[test]
+    fn it_decodes_avro() {
+        let schema = r#"{"type": "record", "name": "item", "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "name", "type": ["null", "string"]}
+        ]}"#;
+        let d = decoder(Some(schema));
+        let mut record = avro_rs::types::Record::new(d.avro.as_ref().unwrap()).unwrap();
+        record.put("id", 7i64);
+        record.put(
+            "name",
+            avro_rs::types::Value::Union(Box::new(avro_rs::types::Value::String("x".to_owned()))),
+        );
+        let datum = avro_rs::to_avro_datum(d.avro.as_ref().unwrap(), record).unwrap();
+
+        let row = vec![7.into(), "x".into(), DataType::None];
+        let upsert = Ok(TableOperation::InsertOrUpdate {
+            update: row.iter().cloned().map(Modification::Set).collect(),
+            row,
+        });
+        assert_eq!(d.operation(None, Some(&datum)), upsert);
+        // in the Confluent wire format
+        let mut framed = vec![0, 0, 0, 0, 1];
+        framed.extend(datum);
+        assert_eq!(d.operation(None, Some(&framed)), upsert);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::controller::capabilities;
+use crate::controller::recipe::{Alteration, Recipe, Removal};
+use nom_sql::{
+    CompoundSelectStatement, ConditionBase, ConditionExpression, FieldDefinitionExpression,
+    JoinRightSide, Literal, Operator, SelectSpecification, SelectStatement, SqlQuery,
+};
+use noria::{StateGrowth, StatementLint};
+use std::collections::{HashMap, HashSet};
+
+/// What linting a `SELECT` found out about its shape.
+#[derive(Default)]
+struct Shape {
+    parameters: usize,
+    aggregates: bool,
+    grouped: bool,
+    joins: bool,
+    limit: Option<usize>,
+    /// Whether a parameter is compared by range (e.g., `score > ?`) rather than for equality.
+    range: bool,
+}
+
+/// Lint every statement in `recipe_text`.
+///
+/// `sharded` and `partial` reflect whether this deployment shards and partially materializes at
+/// all, and `exists` tells whether a table or view of the given name already exists in the
+/// running recipe.
+pub(super) fn lint_recipe<F>(
+    recipe_text: &str,
+    sharded: bool,
+    partial: bool,
+    exists: F,
+) -> Vec<StatementLint>
+where
+    F: Fn(&str) -> bool,
+{
+    // the constructs that cannot be converted into a dataflow, by statement
+    let unsupported: HashMap<_, Vec<_>> = capabilities::check_recipe(recipe_text)
+        .into_iter()
+        .map(|s| {
+            (
+                s.statement,
+                s.features.into_iter().map(|f| f.feature).collect(),
+            )
+        })
+        .collect();
+
+    let mut defined = HashSet::new();
+    Recipe::parse_statements(recipe_text)
+        .into_iter()
+        .map(|(statement, parsed)| {
+            let mut lint = StatementLint {
+                name: None,
+                statement,
+                unsupported: Vec::new(),
+                warnings: Vec::new(),
+                full_materialization: Vec::new(),
+                unsharded: Vec::new(),
+                state_growth: StateGrowth::None,
+            };
+
+            // the recipe applies `ALTER TABLE` and `DROP` itself, and they add no state
+            if Alteration::parse(&lint.statement).is_some()
+                || Removal::parse(&lint.statement).is_some()
+            {
+                return lint;
+            }
+
+            let (name, query) = match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    lint.unsupported.push(e);
+                    return lint;
+                }
+            };
+            if let Some(features) = unsupported.get(&lint.statement) {
+                lint.unsupported.extend(features.iter().cloned());
+            }
+
+            let known = |t: &str| defined.contains(t) || exists(t);
+            let growth = match query {
+                SqlQuery::CreateTable(_) => Some(StateGrowth::PerRow),
+                SqlQuery::CreateView(ref cvq) => match *cvq.definition {
+                    SelectSpecification::Simple(ref sq) => Some(lint_view(&mut lint, sq, &known)),
+                    SelectSpecification::Compound(ref csq) => {
+                        Some(lint_compound(&mut lint, csq, &known))
+                    }
+                },
+                SqlQuery::Select(ref sq) => Some(lint_view(&mut lint, sq, &known)),
+                SqlQuery::CompoundSelect(ref csq) => Some(lint_compound(&mut lint, csq, &known)),
+                // `check_recipe` flags any other statement
+                _ => None,
+            };
+
+            if let Some(growth) = growth {
+                if !sharded {
+                    lint.unsharded
+                        .push("sharding is disabled for this deployment".to_owned());
+                }
+                if !partial && !matches!(query, SqlQuery::CreateTable(_)) {
+                    lint.full_materialization
+                        .push("partial materialization is disabled for this deployment".to_owned());
+                }
+
+                // a view that is partially materialized only holds the keys that are read
+                lint.state_growth = match query {
+                    SqlQuery::CreateTable(_) => growth,
+                    _ if lint.full_materialization.is_empty() => StateGrowth::PerKeyRead,
+                    _ => growth,
+                };
+            }
+            if !lint.unsupported.is_empty() {
+                lint.state_growth = StateGrowth::None;
+            }
+
+            // later statements may refer to this one
+            match query {
+                SqlQuery::CreateTable(ref ctq) => {
+                    defined.insert(ctq.table.name.clone());
+                }
+                SqlQuery::CreateView(ref cvq) => {
+                    defined.insert(cvq.name.clone());
+                }
+                _ => (),
+            }
+            if let Some(name) = name {
+                defined.insert(name.clone());
+                lint.name = Some(name);
+            }
+            lint
+        })
+        .collect()
+}
+
+/// Lint a `SELECT` that is added as a view, and estimate its state growth were it fully
+/// materialized.
+fn lint_view(
+    lint: &mut StatementLint,
+    sq: &SelectStatement,
+    known: &dyn Fn(&str) -> bool,
+) -> StateGrowth {
+    let shape = shape_of(lint, sq, known);
+    lint_keys(lint, &shape);
+    growth(&shape)
+}
+
+/// Lint a compound `SELECT`, and estimate its state growth were it fully materialized.
+fn lint_compound(
+    lint: &mut StatementLint,
+    csq: &CompoundSelectStatement,
+    known: &dyn Fn(&str) -> bool,
+) -> StateGrowth {
+    let shapes: Vec<_> = csq
+        .selects
+        .iter()
+        .map(|&(_, ref sq)| shape_of(lint, sq, known))
+        .collect();
+
+    // the union is keyed like its least-parameterized branch
+    let shape = Shape {
+        parameters: shapes.iter().map(|s| s.parameters).min().unwrap_or(0),
+        ..Default::default()
+    };
+    lint_keys(lint, &shape);
+
+    match csq.limit {
+        Some(ref limit) => {
+            if limit.offset != 0 {
+                lint.unsupported.push("OFFSET is not supported".to_owned());
+            }
+            StateGrowth::Bounded(limit.limit as usize)
+        }
+        None => {
+            if csq.order.is_some() {
+                lint.warnings
+                    .push("ORDER BY without LIMIT is ignored".to_owned());
+            }
+            shapes.iter().map(growth).max().unwrap_or(StateGrowth::None)
+        }
+    }
+}
+
+/// Flag how the parameters of a view affect its materialization and sharding.
+fn lint_keys(lint: &mut StatementLint, shape: &Shape) {
+    if shape.range {
+        if shape.parameters > 1 {
+            lint.unsupported
+                .push("a range parameter cannot be combined with other parameters".to_owned());
+        }
+        if shape.aggregates || shape.grouped {
+            lint.unsupported
+                .push("aggregations cannot have range parameters".to_owned());
+        }
+        if shape.limit.is_some() {
+            lint.unsupported
+                .push("LIMIT cannot be combined with range parameters".to_owned());
+        }
+        lint.full_materialization.push(
+            "view has a range parameter, so it holds all its results to answer any range"
+                .to_owned(),
+        );
+        lint.unsharded
+            .push("view has a range parameter, so it is served from a single shard".to_owned());
+        return;
+    }
+    if shape.parameters == 0 {
+        lint.full_materialization.push(
+            "view has no parameters, so it is keyed on a constant and holds all its results"
+                .to_owned(),
+        );
+        // every shard keeps the top rows of a view with a LIMIT, and reads merge them
+        let merged = shape.limit.is_some() && (shape.grouped || !shape.aggregates);
+        if !merged {
+            lint.unsharded
+                .push("view has no parameters, so it is served from a single shard".to_owned());
+        }
+    }
+    if shape.aggregates && !shape.grouped {
+        lint.unsharded
+            .push("aggregation without GROUP BY is computed on a single shard".to_owned());
+    }
+}
+
+fn growth(shape: &Shape) -> StateGrowth {
+    if let Some(k) = shape.limit {
+        StateGrowth::Bounded(k)
+    } else if shape.aggregates && !shape.grouped {
+        StateGrowth::Bounded(1)
+    } else if shape.aggregates || shape.grouped {
+        StateGrowth::PerGroup
+    } else if shape.joins {
+        StateGrowth::PerJoinedRow
+    } else {
+        StateGrowth::PerRow
+    }
+}
+
+/// Work out the shape of `sq`, and flag the tables it reads that do not exist and the clauses it
+/// cannot be served with.
+///
+/// Constructs that cannot be converted into a dataflow at all are left to
+/// `capabilities::check_recipe`.
+fn shape_of(lint: &mut StatementLint, sq: &SelectStatement, known: &dyn Fn(&str) -> bool) -> Shape {
+    let mut shape = Shape::default();
+
+    let tables: Vec<_> = sq.tables.iter().map(|t| t.name.clone()).collect();
+    for t in &tables {
+        if !known(t) {
+            lint.unsupported
+                .push(format!("no table or view named `{}`", t));
+        }
+    }
+    shape.joins = tables.len() > 1 || !sq.join.is_empty();
+
+    for jc in &sq.join {
+        match jc.right {
+            JoinRightSide::Table(ref t) => {
+                if !known(&t.name) {
+                    lint.unsupported
+                        .push(format!("no table or view named `{}`", t.name));
+                }
+            }
+            JoinRightSide::NestedSelect(ref ns, _) => {
+                shape_of(lint, ns, known);
+            }
+            JoinRightSide::Tables(_) | JoinRightSide::NestedJoin(_) => (),
+        }
+    }
+
+    if let Some(ref cond) = sq.where_clause {
+        count_parameters(lint, cond, &mut shape, known);
+    }
+
+    shape.aggregates = sq.fields.iter().any(|f| match *f {
+        FieldDefinitionExpression::Col(ref c) => c.function.is_some(),
+        _ => false,
+    });
+    if let Some(ref gb) = sq.group_by {
+        shape.grouped = !gb.columns.is_empty();
+    }
+
+    match sq.limit {
+        Some(ref limit) => {
+            if limit.offset != 0 && !orders_by_fields(sq) {
+                lint.unsupported
+                    .push("OFFSET requires the ORDER BY columns to be selected".to_owned());
+            }
+            // the rows skipped by the offset are kept too
+            shape.limit = Some((limit.limit + limit.offset) as usize);
+        }
+        None if sq.order.is_some() => lint
+            .warnings
+            .push("ORDER BY without LIMIT is ignored".to_owned()),
+        None => (),
+    }
+
+    shape
+}
+
+/// Whether every column `sq` is ordered by is among the columns it selects.
+fn orders_by_fields(sq: &SelectStatement) -> bool {
+    let order = match sq.order {
+        Some(ref order) => &order.columns,
+        None => return true,
+    };
+    order.iter().all(|(oc, _)| {
+        sq.fields.iter().any(|f| match *f {
+            FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => true,
+            FieldDefinitionExpression::Col(ref c) => {
+                c.name == oc.name || c.alias.as_ref() == Some(&oc.name)
+            }
+            FieldDefinitionExpression::Value(_) => false,
+        })
+    })
+}
+
+/// Count the parameters of a `WHERE` clause, and look into the subqueries it has.
+fn count_parameters(
+    lint: &mut StatementLint,
+    cond: &ConditionExpression,
+    shape: &mut Shape,
+    known: &dyn Fn(&str) -> bool,
+) {
+    match *cond {
+        ConditionExpression::LogicalOp(ref ct) => {
+            count_parameters(lint, &ct.left, shape, known);
+            count_parameters(lint, &ct.right, shape, known);
+        }
+        ConditionExpression::ComparisonOp(ref ct) => match *ct.right {
+            ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => {
+                shape.parameters += 1;
+                match ct.operator {
+                    Operator::Greater
+                    | Operator::GreaterOrEqual
+                    | Operator::Less
+                    | Operator::LessOrEqual => shape.range = true,
+                    _ => (),
+                }
+            }
+            ConditionExpression::Base(ConditionBase::NestedSelect(ref ns)) => {
+                shape_of(lint, ns, known);
+            }
+            ConditionExpression::NegationOp(ref inner) => {
+                if let ConditionExpression::Base(ConditionBase::NestedSelect(ref ns)) = **inner {
+                    shape_of(lint, ns, known);
+                }
+            }
+            _ => (),
+        },
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            count_parameters(lint, inner, shape, known)
+        }
+        ConditionExpression::Arithmetic(_) | ConditionExpression::Base(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(recipe: &str) -> Vec<StatementLint> {
+        lint_recipe(recipe, true, true, |_| false)
+    }
+
+    #[test]
+    fn it_accepts_parameterized_views() {
+        let lints = lint(
+            "CREATE TABLE article (id int, title varchar(255), PRIMARY KEY(id));
+             QUERY articles: SELECT id, title FROM article WHERE id = ?;",
+        );
+        assert_eq!(lints.len(), 2);
+        assert!(lints.iter().all(StatementLint::is_clean));
+        assert_eq!(lints[0].state_growth, StateGrowth::PerRow);
+        assert_eq!(lints[1].name, Some("articles".to_owned()));
+        assert_eq!(lints[1].state_growth, StateGrowth::PerKeyRead);
+    }
+
+    #[test]
+    fn it_flags_unparameterized_aggregates() {
+        let lints = lint(
+            "CREATE TABLE vote (aid int, uid int);
+             QUERY votes: SELECT COUNT(uid) AS votes FROM vote;",
+        );
+        let l = &lints[1];
+        assert!(l.unsupported.is_empty());
+        assert_eq!(l.full_materialization.len(), 1);
+        assert_eq!(l.unsharded.len(), 2);
+        assert_eq!(l.state_growth, StateGrowth::Bounded(1));
+    }
+
+    #[test]
+    fn it_shards_compound_keys() {
+        let lints = lint(
+            "CREATE TABLE vote (aid int, uid int);
+             QUERY voted: SELECT aid, uid FROM vote WHERE aid = ? AND uid = ?;",
+        );
+        assert!(lints[1].is_clean());
+        assert_eq!(lints[1].state_growth, StateGrowth::PerKeyRead);
+    }
+
+    #[test]
+    fn it_shards_global_top_k() {
+        let lints = lint(
+            "CREATE TABLE story (id int, score int);
+             QUERY top: SELECT id, score FROM story ORDER BY score DESC LIMIT 10;",
+        );
+        let l = &lints[1];
+        assert_eq!(l.full_materialization.len(), 1);
+        assert!(l.unsharded.is_empty());
+        assert_eq!(l.state_growth, StateGrowth::Bounded(10));
+    }
+
+    #[test]
+    fn it_pages_sorted_views() {
+        let lints = lint(
+            "CREATE TABLE story (id int, score int);
+             QUERY page: SELECT id, score FROM story ORDER BY score DESC LIMIT 10 OFFSET 20;
+             QUERY nope: SELECT id FROM story ORDER BY score DESC LIMIT 10 OFFSET 20;",
+        );
+        let l = &lints[1];
+        assert!(l.unsupported.is_empty());
+        assert!(l.unsharded.is_empty());
+        assert_eq!(l.state_growth, StateGrowth::Bounded(30));
+        assert_eq!(lints[2].unsupported.len(), 1);
+    }
+
+    #[test]
+    fn it_accepts_alter_table() {
+        let lints = lint(
+            "CREATE TABLE story (id int, score int);
+             ALTER TABLE story ADD COLUMN title varchar(255) DEFAULT '';",
+        );
+        assert!(lints[1].is_clean());
+        assert_eq!(lints[1].state_growth, StateGrowth::None);
+    }
+
+    #[test]
+    fn it_accepts_drop() {
+        let lints = lint(
+            "DROP VIEW top;
+             DROP TABLE IF EXISTS story;",
+        );
+        assert_eq!(lints.len(), 2);
+        assert!(lints.iter().all(StatementLint::is_clean));
+    }
+
+    #[test]
+    fn it_flags_range_parameters() {
+        let lints = lint(
+            "CREATE TABLE story (id int, score int);
+             QUERY top: SELECT id, score FROM story WHERE score > ?;
+             QUERY nope: SELECT id FROM story WHERE score > ? AND id = ?;",
+        );
+        let l = &lints[1];
+        assert!(l.unsupported.is_empty());
+        assert_eq!(l.full_materialization.len(), 1);
+        assert_eq!(l.unsharded.len(), 1);
+        assert_eq!(l.state_growth, StateGrowth::PerRow);
+        assert_eq!(lints[2].unsupported.len(), 1);
+    }
+
+    #[test]
+    fn it_flags_unsupported_constructs() {
+        let lints = lint(
+            "CREATE TABLE vote (aid int, uid int);
+             QUERY votes: SELECT aid FROM nope WHERE aid = ? ORDER BY aid;
+             INSERT INTO vote (aid, uid) VALUES (1, 2);
+             QUERY x: SELECT t.a FROM vote CROSS JOIN t ON vote.aid = t.a WHERE t.a = ?;",
+        );
+        assert_eq!(lints[1].unsupported.len(), 1);
+        // the view can still be added, but its rows come back in no particular order
+        assert_eq!(
+            lints[1].warnings,
+            vec!["ORDER BY without LIMIT is ignored".to_owned()]
+        );
+        assert_eq!(lints[1].state_growth, StateGrowth::None);
+        assert_eq!(lints[2].unsupported.len(), 1);
+        // constructs that cannot be converted are found like a recipe would find them
+        assert_eq!(
+            lints[3].unsupported,
+            vec![
+                "CROSS JOIN is not supported".to_owned(),
+                "no table or view named `t`".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn it_honors_deployment_settings() {
+        let lints = lint_recipe(
+            "QUERY articles: SELECT id FROM article WHERE id = ?;",
+            false,
+            false,
+            |t| t == "article",
+        );
+        assert!(lints[0].unsupported.is_empty());
+        assert_eq!(lints[0].full_materialization.len(), 1);
+        assert_eq!(lints[0].unsharded.len(), 1);
+        assert_eq!(lints[0].state_growth, StateGrowth::PerRow);
+    }
+}
@@ -79,7 +79,9 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                         let p = &graph[pni];
                         if p.is_source() || p.is_sharder() || p.is_shard_merger() {
                         } else if p.is_base() {
-                            if p.has_domain() {
+                            // the shards of a domain all hold the same nodes
+                            if p.has_domain() && p.sharded_by().shards() == n.sharded_by().shards()
+                            {
                                 friendly_base = Some(p);
                                 break 'search;
                             }
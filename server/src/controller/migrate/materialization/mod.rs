@@ -12,12 +12,14 @@ use crate::controller::{
 };
 use crate::controller::{Worker, WorkerIdentifier};
 use dataflow::prelude::*;
+use dataflow::ReplayConfig;
 use petgraph;
 use petgraph::graph::NodeIndex;
 use slog::Logger;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 mod plan;
 
@@ -46,6 +48,50 @@ impl Default for FrontierStrategy {
     }
 }
 
+/// The new nodes of a migration that are not yet ready.
+///
+/// New nodes are readied one at a time, in topological order. Filling the state of a new
+/// materialization can take a long time, so rather than waiting for its replay to finish, the
+/// remaining nodes are set aside until it does. Later migrations that do not conflict with them
+/// can go ahead in the meantime.
+pub(in crate::controller) struct Backfill {
+    id: usize,
+    /// How the replays of the migration are chunked and paced.
+    replay: ReplayConfig,
+    /// The node whose replay is running, and when it started.
+    replaying: Option<(NodeIndex, Instant)>,
+    /// The nodes that have yet to be set up, in order, along with the indices they need.
+    unready: VecDeque<(NodeIndex, Indices)>,
+}
+
+impl Backfill {
+    pub(in crate::controller) fn id(&self) -> usize {
+        self.id
+    }
+
+    fn is_done(&self) -> bool {
+        self.replaying.is_none() && self.unready.is_empty()
+    }
+
+    /// True if a migration that adds nodes to `domains`, and that reads from or changes `nodes`,
+    /// must wait for this backfill to finish.
+    ///
+    /// A domain can only take one full replay at a time, and nodes that are not ready cannot be
+    /// read from.
+    pub(in crate::controller) fn conflicts(
+        &self,
+        graph: &Graph,
+        nodes: &HashSet<NodeIndex>,
+        domains: &HashSet<DomainIndex>,
+    ) -> bool {
+        self.replaying
+            .iter()
+            .map(|&(ni, _)| ni)
+            .chain(self.unready.iter().map(|&(ni, _)| ni))
+            .any(|ni| nodes.contains(&ni) || domains.contains(&graph[ni].domain()))
+    }
+}
+
 pub(in crate::controller) struct Materializations {
     log: Logger,
 
@@ -54,12 +100,35 @@ pub(in crate::controller) struct Materializations {
 
     partial: HashSet<NodeIndex>,
     partial_enabled: bool,
+    /// Whether new readers are partial even if `partial_enabled` is not set, so that they can be
+    /// warmed in the background instead of being populated up front.
+    warm_readers: bool,
     frontier_strategy: FrontierStrategy,
 
+    /// How full-state replays for the current migration are chunked and paced.
+    replay: ReplayConfig,
+
     tag_generator: AtomicUsize,
+
+    /// Migrations whose new nodes are not all ready yet, oldest first.
+    backfills: Vec<Backfill>,
+    next_backfill: usize,
+
+    /// The checkpoint that new full materializations are restored from, if recovery is running.
+    restoring: Option<u64>,
 }
 
 impl Materializations {
+    /// Forget about all materializations, such as when the whole graph is built again.
+    pub(in crate::controller) fn clear(&mut self) {
+        self.have.clear();
+        self.added.clear();
+        self.partial.clear();
+        self.tag_generator = AtomicUsize::default();
+        self.backfills.clear();
+        self.next_backfill = 0;
+    }
+
     /// Create a new set of materializations.
     pub(in crate::controller) fn new(logger: &Logger) -> Self {
         Materializations {
@@ -70,9 +139,17 @@ impl Materializations {
 
             partial: HashSet::default(),
             partial_enabled: true,
+            warm_readers: false,
             frontier_strategy: FrontierStrategy::None,
 
+            replay: ReplayConfig::default(),
+
             tag_generator: AtomicUsize::default(),
+
+            backfills: Vec::new(),
+            next_backfill: 0,
+
+            restoring: None,
         }
     }
 
@@ -86,10 +163,51 @@ impl Materializations {
         self.partial_enabled = false;
     }
 
+    /// Whether new materializations may be partial.
+    pub(in crate::controller) fn partial_enabled(&self) -> bool {
+        self.partial_enabled
+    }
+
+    /// Make new readers partial where they can be, even if partial materialization is disabled.
+    pub(in crate::controller) fn enable_warm_readers(&mut self) {
+        self.warm_readers = true;
+    }
+
     /// Which nodes should be placed beyond the materialization frontier?
     pub(in crate::controller) fn set_frontier_strategy(&mut self, f: FrontierStrategy) {
         self.frontier_strategy = f;
     }
+
+    /// How should the full-state replays of the next commit be chunked and paced?
+    pub(in crate::controller) fn set_replay_config(&mut self, replay: ReplayConfig) {
+        self.replay = replay;
+    }
+
+    /// The identifier that the backfill of the next commit will get.
+    pub(in crate::controller) fn next_backfill(&self) -> usize {
+        self.next_backfill
+    }
+
+    /// True if the new nodes of the commit with the given backfill are not all ready yet.
+    pub(in crate::controller) fn is_backfilling(&self, id: usize) -> bool {
+        self.backfills.iter().any(|b| b.id == id)
+    }
+
+    /// True if the new nodes of any commit are not all ready yet.
+    pub(in crate::controller) fn any_backfilling(&self) -> bool {
+        !self.backfills.is_empty()
+    }
+
+    /// Restore new full materializations from their snapshots for the given checkpoint where
+    /// possible, rather than replaying them.
+    pub(in crate::controller) fn restore_from(&mut self, checkpoint: Option<u64>) {
+        self.restoring = checkpoint;
+    }
+
+    /// The checkpoint that new full materializations are restored from, if any.
+    pub(in crate::controller) fn restoring(&self) -> Option<u64> {
+        self.restoring
+    }
 }
 
 impl Materializations {
@@ -186,6 +304,30 @@ impl Materializations {
                         .insert(cols);
                 }
             }
+
+            // operators that look up into one parent by several keys need an index for each
+            for (pi, cols) in n.suggest_secondary_indexes() {
+                trace!(self.log, "new secondary indexing obligation";
+                       "node" => pi.index(),
+                       "columns" => ?cols);
+                lookup_obligations
+                    .entry(pi)
+                    .or_insert_with(HashSet::new)
+                    .insert(cols);
+            }
+
+            if let Some(b) = n.get_base() {
+                // base nodes can also ask for indexes besides their primary key
+                for cols in b.indexes() {
+                    trace!(self.log, "new base index";
+                           "node" => ni.index(),
+                           "columns" => ?cols);
+                    lookup_obligations
+                        .entry(ni)
+                        .or_insert_with(HashSet::new)
+                        .insert(cols.clone());
+                }
+            }
         }
 
         // map all the indices to the corresponding columns in the parent
@@ -228,6 +370,37 @@ impl Materializations {
                 .collect()
         }
 
+        // periodically refreshed nodes re-evaluate all of their parent's rows and diff the result
+        // against their own, so both must be materialized, and can't be hoisted like lookups. the
+        // same goes for operators that read all the rows of their parents, like full outer joins.
+        // pinned nodes just need their own state.
+        for &ni in new {
+            let mut read = if graph[ni].is_pinned() {
+                vec![]
+            } else if graph[ni].refresh_every().is_some() {
+                vec![graph
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .next()
+                    .unwrap()]
+            } else if graph[ni].is_internal() && graph[ni].requires_materialized_parents() {
+                graph
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .collect()
+            } else {
+                continue;
+            };
+
+            read.push(ni);
+            for &mi in &read {
+                if !self.have.contains_key(&mi) {
+                    info!(self.log, "adding index to view read in full"; "node" => mi.index());
+                    self.have.entry(mi).or_default().insert(vec![0]);
+                    replay_obligations.entry(mi).or_default().insert(vec![0]);
+                    self.added.entry(mi).or_default().insert(vec![0]);
+                }
+            }
+        }
+
         // lookup obligations are fairly rigid, in that they require a materialization, and can
         // only be pushed through query-through nodes, and never across domains. so, we deal with
         // those first.
@@ -258,12 +431,18 @@ impl Materializations {
                     "query_through had more than one ancestor"
                 );
 
+                // columns the node computes itself (like a bogokey) can only be looked up here
+                let parent_indices = match map_indices(m, parent, &indices) {
+                    Ok(parent_indices) => parent_indices,
+                    Err(_) => break,
+                };
+
                 // hoist index to parent
                 trace!(self.log, "hoisting indexing obligations";
                        "for" => mi.index(),
                        "to" => parent.index());
                 mi = parent;
-                indices = map_indices(m, mi, &indices).unwrap();
+                indices = parent_indices;
                 m = &graph[mi];
             }
 
@@ -322,7 +501,8 @@ impl Materializations {
             // be the case, we need to keep moving up the ancestor tree of `ni`, and check at each
             // stage that we can trace the key column back into each of our nearest
             // materializations.
-            let mut able = self.partial_enabled;
+            let mut able = self.partial_enabled
+                || self.warm_readers && graph[ni].is_reader() && new.contains(&ni);
             let mut add = HashMap::new();
 
             // bases can't be partial
@@ -335,6 +515,42 @@ impl Materializations {
                 able = false;
             }
 
+            if graph[ni].is_pinned() {
+                warn!(self.log, "full because pinned"; "node" => ni.index());
+                able = false;
+            }
+
+            // multi-way joins look up into their parents one after the other, and can't tell which
+            // of those lookups missed
+            if graph
+                .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .any(|c| {
+                    graph[c].is_internal() && graph[c].is_join() && graph[c].ancestors().len() > 2
+                })
+            {
+                warn!(self.log, "full because parent of multi-way join"; "node" => ni.index());
+                able = false;
+            }
+
+            // range lookups cannot tell which keys in the range are missing
+            if graph[ni]
+                .with_reader(|r| r.range().is_some())
+                .unwrap_or(false)
+            {
+                warn!(self.log, "full because range reader"; "node" => ni.index());
+                able = false;
+            }
+
+            // refreshes read the full state of the refreshed node and of its parent
+            if graph[ni].refresh_every().is_some()
+                || graph
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                    .any(|c| graph[c].refresh_every().is_some())
+            {
+                warn!(self.log, "full because refreshed"; "node" => ni.index());
+                able = false;
+            }
+
             // we are already fully materialized, so can't be made partial
             if !new.contains(&ni)
                 && self.added.get(&ni).map(|i| i.len()).unwrap_or(0)
@@ -473,10 +689,36 @@ impl Materializations {
         }
     }
 
+    /// The fully materialized nodes that hold every key of `ni` on `columns`, along with the
+    /// columns the key is in at each of them.
+    ///
+    /// Returns `None` if the key cannot be traced back to full materializations.
+    pub(in crate::controller) fn key_sources(
+        &self,
+        graph: &Graph,
+        ni: NodeIndex,
+        columns: &[usize],
+    ) -> Option<Vec<(NodeIndex, Vec<usize>)>> {
+        let mut sources = Vec::new();
+        for path in keys::provenance_of(graph, ni, columns, plan::Plan::on_join(graph)) {
+            let (source, cols) = path
+                .into_iter()
+                .skip(1)
+                .find(|&(pni, _)| self.have.contains_key(&pni) && !self.partial.contains(&pni))?;
+            sources.push((source, cols.into_iter().collect::<Option<_>>()?));
+        }
+        sources.sort();
+        sources.dedup();
+        Some(sources)
+    }
+
     /// Commit to all materialization decisions since the last time `commit` was called.
     ///
     /// This includes setting up replay paths, adding new indices to existing materializations, and
     /// populating new materializations.
+    ///
+    /// New nodes whose materializations are still being populated when this returns are readied
+    /// by later calls to `advance_backfills`. Returns the identifier of the backfill that does so.
     #[allow(clippy::cognitive_complexity)]
     pub(super) fn commit(
         &mut self,
@@ -485,7 +727,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
+    ) -> usize {
         self.extend(graph, new);
 
         // check that we don't have fully materialized nodes downstream of partially materialized
@@ -639,7 +881,7 @@ impl Materializations {
                     .expect("shard mergers must have a parent");
                 let psharding = graph[parent].sharded_by();
 
-                if let Sharding::ByColumn(col, _) = psharding {
+                if let Sharding::ByColumn(col, _, _) = psharding {
                     // we want to resolve col all the way to its nearest materialized ancestor.
                     // and then check whether any other cols of the parent alias that source column
                     let columns: Vec<_> = (0..n.fields().len()).collect();
@@ -806,7 +1048,11 @@ impl Materializations {
                       "cols" => ?index_on);
                 let log = self.log.new(o!("node" => node.index()));
                 let log = mem::replace(&mut self.log, log);
-                self.setup(node, &mut index_on, graph, domains, workers, replies);
+                if self.setup(node, &mut index_on, graph, domains, workers, replies) {
+                    futures_executor::block_on(
+                        replies.wait_for_replay(&domains[&n.domain()], n.local_addr()),
+                    );
+                }
                 self.log = log;
                 index_on.clear();
             } else {
@@ -826,40 +1072,109 @@ impl Materializations {
         }
 
         // then, we start prepping new nodes
-        for ni in make {
+        replies
+            .progress()
+            .adding(make.iter().map(|&ni| graph[ni].domain()));
+        let unready = make
+            .into_iter()
+            .map(|ni| {
+                let index_on = self
+                    .added
+                    .remove(&ni)
+                    .map(|idxs| {
+                        assert!(!idxs.is_empty());
+                        idxs
+                    })
+                    .unwrap_or_else(HashSet::new);
+                (ni, index_on)
+            })
+            .collect();
+        let mut backfill = Backfill {
+            id: self.next_backfill,
+            replay: self.replay,
+            replaying: None,
+            unready,
+        };
+        self.next_backfill += 1;
+        self.advance(&mut backfill, graph, domains, workers, replies);
+        let id = backfill.id;
+        if !backfill.is_done() {
+            self.backfills.push(backfill);
+        }
+
+        self.added.clear();
+        id
+    }
+
+    /// Ready whatever new nodes can be readied without waiting for a replay to finish.
+    pub(in crate::controller) fn advance_backfills(
+        &mut self,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) {
+        let mut backfills = mem::replace(&mut self.backfills, Vec::new());
+        for backfill in &mut backfills {
+            self.advance(backfill, graph, domains, workers, replies);
+        }
+        backfills.retain(|b| !b.is_done());
+        self.backfills = backfills;
+    }
+
+    /// Wait until none of the remaining backfills are ones that `conflicts` with what is about to
+    /// happen.
+    pub(in crate::controller) fn finish_backfills<F>(
+        &mut self,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+        conflicts: F,
+    ) where
+        F: Fn(&Backfill) -> bool,
+    {
+        loop {
+            self.advance_backfills(graph, domains, workers, replies);
+            if !self.backfills.iter().any(|b| conflicts(b)) {
+                return;
+            }
+            futures_executor::block_on(replies.wait_for_replays());
+        }
+    }
+
+    fn advance(
+        &mut self,
+        backfill: &mut Backfill,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) {
+        if let Some((ni, start)) = backfill.replaying {
             let n = &graph[ni];
-            let mut index_on = self
-                .added
-                .remove(&ni)
-                .map(|idxs| {
-                    assert!(!idxs.is_empty());
-                    idxs
-                })
-                .unwrap_or_else(HashSet::new);
+            if !replies.replayed(&domains[&n.domain()], n.local_addr()) {
+                return;
+            }
+            backfill.replaying = None;
+            self.ready(ni, HashSet::new(), graph, domains, workers, replies);
+            info!(self.log, "reconstruction completed";
+            "ms" => start.elapsed().as_millis(),
+            "node" => ni.index(),
+            );
+        }
 
-            let start = ::std::time::Instant::now();
-            self.ready_one(ni, &mut index_on, graph, domains, workers, replies);
+        let replay = mem::replace(&mut self.replay, backfill.replay);
+        while let Some((ni, mut index_on)) = backfill.unready.pop_front() {
+            let start = Instant::now();
+            replies.progress().readying();
+            if self.ready_one(ni, &mut index_on, graph, domains, workers, replies) {
+                // the node is readied once its state has been filled in
+                backfill.replaying = Some((ni, start));
+                break;
+            }
             let reconstructed = index_on.is_empty();
-
-            // communicate to the domain in charge of a particular node that it should start
-            // delivering updates to a given new node. note that we wait for the domain to
-            // acknowledge the change. this is important so that we don't ready a child in a
-            // different domain before the parent has been readied. it's also important to avoid us
-            // returning before the graph is actually fully operational.
-            trace!(self.log, "readying node"; "node" => ni.index());
-            let domain = domains.get_mut(&n.domain()).unwrap();
-            domain
-                .send_to_healthy(
-                    Box::new(Packet::Ready {
-                        node: n.local_addr(),
-                        purge: n.purge,
-                        index: index_on,
-                    }),
-                    workers,
-                )
-                .unwrap();
-            futures_executor::block_on(replies.wait_for_acks(&domain));
-            trace!(self.log, "node ready"; "node" => ni.index());
+            self.ready(ni, index_on, graph, domains, workers, replies);
 
             if reconstructed {
                 info!(self.log, "reconstruction completed";
@@ -868,12 +1183,45 @@ impl Materializations {
                 );
             }
         }
+        self.replay = replay;
+    }
 
-        self.added.clear();
+    /// Tell the domain in charge of the given new node that it should start delivering updates to
+    /// it.
+    fn ready(
+        &mut self,
+        ni: NodeIndex,
+        index_on: HashSet<Vec<usize>>,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) {
+        // note that we wait for the domain to acknowledge the change. this is important so that we
+        // don't ready a child in a different domain before the parent has been readied. it's also
+        // important to avoid us returning before the graph is actually fully operational.
+        let n = &graph[ni];
+        trace!(self.log, "readying node"; "node" => ni.index());
+        let domain = domains.get_mut(&n.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::Ready {
+                    node: n.local_addr(),
+                    purge: n.purge,
+                    index: index_on,
+                }),
+                workers,
+            )
+            .unwrap();
+        futures_executor::block_on(replies.wait_for_acks(&domain));
+        replies.progress().ready(n.domain());
+        trace!(self.log, "node ready"; "node" => ni.index());
     }
 
-    /// Perform all operations necessary to bring any materializations for the given node up, and
-    /// then mark that node as ready to receive updates.
+    /// Perform all operations necessary to bring any materializations for the given node up.
+    ///
+    /// Returns true if that involves a full replay, in which case the node must not be readied
+    /// until the replay has finished.
     fn ready_one(
         &mut self,
         ni: NodeIndex,
@@ -882,7 +1230,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
+    ) -> bool {
         let n = &graph[ni];
         let mut has_state = !index_on.is_empty();
 
@@ -900,7 +1248,7 @@ impl Materializations {
             // a new base must be empty, so we can materialize it immediately
             info!(self.log, "no need to replay empty new base"; "node" => ni.index());
             assert!(!self.partial.contains(&ni));
-            return;
+            return false;
         }
 
         // if this node doesn't need to be materialized, then we're done.
@@ -914,24 +1262,83 @@ impl Materializations {
 
         if !has_state {
             debug!(self.log, "no need to replay non-materialized view"; "node" => ni.index());
-            return;
+            return false;
+        }
+
+        if let Some(checkpoint) = self.restoring {
+            let full = !index_on.is_empty() && !self.partial.contains(&ni);
+            if full && self.restore(ni, checkpoint, index_on, graph, domains, workers, replies) {
+                index_on.clear();
+                return false;
+            }
         }
 
         // we have a parent that has data, so we need to replay and reconstruct
         info!(self.log, "beginning reconstruction of {:?}", n);
         let log = self.log.new(o!("node" => ni.index()));
         let log = mem::replace(&mut self.log, log);
-        self.setup(ni, index_on, graph, domains, workers, replies);
+        let replaying = self.setup(ni, index_on, graph, domains, workers, replies);
         self.log = log;
 
         // NOTE: the state has already been marked ready by the replay completing, but we want to
-        // wait for the domain to finish replay, which the ready executed by the outer commit()
-        // loop does.
+        // wait for the domain to finish replay, which the ready executed by advance() does.
         index_on.clear();
-        return;
+        replaying
+    }
+
+    /// Restore the state of the given new, fully materialized node from its snapshots for
+    /// `checkpoint`.
+    ///
+    /// Returns false if any shard of the node has no snapshot, in which case its state must be
+    /// replayed as usual.
+    fn restore(
+        &mut self,
+        ni: NodeIndex,
+        checkpoint: u64,
+        index_on: &HashSet<Vec<usize>>,
+        graph: &Graph,
+        domains: &mut HashMap<DomainIndex, DomainHandle>,
+        workers: &HashMap<WorkerIdentifier, Worker>,
+        replies: &mut DomainReplies,
+    ) -> bool {
+        use dataflow::payload::InitialState;
+        let n = &graph[ni];
+        let domain = domains.get_mut(&n.domain()).unwrap();
+        domain
+            .send_to_healthy(
+                Box::new(Packet::RestoreState {
+                    node: n.local_addr(),
+                    index: index_on.clone(),
+                    checkpoint,
+                }),
+                workers,
+            )
+            .unwrap();
+        if !futures_executor::block_on(replies.wait_for_restores(&domain)) {
+            info!(self.log, "no snapshot to restore"; "node" => ni.index());
+            return false;
+        }
+
+        domain
+            .send_to_healthy(
+                Box::new(Packet::PrepareState {
+                    node: n.local_addr(),
+                    state: InitialState::Restored,
+                }),
+                workers,
+            )
+            .unwrap();
+        info!(self.log, "restored state from checkpoint";
+              "node" => ni.index(),
+              "checkpoint" => checkpoint,
+        );
+        true
     }
 
     /// Reconstruct the materialized state required by the given (new) node through replay.
+    ///
+    /// Returns true if full replays were started, which report to the controller once they have
+    /// finished.
     fn setup(
         &mut self,
         ni: NodeIndex,
@@ -940,7 +1347,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
-    ) {
+    ) -> bool {
         if index_on.is_empty() {
             // we must be reconstructing a Reader.
             // figure out what key that Reader is using
@@ -963,10 +1370,11 @@ impl Materializations {
             plan.finalize()
         };
 
-        if !pending.is_empty() {
+        let replaying = !pending.is_empty();
+        if replaying {
             trace!(self.log, "all domains ready for replay");
 
-            // prepare for, start, and wait for replays
+            // prepare for and start replays
             for pending in pending {
                 // tell the first domain to start playing
                 trace!(self.log, "telling root domain to start replay";
@@ -979,20 +1387,19 @@ impl Materializations {
                         Box::new(Packet::StartReplay {
                             tag: pending.tag,
                             from: pending.source,
+                            config: self.replay,
                         }),
                         workers,
                     )
                     .unwrap();
             }
 
-            // and then wait for the last domain to receive all the records
-            let target = graph[ni].domain();
+            // the last domain lets us know once it has received all the records
             trace!(self.log,
                "waiting for done message from target";
-               "domain" => target.index(),
+               "domain" => graph[ni].domain().index(),
             );
-
-            futures_executor::block_on(replies.wait_for_acks(&domains[&target]));
         }
+        replaying
     }
 }
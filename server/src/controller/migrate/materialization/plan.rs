@@ -360,7 +360,7 @@ impl<'a> Plan<'a> {
                             let shards = src_sharding.shards().unwrap_or(1);
                             let lookup_key_to_shard = match src_sharding {
                                 Sharding::Random(..) => None,
-                                Sharding::ByColumn(c, _) => {
+                                Sharding::ByColumn(c, _, _) => {
                                     let lookup_key =
                                         nodes.iter().next().unwrap().1.as_ref().unwrap();
                                     if lookup_key.len() == 1 {
@@ -407,6 +407,10 @@ impl<'a> Plan<'a> {
                                 SourceSelection::KeyShard {
                                     key_i_to_shard: i,
                                     nshards: shards,
+                                    scheme: match src_sharding {
+                                        Sharding::ByColumn(_, _, scheme) => scheme,
+                                        _ => unreachable!(),
+                                    },
                                 }
                             } else {
                                 // replay key != sharding key
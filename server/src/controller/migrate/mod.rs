@@ -22,9 +22,10 @@
 
 use crate::controller::ControllerInner;
 use dataflow::prelude::*;
-use dataflow::{node, prelude::Packet};
+use dataflow::{node, prelude::Packet, ReplayConfig};
+use nom_sql::{Operator, OrderType};
 use std::collections::{HashMap, HashSet};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use petgraph;
 use slog;
@@ -51,6 +52,7 @@ pub struct Migration<'a> {
     pub(super) added: HashSet<NodeIndex>,
     pub(super) columns: Vec<(NodeIndex, ColumnChange)>,
     pub(super) readers: HashMap<NodeIndex, NodeIndex>,
+    pub(super) replay: ReplayConfig,
 
     pub(super) start: Instant,
     pub(super) log: slog::Logger,
@@ -153,6 +155,230 @@ impl<'a> Migration<'a> {
         }
     }
 
+    /// Re-evaluate the output of the given node from scratch roughly every `every`, and diff any
+    /// changes into the data-flow.
+    ///
+    /// This is for query-through operators (like filters and projections) whose output depends on
+    /// more than just their input, such as the current time. The node and its parent are fully
+    /// materialized, and refreshes happen on controller heartbeats, so intervals shorter than the
+    /// heartbeat interval are not honored.
+    ///
+    /// Only query-through operators that were added by this migration can be refreshed.
+    pub fn refresh_every(&mut self, ni: NodeIndex, every: Duration) -> Result<(), String> {
+        info!(self.log,
+              "marking node as periodically refreshed";
+              "node" => ni.index(),
+              "every" => ?every,
+        );
+
+        if !self.added.contains(&ni) {
+            // the node's materialization, and those of its parents, were planned without refreshes
+            return Err(format!(
+                "node {} already exists, so it cannot be refreshed",
+                ni.index()
+            ));
+        }
+        let n = &mut self.mainline.ingredients[ni];
+        if !n.is_internal() || !n.can_query_through() {
+            return Err(format!(
+                "node {} is not a query-through operator, so it cannot be refreshed",
+                ni.index()
+            ));
+        }
+        n.set_refresh_every(every);
+        Ok(())
+    }
+
+    /// Keep the full state of `ni` materialized, even if none of its children need it.
+    ///
+    /// This is for views that later queries are expected to reuse, which can then be added without
+    /// waiting for a replay from the bases. Pinned nodes are never partially materialized.
+    pub fn pin(&mut self, ni: NodeIndex) {
+        info!(self.log,
+              "pinning node materialization";
+              "node" => ni.index(),
+        );
+
+        if !self.added.contains(&ni) {
+            // the view was reused from an existing query, whose materialization we leave alone
+            warn!(self.log, "not pinning existing node"; "node" => ni.index());
+            return;
+        }
+        self.mainline.ingredients[ni].pin();
+    }
+
+    /// Shard `ni` by the columns called `columns`, rather than as the nodes around it would have
+    /// it.
+    ///
+    /// For a base node, rows are routed by the values of all the columns together, into `shards`
+    /// shards if given. For a view, the reader of `ni` is sharded by the single given column,
+    /// which must be one of the columns it is keyed by, and lookups are routed by that column's
+    /// value in the key. Either way, keys are spread across the shards with `scheme` (see
+    /// `set_shard_scheme`). The sharding of existing nodes cannot be changed this way.
+    pub fn shard_by(
+        &mut self,
+        ni: NodeIndex,
+        columns: &[String],
+        shards: Option<usize>,
+        scheme: ShardScheme,
+    ) -> Result<(), String> {
+        info!(self.log,
+              "sharding node by shard key";
+              "node" => ni.index(),
+              "columns" => ?columns,
+              "shards" => ?shards,
+              "scheme" => ?scheme,
+        );
+
+        let n = &self.mainline.ingredients[ni];
+        let name = n.name().to_owned();
+        let cols = columns
+            .iter()
+            .map(|c| {
+                n.fields()
+                    .iter()
+                    .position(|f| f == c)
+                    .ok_or_else(|| format!("{} has no column {}", name, c))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if n.is_base() {
+            if !self.added.contains(&ni) {
+                warn!(self.log, "not sharding existing base"; "node" => ni.index());
+                return Ok(());
+            }
+            if shards.is_some() && self.mainline.sharding.is_none() {
+                return Err(format!(
+                    "table {} cannot have its own number of shards when nodes are not sharded",
+                    name
+                ));
+            }
+            let base = self.mainline.ingredients[ni].get_base_mut().unwrap();
+            if let Some(pk) = base.key() {
+                if !cols.iter().all(|c| pk.contains(c)) {
+                    return Err(format!(
+                        "the shard key of table {} must be part of its primary key",
+                        name
+                    ));
+                }
+            }
+            base.set_shard_key(cols, shards);
+            return self.set_shard_scheme(ni, scheme);
+        }
+
+        let col = match (&cols[..], shards) {
+            ([col], None) => *col,
+            _ => {
+                return Err(format!(
+                    "view {} can only be sharded by a single column",
+                    name
+                ))
+            }
+        };
+        let ri = match self.readers.get(&ni) {
+            Some(&ri) if self.added.contains(&ri) => ri,
+            _ => {
+                warn!(self.log, "not sharding existing reader"; "node" => ni.index());
+                return Ok(());
+            }
+        };
+        self.mainline.ingredients[ri]
+            .with_reader_mut(
+                |r| match r.key().and_then(|k| k.iter().position(|&c| c == col)) {
+                    Some(i) => {
+                        r.set_shard_key_index(i);
+                        Ok(())
+                    }
+                    None => Err(format!(
+                        "view {} can only be sharded by one of its parameters",
+                        name
+                    )),
+                },
+            )
+            .unwrap()?;
+        self.set_shard_scheme(ri, scheme)
+    }
+
+    /// Spread the rows of `ni` across its shards with `scheme` wherever the sharding pass shards
+    /// it by one of its columns, rather than by hashing the values of that column.
+    ///
+    /// Nodes that are sharded like their inputs keep the scheme of their inputs, so the scheme
+    /// only matters for nodes whose inputs are shuffled to shard them, and for bases and readers.
+    /// Keys can only be spread by range across the shards of a base if the values the base
+    /// generates for an auto-increment column are not among them.
+    pub fn set_shard_scheme(&mut self, ni: NodeIndex, scheme: ShardScheme) -> Result<(), String> {
+        info!(self.log,
+              "setting shard scheme of node";
+              "node" => ni.index(),
+              "scheme" => ?scheme,
+        );
+
+        if !self.added.contains(&ni) {
+            warn!(self.log, "not changing shard scheme of existing node"; "node" => ni.index());
+            return Ok(());
+        }
+        let n = &mut self.mainline.ingredients[ni];
+        if let ShardScheme::Range { .. } = scheme {
+            // each shard generates values that route to itself, and only one range is unbounded
+            let generated = n.get_base().and_then(|b| {
+                let col = b.auto_increment()?;
+                Some(b.shard_key().map_or(true, |k| k.contains(&col)))
+            });
+            if generated == Some(true) {
+                return Err(format!(
+                    "table {} generates values for its shard key, so it cannot be range sharded",
+                    n.name()
+                ));
+            }
+        }
+        n.set_shard_scheme(scheme);
+        Ok(())
+    }
+
+    /// Keep wide text values in the given columns of `ni` compressed in its state, and in the
+    /// state of the reader that maintains it, if any.
+    ///
+    /// Values are compressed once they exceed `noria::COMPRESSION_THRESHOLD` bytes, and are
+    /// decompressed whenever they are read.
+    ///
+    /// Only nodes that were added by this migration can have their columns compressed.
+    pub fn compress_columns(&mut self, ni: NodeIndex, columns: &[usize]) -> Result<(), String> {
+        info!(self.log,
+              "compressing node columns";
+              "node" => ni.index(),
+              "columns" => ?columns,
+        );
+
+        if !self.added.contains(&ni) {
+            // the node's state, and its reader's, already hold the values uncompressed
+            return Err(format!(
+                "node {} already exists, so its columns cannot be compressed",
+                ni.index()
+            ));
+        }
+        let width = self.mainline.ingredients[ni].fields().len();
+        if let Some(&c) = columns.iter().find(|&&c| c >= width) {
+            return Err(format!(
+                "node {} has no column {} to compress",
+                ni.index(),
+                c
+            ));
+        }
+        self.mainline.ingredients[ni].set_compressed_columns(columns.to_vec());
+        if let Some(&ri) = self.readers.get(&ni) {
+            self.mainline.ingredients[ri].set_compressed_columns(columns.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Set how the full-state replays that populate new materializations are chunked and paced.
+    ///
+    /// By default, replays are sent as fast as possible, which can delay regular writes to the
+    /// replaying domains for as long as the replay takes.
+    pub fn set_replay_config(&mut self, replay: ReplayConfig) {
+        self.replay = replay;
+    }
+
     /// Returns the context of this migration
     pub(super) fn context(&self) -> &HashMap<String, DataType> {
         &self.context
@@ -234,7 +460,13 @@ impl<'a> Migration<'a> {
         use std::collections::hash_map::Entry;
         if let Entry::Vacant(e) = self.readers.entry(n) {
             // make a reader
-            let r = node::special::Reader::new(n);
+            let mut r = node::special::Reader::new(n);
+            if let Some(&storage) = name
+                .as_ref()
+                .and_then(|n| self.mainline.view_storage.get(n))
+            {
+                r.set_storage(storage);
+            }
             let mut r = if let Some(name) = name {
                 self.mainline.ingredients[n].named_mirror(r, name)
             } else {
@@ -243,6 +475,7 @@ impl<'a> Migration<'a> {
             if r.name().starts_with("SHALLOW_") {
                 r.purge = true;
             }
+            r.set_compressed_columns(self.mainline.ingredients[n].compressed_columns().to_vec());
             let r = self.mainline.ingredients.add_node(r);
             self.mainline.ingredients.add_edge(n, r, ());
             self.added.insert(r);
@@ -250,6 +483,25 @@ impl<'a> Migration<'a> {
         }
     }
 
+    /// Add another reader that serves the same lookups as `reader`, in a domain of its own.
+    pub(super) fn add_reader_replica(&mut self, reader: NodeIndex) -> NodeIndex {
+        let parent = self
+            .mainline
+            .ingredients
+            .neighbors_directed(reader, petgraph::EdgeDirection::Incoming)
+            .next()
+            .unwrap();
+        let n = &self.mainline.ingredients[reader];
+        let mut r = n.mirror(n.with_reader(|r| r.clone()).unwrap());
+        r.purge = n.purge;
+        r.set_compressed_columns(n.compressed_columns().to_vec());
+        r.set_shard_scheme(n.shard_scheme());
+        let r = self.mainline.ingredients.add_node(r);
+        self.mainline.ingredients.add_edge(parent, r, ());
+        self.added.insert(r);
+        r
+    }
+
     /// Set up the given node such that its output can be efficiently queried.
     ///
     /// To query into the maintained state, use `ControllerInner::get_getter`.
@@ -277,13 +529,48 @@ impl<'a> Migration<'a> {
             .unwrap();
     }
 
+    /// Set up the given node such that its output can be queried by ranges over the `key` column.
+    ///
+    /// A lookup for some value returns the records whose `key` column compares to that value as
+    /// given by `op`. The node is always fully materialized.
+    pub fn maintain_range(&mut self, name: String, n: NodeIndex, key: usize, op: Operator) {
+        self.ensure_reader_for(n, Some(name));
+
+        let ri = self.readers[&n];
+
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_range_key(key, op))
+            .unwrap();
+    }
+
+    /// Have lookups on the reader for the given node return the records for each key sorted by
+    /// `order`, skipping the first `offset` of them, and no more than `limit` of the rest.
+    ///
+    /// The node must already be maintained.
+    pub fn maintain_order(
+        &mut self,
+        n: NodeIndex,
+        order: Vec<(usize, OrderType)>,
+        offset: usize,
+        limit: usize,
+    ) {
+        let ri = self.readers[&n];
+
+        self.mainline.ingredients[ri]
+            .with_reader_mut(|r| r.set_order(order, offset, limit))
+            .unwrap();
+    }
+
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
     ///
     /// This will spin up an execution thread for each new thread domain, and hook those new
     /// domains into the larger Soup graph. The returned map contains entry points through which
     /// new updates should be sent to introduce them into the Soup.
+    ///
+    /// Returns the identifier of the backfill that readies the new nodes whose materializations
+    /// are still being populated.
     #[allow(clippy::cognitive_complexity)]
-    pub(super) fn commit(self) {
+    pub(super) fn commit(self) -> usize {
         info!(self.log, "finalizing migration"; "#nodes" => self.added.len());
 
         let log = self.log;
@@ -363,6 +650,34 @@ impl<'a> Migration<'a> {
             }
         }
         let swapped = swapped0;
+
+        // earlier migrations may still be populating their new materializations. we can go ahead
+        // alongside them, as long as we do not read from any of their nodes that are not yet
+        // ready, and do not add nodes to, or replay into, the domains those nodes are in.
+        // changing the columns of a base changes what every replay out of it produces, so that
+        // waits for all of them.
+        {
+            let mut touched = HashSet::new();
+            let mut stack: Vec<_> = new.iter().cloned().collect();
+            while let Some(ni) = stack.pop() {
+                if touched.insert(ni) {
+                    stack.extend(
+                        mainline
+                            .ingredients
+                            .neighbors_directed(ni, petgraph::EdgeDirection::Incoming),
+                    );
+                }
+            }
+            let domains: HashSet<_> = touched
+                .iter()
+                .filter(|&&ni| ni != mainline.source)
+                .filter(|&&ni| !mainline.ingredients[ni].is_dropped())
+                .map(|&ni| mainline.ingredients[ni].domain())
+                .collect();
+            let alters = !self.columns.is_empty();
+            mainline.finish_backfills(|graph, b| alters || b.conflicts(graph, &touched, &domains));
+        }
+
         let mut sorted_new = new.iter().collect::<Vec<_>>();
         sorted_new.sort();
 
@@ -573,7 +888,8 @@ impl<'a> Migration<'a> {
 
         // And now, the last piece of the puzzle -- set up materializations
         info!(log, "initializing new materializations");
-        mainline.materializations.commit(
+        mainline.materializations.set_replay_config(self.replay);
+        let backfill = mainline.materializations.commit(
             &mut mainline.ingredients,
             &new,
             &mut mainline.domains,
@@ -582,5 +898,6 @@ impl<'a> Migration<'a> {
         );
 
         warn!(log, "migration completed"; "ms" => start.elapsed().as_millis());
+        backfill
     }
 }
@@ -21,6 +21,32 @@ pub fn shard(
     // we want to shard every node by its "input" index. if the index required from a parent
     // doesn't match the current sharding key, we need to do a shuffle (i.e., a Union + Sharder).
     'nodes: for &node in topo_list {
+        if let Some(shard_key) = graph[node].get_base().and_then(|b| b.shard_key()) {
+            if let Some(pk) = graph[node].get_base().unwrap().key() {
+                assert!(
+                    shard_key.iter().all(|c| pk.contains(c)),
+                    "shard key must be part of the primary key"
+                );
+            }
+
+            // the table says what it is sharded by, usually to line up with the partitioning of
+            // an upstream system. rows are routed by all the columns of a compound shard key
+            // together, so no single column tells which shard a row is on, which to the rest of
+            // the graph is the same as random sharding.
+            let shards = graph[node]
+                .get_base()
+                .unwrap()
+                .shards()
+                .unwrap_or(sharding_factor);
+            let s = match *shard_key {
+                [col] => Sharding::ByColumn(col, shards, graph[node].shard_scheme()),
+                _ => Sharding::Random(shards),
+            };
+            info!(log, "sharding base node by its shard key"; "node" => ?node, "sharding" => ?s);
+            graph.node_weight_mut(node).unwrap().shard_by(s);
+            continue;
+        }
+
         let mut input_shardings: HashMap<_, _> = graph
             .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
             .map(|ni| (ni, graph[ni].sharded_by()))
@@ -37,18 +63,25 @@ pub fn shard(
                 continue;
             }
 
+            let range = graph[node].with_reader(|r| r.range().is_some()).unwrap();
+            let ordered = graph[node].with_reader(|r| r.order().is_some()).unwrap();
+            let i = graph[node].with_reader(|r| r.shard_key_index()).unwrap();
             let s = graph[node]
                 .with_reader(|r| r.key())
                 .unwrap()
-                .and_then(|c| {
-                    if c.len() == 1 {
-                        if graph[node].fields()[c[0]] == "bogokey" {
-                            Some(Sharding::ForcedNone)
-                        } else {
-                            Some(Sharding::ByColumn(c[0], sharding_factor))
-                        }
+                .map(|c| {
+                    if graph[node].fields()[c[0]] == "bogokey" && ordered {
+                        // each shard holds the top rows of its own part of the input, and lookups
+                        // merge those in order, so there's no need to gather the rows first
+                        input_shardings[&ni]
+                    } else if graph[node].fields()[c[0]] == "bogokey" || range {
+                        // a range may span all the shards, so range readers are not sharded
+                        Sharding::ForcedNone
                     } else {
-                        None
+                        // all the records with a given compound key agree on each of its columns,
+                        // so sharding by any one of them (the first, unless the view says
+                        // otherwise) keeps each key on a single shard
+                        Sharding::ByColumn(c[i], sharding_factor, graph[node].shard_scheme())
                     }
                 })
                 .unwrap_or(Sharding::ForcedNone);
@@ -89,7 +122,7 @@ pub fn shard(
                   "sharding" => ?s);
 
             if graph[node].is_internal() || graph[node].is_base() {
-                if let Sharding::ByColumn(c, shards) = s {
+                if let Sharding::ByColumn(c, shards, scheme) = s {
                     // remap c according to node's semantics
                     let n = &graph[node];
                     let src = (0..n.fields().len()).find(|&col| {
@@ -101,7 +134,7 @@ pub fn shard(
                     });
 
                     if let Some(src) = src {
-                        s = Sharding::ByColumn(src, shards);
+                        s = Sharding::ByColumn(src, shards, scheme);
                     } else {
                         // sharding column is not emitted by this node!
                         // at this point, sharding is effectively random.
@@ -140,6 +173,23 @@ pub fn shard(
             let want_sharding = want_sharding[0];
 
             if graph[node].fields()[want_sharding] == "bogokey" {
+                let s = input_shardings.values().next().cloned().unwrap();
+                if graph[node].is_topk() && merged_by_readers(graph, node) {
+                    // the top k rows overall are among the top k rows of some shard
+                    info!(log, "keeping sharding of top k merged by readers";
+                          "node" => ?node,
+                          "sharding" => ?s);
+                    graph.node_weight_mut(node).unwrap().shard_by(s);
+                    continue;
+                }
+                if graph[node].is_topk() && !s.is_none() {
+                    // rather than gathering all of the input, only gather the top k rows of each
+                    // shard, and pick the top k rows overall among those
+                    info!(log, "merging top k of shards"; "node" => ?node, "sharding" => ?s);
+                    shard_topk(log, new, &mut swaps, graph, node, s);
+                    continue;
+                }
+
                 info!(log, "de-sharding node that operates on bogokey"; "node" => ?node);
                 for (ni, s) in input_shardings.iter_mut() {
                     reshard(log, new, &mut swaps, graph, *ni, node, Sharding::ForcedNone);
@@ -182,7 +232,11 @@ pub fn shard(
                     graph
                         .node_weight_mut(node)
                         .unwrap()
-                        .shard_by(Sharding::ByColumn(want_sharding, sharding_factor));
+                        .shard_by(Sharding::ByColumn(
+                            want_sharding,
+                            sharding_factor,
+                            graph[node].shard_scheme(),
+                        ));
                     continue;
                 }
                 Some(want_sharding_input) => {
@@ -220,13 +274,20 @@ pub fn shard(
 
                     if ok {
                         // we can shard ourselves and our inputs by a single column!
-                        let s = Sharding::ByColumn(want_sharding, sharding_factor);
+                        let scheme = scheme_for(
+                            graph,
+                            node,
+                            &input_shardings,
+                            &want_sharding_input,
+                            sharding_factor,
+                        );
+                        let s = Sharding::ByColumn(want_sharding, sharding_factor, scheme);
                         info!(log, "sharding node doing self-lookup";
                               "node" => ?node,
                               "sharding" => ?s);
 
                         for (ni, col) in want_sharding_input {
-                            let need_sharding = Sharding::ByColumn(col, sharding_factor);
+                            let need_sharding = Sharding::ByColumn(col, sharding_factor, scheme);
                             if input_shardings[&ni] != need_sharding {
                                 // input is sharded by different key -- need shuffle
                                 reshard(log, new, &mut swaps, graph, ni, node, need_sharding);
@@ -278,9 +339,12 @@ pub fn shard(
                     // this is sufficiently common that we want to make sure we don't accidentally
                     // shuffle in those cases.
 
+                    let srcs: HashMap<_, _> = srcs.iter().cloned().collect();
+                    let scheme = scheme_for(graph, node, &input_shardings, &srcs, sharding_factor);
                     let mut all_same = true;
-                    for &(ni, src) in &srcs {
-                        if input_shardings[&ni] != Sharding::ByColumn(src, sharding_factor) {
+                    for (&ni, &src) in &srcs {
+                        if input_shardings[&ni] != Sharding::ByColumn(src, sharding_factor, scheme)
+                        {
                             all_same = false;
                             break;
                         }
@@ -288,7 +352,7 @@ pub fn shard(
 
                     if all_same {
                         // col is consistent with all input shardings!
-                        let s = Sharding::ByColumn(col, sharding_factor);
+                        let s = Sharding::ByColumn(col, sharding_factor, scheme);
                         info!(log, "continuing consistent sharding through node";
                               "node" => ?node,
                               "sharding" => ?s);
@@ -336,7 +400,10 @@ pub fn shard(
 
                     // `col` resolves to the same column we use to lookup in each ancestor
                     // so it's safe for us to shard by `col`!
-                    let s = Sharding::ByColumn(col, sharding_factor);
+                    let by_src: HashMap<_, _> = srcs.iter().cloned().collect();
+                    let scheme =
+                        scheme_for(graph, node, &input_shardings, &by_src, sharding_factor);
+                    let s = Sharding::ByColumn(col, sharding_factor, scheme);
                     info!(log, "sharding node with consistent lookup column";
                           "node" => ?node,
                           "sharding" => ?s);
@@ -344,7 +411,7 @@ pub fn shard(
                     // we have to ensure that each input is also sharded by that key
                     // specifically, some inputs may _not_ be sharded previously
                     for &(ni, src) in &srcs {
-                        let need_sharding = Sharding::ByColumn(src, sharding_factor);
+                        let need_sharding = Sharding::ByColumn(src, sharding_factor, scheme);
                         if input_shardings[&ni] != need_sharding {
                             debug!(log, "resharding input with sharding {:?} to match desired sharding {:?}",
                                input_shardings[&ni], need_sharding; "node" => ?node, "input" => ?ni);
@@ -411,8 +478,10 @@ pub fn shard(
             assert!(!graph[p].is_source());
 
             // and that its children must be sharded somehow (otherwise what is the sharder doing?)
-            let col = graph[n].with_sharder(|s| s.sharded_by()).unwrap();
-            let by = Sharding::ByColumn(col, sharding_factor);
+            let (col, scheme) = graph[n]
+                .with_sharder(|s| (s.sharded_by(), s.scheme()))
+                .unwrap();
+            let by = Sharding::ByColumn(col, sharding_factor, scheme);
 
             // we can only push sharding above newly created nodes that are not already sharded.
             if !new.contains(&p) || graph[p].sharded_by() != Sharding::None {
@@ -502,13 +571,16 @@ pub fn shard(
             let mut remove = Vec::new();
             for c in graph.neighbors_directed(p, petgraph::EdgeDirection::Outgoing) {
                 // what does c shard by?
-                let col = graph[c].with_sharder(|s| s.sharded_by());
-                if col.is_none() {
-                    // lifting n would shard a node that isn't expecting to be sharded
-                    // TODO: we *could* insert a de-shard here
-                    continue 'sharders;
-                }
-                let csharding = Sharding::ByColumn(col.unwrap(), sharding_factor);
+                let col = graph[c].with_sharder(|s| (s.sharded_by(), s.scheme()));
+                let (col, cscheme) = match col {
+                    Some(col) => col,
+                    None => {
+                        // lifting n would shard a node that isn't expecting to be sharded
+                        // TODO: we *could* insert a de-shard here
+                        continue 'sharders;
+                    }
+                };
+                let csharding = Sharding::ByColumn(col, sharding_factor, cscheme);
 
                 if csharding == by {
                     // sharding by the same key, which is now unnecessary.
@@ -562,7 +634,7 @@ pub fn shard(
 
             // then wire us (n) above the parent instead
             warn!(log, "hoisting sharder above new unsharded node"; "sharder" => ?n, "node" => ?p);
-            let new = graph[grandp].mirror(node::special::Sharder::new(src_col));
+            let new = graph[grandp].mirror(node::special::Sharder::new(src_col, scheme));
             *graph.node_weight_mut(n).unwrap() = new;
             let e = graph.find_edge(grandp, p).unwrap();
             graph.remove_edge(e).unwrap();
@@ -623,6 +695,97 @@ pub fn shard(
     (topo_list, swaps)
 }
 
+/// The scheme to shard `node` with when each of its inputs must be sharded by the column it maps
+/// to in `srcs`.
+///
+/// If the inputs are all sharded by those columns with the same scheme already, the node keeps
+/// theirs, so that none of them has to be shuffled. Otherwise they are shuffled anyway, and the
+/// node's own scheme is used.
+fn scheme_for(
+    graph: &Graph,
+    node: NodeIndex,
+    input_shardings: &HashMap<NodeIndex, Sharding>,
+    srcs: &HashMap<NodeIndex, usize>,
+    sharding_factor: usize,
+) -> ShardScheme {
+    let mut schemes = srcs.iter().map(|(ni, &src)| match input_shardings[ni] {
+        Sharding::ByColumn(c, shards, scheme) if c == src && shards == sharding_factor => {
+            Some(scheme)
+        }
+        _ => None,
+    });
+    match schemes.next() {
+        Some(Some(first)) if schemes.all(|s| s == Some(first)) => first,
+        _ => graph[node].shard_scheme(),
+    }
+}
+
+/// Whether all the rows of `node` end up in readers that merge the rows of their shards in order,
+/// only passing through projections on the way.
+fn merged_by_readers(graph: &Graph, node: NodeIndex) -> bool {
+    let mut children = graph
+        .neighbors_directed(node, petgraph::EdgeDirection::Outgoing)
+        .peekable();
+    children.peek().is_some()
+        && children.all(|ni| {
+            let n = &graph[ni];
+            if n.is_reader() {
+                n.with_reader(|r| r.order().is_some()).unwrap()
+            } else {
+                n.is_internal() && n.is_project() && merged_by_readers(graph, ni)
+            }
+        })
+}
+
+/// Insert a TopK sharded by `sharding` between the unsharded TopK `node` and its parent, and de-shard
+/// its output, so that `node` only receives the top rows of each shard of its input.
+fn shard_topk(
+    log: &Logger,
+    new: &mut HashSet<NodeIndex>,
+    swaps: &mut HashMap<(NodeIndex, NodeIndex), NodeIndex>,
+    graph: &mut Graph,
+    node: NodeIndex,
+    sharding: Sharding,
+) {
+    let src = {
+        let mut ps = graph.neighbors_directed(node, petgraph::EdgeDirection::Incoming);
+        let p = ps.next().unwrap();
+        assert_eq!(ps.count(), 0);
+        p
+    };
+
+    let sharded: NodeOperator = graph[node].with_topk(|t| t.over(src)).unwrap().into();
+    let mut sharded = graph[node].mirror(sharded);
+    sharded.shard_by(sharding);
+    let sharded = graph.add_node(sharded);
+    new.insert(sharded);
+
+    let merge: NodeOperator = ops::union::Union::new_deshard(sharded, sharding).into();
+    let mut merge = graph[node].mirror(merge);
+    merge.shard_by(Sharding::ForcedNone);
+    let merge = graph.add_node(merge);
+    new.insert(merge);
+
+    info!(log, "sharding top k";
+           "node" => ?node,
+           "sharded" => ?sharded,
+           "using" => ?merge);
+
+    let old = graph.find_edge(src, node).unwrap();
+    graph.remove_edge(old).unwrap();
+    graph.add_edge(src, sharded, ());
+    graph.add_edge(sharded, merge, ());
+    graph.add_edge(merge, node, ());
+    graph
+        .node_weight_mut(node)
+        .unwrap()
+        .shard_by(Sharding::ForcedNone);
+
+    // `node` still refers to `src`, but now reads from `merge`
+    let old = swaps.insert((node, src), merge);
+    assert_eq!(old, None, "sharding top k introduces swap collision");
+}
+
 /// Modify the graph such that the path between `src` and `dst` shuffles the input such that the
 /// records received by `dst` are sharded by sharding `to`.
 fn reshard(
@@ -653,8 +816,8 @@ fn reshard(
             n.shard_by(to);
             n
         }
-        Sharding::ByColumn(c, _) => {
-            let mut n = graph[src].mirror(node::special::Sharder::new(c));
+        Sharding::ByColumn(c, _, scheme) => {
+            let mut n = graph[src].mirror(node::special::Sharder::new(c, scheme));
             n.shard_by(graph[src].sharded_by());
             n
         }
@@ -703,7 +866,7 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
 
         let remap = |nd: &Node, pni: NodeIndex, ps: Sharding| -> Sharding {
             if nd.is_internal() || nd.is_base() {
-                if let Sharding::ByColumn(c, shards) = ps {
+                if let Sharding::ByColumn(c, shards, scheme) = ps {
                     // remap c according to node's semantics
                     let src = (0..nd.fields().len()).find(|&col| {
                         for pc in nd.parent_columns(col) {
@@ -732,7 +895,7 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
                     });
 
                     if let Some(src) = src {
-                        return Sharding::ByColumn(src, shards);
+                        return Sharding::ByColumn(src, shards, scheme);
                     } else {
                         return Sharding::Random(shards);
                     }
@@ -750,7 +913,7 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
                     let in_sharding = remap(
                         n,
                         in_ni,
-                        Sharding::ByColumn(s.sharded_by(), sharding_factor),
+                        Sharding::ByColumn(s.sharded_by(), sharding_factor, s.scheme()),
                     );
                     if in_sharding != n.sharded_by() {
                         crit!(
@@ -1,6 +1,4 @@
-use nom_sql::{
-    ArithmeticBase, ArithmeticExpression, ColumnConstraint, ColumnSpecification, Literal, OrderType,
-};
+use nom_sql::{ColumnConstraint, ColumnSpecification, Literal, Operator, OrderType};
 use std::collections::HashMap;
 
 use crate::controller::Migration;
@@ -8,12 +6,18 @@ use common::DataType;
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::join::{Join, JoinType};
 use dataflow::ops::latest::Latest;
-use dataflow::ops::project::{Project, ProjectExpression, ProjectExpressionBase};
+use dataflow::ops::project::{Project, ProjectExpression};
+use dataflow::ops::window::WindowFunction as WindowKind;
 use dataflow::{node, ops};
 use mir::node::{GroupedNodeType, MirNode, MirNodeType};
 use mir::query::{MirQuery, QueryFlowParts};
-use mir::{Column, FlowNode, MirNodeRef};
+use mir::{Column, Expression, FlowNode, MirNodeRef};
 use petgraph::graph::NodeIndex;
+use std::time::Duration;
+
+/// How often filters and projections whose output depends on the current time, such as
+/// `ts > NOW() - INTERVAL 1 HOUR`, are re-evaluated.
+const TIME_DEPENDENT_REFRESH: Duration = Duration::from_secs(1);
 
 pub(super) fn mir_query_to_flow_parts(
     mir_query: &mut MirQuery,
@@ -101,12 +105,53 @@ fn mir_node_to_flow_parts(
                         None,
                     )
                 }
+                MirNodeType::DistinctAggregation {
+                    ref on,
+                    ref group_by,
+                    ref kind,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 1);
+                    let parent = mir_node.ancestors[0].clone();
+                    make_grouped_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        on,
+                        None,
+                        group_by,
+                        GroupedNodeType::DistinctAggregation(kind.clone()),
+                        mig,
+                        table_mapping,
+                        None,
+                    )
+                }
+                MirNodeType::CustomAggregation {
+                    ref on,
+                    ref group_by,
+                    ref function,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 1);
+                    let parent = mir_node.ancestors[0].clone();
+                    make_grouped_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        on,
+                        None,
+                        group_by,
+                        GroupedNodeType::CustomAggregation(function.clone()),
+                        mig,
+                        table_mapping,
+                        None,
+                    )
+                }
                 MirNodeType::Base {
                     ref mut column_specs,
                     ref keys,
                     ref adapted_over,
+                    ref indexes,
                 } => match *adapted_over {
-                    None => make_base_node(&name, column_specs.as_mut_slice(), keys, mig),
+                    None => make_base_node(&name, column_specs.as_mut_slice(), keys, indexes, mig),
                     Some(ref bna) => adapt_base_node(
                         bna.over.clone(),
                         mig,
@@ -212,10 +257,15 @@ fn mir_node_to_flow_parts(
                     let parent = mir_node.ancestors[0].clone();
                     make_latest_node(&name, parent, mir_node.columns.as_slice(), group_by, mig)
                 }
-                MirNodeType::Leaf { ref keys, .. } => {
+                MirNodeType::Leaf {
+                    ref keys,
+                    ref range,
+                    ref order,
+                    ..
+                } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    materialize_leaf_node(&parent, name, keys, mig);
+                    materialize_leaf_node(&parent, name, keys, range.clone(), order, mig);
                     // TODO(malte): below is yucky, but required to satisfy the type system:
                     // each match arm must return a `FlowNode`, so we use the parent's one
                     // here.
@@ -245,10 +295,58 @@ fn mir_node_to_flow_parts(
                         mig,
                     )
                 }
+                MirNodeType::OuterJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        JoinType::Full,
+                        mig,
+                    )
+                }
+                MirNodeType::MultiJoin {
+                    ref on,
+                    ref project,
+                } => make_multi_join_node(
+                    &name,
+                    &mir_node.ancestors,
+                    mir_node.columns.as_slice(),
+                    on,
+                    project,
+                    mig,
+                ),
+                MirNodeType::AntiJoin {
+                    ref on_left,
+                    ref on_right,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_antijoin_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        mig,
+                    )
+                }
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
-                    ref arithmetic,
+                    ref expressions,
                 } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
@@ -257,7 +355,7 @@ fn mir_node_to_flow_parts(
                         parent,
                         mir_node.columns.as_slice(),
                         emit,
-                        arithmetic,
+                        expressions,
                         literals,
                         mig,
                         table_mapping,
@@ -311,6 +409,42 @@ fn mir_node_to_flow_parts(
                         mig,
                     )
                 }
+                MirNodeType::Window {
+                    kind,
+                    ref on,
+                    ref partition_by,
+                    ref order,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 1);
+                    let parent = mir_node.ancestors[0].clone();
+                    make_window_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        kind,
+                        on.as_ref(),
+                        partition_by,
+                        order,
+                        mig,
+                    )
+                }
+                MirNodeType::Session {
+                    ref on,
+                    gap,
+                    ref partition_by,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 1);
+                    let parent = mir_node.ancestors[0].clone();
+                    make_session_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        on,
+                        gap,
+                        partition_by,
+                        mig,
+                    )
+                }
                 MirNodeType::Rewrite {
                     ref value,
                     ref column,
@@ -404,6 +538,7 @@ fn make_base_node(
     name: &str,
     column_specs: &mut [(ColumnSpecification, Option<usize>)],
     pkey_columns: &[Column],
+    indexes: &[Vec<Column>],
     mig: &mut Migration,
 ) -> FlowNode {
     // remember the absolute base column ID for potential later removal
@@ -431,21 +566,33 @@ fn make_base_node(
         })
         .collect::<Vec<DataType>>();
 
-    let base = if !pkey_columns.is_empty() {
-        let pkey_column_ids = pkey_columns
+    let column_ids = |key_columns: &[Column]| {
+        key_columns
             .iter()
-            .map(|pkc| {
-                //assert_eq!(pkc.table.as_ref().unwrap(), name);
+            .map(|kc| {
+                //assert_eq!(kc.table.as_ref().unwrap(), name);
                 column_specs
                     .iter()
-                    .position(|&(ref cs, _)| Column::from(&cs.column) == *pkc)
+                    .position(|&(ref cs, _)| Column::from(&cs.column) == *kc)
                     .unwrap()
             })
-            .collect();
-        node::special::Base::new(default_values).with_key(pkey_column_ids)
+            .collect::<Vec<_>>()
+    };
+
+    let mut base = if !pkey_columns.is_empty() {
+        node::special::Base::new(default_values).with_key(column_ids(pkey_columns))
     } else {
         node::special::Base::new(default_values)
     };
+    for index in indexes {
+        base = base.with_index(column_ids(index));
+    }
+    if let Some(col) = column_specs
+        .iter()
+        .position(|&(ref cs, _)| cs.constraints.contains(&ColumnConstraint::AutoIncrement))
+    {
+        base = base.with_auto_increment(col);
+    }
 
     FlowNode::New(mig.add_base(name, column_names.as_slice(), base))
 }
@@ -523,6 +670,10 @@ fn make_filter_node(
         column_names.as_slice(),
         ops::filter::Filter::new(parent_na, conditions),
     );
+    if conditions.iter().any(|(_, c)| c.is_time_dependent()) {
+        mig.refresh_every(node, TIME_DEPENDENT_REFRESH)
+            .expect("nodes that were just added can be refreshed");
+    }
     FlowNode::New(node)
 }
 
@@ -574,6 +725,11 @@ fn make_grouped_node(
             column_names.as_slice(),
             agg.over(parent_na, over_col_indx, group_col_indx.as_slice()),
         ),
+        GroupedNodeType::DistinctAggregation(agg) => mig.add_ingredient(
+            String::from(name),
+            column_names.as_slice(),
+            agg.over_distinct(parent_na, over_col_indx, group_col_indx.as_slice()),
+        ),
         GroupedNodeType::Extremum(extr) => mig.add_ingredient(
             String::from(name),
             column_names.as_slice(),
@@ -598,6 +754,19 @@ fn make_grouped_node(
             let gc = GroupConcat::new(parent_na, vec![TextComponent::Column(over_col_indx)], sep);
             mig.add_ingredient(String::from(name), column_names.as_slice(), gc)
         }
+        GroupedNodeType::CustomAggregation(function) => {
+            use dataflow::ops::grouped::custom::CustomAggregator;
+            mig.add_ingredient(
+                String::from(name),
+                column_names.as_slice(),
+                CustomAggregator::over(
+                    &function,
+                    parent_na,
+                    over_col_indx,
+                    group_col_indx.as_slice(),
+                ),
+            )
+        }
     };
     FlowNode::New(na)
 }
@@ -619,6 +788,30 @@ fn make_identity_node(
     FlowNode::New(node)
 }
 
+fn make_antijoin_node(
+    name: &str,
+    left: MirNodeRef,
+    right: MirNodeRef,
+    columns: &[Column],
+    on_left: &Column,
+    on_right: &Column,
+    mig: &mut Migration,
+) -> FlowNode {
+    let column_names = column_names(columns);
+
+    let left_na = left.borrow().flow_node_addr().unwrap();
+    let right_na = right.borrow().flow_node_addr().unwrap();
+    let left_col = left.borrow().column_id_for_column(on_left, None);
+    let right_col = right.borrow().column_id_for_column(on_right, None);
+
+    let n = mig.add_ingredient(
+        String::from(name),
+        column_names.as_slice(),
+        ops::anti_join::AntiJoin::new(left_na, right_na, (left_col, right_col)),
+    );
+    FlowNode::New(n)
+}
+
 fn make_join_node(
     name: &str,
     left: MirNodeRef,
@@ -734,7 +927,73 @@ fn make_join_node(
     let j = match kind {
         JoinType::Inner => Join::new(left_na, right_na, JoinType::Inner, join_config),
         JoinType::Left => Join::new(left_na, right_na, JoinType::Left, join_config),
+        JoinType::Full => Join::new(left_na, right_na, JoinType::Full, join_config),
+    };
+    let n = mig.add_ingredient(String::from(name), column_names.as_slice(), j);
+
+    FlowNode::New(n)
+}
+
+fn make_multi_join_node(
+    name: &str,
+    parents: &[MirNodeRef],
+    columns: &[Column],
+    on: &[(usize, Column, Column)],
+    proj_cols: &[Column],
+    mig: &mut Migration,
+) -> FlowNode {
+    use dataflow::ops::multi_join::MultiJoin;
+
+    let column_names = column_names(columns);
+    let position = |parent: &MirNodeRef, c: &Column| {
+        parent
+            .borrow()
+            .columns
+            .iter()
+            .position(|pc| pc == c)
+            .unwrap_or_else(|| {
+                panic!(
+                    "missing join column {:#?} in {:#?}",
+                    c,
+                    parent.borrow().columns
+                )
+            })
     };
+
+    let on = on
+        .iter()
+        .enumerate()
+        .map(|(i, &(p, ref pc, ref c))| {
+            (p, position(&parents[p], pc), position(&parents[i + 1], c))
+        })
+        .collect();
+
+    // each output column comes from the first parent that has it; for join columns, that is the
+    // parent whose join column the others' are aliased to
+    let emit = proj_cols
+        .iter()
+        .map(|c| {
+            parents
+                .iter()
+                .enumerate()
+                .find_map(|(p, parent)| {
+                    let i = parent.borrow().columns.iter().position(|pc| pc == c)?;
+                    Some((p, i))
+                })
+                .unwrap_or_else(|| {
+                    panic!(
+                        "could not resolve output column projected from join: {:?}",
+                        c
+                    )
+                })
+        })
+        .collect();
+
+    let parents = parents
+        .iter()
+        .map(|p| p.borrow().flow_node_addr().unwrap())
+        .collect();
+    let j = MultiJoin::new(parents, on, emit);
     let n = mig.add_ingredient(String::from(name), column_names.as_slice(), j);
 
     FlowNode::New(n)
@@ -765,19 +1024,45 @@ fn make_latest_node(
     FlowNode::New(na)
 }
 
-// Converts a nom_sql::ArithmeticBase into a project::ProjectExpressionBase:
-fn generate_projection_base(parent: &MirNodeRef, base: &ArithmeticBase) -> ProjectExpressionBase {
-    match *base {
-        ArithmeticBase::Column(ref column) => {
-            let column_id = parent
-                .borrow()
-                .column_id_for_column(&Column::from(column), None);
-            ProjectExpressionBase::Column(column_id)
+// Converts a MIR expression into a project::ProjectExpression over the columns of `parent`:
+fn generate_projection(parent: &MirNodeRef, e: &Expression) -> ProjectExpression {
+    let generate = |e: &Expression| generate_projection(parent, e);
+    match *e {
+        Expression::Column(ref column) => {
+            ProjectExpression::Column(parent.borrow().column_id_for_column(column, None))
         }
-        ArithmeticBase::Scalar(ref literal) => {
-            let data: DataType = literal.into();
-            ProjectExpressionBase::Literal(data)
+        Expression::Literal(ref data) => ProjectExpression::Literal(data.clone()),
+        Expression::Op {
+            ref op,
+            ref left,
+            ref right,
+        } => ProjectExpression::Op {
+            op: op.clone(),
+            left: Box::new(generate(left)),
+            right: Box::new(generate(right)),
+        },
+        Expression::Call(f, ref args) => {
+            ProjectExpression::Call(f, args.iter().map(generate).collect())
         }
+        Expression::Compare {
+            ref op,
+            ref left,
+            ref right,
+        } => ProjectExpression::Compare {
+            op: op.clone(),
+            left: Box::new(generate(left)),
+            right: Box::new(generate(right)),
+        },
+        Expression::Case {
+            ref branches,
+            ref otherwise,
+        } => ProjectExpression::Case {
+            branches: branches
+                .iter()
+                .map(|(c, v)| (generate(c), generate(v)))
+                .collect(),
+            otherwise: otherwise.as_ref().map(|o| Box::new(generate(o))),
+        },
     }
 }
 
@@ -786,7 +1071,7 @@ fn make_project_node(
     parent: MirNodeRef,
     columns: &[Column],
     emit: &[Column],
-    arithmetic: &[(String, ArithmeticExpression)],
+    expressions: &[(String, Expression)],
     literals: &[(String, DataType)],
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
@@ -801,17 +1086,14 @@ fn make_project_node(
 
     let (_, literal_values): (Vec<_>, Vec<_>) = literals.iter().cloned().unzip();
 
-    let projected_arithmetic: Vec<ProjectExpression> = arithmetic
+    let projected_expressions: Vec<ProjectExpression> = expressions
         .iter()
-        .map(|&(_, ref e)| {
-            ProjectExpression::new(
-                e.op.clone(),
-                generate_projection_base(&parent, &e.left),
-                generate_projection_base(&parent, &e.right),
-            )
-        })
+        .map(|&(_, ref e)| generate_projection(&parent, e))
         .collect();
 
+    let time_dependent = projected_expressions
+        .iter()
+        .any(ProjectExpression::is_time_dependent);
     let n = mig.add_ingredient(
         String::from(name),
         column_names.as_slice(),
@@ -819,9 +1101,13 @@ fn make_project_node(
             parent_na,
             projected_column_ids.as_slice(),
             Some(literal_values),
-            Some(projected_arithmetic),
+            Some(projected_expressions),
         ),
     );
+    if time_dependent {
+        mig.refresh_every(n, TIME_DEPENDENT_REFRESH)
+            .expect("nodes that were just added can be refreshed");
+    }
     FlowNode::New(n)
 }
 
@@ -882,8 +1168,6 @@ fn make_topk_node(
 
     let cmp_rows = match *order {
         Some(ref o) => {
-            assert_eq!(offset, 0); // Non-zero offset not supported
-
             let columns: Vec<_> = o
                 .iter()
                 .map(|&(ref c, ref order_type)| {
@@ -909,7 +1193,88 @@ fn make_topk_node(
     let na = mig.add_ingredient(
         String::from(name),
         column_names.as_slice(),
-        ops::topk::TopK::new(parent_na, cmp_rows, group_by_indx, k),
+        // the rows skipped by the offset must be kept too
+        ops::topk::TopK::new(parent_na, cmp_rows, group_by_indx, k + offset),
+    );
+    FlowNode::New(na)
+}
+
+fn make_window_node(
+    name: &str,
+    parent: MirNodeRef,
+    columns: &[Column],
+    kind: WindowKind,
+    on: Option<&Column>,
+    partition_by: &[Column],
+    order: &[(Column, OrderType)],
+    mig: &mut Migration,
+) -> FlowNode {
+    let parent_na = parent.borrow().flow_node_addr().unwrap();
+    let column_names = column_names(columns);
+
+    assert!(
+        !partition_by.is_empty(),
+        "need bogokey for window functions without partition columns"
+    );
+
+    let over_col_indx = on.map(|c| parent.borrow().column_id_for_column(c, None));
+    let partition_by_indx = partition_by
+        .iter()
+        .map(|c| parent.borrow().column_id_for_column(c, None))
+        .collect::<Vec<_>>();
+    let order_indx = order
+        .iter()
+        .map(|&(ref c, ref order_type)| {
+            (
+                parent.borrow().column_id_for_column(c, None),
+                order_type.clone(),
+            )
+        })
+        .collect();
+
+    // make the new operator and record its metadata
+    let na = mig.add_ingredient(
+        String::from(name),
+        column_names.as_slice(),
+        ops::window::Window::new(
+            parent_na,
+            kind,
+            over_col_indx,
+            partition_by_indx,
+            order_indx,
+        ),
+    );
+    FlowNode::New(na)
+}
+
+fn make_session_node(
+    name: &str,
+    parent: MirNodeRef,
+    columns: &[Column],
+    on: &Column,
+    gap: i64,
+    partition_by: &[Column],
+    mig: &mut Migration,
+) -> FlowNode {
+    let parent_na = parent.borrow().flow_node_addr().unwrap();
+    let column_names = column_names(columns);
+
+    assert!(
+        !partition_by.is_empty(),
+        "need bogokey for sessions without partition columns"
+    );
+
+    let timestamp_col_indx = parent.borrow().column_id_for_column(on, None);
+    let partition_by_indx = partition_by
+        .iter()
+        .map(|c| parent.borrow().column_id_for_column(c, None))
+        .collect::<Vec<_>>();
+
+    // make the new operator and record its metadata
+    let na = mig.add_ingredient(
+        String::from(name),
+        column_names.as_slice(),
+        ops::session::Session::new(parent_na, timestamp_col_indx, gap, partition_by_indx),
     );
     FlowNode::New(na)
 }
@@ -918,6 +1283,8 @@ fn materialize_leaf_node(
     parent: &MirNodeRef,
     name: String,
     key_cols: &[Column],
+    range: Option<Operator>,
+    order: &Option<(Vec<(Column, OrderType)>, usize, usize)>,
     mig: &mut Migration,
 ) {
     let na = parent.borrow().flow_node_addr().unwrap();
@@ -929,7 +1296,11 @@ fn materialize_leaf_node(
 
     // TODO(malte): consider the case when the projected columns need reordering
 
-    if !key_cols.is_empty() {
+    if let Some(op) = range {
+        assert_eq!(key_cols.len(), 1);
+        let key_col = parent.borrow().column_id_for_column(&key_cols[0], None);
+        mig.maintain_range(name, na, key_col, op);
+    } else if !key_cols.is_empty() {
         let key_cols: Vec<_> = key_cols
             .iter()
             .map(|c| parent.borrow().column_id_for_column(c, None))
@@ -939,4 +1310,12 @@ fn materialize_leaf_node(
         // if no key specified, default to the first column
         mig.maintain(name, na, &[0]);
     }
+
+    if let Some((ref order, offset, limit)) = *order {
+        let order = order
+            .iter()
+            .map(|(c, o)| (parent.borrow().column_id_for_column(c, None), o.clone()))
+            .collect();
+        mig.maintain_order(na, order, offset, limit);
+    }
 }
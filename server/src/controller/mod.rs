@@ -1,22 +1,22 @@
 use crate::controller::inner::ControllerInner;
 use crate::controller::migrate::Migration;
 use crate::controller::recipe::Recipe;
+use crate::controller::triggers::TriggerState;
 use crate::coordination::CoordinationMessage;
 use crate::coordination::CoordinationPayload;
 use crate::startup::Event;
 use crate::Config;
 use async_bincode::AsyncBincodeReader;
 use dataflow::payload::ControlReplyPacket;
-use futures_util::{
-    future::FutureExt,
-    future::TryFutureExt,
-    sink::SinkExt,
-    stream::{StreamExt, TryStreamExt},
-};
+use futures_util::stream::StreamExt;
 use hyper::{self, StatusCode};
 use noria::channel::TcpSender;
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::ControllerDescriptor;
+use noria::{
+    ControllerDescriptor, ControllerEvent, ControllerEventKind, Eviction, KafkaSource, Mirror,
+    ReaderLoad, ViewPriority, ViewStorage,
+};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -24,15 +24,62 @@ use std::time;
 use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
+mod assertions;
+mod capabilities;
+mod conjunction;
+mod constraints;
 mod domain_handle;
+mod estimate;
+mod events;
+mod federation;
 mod inner;
+#[cfg(feature = "connectors")]
+mod kafka;
 mod keys;
+mod lint;
 pub(crate) mod migrate; // crate viz for tests
 mod mir_to_flow;
+mod oracle;
+mod pass_through;
+mod progress;
 pub(crate) mod recipe; // crate viz for tests
 mod schema;
 mod security;
 pub(crate) mod sql; // crate viz for tests
+mod triggers;
+mod view_names;
+mod warming;
+
+pub(crate) use self::progress::MigrationProgress;
+
+/// Stands in for the connectors of Kafka sources in servers built without the `connectors`
+/// feature, which cannot add Kafka sources.
+#[cfg(not(feature = "connectors"))]
+mod kafka {
+    use noria::{KafkaSource, Table};
+
+    #[derive(Default)]
+    pub(super) struct KafkaConnectors;
+
+    impl KafkaConnectors {
+        pub(super) fn start(&mut self, _: &str, _: KafkaSource, _: Table, _: slog::Logger) {
+            unreachable!("Kafka sources are only started with the connectors feature");
+        }
+
+        pub(super) fn is_running(&self, _: &str) -> bool {
+            false
+        }
+
+        pub(super) fn stop(&mut self, _: &str) {}
+
+        pub(super) fn reap(&mut self) -> Vec<(String, String)> {
+            Vec::new()
+        }
+    }
+}
+
+/// How many versions of the recipe are kept for rolling back to.
+const MAX_RECIPE_VERSIONS: usize = 16;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct ControllerState {
@@ -41,11 +88,126 @@ pub(crate) struct ControllerState {
 
     recipe_version: usize,
     recipes: Vec<String>,
+
+    /// The most recent versions of the recipe, oldest first, so that it can be rolled back.
+    #[serde(default)]
+    recipe_history: VecDeque<RecipeSnapshot>,
+
+    /// A recipe change that a controller started, but did not finish, applying.
+    #[serde(default)]
+    pending_migration: Option<PendingMigration>,
+
+    /// Whether all tables are in read-only mode.
+    #[serde(default)]
+    read_only: bool,
+    /// Tables that are in read-only mode regardless of `read_only`.
+    #[serde(default)]
+    read_only_tables: HashSet<String>,
+
+    /// Registered triggers.
+    ///
+    /// The firings whose actions have yet to complete are kept under their own key, since they
+    /// can pile up.
+    #[serde(default)]
+    triggers: TriggerState,
+
+    /// Tables whose writes are mirrored, and where to.
+    #[serde(default)]
+    mirrors: HashMap<String, Mirror>,
+
+    /// The event-time column of each table that has one.
+    #[serde(default)]
+    event_time_columns: HashMap<String, String>,
+
+    /// Kafka topics whose messages are written to base tables, by name.
+    #[serde(default)]
+    kafka_sources: BTreeMap<String, KafkaSource>,
+
+    /// Assertions on the rows of views.
+    #[serde(default)]
+    assertions: Vec<assertions::AssertionSpec>,
+
+    /// The most recent entries of the event log.
+    #[serde(default)]
+    events: VecDeque<ControllerEvent>,
+
+    /// Unsupported queries that the adapter executes against the upstream database, by name.
+    #[serde(default)]
+    pass_through: BTreeMap<String, String>,
+
+    /// Aliases and renames of views.
+    #[serde(default)]
+    view_names: view_names::ViewNames,
+
+    /// The latest complete checkpoint of materialized state.
+    #[serde(default)]
+    checkpoint: Option<u64>,
+
+    /// How many workers were healthy under the last leader, so that the next one waits for as
+    /// many to register before it builds the graph.
+    #[serde(default)]
+    workers: usize,
+
+    /// How many readers each replicated view has.
+    #[serde(default)]
+    read_replicas: HashMap<String, usize>,
+
+    /// How the readers of views evict keys, where set.
+    #[serde(default)]
+    evictions: HashMap<String, Eviction>,
+
+    /// How urgently the upqueries of views are handled, where set.
+    #[serde(default)]
+    priorities: HashMap<String, ViewPriority>,
+
+    /// Where the readers of views keep their rows, where set.
+    #[serde(default)]
+    view_storage: HashMap<String, ViewStorage>,
+}
+
+/// A recipe change, persisted before it is applied so that a new leader can resume it if the
+/// controller fails part-way through.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PendingMigration {
+    /// The recipe text being applied.
+    recipe: String,
+    /// Whether the recipe replaces the installed recipe, rather than extending it.
+    replace: bool,
+    /// The epoch of the controller that started the migration.
+    epoch: Epoch,
+}
+
+/// A version of the recipe, kept so that the recipe can be rolled back to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct RecipeSnapshot {
+    version: usize,
+    /// The recipe texts that make up this version, as in `ControllerState::recipes`.
+    recipes: Vec<String>,
+    pass_through: BTreeMap<String, String>,
+    view_names: view_names::ViewNames,
+}
+
+impl RecipeSnapshot {
+    /// Remember the version of the recipe that `state` describes, forgetting the oldest versions
+    /// once more than `MAX_RECIPE_VERSIONS` are kept.
+    fn record(state: &mut ControllerState) {
+        state.recipe_history.push_back(RecipeSnapshot {
+            version: state.recipe_version,
+            recipes: state.recipes.clone(),
+            pass_through: state.pass_through.clone(),
+            view_names: state.view_names.clone(),
+        });
+        while state.recipe_history.len() > MAX_RECIPE_VERSIONS {
+            state.recipe_history.pop_front();
+        }
+    }
 }
 
 struct Worker {
     healthy: bool,
     last_heartbeat: time::Instant,
+    /// How busy the worker's reader was when it last reported.
+    reader_load: ReaderLoad,
     sender: TcpSender<CoordinationMessage>,
 }
 
@@ -54,6 +216,7 @@ impl Worker {
         Worker {
             healthy: true,
             last_heartbeat: time::Instant::now(),
+            reader_load: ReaderLoad::default(),
             sender,
         }
     }
@@ -71,6 +234,7 @@ pub(super) async fn main<A: Authority + 'static>(
     log: slog::Logger,
     authority: Arc<A>,
     tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    progress: MigrationProgress,
 ) {
     let (dtx, drx) = tokio::sync::mpsc::unbounded_channel();
 
@@ -79,18 +243,24 @@ pub(super) async fn main<A: Authority + 'static>(
         valve.clone(),
         log.clone(),
         dtx,
+        tx.clone(),
         cport,
     ));
 
     // note that we do not start up the data-flow until we find a controller!
 
-    let campaign = instance_campaign(tx.clone(), authority.clone(), descriptor, config);
+    let external_addr = descriptor.external_addr;
+    // the campaign follows leadership for as long as the instance runs, and takes over whenever
+    // there is no leader
+    let _campaign = instance_campaign(tx.clone(), authority.clone(), descriptor, config);
 
     // state that this instance will take if it becomes the controller
-    let mut campaign = Some(campaign);
     let mut drx = Some(drx);
 
     let mut controller: Option<ControllerInner> = None;
+    // replies to requests whose migrations are still populating materializations in the
+    // background, along with the backfills they are waiting for
+    let mut held = Vec::new();
     while let Some(e) = ctrl_rx.next().await {
         match e {
             Event::InternalMessage(msg) => match msg.payload {
@@ -105,7 +275,7 @@ pub(super) async fn main<A: Authority + 'static>(
                 CoordinationPayload::Register { .. } => {
                     if let Some(ref mut ctrl) = controller {
                         tokio::task::block_in_place(|| {
-                            if let Err(e) = ctrl.handle_register(msg) {
+                            if let Err(e) = ctrl.handle_register(msg, &authority) {
                                 warn!(log, "worker registered and then immediately left: {:?}", e);
                             }
                         });
@@ -113,19 +283,66 @@ pub(super) async fn main<A: Authority + 'static>(
                 }
                 CoordinationPayload::Heartbeat => {
                     if let Some(ref mut ctrl) = controller {
-                        tokio::task::block_in_place(|| ctrl.handle_heartbeat(msg).unwrap());
+                        tokio::task::block_in_place(|| {
+                            ctrl.handle_heartbeat(msg, &authority).unwrap()
+                        });
+                    }
+                }
+                CoordinationPayload::ReaderLoad(load) => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.record_reader_load(msg.source, load);
+                    }
+                }
+                CoordinationPayload::FireTrigger(firing) => {
+                    if let Some(ref mut ctrl) = controller {
+                        tokio::task::block_in_place(|| ctrl.fire_trigger(&authority, firing));
+                    }
+                }
+                CoordinationPayload::DeadLetters { table, letters } => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.record_dead_letters(table, letters);
+                    }
+                }
+                CoordinationPayload::Quarantine { view, violations } => {
+                    if let Some(ref mut ctrl) = controller {
+                        ctrl.record_violations(view, violations);
+                    }
+                }
+                CoordinationPayload::Evicted { bytes } => {
+                    if let Some(ref mut ctrl) = controller {
+                        tokio::task::block_in_place(|| {
+                            ctrl.handle_eviction(&authority, msg.source, bytes)
+                        });
+                    }
+                }
+                CoordinationPayload::DomainFailed {
+                    domain,
+                    shard,
+                    packet,
+                    reason,
+                } => {
+                    if let Some(ref mut ctrl) = controller {
+                        tokio::task::block_in_place(|| {
+                            ctrl.handle_failed_domain(domain, shard, packet, reason)
+                        });
                     }
                 }
                 _ => unreachable!(),
             },
-            Event::ExternalRequest(method, path, query, body, reply_tx) => {
+            Event::ExternalRequest(method, path, query, body, identity, reply_tx) => {
                 if let Some(ref mut ctrl) = controller {
                     let authority = &authority;
+                    let since = ctrl.next_backfill();
                     let reply = tokio::task::block_in_place(|| {
-                        ctrl.external_request(method, path, query, body, &authority)
+                        ctrl.external_request(method, path, query, body, identity, &authority)
                     });
 
-                    if reply_tx.send(reply).is_err() {
+                    let pending: Vec<_> = (since..ctrl.next_backfill())
+                        .filter(|&b| ctrl.is_backfilling(b))
+                        .collect();
+                    if !pending.is_empty() {
+                        held.push((pending, reply, reply_tx));
+                    } else if reply_tx.send(reply).is_err() {
                         warn!(log, "client hung up");
                     }
                 } else if reply_tx.send(Err(StatusCode::NOT_FOUND)).is_err() {
@@ -144,7 +361,6 @@ pub(super) async fn main<A: Authority + 'static>(
                     unreachable!("got migration closure before becoming leader");
                 }
             }
-            #[cfg(test)]
             Event::IsReady(reply) => {
                 reply
                     .send(
@@ -156,16 +372,54 @@ pub(super) async fn main<A: Authority + 'static>(
                     .unwrap();
             }
             Event::WonLeaderElection(state) => {
-                let c = campaign.take().unwrap();
-                tokio::task::block_in_place(move || c.join().unwrap());
                 let drx = drx.take().unwrap();
-                controller = Some(ControllerInner::new(log.clone(), state, drx));
+                let mut ctrl =
+                    ControllerInner::new(log.clone(), state, drx, progress.clone(), tx.clone());
+                tokio::task::block_in_place(|| {
+                    ctrl.record_event(
+                        &authority,
+                        ControllerEventKind::LeaderElected {
+                            controller: external_addr,
+                        },
+                    )
+                });
+                controller = Some(ctrl);
+            }
+            Event::LostLeadership => {
+                if let Some(ctrl) = controller.take() {
+                    crit!(log, "lost leadership; stepping down");
+                    drx = Some(ctrl.step_down());
+                    for (_, _, reply_tx) in held.drain(..) {
+                        let _ = reply_tx.send(Err(StatusCode::SERVICE_UNAVAILABLE));
+                    }
+                }
             }
             Event::CampaignError(e) => {
                 panic!("{:?}", e);
             }
+            Event::ReplayFinished => {}
+            Event::TriggerActionFinished => {
+                if let Some(ref mut ctrl) = controller {
+                    tokio::task::block_in_place(|| ctrl.run_triggers(&authority));
+                }
+            }
             e => unreachable!("{:?} is not a controller event", e),
         }
+
+        if let Some(ref mut ctrl) = controller {
+            tokio::task::block_in_place(|| ctrl.advance_backfills());
+            let mut i = 0;
+            while i < held.len() {
+                if held[i].0.iter().any(|&b| ctrl.is_backfilling(b)) {
+                    i += 1;
+                    continue;
+                }
+                let (_, reply, reply_tx) = held.swap_remove(i);
+                if reply_tx.send(reply).is_err() {
+                    warn!(log, "client hung up");
+                }
+            }
+        }
     }
 
     // shutting down
@@ -182,6 +436,7 @@ async fn listen_domain_replies(
     valve: Valve,
     log: slog::Logger,
     reply_tx: UnboundedSender<ControlReplyPacket>,
+    wake: UnboundedSender<Event>,
     mut on: tokio::net::TcpListener,
 ) {
     let mut incoming = valve.wrap(on.incoming());
@@ -193,20 +448,25 @@ async fn listen_domain_replies(
             }
             Ok(sock) => {
                 let alive = alive.clone();
-                tokio::spawn(
-                    valve
-                        .wrap(AsyncBincodeReader::from(sock))
-                        .map_err(failure::Error::from)
-                        .forward(
-                            crate::ImplSinkForSender(reply_tx.clone())
-                                .sink_map_err(|_| format_err!("main event loop went away")),
-                        )
-                        .map_err(|e| panic!("{:?}", e))
-                        .map(move |_| {
-                            let _ = alive;
-                            ()
-                        }),
-                );
+                let reply_tx = reply_tx.clone();
+                let wake = wake.clone();
+                let sock = noria::tls::Stream::server(sock);
+                let mut replies = valve.wrap(AsyncBincodeReader::from(sock));
+                tokio::spawn(async move {
+                    while let Some(reply) = replies.next().await {
+                        let reply: ControlReplyPacket = reply.unwrap();
+                        // the controller only looks for finished replays when it is woken up,
+                        // so make sure the reply is there for it to find first
+                        let finished = matches!(reply, ControlReplyPacket::Replayed(..));
+                        if reply_tx.send(reply).is_err() {
+                            panic!("main event loop went away");
+                        }
+                        if finished {
+                            let _ = wake.send(Event::ReplayFinished);
+                        }
+                    }
+                    let _ = alive;
+                });
             }
         }
     }
@@ -262,6 +522,24 @@ fn instance_campaign<A: Authority + 'static>(
                         epoch,
                         recipe_version: 0,
                         recipes: vec![],
+                        recipe_history: VecDeque::new(),
+                        pending_migration: None,
+                        read_only: false,
+                        read_only_tables: HashSet::new(),
+                        triggers: TriggerState::default(),
+                        mirrors: HashMap::new(),
+                        event_time_columns: HashMap::new(),
+                        kafka_sources: BTreeMap::new(),
+                        assertions: Vec::new(),
+                        events: VecDeque::new(),
+                        pass_through: BTreeMap::new(),
+                        view_names: Default::default(),
+                        checkpoint: None,
+                        workers: 0,
+                        read_replicas: HashMap::new(),
+                        evictions: HashMap::new(),
+                        priorities: HashMap::new(),
+                        view_storage: HashMap::new(),
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
@@ -281,16 +559,20 @@ fn instance_campaign<A: Authority + 'static>(
 
             // LEADER STATE - manage system
             //
-            // It is not currently possible to safely handle involuntary loss of leadership status
-            // (and there is nothing that can currently trigger it), so don't bother watching for
-            // it.
+            // Leadership is lost if our session with the authority expires, such as during a
+            // network partition, and a standby may then take over. Every change to the controller
+            // state is fenced by epoch, so a deposed leader cannot overwrite what its successor
+            // writes, but it must still stop managing the workers, which follow the new leader.
             event_tx
                 .send(Event::WonLeaderElection(state.clone().unwrap()))
                 .map_err(|_| format_err!("failed to announce who won leader election"))?;
             event_tx
                 .send(Event::LeaderChange(state.unwrap(), descriptor.clone()))
                 .map_err(|_| format_err!("failed to announce leader change"))?;
-            break Ok(());
+            authority.await_new_epoch(epoch)?;
+            event_tx
+                .send(Event::LostLeadership)
+                .map_err(|_| format_err!("failed to announce loss of leadership"))?;
         }
     };
 
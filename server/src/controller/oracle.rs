@@ -0,0 +1,677 @@
+//! A naive evaluator for the queries of a recipe, whose results views are checked against by
+//! `ControllerHandle::check_view`.
+//!
+//! A query is evaluated straight from its SQL over every row of the tables it reads from: the
+//! tables are joined by filtering their cross product, and the result is then filtered, grouped
+//! and projected. This is slow, but shares none of the planning, rewriting or operators that
+//! compute the views, so that bugs in any of them show up as differences.
+
+use nom_sql::{
+    ArithmeticBase, ArithmeticExpression, ArithmeticOperator, Column, ColumnOrLiteral,
+    ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
+    FieldValueExpression, FunctionArguments, FunctionExpression, JoinConstraint, JoinOperator,
+    JoinRightSide, Literal, Operator, SelectStatement, SqlQuery,
+};
+use noria::debug::oracle::ReferenceRows;
+use noria::DataType;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Rows along with the table (or alias) and name of each of their columns.
+struct Relation {
+    columns: Vec<(String, String)>,
+    rows: Vec<Vec<DataType>>,
+}
+
+/// The position of the column `c` among `columns`.
+fn find(columns: &[(String, String)], c: &Column) -> Result<usize, String> {
+    let mut found = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, (t, n))| *n == c.name && c.table.as_ref().map(|ct| ct == t).unwrap_or(true));
+    match (found.next(), found.next()) {
+        (Some((i, _)), None) => Ok(i),
+        (None, _) => Err(format!("unknown column {}", c)),
+        (Some(_), Some(_)) => Err(format!("ambiguous column {}", c)),
+    }
+}
+
+/// Evaluate `query`, reading the columns and rows of the tables and views it reads from with
+/// `source`.
+///
+/// The equality comparisons with `?` in the `WHERE` clause are the parameters of the query. They
+/// are left out of the filter, and their columns are added to the result if it does not already
+/// have them, so that the rows can be grouped by the key that the view is looked up with.
+pub(super) fn evaluate<F>(query: &SqlQuery, mut source: F) -> Result<ReferenceRows, String>
+where
+    F: FnMut(&str) -> Result<(Vec<String>, Vec<Vec<DataType>>), String>,
+{
+    match *query {
+        SqlQuery::Select(ref q) => select(q, &mut source),
+        _ => Err("only SELECT queries can be evaluated".to_owned()),
+    }
+}
+
+fn select<F>(q: &SelectStatement, source: &mut F) -> Result<ReferenceRows, String>
+where
+    F: FnMut(&str) -> Result<(Vec<String>, Vec<Vec<DataType>>), String>,
+{
+    if q.limit.is_some() {
+        return Err("LIMIT is not supported".to_owned());
+    }
+
+    let mut load = |t: &nom_sql::Table| -> Result<Relation, String> {
+        let (columns, rows) = source(&t.name)?;
+        let label = t.alias.clone().unwrap_or_else(|| t.name.clone());
+        Ok(Relation {
+            columns: columns.into_iter().map(|c| (label.clone(), c)).collect(),
+            rows,
+        })
+    };
+    let mut from = match q.tables.first() {
+        Some(t) => load(t)?,
+        None => return Err("queries must read from a table".to_owned()),
+    };
+    for t in &q.tables[1..] {
+        from = cross(from, load(t)?);
+    }
+    for j in &q.join {
+        let right = match j.right {
+            JoinRightSide::Table(ref t) => load(t)?,
+            JoinRightSide::Tables(ref ts) => {
+                let mut r = load(&ts[0])?;
+                for t in &ts[1..] {
+                    r = cross(r, load(t)?);
+                }
+                r
+            }
+            _ => return Err("nested joins and selects are not supported".to_owned()),
+        };
+        let outer = match j.operator {
+            JoinOperator::LeftJoin | JoinOperator::LeftOuterJoin => true,
+            _ => false,
+        };
+        from = join(from, right, &j.constraint, outer)?;
+    }
+
+    // pull the parameters out of the filter
+    let mut params = Vec::new();
+    let filter = match q.where_clause {
+        Some(ref ce) => without_params(ce, &mut params)?,
+        None => None,
+    };
+    let params = params
+        .iter()
+        .map(|c| find(&from.columns, c))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(ref filter) = filter {
+        let mut kept = Vec::with_capacity(from.rows.len());
+        for row in from.rows.drain(..) {
+            if truth(filter, &from.columns, &row, None)? == Some(true) {
+                kept.push(row);
+            }
+        }
+        from.rows = kept;
+    }
+
+    // the names of the output columns, and for each the column of `from` it takes its value from
+    let mut columns = Vec::new();
+    let mut fields = Vec::new();
+    for f in &q.fields {
+        match *f {
+            FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => {
+                for (i, (t, n)) in from.columns.iter().enumerate() {
+                    if let FieldDefinitionExpression::AllInTable(ref only) = *f {
+                        if t != only {
+                            continue;
+                        }
+                    }
+                    columns.push(n.clone());
+                    fields.push(Field::Column(i));
+                }
+            }
+            FieldDefinitionExpression::Col(ref c) => {
+                columns.push(c.alias.clone().unwrap_or_else(|| c.name.clone()));
+                match c.function {
+                    Some(ref func) => fields.push(Field::Aggregate(func)),
+                    None => fields.push(Field::Column(find(&from.columns, c)?)),
+                }
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => {
+                columns.push(l.alias.clone().unwrap_or_else(|| l.value.to_string()));
+                fields.push(Field::Literal(literal(&l.value)?));
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => {
+                columns.push(a.alias.clone().unwrap_or_else(|| a.to_string()));
+                fields.push(Field::Arithmetic(a));
+            }
+        }
+    }
+    let mut key = Vec::new();
+    for &p in &params {
+        let name = &from.columns[p].1;
+        match columns.iter().position(|c| c == name) {
+            Some(i) => key.push(i),
+            None => {
+                key.push(columns.len());
+                columns.push(name.clone());
+                fields.push(Field::Column(p));
+            }
+        }
+    }
+
+    let aggregated = q.group_by.is_some() || fields.iter().any(Field::is_aggregate);
+    let groups: Vec<Vec<Vec<DataType>>> = if aggregated {
+        // rows are grouped by the columns they are grouped by, by the parameters, and by any
+        // other columns that are not aggregated
+        let mut by = params.clone();
+        if let Some(ref g) = q.group_by {
+            for c in &g.columns {
+                by.push(find(&from.columns, c)?);
+            }
+        }
+        by.extend(fields.iter().filter_map(|f| match *f {
+            Field::Column(i) => Some(i),
+            _ => None,
+        }));
+        let mut groups: BTreeMap<Vec<DataType>, Vec<Vec<DataType>>> = BTreeMap::new();
+        for row in from.rows.drain(..) {
+            let k = by.iter().map(|&c| row[c].clone()).collect();
+            groups.entry(k).or_insert_with(Vec::new).push(row);
+        }
+        groups.into_iter().map(|(_, rows)| rows).collect()
+    } else {
+        from.rows.drain(..).map(|r| vec![r]).collect()
+    };
+
+    let having = q.group_by.as_ref().and_then(|g| g.having.as_ref());
+    let mut rows = Vec::with_capacity(groups.len());
+    for group in &groups {
+        if let Some(having) = having {
+            if truth(having, &from.columns, &group[0], Some(&group[..]))? != Some(true) {
+                continue;
+            }
+        }
+        let row = fields
+            .iter()
+            .map(|f| match *f {
+                Field::Column(i) => Ok(group[0][i].clone()),
+                Field::Literal(ref v) => Ok(v.clone()),
+                Field::Arithmetic(a) => arithmetic(a, &from.columns, &group[0]),
+                Field::Aggregate(func) => aggregate(func, &from.columns, group),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.push(row);
+    }
+    if q.distinct {
+        rows.sort();
+        rows.dedup();
+    }
+
+    Ok(ReferenceRows { columns, key, rows })
+}
+
+/// Where an output column takes its value from.
+enum Field<'a> {
+    Column(usize),
+    Literal(DataType),
+    Arithmetic(&'a ArithmeticExpression),
+    Aggregate(&'a FunctionExpression),
+}
+
+impl Field<'_> {
+    fn is_aggregate(&self) -> bool {
+        match *self {
+            Field::Aggregate(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn cross(left: Relation, right: Relation) -> Relation {
+    let mut rows = Vec::with_capacity(left.rows.len() * right.rows.len());
+    for l in &left.rows {
+        for r in &right.rows {
+            rows.push(l.iter().chain(r).cloned().collect());
+        }
+    }
+    let mut columns = left.columns;
+    columns.extend(right.columns);
+    Relation { columns, rows }
+}
+
+fn join(
+    left: Relation,
+    right: Relation,
+    constraint: &JoinConstraint,
+    outer: bool,
+) -> Result<Relation, String> {
+    let width = right.columns.len();
+    let left_rows = left.rows;
+    let mut columns = left.columns;
+    columns.extend(right.columns);
+
+    let mut rows = Vec::new();
+    for l in &left_rows {
+        let mut matched = false;
+        for r in &right.rows {
+            let row: Vec<_> = l.iter().chain(r).cloned().collect();
+            let on = match *constraint {
+                JoinConstraint::On(ref ce) => truth(ce, &columns, &row, None)? == Some(true),
+                JoinConstraint::Using(ref cs) => cs.iter().all(|c| {
+                    // the column is named once in each side
+                    let i = columns[..l.len()].iter().position(|(_, n)| *n == c.name);
+                    let j = columns[l.len()..].iter().position(|(_, n)| *n == c.name);
+                    match (i, j) {
+                        (Some(i), Some(j)) => {
+                            compare(&row[i], &row[l.len() + j]) == Some(Ordering::Equal)
+                        }
+                        _ => false,
+                    }
+                }),
+            };
+            if on {
+                matched = true;
+                rows.push(row);
+            }
+        }
+        if outer && !matched {
+            let mut row = l.clone();
+            row.extend(std::iter::repeat(DataType::None).take(width));
+            rows.push(row);
+        }
+    }
+    Ok(Relation { columns, rows })
+}
+
+/// `ce` without the equality comparisons with `?`, which are added to `params`.
+///
+/// Parameters may only be combined with the rest of the condition with `AND`.
+fn without_params(
+    ce: &ConditionExpression,
+    params: &mut Vec<Column>,
+) -> Result<Option<ConditionExpression>, String> {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            ref left,
+            ref right,
+        }) => {
+            let left = without_params(left, params)?;
+            let right = without_params(right, params)?;
+            Ok(match (left, right) {
+                (Some(l), Some(r)) => Some(ConditionExpression::LogicalOp(ConditionTree {
+                    operator: Operator::And,
+                    left: Box::new(l),
+                    right: Box::new(r),
+                })),
+                (l, r) => l.or(r),
+            })
+        }
+        ConditionExpression::Bracketed(ref inner) => without_params(inner, params),
+        ConditionExpression::ComparisonOp(ConditionTree {
+            ref operator,
+            ref left,
+            ref right,
+        }) if is_placeholder(right) => match (operator, &**left) {
+            (Operator::Equal, ConditionExpression::Base(ConditionBase::Field(ref c))) => {
+                params.push(c.clone());
+                Ok(None)
+            }
+            _ => Err("only equality comparisons with parameters are supported".to_owned()),
+        },
+        _ if has_placeholder(ce) => {
+            Err("parameters may only be combined with the rest of the filter by AND".to_owned())
+        }
+        _ => Ok(Some(ce.clone())),
+    }
+}
+
+fn is_placeholder(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => true,
+        _ => false,
+    }
+}
+
+fn has_placeholder(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref t) | ConditionExpression::LogicalOp(ref t) => {
+            has_placeholder(&t.left) || has_placeholder(&t.right)
+        }
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            has_placeholder(inner)
+        }
+        _ => is_placeholder(ce),
+    }
+}
+
+fn literal(l: &Literal) -> Result<DataType, String> {
+    Ok(match *l {
+        Literal::Null => DataType::None,
+        Literal::Integer(i) => i.into(),
+        Literal::UnsignedInteger(i) => i.into(),
+        Literal::String(ref s) => s.as_str().into(),
+        Literal::FixedPoint(_) => l.into(),
+        _ => return Err(format!("literal {} is not supported", l.to_string())),
+    })
+}
+
+/// Compare two values as SQL does, which is not at all if either is NULL.
+fn compare(a: &DataType, b: &DataType) -> Option<Ordering> {
+    if a.is_none() || b.is_none() {
+        return None;
+    }
+    let is_number = |v: &DataType| {
+        matches!(
+            *v,
+            DataType::Int(_) | DataType::BigInt(_) | DataType::Real(..) | DataType::Decimal(..)
+        )
+    };
+    if (a.is_integer() && b.is_integer()) || !is_number(a) || !is_number(b) {
+        Some(a.cmp(b))
+    } else {
+        f64::from(a).partial_cmp(&f64::from(b))
+    }
+}
+
+/// The value of `ce` for `row`, whose columns are `columns`.
+fn value(
+    ce: &ConditionExpression,
+    columns: &[(String, String)],
+    row: &[DataType],
+    group: Option<&[Vec<DataType>]>,
+) -> Result<DataType, String> {
+    match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => match (&c.function, group) {
+            (Some(func), Some(group)) => aggregate(func, columns, group),
+            (Some(_), None) => Err(format!("{} is only allowed in HAVING", c)),
+            (None, _) => Ok(row[find(columns, c)?].clone()),
+        },
+        ConditionExpression::Base(ConditionBase::Literal(ref l)) => literal(l),
+        ConditionExpression::Arithmetic(ref a) => arithmetic(a, columns, row),
+        ConditionExpression::Bracketed(ref inner) => value(inner, columns, row, group),
+        _ => Err(format!("{} is not a value", ce)),
+    }
+}
+
+/// Whether `ce` holds for `row`, which is unknown if it compares with NULL.
+///
+/// `group` is the group that `row` is the first row of, if the condition is a `HAVING` clause.
+fn truth(
+    ce: &ConditionExpression,
+    columns: &[(String, String)],
+    row: &[DataType],
+    group: Option<&[Vec<DataType>]>,
+) -> Result<Option<bool>, String> {
+    match *ce {
+        ConditionExpression::LogicalOp(ref t) => {
+            let l = truth(&t.left, columns, row, group)?;
+            let r = truth(&t.right, columns, row, group)?;
+            Ok(match t.operator {
+                Operator::And => match (l, r) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                },
+                Operator::Or => match (l, r) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+                ref op => return Err(format!("unsupported logical operator {}", op)),
+            })
+        }
+        ConditionExpression::NegationOp(ref inner) => {
+            Ok(truth(inner, columns, row, group)?.map(|b| !b))
+        }
+        ConditionExpression::Bracketed(ref inner) => truth(inner, columns, row, group),
+        ConditionExpression::ComparisonOp(ref t) => {
+            let l = value(&t.left, columns, row, group)?;
+            if let ConditionExpression::Base(ConditionBase::LiteralList(ref list)) = *t.right {
+                if t.operator != Operator::In {
+                    return Err(format!("unsupported comparison {}", ce));
+                }
+                let mut found = Some(false);
+                for v in list {
+                    match compare(&l, &literal(v)?) {
+                        Some(Ordering::Equal) => return Ok(Some(true)),
+                        None => found = None,
+                        _ => (),
+                    }
+                }
+                return Ok(found);
+            }
+            // `IS NULL` and `IS NOT NULL` are parsed as comparisons with NULL
+            if let ConditionExpression::Base(ConditionBase::Literal(Literal::Null)) = *t.right {
+                match t.operator {
+                    Operator::Equal => return Ok(Some(l.is_none())),
+                    Operator::NotEqual => return Ok(Some(!l.is_none())),
+                    _ => (),
+                }
+            }
+            let r = value(&t.right, columns, row, group)?;
+            let ord = match compare(&l, &r) {
+                Some(ord) => ord,
+                None => return Ok(None),
+            };
+            Ok(Some(match t.operator {
+                Operator::Equal => ord == Ordering::Equal,
+                Operator::NotEqual => ord != Ordering::Equal,
+                Operator::Greater => ord == Ordering::Greater,
+                Operator::GreaterOrEqual => ord != Ordering::Less,
+                Operator::Less => ord == Ordering::Less,
+                Operator::LessOrEqual => ord != Ordering::Greater,
+                ref op => return Err(format!("unsupported comparison operator {}", op)),
+            }))
+        }
+        _ => Err(format!("{} is not a condition", ce)),
+    }
+}
+
+/// The value of `a` for `row`, computed with the arithmetic of `DataType`.
+fn arithmetic(
+    a: &ArithmeticExpression,
+    columns: &[(String, String)],
+    row: &[DataType],
+) -> Result<DataType, String> {
+    let operand = |b: &ArithmeticBase| match *b {
+        ArithmeticBase::Column(ref c) => value(
+            &ConditionExpression::Base(ConditionBase::Field(c.clone())),
+            columns,
+            row,
+            None,
+        ),
+        ArithmeticBase::Scalar(ref l) => literal(l),
+    };
+    let (l, r) = (operand(&a.left)?, operand(&a.right)?);
+    if l.is_none() || r.is_none() {
+        return Ok(DataType::None);
+    }
+    if !(l.is_integer() || l.is_real()) || !(r.is_integer() || r.is_real()) {
+        return Err(format!("cannot compute {} with {:?} and {:?}", a, l, r));
+    }
+    Ok(match a.op {
+        ArithmeticOperator::Add => &l + &r,
+        ArithmeticOperator::Subtract => &l - &r,
+        ArithmeticOperator::Multiply => &l * &r,
+        ArithmeticOperator::Divide => &l / &r,
+    })
+}
+
+/// The value of the aggregate `func` over the rows of `group`.
+fn aggregate(
+    func: &FunctionExpression,
+    columns: &[(String, String)],
+    group: &[Vec<DataType>],
+) -> Result<DataType, String> {
+    let values = |args: &FunctionArguments, distinct: bool| -> Result<Vec<DataType>, String> {
+        let mut vs = Vec::with_capacity(group.len());
+        for row in group {
+            let v = match *args {
+                FunctionArguments::Column(ref c) => value(
+                    &ConditionExpression::Base(ConditionBase::Field(c.clone())),
+                    columns,
+                    row,
+                    None,
+                )?,
+                FunctionArguments::Conditional(ref case) => {
+                    let branch = if truth(&case.condition, columns, row, None)? == Some(true) {
+                        Some(&case.then_expr)
+                    } else {
+                        case.else_expr.as_ref()
+                    };
+                    match branch {
+                        Some(ColumnOrLiteral::Column(ref c)) => value(
+                            &ConditionExpression::Base(ConditionBase::Field(c.clone())),
+                            columns,
+                            row,
+                            None,
+                        )?,
+                        Some(ColumnOrLiteral::Literal(ref l)) => literal(l)?,
+                        None => DataType::None,
+                    }
+                }
+            };
+            if !v.is_none() {
+                vs.push(v);
+            }
+        }
+        if distinct {
+            vs.sort();
+            vs.dedup();
+        }
+        Ok(vs)
+    };
+    let extreme = |vs: Vec<DataType>, want: Ordering| {
+        vs.into_iter().fold(DataType::None, |best, v| {
+            if best.is_none() || compare(&v, &best) == Some(want) {
+                v
+            } else {
+                best
+            }
+        })
+    };
+    Ok(match *func {
+        FunctionExpression::CountStar => (group.len() as i64).into(),
+        FunctionExpression::Count(ref args, distinct) => {
+            (values(args, distinct)?.len() as i64).into()
+        }
+        FunctionExpression::Sum(ref args, distinct) => {
+            let vs = values(args, distinct)?;
+            if vs.iter().all(DataType::is_integer) {
+                vs.iter().map(|v| i64::from(v)).sum::<i64>().into()
+            } else {
+                vs.iter().map(f64::from).sum::<f64>().into()
+            }
+        }
+        FunctionExpression::Avg(ref args, distinct) => {
+            let vs = values(args, distinct)?;
+            if vs.is_empty() {
+                DataType::None
+            } else {
+                (vs.iter().map(f64::from).sum::<f64>() / vs.len() as f64).into()
+            }
+        }
+        FunctionExpression::Max(ref args) => extreme(values(args, false)?, Ordering::Greater),
+        FunctionExpression::Min(ref args) => extreme(values(args, false)?, Ordering::Less),
+        FunctionExpression::GroupConcat(ref args, ref separator) => {
+            let vs = values(args, false)?;
+            let mut vs: Vec<_> = vs
+                .iter()
+                .map(dataflow::ops::grouped::concat::text)
+                .collect();
+            vs.sort();
+            vs.join(separator.as_str()).into()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(sql: &str) -> Result<ReferenceRows, String> {
+        let q = nom_sql::parse_query(sql).unwrap();
+        evaluate(&q, |table| match table {
+            "vote" => Ok((
+                vec!["aid".to_owned(), "uid".to_owned()],
+                vec![
+                    vec![1.into(), 1.into()],
+                    vec![1.into(), 2.into()],
+                    vec![2.into(), 1.into()],
+                    vec![3.into(), DataType::None],
+                ],
+            )),
+            "article" => Ok((
+                vec!["id".to_owned(), "title".to_owned()],
+                vec![vec![1.into(), "a".into()], vec![4.into(), "b".into()]],
+            )),
+            t => Err(format!("no table {}", t)),
+        })
+    }
+
+    fn sorted(mut rows: Vec<Vec<DataType>>) -> Vec<Vec<DataType>> {
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn it_groups_by_parameters() {
+        let r = eval("SELECT COUNT(uid) AS votes FROM vote WHERE aid = ?").unwrap();
+        assert_eq!(r.columns, vec!["votes".to_owned(), "aid".to_owned()]);
+        assert_eq!(r.key, vec![1]);
+        assert_eq!(
+            sorted(r.rows),
+            vec![
+                vec![0.into(), 3.into()],
+                vec![1.into(), 2.into()],
+                vec![2.into(), 1.into()],
+            ]
+        );
+    }
+
+    #[test]
+    fn it_concatenates_groups() {
+        let r = eval("SELECT aid, GROUP_CONCAT(uid SEPARATOR ';') AS uids FROM vote GROUP BY aid")
+            .unwrap();
+        assert_eq!(
+            sorted(r.rows),
+            vec![
+                vec![1.into(), "1;2".into()],
+                vec![2.into(), "1".into()],
+                vec![3.into(), "".into()],
+            ]
+        );
+    }
+
+    #[test]
+    fn it_filters_and_joins() {
+        let r = eval(
+            "SELECT article.id, title, uid FROM article \
+             LEFT JOIN vote ON (article.id = vote.aid) WHERE title = ?",
+        )
+        .unwrap();
+        assert_eq!(r.key, vec![1]);
+        assert_eq!(
+            sorted(r.rows),
+            vec![
+                vec![1.into(), "a".into(), 1.into()],
+                vec![1.into(), "a".into(), 2.into()],
+                vec![4.into(), "b".into(), DataType::None],
+            ]
+        );
+
+        let r = eval("SELECT aid FROM vote WHERE uid IS NULL OR uid > 1").unwrap();
+        assert!(r.key.is_empty());
+        assert_eq!(sorted(r.rows), vec![vec![1.into()], vec![3.into()]]);
+    }
+
+    #[test]
+    fn it_rejects_what_it_cannot_evaluate() {
+        assert!(eval("SELECT aid FROM vote WHERE uid = ? OR aid = 1").is_err());
+        assert!(eval("SELECT aid FROM vote WHERE uid > ?").is_err());
+        assert!(eval("SELECT aid FROM vote LIMIT 3").is_err());
+        assert!(eval("SELECT aid FROM comment").is_err());
+    }
+}
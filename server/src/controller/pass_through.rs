@@ -0,0 +1,109 @@
+use crate::controller::lint;
+use crate::controller::recipe::Recipe;
+use std::collections::BTreeMap;
+
+/// Remove the named queries from `recipe_text` that Noria cannot support, so that they can be
+/// executed against the upstream database instead.
+///
+/// Queries that only fail because they read from a removed query are removed as well. Other
+/// statements are kept, so that the migration still reports their errors. Returns the remaining
+/// recipe, and the SQL of every removed query by name.
+pub(super) fn split_unsupported<F>(
+    recipe_text: &str,
+    sharded: bool,
+    partial: bool,
+    exists: F,
+) -> (String, BTreeMap<String, String>)
+where
+    F: Fn(&str) -> bool,
+{
+    let mut kept: Vec<String> = Vec::new();
+    for (statement, _) in Recipe::parse_statements(recipe_text) {
+        // several statements on one line share their text
+        if kept.last() != Some(&statement) {
+            kept.push(statement);
+        }
+    }
+
+    let mut pass_through = BTreeMap::new();
+    loop {
+        let text = kept.join("\n");
+        let unsupported: Vec<_> = lint::lint_recipe(&text, sharded, partial, &exists)
+            .into_iter()
+            .filter(|l| !l.unsupported.is_empty())
+            .filter_map(|l| {
+                let (name, sql) = Recipe::split_name(&l.statement);
+                let name = name.or(l.name)?;
+                if !is_query(sql) {
+                    return None;
+                }
+                let sql = sql.to_owned();
+                Some((l.statement, name, sql))
+            })
+            .collect();
+
+        if unsupported.is_empty() {
+            return (text, pass_through);
+        }
+        for (statement, name, sql) in unsupported {
+            kept.retain(|s| *s != statement);
+            pass_through.insert(name, sql);
+        }
+    }
+}
+
+/// True if `sql` reads data, and so can be answered by the upstream database.
+fn is_query(sql: &str) -> bool {
+    sql.trim_start()
+        .get(..6)
+        .map(|kw| kw.eq_ignore_ascii_case("select"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(recipe: &str) -> (String, BTreeMap<String, String>) {
+        split_unsupported(recipe, false, true, |t| t == "article")
+    }
+
+    #[test]
+    fn it_keeps_supported_statements() {
+        let recipe = "CREATE TABLE vote (aid int, uid int);\n\
+                      QUERY votes: SELECT uid FROM vote WHERE aid = ?;";
+        let (kept, pass_through) = split(recipe);
+        assert_eq!(kept, recipe);
+        assert!(pass_through.is_empty());
+    }
+
+    #[test]
+    fn it_removes_unsupported_queries_and_their_dependents() {
+        let (kept, pass_through) = split(
+            "CREATE TABLE vote (aid int, uid int);\n\
+             QUERY odd: SELECT uid FROM vote WHERE aid = ? FOR UPDATE;\n\
+             QUERY missing: SELECT id FROM nope WHERE id = ?;\n\
+             QUERY also_missing: SELECT id FROM missing WHERE id = ?;\n\
+             QUERY titles: SELECT title FROM article WHERE id = ?;",
+        );
+        assert_eq!(
+            kept,
+            "CREATE TABLE vote (aid int, uid int);\n\
+             QUERY titles: SELECT title FROM article WHERE id = ?;"
+        );
+        assert_eq!(
+            pass_through.keys().collect::<Vec<_>>(),
+            vec!["also_missing", "missing", "odd"]
+        );
+        assert_eq!(pass_through["missing"], "SELECT id FROM nope WHERE id = ?;");
+    }
+
+    #[test]
+    fn it_keeps_unsupported_tables_and_unnamed_queries() {
+        let recipe = "CREATE TABLE vote (aid int, uid int) WITH SOMETHING;\n\
+                      SELECT id FROM nope WHERE id = ?;";
+        let (kept, pass_through) = split(recipe);
+        assert_eq!(kept, recipe);
+        assert!(pass_through.is_empty());
+    }
+}
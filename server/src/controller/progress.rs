@@ -0,0 +1,150 @@
+use dataflow::prelude::DomainIndex;
+use noria::{DomainProgress, MigrationStatus};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Tracks how far along the controller is in applying a migration.
+///
+/// Requests to the controller are not served while a migration is running, so this is shared
+/// with the external request server, which answers status requests directly.
+#[derive(Clone, Default)]
+pub(crate) struct MigrationProgress(Arc<Mutex<Progress>>);
+
+#[derive(Default)]
+struct Progress {
+    /// When the running migration started, if one is running.
+    started: Option<Instant>,
+    rows_replayed: u64,
+    bytes_replayed: u64,
+    rows_to_replay: u64,
+    /// (ready, new) nodes in each domain.
+    domains: BTreeMap<DomainIndex, (usize, usize)>,
+    /// (replayed, to replay) rows for the node that is currently being readied.
+    readying: (u64, u64),
+}
+
+impl MigrationProgress {
+    /// Note that a migration is starting.
+    ///
+    /// Migrations that start while earlier ones are still populating their materializations in
+    /// the background are counted as part of the same run.
+    pub(super) fn start(&self) {
+        let mut p = self.0.lock().unwrap();
+        if p.started.is_none() {
+            *p = Progress::default();
+            p.started = Some(Instant::now());
+        }
+    }
+
+    pub(super) fn finish(&self) {
+        self.0.lock().unwrap().started = None;
+    }
+
+    /// Note that the migration adds the given nodes, by the domain they are in.
+    pub(super) fn adding(&self, nodes: impl IntoIterator<Item = DomainIndex>) {
+        let mut p = self.0.lock().unwrap();
+        for domain in nodes {
+            p.domains.entry(domain).or_default().1 += 1;
+        }
+    }
+
+    /// Note that the next new node is about to be readied.
+    pub(super) fn readying(&self) {
+        self.0.lock().unwrap().readying = (0, 0);
+    }
+
+    /// Note that a new node in `domain` is now ready.
+    pub(super) fn ready(&self, domain: DomainIndex) {
+        let mut p = self.0.lock().unwrap();
+        p.domains.entry(domain).or_default().0 += 1;
+        p.readying = (0, 0);
+    }
+
+    /// Record a progress report from a replay.
+    pub(super) fn replayed(&self, rows: usize, bytes: u64, added: usize) {
+        let mut p = self.0.lock().unwrap();
+        p.rows_replayed += rows as u64;
+        p.bytes_replayed += bytes;
+        p.rows_to_replay += added as u64;
+        p.readying.0 += rows as u64;
+        p.readying.1 += added as u64;
+    }
+
+    pub(crate) fn status(&self) -> MigrationStatus {
+        let p = self.0.lock().unwrap();
+        let running_for = p.started.map(|started| started.elapsed());
+
+        // a node that is being readied counts for as much of itself as has been replayed
+        let (ready, nodes) = p
+            .domains
+            .values()
+            .fold((0, 0), |(r, n), &(ready, new)| (r + ready, n + new));
+        let mut done = ready as f64;
+        if p.readying.1 != 0 {
+            done += p.readying.0 as f64 / p.readying.1 as f64;
+        }
+        let eta = running_for.and_then(|elapsed| {
+            if done == 0.0 || nodes == 0 {
+                return None;
+            }
+            let fraction = f64::min(done / nodes as f64, 1.0);
+            Some(elapsed.mul_f64((1.0 - fraction) / fraction))
+        });
+
+        MigrationStatus {
+            running_for,
+            rows_replayed: p.rows_replayed,
+            bytes_replayed: p.bytes_replayed,
+            rows_to_replay: p.rows_to_replay,
+            domains: p
+                .domains
+                .iter()
+                .map(|(domain, &(ready, nodes))| DomainProgress {
+                    domain: domain.index(),
+                    ready,
+                    nodes,
+                })
+                .collect(),
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_estimates_from_ready_and_replaying_nodes() {
+        let p = MigrationProgress::default();
+        let (d0, d1) = (DomainIndex::from(0), DomainIndex::from(1));
+        p.start();
+        p.adding(vec![d0, d0, d1, d1]);
+        assert_eq!(p.status().eta, None);
+
+        p.readying();
+        p.ready(d0);
+        p.readying();
+        p.replayed(50, 400, 100);
+        let status = p.status();
+        assert!(status.running_for.is_some());
+        assert!(status.eta.is_some());
+        assert_eq!(status.rows_replayed, 50);
+        assert_eq!(status.bytes_replayed, 400);
+        assert_eq!(status.rows_to_replay, 100);
+        assert_eq!(status.domains[0].ready, 1);
+        assert_eq!(status.domains[0].nodes, 2);
+        assert!(!status.domains[0].is_complete());
+
+        p.replayed(50, 400, 0);
+        p.ready(d0);
+        p.finish();
+        let status = p.status();
+        assert_eq!(status.running_for, None);
+        assert_eq!(status.eta, None);
+        assert_eq!(status.rows_replayed, 100);
+        assert!(status.domains[0].is_complete());
+        assert!(!status.domains[1].is_complete());
+    }
+}
@@ -1,22 +1,31 @@
+use crate::controller::constraints::CheckConstraint;
+use crate::controller::security::write_policy::WritePolicyConfig;
 use crate::controller::security::SecurityConfig;
+use crate::controller::sql::cost::TableStatistics;
 use crate::controller::sql::SqlIncorporator;
 use crate::controller::Migration;
 use crate::ReuseConfigType;
+use ::mir::query::MirQuery;
 use dataflow::ops::trigger::Trigger;
 use dataflow::ops::trigger::TriggerEvent;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
-use nom_sql::SqlQuery;
-use noria::ActivationResult;
+use nom_sql::{ConditionExpression, SqlQuery};
+use noria::{ActivationResult, ShardScheme};
 use petgraph::graph::NodeIndex;
 
-use nom_sql::CreateTableStatement;
+use nom_sql::{
+    ColumnConstraint, CreateTableStatement, JoinRightSide, SelectSpecification, SelectStatement,
+    SqlType, TableKey,
+};
 use slog;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str;
+use std::time::Duration;
 use std::vec::Vec;
 
 type QueryID = u64;
+type QueryList<'a> = Vec<(Option<&'a String>, &'a SqlQuery)>;
 
 /// Represents a Soup recipe.
 #[derive(Clone, Debug)]
@@ -28,6 +37,16 @@ pub(crate) struct Recipe {
     expression_order: Vec<QueryID>,
     /// Named read/write expression aliases, mapping to queries in `expressions`.
     aliases: HashMap<String, QueryID>,
+    /// Names of the views declared with `MATERIALIZE`, which later queries can reuse as `@name`.
+    materializations: HashSet<String>,
+    /// Foreign keys declared with `ALTER TABLE ... ADD FOREIGN KEY`.
+    foreign_keys: Vec<ForeignKey>,
+    /// Check constraints declared with `ALTER TABLE ... ADD CONSTRAINT`.
+    checks: Vec<CheckConstraint>,
+    /// Shard keys declared with `ALTER TABLE ... SHARD BY` and `ALTER VIEW ... SHARD BY`.
+    shard_keys: Vec<ShardKey>,
+    /// Row TTLs declared with `ALTER TABLE ... TTL` and `ALTER VIEW ... TTL`.
+    ttls: Vec<Ttl>,
     /// Security configuration
     security_config: Option<SecurityConfig>,
 
@@ -50,6 +69,7 @@ impl PartialEq for Recipe {
         self.expressions == other.expressions
             && self.expression_order == other.expression_order
             && self.aliases == other.aliases
+            && self.materializations == other.materializations
             && self.version == other.version
             && self.prior == other.prior
     }
@@ -78,14 +98,30 @@ fn ident(input: &str) -> nom::IResult<&str, &str> {
     })
 }
 
-fn query_prefix(input: &str) -> nom::IResult<&str, (bool, Option<&str>)> {
+/// How a recipe expression is exposed, as given by the prefix before its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exposure {
+    /// No keyword: an internal view that other expressions can read from.
+    Internal,
+    /// `QUERY` or `VIEW`: a view that clients can read from.
+    Public,
+    /// `MATERIALIZE`: an internal view that is always fully materialized, so that later queries
+    /// can reuse its state by referring to it as `@name`.
+    Materialized,
+}
+
+fn query_prefix(input: &str) -> nom::IResult<&str, (Exposure, Option<&str>)> {
     use nom::branch::alt;
     use nom::bytes::complete::tag_no_case;
     use nom::character::complete::{char, multispace0, space1};
-    use nom::combinator::opt;
-    use nom::sequence::{pair, terminated};
-    let (input, public) = opt(pair(
-        alt((tag_no_case("query"), tag_no_case("view"))),
+    use nom::combinator::{map, opt};
+    use nom::sequence::terminated;
+    let (input, exposure) = opt(terminated(
+        alt((
+            map(tag_no_case("query"), |_| Exposure::Public),
+            map(tag_no_case("view"), |_| Exposure::Public),
+            map(tag_no_case("materialize"), |_| Exposure::Materialized),
+        )),
         space1,
     ))(input)?;
     let (input, _) = multispace0(input)?;
@@ -93,10 +129,10 @@ fn query_prefix(input: &str) -> nom::IResult<&str, (bool, Option<&str>)> {
     let (input, _) = multispace0(input)?;
     let (input, _) = char(':')(input)?;
     let (input, _) = multispace0(input)?;
-    Ok((input, (public.is_some(), name)))
+    Ok((input, (exposure.unwrap_or(Exposure::Internal), name)))
 }
 
-fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)> {
+fn query_expr(input: &str) -> nom::IResult<&str, (Exposure, Option<&str>, SqlQuery)> {
     use nom::character::complete::multispace0;
     use nom::combinator::opt;
     let (input, prefix) = opt(query_prefix)(input)?;
@@ -113,26 +149,464 @@ fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)>
     Ok((
         input,
         match prefix {
-            None => (false, None, expr),
-            Some((public, name)) => (public, name, expr),
+            None => (Exposure::Internal, None, expr),
+            Some((exposure, name)) => (exposure, name, expr),
         },
     ))
 }
 
-fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(bool, Option<&str>, SqlQuery)>> {
+fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(Exposure, Option<&str>, SqlQuery)>> {
     nom::multi::many1(query_expr)(input)
 }
 
+/// Rewrites the references to materialization points (`@name`) in `statement` into plain view
+/// names, which nom-sql can parse. Returns the rewritten statement and the names it referenced.
+fn expand_materializations(statement: &str) -> (String, Vec<String>) {
+    let mut expanded = String::with_capacity(statement.len());
+    let mut referenced = Vec::new();
+    let mut quote = None;
+    for (i, c) in statement.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == '@' => {
+                let (_, name) = ident(&statement[i + 1..]).unwrap();
+                if !name.is_empty() {
+                    referenced.push(name.to_owned());
+                    continue;
+                }
+            }
+            None => (),
+        }
+        expanded.push(c);
+    }
+    (expanded, referenced)
+}
+
+/// A foreign key from a column of one table to the primary key of another, whose deletes cascade.
+///
+/// Declared with `ALTER TABLE table ADD FOREIGN KEY (column) REFERENCES references
+/// (referenced_column) ON DELETE CASCADE`. Deleting a row of `references` deletes the rows of
+/// `table` that refer to it, so that they do not linger in views that join the two.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(in crate::controller) struct ForeignKey {
+    pub(in crate::controller) table: String,
+    pub(in crate::controller) column: String,
+    pub(in crate::controller) references: String,
+    pub(in crate::controller) referenced_column: String,
+}
+
+impl ForeignKey {
+    /// Parse the definition of a foreign key on `table` that follows `ADD`.
+    ///
+    /// Only single-column keys that cascade deletes are supported.
+    fn parse(table: &str, definition: &[&str]) -> Option<ForeignKey> {
+        let spaced = definition.join(" ").replace('(', " ( ").replace(')', " ) ");
+        let words: Vec<_> = spaced.split_whitespace().collect();
+        let is = |word: &str, keyword: &str| word.eq_ignore_ascii_case(keyword);
+        let (declaration, action) = words.split_at(words.len().saturating_sub(3));
+        match *action {
+            [on, delete, cascade]
+                if is(on, "on") && is(delete, "delete") && is(cascade, "cascade") => {}
+            _ => return None,
+        }
+        match *declaration {
+            [foreign, key, "(", column, ")", references, referenced, "(", referenced_column, ")"]
+                if is(foreign, "foreign") && is(key, "key") && is(references, "references") =>
+            {
+                Some(ForeignKey {
+                    table: table.to_owned(),
+                    column: column.to_owned(),
+                    references: referenced.to_owned(),
+                    referenced_column: referenced_column.to_owned(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The columns that a table or view is sharded by, rather than by what its queries would have it
+/// sharded by.
+///
+/// Declared with `ALTER TABLE table SHARD BY (column, ...) [INTO n SHARDS]`, which routes the rows
+/// of a table by the values of all of the given columns together, or with `ALTER VIEW view SHARD
+/// BY (column)`, which shards the reader of a view by one of its parameters rather than by the
+/// first. Either may end in `USING CONSISTENT HASHING` or `USING RANGE (start, end)` to spread
+/// keys across the shards by that scheme rather than by hashing them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(in crate::controller) struct ShardKey {
+    pub(in crate::controller) name: String,
+    pub(in crate::controller) columns: Vec<String>,
+    /// The number of shards of a table, if not the number the deployment shards nodes into.
+    pub(in crate::controller) shards: Option<usize>,
+    pub(in crate::controller) scheme: ShardScheme,
+}
+
+impl ShardKey {
+    /// Parse the declaration of the shard key of `name` that follows its name.
+    fn parse(name: &str, declaration: &[&str]) -> Option<ShardKey> {
+        let spaced = declaration
+            .join(" ")
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace(',', " , ");
+        let words: Vec<_> = spaced.split_whitespace().collect();
+        let is = |word: &str, keyword: &str| word.eq_ignore_ascii_case(keyword);
+        let rest = match words[..] {
+            [shard, by, "(", ref rest @ ..] if is(shard, "shard") && is(by, "by") => rest,
+            _ => return None,
+        };
+        let end = rest.iter().position(|&w| w == ")")?;
+        let (columns, rest) = (&rest[..end], &rest[end + 1..]);
+        let columns: Vec<_> = columns
+            .split(|&w| w == ",")
+            .map(|c| match *c {
+                [column] => Some(column.to_owned()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let (shards, rest) = match *rest {
+            [into, n, unit, ref rest @ ..] if is(into, "into") && is(unit, "shards") => {
+                (Some(n.parse::<usize>().ok().filter(|&n| n > 0)?), rest)
+            }
+            ref rest => (None, rest),
+        };
+        let scheme = match *rest {
+            [] => ShardScheme::Hash,
+            [using, consistent, hashing]
+                if is(using, "using") && is(consistent, "consistent") && is(hashing, "hashing") =>
+            {
+                ShardScheme::Consistent
+            }
+            [using, range, "(", start, ",", end, ")"]
+                if is(using, "using") && is(range, "range") =>
+            {
+                let start = start.parse::<i64>().ok()?;
+                let end = end.parse::<i64>().ok().filter(|&end| end > start)?;
+                ShardScheme::Range { start, end }
+            }
+            _ => return None,
+        };
+        Some(ShardKey {
+            name: name.to_owned(),
+            columns,
+            shards,
+            scheme,
+        })
+    }
+}
+
+/// How long the rows of a table or view are kept, going by a timestamp column of theirs.
+///
+/// Declared with `ALTER TABLE table TTL = n unit ON column` or `ALTER VIEW view TTL = n unit ON
+/// column`, where `unit` is one of `SECONDS`, `MINUTES`, `HOURS` or `DAYS`. Rows whose timestamp
+/// is older than that are deleted from the table, and so retracted from everything below it, or
+/// retracted from the view. Integer columns are taken to hold milliseconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(in crate::controller) struct Ttl {
+    pub(in crate::controller) name: String,
+    pub(in crate::controller) column: String,
+    pub(in crate::controller) ttl: Duration,
+}
+
+impl Ttl {
+    /// Parse the declaration of the TTL of `name` that follows its name.
+    fn parse(name: &str, declaration: &[&str]) -> Option<Ttl> {
+        let spaced = declaration.join(" ").replace('=', " = ");
+        let words: Vec<_> = spaced.split_whitespace().collect();
+        let is = |word: &str, keyword: &str| word.eq_ignore_ascii_case(keyword);
+        match words[..] {
+            [ttl, "=", n, unit, on, column] if is(ttl, "ttl") && is(on, "on") => {
+                let n = n.parse::<u64>().ok().filter(|&n| n > 0)?;
+                let unit = unit.to_ascii_lowercase();
+                let secs = match unit.trim_end_matches('s') {
+                    "second" => 1,
+                    "minute" => 60,
+                    "hour" => 60 * 60,
+                    "day" => 24 * 60 * 60,
+                    _ => return None,
+                };
+                Some(Ttl {
+                    name: name.to_owned(),
+                    column: column.to_owned(),
+                    ttl: Duration::from_secs(n.checked_mul(secs)?),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The columns of the primary key of the table created by `ctq`.
+fn primary_key(ctq: &CreateTableStatement) -> Vec<&str> {
+    let inline = ctq.fields.iter().filter_map(|f| {
+        if f.constraints.contains(&ColumnConstraint::PrimaryKey) {
+            Some(f.column.name.as_str())
+        } else {
+            None
+        }
+    });
+    let declared = ctq.keys.iter().flatten().flat_map(|k| match *k {
+        TableKey::PrimaryKey(ref cols) => cols.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+        _ => Vec::new(),
+    });
+    inline.chain(declared).collect()
+}
+
+/// A change to a table, made with `ALTER TABLE`, which the SQL parser does not support.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Alteration {
+    /// `ALTER TABLE table ADD [COLUMN] definition`.
+    AddColumn { table: String, definition: String },
+    /// `ALTER TABLE table DROP [COLUMN] column`.
+    DropColumn { table: String, column: String },
+    /// `ALTER TABLE table ADD FOREIGN KEY (column) REFERENCES other (column) ON DELETE CASCADE`.
+    AddForeignKey(ForeignKey),
+    /// `ALTER TABLE table ADD [CONSTRAINT name] CHECK (predicate)`.
+    AddCheck {
+        table: String,
+        name: Option<String>,
+        predicate: ConditionExpression,
+    },
+    /// `ALTER TABLE table SHARD BY (column, ...) [INTO n SHARDS]`, or `ALTER VIEW view SHARD BY
+    /// (column)` if `view` is set.
+    ShardBy { key: ShardKey, view: bool },
+    /// `ALTER TABLE table TTL = n unit ON column`, or `ALTER VIEW view TTL = n unit ON column` if
+    /// `view` is set.
+    Ttl { ttl: Ttl, view: bool },
+}
+
+impl Alteration {
+    /// Parse an `ALTER TABLE` statement.
+    pub(super) fn parse(statement: &str) -> Option<Alteration> {
+        let words: Vec<_> = statement
+            .trim_end()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .collect();
+        match words[..] {
+            [alter, kind, name, ref rest @ ..]
+                if alter.eq_ignore_ascii_case("alter")
+                    && (kind.eq_ignore_ascii_case("table")
+                        || kind.eq_ignore_ascii_case("view"))
+                    && rest
+                        .first()
+                        .map_or(false, |w| w.eq_ignore_ascii_case("shard")) =>
+            {
+                return ShardKey::parse(name, rest).map(|key| Alteration::ShardBy {
+                    key,
+                    view: kind.eq_ignore_ascii_case("view"),
+                });
+            }
+            [alter, kind, name, ref rest @ ..]
+                if alter.eq_ignore_ascii_case("alter")
+                    && (kind.eq_ignore_ascii_case("table")
+                        || kind.eq_ignore_ascii_case("view"))
+                    && rest
+                        .first()
+                        .map_or(false, |w| w.to_ascii_lowercase().starts_with("ttl")) =>
+            {
+                return Ttl::parse(name, rest).map(|ttl| Alteration::Ttl {
+                    ttl,
+                    view: kind.eq_ignore_ascii_case("view"),
+                });
+            }
+            _ => (),
+        }
+        let (table, op, rest) = match words[..] {
+            [alter, table_kw, table, op, ref rest @ ..]
+                if alter.eq_ignore_ascii_case("alter")
+                    && table_kw.eq_ignore_ascii_case("table") =>
+            {
+                (table.to_owned(), op, rest)
+            }
+            _ => return None,
+        };
+        let rest = match rest {
+            [column, rest @ ..] if column.eq_ignore_ascii_case("column") => rest,
+            _ => rest,
+        };
+        match rest {
+            [] => None,
+            [foreign, ..]
+                if op.eq_ignore_ascii_case("add") && foreign.eq_ignore_ascii_case("foreign") =>
+            {
+                ForeignKey::parse(&table, rest).map(Alteration::AddForeignKey)
+            }
+            [constraint, name, check, predicate @ ..]
+                if op.eq_ignore_ascii_case("add")
+                    && constraint.eq_ignore_ascii_case("constraint")
+                    && check.eq_ignore_ascii_case("check") =>
+            {
+                let predicate = CheckConstraint::parse_predicate(&table, &predicate.join(" "))?;
+                Some(Alteration::AddCheck {
+                    table,
+                    name: Some((*name).to_owned()),
+                    predicate,
+                })
+            }
+            [check, predicate @ ..]
+                if op.eq_ignore_ascii_case("add") && check.eq_ignore_ascii_case("check") =>
+            {
+                let predicate = CheckConstraint::parse_predicate(&table, &predicate.join(" "))?;
+                Some(Alteration::AddCheck {
+                    table,
+                    name: None,
+                    predicate,
+                })
+            }
+            _ if op.eq_ignore_ascii_case("add") => Some(Alteration::AddColumn {
+                table,
+                definition: rest.join(" "),
+            }),
+            [column] if op.eq_ignore_ascii_case("drop") => Some(Alteration::DropColumn {
+                table,
+                column: (*column).to_owned(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The removal of a table or view, made with `DROP TABLE` or `DROP VIEW`.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Removal {
+    /// `DROP TABLE [IF EXISTS] name`.
+    Table { name: String, if_exists: bool },
+    /// `DROP VIEW [IF EXISTS] name`.
+    View { name: String, if_exists: bool },
+}
+
+impl Removal {
+    /// Parse a `DROP TABLE` or `DROP VIEW` statement.
+    pub(super) fn parse(statement: &str) -> Option<Removal> {
+        let words: Vec<_> = statement
+            .trim_end()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .collect();
+        let (kind, rest) = match words[..] {
+            [drop, kind, ref rest @ ..] if drop.eq_ignore_ascii_case("drop") => (kind, rest),
+            _ => return None,
+        };
+        let (name, if_exists) = match rest {
+            [if_kw, exists, name]
+                if if_kw.eq_ignore_ascii_case("if") && exists.eq_ignore_ascii_case("exists") =>
+            {
+                ((*name).to_owned(), true)
+            }
+            [name] => ((*name).to_owned(), false),
+            _ => return None,
+        };
+        if kind.eq_ignore_ascii_case("table") {
+            Some(Removal::Table { name, if_exists })
+        } else if kind.eq_ignore_ascii_case("view") {
+            Some(Removal::View { name, if_exists })
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `sq` reads from the table or view called `name`.
+/// The name that the query called `name` goes by in the universe of `mig`, which keeps it apart
+/// from the global query and from the query of other universes.
+fn universe_query_name(name: &str, mig: &Migration) -> String {
+    match mig.universe() {
+        (id, Some(g)) => format!("{}_{}{}", name, g.to_string(), id.to_string()),
+        (id, None) => user_universe_name(name, &id),
+    }
+}
+
+/// The name that the query called `name` goes by in the universe of the user `id`.
+pub(in crate::controller) fn user_universe_name(name: &str, id: &DataType) -> String {
+    format!("{}_u{}", name, id.to_string())
+}
+
+fn selects_from(sq: &SelectStatement, name: &str) -> bool {
+    fn joins_from(right: &JoinRightSide, name: &str) -> bool {
+        match *right {
+            JoinRightSide::Table(ref t) => t.name == name,
+            JoinRightSide::Tables(ref ts) => ts.iter().any(|t| t.name == name),
+            JoinRightSide::NestedSelect(ref ns, _) => selects_from(ns, name),
+            JoinRightSide::NestedJoin(ref jc) => joins_from(&jc.right, name),
+        }
+    }
+    sq.tables.iter().any(|t| t.name == name) || sq.join.iter().any(|jc| joins_from(&jc.right, name))
+}
+
+/// Whether `q` reads from the table or view called `name`.
+fn reads_from(q: &SqlQuery, name: &str) -> bool {
+    let definition = match *q {
+        SqlQuery::Select(ref sq) => return selects_from(sq, name),
+        SqlQuery::CompoundSelect(ref csq) => {
+            return csq
+                .selects
+                .iter()
+                .any(|&(_, ref sq)| selects_from(sq, name))
+        }
+        SqlQuery::CreateView(ref cvq) => &*cvq.definition,
+        _ => return false,
+    };
+    match *definition {
+        SelectSpecification::Simple(ref sq) => selects_from(sq, name),
+        SelectSpecification::Compound(ref csq) => csq
+            .selects
+            .iter()
+            .any(|&(_, ref sq)| selects_from(sq, name)),
+    }
+}
+
 #[allow(unused)]
 impl Recipe {
-    /// Return security groups in the recipe
+    /// Return the security groups in the recipe whose members get group universes
     pub(in crate::controller) fn security_groups(&self) -> Vec<String> {
         match self.security_config {
-            Some(ref config) => config.groups.keys().cloned().collect(),
+            Some(ref config) => config
+                .groups
+                .values()
+                .filter(|g| !g.is_dynamic())
+                .map(|g| g.name())
+                .collect(),
             None => vec![],
         }
     }
 
+    /// Return the write policies in the recipe's security configuration
+    pub(in crate::controller) fn write_policies(&self) -> &[WritePolicyConfig] {
+        match self.security_config {
+            Some(ref config) => config.write_policies(),
+            None => &[],
+        }
+    }
+
+    /// Return the foreign keys declared in the recipe, whose deletes cascade.
+    pub(in crate::controller) fn foreign_keys(&self) -> &[ForeignKey] {
+        &self.foreign_keys[..]
+    }
+
+    /// Return the check constraints declared in the recipe.
+    pub(in crate::controller) fn checks(&self) -> &[CheckConstraint] {
+        &self.checks[..]
+    }
+
+    /// Return the shard key declared in the recipe for the table or view called `name`, if any.
+    pub(in crate::controller) fn shard_key(&self, name: &str) -> Option<&ShardKey> {
+        self.shard_keys.iter().find(|k| k.name == name)
+    }
+
+    /// Return the row TTLs declared in the recipe.
+    pub(in crate::controller) fn ttls(&self) -> &[Ttl] {
+        &self.ttls[..]
+    }
+
+    /// Return the row TTL declared in the recipe for the table or view called `name`, if any.
+    pub(in crate::controller) fn ttl(&self, name: &str) -> Option<&Ttl> {
+        self.ttls.iter().find(|t| t.name == name)
+    }
+
     /// Return active aliases for expressions
     fn aliases(&self) -> Vec<&str> {
         self.aliases.keys().map(String::as_str).collect()
@@ -146,6 +620,11 @@ impl Recipe {
             expressions: HashMap::default(),
             expression_order: Vec::default(),
             aliases: HashMap::default(),
+            materializations: HashSet::default(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+            shard_keys: Vec::new(),
+            ttls: Vec::new(),
             version: 0,
             prior: None,
             inc: match log {
@@ -185,6 +664,14 @@ impl Recipe {
         self.inc.as_mut().unwrap().enable_reuse(reuse_type)
     }
 
+    /// Plan the joins of queries added from now on with the given statistics about base tables.
+    pub(in crate::controller) fn set_table_statistics(
+        &mut self,
+        stats: HashMap<String, TableStatistics>,
+    ) {
+        self.inc.as_mut().unwrap().set_table_statistics(stats)
+    }
+
     pub(in crate::controller) fn resolve_alias(&self, alias: &str) -> Option<&str> {
         self.aliases.get(alias).map(|ref qid| {
             let (ref internal_qn, _, _) = self.expressions[qid];
@@ -228,6 +715,53 @@ impl Recipe {
         }
     }
 
+    /// The MIR of the table or view called `query`, or otherwise that of the single query in
+    /// `query`, planned without adding it.
+    ///
+    /// Returned along with it is the name of the table or view the MIR belongs to, if it is
+    /// installed.
+    pub(super) fn plan(&self, query: &str) -> Result<(Option<String>, MirQuery), String> {
+        let inc = self.inc.as_ref().expect("Recipe not applied");
+        let name = self.resolve_alias(query).unwrap_or(query);
+        if let Some(mir) = inc.get_mir_query(name) {
+            return Ok((Some(query.to_owned()), mir.clone()));
+        }
+
+        let mut statements = Recipe::parse_statements(query);
+        if statements.len() != 1 {
+            return Err("expected a single query".to_owned());
+        }
+        let (_, parsed) = statements.pop().unwrap();
+        let (_, parsed) = parsed?;
+        inc.plan_query(parsed)
+    }
+
+    /// Get the query that defines a table or view in the recipe.
+    pub(super) fn query_for(&self, name: &str) -> Option<&SqlQuery> {
+        match self.aliases.get(name) {
+            Some(qid) => Some(&self.expressions[qid].1),
+            None => self
+                .expressions
+                .values()
+                .find(|&&(ref n, _, _)| n.as_deref() == Some(name))
+                .map(|&(_, ref q, _)| q),
+        }
+    }
+
+    /// Get the SQL text of the query that defines a view in the recipe.
+    pub(super) fn sql_for(&self, name: &str) -> Option<String> {
+        match *self.query_for(name)? {
+            SqlQuery::Select(ref q) => Some(q.to_string()),
+            SqlQuery::CompoundSelect(ref q) => Some(q.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Return the recipe's security configuration, if it has one
+    pub(in crate::controller) fn security_config(&self) -> Option<&SecurityConfig> {
+        self.security_config.as_ref()
+    }
+
     /// Set recipe's security configuration
     pub(in crate::controller) fn set_security_config(&mut self, config_text: &str) {
         let mut config = SecurityConfig::parse(config_text);
@@ -239,19 +773,23 @@ impl Recipe {
     /// it.
     // crate viz for tests
     pub(crate) fn from_str(recipe_text: &str, log: Option<slog::Logger>) -> Result<Recipe, String> {
-        // remove comment lines
-        let lines: Vec<String> = recipe_text
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("--"))
-            .map(String::from)
-            .collect();
-        let cleaned_recipe_text = lines.join("\n");
+        Recipe::from_str_with(recipe_text, &HashSet::default(), log)
+    }
 
+    /// Like `from_str`, but also lets queries reuse the materialization points in `known`, which
+    /// were declared by an earlier recipe.
+    fn from_str_with(
+        recipe_text: &str,
+        known: &HashSet<String>,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, String> {
         // parse and compute differences to current recipe
-        let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
+        let (parsed_queries, materializations) =
+            Recipe::parse(&Recipe::strip_comments(recipe_text), known)?;
 
-        Ok(Recipe::from_queries(parsed_queries, log))
+        let mut recipe = Recipe::from_queries(parsed_queries, log);
+        recipe.materializations = materializations;
+        Ok(recipe)
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
@@ -304,6 +842,11 @@ impl Recipe {
             expressions,
             expression_order,
             aliases,
+            materializations: HashSet::default(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+            shard_keys: Vec::new(),
+            ttls: Vec::new(),
             security_config: None,
             version: 0,
             prior: None,
@@ -325,6 +868,7 @@ impl Recipe {
             removed_leaves: Vec::default(),
             expressions_added: 0,
             expressions_removed: 0,
+            pass_through: Vec::default(),
         };
 
         if self.security_config.is_some() {
@@ -344,20 +888,8 @@ impl Recipe {
 
             // add the universe-specific query
             // don't use query name to avoid conflict with global queries
-            let (id, group) = mig.universe();
-            let new_name = if n.is_some() {
-                match group {
-                    Some(ref g) => Some(format!(
-                        "{}_{}{}",
-                        n.clone().unwrap(),
-                        g.to_string(),
-                        id.to_string()
-                    )),
-                    None => Some(format!("{}_u{}", n.clone().unwrap(), id.to_string())),
-                }
-            } else {
-                None
-            };
+            let (_, group) = mig.universe();
+            let new_name = n.as_ref().map(|n| universe_query_name(n, mig));
 
             let is_leaf = if group.is_some() { false } else { is_leaf };
 
@@ -380,6 +912,81 @@ impl Recipe {
         Ok(result)
     }
 
+    /// Stop sharing the row policy views of `tables` between the user universes that are updated
+    /// from now on, since their policies have changed.
+    pub(in crate::controller) fn forget_shared_policies(&mut self, tables: &HashSet<String>) {
+        use crate::controller::sql::security::Multiverse;
+
+        if let Some(ref mut inc) = self.inc {
+            inc.forget_shared_policies(tables);
+        }
+    }
+
+    /// Reinstall the queries of the universe of `mig` that read from any of `tables`, directly or
+    /// through other queries, so that they enforce the current security configuration.
+    ///
+    /// The universe's other queries are left as they are. The leaves of the queries' previous
+    /// versions are returned in `removed_leaves`, and it is the caller's responsibility to remove
+    /// them once the migration has been committed.
+    pub(in crate::controller) fn update_universe(
+        &mut self,
+        mig: &mut Migration,
+        universe_groups: HashMap<String, Vec<DataType>>,
+        tables: &HashSet<String>,
+    ) -> Result<ActivationResult, String> {
+        use crate::controller::sql::security::Multiverse;
+
+        let mut result = ActivationResult {
+            new_nodes: HashMap::default(),
+            removed_leaves: Vec::default(),
+            expressions_added: 0,
+            expressions_removed: 0,
+            pass_through: Vec::default(),
+        };
+
+        let config = match self.security_config {
+            Some(ref config) => config.clone(),
+            None => return Ok(result),
+        };
+        let inc = self.inc.as_mut().unwrap();
+        for qfp in inc.prepare_universe(&config, universe_groups, mig)? {
+            result.new_nodes.insert(qfp.name.clone(), qfp.query_leaf);
+        }
+
+        let (_, group) = mig.universe();
+        let mut affected = tables.clone();
+        for qid in &self.expression_order {
+            let (ref n, ref q, is_leaf) = self.expressions[qid];
+            let name = match (n, q) {
+                (_, SqlQuery::CreateTable(_)) => continue,
+                // unnamed queries get a generated name in each universe, and can't be found again
+                (None, _) => continue,
+                (Some(n), _) => n,
+            };
+            if !affected.iter().any(|t| reads_from(q, t)) {
+                continue;
+            }
+            affected.insert(name.clone());
+
+            let universe_name = universe_query_name(name, mig);
+            if !inc.has_query(&universe_name) {
+                // the query was added after the universe was created
+                continue;
+            }
+            result
+                .removed_leaves
+                .extend(inc.remove_query(&universe_name, mig));
+
+            let is_leaf = if group.is_some() { false } else { is_leaf };
+            let qfp = inc.add_parsed_query(q.clone(), Some(universe_name), is_leaf, mig)?;
+            result.new_nodes.insert(name.clone(), qfp.query_leaf);
+            result.expressions_added += 1;
+            result.expressions_removed += 1;
+        }
+
+        Ok(result)
+    }
+
     /// Activate the recipe by migrating the Soup data-flow graph wrapped in `mig` to the recipe.
     /// This causes all necessary changes to said graph to be applied; however, it is the caller's
     /// responsibility to call `mig.commit()` afterwards.
@@ -402,6 +1009,7 @@ impl Recipe {
             removed_leaves: Vec::default(),
             expressions_added: added.len(),
             expressions_removed: removed.len(),
+            pass_through: Vec::default(),
         };
 
         // upgrade schema version *before* applying changes, so that new queries are correctly
@@ -431,6 +1039,13 @@ impl Recipe {
                     mig,
                 )?;
 
+                // dynamic groups don't have universes of their own; their policies look up the
+                // membership view instead
+                if group.is_dynamic() {
+                    result.new_nodes.insert(group.name(), qfp.query_leaf);
+                    continue;
+                }
+
                 /// Add trigger node below group membership views
                 let group_creation = TriggerEvent::GroupCreation {
                     group: group.name(),
@@ -461,6 +1076,14 @@ impl Recipe {
                 .unwrap()
                 .add_parsed_query(q, n.clone(), is_leaf, mig)?;
 
+            // keep the state of materialization points around in full, so that the queries that
+            // reuse them never have to wait for a replay
+            if let Some(ref name) = n {
+                if self.materializations.contains(name) {
+                    mig.pin(qfp.query_leaf);
+                }
+            }
+
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
             let query_name = match n {
@@ -471,11 +1094,30 @@ impl Recipe {
             result.new_nodes.insert(query_name, qfp.query_leaf);
         }
 
+        // the sharding of new nodes is settled once the migration commits, so they are sharded by
+        // their shard keys here
+        for key in &self.shard_keys {
+            if let Some(&ni) = result.new_nodes.get(&key.name) {
+                mig.shard_by(ni, &key.columns, key.shards, key.scheme)?;
+            }
+        }
+
+        let tables: HashSet<_> = self
+            .expressions
+            .values()
+            .filter_map(|&(_, ref q, _)| match *q {
+                SqlQuery::CreateTable(ref ctq) => Some(ctq.table.name.clone()),
+                _ => None,
+            })
+            .collect();
         result.removed_leaves = removed
             .iter()
             .filter_map(|qid| {
                 let (ref n, ref q, _) = self.prior.as_ref().unwrap().expressions[qid];
                 match q {
+                    // a table that is still defined had its columns altered, which adapted its
+                    // base node in place
+                    SqlQuery::CreateTable(ref ctq) if tables.contains(&ctq.table.name) => None,
                     SqlQuery::CreateTable(ref ctq) => {
                         // a base may have many dependent queries, including ones that also lost
                         // nodes; the code handling `removed_leaves` therefore needs to take care
@@ -494,6 +1136,12 @@ impl Recipe {
                             }
                         }
                     }
+                    // views created with `CREATE VIEW` may go by the name in the statement alone
+                    SqlQuery::CreateView(ref cvq) => self
+                        .inc
+                        .as_mut()
+                        .unwrap()
+                        .remove_query(n.as_ref().unwrap_or(&cvq.name), mig),
                     _ => self
                         .inc
                         .as_mut()
@@ -527,6 +1175,31 @@ impl Recipe {
         (added_queries, removed_queries)
     }
 
+    /// The queries this recipe adds to the one it replaced or extended, and the queries it
+    /// removes from it, each in the order they were added.
+    pub(super) fn delta(&self) -> (QueryList<'_>, QueryList<'_>) {
+        fn expression<'a>(recipe: &'a Recipe, qid: &QueryID) -> (Option<&'a String>, &'a SqlQuery) {
+            let (ref n, ref q, _) = recipe.expressions[qid];
+            (n.as_ref(), q)
+        }
+        match self.prior {
+            None => (
+                self.expression_order
+                    .iter()
+                    .map(|qid| expression(self, qid))
+                    .collect(),
+                Vec::new(),
+            ),
+            Some(ref prior) => {
+                let (added, removed) = self.compute_delta(prior);
+                (
+                    added.iter().map(|qid| expression(self, qid)).collect(),
+                    removed.iter().map(|qid| expression(prior, qid)).collect(),
+                )
+            }
+        }
+    }
+
     /// Returns the query expressions in the recipe.
     // crate viz for tests
     pub(crate) fn expressions(&self) -> Vec<(Option<&String>, &SqlQuery)> {
@@ -537,17 +1210,37 @@ impl Recipe {
     }
 
     /// Append the queries in the `additions` argument to this recipe. This will attempt to parse
-    /// `additions`, and if successful, will extend the recipe. No expressions are removed from the
-    /// recipe; use `replace` if removal of unused expressions is desired.
+    /// `additions`, and if successful, will extend the recipe. Only the tables and views named by
+    /// `DROP TABLE` and `DROP VIEW` statements are removed from the recipe; use `replace` if
+    /// removal of unused expressions is desired.
     /// Consumes `self` and returns a replacement recipe.
     // crate viz for tests
     pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, String)> {
+        // `DROP` statements are applied before the other statements are added, and `ALTER TABLE`
+        // statements once they have been
+        let mut removals = Vec::new();
+        let mut alterations = Vec::new();
+        let additions = Recipe::split_queries(&Recipe::strip_comments(additions))
+            .into_iter()
+            .filter(|q| {
+                if let Some(removal) = Removal::parse(q) {
+                    removals.push(removal);
+                    false
+                } else if let Some(alteration) = Alteration::parse(q) {
+                    alterations.push(alteration);
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
         // parse and compute differences to current recipe
-        let add_rp = match Recipe::from_str(additions, None) {
+        let add_rp = match Recipe::from_str_with(&additions, &self.materializations, None) {
             Ok(rp) => rp,
             Err(e) => return Err((self, e)),
         };
-        let (added, _) = add_rp.compute_delta(&self);
 
         // move the incorporator state from the old recipe to the new one
         let prior_inc = self.inc.take();
@@ -557,6 +1250,11 @@ impl Recipe {
             expressions: self.expressions.clone(),
             expression_order: self.expression_order.clone(),
             aliases: self.aliases.clone(),
+            materializations: self.materializations.clone(),
+            foreign_keys: self.foreign_keys.clone(),
+            checks: self.checks.clone(),
+            shard_keys: self.shard_keys.clone(),
+            ttls: self.ttls.clone(),
             version: self.version + 1,
             inc: prior_inc,
             log: self.log.clone(),
@@ -565,7 +1263,14 @@ impl Recipe {
             prior: Some(Box::new(self)),
         };
 
+        for removal in &removals {
+            if let Err(e) = new.remove(removal) {
+                return Err((new.abandon(), e));
+            }
+        }
+
         // apply changes
+        let (added, _) = add_rp.compute_delta(&new);
         for qid in added {
             let q = add_rp.expressions[&qid].clone();
             new.expressions.insert(qid, q);
@@ -580,11 +1285,459 @@ impl Recipe {
             );
         }
         new.aliases.extend(add_rp.aliases);
+        new.materializations.extend(add_rp.materializations);
+
+        for alteration in &alterations {
+            if let Err(e) = new.alter(alteration) {
+                return Err((new.abandon(), e));
+            }
+        }
 
         // return new recipe as replacement for self
         Ok(new)
     }
 
+    /// Give up on a recipe produced by `extend`, and return the recipe it extended along with the
+    /// incorporator state.
+    fn abandon(mut self) -> Recipe {
+        let mut old = *self.prior.take().unwrap();
+        old.inc = self.inc.take();
+        old
+    }
+
+    /// Remove the table or view named by `removal`, unless other views in the recipe read from
+    /// it.
+    ///
+    /// Activating the recipe then tears down the dataflow nodes of the table or view that no other
+    /// view shares.
+    fn remove(&mut self, removal: &Removal) -> Result<(), String> {
+        let (name, if_exists) = match *removal {
+            Removal::Table {
+                ref name,
+                if_exists,
+            }
+            | Removal::View {
+                ref name,
+                if_exists,
+            } => (name, if_exists),
+        };
+        let (kind, qid) = match *removal {
+            Removal::Table { .. } => (
+                "table",
+                self.expression_order
+                    .iter()
+                    .cloned()
+                    .find(|qid| match self.expressions[qid].1 {
+                        SqlQuery::CreateTable(ref ctq) => ctq.table.name == *name,
+                        _ => false,
+                    }),
+            ),
+            Removal::View { .. } => (
+                "view",
+                self.aliases
+                    .get(name)
+                    .cloned()
+                    .or_else(|| {
+                        self.expression_order.iter().cloned().find(|qid| {
+                            match self.expressions[qid] {
+                                (Some(ref n), _, _) => n == name,
+                                (None, SqlQuery::CreateView(ref cvq), _) => cvq.name == *name,
+                                _ => false,
+                            }
+                        })
+                    })
+                    .filter(|qid| !matches!(self.expressions[qid].1, SqlQuery::CreateTable(_))),
+            ),
+        };
+        let qid = match qid {
+            Some(qid) => qid,
+            None if if_exists => return Ok(()),
+            None => return Err(format!("no {} named {}", kind, name)),
+        };
+
+        // the expression may be known by several names, and other views may use any of them
+        let mut names: Vec<&str> = self
+            .aliases
+            .iter()
+            .filter(|&(_, q)| *q == qid)
+            .map(|(n, _)| n.as_str())
+            .collect();
+        names.push(name);
+        let readers: Vec<_> = self
+            .expression_order
+            .iter()
+            .filter(|q| **q != qid)
+            .filter_map(|q| {
+                let (ref n, ref q, _) = self.expressions[q];
+                if names.iter().any(|name| reads_from(q, name)) {
+                    Some(match (n, q) {
+                        (Some(n), _) => n.clone(),
+                        (None, SqlQuery::CreateView(cvq)) => cvq.name.clone(),
+                        (None, q) => q.to_string(),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if !readers.is_empty() {
+            return Err(format!(
+                "cannot drop {}, which {} read from",
+                name,
+                readers.join(", ")
+            ));
+        }
+
+        if let Removal::Table { .. } = *removal {
+            if let Some(fk) = self
+                .foreign_keys
+                .iter()
+                .find(|fk| fk.table == *name || fk.references == *name)
+            {
+                return Err(format!(
+                    "cannot drop {}, which the foreign key on {}.{} uses",
+                    name, fk.table, fk.column
+                ));
+            }
+        }
+
+        if let Removal::Table { .. } = *removal {
+            self.checks.retain(|c| c.table != *name);
+        }
+        self.shard_keys.retain(|k| k.name != *name);
+        self.ttls.retain(|t| t.name != *name);
+
+        self.expressions.remove(&qid);
+        self.expression_order.retain(|q| *q != qid);
+        self.aliases.retain(|_, q| *q != qid);
+        self.materializations.remove(name);
+        Ok(())
+    }
+
+    /// Apply `alteration` to the `CREATE TABLE` statement of the table it names.
+    ///
+    /// Activating the recipe then adapts the existing base node of the table rather than
+    /// replacing it: rows written before an added column existed read as the column's default,
+    /// and views that read a dropped column keep seeing its default for later writes. Foreign
+    /// keys and check constraints are kept alongside the statement instead, since the parser has
+    /// no place for them.
+    fn alter(&mut self, alteration: &Alteration) -> Result<(), String> {
+        if let Alteration::ShardBy { ref key, view } = *alteration {
+            return self.shard(key, view);
+        }
+        if let Alteration::Ttl { ref ttl, view } = *alteration {
+            return self.expire(ttl, view);
+        }
+        let table = match *alteration {
+            Alteration::AddColumn { ref table, .. } | Alteration::DropColumn { ref table, .. } => {
+                table
+            }
+            Alteration::AddForeignKey(ref fk) => &fk.table,
+            Alteration::AddCheck { ref table, .. } => table,
+            Alteration::ShardBy { .. } | Alteration::Ttl { .. } => unreachable!(),
+        };
+        let (pos, qid, mut ctq) = self
+            .expression_order
+            .iter()
+            .enumerate()
+            .find_map(|(pos, qid)| match self.expressions[qid].1 {
+                SqlQuery::CreateTable(ref ctq) if ctq.table.name == *table => {
+                    Some((pos, *qid, ctq.clone()))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| format!("no table named {}", table))?;
+
+        match *alteration {
+            Alteration::AddColumn { ref definition, .. } => {
+                // the parser only knows column definitions as part of a CREATE TABLE
+                let create = format!("CREATE TABLE {} ({});", table, definition);
+                let field = match sql_parser::parse_query(&create) {
+                    Ok(SqlQuery::CreateTable(mut added))
+                        if added.fields.len() == 1 && added.keys.is_none() =>
+                    {
+                        added.fields.remove(0)
+                    }
+                    _ => return Err(format!("invalid column definition: {}", definition)),
+                };
+                if ctq
+                    .fields
+                    .iter()
+                    .any(|f| f.column.name == field.column.name)
+                {
+                    return Err(format!(
+                        "table {} already has a column named {}",
+                        table, field.column.name
+                    ));
+                }
+                if field
+                    .constraints
+                    .iter()
+                    .any(|c| matches!(*c, ColumnConstraint::PrimaryKey | ColumnConstraint::Unique))
+                {
+                    return Err(format!(
+                        "cannot add key column {} to table {}",
+                        field.column.name, table
+                    ));
+                }
+                ctq.fields.push(field);
+            }
+            Alteration::DropColumn { ref column, .. } => {
+                let i = ctq
+                    .fields
+                    .iter()
+                    .position(|f| f.column.name == *column)
+                    .ok_or_else(|| format!("table {} has no column named {}", table, column))?;
+                let keyed = ctq.fields[i]
+                    .constraints
+                    .iter()
+                    .any(|c| matches!(*c, ColumnConstraint::PrimaryKey))
+                    || ctq.keys.iter().flatten().any(|k| match *k {
+                        TableKey::PrimaryKey(ref cols)
+                        | TableKey::UniqueKey(_, ref cols)
+                        | TableKey::FulltextKey(_, ref cols)
+                        | TableKey::Key(_, ref cols) => cols.iter().any(|c| c.name == *column),
+                    });
+                if keyed {
+                    return Err(format!(
+                        "cannot drop key column {} from table {}",
+                        column, table
+                    ));
+                }
+                if self
+                    .foreign_keys
+                    .iter()
+                    .any(|fk| fk.table == *table && fk.column == *column)
+                {
+                    return Err(format!(
+                        "cannot drop foreign key column {} from table {}",
+                        column, table
+                    ));
+                }
+                if ctq.fields.len() == 1 {
+                    return Err(format!("cannot drop the only column of table {}", table));
+                }
+                if self
+                    .shard_key(table)
+                    .map_or(false, |k| k.columns.contains(column))
+                {
+                    return Err(format!(
+                        "cannot drop column {} from table {}, which it is sharded by",
+                        column, table
+                    ));
+                }
+                if self.ttl(table).map_or(false, |t| t.column == *column) {
+                    return Err(format!(
+                        "cannot drop column {} from table {}, whose rows expire by it",
+                        column, table
+                    ));
+                }
+                ctq.fields.remove(i);
+                let fields: Vec<_> = ctq.fields.iter().map(|f| f.column.name.clone()).collect();
+                if let Some(c) = self
+                    .checks
+                    .iter()
+                    .find(|c| c.table == *table && c.compile(&fields).is_err())
+                {
+                    return Err(format!(
+                        "cannot drop column {} from table {}, which constraint {} checks",
+                        column, table, c.name
+                    ));
+                }
+            }
+            Alteration::AddForeignKey(ref fk) => {
+                if !ctq.fields.iter().any(|f| f.column.name == fk.column) {
+                    return Err(format!("table {} has no column named {}", table, fk.column));
+                }
+                // deletes select the rows to cascade to by condition, which needs a primary key
+                if primary_key(&ctq).is_empty() {
+                    return Err(format!(
+                        "table {} needs a primary key for deletes to cascade to it",
+                        table
+                    ));
+                }
+                let referenced = self
+                    .expressions
+                    .values()
+                    .find_map(|&(_, ref q, _)| match *q {
+                        SqlQuery::CreateTable(ref ctq) if ctq.table.name == fk.references => {
+                            Some(ctq)
+                        }
+                        _ => None,
+                    })
+                    .ok_or_else(|| format!("no table named {}", fk.references))?;
+                if primary_key(referenced) != [fk.referenced_column.as_str()] {
+                    return Err(format!(
+                        "foreign key on {}.{} must refer to the primary key of table {}",
+                        table, fk.column, fk.references
+                    ));
+                }
+                if self
+                    .foreign_keys
+                    .iter()
+                    .any(|k| k.table == fk.table && k.column == fk.column)
+                {
+                    return Err(format!(
+                        "column {} of table {} already has a foreign key",
+                        fk.column, table
+                    ));
+                }
+                self.foreign_keys.push(fk.clone());
+                return Ok(());
+            }
+            Alteration::ShardBy { .. } | Alteration::Ttl { .. } => unreachable!(),
+            Alteration::AddCheck {
+                ref name,
+                ref predicate,
+                ..
+            } => {
+                // unnamed checks are named like MySQL names them
+                let on_table = self.checks.iter().filter(|c| c.table == *table).count();
+                let name = name
+                    .clone()
+                    .unwrap_or_else(|| format!("{}_chk_{}", table, on_table + 1));
+                if self
+                    .checks
+                    .iter()
+                    .any(|c| c.table == *table && c.name == name)
+                {
+                    return Err(format!(
+                        "table {} already has a constraint named {}",
+                        table, name
+                    ));
+                }
+                let check = CheckConstraint {
+                    name,
+                    table: table.clone(),
+                    predicate: predicate.clone(),
+                };
+                let fields: Vec<_> = ctq.fields.iter().map(|f| f.column.name.clone()).collect();
+                check.compile(&fields)?;
+                self.checks.push(check);
+                return Ok(());
+            }
+        }
+
+        // the altered statement takes the place of the original one
+        let altered = SqlQuery::CreateTable(ctq);
+        let altered_qid = hash_query(&altered);
+        let (name, _, public) = self.expressions.remove(&qid).unwrap();
+        self.expressions
+            .insert(altered_qid, (name, altered, public));
+        self.expression_order[pos] = altered_qid;
+        for q in self.aliases.values_mut() {
+            if *q == qid {
+                *q = altered_qid;
+            }
+        }
+        Ok(())
+    }
+
+    /// Declare the shard key of the table or view that `key` names, in place of any it had.
+    ///
+    /// Activating the recipe shards new tables and views by their shard key. Tables and views that
+    /// are already there have to be re-sharded, which the controller takes care of.
+    fn shard(&mut self, key: &ShardKey, view: bool) -> Result<(), String> {
+        let table = self
+            .expressions
+            .values()
+            .find_map(|&(_, ref q, _)| match *q {
+                SqlQuery::CreateTable(ref ctq) if ctq.table.name == key.name => Some(ctq),
+                _ => None,
+            });
+        match table {
+            Some(_) if view => return Err(format!("{} is a table, not a view", key.name)),
+            Some(ctq) => {
+                if let Some(c) = key
+                    .columns
+                    .iter()
+                    .find(|c| !ctq.fields.iter().any(|f| f.column.name == **c))
+                {
+                    return Err(format!("table {} has no column named {}", key.name, c));
+                }
+                let pk = primary_key(ctq);
+                if !pk.is_empty() && !key.columns.iter().all(|c| pk.contains(&c.as_str())) {
+                    return Err(format!(
+                        "the shard key of table {} must be part of its primary key",
+                        key.name
+                    ));
+                }
+            }
+            None if !view => return Err(format!("no table named {}", key.name)),
+            None => {
+                if !self.aliases.contains_key(&key.name) {
+                    return Err(format!("no view named {}", key.name));
+                }
+                // the number of shards of a view follows from the views and tables it reads
+                if key.columns.len() != 1 || key.shards.is_some() {
+                    return Err(format!(
+                        "view {} can only be sharded by a single column",
+                        key.name
+                    ));
+                }
+            }
+        }
+        self.shard_keys.retain(|k| k.name != key.name);
+        self.shard_keys.push(key.clone());
+        Ok(())
+    }
+
+    /// Declare the row TTL of the table or view that `ttl` names, in place of any it had.
+    ///
+    /// The controller tells the base node of the table, or the readers of the view, to retract
+    /// expired rows once the recipe is activated. Whether a view has the column is only known then.
+    fn expire(&mut self, ttl: &Ttl, view: bool) -> Result<(), String> {
+        let table = self
+            .expressions
+            .values()
+            .find_map(|&(_, ref q, _)| match *q {
+                SqlQuery::CreateTable(ref ctq) if ctq.table.name == ttl.name => Some(ctq),
+                _ => None,
+            });
+        match table {
+            Some(_) if view => return Err(format!("{} is a table, not a view", ttl.name)),
+            Some(ctq) => {
+                let field = ctq
+                    .fields
+                    .iter()
+                    .find(|f| f.column.name == ttl.column)
+                    .ok_or_else(|| {
+                        format!("table {} has no column named {}", ttl.name, ttl.column)
+                    })?;
+                match field.sql_type {
+                    SqlType::Timestamp
+                    | SqlType::DateTime(_)
+                    | SqlType::Int(_)
+                    | SqlType::Bigint(_)
+                    | SqlType::UnsignedInt(_)
+                    | SqlType::UnsignedBigint(_) => (),
+                    _ => {
+                        return Err(format!(
+                            "column {} of table {} does not hold timestamps",
+                            ttl.column, ttl.name
+                        ));
+                    }
+                }
+                // expired rows are deleted by key
+                if primary_key(ctq).is_empty() {
+                    return Err(format!(
+                        "table {} needs a primary key for its rows to expire",
+                        ttl.name
+                    ));
+                }
+            }
+            None if !view => return Err(format!("no table named {}", ttl.name)),
+            None => {
+                if !self.aliases.contains_key(&ttl.name) {
+                    return Err(format!("no view named {}", ttl.name));
+                }
+            }
+        }
+        self.ttls.retain(|t| t.name != ttl.name);
+        self.ttls.push(ttl.clone());
+        Ok(())
+    }
+
     /// Helper method to reparent a recipe. This is needed for the recovery logic to build
     /// recovery and original recipe (see `make_recovery`).
     pub(in crate::controller) fn set_prior(&mut self, new_prior: Recipe) {
@@ -601,7 +1754,52 @@ impl Recipe {
         self.inc = Some(new_inc);
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery, bool)>, String> {
+    /// Parses each statement in `recipe_text` on its own, so that a statement that fails to parse
+    /// does not prevent the others from being inspected. Returns the text of each statement along
+    /// with its name and parsed form.
+    pub(super) fn parse_statements(
+        recipe_text: &str,
+    ) -> Vec<(String, Result<(Option<String>, SqlQuery), String>)> {
+        Recipe::split_queries(&Recipe::strip_comments(recipe_text))
+            .into_iter()
+            .flat_map(|q| match query_exprs(&expand_materializations(&q).0) {
+                Err(e) => vec![(q.clone(), Err(format!("parse error: {}", e)))],
+                Ok((remainder, _)) if !remainder.is_empty() => vec![(
+                    q.clone(),
+                    Err(format!(
+                        "failed to parse statement; left with: {}",
+                        remainder
+                    )),
+                )],
+                Ok((_, parsed)) => parsed
+                    .into_iter()
+                    .map(|(_, name, query)| (q.clone(), Ok((name.map(String::from), query))))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Splits the text of a statement into the name it is given in the recipe, if any, and the
+    /// SQL that follows the name. Works even if the SQL itself does not parse.
+    pub(super) fn split_name(statement: &str) -> (Option<String>, &str) {
+        match query_prefix(statement) {
+            Ok((sql, (_, name))) => (name.map(String::from), sql),
+            Err(_) => (None, statement),
+        }
+    }
+
+    /// Removes blank and comment lines from a recipe.
+    fn strip_comments(recipe_text: &str) -> String {
+        let lines: Vec<&str> = recipe_text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("--"))
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Splits a recipe into the text of its statements.
+    fn split_queries(recipe_text: &str) -> Vec<String> {
         let lines: Vec<&str> = recipe_text
             .lines()
             .filter(|l| !l.is_empty() && !l.starts_with('#'))
@@ -631,10 +1829,28 @@ impl Recipe {
             }
             i += 1;
         }
+        query_strings
+    }
 
+    /// Parses the statements in `recipe_text`. Returns them along with the names of the
+    /// materialization points they declare; `known` holds the ones that they may reuse in
+    /// addition to those.
+    fn parse(
+        recipe_text: &str,
+        known: &HashSet<String>,
+    ) -> Result<(Vec<(Option<String>, SqlQuery, bool)>, HashSet<String>), String> {
+        let mut referenced = Vec::new();
+        let query_strings: Vec<_> = Recipe::split_queries(recipe_text)
+            .iter()
+            .map(|q| {
+                let (q, refs) = expand_materializations(q);
+                referenced.extend(refs);
+                q
+            })
+            .collect();
         let parsed_queries = query_strings.iter().fold(
             Vec::new(),
-            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>, q| {
+            |mut acc: Vec<Result<(Exposure, Option<&str>, SqlQuery), String>>, q| {
                 match query_exprs(q) {
                     Result::Err(e) => {
                         // we got a parse error
@@ -656,13 +1872,31 @@ impl Recipe {
             },
         );
 
-        Ok(parsed_queries
+        let mut materializations = HashSet::new();
+        let parsed_queries = parsed_queries
             .into_iter()
             .map(|pr| {
-                let pr = pr.unwrap();
-                (pr.1.map(String::from), pr.2, pr.0)
+                let (exposure, name, query) = pr.unwrap();
+                if exposure == Exposure::Materialized {
+                    match name {
+                        Some(name) if !name.is_empty() => {
+                            materializations.insert(name.to_owned());
+                        }
+                        _ => return Err(String::from("materialization points must be named")),
+                    }
+                }
+                Ok((name.map(String::from), query, exposure == Exposure::Public))
             })
-            .collect::<Vec<_>>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(name) = referenced
+            .iter()
+            .find(|n| !materializations.contains(*n) && !known.contains(*n))
+        {
+            return Err(format!("no materialization point named {}", name));
+        }
+
+        Ok((parsed_queries, materializations))
     }
 
     /// Returns the predecessor from which this `Recipe` was migrated to.
@@ -896,4 +2130,281 @@ mod tests {
         let r1 = r0.replace(r1_t).unwrap();
         assert_eq!(r1.expressions.len(), 2);
     }
+
+    #[test]
+    fn it_reuses_materialization_points() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "MATERIALIZE hot: SELECT a, x FROM b WHERE a = 'x@y';\n\
+                      QUERY q_0: SELECT a FROM @hot WHERE x = 42;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        assert_eq!(r1.expressions.len(), 2);
+        assert!(r1.materializations.contains("hot"));
+        let (_, ref hot, public) = r1.expressions[&r1.aliases["hot"]];
+        assert!(!public);
+        assert_eq!(
+            *hot,
+            sql_parser::parse_query("SELECT a, x FROM b WHERE a = 'x@y';").unwrap()
+        );
+        let (_, ref q0, _) = r1.expressions[&r1.aliases["q_0"]];
+        assert_eq!(
+            *q0,
+            sql_parser::parse_query("SELECT a FROM hot WHERE x = 42;").unwrap()
+        );
+
+        // later recipes can reuse the materialization points of the ones they extend
+        let r2 = r1.extend("QUERY q_1: SELECT x FROM @hot;").unwrap();
+        assert_eq!(r2.expressions.len(), 3);
+    }
+
+    #[test]
+    fn it_alters_tables() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE b (a int, x int, PRIMARY KEY(a));\n\
+                      QUERY q_0: SELECT a FROM b WHERE x = ?;";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2 = r1
+            .extend(
+                "ALTER TABLE b ADD COLUMN c int DEFAULT 1;\n\
+                 alter table b drop x;\n\
+                 QUERY q_1: SELECT a, c FROM b;",
+            )
+            .unwrap();
+        assert_eq!(r2.expressions.len(), 3);
+        // the altered table keeps its place ahead of the queries that read it
+        let (_, ref b, _) = r2.expressions[&r2.expression_order[0]];
+        assert_eq!(
+            *b,
+            sql_parser::parse_query("CREATE TABLE b (a int, c int DEFAULT 1, PRIMARY KEY(a));")
+                .unwrap()
+        );
+
+        // key columns cannot be dropped, and failing leaves the recipe as it was
+        let (r2, _) = r2.extend("ALTER TABLE b DROP COLUMN a;").unwrap_err();
+        assert_eq!(r2.version, 2);
+        assert!(r2.extend("ALTER TABLE nope ADD c int;").is_err());
+    }
+
+    #[test]
+    fn it_adds_foreign_keys() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE u (id int, PRIMARY KEY(id));\n\
+                      CREATE TABLE p (id int, author int, PRIMARY KEY(id));";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2 = r1
+            .extend("ALTER TABLE p ADD FOREIGN KEY (author) REFERENCES u(id) ON DELETE CASCADE;")
+            .unwrap();
+        assert_eq!(
+            r2.foreign_keys(),
+            &[ForeignKey {
+                table: "p".to_owned(),
+                column: "author".to_owned(),
+                references: "u".to_owned(),
+                referenced_column: "id".to_owned(),
+            }]
+        );
+        // the expressions are left as they were
+        assert_eq!(r2.expressions.len(), 2);
+
+        // keys must refer to primary keys, and the tables they join can't go away under them
+        let (r2, _) = r2
+            .extend("ALTER TABLE u ADD FOREIGN KEY (id) REFERENCES p (author) ON DELETE CASCADE;")
+            .unwrap_err();
+        let (r2, _) = r2.extend("ALTER TABLE p DROP COLUMN author;").unwrap_err();
+        let (r2, _) = r2.extend("DROP TABLE u;").unwrap_err();
+        assert_eq!(r2.foreign_keys().len(), 1);
+
+        // only cascading deletes are supported
+        assert_eq!(
+            Alteration::parse("ALTER TABLE p ADD FOREIGN KEY (author) REFERENCES u (id);"),
+            None
+        );
+    }
+
+    #[test]
+    fn it_adds_check_constraints() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE item (id int, price int, discount int, PRIMARY KEY(id));";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2 = r1
+            .extend(
+                "ALTER TABLE item ADD CONSTRAINT sane CHECK (price >= 0 AND discount <= price);\n\
+                 ALTER TABLE item ADD CHECK (discount >= 0);",
+            )
+            .unwrap();
+        let names: Vec<_> = r2.checks().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["sane", "item_chk_2"]);
+        assert_eq!(r2.expressions.len(), 1);
+
+        // names are unique, and checked columns must exist and can't be dropped
+        let (r2, _) = r2
+            .extend("ALTER TABLE item ADD CONSTRAINT sane CHECK (price > 0);")
+            .unwrap_err();
+        let (r2, _) = r2
+            .extend("ALTER TABLE item ADD CHECK (cost > 0);")
+            .unwrap_err();
+        let (r2, _) = r2
+            .extend("ALTER TABLE item DROP COLUMN discount;")
+            .unwrap_err();
+        assert_eq!(r2.checks().len(), 2);
+
+        // dropping the table drops its checks
+        let r3 = r2.extend("DROP TABLE item;").unwrap();
+        assert!(r3.checks().is_empty());
+    }
+
+    #[test]
+    fn it_declares_shard_keys() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE o (region int, id int, total int, PRIMARY KEY(region, id));\n\
+                      QUERY by_id: SELECT id, total FROM o WHERE region = ? AND id = ?;";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2 = r1
+            .extend(
+                "ALTER TABLE o SHARD BY (region, id) INTO 8 SHARDS;\n\
+                 ALTER VIEW by_id SHARD BY (id);",
+            )
+            .unwrap();
+        assert_eq!(
+            r2.shard_key("o"),
+            Some(&ShardKey {
+                name: "o".to_owned(),
+                columns: vec!["region".to_owned(), "id".to_owned()],
+                shards: Some(8),
+                scheme: ShardScheme::Hash,
+            })
+        );
+        assert_eq!(r2.shard_key("by_id").unwrap().columns, ["id"]);
+        assert_eq!(r2.expressions.len(), 2);
+
+        // a later declaration takes the place of the earlier one
+        let r3 = r2.extend("ALTER TABLE o SHARD BY (id);").unwrap();
+        assert_eq!(r3.shard_key("o").unwrap().shards, None);
+
+        // keys can be spread across the shards by other schemes than hashing
+        let r3 = r3
+            .extend(
+                "ALTER TABLE o SHARD BY (id) INTO 4 SHARDS USING CONSISTENT HASHING;\n\
+                 ALTER VIEW by_id SHARD BY (id) USING RANGE (-10, 1000);",
+            )
+            .unwrap();
+        assert_eq!(r3.shard_key("o").unwrap().scheme, ShardScheme::Consistent);
+        assert_eq!(
+            r3.shard_key("by_id").unwrap().scheme,
+            ShardScheme::Range {
+                start: -10,
+                end: 1000
+            }
+        );
+        assert_eq!(
+            Alteration::parse("ALTER TABLE o SHARD BY (id) USING RANGE (5, 5);"),
+            None
+        );
+
+        // shard keys must be part of the primary key, and views have a single one
+        let (r3, _) = r3.extend("ALTER TABLE o SHARD BY (total);").unwrap_err();
+        let (r3, _) = r3
+            .extend("ALTER VIEW by_id SHARD BY (id, total);")
+            .unwrap_err();
+        let (r3, _) = r3.extend("ALTER VIEW o SHARD BY (id);").unwrap_err();
+        let (r3, _) = r3.extend("ALTER TABLE o DROP COLUMN id;").unwrap_err();
+        assert_eq!(
+            Alteration::parse("ALTER TABLE o SHARD BY (id) INTO 0 SHARDS;"),
+            None
+        );
+
+        let r4 = r3.extend("DROP VIEW by_id;").unwrap();
+        assert_eq!(r4.shard_key("by_id"), None);
+        assert!(r4.shard_key("o").is_some());
+    }
+
+    #[test]
+    fn it_declares_row_ttls() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE e (id int, ts timestamp, what text, PRIMARY KEY(id));\n\
+                      CREATE TABLE l (ts timestamp, what text);\n\
+                      QUERY recent: SELECT id, ts FROM e WHERE id = ?;";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        let r2 = r1
+            .extend(
+                "ALTER TABLE e TTL = 7 DAYS ON ts;\n\
+                 ALTER VIEW recent TTL=90 minutes ON ts;",
+            )
+            .unwrap();
+        assert_eq!(
+            r2.ttl("e"),
+            Some(&Ttl {
+                name: "e".to_owned(),
+                column: "ts".to_owned(),
+                ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            })
+        );
+        assert_eq!(r2.ttl("recent").unwrap().ttl, Duration::from_secs(90 * 60));
+        assert_eq!(r2.expressions.len(), 3);
+
+        // a later declaration takes the place of the earlier one
+        let r3 = r2.extend("ALTER TABLE e TTL = 1 HOUR ON ts;").unwrap();
+        assert_eq!(r3.ttls().len(), 2);
+        assert_eq!(r3.ttl("e").unwrap().ttl, Duration::from_secs(60 * 60));
+
+        assert_eq!(Alteration::parse("ALTER TABLE e TTL = 0 DAYS ON ts;"), None);
+        assert_eq!(
+            Alteration::parse("ALTER TABLE e TTL = 7 WEEKS ON ts;"),
+            None
+        );
+        let (r3, _) = r3.extend("ALTER TABLE e TTL = 1 DAY ON what;").unwrap_err();
+        let (r3, _) = r3.extend("ALTER TABLE l TTL = 1 DAY ON ts;").unwrap_err();
+        let (r3, _) = r3.extend("ALTER VIEW e TTL = 1 DAY ON ts;").unwrap_err();
+        let (r3, _) = r3.extend("ALTER TABLE e DROP COLUMN ts;").unwrap_err();
+
+        let r4 = r3.extend("DROP VIEW recent;").unwrap();
+        assert_eq!(r4.ttl("recent"), None);
+        assert!(r4.ttl("e").is_some());
+    }
+
+    #[test]
+    fn it_drops_views_and_tables() {
+        let r0 = Recipe::blank(None);
+        let r1_txt = "CREATE TABLE b (a int, x int);\n\
+                      QUERY q_0: SELECT a, x FROM b WHERE a = ?;\n\
+                      CREATE VIEW q_1 AS SELECT x FROM q_0 WHERE x = 42;";
+        let r1 = r0.replace(Recipe::from_str(r1_txt, None).unwrap()).unwrap();
+
+        // views and tables that other views read from stay
+        let (r1, _) = r1.extend("DROP TABLE b;").unwrap_err();
+        let (r1, _) = r1.extend("DROP VIEW q_0;").unwrap_err();
+        let (r1, _) = r1.extend("DROP VIEW b;").unwrap_err();
+        assert_eq!(r1.expressions.len(), 3);
+
+        let r2 = r1
+            .extend("DROP VIEW q_1;\nDROP VIEW q_0;\nDROP VIEW IF EXISTS q_2;")
+            .unwrap();
+        assert_eq!(r2.expressions.len(), 1);
+        assert!(r2.resolve_alias("q_0").is_none());
+
+        // a dropped view can be defined anew
+        let r3 = r2.extend("QUERY q_0: SELECT a FROM b;").unwrap();
+        assert_eq!(r3.expressions.len(), 2);
+        let r4 = r3.extend("DROP VIEW q_0;\nDROP TABLE b;").unwrap();
+        assert!(r4.expressions.is_empty());
+        assert!(r4.extend("DROP TABLE b;").is_err());
+    }
+
+    #[test]
+    fn it_rejects_unknown_materialization_points() {
+        assert!(Recipe::from_str("QUERY q_0: SELECT a FROM @hot;", None).is_err());
+        // plain views cannot be reused explicitly
+        assert!(Recipe::from_str(
+            "hot: SELECT a FROM b;\nQUERY q_0: SELECT a FROM @hot;",
+            None
+        )
+        .is_err());
+        assert!(Recipe::from_str("MATERIALIZE : SELECT a FROM b;", None).is_err());
+    }
 }
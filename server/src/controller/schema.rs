@@ -1,6 +1,7 @@
 use super::keys::provenance_of;
 use super::recipe::{Recipe, Schema};
 use dataflow::ops;
+use dataflow::ops::project::{BuiltinFunction, ProjectExpression};
 use dataflow::prelude::*;
 use nom_sql::{Column, ColumnSpecification, SqlType};
 
@@ -18,12 +19,16 @@ fn to_sql_type(d: &DataType) -> Option<SqlType> {
         DataType::BigInt(_) => Some(SqlType::Bigint(64)),
         DataType::UnsignedBigInt(_) => Some(SqlType::UnsignedBigint(64)),
         DataType::Real(_, _) => Some(SqlType::Real),
-        DataType::Text(_) => Some(SqlType::Text),
+        // decimals don't know how many digits they may have, so allow as many as MySQL does
+        DataType::Decimal(_, scale) => Some(SqlType::Decimal(65, *scale)),
+        DataType::Text(_) | DataType::Compressed(_) => Some(SqlType::Text),
         DataType::TinyText(_) => Some(SqlType::Varchar(8)),
         // TODO(malte): There is no SqlType for `NULL` (as it's not a
         // type), so caller must handle appropriately.
         DataType::None => None,
         DataType::Timestamp(_) => Some(SqlType::Timestamp),
+        // there is no SQL type for JSON documents, so they are typed as their text
+        DataType::Json(_) => Some(SqlType::Text),
     }
 }
 
@@ -43,16 +48,34 @@ fn type_for_internal_column(
             assert!(column_index >= emits.0.len());
             if column_index < emits.0.len() + emits.2.len() {
                 // computed expression
-                // TODO(malte): trace the actual column types, since this could be a
-                // real-valued arithmetic operation
-                Some(SqlType::Bigint(64))
+                match emits.2[column_index - emits.0.len()] {
+                    ProjectExpression::Call(BuiltinFunction::Date, _)
+                    | ProjectExpression::Call(BuiltinFunction::Now, _)
+                    | ProjectExpression::Call(BuiltinFunction::Timestamp, _)
+                    | ProjectExpression::Call(BuiltinFunction::DateAdd, _)
+                    | ProjectExpression::Call(BuiltinFunction::DateSub, _) => {
+                        Some(SqlType::Timestamp)
+                    }
+                    ProjectExpression::Call(BuiltinFunction::Length, _) => {
+                        Some(SqlType::Bigint(64))
+                    }
+                    // the types of these depend on the values they are given
+                    ProjectExpression::Call(BuiltinFunction::Coalesce, _)
+                    | ProjectExpression::Call(BuiltinFunction::JsonExtractText, _) => None,
+                    ProjectExpression::Call(..) => Some(SqlType::Text),
+                    // TODO(malte): trace the actual column types, since this could be a
+                    // real-valued arithmetic operation
+                    _ => Some(SqlType::Bigint(64)),
+                }
             } else {
                 // literal
                 let off = column_index - (emits.0.len() + emits.2.len());
                 to_sql_type(&emits.1[off])
             }
         }
-        ops::NodeOperator::Sum(_) | ops::NodeOperator::FilterSum(_) => {
+        ops::NodeOperator::Sum(_)
+        | ops::NodeOperator::FilterSum(_)
+        | ops::NodeOperator::DistinctSum(_) => {
             // computed column is always emitted last
             if column_index == node.fields().len() - 1 {
                 // counts and sums always produce integral columns
@@ -80,11 +103,30 @@ fn type_for_internal_column(
                 unreachable!();
             }
         }
-        ops::NodeOperator::Join(_) => {
+        ops::NodeOperator::CustomAggregation(_) => {
+            // user-defined aggregates can produce values of any type, so we don't know it
+            None
+        }
+        ops::NodeOperator::Join(_) | ops::NodeOperator::MultiJoin(_) => {
             // join doesn't "generate" columns, but they may come from one of the other
             // ancestors; so keep iterating to try the other paths
             None
         }
+        ops::NodeOperator::Window(_) => {
+            // row numbers, ranks, and running sums are all integral, and emitted last
+            assert_eq!(column_index, node.fields().len() - 1);
+            Some(SqlType::Bigint(64))
+        }
+        ops::NodeOperator::Session(ref o) => {
+            // the session start is one of the session's timestamps, and emitted last
+            assert_eq!(column_index, node.fields().len() - 1);
+            column_schema(graph, next_node_on_path, recipe, o.timestamp_column(), log)
+                .map(|cs| cs.sql_type)
+        }
+        ops::NodeOperator::ExternalLookup(_) => {
+            // enriched columns come from an external service, so we don't know their type
+            None
+        }
         // no other operators should every generate columns
         _ => unreachable!(),
     }
@@ -1,6 +1,9 @@
-use crate::controller::security::policy::Policy;
+use crate::controller::security::policy::{Policy, RowPolicy};
 use nom_sql::parser as sql_parser;
-use nom_sql::SqlQuery;
+use nom_sql::{
+    ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression, Operator,
+    SqlQuery,
+};
 use serde_json;
 use serde_json::Value;
 
@@ -9,6 +12,9 @@ pub struct Group {
     name: String,
     membership: SqlQuery,
     policies: Vec<Policy>,
+    /// Whether the group's policies are enforced by looking up the members of each group in the
+    /// membership view, instead of by a universe for every group.
+    dynamic: bool,
 }
 
 impl Group {
@@ -24,11 +30,18 @@ impl Group {
                 let name = g["name"].as_str().unwrap();
                 let membership = g["membership"].as_str().unwrap();
                 let policies = format!("{}", g["policies"]);
+                let dynamic = g.get("dynamic").and_then(Value::as_bool).unwrap_or(false);
+
+                let policies = Policy::parse(&policies);
+                if dynamic && policies.iter().any(|p| !p.is_row_policy()) {
+                    panic!("Dynamic group {} can only have row policies", name);
+                }
 
                 Group {
                     name: name.to_string(),
                     membership: sql_parser::parse_query(membership).unwrap(),
-                    policies: Policy::parse(&policies),
+                    policies,
+                    dynamic,
                 }
             })
             .collect()
@@ -45,6 +58,114 @@ impl Group {
     pub fn policies(&self) -> &[Policy] {
         self.policies.as_slice()
     }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// The group's policies as user universes enforce them for dynamic groups.
+    ///
+    /// Every comparison of a column with `GroupContext.id` becomes a check that the column holds
+    /// one of the groups that the membership view currently lists the user in, so that adding or
+    /// removing members changes what the user sees without any migration.
+    pub fn member_policies(&self) -> Vec<Policy> {
+        let (uid, gid) = self.membership_columns();
+        let members = sql_parser::parse_query(&format!(
+            "SELECT {g}.{gid} FROM {g} WHERE {g}.{uid} = UserContext.id;",
+            g = self.name,
+            uid = uid,
+            gid = gid
+        ))
+        .unwrap();
+        let members = match members {
+            SqlQuery::Select(st) => ConditionBase::NestedSelect(Box::new(st)),
+            _ => unreachable!(),
+        };
+
+        self.policies
+            .iter()
+            .map(|p| {
+                let rewrite = |rp: &RowPolicy| {
+                    let mut predicate = rp.predicate.clone();
+                    if let SqlQuery::Select(ref mut st) = predicate {
+                        st.where_clause = st
+                            .where_clause
+                            .take()
+                            .map(|ce| member_of_group(ce, &members));
+                    }
+                    RowPolicy {
+                        name: rp.name.clone(),
+                        table: rp.table.clone(),
+                        predicate,
+                    }
+                };
+                match *p {
+                    Policy::Allow(ref rp) => Policy::Allow(rewrite(rp)),
+                    Policy::Deny(ref rp) => Policy::Deny(rewrite(rp)),
+                    _ => unreachable!("dynamic groups only have row policies"),
+                }
+            })
+            .collect()
+    }
+
+    /// The names of the user id and group id columns of the membership view.
+    fn membership_columns(&self) -> (String, String) {
+        let fields = match self.membership {
+            SqlQuery::Select(ref st) => &st.fields,
+            _ => panic!("Membership of group {} must be a SELECT", self.name),
+        };
+        let name = |f: &FieldDefinitionExpression| match *f {
+            FieldDefinitionExpression::Col(ref c) => {
+                c.alias.clone().unwrap_or_else(|| c.name.clone())
+            }
+            _ => panic!("Membership of group {} must select columns", self.name),
+        };
+        if fields.len() < 2 {
+            panic!(
+                "Membership of group {} must select a user and a group",
+                self.name
+            );
+        }
+        (name(&fields[0]), name(&fields[1]))
+    }
+}
+
+fn is_group_id(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => {
+            c.table.as_ref().map(String::as_str) == Some("GroupContext") && c.name == "id"
+        }
+        _ => false,
+    }
+}
+
+/// Replaces `x = GroupContext.id` (or the reverse) in `ce` with `x IN members`.
+fn member_of_group(ce: ConditionExpression, members: &ConditionBase) -> ConditionExpression {
+    match ce {
+        ConditionExpression::ComparisonOp(ct) if ct.operator == Operator::Equal => {
+            let other = if is_group_id(&ct.right) {
+                ct.left
+            } else if is_group_id(&ct.left) {
+                ct.right
+            } else {
+                return ConditionExpression::ComparisonOp(ct);
+            };
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: other,
+                right: Box::new(ConditionExpression::Base(members.clone())),
+            })
+        }
+        ConditionExpression::LogicalOp(ct) => ConditionExpression::LogicalOp(ConditionTree {
+            operator: ct.operator,
+            left: Box::new(member_of_group(*ct.left, members)),
+            right: Box::new(member_of_group(*ct.right, members)),
+        }),
+        ConditionExpression::Bracketed(inner) => {
+            ConditionExpression::Bracketed(Box::new(member_of_group(*inner, members)))
+        }
+        ce => ce,
+    }
 }
 
 mod tests {
@@ -72,5 +193,38 @@ mod tests {
             groups[0].membership,
             sql_parser::parse_query(membership).unwrap()
         );
+        assert!(!groups[0].is_dynamic());
+    }
+
+    #[test]
+    fn it_looks_up_members_of_dynamic_groups() {
+        use super::*;
+
+        let group_text = r#"
+            [
+                {
+                    "name": "ta",
+                    "membership": "select r_uid as uid, r_cid as gid FROM Role WHERE r_role = 1;",
+                    "dynamic": true,
+                    "policies": [
+                        {
+                            "table": "Post",
+                            "predicate": "WHERE Post.p_private = 1 and GroupContext.id = p_cid"
+                        }
+                    ]
+                }
+            ]"#;
+
+        let groups = Group::parse(group_text);
+        assert!(groups[0].is_dynamic());
+
+        let expected = sql_parser::parse_query(
+            "select * from Post WHERE Post.p_private = 1 and p_cid IN \
+             (SELECT ta.gid FROM ta WHERE ta.uid = UserContext.id);",
+        )
+        .unwrap();
+        let policies = groups[0].member_policies();
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].predicate(), expected);
     }
 }
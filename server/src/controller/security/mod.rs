@@ -1,17 +1,20 @@
 use serde_json;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub mod group;
 pub mod policy;
+pub mod write_policy;
 
 use crate::controller::security::group::Group;
 use crate::controller::security::policy::Policy;
+use crate::controller::security::write_policy::WritePolicyConfig;
 
 #[derive(Clone, Debug)]
 pub struct SecurityConfig {
     pub groups: HashMap<String, Group>,
     policies: Vec<Policy>,
+    write_policies: Vec<WritePolicyConfig>,
 }
 
 impl SecurityConfig {
@@ -30,9 +33,15 @@ impl SecurityConfig {
 
         let policies = Policy::parse(&format!("{}", config["policies"]));
 
+        let write_policies = match config.get("write_policies") {
+            Some(policies) => WritePolicyConfig::parse(&format!("{}", policies)),
+            None => Vec::new(),
+        };
+
         SecurityConfig {
             groups: groups_map,
             policies,
+            write_policies,
         }
     }
 
@@ -40,9 +49,61 @@ impl SecurityConfig {
         self.policies.as_slice()
     }
 
+    /// The policies that user universes enforce: the configuration's own, and those of the
+    /// dynamic groups.
+    pub fn user_policies(&self) -> Vec<Policy> {
+        let mut policies = self.policies.clone();
+        for group in self.groups.values().filter(|g| g.is_dynamic()) {
+            policies.extend(group.member_policies());
+        }
+        policies
+    }
+
+    pub fn write_policies(&self) -> &[WritePolicyConfig] {
+        self.write_policies.as_slice()
+    }
+
     pub fn get_group_policies(&self, group_name: String) -> &[Policy] {
         self.groups[&group_name].policies()
     }
+
+    /// The tables whose read policies differ between this configuration and `new`, keyed by the
+    /// group whose universes enforce them, or by `None` for the policies of user universes.
+    ///
+    /// Returns `None` if groups were added, removed or given a different membership, since the
+    /// universes of their members then have to be created anew.
+    pub fn changed_tables(
+        &self,
+        new: &SecurityConfig,
+    ) -> Option<HashMap<Option<String>, HashSet<String>>> {
+        if self.groups.len() != new.groups.len() {
+            return None;
+        }
+
+        fn diff(old: &[Policy], new: &[Policy]) -> HashSet<String> {
+            let removed = old.iter().filter(|p| !new.contains(p));
+            let added = new.iter().filter(|p| !old.contains(p));
+            removed.chain(added).map(Policy::table).collect()
+        }
+
+        let mut changed = HashMap::new();
+        changed.insert(None, diff(&self.user_policies(), &new.user_policies()));
+        for (name, group) in &self.groups {
+            match new.groups.get(name) {
+                Some(ng)
+                    if ng.membership() == group.membership()
+                        && ng.is_dynamic() == group.is_dynamic() =>
+                {
+                    if !group.is_dynamic() {
+                        let tables = diff(group.policies(), ng.policies());
+                        changed.insert(Some(name.clone()), tables);
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(changed)
+    }
 }
 
 mod tests {
@@ -64,12 +125,58 @@ mod tests {
             "policies": [
                             { "table": "post", "predicate": "WHERE post.type = ?" },
                             { "table": "post", "predicate": "WHERE post.author = ?" }
+                        ],
+            "write_policies": [
+                            { "table": "post", "predicate": "WHERE post.author = Writer.id" }
                         ]
         }"#;
 
         let config = SecurityConfig::parse(config_txt);
 
         assert_eq!(config.policies.len(), 2);
+        assert_eq!(config.write_policies.len(), 1);
         assert_eq!(config.groups.len(), 1);
     }
+
+    #[test]
+    fn it_finds_changed_policies() {
+        use super::*;
+
+        let config = |group_pred: &str, pred: &str| {
+            SecurityConfig::parse(&format!(
+                r#"
+            {{
+                "groups": [
+                    {{
+                        "name": "ta",
+                        "membership": "select uid, cid FROM tas;",
+                        "policies": [ {{ "table": "post", "predicate": "{}" }} ]
+                    }}
+                ],
+                "policies": [
+                    {{ "table": "post", "predicate": "WHERE post.type = ?" }},
+                    {{ "table": "users", "predicate": "{}" }}
+                ]
+            }}"#,
+                group_pred, pred
+            ))
+        };
+
+        let old = config("WHERE post.type = ?", "WHERE users.id = ?");
+        let changed = old
+            .changed_tables(&config("WHERE post.type = ?", "WHERE users.name = ?"))
+            .unwrap();
+        assert_eq!(changed[&None].len(), 1);
+        assert!(changed[&None].contains("users"));
+        assert!(changed[&Some("ta".to_owned())].is_empty());
+
+        let changed = old
+            .changed_tables(&config("WHERE post.author = ?", "WHERE users.id = ?"))
+            .unwrap();
+        assert!(changed[&None].is_empty());
+        assert_eq!(changed[&Some("ta".to_owned())].len(), 1);
+
+        let no_groups = SecurityConfig::parse(r#"{ "policies": [] }"#);
+        assert!(old.changed_tables(&no_groups).is_none());
+    }
 }
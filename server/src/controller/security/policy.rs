@@ -15,6 +15,7 @@ pub enum Policy {
     Rewrite(RewritePolicy),
     Allow(RowPolicy),
     Deny(RowPolicy),
+    Mask(MaskPolicy),
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
@@ -34,12 +35,23 @@ pub struct RewritePolicy {
     pub rewrite_view: SqlQuery,
 }
 
+/// Replaces the values of a column with `value` in the universes the policy applies to, or with
+/// `NULL` if the column is hidden altogether.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
+pub struct MaskPolicy {
+    pub name: String,
+    pub table: String,
+    pub column: String,
+    pub value: Option<String>,
+}
+
 impl Policy {
     pub fn name(&self) -> String {
         match *self {
             Policy::Rewrite(ref p) => p.name.clone(),
             Policy::Allow(ref p) => p.name.clone(),
             Policy::Deny(ref p) => p.name.clone(),
+            Policy::Mask(ref p) => p.name.clone(),
         }
     }
 
@@ -48,6 +60,7 @@ impl Policy {
             Policy::Rewrite(ref p) => p.table.clone(),
             Policy::Allow(ref p) => p.table.clone(),
             Policy::Deny(ref p) => p.table.clone(),
+            Policy::Mask(ref p) => p.table.clone(),
         }
     }
 
@@ -56,6 +69,7 @@ impl Policy {
             Policy::Rewrite(_) => false,
             Policy::Allow(_) => true,
             Policy::Deny(_) => true,
+            Policy::Mask(_) => false,
         }
     }
 
@@ -64,6 +78,7 @@ impl Policy {
             Policy::Rewrite(ref p) => p.rewrite_view.clone(),
             Policy::Allow(ref p) => p.predicate.clone(),
             Policy::Deny(ref p) => p.predicate.clone(),
+            Policy::Mask(_) => panic!("Mask policy doesn't have predicate field"),
         }
     }
 
@@ -72,6 +87,7 @@ impl Policy {
             Policy::Rewrite(ref p) => p.value.clone(),
             Policy::Allow(_) => panic!("Row policy doesn't have value field"),
             Policy::Deny(_) => panic!("Row policy doesn't have value field"),
+            Policy::Mask(_) => panic!("Mask policy doesn't have rewrite value field"),
         }
     }
 
@@ -80,6 +96,7 @@ impl Policy {
             Policy::Rewrite(ref p) => p.column.clone(),
             Policy::Allow(_) => panic!("Row policy doesn't have column field"),
             Policy::Deny(_) => panic!("Row policy doesn't have column field"),
+            Policy::Mask(ref p) => p.column.clone(),
         }
     }
 
@@ -88,6 +105,7 @@ impl Policy {
             Policy::Rewrite(ref p) => p.key.clone(),
             Policy::Allow(_) => panic!("Row policy doesn't have key field"),
             Policy::Deny(_) => panic!("Row policy doesn't have key field"),
+            Policy::Mask(_) => panic!("Mask policy doesn't have key field"),
         }
     }
 
@@ -104,6 +122,7 @@ impl Policy {
                     Some("rewrite") => Policy::parse_rewrite_policy(p),
                     Some("allow") => Policy::parse_row_policy(p, Action::Allow),
                     Some("deny") => Policy::parse_row_policy(p, Action::Deny),
+                    Some("mask") | Some("hide") => Policy::parse_mask_policy(p),
                     _ => panic!("Unsupported policy action {}", action),
                 },
                 None => Policy::parse_row_policy(p, Action::Allow),
@@ -134,6 +153,25 @@ impl Policy {
         }
     }
 
+    fn parse_mask_policy(p: &Value) -> Policy {
+        let name = match p.get("name") {
+            Some(n) => n.as_str().unwrap(),
+            None => "",
+        };
+
+        let table = p["table"].as_str().unwrap();
+        let column = p["column"].as_str().unwrap();
+        // hidden columns have no value
+        let value = p.get("value").and_then(Value::as_str);
+
+        Policy::Mask(MaskPolicy {
+            name: name.to_string(),
+            table: table.to_string(),
+            column: column.to_string(),
+            value: value.map(ToString::to_string),
+        })
+    }
+
     fn parse_rewrite_policy(p: &Value) -> Policy {
         let name = match p.get("name") {
             Some(n) => n.as_str().unwrap(),
@@ -181,4 +219,30 @@ mod tests {
             sql_parser::parse_query(p1).unwrap()
         );
     }
+
+    #[test]
+    fn it_parses_mask_policies() {
+        use super::*;
+
+        let policy_text = r#"[
+            { "action": "mask", "table": "post", "column": "author", "value": "anon" },
+            { "action": "hide", "table": "post", "column": "email" }
+        ]"#;
+
+        let policies = Policy::parse(policy_text);
+
+        assert_eq!(policies.len(), 2);
+        assert!(!policies[0].is_row_policy());
+        match policies[0] {
+            Policy::Mask(ref p) => assert_eq!(p.value.as_ref().map(String::as_str), Some("anon")),
+            _ => unreachable!(),
+        }
+        match policies[1] {
+            Policy::Mask(ref p) => {
+                assert_eq!(p.column, "email");
+                assert_eq!(p.value, None);
+            }
+            _ => unreachable!(),
+        }
+    }
 }
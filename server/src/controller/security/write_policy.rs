@@ -0,0 +1,130 @@
+use crate::controller::conjunction::{Conjunction, Operand};
+use dataflow::node::special::{PolicyValue, WritePolicy};
+use dataflow::prelude::DataType;
+use nom_sql::parser as sql_parser;
+use nom_sql::{Column, ConditionExpression, SqlQuery};
+use serde_json;
+use serde_json::Value;
+
+/// Name under which write policy predicates refer to the identity of the writer.
+const WRITER: &str = "Writer";
+
+/// A policy restricting which rows of a base table a writer may write.
+///
+/// The predicate is a conjunction of comparisons between columns of the table, literals, and
+/// `Writer.id`, e.g., `WHERE post.author = Writer.id AND post.anonymous = 0`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WritePolicyConfig {
+    pub name: String,
+    pub table: String,
+    pub predicate: ConditionExpression,
+}
+
+impl WritePolicyConfig {
+    pub fn parse(policy_text: &str) -> Vec<WritePolicyConfig> {
+        let config: Vec<Value> = match serde_json::from_str(policy_text) {
+            Ok(v) => v,
+            Err(e) => panic!(e.to_string()),
+        };
+
+        config
+            .iter()
+            .map(|p| {
+                let name = match p.get("name") {
+                    Some(n) => n.as_str().unwrap(),
+                    None => "",
+                };
+                let table = p["table"].as_str().unwrap();
+                let pred = p["predicate"].as_str().unwrap();
+
+                let sq =
+                    sql_parser::parse_query(&format!("select * from {} {};", table, pred)).unwrap();
+                let predicate = match sq {
+                    SqlQuery::Select(sq) => sq.where_clause.expect("write policy has no predicate"),
+                    _ => unreachable!(),
+                };
+
+                WritePolicyConfig {
+                    name: name.to_string(),
+                    table: table.to_string(),
+                    predicate,
+                }
+            })
+            .collect()
+    }
+
+    /// Compile the policy into a form that can be checked by the table's base node, whose columns
+    /// are `fields`.
+    pub fn compile(&self, fields: &[String]) -> Result<WritePolicy, String> {
+        let conditions = Conjunction {
+            kind: "write policy",
+            name: &self.name,
+            table: &self.table,
+            fields,
+        }
+        .compile(&self.predicate)?;
+        Ok(WritePolicy::new(&self.name, conditions))
+    }
+}
+
+impl Operand for PolicyValue {
+    fn constant(value: DataType) -> Self {
+        PolicyValue::Constant(value)
+    }
+
+    fn column(index: usize) -> Self {
+        PolicyValue::Column(index)
+    }
+
+    fn named(column: &Column) -> Option<Self> {
+        if column.table.as_ref().map(String::as_str) == Some(WRITER) {
+            Some(PolicyValue::Writer)
+        } else {
+            None
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn it_compiles_write_policies() {
+        use super::*;
+
+        let policy_text = r#"[{ "table": "post", "name": "own_posts",
+                                "predicate": "WHERE post.author = Writer.id AND post.type = 1" }]"#;
+
+        let policies = WritePolicyConfig::parse(policy_text);
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].table, "post");
+
+        let fields = vec!["id".to_owned(), "author".to_owned(), "type".to_owned()];
+        let wp = policies[0].compile(&fields).unwrap();
+        assert_eq!(wp.name(), "own_posts");
+        assert!(wp.permits(&[1.into(), 42.into(), 1.into()], Some(&42.into())));
+        assert!(!wp.permits(&[1.into(), 42.into(), 1.into()], Some(&43.into())));
+        assert!(!wp.permits(&[1.into(), 42.into(), 2.into()], Some(&42.into())));
+        assert!(!wp.permits(&[1.into(), 42.into(), 1.into()], None));
+    }
+
+    #[test]
+    fn it_rejects_unknown_columns() {
+        use super::*;
+
+        let policy_text = r#"[{ "table": "post", "predicate": "WHERE post.owner = Writer.id" }]"#;
+
+        let policies = WritePolicyConfig::parse(policy_text);
+        let fields = vec!["id".to_owned(), "author".to_owned()];
+        assert!(policies[0].compile(&fields).is_err());
+    }
+
+    #[test]
+    fn it_rejects_unsupported_comparisons() {
+        use super::*;
+
+        let policy_text = r#"[{ "table": "post", "predicate": "WHERE post.title LIKE 'mine%'" }]"#;
+
+        let policies = WritePolicyConfig::parse(policy_text);
+        let fields = vec!["id".to_owned(), "title".to_owned()];
+        assert!(policies[0].compile(&fields).is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! A cost model for the order in which a query's joins are performed.
+//!
+//! Each join is charged the number of rows it is estimated to produce, since that is what its
+//! children have to process and what any state below it has to hold. The estimates are the usual
+//! ones: a join of `l` and `r` rows on columns with `dl` and `dr` distinct values produces
+//! `l * r / max(dl, dr)` rows.
+
+use crate::controller::sql::query_graph::{JoinRef, QueryGraph, QueryGraphEdge};
+use nom_sql::{ConditionBase, ConditionExpression, ConditionTree};
+use std::collections::{HashMap, HashSet};
+
+/// Rows assumed for a table we know nothing about.
+const DEFAULT_ROWS: f64 = 1000.0;
+
+/// Queries with at most this many joins have every order of them considered; the joins of larger
+/// ones are ordered greedily.
+const EXHAUSTIVE_JOINS: usize = 6;
+
+/// What the planner knows about the contents of a base table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(in crate::controller) struct TableStatistics {
+    /// The number of rows in the table.
+    pub(in crate::controller) rows: u64,
+    /// The number of distinct values of those of its columns that it has an index on.
+    pub(in crate::controller) distinct: HashMap<String, u64>,
+}
+
+/// The tables a group of already joined relations covers, and how many rows it holds.
+struct Chain {
+    tables: HashSet<String>,
+    rows: f64,
+}
+
+fn rows_of(stats: &HashMap<String, TableStatistics>, table: &str) -> f64 {
+    stats
+        .get(table)
+        .map(|t| t.rows as f64)
+        .unwrap_or(DEFAULT_ROWS)
+}
+
+/// The number of distinct values of `column` in `table`, assuming that columns without statistics
+/// are keys.
+fn distinct_of(stats: &HashMap<String, TableStatistics>, table: &str, column: &str) -> f64 {
+    stats
+        .get(table)
+        .and_then(|t| t.distinct.get(column))
+        .map(|&d| d as f64)
+        .unwrap_or_else(|| rows_of(stats, table))
+        .max(1.0)
+}
+
+fn predicate<'a>(qg: &'a QueryGraph, jref: &JoinRef) -> &'a ConditionTree {
+    match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
+        QueryGraphEdge::Join(ref jps)
+        | QueryGraphEdge::LeftJoin(ref jps)
+        | QueryGraphEdge::AntiJoin(ref jps) => &jps[jref.index],
+        QueryGraphEdge::GroupBy(_) => unreachable!(),
+    }
+}
+
+/// How many distinct values the join `jref` matches rows on.
+fn join_distinct(qg: &QueryGraph, jref: &JoinRef, stats: &HashMap<String, TableStatistics>) -> f64 {
+    let jp = predicate(qg, jref);
+    let distinct = |side: &ConditionExpression, rel: &str| match *side {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => {
+            distinct_of(stats, c.table.as_deref().unwrap_or(rel), &c.name)
+        }
+        _ => rows_of(stats, rel),
+    };
+    distinct(&jp.left, &jref.src).max(distinct(&jp.right, &jref.dst))
+}
+
+/// The estimated number of rows each of the joins of `qg` produces when they are performed in
+/// `order`.
+fn join_rows(
+    qg: &QueryGraph,
+    order: &[JoinRef],
+    stats: &HashMap<String, TableStatistics>,
+) -> Vec<f64> {
+    let mut chains: Vec<Chain> = Vec::new();
+    let take = |chains: &mut Vec<Chain>, table: &str| match chains
+        .iter()
+        .position(|c| c.tables.contains(table))
+    {
+        Some(i) => chains.swap_remove(i),
+        None => Chain {
+            tables: std::iter::once(table.to_owned()).collect(),
+            rows: rows_of(stats, table),
+        },
+    };
+
+    let mut rows = Vec::with_capacity(order.len());
+    for jref in order {
+        let distinct = join_distinct(qg, jref, stats);
+        let left = take(&mut chains, &jref.src);
+        let chain = if left.tables.contains(&jref.dst) {
+            // both sides are already joined, so the predicate only filters
+            Chain {
+                rows: left.rows / distinct,
+                ..left
+            }
+        } else {
+            let right = take(&mut chains, &jref.dst);
+            Chain {
+                rows: left.rows * right.rows / distinct,
+                tables: left.tables.union(&right.tables).cloned().collect(),
+            }
+        };
+        rows.push(chain.rows);
+        chains.push(chain);
+    }
+    rows
+}
+
+/// The estimated cost of performing the joins of `qg` in `order`, not counting the first `free`
+/// of them, which already exist.
+pub(super) fn join_cost(
+    qg: &QueryGraph,
+    order: &[JoinRef],
+    stats: &HashMap<String, TableStatistics>,
+    free: usize,
+) -> f64 {
+    join_rows(qg, order, stats).into_iter().skip(free).sum()
+}
+
+fn inner_joins_only(qg: &QueryGraph) -> bool {
+    qg.join_order.iter().all(
+        |jref| match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
+            QueryGraphEdge::Join(_) => true,
+            _ => false,
+        },
+    )
+}
+
+/// Whether the joins of `qg` should be performed by a single multi-way join rather than by a
+/// chain of binary ones.
+///
+/// A chain of binary joins keeps the result of every join but the last, so that the next one can
+/// look into it. A multi-way join keeps nothing of its own and looks into the tables it joins
+/// instead, but needs an index on each column that a table is joined on, where a binary join only
+/// needs one on the column of the table that it joins in. The multi-way join is picked when those
+/// additional indexes are estimated to hold no more rows than the intermediate results, as is the
+/// case when a large table is joined with several smaller ones. Only trees of at least two inner
+/// joins, each on a single pair of columns, qualify.
+pub(super) fn multiway_join(qg: &QueryGraph, stats: &HashMap<String, TableStatistics>) -> bool {
+    if stats.is_empty() || qg.join_order.len() < 2 || !inner_joins_only(qg) {
+        return false;
+    }
+
+    // every join must bring in exactly one new relation, so that the joins form a tree that grows
+    // from the first one
+    let mut tables: HashMap<&str, HashSet<&str>> = HashMap::new();
+    tables.insert(&qg.join_order[0].src, HashSet::new());
+    for jref in &qg.join_order {
+        let (src, dst) = (&jref.src[..], &jref.dst[..]);
+        if tables.contains_key(src) == tables.contains_key(dst) {
+            return false;
+        }
+        let jp = predicate(qg, jref);
+        for &(side, rel) in &[(&jp.left, src), (&jp.right, dst)] {
+            let column = match **side {
+                ConditionExpression::Base(ConditionBase::Field(ref c)) => &c.name[..],
+                _ => return false,
+            };
+            tables.entry(rel).or_default().insert(column);
+        }
+    }
+    let relations = qg
+        .relations
+        .keys()
+        .filter(|r| *r != "computed_columns")
+        .count();
+    if relations != tables.len() {
+        return false;
+    }
+
+    let rows = join_rows(qg, &qg.join_order, stats);
+    let intermediate: f64 = rows[..rows.len() - 1].iter().sum();
+    let indexed: f64 = tables
+        .iter()
+        .map(|(t, columns)| (columns.len() - 1) as f64 * rows_of(stats, t))
+        .sum();
+    indexed <= intermediate
+}
+
+/// Calls `f` with every order of `items`, starting with the one they are in.
+fn permutations<T>(items: &mut [T], k: usize, f: &mut dyn FnMut(&[T])) {
+    if k == items.len() {
+        f(items);
+        return;
+    }
+    for i in k..items.len() {
+        items.swap(k, i);
+        permutations(items, k + 1, f);
+        items.swap(k, i);
+    }
+}
+
+/// The cheapest order in which to perform the joins of `qg`, or `None` if they should stay in the
+/// order they are in.
+///
+/// Only queries whose joins are all inner joins are reordered, since moving an outer join or an
+/// anti-join past another join can change what the query returns. Without any statistics, there
+/// is nothing to go by, and the order is kept as well.
+pub(super) fn best_join_order(
+    qg: &QueryGraph,
+    stats: &HashMap<String, TableStatistics>,
+) -> Option<Vec<JoinRef>> {
+    if stats.is_empty() || qg.join_order.len() < 2 || !inner_joins_only(qg) {
+        return None;
+    }
+
+    let mut best = qg.join_order.clone();
+    let mut best_cost = join_cost(qg, &best, stats, 0);
+    if qg.join_order.len() <= EXHAUSTIVE_JOINS {
+        // ties go to the order found first, which keeps the current order if nothing is better
+        let mut order = qg.join_order.clone();
+        permutations(&mut order, 0, &mut |order| {
+            let cost = join_cost(qg, order, stats, 0);
+            if cost < best_cost {
+                best_cost = cost;
+                best = order.to_vec();
+            }
+        });
+    } else {
+        let mut order = Vec::new();
+        let mut left = qg.join_order.clone();
+        while !left.is_empty() {
+            let (i, _) = left
+                .iter()
+                .enumerate()
+                .map(|(i, jref)| {
+                    order.push(jref.clone());
+                    let cost = join_cost(qg, &order, stats, 0);
+                    order.pop();
+                    (i, cost)
+                })
+                .fold((0, std::f64::INFINITY), |best, next| {
+                    if next.1 < best.1 {
+                        next
+                    } else {
+                        best
+                    }
+                });
+            order.push(left.remove(i));
+        }
+        if join_cost(qg, &order, stats, 0) < best_cost {
+            best = order;
+        }
+    }
+
+    if best == qg.join_order {
+        None
+    } else {
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::sql::query_graph::to_query_graph;
+    use nom_sql::SqlQuery;
+
+    fn query_graph(sql: &str) -> QueryGraph {
+        match nom_sql::parse_query(sql).unwrap() {
+            SqlQuery::Select(ref st) => to_query_graph(st).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn table(rows: u64, distinct: &[(&str, u64)]) -> TableStatistics {
+        TableStatistics {
+            rows,
+            distinct: distinct.iter().map(|&(c, d)| (c.to_owned(), d)).collect(),
+        }
+    }
+
+    #[test]
+    fn selective_joins_go_first() {
+        let qg =
+            query_graph("SELECT a.x, c.z FROM a, b, c WHERE a.x = b.x AND b.y = c.y AND a.x = ?;");
+        assert!(best_join_order(&qg, &HashMap::new()).is_none());
+
+        let mut stats = HashMap::new();
+        stats.insert("a".to_owned(), table(10, &[("x", 10)]));
+        stats.insert("b".to_owned(), table(1000, &[("x", 1000), ("y", 10)]));
+        stats.insert("c".to_owned(), table(1000, &[("y", 10)]));
+
+        let order = best_join_order(&qg, &stats).unwrap();
+        assert_eq!((&order[0].src[..], &order[0].dst[..]), ("a", "b"));
+        assert_eq!((&order[1].src[..], &order[1].dst[..]), ("b", "c"));
+        assert!(join_cost(&qg, &order, &stats, 0) < join_cost(&qg, &qg.join_order, &stats, 0));
+
+        // joins that already exist cost nothing
+        assert_eq!(join_cost(&qg, &order, &stats, 2), 0.0);
+
+        // the intermediate result is tiny, so the joins stay binary
+        assert!(!multiway_join(&qg, &stats));
+    }
+
+    #[test]
+    fn star_joins_are_multiway() {
+        let qg = query_graph(
+            "SELECT products.name, stores.city, sales.amount FROM sales, products, stores \
+             WHERE sales.product = products.id AND sales.store = stores.id;",
+        );
+        assert!(!multiway_join(&qg, &HashMap::new()));
+
+        let mut stats = HashMap::new();
+        stats.insert(
+            "sales".to_owned(),
+            table(100_000, &[("product", 100), ("store", 10)]),
+        );
+        stats.insert("products".to_owned(), table(100, &[("id", 100)]));
+        stats.insert("stores".to_owned(), table(10, &[("id", 10)]));
+        assert!(multiway_join(&qg, &stats));
+
+        // joins that form a cycle don't make a tree
+        let qg = query_graph(
+            "SELECT sales.amount FROM sales, products, stores WHERE sales.product = products.id \
+             AND sales.store = stores.id AND products.id = stores.id;",
+        );
+        assert!(!multiway_join(&qg, &stats));
+    }
+}
@@ -13,8 +13,13 @@ fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column
     use nom_sql::FunctionExpression::*;
 
     match *computed_col.function.as_ref().unwrap().deref() {
+        // the column a `COUNT(*)` was rewritten to count over is marked as such, but is otherwise
+        // an ordinary column
+        Count(FunctionArguments::Column(ref col), _) => Column {
+            function: None,
+            ..Column::from(col)
+        },
         Avg(FunctionArguments::Column(ref col), _)
-        | Count(FunctionArguments::Column(ref col), _)
         | Count(
             FunctionArguments::Conditional(CaseWhenExpression {
                 then_expr: ColumnOrLiteral::Column(ref col),
@@ -109,7 +114,9 @@ pub(super) fn make_grouped(
                 .edges
                 .values()
                 .filter(|e| match **e {
-                    QueryGraphEdge::Join(_) | QueryGraphEdge::LeftJoin(_) => false,
+                    QueryGraphEdge::Join(_)
+                    | QueryGraphEdge::LeftJoin(_)
+                    | QueryGraphEdge::AntiJoin(_) => false,
                     QueryGraphEdge::GroupBy(_) => true,
                 })
                 .collect();
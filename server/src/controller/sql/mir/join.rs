@@ -1,9 +1,10 @@
 use crate::controller::sql::mir::SqlToMirConverter;
 use crate::controller::sql::query_graph::{JoinRef, QueryGraph, QueryGraphEdge};
 use dataflow::ops::join::JoinType;
-use mir::MirNodeRef;
-use nom_sql::ConditionTree;
+use mir::{Column, MirNodeRef};
+use nom_sql::{ConditionBase, ConditionExpression, ConditionTree};
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 struct JoinChain {
     tables: HashSet<String>,
@@ -30,6 +31,8 @@ impl JoinChain {
 // If a predicate's parent tables haven't been used by any previous predicate,
 // a new join chain is started for the current predicate. And we assume that
 // a future predicate will bring these chains together.
+// Joins are on a single column, so a predicate between two tables that are
+// already in the same chain filters the chain's rows instead.
 pub(super) fn make_joins(
     mir_converter: &SqlToMirConverter,
     name: &str,
@@ -37,22 +40,58 @@ pub(super) fn make_joins(
     node_for_rel: &HashMap<&str, MirNodeRef>,
     node_count: usize,
 ) -> Vec<MirNodeRef> {
+    if qg.multiway_join {
+        if let Some(jn) = make_multiway_join(
+            mir_converter,
+            &format!("{}_n{}", name, node_count),
+            qg,
+            node_for_rel,
+        ) {
+            return vec![jn];
+        }
+    }
+
     let mut join_nodes: Vec<MirNodeRef> = Vec::new();
     let mut join_chains = Vec::new();
     let mut node_count = node_count;
 
     for jref in qg.join_order.iter() {
         let (join_type, jp) = from_join_ref(jref, &qg);
+        if join_type == Some(JoinType::Inner) {
+            if let Some(chain) = join_chains
+                .iter_mut()
+                .find(|chain| chain.has_table(&jref.src) && chain.has_table(&jref.dst))
+            {
+                let f = mir_converter.make_filter_node(
+                    &format!("{}_n{}", name, node_count),
+                    chain.last_node.clone(),
+                    jp,
+                );
+                chain.last_node = f.clone();
+                node_count += 1;
+                join_nodes.push(f);
+                continue;
+            }
+        }
+
         let (left_chain, right_chain) =
             pick_join_chains(&jref.src, &jref.dst, &mut join_chains, node_for_rel);
 
-        let jn = mir_converter.make_join_node(
-            &format!("{}_n{}", name, node_count),
-            jp,
-            left_chain.last_node.clone(),
-            right_chain.last_node.clone(),
-            join_type,
-        );
+        let jn = match join_type {
+            Some(join_type) => mir_converter.make_join_node(
+                &format!("{}_n{}", name, node_count),
+                jp,
+                left_chain.last_node.clone(),
+                right_chain.last_node.clone(),
+                join_type,
+            ),
+            None => mir_converter.make_antijoin_node(
+                &format!("{}_n{}", name, node_count),
+                jp,
+                left_chain.last_node.clone(),
+                right_chain.last_node.clone(),
+            ),
+        };
 
         // merge node chains
         let new_chain = left_chain.merge_chain(right_chain, jn.clone());
@@ -66,10 +105,54 @@ pub(super) fn make_joins(
     join_nodes
 }
 
-fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> (JoinType, &'a ConditionTree) {
+// Performs all the joins of the query in a single multi-way join. The planner only picks one for
+// trees of inner joins on single columns, where every join brings in a new relation; `None` if
+// the relations don't map to distinct nodes to join.
+fn make_multiway_join(
+    mir_converter: &SqlToMirConverter,
+    name: &str,
+    qg: &QueryGraph,
+    node_for_rel: &HashMap<&str, MirNodeRef>,
+) -> Option<MirNodeRef> {
+    let field = |e: &ConditionExpression| match *e {
+        ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
+        _ => unimplemented!(),
+    };
+
+    let mut rels: Vec<&str> = vec![&qg.join_order[0].src[..]];
+    let mut on = Vec::new();
+    for jref in qg.join_order.iter() {
+        let (_, jp) = from_join_ref(jref, qg);
+        let (l_col, r_col) = (field(&jp.left), field(&jp.right));
+        let (known, new, known_col, new_col) = match rels.iter().position(|&r| r == jref.src) {
+            Some(i) => (i, &jref.dst, l_col, r_col),
+            None => (
+                rels.iter().position(|&r| r == jref.dst)?,
+                &jref.src,
+                r_col,
+                l_col,
+            ),
+        };
+        rels.push(new);
+        on.push((known, known_col, new_col));
+    }
+
+    let parents: Vec<MirNodeRef> = rels.iter().map(|r| node_for_rel[r].clone()).collect();
+    for (i, p) in parents.iter().enumerate() {
+        if parents[..i].iter().any(|q| Rc::ptr_eq(p, q)) {
+            return None;
+        }
+    }
+
+    Some(mir_converter.make_multi_join_node(name, parents, on))
+}
+
+// Returns the kind of join for a join reference, or `None` for an anti-join.
+fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> (Option<JoinType>, &'a ConditionTree) {
     match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
-        QueryGraphEdge::Join(ref jps) => (JoinType::Inner, &jps[jref.index]),
-        QueryGraphEdge::LeftJoin(ref jps) => (JoinType::Left, &jps[jref.index]),
+        QueryGraphEdge::Join(ref jps) => (Some(JoinType::Inner), &jps[jref.index]),
+        QueryGraphEdge::LeftJoin(ref jps) => (Some(JoinType::Left), &jps[jref.index]),
+        QueryGraphEdge::AntiJoin(ref jps) => (None, &jps[jref.index]),
         QueryGraphEdge::GroupBy(_) => unreachable!(),
     }
 }
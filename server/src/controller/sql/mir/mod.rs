@@ -1,6 +1,6 @@
 use mir::node::{GroupedNodeType, MirNode, MirNodeType};
 use mir::query::MirQuery;
-use mir::{Column, MirNodeRef};
+use mir::{Column, Expression, MirNodeRef};
 use noria::DataType;
 use petgraph::graph::NodeIndex;
 // TODO(malte): remove if possible
@@ -10,9 +10,8 @@ use dataflow::ops::join::JoinType;
 use crate::controller::sql::query_graph::{OutputColumn, QueryGraph};
 use crate::controller::sql::query_signature::Signature;
 use nom_sql::{
-    ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
-    CompoundSelectOperator, ConditionBase, ConditionExpression, ConditionTree, Literal, Operator,
-    SqlQuery, TableKey,
+    CaseWhenExpression, ColumnOrLiteral, ColumnSpecification, CompoundSelectOperator,
+    ConditionBase, ConditionExpression, ConditionTree, Literal, Operator, SqlQuery, TableKey,
 };
 use nom_sql::{LimitClause, OrderClause, SelectStatement};
 
@@ -206,6 +205,15 @@ impl SqlToMirConverter {
     ) -> Vec<(usize, FilterCondition)> {
         use std::cmp::max;
 
+        // joined relations may have columns of the same name, so prefer the column of the
+        // right table if there is one
+        let position = |columns: &[Column], f: &nom_sql::Column| {
+            columns
+                .iter()
+                .rposition(|c| c.name == f.name && f.table.is_some() && c.table == f.table)
+                .or_else(|| columns.iter().rposition(|c| *c.name == f.name))
+        };
+
         // TODO(malte): we only support one level of condition nesting at this point :(
         let l = match *ct.left.as_ref() {
             ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
@@ -238,7 +246,7 @@ impl SqlToMirConverter {
                 // NOTE(jon): the uwnrap here is almost certainly wrong given the business
                 // that goes on further down where it appens a column in magical circumstances.
                 // also, what if two columns share a name, but differ in .table?
-                let fi = columns.iter().rposition(|c| *c.name == f.name).unwrap();
+                let fi = position(&columns[..], f).unwrap();
                 FilterCondition::Comparison(ct.operator.clone(), filter::Value::Column(fi))
             }
             _ => unimplemented!(),
@@ -252,13 +260,16 @@ impl SqlToMirConverter {
         let num_columns = max(columns.len(), max_column_id + 1);
         let mut filters = Vec::new();
 
-        match columns.iter().rposition(|c| *c.name == l.name) {
+        match position(&columns[..], &l) {
             None => {
                 // Might occur if the column doesn't exist in the parent; e.g., for aggregations.
                 // We assume that the column is appended at the end, unless we have an aggregation,
                 // in which case it needs to go before the computed column, which is last.
                 match n.borrow().inner {
-                    MirNodeType::Aggregation { .. } => {
+                    MirNodeType::Aggregation { .. }
+                    | MirNodeType::DistinctAggregation { .. }
+                    | MirNodeType::FilterAggregation { .. }
+                    | MirNodeType::CustomAggregation { .. } => {
                         columns.insert(columns.len() - 1, Column::from(l));
                         filters.push((num_columns - 1, f));
                     }
@@ -282,6 +293,7 @@ impl SqlToMirConverter {
         prior_leaf: MirNodeRef,
         name: &str,
         params: &[Column],
+        range: Option<Operator>,
         project_columns: Option<Vec<Column>>,
     ) -> MirQuery {
         // hang off the previous logical leaf node
@@ -304,7 +316,7 @@ impl SqlToMirConverter {
                 MirNodeType::Project {
                     emit: columns.clone(),
                     literals: vec![],
-                    arithmetic: vec![],
+                    expressions: vec![],
                 },
                 vec![parent.clone()],
                 vec![],
@@ -335,6 +347,8 @@ impl SqlToMirConverter {
             MirNodeType::Leaf {
                 node: parent.clone(),
                 keys: Vec::from(params),
+                range,
+                order: None,
             },
             vec![n],
             vec![],
@@ -362,23 +376,38 @@ impl SqlToMirConverter {
         limit: &Option<LimitClause>,
         has_leaf: bool,
     ) -> MirQuery {
-        let union_name = if !has_leaf && limit.is_none() {
+        let distinct = match op {
+            CompoundSelectOperator::Union => false,
+            CompoundSelectOperator::DistinctUnion => true,
+            _ => unimplemented!(),
+        };
+        let union_name = if !distinct && !has_leaf && limit.is_none() {
             String::from(name)
         } else {
             format!("{}_union", name)
         };
-        let mut final_node = match op {
-            CompoundSelectOperator::Union => self.make_union_node(
-                &union_name,
-                &sqs.iter().map(|mq| mq.leaf.clone()).collect::<Vec<_>>()[..],
-            ),
-            _ => unimplemented!(),
-        };
+        let mut final_node = self.make_compound_union_node(
+            &union_name,
+            &sqs.iter().map(|mq| mq.leaf.clone()).collect::<Vec<_>>()[..],
+        );
         let node_id = (union_name, self.schema_version);
         self.nodes
             .entry(node_id)
             .or_insert_with(|| final_node.clone());
 
+        if distinct {
+            let distinct_name = if !has_leaf && limit.is_none() {
+                String::from(name)
+            } else {
+                format!("{}_distinct", name)
+            };
+            for node in self.make_union_distinct_nodes(&distinct_name, final_node) {
+                let node_id = (String::from(node.borrow().name()), self.schema_version);
+                self.nodes.entry(node_id).or_insert_with(|| node.clone());
+                final_node = node;
+            }
+        }
+
         // we use these columns for intermediate nodes
         let columns: Vec<Column> = final_node.borrow().columns().to_vec();
         // we use these columns for whichever node ends up being the leaf
@@ -392,6 +421,8 @@ impl SqlToMirConverter {
             .collect();
 
         if limit.is_some() {
+            // the leaf of a compound query doesn't order its rows, so it can't skip any
+            assert_eq!(limit.as_ref().unwrap().offset, 0);
             let (topk_name, topk_columns) = if !has_leaf {
                 (String::from(name), sanitized_columns.iter().collect())
             } else {
@@ -419,6 +450,8 @@ impl SqlToMirConverter {
                 MirNodeType::Leaf {
                     node: final_node.clone(),
                     keys: vec![],
+                    range: None,
+                    order: None,
                 },
                 vec![final_node.clone()],
                 vec![],
@@ -721,6 +754,19 @@ impl SqlToMirConverter {
         };
         assert!(primary_keys.len() <= 1);
 
+        // any other keys become secondary indexes on the base. uniqueness is not enforced, and
+        // full-text keys get no special treatment.
+        let indexes: Vec<Vec<Column>> = keys
+            .into_iter()
+            .flatten()
+            .filter_map(|k| match *k {
+                TableKey::PrimaryKey(..) => None,
+                TableKey::UniqueKey(_, ref cols)
+                | TableKey::FulltextKey(_, ref cols)
+                | TableKey::Key(_, ref cols) => Some(cols.iter().map(Column::from).collect()),
+            })
+            .collect();
+
         // remember the schema for this version
         let base_schemas = self.base_schemas.entry(String::from(name)).or_default();
         base_schemas.push((self.schema_version, cols.to_vec()));
@@ -747,6 +793,7 @@ impl SqlToMirConverter {
                             column_specs: cols.iter().map(|cs| (cs.clone(), None)).collect(),
                             keys: key_cols.iter().map(Column::from).collect(),
                             adapted_over: None,
+                            indexes,
                         },
                         vec![],
                         vec![],
@@ -763,6 +810,7 @@ impl SqlToMirConverter {
                     column_specs: cols.iter().map(|cs| (cs.clone(), None)).collect(),
                     keys: vec![],
                     adapted_over: None,
+                    indexes,
                 },
                 vec![],
                 vec![],
@@ -770,6 +818,80 @@ impl SqlToMirConverter {
         }
     }
 
+    /// Unions the leaves of the queries in a compound `SELECT`.
+    ///
+    /// Unlike `make_union_node`, this matches the columns of the ancestors by position, as SQL
+    /// does; the union takes its column names from the first ancestor.
+    fn make_compound_union_node(&self, name: &str, ancestors: &[MirNodeRef]) -> MirNodeRef {
+        assert!(ancestors.len() > 1, "union must have more than 1 ancestors");
+
+        let emit: Vec<Vec<Column>> = ancestors
+            .iter()
+            .map(|a| a.borrow().columns().to_vec())
+            .collect();
+        assert!(
+            emit.iter().all(|e| e.len() == emit[0].len()),
+            "all ancestors columns must have the same size, but got emit: {:?}",
+            emit
+        );
+
+        MirNode::new(
+            name,
+            self.schema_version,
+            emit.first().unwrap().clone(),
+            MirNodeType::Union { emit },
+            ancestors.to_vec(),
+            vec![],
+        )
+    }
+
+    /// Removes the duplicate records of a union, as a `UNION` without `ALL` requires.
+    ///
+    /// The union's records are counted, so that a record is only removed from the output once its
+    /// last copy is gone. Returns the count, a filter for records that still have copies, and a
+    /// projection that drops the count, in that order.
+    fn make_union_distinct_nodes(&self, name: &str, parent: MirNodeRef) -> Vec<MirNodeRef> {
+        use dataflow::ops::grouped::aggregate::Aggregation;
+
+        let columns: Vec<Column> = parent.borrow().columns().to_vec();
+        let occurrences = nom_sql::Column {
+            name: String::from("occurrences"),
+            alias: None,
+            table: None,
+            function: None,
+        };
+
+        let count = self.make_grouped_node(
+            &format!("{}_count", name),
+            &Column::from(&occurrences),
+            (parent, &columns[0], None),
+            columns.iter().collect(),
+            GroupedNodeType::Aggregation(Aggregation::COUNT),
+            None,
+        );
+        let filter = self.make_filter_node(
+            &format!("{}_f", name),
+            count.clone(),
+            &ConditionTree {
+                operator: Operator::Greater,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field(occurrences))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                    Literal::Integer(0),
+                ))),
+            },
+        );
+        let project = self.make_project_node(
+            name,
+            filter.clone(),
+            columns.iter().collect(),
+            vec![],
+            vec![],
+            false,
+        );
+
+        vec![count, filter, project]
+    }
+
     fn make_union_node(&self, name: &str, ancestors: &[MirNodeRef]) -> MirNodeRef {
         let mut emit: Vec<Vec<Column>> = Vec::new();
         assert!(ancestors.len() > 1, "union must have more than 1 ancestors");
@@ -988,6 +1110,7 @@ impl SqlToMirConverter {
         group_cols: Vec<&Column>,
         parent: MirNodeRef,
     ) -> Vec<MirNodeRef> {
+        use crate::controller::sql::passes::count_star_rewrite::counts_rows;
         use dataflow::ops::grouped::aggregate::Aggregation;
         use dataflow::ops::grouped::extremum::Extremum;
         use dataflow::ops::grouped::filteraggregate::FilterAggregation;
@@ -1002,12 +1125,31 @@ impl SqlToMirConverter {
                       distinct: bool,
                       cond: Option<&ConditionExpression>| {
             if distinct {
+                // count the occurrences of each value in each group, so that a value only stops
+                // contributing to the aggregate once its last occurrence is gone
                 let new_name = name.to_owned() + "_distinct";
-                let mut dist_col = Vec::new();
-                dist_col.push(over);
-                dist_col.extend(group_cols.clone());
-                let node = self.make_distinct_node(&new_name, parent, dist_col.clone());
+                let occurrences = Column::new(None, &format!("{}_occurrences", over.name));
+                let multiset_cols: Vec<Column> = parent
+                    .borrow()
+                    .columns()
+                    .iter()
+                    .filter(|c| *c == over || group_cols.contains(c))
+                    .cloned()
+                    .collect();
+                let node = self.make_grouped_node(
+                    &new_name,
+                    &occurrences,
+                    (parent, over, None),
+                    multiset_cols.iter().collect(),
+                    GroupedNodeType::Aggregation(Aggregation::COUNT),
+                    None,
+                );
                 out_nodes.push(node.clone());
+
+                let t = match t {
+                    GroupedNodeType::Aggregation(agg) => GroupedNodeType::DistinctAggregation(agg),
+                    _ => unreachable!("only counts and sums can be distinct"),
+                };
                 out_nodes.push(self.make_grouped_node(
                     name,
                     &func_col,
@@ -1067,8 +1209,28 @@ impl SqlToMirConverter {
                 false,
                 Some(condition),
             ),
+            Count(FunctionArguments::Column(ref col), false) if !counts_rows(col) => {
+                // unlike COUNT(*), COUNT(col) only counts the rows where col is not NULL
+                let not_null = ConditionExpression::ComparisonOp(ConditionTree {
+                    operator: Operator::NotEqual,
+                    left: Box::new(ConditionExpression::Base(ConditionBase::Field(col.clone()))),
+                    right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                        Literal::Null,
+                    ))),
+                });
+                mknode(
+                    &Column::from(col),
+                    None,
+                    GroupedNodeType::FilterAggregation(FilterAggregation::COUNT),
+                    false,
+                    Some(&not_null),
+                )
+            }
             Count(FunctionArguments::Column(ref col), distinct) => mknode(
-                &Column::from(col),
+                &Column {
+                    function: None,
+                    ..Column::from(col)
+                },
                 None,
                 GroupedNodeType::Aggregation(Aggregation::COUNT),
                 distinct,
@@ -1076,11 +1238,9 @@ impl SqlToMirConverter {
             ),
             CountStar => {
                 // XXX(malte): there is no "over" column, but our aggregation operators' API
-                // requires one to be specified, so we earlier rewrote it to use the last parent
-                // column (see passes/count_star_rewrite.rs). However, this isn't *entirely*
-                // faithful to COUNT(*) semantics, because COUNT(*) is supposed to count all
-                // rows including those with NULL values, and we don't have a mechanism to do that
-                // (but we also don't have a NULL value, so maybe we're okay).
+                // requires one to be specified, so we earlier rewrote it to count over one of the
+                // table's columns, marked as standing in for `*` so that rows where it is NULL
+                // are still counted (see passes/count_star_rewrite.rs).
                 panic!("COUNT(*) should have been rewritten earlier!")
             }
             Count(
@@ -1173,6 +1333,18 @@ impl SqlToMirConverter {
                 vec![parent_node.clone()],
                 vec![],
             ),
+            GroupedNodeType::DistinctAggregation(agg) => MirNode::new(
+                name,
+                self.schema_version,
+                combined_columns,
+                MirNodeType::DistinctAggregation {
+                    on: over_col.clone(),
+                    group_by: group_by.into_iter().cloned().collect(),
+                    kind: agg,
+                },
+                vec![parent_node.clone()],
+                vec![],
+            ),
             GroupedNodeType::Extremum(extr) => MirNode::new(
                 name,
                 self.schema_version,
@@ -1226,9 +1398,56 @@ impl SqlToMirConverter {
                 vec![parent_node.clone()],
                 vec![],
             ),
+            GroupedNodeType::CustomAggregation(function) => MirNode::new(
+                name,
+                self.schema_version,
+                combined_columns,
+                MirNodeType::CustomAggregation {
+                    on: over_col.clone(),
+                    group_by: group_by.into_iter().cloned().collect(),
+                    function,
+                },
+                vec![parent_node.clone()],
+                vec![],
+            ),
         }
     }
 
+    /// Keeps the rows of `left_node` that match no row of `right_node` under the equality `jp`.
+    fn make_antijoin_node(
+        &self,
+        name: &str,
+        jp: &ConditionTree,
+        left_node: MirNodeRef,
+        right_node: MirNodeRef,
+    ) -> MirNodeRef {
+        assert!(jp.operator == Operator::Equal || jp.operator == Operator::In);
+        let l_col = match *jp.left {
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
+            _ => unimplemented!(),
+        };
+        let r_col = match *jp.right {
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
+            _ => unimplemented!(),
+        };
+
+        // only the left side's rows make it through
+        let fields = left_node.borrow().columns().to_vec();
+        let inner = MirNodeType::AntiJoin {
+            on_left: l_col,
+            on_right: r_col,
+        };
+        trace!(self.log, "Added anti-join node {:?}", inner);
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            inner,
+            vec![left_node.clone(), right_node.clone()],
+            vec![],
+        )
+    }
+
     fn make_join_node(
         &self,
         name: &str,
@@ -1304,6 +1523,11 @@ impl SqlToMirConverter {
                 on_right: right_join_columns,
                 project: fields.clone(),
             },
+            JoinType::Full => MirNodeType::OuterJoin {
+                on_left: left_join_columns,
+                on_right: right_join_columns,
+                project: fields.clone(),
+            },
         };
         trace!(self.log, "Added join node {:?}", inner);
         MirNode::new(
@@ -1316,6 +1540,51 @@ impl SqlToMirConverter {
         )
     }
 
+    /// Joins `parents` in a single multi-way join. `on` has an entry for each parent but the
+    /// first, which gives the parent before it that it is joined with, the join column of that
+    /// parent, and its own join column.
+    pub(super) fn make_multi_join_node(
+        &self,
+        name: &str,
+        parents: Vec<MirNodeRef>,
+        on: Vec<(usize, Column, Column)>,
+    ) -> MirNodeRef {
+        let mut fields: Vec<Column> = parents
+            .iter()
+            .flat_map(|p| p.borrow().columns().to_vec())
+            .collect();
+
+        // as for binary joins, each join column is only output once, aliased to the column of the
+        // parent it is joined with
+        let on: Vec<_> = on
+            .into_iter()
+            .map(|(p, mut l_col, r_col)| {
+                l_col.add_alias(&r_col);
+                fields = std::mem::take(&mut fields)
+                    .into_iter()
+                    .filter_map(|mut f| {
+                        if f == r_col {
+                            None
+                        } else if f == l_col {
+                            f.add_alias(&r_col);
+                            Some(f)
+                        } else {
+                            Some(f)
+                        }
+                    })
+                    .collect();
+                (p, l_col, r_col)
+            })
+            .collect();
+
+        let inner = MirNodeType::MultiJoin {
+            on,
+            project: fields.clone(),
+        };
+        trace!(self.log, "Added multi-way join node {:?}", inner);
+        MirNode::new(name, self.schema_version, fields, inner, parents, vec![])
+    }
+
     fn make_projection_helper(
         &self,
         name: &str,
@@ -1337,13 +1606,13 @@ impl SqlToMirConverter {
         name: &str,
         parent_node: MirNodeRef,
         proj_cols: Vec<&Column>,
-        arithmetic: Vec<(String, ArithmeticExpression)>,
+        expressions: Vec<(String, Expression)>,
         literals: Vec<(String, DataType)>,
         is_leaf: bool,
     ) -> MirNodeRef {
         //assert!(proj_cols.iter().all(|c| c.table == parent_name));
 
-        let names: Vec<String> = arithmetic
+        let names: Vec<String> = expressions
             .iter()
             .map(|&(ref n, _)| n.clone())
             .chain(literals.iter().map(|&(ref n, _)| n.clone()))
@@ -1379,33 +1648,13 @@ impl SqlToMirConverter {
             MirNodeType::Project {
                 emit: emit_cols,
                 literals,
-                arithmetic,
+                expressions,
             },
             vec![parent_node.clone()],
             vec![],
         )
     }
 
-    fn make_distinct_node(
-        &self,
-        name: &str,
-        parent: MirNodeRef,
-        group_by: Vec<&Column>,
-    ) -> MirNodeRef {
-        let combined_columns = parent.borrow().columns().to_vec();
-
-        // make the new operator and record its metadata
-        MirNode::new(
-            name,
-            self.schema_version,
-            combined_columns,
-            MirNodeType::Distinct {
-                group_by: group_by.into_iter().cloned().collect(),
-            },
-            vec![parent.clone()],
-            vec![],
-        )
-    }
     fn make_topk_node(
         &self,
         name: &str,
@@ -1426,8 +1675,6 @@ impl SqlToMirConverter {
             None => None,
         };
 
-        assert_eq!(limit.offset, 0); // Non-zero offset not supported
-
         // make the new operator and record its metadata
         MirNode::new(
             name,
@@ -1437,7 +1684,7 @@ impl SqlToMirConverter {
                 order,
                 group_by: group_by.into_iter().cloned().collect(),
                 k: limit.limit as usize,
-                offset: 0,
+                offset: limit.offset as usize,
             },
             vec![parent.clone()],
             vec![],
@@ -1558,17 +1805,16 @@ impl SqlToMirConverter {
             value_columns_needed_for_predicates(&qg.columns, &qg.global_predicates);
 
         if !arith_and_lit_columns_needed.is_empty() {
-            let projected_arithmetic: Vec<(String, ArithmeticExpression)> =
-                arith_and_lit_columns_needed
-                    .iter()
-                    .filter_map(|&(_, ref oc)| match oc {
-                        OutputColumn::Arithmetic(ref ac) => {
-                            Some((ac.name.clone(), ac.expression.clone()))
-                        }
-                        OutputColumn::Data(_) => None,
-                        OutputColumn::Literal(_) => None,
-                    })
-                    .collect();
+            let projected_expressions: Vec<(String, Expression)> = arith_and_lit_columns_needed
+                .iter()
+                .filter_map(|&(_, ref oc)| match oc {
+                    OutputColumn::Arithmetic(ref ac) => {
+                        Some((ac.name.clone(), Expression::from(&ac.expression)))
+                    }
+                    OutputColumn::Data(_) => None,
+                    OutputColumn::Literal(_) => None,
+                })
+                .collect();
             let projected_literals: Vec<(String, DataType)> = arith_and_lit_columns_needed
                 .iter()
                 .filter_map(|&(_, ref oc)| match oc {
@@ -1591,7 +1837,7 @@ impl SqlToMirConverter {
                 &format!("q_{:x}_n{}{}", qg.signature().hash, node_count, universe),
                 parent.clone(),
                 passthru_cols.iter().collect(),
-                projected_arithmetic,
+                projected_expressions,
                 projected_literals,
                 false,
             );
@@ -1879,6 +2125,32 @@ impl SqlToMirConverter {
                     predicate_nodes.extend(fns);
                 }
 
+                // 5b. HAVING predicates, which filter the output of the grouped nodes
+                for (i, ref p) in qg.having_predicates.iter().enumerate() {
+                    let parent = match prev_node {
+                        None => unimplemented!(),
+                        Some(pn) => pn,
+                    };
+
+                    let fns = self.make_predicate_nodes(
+                        &format!(
+                            "q_{:x}_n{}_h{}{}",
+                            qg.signature().hash,
+                            new_node_count,
+                            i,
+                            uformat,
+                        ),
+                        parent,
+                        p,
+                        0,
+                    );
+
+                    assert!(!fns.is_empty());
+                    new_node_count += fns.len();
+                    prev_node = Some(fns.iter().last().unwrap().clone());
+                    predicate_nodes.extend(fns);
+                }
+
                 // 6. Get the final node
                 let mut final_node: MirNodeRef = if prev_node.is_some() {
                     prev_node.unwrap().clone()
@@ -1993,13 +2265,13 @@ impl SqlToMirConverter {
                 value_columns_needed_for_predicates(&qg.columns, &qg.global_predicates)
                     .into_iter()
                     .unzip();
-            let projected_arithmetic: Vec<(String, ArithmeticExpression)> = qg
+            let projected_expressions: Vec<(String, Expression)> = qg
                 .columns
                 .iter()
                 .filter_map(|oc| match *oc {
                     OutputColumn::Arithmetic(ref ac) => {
                         if !already_computed.contains(oc) {
-                            Some((ac.name.clone(), ac.expression.clone()))
+                            Some((ac.name.clone(), Expression::from(&ac.expression)))
                         } else {
                             projected_columns.push(Column::new(None, &ac.name));
                             None
@@ -2047,7 +2319,7 @@ impl SqlToMirConverter {
                 &ident,
                 final_node,
                 projected_columns.iter().collect(),
-                projected_arithmetic,
+                projected_expressions,
                 projected_literals,
                 !has_leaf,
             );
@@ -2074,6 +2346,27 @@ impl SqlToMirConverter {
                     qg.parameters().into_iter().map(Column::from).collect()
                 };
 
+                // readers return the rows for each key in the order of the query, starting at its
+                // offset, as long as they have all the columns it orders by
+                let order = match st.limit {
+                    Some(ref limit) => {
+                        let order: Vec<_> = st
+                            .order
+                            .iter()
+                            .flat_map(|o| o.columns.iter())
+                            .map(|(c, o)| (Column::from(c), o.clone()))
+                            .collect();
+                        let project = leaf_project_node.borrow();
+                        if order.iter().all(|(c, _)| project.columns().contains(c)) {
+                            Some((order, limit.offset as usize, limit.limit as usize))
+                        } else {
+                            assert_eq!(limit.offset, 0, "OFFSET needs the ORDER BY columns");
+                            None
+                        }
+                    }
+                    None => None,
+                };
+
                 let leaf_node = MirNode::new(
                     name,
                     self.schema_version,
@@ -2081,6 +2374,8 @@ impl SqlToMirConverter {
                     MirNodeType::Leaf {
                         node: leaf_project_node.clone(),
                         keys: query_params,
+                        range: qg.range_operator.clone(),
+                        order,
                     },
                     vec![leaf_project_node.clone()],
                     vec![],
@@ -3,7 +3,10 @@ use crate::controller::sql::mir::SqlToMirConverter;
 use crate::controller::sql::query_graph::QueryGraph;
 use crate::controller::sql::query_signature::Signature;
 use crate::controller::sql::UniverseId;
-use mir::MirNodeRef;
+use dataflow::prelude::DataType;
+use mir::node::{MirNode, MirNodeType};
+use mir::{Column, MirNodeRef};
+use nom_sql::{ConditionBase, ConditionExpression, ConditionTree, Literal, Operator};
 use std::collections::HashMap;
 
 pub trait SecurityBoundary {
@@ -132,6 +135,21 @@ impl SecurityBoundary for SqlToMirConverter {
             last_security_nodes.push(prev_node.clone());
         }
 
+        // Masked columns are replaced at the end of every policy chain, so that the rest of the
+        // query never sees their values.
+        let tables: Vec<&str> = node_for_rel.keys().cloned().collect();
+        let suffix = match universe.1 {
+            Some(ref g) => format!("_{}{}", g.to_string(), universe.0.to_string()),
+            None => format!("_u{}", universe.0.to_string()),
+        };
+        for last in &mut last_security_nodes {
+            let name = format!("{}_mask{}", last.borrow().name, suffix);
+            if let Some(mask) = make_mask_node(self, &name, last, &tables) {
+                security_nodes.push(mask.clone());
+                *last = mask;
+            }
+        }
+
         Ok((last_security_nodes, security_nodes))
     }
 }
@@ -142,23 +160,32 @@ fn make_security_nodes(
     prev_node: &MirNodeRef,
     node_for_rel: HashMap<&str, MirNodeRef>,
 ) -> Result<(Vec<MirNodeRef>, Vec<MirNodeRef>), String> {
-    let policies = match mir_converter
+    let policies = mir_converter
         .universe
         .row_policies
-        .get(&String::from(table))
-    {
-        Some(p) => p.clone(),
+        .get(table)
+        .cloned()
+        .unwrap_or_default();
+    let shared_policies = mir_converter
+        .universe
+        .shared_row_policies
+        .get(table)
+        .cloned()
+        .unwrap_or_default();
+
+    if policies.is_empty() && shared_policies.is_empty() {
         // no policies associated with this base node
-        None => return Ok((vec![], vec![])),
-    };
+        return Ok((vec![], vec![]));
+    }
 
     let mut node_count = 0;
     let mut local_node_for_rel = node_for_rel.clone();
 
     debug!(
         mir_converter.log,
-        "Found {} row policies for table {}",
-        policies.len(),
+        "Found {} row policies ({} shared) for table {}",
+        policies.len() + shared_policies.len(),
+        shared_policies.len(),
         table
     );
 
@@ -274,5 +301,121 @@ fn make_security_nodes(
         last_policy_nodes.push(policy_nodes.last().unwrap().clone())
     }
 
+    // Shared policies hang off a subtree that all user universes have in common (or directly off
+    // the base), so each universe only contributes a filter on its user id.
+    let uid = mir_converter.universe.id.clone();
+    for (i, sp) in shared_policies.into_iter().enumerate() {
+        let name = format!("sp_{}_shared{}_u{}", table, i, user_id_name(&uid));
+        let mut policy_nodes = Vec::new();
+
+        let parent = match sp.view {
+            Some(ref view) => {
+                let shared = mir_converter.get_view(view)?;
+                policy_nodes.push(shared.clone());
+                shared
+            }
+            None => prev_node.clone(),
+        };
+
+        let cond = ConditionTree {
+            operator: Operator::Equal,
+            left: Box::new(ConditionExpression::Base(ConditionBase::Field(sp.column))),
+            right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                user_id_literal(&uid),
+            ))),
+        };
+        let filter = mir_converter.make_filter_node(&format!("{}_f0", name), parent, &cond);
+        policy_nodes.push(filter.clone());
+
+        let rewrite_nodes = make_rewrite_nodes(mir_converter, &name, filter, table, 1)?;
+        policy_nodes.extend(rewrite_nodes);
+
+        security_nodes.extend(policy_nodes.clone());
+        last_policy_nodes.push(policy_nodes.last().unwrap().clone());
+    }
+
     Ok((last_policy_nodes, security_nodes))
 }
+
+/// A projection of `parent` that replaces the columns of `tables` that the universe masks with
+/// their masked values, or `None` if the universe masks none of their columns.
+fn make_mask_node(
+    mir_converter: &SqlToMirConverter,
+    name: &str,
+    parent: &MirNodeRef,
+    tables: &[&str],
+) -> Option<MirNodeRef> {
+    let masks: Vec<_> = tables
+        .iter()
+        .filter_map(|&t| Some((t, mir_converter.universe.column_masks.get(t)?)))
+        .flat_map(|(t, masks)| masks.iter().map(move |m| (t, m)))
+        .collect();
+    if masks.is_empty() {
+        return None;
+    }
+
+    debug!(
+        mir_converter.log,
+        "Masking {} columns below {}",
+        masks.len(),
+        name
+    );
+
+    let is_masked = |c: &Column| {
+        masks
+            .iter()
+            .any(|&(t, m)| c.name == m.column && c.table.as_ref().map_or(true, |table| table == t))
+    };
+    let emit: Vec<Column> = parent
+        .borrow()
+        .columns()
+        .iter()
+        .filter(|c| !is_masked(c))
+        .cloned()
+        .collect();
+    let literals = masks
+        .iter()
+        .map(|&(_, m)| (m.column.clone(), m.value.clone()))
+        .collect();
+    // the masked values keep their table, so that the query finds them where it expects the
+    // original columns
+    let columns = emit
+        .iter()
+        .cloned()
+        .chain(masks.iter().map(|&(t, m)| Column::new(Some(t), &m.column)))
+        .collect();
+
+    Some(MirNode::new(
+        name,
+        mir_converter.schema_version,
+        columns,
+        MirNodeType::Project {
+            emit,
+            expressions: vec![],
+            literals,
+        },
+        vec![parent.clone()],
+        vec![],
+    ))
+}
+
+/// User ids arrive as whatever type the client used when creating the universe, so integer ids
+/// are kept as integers to compare equal to integer columns.
+fn user_id_literal(uid: &DataType) -> Literal {
+    match *uid {
+        DataType::Int(_)
+        | DataType::UnsignedInt(_)
+        | DataType::BigInt(_)
+        | DataType::UnsignedBigInt(_) => Literal::Integer(i64::from(uid)),
+        _ => Literal::String(user_id_name(uid)),
+    }
+}
+
+/// The user id as it appears in node names. Text ids go in without the quotes that `Display`
+/// puts around them.
+fn user_id_name(uid: &DataType) -> String {
+    match *uid {
+        DataType::Text(..) | DataType::TinyText(..) => <&str>::from(uid).to_owned(),
+        _ => uid.to_string(),
+    }
+}
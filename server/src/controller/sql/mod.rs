@@ -1,3 +1,4 @@
+pub(super) mod cost;
 mod mir;
 mod passes;
 mod query_graph;
@@ -6,6 +7,7 @@ mod query_utils;
 mod reuse;
 pub(super) mod security;
 
+use self::cost::TableStatistics;
 use self::mir::SqlToMirConverter;
 use self::query_graph::{to_query_graph, QueryGraph};
 use self::query_signature::Signature;
@@ -16,11 +18,11 @@ use crate::ReuseConfigType;
 use ::mir::query::{MirQuery, QueryFlowParts};
 use ::mir::reuse as mir_reuse;
 use ::mir::Column;
-use ::mir::MirNodeRef;
+use ::mir::{MirError, MirNodeRef};
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
 use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery};
-use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, SelectStatement};
+use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, Operator, SelectStatement};
 use petgraph::graph::NodeIndex;
 
 use slog;
@@ -65,6 +67,13 @@ pub(crate) struct SqlIncorporator {
     /// Active universes mapped to the group they belong to.
     /// If an user universe, mapped to None.
     universes: HashMap<Option<DataType>, Vec<UniverseId>>,
+
+    /// Views holding row policy subtrees that are shared by all user universes, by the table the
+    /// policy is on and the signature of the policy's universe-independent part.
+    shared_policies: HashMap<String, HashMap<u64, String>>,
+
+    /// What we know about the contents of base tables, for choosing the order of joins.
+    table_statistics: HashMap<String, TableStatistics>,
 }
 
 impl Default for SqlIncorporator {
@@ -87,6 +96,8 @@ impl Default for SqlIncorporator {
 
             reuse_type: ReuseConfigType::Finkelstein,
             universes: HashMap::default(),
+            shared_policies: HashMap::default(),
+            table_statistics: HashMap::default(),
         }
     }
 }
@@ -114,6 +125,11 @@ impl SqlIncorporator {
         self.reuse_type = reuse_type;
     }
 
+    /// Plan the joins of future queries with the given statistics about base tables.
+    pub(super) fn set_table_statistics(&mut self, stats: HashMap<String, TableStatistics>) {
+        self.table_statistics = stats;
+    }
+
     /// Incorporates a single query into via the flow graph migration in `mig`. The `query`
     /// argument is a string that holds a parameterized SQL query, and the `name` argument supplies
     /// an optional name for the query. If no `name` is specified, the table name is used in the
@@ -185,24 +201,124 @@ impl SqlIncorporator {
             .collect()
     }
 
+    /// Whether a query called `name` has been added in any universe.
+    pub(super) fn has_query(&self, name: &str) -> bool {
+        self.leaf_addresses.contains_key(name)
+    }
+
+    /// The MIR of the installed table or query called `name`.
+    pub(super) fn get_mir_query(&self, name: &str) -> Option<&MirQuery> {
+        let universe: UniverseId = ("global".into(), None);
+        match self.named_queries.get(name) {
+            Some(&qid) => self.mir_queries.get(&(qid, universe)),
+            None => self.base_mir_queries.get(name),
+        }
+    }
+
+    /// The MIR that adding the `SELECT` in `query` would produce, planned without adding it.
+    ///
+    /// If an installed query computes the same thing already, its MIR is returned instead, along
+    /// with its name.
+    pub(super) fn plan_query(&self, query: SqlQuery) -> Result<(Option<String>, MirQuery), String> {
+        use ::mir::visualize::GraphViz;
+        use nom_sql::SelectSpecification;
+        use passes::subqueries::SubQueries;
+
+        let sq = match query {
+            SqlQuery::Select(sq) => sq,
+            SqlQuery::CreateView(cvq) => match *cvq.definition {
+                SelectSpecification::Simple(sq) => sq,
+                SelectSpecification::Compound(_) => {
+                    return Err("compound queries cannot be planned".to_owned());
+                }
+            },
+            _ => return Err("only SELECT queries can be planned".to_owned()),
+        };
+        let mut q = SqlQuery::Select(sq);
+        if !q.extract_subqueries().is_empty() {
+            // subqueries become views of their own, which we would have to add to plan them
+            return Err("queries with subqueries cannot be planned without adding them".to_owned());
+        }
+
+        // plan on a copy, so that nothing we register while planning sticks
+        let mut inc = self.clone();
+        inc.num_queries += 1;
+        let name = format!("q_{}", inc.num_queries);
+        let universe: UniverseId = ("global".into(), None);
+        let sq = match inc.rewrite_flattened_query(q, &HashMap::new())? {
+            SqlQuery::Select(sq) => sq,
+            _ => unreachable!(),
+        };
+        let (qg, reuse) = inc.consider_query_graph(&name, universe.clone(), &sq)?;
+        let mir = match reuse {
+            QueryGraphReuse::ExactMatch(_) => {
+                let qid = qg.signature().hash;
+                let existing = self
+                    .named_queries
+                    .iter()
+                    .filter(|&(_, &id)| id == qid)
+                    .map(|(name, _)| name)
+                    .min();
+                return Ok((
+                    existing.cloned(),
+                    self.mir_queries[&(qid, universe)].clone(),
+                ));
+            }
+            QueryGraphReuse::ReaderOntoExisting(mn, project_columns, params) => {
+                let range = qg.range_operator.clone();
+                inc.mir_converter
+                    .add_leaf_below(mn, &name, &params, range, project_columns)
+            }
+            QueryGraphReuse::ExtendExisting(mqs) => {
+                let (_, mir, table_mapping, _) = inc.mir_converter.named_query_to_mir(
+                    &name,
+                    &sq,
+                    &qg,
+                    true,
+                    universe.clone(),
+                )?;
+                let (mut mir, _) = mir.optimize(table_mapping.as_ref(), false)?;
+                for m in mqs {
+                    if let Some(mq) = self.mir_queries.get(&m) {
+                        mir = mir_reuse::merge_mir_for_queries(&self.log, &mir, mq).0;
+                    }
+                }
+                mir.optimize_post_reuse()
+            }
+            QueryGraphReuse::None => {
+                let (_, mir, table_mapping, _) = inc.mir_converter.named_query_to_mir(
+                    &name,
+                    &sq,
+                    &qg,
+                    true,
+                    universe.clone(),
+                )?;
+                mir.optimize(table_mapping.as_ref(), false)?.0
+            }
+        };
+        trace!(self.log, "Planned MIR:\n{}", mir.to_graphviz().unwrap());
+        Ok((None, mir))
+    }
+
     fn consider_query_graph(
         &mut self,
         query_name: &str,
         universe: UniverseId,
         st: &SelectStatement,
-    ) -> (QueryGraph, QueryGraphReuse) {
+    ) -> Result<(QueryGraph, QueryGraphReuse), String> {
         debug!(self.log, "Making QG for \"{}\"", query_name);
         trace!(self.log, "Query \"{}\": {:#?}", query_name, st);
 
-        let mut qg = match to_query_graph(st) {
-            Ok(qg) => qg,
-            Err(e) => panic!(e),
-        };
+        let mut qg = to_query_graph(st)?;
 
         trace!(self.log, "QG for \"{}\": {:#?}", query_name, qg);
 
-        // if reuse is disabled, we're done
+        // if reuse is disabled, we're done once we have picked the cheapest join order
         if self.reuse_type == ReuseConfigType::NoReuse {
+            if let Some(order) = cost::best_join_order(&qg, &self.table_statistics) {
+                qg.join_order = order;
+            }
+            qg.multiway_join = self.multiway_join(&qg);
             return (qg, QueryGraphReuse::None);
         }
 
@@ -240,7 +356,8 @@ impl SqlIncorporator {
 
                     return (qg, QueryGraphReuse::ExactMatch(mir_query.leaf.clone()));
                 } else if existing_qg.signature() == qg.signature()
-                    && existing_qg.parameters() != qg.parameters()
+                    && (existing_qg.parameters() != qg.parameters()
+                        || existing_qg.range_operator != qg.range_operator)
                 {
                     use self::query_graph::OutputColumn;
 
@@ -285,7 +402,8 @@ impl SqlIncorporator {
                     });
 
                     if predicates_match && no_grouped_columns {
-                        // QGs are identical, except for parameters (or their order)
+                        // QGs are identical, except for parameters (or their order, or how they
+                        // are compared)
                         info!(
                             self.log,
                             "Query '{}' has an exact match modulo parameters in {}, \
@@ -344,7 +462,8 @@ impl SqlIncorporator {
         let reuse_config = ReuseConfig::new(self.reuse_type.clone());
 
         // Find a promising set of query graphs
-        let reuse_candidates = reuse_config.reuse_candidates(&mut qg, &self.query_graphs);
+        let reuse_candidates =
+            reuse_config.reuse_candidates(&mut qg, &self.query_graphs, &self.table_statistics);
 
         if !reuse_candidates.is_empty() {
             info!(
@@ -377,13 +496,25 @@ impl SqlIncorporator {
             info!(self.log, "No reuse opportunity, adding fresh query");
         }
 
+        qg.multiway_join = self.multiway_join(&qg);
         (qg, QueryGraphReuse::None)
     }
 
+    /// Whether the joins of a query that shares nothing with existing ones should be performed by
+    /// a single multi-way join. Its parents have to be fully materialized, which only base tables
+    /// are sure to be.
+    fn multiway_join(&self, qg: &QueryGraph) -> bool {
+        qg.relations
+            .keys()
+            .all(|r| r == "computed_columns" || self.base_schemas.contains_key(r))
+            && cost::multiway_join(qg, &self.table_statistics)
+    }
+
     fn add_leaf_to_existing_query(
         &mut self,
         query_name: &str,
         params: &[Column],
+        range: Option<Operator>,
         final_query_node: MirNodeRef,
         project_columns: Option<Vec<Column>>,
         mut mig: &mut Migration,
@@ -394,6 +525,7 @@ impl SqlIncorporator {
             final_query_node,
             query_name,
             params,
+            range,
             project_columns,
         );
 
@@ -446,7 +578,25 @@ impl SqlIncorporator {
         is_leaf: bool,
         mut mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
-        let subqueries: Result<Vec<_>, String> = query
+        // UNION ALL keeps every record of its inputs, while UNION removes duplicates
+        let op = match query
+            .selects
+            .iter()
+            .filter_map(|&(ref op, _)| op.clone())
+            .fold(Ok(None), |acc, op| match acc? {
+                Some(ref prev) if *prev != op => {
+                    Err("mixing UNION and UNION ALL is not supported".to_owned())
+                }
+                _ => Ok(Some(op)),
+            })? {
+            Some(CompoundSelectOperator::Intersect) | Some(CompoundSelectOperator::Except) => {
+                return Err("INTERSECT and EXCEPT are not supported".to_owned());
+            }
+            Some(op) => op,
+            None => CompoundSelectOperator::Union,
+        };
+
+        let subqueries: Vec<_> = query
             .selects
             .iter()
             .enumerate()
@@ -456,12 +606,31 @@ impl SqlIncorporator {
                     .1
                     .unwrap())
             })
-            .collect();
+            .collect::<Result<_, String>>()?;
+
+        // the union matches columns by position, so all queries must select as many columns
+        let arity = subqueries[0].leaf.borrow().columns().len();
+        if subqueries
+            .iter()
+            .any(|mq| mq.leaf.borrow().columns().len() != arity)
+        {
+            return Err(format!(
+                "all queries in the union {} must select the same number of columns",
+                query_name
+            ));
+        }
+        // removing duplicates groups by every column, and grouped operators take at most six
+        if op == CompoundSelectOperator::DistinctUnion && arity > 6 {
+            return Err(format!(
+                "UNION {} selects more than 6 columns; use UNION ALL instead",
+                query_name
+            ));
+        }
 
         let mut combined_mir_query = self.mir_converter.compound_query_to_mir(
             query_name,
-            subqueries?.iter().collect(),
-            CompoundSelectOperator::Union,
+            subqueries.iter().collect(),
+            op,
             &query.order,
             &query.limit,
             is_leaf,
@@ -483,7 +652,7 @@ impl SqlIncorporator {
         is_leaf: bool,
         mig: &mut Migration,
     ) -> Result<(QueryFlowParts, Option<MirQuery>), String> {
-        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq);
+        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq)?;
         Ok(match reuse {
             QueryGraphReuse::ExactMatch(mn) => {
                 let flow_node = mn.borrow().flow_node.as_ref().unwrap().address();
@@ -500,8 +669,15 @@ impl SqlIncorporator {
                 (qfp, None)
             }
             QueryGraphReuse::ReaderOntoExisting(mn, project_columns, params) => {
-                let qfp =
-                    self.add_leaf_to_existing_query(&query_name, &params, mn, project_columns, mig);
+                let range = qg.range_operator.clone();
+                let qfp = self.add_leaf_to_existing_query(
+                    &query_name,
+                    &params,
+                    range,
+                    mn,
+                    project_columns,
+                    mig,
+                );
                 (qfp, None)
             }
             QueryGraphReuse::None => {
@@ -538,7 +714,7 @@ impl SqlIncorporator {
         );
 
         // run MIR-level optimizations
-        let (mut mir, nodes_added) = og_mir.optimize(table_mapping.as_ref(), sec);
+        let (mut mir, nodes_added) = og_mir.optimize(table_mapping.as_ref(), sec)?;
         // update mir_converter with the nodes added. Note (jamb): we never remove the nodes removed
         // by the optimizations, but they do get disconnected pointer-wise, so I think it's fine.
         // (If we ever want to fix this, it's also relevant to the place below that calls optimize.)
@@ -546,13 +722,28 @@ impl SqlIncorporator {
 
         trace!(self.log, "Optimized MIR:\n{}", mir.to_graphviz().unwrap());
 
+        // the query graph had nothing in common with the existing queries, but their plans may
+        // still share filtered or aggregated inputs
+        let candidates = self.structural_reuse_candidates(&mir, &universe, &[]);
+        if !candidates.is_empty() {
+            let (reused_mir, num_reused_nodes) = self.merge_with_existing(mir, candidates);
+            mir = reused_mir.optimize_post_reuse();
+            info!(
+                self.log,
+                "Reused {} nodes for {}", num_reused_nodes, query_name
+            );
+        }
+
         if sec {
             match table_mapping {
                 Some(ref x) => {
                     mir = mir.make_universe_naming_consistent(x, base_name);
                 }
                 None => {
-                    panic!("Missing table mapping when reconciling universe table names!");
+                    return Err(MirError::MissingTableMapping {
+                        query: query_name.to_owned(),
+                    }
+                    .into());
                 }
             }
         }
@@ -675,11 +866,10 @@ impl SqlIncorporator {
         query_name: &str,
         query: &SelectStatement,
         qg: QueryGraph,
-        reuse_mirs: Vec<(u64, UniverseId)>,
+        mut reuse_mirs: Vec<(u64, UniverseId)>,
         is_leaf: bool,
         mut mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
-        use ::mir::reuse::merge_mir_for_queries;
         use ::mir::visualize::GraphViz;
         let universe = mig.universe();
 
@@ -695,7 +885,7 @@ impl SqlIncorporator {
             new_query_mir.to_graphviz().unwrap()
         );
 
-        let (new_opt_mir, new_nodes) = new_query_mir.optimize(table_mapping.as_ref(), sec);
+        let (new_opt_mir, new_nodes) = new_query_mir.optimize(table_mapping.as_ref(), sec)?;
         self.mir_converter.add_nodes(new_nodes);
 
         trace!(
@@ -704,20 +894,11 @@ impl SqlIncorporator {
             new_opt_mir.to_graphviz().unwrap()
         );
 
-        // compare to existing query MIR and reuse prefix
-        let mut reused_mir = new_opt_mir.clone();
-        let mut num_reused_nodes = 0;
-        for m in reuse_mirs {
-            if !self.mir_queries.contains_key(&m) {
-                continue;
-            }
-            let mq = &self.mir_queries[&m];
-            let res = merge_mir_for_queries(&self.log, &reused_mir, &mq);
-            reused_mir = res.0;
-            if res.1 > num_reused_nodes {
-                num_reused_nodes = res.1;
-            }
-        }
+        // compare to existing query MIR and reuse prefix, including that of queries whose plans
+        // overlap with this one even though the query graphs did not suggest it
+        let structural = self.structural_reuse_candidates(&new_opt_mir, &universe, &reuse_mirs);
+        reuse_mirs.extend(structural);
+        let (reused_mir, num_reused_nodes) = self.merge_with_existing(new_opt_mir, reuse_mirs);
 
         let mut post_reuse_opt_mir = reused_mir.optimize_post_reuse();
 
@@ -730,7 +911,10 @@ impl SqlIncorporator {
                         post_reuse_opt_mir.make_universe_naming_consistent(x, base_name);
                 }
                 None => {
-                    panic!("Missing table mapping when reconciling universe table names!");
+                    return Err(MirError::MissingTableMapping {
+                        query: query_name.to_owned(),
+                    }
+                    .into());
                 }
             }
         }
@@ -755,6 +939,69 @@ impl SqlIncorporator {
         Ok(qfp)
     }
 
+    /// Existing queries in `universe`, other than those in `exclude`, whose plans compute the same
+    /// thing as `mir` directly on top of a table they both read, such as the same filter or
+    /// aggregation. These can share that part of their plan with `mir` even if their query graphs
+    /// are unrelated, for example because they go on to join with different tables.
+    fn structural_reuse_candidates(
+        &self,
+        mir: &MirQuery,
+        universe: &UniverseId,
+        exclude: &[(u64, UniverseId)],
+    ) -> Vec<(u64, UniverseId)> {
+        if self.reuse_type == ReuseConfigType::NoReuse {
+            return Vec::new();
+        }
+
+        let shares_subplan = |old: &MirQuery| {
+            old.roots.iter().any(|old_root| {
+                mir.roots.iter().any(|new_root| {
+                    let (old_root, new_root) = (old_root.borrow(), new_root.borrow());
+                    old_root.can_reuse_as(&new_root)
+                        && old_root.children().iter().any(|oc| {
+                            new_root
+                                .children()
+                                .iter()
+                                .any(|nc| oc.borrow().can_reuse_as(&nc.borrow()))
+                        })
+                })
+            })
+        };
+
+        let mut candidates: Vec<_> = self
+            .mir_queries
+            .iter()
+            .filter(|&(k, mq)| k.1 == *universe && !exclude.contains(k) && shares_subplan(mq))
+            .map(|(k, _)| k.clone())
+            .collect();
+        // merge in a deterministic order, so that the same recipe always yields the same graph
+        candidates.sort_by_key(|k| k.0);
+        candidates
+    }
+
+    /// Merge `mir` with the existing queries in `reuse_mirs`, returning the merged query and the
+    /// largest number of nodes it reuses from any one of them.
+    fn merge_with_existing(
+        &self,
+        mir: MirQuery,
+        reuse_mirs: Vec<(u64, UniverseId)>,
+    ) -> (MirQuery, usize) {
+        let mut reused_mir = mir;
+        let mut num_reused_nodes = 0;
+        for m in reuse_mirs {
+            if !self.mir_queries.contains_key(&m) {
+                continue;
+            }
+            let mq = &self.mir_queries[&m];
+            let res = mir_reuse::merge_mir_for_queries(&self.log, &reused_mir, &mq);
+            reused_mir = res.0;
+            if res.1 > num_reused_nodes {
+                num_reused_nodes = res.1;
+            }
+        }
+        (reused_mir, num_reused_nodes)
+    }
+
     fn nodes_for_query(
         &mut self,
         q: SqlQuery,
@@ -765,7 +1012,13 @@ impl SqlIncorporator {
             SqlQuery::CreateTable(ref ctq) => ctq.table.name.clone(),
             SqlQuery::CreateView(ref cvq) => cvq.name.clone(),
             SqlQuery::Select(_) | SqlQuery::CompoundSelect(_) => format!("q_{}", self.num_queries),
-            _ => panic!("only CREATE TABLE and SELECT queries can be added to the graph!"),
+            ref q => {
+                return Err(MirError::Unsupported(format!(
+                    "only CREATE TABLE and SELECT queries can be added to the graph, not {}",
+                    q
+                ))
+                .into());
+            }
         };
         self.nodes_for_named_query(q, name, is_leaf, mig)
     }
@@ -774,14 +1027,7 @@ impl SqlIncorporator {
     fn rewrite_query(&mut self, q: SqlQuery, mig: &mut Migration) -> Result<SqlQuery, String> {
         // TODO: make this not take &mut self
 
-        use passes::alias_removal::AliasRemoval;
-        use passes::count_star_rewrite::CountStarRewrite;
-        use passes::implied_tables::ImpliedTableExpansion;
-        use passes::key_def_coalescing::KeyDefinitionCoalescing;
-        use passes::negation_removal::NegationRemoval;
-        use passes::star_expansion::StarExpansion;
         use passes::subqueries::SubQueries;
-        use query_utils::ReferredTables;
 
         // need to increment here so that each subquery has a unique name.
         // (subqueries call recursively into `nodes_for_named_query` via `add_parsed_query` below,
@@ -791,19 +1037,47 @@ impl SqlIncorporator {
         // flattens out the query by replacing subqueries for references
         // to existing views in the graph
         let mut fq = q.clone();
+        // correlated subqueries are joined with the outer query on their correlation columns
+        let mut correlated = Vec::new();
+        // `NOT IN` subqueries become anti-joins, which cannot also join on correlation columns
+        let negated: Vec<_> = match q {
+            SqlQuery::Select(ref st) => st
+                .where_clause
+                .iter()
+                .flat_map(passes::subqueries::negated_subqueries)
+                .cloned()
+                .collect(),
+            _ => vec![],
+        };
         for sq in fq.extract_subqueries() {
             use self::passes::subqueries::{
-                field_with_table_name, query_from_condition_base, Subquery,
+                decorrelate, field_with_table_name, query_from_condition_base, Subquery,
             };
-            use nom_sql::{JoinRightSide, Table};
+            use nom_sql::{ConditionBase, JoinRightSide, Table};
             match sq {
                 Subquery::InComparison(cond_base) => {
+                    let correlation = match (&mut *cond_base, &q) {
+                        (ConditionBase::NestedSelect(ns), SqlQuery::Select(outer)) => {
+                            let is_negated = negated.contains(&**ns);
+                            let correlation = decorrelate(ns, outer)?;
+                            if is_negated && !correlation.is_empty() {
+                                return Err(
+                                    "correlated NOT IN subqueries are not supported".to_owned()
+                                );
+                            }
+                            correlation
+                        }
+                        _ => vec![],
+                    };
                     let (sq, column) = query_from_condition_base(&cond_base);
 
                     let qfp = self
                         .add_parsed_query(sq, None, false, mig)
                         .expect("failed to add subquery");
                     *cond_base = field_with_table_name(qfp.name.clone(), column);
+                    if !correlation.is_empty() {
+                        correlated.push((qfp.name.clone(), correlation));
+                    }
                 }
                 Subquery::InJoin(join_right_side) => {
                     *join_right_side = match *join_right_side {
@@ -827,6 +1101,61 @@ impl SqlIncorporator {
             }
         }
 
+        if let SqlQuery::Select(ref mut st) = fq {
+            use nom_sql::{
+                ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
+                Operator, Table,
+            };
+            for (view, correlation) in correlated {
+                // the view joins the outer query, but must not add to the columns it selects
+                if st.fields.contains(&FieldDefinitionExpression::All) {
+                    let tables = st.tables.clone();
+                    st.fields.retain(|f| *f != FieldDefinitionExpression::All);
+                    for (i, t) in tables.into_iter().enumerate() {
+                        st.fields
+                            .insert(i, FieldDefinitionExpression::AllInTable(t.name));
+                    }
+                }
+                st.tables.push(Table::from(view.as_str()));
+                for (inner, outer) in correlation {
+                    let field = |c| Box::new(ConditionExpression::Base(c));
+                    let join = ConditionExpression::ComparisonOp(ConditionTree {
+                        operator: Operator::Equal,
+                        left: field(ConditionBase::Field(outer)),
+                        right: field(passes::subqueries::field_with_table_name(
+                            view.clone(),
+                            inner,
+                        )),
+                    });
+                    st.where_clause = Some(match st.where_clause.take() {
+                        None => join,
+                        Some(ce) => ConditionExpression::LogicalOp(ConditionTree {
+                            operator: Operator::And,
+                            left: Box::new(ce),
+                            right: Box::new(join),
+                        }),
+                    });
+                }
+            }
+        }
+
+        self.rewrite_flattened_query(fq, mig.context())
+    }
+
+    /// Runs the standard rewrite passes on a query without subqueries.
+    fn rewrite_flattened_query(
+        &self,
+        fq: SqlQuery,
+        context: &HashMap<String, DataType>,
+    ) -> Result<SqlQuery, String> {
+        use passes::alias_removal::AliasRemoval;
+        use passes::count_star_rewrite::CountStarRewrite;
+        use passes::implied_tables::ImpliedTableExpansion;
+        use passes::key_def_coalescing::KeyDefinitionCoalescing;
+        use passes::negation_removal::NegationRemoval;
+        use passes::star_expansion::StarExpansion;
+        use query_utils::ReferredTables;
+
         // Check that all tables mentioned in the query exist.
         // This must happen before the rewrite passes are applied because some of them rely on
         // having the table schema available in `self.view_schemas`.
@@ -854,7 +1183,7 @@ impl SqlIncorporator {
         // Run some standard rewrite passes on the query. This makes the later work easier,
         // as we no longer have to consider complications like aliases.
         Ok(fq
-            .expand_table_aliases(mig.context())
+            .expand_table_aliases(context)
             .remove_negation()
             .coalesce_key_definitions()
             .expand_stars(&self.view_schemas)
@@ -899,12 +1228,17 @@ impl SqlIncorporator {
                 // NOTE(malte): We can't currently reuse complete compound select queries, since
                 // our reuse logic operates on `SqlQuery` structures. Their subqueries do get
                 // reused, however.
-                self.add_compound_query(&query_name, &csq, is_leaf, mig)
-                    .unwrap()
+                self.add_compound_query(&query_name, &csq, is_leaf, mig)?
             }
             SqlQuery::Select(sq) => self.add_select_query(&query_name, &sq, is_leaf, mig)?.0,
             ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig),
-            q => panic!("unhandled query type in recipe: {:?}", q),
+            q => {
+                return Err(MirError::Unsupported(format!(
+                    "unhandled query type in recipe: {}",
+                    q
+                ))
+                .into());
+            }
         };
 
         // record info about query
@@ -1053,6 +1387,13 @@ mod tests {
                 .is_err());
             // Should still only have source, "users" and the two nodes for the above selection
             assert_eq!(mig.graph().node_count(), ncount + 2);
+
+            // Queries that parse, but cannot be added to the graph, should be rejected too
+            let err = "INSERT INTO users (id, name) VALUES (1, 'bob');"
+                .to_flow_parts(&mut inc, None, mig)
+                .unwrap_err();
+            assert!(err.contains("only CREATE TABLE and SELECT queries"));
+            assert_eq!(mig.graph().node_count(), ncount + 2);
         })
         .await;
     }
@@ -1103,11 +1444,12 @@ mod tests {
             );
             // join node
             let new_join_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
-            assert_eq!(new_join_view.fields(), &["id", "author", "title", "name"]);
+            // articles.id is not needed by anything below the join
+            assert_eq!(new_join_view.fields(), &["author", "title", "name"]);
             // leaf node
             let new_leaf_view = get_node(&inc, mig, &q.unwrap().name);
             assert_eq!(new_leaf_view.fields(), &["name", "title", "bogokey"]);
-            assert_eq!(new_leaf_view.description(true), "π[3, 2, lit: 0]");
+            assert_eq!(new_leaf_view.description(true), "π[2, 1, lit: 0]");
         })
         .await;
     }
@@ -1196,7 +1538,7 @@ mod tests {
             );
             let agg_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
             assert_eq!(agg_view.fields(), &["aid", "votes"]);
-            assert_eq!(agg_view.description(true), "|*| γ[0]");
+            assert_eq!(agg_view.description(true), "|σ(1)| γ[0]");
             // check edge view
             let edge_view = get_node(&inc, mig, &res.unwrap().name);
             assert_eq!(edge_view.fields(), &["votes", "bogokey"]);
@@ -1205,6 +1547,53 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_incorporates_having() {
+        // set up graph
+        let mut g = integration::start_simple("it_incorporates_having").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            // Establish a base write type
+            assert!(inc
+                .add_query("CREATE TABLE votes (aid int, userid int);", None, mig)
+                .is_ok());
+            let res = inc.add_query(
+                "SELECT votes.aid, COUNT(votes.userid) AS votes \
+                 FROM votes GROUP BY votes.aid HAVING votes > 5;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+            // added the aggregation, a filter on its result, the edge view, and a reader
+            assert_eq!(mig.graph().node_count(), 6);
+            let f = Box::new(FunctionExpression::Count(
+                FunctionArguments::Column(Column::from("votes.userid")),
+                false,
+            ));
+            let qid = query_id_hash(
+                &["computed_columns", "votes"],
+                &[&Column::from("votes.aid"), &Column::from("votes")],
+                &[
+                    &Column::from("votes.aid"),
+                    &Column {
+                        name: String::from("votes"),
+                        alias: Some(String::from("votes")),
+                        table: None,
+                        function: Some(f),
+                    },
+                ],
+            );
+            let agg_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
+            assert_eq!(agg_view.fields(), &["aid", "votes"]);
+            assert_eq!(agg_view.description(true), "|σ(1)| γ[0]");
+            // check the filter, which must come after the aggregation
+            let having_view = get_node(&inc, mig, &format!("q_{:x}_n1_h0_f0", qid));
+            assert_eq!(having_view.fields(), &["aid", "votes"]);
+            assert_eq!(having_view.description(true), "σ[f1 \\> 5]");
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_does_not_reuse_if_disabled() {
         // set up graph
@@ -1266,6 +1655,89 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_orders_parameter_columns_across_tables() {
+        // set up graph
+        let mut g = integration::start_simple("it_orders_parameter_columns_across_tables").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE votes (uid int, story int);", None, mig)
+                .is_ok());
+            assert!(inc
+                .add_query("CREATE TABLE stories (id int, title text);", None, mig)
+                .is_ok());
+
+            // the key columns follow the parameters, even if they come from different tables
+            let res = inc.add_query(
+                "SELECT votes.uid, stories.id, stories.title \
+                 FROM votes JOIN stories ON (votes.story = stories.id) \
+                 WHERE stories.id = ? AND votes.uid = ?;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+            let n = get_reader(&inc, mig, &res.unwrap().name);
+            let key: Vec<_> = n
+                .with_reader(|r| r.key().unwrap().to_vec())
+                .unwrap()
+                .into_iter()
+                .map(|c| n.fields()[c].clone())
+                .collect();
+            assert_eq!(key, &["id", "uid"]);
+        })
+        .await;
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn it_incorporates_range_parameters() {
+        // set up graph
+        let mut g = integration::start_simple("it_incorporates_range_parameters").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE stories (id int, score int);", None, mig)
+                .is_ok());
+
+            // a range parameter keys the reader on its column, and remembers the comparison
+            let res = inc.add_query("SELECT id, score FROM stories WHERE score > ?;", None, mig);
+            assert!(res.is_ok());
+            let n = get_reader(&inc, mig, &res.unwrap().name);
+            n.with_reader(|r| {
+                assert_eq!(r.key().unwrap(), &[1]);
+                assert_eq!(r.range(), Some(&Operator::Greater));
+            })
+            .unwrap();
+
+            // the same query with an equality parameter must not reuse that reader
+            let res = inc.add_query("SELECT id, score FROM stories WHERE score = ?;", None, mig);
+            assert!(res.is_ok());
+            let n = get_reader(&inc, mig, &res.unwrap().name);
+            n.with_reader(|r| {
+                assert_eq!(r.key().unwrap(), &[1]);
+                assert_eq!(r.range(), None);
+            })
+            .unwrap();
+
+            // ranges only work on their own, and not below aggregations
+            assert!(inc
+                .add_query(
+                    "SELECT id FROM stories WHERE score > ? AND id = ?;",
+                    None,
+                    mig
+                )
+                .is_err());
+            assert!(inc
+                .add_query(
+                    "SELECT COUNT(id) AS n FROM stories WHERE score > ?;",
+                    None,
+                    mig
+                )
+                .is_err());
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_reuses_identical_query() {
         // set up graph
@@ -1435,6 +1907,54 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_reuses_shared_subplans_of_unrelated_queries() {
+        // set up graph
+        let mut g =
+            integration::start_simple("it_reuses_shared_subplans_of_unrelated_queries").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE articles (id int, author int, status int);",
+                    None,
+                    mig
+                )
+                .is_ok());
+            assert!(inc
+                .add_query("CREATE TABLE users (id int, name varchar(40));", None, mig)
+                .is_ok());
+            assert!(inc
+                .add_query("CREATE TABLE votes (aid int, uid int);", None, mig)
+                .is_ok());
+
+            // neither query's graph generalizes the other's, but both filter articles the same way
+            let res = inc.add_query(
+                "SELECT articles.id, users.name FROM articles, users \
+                 WHERE articles.author = users.id AND articles.status = 1;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+            let res = inc.add_query(
+                "SELECT articles.id, votes.uid FROM articles, votes \
+                 WHERE votes.aid = articles.id AND articles.status = 1;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+
+            let filters = mig
+                .graph()
+                .node_indices()
+                .map(|ni| &mig.graph()[ni])
+                .filter(|n| n.is_internal() && n.description(true).starts_with('σ'))
+                .count();
+            assert_eq!(filters, 1);
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_incorporates_aggregation_no_group_by() {
         // set up graph
@@ -1476,7 +1996,7 @@ mod tests {
             // check aggregation view
             let agg_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
             assert_eq!(agg_view.fields(), &["grp", "count"]);
-            assert_eq!(agg_view.description(true), "|*| γ[1]");
+            assert_eq!(agg_view.description(true), "|σ(0)| γ[1]");
             // check edge view -- note that it's not actually currently possible to read from
             // this for a lack of key (the value would be the key). Hence, the view also has a
             // bogokey column.
@@ -1513,7 +2033,10 @@ mod tests {
             assert_eq!(mig.graph().node_count(), 5);
             // check aggregation view
             let f = Box::new(FunctionExpression::Count(
-                FunctionArguments::Column(Column::from("votes.aid")),
+                FunctionArguments::Column(Column {
+                    function: Some(Box::new(FunctionExpression::CountStar)),
+                    ..Column::from("votes.aid")
+                }),
                 false,
             ));
             let qid = query_id_hash(
@@ -2054,11 +2577,8 @@ mod tests {
             // articles join users
             assert_eq!(join1_view.fields(), &["aid", "title", "author", "name"]);
             let join2_view = get_node(&inc, mig, &format!("q_{:x}_n1", qid));
-            // join1_view join vptes
-            assert_eq!(
-                join2_view.fields(),
-                &["aid", "title", "author", "name", "uid"]
-            );
+            // join1_view join vptes; the author is only needed for the first join
+            assert_eq!(join2_view.fields(), &["aid", "title", "name", "uid"]);
             // leaf view
             let leaf_view = get_node(&inc, mig, "q_3");
             assert_eq!(leaf_view.fields(), &["name", "title", "uid", "bogokey"]);
@@ -2066,6 +2586,77 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_incorporates_star_join_as_multiway_join() {
+        use crate::controller::sql::cost::TableStatistics;
+        use std::collections::HashMap;
+
+        // set up graph
+        let mut g = integration::start_simple("it_incorporates_star_join_as_multiway_join").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE sales (product int, store int, amount int);",
+                    None,
+                    mig
+                )
+                .is_ok());
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE products (id int, name varchar(40));",
+                    None,
+                    mig
+                )
+                .is_ok());
+            assert!(inc
+                .add_query("CREATE TABLE stores (id int, city varchar(40));", None, mig)
+                .is_ok());
+
+            // a large table joined with two small ones
+            let table = |rows, distinct: &[(&str, u64)]| TableStatistics {
+                rows,
+                distinct: distinct.iter().map(|&(c, d)| (c.to_owned(), d)).collect(),
+            };
+            let mut stats = HashMap::new();
+            stats.insert(
+                "sales".to_owned(),
+                table(100_000, &[("product", 100), ("store", 10)]),
+            );
+            stats.insert("products".to_owned(), table(100, &[("id", 100)]));
+            stats.insert("stores".to_owned(), table(10, &[("id", 10)]));
+            inc.set_table_statistics(stats);
+
+            let q = "SELECT products.name, stores.city, sales.amount \
+                     FROM sales, products, stores \
+                     WHERE sales.product = products.id AND sales.store = stores.id;";
+            assert!(inc.add_query(q, None, mig).is_ok());
+            let qid = query_id_hash(
+                &["products", "sales", "stores"],
+                &[
+                    &Column::from("products.id"),
+                    &Column::from("sales.product"),
+                    &Column::from("sales.store"),
+                    &Column::from("stores.id"),
+                ],
+                &[
+                    &Column::from("products.name"),
+                    &Column::from("stores.city"),
+                    &Column::from("sales.amount"),
+                ],
+            );
+            // all three tables are joined at once, and the join columns of the small ones are
+            // only output as those of sales
+            let join_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
+            assert_eq!(join_view.ancestors().len(), 3);
+            assert_eq!(join_view.fields().len(), 5);
+            assert_eq!(&join_view.fields()[..3], &["product", "store", "amount"]);
+            let leaf_view = get_node(&inc, mig, "q_3");
+            assert_eq!(leaf_view.fields(), &["name", "city", "amount", "bogokey"]);
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     #[ignore]
     async fn it_incorporates_join_projecting_join_columns() {
@@ -2100,14 +2691,15 @@ mod tests {
             );
             // join node
             let new_join_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
-            assert_eq!(new_join_view.fields(), &["id", "author", "title", "name"]);
+            // users.id is the join column, so articles.id is not needed by anything below the join
+            assert_eq!(new_join_view.fields(), &["author", "title", "name"]);
             // leaf node
             let new_leaf_view = get_node(&inc, mig, &q.unwrap().name);
             assert_eq!(
                 new_leaf_view.fields(),
                 &["id", "name", "author", "title", "bogokey"]
             );
-            assert_eq!(new_leaf_view.description(true), "π[1, 3, 1, 2, lit: 0]");
+            assert_eq!(new_leaf_view.description(true), "π[0, 2, 0, 1, lit: 0]");
         })
         .await;
     }
@@ -2220,11 +2812,12 @@ mod tests {
             );
             // join node
             let new_join_view = get_node(&inc, mig, &format!("q_{:x}_n0", qid));
-            assert_eq!(new_join_view.fields(), &["id", "author", "title", "name"]);
+            // articles.id is not needed by anything below the join
+            assert_eq!(new_join_view.fields(), &["author", "title", "name"]);
             // leaf node
             let new_leaf_view = get_node(&inc, mig, &q.unwrap().name);
             assert_eq!(new_leaf_view.fields(), &["name", "title", "bogokey"]);
-            assert_eq!(new_leaf_view.description(true), "π[3, 2, lit: 0]");
+            assert_eq!(new_leaf_view.description(true), "π[2, 1, lit: 0]");
         })
         .await;
     }
@@ -2242,7 +2835,7 @@ mod tests {
             let res = inc.add_query(
                 "SELECT users.id, users.name FROM users \
                  WHERE users.id = 32 \
-                 UNION \
+                 UNION ALL \
                  SELECT users.id, users.name FROM users \
                  WHERE users.id = 42 AND users.name = 'bob';",
                 None,
@@ -2253,7 +2846,51 @@ mod tests {
             // the leaf of this query (node above the reader) is a union
             let union_view = get_node(&inc, mig, &res.unwrap().name);
             assert_eq!(union_view.fields(), &["id", "name"]);
-            assert_eq!(union_view.description(true), "3:[0, 1] ⋃ 6:[0, 1]");
+            assert_eq!(union_view.description(true), "3:[0, 1] ⋃ 5:[0, 1]");
+        })
+        .await;
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn it_incorporates_distinct_compound_selection() {
+        // set up graph
+        let mut g = integration::start_simple("it_incorporates_distinct_compound_selection").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE users (id int, name varchar(40));", None, mig)
+                .is_ok());
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE admins (uid int, login varchar(40));",
+                    None,
+                    mig
+                )
+                .is_ok());
+
+            let res = inc.add_query(
+                "SELECT users.id, users.name FROM users \
+                 UNION \
+                 SELECT admins.uid, admins.login FROM admins;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+
+            // the union is followed by a count of each record, a filter, and a projection
+            let distinct_view = get_node(&inc, mig, &res.unwrap().name);
+            assert_eq!(distinct_view.fields(), &["id", "name"]);
+            assert_eq!(distinct_view.description(true), "π[0, 1]");
+
+            // queries that select different numbers of columns cannot be unioned
+            let res = inc.add_query(
+                "SELECT users.id, users.name FROM users \
+                 UNION ALL \
+                 SELECT admins.uid FROM admins;",
+                None,
+                mig,
+            );
+            assert!(res.is_err());
         })
         .await;
     }
@@ -2332,4 +2969,33 @@ mod tests {
         })
         .await;
     }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn it_shares_policy_views_until_forgotten() {
+        use crate::controller::sql::security::Multiverse;
+        use nom_sql::parser::parse_query;
+
+        let mut g = integration::start_simple("it_shares_policy_views_until_forgotten").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE posts (author int, public int);", None, mig)
+                .is_ok());
+            let policy = || parse_query("SELECT * FROM posts WHERE posts.public = 1;").unwrap();
+
+            let (name, qfp) = inc.shared_policy_view("posts", policy(), mig).unwrap();
+            assert!(qfp.is_some());
+            // a policy with the same signature reuses the view
+            let (again, qfp) = inc.shared_policy_view("posts", policy(), mig).unwrap();
+            assert_eq!(again, name);
+            assert!(qfp.is_none());
+
+            // once the policies of the table change, the view is installed anew
+            inc.forget_shared_policies(&vec!["posts".to_owned()].into_iter().collect());
+            let (fresh, qfp) = inc.shared_policy_view("posts", policy(), mig).unwrap();
+            assert_ne!(fresh, name);
+            assert!(qfp.is_some());
+        })
+        .await;
+    }
 }
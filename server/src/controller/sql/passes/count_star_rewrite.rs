@@ -1,6 +1,6 @@
 use nom_sql::{
     Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    FunctionArguments, SqlQuery, Table,
+    FunctionArguments, FunctionExpression, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
@@ -9,6 +9,15 @@ pub trait CountStarRewrite {
     fn rewrite_count_star(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery;
 }
 
+/// Whether `col`, the argument of a `COUNT`, is the column that a `COUNT(*)` was rewritten to
+/// count over.
+///
+/// `COUNT(*)` counts all rows, but `COUNT(col)` only counts those where `col` is not `NULL`, so
+/// the rewrite marks the column it picks with the `COUNT(*)` it stands in for.
+pub fn counts_rows(col: &Column) -> bool {
+    col.function.as_deref() == Some(&FunctionExpression::CountStar)
+}
+
 fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
     match *ce {
         ConditionExpression::LogicalOp(ConditionTree {
@@ -41,6 +50,23 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
     }
 }
 
+fn rewrite_condition_columns<F>(ce: &mut ConditionExpression, f: &mut F)
+where
+    F: FnMut(&mut Column),
+{
+    match *ce {
+        ConditionExpression::LogicalOp(ref mut ct)
+        | ConditionExpression::ComparisonOp(ref mut ct) => {
+            rewrite_condition_columns(&mut ct.left, f);
+            rewrite_condition_columns(&mut ct.right, f);
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => rewrite_condition_columns(inner, f),
+        ConditionExpression::Base(ConditionBase::Field(ref mut c)) => f(c),
+        _ => (),
+    }
+}
+
 impl CountStarRewrite for SqlQuery {
     fn rewrite_count_star(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery {
         use nom_sql::FunctionExpression::*;
@@ -67,7 +93,7 @@ impl CountStarRewrite for SqlQuery {
                             name: bogo_column.clone(),
                             alias: None,
                             table: Some(bogo_table.name.clone()),
-                            function: None,
+                            function: Some(Box::new(CountStar)),
                         }),
                         false,
                     )));
@@ -96,6 +122,13 @@ impl CountStarRewrite for SqlQuery {
                         }
                     }
                 }
+                // Expand within HAVING clause, so that it refers to the same aggregation as any
+                // COUNT(*) in the field list
+                if let Some(ref mut h) = sq.group_by.as_mut().and_then(|gb| gb.having.as_mut()) {
+                    rewrite_condition_columns(h, &mut |c| {
+                        rewrite_count_star(c, &tables, &avoid_cols)
+                    });
+                }
                 // TODO: also expand function columns within WHERE clause
                 SqlQuery::Select(sq)
             }
@@ -107,7 +140,7 @@ impl CountStarRewrite for SqlQuery {
 
 #[cfg(test)]
 mod tests {
-    use super::CountStarRewrite;
+    use super::{counts_rows, CountStarRewrite};
     use nom_sql::{Column, FieldDefinitionExpression, SqlQuery};
     use std::collections::HashMap;
 
@@ -136,7 +169,10 @@ mod tests {
                         alias: None,
                         table: None,
                         function: Some(Box::new(FunctionExpression::Count(
-                            FunctionArguments::Column(Column::from("users.id")),
+                            FunctionArguments::Column(Column {
+                                function: Some(Box::new(FunctionExpression::CountStar)),
+                                ..Column::from("users.id")
+                            }),
                             false,
                         ))),
                     })]
@@ -172,7 +208,10 @@ mod tests {
                         alias: None,
                         table: None,
                         function: Some(Box::new(FunctionExpression::Count(
-                            FunctionArguments::Column(Column::from("users.name")),
+                            FunctionArguments::Column(Column {
+                                function: Some(Box::new(FunctionExpression::CountStar)),
+                                ..Column::from("users.name")
+                            }),
                             false,
                         ))),
                     })]
@@ -182,4 +221,37 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn it_tells_count_star_from_count() {
+        use nom_sql::parser::parse_query;
+        use nom_sql::{FunctionArguments, FunctionExpression};
+
+        let q = parse_query("SELECT COUNT(*), COUNT(users.age) FROM users;").unwrap();
+        let mut schema = HashMap::new();
+        schema.insert(
+            "users".into(),
+            vec!["id".into(), "name".into(), "age".into()],
+        );
+
+        let counted: Vec<_> = match q.rewrite_count_star(&schema) {
+            SqlQuery::Select(tq) => tq
+                .fields
+                .into_iter()
+                .map(|f| match f {
+                    FieldDefinitionExpression::Col(Column {
+                        function: Some(f), ..
+                    }) => match *f {
+                        FunctionExpression::Count(FunctionArguments::Column(c), _) => c,
+                        _ => panic!(),
+                    },
+                    _ => panic!(),
+                })
+                .collect(),
+            _ => panic!(),
+        };
+        assert_eq!(counted.len(), 2);
+        assert!(counts_rows(&counted[0]));
+        assert!(!counts_rows(&counted[1]));
+    }
 }
@@ -3,5 +3,6 @@ pub mod count_star_rewrite;
 pub mod implied_tables;
 pub mod key_def_coalescing;
 pub mod negation_removal;
+pub mod policy_parameterization;
 pub mod star_expansion;
 pub mod subqueries;
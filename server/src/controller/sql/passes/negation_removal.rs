@@ -26,6 +26,24 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
             normalize_condition_expr(left, negate);
             normalize_condition_expr(right, negate);
         }
+        ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::In,
+            ref mut right,
+            ..
+        }) => {
+            // `NOT IN` is an `IN` whose right-hand side is negated, so negating an `IN` negates
+            // its right-hand side, and the negation of a `NOT IN` is an `IN`
+            if negate {
+                let r = mem::replace(
+                    &mut **right,
+                    ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+                );
+                **right = match r {
+                    ConditionExpression::NegationOp(inner) => *inner,
+                    r => ConditionExpression::NegationOp(Box::new(r)),
+                };
+            }
+        }
         ConditionExpression::ComparisonOp(ConditionTree {
             ref mut operator,
             ref mut left,
@@ -73,6 +91,10 @@ impl NegationRemoval for SqlQuery {
                 normalize_condition_expr(w, false);
             }
 
+            if let Some(ref mut h) = s.group_by.as_mut().and_then(|gb| gb.having.as_mut()) {
+                normalize_condition_expr(h, false);
+            }
+
             for j in s.join.iter_mut() {
                 if let JoinConstraint::On(ref mut ce) = j.constraint {
                     normalize_condition_expr(ce, false);
@@ -122,4 +144,31 @@ mod tests {
         normalize_condition_expr(&mut expr, false);
         assert_eq!(expr, target);
     }
+
+    #[test]
+    fn it_negates_in_comparisons() {
+        let field = |f: &str| Box::new(ConditionExpression::Base(ConditionBase::Field(f.into())));
+        let in_comparison = |right| {
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: field("a"),
+                right,
+            })
+        };
+        let not_in = in_comparison(Box::new(ConditionExpression::NegationOp(field("v.b"))));
+
+        // NOT (a IN v.b) is a NOT IN
+        let mut expr = ConditionExpression::NegationOp(Box::new(in_comparison(field("v.b"))));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, not_in);
+
+        // which is left alone
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, not_in);
+
+        // and whose negation is an IN again
+        let mut expr = ConditionExpression::NegationOp(Box::new(not_in));
+        normalize_condition_expr(&mut expr, false);
+        assert_eq!(expr, in_comparison(field("v.b")));
+    }
 }
@@ -0,0 +1,163 @@
+use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, Operator, SqlQuery};
+
+/// Row policies that differ between universes only in the user id can share their dataflow
+/// subtree across universes. This pass recognizes such policies: those whose only reference to
+/// the universe's context is a single `table.column = UserContext.id` conjunct.
+pub trait PolicyParameterization {
+    /// Returns the policy with its user id equality removed, together with the column that was
+    /// compared against the user id, or `None` if the policy cannot be shared between universes.
+    fn parameterize_user_context(&self) -> Option<(SqlQuery, Column)>;
+}
+
+fn is_user_context(c: &Column) -> bool {
+    c.table.as_ref().map(String::as_str) == Some("UserContext")
+}
+
+fn refers_to_user_context(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) | ConditionExpression::ComparisonOp(ref ct) => {
+            refers_to_user_context(&ct.left) || refers_to_user_context(&ct.right)
+        }
+        ConditionExpression::Bracketed(ref inner) | ConditionExpression::NegationOp(ref inner) => {
+            refers_to_user_context(inner)
+        }
+        ConditionExpression::Base(ConditionBase::Field(ref f)) => is_user_context(f),
+        // we can't see into nested queries or arithmetic, so be conservative
+        ConditionExpression::Base(ConditionBase::NestedSelect(_)) => true,
+        ConditionExpression::Arithmetic(_) => true,
+        ConditionExpression::Base(_) => false,
+    }
+}
+
+/// If `ce` is `t.c = UserContext.id` (or the reverse), returns `t.c`.
+fn user_id_equality(ce: &ConditionExpression) -> Option<Column> {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref ct) if ct.operator == Operator::Equal => {
+            match (&*ct.left, &*ct.right) {
+                (
+                    ConditionExpression::Base(ConditionBase::Field(ref l)),
+                    ConditionExpression::Base(ConditionBase::Field(ref r)),
+                ) => match (is_user_context(l), is_user_context(r)) {
+                    (false, true) if r.name == "id" => Some(l.clone()),
+                    (true, false) if l.name == "id" => Some(r.clone()),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Splits a conjunction into the user id column and the remaining conjuncts (if any). Fails if
+/// the user id equality is missing, is under a disjunction, or if any other conjunct also
+/// refers to `UserContext`.
+fn split_user_id_conjunct(
+    ce: ConditionExpression,
+) -> Option<(Column, Option<ConditionExpression>)> {
+    if let Some(col) = user_id_equality(&ce) {
+        return Some((col, None));
+    }
+
+    let and = |l: ConditionExpression, r: ConditionExpression| {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(l),
+            right: Box::new(r),
+        })
+    };
+
+    match ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left,
+            right,
+        }) => {
+            if !refers_to_user_context(&right) {
+                let (col, rest) = split_user_id_conjunct(*left)?;
+                let rest = match rest {
+                    Some(l) => and(l, *right),
+                    None => *right,
+                };
+                Some((col, Some(rest)))
+            } else if !refers_to_user_context(&left) {
+                let (col, rest) = split_user_id_conjunct(*right)?;
+                let rest = match rest {
+                    Some(r) => and(*left, r),
+                    None => *left,
+                };
+                Some((col, Some(rest)))
+            } else {
+                None
+            }
+        }
+        ConditionExpression::Bracketed(inner) => split_user_id_conjunct(*inner),
+        _ => None,
+    }
+}
+
+impl PolicyParameterization for SqlQuery {
+    fn parameterize_user_context(&self) -> Option<(SqlQuery, Column)> {
+        match *self {
+            SqlQuery::Select(ref sq) => {
+                // policies that join against other views may pull in further universe-specific
+                // state, so we leave those alone
+                if !sq.join.is_empty() || sq.tables.iter().any(|t| t.name == "UserContext") {
+                    return None;
+                }
+
+                let (col, rest) = split_user_id_conjunct(sq.where_clause.clone()?)?;
+                let mut shared = sq.clone();
+                shared.where_clause = rest;
+                Some((SqlQuery::Select(shared), col))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PolicyParameterization;
+    use nom_sql::parser as sql_parser;
+    use nom_sql::{Column, SqlQuery};
+
+    fn parse(q: &str) -> SqlQuery {
+        sql_parser::parse_query(q).unwrap()
+    }
+
+    #[test]
+    fn it_parameterizes_user_id_policy() {
+        let q = parse("SELECT * FROM post WHERE post.author = UserContext.id;");
+        let (shared, col) = q.parameterize_user_context().unwrap();
+        assert_eq!(col, Column::from("post.author"));
+        match shared {
+            SqlQuery::Select(sq) => assert_eq!(sq.where_clause, None),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn it_keeps_remaining_predicates() {
+        let q =
+            parse("SELECT * FROM post WHERE post.private = 1 AND UserContext.id = post.author;");
+        let (shared, col) = q.parameterize_user_context().unwrap();
+        assert_eq!(col, Column::from("post.author"));
+        assert_eq!(shared, parse("SELECT * FROM post WHERE post.private = 1;"));
+    }
+
+    #[test]
+    fn it_does_not_parameterize_other_context_references() {
+        let q = parse("SELECT * FROM post WHERE post.author = UserContext.id OR post.public = 1;");
+        assert!(q.parameterize_user_context().is_none());
+
+        let q = parse(
+            "SELECT * FROM post \
+             WHERE post.author = UserContext.id AND post.class = UserContext.class;",
+        );
+        assert!(q.parameterize_user_context().is_none());
+
+        let q = parse("SELECT * FROM post WHERE post.public = 1;");
+        assert!(q.parameterize_user_context().is_none());
+    }
+}
@@ -1,5 +1,8 @@
 use nom_sql::ConditionExpression::*;
-use nom_sql::{Column, ConditionBase, ConditionExpression, JoinRightSide, SqlQuery};
+use nom_sql::{
+    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
+    JoinRightSide, Operator, SelectStatement, SqlQuery,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum Subquery<'a> {
@@ -62,6 +65,158 @@ pub fn query_from_condition_base(cond: &ConditionBase) -> (SqlQuery, Column) {
     (sq, column)
 }
 
+/// The subqueries of `ce` whose rows must *not* contain the value they are compared with, as in
+/// `NOT IN`.
+pub fn negated_subqueries(ce: &ConditionExpression) -> Vec<&SelectStatement> {
+    fn walk<'a>(ce: &'a ConditionExpression, negated: bool, out: &mut Vec<&'a SelectStatement>) {
+        match *ce {
+            ComparisonOp(ref ct) | LogicalOp(ref ct) => {
+                walk(&ct.left, negated, out);
+                walk(&ct.right, negated, out);
+            }
+            NegationOp(ref inner) => walk(inner, !negated, out),
+            Bracketed(ref inner) => walk(inner, negated, out),
+            Base(ConditionBase::NestedSelect(ref sq)) if negated => out.push(sq),
+            Base(_) | Arithmetic(_) => (),
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(ce, false, &mut out);
+    out
+}
+
+/// The names by which the relations of `st` can be referred to.
+fn relation_names(st: &SelectStatement) -> Vec<&str> {
+    let tables = st
+        .tables
+        .iter()
+        .chain(st.join.iter().filter_map(|jc| match jc.right {
+            JoinRightSide::Table(ref t) => Some(t),
+            _ => None,
+        }));
+    tables
+        .flat_map(|t| std::iter::once(t.name.as_str()).chain(t.alias.as_ref().map(String::as_str)))
+        .collect()
+}
+
+fn split_conjuncts(ce: ConditionExpression, conjuncts: &mut Vec<ConditionExpression>) {
+    match ce {
+        LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left,
+            right,
+        }) => {
+            split_conjuncts(*left, conjuncts);
+            split_conjuncts(*right, conjuncts);
+        }
+        Bracketed(inner) => split_conjuncts(*inner, conjuncts),
+        ce => conjuncts.push(ce),
+    }
+}
+
+/// Removes the correlation between the subquery `sq` and the query `outer` that it is nested in,
+/// so that the subquery can be added as a view of its own and joined with the outer query.
+///
+/// Only equality comparisons between a column of the subquery and a column of the outer query
+/// that are conjuncts of the subquery's `WHERE` clause can be decorrelated. They are removed
+/// from the subquery, which projects its side of each comparison instead. Returns those columns
+/// along with the outer query columns that they must be equal to.
+pub fn decorrelate(
+    sq: &mut SelectStatement,
+    outer: &SelectStatement,
+) -> Result<Vec<(Column, Column)>, String> {
+    let inner_relations = relation_names(sq);
+    let outer_relations = relation_names(outer);
+    let is_outer = |c: &Column| match c.table {
+        Some(ref t) => {
+            !inner_relations.contains(&t.as_str()) && outer_relations.contains(&t.as_str())
+        }
+        None => false,
+    };
+    fn refers_to<F: Fn(&Column) -> bool>(ce: &ConditionExpression, f: &F) -> bool {
+        match *ce {
+            LogicalOp(ref ct) | ComparisonOp(ref ct) => {
+                refers_to(&ct.left, f) || refers_to(&ct.right, f)
+            }
+            NegationOp(ref inner) | Bracketed(ref inner) => refers_to(inner, f),
+            Base(ConditionBase::Field(ref c)) => f(c),
+            Base(_) | Arithmetic(_) => false,
+        }
+    }
+
+    let mut conjuncts = Vec::new();
+    if let Some(ce) = sq.where_clause.take() {
+        split_conjuncts(ce, &mut conjuncts);
+    }
+
+    let mut correlated = Vec::new();
+    let mut kept = Vec::new();
+    for ce in conjuncts {
+        if !refers_to(&ce, &is_outer) {
+            kept.push(ce);
+            continue;
+        }
+        let pair = match ce {
+            ComparisonOp(ref ct) if ct.operator == Operator::Equal => match (&*ct.left, &*ct.right)
+            {
+                (Base(ConditionBase::Field(ref l)), Base(ConditionBase::Field(ref r))) => {
+                    match (is_outer(l), is_outer(r)) {
+                        (false, true) => Some((l.clone(), r.clone())),
+                        (true, false) => Some((r.clone(), l.clone())),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match pair {
+            Some(pair) => correlated.push(pair),
+            None => {
+                return Err(
+                    "correlated subqueries may only compare outer columns for equality".to_owned(),
+                )
+            }
+        }
+    }
+
+    if !correlated.is_empty() {
+        let aggregated = sq.fields.iter().any(|f| match *f {
+            FieldDefinitionExpression::Col(ref c) => c.function.is_some(),
+            _ => false,
+        });
+        if aggregated || sq.group_by.is_some() || sq.limit.is_some() {
+            return Err(
+                "correlated subqueries may not aggregate, group, or have a LIMIT".to_owned(),
+            );
+        }
+    }
+
+    sq.where_clause = kept.into_iter().fold(None, |acc, ce| match acc {
+        None => Some(ce),
+        Some(acc) => Some(LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(acc),
+            right: Box::new(ce),
+        })),
+    });
+
+    // the subquery must now project the columns the outer query joins on
+    for &(ref inner, _) in &correlated {
+        let projected = sq.fields.iter().any(|f| match *f {
+            FieldDefinitionExpression::Col(ref c) => c == inner,
+            _ => false,
+        });
+        if !projected {
+            sq.fields
+                .push(FieldDefinitionExpression::Col(inner.clone()));
+        }
+    }
+
+    Ok(correlated)
+}
+
 impl SubQueries for SqlQuery {
     fn extract_subqueries(&mut self) -> Vec<Subquery> {
         let mut subqueries = Vec::new();
@@ -186,4 +341,61 @@ mod tests {
 
         assert_eq!(res, expected);
     }
+
+    fn select(sql: &str) -> SelectStatement {
+        match nom_sql::parser::parse_query(sql).unwrap() {
+            SqlQuery::Select(st) => st,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_decorrelates_equalities() {
+        let outer = select("SELECT p.pid FROM post AS p WHERE p.author = 1;");
+        let mut sq = select("SELECT r.uid FROM role AS r WHERE r.kind = 1 AND r.org = p.org;");
+
+        let correlated = decorrelate(&mut sq, &outer).unwrap();
+        assert_eq!(
+            correlated,
+            vec![(Column::from("r.org"), Column::from("p.org"))]
+        );
+        assert_eq!(
+            sq,
+            select("SELECT r.uid, r.org FROM role AS r WHERE r.kind = 1;")
+        );
+    }
+
+    #[test]
+    fn it_leaves_uncorrelated_subqueries_alone() {
+        let outer = select("SELECT pid FROM post WHERE author = 1;");
+        let mut sq = select("SELECT uid FROM role WHERE role.kind = 1;");
+        let expected = sq.clone();
+
+        assert_eq!(decorrelate(&mut sq, &outer).unwrap(), vec![]);
+        assert_eq!(sq, expected);
+    }
+
+    #[test]
+    fn it_rejects_other_correlations() {
+        let outer = select("SELECT pid FROM post WHERE author = 1;");
+        let mut sq = select("SELECT uid FROM role WHERE role.org > post.org;");
+        assert!(decorrelate(&mut sq, &outer).is_err());
+
+        let mut sq = select("SELECT uid FROM role WHERE role.org = post.org OR role.kind = 1;");
+        assert!(decorrelate(&mut sq, &outer).is_err());
+    }
+
+    #[test]
+    fn it_finds_negated_subqueries() {
+        let st = select(
+            "SELECT pid FROM post \
+             WHERE author NOT IN (SELECT uid FROM role WHERE role.kind = 1) \
+             AND org IN (SELECT oid FROM org);",
+        );
+        let expected = select("SELECT uid FROM role WHERE role.kind = 1;");
+        assert_eq!(
+            negated_subqueries(st.where_clause.as_ref().unwrap()),
+            vec![&expected]
+        );
+    }
 }
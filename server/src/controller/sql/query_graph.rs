@@ -147,6 +147,8 @@ pub struct QueryGraphNode {
 pub enum QueryGraphEdge {
     Join(Vec<ConditionTree>),
     LeftJoin(Vec<ConditionTree>),
+    /// Rows of the source relation that match no row of the destination relation; `NOT IN`.
+    AntiJoin(Vec<ConditionTree>),
     GroupBy(Vec<Column>),
 }
 
@@ -162,8 +164,19 @@ pub struct QueryGraph {
     /// Establishes an order for join predicates. Each join predicate can be identified by
     /// its (src, dst) pair, and its index in the array of predicates.
     pub join_order: Vec<JoinRef>,
+    /// Whether the joins are performed by a single multi-way join rather than by a chain of
+    /// binary ones.
+    pub multiway_join: bool,
     /// Global predicates (not associated with a particular relation)
     pub global_predicates: Vec<ConditionExpression>,
+    /// Predicates from the HAVING clause, which apply to the output of the aggregations
+    pub having_predicates: Vec<ConditionExpression>,
+    /// Comparison of the query's only parameter if it is a range parameter (e.g., `score > ?`),
+    /// rather than an equality one.
+    pub range_operator: Option<Operator>,
+    /// Parameter columns of all relations, in the order in which they appear in the query; this
+    /// is the order of the columns in the key of the query's reader.
+    pub parameter_order: Vec<Column>,
 }
 
 impl QueryGraph {
@@ -173,19 +186,18 @@ impl QueryGraph {
             edges: HashMap::new(),
             columns: Vec::new(),
             join_order: Vec::new(),
+            multiway_join: false,
             global_predicates: Vec::new(),
+            having_predicates: Vec::new(),
+            range_operator: None,
+            parameter_order: Vec::new(),
         }
     }
 
-    /// Returns the set of columns on which this query is parameterized. They can come from
-    /// multiple tables involved in the query.
-    pub fn parameters<'a>(&'a self) -> Vec<&'a Column> {
-        self.relations
-            .values()
-            .fold(Vec::new(), |mut acc: Vec<&'a Column>, qgn| {
-                acc.extend(qgn.parameters.iter());
-                acc
-            })
+    /// Returns the set of columns on which this query is parameterized, in the order in which
+    /// they appear in the query. They can come from multiple tables involved in the query.
+    pub fn parameters(&self) -> Vec<&Column> {
+        self.parameter_order.iter().collect()
     }
 
     pub fn exact_hash(&self) -> u64 {
@@ -211,10 +223,52 @@ impl Hash for QueryGraph {
         });
         edges.hash(state);
 
-        // columns and join_order are Vecs, so already ordered
+        // columns is a Vec, so already ordered. the join order and the choice of a multi-way join
+        // are left out: they are made when the query is planned, and do not change what the query
+        // computes.
         self.columns.hash(state);
-        self.join_order.hash(state);
         self.global_predicates.hash(state);
+        self.having_predicates.hash(state);
+        self.range_operator.hash(state);
+        self.parameter_order.hash(state);
+    }
+}
+
+/// Rewrites the aggregates in a HAVING condition to refer to the computed columns that hold them,
+/// and collects any aggregates that are not among the `selected` ones into `unselected`.
+fn resolve_having_columns(
+    ce: &mut ConditionExpression,
+    selected: &[Column],
+    unselected: &mut Vec<Column>,
+) {
+    match *ce {
+        ConditionExpression::LogicalOp(ref mut ct)
+        | ConditionExpression::ComparisonOp(ref mut ct) => {
+            resolve_having_columns(&mut ct.left, selected, unselected);
+            resolve_having_columns(&mut ct.right, selected, unselected);
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => {
+            resolve_having_columns(inner, selected, unselected)
+        }
+        ConditionExpression::Base(ConditionBase::Field(ref mut c)) if c.function.is_some() => {
+            let name = match selected.iter().find(|s| s.function == c.function) {
+                Some(s) => s.alias.clone().unwrap_or_else(|| s.name.clone()),
+                None => {
+                    if !unselected.contains(c) {
+                        unselected.push(c.clone());
+                    }
+                    c.name.clone()
+                }
+            };
+            *c = Column {
+                name,
+                alias: None,
+                table: None,
+                function: None,
+            };
+        }
+        _ => (),
     }
 }
 
@@ -255,7 +309,7 @@ fn classify_conditionals(
     local: &mut HashMap<String, Vec<ConditionExpression>>,
     join: &mut Vec<ConditionTree>,
     global: &mut Vec<ConditionExpression>,
-    params: &mut Vec<Column>,
+    params: &mut Vec<(Column, Operator)>,
 ) {
     // Handling OR and AND expressions requires some care as there are some corner cases.
     //    a) we don't support OR expressions with predicates with placeholder parameters,
@@ -358,6 +412,25 @@ fn classify_conditionals(
             params.extend(new_params);
         }
         ConditionExpression::ComparisonOp(ref ct) => {
+            // `NOT IN` a subquery, which has been replaced by the subquery's view by now
+            if let ConditionExpression::NegationOp(ref r) = *ct.right.as_ref() {
+                if let (
+                    ConditionExpression::Base(ConditionBase::Field(ref lf)),
+                    ConditionExpression::Base(ConditionBase::Field(ref rf)),
+                ) = (ct.left.as_ref(), r.as_ref())
+                {
+                    if ct.operator == Operator::In
+                        && lf.table.is_some()
+                        && tables.contains(&Table::from(lf.table.as_ref().unwrap().as_str()))
+                        && rf.table.is_some()
+                    {
+                        // anti-join with the view; the outer table always stays on the left
+                        join.push(ct.clone());
+                        return;
+                    }
+                }
+            }
+
             // atomic selection predicate
             if let ConditionExpression::Base(ref l) = *ct.left.as_ref() {
                 if let ConditionExpression::Base(ref r) = *ct.right.as_ref() {
@@ -404,7 +477,7 @@ fn classify_conditionals(
                         // right-hand side is a placeholder, so this must be a query parameter
                         ConditionBase::Literal(Literal::Placeholder) => {
                             if let ConditionBase::Field(ref lf) = *l {
-                                params.push(lf.clone());
+                                params.push((lf.clone(), ct.operator.clone()));
                             }
                         }
                         // right-hand side is a non-placeholder literal, so this is a predicate
@@ -619,7 +692,9 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     .edges
                     .entry((left_table.clone(), right_table.clone()))
                     .or_insert_with(|| match jc.operator {
-                        JoinOperator::LeftJoin => QueryGraphEdge::LeftJoin(vec![join_pred]),
+                        JoinOperator::LeftJoin | JoinOperator::LeftOuterJoin => {
+                            QueryGraphEdge::LeftJoin(vec![join_pred])
+                        }
                         JoinOperator::Join | JoinOperator::InnerJoin => {
                             QueryGraphEdge::Join(vec![join_pred])
                         }
@@ -664,6 +739,19 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
 
         // 2. Add predicates for implied (comma) joins
         for jp in join_predicates {
+            // `NOT IN` predicates have a negated right-hand side, and become anti-joins
+            let (jp, anti) = match *jp.right {
+                ConditionExpression::NegationOp(ref r) => (
+                    ConditionTree {
+                        operator: Operator::Equal,
+                        left: jp.left.clone(),
+                        right: r.clone(),
+                    },
+                    true,
+                ),
+                _ => (jp, false),
+            };
+
             // We have a ConditionExpression, but both sides of it are ConditionBase of type Field
             if let ConditionExpression::Base(ConditionBase::Field(ref l)) = *jp.left.as_ref() {
                 if let ConditionExpression::Base(ConditionBase::Field(ref r)) = *jp.right.as_ref() {
@@ -679,9 +767,16 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     let e = qg
                         .edges
                         .entry((l.table.clone().unwrap(), r.table.clone().unwrap()))
-                        .or_insert_with(|| QueryGraphEdge::Join(vec![]));
+                        .or_insert_with(|| {
+                            if anti {
+                                QueryGraphEdge::AntiJoin(vec![])
+                            } else {
+                                QueryGraphEdge::Join(vec![])
+                            }
+                        });
                     match *e {
-                        QueryGraphEdge::Join(ref mut preds) => preds.push(jp.clone()),
+                        QueryGraphEdge::Join(ref mut preds) if !anti => preds.push(jp.clone()),
+                        QueryGraphEdge::AntiJoin(ref mut preds) if anti => preds.push(jp.clone()),
                         _ => panic!("Expected join edge for join condition {:#?}", jp),
                     };
                 }
@@ -692,7 +787,14 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         //    node for this query. Such columns will be carried all the way through the operators
         //    implementing the query (unlike in a traditional query plan, where the predicates on
         //    parameters might be evaluated sooner).
-        for column in query_parameters.into_iter() {
+        for (column, operator) in query_parameters.into_iter() {
+            match operator {
+                Operator::Greater
+                | Operator::GreaterOrEqual
+                | Operator::Less
+                | Operator::LessOrEqual => qg.range_operator = Some(operator),
+                _ => (),
+            }
             match column.table {
                 None => panic!("each parameter's column must have an associated table!"),
                 Some(ref table) => {
@@ -704,10 +806,18 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     // we also separately register it as a parameter so that we can set keys
                     // correctly on the leaf view
                     rel.parameters.push(column.clone());
+                    qg.parameter_order.push(column.clone());
                 }
             }
         }
 
+        if qg.range_operator.is_some() && qg.parameters().len() > 1 {
+            return Err(format!(
+                "a range parameter cannot be combined with other parameters: {}",
+                st
+            ));
+        }
+
         // 4. Add global predicates
         qg.global_predicates = global_predicates;
     }
@@ -788,6 +898,43 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         }
     }
 
+    // 5. Add predicates from the HAVING clause. These filter the output of the aggregations, so
+    //    they refer to aggregates through the computed columns that hold them. An aggregate that
+    //    isn't selected gets a computed column of its own, as long as it's the only one.
+    if let Some(having) = st.group_by.as_ref().and_then(|gb| gb.having.as_ref()) {
+        let selected = qg
+            .relations
+            .get("computed_columns")
+            .map(|n| n.columns.clone())
+            .unwrap_or_default();
+        let mut having = having.clone();
+        let mut unselected = Vec::new();
+        resolve_having_columns(&mut having, &selected, &mut unselected);
+        if !unselected.is_empty() && selected.len() + unselected.len() > 1 {
+            return Err(format!(
+                "HAVING can only use aggregates other than the selected ones if there is just one: {}",
+                having
+            ));
+        }
+        for column in &unselected {
+            add_computed_column(&mut qg, column);
+        }
+        qg.having_predicates = split_conjunctions(vec![having]);
+    }
+
+    // a range lookup spans many keys, but aggregates and limits are computed per key
+    if qg.range_operator.is_some() {
+        if qg.relations.contains_key("computed_columns") || st.group_by.is_some() {
+            return Err(format!("aggregations cannot have range parameters: {}", st));
+        }
+        if st.limit.is_some() {
+            return Err(format!(
+                "LIMIT cannot be combined with range parameters: {}",
+                st
+            ));
+        }
+    }
+
     // create initial join order
     {
         let mut sorted_edges: Vec<(&(String, String), &QueryGraphEdge)> = qg.edges.iter().collect();
@@ -813,16 +960,18 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                         })
                         .collect::<Vec<_>>(),
                 ),
-                QueryGraphEdge::LeftJoin(ref jps) => qg.join_order.extend(
-                    jps.iter()
-                        .enumerate()
-                        .map(|(idx, _)| JoinRef {
-                            src: src.clone(),
-                            dst: dst.clone(),
-                            index: idx,
-                        })
-                        .collect::<Vec<_>>(),
-                ),
+                QueryGraphEdge::LeftJoin(ref jps) | QueryGraphEdge::AntiJoin(ref jps) => {
+                    qg.join_order.extend(
+                        jps.iter()
+                            .enumerate()
+                            .map(|(idx, _)| JoinRef {
+                                src: src.clone(),
+                                dst: dst.clone(),
+                                index: idx,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }
                 QueryGraphEdge::GroupBy(_) => continue,
             }
         }
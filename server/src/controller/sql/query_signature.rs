@@ -117,7 +117,8 @@ impl Signature for QueryGraph {
         for e in self.edges.values() {
             match *e {
                 QueryGraphEdge::Join(ref join_predicates)
-                | QueryGraphEdge::LeftJoin(ref join_predicates) => {
+                | QueryGraphEdge::LeftJoin(ref join_predicates)
+                | QueryGraphEdge::AntiJoin(ref join_predicates) => {
                     for p in join_predicates {
                         for c in &p.contained_columns() {
                             attrs_vec.push(c);
@@ -147,6 +148,19 @@ impl Signature for QueryGraph {
             }
         }
 
+        // And so are the predicates from the HAVING clause
+        for p in &self.having_predicates {
+            match *p {
+                ComparisonOp(ref ct) | LogicalOp(ref ct) => {
+                    for c in &ct.contained_columns() {
+                        attrs_vec.push(c);
+                        attrs.insert(c);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
         // Compute attributes part of hash
         attrs_vec.sort();
         for a in &attrs_vec {
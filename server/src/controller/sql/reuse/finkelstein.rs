@@ -154,6 +154,13 @@ impl Finkelstein {
                         _ => return None,
                     }
                 }
+                QueryGraphEdge::AntiJoin(_) => {
+                    match *new_qge {
+                        QueryGraphEdge::AntiJoin(_) => {}
+                        // If there is no matching AntiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
             }
         }
 
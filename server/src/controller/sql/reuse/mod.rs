@@ -1,9 +1,11 @@
-use crate::controller::sql::query_graph::QueryGraph;
+use crate::controller::sql::cost::{self, TableStatistics};
+use crate::controller::sql::query_graph::{JoinRef, QueryGraph, QueryGraphEdge};
+use crate::controller::sql::reuse::helpers::predicate_implication::predicate_is_equivalent;
 use crate::controller::sql::reuse::join_order::reorder_joins;
 use crate::controller::sql::UniverseId;
 use crate::ReuseConfigType;
 use dataflow::prelude::DataType;
-use nom_sql::Table;
+use nom_sql::{ConditionTree, Table};
 use std::collections::HashMap;
 use std::vec::Vec;
 
@@ -30,8 +32,9 @@ impl ReuseConfig {
         &self,
         qg: &mut QueryGraph,
         query_graphs: &'a HashMap<u64, QueryGraph>,
+        stats: &HashMap<String, TableStatistics>,
     ) -> Vec<(ReuseType, (u64, &'a QueryGraph))> {
-        let reuse_candidates = match self.config {
+        let mut reuse_candidates = match self.config {
             ReuseConfigType::Finkelstein => {
                 finkelstein::Finkelstein::reuse_candidates(qg, query_graphs)
             }
@@ -39,8 +42,28 @@ impl ReuseConfig {
             ReuseConfigType::Full => full::Full::reuse_candidates(qg, query_graphs),
             _ => unreachable!(),
         };
+        // queries that are not otherwise compatible can still share the state of a join they
+        // both perform; MIR reuse then shares only the parts of the two that are the same.
+        for (id, eqg) in query_graphs {
+            let known = reuse_candidates.iter().any(|&(_, (cid, _))| cid == *id);
+            if !known && shares_join(qg, eqg) {
+                reuse_candidates.push((ReuseType::PrefixReuse, (*id, eqg)));
+            }
+        }
+        let fresh = cost::best_join_order(qg, stats);
         self.reorder_joins(qg, &reuse_candidates);
 
+        // joins that the candidates already perform in the same order come for free, but the
+        // order that reuses them may still be more expensive than building the query afresh.
+        if let Some(fresh) = fresh {
+            let reused = reused_joins(qg, &qg.join_order, &reuse_candidates);
+            let reuse_cost = cost::join_cost(qg, &qg.join_order, stats, reused);
+            let reused = reused_joins(qg, &fresh, &reuse_candidates);
+            if cost::join_cost(qg, &fresh, stats, reused) < reuse_cost {
+                qg.join_order = fresh;
+            }
+        }
+
         reuse_candidates
     }
 
@@ -99,6 +122,58 @@ impl ReuseConfig {
     }
 }
 
+/// How many of the first joins in `order` one of the candidates performs in the same order, and
+/// thus has state for already.
+fn reused_joins(
+    qg: &QueryGraph,
+    order: &[JoinRef],
+    reuse_candidates: &[(ReuseType, (u64, &QueryGraph))],
+) -> usize {
+    reuse_candidates
+        .iter()
+        .map(|&(_, (_, eqg))| {
+            order
+                .iter()
+                .zip(eqg.join_order.iter())
+                .take_while(|&(njref, ejref)| {
+                    match (join_predicate(qg, njref), join_predicate(eqg, ejref)) {
+                        (Some(np), Some(ep)) => predicate_is_equivalent(np, ep),
+                        _ => false,
+                    }
+                })
+                .count()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn join_predicate<'a>(qg: &'a QueryGraph, jref: &JoinRef) -> Option<&'a ConditionTree> {
+    match *qg.edges.get(&(jref.src.clone(), jref.dst.clone()))? {
+        QueryGraphEdge::Join(ref jps) => jps.get(jref.index),
+        _ => None,
+    }
+}
+
+/// Whether `qg` joins two relations on the same columns as `existing` does.
+fn shares_join(qg: &QueryGraph, existing: &QueryGraph) -> bool {
+    let same_predicates = |np: &[ConditionTree], ep: &[ConditionTree]| {
+        np.len() == ep.len()
+            && np
+                .iter()
+                .zip(ep.iter())
+                .all(|(np, ep)| predicate_is_equivalent(np, ep))
+    };
+    qg.edges
+        .iter()
+        .any(|(rels, edge)| match (edge, existing.edges.get(rels)) {
+            (QueryGraphEdge::Join(np), Some(QueryGraphEdge::Join(ep)))
+            | (QueryGraphEdge::LeftJoin(np), Some(QueryGraphEdge::LeftJoin(ep))) => {
+                same_predicates(np, ep)
+            }
+            _ => false,
+        })
+}
+
 trait ReuseConfiguration {
     fn reuse_candidates<'a>(
         qg: &QueryGraph,
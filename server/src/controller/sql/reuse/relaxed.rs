@@ -93,6 +93,17 @@ impl Relaxed {
                         _ => return None,
                     }
                 }
+                QueryGraphEdge::AntiJoin(_) => {
+                    if !new_qg.edges.contains_key(srcdst) {
+                        return None;
+                    }
+                    let new_qge = &new_qg.edges[srcdst];
+                    match *new_qge {
+                        QueryGraphEdge::AntiJoin(_) => {}
+                        // If there is no matching AntiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
                 _ => continue,
             }
         }
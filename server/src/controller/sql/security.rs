@@ -1,19 +1,24 @@
+use crate::controller::security::policy::Policy;
 use crate::controller::security::SecurityConfig;
+use crate::controller::sql::passes::policy_parameterization::PolicyParameterization;
 use crate::controller::sql::query_graph::{to_query_graph, QueryGraph};
+use crate::controller::sql::query_signature::Signature;
 use crate::controller::sql::{QueryFlowParts, SqlIncorporator};
 use crate::controller::Migration;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
-use nom_sql::SqlQuery;
-use std::collections::HashMap;
+use nom_sql::{Column, SqlQuery};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Debug)]
 pub(super) struct Universe {
-    id: DataType,
+    pub(super) id: DataType,
     from_group: Option<DataType>,
     pub(super) member_of: HashMap<String, Vec<DataType>>,
     pub(super) row_policies: HashMap<String, Vec<QueryGraph>>,
+    pub(super) shared_row_policies: HashMap<String, Vec<SharedRowPolicy>>,
     pub(super) rewrite_policies: HashMap<String, Vec<RewritePolicy>>,
+    pub(super) column_masks: HashMap<String, Vec<ColumnMask>>,
 }
 
 impl Default for Universe {
@@ -23,11 +28,33 @@ impl Default for Universe {
             from_group: None,
             member_of: HashMap::default(),
             row_policies: HashMap::default(),
+            shared_row_policies: HashMap::default(),
             rewrite_policies: HashMap::default(),
+            column_masks: HashMap::default(),
         }
     }
 }
 
+/// A row policy that only depends on the universe through the user id.
+///
+/// The universe-independent part of the policy is installed once, as a view shared by all
+/// universes, and each universe only adds a single filter on `column` below it.
+#[derive(Clone, Debug)]
+pub(super) struct SharedRowPolicy {
+    /// Name of the shared view, or `None` if the policy consists of the user id check only.
+    pub(super) view: Option<String>,
+    /// Column that must equal the universe's user id.
+    pub(super) column: Column,
+}
+
+/// A column whose values the universe replaces before its queries see them.
+#[derive(Clone, Debug)]
+pub(super) struct ColumnMask {
+    pub(super) column: String,
+    /// The value the column's values are replaced with; `NULL` for hidden columns.
+    pub(super) value: DataType,
+}
+
 #[derive(Clone, Debug)]
 pub(super) struct RewritePolicy {
     pub(super) value: String,
@@ -53,6 +80,19 @@ pub(in crate::controller) trait Multiverse {
         fields: &mut Vec<String>,
         mig: &mut Migration,
     ) -> QueryFlowParts;
+
+    /// Returns the name of the view that holds the universe-independent part of a shared row
+    /// policy on `table`, installing it first if no earlier universe has done so already.
+    fn shared_policy_view(
+        &mut self,
+        table: &str,
+        shared: SqlQuery,
+        mig: &mut Migration,
+    ) -> Result<(String, Option<QueryFlowParts>), String>;
+
+    /// Stop handing out the shared row policy views of `tables`, whose policies have changed.
+    /// Universes prepared from now on install fresh views for them.
+    fn forget_shared_policies(&mut self, tables: &HashSet<String>);
 }
 
 impl Multiverse for SqlIncorporator {
@@ -72,7 +112,9 @@ impl Multiverse for SqlIncorporator {
             from_group: group.clone(),
             member_of: universe_groups,
             row_policies: HashMap::new(),
+            shared_row_policies: HashMap::new(),
             rewrite_policies: HashMap::new(),
+            column_masks: HashMap::new(),
         };
 
         // Create the UserContext base node.
@@ -82,7 +124,7 @@ impl Multiverse for SqlIncorporator {
             info!(self.log, "Starting user universe {}", universe.id);
             let uc_name = format!("UserContext_{}", universe.id.to_string());
 
-            (uc_name, config.policies())
+            (uc_name, config.user_policies())
         } else {
             info!(self.log, "Starting group universe {}", universe.id);
             let group_name: DataType = group.clone().unwrap();
@@ -92,11 +134,17 @@ impl Multiverse for SqlIncorporator {
                 universe.id.to_string()
             );
 
-            (uc_name, config.get_group_policies(group_name.to_string()))
+            (
+                uc_name,
+                config.get_group_policies(group_name.to_string()).to_vec(),
+            )
         };
 
-        let base = self.add_base(uc_name.clone(), &mut fields, mig);
-        qfps.push(base);
+        // a universe whose policies are being updated already has its context table
+        if !self.base_schemas.contains_key(&uc_name) {
+            let base = self.add_base(uc_name.clone(), &mut fields, mig);
+            qfps.push(base);
+        }
 
         // Then, we need to transform policies' predicates into QueryGraphs.
         // We do this in a per-universe base, instead of once per policy,
@@ -104,7 +152,24 @@ impl Multiverse for SqlIncorporator {
         // a view creation and these views might be unique to each universe
         // e.g. if they reference UserContext.
         let mut row_policies_qg: HashMap<String, Vec<QueryGraph>> = HashMap::new();
-        for policy in universe_policies {
+        for policy in &universe_policies {
+            if let Policy::Mask(ref mask) = *policy {
+                trace!(self.log, "Masking column {}.{}", mask.table, mask.column);
+                let value = match mask.value {
+                    Some(ref value) => value.as_str().into(),
+                    None => DataType::None,
+                };
+                universe
+                    .column_masks
+                    .entry(mask.table.clone())
+                    .or_insert_with(Vec::new)
+                    .push(ColumnMask {
+                        column: mask.column.clone(),
+                        value,
+                    });
+                continue;
+            }
+
             if !policy.is_row_policy() {
                 let qfp = self
                     .add_parsed_query(policy.predicate(), None, false, mig)
@@ -126,6 +191,30 @@ impl Multiverse for SqlIncorporator {
                 continue;
             }
 
+            // Policies that only compare a column against the user id don't need a subtree of
+            // their own in every user universe.
+            if group.is_none() {
+                if let Some((shared, column)) = policy.predicate().parameterize_user_context() {
+                    trace!(self.log, "Sharing row policy {:?}", policy.name());
+                    let view = match shared {
+                        SqlQuery::Select(ref st) if st.where_clause.is_none() => None,
+                        shared => {
+                            let (name, qfp) =
+                                self.shared_policy_view(policy.table(), shared, mig)?;
+                            qfps.extend(qfp);
+                            Some(name)
+                        }
+                    };
+
+                    universe
+                        .shared_row_policies
+                        .entry(policy.table().clone())
+                        .or_insert_with(Vec::new)
+                        .push(SharedRowPolicy { view, column });
+                    continue;
+                }
+            }
+
             trace!(self.log, "Adding row policy {:?}", policy.name());
             let predicate = self.rewrite_query(policy.predicate(), mig)?;
             let st = match predicate {
@@ -137,10 +226,7 @@ impl Multiverse for SqlIncorporator {
             // represented as a query graph. This will change for more complex policies eg. column
             // replacement and aggregation permission.
 
-            let qg = match to_query_graph(st) {
-                Ok(qg) => qg,
-                Err(e) => panic!(e),
-            };
+            let qg = to_query_graph(st)?;
 
             let e = row_policies_qg
                 .entry(policy.table().clone())
@@ -151,7 +237,9 @@ impl Multiverse for SqlIncorporator {
         universe.row_policies = row_policies_qg;
 
         let e = self.universes.entry(group.clone()).or_insert_with(Vec::new);
-        e.push((id, group));
+        if !e.contains(&(id.clone(), group.clone())) {
+            e.push((id, group));
+        }
 
         self.mir_converter.set_universe(universe);
 
@@ -184,4 +272,49 @@ impl Multiverse for SqlIncorporator {
         self.add_parsed_query(parsed_query, Some(name), false, mig)
             .unwrap()
     }
+
+    fn shared_policy_view(
+        &mut self,
+        table: &str,
+        shared: SqlQuery,
+        mig: &mut Migration,
+    ) -> Result<(String, Option<QueryFlowParts>), String> {
+        let signature = match self.rewrite_query(shared.clone(), mig)? {
+            SqlQuery::Select(ref st) => to_query_graph(st)?.signature().hash,
+            _ => unreachable!(),
+        };
+
+        let known = self.shared_policies.get(table);
+        if let Some(name) = known.and_then(|policies| policies.get(&signature)) {
+            return Ok((name.clone(), None));
+        }
+
+        // the view of a policy that has since been forgotten may still be around
+        let mut name = format!("sp_shared_{:x}", signature);
+        let mut version = 0;
+        while self.has_query(&name) {
+            version += 1;
+            name = format!("sp_shared_{:x}_v{}", signature, version);
+        }
+
+        let qfp = self.add_parsed_query(shared, Some(name.clone()), false, mig)?;
+        self.shared_policies
+            .entry(table.to_owned())
+            .or_insert_with(HashMap::new)
+            .insert(signature, name.clone());
+        Ok((name, Some(qfp)))
+    }
+
+    fn forget_shared_policies(&mut self, tables: &HashSet<String>) {
+        for table in tables {
+            if let Some(policies) = self.shared_policies.remove(table) {
+                trace!(
+                    self.log,
+                    "Forgetting {} shared row policies on {}",
+                    policies.len(),
+                    table
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,299 @@
+use crate::startup::Event;
+use dataflow::ops::trigger::TriggerFiring;
+use futures_util::future::{AbortHandle, Abortable, BoxFuture};
+use futures_util::FutureExt;
+use noria::consensus::Epoch;
+use noria::TriggerAction;
+use std::collections::{HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// How long to wait before the first retry of a failed trigger action.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// The longest we will wait between retries of a failed trigger action.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a trigger action may take before it counts as failed, and is retried.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The authority key that the firings whose actions have yet to complete are kept under.
+///
+/// They are kept apart from the rest of the controller state, so that a backlog of firings does
+/// not have to be rewritten with every other change to the controller.
+pub(super) const FIRINGS_KEY: &str = "/trigger_firings";
+
+/// The most bytes of serialized firings that are kept, since the authority limits how large a
+/// value may be. The oldest firings are dropped to make room for new ones.
+const MAX_FIRINGS_SIZE: usize = 512 * 1024;
+
+/// A trigger added through `ControllerHandle::add_trigger`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TriggerSpec {
+    pub(crate) name: String,
+    pub(crate) view: String,
+    pub(crate) action: TriggerAction,
+}
+
+/// A trigger firing whose action has not yet run successfully.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PendingFiring {
+    /// Tells the firing apart from other firings of the same trigger.
+    pub(crate) id: u64,
+    pub(crate) firing: TriggerFiring,
+    pub(crate) attempts: u32,
+    /// The size of the serialized firing.
+    size: usize,
+    #[serde(skip)]
+    last_attempt: Option<Instant>,
+}
+
+impl PendingFiring {
+    pub(crate) fn new(firing: TriggerFiring) -> Self {
+        let size = serde_json::to_vec(&firing).map(|b| b.len()).unwrap_or(0);
+        PendingFiring {
+            id: 0,
+            firing,
+            attempts: 0,
+            size,
+            last_attempt: None,
+        }
+    }
+
+    /// Whether enough time has passed since the last failed attempt to try again.
+    pub(crate) fn is_due(&self) -> bool {
+        match self.last_attempt {
+            None => true,
+            Some(t) => {
+                let backoff = INITIAL_BACKOFF
+                    .checked_mul(1 << self.attempts.saturating_sub(1).min(16))
+                    .map(|b| b.min(MAX_BACKOFF))
+                    .unwrap_or(MAX_BACKOFF);
+                t.elapsed() >= backoff
+            }
+        }
+    }
+
+    pub(crate) fn failed(&mut self) {
+        self.attempts += 1;
+        self.last_attempt = Some(Instant::now());
+    }
+}
+
+/// Trigger state that is kept in the authority, so that it survives controller failures.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct TriggerState {
+    pub(crate) registered: Vec<TriggerSpec>,
+}
+
+/// The trigger firings whose actions have not yet completed, which are kept in the authority
+/// under `FIRINGS_KEY`.
+///
+/// A firing is only removed once its action has completed, so every action runs at least once,
+/// unless so many firings pile up that the oldest are dropped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PendingFirings {
+    /// The epoch of the controller that last wrote the firings, so that a controller that has
+    /// been deposed cannot overwrite them.
+    pub(crate) epoch: Epoch,
+    pub(crate) pending: VecDeque<PendingFiring>,
+    next_id: u64,
+}
+
+impl PendingFirings {
+    pub(crate) fn new(epoch: Epoch) -> Self {
+        PendingFirings {
+            epoch,
+            pending: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Queue `firing` to run after the firings already queued, and return the oldest firings
+    /// that had to be dropped to keep the serialized firings under `MAX_FIRINGS_SIZE`.
+    pub(crate) fn push(&mut self, mut firing: PendingFiring) -> Vec<PendingFiring> {
+        firing.id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(firing);
+        let mut size: usize = self.pending.iter().map(|p| p.size).sum();
+        let mut dropped = Vec::new();
+        while size > MAX_FIRINGS_SIZE && self.pending.len() > 1 {
+            let p = self.pending.pop_front().unwrap();
+            size -= p.size;
+            dropped.push(p);
+        }
+        dropped
+    }
+}
+
+/// A trigger action that has finished: the trigger, the firing, and whether the action succeeded.
+type Finished = (String, u64, Result<(), String>);
+
+/// The trigger actions that are running, by the name of their trigger.
+///
+/// Actions run as tasks of their own, so that slow actions do not hold up the controller, which
+/// is woken up whenever one finishes. Each trigger runs one action at a time, so that it
+/// observes changes in order. Actions are stopped when this is dropped, such as when the
+/// controller loses leadership.
+pub(super) struct RunningActions {
+    /// The firing whose action is running, by trigger, and how to stop the action.
+    running: HashMap<String, (u64, AbortHandle)>,
+    finished: (UnboundedSender<Finished>, UnboundedReceiver<Finished>),
+    wake: UnboundedSender<Event>,
+}
+
+impl RunningActions {
+    pub(super) fn new(wake: UnboundedSender<Event>) -> Self {
+        RunningActions {
+            running: HashMap::new(),
+            finished: tokio::sync::mpsc::unbounded_channel(),
+            wake,
+        }
+    }
+
+    /// Start running `action` for the firing `id` of `trigger`.
+    ///
+    /// The action fails if it takes longer than `ACTION_TIMEOUT`.
+    pub(super) fn start<F>(&mut self, trigger: &str, id: u64, action: F)
+    where
+        F: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let (abort, registration) = AbortHandle::new_pair();
+        let (finished, wake) = (self.finished.0.clone(), self.wake.clone());
+        let name = trigger.to_owned();
+        let action = async move {
+            let result = match AssertUnwindSafe(tokio::time::timeout(ACTION_TIMEOUT, action))
+                .catch_unwind()
+                .await
+            {
+                Ok(Ok(r)) => r,
+                Ok(Err(_)) => Err(format!("timed out after {:?}", ACTION_TIMEOUT)),
+                Err(_) => Err(String::from("the action panicked")),
+            };
+            if finished.send((name, id, result)).is_ok() {
+                let _ = wake.send(Event::TriggerActionFinished);
+            }
+        };
+        tokio::spawn(Abortable::new(action, registration));
+        self.running.insert(trigger.to_owned(), (id, abort));
+    }
+
+    /// True if an action of `trigger` is running.
+    pub(super) fn is_running(&self, trigger: &str) -> bool {
+        self.running.contains_key(trigger)
+    }
+
+    /// Forget the actions that have finished since the last call, and return the ids of their
+    /// firings along with whether they succeeded.
+    pub(super) fn reap(&mut self) -> Vec<(u64, Result<(), String>)> {
+        let mut finished = Vec::new();
+        while let Some(Some((trigger, id, result))) = self.finished.1.recv().now_or_never() {
+            self.running.remove(&trigger);
+            finished.push((id, result));
+        }
+        finished
+    }
+}
+
+impl Drop for RunningActions {
+    fn drop(&mut self) {
+        for (_, abort) in self.running.values() {
+            abort.abort();
+        }
+    }
+}
+
+/// Run an action that blocks on a thread where blocking is fine.
+pub(super) fn blocking<F>(action: F) -> BoxFuture<'static, Result<(), String>>
+where
+    F: FnOnce() -> Result<(), String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(action)
+        .map(|r| r.unwrap_or_else(|e| Err(format!("the action panicked: {}", e))))
+        .boxed()
+}
+
+/// `POST` the given changes to `url` as JSON.
+pub(super) async fn post_to_webhook(url: String, firing: TriggerFiring) -> Result<(), String> {
+    let body = serde_json::to_vec(&firing).map_err(|e| e.to_string())?;
+    let req = hyper::Request::post(&url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body))
+        .map_err(|e| e.to_string())?;
+
+    let res = hyper::Client::new()
+        .request(req)
+        .await
+        .map_err(|e| format!("webhook {} failed: {}", url, e))?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook {} returned {}", url, res.status()))
+    }
+}
+
+/// Append the given changes to the file at `path` as a line of JSON.
+pub(super) fn append_to_sink(path: &str, firing: &TriggerFiring) -> Result<(), String> {
+    let mut line = serde_json::to_vec(firing).map_err(|e| e.to_string())?;
+    line.push(b'\n');
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(&line))
+        .map_err(|e| format!("failed to append to sink {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::consensus::{Authority, LocalAuthority};
+
+    #[test]
+    fn it_backs_off() {
+        let mut p = PendingFiring::new(TriggerFiring {
+            trigger: "t".to_owned(),
+            action: TriggerAction::Sink {
+                path: "/dev/null".to_owned(),
+            },
+            changes: vec![],
+        });
+        assert!(p.is_due());
+        p.failed();
+        assert!(!p.is_due());
+        std::thread::sleep(INITIAL_BACKOFF * 2);
+        assert!(p.is_due());
+    }
+
+    #[test]
+    fn it_drops_the_oldest_firings_when_full() {
+        let firing = |trigger: &str| {
+            PendingFiring::new(TriggerFiring {
+                trigger: trigger.to_owned(),
+                action: TriggerAction::Sink {
+                    path: "/dev/null".to_owned(),
+                },
+                changes: vec![(vec!["x".repeat(1024).into()], true)],
+            })
+        };
+        let epoch = LocalAuthority::new()
+            .become_leader(vec![])
+            .unwrap()
+            .unwrap();
+        let mut firings = PendingFirings::new(epoch);
+        let fit = MAX_FIRINGS_SIZE / firing("a").size;
+        for _ in 0..fit {
+            assert!(firings.push(firing("a")).is_empty());
+        }
+
+        let dropped = firings.push(firing("b"));
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].firing.trigger, "a");
+        assert_eq!(firings.pending.len(), fit);
+        assert_eq!(firings.pending.back().unwrap().firing.trigger, "b");
+    }
+}
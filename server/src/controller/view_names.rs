@@ -0,0 +1,235 @@
+use crate::controller::recipe::Recipe;
+use nom_sql::{FieldDefinitionExpression, SelectSpecification, SqlQuery};
+use petgraph::graph::NodeIndex;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// A change to the names that views can be read by, which needs no change to the dataflow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum NameChange {
+    /// `CREATE VIEW alias AS SELECT * FROM target`, where `target` is a view.
+    Alias { alias: String, target: String },
+    /// `RENAME VIEW from TO to`.
+    Rename { from: String, to: String },
+}
+
+/// The names that clients read views by, where they differ from the names in the recipe.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(super) struct ViewNames {
+    /// Additional names, and the name in the recipe of the view each refers to.
+    aliases: BTreeMap<String, String>,
+    /// Names in the recipe that have been renamed, and so are no longer readable.
+    renamed: BTreeSet<String>,
+}
+
+impl ViewNames {
+    /// The name in the recipe of the view that clients read as `name`, if any.
+    pub(super) fn resolve<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if let Some(target) = self.aliases.get(name) {
+            Some(target)
+        } else if self.renamed.contains(name) {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    /// Apply `change`, given which names in the recipe are views.
+    pub(super) fn apply<F>(&mut self, change: &NameChange, is_view: F) -> Result<(), String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let (from, to) = match *change {
+            NameChange::Alias {
+                ref alias,
+                ref target,
+            } => (target, alias),
+            NameChange::Rename { ref from, ref to } => (from, to),
+        };
+
+        let target = match self.resolve(from) {
+            Some(target) if is_view(target) => target.to_owned(),
+            _ => return Err(format!("no view named {}", from)),
+        };
+        if self.resolve(to).map(&is_view).unwrap_or(false) {
+            return Err(format!("a view named {} already exists", to));
+        }
+
+        if let NameChange::Rename { .. } = *change {
+            if self.aliases.remove(from).is_none() {
+                self.renamed.insert(from.clone());
+            }
+        }
+        if *to == target {
+            // renamed back to its name in the recipe
+            self.renamed.remove(to);
+        } else {
+            self.aliases.insert(to.clone(), target);
+        }
+        Ok(())
+    }
+
+    /// Rewrite a map of views by their names in the recipe to one by the names clients use.
+    pub(super) fn rename_outputs(
+        &self,
+        mut outputs: BTreeMap<String, NodeIndex>,
+    ) -> BTreeMap<String, NodeIndex> {
+        let aliased: Vec<_> = self
+            .aliases
+            .iter()
+            .filter_map(|(alias, target)| Some((alias.clone(), *outputs.get(target)?)))
+            .collect();
+        for name in &self.renamed {
+            outputs.remove(name);
+        }
+        outputs.extend(aliased);
+        outputs
+    }
+}
+
+/// Remove the statements from `recipe_text` that only change the names views are read by.
+///
+/// `is_table` identifies the base tables of the running recipe; a `SELECT *` from a base table
+/// defines a view as usual. Returns the remaining recipe, and the name changes in the order they
+/// appear in.
+pub(super) fn split_name_changes<F>(recipe_text: &str, is_table: F) -> (String, Vec<NameChange>)
+where
+    F: Fn(&str) -> bool,
+{
+    let mut tables = HashSet::new();
+    let mut kept: Vec<String> = Vec::new();
+    let mut changes = Vec::new();
+    for (statement, parsed) in Recipe::parse_statements(recipe_text) {
+        let change = match parsed {
+            Ok((_, SqlQuery::CreateTable(ref ctq))) => {
+                tables.insert(ctq.table.name.clone());
+                None
+            }
+            Ok((_, SqlQuery::CreateView(ref cvq))) => match *cvq.definition {
+                SelectSpecification::Simple(ref sq)
+                    if sq.fields == [FieldDefinitionExpression::All]
+                        && sq.tables.len() == 1
+                        && sq.tables[0].alias.is_none()
+                        && !sq.distinct
+                        && sq.join.is_empty()
+                        && sq.where_clause.is_none()
+                        && sq.group_by.is_none()
+                        && sq.order.is_none()
+                        && sq.limit.is_none()
+                        && !tables.contains(&sq.tables[0].name)
+                        && !is_table(&sq.tables[0].name) =>
+                {
+                    Some(NameChange::Alias {
+                        alias: cvq.name.clone(),
+                        target: sq.tables[0].name.clone(),
+                    })
+                }
+                _ => None,
+            },
+            Ok(_) => None,
+            Err(_) => parse_rename(&statement),
+        };
+
+        match change {
+            Some(change) => changes.push(change),
+            // several statements on one line share their text
+            None if kept.last() != Some(&statement) => kept.push(statement),
+            None => (),
+        }
+    }
+    (kept.join("\n"), changes)
+}
+
+/// Parse `RENAME VIEW from TO to;`, which the SQL parser does not support.
+fn parse_rename(statement: &str) -> Option<NameChange> {
+    let words: Vec<_> = statement
+        .trim_end()
+        .trim_end_matches(';')
+        .split_whitespace()
+        .collect();
+    match words[..] {
+        [rename, view, from, to_kw, to]
+            if rename.eq_ignore_ascii_case("rename")
+                && view.eq_ignore_ascii_case("view")
+                && to_kw.eq_ignore_ascii_case("to") =>
+        {
+            Some(NameChange::Rename {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(alias: &str, target: &str) -> NameChange {
+        NameChange::Alias {
+            alias: alias.to_owned(),
+            target: target.to_owned(),
+        }
+    }
+
+    fn rename(from: &str, to: &str) -> NameChange {
+        NameChange::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        }
+    }
+
+    #[test]
+    fn it_splits_name_changes_from_the_recipe() {
+        let (kept, changes) = split_name_changes(
+            "CREATE TABLE article (id int, title varchar(255));\n\
+             CREATE VIEW everything AS SELECT * FROM article;\n\
+             QUERY titles: SELECT title FROM article WHERE id = ?;\n\
+             CREATE VIEW headlines AS SELECT * FROM titles;\n\
+             rename view titles to article_titles;",
+            |_| false,
+        );
+        assert_eq!(
+            kept,
+            "CREATE TABLE article (id int, title varchar(255));\n\
+             CREATE VIEW everything AS SELECT * FROM article;\n\
+             QUERY titles: SELECT title FROM article WHERE id = ?;"
+        );
+        assert_eq!(
+            changes,
+            vec![
+                alias("headlines", "titles"),
+                rename("titles", "article_titles")
+            ]
+        );
+    }
+
+    #[test]
+    fn renames_and_aliases_resolve() {
+        let is_view = |n: &str| n == "titles";
+        let mut names = ViewNames::default();
+        names.apply(&alias("headlines", "titles"), is_view).unwrap();
+        assert_eq!(names.resolve("headlines"), Some("titles"));
+        assert!(names.apply(&alias("headlines", "titles"), is_view).is_err());
+        assert!(names.apply(&alias("other", "nope"), is_view).is_err());
+
+        names
+            .apply(&rename("titles", "article_titles"), is_view)
+            .unwrap();
+        assert_eq!(names.resolve("titles"), None);
+        assert_eq!(names.resolve("article_titles"), Some("titles"));
+        assert_eq!(names.resolve("headlines"), Some("titles"));
+
+        // renaming an alias moves it
+        names.apply(&rename("headlines", "news"), is_view).unwrap();
+        assert_eq!(names.resolve("headlines"), Some("headlines"));
+        assert_eq!(names.resolve("news"), Some("titles"));
+
+        // and renaming back restores the original name
+        names
+            .apply(&rename("article_titles", "titles"), is_view)
+            .unwrap();
+        assert_eq!(names.resolve("titles"), Some("titles"));
+        assert_eq!(names.resolve("article_titles"), Some("article_titles"));
+    }
+}
@@ -0,0 +1,159 @@
+use dataflow::prelude::*;
+use noria::ViewWarming;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// The readers of new views that are being filled in the background, a few keys at a time.
+///
+/// Keys are requested at a limited rate, so that warming does not take replay capacity away from
+/// reads and writes. Reads of keys that have not been requested yet miss and are replayed as
+/// usual.
+pub(super) struct Warming {
+    /// How many keys may be requested per second.
+    rate: usize,
+    /// The number of keys that may be requested, but have not been.
+    credit: f64,
+    /// When `credit` was last topped up.
+    last: Instant,
+    views: Vec<WarmingView>,
+}
+
+struct WarmingView {
+    view: String,
+    reader: NodeIndex,
+    /// The keys left to request, once they have been looked up.
+    keys: Option<VecDeque<Vec<DataType>>>,
+    total: usize,
+    warmed: usize,
+}
+
+impl Warming {
+    pub(super) fn new(rate: usize) -> Self {
+        Warming {
+            rate,
+            credit: 0.0,
+            last: Instant::now(),
+            views: Vec::new(),
+        }
+    }
+
+    /// Start warming `reader`, the reader of `view`, unless it is already being warmed.
+    pub(super) fn add(&mut self, view: String, reader: NodeIndex) {
+        if self.views.iter().any(|w| w.reader == reader) {
+            return;
+        }
+        self.views.push(WarmingView {
+            view,
+            reader,
+            keys: None,
+            total: 0,
+            warmed: 0,
+        });
+    }
+
+    /// Stop warming the readers for which `keep` returns false.
+    pub(super) fn retain(&mut self, mut keep: impl FnMut(NodeIndex) -> bool) {
+        self.views.retain(|w| keep(w.reader));
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.views.clear();
+    }
+
+    /// The readers whose keys have yet to be looked up.
+    pub(super) fn unscanned(&self) -> Vec<NodeIndex> {
+        self.views
+            .iter()
+            .filter(|w| w.keys.is_none())
+            .map(|w| w.reader)
+            .collect()
+    }
+
+    /// Record the keys to warm `reader` with.
+    pub(super) fn scanned(&mut self, reader: NodeIndex, keys: Vec<Vec<DataType>>) {
+        if let Some(w) = self.views.iter_mut().find(|w| w.reader == reader) {
+            w.total = keys.len();
+            w.keys = Some(keys.into());
+        }
+    }
+
+    /// Top up the number of keys that may be requested by the time that has passed since the
+    /// last top-up.
+    ///
+    /// At most a second's worth of keys is kept in hand, so that warming does not burst after
+    /// being held up.
+    pub(super) fn tick(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.last).as_secs_f64() * self.rate as f64;
+        self.credit = f64::min(self.credit + earned, self.rate as f64);
+        self.last = now;
+    }
+
+    /// Take as many keys to request as the credit allows, oldest view first, grouped by reader.
+    pub(super) fn take(&mut self) -> Vec<(NodeIndex, Vec<Vec<DataType>>)> {
+        let mut taken = Vec::new();
+        for w in &mut self.views {
+            let budget = self.credit as usize;
+            if budget == 0 {
+                break;
+            }
+            let keys = match w.keys {
+                Some(ref mut keys) if !keys.is_empty() => keys,
+                _ => continue,
+            };
+            let n = usize::min(budget, keys.len());
+            taken.push((w.reader, keys.drain(..n).collect()));
+            w.warmed += n;
+            self.credit -= n as f64;
+        }
+        taken
+    }
+
+    pub(super) fn status(&self) -> Vec<ViewWarming> {
+        self.views
+            .iter()
+            .map(|w| ViewWarming {
+                view: w.view.clone(),
+                warmed: w.warmed,
+                keys: w.keys.as_ref().map(|_| w.total),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_warms_at_most_the_credit() {
+        let mut w = Warming::new(3);
+        let (a, b) = (NodeIndex::new(1), NodeIndex::new(2));
+        w.add("a".to_owned(), a);
+        w.add("b".to_owned(), b);
+        w.add("a".to_owned(), a);
+        assert_eq!(w.unscanned(), vec![a, b]);
+        assert_eq!(w.status()[0].keys, None);
+
+        w.scanned(a, vec![vec![1.into()], vec![2.into()]]);
+        w.scanned(b, vec![vec![3.into()], vec![4.into()]]);
+        assert!(w.unscanned().is_empty());
+
+        w.credit = 3.0;
+        let taken = w.take();
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0], (a, vec![vec![1.into()], vec![2.into()]]));
+        assert_eq!(taken[1], (b, vec![vec![3.into()]]));
+        assert!(w.take().is_empty());
+
+        let status = w.status();
+        assert!(status[0].is_complete());
+        assert_eq!(status[1].warmed, 1);
+        assert_eq!(status[1].keys, Some(2));
+
+        // credit does not pile up beyond a second's worth
+        w.last -= std::time::Duration::from_secs(10);
+        w.tick();
+        assert_eq!(w.credit, 3.0);
+    }
+}
@@ -1,6 +1,8 @@
+use dataflow::ops::trigger::TriggerFiring;
 use dataflow::prelude::*;
 use dataflow::DomainBuilder;
 use noria::consensus::Epoch;
+use noria::{DeadLetter, Protocol, ReaderLoad, Violation};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
@@ -26,11 +28,15 @@ pub enum CoordinationPayload {
         read_listen_addr: SocketAddr,
         /// Which log files are stored locally on the worker.
         log_files: Vec<String>,
+        /// The protocol version and features the worker supports.
+        protocol: Protocol,
     },
     /// Worker going offline.
     Deregister,
     /// Worker is still alive.
     Heartbeat,
+    /// How busy the worker's reader is, sent along with every heartbeat.
+    ReaderLoad(ReaderLoad),
     /// Assign a new domain for a worker to run.
     AssignDomain(DomainBuilder),
     /// Remove a running domain from a worker.
@@ -39,6 +45,31 @@ pub enum CoordinationPayload {
     DomainBooted(DomainDescriptor),
     /// Create a new security universe.
     CreateUniverse(HashMap<String, DataType>),
+    /// Run the action of a trigger whose view changed.
+    FireTrigger(TriggerFiring),
+    /// Keep writes to a table that did not fit its schema.
+    DeadLetters {
+        table: String,
+        letters: Vec<DeadLetter>,
+    },
+    /// Keep rows that violated an assertion on a view.
+    Quarantine {
+        view: String,
+        violations: Vec<Violation>,
+    },
+    /// The worker evicted state to stay within its memory limit.
+    Evicted {
+        /// The number of bytes evicted across all domains.
+        bytes: usize,
+    },
+    /// A domain panicked, and has stopped.
+    DomainFailed {
+        domain: DomainIndex,
+        shard: usize,
+        /// The packet the domain was processing, if any.
+        packet: Option<String>,
+        reason: String,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
@@ -4,6 +4,7 @@ use dataflow::prelude::*;
 use noria::consensus::Authority;
 use noria::prelude::*;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use stream_cancel::Trigger;
@@ -14,6 +15,7 @@ pub struct Handle<A: Authority + 'static> {
     #[allow(dead_code)]
     event_tx: Option<tokio::sync::mpsc::UnboundedSender<Event>>,
     kill: Option<Trigger>,
+    http_reads_addr: Option<SocketAddr>,
 }
 
 impl<A: Authority> Deref for Handle<A> {
@@ -34,17 +36,26 @@ impl<A: Authority + 'static> Handle<A> {
         authority: Arc<A>,
         event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
         kill: Trigger,
+        http_reads_addr: Option<SocketAddr>,
     ) -> Result<Self, failure::Error> {
         let c = ControllerHandle::make(authority).await?;
         Ok(Handle {
             c: Some(c),
             event_tx: Some(event_tx),
             kill: Some(kill),
+            http_reads_addr,
         })
     }
 
-    #[cfg(test)]
-    pub(super) async fn backend_ready(&mut self) {
+    /// The address that this instance serves view lookups over HTTP on, if it was started with
+    /// `Builder::enable_http_reads`.
+    pub fn http_reads_addr(&self) -> Option<SocketAddr> {
+        self.http_reads_addr
+    }
+
+    /// Wait until the controller has elected a leader and has workers to place domains on.
+    #[doc(hidden)]
+    pub async fn backend_ready(&mut self) {
         use std::time;
 
         loop {
@@ -87,6 +98,9 @@ impl<A: Authority + 'static> Handle<A> {
     }
 
     /// Install a new set of policies on the controller.
+    ///
+    /// Universes that already exist start enforcing changed read policies without being created
+    /// anew, but their security groups cannot be changed.
     #[must_use]
     pub async fn set_security_config(&mut self, p: String) -> Result<(), failure::Error> {
         self.rpc("set_security_config", p, "failed to set security config")
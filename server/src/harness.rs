@@ -0,0 +1,217 @@
+//! A harness for testing applications and custom operators against a small, in-process Noria.
+//!
+//! A [`Harness`] runs a single unsharded worker, installs a recipe, and then plays a script of
+//! [`Step`]s against it: writes to tables, evictions of partial state, and reads that check the
+//! contents of views. Every read first waits for all earlier writes to reach every view, so the
+//! outcome of a script only depends on the order of its steps, and not on how the worker happened
+//! to schedule its domains.
+//!
+//! ```no_run
+//! # async fn f() -> Result<(), failure::Error> {
+//! use noria_server::harness::{Harness, Step};
+//!
+//! let recipe = "CREATE TABLE votes (aid int, uid int);
+//!               QUERY VoteCount: SELECT aid, COUNT(uid) AS n FROM votes WHERE aid = ? GROUP BY aid;";
+//! let mut h = Harness::new(recipe).await?;
+//! h.run(vec![
+//!     Step::insert("votes", vec![1.into(), 7.into()]),
+//!     Step::expect("VoteCount", vec![1.into()], vec![vec![1.into(), 1.into()]]),
+//!     Step::EvictAll,
+//!     Step::insert("votes", vec![1.into(), 8.into()]),
+//!     Step::expect("VoteCount", vec![1.into()], vec![vec![1.into(), 2.into()]]),
+//! ])
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Builder, Handle, LocalAuthority};
+use noria::{DataType, Table, View};
+use std::collections::HashMap;
+
+/// One step of a script played by [`Harness::run`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Insert a row into a table.
+    Insert {
+        /// The table to insert into.
+        table: String,
+        /// The row to insert.
+        row: Vec<DataType>,
+    },
+    /// Delete the rows with the given primary key from a table.
+    Delete {
+        /// The table to delete from.
+        table: String,
+        /// The primary key of the rows to delete.
+        key: Vec<DataType>,
+    },
+    /// Wait until all earlier writes are visible in every view.
+    Settle,
+    /// Evict all partially materialized state, so that later reads have to be replayed.
+    EvictAll,
+    /// Look up a key in a view, and fail unless it holds exactly the given rows, in any order.
+    Expect {
+        /// The view to read from.
+        view: String,
+        /// The key to look up.
+        key: Vec<DataType>,
+        /// The rows the key is expected to hold.
+        rows: Vec<Vec<DataType>>,
+    },
+}
+
+impl Step {
+    /// Insert `row` into `table`.
+    pub fn insert(table: &str, row: Vec<DataType>) -> Self {
+        Step::Insert {
+            table: table.to_owned(),
+            row,
+        }
+    }
+
+    /// Delete the rows with primary key `key` from `table`.
+    pub fn delete(table: &str, key: Vec<DataType>) -> Self {
+        Step::Delete {
+            table: table.to_owned(),
+            key,
+        }
+    }
+
+    /// Check that `key` holds exactly `rows` in `view`.
+    pub fn expect(view: &str, key: Vec<DataType>, rows: Vec<Vec<DataType>>) -> Self {
+        Step::Expect {
+            view: view.to_owned(),
+            key,
+            rows,
+        }
+    }
+}
+
+/// A single in-process Noria worker for tests.
+///
+/// The handles of the tables and views that a test uses are kept, so that each is only fetched
+/// from the controller once.
+pub struct Harness {
+    g: Handle<LocalAuthority>,
+    tables: HashMap<String, Table>,
+    views: HashMap<String, View>,
+    /// The number of steps played so far, for error messages.
+    played: usize,
+}
+
+impl Harness {
+    /// Start a harness with the default configuration, and install `recipe` in it.
+    pub async fn new(recipe: &str) -> Result<Self, failure::Error> {
+        Self::with_builder(Builder::default(), recipe).await
+    }
+
+    /// Start a harness from `builder`, and install `recipe` in it.
+    ///
+    /// This is how tests register custom aggregates with `Builder::register_aggregate`, or turn
+    /// off partial materialization. Sharding is always turned off, so that each key is handled by
+    /// a single domain.
+    pub async fn with_builder(mut builder: Builder, recipe: &str) -> Result<Self, failure::Error> {
+        builder.set_sharding(None);
+        let (mut g, _) = builder.start_local().await?;
+        g.backend_ready().await;
+        g.install_recipe(recipe).await?;
+        Ok(Harness {
+            g,
+            tables: HashMap::new(),
+            views: HashMap::new(),
+            played: 0,
+        })
+    }
+
+    /// The handle to the worker, for anything the harness does not cover.
+    pub fn handle(&mut self) -> &mut Handle<LocalAuthority> {
+        &mut self.g
+    }
+
+    /// The table called `name`.
+    pub async fn table(&mut self, name: &str) -> Result<&mut Table, failure::Error> {
+        if !self.tables.contains_key(name) {
+            let table = self.g.table(name).await?;
+            self.tables.insert(name.to_owned(), table);
+        }
+        Ok(self.tables.get_mut(name).unwrap())
+    }
+
+    /// The view called `name`.
+    pub async fn view(&mut self, name: &str) -> Result<&mut View, failure::Error> {
+        if !self.views.contains_key(name) {
+            let view = self.g.view(name).await?;
+            self.views.insert(name.to_owned(), view);
+        }
+        Ok(self.views.get_mut(name).unwrap())
+    }
+
+    /// Wait until all writes made through the harness are visible in every view.
+    pub async fn settle(&mut self) -> Result<(), failure::Error> {
+        for table in self.tables.values_mut() {
+            table.flush_barrier().await?;
+        }
+        Ok(())
+    }
+
+    /// The rows that `key` holds in `view` once all earlier writes are visible, sorted.
+    ///
+    /// Keys that are missing are replayed.
+    pub async fn lookup(
+        &mut self,
+        view: &str,
+        key: Vec<DataType>,
+    ) -> Result<Vec<Vec<DataType>>, failure::Error> {
+        self.settle().await?;
+        let results = self.view(view).await?.lookup(&key, true).await?;
+        let mut rows: Vec<Vec<DataType>> = results.into();
+        rows.sort();
+        Ok(rows)
+    }
+
+    /// Play a single step.
+    pub async fn step(&mut self, step: Step) -> Result<(), failure::Error> {
+        self.played += 1;
+        match step {
+            Step::Insert { table, row } => {
+                self.table(&table).await?.insert(row).await?;
+            }
+            Step::Delete { table, key } => {
+                self.table(&table).await?.delete(key).await?;
+            }
+            Step::Settle => self.settle().await?,
+            Step::EvictAll => {
+                self.settle().await?;
+                self.g.flush_partial().await?;
+            }
+            Step::Expect {
+                view,
+                key,
+                mut rows,
+            } => {
+                let got = self.lookup(&view, key.clone()).await?;
+                rows.sort();
+                if got != rows {
+                    bail!(
+                        "step {}: {}[{:?}] holds {:?}, but {:?} was expected",
+                        self.played,
+                        view,
+                        key,
+                        got,
+                        rows
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Play `steps` in order, stopping at the first that fails.
+    pub async fn run(&mut self, steps: Vec<Step>) -> Result<(), failure::Error> {
+        for step in steps {
+            self.step(step).await?;
+        }
+        Ok(())
+    }
+}
@@ -8,9 +8,9 @@ use dataflow::ops::join::JoinSource::*;
 use dataflow::ops::join::{Join, JoinSource, JoinType};
 use dataflow::ops::project::Project;
 use dataflow::ops::union::Union;
-use dataflow::{DurabilityMode, PersistenceParameters};
+use dataflow::{DurabilityMode, PersistenceParameters, ReplayConfig, ReplayPacing};
 use noria::consensus::LocalAuthority;
-use noria::DataType;
+use noria::{DataType, Eviction, EvictionPolicy, ViewPriority, ViewStorage};
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -245,6 +245,145 @@ async fn sharded_shuffle() {
     assert_eq!(rows.len(), 100);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn sharded_by_compound_shard_key() {
+    use noria::Modification;
+
+    let mut g = start_simple("sharded_by_compound_shard_key").await;
+    g.migrate(|mig| {
+        let a = mig.add_base(
+            "base",
+            &["tenant", "id", "v"],
+            Base::new(vec![])
+                .with_key(vec![0, 1])
+                .with_shard_key(vec![0, 1]),
+        );
+        mig.maintain_anonymous(a, &[0]);
+    })
+    .await;
+
+    let mut base = g.table("base").await.unwrap();
+    let mut view = g.view("base").await.unwrap();
+
+    // the rows of one tenant end up on all the shards, but are still found by a lookup on tenant
+    base.perform_all((0..100).map(|i| vec![DataType::Int(1), i.into(), i.into()]))
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        view.lookup(&[DataType::Int(1)], true).await.unwrap().len(),
+        100
+    );
+
+    // deletes and updates by the compound key reach the shard the row is on
+    for i in 0..50 {
+        base.delete(vec![DataType::Int(1), i.into()]).await.unwrap();
+    }
+    base.update(
+        vec![DataType::Int(1), 99.into()],
+        vec![(2, Modification::Set(0.into()))],
+    )
+    .await
+    .unwrap();
+    sleep().await;
+    let rows = view.lookup(&[DataType::Int(1)], true).await.unwrap();
+    assert_eq!(rows.len(), 50);
+    assert!(rows.contains(&vec![1.into(), 99.into(), 0.into()]));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn sharded_by_range_and_consistent_hashing() {
+    use noria::ShardScheme;
+
+    let mut g = start_simple("sharded_by_range_and_consistent_hashing").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["id", "x"], Base::new(vec![]).with_key(vec![0]));
+        mig.set_shard_scheme(a, ShardScheme::Range { start: 0, end: 100 })
+            .unwrap();
+        let b = mig.add_base("b", &["id", "y"], Base::new(vec![]).with_key(vec![0]));
+        mig.set_shard_scheme(b, ShardScheme::Consistent).unwrap();
+        mig.maintain_anonymous(a, &[0]);
+
+        // the bases spread ids across their shards differently, so one of them is shuffled
+        let j = Join::new(a, b, JoinType::Inner, vec![B(0, 0), L(1), R(1)]);
+        let j = mig.add_ingredient("j", &["id", "x", "y"], j);
+        mig.maintain_anonymous(j, &[0]);
+    })
+    .await;
+
+    let mut a = g.table("a").await.unwrap();
+    let mut b = g.table("b").await.unwrap();
+    a.perform_all((0..200).map(|i| vec![i.into(), (i * 2).into()]))
+        .await
+        .unwrap();
+    b.perform_all((0..200).map(|i| vec![i.into(), (i * 3).into()]))
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut av = g.view("a").await.unwrap();
+    let mut jv = g.view("j").await.unwrap();
+    for &i in &[0, 42, 99, 150] {
+        assert_eq!(
+            av.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![i.into(), (i * 2).into()]]
+        );
+        assert_eq!(
+            jv.lookup(&[i.into()], true).await.unwrap(),
+            vec![vec![i.into(), (i * 2).into(), (i * 3).into()]]
+        );
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn reshards_tables_and_views() {
+    let mut g = start_simple("reshards_tables_and_views").await;
+    g.install_recipe(
+        "CREATE TABLE o (region int, id int, total int, PRIMARY KEY(region, id));
+         QUERY by_id: SELECT id, total FROM o WHERE region = ? AND id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut o = g.table("o").await.unwrap();
+    o.perform_all((0..100).map(|i| vec![(i % 4).into(), i.into(), i.into()]))
+        .await
+        .unwrap();
+    sleep().await;
+
+    // move the rows to a layout with fewer shards, routed by both columns of the key
+    g.extend_recipe("ALTER TABLE o SHARD BY (region, id) INTO 2 SHARDS;")
+        .await
+        .unwrap();
+    let mut o = g.table("o").await.unwrap();
+    o.insert(vec![1.into(), 101.into(), 7.into()])
+        .await
+        .unwrap();
+
+    // and look up rows by the shard of their id rather than of their region
+    g.extend_recipe("ALTER VIEW by_id SHARD BY (id);")
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut by_id = g.view("by_id").await.unwrap();
+    for &(region, id, total) in &[(2, 42, 42), (1, 101, 7)] {
+        assert_eq!(
+            by_id
+                .lookup(&[DataType::from(region), DataType::from(id)], true)
+                .await
+                .unwrap(),
+            vec![vec![DataType::from(id), DataType::from(total)]]
+        );
+    }
+
+    // a table with a shard key cannot be carried over into a replaced recipe
+    assert!(g
+        .install_recipe("CREATE TABLE o (region int, id int, total int, PRIMARY KEY(region, id));")
+        .await
+        .is_err());
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn broad_recursing_upquery() {
     let nshards = 16;
@@ -999,6 +1138,184 @@ async fn it_recovers_persisted_bases() {
     done.await;
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_aggregates_with_registered_functions() {
+    use crate::GroupedAggregator;
+    use dataflow::ops::grouped::custom::CustomAggregator;
+
+    /// Multiplies the (non-zero) values in each group.
+    struct Product;
+
+    impl GroupedAggregator for Product {
+        fn apply(
+            &self,
+            current: Option<&DataType>,
+            diffs: &mut dyn Iterator<Item = (DataType, bool)>,
+        ) -> DataType {
+            let current = current.map(i64::from).unwrap_or(1);
+            diffs
+                .fold(current, |p, (v, pos)| {
+                    if pos {
+                        p * i64::from(&v)
+                    } else {
+                        p / i64::from(&v)
+                    }
+                })
+                .into()
+        }
+    }
+
+    let mut builder = Builder::default();
+    builder.set_persistence(get_persistence_params(
+        "it_aggregates_with_registered_functions",
+    ));
+    builder.register_aggregate("product", Arc::new(Product));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["k", "v"], Base::new(vec![]).with_key(vec![0, 1]));
+        let p = mig.add_ingredient(
+            "p",
+            &["k", "product"],
+            CustomAggregator::over("product", a, 1, &[0]),
+        );
+        mig.maintain_anonymous(p, &[0]);
+    })
+    .await;
+
+    let mut a = g.table("a").await.unwrap();
+    let mut p = g.view("p").await.unwrap();
+    a.insert(vec![1.into(), 2.into()]).await.unwrap();
+    a.insert(vec![1.into(), 3.into()]).await.unwrap();
+    a.insert(vec![2.into(), 5.into()]).await.unwrap();
+    sleep().await;
+
+    assert_eq!(
+        p.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 6.into()]]
+    );
+    assert_eq!(
+        p.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), 5.into()]]
+    );
+
+    a.delete(vec![1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        p.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 3.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn flush_barrier_waits_for_derived_views() {
+    let mut g = start_simple("flush_barrier_waits_for_derived_views").await;
+    let sql = "
+        CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(aid, uid));
+        QUERY Votes: SELECT aid, uid FROM Vote WHERE aid = ?;
+        QUERY VoteCount: SELECT aid, COUNT(uid) AS votes FROM Vote WHERE aid = ? GROUP BY aid;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut vote = g.table("Vote").await.unwrap();
+    let mut votes = g.view("Votes").await.unwrap();
+    let mut count = g.view("VoteCount").await.unwrap();
+
+    // fill the keys, so that the reads below see what the writes did to the views, rather than
+    // replaying the key from the base table
+    vote.insert(vec![1.into(), 0.into()]).await.unwrap();
+    vote.flush_barrier().await.unwrap();
+    assert_eq!(votes.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+
+    for uid in 1..10 {
+        vote.insert(vec![1.into(), uid.into()]).await.unwrap();
+    }
+    vote.flush_barrier().await.unwrap();
+
+    assert_eq!(votes.lookup(&[1.into()], true).await.unwrap().len(), 10);
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn session_settings_apply_to_reads() {
+    let mut g = start_simple("session_settings_apply_to_reads").await;
+    let sql = "
+        CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(aid, uid));
+        QUERY VoteCount: SELECT aid, COUNT(uid) AS votes FROM Vote WHERE aid = ? GROUP BY aid;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut vote = g.table("Vote").await.unwrap();
+    let mut count = g.view("VoteCount").await.unwrap();
+    vote.insert(vec![1.into(), 1.into()]).await.unwrap();
+    vote.flush_barrier().await.unwrap();
+
+    // settings changed after the view was created still apply to it
+    g.session().set("noria_block_on_miss", "off").unwrap();
+    assert!(count.lookup(&[1.into()], true).await.unwrap().is_empty());
+    g.session().set("noria_block_on_miss", "DEFAULT").unwrap();
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+
+    // reads may be answered from earlier results while they are recent enough
+    g.session().set("noria_max_staleness", "1min").unwrap();
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+    vote.insert(vec![1.into(), 2.into()]).await.unwrap();
+    vote.flush_barrier().await.unwrap();
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+
+    g.session().set("noria_max_staleness", "0").unwrap();
+    assert_eq!(
+        count.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_track_event_time() {
+    let mut g = start_simple("views_track_event_time").await;
+    let sql = "
+        CREATE TABLE Click (uid int, at bigint, PRIMARY KEY(uid, at));
+        QUERY Clicks: SELECT uid, COUNT(at) AS clicks FROM Click WHERE uid = ? GROUP BY uid;
+    ";
+    g.install_recipe(sql).await.unwrap();
+    assert!(g.set_event_time("Click", Some("nope")).await.is_err());
+    g.set_event_time("Click", Some("at")).await.unwrap();
+
+    let mut click = g.table("Click").await.unwrap();
+    let mut clicks = g.view("Clicks").await.unwrap();
+    assert_eq!(clicks.event_time_lag().await.unwrap(), None);
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let hour = 60 * 60 * 1000;
+    click
+        .insert(vec![1.into(), (now - hour).into()])
+        .await
+        .unwrap();
+    click.flush_barrier().await.unwrap();
+
+    let lag = clicks.event_time_lag().await.unwrap().unwrap();
+    assert!(lag >= Duration::from_secs(60 * 60));
+    assert!(lag < Duration::from_secs(2 * 60 * 60));
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn mutator_churn() {
     let mut g = start_simple("mutator_churn").await;
@@ -1051,6 +1368,55 @@ async fn mutator_churn() {
     }
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn deposed_leader_leaves_the_dataflow_running() {
+    use noria::consensus::Authority;
+
+    let authority = Arc::new(LocalAuthority::new());
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir
+        .path()
+        .join("deposed_leader_leaves_the_dataflow_running");
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(PersistenceParameters::new(
+        DurabilityMode::Permanent,
+        Duration::from_millis(1),
+        Some(path.to_string_lossy().into()),
+        1,
+    ));
+    let (mut g, done) = builder.start(authority.clone()).await.unwrap();
+    g.install_recipe(
+        "CREATE TABLE Car (id int, price int, PRIMARY KEY(id));
+         QUERY CarPrice: SELECT price FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator.insert(vec![1.into(), 10.into()]).await.unwrap();
+    sleep().await;
+
+    // the controller loses its session and steps down, and the next leader takes over the workers
+    authority.surrender_leadership().unwrap();
+
+    let mut served = false;
+    for _ in 0..50 {
+        sleep().await;
+        let mut getter = match g.view("CarPrice").await {
+            Ok(getter) => getter,
+            Err(_) => continue,
+        };
+        if let Ok(rs) = getter.lookup(&[1.into()], true).await {
+            assert_eq!(rs, vec![vec![10.into()]]);
+            served = true;
+            break;
+        }
+    }
+    assert!(served, "the successor never served reads");
+    drop(g);
+    done.await;
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn view_connection_churn() {
     let authority = Arc::new(LocalAuthority::new());
@@ -2519,6 +2885,38 @@ async fn state_replay_migration_query() {
     assert!(out.lookup(&[3.into()], true).await.unwrap().is_empty());
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn paced_state_replay() {
+    // partial views are filled on demand, so make sure we actually do a full-state replay
+    let mut g = Builder::default();
+    g.disable_partial();
+    g.set_persistence(get_persistence_params("paced_state_replay"));
+    let mut g = g.start_local().await.unwrap().0;
+    let a = g
+        .migrate(|mig| mig.add_base("a", &["x", "y"], Base::default()))
+        .await;
+    let mut muta = g.table("a").await.unwrap();
+    muta.perform_all((0..100).map(|i| vec![i.into(), (i % 10).into()]))
+        .await
+        .unwrap();
+
+    g.migrate(move |mig| {
+        mig.set_replay_config(ReplayConfig {
+            batch_size: 7,
+            pacing: ReplayPacing::Adaptive(Duration::from_millis(5)),
+        });
+        let p = mig.add_ingredient("p", &["x", "y"], Project::new(a, &[0, 1], None, None));
+        mig.maintain_anonymous(p, &[1]);
+    })
+    .await;
+    let mut out = g.view("p").await.unwrap();
+
+    // the replay was split into many chunks, but all of them should have arrived
+    for y in 0..10 {
+        assert_eq!(out.lookup(&[y.into()], true).await.unwrap().len(), 10);
+    }
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn recipe_activates() {
     let mut g = start_simple("recipe_activates").await;
@@ -2959,3 +3357,3448 @@ async fn correct_nested_view_schema() {
     ];
     assert_eq!(q.schema(), Some(&expected_schema[..]));
 }
+
+#[tokio::test(threaded_scheduler)]
+async fn read_only_tables_reject_writes() {
+    use noria::error::{TableError, WriteRejection};
+
+    let mut g = start_simple("read_only_tables_reject_writes").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarsByBrand").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    g.set_read_only(Some("Car"), true).await.unwrap();
+    match mutator.insert(vec![2.into(), "Volvo".into()]).await {
+        Err(TableError::Rejected(WriteRejection::ReadOnly { table })) => assert_eq!(table, "Car"),
+        r => panic!("write to read-only table was not rejected: {:?}", r),
+    }
+
+    // reads are still served
+    sleep().await;
+    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), "Volvo".into()]]);
+
+    g.set_read_only(None, false).await.unwrap();
+    mutator
+        .insert(vec![2.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
+    assert_eq!(result.len(), 2);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn controller_advertises_its_protocol() {
+    use noria::protocol::feature;
+    use noria::Protocol;
+
+    let g = start_simple("controller_advertises_its_protocol").await;
+    let protocol = g.protocol().unwrap();
+    assert!(protocol.is_compatible(&Protocol::current()));
+    assert!(protocol.supports(feature::DEAD_LETTERS));
+    assert_eq!(
+        protocol.supports(feature::KAFKA_SOURCES),
+        cfg!(feature = "connectors")
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn invalid_writes_become_dead_letters() {
+    use noria::error::{TableError, WriteRejection};
+    use noria::{Modification, Operation, TableOperation};
+
+    let mut g = start_simple("invalid_writes_become_dead_letters").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), sold int, PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand, sold FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarsByBrand").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into(), 10.into()])
+        .await
+        .unwrap();
+    let sold = Modification::Apply(Operation::Add, "many".into());
+    match mutator
+        .update(vec![1.into()], vec![(2, sold.clone())])
+        .await
+    {
+        Err(TableError::Rejected(WriteRejection::Invalid { table, .. })) => {
+            assert_eq!(table, "Car")
+        }
+        r => panic!("invalid write was not rejected: {:?}", r),
+    }
+
+    // the row is untouched, and the rejected write is kept
+    sleep().await;
+    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), "Volvo".into(), 10.into()]]);
+
+    let dead = g.dead_letters("Car").await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(
+        dead[0].op,
+        TableOperation::Update {
+            key: vec![1.into()],
+            set: vec![Modification::None, Modification::None, sold],
+        }
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn assertion_violations_are_quarantined() {
+    use noria::{Assertion, Modification};
+
+    let mut g = start_simple("assertion_violations_are_quarantined").await;
+    g.install_recipe(
+        "CREATE TABLE Account (id int, balance int, PRIMARY KEY(id));
+         QUERY Balance: SELECT id, balance FROM Account WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    g.add_assertion(
+        "no_overdraft",
+        "Balance",
+        Assertion::NonNegative {
+            column: "balance".to_owned(),
+        },
+        true,
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Account").await.unwrap();
+    let mut getter = g.view("Balance").await.unwrap();
+
+    mutator.insert(vec![1.into(), 10.into()]).await.unwrap();
+    sleep().await;
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), 10.into()]]);
+    assert!(g.quarantine("Balance").await.unwrap().is_empty());
+
+    // the violating change is kept out of the view, which keeps the last good balance
+    mutator
+        .update(vec![1.into()], vec![(1, Modification::Set((-5).into()))])
+        .await
+        .unwrap();
+    sleep().await;
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), 10.into()]]);
+
+    let quarantined = g.quarantine("Balance").await.unwrap();
+    assert_eq!(quarantined.len(), 1);
+    let v = &quarantined[0];
+    assert_eq!(v.assertion, "no_overdraft");
+    assert_eq!(v.row, vec![1.into(), (-5).into()]);
+    assert!(v.halted);
+    assert!(v.delta.contains(&(vec![1.into(), 10.into()], false)));
+    assert!(v.delta.contains(&(vec![1.into(), (-5).into()], true)));
+    assert_eq!(v.provenance.len(), 1);
+    assert_eq!(v.provenance[0].first().map(String::as_str), Some("Balance"));
+    assert_eq!(v.provenance[0].last().map(String::as_str), Some("Account"));
+
+    assert!(g
+        .add_assertion(
+            "no_overdraft",
+            "Balance",
+            Assertion::NotNull {
+                column: "id".to_owned(),
+            },
+            false,
+        )
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn triggers_insert_view_changes_into_table() {
+    use noria::TriggerAction;
+
+    let mut g = start_simple("triggers_insert_view_changes_into_table").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         CREATE TABLE VolvoLog (id int, brand varchar(255));
+         QUERY Volvos: SELECT id, brand FROM Car WHERE brand = 'Volvo';
+         QUERY AllVolvoLog: SELECT id, brand FROM VolvoLog;",
+    )
+    .await
+    .unwrap();
+
+    g.add_trigger(
+        "log_volvos",
+        "Volvos",
+        TriggerAction::Insert {
+            table: "VolvoLog".to_owned(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // trigger names are unique
+    assert!(g
+        .add_trigger(
+            "log_volvos",
+            "Volvos",
+            TriggerAction::Insert {
+                table: "VolvoLog".to_owned(),
+            },
+        )
+        .await
+        .is_err());
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("AllVolvoLog").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    mutator.insert(vec![2.into(), "Saab".into()]).await.unwrap();
+    sleep().await;
+    sleep().await;
+
+    let result = getter.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(result, vec![vec![1.into(), "Volvo".into()]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn compressed_columns_read_back_uncompressed() {
+    let mut g = start_simple_unsharded("compressed_columns_read_back_uncompressed").await;
+    let a = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["id", "body"], Base::new(vec![]).with_key(vec![0]));
+            mig.maintain_anonymous(a, &[0]);
+            mig.compress_columns(a, &[1]).unwrap();
+            a
+        })
+        .await;
+
+    let body = "a".repeat(noria::COMPRESSION_THRESHOLD * 4);
+    let mut muta = g.table("a").await.unwrap();
+    let mut aq = g.view("a").await.unwrap();
+    muta.insert(vec![1.into(), body.clone().into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        aq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), body.into()]]
+    );
+
+    // the states of existing nodes already hold their values uncompressed
+    assert!(g
+        .migrate(move |mig| mig.compress_columns(a, &[1]))
+        .await
+        .is_err());
+
+    // and only columns the node has can be compressed
+    assert!(g
+        .migrate(|mig| {
+            let b = mig.add_base("b", &["id", "body"], Base::new(vec![]).with_key(vec![0]));
+            mig.compress_columns(b, &[2])
+        })
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn refreshed_nodes_keep_their_output() {
+    use dataflow::ops::filter::{Filter, FilterCondition, Operator, Value};
+
+    let mut g = start_simple_unsharded("refreshed_nodes_keep_their_output").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+        let f = mig.add_ingredient(
+            "f",
+            &["a", "b"],
+            Filter::new(
+                a,
+                &[(
+                    1,
+                    FilterCondition::Comparison(Operator::Equal, Value::Constant(2.into())),
+                )],
+            ),
+        );
+        mig.refresh_every(f, Duration::from_millis(100)).unwrap();
+        mig.maintain_anonymous(f, &[0]);
+        f
+    })
+    .await;
+
+    assert!(g.graphviz().await.unwrap().contains("⟳"));
+
+    // nodes can only be refreshed from the migration that adds them
+    assert!(g
+        .migrate(move |mig| mig.refresh_every(f, Duration::from_millis(100)))
+        .await
+        .is_err());
+
+    // and only query-through operators can be refreshed
+    assert!(g
+        .migrate(|mig| {
+            let b = mig.add_base("b", &["a", "b"], Base::new(vec![]).with_key(vec![0]));
+            mig.refresh_every(b, Duration::from_millis(100))
+        })
+        .await
+        .is_err());
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut fq = g.view("f").await.unwrap();
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    muta.insert(vec![2.into(), 3.into()]).await.unwrap();
+    sleep().await;
+
+    // let a few heartbeats trigger refreshes, which shouldn't change anything
+    tokio::time::delay_for(Duration::from_secs(3)).await;
+    assert_eq!(
+        fq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+    assert!(fq.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    // and the node is still maintained incrementally in between
+    muta.insert(vec![3.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        fq.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), 2.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn refreshes_reevaluate_rows() {
+    use dataflow::ops::project::{BuiltinFunction, Project, ProjectExpression};
+
+    let mut g = start_simple_unsharded("refreshes_reevaluate_rows").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["id"], Base::new(vec![]).with_key(vec![0]));
+        let p = mig.add_ingredient(
+            "p",
+            &["id", "now"],
+            Project::new(
+                a,
+                &[0],
+                None,
+                Some(vec![ProjectExpression::Call(BuiltinFunction::Now, vec![])]),
+            ),
+        );
+        mig.refresh_every(p, Duration::from_millis(100)).unwrap();
+        mig.maintain_anonymous(p, &[0]);
+    })
+    .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut pq = g.view("p").await.unwrap();
+    muta.insert(vec![1.into()]).await.unwrap();
+    sleep().await;
+    let before = pq.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(before.len(), 1);
+
+    // nothing is written, but refreshes evaluate the row again
+    tokio::time::delay_for(Duration::from_secs(3)).await;
+    let after = pq.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(after.len(), 1);
+    assert!(after[0][1] > before[0][1]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn time_dependent_filters_drop_old_rows() {
+    use dataflow::ops::filter::{Filter, FilterCondition, Operator};
+    use dataflow::ops::project::{BuiltinFunction, ProjectExpression};
+
+    let mut g = start_simple_unsharded("time_dependent_filters_drop_old_rows").await;
+    g.migrate(|mig| {
+        let a = mig.add_base("a", &["id", "ts"], Base::new(vec![]).with_key(vec![0]));
+        // ts > NOW() - INTERVAL 2 SECOND
+        let recent = ProjectExpression::Compare {
+            op: Operator::Greater,
+            left: Box::new(ProjectExpression::Column(1)),
+            right: Box::new(ProjectExpression::Call(
+                BuiltinFunction::DateSub,
+                vec![
+                    ProjectExpression::Call(BuiltinFunction::Now, vec![]),
+                    ProjectExpression::Literal(2.into()),
+                    ProjectExpression::Literal("second".into()),
+                ],
+            )),
+        };
+        let f = mig.add_ingredient(
+            "recent",
+            &["id", "ts"],
+            Filter::new(a, &[(1, FilterCondition::Expression(recent))]),
+        );
+        mig.refresh_every(f, Duration::from_millis(100)).unwrap();
+        mig.maintain_anonymous(f, &[0]);
+    })
+    .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    let mut recent = g.view("recent").await.unwrap();
+    let now = ProjectExpression::Call(BuiltinFunction::Now, vec![]).eval(&[]);
+    muta.insert(vec![1.into(), now.clone()]).await.unwrap();
+    muta.insert(vec![2.into(), "2000-01-01T00:00:00Z".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        recent.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), now]]
+    );
+    assert!(recent.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    // once the row is old enough, a refresh removes it
+    tokio::time::delay_for(Duration::from_secs(4)).await;
+    assert!(recent.lookup(&[1.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn mirrored_tables_forward_writes_to_sink() {
+    use noria::{Mirror, MirrorTarget};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mirror.json");
+
+    let mut g = start_simple("mirrored_tables_forward_writes_to_sink").await;
+    g.install_recipe("CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));")
+        .await
+        .unwrap();
+
+    // fractions must be between 0 and 1
+    assert!(g
+        .set_mirror(
+            "Car",
+            Some(Mirror {
+                fraction: 2.0,
+                target: MirrorTarget::Sink {
+                    path: path.to_str().unwrap().to_owned(),
+                },
+            }),
+        )
+        .await
+        .is_err());
+
+    g.set_mirror(
+        "Car",
+        Some(Mirror {
+            fraction: 1.0,
+            target: MirrorTarget::Sink {
+                path: path.to_str().unwrap().to_owned(),
+            },
+        }),
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mirrored = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(mirrored.lines().count(), 1);
+    assert!(mirrored.contains("Volvo"));
+
+    // writes are no longer mirrored once the mirror is removed
+    g.set_mirror("Car", None).await.unwrap();
+    mutator.insert(vec![2.into(), "Saab".into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), mirrored);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn read_transactions_see_consistent_snapshots() {
+    use noria::error::TransactionError;
+
+    // snapshot reads have to be enabled explicitly
+    let g = start_simple("read_transactions_unsupported").await;
+    assert!(g.read_transaction().is_err());
+
+    let mut builder = Builder::default();
+    builder.set_sharding(Some(DEFAULT_SHARDING));
+    builder.set_persistence(get_persistence_params(
+        "read_transactions_see_consistent_snapshots",
+    ));
+    builder.enable_snapshot_reads();
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;
+         QUERY CarById: SELECT id, brand FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+    let mut by_id = g.view("CarById").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![2.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut tx = g.read_transaction().unwrap();
+    let volvos = tx.lookup(&mut by_brand, &["Volvo".into()]).await.unwrap();
+    assert_eq!(volvos.len(), 2);
+    let car = tx.lookup(&mut by_id, &[1.into()]).await.unwrap();
+    assert_eq!(car.len(), 1);
+    assert!(!tx.snapshot().is_empty());
+
+    // once a view has moved past the snapshot, the transaction has to be restarted
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert!(matches!(
+        tx.lookup(&mut by_id, &[1.into()]).await,
+        Err(TransactionError::Conflict)
+    ));
+
+    let mut tx = g.read_transaction().unwrap();
+    let volvos = tx.lookup(&mut by_brand, &["Volvo".into()]).await.unwrap();
+    assert_eq!(volvos.len(), 1);
+    let car = tx.lookup(&mut by_id, &[1.into()]).await.unwrap();
+    assert!(car.is_empty());
+
+    // reading the views together settles on the latest writes that all of them reflect
+    mutator
+        .insert(vec![3.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    let mut tx = g.read_transaction().unwrap();
+    let (volvo, brand) = (vec![3.into()], vec!["Volvo".into()]);
+    let results = tx
+        .lookup_all(&mut [(&mut by_id, &volvo[..]), (&mut by_brand, &brand[..])])
+        .await
+        .unwrap();
+    // either both views reflect the new car, or neither does
+    assert_eq!(results[1].len(), results[0].len() + 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn batched_lookups_return_every_key() {
+    use futures_util::stream::TryStreamExt;
+
+    let mut g = start_simple("batched_lookups_return_every_key").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarById: SELECT id, brand FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_id = g.view("CarById").await.unwrap();
+    mutator
+        .perform_all((0..3000).map(|i| vec![i.into(), "Volvo".into()]))
+        .await
+        .unwrap();
+    sleep().await;
+
+    // more keys than fit in one batch, some repeated, and some missing
+    let keys: Vec<Vec<DataType>> = (0..3500).chain(0..10).map(|i| vec![i.into()]).collect();
+    let results: HashMap<_, _> = by_id
+        .lookup_batched(keys, true)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 3500);
+    for (key, rs) in results {
+        let id: i32 = (&key[0]).into();
+        if id < 3000 {
+            assert_eq!(rs, vec![vec![key[0].clone(), "Volvo".into()]]);
+        } else {
+            assert!(rs.is_empty());
+        }
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn lookups_with_a_deadline_fill_misses() {
+    let mut g = start_simple("lookups_with_a_deadline_fill_misses").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the key has never been read, so the lookup has to wait for it to be replayed
+    let volvos = by_brand
+        .lookup_timeout(&["Volvo".into()], Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(volvos, vec![vec![1.into(), "Volvo".into()]]);
+    let saabs = by_brand
+        .lookup_timeout(&["Saab".into()], Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert!(saabs.is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn lookups_after_a_write_token_see_the_write() {
+    let mut builder = Builder::default();
+    builder.set_sharding(Some(DEFAULT_SHARDING));
+    builder.set_persistence(get_persistence_params(
+        "lookups_after_a_write_token_see_the_write",
+    ));
+    builder.enable_snapshot_reads();
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+
+    let token = mutator
+        .insert_with_token(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    assert!(!token.is_empty());
+    let volvos = by_brand
+        .lookup_after(&["Volvo".into()], &token)
+        .await
+        .unwrap();
+    assert_eq!(volvos.len(), 1);
+
+    // writes that a view filters out still satisfy tokens
+    let token = mutator
+        .perform_all_with_token(vec![
+            vec![2.into(), "Volvo".into()],
+            vec![3.into(), "Saab".into()],
+        ])
+        .await
+        .unwrap();
+    let volvos = by_brand
+        .lookup_after(&["Volvo".into()], &token)
+        .await
+        .unwrap();
+    assert_eq!(volvos.len(), 2);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn fresh_lookups_see_earlier_writes() {
+    let mut builder = Builder::default();
+    builder.set_sharding(Some(DEFAULT_SHARDING));
+    builder.set_persistence(get_persistence_params("fresh_lookups_see_earlier_writes"));
+    builder.set_frontier_interval(Duration::from_millis(10));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    let volvos = by_brand
+        .lookup_fresh(
+            &["Volvo".into()],
+            std::time::SystemTime::now(),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+    assert_eq!(volvos.len(), 1);
+
+    let staleness = by_brand.staleness().await.unwrap().unwrap();
+    assert!(staleness < Duration::from_secs(5));
+
+    // a time the frontiers have not reached yet cannot be read within the timeout
+    let future = std::time::SystemTime::now() + Duration::from_secs(60);
+    assert!(matches!(
+        by_brand
+            .lookup_fresh(&["Volvo".into()], future, Duration::from_millis(100))
+            .await,
+        Err(noria::error::ViewError::Timeout)
+    ));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn controller_records_events() {
+    use noria::ControllerEventKind;
+
+    let mut g = start_simple("controller_records_events").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let events = g.events(0).await.unwrap();
+    assert!(events
+        .iter()
+        .any(|e| matches!(e.kind, ControllerEventKind::LeaderElected { .. })));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e.kind, ControllerEventKind::WorkerJoined { .. })));
+    assert!(matches!(
+        events.last().unwrap().kind,
+        ControllerEventKind::Migration { replace: true, .. }
+    ));
+    assert!(events.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+
+    // the log can be read from a given position onwards
+    let last = events.last().unwrap().seq;
+    assert_eq!(g.events(last).await.unwrap(), &events[events.len() - 1..]);
+    assert!(g.events(last + 1).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn estimate_reflects_existing_state() {
+    let mut g = start_simple("estimate_reflects_existing_state").await;
+    g.install_recipe("CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));")
+        .await
+        .unwrap();
+
+    let query = "SELECT id, brand FROM Car WHERE brand = ?;";
+    let before = g.estimate(query).await.unwrap();
+    assert!(before.nodes >= 2);
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for i in 0..100 {
+        mutator
+            .insert(vec![i.into(), "Volvo".into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let after = g.estimate(query).await.unwrap();
+    assert!(after.state_bytes > before.state_bytes);
+    assert!(g
+        .estimate("SELECT id FROM Truck WHERE id = ?;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn state_statistics_describe_tables_and_views() {
+    let mut g = start_simple_unsharded("state_statistics_describe_tables_and_views").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+    mutator
+        .perform_all(vec![
+            vec![1.into(), "Volvo".into()],
+            vec![2.into(), "Volvo".into()],
+            vec![3.into(), "Saab".into()],
+        ])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // the first lookup of a key misses, and the next one finds it
+    for _ in 0..2 {
+        by_brand.lookup(&["Volvo".into()], true).await.unwrap();
+    }
+    by_brand.lookup(&["Saab".into()], true).await.unwrap();
+
+    let stats = g.state_statistics(true).await.unwrap();
+    let view = &stats.views["CarsByBrand"];
+    assert_eq!(view.rows, 3);
+    assert_eq!(
+        view.distinct_keys
+            .iter()
+            .map(|&(_, n)| n)
+            .collect::<Vec<_>>(),
+        vec![2]
+    );
+    assert!(view.mem_size > 0);
+    assert!(view.hits > 0 && view.misses > 0);
+    assert!(view.hit_ratio().unwrap() < 1.0);
+
+    let (_, car) = stats
+        .nodes
+        .values()
+        .find(|(name, _)| name == "Car")
+        .unwrap();
+    assert!(car.rows > 0);
+    assert!(!car.distinct_keys.is_empty());
+
+    // without asking for fresh statistics, the ones just gathered are returned
+    let cached = g.state_statistics(false).await.unwrap();
+    assert_eq!(cached.views, stats.views);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn explain_plans_queries() {
+    let mut g = start_simple("explain_plans_queries").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    // installed queries are explained by name, and by their text
+    let plan = g.explain("CarsByBrand").await.unwrap();
+    assert_eq!(plan.view.as_deref(), Some("CarsByBrand"));
+    assert_eq!(plan.key, vec!["Car.brand".to_owned()]);
+    assert!(plan.nodes.iter().all(|n| n.node.is_some()));
+    let same = g
+        .explain("EXPLAIN SELECT id, brand FROM Car WHERE brand = ?;")
+        .await
+        .unwrap();
+    assert_eq!(same.view, plan.view);
+
+    // new queries reuse what they can of existing ones
+    let plan = g
+        .explain("SELECT brand, COUNT(id) AS n FROM Car GROUP BY brand;")
+        .await
+        .unwrap();
+    assert_eq!(plan.view, None);
+    assert!(plan.nodes.iter().any(|n| n.reused));
+    assert!(plan.nodes.iter().any(|n| !n.reused));
+
+    assert!(g.explain("SELECT id FROM Truck;").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn dataflow_graph_shows_where_state_lives() {
+    let mut g = start_simple("dataflow_graph_shows_where_state_lives").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for i in 0..10 {
+        mutator
+            .insert(vec![i.into(), "Volvo".into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    let graph = g.dataflow_graph().await.unwrap();
+    let car = graph.nodes.iter().find(|n| n.name == "Car").unwrap();
+    assert_eq!(car.operator, "base");
+    assert!(car.domain.is_some());
+    assert_eq!(car.rows, 10);
+    assert!(car.mem_size > 0);
+    assert!(graph.nodes.iter().any(|n| n.operator == "reader"));
+    assert!(graph
+        .edges
+        .iter()
+        .all(|&(from, to)| graph.nodes.iter().any(|n| n.index == from)
+            && graph.nodes.iter().any(|n| n.index == to)));
+    assert!(graph.graphviz().contains(&format!("n{} ", car.index)));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn recipe_can_be_dry_run_and_rolled_back() {
+    let mut g = start_simple("recipe_can_be_dry_run_and_rolled_back").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe("QUERY CarsById: SELECT id, brand FROM Car WHERE id = ?;")
+        .await
+        .unwrap();
+
+    let diff = g
+        .dry_run_recipe(
+            "QUERY BrandsById: SELECT brand FROM Car WHERE id = ?;",
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(diff.added, vec!["BrandsById".to_owned()]);
+    assert!(diff.removed.is_empty());
+    assert!(!diff.new_nodes.is_empty());
+    assert!(g.view("BrandsById").await.is_err());
+
+    let diff = g
+        .dry_run_recipe(
+            "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));",
+            true,
+        )
+        .await
+        .unwrap();
+    assert!(diff.added.is_empty());
+    assert_eq!(diff.removed.len(), 2);
+    assert_eq!(diff.removed_leaves.len(), 2);
+
+    let history = g.recipe_history().await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(!history[0].recipe.contains("CarsById"));
+    g.rollback_recipe(history[0].version).await.unwrap();
+    assert!(g.view("CarsByBrand").await.is_ok());
+    assert!(g.view("CarsById").await.is_err());
+
+    // the rollback is itself a new version
+    let rolled_back = g.recipe_history().await.unwrap();
+    assert_eq!(rolled_back.len(), 3);
+    assert_eq!(rolled_back[2].recipe, history[0].recipe);
+    assert!(g.rollback_recipe(rolled_back[2].version).await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn slow_replays_are_logged_by_key() {
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params("slow_replays_are_logged_by_key"));
+    // every replay counts as slow
+    builder.set_slow_replay_threshold(Duration::from_secs(0));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut q = g.view("CarsByBrand").await.unwrap();
+    assert_eq!(q.lookup(&["Volvo".into()], true).await.unwrap().len(), 1);
+    sleep().await;
+
+    let slow = g.slow_replays().await.unwrap();
+    let replay = slow
+        .iter()
+        .find(|s| s.view == "CarsByBrand")
+        .expect("replay into the view was not logged");
+    assert_eq!(replay.key, vec![DataType::from("Volvo")]);
+    assert!(!replay.domains.is_empty());
+    assert!(replay.bytes > 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn new_views_are_warmed_in_the_background() {
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params(
+        "new_views_are_warmed_in_the_background",
+    ));
+    builder.disable_partial();
+    builder.set_warm_rate(1000);
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe("CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));")
+        .await
+        .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    for (i, brand) in ["Volvo", "Saab", "Volvo", "Koenigsegg"].iter().enumerate() {
+        mutator
+            .insert(vec![(i as i32).into(), (*brand).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    g.extend_recipe("QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;")
+        .await
+        .unwrap();
+    let mut q = g.view("CarsByBrand").await.unwrap();
+    assert_eq!(q.lookup(&["Volvo".into()], true).await.unwrap().len(), 2);
+
+    // warming happens on heartbeats
+    let mut status = Vec::new();
+    for _ in 0..20 {
+        status = g.warming_status().await.unwrap();
+        if status.iter().all(|w| w.is_complete()) {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(500)).await;
+    }
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].view, "CarsByBrand");
+    assert_eq!(status[0].keys, Some(3));
+    assert!(status[0].is_complete());
+
+    // warmed keys are there without having to be replayed when they are read
+    sleep().await;
+    assert_eq!(q.lookup(&["Saab".into()], false).await.unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn profile_attributes_time_to_nodes() {
+    let mut g = start_simple("profile_attributes_time_to_nodes").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CountByBrand: SELECT brand, COUNT(id) AS n FROM Car GROUP BY brand;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let writes = async {
+        for i in 0..1000 {
+            mutator
+                .insert(vec![i.into(), "Volvo".into()])
+                .await
+                .unwrap();
+        }
+    };
+    let (profile, _) = tokio::join!(g.profile(Duration::from_secs(1)), writes);
+    let profile = profile.unwrap();
+
+    assert!(profile.duration >= Duration::from_secs(1));
+    assert!(!profile.nodes.is_empty());
+    assert!(profile
+        .nodes
+        .windows(2)
+        .all(|w| w[0].process_time >= w[1].process_time));
+    assert!(profile.nodes.iter().any(|n| n.name == "Car"));
+    assert!(profile.folded().lines().all(|l| l.starts_with("domain ")));
+
+    // nothing is measured outside of a profiling run
+    let idle = g.profile(Duration::from_millis(10)).await.unwrap();
+    assert!(idle.nodes.is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn unsupported_queries_pass_through() {
+    let recipe = "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+                  QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;
+                  QUERY Trucks: SELECT id FROM Truck WHERE id = ?;";
+
+    let mut builder = Builder::default();
+    builder.set_persistence(get_persistence_params("unsupported_queries_pass_through"));
+    builder.enable_pass_through();
+    let mut g = builder.start_local().await.unwrap().0;
+    let res = g.install_recipe(recipe).await.unwrap();
+    assert_eq!(res.pass_through, vec!["Trucks".to_owned()]);
+    assert!(g.view("CarsByBrand").await.is_ok());
+    assert!(g.view("Trucks").await.is_err());
+
+    let pass_through = g.pass_through_queries().await.unwrap();
+    assert_eq!(pass_through.len(), 1);
+    assert_eq!(pass_through["Trucks"], "SELECT id FROM Truck WHERE id = ?;");
+
+    // extensions add to the pass-through queries
+    let res = g
+        .extend_recipe("QUERY Bikes: SELECT id FROM Bike WHERE id = ?;")
+        .await
+        .unwrap();
+    assert_eq!(res.pass_through, vec!["Bikes".to_owned()]);
+    assert_eq!(g.pass_through_queries().await.unwrap().len(), 2);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_can_be_aliased_and_renamed() {
+    let mut g = start_simple("views_can_be_aliased_and_renamed").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;
+         CREATE VIEW Cars AS SELECT * FROM CarsByBrand;",
+    )
+    .await
+    .unwrap();
+    let nodes = g.outputs().await.unwrap();
+    assert_eq!(nodes["Cars"], nodes["CarsByBrand"]);
+
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut old = g.view("CarsByBrand").await.unwrap();
+    let mut alias = g.view("Cars").await.unwrap();
+    assert_eq!(
+        alias.lookup(&["Volvo".into()], true).await.unwrap().len(),
+        1
+    );
+
+    let res = g
+        .extend_recipe("RENAME VIEW CarsByBrand TO VehiclesByBrand;")
+        .await
+        .unwrap();
+    assert!(res.new_nodes.is_empty());
+    assert!(g.view("CarsByBrand").await.is_err());
+    let mut renamed = g.view("VehiclesByBrand").await.unwrap();
+    assert_eq!(
+        renamed.lookup(&["Volvo".into()], true).await.unwrap().len(),
+        1
+    );
+
+    // handles obtained before the rename keep working
+    assert_eq!(old.lookup(&["Volvo".into()], true).await.unwrap().len(), 1);
+    assert_eq!(
+        alias.lookup(&["Volvo".into()], true).await.unwrap().len(),
+        1
+    );
+
+    // names that are taken cannot be reused
+    assert!(g
+        .extend_recipe("RENAME VIEW Cars TO VehiclesByBrand;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn write_batches_apply_in_order() {
+    use noria::WriteBatch;
+
+    let mut g = start_simple("write_batches_apply_in_order").await;
+    g.install_recipe(
+        "CREATE TABLE Author (id int, name varchar(255), PRIMARY KEY(id));
+         CREATE TABLE Book (id int, author int, title varchar(255), PRIMARY KEY(id));
+         QUERY BooksByAuthor: SELECT Book.title, Author.name FROM Book \
+            JOIN Author ON (Book.author = Author.id) WHERE Author.id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .insert("Author", vec![1.into(), "Le Guin".into()])
+        .insert("Book", vec![1.into(), 1.into(), "The Dispossessed".into()])
+        .insert(
+            "Book",
+            vec![2.into(), 1.into(), "The Lathe of Heaven".into()],
+        );
+    g.write_batch(batch).await.unwrap();
+    sleep().await;
+
+    let mut books = g.view("BooksByAuthor").await.unwrap();
+    assert_eq!(books.lookup(&[1.into()], true).await.unwrap().len(), 2);
+
+    // writes after a failing one are not applied
+    let mut batch = WriteBatch::new();
+    batch
+        .insert("Author", vec![2.into()])
+        .insert("Book", vec![3.into(), 2.into(), "Kindred".into()]);
+    assert!(g.write_batch(batch).await.is_err());
+    sleep().await;
+    assert!(books.lookup(&[2.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn atomic_writes_become_visible_together() {
+    use noria::WriteBatch;
+
+    let mut g = start_simple("atomic_writes_become_visible_together").await;
+    g.install_recipe(
+        "CREATE TABLE Purchase (id int, customer int, PRIMARY KEY(id));
+         CREATE TABLE LineItem (id int, purchase int, PRIMARY KEY(id));
+         QUERY Purchases: SELECT id, customer FROM Purchase WHERE customer = ?;
+         QUERY PurchasedItems: SELECT Purchase.customer, LineItem.id FROM LineItem \
+            JOIN Purchase ON (LineItem.purchase = Purchase.id) WHERE Purchase.customer = ?;",
+    )
+    .await
+    .unwrap();
+
+    // fill the keys, so that the reads below see what the writes did to the views, rather than
+    // replaying the key from the base tables
+    let mut purchases = g.view("Purchases").await.unwrap();
+    let mut items = g.view("PurchasedItems").await.unwrap();
+    assert!(purchases
+        .lookup(&[1.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(items.lookup(&[1.into()], true).await.unwrap().is_empty());
+
+    let mut batch = WriteBatch::new();
+    batch
+        .insert("Purchase", vec![1.into(), 1.into()])
+        .insert("LineItem", vec![10.into(), 1.into()])
+        .insert("LineItem", vec![11.into(), 1.into()]);
+    g.write_atomically(batch).await.unwrap();
+
+    // once the write returns, every view shows all of it
+    assert_eq!(purchases.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    assert_eq!(items.lookup(&[1.into()], true).await.unwrap().len(), 2);
+
+    // and the views no longer hold back later writes
+    let mut item = g.table("LineItem").await.unwrap();
+    item.insert(vec![12.into(), 1.into()]).await.unwrap();
+    item.flush_barrier().await.unwrap();
+    assert_eq!(items.lookup(&[1.into()], true).await.unwrap().len(), 3);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn rejected_atomic_writes_are_undone() {
+    use noria::WriteBatch;
+
+    let mut g = start_simple("rejected_atomic_writes_are_undone").await;
+    g.install_recipe(
+        "CREATE TABLE Purchase (id int, customer int, PRIMARY KEY(id));
+         CREATE TABLE LineItem (id int, purchase int, PRIMARY KEY(id));
+         QUERY Purchases: SELECT id, customer FROM Purchase WHERE customer = ?;
+         QUERY PurchasedItems: SELECT Purchase.customer, LineItem.id FROM LineItem \
+            JOIN Purchase ON (LineItem.purchase = Purchase.id) WHERE Purchase.customer = ?;",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe("ALTER TABLE LineItem ADD CONSTRAINT positive_id CHECK (id > 0);")
+        .await
+        .unwrap();
+
+    let mut purchases = g.view("Purchases").await.unwrap();
+    let mut items = g.view("PurchasedItems").await.unwrap();
+    assert!(purchases
+        .lookup(&[1.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(items.lookup(&[1.into()], true).await.unwrap().is_empty());
+
+    let mut batch = WriteBatch::new();
+    batch
+        .insert("Purchase", vec![1.into(), 1.into()])
+        .insert("LineItem", vec![(-10).into(), 1.into()]);
+    assert!(g.write_atomically(batch).await.is_err());
+
+    // the purchase was undone along with the rejected line item
+    assert!(purchases
+        .lookup(&[1.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(items.lookup(&[1.into()], true).await.unwrap().is_empty());
+
+    // and the table is as it was, so the purchase can be written again
+    let mut batch = WriteBatch::new();
+    batch
+        .insert("Purchase", vec![1.into(), 1.into()])
+        .insert("LineItem", vec![10.into(), 1.into()]);
+    g.write_atomically(batch).await.unwrap();
+    assert_eq!(purchases.lookup(&[1.into()], true).await.unwrap().len(), 1);
+    assert_eq!(items.lookup(&[1.into()], true).await.unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn full_outer_join_pads_both_sides() {
+    let mut g = start_simple("full_outer_join_pads_both_sides").await;
+    let (a, b) = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["id", "a"], Base::new(vec![]).with_key(vec![0]));
+            let b = mig.add_base("b", &["id", "b"], Base::new(vec![]).with_key(vec![0]));
+            (a, b)
+        })
+        .await;
+
+    let mut at = g.table("a").await.unwrap();
+    let mut bt = g.table("b").await.unwrap();
+    at.insert(vec![1.into(), "a1".into()]).await.unwrap();
+    at.insert(vec![3.into(), "a3".into()]).await.unwrap();
+    bt.insert(vec![1.into(), "b1".into()]).await.unwrap();
+    bt.insert(vec![2.into(), "b2".into()]).await.unwrap();
+    sleep().await;
+
+    // the join is added once both sides have rows, so its initial state comes from replay
+    g.migrate(move |mig| {
+        let j = Join::new(a, b, JoinType::Full, vec![B(0, 0), L(1), R(1)]);
+        let j = mig.add_ingredient("ab", &["id", "a", "b"], j);
+        mig.maintain_anonymous(j, &[0]);
+    })
+    .await;
+
+    let mut r = g.view("ab").await.unwrap();
+    assert_eq!(
+        r.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a1".into(), "b1".into()]]
+    );
+    assert_eq!(
+        r.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), DataType::None, "b2".into()]]
+    );
+    assert_eq!(
+        r.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), "a3".into(), DataType::None]]
+    );
+
+    // a matching row revokes the padded one
+    at.insert(vec![2.into(), "a2".into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        r.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), "a2".into(), "b2".into()]]
+    );
+
+    // and removing the last match brings it back
+    bt.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        r.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a1".into(), DataType::None]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn slow_lookups_fall_back_to_upstream() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingUpstream(Mutex<Vec<(String, Vec<DataType>)>>);
+    impl noria::Upstream for RecordingUpstream {
+        fn execute(&self, query: &str, params: &[DataType]) -> noria::UpstreamFuture {
+            self.0
+                .lock()
+                .unwrap()
+                .push((query.to_owned(), params.to_vec()));
+            let row = vec![params[0].clone(), "Upstream".into()];
+            Box::pin(async move { Ok(vec![row]) })
+        }
+    }
+
+    let mut g = start_simple("slow_lookups_fall_back_to_upstream").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsById: SELECT id, brand FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut cars = g.view("CarsById").await.unwrap();
+    assert!(cars.query().unwrap().starts_with("SELECT"));
+
+    // a lookup that can never finish in time is answered upstream
+    let upstream = Arc::new(RecordingUpstream::default());
+    cars.fall_back_to(upstream.clone(), Duration::from_secs(0));
+    assert_eq!(
+        cars.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "Upstream".into()]]
+    );
+    {
+        let calls = upstream.0.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, cars.query().unwrap());
+        assert_eq!(calls[0].1, vec![DataType::from(1)]);
+    }
+
+    // while one that does is answered by the view
+    cars.fall_back_to(upstream.clone(), Duration::from_secs(10));
+    assert_eq!(
+        cars.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "Volvo".into()]]
+    );
+    assert_eq!(upstream.0.lock().unwrap().len(), 1);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn count_distinct_survives_deletes() {
+    let mut g = start_simple("count_distinct_survives_deletes").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), color varchar(255), PRIMARY KEY(id));
+         QUERY Colors: SELECT brand, COUNT(DISTINCT color) AS colors \
+                       FROM Car WHERE brand = ? GROUP BY brand;",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut colors = g.view("Colors").await.unwrap();
+    for (id, color) in &[(1, "red"), (2, "red"), (3, "blue")] {
+        mutator
+            .insert(vec![(*id).into(), "Volvo".into(), (*color).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+    assert_eq!(
+        colors.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec!["Volvo".into(), 2.into()]]
+    );
+
+    // one of the two red cars going away leaves the count alone
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        colors.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec!["Volvo".into(), 2.into()]]
+    );
+
+    // but the last one does not
+    mutator.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        colors.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec!["Volvo".into(), 1.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn having_filters_aggregates() {
+    let mut g = start_simple("having_filters_aggregates").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY PopularBrands: SELECT brand, COUNT(id) AS n FROM Car \
+                              WHERE brand = ? GROUP BY brand HAVING n > 1;",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut popular = g.view("PopularBrands").await.unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert!(popular
+        .lookup(&["Volvo".into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    mutator
+        .insert(vec![2.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        popular.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec!["Volvo".into(), 2.into()]]
+    );
+
+    // and the group goes away again once it no longer qualifies
+    mutator.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert!(popular
+        .lookup(&["Volvo".into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn queries_reuse_named_materializations() {
+    let mut g = start_simple("queries_reuse_named_materializations").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), price int, PRIMARY KEY(id));
+         MATERIALIZE cheap: SELECT id, brand FROM Car WHERE price < 100;",
+    )
+    .await
+    .unwrap();
+    let mut mutator = g.table("Car").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Volvo".into(), 50.into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![2.into(), "Volvo".into(), 500.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // a query added later reads from the materialization point's state
+    g.extend_recipe("QUERY CheapCars: SELECT id FROM @cheap WHERE brand = ?;")
+        .await
+        .unwrap();
+    let mut cheap = g.view("CheapCars").await.unwrap();
+    assert_eq!(
+        cheap.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec![1.into()]]
+    );
+
+    // only declared materialization points can be reused
+    assert!(g
+        .extend_recipe("QUERY Cars: SELECT id FROM @expensive WHERE brand = ?;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn correlated_subqueries_become_joins() {
+    let mut g = start_simple("correlated_subqueries_become_joins").await;
+    g.install_recipe(
+        "CREATE TABLE Post (pid int, author int, org int, PRIMARY KEY(pid));
+         CREATE TABLE Role (rid int, uid int, org int, PRIMARY KEY(rid));
+         QUERY MemberPosts: SELECT Post.pid FROM Post \
+                            WHERE Post.author IN \
+                                (SELECT Role.uid FROM Role WHERE Role.org = Post.org) \
+                            AND Post.pid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut posts = g.table("Post").await.unwrap();
+    let mut roles = g.table("Role").await.unwrap();
+    let mut member_posts = g.view("MemberPosts").await.unwrap();
+
+    posts
+        .insert(vec![1.into(), 10.into(), 1.into()])
+        .await
+        .unwrap();
+    posts
+        .insert(vec![2.into(), 10.into(), 2.into()])
+        .await
+        .unwrap();
+    roles
+        .insert(vec![1.into(), 10.into(), 1.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    assert_eq!(
+        member_posts.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into()]]
+    );
+    // the author is not a member of the second post's organization
+    assert!(member_posts
+        .lookup(&[2.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    roles
+        .insert(vec![2.into(), 10.into(), 2.into()])
+        .await
+        .unwrap();
+    sleep().await;
+    assert_eq!(
+        member_posts.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn union_all_keeps_duplicates() {
+    let mut g = start_simple("union_all_keeps_duplicates").await;
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, author int, title text, PRIMARY KEY(aid));
+         CREATE TABLE Draft (did int, author int, title text, PRIMARY KEY(did));
+         VIEW AllTitles: SELECT Article.author, Article.title FROM Article \
+                         UNION ALL \
+                         SELECT Draft.author, Draft.title FROM Draft;
+         VIEW Titles: SELECT Article.author, Article.title FROM Article \
+                      UNION \
+                      SELECT Draft.author, Draft.title FROM Draft;
+         QUERY AllTitlesByAuthor: SELECT AllTitles.title FROM AllTitles \
+                                  WHERE AllTitles.author = ?;
+         QUERY TitlesByAuthor: SELECT Titles.title FROM Titles WHERE Titles.author = ?;",
+    )
+    .await
+    .unwrap();
+    let mut articles = g.table("Article").await.unwrap();
+    let mut drafts = g.table("Draft").await.unwrap();
+    let mut all_titles = g.view("AllTitlesByAuthor").await.unwrap();
+    let mut titles = g.view("TitlesByAuthor").await.unwrap();
+
+    articles
+        .insert(vec![1.into(), 10.into(), "a".into()])
+        .await
+        .unwrap();
+    drafts
+        .insert(vec![1.into(), 10.into(), "a".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // UNION ALL keeps both copies of the title, while UNION only keeps one
+    assert_eq!(
+        all_titles.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec!["a".into()], vec!["a".into()]]
+    );
+    assert_eq!(
+        titles.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec!["a".into()]]
+    );
+
+    // removing one copy leaves the title in both views
+    articles.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        all_titles.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec!["a".into()]]
+    );
+    assert_eq!(
+        titles.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec!["a".into()]]
+    );
+
+    // and removing the last one removes it from both
+    drafts.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert!(all_titles
+        .lookup(&[10.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(titles.lookup(&[10.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn not_in_subqueries_become_anti_joins() {
+    let mut g = start_simple("not_in_subqueries_become_anti_joins").await;
+    g.install_recipe(
+        "CREATE TABLE Customer (cid int, name text, PRIMARY KEY(cid));
+         CREATE TABLE Purchase (pid int, customer int, PRIMARY KEY(pid));
+         QUERY IdleCustomers: SELECT Customer.name FROM Customer \
+                              WHERE Customer.cid NOT IN (SELECT Purchase.customer FROM Purchase) \
+                              AND Customer.cid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut customers = g.table("Customer").await.unwrap();
+    let mut purchases = g.table("Purchase").await.unwrap();
+    let mut idle = g.view("IdleCustomers").await.unwrap();
+
+    customers
+        .insert(vec![1.into(), "alice".into()])
+        .await
+        .unwrap();
+    customers
+        .insert(vec![2.into(), "bob".into()])
+        .await
+        .unwrap();
+    purchases.insert(vec![1.into(), 1.into()]).await.unwrap();
+    sleep().await;
+
+    assert!(idle.lookup(&[1.into()], true).await.unwrap().is_empty());
+    assert_eq!(
+        idle.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec!["bob".into()]]
+    );
+
+    // the first purchase makes a customer busy, and further ones change nothing
+    purchases.insert(vec![2.into(), 2.into()]).await.unwrap();
+    purchases.insert(vec![3.into(), 1.into()]).await.unwrap();
+    sleep().await;
+    assert!(idle.lookup(&[2.into()], true).await.unwrap().is_empty());
+
+    // and removing the last one makes them idle again
+    purchases.delete(vec![1.into()]).await.unwrap();
+    sleep().await;
+    assert!(idle.lookup(&[1.into()], true).await.unwrap().is_empty());
+    purchases.delete(vec![3.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        idle.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec!["alice".into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn range_parameters() {
+    let mut g = start_simple("range_parameters").await;
+    g.install_recipe(
+        "CREATE TABLE stories (id int, score int, PRIMARY KEY(id));
+         QUERY TopStories: SELECT id, score FROM stories WHERE score > ?;",
+    )
+    .await
+    .unwrap();
+    let mut stories = g.table("stories").await.unwrap();
+    let mut top = g.view("TopStories").await.unwrap();
+
+    stories.insert(vec![1.into(), 5.into()]).await.unwrap();
+    stories.insert(vec![2.into(), 20.into()]).await.unwrap();
+    stories.insert(vec![3.into(), 12.into()]).await.unwrap();
+    sleep().await;
+
+    // rows come back ordered by the parameter column
+    assert_eq!(
+        top.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec![3.into(), 12.into()], vec![2.into(), 20.into()]]
+    );
+    assert_eq!(top.lookup(&[0.into()], true).await.unwrap().len(), 3);
+    assert!(top.lookup(&[20.into()], true).await.unwrap().is_empty());
+
+    stories.delete(vec![3.into()]).await.unwrap();
+    stories.insert(vec![4.into(), 21.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        top.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec![2.into(), 20.into()], vec![4.into(), 21.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn compound_keys_across_tables() {
+    let mut g = start_simple("compound_keys_across_tables").await;
+    g.install_recipe(
+        "CREATE TABLE Vote (uid int, story int);
+         CREATE TABLE Story (id int, title text, PRIMARY KEY(id));
+         QUERY UserVotes: SELECT Story.title FROM Vote JOIN Story ON (Vote.story = Story.id) \
+                          WHERE Story.id = ? AND Vote.uid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut votes = g.table("Vote").await.unwrap();
+    let mut stories = g.table("Story").await.unwrap();
+    let mut user_votes = g.view("UserVotes").await.unwrap();
+
+    stories
+        .insert(vec![1.into(), "first".into()])
+        .await
+        .unwrap();
+    stories
+        .insert(vec![2.into(), "second".into()])
+        .await
+        .unwrap();
+    votes.insert(vec![10.into(), 1.into()]).await.unwrap();
+    votes.insert(vec![10.into(), 2.into()]).await.unwrap();
+    votes.insert(vec![20.into(), 2.into()]).await.unwrap();
+    sleep().await;
+
+    // keys are given in the order of the parameters
+    let rows = user_votes
+        .lookup(&[1.into(), 10.into()], true)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][0], "first".into());
+    assert!(user_votes
+        .lookup(&[1.into(), 20.into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // and stay up to date once filled
+    votes.insert(vec![20.into(), 1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        user_votes
+            .lookup(&[1.into(), 20.into()], true)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn global_topk() {
+    let mut g = start_simple("global_topk").await;
+    g.install_recipe(
+        "CREATE TABLE posts (id int, score int, PRIMARY KEY(id));
+         QUERY TopPosts: SELECT id, score FROM posts ORDER BY score DESC LIMIT 2;",
+    )
+    .await
+    .unwrap();
+    let mut posts = g.table("posts").await.unwrap();
+    let mut top = g.view("TopPosts").await.unwrap();
+
+    posts.insert(vec![1.into(), 10.into()]).await.unwrap();
+    posts.insert(vec![2.into(), 30.into()]).await.unwrap();
+    posts.insert(vec![3.into(), 20.into()]).await.unwrap();
+    sleep().await;
+
+    let res = top.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().any(|r| r[0] == 2.into() && r[1] == 30.into()));
+    assert!(res.iter().any(|r| r[0] == 3.into() && r[1] == 20.into()));
+
+    // deleting a top result brings back the next best one
+    posts.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+
+    let res = top.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().any(|r| r[0] == 3.into() && r[1] == 20.into()));
+    assert!(res.iter().any(|r| r[0] == 1.into() && r[1] == 10.into()));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn sorted_view_across_shards() {
+    let mut g = start_simple("sorted_view_across_shards").await;
+    g.install_recipe(
+        "CREATE TABLE posts (id int, score int, PRIMARY KEY(id));
+         QUERY TopPosts: SELECT id, score FROM posts ORDER BY score DESC LIMIT 3;",
+    )
+    .await
+    .unwrap();
+    let mut posts = g.table("posts").await.unwrap();
+    let mut top = g.view("TopPosts").await.unwrap();
+
+    for i in 0..20 {
+        posts
+            .insert(vec![i.into(), ((i * 7) % 20).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // the shards' top rows are merged in order, and only the overall top rows are kept
+    let res = top.lookup(&[0.into()], true).await.unwrap();
+    let scores: Vec<DataType> = res.iter().map(|r| r[1].clone()).collect();
+    assert_eq!(scores, vec![19.into(), 18.into(), 17.into()]);
+
+    // removing a top row brings in the next best one, from whichever shard it is on
+    posts.delete(vec![11.into()]).await.unwrap();
+    sleep().await;
+
+    let res = top.lookup(&[0.into()], true).await.unwrap();
+    let scores: Vec<DataType> = res.iter().map(|r| r[1].clone()).collect();
+    assert_eq!(scores, vec![19.into(), 18.into(), 16.into()]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn unordered_view_of_top_k_across_shards() {
+    let mut g = start_simple("unordered_view_of_top_k_across_shards").await;
+    // the view doesn't have the column it is ordered by, so its shards can't be merged on reads
+    g.install_recipe(
+        "CREATE TABLE posts (id int, score int, PRIMARY KEY(id));
+         QUERY TopPosts: SELECT id FROM posts ORDER BY score DESC LIMIT 3;",
+    )
+    .await
+    .unwrap();
+    let mut posts = g.table("posts").await.unwrap();
+    let mut top = g.view("TopPosts").await.unwrap();
+
+    for i in 0..20 {
+        posts
+            .insert(vec![i.into(), ((i * 7) % 20).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // scores 19, 18, and 17 are those of ids 17, 14, and 11
+    let mut ids: Vec<DataType> = top
+        .lookup(&[0.into()], true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r[0].clone())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![11.into(), 14.into(), 17.into()]);
+
+    // id 8 has the next best score, 16
+    posts.delete(vec![11.into()]).await.unwrap();
+    sleep().await;
+
+    let mut ids: Vec<DataType> = top
+        .lookup(&[0.into()], true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r[0].clone())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![8.into(), 14.into(), 17.into()]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn paged_view() {
+    let mut g = start_simple("paged_view").await;
+    g.install_recipe(
+        "CREATE TABLE posts (id int, author int, score int, PRIMARY KEY(id));
+         QUERY Page: SELECT id, score FROM posts ORDER BY score DESC LIMIT 3 OFFSET 3;
+         QUERY AuthorPage: SELECT id, score FROM posts WHERE author = ?
+                           ORDER BY score DESC LIMIT 2 OFFSET 2;",
+    )
+    .await
+    .unwrap();
+    let mut posts = g.table("posts").await.unwrap();
+    let mut page = g.view("Page").await.unwrap();
+    let mut author_page = g.view("AuthorPage").await.unwrap();
+
+    for i in 0..20 {
+        posts
+            .insert(vec![i.into(), (i % 2).into(), ((i * 7) % 20).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // the page skips the top rows of all the shards taken together
+    let res = page.lookup(&[0.into()], true).await.unwrap();
+    let scores: Vec<DataType> = res.iter().map(|r| r[1].clone()).collect();
+    assert_eq!(scores, vec![16.into(), 15.into(), 14.into()]);
+
+    // a top row that goes away moves the next one onto the previous page
+    posts.delete(vec![11.into()]).await.unwrap();
+    sleep().await;
+
+    let res = page.lookup(&[0.into()], true).await.unwrap();
+    let scores: Vec<DataType> = res.iter().map(|r| r[1].clone()).collect();
+    assert_eq!(scores, vec![15.into(), 14.into(), 13.into()]);
+
+    // author 1's remaining scores are 19, 15, 13, 11, ...
+    let res = author_page.lookup(&[1.into()], true).await.unwrap();
+    let scores: Vec<DataType> = res.iter().map(|r| r[1].clone()).collect();
+    assert_eq!(scores, vec![13.into(), 11.into()]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn alter_table() {
+    let mut g = start_simple("alter_table").await;
+    g.install_recipe(
+        "CREATE TABLE article (id int, title varchar(255), PRIMARY KEY(id));
+         QUERY Title: SELECT id, title FROM article WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("article").await.unwrap();
+    article.insert(vec![1.into(), "a".into()]).await.unwrap();
+    sleep().await;
+
+    // a new query can read the added column, which existing rows have the default of
+    g.extend_recipe(
+        "ALTER TABLE article ADD COLUMN score int DEFAULT 5;
+         QUERY Score: SELECT id, score FROM article WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("article").await.unwrap();
+    assert_eq!(article.columns(), &["id", "title", "score"]);
+    article
+        .insert(vec![2.into(), "b".into(), 7.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let mut score = g.view("Score").await.unwrap();
+    assert_eq!(
+        score.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 5.into()]]
+    );
+    assert_eq!(
+        score.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), 7.into()]]
+    );
+
+    // dropping a column keeps the existing views, which see its default from then on
+    g.extend_recipe("ALTER TABLE article DROP COLUMN title;")
+        .await
+        .unwrap();
+    let mut article = g.table("article").await.unwrap();
+    assert_eq!(article.columns(), &["id", "score"]);
+    article.insert(vec![3.into(), 9.into()]).await.unwrap();
+    sleep().await;
+
+    let mut title = g.view("Title").await.unwrap();
+    assert_eq!(
+        title.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a".into()]]
+    );
+    assert_eq!(
+        title.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), DataType::None]]
+    );
+    assert_eq!(
+        score.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), 9.into()]]
+    );
+
+    // the primary key cannot be dropped
+    assert!(g
+        .extend_recipe("ALTER TABLE article DROP COLUMN id;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn drop_view_and_table() {
+    let mut g = start_simple("drop_view_and_table").await;
+    g.install_recipe(
+        "CREATE TABLE article (id int, title varchar(255), PRIMARY KEY(id));
+         QUERY Title: SELECT id, title FROM article WHERE id = ?;
+         QUERY Titled: SELECT id FROM article WHERE title = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("article").await.unwrap();
+    article.insert(vec![1.into(), "a".into()]).await.unwrap();
+    sleep().await;
+
+    // the table has views that read from it
+    assert!(g.extend_recipe("DROP TABLE article;").await.is_err());
+
+    g.extend_recipe("DROP VIEW Title;").await.unwrap();
+    let outputs = g.outputs().await.unwrap();
+    assert!(!outputs.contains_key("Title"));
+    assert!(g.view("Title").await.is_err());
+
+    // the other view of the table is unaffected
+    let mut titled = g.view("Titled").await.unwrap();
+    article.insert(vec![2.into(), "a".into()]).await.unwrap();
+    sleep().await;
+    let mut ids = titled.lookup(&["a".into()], true).await.unwrap();
+    ids.sort();
+    assert_eq!(ids, vec![vec![1.into()], vec![2.into()]]);
+
+    g.extend_recipe("DROP VIEW Titled;\nDROP TABLE article;")
+        .await
+        .unwrap();
+    assert!(g.outputs().await.unwrap().is_empty());
+    assert!(!g.inputs().await.unwrap().contains_key("article"));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn migration_status() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(None);
+    b.set_persistence(get_persistence_params("migration_status"));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe("CREATE TABLE votes (story int, user int);")
+        .await
+        .unwrap();
+
+    let status = g.migration_status().await.unwrap();
+    assert_eq!(status.running_for, None);
+
+    let mut votes = g.table("votes").await.unwrap();
+    for i in 0..1000 {
+        votes.insert(vec![(i % 10).into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    // the new view is fully materialized, so all the votes are replayed into it
+    g.extend_recipe("VIEW vc: SELECT story, COUNT(user) AS n FROM votes GROUP BY story;")
+        .await
+        .unwrap();
+    let status = g.migration_status().await.unwrap();
+    assert_eq!(status.running_for, None);
+    assert!(status.rows_replayed >= 1000);
+    assert_eq!(status.rows_replayed, status.rows_to_replay);
+    assert!(status.bytes_replayed > 0);
+    assert!(!status.domains.is_empty());
+    assert!(status.domains.iter().all(|d| d.is_complete()));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn disjoint_migrations_run_alongside_backfills() {
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(None);
+    b.set_persistence(get_persistence_params(
+        "disjoint_migrations_run_alongside_backfills",
+    ));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         CREATE TABLE stories (id int, title varchar(255), PRIMARY KEY(id));",
+    )
+    .await
+    .unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    for i in 0..1000 {
+        votes.insert(vec![(i % 10).into(), i.into()]).await.unwrap();
+    }
+    let mut stories = g.table("stories").await.unwrap();
+    stories
+        .insert(vec![1.into(), "hello".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // neither query reads from what the other adds, so they do not wait for each other
+    let mut other = (*g).clone();
+    let (vc, title) = tokio::join!(
+        g.extend_recipe(
+            "QUERY vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;"
+        ),
+        other.extend_recipe("QUERY title: SELECT id, title FROM stories WHERE id = ?;"),
+    );
+    vc.unwrap();
+    title.unwrap();
+
+    // both views are fully populated once the requests return
+    let mut vc = g.view("vc").await.unwrap();
+    assert_eq!(
+        vc.lookup(&[0.into()], true).await.unwrap(),
+        vec![vec![0.into(), 100.into()]]
+    );
+    let mut title = g.view("title").await.unwrap();
+    assert_eq!(
+        title.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "hello".into()]]
+    );
+    assert_eq!(g.migration_status().await.unwrap().running_for, None);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn dedicated_reader_threads() {
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.set_reader_threads(2);
+    b.set_max_pending_reads(16);
+    b.set_persistence(get_persistence_params("dedicated_reader_threads"));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    for i in 0..10 {
+        votes.insert(vec![1.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+    assert_eq!(
+        vc.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+
+    // let the worker report its reader load
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    let load = g.reader_load().await.unwrap();
+    assert_eq!(load.len(), 1);
+    let load = &load[0].1;
+    assert_eq!(load.threads, Some(2));
+    assert_eq!(load.max_pending, Some(16));
+    assert_eq!(load.pending, 0);
+    assert_eq!(load.shed, 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn replicated_readers_serve_the_same_lookups() {
+    let mut g = start_simple_unsharded("replicated_readers_serve_the_same_lookups").await;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+    assert!(g.replicate_view("vc", 0).await.is_err());
+    assert!(g.replicate_view("nope", 2).await.is_err());
+    g.replicate_view("vc", 3).await.unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    for i in 0..10 {
+        votes.insert(vec![1.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+    // lookups take turns among the replicas
+    for _ in 0..3 {
+        assert_eq!(
+            vc.lookup(&[1.into()], true).await.unwrap(),
+            vec![vec![1.into(), 10.into()]]
+        );
+    }
+
+    g.replicate_view("vc", 1).await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    assert_eq!(
+        vc.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+
+    // replicas go along with the view's reader when the view is removed
+    g.replicate_view("vc", 2).await.unwrap();
+    g.install_recipe("CREATE TABLE votes (story int, user int);")
+        .await
+        .unwrap();
+    assert!(g.view("vc").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_move_to_workers_that_join_later() {
+    let authority = Arc::new(LocalAuthority::new());
+    let persistence = get_persistence_params("views_move_to_workers_that_join_later");
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(persistence.clone());
+    let (mut g, done) = builder.start(authority.clone()).await.unwrap();
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    for i in 0..10 {
+        votes.insert(vec![1.into(), i.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    // a second worker joins the running deployment
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(persistence);
+    let (g2, done2) = builder.start(authority.clone()).await.unwrap();
+    let workers = loop {
+        let workers = g.reader_load().await.unwrap();
+        if workers.len() == 2 {
+            break workers;
+        }
+        sleep().await;
+    };
+    assert!(g.move_view("nope", workers[0].0).await.is_err());
+
+    // one of the two moves takes the reader to the new worker, and writes keep flowing
+    let mut n = 10;
+    for &(worker, _) in &workers {
+        g.move_view("vc", worker).await.unwrap();
+        votes.insert(vec![1.into(), n.into()]).await.unwrap();
+        n += 1;
+        sleep().await;
+        let mut vc = g.view("vc").await.unwrap();
+        assert_eq!(
+            vc.lookup(&[1.into()], true).await.unwrap(),
+            vec![vec![1.into(), n.into()]]
+        );
+    }
+
+    g.replicate_view("vc", 2).await.unwrap();
+    assert!(g.move_view("vc", workers[0].0).await.is_err());
+
+    drop(g2);
+    done2.await;
+    drop(g);
+    done.await;
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_evict_to_fit_their_budget() {
+    let mut g = start_simple_unsharded("views_evict_to_fit_their_budget").await;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+    let mut votes = g.table("votes").await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    for story in 0..10 {
+        votes.insert(vec![story.into(), 1.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    let eviction = Eviction {
+        policy: EvictionPolicy::LeastRecentlyUsed,
+        budget: Some(1),
+    };
+    assert!(g.set_eviction("nope", eviction).await.is_err());
+    g.set_eviction("vc", eviction).await.unwrap();
+
+    // keys are evicted as soon as they are filled, but lookups still find them
+    for _ in 0..2 {
+        for story in 0..10 {
+            assert_eq!(
+                vc.lookup(&[story.into()], true).await.unwrap(),
+                vec![vec![story.into(), 1.into()]]
+            );
+        }
+        sleep().await;
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_can_be_kept_on_disk() {
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    builder.set_persistence(get_persistence_params("views_can_be_kept_on_disk"));
+    builder.disable_partial();
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+    let mut votes = g.table("votes").await.unwrap();
+    for story in 0..10 {
+        votes.insert(vec![story.into(), 1.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    assert!(g.set_view_storage("nope", ViewStorage::Disk).await.is_err());
+    g.set_view_storage("vc", ViewStorage::Disk).await.unwrap();
+    // the cache in front of the disk is evicted from as soon as a key is filled
+    let eviction = Eviction {
+        policy: EvictionPolicy::LeastRecentlyUsed,
+        budget: Some(1),
+    };
+    g.set_eviction("vc", eviction).await.unwrap();
+
+    // writes reach the rows on disk whether or not their keys are cached
+    votes.insert(vec![3.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    let mut vc = g.view("vc").await.unwrap();
+    for _ in 0..2 {
+        for story in 0..10 {
+            let n = if story == 3 { 2 } else { 1 };
+            assert_eq!(
+                vc.lookup(&[story.into()], true).await.unwrap(),
+                vec![vec![story.into(), n.into()]]
+            );
+        }
+        sleep().await;
+    }
+
+    // the view can be moved back into memory
+    g.set_view_storage("vc", ViewStorage::Memory).await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    assert_eq!(
+        vc.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), 2.into()]]
+    );
+}
+
+#[cfg(feature = "parquet")]
+#[tokio::test(threaded_scheduler)]
+async fn views_can_be_exported_and_bulk_loaded() {
+    use noria::error::TableError;
+
+    let mut builder = Builder::default();
+    builder.set_sharding(Some(DEFAULT_SHARDING));
+    builder.set_persistence(get_persistence_params(
+        "views_can_be_exported_and_bulk_loaded",
+    ));
+    builder.disable_partial();
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         CREATE TABLE Archive (id int, brand varchar(255), price int, PRIMARY KEY(id));
+         VIEW cars: SELECT id, brand FROM Car WHERE id = ?;
+         VIEW archived: SELECT id, brand, price FROM Archive WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut car = g.table("Car").await.unwrap();
+    for id in 0..100 {
+        let brand = format!("brand{}", id % 7);
+        car.insert(vec![id.into(), brand.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cars.parquet");
+    assert!(g.export_view("nope", &path).await.is_err());
+    assert_eq!(g.export_view("cars", &path).await.unwrap(), 100);
+
+    let mut archive = g.table("Archive").await.unwrap();
+    assert_eq!(archive.bulk_load(&path).await.unwrap(), 100);
+    sleep().await;
+    let mut archived = g.view("archived").await.unwrap();
+    assert_eq!(archived.len().await.unwrap(), 100);
+    assert_eq!(
+        archived.lookup(&[12.into()], true).await.unwrap(),
+        vec![vec![12.into(), "brand5".into(), DataType::None]]
+    );
+
+    // files whose columns the table does not have are turned away
+    let mut car = g.table("Car").await.unwrap();
+    g.export_view("archived", &path).await.unwrap();
+    match car.bulk_load(&path).await {
+        Err(TableError::InvalidFile(e)) => assert!(e.contains("price")),
+        r => unreachable!("{:?}", r),
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn writes_wait_for_slow_domains() {
+    let mut builder = Builder::default();
+    builder.set_sharding(Some(DEFAULT_SHARDING));
+    builder.set_persistence(get_persistence_params("writes_wait_for_slow_domains"));
+    // every domain stops accepting input as soon as it has anything queued for another
+    builder.set_max_queued_packets(Some(1));
+    let mut g = builder.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;
+         VIEW vu: SELECT user, COUNT(story) AS n FROM votes WHERE user = ? GROUP BY user;",
+    )
+    .await
+    .unwrap();
+    let mut votes = g.table("votes").await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+    let mut vu = g.view("vu").await.unwrap();
+
+    for batch in 0..10 {
+        votes
+            .perform_all((0..100).map(|i| vec![(i % 10).into(), ((batch * 100 + i) % 7).into()]))
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    assert_eq!(
+        vc.lookup(&[3.into()], true).await.unwrap(),
+        vec![vec![3.into(), 100.into()]]
+    );
+    assert_eq!(
+        vu.lookup(&[0.into()], true).await.unwrap(),
+        vec![vec![0.into(), 143.into()]]
+    );
+
+    // everything queued has been delivered by now
+    let stats = g.statistics().await.unwrap();
+    assert!(stats.values().all(|(d, _)| d.queued_packets == 0));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn prioritized_views_answer_lookups() {
+    let mut g = start_simple_unsharded("prioritized_views_answer_lookups").await;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;
+         VIEW vu: SELECT user, COUNT(story) AS n FROM votes WHERE user = ? GROUP BY user;",
+    )
+    .await
+    .unwrap();
+    assert!(g
+        .set_view_priority("nope", ViewPriority::High)
+        .await
+        .is_err());
+    g.set_view_priority("vc", ViewPriority::High).await.unwrap();
+    g.set_view_priority("vu", ViewPriority::Low).await.unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    for story in 0..10 {
+        votes.insert(vec![story.into(), 1.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    // deferred upqueries are still answered, and the priorities survive recipe changes
+    let mut vc = g.view("vc").await.unwrap();
+    let mut vu = g.view("vu").await.unwrap();
+    for story in 0..10 {
+        assert_eq!(
+            vc.lookup(&[story.into()], true).await.unwrap(),
+            vec![vec![story.into(), 1.into()]]
+        );
+    }
+    assert_eq!(
+        vu.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 10.into()]]
+    );
+
+    g.extend_recipe("VIEW vs: SELECT story FROM votes WHERE user = ?;")
+        .await
+        .unwrap();
+    votes.insert(vec![10.into(), 1.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        vc.lookup(&[10.into()], true).await.unwrap(),
+        vec![vec![10.into(), 1.into()]]
+    );
+    assert_eq!(
+        vu.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 11.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn expired_rows_are_retracted() {
+    let mut g = start_simple_unsharded("expired_rows_are_retracted").await;
+    g.install_recipe(
+        "CREATE TABLE events (id int, kind int, ts bigint, PRIMARY KEY(id));
+         CREATE TABLE stamps (id int, ts bigint);
+         VIEW counts: SELECT kind, COUNT(id) AS n FROM events WHERE kind = ? GROUP BY kind;
+         VIEW latest: SELECT id, ts FROM stamps WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut events = g.table("events").await.unwrap();
+    let mut stamps = g.table("stamps").await.unwrap();
+    let mut counts = g.view("counts").await.unwrap();
+    let mut latest = g.view("latest").await.unwrap();
+
+    // integer timestamps are milliseconds since the epoch
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    events
+        .insert(vec![1.into(), 1.into(), 0.into()])
+        .await
+        .unwrap();
+    events
+        .insert(vec![2.into(), 1.into(), now.into()])
+        .await
+        .unwrap();
+    stamps.insert(vec![1.into(), 0.into()]).await.unwrap();
+    stamps.insert(vec![2.into(), now.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        counts.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    g.extend_recipe(
+        "ALTER TABLE events TTL = 1 HOUR ON ts;
+         ALTER VIEW latest TTL = 1 HOUR ON ts;",
+    )
+    .await
+    .unwrap();
+    assert!(g
+        .extend_recipe("ALTER TABLE stamps TTL = 1 HOUR ON ts;")
+        .await
+        .is_err());
+
+    // the table deletes its expired row, which takes it out of the count
+    let mut n = Vec::new();
+    for _ in 0..50 {
+        n = counts.lookup(&[1.into()], true).await.unwrap();
+        if n == vec![vec![1.into(), 1.into()]] {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+    }
+    assert_eq!(n, vec![vec![1.into(), 1.into()]]);
+
+    // the view keeps expired rows out, though the table still has them
+    assert!(latest.lookup(&[1.into()], true).await.unwrap().is_empty());
+    assert_eq!(
+        latest.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), now.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn unsupported_sql_is_rejected_upfront() {
+    let mut g = build("unsupported_sql_is_rejected_upfront", None, false).await;
+    g.install_recipe("CREATE TABLE a (x int, y int);\nCREATE TABLE b (x int, z int);")
+        .await
+        .unwrap();
+
+    let recipe = "QUERY q: SELECT a.y FROM a CROSS JOIN b ON a.x = b.x WHERE a.x = ?;";
+    let unsupported = g.check_recipe(recipe).await.unwrap();
+    assert_eq!(unsupported.len(), 1);
+    assert_eq!(unsupported[0].name, Some("q".to_owned()));
+    assert_eq!(unsupported[0].features.len(), 1);
+    assert_eq!(unsupported[0].features[0].offset, recipe.find("CROSS"));
+
+    // the whole extension is rejected, without the controller falling over
+    let err = g
+        .extend_recipe(&format!(
+            "QUERY ok: SELECT y FROM a WHERE x = ?;\n{}",
+            recipe
+        ))
+        .await
+        .unwrap_err();
+    assert!(err
+        .iter_chain()
+        .any(|e| e.to_string().contains("CROSS JOIN is not supported")));
+    assert!(g.view("ok").await.is_err());
+
+    g.extend_recipe("QUERY ok: SELECT y FROM a WHERE x = ?;")
+        .await
+        .unwrap();
+    assert!(g.view("ok").await.is_ok());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn nulls_follow_sql_semantics() {
+    let mut g = start_simple("nulls_follow_sql_semantics").await;
+    g.install_recipe(
+        "CREATE TABLE t (v int, g int);
+         QUERY NonNull: SELECT g, COUNT(v) AS n FROM t WHERE g = ? GROUP BY g;
+         QUERY AllRows: SELECT g, COUNT(*) AS n FROM t WHERE g = ? GROUP BY g;
+         QUERY NotOne: SELECT v FROM t WHERE v != 1;",
+    )
+    .await
+    .unwrap();
+
+    let mut t = g.table("t").await.unwrap();
+    t.insert(vec![1.into(), 1.into()]).await.unwrap();
+    t.insert(vec![DataType::None, 1.into()]).await.unwrap();
+    t.insert(vec![2.into(), 1.into()]).await.unwrap();
+
+    sleep().await;
+
+    // COUNT(v) skips the NULL, but COUNT(*) doesn't
+    let mut non_null = g.view("NonNull").await.unwrap();
+    let rs = non_null.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), 2.into()]]);
+    let mut all_rows = g.view("AllRows").await.unwrap();
+    let rs = all_rows.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), 3.into()]]);
+
+    // NULL != 1 is not true
+    let mut not_one = g.view("NotOne").await.unwrap();
+    let rs = not_one.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0][0], 2.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn subscriptions_see_changes_to_their_keys() {
+    use futures_util::stream::StreamExt;
+
+    let mut g = start_simple("subscriptions_see_changes_to_their_keys").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+    let mut volvos = by_brand
+        .subscribe(vec![vec!["Volvo".into()]])
+        .await
+        .unwrap();
+
+    mutator
+        .insert(vec![1.into(), "Volvo".into()])
+        .await
+        .unwrap();
+    mutator.insert(vec![2.into(), "Saab".into()]).await.unwrap();
+    mutator.delete(vec![1.into()]).await.unwrap();
+
+    // the Saab is never seen, since nobody watches its brand
+    let volvo: Vec<DataType> = vec![1.into(), "Volvo".into()];
+    for &added in &[true, false] {
+        let change = tokio::time::timeout(Duration::from_secs(5), volvos.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(change, (volvo.clone(), added));
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn prepared_writes_check_their_values() {
+    use noria::error::TableError;
+
+    let mut g = start_simple("prepared_writes_check_their_values").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), sold int DEFAULT 0, PRIMARY KEY(id));
+         QUERY CarById: SELECT id, brand, sold FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let car = g.table("Car").await.unwrap();
+    let mut by_id = g.view("CarById").await.unwrap();
+    match car.prepare_insert(&["id", "model"]) {
+        Err(TableError::UnknownColumn(c)) => assert_eq!(c, "model"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let mut insert = car.prepare_insert(&["brand", "id"]).unwrap();
+    insert
+        .execute(vec!["Volvo".into(), 1.into()])
+        .await
+        .unwrap();
+    match insert.execute(vec![2.into(), "Saab".into()]).await {
+        Err(TableError::InvalidValue { column, .. }) => assert_eq!(column, "brand"),
+        r => panic!("unexpected result {:?}", r),
+    }
+    let mut update = car.prepare_update(&["sold"]).unwrap();
+    update
+        .execute(vec![1.into()], vec![3.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let rs = by_id.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), "Volvo".into(), 3.into()]]);
+    let rs = by_id.lookup(&[2.into()], true).await.unwrap();
+    assert!(rs.is_empty());
+
+    let mut upsert = car
+        .prepare_insert(&["id", "brand", "sold"])
+        .unwrap()
+        .on_duplicate_key_update(&["sold"])
+        .unwrap();
+    upsert
+        .execute_all(vec![
+            vec![1.into(), "Saab".into(), 4.into()],
+            vec![2.into(), "Saab".into(), 1.into()],
+        ])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let rs = by_id.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), "Volvo".into(), 4.into()]]);
+    let rs = by_id.lookup(&[2.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![2.into(), "Saab".into(), 1.into()]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn writes_can_select_rows_by_condition() {
+    use noria::{Comparison, Condition, Modification};
+
+    let mut g = start_simple("writes_can_select_rows_by_condition").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), sold int, PRIMARY KEY(id));
+         QUERY CarById: SELECT id, brand, sold FROM Car WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_id = g.view("CarById").await.unwrap();
+    mutator
+        .perform_all(vec![
+            vec![1.into(), "Volvo".into(), 1.into()],
+            vec![2.into(), "Saab".into(), 1.into()],
+            vec![3.into(), "Volvo".into(), 5.into()],
+        ])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let brand = |b: &str| Condition {
+        column: 1,
+        comparison: Comparison::Equal,
+        value: b.into(),
+    };
+    mutator.delete_where(vec![brand("Saab")]).await.unwrap();
+    let few_sold = Condition {
+        column: 2,
+        comparison: Comparison::Less,
+        value: 5.into(),
+    };
+    mutator
+        .update_where(
+            vec![brand("Volvo"), few_sold],
+            vec![(2, Modification::Set(0.into()))],
+        )
+        .await
+        .unwrap();
+    sleep().await;
+
+    let rs = by_id
+        .multi_lookup(vec![vec![1.into()], vec![2.into()], vec![3.into()]], true)
+        .await
+        .unwrap();
+    assert_eq!(rs[0], vec![vec![1.into(), "Volvo".into(), 0.into()]]);
+    assert!(rs[1].is_empty());
+    assert_eq!(rs[2], vec![vec![3.into(), "Volvo".into(), 5.into()]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn base_tables_can_declare_secondary_indexes() {
+    use noria::{Comparison, Condition};
+
+    let mut g = start_simple("base_tables_can_declare_secondary_indexes").await;
+    g.install_recipe(
+        "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id), KEY brand_idx (brand));
+         QUERY CarsByBrand: SELECT id, brand FROM Car WHERE brand = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut by_brand = g.view("CarsByBrand").await.unwrap();
+    mutator
+        .perform_all(vec![
+            vec![1.into(), "Volvo".into()],
+            vec![2.into(), "Saab".into()],
+            vec![3.into(), "Volvo".into()],
+        ])
+        .await
+        .unwrap();
+    sleep().await;
+
+    mutator
+        .delete_where(vec![Condition {
+            column: 1,
+            comparison: Comparison::Equal,
+            value: "Volvo".into(),
+        }])
+        .await
+        .unwrap();
+    sleep().await;
+
+    assert!(by_brand
+        .lookup(&["Volvo".into()], true)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        by_brand.lookup(&["Saab".into()], true).await.unwrap(),
+        vec![vec![2.into(), "Saab".into()]]
+    );
+
+    // the index columns can't be dropped out from under it
+    assert!(g
+        .extend_recipe("ALTER TABLE Car DROP COLUMN brand;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn foreign_keys_cascade_deletes() {
+    use noria::Modification;
+
+    let mut g = start_simple("foreign_keys_cascade_deletes").await;
+    g.install_recipe(
+        "CREATE TABLE Person (id int, name varchar(255), PRIMARY KEY(id));
+         CREATE TABLE Post (id int, author int, title varchar(255), PRIMARY KEY(id));
+         QUERY PostsByAuthor: SELECT id, author, title FROM Post WHERE author = ?;",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe(
+        "ALTER TABLE Post ADD FOREIGN KEY (author) REFERENCES Person (id) ON DELETE CASCADE;",
+    )
+    .await
+    .unwrap();
+
+    let mut people = g.table("Person").await.unwrap();
+    let mut posts = g.table("Post").await.unwrap();
+    let mut by_author = g.view("PostsByAuthor").await.unwrap();
+    people
+        .perform_all(vec![vec![1.into(), "a".into()], vec![2.into(), "b".into()]])
+        .await
+        .unwrap();
+    posts
+        .perform_all(vec![
+            vec![1.into(), 1.into(), "x".into()],
+            vec![2.into(), 1.into(), "y".into()],
+            vec![3.into(), 2.into(), "z".into()],
+        ])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // updating a person leaves their posts alone, deleting them does not
+    people
+        .update(vec![1.into()], vec![(1, Modification::Set("c".into()))])
+        .await
+        .unwrap();
+    people.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    sleep().await;
+
+    let rs = by_author.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 2);
+    let rs = by_author.lookup(&[2.into()], true).await.unwrap();
+    assert!(rs.is_empty());
+
+    // the referenced table can't be dropped while the foreign key refers to it
+    assert!(g.extend_recipe("DROP TABLE Person;").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn base_tables_enforce_constraints() {
+    use noria::error::{TableError, WriteRejection};
+    use noria::Modification;
+
+    let mut g = start_simple("base_tables_enforce_constraints").await;
+    g.install_recipe(
+        "CREATE TABLE Item (id int, name varchar(255) NOT NULL, price int, PRIMARY KEY(id));
+         QUERY ItemById: SELECT id, name, price FROM Item WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    g.extend_recipe("ALTER TABLE Item ADD CONSTRAINT positive_price CHECK (price > 0);")
+        .await
+        .unwrap();
+
+    let mut items = g.table("Item").await.unwrap();
+    let mut getter = g.view("ItemById").await.unwrap();
+    items
+        .insert(vec![1.into(), "a".into(), 10.into()])
+        .await
+        .unwrap();
+    // as in SQL, NULL passes checks
+    items
+        .insert(vec![2.into(), "b".into(), DataType::None])
+        .await
+        .unwrap();
+
+    match items
+        .insert(vec![3.into(), DataType::None, 10.into()])
+        .await
+    {
+        Err(TableError::Rejected(WriteRejection::ConstraintViolation {
+            table,
+            constraint,
+            ..
+        })) => {
+            assert_eq!(table, "Item");
+            assert_eq!(constraint, "name NOT NULL");
+        }
+        r => panic!("expected constraint violation, got {:?}", r),
+    }
+    // updates are checked against the row they leave behind
+    match items
+        .update(vec![1.into()], vec![(2, Modification::Set((-1).into()))])
+        .await
+    {
+        Err(TableError::Rejected(WriteRejection::ConstraintViolation {
+            constraint, row, ..
+        })) => {
+            assert_eq!(constraint, "positive_price");
+            assert_eq!(row, vec![1.into(), "a".into(), (-1).into()]);
+        }
+        r => panic!("expected constraint violation, got {:?}", r),
+    }
+    sleep().await;
+
+    assert_eq!(
+        getter.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "a".into(), 10.into()]]
+    );
+    assert!(getter.lookup(&[3.into()], true).await.unwrap().is_empty());
+
+    // constraints must refer to the table's columns, and keep the columns they check around
+    assert!(g
+        .extend_recipe("ALTER TABLE Item ADD CHECK (cost > 0);")
+        .await
+        .is_err());
+    assert!(g
+        .extend_recipe("ALTER TABLE Item DROP COLUMN price;")
+        .await
+        .is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn auto_increment_columns_are_generated() {
+    let mut g = start_simple("auto_increment_columns_are_generated").await;
+    g.install_recipe(
+        "CREATE TABLE Note (id int NOT NULL AUTO_INCREMENT, body varchar(255), PRIMARY KEY(id));
+         QUERY NoteById: SELECT id, body FROM Note WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut notes = g.table("Note").await.unwrap();
+    let mut getter = g.view("NoteById").await.unwrap();
+    let mut ids = Vec::new();
+    for body in &["a", "b", "c", "d"] {
+        let id = notes
+            .insert(vec![DataType::None, (*body).into()])
+            .await
+            .unwrap()
+            .expect("no id was generated");
+        assert!(!ids.contains(&id));
+        ids.push(id);
+    }
+    // given values are kept
+    let given = notes.insert(vec![100.into(), "e".into()]).await.unwrap();
+    assert_eq!(given, None);
+    sleep().await;
+
+    for (id, body) in ids.iter().zip(&["a", "b", "c", "d"]) {
+        assert_eq!(
+            getter.lookup(&[id.clone()], true).await.unwrap(),
+            vec![vec![id.clone(), (*body).into()]]
+        );
+    }
+
+    // rows are on the shard their generated key routes to
+    notes.delete(vec![ids[1].clone()]).await.unwrap();
+    sleep().await;
+    assert!(getter
+        .lookup(&[ids[1].clone()], true)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn binlog_changes_are_applied() {
+    use crate::{BinlogReplicator, RowChange};
+
+    let mut g = start_simple("binlog_changes_are_applied").await;
+    g.install_recipe(
+        "CREATE TABLE item (id int, name varchar(255), price double, PRIMARY KEY(id));
+         QUERY ItemById: SELECT id, name, price FROM item WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut getter = g.view("ItemById").await.unwrap();
+
+    let change = |before: Option<&[&str]>, after: Option<&[&str]>| RowChange {
+        database: "shop".to_owned(),
+        table: "item".to_owned(),
+        before: before.map(|r| r.iter().map(|&v| v.to_owned()).collect()),
+        after: after.map(|r| r.iter().map(|&v| v.to_owned()).collect()),
+    };
+    let mut replicator = BinlogReplicator::new((*g).clone()).only_database("shop");
+    replicator
+        .apply(vec![
+            change(None, Some(&["1", "'a'", "1.5"])),
+            change(None, Some(&["2", "'b'", "2"])),
+            // tables that are not in Noria, or not in the replicated database, are skipped
+            RowChange {
+                table: "other".to_owned(),
+                ..change(None, Some(&["3"]))
+            },
+            RowChange {
+                database: "other".to_owned(),
+                ..change(None, Some(&["3", "'c'", "3"]))
+            },
+        ])
+        .await
+        .unwrap();
+    replicator
+        .apply(vec![
+            change(Some(&["1", "'a'", "1.5"]), Some(&["1", "'aa'", "1.5"])),
+            change(Some(&["2", "'b'", "2"]), None),
+        ])
+        .await
+        .unwrap();
+    // applying a transaction again changes nothing
+    replicator
+        .apply(vec![change(None, Some(&["1", "'aa'", "1.5"]))])
+        .await
+        .unwrap();
+    sleep().await;
+
+    assert_eq!(
+        getter.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "aa".into(), 1.5.into()]]
+    );
+    assert!(getter.lookup(&[2.into()], true).await.unwrap().is_empty());
+    assert!(getter.lookup(&[3.into()], true).await.unwrap().is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn http_reads() {
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.enable_http_reads(0);
+    b.set_http_reads_max_age(Duration::from_secs(10));
+    b.set_http_reads_views(vec!["by_name"]);
+    b.set_persistence(get_persistence_params("http_reads"));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE item (id int, name text, PRIMARY KEY(id));
+         VIEW by_name: SELECT id, name FROM item WHERE name = ?;
+         VIEW by_id: SELECT id, name FROM item WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut item = g.table("item").await.unwrap();
+    item.insert(vec![1.into(), "ann lee".into()]).await.unwrap();
+    sleep().await;
+
+    let addr = g.http_reads_addr().unwrap();
+    let get = |path: &str| {
+        let uri = format!("http://{}{}", addr, path).parse().unwrap();
+        hyper::Client::new().get(uri)
+    };
+    let res = get("/view/by_name/ann%20lee").await.unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        res.headers()[hyper::header::CACHE_CONTROL],
+        "public, max-age=10"
+    );
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, serde_json::json!([{"id": 1, "name": "ann lee"}]));
+
+    let res = get("/view/by_name/nobody?block=false").await.unwrap();
+    assert_eq!(res.headers()[hyper::header::CACHE_CONTROL], "no-store");
+
+    let res = get("/view/no_such_view/1").await.unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::NOT_FOUND);
+
+    // views that are not named as public are not served
+    let res = get("/view/by_id/1").await.unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn http_reads_are_private_unless_listed() {
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.enable_http_reads(0);
+    b.set_http_reads_max_age(Duration::from_secs(10));
+    b.set_persistence(get_persistence_params(
+        "http_reads_are_private_unless_listed",
+    ));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE item (id int, name text, PRIMARY KEY(id));
+         VIEW by_id: SELECT id, name FROM item WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut item = g.table("item").await.unwrap();
+    item.insert(vec![1.into(), "ann lee".into()]).await.unwrap();
+    sleep().await;
+
+    let uri = format!("http://{}/view/by_id/1", g.http_reads_addr().unwrap());
+    let res = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        res.headers()[hyper::header::CACHE_CONTROL],
+        "private, max-age=10"
+    );
+    assert!(!res
+        .headers()
+        .contains_key(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn harness_plays_scripts() {
+    use crate::harness::{Harness, Step};
+
+    let mut b = Builder::default();
+    b.set_persistence(get_persistence_params("harness_plays_scripts"));
+    let mut h = Harness::with_builder(
+        b,
+        "CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(uid));
+         QUERY VoteCount: SELECT aid, COUNT(uid) AS votes FROM Vote WHERE aid = ? GROUP BY aid;",
+    )
+    .await
+    .unwrap();
+
+    h.run(vec![
+        Step::insert("Vote", vec![1.into(), 1.into()]),
+        Step::insert("Vote", vec![1.into(), 2.into()]),
+        Step::expect("VoteCount", vec![1.into()], vec![vec![1.into(), 2.into()]]),
+        Step::EvictAll,
+        Step::delete("Vote", vec![1.into()]),
+        Step::expect("VoteCount", vec![1.into()], vec![vec![1.into(), 1.into()]]),
+        Step::insert("Vote", vec![2.into(), 3.into()]),
+        Step::expect("VoteCount", vec![2.into()], vec![vec![2.into(), 1.into()]]),
+    ])
+    .await
+    .unwrap();
+
+    // a failed expectation names the step, and stops the script
+    let err = h
+        .run(vec![
+            Step::expect("VoteCount", vec![1.into()], vec![]),
+            Step::insert("Vote", vec![1.into(), 4.into()]),
+        ])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("step 9: VoteCount"));
+    assert_eq!(
+        h.lookup("VoteCount", vec![1.into()]).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn views_agree_with_reference_evaluation() {
+    let mut g = start_simple("views_agree_with_reference_evaluation").await;
+    g.install_recipe(
+        "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+         CREATE TABLE Vote (aid int, uid int, PRIMARY KEY(uid));
+         QUERY ArticleVotes: SELECT Article.id, title, uid FROM Article \
+             JOIN Vote ON (Article.id = Vote.aid) WHERE Article.id = ?;
+         QUERY VoteCount: SELECT aid, COUNT(uid) AS votes FROM Vote WHERE aid = ? GROUP BY aid;
+         QUERY Recent: SELECT aid, uid FROM Vote WHERE aid = ? ORDER BY uid DESC LIMIT 3;",
+    )
+    .await
+    .unwrap();
+
+    let mut article = g.table("Article").await.unwrap();
+    let mut vote = g.table("Vote").await.unwrap();
+    for id in 0..5 {
+        article
+            .insert(vec![id.into(), format!("title{}", id).into()])
+            .await
+            .unwrap();
+    }
+    for uid in 0..20 {
+        vote.insert(vec![(uid % 3).into(), uid.into()])
+            .await
+            .unwrap();
+    }
+    vote.delete(vec![4.into()]).await.unwrap();
+    article.flush_barrier().await.unwrap();
+    vote.flush_barrier().await.unwrap();
+
+    for view in &["ArticleVotes", "VoteCount"] {
+        assert_eq!(g.check_view(view).await.unwrap(), vec![]);
+    }
+    // once evicted, keys are replayed to be checked
+    g.flush_partial().await.unwrap();
+    assert_eq!(g.check_view("VoteCount").await.unwrap(), vec![]);
+
+    // the reference evaluation does not support LIMIT
+    assert!(g.check_view("Recent").await.is_err());
+    assert!(g.check_view("nope").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn quotas_turn_away_excess() {
+    use noria::error::{TableError, ViewError, WriteRejection};
+
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.set_lookup_quota(2);
+    b.set_write_quota(2);
+    b.set_max_client_upqueries(8);
+    b.set_persistence(get_persistence_params("quotas_turn_away_excess"));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW vc: SELECT story, COUNT(user) AS n FROM votes WHERE story = ? GROUP BY story;",
+    )
+    .await
+    .unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    let mut vc = g.view("vc").await.unwrap();
+
+    // a full bucket admits a write beyond the quota, but nothing after it
+    votes
+        .perform_all((0..3).map(|i| vec![1.into(), i.into()]))
+        .await
+        .unwrap();
+    match votes.insert(vec![2.into(), 0.into()]).await {
+        Err(TableError::Rejected(WriteRejection::QuotaExceeded { table, writer })) => {
+            assert_eq!(table, "votes");
+            assert_eq!(writer, None);
+        }
+        r => panic!("write beyond the quota was not rejected: {:?}", r),
+    }
+    sleep().await;
+
+    // the same goes for lookups
+    let keys = (1..4).map(|story| vec![story.into()]).collect();
+    let results = vc.multi_lookup(keys, true).await.unwrap();
+    assert_eq!(results[0], vec![vec![1.into(), 3.into()]]);
+    assert!(results[1].is_empty());
+    match vc.lookup(&[1.into()], true).await {
+        Err(ViewError::QuotaExceeded) => {}
+        r => panic!("lookup beyond the quota was not turned away: {:?}", r),
+    }
+
+    // both quotas recover over time
+    tokio::time::delay_for(Duration::from_secs(2)).await;
+    votes.insert(vec![2.into(), 0.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        vc.lookup(&[2.into()], true).await.unwrap(),
+        vec![vec![2.into(), 1.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn universes_with_text_user_ids_see_their_rows() {
+    let mut tokens = HashMap::new();
+    tokens.insert("s3cret".to_owned(), DataType::from("alice"));
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.set_authenticator(Arc::new(tokens));
+    b.set_persistence(get_persistence_params(
+        "universes_with_text_user_ids_see_their_rows",
+    ));
+    let mut g = b.start_local().await.unwrap().0;
+    g.authenticate("s3cret");
+    g.install_recipe(
+        "CREATE TABLE posts (id int, author text);
+         VIEW posts_by_id: SELECT id, author FROM posts WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    // the policy only compares against the user id, so the universe shares its subtree
+    g.set_security_config(
+        r#"{ "policies": [
+            { "table": "posts", "predicate": "WHERE posts.author = UserContext.id" }
+        ] }"#
+            .to_owned(),
+    )
+    .await
+    .unwrap();
+    let mut context = HashMap::new();
+    context.insert("id".to_owned(), DataType::from("alice"));
+    g.create_universe(context).await.unwrap();
+
+    let mut posts = g.table("posts").await.unwrap();
+    posts.insert(vec![1.into(), "alice".into()]).await.unwrap();
+    posts.insert(vec![1.into(), "bob".into()]).await.unwrap();
+    sleep().await;
+
+    let mut view = g.view("posts_by_id").await.unwrap();
+    assert_eq!(
+        view.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), "alice".into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn authenticated_clients_write_as_themselves() {
+    use noria::error::{TableError, WriteRejection};
+
+    let mut tokens = HashMap::new();
+    tokens.insert("s3cret".to_owned(), DataType::from("alice"));
+    let mut b = Builder::default();
+    b.set_sharding(None);
+    b.set_write_quota(1);
+    b.set_authenticator(Arc::new(tokens));
+    b.set_persistence(get_persistence_params(
+        "authenticated_clients_write_as_themselves",
+    ));
+    let mut g = b.start_local().await.unwrap().0;
+
+    // requests without valid credentials are turned away
+    assert!(g
+        .install_recipe("CREATE TABLE votes (story int, user int);")
+        .await
+        .is_err());
+    g.authenticate("guess");
+    assert!(g
+        .install_recipe("CREATE TABLE votes (story int, user int);")
+        .await
+        .is_err());
+
+    g.authenticate("s3cret");
+    g.install_recipe("CREATE TABLE votes (story int, user int);")
+        .await
+        .unwrap();
+
+    // tables fetched by an authenticated client write as the identity it authenticated as
+    let mut votes = g.table("votes").await.unwrap();
+    votes.insert(vec![1.into(), 1.into()]).await.unwrap();
+    match votes.insert(vec![1.into(), 2.into()]).await {
+        Err(TableError::Rejected(WriteRejection::QuotaExceeded { writer, .. })) => {
+            assert_eq!(writer, Some("alice".into()));
+        }
+        r => panic!("write beyond the quota was not rejected: {:?}", r),
+    }
+
+    // the workers turn away tables that do not present credentials themselves
+    let tb: Option<noria::builders::TableBuilder> = g
+        .rpc("table_builder", "votes", "failed to fetch table builder")
+        .await
+        .unwrap();
+    let mut anonymous = tb.unwrap().build(Default::default()).unwrap();
+    let write = anonymous.insert(vec![2.into(), 1.into()]);
+    match tokio::time::timeout(Duration::from_secs(5), write).await {
+        Ok(Ok(())) => panic!("write without credentials was accepted"),
+        Ok(Err(_)) | Err(_) => {}
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn large_lookups_are_capped_and_streamed() {
+    use futures_util::stream::TryStreamExt;
+    use noria::Cursor;
+
+    let mut b = Builder::default();
+    b.set_max_lookup_rows(3);
+    b.set_persistence(get_persistence_params(
+        "large_lookups_are_capped_and_streamed",
+    ));
+    let mut g = b.start_local().await.unwrap().0;
+    g.install_recipe(
+        "CREATE TABLE votes (story int, user int);
+         VIEW sv: SELECT story, user FROM votes WHERE story = ?;",
+    )
+    .await
+    .unwrap();
+
+    let mut votes = g.table("votes").await.unwrap();
+    let mut sv = g.view("sv").await.unwrap();
+    votes
+        .perform_all((0..10).map(|i| vec![1.into(), i.into()]))
+        .await
+        .unwrap();
+    votes.insert(vec![2.into(), 0.into()]).await.unwrap();
+    sleep().await;
+
+    let rs = sv.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 3);
+    assert!(rs.is_truncated());
+    let rs = sv.lookup(&[2.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 1);
+    assert!(!rs.is_truncated());
+
+    // chunks larger than the cap are cut down to it
+    let (chunk, next) = sv
+        .lookup_chunk(&[1.into()], Cursor::default(), 5)
+        .await
+        .unwrap();
+    assert_eq!(chunk.len(), 3);
+    let (chunk, _) = sv
+        .lookup_chunk(&[1.into()], next.unwrap(), 5)
+        .await
+        .unwrap();
+    assert_eq!(chunk.len(), 3);
+
+    // streaming a key reads all of its rows
+    let chunks: Vec<_> = sv
+        .lookup_stream(&[1.into()], 2)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), 5);
+    let mut users: Vec<i32> = chunks
+        .into_iter()
+        .flatten()
+        .map(|row| i32::from(row[1].clone()))
+        .collect();
+    users.sort();
+    assert_eq!(users, (0..10).collect::<Vec<_>>());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn inferred_views_are_not_retried_after_failing() {
+    use noria::ParameterInference;
+
+    let mut g = start_simple("inferred_views_are_not_retried_after_failing").await;
+    g.install_recipe("CREATE TABLE article (id int, title text, PRIMARY KEY(id));")
+        .await
+        .unwrap();
+    let mut article = g.table("article").await.unwrap();
+    article.insert(vec![1.into(), "a".into()]).await.unwrap();
+    sleep().await;
+
+    let mut inference = ParameterInference::new(2);
+    let sql = "SELECT title FROM article WHERE id = 1";
+    assert!(inference.execute(&mut *g, sql).await.unwrap().is_none());
+    let rs = inference.execute(&mut *g, sql).await.unwrap().unwrap();
+    assert_eq!(rs, vec![vec![DataType::from("a")]]);
+
+    // a shape whose view cannot be installed fails once, and is then left to the caller
+    let missing = "SELECT title FROM missing WHERE id = 1";
+    assert!(inference.execute(&mut *g, missing).await.unwrap().is_none());
+    assert!(inference.execute(&mut *g, missing).await.is_err());
+    assert!(inference.execute(&mut *g, missing).await.unwrap().is_none());
+    assert_eq!(inference.stats().rewrites.len(), 1);
+}
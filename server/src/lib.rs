@@ -357,10 +357,13 @@ extern crate serde_derive;
 #[macro_use]
 extern crate slog;
 
+mod authentication;
+mod binlog;
 mod builder;
 mod controller;
 mod coordination;
 mod handle;
+pub mod harness;
 mod startup;
 mod worker;
 
@@ -376,10 +379,16 @@ pub enum ReuseConfigType {
     NoReuse,
 }
 
+pub use crate::authentication::Authenticator;
+pub use crate::binlog::{BinlogDecoder, BinlogPosition, BinlogReplicator, RowChange};
 pub use crate::builder::Builder;
 pub use crate::handle::Handle;
 pub use controller::migrate::materialization::FrontierStrategy;
-pub use dataflow::{DurabilityMode, PersistenceParameters};
+pub use dataflow::ops::grouped::custom::GroupedAggregator;
+pub use dataflow::{
+    compact_state, inspect_state, upgrade_state, StateReport, STATE_FORMAT_VERSION,
+};
+pub use dataflow::{DurabilityMode, PersistenceParameters, ReplayConfig, ReplayPacing};
 pub use noria::consensus::LocalAuthority;
 pub use noria::*;
 pub use petgraph::graph::NodeIndex;
@@ -392,6 +401,7 @@ pub mod manual {
 }
 
 use dataflow::DomainConfig;
+use std::collections::HashSet;
 use std::time;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -406,6 +416,45 @@ pub(crate) struct Config {
     pub(crate) quorum: usize,
     pub(crate) reuse: ReuseConfigType,
     pub(crate) threads: Option<usize>,
+    /// Where to `POST` controller events to, if anywhere.
+    #[serde(default)]
+    pub(crate) event_webhook: Option<String>,
+    /// Evictions of at least this many bytes are recorded as controller events.
+    #[serde(default)]
+    pub(crate) eviction_event_threshold: Option<usize>,
+    /// Whether unsupported queries become pass-through queries instead of failing the recipe.
+    #[serde(default)]
+    pub(crate) pass_through_unsupported: bool,
+    /// Serve reads on each worker from a pool of this many threads, instead of from the threads
+    /// that also process writes and replays.
+    #[serde(default)]
+    pub(crate) reader_threads: Option<usize>,
+    /// Turn reads away once this many are pending on a worker.
+    #[serde(default)]
+    pub(crate) max_pending_reads: Option<usize>,
+    /// Turn reads away once a client connection looks up more than this many keys per second.
+    #[serde(default)]
+    pub(crate) lookup_quota: Option<u32>,
+    /// Turn reads away that would have a client connection wait on more than this many missed
+    /// keys at a time.
+    #[serde(default)]
+    pub(crate) max_client_upqueries: Option<usize>,
+    /// Return at most this many rows for each key of a lookup.
+    #[serde(default)]
+    pub(crate) max_lookup_rows: Option<usize>,
+    /// Serve view lookups as JSON over HTTP on this port of each worker.
+    #[serde(default)]
+    pub(crate) http_reads_port: Option<u16>,
+    /// How long HTTP proxies may cache the results of HTTP lookups for.
+    #[serde(default)]
+    pub(crate) http_reads_max_age: time::Duration,
+    /// Only serve these views over HTTP, and let any origin read and cache them, if set.
+    #[serde(default)]
+    pub(crate) http_reads_views: Option<HashSet<String>>,
+    /// Make new views readable right away, and fill them in the background at up to this many
+    /// keys per second.
+    #[serde(default)]
+    pub(crate) warm_rate: Option<usize>,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -419,6 +468,14 @@ impl Default for Config {
             domain_config: DomainConfig {
                 concurrent_replays: 512,
                 replay_batch_timeout: time::Duration::new(0, 100_000),
+                join_spill_threshold: None,
+                snapshot_reads: false,
+                frontier_interval: None,
+                max_queued_packets: Some(16 * 1024),
+                slow_replay_threshold: None,
+                state_compression: None,
+                packet_compression: None,
+                write_quota: None,
             },
             persistence: Default::default(),
             heartbeat_every: time::Duration::from_secs(1),
@@ -429,6 +486,18 @@ impl Default for Config {
             threads: Some(2),
             #[cfg(not(any(debug_assertions, test)))]
             threads: None,
+            event_webhook: None,
+            eviction_event_threshold: None,
+            pass_through_unsupported: false,
+            reader_threads: None,
+            max_pending_reads: None,
+            lookup_quota: None,
+            max_client_upqueries: None,
+            max_lookup_rows: None,
+            http_reads_port: None,
+            http_reads_max_age: time::Duration::from_secs(0),
+            http_reads_views: None,
+            warm_rate: None,
         }
     }
 }
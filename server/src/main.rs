@@ -1,4 +1,5 @@
 use clap::value_t_or_exit;
+use noria::Compression;
 use noria_server::{Builder, ReuseConfigType, ZookeeperAuthority};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -51,6 +52,15 @@ fn main() {
                 .takes_value(true)
                 .help("Absolute path to the directory where the log files will be written."),
         )
+        .arg(
+            Arg::with_name("checkpoint-every")
+                .long("checkpoint-every")
+                .takes_value(true)
+                .help(
+                    "Checkpoint materialized state this often, in seconds, so that recovery \
+                     only replays the writes since the last checkpoint.",
+                ),
+        )
         .arg(
             Arg::with_name("zookeeper")
                 .short("z")
@@ -75,6 +85,179 @@ fn main() {
                 .requires("memory")
                 .help("Frequency at which to check the state size against the memory limit [in seconds]."),
         )
+        .arg(
+            Arg::with_name("reader_threads")
+                .long("reader-threads")
+                .takes_value(true)
+                .default_value("0")
+                .help("Number of threads dedicated to serving reads [0 = share the worker threads]."),
+        )
+        .arg(
+            Arg::with_name("max_pending_reads")
+                .long("max-pending-reads")
+                .takes_value(true)
+                .default_value("0")
+                .help("Number of pending reads beyond which reads are turned away [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("lookup_quota")
+                .long("lookup-quota")
+                .takes_value(true)
+                .default_value("0")
+                .help("Keys each client connection may look up per second [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("write_quota")
+                .long("write-quota")
+                .takes_value(true)
+                .default_value("0")
+                .help("Writes each writer may make to a table per second [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("max_client_upqueries")
+                .long("max-client-upqueries")
+                .takes_value(true)
+                .default_value("0")
+                .help("Missed keys each client connection may wait on at a time [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("max_lookup_rows")
+                .long("max-lookup-rows")
+                .takes_value(true)
+                .default_value("0")
+                .help("Rows a lookup returns for each key [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("tls_ca")
+                .long("tls-ca")
+                .takes_value(true)
+                .requires_all(&["tls_cert", "tls_key"])
+                .help("PEM file of the CA that signs all worker and client certificates; turns on TLS."),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .requires("tls_ca")
+                .help("PEM file of the certificate chain this worker presents."),
+        )
+        .arg(
+            Arg::with_name("tls_key")
+                .long("tls-key")
+                .takes_value(true)
+                .requires("tls_ca")
+                .help("PEM file of the private key of --tls-cert."),
+        )
+        .arg(
+            Arg::with_name("tls_server_name")
+                .long("tls-server-name")
+                .takes_value(true)
+                .default_value("noria")
+                .help("Name that the certificates of all workers are valid for."),
+        )
+        .arg(
+            Arg::with_name("tls_verify_clients")
+                .long("tls-verify-clients")
+                .requires("tls_ca")
+                .help("Only accept connections from clients with a certificate signed by the CA."),
+        )
+        .arg(
+            Arg::with_name("join_spill")
+                .long("join-spill")
+                .takes_value(true)
+                .default_value("0")
+                .help("Size, in bytes, beyond which fully materialized join inputs spill to disk [0 = never]."),
+        )
+        .arg(
+            Arg::with_name("max_queued_packets")
+                .long("max-queued-packets")
+                .takes_value(true)
+                .default_value("16384")
+                .help("Number of packets a domain may queue for other domains before it stops accepting input [0 = unbounded]."),
+        )
+        .arg(
+            Arg::with_name("slow_replay_ms")
+                .long("slow-replay-ms")
+                .takes_value(true)
+                .default_value("0")
+                .help("Record partial replays that take longer than this many milliseconds to fill a key [0 = never]."),
+        )
+        .arg(
+            Arg::with_name("state_compression")
+                .long("state-compression")
+                .takes_value(true)
+                .possible_values(&["none", "lz4", "zstd"])
+                .default_value("none")
+                .help("Keep wide text values in materialized state compressed with this codec."),
+        )
+        .arg(
+            Arg::with_name("packet_compression")
+                .long("packet-compression")
+                .takes_value(true)
+                .possible_values(&["none", "lz4", "zstd"])
+                .default_value("none")
+                .help("Compress updates sent to other workers with this codec."),
+        )
+        .arg(
+            Arg::with_name("warm_rate")
+                .long("warm-rate")
+                .takes_value(true)
+                .default_value("0")
+                .help("Fill new views in the background at this many keys per second, so they are readable right away [0 = off]."),
+        )
+        .arg(
+            Arg::with_name("snapshot_reads")
+                .long("snapshot-reads")
+                .help("Track which writes each view reflects to allow consistent reads across views."),
+        )
+        .arg(
+            Arg::with_name("frontier_interval")
+                .long("frontier-interval")
+                .takes_value(true)
+                .default_value("0")
+                .help("Advance the write frontiers of base tables every this many ms, to allow reads that reflect every write up to a time [0 = off]."),
+        )
+        .arg(
+            Arg::with_name("event_webhook")
+                .long("event-webhook")
+                .takes_value(true)
+                .help("URL to POST controller events to."),
+        )
+        .arg(
+            Arg::with_name("eviction_event_threshold")
+                .long("eviction-event-threshold")
+                .takes_value(true)
+                .default_value("0")
+                .help("Size, in bytes, of evictions that are recorded as controller events [0 = none]."),
+        )
+        .arg(
+            Arg::with_name("pass_through")
+                .long("pass-through-unsupported")
+                .help("Register unsupported queries for the adapter to run against its upstream database."),
+        )
+        .arg(
+            Arg::with_name("http_reads_port")
+                .long("http-reads-port")
+                .takes_value(true)
+                .help("Port to serve view lookups as JSON on, at GET /view/{name}/{key}."),
+        )
+        .arg(
+            Arg::with_name("http_reads_max_age")
+                .long("http-reads-max-age")
+                .takes_value(true)
+                .default_value("0")
+                .requires("http_reads_port")
+                .help("Time HTTP proxies may cache the results of HTTP lookups for [in seconds]."),
+        )
+        .arg(
+            Arg::with_name("http_reads_view")
+                .long("http-reads-view")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("http_reads_port")
+                .help("Only serve this view over HTTP, to any origin (can be repeated)."),
+        )
         .arg(
             Arg::with_name("noreuse")
                 .long("no-reuse")
@@ -100,6 +283,14 @@ fn main() {
                 .default_value("0")
                 .help("Shard the graph this many ways (0 = disable sharding)."),
         )
+        .arg(
+            Arg::with_name("shard-hasher")
+                .long("shard-hasher")
+                .takes_value(true)
+                .possible_values(&["default", "jump"])
+                .default_value("default")
+                .help("How keys are assigned to shards. Clients must use the same hasher."),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -116,6 +307,18 @@ fn main() {
     let zookeeper_addr = matches.value_of("zookeeper").unwrap();
     let memory = value_t_or_exit!(matches, "memory", usize);
     let memory_check_freq = value_t_or_exit!(matches, "memory_check_freq", u64);
+    let join_spill = value_t_or_exit!(matches, "join_spill", usize);
+    let max_queued_packets = value_t_or_exit!(matches, "max_queued_packets", usize);
+    let slow_replay_ms = value_t_or_exit!(matches, "slow_replay_ms", u64);
+    let warm_rate = value_t_or_exit!(matches, "warm_rate", usize);
+    let frontier_interval = value_t_or_exit!(matches, "frontier_interval", u64);
+    let reader_threads = value_t_or_exit!(matches, "reader_threads", usize);
+    let max_pending_reads = value_t_or_exit!(matches, "max_pending_reads", usize);
+    let lookup_quota = value_t_or_exit!(matches, "lookup_quota", u32);
+    let write_quota = value_t_or_exit!(matches, "write_quota", u32);
+    let max_client_upqueries = value_t_or_exit!(matches, "max_client_upqueries", usize);
+    let max_lookup_rows = value_t_or_exit!(matches, "max_lookup_rows", usize);
+    let eviction_event_threshold = value_t_or_exit!(matches, "eviction_event_threshold", usize);
     let quorum = value_t_or_exit!(matches, "quorum", usize);
     let persistence_threads = value_t_or_exit!(matches, "persistence-threads", i32);
     let flush_ns = value_t_or_exit!(matches, "flush-timeout", u32);
@@ -133,14 +336,96 @@ fn main() {
     if memory > 0 {
         builder.set_memory_limit(memory, Duration::from_secs(memory_check_freq));
     }
+    if join_spill > 0 {
+        builder.set_join_spill_threshold(join_spill);
+    }
+    builder.set_max_queued_packets(Some(max_queued_packets).filter(|&n| n > 0));
+    if slow_replay_ms > 0 {
+        builder.set_slow_replay_threshold(Duration::from_millis(slow_replay_ms));
+    }
+    let codec = |arg: &str| match matches.value_of(arg) {
+        Some("lz4") => Some(Compression::Lz4),
+        Some("zstd") => Some(Compression::Zstd),
+        _ => None,
+    };
+    if let Some(codec) = codec("state_compression") {
+        builder.set_state_compression(codec);
+    }
+    if let Some(codec) = codec("packet_compression") {
+        builder.set_packet_compression(codec);
+    }
+    if warm_rate > 0 {
+        builder.set_warm_rate(warm_rate);
+    }
+    if reader_threads > 0 {
+        builder.set_reader_threads(reader_threads);
+    }
+    if max_pending_reads > 0 {
+        builder.set_max_pending_reads(max_pending_reads);
+    }
+    if lookup_quota > 0 {
+        builder.set_lookup_quota(lookup_quota);
+    }
+    if write_quota > 0 {
+        builder.set_write_quota(write_quota);
+    }
+    if max_client_upqueries > 0 {
+        builder.set_max_client_upqueries(max_client_upqueries);
+    }
+    if max_lookup_rows > 0 {
+        builder.set_max_lookup_rows(max_lookup_rows);
+    }
     builder.set_sharding(sharding);
+    if matches.value_of("shard-hasher") == Some("jump") {
+        noria::set_shard_hasher(Arc::new(noria::JumpShardHasher));
+    }
     builder.set_quorum(quorum);
     if matches.is_present("nopartial") {
         builder.disable_partial();
     }
+    if matches.is_present("snapshot_reads") {
+        builder.enable_snapshot_reads();
+    }
+    if frontier_interval > 0 {
+        builder.set_frontier_interval(Duration::from_millis(frontier_interval));
+    }
+    if let Some(url) = matches.value_of("event_webhook") {
+        builder.set_event_webhook(url.to_owned());
+    }
+    if eviction_event_threshold > 0 {
+        builder.set_eviction_event_threshold(eviction_event_threshold);
+    }
+    if matches.is_present("pass_through") {
+        builder.enable_pass_through();
+    }
+    if matches.is_present("http_reads_port") {
+        builder.enable_http_reads(value_t_or_exit!(matches, "http_reads_port", u16));
+        builder.set_http_reads_max_age(Duration::from_secs(value_t_or_exit!(
+            matches,
+            "http_reads_max_age",
+            u64
+        )));
+        if let Some(views) = matches.values_of("http_reads_view") {
+            builder.set_http_reads_views(views);
+        }
+    }
     if matches.is_present("noreuse") {
         builder.set_reuse(ReuseConfigType::NoReuse);
     }
+    if matches.is_present("tls_ca") {
+        let read = |arg| {
+            let path = matches.value_of(arg).unwrap();
+            std::fs::read(path).unwrap_or_else(|e| panic!("could not read {}: {}", path, e))
+        };
+        let name = matches.value_of("tls_server_name").unwrap();
+        let mut tls = noria::TlsConfig::new(&read("tls_ca"), name)
+            .and_then(|tls| tls.with_identity(&read("tls_cert"), &read("tls_key")))
+            .expect("invalid TLS configuration");
+        if matches.is_present("tls_verify_clients") {
+            tls = tls.verify_clients();
+        }
+        builder.set_tls(tls).expect("could not turn on TLS");
+    }
 
     let mut persistence_params = noria_server::PersistenceParameters::new(
         match durability {
@@ -156,6 +441,10 @@ fn main() {
     persistence_params.log_dir = matches
         .value_of("log-dir")
         .and_then(|p| Some(PathBuf::from(p)));
+    if matches.is_present("checkpoint-every") {
+        let secs = value_t_or_exit!(matches, "checkpoint-every", u64);
+        persistence_params.checkpoint_every = Some(Duration::from_secs(secs));
+    }
     builder.set_persistence(persistence_params);
 
     if verbose {
@@ -1,15 +1,18 @@
-use crate::controller::ControllerState;
+use crate::controller::{ControllerState, MigrationProgress};
 use crate::coordination::{CoordinationMessage, CoordinationPayload};
 use async_bincode::AsyncBincodeReader;
+use dataflow::metrics::Metrics;
 use futures_util::{
     future::FutureExt,
     future::TryFutureExt,
     sink::SinkExt,
     stream::{StreamExt, TryStreamExt},
 };
-use hyper::{self, header::CONTENT_TYPE, Method, StatusCode};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{self, Method, StatusCode};
 use noria::consensus::Authority;
-use noria::ControllerDescriptor;
+use noria::tls::Stream;
+use noria::{ControllerDescriptor, DataType, Protocol};
 use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
@@ -23,7 +26,7 @@ use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::handle::Handle;
-use crate::Config;
+use crate::{Authenticator, Config};
 
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum Event {
@@ -33,17 +36,24 @@ pub(crate) enum Event {
         String,
         Option<String>,
         hyper::body::Bytes,
+        Option<DataType>,
         tokio::sync::oneshot::Sender<Result<Result<String, String>, StatusCode>>,
     ),
     LeaderChange(ControllerState, ControllerDescriptor),
     WonLeaderElection(ControllerState),
+    /// This instance was the controller, but another instance has taken over, or is about to.
+    LostLeadership,
     CampaignError(failure::Error),
-    #[cfg(test)]
     IsReady(tokio::sync::oneshot::Sender<bool>),
     ManualMigration {
         f: Box<dyn FnOnce(&mut crate::controller::migrate::Migration) + Send + 'static>,
         done: tokio::sync::oneshot::Sender<()>,
     },
+    /// A full replay into a new node has finished, so a background backfill may be able to make
+    /// progress.
+    ReplayFinished,
+    /// A trigger action has finished, so the next firing of its trigger may be able to run.
+    TriggerActionFinished,
 }
 
 use std::fmt;
@@ -54,10 +64,12 @@ impl fmt::Debug for Event {
             Event::ExternalRequest(ref m, ref path, ..) => write!(f, "Request({} {})", m, path),
             Event::LeaderChange(..) => write!(f, "LeaderChange(..)"),
             Event::WonLeaderElection(..) => write!(f, "Won(..)"),
+            Event::LostLeadership => write!(f, "LostLeadership"),
             Event::CampaignError(ref e) => write!(f, "CampaignError({:?})", e),
-            #[cfg(test)]
             Event::IsReady(..) => write!(f, "IsReady"),
             Event::ManualMigration { .. } => write!(f, "ManualMigration{{..}}"),
+            Event::ReplayFinished => write!(f, "ReplayFinished"),
+            Event::TriggerActionFinished => write!(f, "TriggerActionFinished"),
         }
     }
 }
@@ -70,6 +82,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     config: Config,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    authenticator: Option<Arc<dyn Authenticator>>,
     log: slog::Logger,
 ) -> Result<(Handle<A>, impl Future<Output = ()> + Unpin + Send), failure::Error> {
     let (trigger, valve) = Valve::new();
@@ -91,6 +104,14 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     // give it its own channel.
     let cport = tokio::net::TcpListener::bind(SocketAddr::new(listen_addr, 0)).await?;
     let caddr = cport.local_addr()?;
+    // and finally, view lookups over HTTP, if enabled
+    let hport = match config.http_reads_port {
+        Some(port) => {
+            Some(tokio::net::TcpListener::bind(SocketAddr::new(listen_addr, port)).await?)
+        }
+        None => None,
+    };
+    let haddr = hport.as_ref().map(|p| p.local_addr()).transpose()?;
 
     // set up different loops for the controller "part" and the worker "part" of us. this is
     // necessary because sometimes the two need to communicate (e.g., for migrations), and if they
@@ -98,6 +119,12 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
     let (worker_tx, worker_rx) = tokio::sync::mpsc::unbounded_channel();
 
+    // how far along migrations are is reported without going through the controller, since the
+    // controller does not handle requests while it is migrating.
+    let progress = MigrationProgress::default();
+    // and so are the metrics of the domains on this worker, which it serves at /metrics
+    let metrics = Metrics::default();
+
     // spawn all of those
     tokio::spawn(listen_internal(
         alive.clone(),
@@ -114,6 +141,9 @@ pub(super) async fn start_instance<A: Authority + 'static>(
             tx.clone(),
             xport,
             authority.clone(),
+            progress.clone(),
+            metrics.clone(),
+            authenticator.clone(),
         )
         .map_err(move |e| {
             warn!(ext_log, "external request failed: {:?}", e);
@@ -121,6 +151,23 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         .map(|_| ()),
     );
 
+    if let Some(hport) = hport {
+        let http_log = log.clone();
+        tokio::spawn(
+            crate::worker::http::listen(
+                valve.clone(),
+                hport,
+                authority.clone(),
+                config.http_reads_max_age,
+                config.http_reads_views.clone(),
+            )
+            .map_err(move |e| {
+                warn!(http_log, "http read server failed: {:?}", e);
+            })
+            .map(|_| ()),
+        );
+    }
+
     // first, a loop that just forwards to the appropriate place
     let a = alive.clone();
     tokio::spawn(async move {
@@ -136,26 +183,54 @@ pub(super) async fn start_instance<A: Authority + 'static>(
                     CoordinationPayload::DomainBooted(..) => wtx.send(e),
                     CoordinationPayload::Register { .. } => ctx.send(e),
                     CoordinationPayload::Heartbeat => ctx.send(e),
+                    CoordinationPayload::ReaderLoad(..) => ctx.send(e),
                     CoordinationPayload::CreateUniverse(..) => ctx.send(e),
+                    CoordinationPayload::FireTrigger(..) => ctx.send(e),
+                    CoordinationPayload::DeadLetters { .. } => ctx.send(e),
+                    CoordinationPayload::Quarantine { .. } => ctx.send(e),
+                    CoordinationPayload::Evicted { .. } => ctx.send(e),
+                    CoordinationPayload::DomainFailed { .. } => ctx.send(e),
                 },
                 Event::ExternalRequest(..) => ctx.send(e),
                 Event::ManualMigration { .. } => ctx.send(e),
                 Event::LeaderChange(..) => wtx.send(e),
                 Event::WonLeaderElection(..) => ctx.send(e),
+                Event::LostLeadership => ctx.send(e),
                 Event::CampaignError(..) => ctx.send(e),
-                #[cfg(test)]
                 Event::IsReady(..) => ctx.send(e),
+                Event::ReplayFinished => ctx.send(e),
+                Event::TriggerActionFinished => ctx.send(e),
             };
             // needed for https://gist.github.com/nikomatsakis/fee0e47e14c09c4202316d8ea51e50a0
             snd.unwrap();
         }
     });
 
+    let mut protocol = Protocol::current();
+    if config.domain_config.snapshot_reads {
+        protocol
+            .features
+            .insert(noria::protocol::feature::SNAPSHOT_READS.to_owned());
+    }
+    if config.domain_config.frontier_interval.is_some() {
+        protocol
+            .features
+            .insert(noria::protocol::feature::FRESH_READS.to_owned());
+    }
+    if cfg!(feature = "connectors") {
+        protocol
+            .features
+            .insert(noria::protocol::feature::KAFKA_SOURCES.to_owned());
+        protocol
+            .features
+            .insert(noria::protocol::feature::VIEW_SINKS.to_owned());
+    }
     let descriptor = ControllerDescriptor {
         external_addr: xaddr,
         worker_addr: waddr,
         domain_addr: caddr,
         nonce: rand::random(),
+        protocol,
     };
     tokio::spawn(crate::controller::main(
         alive.clone(),
@@ -167,6 +242,7 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         log.clone(),
         authority.clone(),
         tx.clone(),
+        progress,
     ));
     tokio::spawn(crate::worker::main(
         alive.clone(),
@@ -175,10 +251,12 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         waddr,
         memory_limit,
         memory_check_frequency,
+        metrics,
+        authenticator,
         log.clone(),
     ));
 
-    let h = Handle::new(authority, tx, trigger).await?;
+    let h = Handle::new(authority, tx, trigger, haddr).await?;
     Ok((h, done.into_future().map(|_| {})))
 }
 
@@ -200,7 +278,7 @@ async fn listen_internal(
                 let alive = alive.clone();
                 tokio::spawn(
                     valve
-                        .wrap(AsyncBincodeReader::from(sock))
+                        .wrap(AsyncBincodeReader::from(Stream::server(sock)))
                         .map_ok(Event::InternalMessage)
                         .map_err(failure::Error::from)
                         .forward(
@@ -222,6 +300,9 @@ struct ExternalServer<A: Authority>(
     tokio::sync::mpsc::Sender<()>,
     UnboundedSender<Event>,
     Arc<A>,
+    MigrationProgress,
+    Metrics,
+    Option<Arc<dyn Authenticator>>,
 );
 
 async fn listen_external<A: Authority + 'static>(
@@ -230,14 +311,24 @@ async fn listen_external<A: Authority + 'static>(
     event_tx: UnboundedSender<Event>,
     mut on: tokio::net::TcpListener,
     authority: Arc<A>,
+    progress: MigrationProgress,
+    metrics: Metrics,
+    authenticator: Option<Arc<dyn Authenticator>>,
 ) -> Result<(), hyper::Error> {
-    let on = valve.wrap(on.incoming());
+    let on = valve.wrap(on.incoming()).map_ok(Stream::server);
     use hyper::{service::make_service_fn, Body, Request, Response};
     use tower::Service;
     impl<A: Authority> Clone for ExternalServer<A> {
         // Needed due to #26925
         fn clone(&self) -> Self {
-            ExternalServer(self.0.clone(), self.1.clone(), self.2.clone())
+            ExternalServer(
+                self.0.clone(),
+                self.1.clone(),
+                self.2.clone(),
+                self.3.clone(),
+                self.4.clone(),
+                self.5.clone(),
+            )
         }
     }
 
@@ -274,6 +365,59 @@ async fn listen_external<A: Authority + 'static>(
                     _ => {}
                 }
             }
+            let identity = match self.5 {
+                Some(ref authenticator) => {
+                    let credentials = req
+                        .headers()
+                        .get(AUTHORIZATION)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.trim_start_matches("Bearer "));
+                    match authenticator.authenticate(credentials) {
+                        Ok(identity) => identity,
+                        Err(reason) => {
+                            let res = res
+                                .status(StatusCode::UNAUTHORIZED)
+                                .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                                .body(hyper::Body::from(reason));
+                            return Box::pin(async move { Ok(res.unwrap()) });
+                        }
+                    }
+                }
+                None => None,
+            };
+            if req.uri().path() == "/migration_status" {
+                let status = serde_json::to_string(&self.3.status()).unwrap();
+                let res = res
+                    .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                    .body(hyper::Body::from(status));
+                return Box::pin(async move { Ok(res.unwrap()) });
+            }
+            if let (&Method::GET, "/metrics") = (req.method(), req.uri().path()) {
+                // the metrics of this worker, followed by those of the controller if it is the
+                // leader
+                let mut metrics = self.4.render();
+                let event_tx = self.1.clone();
+                return Box::pin(async move {
+                    let (tx, rx) = tokio::sync::oneshot::channel();
+                    let req = Event::ExternalRequest(
+                        Method::GET,
+                        "/metrics".to_owned(),
+                        None,
+                        hyper::body::Bytes::new(),
+                        None,
+                        tx,
+                    );
+                    if event_tx.send(req).is_ok() {
+                        if let Ok(Ok(Ok(controller))) = rx.await {
+                            metrics.push_str(&controller);
+                        }
+                    }
+                    let res = res
+                        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(hyper::Body::from(metrics));
+                    Ok(res.unwrap())
+                });
+            }
 
             let method = req.method().clone();
             let path = req.uri().path().to_string();
@@ -284,8 +428,8 @@ async fn listen_external<A: Authority + 'static>(
                 let body = hyper::body::to_bytes(req.into_body()).await?;
                 let (tx, rx) = tokio::sync::oneshot::channel();
 
-                if let Err(_) = event_tx.send(Event::ExternalRequest(method, path, query, body, tx))
-                {
+                let req = Event::ExternalRequest(method, path, query, body, identity, tx);
+                if let Err(_) = event_tx.send(req) {
                     let res = res
                         .status(StatusCode::SERVICE_UNAVAILABLE)
                         .header("Content-Type", "text/plain; charset=utf-8");
@@ -315,7 +459,7 @@ async fn listen_external<A: Authority + 'static>(
         }
     }
 
-    let service = ExternalServer(alive, event_tx, authority);
+    let service = ExternalServer(alive, event_tx, authority, progress, metrics, authenticator);
     hyper::server::Server::builder(hyper::server::accept::from_stream(on))
         .serve(make_service_fn(move |_| {
             let s = service.clone();
@@ -0,0 +1,310 @@
+use futures_util::stream::TryStreamExt;
+use hyper::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use noria::consensus::Authority;
+use noria::error::ViewError;
+use noria::{ControllerHandle, DataType, View};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use stream_cancel::Valve;
+use tokio::sync::Mutex;
+
+/// How long a blocking lookup may wait for a missing key to be filled.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handles to the views that have been looked up, so that each is only resolved once.
+struct Views<A: Authority + 'static> {
+    handle: Mutex<Option<ControllerHandle<A>>>,
+    authority: Arc<A>,
+    views: Mutex<HashMap<String, View>>,
+}
+
+impl<A: Authority + 'static> Views<A> {
+    async fn get(&self, name: &str) -> Result<View, failure::Error> {
+        if let Some(view) = self.views.lock().await.get(name) {
+            return Ok(view.clone());
+        }
+
+        // the view is resolved without holding any lock, so other lookups are not held up by it
+        let resolve = {
+            let mut handle = self.handle.lock().await;
+            if handle.is_none() {
+                *handle = Some(ControllerHandle::make(self.authority.clone()).await?);
+            }
+            handle.as_mut().unwrap().view(name)
+        };
+        let view = resolve.await?;
+        self.views
+            .lock()
+            .await
+            .insert(name.to_owned(), view.clone());
+        Ok(view)
+    }
+
+    /// Resolve `name` anew the next time it is looked up, since it may have been dropped or
+    /// renamed.
+    async fn forget(&self, name: &str) {
+        self.views.lock().await.remove(name);
+    }
+}
+
+/// Serve `GET /view/{name}/{key}` lookups on `on` as JSON until `valve` closes.
+///
+/// A key is a comma-separated list of the values of the view's parameters. Values that look like
+/// integers are looked up as integers, and everything else as text, unless it is in double
+/// quotes. Views without parameters are looked up with `GET /view/{name}`. Lookups block until
+/// missing keys are filled unless `?block=false` is given, and successful blocking lookups may be
+/// cached for `max_age`. Results that were cut off at the worker's cap on the rows of a lookup
+/// come with an `X-Noria-Truncated: true` header.
+///
+/// Lookups are not authenticated. If `public` is given, only the views it names are served, and
+/// their results may be read from any origin and cached by shared HTTP proxies. Otherwise every
+/// view is served, including the views of security universes, to same-origin pages only, and only
+/// the client itself may cache the results.
+pub(crate) async fn listen<A: Authority + 'static>(
+    valve: Valve,
+    mut on: tokio::net::TcpListener,
+    authority: Arc<A>,
+    max_age: Duration,
+    public: Option<HashSet<String>>,
+) -> Result<(), hyper::Error> {
+    let views = Arc::new(Views {
+        handle: Mutex::new(None),
+        authority,
+        views: Mutex::new(HashMap::new()),
+    });
+    let public = Arc::new(public);
+    let on = valve.wrap(on.incoming()).map_ok(noria::tls::Stream::server);
+    hyper::server::Server::builder(hyper::server::accept::from_stream(on))
+        .serve(make_service_fn(move |_| {
+            let views = views.clone();
+            let public = public.clone();
+            async move {
+                io::Result::Ok(service_fn(move |req| {
+                    let views = views.clone();
+                    let public = public.clone();
+                    async move { io::Result::Ok(serve(&views, &public, req, max_age).await) }
+                }))
+            }
+        }))
+        .await
+}
+
+async fn serve<A: Authority + 'static>(
+    views: &Views<A>,
+    public: &Option<HashSet<String>>,
+    req: Request<Body>,
+    max_age: Duration,
+) -> Response<Body> {
+    // only views named as public may be read from pages of any origin
+    let res = if public.is_some() {
+        Response::builder().header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+    } else {
+        Response::builder()
+    };
+    let text = |status: StatusCode, body: String| {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    if req.method() != Method::GET {
+        return text(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only GET is supported".to_owned(),
+        );
+    }
+    let (name, key) = match parse_path(req.uri().path()) {
+        Some(lookup) => lookup,
+        None => {
+            return text(
+                StatusCode::NOT_FOUND,
+                "expected /view/{name}/{key}".to_owned(),
+            )
+        }
+    };
+    let block = req
+        .uri()
+        .query()
+        .map_or(true, |q| !q.split('&').any(|p| p == "block=false"));
+
+    if public
+        .as_ref()
+        .map_or(false, |public| !public.contains(&name))
+    {
+        return text(
+            StatusCode::NOT_FOUND,
+            format!("view {} is not served", name),
+        );
+    }
+    let mut view = match views.get(&name).await {
+        Ok(view) => view,
+        Err(e) => return text(StatusCode::NOT_FOUND, format!("no view {}: {}", name, e)),
+    };
+    let results = if block {
+        view.lookup_timeout(&key, LOOKUP_TIMEOUT).await
+    } else {
+        view.lookup(&key, false).await
+    };
+    let (rows, truncated): (Vec<Vec<DataType>>, _) = match results {
+        Ok(rs) => {
+            let truncated = rs.is_truncated();
+            (rs.into(), truncated)
+        }
+        Err(ViewError::Timeout) => {
+            let msg = format!("lookup in {} did not complete in time", name);
+            return text(StatusCode::GATEWAY_TIMEOUT, msg);
+        }
+        Err(e) => {
+            // the view may have been dropped or renamed since it was resolved
+            views.forget(&name).await;
+            return text(StatusCode::SERVICE_UNAVAILABLE, e.to_string());
+        }
+    };
+
+    let body = serde_json::to_string(&to_json(view.columns(), &rows)).unwrap();
+    // non-blocking lookups answer misses with no rows, which must not be cached
+    let cache = if block && max_age > Duration::from_secs(0) {
+        // views that were not named as public may hold what only some users should see
+        let scope = if public.is_some() {
+            "public"
+        } else {
+            "private"
+        };
+        format!("{}, max-age={}", scope, max_age.as_secs())
+    } else {
+        "no-store".to_owned()
+    };
+    // rows beyond the worker's cap on the rows of a lookup are left out
+    let res = if truncated {
+        res.header("X-Noria-Truncated", "true")
+    } else {
+        res
+    };
+    res.header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .header(CACHE_CONTROL, cache)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// The view name and key of a `/view/{name}/{key}` path.
+fn parse_path(path: &str) -> Option<(String, Vec<DataType>)> {
+    let mut parts = path.strip_prefix("/view/")?.splitn(2, '/');
+    let name = percent_decode(parts.next()?)?;
+    if name.is_empty() {
+        return None;
+    }
+    let key = match parts.next() {
+        // views without parameters are keyed on a constant
+        None | Some("") => vec![DataType::from(0)],
+        Some(key) => key
+            .split(',')
+            .map(|v| {
+                let v = percent_decode(v)?;
+                Some(match v.parse::<i64>() {
+                    Ok(n) => DataType::from(n),
+                    Err(_) if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') => {
+                        DataType::from(&v[1..v.len() - 1])
+                    }
+                    Err(_) => DataType::from(v),
+                })
+            })
+            .collect::<Option<_>>()?,
+    };
+    Some((name, key))
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The rows of a view as a JSON array of objects keyed by column name.
+///
+/// Decimals are given as strings so that they keep their exact value, and timestamps as
+/// `YYYY-MM-DD HH:MM:SS`.
+fn to_json(columns: &[String], rows: &[Vec<DataType>]) -> serde_json::Value {
+    use serde_json::Value;
+    let value = |v: &DataType| match *v {
+        DataType::None => Value::Null,
+        DataType::Int(_) | DataType::BigInt(_) => Value::from(i64::from(v.clone())),
+        DataType::UnsignedInt(_) | DataType::UnsignedBigInt(_) => Value::from(u64::from(v.clone())),
+        DataType::Real(..) => Value::from(f64::from(v.clone())),
+        DataType::Timestamp(ts) => Value::from(ts.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        DataType::Json(..) => v.to_json().unwrap_or(Value::Null),
+        DataType::Text(..) | DataType::TinyText(..) | DataType::Compressed(..) => {
+            Value::from(<&str>::from(&*v.decompress()))
+        }
+        DataType::Decimal(..) => Value::from(v.to_string()),
+    };
+    Value::Array(
+        rows.iter()
+            .map(|row| {
+                Value::Object(
+                    columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, v)| (c.clone(), value(v)))
+                        .collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_paths() {
+        assert_eq!(
+            parse_path("/view/vc/1"),
+            Some(("vc".to_owned(), vec![1.into()]))
+        );
+        assert_eq!(
+            parse_path("/view/by_name/ann%20lee,%2242%22,7"),
+            Some((
+                "by_name".to_owned(),
+                vec!["ann lee".into(), "42".into(), 7.into()]
+            ))
+        );
+        assert_eq!(
+            parse_path("/view/totals"),
+            Some(("totals".to_owned(), vec![0.into()]))
+        );
+        assert_eq!(parse_path("/view/"), None);
+        assert_eq!(parse_path("/view/vc/%4"), None);
+        assert_eq!(parse_path("/tables/t/1"), None);
+    }
+
+    #[test]
+    fn it_renders_rows() {
+        let columns = vec!["id".to_owned(), "name".to_owned(), "price".to_owned()];
+        let rows = vec![vec![
+            1.into(),
+            "ann".into(),
+            DataType::decimal("12.50").unwrap(),
+        ]];
+        assert_eq!(
+            to_json(&columns, &rows),
+            serde_json::json!([{"id": 1, "name": "ann", "price": "12.50"}])
+        );
+    }
+}
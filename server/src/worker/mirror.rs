@@ -0,0 +1,168 @@
+use noria::{ControllerHandle, MirrorTarget, Table, TableOperation, ZookeeperAuthority};
+use std::collections::HashMap;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// How many batches of sampled writes a domain may have waiting to be forwarded before it drops
+/// new ones.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// A batch of sampled writes to a table, and where to forward them.
+struct Batch {
+    table: String,
+    target: MirrorTarget,
+    ops: Vec<TableOperation>,
+}
+
+/// A batch of mirrored writes, as it is written to a sink.
+#[derive(Serialize)]
+struct MirroredWrites<'a> {
+    table: &'a str,
+    ops: &'a [TableOperation],
+}
+
+/// Hands the sampled writes to mirrored tables to a task that forwards them, so that the domain
+/// that the writes come from never waits for the mirror.
+///
+/// Batches are forwarded in the order they are handed over. They are dropped if too many are
+/// waiting to be forwarded, or if forwarding them fails.
+pub(super) struct Mirrorer {
+    tx: mpsc::Sender<Batch>,
+    log: slog::Logger,
+    /// How many batches have been dropped since the last one that was queued.
+    dropped: usize,
+}
+
+impl Mirrorer {
+    pub(super) fn start(log: slog::Logger) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(forward(rx, log.clone()));
+        Mirrorer {
+            tx,
+            log,
+            dropped: 0,
+        }
+    }
+
+    /// Queue writes to `table` to be forwarded to `target`, unless the queue is full.
+    pub(super) fn mirror(&mut self, table: String, target: MirrorTarget, ops: Vec<TableOperation>) {
+        match self.tx.try_send(Batch { table, target, ops }) {
+            Ok(()) => {
+                if self.dropped != 0 {
+                    warn!(self.log, "resumed mirroring writes"; "dropped" => self.dropped);
+                    self.dropped = 0;
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!(
+                        self.log,
+                        "dropping mirrored writes, since too many wait to be sent"
+                    );
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Closed(_)) => unreachable!("mirroring task went away"),
+        }
+    }
+}
+
+/// Forward the batches of writes that arrive on `rx` until the domain goes away.
+async fn forward(mut rx: mpsc::Receiver<Batch>, log: slog::Logger) {
+    let mut shadows = Shadows::default();
+    while let Some(batch) = rx.recv().await {
+        let forwarded = match batch.target {
+            MirrorTarget::Cluster { ref zookeeper } => {
+                shadows.forward(zookeeper, &batch.table, batch.ops).await
+            }
+            MirrorTarget::Sink { ref path } => append_to_sink(path, &batch.table, &batch.ops).await,
+        };
+        if let Err(e) = forwarded {
+            warn!(log, "failed to mirror writes: {}", e; "table" => &batch.table);
+        }
+    }
+}
+
+/// Connections to the tables of the shadow deployments that writes are mirrored to.
+#[derive(Default)]
+struct Shadows {
+    /// Tables by the ZooKeeper address of their deployment and their name.
+    tables: HashMap<(String, String), Table>,
+}
+
+impl Shadows {
+    /// Perform `ops` on `table` in the deployment at `zookeeper`.
+    async fn forward(
+        &mut self,
+        zookeeper: &str,
+        table: &str,
+        ops: Vec<TableOperation>,
+    ) -> Result<(), String> {
+        let key = (zookeeper.to_owned(), table.to_owned());
+        if !self.tables.contains_key(&key) {
+            let t = async {
+                let mut ch = ControllerHandle::<ZookeeperAuthority>::from_zk(zookeeper).await?;
+                ch.ready().await?;
+                ch.table(table).await
+            }
+            .await
+            .map_err(|e| format!("failed to connect to shadow of {}: {}", table, e))?;
+            self.tables.insert(key.clone(), t);
+        }
+
+        let t = self.tables.get_mut(&key).unwrap();
+        if let Err(e) = t.perform_all(ops).await {
+            // the table may have moved, so reconnect next time around
+            self.tables.remove(&key);
+            return Err(format!("failed to write to shadow of {}: {}", table, e));
+        }
+        Ok(())
+    }
+}
+
+/// Append writes to `table` to the file at `path` as a line of JSON.
+async fn append_to_sink(path: &str, table: &str, ops: &[TableOperation]) -> Result<(), String> {
+    let mut line = serde_json::to_vec(&MirroredWrites { table, ops }).map_err(|e| e.to_string())?;
+    line.push(b'\n');
+
+    let appended = async {
+        let mut f = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        f.write_all(&line).await
+    };
+    appended
+        .await
+        .map_err(|e| format!("failed to append to sink {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_drops_writes_when_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut mirrorer = Mirrorer {
+            tx,
+            log: slog::Logger::root(slog::Discard, o!()),
+            dropped: 0,
+        };
+        let target = MirrorTarget::Sink {
+            path: "mirror.json".to_owned(),
+        };
+        let ops = vec![TableOperation::Insert(vec![1.into()])];
+
+        mirrorer.mirror("t".to_owned(), target.clone(), ops.clone());
+        mirrorer.mirror("t".to_owned(), target.clone(), ops.clone());
+        assert_eq!(mirrorer.dropped, 1);
+
+        // once there is room again, writes are queued
+        assert!(rx.recv().await.is_some());
+        mirrorer.mirror("t".to_owned(), target, ops);
+        assert_eq!(mirrorer.dropped, 0);
+        assert!(rx.recv().await.is_some());
+    }
+}
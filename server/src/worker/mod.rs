@@ -1,13 +1,15 @@
 use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
 use crate::startup::Event;
+use crate::Authenticator;
 use async_bincode::AsyncBincodeWriter;
+use dataflow::metrics::Metrics;
 use dataflow::{DomainBuilder, Packet};
 use futures_util::{future::FutureExt, future::TryFutureExt, sink::SinkExt, stream::StreamExt};
 use noria::channel;
 use noria::consensus::Epoch;
 use noria::internal::DomainIndex;
-use noria::ControllerDescriptor;
+use noria::{ControllerDescriptor, Protocol};
 use replica::ReplicaAddr;
 use slog;
 use std::collections::HashMap;
@@ -18,13 +20,18 @@ use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Mutex,
 };
+use std::thread;
 use std::time::{self, Duration};
 use stream_cancel::{Trigger, Valve};
 use tokio;
 use tokio::sync::mpsc::UnboundedSender;
 
+pub(crate) mod http;
+mod mirror;
 mod readers;
 mod replica;
+#[cfg(feature = "connectors")]
+mod sinks;
 
 type ChannelCoordinator = channel::ChannelCoordinator<ReplicaAddr, Box<Packet>>;
 
@@ -49,6 +56,8 @@ pub(super) async fn main(
     waddr: SocketAddr,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    metrics: Metrics,
+    authenticator: Option<Arc<dyn Authenticator>>,
     log: slog::Logger,
 ) {
     // shared df state
@@ -126,6 +135,15 @@ pub(super) async fn main(
                     "leader's domain listen address: {:?}", descriptor.domain_addr
                 );
 
+                if !descriptor.protocol.is_compatible(&Protocol::current()) {
+                    crit!(
+                        log,
+                        "not joining leader that speaks incompatible protocol version {}",
+                        descriptor.protocol.version
+                    );
+                    continue;
+                }
+
                 // we need to make a new valve that we can use to shut down *just* the
                 // worker in the case of controller failover.
                 let (trigger, valve) = Valve::new();
@@ -143,6 +161,8 @@ pub(super) async fn main(
                     coord.clone(),
                     listen_addr,
                     rep_rx,
+                    metrics.clone(),
+                    authenticator.clone(),
                 )
                 .await;
 
@@ -182,11 +202,14 @@ async fn listen_df<'a>(
     coord: Arc<ChannelCoordinator>,
     on: IpAddr,
     mut replicas: tokio::sync::mpsc::UnboundedReceiver<DomainBuilder>,
+    metrics: Metrics,
+    authenticator: Option<Arc<dyn Authenticator>>,
 ) -> Result<(), failure::Error> {
     // first, try to connect to controller
     let ctrl = tokio::net::TcpStream::connect(&desc.worker_addr).await?;
     let ctrl_addr = ctrl.local_addr()?;
     info!(log, "connected to controller"; "src" => ?ctrl_addr);
+    let ctrl = noria::tls::Stream::client(ctrl);
 
     let log_prefix = state.config.persistence.log_prefix.clone();
     let prefix = format!("{}-log-", log_prefix);
@@ -201,14 +224,20 @@ async fn listen_df<'a>(
     // extract important things from state config
     let epoch = state.epoch;
     let heartbeat_every = state.config.heartbeat_every;
+    let reader_threads = state.config.reader_threads;
 
     let (ctrl_tx, mut ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
 
     // reader setup
     let readers = Arc::new(Mutex::new(HashMap::new()));
-    let rport = tokio::net::TcpListener::bind(&SocketAddr::new(on, 0)).await?;
+    let rport = std::net::TcpListener::bind(&SocketAddr::new(on, 0))?;
+    rport.set_nonblocking(true)?;
     let raddr = rport.local_addr()?;
     info!(log, "listening for reads"; "on" => ?raddr);
+    let load = Arc::new(readers::Load::new(
+        reader_threads,
+        state.config.max_pending_reads,
+    ));
 
     // start controller message handler
     let mut ctrl = AsyncBincodeWriter::from(ctrl).for_async();
@@ -231,13 +260,34 @@ async fn listen_df<'a>(
         }
     });
 
-    // also start readers
-    tokio::spawn(readers::listen(
+    // also start readers, on threads of their own if asked to, so that reads aren't stuck behind
+    // writes and replays
+    let listen = readers::listen(
         alive.clone(),
         valve.clone(),
         rport,
         readers.clone(),
-    ));
+        load.clone(),
+        readers::Quota {
+            lookups_per_sec: state.config.lookup_quota,
+            max_upqueries: state.config.max_client_upqueries,
+            max_rows: state.config.max_lookup_rows,
+        },
+    );
+    if let Some(threads) = reader_threads {
+        let mut rt = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(threads)
+            .thread_name("reader")
+            .enable_all()
+            .build()?;
+        // the runtime shuts down once the valve closes and the readers stop listening
+        thread::Builder::new()
+            .name("reader-listen".to_owned())
+            .spawn(move || rt.block_on(listen))?;
+    } else {
+        tokio::spawn(listen);
+    }
 
     // and tell the controller about us
     let mut timer = valve.wrap(tokio::time::interval_at(
@@ -252,6 +302,7 @@ async fn listen_df<'a>(
             addr: waddr,
             read_listen_addr: raddr,
             log_files,
+            protocol: Protocol::current(),
         });
 
         // start sending heartbeats
@@ -260,6 +311,7 @@ async fn listen_df<'a>(
                 // if we error we're probably just shutting down
                 break;
             }
+            let _ = ctx.send(CoordinationPayload::ReaderLoad(load.report()));
         }
     });
 
@@ -269,6 +321,7 @@ async fn listen_df<'a>(
         let coord = coord.clone();
         let mut domain_senders = HashMap::new();
         let state_sizes = state_sizes.clone();
+        let ctx = ctrl_tx.clone();
         let mut timer = valve.wrap(tokio::time::interval_at(
             tokio::time::Instant::now() + evict_every,
             evict_every,
@@ -283,6 +336,7 @@ async fn listen_df<'a>(
                     &mut domain_senders,
                     &coord,
                     &state_sizes,
+                    &ctx,
                 )
                 .await;
             }
@@ -310,6 +364,7 @@ async fn listen_df<'a>(
                         dcaddr,
                         &valve,
                         state_size.clone(),
+                        &metrics,
                     )
                 });
 
@@ -333,6 +388,7 @@ async fn listen_df<'a>(
                     ctrl_tx.clone(),
                     log.clone(),
                     coord.clone(),
+                    authenticator.clone(),
                 );
                 let a = alive.clone();
                 tokio::spawn(async move {
@@ -380,6 +436,7 @@ async fn do_eviction(
     >,
     coord: &ChannelCoordinator,
     state_sizes: &Arc<Mutex<HashMap<(DomainIndex, usize), Arc<AtomicUsize>>>>,
+    ctrl_tx: &tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
 ) {
     use std::cmp;
 
@@ -432,6 +489,7 @@ async fn do_eviction(
 
                 // starting with the smallest of the n domains
                 let mut n = sizes.len();
+                let mut evicted = 0;
                 for &(target, size) in sizes.iter().rev() {
                     // TODO: should this be evenly divided, or weighted by the size of the domains?
                     let share = (over + n - 1) / n;
@@ -471,8 +529,15 @@ async fn do_eviction(
                         warn!(log, "failed to evict from {}: {}", target.0.index(), e);
                         // remove sender so we don't try to use it again
                         domain_senders.remove(&target);
+                    } else {
+                        evicted += evict;
                     }
                 }
+
+                if evicted > 0 {
+                    // the controller decides whether this is worth reporting
+                    let _ = ctrl_tx.send(CoordinationPayload::Evicted { bytes: evicted });
+                }
             }
         }
     }
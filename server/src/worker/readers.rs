@@ -1,6 +1,7 @@
 use async_bincode::AsyncBincodeStream;
 use dataflow::prelude::DataType;
 use dataflow::prelude::*;
+use dataflow::RateLimiter;
 use dataflow::Readers;
 use dataflow::SingleReadHandle;
 use futures_util::{
@@ -9,11 +10,15 @@ use futures_util::{
     future::{FutureExt, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
-use noria::{ReadQuery, ReadReply, Tagged};
+use noria::tls::Stream;
+use noria::{ReadQuery, ReadReply, ReaderLoad, Tagged, TraceContext, Watermarks};
 use pin_project::pin_project;
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::HashMap;
 use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time;
 use std::{future::Future, task::Poll};
 use stream_cancel::Valve;
@@ -55,12 +60,111 @@ impl From<Vec<u8>> for SerializedReadReplyBatch {
 
 type Ack = tokio::sync::oneshot::Sender<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>>;
 
+/// The reads a worker is serving, across all of its reader connections.
+#[derive(Debug)]
+pub(super) struct Load {
+    threads: Option<usize>,
+    max_pending: Option<usize>,
+    pending: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Load {
+    pub(super) fn new(threads: Option<usize>, max_pending: Option<usize>) -> Self {
+        Load {
+            threads,
+            max_pending,
+            pending: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn report(&self) -> ReaderLoad {
+        ReaderLoad {
+            threads: self.threads,
+            pending: self.pending.load(Ordering::Relaxed),
+            max_pending: self.max_pending,
+            shed: self.shed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count a new read as pending until the returned guard is dropped, unless too many reads
+    /// are pending already.
+    fn admit(self: &Arc<Self>) -> Option<Admitted> {
+        let pending = self.pending.fetch_add(1, Ordering::AcqRel);
+        if self.max_pending.map(|max| pending >= max).unwrap_or(false) {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(Admitted(Arc::clone(self)))
+    }
+}
+
+struct Admitted(Arc<Load>);
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The limits on the reads of each client connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Quota {
+    /// How many keys a connection may look up per second.
+    pub(super) lookups_per_sec: Option<u32>,
+    /// How many missed keys a connection's blocking reads may wait on at a time.
+    pub(super) max_upqueries: Option<usize>,
+    /// How many rows a lookup returns for a single key.
+    pub(super) max_rows: Option<usize>,
+}
+
+/// The missed keys that the blocking reads of a client connection are waiting on.
+#[derive(Debug)]
+struct Upqueries {
+    in_flight: Arc<AtomicUsize>,
+    max: Option<usize>,
+}
+
+impl Upqueries {
+    /// Count `n` more missed keys as in flight until the returned guard is dropped, unless that
+    /// would take the connection beyond its maximum.
+    ///
+    /// A read that misses on more keys than the maximum is let through if the connection has
+    /// nothing else in flight, so that it does not fail forever.
+    fn admit(&self, n: usize) -> Option<InFlight> {
+        let before = self.in_flight.fetch_add(n, Ordering::AcqRel);
+        if self
+            .max
+            .map(|max| before > 0 && before + n > max)
+            .unwrap_or(false)
+        {
+            self.in_flight.fetch_sub(n, Ordering::AcqRel);
+            return None;
+        }
+        Some(InFlight(Arc::clone(&self.in_flight), n))
+    }
+}
+
+struct InFlight(Arc<AtomicUsize>, usize);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(self.1, Ordering::AcqRel);
+    }
+}
+
 pub(super) async fn listen(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
-    mut on: tokio::net::TcpListener,
+    on: std::net::TcpListener,
     readers: Readers,
+    load: Arc<Load>,
+    quota: Quota,
 ) {
+    // the listener is registered with whichever runtime serves the reads
+    let mut on = tokio::net::TcpListener::from_std(on).expect("could not register read listener");
     let mut stream = valve.wrap(on.incoming()).into_stream();
     while let Some(stream) = stream.next().await {
         if let Err(_) = stream {
@@ -70,6 +174,13 @@ pub(super) async fn listen(
 
         let stream = stream.unwrap();
         let readers = readers.clone();
+        let load = load.clone();
+        let mut lookups = quota.lookups_per_sec.map(RateLimiter::new);
+        let upqueries = Upqueries {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max: quota.max_upqueries,
+        };
+        let max_rows = quota.max_rows;
         stream.set_nodelay(true).expect("could not set TCP_NODELAY");
         let alive = alive.clone();
 
@@ -118,8 +229,43 @@ pub(super) async fn listen(
         let server = READERS.scope(
             Default::default(),
             server::Server::new(
-                AsyncBincodeStream::from(stream).for_async(),
-                service_fn(move |req| handle_message(req, &readers, &mut tx)),
+                AsyncBincodeStream::from(Stream::server(stream)).for_async(),
+                service_fn(move |req: Tagged<ReadQuery>| {
+                    let keys = match req.v {
+                        ReadQuery::Normal { ref keys, .. } => keys.len(),
+                        ReadQuery::Watermarked { .. } | ReadQuery::Chunk { .. } => 1,
+                        _ => 0,
+                    };
+                    if let Some(ref mut lookups) = lookups {
+                        if keys > 0 && !lookups.try_take(keys) {
+                            return Either::Left(future::ready(Ok(Tagged {
+                                tag: req.tag,
+                                v: ReadReply::QuotaExceeded,
+                            })));
+                        }
+                    }
+
+                    // only lookups are counted, since they are the reads that can pile up
+                    let admitted = match req.v {
+                        ReadQuery::Normal { .. }
+                        | ReadQuery::Watermarked { .. }
+                        | ReadQuery::Chunk { .. } => match load.admit() {
+                            Some(admitted) => Some(admitted),
+                            None => {
+                                return Either::Left(future::ready(Ok(Tagged {
+                                    tag: req.tag,
+                                    v: ReadReply::Overloaded,
+                                })));
+                            }
+                        },
+                        _ => None,
+                    };
+                    let reply = handle_message(req, &readers, &mut tx, &upqueries, max_rows);
+                    Either::Right(reply.map(move |r| {
+                        drop(admitted);
+                        r
+                    }))
+                }),
             ),
         );
         tokio::spawn(
@@ -176,10 +322,76 @@ where
     SerializedReadReplyBatch(v)
 }
 
+/// Look up `key` in `reader`, and serialize the records found.
+///
+/// A range reader returns the records of all the keys in the range it derives from `key`, and an
+/// ordered reader returns the records in its order, up to its limit.
+fn find(
+    reader: &SingleReadHandle,
+    key: &[DataType],
+) -> Result<(Option<SerializedReadReplyBatch>, Watermarks), ()> {
+    find_window(reader, key, 0, None).map(|(rs, watermarks)| (rs.map(|(rs, _)| rs), watermarks))
+}
+
+/// Like `find`, but only serializes the records from the `skip`th on, and at most `max` of them.
+///
+/// Along with the records comes whether any were left out after them.
+fn find_window(
+    reader: &SingleReadHandle,
+    key: &[DataType],
+    skip: usize,
+    max: Option<usize>,
+) -> Result<(Option<(SerializedReadReplyBatch, bool)>, Watermarks), ()> {
+    let window = |n: usize| {
+        let end = max.map_or(n, |max| cmp::min(n, skip.saturating_add(max)));
+        (cmp::min(skip, end), end)
+    };
+    if reader.is_range() {
+        reader
+            .try_find_range_and(key, |rs| rs.iter().cloned().collect::<Vec<_>>())
+            .map(|(rs, watermarks)| {
+                let rs: Vec<_> = rs.into_iter().flatten().collect();
+                let (start, end) = window(rs.len());
+                (
+                    Some((serialize(&rs[start..end]), end < rs.len())),
+                    watermarks,
+                )
+            })
+    } else if reader.is_ordered() {
+        reader.try_find_and(key, |rs| {
+            let mut rs: Vec<_> = rs.iter().cloned().collect();
+            reader.order(&mut rs);
+            let (start, end) = window(rs.len());
+            (serialize(&rs[start..end]), end < rs.len())
+        })
+    } else {
+        reader.try_find_and(key, |rs| {
+            let (start, end) = window(rs.len());
+            let batch = serialize(rs.iter().skip(start).take(end - start));
+            (batch, end < rs.len())
+        })
+    }
+}
+
+/// The reply to a lookup that read `rows`, the records of the keys at `truncated` among them
+/// having been cut off at the row cap.
+fn lookup_reply(
+    rows: Vec<SerializedReadReplyBatch>,
+    truncated: Vec<usize>,
+) -> ReadReply<SerializedReadReplyBatch> {
+    if truncated.is_empty() {
+        ReadReply::Normal(Ok(rows))
+    } else {
+        ReadReply::Truncated(rows, truncated)
+    }
+}
+
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
     wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
+    upqueries: &Upqueries,
+    max_rows: Option<usize>,
 ) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
     let tag = m.tag;
     match m.v {
@@ -187,6 +399,7 @@ fn handle_message(
             target,
             mut keys,
             block,
+            trace,
         } => {
             let immediate = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
@@ -196,6 +409,7 @@ fn handle_message(
                 });
 
                 let mut ret = Vec::with_capacity(keys.len());
+                let mut truncated = Vec::new();
 
                 // first do non-blocking reads for all keys to see if we can return immediately
                 let mut i = -1;
@@ -207,10 +421,13 @@ fn handle_message(
                         ret.push(SerializedReadReplyBatch::empty());
                         return false;
                     }
-                    let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
+                    let rs = find_window(reader, key, 0, max_rows).map(|r| r.0);
                     match rs {
-                        Ok(Some(rs)) => {
+                        Ok(Some((rs, cut))) => {
                             // immediate hit!
+                            if cut {
+                                truncated.push(ret.len());
+                            }
                             ret.push(rs);
                             false
                         }
@@ -235,29 +452,41 @@ fn handle_message(
                         v: ReadReply::Normal(Err(())),
                     });
                 }
+                reader.looked_up(ret.len() - keys.len(), keys.len());
 
                 if keys.is_empty() {
                     // we hit on all the keys!
                     assert!(pending.is_empty());
                     return Ok(Tagged {
                         tag,
-                        v: ReadReply::Normal(Ok(ret)),
+                        v: lookup_reply(ret, truncated),
                     });
                 }
 
+                let upquerying = match upqueries.admit(keys.len()) {
+                    Some(upquerying) => upquerying,
+                    None => {
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::QuotaExceeded,
+                        });
+                    }
+                };
+
                 // trigger backfills for all the keys we missed on
-                reader.trigger(keys.iter().map(Vec::as_slice));
+                reader.trigger(keys.iter().map(Vec::as_slice), trace);
 
-                Err((keys, ret, pending))
+                Err((keys, ret, truncated, pending, upquerying))
             });
 
             match immediate {
                 Ok(reply) => Either::Left(Either::Left(future::ready(Ok(reply)))),
-                Err((keys, ret, pending)) => {
+                Err((keys, ret, truncated, pending, upquerying)) => {
                     if !block {
+                        // a read that does not wait cannot tell when its backfills are done
                         Either::Left(Either::Left(future::ready(Ok(Tagged {
                             tag,
-                            v: ReadReply::Normal(Ok(ret)),
+                            v: lookup_reply(ret, truncated),
                         }))))
                     } else {
                         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -270,10 +499,14 @@ fn handle_message(
                                 keys,
                                 pending,
                                 read: ret,
+                                truncated,
+                                max_rows,
                                 truth: s.clone(),
+                                trace,
                                 trigger_timeout: trigger,
                                 next_trigger: now,
                                 first: now,
+                                _upquerying: upquerying,
                             },
                             tx,
                         ));
@@ -305,6 +538,136 @@ fn handle_message(
                 v: ReadReply::Size(size),
             })))
         }
+        ReadQuery::Watermarked { target, key } => {
+            let reply = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                match find(reader, &key) {
+                    Ok((Some(rs), watermarks)) => Ok(Some((rs, watermarks))),
+                    Ok((None, _)) => {
+                        // the client fills the hole with a blocking read and then retries
+                        reader.trigger(std::iter::once(&key[..]), None);
+                        Ok(None)
+                    }
+                    Err(()) => Err(()),
+                }
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Watermarked(reply),
+            })))
+        }
+        ReadQuery::EventTime { target } => {
+            let low_watermark = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.low_watermark()
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::EventTime(low_watermark),
+            })))
+        }
+        ReadQuery::WriteFrontier { target } => {
+            let frontier = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.write_frontier()
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::WriteFrontier(frontier),
+            })))
+        }
+        ReadQuery::Subscribe { target, keys } => {
+            let id = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                let mut missing = Vec::new();
+                for key in &keys {
+                    match find(reader, key) {
+                        Ok((Some(_), _)) => {}
+                        Ok((None, _)) => missing.push(&key[..]),
+                        Err(()) => return Err(()),
+                    }
+                }
+                if !missing.is_empty() {
+                    // changes are only seen for keys whose records reach the reader
+                    reader.trigger(missing.into_iter(), None);
+                }
+                Ok(reader.subscribe(keys))
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Subscribed(id),
+            })))
+        }
+        ReadQuery::Chunk {
+            target,
+            key,
+            skip,
+            rows,
+        } => {
+            let reply = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                let rows = max_rows.map_or(rows, |max| cmp::min(rows, max));
+                match find_window(reader, &key, skip, Some(rows)) {
+                    Ok((Some(chunk), _)) => Ok(Some(chunk)),
+                    Ok((None, _)) => {
+                        // the client asks again until the hole has been filled
+                        reader.trigger(std::iter::once(&key[..]), None);
+                        Ok(None)
+                    }
+                    Err(()) => Err(()),
+                }
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Chunk(reply),
+            })))
+        }
+        ReadQuery::Changes { target, id } => {
+            let changes = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.changes(id)
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Changes(changes),
+            })))
+        }
     }
 }
 
@@ -314,15 +677,22 @@ struct BlockingRead {
     target: (NodeIndex, usize),
     // serialized records for keys we have already read
     read: Vec<SerializedReadReplyBatch>,
+    // index in self.read of the keys whose records were cut off at max_rows
+    truncated: Vec<usize>,
+    max_rows: Option<usize>,
     // keys we have yet to read
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
     pending: Vec<usize>,
     truth: Readers,
+    // the trace that the backfills of the keys are part of
+    trace: Option<TraceContext>,
 
     trigger_timeout: time::Duration,
     next_trigger: time::Instant,
     first: time::Instant,
+    // keeps the missed keys counted against the connection's upqueries until the read is done
+    _upquerying: InFlight,
 }
 
 impl std::fmt::Debug for BlockingRead {
@@ -331,8 +701,10 @@ impl std::fmt::Debug for BlockingRead {
             .field("tag", &self.tag)
             .field("target", &self.target)
             .field("read", &self.read)
+            .field("truncated", &self.truncated)
             .field("keys", &self.keys)
             .field("pending", &self.pending)
+            .field("trace", &self.trace)
             .field("trigger_timeout", &self.trigger_timeout)
             .field("next_trigger", &self.next_trigger)
             .field("first", &self.first)
@@ -353,6 +725,7 @@ impl BlockingRead {
 
             let now = time::Instant::now();
             let read = &mut self.read;
+            let truncated = &mut self.truncated;
             let next_trigger = self.next_trigger;
 
             // here's the trick we're going to play:
@@ -364,8 +737,11 @@ impl BlockingRead {
 
             while let Some(read_i) = self.pending.pop() {
                 let key = self.keys.pop().expect("pending.len() == keys.len()");
-                match reader.try_find_and(&key, |rs| serialize(rs)).map(|r| r.0) {
-                    Ok(Some(rs)) => {
+                match find_window(reader, &key, 0, self.max_rows).map(|r| r.0) {
+                    Ok(Some((rs, cut))) => {
+                        if cut {
+                            truncated.push(read_i);
+                        }
                         read[read_i] = rs;
                     }
                     Err(()) => {
@@ -386,7 +762,7 @@ impl BlockingRead {
 
             if !self.keys.is_empty() && now > next_trigger {
                 // maybe the key got filled, then evicted, and we missed it?
-                if !reader.trigger(self.keys.iter().map(Vec::as_slice)) {
+                if !reader.trigger(self.keys.iter().map(Vec::as_slice), self.trace) {
                     // server is shutting down and won't do the backfill
                     return Err(());
                 }
@@ -412,7 +788,7 @@ impl BlockingRead {
         if self.keys.is_empty() {
             Poll::Ready(Ok(Tagged {
                 tag: self.tag,
-                v: ReadReply::Normal(Ok(mem::take(&mut self.read))),
+                v: lookup_reply(mem::take(&mut self.read), mem::take(&mut self.truncated)),
             }))
         } else {
             Poll::Pending
@@ -420,6 +796,57 @@ impl BlockingRead {
     }
 }
 
+#[cfg(test)]
+mod load {
+    use super::{Load, Upqueries};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn sheds_beyond_max_pending() {
+        let load = Arc::new(Load::new(Some(2), Some(2)));
+        let a = load.admit().unwrap();
+        let b = load.admit().unwrap();
+        assert!(load.admit().is_none());
+        assert_eq!(load.report().pending, 2);
+        assert_eq!(load.report().shed, 1);
+
+        drop(a);
+        let c = load.admit().unwrap();
+        assert!(load.admit().is_none());
+        drop((b, c));
+        let report = load.report();
+        assert_eq!((report.pending, report.shed), (0, 2));
+    }
+
+    #[test]
+    fn unlimited_never_sheds() {
+        let load = Arc::new(Load::new(None, None));
+        let admitted: Vec<_> = (0..100).map(|_| load.admit().unwrap()).collect();
+        assert_eq!(load.report().pending, 100);
+        drop(admitted);
+        assert_eq!(load.report().pending, 0);
+    }
+
+    #[test]
+    fn caps_upqueries_per_connection() {
+        let upqueries = Upqueries {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max: Some(4),
+        };
+        // a read that misses on more keys than the maximum goes through on its own
+        let a = upqueries.admit(5).unwrap();
+        assert!(upqueries.admit(1).is_none());
+        drop(a);
+
+        let b = upqueries.admit(3).unwrap();
+        let c = upqueries.admit(1).unwrap();
+        assert!(upqueries.admit(1).is_none());
+        drop((b, c));
+        assert_eq!(upqueries.in_flight.load(Ordering::Acquire), 0);
+    }
+}
+
 #[cfg(test)]
 mod readreply {
     use super::SerializedReadReplyBatch;
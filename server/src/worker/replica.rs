@@ -3,11 +3,13 @@ const FORCE_INPUT_YIELD_EVERY: usize = 32;
 
 use super::ChannelCoordinator;
 use crate::coordination::CoordinationPayload;
+use crate::Authenticator;
 use ahash::{AHashMap, AHashSet};
 use async_bincode::AsyncDestination;
 use async_timer::Oneshot;
 use bincode;
 use dataflow::{
+    ops::trigger::TriggerFiring,
     payload::SourceChannelIdentifier,
     prelude::{DataType, Executor},
     Domain, Packet, PollEvent, ProcessResult,
@@ -18,13 +20,20 @@ use futures_util::{
     stream::{futures_unordered::FuturesUnordered, Stream},
 };
 use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE};
+use noria::error::WriteRejection;
 use noria::internal::DomainIndex;
 use noria::internal::LocalOrNot;
-use noria::{Input, Tagged};
+use noria::tls::Stream;
+#[cfg(feature = "connectors")]
+use noria::TriggerAction;
+use noria::{
+    DeadLetter, Input, MirrorTarget, TableOperation, Tagged, Violation, WriteAck, WriteReply,
+};
 use pin_project::pin_project;
 use slog;
 use std::collections::{HashMap, VecDeque};
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::time;
 use std::{
@@ -40,15 +49,32 @@ use tokio::io::{AsyncReadExt, BufReader, BufStream, BufWriter};
 pub(super) type ReplicaAddr = (DomainIndex, usize);
 
 // https://github.com/rust-lang/rust/issues/64445
-type FirstByte = impl Future<Output = Result<(tokio::net::TcpStream, u8), tokio::io::Error>> + Send;
+type Preamble = impl Future<Output = Result<(Stream, u8, Option<String>), tokio::io::Error>> + Send;
 
-/// Read the first byte of a stream.
-fn read_first_byte(mut stream: tokio::net::TcpStream) -> FirstByte {
+/// Read the preamble of a stream: the byte that tags it, and the credentials that connections
+/// from bases present.
+fn read_preamble(mut stream: Stream) -> Preamble {
     async move {
         let mut byte = [0; 1];
         let n = stream.read_exact(&mut byte[..]).await?;
         assert_eq!(n, 1);
-        Ok((stream, byte[0]))
+        if byte[0] != CONNECTION_FROM_BASE {
+            return Ok((stream, byte[0], None));
+        }
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len[..]).await?;
+        let mut credentials = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut credentials[..]).await?;
+        let credentials = if credentials.is_empty() {
+            None
+        } else {
+            Some(
+                String::from_utf8(credentials)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.utf8_error()))?,
+            )
+        };
+        Ok((stream, byte[0], credentials))
     }
 }
 
@@ -71,18 +97,17 @@ pub(super) struct Replica {
     incoming: Strawpoll<tokio::net::TcpListener>,
 
     #[pin]
-    first_byte: FuturesUnordered<FirstByte>,
+    preambles: FuturesUnordered<Preamble>,
+
+    /// Decides who the writes that arrive over connections from bases are issued by, if clients
+    /// have to authenticate.
+    authenticator: Option<Arc<dyn Authenticator>>,
 
     locals: tokio::sync::mpsc::UnboundedReceiver<Box<Packet>>,
 
     #[pin]
     inputs: StreamUnordered<
-        DualTcpStream<
-            BufStream<tokio::net::TcpStream>,
-            Box<Packet>,
-            Tagged<LocalOrNot<Input>>,
-            AsyncDestination,
-        >,
+        DualTcpStream<BufStream<Stream>, Box<Packet>, Tagged<LocalOrNot<Input>>, AsyncDestination>,
     >,
 
     outputs: AHashMap<
@@ -97,6 +122,13 @@ pub(super) struct Replica {
     timeout: Strawpoll<async_timer::oneshot::Timer>,
     timed_out: bool,
 
+    /// When the domain stopped accepting input because too many packets were waiting to be sent
+    /// to other domains, if it has.
+    stalled_since: Option<time::Instant>,
+
+    /// Set once the domain has panicked, after which incoming packets are dropped.
+    failed: bool,
+
     out: Outboxes,
 }
 
@@ -109,9 +141,11 @@ impl Replica {
         ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
         log: slog::Logger,
         cc: Arc<ChannelCoordinator>,
+        authenticator: Option<Arc<dyn Authenticator>>,
     ) -> Self {
         let id = domain.id();
         let id = format!("{}.{}", id.0.index(), id.1);
+        let log = log.new(o! {"id" => id});
         domain.booted(on.local_addr().unwrap());
         Replica {
             coord: cc,
@@ -119,17 +153,20 @@ impl Replica {
             retry: None,
             valve: valve.clone(),
             incoming: Strawpoll::from(on),
-            first_byte: FuturesUnordered::new(),
+            preambles: FuturesUnordered::new(),
+            authenticator,
             locals,
-            log: log.new(o! {"id" => id}),
+            out: Outboxes::new(ctrl_tx, &log),
+            log,
             inputs: Default::default(),
             outputs: Default::default(),
-            out: Outboxes::new(ctrl_tx),
             timeout: Strawpoll::from(async_timer::oneshot::Timer::new(time::Duration::from_secs(
                 3600,
             ))),
             refresh_sizes: tokio::time::interval(time::Duration::from_millis(500)),
             timed_out: false,
+            stalled_since: None,
+            failed: false,
         }
     }
 
@@ -147,7 +184,7 @@ impl Replica {
             let mut stream = Pin::new(&mut inputs[streami]);
             let mut sent = 0;
 
-            for &tag in &conn.tag_acks {
+            for &(tag, ref reply) in &conn.tag_acks {
                 match stream.as_mut().poll_ready(cx) {
                     Poll::Ready(Ok(())) => {}
                     Poll::Pending => break,
@@ -157,7 +194,10 @@ impl Replica {
                     }
                 }
 
-                if let Err(e) = stream.as_mut().start_send(Tagged { tag, v: () }) {
+                if let Err(e) = stream.as_mut().start_send(Tagged {
+                    tag,
+                    v: reply.clone(),
+                }) {
                     // start_send shouldn't generally error
                     err.push(e.into());
                     break;
@@ -261,6 +301,7 @@ impl Replica {
 
         let cc = this.coord;
         let outputs = this.outputs;
+        let codec = this.domain.packet_compression();
 
         // just like in try_acks:
         // first, queue up any additional writes we have to do
@@ -277,6 +318,9 @@ impl Replica {
             });
 
             let mut tx = Pin::new(tx);
+            // packets to domains on this worker are never serialized, so only those that go over
+            // the network are worth compressing
+            let codec = codec.filter(|_| cc.is_local(&ri) != Some(true));
 
             while !ms.is_empty() {
                 match tx.as_mut().poll_ready(cx) {
@@ -289,6 +333,13 @@ impl Replica {
                 }
 
                 let m = ms.pop_front().expect("!is_empty");
+                if flows_down(&m) {
+                    this.out.queued -= 1;
+                }
+                let m = match codec {
+                    Some(codec) => m.compress(codec),
+                    None => m,
+                };
                 match tx.as_mut().start_send(m) {
                     Ok(()) => {
                         // we queued something, so we'll need to send!
@@ -341,15 +392,21 @@ impl Replica {
                 .map_err(|e| e.context("poll_accept"))?
             {
                 // we know that any new connection to a domain will first send a one-byte
-                // token to indicate whether the connection is from a base or not.
+                // token to indicate whether the connection is from a base or not, and that
+                // connections from bases then send the credentials of their writer.
                 debug!(this.log, "accepted new connection"; "from" => ?stream.peer_addr().unwrap());
-                this.first_byte.push(read_first_byte(stream));
+                if let Err(e) = stream.set_nodelay(true) {
+                    warn!(this.log,
+                          "failed to set TCP_NODELAY for new connection: {:?}", e;
+                          "from" => ?stream.peer_addr().unwrap());
+                }
+                this.preambles.push(read_preamble(Stream::server(stream)));
             }
         }
 
-        while let Poll::Ready(Some(r)) = this.first_byte.as_mut().poll_next(cx) {
-            let (stream, tag) = match r {
-                Ok((s, t)) => (s, t),
+        while let Poll::Ready(Some(r)) = this.preambles.as_mut().poll_next(cx) {
+            let (stream, tag, credentials) = match r {
+                Ok(p) => p,
                 Err(e) => {
                     if let io::ErrorKind::BrokenPipe
                     | io::ErrorKind::NotConnected
@@ -361,12 +418,33 @@ impl Replica {
                         // let's not bother the user with it
                         continue;
                     }
+                    if let io::ErrorKind::InvalidData = e.kind() {
+                        // the TLS handshake failed, or the credentials were garbled, which is the
+                        // peer's problem and not ours
+                        warn!(this.log, "rejected connection: {}", e);
+                        continue;
+                    }
                     Err(e).context("poll_next")?;
                     unreachable!();
                 }
             };
             let is_base = tag == CONNECTION_FROM_BASE;
 
+            // writes are issued by whoever the connection authenticated as, and not by whoever
+            // they claim to be issued by
+            let writer = match *this.authenticator {
+                Some(ref authenticator) if is_base => {
+                    match authenticator.authenticate(credentials.as_ref().map(String::as_str)) {
+                        Ok(writer) => writer,
+                        Err(reason) => {
+                            warn!(this.log, "rejected connection from base"; "reason" => reason);
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
+
             debug!(this.log, "established new connection"; "base" => ?is_base);
             let slot = this.inputs.stream_entry();
             let token = slot.token();
@@ -383,15 +461,12 @@ impl Replica {
                 assert_eq!(t, token);
                 epoch
             };
-            if let Err(e) = stream.set_nodelay(true) {
-                warn!(this.log,
-                      "failed to set TCP_NODELAY for new connection: {:?}", e;
-                      "from" => ?stream.peer_addr().unwrap());
-            }
             let tcp = if is_base {
                 DualTcpStream::upgrade(
                     tokio::io::BufStream::new(stream),
-                    move |Tagged { v: input, tag }| {
+                    move |Tagged { v: mut input, tag }| {
+                        // the input is only local if it came from this process
+                        unsafe { input.deref_mut() }.writer = writer.clone();
                         Box::new(Packet::Input {
                             inner: input,
                             src: Some(SourceChannelIdentifier { token, tag, epoch }),
@@ -412,7 +487,7 @@ impl Replica {
     }
 
     // returns true if on_event(Timeout) was called
-    fn try_timeout(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+    fn try_timeout(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Result<bool, failure::Error> {
         let mut processed = false;
         let mut this = self.project();
 
@@ -424,11 +499,11 @@ impl Replica {
 
         if *this.timed_out {
             *this.timed_out = false;
-            this.domain.on_event(this.out, PollEvent::Timeout);
+            isolate(this.domain, this.out, PollEvent::Timeout)?;
             processed = true;
         }
 
-        processed
+        Ok(processed)
     }
 }
 
@@ -436,8 +511,8 @@ struct ConnState {
     // number of unacked inputs
     unacked: usize,
 
-    // unsent acks (value is the tag)
-    tag_acks: Vec<u32>,
+    // unsent acks (tag and reply)
+    tag_acks: Vec<(u32, WriteReply)>,
 
     // epoch counter for each stream index (since they're re-used)
     epoch: usize,
@@ -453,6 +528,9 @@ struct Outboxes {
     // messages for other domains
     domains: AHashMap<ReplicaAddr, VecDeque<Box<Packet>>>,
 
+    // how many of the messages for other domains flow down the data-flow
+    queued: usize,
+
     // connection state for each stream
     connections: slab::Slab<ConnState>,
 
@@ -461,10 +539,20 @@ struct Outboxes {
 
     // for sending messages to the controller
     ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+
+    /// Publishes the changes to views with Kafka and Redis triggers, once there are any.
+    #[cfg(feature = "connectors")]
+    publisher: Option<super::sinks::Publisher>,
+    /// Forwards the sampled writes to mirrored tables, once there are any.
+    mirrorer: Option<super::mirror::Mirrorer>,
+    log: slog::Logger,
 }
 
 impl Outboxes {
-    fn new(ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>) -> Self {
+    fn new(
+        ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+        log: &slog::Logger,
+    ) -> Self {
         let mut connections = slab::Slab::new();
 
         // index 0 is reserved
@@ -477,9 +565,14 @@ impl Outboxes {
 
         Outboxes {
             domains: Default::default(),
+            queued: 0,
             connections,
             pending: Default::default(),
             ctrl_tx,
+            #[cfg(feature = "connectors")]
+            publisher: None,
+            mirrorer: None,
+            log: log.clone(),
             dirty: false,
         }
     }
@@ -491,6 +584,12 @@ impl Outboxes {
         }
     }
 
+    /// Whether so many packets are waiting to be sent to other domains that no more input should
+    /// be accepted.
+    fn is_full(&self, max: Option<usize>) -> bool {
+        max.map_or(false, |max| self.queued >= max)
+    }
+
     fn try_retire(&mut self, streami: usize) -> bool {
         let mut c = &mut self.connections[streami];
         if c.unacked == 0 && c.tag_acks.is_empty() && !c.pending_flush {
@@ -509,14 +608,14 @@ impl Outboxes {
     }
 }
 
-impl Executor for Outboxes {
-    fn ack(&mut self, id: SourceChannelIdentifier) {
+impl Outboxes {
+    fn reply(&mut self, id: SourceChannelIdentifier, reply: WriteReply) {
         self.dirty = true;
         let mut c = &mut self.connections[id.token];
         if id.epoch == c.epoch {
             // if the epoch doesn't match, the stream was closed and a new one has been established
             // note that this only matters for connections that do not wait for all acks!
-            c.tag_acks.push(id.tag);
+            c.tag_acks.push((id.tag, reply));
 
             // NOTE: it's a little sad we can't crash on underflow here.
             // it is because if a send fails, we set c.unacked = 0, and should the domain _then_
@@ -527,6 +626,16 @@ impl Executor for Outboxes {
             self.pending.insert(id.token);
         }
     }
+}
+
+impl Executor for Outboxes {
+    fn ack(&mut self, id: SourceChannelIdentifier, ack: WriteAck) {
+        self.reply(id, Ok(ack));
+    }
+
+    fn reject(&mut self, id: SourceChannelIdentifier, reason: WriteRejection) {
+        self.reply(id, Err(reason));
+    }
 
     fn create_universe(&mut self, universe: HashMap<String, DataType>) {
         self.ctrl_tx
@@ -534,15 +643,108 @@ impl Executor for Outboxes {
             .expect("asked to send to controller, but controller has gone away");
     }
 
+    fn fire_trigger(&mut self, firing: TriggerFiring) {
+        // changes are published by the worker, rather than by the controller, which would have to
+        // receive every change
+        #[cfg(feature = "connectors")]
+        {
+            if let TriggerAction::Kafka { .. } | TriggerAction::Redis { .. } = firing.action {
+                let log = &self.log;
+                self.publisher
+                    .get_or_insert_with(|| super::sinks::Publisher::start(log.clone()))
+                    .publish(firing);
+                return;
+            }
+        }
+        self.ctrl_tx
+            .send(CoordinationPayload::FireTrigger(firing))
+            .expect("asked to send to controller, but controller has gone away");
+    }
+
+    fn mirror(&mut self, table: String, target: MirrorTarget, ops: Vec<TableOperation>) {
+        let log = &self.log;
+        self.mirrorer
+            .get_or_insert_with(|| super::mirror::Mirrorer::start(log.clone()))
+            .mirror(table, target, ops);
+    }
+
+    fn dead_letters(&mut self, table: String, letters: Vec<DeadLetter>) {
+        self.ctrl_tx
+            .send(CoordinationPayload::DeadLetters { table, letters })
+            .expect("asked to send to controller, but controller has gone away");
+    }
+
+    fn quarantine(&mut self, view: String, violations: Vec<Violation>) {
+        self.ctrl_tx
+            .send(CoordinationPayload::Quarantine { view, violations })
+            .expect("asked to send to controller, but controller has gone away");
+    }
+
     fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
         self.dirty = true;
+        if flows_down(&m) {
+            self.queued += 1;
+        }
         self.domains.entry(dest).or_default().push_back(m);
     }
 }
 
-impl Future for Replica {
-    type Output = Result<(), failure::Error>;
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+/// Whether `m` flows down the data-flow, rather than up it like requests for replays do.
+///
+/// Only packets that flow down count towards the packets a domain may queue before it stops
+/// accepting input. Since the data-flow has no cycles, domains that stop accepting input then
+/// never wait for each other in a cycle.
+fn flows_down(m: &Packet) -> bool {
+    !matches!(
+        *m,
+        Packet::RequestPartialReplay { .. } | Packet::RequestReaderReplay { .. }
+    )
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "domain panicked: {}", _0)]
+struct DomainPanicked(String);
+
+/// Hand `event` to `domain`, and report the domain as failed to the controller if it panics.
+///
+/// A panic may leave the domain's state inconsistent, so a domain that panicked must not process
+/// anything else; the controller recovers its nodes instead. Catching the panic here keeps it from
+/// taking down the other domains on this worker.
+fn isolate(
+    domain: &mut Domain,
+    out: &mut Outboxes,
+    event: PollEvent,
+) -> Result<ProcessResult, failure::Error> {
+    let packet = match event {
+        PollEvent::Process(ref p) => Some(p.summary()),
+        _ => None,
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| domain.on_event(out, event))) {
+        Ok(r) => Ok(r),
+        Err(cause) => {
+            let reason = if let Some(s) = cause.downcast_ref::<&str>() {
+                (*s).to_owned()
+            } else if let Some(s) = cause.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                String::from("unknown panic")
+            };
+            let (domain, shard) = domain.id();
+            out.ctrl_tx
+                .send(CoordinationPayload::DomainFailed {
+                    domain,
+                    shard,
+                    packet: packet.map(|p| p.to_string()),
+                    reason: reason.clone(),
+                })
+                .expect("asked to send to controller, but controller has gone away");
+            Err(DomainPanicked(reason).into())
+        }
+    }
+}
+
+impl Replica {
+    fn run(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), failure::Error>> {
         'process: loop {
             // are there any new connections?
             if !self
@@ -555,7 +757,7 @@ impl Future for Replica {
             }
 
             // have any of our timers expired?
-            self.as_mut().try_timeout(cx);
+            self.as_mut().try_timeout(cx)?;
 
             // we have three logical input sources: receives from local domains, receives from
             // remote domains, and remote mutators. we want to achieve some kind of fairness among
@@ -582,8 +784,24 @@ impl Future for Replica {
             let d = this.domain;
             let out = this.out;
 
+            // don't take on more work while too many packets are waiting to go to other domains,
+            // so that the domains feeding this one, and eventually writers, have to wait too.
+            let mut stalled = time::Duration::from_secs(0);
+            if out.is_full(d.max_queued_packets()) {
+                local_done = true;
+                remote_done = true;
+                if this.stalled_since.is_none() {
+                    debug!(this.log, "too many queued packets, not accepting input";
+                           "queued" => out.queued);
+                    *this.stalled_since = Some(time::Instant::now());
+                }
+            } else if let Some(since) = this.stalled_since.take() {
+                stalled = since.elapsed();
+            }
+
             if let Poll::Ready(Some(_)) = this.refresh_sizes.poll_next(cx) {
                 // TODO: keep the state size up-to-date continuously?
+                d.enforce_eviction();
                 d.update_state_sizes();
             }
 
@@ -600,7 +818,7 @@ impl Future for Replica {
                         {
                             $outbox.saw_input(token, epoch);
                         }
-                        $pp(packet)
+                        $pp(packet)?
                     } {
                         // domain got a message to quit
                         // TODO: should we finish up remaining work?
@@ -611,16 +829,22 @@ impl Future for Replica {
 
             if let Some(p) = this.retry.take() {
                 // first try the thing we failed to process last time again
-                process!(*this.retry, out, p, |p| d
-                    .on_event(out, PollEvent::Process(p),));
+                process!(*this.retry, out, p, |p| isolate(
+                    d,
+                    out,
+                    PollEvent::Process(p)
+                ));
             }
 
             for _ in 0..FORCE_INPUT_YIELD_EVERY {
                 if !local_done && (check_local || remote_done) {
                     match this.locals.poll_recv(cx) {
                         Poll::Ready(Some(packet)) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                            process!(*this.retry, out, packet, |p| isolate(
+                                d,
+                                out,
+                                PollEvent::Process(p)
+                            ));
                         }
                         Poll::Ready(None) => {
                             // local input stream finished
@@ -637,8 +861,12 @@ impl Future for Replica {
                 if !remote_done && (!check_local || local_done) {
                     match this.inputs.as_mut().poll_next(cx) {
                         Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                            let packet = packet.decompress().context("decompress packet")?;
+                            process!(*this.retry, out, packet, |p| isolate(
+                                d,
+                                out,
+                                PollEvent::Process(p)
+                            ));
                         }
                         Poll::Ready(Some((StreamYield::Finished(f), streami))) => {
                             if out.try_retire(streami) {
@@ -684,6 +912,16 @@ impl Future for Replica {
             self.as_mut()
                 .try_flush(cx)
                 .context("downstream flush (after)")?;
+            {
+                let this = self.as_mut().project();
+                this.domain.record_backlog(this.out.queued, stalled);
+                if this.stalled_since.is_some()
+                    && !this.out.is_full(this.domain.max_queued_packets())
+                {
+                    // enough was sent to accept input again
+                    cx.waker().wake_by_ref();
+                }
+            }
 
             // send acks
             self.as_mut().try_acks(cx)?;
@@ -698,7 +936,7 @@ impl Future for Replica {
             self.out.dirty = false;
             loop {
                 let mut this = self.as_mut().project();
-                match this.domain.on_event(this.out, PollEvent::ResumePolling) {
+                match isolate(this.domain, this.out, PollEvent::ResumePolling)? {
                     ProcessResult::KeepPolling(timeout) => {
                         if let Some(timeout) = timeout {
                             if timeout == time::Duration::new(0, 0) {
@@ -708,7 +946,7 @@ impl Future for Replica {
                             }
 
                             // we need to poll the timer to ensure we'll get woken up
-                            if self.as_mut().try_timeout(cx) {
+                            if self.as_mut().try_timeout(cx)? {
                                 // a timeout occurred, so we may have to set a new timer
                                 if self.out.dirty {
                                     // if we're already dirty, we'll re-do processing anyway
@@ -736,4 +974,54 @@ impl Future for Replica {
             break Poll::Pending;
         }
     }
+
+    /// Drop everything that is sent to this replica after its domain has failed.
+    ///
+    /// We keep accepting packets until the controller has removed the failed domain's nodes, since
+    /// the domains that send to them would otherwise fail too.
+    fn discard(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), failure::Error>> {
+        if !self
+            .as_mut()
+            .try_new(cx)
+            .context("check for new connections")?
+        {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut this = self.as_mut().project();
+        loop {
+            match this.locals.poll_recv(cx) {
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            match this.inputs.as_mut().poll_next(cx) {
+                Poll::Ready(Some((StreamYield::Finished(f), _))) => f.remove(this.inputs.as_mut()),
+                Poll::Ready(Some((StreamYield::Item(Err(_)), streami))) => {
+                    this.inputs.as_mut().remove(streami);
+                }
+                Poll::Ready(Some((StreamYield::Item(Ok(_)), _))) => {}
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Future for Replica {
+    type Output = Result<(), failure::Error>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !self.failed {
+            match self.as_mut().run(cx) {
+                Poll::Ready(Err(e)) if e.downcast_ref::<DomainPanicked>().is_some() => {
+                    crit!(self.log, "{}", e);
+                    *self.as_mut().project().failed = true;
+                }
+                r => return r,
+            }
+        }
+        self.discard(cx)
+    }
 }
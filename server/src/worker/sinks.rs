@@ -0,0 +1,187 @@
+use dataflow::ops::trigger::TriggerFiring;
+use noria::TriggerAction;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::collections::HashMap;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// How many batches of view changes a domain may have waiting to be published before it drops
+/// new ones.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How long a Kafka producer may wait for room in its queue before a publish fails.
+const KAFKA_QUEUE_TIMEOUT_MS: i64 = 5_000;
+
+/// Hands the changes to views with Kafka and Redis triggers to a task that publishes them, so
+/// that the domain that the changes come from never waits for a broker.
+///
+/// Batches are published in the order they are handed over. They are dropped if too many are
+/// waiting to be published, or if publishing them fails.
+pub(super) struct Publisher {
+    tx: mpsc::Sender<TriggerFiring>,
+    log: slog::Logger,
+    /// How many batches have been dropped since the last one that was queued.
+    dropped: usize,
+}
+
+impl Publisher {
+    pub(super) fn start(log: slog::Logger) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(publish(rx, log.clone()));
+        Publisher {
+            tx,
+            log,
+            dropped: 0,
+        }
+    }
+
+    /// Queue the changes of `firing` to be published, unless the queue is full.
+    pub(super) fn publish(&mut self, firing: TriggerFiring) {
+        match self.tx.try_send(firing) {
+            Ok(()) => {
+                if self.dropped != 0 {
+                    warn!(self.log, "resumed publishing view changes"; "dropped" => self.dropped);
+                    self.dropped = 0;
+                }
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!(
+                        self.log,
+                        "dropping view changes, since too many wait to be published"
+                    );
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Closed(_)) => unreachable!("publisher task went away"),
+        }
+    }
+}
+
+/// Publish the batches of view changes that arrive on `rx` until the domain goes away.
+async fn publish(mut rx: mpsc::Receiver<TriggerFiring>, log: slog::Logger) {
+    let mut publishers = Publishers::default();
+    while let Some(firing) = rx.recv().await {
+        let published = match firing.action {
+            TriggerAction::Kafka {
+                ref brokers,
+                ref topic,
+            } => publishers.publish_to_kafka(brokers, topic, &firing).await,
+            TriggerAction::Redis {
+                ref url,
+                ref channel,
+            } => publishers.publish_to_redis(url, channel, &firing).await,
+            ref action => unreachable!("{:?} is not published by workers", action),
+        };
+        if let Err(e) = published {
+            warn!(log, "failed to publish view changes: {}", e; "trigger" => &firing.trigger);
+        }
+    }
+}
+
+/// Connections to the Kafka clusters and Redis servers that view changes are published to.
+#[derive(Default)]
+struct Publishers {
+    /// Producers by the brokers they connect to.
+    kafka: HashMap<String, FutureProducer>,
+    /// Connections by the URL of their server.
+    redis: HashMap<String, redis::aio::Connection>,
+}
+
+impl Publishers {
+    /// Publish the changes of `firing` to `topic` on the Kafka cluster at `brokers`.
+    ///
+    /// The message is keyed by the name of the trigger, so that all of a view's changes go to the
+    /// same partition and are consumed in order. Returns once the brokers have acknowledged it.
+    async fn publish_to_kafka(
+        &mut self,
+        brokers: &str,
+        topic: &str,
+        firing: &TriggerFiring,
+    ) -> Result<(), String> {
+        let payload = serde_json::to_vec(firing).map_err(|e| e.to_string())?;
+        if !self.kafka.contains_key(brokers) {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("acks", "all")
+                .create()
+                .map_err(|e| format!("failed to connect to Kafka at {}: {}", brokers, e))?;
+            self.kafka.insert(brokers.to_owned(), producer);
+        }
+
+        let record = FutureRecord::to(topic)
+            .key(&firing.trigger)
+            .payload(&payload);
+        let delivery = self.kafka[brokers].send(record, KAFKA_QUEUE_TIMEOUT_MS);
+        match delivery.await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err((e, _))) => Err(format!("failed to publish to Kafka topic {}: {}", topic, e)),
+            Err(_) => {
+                // the producer went away, so make a new one next time around
+                self.kafka.remove(brokers);
+                Err(format!("failed to publish to Kafka topic {}", topic))
+            }
+        }
+    }
+
+    /// Publish the changes of `firing` as JSON on `channel` of the Redis server at `url`.
+    ///
+    /// Redis pub/sub does not keep messages, so subscribers only see changes published while they
+    /// are subscribed.
+    async fn publish_to_redis(
+        &mut self,
+        url: &str,
+        channel: &str,
+        firing: &TriggerFiring,
+    ) -> Result<(), String> {
+        let payload = serde_json::to_vec(firing).map_err(|e| e.to_string())?;
+        if !self.redis.contains_key(url) {
+            let client = redis::Client::open(url)
+                .map_err(|e| format!("failed to connect to Redis at {}: {}", url, e))?;
+            let conn = client
+                .get_async_connection()
+                .await
+                .map_err(|e| format!("failed to connect to Redis at {}: {}", url, e))?;
+            self.redis.insert(url.to_owned(), conn);
+        }
+
+        let conn = self.redis.get_mut(url).unwrap();
+        if let Err(e) = redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(payload)
+            .query_async::<_, i64>(conn)
+            .await
+        {
+            // the connection may be broken, so reconnect next time around
+            self.redis.remove(url);
+            return Err(format!(
+                "failed to publish to Redis channel {}: {}",
+                channel, e
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_reports_unreachable_redis() {
+        let firing = TriggerFiring {
+            trigger: "t".to_owned(),
+            action: TriggerAction::Redis {
+                url: "redis://127.0.0.1:1/".to_owned(),
+                channel: "c".to_owned(),
+            },
+            changes: vec![(vec![1.into()], true)],
+        };
+        let mut publishers = Publishers::default();
+        assert!(publishers
+            .publish_to_redis("redis://127.0.0.1:1/", "c", &firing)
+            .await
+            .is_err());
+        assert!(publishers.redis.is_empty());
+    }
+}